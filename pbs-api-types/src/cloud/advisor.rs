@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{api, Schema, StringSchema};
+use proxmox_uuid::Uuid;
+
+use super::CLOUD_MEDIA_SET_UUID_SCHEMA;
+use crate::UUID_FORMAT;
+
+pub const CLOUD_CHUNK_ARCHIVE_UUID_SCHEMA: Schema = StringSchema::new("Chunk archive Uuid.")
+    .format(&UUID_FORMAT)
+    .schema();
+
+#[api()]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Access statistics of a chunk stored on a cloud target
+pub struct CloudChunkPopularity {
+    /// Chunk digest (hex)
+    pub digest: String,
+    /// Number of downloads
+    pub hits: u64,
+    /// Time of the last download (epoch)
+    pub last_access: i64,
+    /// Hits weighted by age (recent downloads count more)
+    pub score: f64,
+}
+
+#[api(
+    properties: {
+        "media-set": {
+            schema: CLOUD_MEDIA_SET_UUID_SCHEMA,
+        },
+        archive: {
+            schema: CLOUD_CHUNK_ARCHIVE_UUID_SCHEMA,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Chunk archive which is rarely read and could move to a colder storage tier
+pub struct CloudTierCandidate {
+    pub media_set: Uuid,
+    pub archive: Uuid,
+    /// Archive size in bytes
+    pub size: u64,
+    /// Time of the last download of any chunk in the archive (epoch)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_access: Option<i64>,
+}
+
+#[api(
+    properties: {
+        "hot-chunks": {
+            type: Array,
+            items: {
+                type: CloudChunkPopularity,
+            },
+        },
+        "tier-candidates": {
+            type: Array,
+            items: {
+                type: CloudTierCandidate,
+            },
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Cache and storage tier placement advice for a cloud target
+pub struct CloudPlacementAdvice {
+    /// Number of chunks with recorded downloads
+    pub tracked_chunks: u64,
+    /// Most popular chunks, which should stay in the local cache
+    pub hot_chunks: Vec<CloudChunkPopularity>,
+    /// Total size of the hot chunks in bytes
+    pub hot_size: u64,
+    /// Archives without recent downloads
+    pub tier_candidates: Vec<CloudTierCandidate>,
+}
@@ -0,0 +1,34 @@
+//! Types for the local cache of downloaded cloud chunks
+
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{api, IntegerSchema, Schema};
+
+pub const CLOUD_CHUNK_CACHE_SIZE_SCHEMA: Schema = IntegerSchema::new(
+    "Size of the local cache for chunks downloaded from cloud targets (GiB, 0 disables the cache).",
+)
+.minimum(0)
+.default(0)
+.schema();
+
+#[api()]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Utilization of the local chunk cache
+pub struct CloudChunkCacheStatus {
+    /// Configured maximum size (in bytes)
+    pub max_size: u64,
+    /// Size of the cached chunks (in bytes)
+    pub size: u64,
+    /// Number of cached chunks
+    pub entries: u64,
+    /// Chunk reads served from the cache
+    pub hits: u64,
+    /// Chunk reads which needed a download
+    pub misses: u64,
+    /// Chunks removed to make room for new ones
+    pub evictions: u64,
+    /// Share of chunk reads served from the cache (0.0 - 1.0)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hit_rate: Option<f64>,
+}
@@ -0,0 +1,40 @@
+//! Types for the consistency check of the cloud configuration
+
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::api;
+
+#[api()]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Kind of a problem found in the cloud configuration
+pub enum CloudConfigIssueKind {
+    /// A job or mapping references a target which does not exist
+    MissingTarget,
+    /// A target could not be reached
+    UnreachableTarget,
+    /// An encryption key is referenced, but not in the key store
+    MissingKey,
+    /// A schedule of an enabled job never fires (again)
+    DeadSchedule,
+    /// Two targets store their objects in the same place
+    OverlappingPrefix,
+}
+
+#[api(
+    properties: {
+        kind: {
+            type: CloudConfigIssueKind,
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Problem found in the cloud configuration
+pub struct CloudConfigIssue {
+    pub kind: CloudConfigIssueKind,
+    /// Config entry with the problem (`type/id`, e.g. `backup/daily`)
+    pub entity: String,
+    /// Description of the problem
+    pub message: String,
+}
@@ -0,0 +1,199 @@
+//! Types for listing the content of cloud targets
+
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::api;
+
+use crate::{BackupDir, BackupGroup, BackupNamespace, Fingerprint, SnapshotVerifyState};
+
+#[api()]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Verification status of snapshots on a cloud target.
+pub enum CloudVerifyStatus {
+    /// The last verification succeeded and is not outdated.
+    Ok,
+    /// The last verification succeeded, but is outdated. Groups are also
+    /// outdated if some of their snapshots were not verified.
+    Outdated,
+    /// The last verification failed.
+    Failed,
+}
+
+serde_plain::derive_display_from_serialize!(CloudVerifyStatus);
+
+#[api(
+    properties: {
+        ns: { type: BackupNamespace },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// A namespace with snapshots on a cloud target.
+pub struct CloudNamespaceListItem {
+    /// Datastore the snapshots were backed up from.
+    pub store: String,
+    pub ns: BackupNamespace,
+    /// Number of backup groups.
+    pub group_count: u64,
+    /// Number of snapshots.
+    pub snapshot_count: u64,
+}
+
+#[api(
+    properties: {
+        backup: { type: BackupGroup },
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        "verify-status": {
+            type: CloudVerifyStatus,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// A backup group with snapshots on a cloud target.
+pub struct CloudGroupListItem {
+    /// Datastore the snapshots were backed up from.
+    pub store: String,
+    #[serde(default, skip_serializing_if = "BackupNamespace::is_root")]
+    pub ns: BackupNamespace,
+    #[serde(flatten)]
+    pub backup: BackupGroup,
+    /// Time of the last snapshot.
+    pub last_backup: i64,
+    /// Number of snapshots.
+    pub backup_count: u64,
+    /// Worst verification status of the snapshots on the target (unset if
+    /// no snapshot was verified).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_status: Option<CloudVerifyStatus>,
+}
+
+#[api(
+    properties: {
+        backup: { type: BackupDir },
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        verification: {
+            type: SnapshotVerifyState,
+            optional: true,
+        },
+        "cloud-verification": {
+            type: SnapshotVerifyState,
+            optional: true,
+        },
+        "verify-status": {
+            type: CloudVerifyStatus,
+            optional: true,
+        },
+        fingerprint: {
+            type: String,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// A snapshot on a cloud target.
+pub struct CloudSnapshotListItem {
+    /// Datastore the snapshot was backed up from.
+    pub store: String,
+    #[serde(default, skip_serializing_if = "BackupNamespace::is_root")]
+    pub ns: BackupNamespace,
+    #[serde(flatten)]
+    pub backup: BackupDir,
+    /// UUID of the media set containing the snapshot.
+    pub media_set: String,
+    /// Creation time of the media set (UNIX epoch).
+    pub ctime: i64,
+    /// Size of the snapshot files and all referenced chunks on the target.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Verification state when the snapshot was backed up.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<SnapshotVerifyState>,
+    /// Last verification of the snapshot on the target.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_verification: Option<SnapshotVerifyState>,
+    /// Status of the last verification on the target.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_status: Option<CloudVerifyStatus>,
+    /// Fingerprint of the client side encryption key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<Fingerprint>,
+    /// The snapshot is encrypted on the target (namespace key).
+    pub encrypted: bool,
+}
+
+#[api(
+    properties: {
+        verification: {
+            type: SnapshotVerifyState,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Offsite copy of a local snapshot on a cloud target.
+pub struct CloudSnapshotCopy {
+    /// Name of the cloud target.
+    pub target: String,
+    /// Upload time (creation time of the newest media set containing the
+    /// snapshot, UNIX epoch).
+    pub upload_time: i64,
+    /// Verification state when the snapshot was uploaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<SnapshotVerifyState>,
+}
+
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        backup: { type: BackupDir },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// A snapshot in a prune simulation.
+pub struct CloudPruneSimulationItem {
+    /// Datastore the snapshot was backed up from.
+    pub store: String,
+    #[serde(default, skip_serializing_if = "BackupNamespace::is_root")]
+    pub ns: BackupNamespace,
+    #[serde(flatten)]
+    pub backup: BackupDir,
+    /// Keep snapshot.
+    pub keep: bool,
+}
+
+#[api(
+    properties: {
+        snapshots: {
+            type: Array,
+            items: { type: CloudPruneSimulationItem },
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Result of a prune simulation on a cloud target.
+pub struct CloudPruneSimulation {
+    /// The selected snapshots, sorted by store, namespace, group and time.
+    pub snapshots: Vec<CloudPruneSimulationItem>,
+    /// Number of kept snapshots.
+    pub kept: u64,
+    /// Number of removed snapshots.
+    pub removed: u64,
+    /// Bytes of snapshot files and chunks no kept snapshot references.
+    pub reclaimed_bytes: u64,
+}
@@ -0,0 +1,82 @@
+//! Types for the change history of the cloud configuration
+
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{api, IntegerSchema, Schema};
+
+use crate::Authid;
+
+pub const CLOUD_CONFIG_HISTORY_DAYS_SCHEMA: Schema =
+    IntegerSchema::new("Keep the change history of the cloud configuration for this many days.")
+        .minimum(1)
+        .default(365)
+        .schema();
+
+#[api()]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Kind of a configuration change
+pub enum CloudConfigAction {
+    /// The object was created
+    Create,
+    /// Properties of the object were changed
+    Update,
+    /// The object was removed
+    Delete,
+}
+
+#[api(
+    properties: {
+        old: {
+            optional: true,
+        },
+        new: {
+            optional: true,
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A changed property
+pub struct CloudConfigPropertyChange {
+    /// Property name
+    pub property: String,
+    /// Previous value, not set if the property was added
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<String>,
+    /// New value, not set if the property was removed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<String>,
+}
+
+#[api(
+    properties: {
+        user: {
+            type: Authid,
+        },
+        action: {
+            type: CloudConfigAction,
+        },
+        changes: {
+            type: Array,
+            items: {
+                type: CloudConfigPropertyChange,
+            },
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A single change of the cloud configuration
+pub struct CloudConfigChange {
+    /// Time of the change (UNIX epoch)
+    pub time: i64,
+    /// The user or token doing the change
+    pub user: Authid,
+    /// Section type of the changed object ('target', 'backup' or 'template')
+    pub config_type: String,
+    /// ID of the changed object
+    pub id: String,
+    pub action: CloudConfigAction,
+    pub changes: Vec<CloudConfigPropertyChange>,
+}
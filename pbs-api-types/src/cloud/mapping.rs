@@ -0,0 +1,107 @@
+//! Types for datastore to cloud target mappings
+
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{api, Schema, StringSchema, Updater};
+
+use super::{
+    CLOUD_ENCRYPTION_KEY_FINGERPRINT_SCHEMA, CLOUD_OBJECT_PREFIX_SCHEMA, CLOUD_TARGET_NAME_SCHEMA,
+};
+use crate::{
+    BackupNamespace, DATASTORE_SCHEMA, PROXMOX_SAFE_ID_FORMAT, SINGLE_LINE_COMMENT_SCHEMA,
+};
+
+pub const CLOUD_MAPPING_ID_SCHEMA: Schema = StringSchema::new("Cloud mapping ID.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(2)
+    .max_length(32)
+    .schema();
+
+#[api(
+    properties: {
+        id: {
+            schema: CLOUD_MAPPING_ID_SCHEMA,
+        },
+        store: {
+            schema: DATASTORE_SCHEMA,
+        },
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        target: {
+            schema: CLOUD_TARGET_NAME_SCHEMA,
+        },
+        prefix: {
+            schema: CLOUD_OBJECT_PREFIX_SCHEMA,
+            optional: true,
+        },
+        key: {
+            schema: CLOUD_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+            optional: true,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, Updater, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Cloud Mapping
+///
+/// Default cloud target of a datastore (or a namespace and its
+/// sub-namespaces). Backup jobs of the datastore without a target of
+/// their own use it.
+pub struct CloudMapping {
+    #[updater(skip)]
+    pub id: String,
+    pub store: String,
+    /// Only map this namespace and its sub-namespaces (whole datastore if not set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<BackupNamespace>,
+    pub target: String,
+    /// Object prefix the target has to use, so that data of different
+    /// datastores cannot end up under the same prefix
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// Encrypt the data of the mapped datastore (or namespace) with this key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl CloudMapping {
+    /// Distance between the mapped namespace and `ns`, `None` if the
+    /// mapping does not apply to `ns` of datastore `store`
+    pub fn applies_to(&self, store: &str, ns: &BackupNamespace) -> Option<usize> {
+        if self.store != store {
+            return None;
+        }
+        match self.ns {
+            Some(ref mapped) => mapped.contains(ns),
+            None => Some(ns.depth()),
+        }
+    }
+}
+
+/// Find the mapping of namespace `ns` of datastore `store`
+///
+/// The mapping of the closest parent namespace is used, a mapping
+/// without namespace applies to the whole datastore.
+pub fn find_cloud_mapping<'a>(
+    mappings: &'a [CloudMapping],
+    store: &str,
+    ns: &BackupNamespace,
+) -> Option<&'a CloudMapping> {
+    let mut best: Option<(usize, &CloudMapping)> = None;
+    for mapping in mappings {
+        if let Some(distance) = mapping.applies_to(store, ns) {
+            if best.map(|(d, _)| distance < d).unwrap_or(true) {
+                best = Some((distance, mapping));
+            }
+        }
+    }
+    best.map(|(_, mapping)| mapping)
+}
@@ -1,30 +1,134 @@
 //! Types for cloud backup API
 
+mod advisor;
+pub use advisor::*;
+
+mod chunk_cache;
+pub use chunk_cache::*;
+
+mod config_check;
+pub use config_check::*;
+
+mod content;
+pub use content::*;
+
+mod history;
+pub use history::*;
+
+mod mapping;
+pub use mapping::*;
+
+mod share;
+pub use share::*;
+
+mod staging;
+pub use staging::*;
+
+mod target;
+pub use target::*;
+
+mod task_log;
+pub use task_log::*;
+
+mod upload_estimate;
+pub use upload_estimate::*;
+
 use serde::{Deserialize, Serialize};
 
-use proxmox_schema::{api, const_regex, ApiStringFormat, Schema, StringSchema};
+use proxmox_schema::{
+    api, const_regex, ApiStringFormat, ArraySchema, BooleanSchema, IntegerSchema, Schema,
+    StringSchema,
+};
 use proxmox_uuid::Uuid;
 
-use crate::{BackupType, BACKUP_ID_SCHEMA, FINGERPRINT_SHA256_FORMAT};
+use crate::{
+    BackupType, BACKUP_ID_SCHEMA, FINGERPRINT_SHA256_FORMAT, PROXMOX_SAFE_ID_FORMAT, UUID_FORMAT,
+};
 
 const_regex! {
     pub CLOUD_RESTORE_SNAPSHOT_REGEX = concat!(r"^", PROXMOX_SAFE_ID_REGEX_STR!(), r":(?:", BACKUP_NS_PATH_RE!(),")?", SNAPSHOT_PATH_REGEX_STR!(), r"$");
+    pub CLOUD_KEY_SHARE_REGEX = r"^pbs-share-v1-[0-9a-f]{8}-[0-9]{1,3}-[0-9]{1,3}-[0-9a-f]{64}-[0-9a-f]{8}$";
+    pub CLOUD_OBJECT_TAG_REGEX = r"^[A-Za-z0-9 +\-._:/@]{1,128}=[A-Za-z0-9 +\-._:/@]{0,256}$";
 }
 
 pub const CLOUD_RESTORE_SNAPSHOT_FORMAT: ApiStringFormat =
     ApiStringFormat::Pattern(&CLOUD_RESTORE_SNAPSHOT_REGEX);
 
+pub const CLOUD_MEDIA_SET_UUID_SCHEMA: Schema = StringSchema::new("Cloud media set Uuid.")
+    .format(&UUID_FORMAT)
+    .schema();
+
 pub const CLOUD_ENCRYPTION_KEY_FINGERPRINT_SCHEMA: Schema =
     StringSchema::new("Cloud encryption key fingerprint (sha256).")
         .format(&FINGERPRINT_SHA256_FORMAT)
         .schema();
 
+pub const CLOUD_KEY_SHARE_SCHEMA: Schema =
+    StringSchema::new("Share of a split cloud encryption key.")
+        .format(&ApiStringFormat::Pattern(&CLOUD_KEY_SHARE_REGEX))
+        .schema();
+
+pub const CLOUD_TAG_SCHEMA: Schema = StringSchema::new("Tag for grouping cloud targets and jobs.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(2)
+    .max_length(32)
+    .schema();
+
+pub const CLOUD_TAG_LIST_SCHEMA: Schema =
+    ArraySchema::new("List of tags.", &CLOUD_TAG_SCHEMA).schema();
+
+pub const CLOUD_OBJECT_TAG_SCHEMA: Schema =
+    StringSchema::new("Object tag applied to uploaded objects, for cost allocation.")
+        .format(&ApiStringFormat::Pattern(&CLOUD_OBJECT_TAG_REGEX))
+        .type_text("<key>=<value>")
+        .schema();
+
+pub const CLOUD_OBJECT_TAG_LIST_SCHEMA: Schema =
+    ArraySchema::new("List of object tags.", &CLOUD_OBJECT_TAG_SCHEMA).schema();
+
+/// Split an object tag into key and value
+pub fn parse_cloud_object_tag(tag: &str) -> Result<(String, String), anyhow::Error> {
+    match tag.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => anyhow::bail!("invalid object tag '{}'", tag),
+    }
+}
+
 pub const CLOUD_RESTORE_SNAPSHOT_SCHEMA: Schema =
     StringSchema::new("A snapshot in the format: 'store:[ns/namespace/...]type/id/time")
         .format(&CLOUD_RESTORE_SNAPSHOT_FORMAT)
         .type_text("store:[ns/namespace/...]type/id/time")
         .schema();
 
+pub const CLOUD_RESTORE_DOWNLOAD_THREADS_SCHEMA: Schema =
+    IntegerSchema::new("Number of chunks downloaded in parallel.")
+        .minimum(1)
+        .maximum(32)
+        .default(4)
+        .schema();
+
+pub const CLOUD_RESTORE_STAGING_DIR_SCHEMA: Schema = StringSchema::new(
+    "Keep downloaded chunks in this directory (instead of memory) until they are \
+    inserted into the datastore.",
+)
+.format(&ApiStringFormat::Pattern(&CLOUD_LOCAL_PATH_REGEX))
+.max_length(4096)
+.schema();
+
+pub const CLOUD_CONFIG_VALIDATE_SCHEMA: Schema = BooleanSchema::new(
+    "Only validate the configuration and return the resulting object, do not save it.",
+)
+.default(false)
+.schema();
+
+pub const CLOUD_COMPACT_THRESHOLD_SCHEMA: Schema = IntegerSchema::new(
+    "Compact media sets with less live data than this percentage of their size.",
+)
+.minimum(1)
+.maximum(100)
+.default(40)
+.schema();
+
 pub struct CloudContentListFilter {
     pub label_text: Option<String>,
     pub backup_type: Option<BackupType>,
@@ -0,0 +1,60 @@
+//! Types for shared downloads of cloud objects
+
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{api, const_regex, ApiStringFormat, Schema, StringSchema};
+
+use crate::{Authid, SINGLE_LINE_COMMENT_SCHEMA};
+
+const_regex! {
+    pub CLOUD_EXPORT_KEY_REGEX = r"^export/\S+\.tar$";
+}
+
+pub const CLOUD_EXPORT_KEY_SCHEMA: Schema =
+    StringSchema::new("Key of a snapshot export object (relative to the target prefix).")
+        .format(&ApiStringFormat::Pattern(&CLOUD_EXPORT_KEY_REGEX))
+        .max_length(1024)
+        .schema();
+
+#[api()]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A time limited download URL of an object
+pub struct CloudShareUrl {
+    /// Pre-signed GET URL
+    pub url: String,
+    /// Time the URL stops working (epoch)
+    pub expires: i64,
+}
+
+#[api(
+    properties: {
+        key: {
+            schema: CLOUD_EXPORT_KEY_SCHEMA,
+        },
+        "auth-id": {
+            type: Authid,
+        },
+        comment: {
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+            optional: true,
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A shared download URL, as recorded in the audit log
+pub struct CloudShareRecord {
+    /// Time the URL was created (epoch)
+    pub time: i64,
+    /// Name of the target
+    pub target: String,
+    /// Shared object
+    pub key: String,
+    /// User who created the URL
+    pub auth_id: Authid,
+    /// Time the URL stops working (epoch)
+    pub expires: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
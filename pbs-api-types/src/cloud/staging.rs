@@ -0,0 +1,45 @@
+//! Types for the write-back staging spool of cloud targets
+
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::api;
+
+#[api(
+    properties: {
+        alerts: {
+            type: Array,
+            optional: true,
+            items: {
+                description: "Exceeded threshold.",
+                type: String,
+            },
+        },
+    },
+)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Objects of a target waiting in the write-back spool
+pub struct CloudStagingStatus {
+    /// Whether the target uses write-back uploads
+    pub write_back: bool,
+    /// Number of objects waiting for upload
+    pub objects: u64,
+    /// Size of the spooled objects (in bytes)
+    pub size: u64,
+    /// Configured maximum size of the spool (in bytes)
+    pub max_size: u64,
+    /// Time the oldest object was spooled (epoch)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oldest: Option<i64>,
+    /// Seconds the oldest object is waiting for upload
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oldest_age: Option<i64>,
+    /// Whether an uploader is draining the spool
+    pub uploading: bool,
+    /// Time of the last object the provider accepted from the uploader (epoch)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_upload: Option<i64>,
+    /// Health thresholds of the uploader which are exceeded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alerts: Vec<String>,
+}
@@ -0,0 +1,1714 @@
+//! Types for cloud target configuration
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{
+    api, const_regex, ApiStringFormat, ApiType, IntegerSchema, Schema, StringSchema, Updater,
+};
+
+use super::CLOUD_ENCRYPTION_KEY_FINGERPRINT_SCHEMA;
+use crate::{
+    Authid, BackupNamespace, Fingerprint, DNS_NAME_OR_IP_REGEX, HOST_PORT_REGEX, IP_V4_SCHEMA,
+    IP_V6_SCHEMA, NETWORK_INTERFACE_FORMAT, PASSWORD_FORMAT, PROXMOX_SAFE_ID_FORMAT,
+    SINGLE_LINE_COMMENT_FORMAT, SINGLE_LINE_COMMENT_SCHEMA,
+};
+
+const_regex! {
+    pub CLOUD_OBJECT_PREFIX_REGEX = r"^[A-Za-z0-9_.\-]+(?:/[A-Za-z0-9_.\-]+)*$";
+    pub CLOUD_LOCAL_PATH_REGEX = r"^/[^\x00-\x1F\x7F]*$";
+    pub CLOUD_ACCESS_KEY_REGEX = r"^[A-Za-z0-9_.+/=\-]+$";
+    pub CLOUD_USAGE_MONTH_REGEX = r"^[0-9]{4}-(?:0[1-9]|1[0-2])$";
+    pub CLOUD_EVENT_TOPIC_REGEX = r"^arn:aws[a-z\-]*:sns:[a-z0-9\-]+:[0-9]{12}:[A-Za-z0-9_\-]{1,256}(?:\.fifo)?$";
+    pub CLOUD_EVENT_QUEUE_REGEX = r"^https://sqs\.[a-z0-9\-]+\.amazonaws\.com(?:\.cn)?/[0-9]{12}/[A-Za-z0-9_\-]{1,80}(?:\.fifo)?$";
+}
+
+pub const CLOUD_TARGET_NAME_SCHEMA: Schema = StringSchema::new("Cloud target name.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(2)
+    .max_length(32)
+    .schema();
+
+fn verify_cloud_endpoint(input: &str) -> Result<(), anyhow::Error> {
+    if DNS_NAME_OR_IP_REGEX.is_match(input) || HOST_PORT_REGEX.is_match(input) {
+        return Ok(());
+    }
+    bail!("expected '<host>' or '<host>:<port>'");
+}
+
+pub const CLOUD_ENDPOINT_FORMAT: ApiStringFormat = ApiStringFormat::VerifyFn(verify_cloud_endpoint);
+
+pub const CLOUD_ENDPOINT_SCHEMA: Schema =
+    StringSchema::new("Endpoint of the object storage service (host name or IP, optional port).")
+        .format(&CLOUD_ENDPOINT_FORMAT)
+        .type_text("<host>[:<port>]")
+        .schema();
+
+fn verify_cloud_proxy(input: &str) -> Result<(), anyhow::Error> {
+    let rest = match input.split_once("://") {
+        Some(("http", rest)) => rest,
+        Some((scheme, _)) => bail!(
+            "unsupported proxy scheme '{}' - only HTTP proxies (CONNECT) are supported",
+            scheme
+        ),
+        None => input,
+    };
+    let host = match rest.rsplit_once('@') {
+        Some((userinfo, host)) => {
+            if userinfo.is_empty() || userinfo.starts_with(':') {
+                bail!("missing proxy user name");
+            }
+            host
+        }
+        None => rest,
+    };
+    let host = host.strip_suffix('/').unwrap_or(host);
+    verify_cloud_endpoint(host)
+}
+
+pub const CLOUD_PROXY_SCHEMA: Schema =
+    StringSchema::new("HTTP proxy for cloud traffic (TLS is tunneled with CONNECT).")
+        .format(&ApiStringFormat::VerifyFn(verify_cloud_proxy))
+        .max_length(256)
+        .type_text("[http://][<user>:<password>@]<host>[:<port>]")
+        .schema();
+
+fn verify_cloud_no_proxy(input: &str) -> Result<(), anyhow::Error> {
+    for entry in input.split(',').map(str::trim) {
+        if entry == "*" {
+            continue;
+        }
+        let host = entry
+            .strip_prefix("*.")
+            .or_else(|| entry.strip_prefix('.'))
+            .unwrap_or(entry);
+        if !DNS_NAME_OR_IP_REGEX.is_match(host) {
+            bail!("invalid no-proxy entry '{}'", entry);
+        }
+    }
+    Ok(())
+}
+
+pub const CLOUD_NO_PROXY_SCHEMA: Schema = StringSchema::new(
+    "Hosts reached without proxy (comma separated host names, IP addresses, or domains \
+    like '.example.com').",
+)
+.format(&ApiStringFormat::VerifyFn(verify_cloud_no_proxy))
+.max_length(1024)
+.type_text("<host>[,<host>...]")
+.schema();
+
+pub const CLOUD_USAGE_MONTH_SCHEMA: Schema =
+    StringSchema::new("Accounting period (calendar month, UTC).")
+        .format(&ApiStringFormat::Pattern(&CLOUD_USAGE_MONTH_REGEX))
+        .type_text("<YYYY-MM>")
+        .schema();
+
+pub const CLOUD_BUCKET_SCHEMA: Schema = StringSchema::new("Bucket or container name.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(3)
+    .max_length(63)
+    .schema();
+
+pub const CLOUD_REGION_SCHEMA: Schema = StringSchema::new("Region of the bucket.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .max_length(64)
+    .schema();
+
+pub const CLOUD_OBJECT_PREFIX_SCHEMA: Schema =
+    StringSchema::new("Prefix prepended to all object keys (no leading or trailing '/').")
+        .format(&ApiStringFormat::Pattern(&CLOUD_OBJECT_PREFIX_REGEX))
+        .max_length(256)
+        .schema();
+
+pub const CLOUD_LOCAL_PATH_SCHEMA: Schema =
+    StringSchema::new("Base directory used by the 'local' provider (absolute path).")
+        .format(&ApiStringFormat::Pattern(&CLOUD_LOCAL_PATH_REGEX))
+        .max_length(4096)
+        .schema();
+
+pub const CLOUD_EXPORT_PATH_SCHEMA: Schema = StringSchema::new(
+    "Write the objects to this directory (e.g. a mounted disk for offline seeding) \
+    instead of uploading them to the target.",
+)
+.format(&ApiStringFormat::Pattern(&CLOUD_LOCAL_PATH_REGEX))
+.max_length(4096)
+.schema();
+
+pub const CLOUD_ACCESS_KEY_SCHEMA: Schema = StringSchema::new("Access key ID.")
+    .format(&ApiStringFormat::Pattern(&CLOUD_ACCESS_KEY_REGEX))
+    .min_length(1)
+    .max_length(128)
+    .schema();
+
+pub const CLOUD_SECRET_KEY_SCHEMA: Schema = StringSchema::new("Secret access key.")
+    .format(&PASSWORD_FORMAT)
+    .min_length(1)
+    .max_length(1024)
+    .schema();
+
+pub const CLOUD_CREDENTIAL_PROCESS_SCHEMA: Schema = StringSchema::new(
+    "Command printing temporary credentials (JSON, compatible with the AWS 'credential_process' format).",
+)
+.format(&SINGLE_LINE_COMMENT_FORMAT)
+.min_length(1)
+.max_length(1024)
+.schema();
+
+pub const CLOUD_USER_AGENT_SCHEMA: Schema = StringSchema::new(
+    "User-Agent sent with all requests (default: 'proxmox-backup-server/<version> (<node>)').",
+)
+.format(&SINGLE_LINE_COMMENT_FORMAT)
+.min_length(1)
+.max_length(256)
+.schema();
+
+/// Default timeout of metadata requests (seconds)
+pub const DEFAULT_CLOUD_METADATA_TIMEOUT: u64 = 30;
+/// Default timeout of data transfers (seconds)
+pub const DEFAULT_CLOUD_DATA_TIMEOUT: u64 = 900;
+/// Default number of days deleted snapshots are kept in the trash
+pub const DEFAULT_CLOUD_TRASH_RETENTION: u64 = 7;
+/// Default maximum lifetime of shared download URLs (seconds)
+pub const DEFAULT_CLOUD_SHARE_MAX_TTL: u64 = 24 * 3600;
+/// Longest lifetime of pre-signed URLs supported by S3 (seconds)
+pub const MAX_CLOUD_SHARE_TTL: u64 = 7 * 24 * 3600;
+
+pub const CLOUD_SHARE_TTL_SCHEMA: Schema =
+    IntegerSchema::new("Lifetime of a shared download URL (seconds).")
+        .minimum(60)
+        .maximum(MAX_CLOUD_SHARE_TTL as isize)
+        .schema();
+
+pub const CLOUD_STORAGE_CLASS_SCHEMA: Schema = StringSchema::new(
+    "Storage class used for backup data (provider specific, e.g. 'STANDARD_IA').",
+)
+.format(&PROXMOX_SAFE_ID_FORMAT)
+.max_length(64)
+.schema();
+
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        key: {
+            schema: CLOUD_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Encryption key used for a namespace and its sub-namespaces.
+pub struct CloudNamespaceKey {
+    #[serde(default, skip_serializing_if = "BackupNamespace::is_root")]
+    pub ns: BackupNamespace,
+    pub key: String,
+}
+
+pub const CLOUD_NAMESPACE_KEY_SCHEMA: Schema =
+    StringSchema::new("Encrypt backup data of a namespace (and its sub-namespaces) with this key.")
+        .format(&ApiStringFormat::PropertyString(
+            &CloudNamespaceKey::API_SCHEMA,
+        ))
+        .schema();
+
+#[api(
+    properties: {
+        owner: {
+            type: Authid,
+            optional: true,
+        },
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        "max-size": {
+            description: "Maximum size of the uploaded data (GiB).",
+            type: u64,
+            optional: true,
+            minimum: 1,
+        },
+        "max-objects": {
+            description: "Maximum number of uploaded objects.",
+            type: u64,
+            optional: true,
+            minimum: 1,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Storage quota of a backup owner, or of a namespace and its sub-namespaces.
+///
+/// Data uploaded for a snapshot is attributed to the owner of its backup
+/// group and to its namespace.
+pub struct CloudQuota {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Authid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<BackupNamespace>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_objects: Option<u64>,
+}
+
+impl CloudQuota {
+    /// Maximum size in bytes
+    pub fn max_bytes(&self) -> Option<u64> {
+        self.max_size
+            .map(|gib| gib.saturating_mul(1024 * 1024 * 1024))
+    }
+
+    /// Returns true if data of namespace `ns` owned by `owner` counts against the quota
+    pub fn applies_to(&self, owner: Option<&Authid>, ns: &BackupNamespace) -> bool {
+        match (&self.owner, &self.ns) {
+            (Some(quota_owner), _) => owner == Some(quota_owner),
+            (None, Some(quota_ns)) => quota_ns.contains(ns).is_some(),
+            (None, None) => false,
+        }
+    }
+}
+
+impl std::fmt::Display for CloudQuota {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.owner, &self.ns) {
+            (Some(owner), _) => write!(f, "quota of owner '{}'", owner),
+            (None, Some(ns)) if ns.is_root() => write!(f, "quota of the root namespace"),
+            (None, Some(ns)) => write!(f, "quota of namespace '{}'", ns),
+            (None, None) => write!(f, "quota"),
+        }
+    }
+}
+
+pub const CLOUD_QUOTA_SCHEMA: Schema = StringSchema::new(
+    "Limit the data uploaded for an owner or a namespace (and its sub-namespaces).",
+)
+.format(&ApiStringFormat::PropertyString(&CloudQuota::API_SCHEMA))
+.schema();
+
+#[api()]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Object storage provider of a cloud target.
+pub enum CloudProvider {
+    /// Amazon S3 or any S3 compatible object storage.
+    #[default]
+    S3,
+    /// Local directory (or mounted network share) using the same object layout.
+    Local,
+}
+
+serde_plain::derive_display_from_serialize!(CloudProvider);
+serde_plain::derive_fromstr_from_deserialize!(CloudProvider);
+
+#[api()]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// IP address family used for cloud connections.
+pub enum CloudIpFamily {
+    /// IPv4 and IPv6, in the order returned by the resolver.
+    #[default]
+    Any,
+    /// Connect over IPv4 only.
+    Ipv4,
+    /// Connect over IPv6 only.
+    Ipv6,
+}
+
+pub const CLOUD_SOURCE_INTERFACE_SCHEMA: Schema =
+    StringSchema::new("Bind cloud connections to the addresses of this network interface.")
+        .format(&NETWORK_INTERFACE_FORMAT)
+        .min_length(1)
+        .max_length(15)
+        .schema();
+
+pub const CLOUD_EVENT_TOPIC_SCHEMA: Schema =
+    StringSchema::new("Publish job lifecycle events to this SNS topic (ARN).")
+        .format(&ApiStringFormat::Pattern(&CLOUD_EVENT_TOPIC_REGEX))
+        .max_length(512)
+        .type_text("arn:aws:sns:<region>:<account>:<topic>")
+        .schema();
+
+pub const CLOUD_EVENT_QUEUE_SCHEMA: Schema =
+    StringSchema::new("Send job lifecycle events to this SQS queue (queue URL).")
+        .format(&ApiStringFormat::Pattern(&CLOUD_EVENT_QUEUE_REGEX))
+        .max_length(512)
+        .type_text("https://sqs.<region>.amazonaws.com/<account>/<queue>")
+        .schema();
+
+pub const CLOUD_CHANGE_QUEUE_SCHEMA: Schema = StringSchema::new(
+    "SQS queue (queue URL) receiving the S3 event notifications of the bucket. Objects \
+    written or removed by other producers are applied to the local catalog.",
+)
+.format(&ApiStringFormat::Pattern(&CLOUD_EVENT_QUEUE_REGEX))
+.max_length(512)
+.type_text("https://sqs.<region>.amazonaws.com/<account>/<queue>")
+.schema();
+
+#[api()]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Credentials used for SNS and SQS requests (events and change notifications).
+pub enum CloudEventCredentials {
+    /// Credentials of the target ('access-key' or 'credential-process').
+    #[default]
+    Target,
+    /// Role credentials of the EC2 instance profile.
+    InstanceRole,
+}
+
+#[api(
+    properties: {
+        provider: {
+            type: CloudProvider,
+        },
+        endpoint: {
+            schema: CLOUD_ENDPOINT_SCHEMA,
+            optional: true,
+        },
+        region: {
+            schema: CLOUD_REGION_SCHEMA,
+            optional: true,
+        },
+        "alternate-endpoint": {
+            description: "Other endpoints serving the same bucket (e.g. other regions or \
+                accelerated transfer endpoints). The fastest endpoint is selected at job start.",
+            type: Array,
+            optional: true,
+            items: {
+                schema: CLOUD_ENDPOINT_SCHEMA,
+            },
+        },
+        "failover-endpoint": {
+            description: "Endpoints serving replicas of the bucket, in order of priority. If \
+                the current endpoint is unreachable, requests fail over to the next one.",
+            type: Array,
+            optional: true,
+            items: {
+                schema: CLOUD_ENDPOINT_SCHEMA,
+            },
+        },
+        bucket: {
+            schema: CLOUD_BUCKET_SCHEMA,
+            optional: true,
+        },
+        prefix: {
+            schema: CLOUD_OBJECT_PREFIX_SCHEMA,
+            optional: true,
+        },
+        path: {
+            schema: CLOUD_LOCAL_PATH_SCHEMA,
+            optional: true,
+        },
+        "access-key": {
+            schema: CLOUD_ACCESS_KEY_SCHEMA,
+            optional: true,
+        },
+        "credential-process": {
+            schema: CLOUD_CREDENTIAL_PROCESS_SCHEMA,
+            optional: true,
+        },
+        "path-style": {
+            description: "Use path style bucket addressing instead of virtual hosted style.",
+            type: bool,
+            optional: true,
+            default: false,
+        },
+        "transfer-acceleration": {
+            description: "Upload objects through the S3 Transfer Acceleration endpoint of \
+                the bucket (AWS only, needs to be enabled on the bucket).",
+            type: bool,
+            optional: true,
+            default: false,
+        },
+        "download-host": {
+            description: "Download objects through this host (e.g. a CDN in front of the \
+                bucket). Requests are signed for the bucket endpoint, so the host has to \
+                forward them unchanged.",
+            schema: CLOUD_ENDPOINT_SCHEMA,
+            optional: true,
+        },
+        "metadata-timeout": {
+            description: "Timeout for small requests like HEAD, LIST or DELETE (seconds).",
+            type: u64,
+            optional: true,
+            minimum: 1,
+            maximum: 3600,
+            default: DEFAULT_CLOUD_METADATA_TIMEOUT,
+        },
+        "data-timeout": {
+            description: "Timeout for object (or part) uploads and downloads (seconds).",
+            type: u64,
+            optional: true,
+            minimum: 1,
+            maximum: 86400,
+            default: DEFAULT_CLOUD_DATA_TIMEOUT,
+        },
+        "user-agent": {
+            schema: CLOUD_USER_AGENT_SCHEMA,
+            optional: true,
+        },
+        proxy: {
+            schema: CLOUD_PROXY_SCHEMA,
+            optional: true,
+        },
+        "no-proxy": {
+            schema: CLOUD_NO_PROXY_SCHEMA,
+            optional: true,
+        },
+        "ip-family": {
+            type: CloudIpFamily,
+            optional: true,
+        },
+        "source-address": {
+            schema: IP_V4_SCHEMA,
+            optional: true,
+        },
+        "source-address6": {
+            schema: IP_V6_SCHEMA,
+            optional: true,
+        },
+        "source-interface": {
+            schema: CLOUD_SOURCE_INTERFACE_SCHEMA,
+            optional: true,
+        },
+        "request-tagging": {
+            description: "Tag uploaded objects with node, datastore and job, for cost \
+                attribution on the provider side (S3 object tags).",
+            type: bool,
+            optional: true,
+            default: false,
+        },
+        "egress-budget": {
+            description: "Monthly egress budget (GiB). Restores exceeding it need to be forced.",
+            type: u64,
+            optional: true,
+            minimum: 1,
+        },
+        "namespace-key": {
+            type: Array,
+            optional: true,
+            items: {
+                schema: CLOUD_NAMESPACE_KEY_SCHEMA,
+            },
+        },
+        quota: {
+            type: Array,
+            optional: true,
+            items: {
+                schema: CLOUD_QUOTA_SCHEMA,
+            },
+        },
+        "delete-protection": {
+            description: "Never delete objects with the configured credentials. Deletions are \
+                queued locally and processed with separate credentials \
+                (see 'proxmox-backup-debug cloud delete-queue').",
+            type: bool,
+            optional: true,
+            default: false,
+        },
+        "object-tag": {
+            schema: super::CLOUD_OBJECT_TAG_LIST_SCHEMA,
+            optional: true,
+        },
+        "access-log-prefix": {
+            description: "Prefix (below the target prefix) where the provider delivers S3 server \
+                access logs of the bucket. Object accesses not originating from PBS are reported \
+                by 'access-log-scan'.",
+            schema: CLOUD_OBJECT_PREFIX_SCHEMA,
+            optional: true,
+        },
+        "standby-remote": {
+            description: "Run as warm standby of the target configured with the same name on \
+                this remote. The local catalog follows the catalog of the remote, and no jobs \
+                can write to the target.",
+            schema: crate::REMOTE_ID_SCHEMA,
+            optional: true,
+        },
+        "parity-group": {
+            description: "Upload an XOR parity object per this many chunk archives, so a single \
+                lost archive of each group can be repaired by 'reconcile'.",
+            type: u64,
+            optional: true,
+            minimum: 2,
+            maximum: 64,
+        },
+        "health-check": {
+            description: "Periodically check that the target is reachable.",
+            type: bool,
+            optional: true,
+            default: true,
+        },
+        "write-back": {
+            description: "Write objects of backup jobs to a local spool and upload them \
+                asynchronously, so jobs finish at local disk speed.",
+            type: bool,
+            optional: true,
+            default: false,
+        },
+        "write-back-spool-size": {
+            description: "Maximum size of the write-back spool (GiB). Backup jobs wait for \
+                uploads while the spool is full.",
+            type: u64,
+            optional: true,
+            minimum: 1,
+            default: 16,
+        },
+        "trash-retention": {
+            description: "Keep deleted snapshots in the trash for this many days. Their data is \
+                only removed by compaction after that.",
+            type: u64,
+            optional: true,
+            minimum: 0,
+            default: DEFAULT_CLOUD_TRASH_RETENTION,
+        },
+        "upload-checksums": {
+            description: "Send SHA-256 checksums with all uploads (S3). The provider rejects \
+                damaged uploads and keeps the checksum, so verification does not need to \
+                download objects uploaded in a single request.",
+            type: bool,
+            optional: true,
+            default: false,
+        },
+        "event-topic": {
+            schema: CLOUD_EVENT_TOPIC_SCHEMA,
+            optional: true,
+        },
+        "event-queue": {
+            schema: CLOUD_EVENT_QUEUE_SCHEMA,
+            optional: true,
+        },
+        "event-credentials": {
+            type: CloudEventCredentials,
+            optional: true,
+        },
+        "change-queue": {
+            schema: CLOUD_CHANGE_QUEUE_SCHEMA,
+            optional: true,
+        },
+        "share-max-ttl": {
+            description: "Longest lifetime of shared download URLs (seconds).",
+            type: u64,
+            optional: true,
+            minimum: 60,
+            maximum: 604800,
+            default: DEFAULT_CLOUD_SHARE_MAX_TTL,
+        },
+        tags: {
+            schema: super::CLOUD_TAG_LIST_SCHEMA,
+            optional: true,
+        },
+        comment: {
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+            optional: true,
+        },
+    },
+)]
+#[derive(Default, Serialize, Deserialize, Updater, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Cloud target configuration properties.
+pub struct CloudTargetConfig {
+    #[updater(skip)]
+    pub provider: CloudProvider,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alternate_endpoint: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failover_endpoint: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential_process: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_style: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_acceleration: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_family: Option<CloudIpFamily>,
+    /// IPv4 source address of cloud connections.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_address: Option<String>,
+    /// IPv6 source address of cloud connections.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_address6: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_interface: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_tagging: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub egress_budget: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace_key: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_protection: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub object_tag: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_log_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub standby_remote: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parity_group: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub write_back: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub write_back_spool_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trash_retention: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_checksums: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_queue: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_credentials: Option<CloudEventCredentials>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change_queue: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share_max_ttl: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl CloudTargetConfig {
+    /// Check that all properties required by the configured provider are set.
+    pub fn check_provider_properties(&self) -> Result<(), anyhow::Error> {
+        match self.provider {
+            CloudProvider::S3 => {
+                if self.bucket.is_none() {
+                    bail!("provider 's3' requires the 'bucket' property");
+                }
+                if self.endpoint.is_none() && self.region.is_none() {
+                    bail!("provider 's3' requires either 'endpoint' or 'region'");
+                }
+                if self.access_key.is_some() && self.credential_process.is_some() {
+                    bail!("'access-key' and 'credential-process' are mutually exclusive");
+                }
+                if self.source_interface.is_some()
+                    && (self.source_address.is_some() || self.source_address6.is_some())
+                {
+                    bail!("'source-interface' and 'source-address' are mutually exclusive");
+                }
+                if self.transfer_acceleration.unwrap_or(false) {
+                    if self.endpoint.is_some() {
+                        bail!("'transfer-acceleration' is only available with AWS endpoints");
+                    }
+                    if self.path_style.unwrap_or(false) {
+                        bail!("'transfer-acceleration' requires virtual hosted style addressing");
+                    }
+                    if self.bucket.as_deref().unwrap_or("").contains('.') {
+                        bail!("'transfer-acceleration' does not support bucket names with dots");
+                    }
+                }
+            }
+            CloudProvider::Local => {
+                if self.path.is_none() {
+                    bail!("provider 'local' requires the 'path' property");
+                }
+                if self.change_queue.is_some() {
+                    bail!("'change-queue' requires provider 's3'");
+                }
+                if (self.event_topic.is_some() || self.event_queue.is_some())
+                    && self.event_credentials.unwrap_or_default() == CloudEventCredentials::Target
+                {
+                    bail!("provider 'local' has no credentials for events, use 'instance-role'");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the target is tagged with `tag`
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().flatten().any(|t| t == tag)
+    }
+
+    /// Object tags applied to all uploads
+    pub fn object_tags(&self) -> Result<Vec<(String, String)>, anyhow::Error> {
+        self.object_tag
+            .iter()
+            .flatten()
+            .map(|tag| super::parse_cloud_object_tag(tag))
+            .collect()
+    }
+
+    /// Time deleted snapshots are kept in the trash (seconds)
+    pub fn trash_retention_secs(&self) -> i64 {
+        let days = self
+            .trash_retention
+            .unwrap_or(DEFAULT_CLOUD_TRASH_RETENTION);
+        (days as i64).saturating_mul(24 * 3600)
+    }
+
+    /// Longest lifetime of shared download URLs (seconds)
+    pub fn share_max_ttl(&self) -> u64 {
+        self.share_max_ttl.unwrap_or(DEFAULT_CLOUD_SHARE_MAX_TTL)
+    }
+
+    /// Monthly egress budget in bytes
+    pub fn egress_budget_bytes(&self) -> Option<u64> {
+        self.egress_budget
+            .map(|gib| gib.saturating_mul(1024 * 1024 * 1024))
+    }
+
+    /// Parse the configured namespace encryption keys
+    pub fn namespace_keys(&self) -> Result<Vec<CloudNamespaceKey>, anyhow::Error> {
+        let mut list = Vec::new();
+        for entry in self.namespace_key.iter().flatten() {
+            let value = CloudNamespaceKey::API_SCHEMA.parse_property_string(entry)?;
+            let item = CloudNamespaceKey::deserialize(value)?;
+            if list
+                .iter()
+                .any(|other: &CloudNamespaceKey| other.ns == item.ns)
+            {
+                bail!("duplicate encryption key for namespace '{}'", item.ns);
+            }
+            list.push(item);
+        }
+        Ok(list)
+    }
+
+    /// Encryption key for backup data of namespace `ns`
+    ///
+    /// Uses the key of the closest configured parent namespace, or `None`
+    /// if data of this namespace is not encrypted.
+    pub fn namespace_key_for(
+        &self,
+        ns: &BackupNamespace,
+    ) -> Result<Option<Fingerprint>, anyhow::Error> {
+        let mut best: Option<(usize, CloudNamespaceKey)> = None;
+        for item in self.namespace_keys()? {
+            if let Some(distance) = item.ns.contains(ns) {
+                if best.as_ref().map(|(d, _)| distance < *d).unwrap_or(true) {
+                    best = Some((distance, item));
+                }
+            }
+        }
+        match best {
+            Some((_, item)) => Ok(Some(item.key.parse()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Parse the configured quotas
+    pub fn quotas(&self) -> Result<Vec<CloudQuota>, anyhow::Error> {
+        let mut list = Vec::new();
+        for entry in self.quota.iter().flatten() {
+            let value = CloudQuota::API_SCHEMA.parse_property_string(entry)?;
+            let item = CloudQuota::deserialize(value)?;
+            if item.owner.is_some() == item.ns.is_some() {
+                bail!("quota '{}' needs either 'owner' or 'ns'", entry);
+            }
+            if item.max_size.is_none() && item.max_objects.is_none() {
+                bail!("quota '{}' needs 'max-size' or 'max-objects'", entry);
+            }
+            if list
+                .iter()
+                .any(|other: &CloudQuota| other.owner == item.owner && other.ns == item.ns)
+            {
+                bail!("duplicate {}", item);
+            }
+            list.push(item);
+        }
+        Ok(list)
+    }
+}
+
+#[api(
+    properties: {
+        name: {
+            schema: CLOUD_TARGET_NAME_SCHEMA,
+        },
+        config: {
+            type: CloudTargetConfig,
+        },
+        "secret-key": {
+            schema: CLOUD_SECRET_KEY_SCHEMA,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+/// Cloud target properties.
+pub struct CloudTarget {
+    pub name: String,
+    // Note: The stored secret is base64 encoded
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    #[serde(with = "proxmox_serde::string_as_base64")]
+    pub secret_key: String,
+    #[serde(flatten)]
+    pub config: CloudTargetConfig,
+}
+
+#[api(
+    properties: {
+        name: {
+            schema: CLOUD_TARGET_NAME_SCHEMA,
+        },
+        config: {
+            type: CloudTargetConfig,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Cloud target properties without the secret key.
+pub struct CloudTargetWithoutSecret {
+    pub name: String,
+    #[serde(flatten)]
+    pub config: CloudTargetConfig,
+}
+
+#[api(
+    properties: {
+        "storage-classes": {
+            type: Array,
+            items: {
+                schema: CLOUD_STORAGE_CLASS_SCHEMA,
+            },
+        },
+    },
+)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Features supported by the object storage of a cloud target.
+pub struct CloudTargetCapabilities {
+    /// Objects can be locked against deletion (WORM retention).
+    pub object_lock: bool,
+    /// Storage classes which can be selected per object (empty if not supported).
+    pub storage_classes: Vec<String>,
+    /// Partial object downloads are supported.
+    pub ranged_reads: bool,
+    /// Objects can be created only if they do not exist yet.
+    pub conditional_put: bool,
+    /// Objects can be copied without downloading them.
+    pub server_side_copy: bool,
+    /// Overwritten and deleted objects are kept as noncurrent versions.
+    pub versioning: bool,
+}
+
+#[api()]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A version of an object on a versioned cloud target.
+pub struct CloudObjectVersion {
+    /// Object key, relative to the target prefix.
+    pub key: String,
+    /// Provider version ID.
+    pub version_id: String,
+    /// Time the version was created (UNIX epoch).
+    pub mtime: i64,
+    /// Object size in bytes (0 for delete markers).
+    pub size: u64,
+    /// This is the current version of the object.
+    pub is_latest: bool,
+    /// The version marks the deletion of the object.
+    pub delete_marker: bool,
+}
+
+#[api(
+    properties: {
+        etag: {
+            optional: true,
+        },
+        "storage-class": {
+            optional: true,
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// An object on a cloud target, as listed by the provider.
+pub struct CloudRawObject {
+    /// Object key, relative to the target prefix.
+    pub key: String,
+    /// Object size in bytes.
+    pub size: u64,
+    /// Last modification time (UNIX epoch).
+    pub mtime: i64,
+    /// Provider entity tag.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// Storage class of the object.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_class: Option<String>,
+}
+
+#[api(
+    properties: {
+        "user-agent": {
+            optional: true,
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// An object access not originating from PBS, found in the provider access logs.
+pub struct CloudAccessAnomaly {
+    /// Time of the access (UNIX epoch).
+    pub time: i64,
+    /// Provider operation (e.g. 'REST.GET.OBJECT').
+    pub operation: String,
+    /// Object key, relative to the target prefix.
+    pub key: String,
+    /// Remote IP address.
+    pub remote_ip: String,
+    /// Canonical user ID or IAM principal of the requester.
+    pub requester: String,
+    /// HTTP status of the response.
+    pub status: u16,
+    /// User-Agent of the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Log object containing the access.
+    pub log: String,
+}
+
+#[api(
+    properties: {
+        "default-mode": {
+            optional: true,
+        },
+        "default-days": {
+            optional: true,
+        },
+    },
+)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Object lock configuration of the bucket of a cloud target.
+pub struct CloudObjectLockConfig {
+    /// Object lock is enabled on the bucket.
+    pub enabled: bool,
+    /// Default retention mode for new objects ('GOVERNANCE' or 'COMPLIANCE').
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_mode: Option<String>,
+    /// Default retention period for new objects (days).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_days: Option<u64>,
+}
+
+#[api(
+    properties: {
+        "locked-until": {
+            optional: true,
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Object lock state of a media set.
+pub struct CloudRetentionMediaSet {
+    /// Media set UUID.
+    pub uuid: String,
+    /// Creation time of the media set (UNIX epoch).
+    pub ctime: i64,
+    /// Number of objects of the media set.
+    pub objects: u64,
+    /// Number of objects under object lock.
+    pub locked_objects: u64,
+    /// All objects are locked at least until this time (UNIX epoch).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locked_until: Option<i64>,
+}
+
+#[api(
+    properties: {
+        "locked-until": {
+            optional: true,
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Object lock state of a snapshot.
+pub struct CloudRetentionSnapshot {
+    /// The snapshot ('store:[ns/namespace/...]type/id/time').
+    pub snapshot: String,
+    /// Media set containing the snapshot.
+    pub media_set: String,
+    /// All files and chunk archives of the snapshot are locked at least
+    /// until this time (UNIX epoch).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locked_until: Option<i64>,
+}
+
+#[api(
+    properties: {
+        bucket: {
+            optional: true,
+        },
+        "lock-config": {
+            type: CloudObjectLockConfig,
+        },
+        "media-sets": {
+            type: Array,
+            items: { type: CloudRetentionMediaSet },
+        },
+        snapshots: {
+            type: Array,
+            items: { type: CloudRetentionSnapshot },
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Object lock state of all media sets and snapshots of a cloud target.
+pub struct CloudRetentionReport {
+    /// Cloud target name.
+    pub target: String,
+    /// Bucket of the target.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    /// Node which created the report.
+    pub node: String,
+    /// Creation time of the report (UNIX epoch).
+    pub generated: i64,
+    pub lock_config: CloudObjectLockConfig,
+    pub media_sets: Vec<CloudRetentionMediaSet>,
+    pub snapshots: Vec<CloudRetentionSnapshot>,
+}
+
+#[api(
+    properties: {
+        report: {
+            type: CloudRetentionReport,
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A retention report signed with the key of the node certificate.
+///
+/// The signature covers the compact JSON serialization of `report`.
+pub struct CloudRetentionAttestation {
+    pub report: CloudRetentionReport,
+    /// Signature algorithm ('sha256' with the certificate key).
+    pub algorithm: String,
+    /// Signature (base64).
+    pub signature: String,
+    /// Node certificate (PEM).
+    pub certificate: String,
+    /// SHA-256 fingerprint of the node certificate.
+    pub fingerprint: String,
+}
+
+#[api()]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// An object deletion deferred by the delete protection of a cloud target.
+pub struct CloudDeleteQueueEntry {
+    /// Object key, relative to the target prefix.
+    pub key: String,
+    /// Time the deletion was requested (UNIX epoch).
+    pub queued: i64,
+}
+
+#[api()]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Latency probe result of a cloud target endpoint.
+pub struct CloudEndpointProbe {
+    /// Endpoint (host name, optional port).
+    pub endpoint: String,
+    /// Time of the probe (UNIX epoch).
+    pub time: i64,
+    /// Best round trip time of a metadata request (milliseconds).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency: Option<u64>,
+    /// Error message if the endpoint was not reachable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The endpoint was selected.
+    pub selected: bool,
+}
+
+#[api()]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Result of a single health check of a cloud target.
+pub struct CloudHealthSample {
+    /// Time of the check (UNIX epoch).
+    pub time: i64,
+    /// Round trip time of the check (milliseconds).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency: Option<u64>,
+    /// Error message if the target was not reachable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[api(
+    properties: {
+        history: {
+            type: Array,
+            optional: true,
+            items: { type: CloudHealthSample },
+        },
+    },
+)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Health of a cloud target.
+pub struct CloudTargetHealth {
+    /// Target name.
+    pub target: String,
+    /// The last check succeeded.
+    pub reachable: bool,
+    /// Time of the last check (UNIX epoch).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_check: Option<i64>,
+    /// Time of the last successful check (UNIX epoch).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<i64>,
+    /// Error of the last failed check.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Latency of the last successful check (milliseconds).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency: Option<u64>,
+    /// Successful checks in the recorded history (percent).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub availability: Option<f64>,
+    /// Recorded checks, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<CloudHealthSample>,
+}
+
+#[api()]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Egress (download) accounting of a cloud target.
+pub struct CloudEgressStatus {
+    /// Accounting period (calendar month, UTC).
+    pub month: String,
+    /// Bytes downloaded in this month.
+    pub used: u64,
+    /// Monthly budget in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget: Option<u64>,
+}
+
+#[api()]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Number of requests sent to a cloud target, by type.
+pub struct CloudRequestCounts {
+    /// Object uploads.
+    #[serde(default)]
+    pub put: u64,
+    /// Object (and object range) downloads.
+    #[serde(default)]
+    pub get: u64,
+    /// Metadata queries.
+    #[serde(default)]
+    pub head: u64,
+    /// Object listings.
+    #[serde(default)]
+    pub list: u64,
+    /// Server side copies.
+    #[serde(default)]
+    pub copy: u64,
+    /// Object deletions.
+    #[serde(default)]
+    pub delete: u64,
+}
+
+impl CloudRequestCounts {
+    pub fn add(&mut self, other: &Self) {
+        self.put += other.put;
+        self.get += other.get;
+        self.head += other.head;
+        self.list += other.list;
+        self.copy += other.copy;
+        self.delete += other.delete;
+    }
+}
+
+#[api(
+    properties: {
+        requests: {
+            type: CloudRequestCounts,
+        },
+    },
+)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Transfer volume and requests of a cloud target.
+pub struct CloudTransferUsage {
+    /// Bytes uploaded.
+    #[serde(default)]
+    pub uploaded: u64,
+    /// Bytes downloaded.
+    #[serde(default)]
+    pub downloaded: u64,
+    #[serde(default)]
+    pub requests: CloudRequestCounts,
+    /// Objects deleted.
+    #[serde(default)]
+    pub deleted: u64,
+}
+
+impl CloudTransferUsage {
+    pub fn add(&mut self, other: &Self) {
+        self.uploaded += other.uploaded;
+        self.downloaded += other.downloaded;
+        self.requests.add(&other.requests);
+        self.deleted += other.deleted;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[api(
+    properties: {
+        month: {
+            schema: CLOUD_USAGE_MONTH_SCHEMA,
+        },
+        usage: {
+            type: CloudTransferUsage,
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Monthly transfer report of a cloud target.
+pub struct CloudUsageReport {
+    pub month: String,
+    #[serde(flatten)]
+    pub usage: CloudTransferUsage,
+}
+
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A deleted snapshot in the trash of a cloud target.
+pub struct CloudTrashEntry {
+    /// Datastore the snapshot was backed up from.
+    pub store: String,
+    #[serde(default, skip_serializing_if = "BackupNamespace::is_root")]
+    pub ns: BackupNamespace,
+    /// Snapshot path ('type/id/time').
+    pub snapshot: String,
+    /// UUID of the media set containing the snapshot.
+    pub media_set: String,
+    /// Time the snapshot was deleted (epoch).
+    pub deleted: i64,
+    /// Time the trash retention of the snapshot expires (epoch).
+    pub expires: i64,
+}
+
+#[api(
+    properties: {
+        quota: {
+            type: CloudQuota,
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Usage and remaining quota of an owner or namespace on a cloud target.
+pub struct CloudQuotaStatus {
+    #[serde(flatten)]
+    pub quota: CloudQuota,
+    /// Bytes uploaded for the snapshots on the target.
+    pub used_bytes: u64,
+    /// Objects uploaded for the snapshots on the target.
+    pub used_objects: u64,
+    /// Bytes which can still be uploaded (without size limit if unset).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining_bytes: Option<u64>,
+    /// Objects which can still be uploaded (without object limit if unset).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining_objects: Option<u64>,
+}
+
+#[api()]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Size of a snapshot file on a cloud target.
+pub struct CloudSnapshotFileSize {
+    /// File name.
+    pub filename: String,
+    /// Size of the (unencrypted) file in bytes.
+    pub size: u64,
+}
+
+#[api()]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Size of a file inside a snapshot archive.
+pub struct CloudArchiveEntrySize {
+    /// Path inside the archive.
+    pub path: String,
+    /// File size in bytes.
+    pub size: u64,
+}
+
+#[api(
+    properties: {
+        largest: {
+            type: Array,
+            items: { type: CloudArchiveEntrySize },
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Content breakdown of a snapshot archive.
+pub struct CloudArchivePreview {
+    /// Archive name.
+    pub archive: String,
+    /// Size of the archive content in bytes.
+    pub size: u64,
+    /// Number of regular files (only known for file archives with catalog).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_count: Option<u64>,
+    /// Total size of the regular files in bytes (only known with catalog).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files_size: Option<u64>,
+    /// Largest files of the archive, largest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub largest: Vec<CloudArchiveEntrySize>,
+}
+
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        files: {
+            type: Array,
+            items: { type: CloudSnapshotFileSize },
+        },
+        archives: {
+            type: Array,
+            items: { type: CloudArchivePreview },
+            optional: true,
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Sizes of a snapshot on a cloud target.
+///
+/// Stored next to the snapshot files, so that sizes can be shown without
+/// downloading the manifest.
+pub struct CloudSnapshotSummary {
+    /// Datastore the snapshot was backed up from.
+    pub store: String,
+    #[serde(default, skip_serializing_if = "BackupNamespace::is_root")]
+    pub ns: BackupNamespace,
+    /// Snapshot path ('type/id/time').
+    pub snapshot: String,
+    /// UUID of the media set containing the snapshot.
+    pub media_set: String,
+    pub files: Vec<CloudSnapshotFileSize>,
+    /// Total size of the snapshot files in bytes.
+    pub files_size: u64,
+    /// Number of chunks referenced by the snapshot.
+    pub chunk_count: u64,
+    /// Stored size of the referenced chunks in bytes, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<u64>,
+    /// The snapshot is encrypted.
+    #[serde(default)]
+    pub encrypted: bool,
+    /// Content of the archives (snapshots backed up before breakdowns
+    /// were recorded have none).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub archives: Vec<CloudArchivePreview>,
+}
+
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        archives: {
+            type: Array,
+            items: { type: CloudArchivePreview },
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Restore preview of a snapshot on a cloud target.
+pub struct CloudRestorePreview {
+    /// Datastore the snapshot was backed up from.
+    pub store: String,
+    #[serde(default, skip_serializing_if = "BackupNamespace::is_root")]
+    pub ns: BackupNamespace,
+    /// Snapshot path ('type/id/time').
+    pub snapshot: String,
+    /// UUID of the media set containing the snapshot.
+    pub media_set: String,
+    /// Bytes downloaded by a full restore, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_size: Option<u64>,
+    /// The snapshot is encrypted.
+    #[serde(default)]
+    pub encrypted: bool,
+    pub archives: Vec<CloudArchivePreview>,
+}
+
+#[api()]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Checksum of a snapshot file on a cloud target.
+pub struct CloudFileChecksum {
+    /// Object key, relative to the target prefix.
+    pub key: String,
+    /// File name.
+    pub filename: String,
+    /// Size of the unencrypted file in bytes.
+    pub size: u64,
+    /// SHA-256 of the unencrypted file (hex).
+    pub csum: String,
+}
+
+#[api()]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Checksum of a chunk archive object on a cloud target.
+pub struct CloudArchiveChecksum {
+    /// Object key, relative to the target prefix.
+    pub key: String,
+    /// Object size in bytes.
+    pub size: u64,
+    /// SHA-256 of the object (hex), if recorded when it was written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub csum: Option<String>,
+}
+
+#[api()]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Location of a chunk blob inside a chunk archive.
+pub struct CloudChunkChecksum {
+    /// Chunk digest (hex), the SHA-256 of the unencrypted chunk data.
+    pub digest: String,
+    /// Object key of the chunk archive.
+    pub archive: String,
+    /// Offset of the chunk blob inside the archive.
+    pub offset: u64,
+    /// Size of the chunk blob in bytes.
+    pub size: u64,
+}
+
+#[api(
+    properties: {
+        files: {
+            type: Array,
+            items: { type: CloudFileChecksum },
+        },
+        archives: {
+            type: Array,
+            items: { type: CloudArchiveChecksum },
+        },
+        chunks: {
+            type: Array,
+            items: { type: CloudChunkChecksum },
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Checksums of all objects of a snapshot on a cloud target.
+pub struct CloudSnapshotChecksums {
+    /// Snapshot ('store:[ns/namespace/...]type/id/time').
+    pub snapshot: String,
+    /// UUID of the media set containing the snapshot.
+    pub media_set: String,
+    /// Files and chunks are encrypted, so only sizes and archive
+    /// checksums can be verified without the key.
+    pub encrypted: bool,
+    pub files: Vec<CloudFileChecksum>,
+    pub archives: Vec<CloudArchiveChecksum>,
+    pub chunks: Vec<CloudChunkChecksum>,
+}
+
+#[api()]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Digest of a local media set catalog
+pub struct CloudCatalogDigest {
+    /// Media set UUID
+    pub uuid: String,
+    /// Creation time of the media set
+    pub ctime: i64,
+    /// SHA-256 of the catalog (hex)
+    pub digest: String,
+}
+
+#[api()]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Warm standby state of a target
+pub struct CloudStandbyStatus {
+    /// Remote the catalog follows
+    pub remote: String,
+    /// Time of the last successful catalog check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_sync: Option<i64>,
+    /// Time the local catalog last changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_change: Option<i64>,
+    /// Number of local media set catalogs
+    pub media_sets: u64,
+    /// Error of the last catalog check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+#[api()]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Class of a problem found by a consistency check of a cloud target.
+pub enum CloudFsckIssueKind {
+    /// An object referenced by the catalog does not exist.
+    Missing,
+    /// An object does not have the size recorded in the catalog.
+    SizeMismatch,
+    /// An object does not match the checksum recorded in the catalog.
+    DigestMismatch,
+    /// An object of a known media set (or the trash) not referenced by the catalog.
+    Orphaned,
+    /// A media set without catalog, left behind by an aborted backup.
+    DanglingMediaSet,
+    /// A committed media set which is not in the local catalog.
+    UnknownMediaSet,
+}
+
+serde_plain::derive_display_from_serialize!(CloudFsckIssueKind);
+
+#[api(
+    properties: {
+        kind: {
+            type: CloudFsckIssueKind,
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A problem found by a consistency check of a cloud target.
+pub struct CloudFsckIssue {
+    pub kind: CloudFsckIssueKind,
+    /// Object key (media set prefix for media set problems).
+    pub key: String,
+    /// The problem was repaired.
+    #[serde(default)]
+    pub repaired: bool,
+}
+
+#[api(
+    properties: {
+        issues: {
+            type: Array,
+            items: { type: CloudFsckIssue },
+        },
+    },
+)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Result of a consistency check of a cloud target.
+pub struct CloudFsckReport {
+    /// Time of the check (epoch).
+    pub time: i64,
+    /// Number of objects on the target.
+    pub objects: u64,
+    /// Number of objects referenced by the catalog which were checked.
+    pub checked: u64,
+    /// Number of objects whose checksum was verified.
+    pub verified: u64,
+    /// Number of verified objects checked by their provider checksum,
+    /// without downloading them.
+    #[serde(default)]
+    pub provider_checksums: u64,
+    /// Problems found.
+    pub issues: Vec<CloudFsckIssue>,
+}
+
+impl CloudFsckReport {
+    /// Number of problems which were not repaired
+    pub fn unrepaired(&self) -> usize {
+        self.issues.iter().filter(|issue| !issue.repaired).count()
+    }
+}
+
+#[api()]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Format of backup data written to a bucket by other tools.
+pub enum CloudForeignFormat {
+    /// A Proxmox Backup Server datastore copied as is (e.g. with rclone).
+    PbsDatastore,
+    /// A restic repository.
+    Restic,
+}
+
+serde_plain::derive_display_from_serialize!(CloudForeignFormat);
+
+#[api(
+    properties: {
+        format: {
+            type: CloudForeignFormat,
+        },
+    },
+)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Backup data of another tool found on a cloud target.
+pub struct CloudForeignLayout {
+    /// Key prefix of the data, relative to the target prefix ('' for the top level).
+    pub prefix: String,
+    pub format: CloudForeignFormat,
+    /// Number of snapshots found (only known for copied datastores).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshots: Option<u64>,
+    /// The snapshots can be imported into the catalog.
+    pub importable: bool,
+}
@@ -0,0 +1,89 @@
+//! Types for structured task logs of cloud workers
+
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::api;
+
+#[api()]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Format of a cloud task log
+pub enum CloudTaskLogFormat {
+    /// Plain text lines of the task log
+    #[default]
+    Text,
+    /// Structured records
+    Json,
+}
+
+#[api()]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Structured record of a cloud task
+pub struct CloudTaskRecord {
+    /// Time of the record (UNIX epoch)
+    pub time: i64,
+    /// Phase of the task, e.g. 'chunk-archive' or 'catalog'
+    pub phase: String,
+    /// Object key (relative to the target prefix)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Bytes transferred
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    /// Number of retries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u64>,
+}
+
+impl CloudTaskRecord {
+    /// New record of `phase` at the current time
+    pub fn new(phase: &str) -> Self {
+        Self {
+            time: proxmox_time::epoch_i64(),
+            phase: phase.to_string(),
+            key: None,
+            bytes: None,
+            retries: None,
+        }
+    }
+
+    pub fn key(mut self, key: &str) -> Self {
+        self.key = Some(key.to_string());
+        self
+    }
+
+    pub fn bytes(mut self, bytes: u64) -> Self {
+        self.bytes = Some(bytes);
+        self
+    }
+
+    pub fn retries(mut self, retries: u64) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+}
+
+#[api()]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Provider request of a cloud task (request tracing)
+pub struct CloudRequestTrace {
+    /// Start of the request (UNIX epoch)
+    pub time: i64,
+    /// HTTP method
+    pub method: String,
+    /// Endpoint the request was sent to
+    pub host: String,
+    /// Object key (relative to the target prefix)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// HTTP status, none if no response was received
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Duration of the request (milliseconds)
+    pub duration: u64,
+    /// Request ID assigned by the provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
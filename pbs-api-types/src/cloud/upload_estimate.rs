@@ -0,0 +1,59 @@
+//! Types for upload estimates of cloud backups
+
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::api;
+
+#[api()]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Estimated upload of a backup group
+pub struct CloudGroupUploadEstimate {
+    /// Datastore, namespace and group (`store:ns/type/id`)
+    pub group: String,
+    /// Number of snapshots which would be written
+    pub snapshots: u64,
+    /// Plain size of all chunk references (in bytes)
+    pub logical_bytes: u64,
+    /// Plain size of the chunks which would be uploaded (in bytes)
+    pub new_bytes: u64,
+    /// Stored size of the chunks which would be uploaded (in bytes)
+    pub upload_bytes: u64,
+}
+
+#[api(
+    properties: {
+        groups: {
+            type: Array,
+            items: {
+                type: CloudGroupUploadEstimate,
+            },
+        },
+    },
+)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Estimated upload of a backup to a cloud target
+pub struct CloudUploadEstimate {
+    /// Number of snapshots which would be written
+    pub snapshots: u64,
+    /// Number of selected snapshots already on the target
+    pub present_snapshots: u64,
+    /// Number of chunk references
+    pub chunks: u64,
+    /// Plain size of all chunk references (in bytes)
+    pub logical_bytes: u64,
+    /// Number of distinct chunks which would be uploaded
+    pub new_chunks: u64,
+    /// Plain size of the chunks which would be uploaded (in bytes)
+    pub new_bytes: u64,
+    /// Plain size of the chunk references which would not be uploaded (in bytes)
+    pub reused_bytes: u64,
+    /// Stored size of the chunks which would be uploaded (in bytes)
+    pub upload_bytes: u64,
+    /// Estimated transfer time at the given upload rate (in seconds)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_time: Option<u64>,
+    /// Estimate per backup group
+    pub groups: Vec<CloudGroupUploadEstimate>,
+}
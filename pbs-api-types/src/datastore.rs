@@ -10,7 +10,7 @@ use proxmox_schema::{
 };
 
 use crate::{
-    Authid, CryptMode, Fingerprint, GroupFilter, MaintenanceMode, Userid,
+    Authid, CloudSnapshotCopy, CryptMode, Fingerprint, GroupFilter, MaintenanceMode, Userid,
     CLOUD_DATASTORE_NOTIFY_STRING_SCHEMA, GC_SCHEDULE_SCHEMA, PROXMOX_SAFE_ID_FORMAT,
     PRUNE_SCHEDULE_SCHEMA, SHA256_HEX_REGEX, SINGLE_LINE_COMMENT_SCHEMA, UPID,
 };
@@ -35,6 +35,8 @@ const_regex! {
     pub CLOUD_GROUP_OR_SNAPSHOT_PATH_REGEX = concat!(r"^", CLOUD_GROUP_OR_SNAPSHOT_PATH_REGEX_STR!(), r"$");
 
     pub CLOUD_DATASTORE_MAP_REGEX = concat!(r"^(?:", PROXMOX_SAFE_ID_REGEX_STR!(), r"=)?", PROXMOX_SAFE_ID_REGEX_STR!(), r"$");
+
+    pub CLOUD_ZFS_SNAPSHOT_NAME_TEMPLATE_REGEX = r"^[A-Za-z0-9_\-.:{}]+$";
 }
 
 pub const CHUNK_DIGEST_FORMAT: ApiStringFormat = ApiStringFormat::Pattern(&SHA256_HEX_REGEX);
@@ -228,6 +230,51 @@ pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore
     ))
     .schema();
 
+pub const CLOUD_ZFS_SNAPSHOT_NAME_TEMPLATE_SCHEMA: Schema = StringSchema::new(
+    "Name of the temporary ZFS snapshot. The placeholders {store}, {target} and {time} \
+     (UTC, e.g. 20240131T120000Z) are replaced.")
+    .format(&ApiStringFormat::Pattern(&CLOUD_ZFS_SNAPSHOT_NAME_TEMPLATE_REGEX))
+    .min_length(1)
+    .max_length(128)
+    .schema();
+
+/// Default name of the temporary ZFS snapshot of a cloud backup
+pub const CLOUD_ZFS_SNAPSHOT_DEFAULT_NAME_TEMPLATE: &str = "pbs-cloud-{target}-{time}";
+
+#[api(
+    properties: {
+        "name-template": {
+            schema: CLOUD_ZFS_SNAPSHOT_NAME_TEMPLATE_SCHEMA,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+/// Direct-from-ZFS-snapshot cloud backup options
+pub struct CloudZfsSnapshotOptions {
+    /// Read cloud backups from a temporary snapshot of the ZFS dataset of the datastore
+    #[serde(default)]
+    pub enable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_template: Option<String>,
+}
+
+impl CloudZfsSnapshotOptions {
+    pub fn name_template(&self) -> &str {
+        self.name_template
+            .as_deref()
+            .unwrap_or(CLOUD_ZFS_SNAPSHOT_DEFAULT_NAME_TEMPLATE)
+    }
+}
+
+pub const DATASTORE_CLOUD_ZFS_SNAPSHOT_STRING_SCHEMA: Schema =
+    StringSchema::new("Direct-from-ZFS-snapshot cloud backup options")
+    .format(&ApiStringFormat::PropertyString(
+        &CloudZfsSnapshotOptions::API_SCHEMA,
+    ))
+    .schema();
+
 #[api(
     properties: {
         name: {
@@ -273,6 +320,10 @@ pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore
             format: &ApiStringFormat::PropertyString(&MaintenanceMode::API_SCHEMA),
             type: String,
         },
+        "cloud-zfs-snapshot": {
+            optional: true,
+            schema: DATASTORE_CLOUD_ZFS_SNAPSHOT_STRING_SCHEMA,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Updater, Clone, PartialEq)]
@@ -316,6 +367,10 @@ pub struct DataStoreConfig {
     /// Maintenance mode, type is either 'offline' or 'read-only', message should be enclosed in "
     #[serde(skip_serializing_if = "Option::is_none")]
     pub maintenance_mode: Option<String>,
+
+    /// Direct-from-ZFS-snapshot cloud backup options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_zfs_snapshot: Option<String>,
 }
 
 impl DataStoreConfig {
@@ -332,6 +387,7 @@ impl DataStoreConfig {
             notify: None,
             tuning: None,
             maintenance_mode: None,
+            cloud_zfs_snapshot: None,
         }
     }
 
@@ -1095,6 +1151,11 @@ impl std::str::FromStr for BackupPart {
             type: Authid,
             optional: true,
         },
+        "cloud-copies": {
+            type: Array,
+            items: { type: CloudSnapshotCopy },
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
@@ -1123,6 +1184,9 @@ pub struct SnapshotListItem {
     /// Protection from prunes
     #[serde(default)]
     pub protected: bool,
+    /// Copies on cloud targets (newest copy per target)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cloud_copies: Vec<CloudSnapshotCopy>,
 }
 
 #[api(
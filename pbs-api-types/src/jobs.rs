@@ -1,16 +1,20 @@
 use std::str::FromStr;
 
-use anyhow::bail;
+use anyhow::{bail, format_err};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use proxmox_human_byte::HumanByte;
 use proxmox_schema::*;
 
 use crate::{
-    Authid, BackupNamespace, BackupType, RateLimitConfig, Userid, BACKUP_GROUP_SCHEMA,
-    BACKUP_NAMESPACE_SCHEMA, DATASTORE_SCHEMA, DRIVE_NAME_SCHEMA, MEDIA_POOL_NAME_SCHEMA,
-    NS_MAX_DEPTH_REDUCED_SCHEMA, PROXMOX_SAFE_ID_FORMAT, REMOTE_ID_SCHEMA,
-    SINGLE_LINE_COMMENT_SCHEMA,
+    Authid, BackupNamespace, BackupType, CloudTargetConfig, RateLimitConfig, Role, Userid,
+    ACL_PATH_SCHEMA, ACL_PROPAGATE_SCHEMA, BACKUP_GROUP_SCHEMA, BACKUP_NAMESPACE_SCHEMA,
+    CLOUD_OBJECT_TAG_LIST_SCHEMA, CLOUD_STORAGE_CLASS_SCHEMA, CLOUD_TAG_LIST_SCHEMA,
+    CLOUD_TAG_SCHEMA, CLOUD_TARGET_NAME_SCHEMA, DAILY_DURATION_FORMAT, DATASTORE_SCHEMA,
+    DRIVE_NAME_SCHEMA, HTTP_URL_SCHEMA, MEDIA_POOL_NAME_SCHEMA, NS_MAX_DEPTH_REDUCED_SCHEMA,
+    PROXMOX_SAFE_ID_FORMAT, REALM_ID_SCHEMA, REMOTE_ID_SCHEMA, SINGLE_LINE_COMMENT_FORMAT,
+    SINGLE_LINE_COMMENT_SCHEMA, UPID_SCHEMA,
 };
 
 const_regex! {
@@ -19,6 +23,10 @@ const_regex! {
     pub CLOUD_VERIFICATION_JOB_WORKER_ID_REGEX = concat!(r"^(", PROXMOX_SAFE_ID_REGEX_STR!(), r"):");
     /// Regex for sync jobs '(REMOTE|\-):REMOTE_CLOUD_DATASTORE:LOCAL_CLOUD_DATASTORE:(?:LOCAL_NS_ANCHOR:)ACTUAL_JOB_ID'
     pub CLOUD_SYNC_JOB_WORKER_ID_REGEX = concat!(r"^(", PROXMOX_SAFE_ID_REGEX_STR!(), r"|\-):(", PROXMOX_SAFE_ID_REGEX_STR!(), r"):(", PROXMOX_SAFE_ID_REGEX_STR!(), r")(?::(", BACKUP_NS_RE!(), r"))?:");
+    /// Regex for cloud job template values, allowing placeholders
+    pub CLOUD_JOB_TEMPLATE_VALUE_REGEX = r"^(?:[A-Za-z0-9_.\-/]|\{(?:template|store|target|ns)\})*$";
+    /// Regex for bulk job sources 'STORE[:NS]'
+    pub CLOUD_BULK_JOB_SOURCE_REGEX = concat!(r"^(", PROXMOX_SAFE_ID_REGEX_STR!(), r")(?::(", BACKUP_NS_RE!(), r"))?$");
 }
 
 pub const CLOUD_JOB_ID_SCHEMA: Schema = StringSchema::new("Cloud Job ID.")
@@ -27,12 +35,50 @@ pub const CLOUD_JOB_ID_SCHEMA: Schema = StringSchema::new("Cloud Job ID.")
     .max_length(32)
     .schema();
 
-pub const CLOUD_SYNC_SCHEDULE_SCHEMA: Schema = StringSchema::new("Run cloud sync job at specified schedule.")
-    .format(&ApiStringFormat::VerifyFn(
-        proxmox_time::verify_calendar_event,
-    ))
-    .type_text("<calendar-event>")
-    .schema();
+pub const CLOUD_RUN_AFTER_SCHEMA: Schema = StringSchema::new(
+    "Run the job after each successful run of this cloud backup or replication job.",
+)
+.format(&PROXMOX_SAFE_ID_FORMAT)
+.min_length(3)
+.max_length(32)
+.schema();
+
+pub const CLOUD_SYNC_SCHEDULE_SCHEMA: Schema =
+    StringSchema::new("Run cloud sync job at specified schedule.")
+        .format(&ApiStringFormat::VerifyFn(
+            proxmox_time::verify_calendar_event,
+        ))
+        .type_text("<calendar-event>")
+        .schema();
+
+pub const CLOUD_TIME_SPAN_FORMAT: ApiStringFormat = ApiStringFormat::VerifyFn(|s| {
+    s.parse::<proxmox_time::TimeSpan>()?;
+    Ok(())
+});
+
+pub const CLOUD_JOB_SPLAY_SCHEMA: Schema = StringSchema::new(
+    "Delay scheduled runs by a fixed offset of up to this time span, derived from the job ID \
+     (e.g. '30m'). Spreads jobs sharing a schedule over time.",
+)
+.format(&CLOUD_TIME_SPAN_FORMAT)
+.type_text("<time-span>")
+.schema();
+
+pub const CLOUD_JOB_RETRY_SCHEMA: Schema = IntegerSchema::new(
+    "Retry a failed run this many times, before the job is considered failed until its next \
+     scheduled run.",
+)
+.minimum(0)
+.maximum(10)
+.default(0)
+.schema();
+
+pub const CLOUD_JOB_RETRY_DELAY_SCHEMA: Schema = StringSchema::new(
+    "Delay before the first retry of a failed run (e.g. '10m'), doubled for each further retry.",
+)
+.format(&CLOUD_TIME_SPAN_FORMAT)
+.type_text("<time-span>")
+.schema();
 
 pub const CLOUD_GC_SCHEDULE_SCHEMA: Schema =
     StringSchema::new("Run garbage collection job at specified schedule.")
@@ -42,12 +88,13 @@ pub const CLOUD_GC_SCHEDULE_SCHEMA: Schema =
         .type_text("<calendar-event>")
         .schema();
 
-pub const CLOUD_PRUNE_SCHEDULE_SCHEMA: Schema = StringSchema::new("Run prune job at specified schedule.")
-    .format(&ApiStringFormat::VerifyFn(
-        proxmox_time::verify_calendar_event,
-    ))
-    .type_text("<calendar-event>")
-    .schema();
+pub const CLOUD_PRUNE_SCHEDULE_SCHEMA: Schema =
+    StringSchema::new("Run prune job at specified schedule.")
+        .format(&ApiStringFormat::VerifyFn(
+            proxmox_time::verify_calendar_event,
+        ))
+        .type_text("<calendar-event>")
+        .schema();
 
 pub const CLOUD_VERIFICATION_SCHEDULE_SCHEMA: Schema =
     StringSchema::new("Run verify job at specified schedule.")
@@ -89,6 +136,70 @@ pub const REMOVE_VANISHED_CLOUD_BACKUPS_SCHEMA: Schema = BooleanSchema::new(
 )]
 #[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
 #[serde(rename_all = "kebab-case")]
+/// Job Scheduling Status
+pub struct JobScheduleStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_run: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_run_state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_run_upid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_run_endtime: Option<i64>,
+}
+
+#[api(
+    properties: {
+        "next-run": {
+            description: "Estimated time of the next run (UNIX epoch).",
+            optional: true,
+            type: Integer,
+        },
+        "last-run-state": {
+            description: "Result of the last run.",
+            optional: true,
+            type: String,
+        },
+        "last-run-upid": {
+            description: "Task UPID of the last run.",
+            optional: true,
+            type: String,
+        },
+        "last-run-endtime": {
+            description: "End time of the last run.",
+            optional: true,
+            type: Integer,
+        },
+        "triggered-by": {
+            description: "The job whose completion triggered the last run (see 'run-after').",
+            optional: true,
+            type: String,
+        },
+        "splay-offset": {
+            description: "Delay of scheduled runs in seconds (see 'splay'), included in 'next-run'.",
+            optional: true,
+            type: Integer,
+        },
+        paused: {
+            description: "The job is paused and not scheduled.",
+            optional: true,
+            default: false,
+            type: Boolean,
+        },
+        "retry-attempts": {
+            description: "Number of retries of the failed run (see 'retry-on-failure').",
+            optional: true,
+            type: Integer,
+        },
+        "next-retry": {
+            description: "Time of the next retry of the failed run (UNIX epoch).",
+            optional: true,
+            type: Integer,
+        },
+    }
+)]
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
 /// Cloud Job Scheduling Status
 pub struct CloudJobScheduleStatus {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -99,6 +210,32 @@ pub struct CloudJobScheduleStatus {
     pub last_run_upid: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub last_run_endtime: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub triggered_by: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub splay_offset: Option<i64>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub paused: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_attempts: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_retry: Option<i64>,
+}
+
+impl From<JobScheduleStatus> for CloudJobScheduleStatus {
+    fn from(status: JobScheduleStatus) -> Self {
+        Self {
+            next_run: status.next_run,
+            last_run_state: status.last_run_state,
+            last_run_upid: status.last_run_upid,
+            last_run_endtime: status.last_run_endtime,
+            triggered_by: None,
+            splay_offset: None,
+            paused: false,
+            retry_attempts: None,
+            next_retry: None,
+        }
+    }
 }
 
 #[api()]
@@ -151,40 +288,1261 @@ pub struct CloudDatastoreNotify {
     pub prune: Option<CloudNotify>,
 }
 
-pub const CLOUD_DATASTORE_NOTIFY_STRING_SCHEMA: Schema = StringSchema::new(
-    "Cloud datastore notification setting, enum can be one of 'always', 'never', or 'error'.",
-)
-.format(&ApiStringFormat::PropertyString(
-    &CloudDatastoreNotify::API_SCHEMA,
-))
-.schema();
+pub const CLOUD_DATASTORE_NOTIFY_STRING_SCHEMA: Schema = StringSchema::new(
+    "Cloud datastore notification setting, enum can be one of 'always', 'never', or 'error'.",
+)
+.format(&ApiStringFormat::PropertyString(
+    &CloudDatastoreNotify::API_SCHEMA,
+))
+.schema();
+
+pub const IGNORE_VERIFIED_CLOUD_BACKUPS_SCHEMA: Schema = BooleanSchema::new(
+    "Do not verify cloud backups that are already verified if their verification is not outdated.",
+)
+.default(true)
+.schema();
+
+pub const CLOUD_VERIFICATION_OUTDATED_AFTER_SCHEMA: Schema =
+    IntegerSchema::new("Days after which a cloud verification becomes outdated. (0 is deprecated)")
+        .minimum(0)
+        .schema();
+
+#[api(
+    properties: {
+        id: {
+            schema: CLOUD_JOB_ID_SCHEMA,
+        },
+        target: {
+            schema: CLOUD_TARGET_NAME_SCHEMA,
+        },
+        store: {
+            schema: DATASTORE_SCHEMA,
+        },
+        "ignore-verified": {
+            optional: true,
+            schema: IGNORE_VERIFIED_CLOUD_BACKUPS_SCHEMA,
+        },
+        "outdated-after": {
+            optional: true,
+            schema: CLOUD_VERIFICATION_OUTDATED_AFTER_SCHEMA,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+        schedule: {
+            optional: true,
+            schema: CLOUD_VERIFICATION_SCHEDULE_SCHEMA,
+        },
+        ns: {
+            optional: true,
+            schema: BACKUP_NAMESPACE_SCHEMA,
+        },
+        "max-depth": {
+            optional: true,
+            schema: crate::NS_MAX_DEPTH_SCHEMA,
+        },
+        disable: {
+            type: Boolean,
+            optional: true,
+            default: false,
+        },
+    }
+)]
+#[derive(Serialize, Deserialize, Updater, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Cloud Verification Job
+///
+/// Verifies the copies of a datastore's snapshots on a cloud target.
+pub struct CloudVerificationJobConfig {
+    /// Unique ID to address this job
+    #[updater(skip)]
+    pub id: String,
+    /// The cloud target holding the copies
+    pub target: String,
+    /// The datastore whose snapshots are verified
+    pub store: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// if not set to false, check the age of the last snapshot verification to filter
+    /// out recent ones, depending on 'outdated_after' configuration.
+    pub ignore_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Reverify snapshots after X days, never if 0. Ignored if 'ignore_verified' is false.
+    pub outdated_after: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// when to schedule this job in calendar event notation
+    pub schedule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    /// on which backup namespace to run the verification recursively
+    pub ns: Option<BackupNamespace>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    /// how deep the verify should go from the `ns` level downwards. Passing 0 verifies only the
+    /// snapshots on the same level as the passed `ns`, or the datastore root if none.
+    pub max_depth: Option<usize>,
+    /// Disable this job.
+    #[serde(default, skip_serializing_if = "is_false")]
+    #[updater(serde(skip_serializing_if = "Option::is_none"))]
+    pub disable: bool,
+}
+
+#[api(
+    properties: {
+        config: {
+            type: CloudVerificationJobConfig,
+        },
+        status: {
+            type: CloudJobScheduleStatus,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Status of Cloud Verification Job
+pub struct CloudVerificationJobStatus {
+    #[serde(flatten)]
+    pub config: CloudVerificationJobConfig,
+    #[serde(flatten)]
+    pub status: CloudJobScheduleStatus,
+}
+
+#[api(
+    properties: {
+        id: {
+            schema: CLOUD_JOB_ID_SCHEMA,
+        },
+        target: {
+            schema: CLOUD_TARGET_NAME_SCHEMA,
+        },
+        store: {
+            schema: DATASTORE_SCHEMA,
+        },
+        ns: {
+            optional: true,
+            schema: BACKUP_NAMESPACE_SCHEMA,
+        },
+        "max-depth": {
+            optional: true,
+            schema: crate::NS_MAX_DEPTH_SCHEMA,
+        },
+        keep: {
+            type: KeepOptions,
+        },
+        schedule: {
+            schema: CLOUD_PRUNE_SCHEDULE_SCHEMA,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+        disable: {
+            type: Boolean,
+            optional: true,
+            default: false,
+        },
+    }
+)]
+#[derive(Serialize, Deserialize, Updater, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Cloud Prune Job
+///
+/// Prunes the copies of a datastore's snapshots on a cloud target, the
+/// local datastore is not changed. Pruned snapshots are moved to the trash
+/// of the target.
+pub struct CloudPruneJobConfig {
+    /// Unique ID to address this job
+    #[updater(skip)]
+    pub id: String,
+    /// The cloud target holding the copies
+    pub target: String,
+    /// The datastore whose snapshots are pruned
+    pub store: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    /// on which backup namespace to prune recursively
+    pub ns: Option<BackupNamespace>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    /// how deep the prune should go from the `ns` level downwards (0 prunes only `ns`)
+    pub max_depth: Option<usize>,
+    #[serde(flatten)]
+    pub keep: KeepOptions,
+    /// when to schedule this job in calendar event notation
+    pub schedule: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Disable this job.
+    #[serde(default, skip_serializing_if = "is_false")]
+    #[updater(serde(skip_serializing_if = "Option::is_none"))]
+    pub disable: bool,
+}
+
+#[api(
+    properties: {
+        config: {
+            type: CloudPruneJobConfig,
+        },
+        status: {
+            type: CloudJobScheduleStatus,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Status of Cloud Prune Job
+pub struct CloudPruneJobStatus {
+    #[serde(flatten)]
+    pub config: CloudPruneJobConfig,
+    #[serde(flatten)]
+    pub status: CloudJobScheduleStatus,
+}
+
+#[api(
+    properties: {
+        id: {
+            schema: JOB_ID_SCHEMA,
+        },
+        setup: {
+            type: TapeBackupJobSetup,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+        schedule: {
+            optional: true,
+            schema: SYNC_SCHEDULE_SCHEMA,
+        },
+    }
+)]
+#[derive(Serialize, Deserialize, Clone, Updater, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Tape Backup Job
+pub struct TapeBackupJobConfig {
+    #[updater(skip)]
+    pub id: String,
+    #[serde(flatten)]
+    pub setup: TapeBackupJobSetup,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+}
+
+#[api(
+    properties: {
+        config: {
+            type: TapeBackupJobConfig,
+        },
+        status: {
+            type: JobScheduleStatus,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Status of Tape Backup Job
+pub struct TapeBackupJobStatus {
+    #[serde(flatten)]
+    pub config: TapeBackupJobConfig,
+    #[serde(flatten)]
+    pub status: JobScheduleStatus,
+    /// Next tape used (best guess)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_media_label: Option<String>,
+}
+
+pub const CLOUD_MAX_CHAIN_LENGTH_SCHEMA: Schema = IntegerSchema::new(
+    "Create a synthetic full media set before the backup when the current chain \
+     contains this many media sets.",
+)
+.minimum(1)
+.schema();
+
+pub const CLOUD_RETENTION_LOCK_SCHEMA: Schema = IntegerSchema::new(
+    "Lock uploaded backup data against deletion for this many days \
+     (requires object lock support on the target).",
+)
+.minimum(1)
+.maximum(36500)
+.schema();
+
+pub const CLOUD_JOB_WINDOW_SCHEMA: Schema =
+    StringSchema::new("Time window (e.g. 'mon..fri 20:00-23:59').")
+        .format(&DAILY_DURATION_FORMAT)
+        .schema();
+
+pub const CLOUD_ALLOWED_WINDOW_LIST_SCHEMA: Schema = ArraySchema::new(
+    "Time windows in which the job may transfer data (default: always).",
+    &CLOUD_JOB_WINDOW_SCHEMA,
+)
+.schema();
+
+pub const CLOUD_BLACKOUT_WINDOW_LIST_SCHEMA: Schema = ArraySchema::new(
+    "Time windows in which the job must not transfer data (takes precedence \
+     over 'allowed-window').",
+    &CLOUD_JOB_WINDOW_SCHEMA,
+)
+.schema();
+
+#[api()]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Order in which a backup job transfers the selected snapshots
+pub enum CloudSnapshotOrder {
+    /// Group by group, oldest snapshot first
+    #[default]
+    OldestFirst,
+    /// Newest snapshots of all groups first
+    NewestFirst,
+    /// Smallest snapshots of all groups first
+    SmallestFirst,
+}
+serde_plain::derive_display_from_serialize!(CloudSnapshotOrder);
+
+#[api()]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// What a backup job does when backing up a snapshot fails
+pub enum CloudErrorPolicy {
+    /// Continue with the remaining snapshots
+    #[default]
+    Continue,
+    /// Skip the remaining snapshots of the group, continue with other groups
+    AbortGroup,
+    /// Stop the job, keeping the snapshots written so far
+    AbortJob,
+}
+serde_plain::derive_display_from_serialize!(CloudErrorPolicy);
+
+pub const CLOUD_LOCAL_IO_LATENCY_SCHEMA: Schema = IntegerSchema::new(
+    "Throttle uploads while other operations use the datastore and reading \
+     chunks takes longer than this (milliseconds).",
+)
+.minimum(1)
+.maximum(60_000)
+.schema();
+
+#[api()]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// What a running job does when its time window closes
+pub enum CloudWindowAction {
+    /// Wait until the next window opens, then continue
+    #[default]
+    Pause,
+    /// Finish the current snapshot and stop, the next run continues
+    Stop,
+}
+
+#[api(
+    properties: {
+        store: {
+            schema: DATASTORE_SCHEMA,
+        },
+        target: {
+            schema: CLOUD_TARGET_NAME_SCHEMA,
+            optional: true,
+        },
+        "latest-only": {
+            description: "Backup latest snapshots only.",
+            type: bool,
+            optional: true,
+        },
+        "notify-user": {
+            optional: true,
+            type: Userid,
+        },
+        "group-filter": {
+            schema: GROUP_FILTER_LIST_SCHEMA,
+            optional: true,
+        },
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        "max-depth": {
+            schema: crate::NS_MAX_DEPTH_SCHEMA,
+            optional: true,
+        },
+        "max-chain-length": {
+            schema: CLOUD_MAX_CHAIN_LENGTH_SCHEMA,
+            optional: true,
+        },
+        "storage-class": {
+            schema: CLOUD_STORAGE_CLASS_SCHEMA,
+            optional: true,
+        },
+        "retention-lock": {
+            schema: CLOUD_RETENTION_LOCK_SCHEMA,
+            optional: true,
+        },
+        "transfer-last": {
+            schema: TRANSFER_LAST_SCHEMA,
+            optional: true,
+        },
+        "object-tag": {
+            schema: CLOUD_OBJECT_TAG_LIST_SCHEMA,
+            optional: true,
+        },
+        "allowed-window": {
+            schema: CLOUD_ALLOWED_WINDOW_LIST_SCHEMA,
+            optional: true,
+        },
+        "blackout-window": {
+            schema: CLOUD_BLACKOUT_WINDOW_LIST_SCHEMA,
+            optional: true,
+        },
+        "window-action": {
+            type: CloudWindowAction,
+            optional: true,
+        },
+        "zfs-change-detection": {
+            description: "Use 'zfs diff' to skip backup groups unchanged since the last \
+                successful run (datastores on ZFS only).",
+            type: bool,
+            optional: true,
+        },
+        "local-io-latency": {
+            schema: CLOUD_LOCAL_IO_LATENCY_SCHEMA,
+            optional: true,
+        },
+        "local-io-min-rate": {
+            type: HumanByte,
+            optional: true,
+        },
+        order: {
+            type: CloudSnapshotOrder,
+            optional: true,
+        },
+        "on-error": {
+            type: CloudErrorPolicy,
+            optional: true,
+        },
+        "request-trace": {
+            description: "Record the metadata of all provider requests (for support cases).",
+            type: bool,
+            optional: true,
+        },
+    }
+)]
+#[derive(Serialize, Deserialize, Clone, Updater, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Cloud Backup Job Setup
+pub struct CloudBackupJobSetup {
+    pub store: String,
+    /// Defaults to the target of the datastore's cloud mapping
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latest_only: Option<bool>,
+    /// Send job email notification to this user
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_user: Option<Userid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_filter: Option<Vec<GroupFilter>>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub ns: Option<BackupNamespace>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_chain_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retention_lock: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_last: Option<usize>,
+    /// Object tags added to the tags of the target (same keys are replaced)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub object_tag: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_window: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blackout_window: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window_action: Option<CloudWindowAction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zfs_change_detection: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_io_latency: Option<u64>,
+    /// Upload rate guaranteed while local I/O is protected (default 10 MB/s)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_io_min_rate: Option<HumanByte>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<CloudSnapshotOrder>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_error: Option<CloudErrorPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_trace: Option<bool>,
+}
+
+pub const CLOUD_HOOK_COMMAND_SCHEMA: Schema =
+    StringSchema::new("Command run with '/bin/sh -c', gets the job information as JSON on stdin.")
+        .format(&SINGLE_LINE_COMMENT_FORMAT)
+        .min_length(1)
+        .max_length(1024)
+        .schema();
+
+pub const CLOUD_HOOK_USER_SCHEMA: Schema =
+    StringSchema::new("System user running the hook commands (default: the user running the job).")
+        .format(&PROXMOX_SAFE_ID_FORMAT)
+        .min_length(1)
+        .max_length(32)
+        .schema();
+
+pub const CLOUD_HOOK_TIMEOUT_SCHEMA: Schema =
+    IntegerSchema::new("Timeout of each hook command in seconds.")
+        .minimum(1)
+        .maximum(86400)
+        .default(300)
+        .schema();
+
+#[api()]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// What happens if a hook command fails or times out
+pub enum CloudHookFailure {
+    /// Fail the job (a failing pre-hook skips the job)
+    #[default]
+    Abort,
+    /// Log a warning and continue
+    Warn,
+}
+
+#[api(
+    properties: {
+        "pre-hook": {
+            schema: CLOUD_HOOK_COMMAND_SCHEMA,
+            optional: true,
+        },
+        "post-hook": {
+            schema: CLOUD_HOOK_COMMAND_SCHEMA,
+            optional: true,
+        },
+        "hook-user": {
+            schema: CLOUD_HOOK_USER_SCHEMA,
+            optional: true,
+        },
+        "hook-timeout": {
+            schema: CLOUD_HOOK_TIMEOUT_SCHEMA,
+            optional: true,
+        },
+        "hook-failure": {
+            type: CloudHookFailure,
+            optional: true,
+        },
+    }
+)]
+#[derive(Serialize, Deserialize, Clone, Default, Updater, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Commands run before and after a cloud job
+pub struct CloudJobHooks {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_hook: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_hook: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hook_user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hook_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hook_failure: Option<CloudHookFailure>,
+}
+
+#[api(
+    properties: {
+        id: {
+            schema: JOB_ID_SCHEMA,
+        },
+        setup: {
+            type: CloudBackupJobSetup,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+        schedule: {
+            optional: true,
+            schema: CLOUD_SYNC_SCHEDULE_SCHEMA,
+        },
+        splay: {
+            optional: true,
+            schema: CLOUD_JOB_SPLAY_SCHEMA,
+        },
+        "retry-on-failure": {
+            optional: true,
+            schema: CLOUD_JOB_RETRY_SCHEMA,
+        },
+        "retry-delay": {
+            optional: true,
+            schema: CLOUD_JOB_RETRY_DELAY_SCHEMA,
+        },
+        "run-after": {
+            optional: true,
+            schema: CLOUD_RUN_AFTER_SCHEMA,
+        },
+        hooks: {
+            type: CloudJobHooks,
+        },
+        template: {
+            optional: true,
+            schema: CLOUD_JOB_TEMPLATE_ID_SCHEMA,
+        },
+        disable: {
+            type: Boolean,
+            optional: true,
+            default: false,
+        },
+        tags: {
+            schema: CLOUD_TAG_LIST_SCHEMA,
+            optional: true,
+        },
+    }
+)]
+#[derive(Serialize, Deserialize, Clone, Updater, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Cloud Backup Job
+pub struct CloudBackupJobConfig {
+    #[updater(skip)]
+    pub id: String,
+    #[serde(flatten)]
+    pub setup: CloudBackupJobSetup,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub splay: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_on_failure: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_delay: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_after: Option<String>,
+    #[serde(flatten)]
+    pub hooks: CloudJobHooks,
+    /// The template this job was created from
+    #[updater(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Disable this job.
+    #[serde(default, skip_serializing_if = "is_false")]
+    #[updater(serde(skip_serializing_if = "Option::is_none"))]
+    pub disable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+}
+
+impl CloudBackupJobConfig {
+    /// Returns true if the job is tagged with `tag`
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().flatten().any(|t| t == tag)
+    }
+}
+
+/// Oldest snapshot time considered by a cloud backup, either a point in
+/// time or a time span relative to the start of the backup.
+#[derive(Clone, Debug)]
+pub enum CloudBackupSince {
+    /// Absolute time (epoch)
+    Time(i64),
+    /// Time span before now
+    Ago(proxmox_time::TimeSpan),
+}
+
+impl CloudBackupSince {
+    /// Returns the epoch of the oldest snapshot to consider.
+    pub fn resolve(&self, now: i64) -> i64 {
+        match self {
+            CloudBackupSince::Time(epoch) => *epoch,
+            CloudBackupSince::Ago(span) => now - f64::from(span.clone()) as i64,
+        }
+    }
+}
+
+impl FromStr for CloudBackupSince {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(epoch) = proxmox_time::parse_rfc3339(s) {
+            return Ok(CloudBackupSince::Time(epoch));
+        }
+        match s.parse() {
+            Ok(span) => Ok(CloudBackupSince::Ago(span)),
+            Err(_) => bail!("expected RFC3339 timestamp or time span, got '{}'", s),
+        }
+    }
+}
+
+pub const CLOUD_BACKUP_SINCE_FORMAT: ApiStringFormat = ApiStringFormat::VerifyFn(|s| {
+    CloudBackupSince::from_str(s)?;
+    Ok(())
+});
+
+pub const CLOUD_BACKUP_SINCE_SCHEMA: Schema = StringSchema::new(
+    "Only consider snapshots taken at or after this time (RFC3339 timestamp, \
+     or a time span before now like '7d').",
+)
+.format(&CLOUD_BACKUP_SINCE_FORMAT)
+.type_text("<rfc3339|time-span>")
+.schema();
+
+#[api(
+    properties: {
+        target: {
+            schema: CLOUD_TARGET_NAME_SCHEMA,
+            optional: true,
+        },
+        store: {
+            schema: DATASTORE_SCHEMA,
+            optional: true,
+        },
+        tag: {
+            schema: CLOUD_TAG_SCHEMA,
+            optional: true,
+        },
+        "target-tag": {
+            schema: CLOUD_TAG_SCHEMA,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Selects cloud backup jobs for bulk operations. All given properties
+/// must match, an empty selector matches every job.
+pub struct CloudBackupJobSelector {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    /// Only select jobs with this tag
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Only select jobs whose target has this tag
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_tag: Option<String>,
+}
+
+impl CloudBackupJobSelector {
+    /// Check if `job` is selected. `target_name` is the job's target
+    /// (inherited from the cloud mapping if the job has none), `target`
+    /// its configuration, if it exists.
+    pub fn matches(
+        &self,
+        job: &CloudBackupJobConfig,
+        target_name: Option<&str>,
+        target: Option<&CloudTargetConfig>,
+    ) -> bool {
+        if let Some(ref target) = self.target {
+            if Some(target.as_str()) != target_name {
+                return false;
+            }
+        }
+        if let Some(ref store) = self.store {
+            if *store != job.setup.store {
+                return false;
+            }
+        }
+        if let Some(ref tag) = self.tag {
+            if !job.has_tag(tag) {
+                return false;
+            }
+        }
+        if let Some(ref tag) = self.target_tag {
+            if !target.map_or(false, |target| target.has_tag(tag)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[api(
+    properties: {
+        id: {
+            schema: JOB_ID_SCHEMA,
+        },
+        upid: {
+            optional: true,
+            schema: UPID_SCHEMA,
+        },
+        message: {
+            optional: true,
+            type: String,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Result of a bulk operation for a single cloud backup job
+pub struct CloudBulkJobResult {
+    pub id: String,
+    /// Whether the operation was applied to this job
+    pub success: bool,
+    /// The task started for this job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upid: Option<String>,
+    /// Why the job was skipped or failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+pub const CLOUD_BULK_JOB_SOURCE_FORMAT: ApiStringFormat =
+    ApiStringFormat::Pattern(&CLOUD_BULK_JOB_SOURCE_REGEX);
+
+pub const CLOUD_BULK_JOB_SOURCE_SCHEMA: Schema =
+    StringSchema::new("Datastore, optionally followed by a namespace ('<store>[:<ns>]').")
+        .format(&CLOUD_BULK_JOB_SOURCE_FORMAT)
+        .type_text("<store>[:<ns>]")
+        .schema();
+
+/// Split a bulk job source into datastore and namespace
+///
+/// An empty namespace is the root namespace, which is returned as `None`.
+pub fn parse_bulk_job_source(
+    source: &str,
+) -> Result<(String, Option<BackupNamespace>), anyhow::Error> {
+    let (store, ns) = match source.split_once(':') {
+        Some((store, ns)) => (store, ns),
+        None => (source, ""),
+    };
+    DATASTORE_SCHEMA
+        .parse_simple_value(store)
+        .map_err(|err| format_err!("invalid datastore '{}' - {}", store, err))?;
+    let ns = BackupNamespace::new(ns)
+        .map_err(|err| format_err!("invalid namespace '{}' - {}", ns, err))?;
+    Ok((store.to_string(), (!ns.is_root()).then_some(ns)))
+}
+
+#[api(
+    properties: {
+        backup: {
+            schema: JOB_ID_SCHEMA,
+        },
+        verify: {
+            schema: JOB_ID_SCHEMA,
+        },
+        prune: {
+            schema: JOB_ID_SCHEMA,
+        },
+        "local-prune": {
+            schema: JOB_ID_SCHEMA,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Cloud backup job with the verification and prune jobs of its copies
+///
+/// The verification and prune jobs are cloud jobs covering the copies of
+/// the same datastore namespace on the backup job's target, their IDs are
+/// derived from its ID.
+pub struct CloudJobTriple {
+    /// The cloud backup job
+    pub backup: String,
+    /// The cloud verification job
+    pub verify: String,
+    /// The cloud prune job
+    pub prune: String,
+    /// The prune job of the local datastore, only created on request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_prune: Option<String>,
+}
+
+impl CloudJobTriple {
+    /// Derive the verification and prune job IDs from the backup job ID
+    pub fn new(backup: &str) -> Result<Self, anyhow::Error> {
+        let triple = Self {
+            backup: backup.to_string(),
+            verify: format!("{}-verify", backup),
+            prune: format!("{}-prune", backup),
+            local_prune: None,
+        };
+        for id in [&triple.verify, &triple.prune] {
+            JOB_ID_SCHEMA
+                .parse_simple_value(id)
+                .map_err(|err| format_err!("invalid job id '{}' - {}", id, err))?;
+        }
+        Ok(triple)
+    }
+
+    /// Add the ID of the local prune job
+    pub fn with_local_prune(mut self) -> Result<Self, anyhow::Error> {
+        let id = format!("{}-local-prune", self.backup);
+        JOB_ID_SCHEMA
+            .parse_simple_value(&id)
+            .map_err(|err| format_err!("invalid job id '{}' - {}", id, err))?;
+        self.local_prune = Some(id);
+        Ok(self)
+    }
+}
+
+pub const CLOUD_JOB_TEMPLATE_ID_SCHEMA: Schema = StringSchema::new("Cloud backup job template ID.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(3)
+    .max_length(32)
+    .schema();
+
+pub const CLOUD_JOB_TEMPLATE_VALUE_FORMAT: ApiStringFormat =
+    ApiStringFormat::Pattern(&CLOUD_JOB_TEMPLATE_VALUE_REGEX);
+
+fn expand_template_value(
+    value: &str,
+    vars: &[(&str, Option<&str>)],
+) -> Result<String, anyhow::Error> {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => bail!("unterminated placeholder in '{}'", value),
+        };
+        let name = &rest[start + 1..end];
+        match vars.iter().find(|(var, _)| *var == name) {
+            Some((_, Some(var_value))) => result.push_str(var_value),
+            Some((_, None)) => bail!("'{}' needs a value for '{{{}}}'", value, name),
+            None => bail!("unknown placeholder '{{{}}}' in '{}'", name, value),
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[api(
+    properties: {
+        id: {
+            schema: CLOUD_JOB_TEMPLATE_ID_SCHEMA,
+        },
+        "job-id": {
+            description: "ID of the created jobs (default '{template}-{store}').",
+            type: String,
+            format: &CLOUD_JOB_TEMPLATE_VALUE_FORMAT,
+            max_length: 128,
+            optional: true,
+        },
+        target: {
+            description: "Cloud target of the created jobs (default '{target}' if given, \
+                else the target of the datastore's cloud mapping).",
+            type: String,
+            format: &CLOUD_JOB_TEMPLATE_VALUE_FORMAT,
+            max_length: 128,
+            optional: true,
+        },
+        ns: {
+            description: "Namespace of the created jobs (default '{ns}' if given).",
+            type: String,
+            format: &CLOUD_JOB_TEMPLATE_VALUE_FORMAT,
+            max_length: 256,
+            optional: true,
+        },
+        "latest-only": {
+            description: "Backup latest snapshots only.",
+            type: bool,
+            optional: true,
+        },
+        "notify-user": {
+            optional: true,
+            type: Userid,
+        },
+        "group-filter": {
+            schema: GROUP_FILTER_LIST_SCHEMA,
+            optional: true,
+        },
+        "max-depth": {
+            schema: crate::NS_MAX_DEPTH_SCHEMA,
+            optional: true,
+        },
+        "max-chain-length": {
+            schema: CLOUD_MAX_CHAIN_LENGTH_SCHEMA,
+            optional: true,
+        },
+        "storage-class": {
+            schema: CLOUD_STORAGE_CLASS_SCHEMA,
+            optional: true,
+        },
+        "retention-lock": {
+            schema: CLOUD_RETENTION_LOCK_SCHEMA,
+            optional: true,
+        },
+        "transfer-last": {
+            schema: TRANSFER_LAST_SCHEMA,
+            optional: true,
+        },
+        "object-tag": {
+            schema: CLOUD_OBJECT_TAG_LIST_SCHEMA,
+            optional: true,
+        },
+        "allowed-window": {
+            schema: CLOUD_ALLOWED_WINDOW_LIST_SCHEMA,
+            optional: true,
+        },
+        "blackout-window": {
+            schema: CLOUD_BLACKOUT_WINDOW_LIST_SCHEMA,
+            optional: true,
+        },
+        "window-action": {
+            type: CloudWindowAction,
+            optional: true,
+        },
+        "zfs-change-detection": {
+            description: "Use 'zfs diff' to skip backup groups unchanged since the last \
+                successful run (datastores on ZFS only).",
+            type: bool,
+            optional: true,
+        },
+        "local-io-latency": {
+            schema: CLOUD_LOCAL_IO_LATENCY_SCHEMA,
+            optional: true,
+        },
+        "local-io-min-rate": {
+            type: HumanByte,
+            optional: true,
+        },
+        order: {
+            type: CloudSnapshotOrder,
+            optional: true,
+        },
+        "on-error": {
+            type: CloudErrorPolicy,
+            optional: true,
+        },
+        "request-trace": {
+            description: "Record the metadata of all provider requests (for support cases).",
+            type: bool,
+            optional: true,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+        schedule: {
+            optional: true,
+            schema: CLOUD_SYNC_SCHEDULE_SCHEMA,
+        },
+        splay: {
+            optional: true,
+            schema: CLOUD_JOB_SPLAY_SCHEMA,
+        },
+        "retry-on-failure": {
+            optional: true,
+            schema: CLOUD_JOB_RETRY_SCHEMA,
+        },
+        "retry-delay": {
+            optional: true,
+            schema: CLOUD_JOB_RETRY_DELAY_SCHEMA,
+        },
+    }
+)]
+#[derive(Serialize, Deserialize, Clone, Updater, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Cloud Backup Job Template
+///
+/// Creates cloud backup jobs for several datastores. The string values
+/// `job-id`, `target` and `ns` may contain the placeholders `{template}`,
+/// `{store}`, `{target}` and `{ns}`, which are replaced when the
+/// template is instantiated.
+pub struct CloudBackupJobTemplate {
+    #[updater(skip)]
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latest_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_user: Option<Userid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_filter: Option<Vec<GroupFilter>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_chain_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retention_lock: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_last: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub object_tag: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_window: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blackout_window: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window_action: Option<CloudWindowAction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zfs_change_detection: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_io_latency: Option<u64>,
+    /// Upload rate guaranteed while local I/O is protected (default 10 MB/s)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_io_min_rate: Option<HumanByte>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<CloudSnapshotOrder>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_error: Option<CloudErrorPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_trace: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub splay: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_on_failure: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_delay: Option<String>,
+}
+
+impl CloudBackupJobTemplate {
+    /// Create the job configuration for datastore `store`
+    ///
+    /// `target` and `ns` are the values of the `{target}` and `{ns}`
+    /// placeholders. `{target}` is the target of the created job, if the
+    /// template sets one.
+    pub fn instantiate(
+        &self,
+        store: &str,
+        target: Option<&str>,
+        ns: Option<&BackupNamespace>,
+    ) -> Result<CloudBackupJobConfig, anyhow::Error> {
+        let ns_str = ns.map(|ns| ns.to_string());
+        let mut vars = [
+            ("template", Some(self.id.as_str())),
+            ("store", Some(store)),
+            ("target", target),
+            ("ns", ns_str.as_deref()),
+        ];
+
+        // without target, the jobs use the cloud mapping of the datastore
+        let target = match (self.target.as_deref(), target) {
+            (Some(value), _) => Some(expand_template_value(value, &vars)?),
+            (None, Some(target)) => Some(target.to_string()),
+            (None, None) => None,
+        };
+        if let Some(ref target) = target {
+            CLOUD_TARGET_NAME_SCHEMA
+                .parse_simple_value(target)
+                .map_err(|err| format_err!("invalid target '{}' - {}", target, err))?;
+        }
+        vars[2].1 = target.as_deref();
+
+        let id = expand_template_value(
+            self.job_id.as_deref().unwrap_or("{template}-{store}"),
+            &vars,
+        )?;
+        JOB_ID_SCHEMA
+            .parse_simple_value(&id)
+            .map_err(|err| format_err!("invalid job id '{}' - {}", id, err))?;
+
+        let ns = match self.ns {
+            Some(ref value) => {
+                let ns = expand_template_value(value, &vars)?;
+                Some(
+                    BackupNamespace::new(&ns)
+                        .map_err(|err| format_err!("invalid namespace '{}' - {}", ns, err))?,
+                )
+            }
+            None => ns.cloned(),
+        };
+
+        Ok(CloudBackupJobConfig {
+            id,
+            setup: CloudBackupJobSetup {
+                store: store.to_string(),
+                target,
+                latest_only: self.latest_only,
+                notify_user: self.notify_user.clone(),
+                group_filter: self.group_filter.clone(),
+                ns,
+                max_depth: self.max_depth,
+                max_chain_length: self.max_chain_length,
+                storage_class: self.storage_class.clone(),
+                retention_lock: self.retention_lock,
+                transfer_last: self.transfer_last,
+                object_tag: self.object_tag.clone(),
+                allowed_window: self.allowed_window.clone(),
+                blackout_window: self.blackout_window.clone(),
+                window_action: self.window_action,
+                zfs_change_detection: self.zfs_change_detection,
+                local_io_latency: self.local_io_latency,
+                local_io_min_rate: self.local_io_min_rate,
+                order: self.order,
+                on_error: self.on_error,
+                request_trace: self.request_trace,
+            },
+            comment: self.comment.clone(),
+            schedule: self.schedule.clone(),
+            splay: self.splay.clone(),
+            retry_on_failure: self.retry_on_failure,
+            retry_delay: self.retry_delay.clone(),
+            run_after: None,
+            hooks: Default::default(),
+            template: Some(self.id.clone()),
+            disable: false,
+            tags: None,
+        })
+    }
+}
+
+#[api(
+    properties: {
+        config: {
+            type: CloudBackupJobConfig,
+        },
+        status: {
+            type: CloudJobScheduleStatus,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Status of Cloud Backup Job
+pub struct CloudBackupJobStatus {
+    #[serde(flatten)]
+    pub config: CloudBackupJobConfig,
+    #[serde(flatten)]
+    pub status: CloudJobScheduleStatus,
+}
 
-pub const IGNORE_VERIFIED_CLOUD_BACKUPS_SCHEMA: Schema = BooleanSchema::new(
-    "Do not verify cloud backups that are already verified if their verification is not outdated.",
+pub const CLOUD_REPLICATION_MAX_AGE_SCHEMA: Schema = IntegerSchema::new(
+    "Only replicate media sets created within this many days \
+    (and the media sets they are based on).",
 )
-.default(true)
+.minimum(1)
 .schema();
 
-pub const CLOUD_VERIFICATION_OUTDATED_AFTER_SCHEMA: Schema =
-    IntegerSchema::new("Days after which a cloud verification becomes outdated. (0 is deprecated)")
-        .minimum(0)
-        .schema();
-
 #[api(
     properties: {
         id: {
-            schema: CLOUD_JOB_ID_SCHEMA,
+            schema: JOB_ID_SCHEMA,
+        },
+        source: {
+            schema: CLOUD_TARGET_NAME_SCHEMA,
+        },
+        target: {
+            schema: CLOUD_TARGET_NAME_SCHEMA,
         },
         store: {
             schema: DATASTORE_SCHEMA,
+            optional: true,
         },
-        "ignore-verified": {
+        "max-age": {
+            schema: CLOUD_REPLICATION_MAX_AGE_SCHEMA,
             optional: true,
-            schema: IGNORE_VERIFIED_CLOUD_BACKUPS_SCHEMA,
         },
-        "outdated-after": {
+        "group-filter": {
+            schema: GROUP_FILTER_LIST_SCHEMA,
             optional: true,
-            schema: CLOUD_VERIFICATION_OUTDATED_AFTER_SCHEMA,
         },
         comment: {
             optional: true,
@@ -192,38 +1550,153 @@ pub const CLOUD_VERIFICATION_OUTDATED_AFTER_SCHEMA: Schema =
         },
         schedule: {
             optional: true,
-            schema: CLOUD_VERIFICATION_SCHEDULE_SCHEMA,
+            schema: CLOUD_SYNC_SCHEDULE_SCHEMA,
         },
-        ns: {
+        splay: {
             optional: true,
-            schema: BACKUP_NAMESPACE_SCHEMA,
+            schema: CLOUD_JOB_SPLAY_SCHEMA,
         },
-        "max-depth": {
+        "retry-on-failure": {
             optional: true,
-            schema: crate::NS_MAX_DEPTH_SCHEMA,
+            schema: CLOUD_JOB_RETRY_SCHEMA,
+        },
+        "retry-delay": {
+            optional: true,
+            schema: CLOUD_JOB_RETRY_DELAY_SCHEMA,
+        },
+        "run-after": {
+            optional: true,
+            schema: CLOUD_RUN_AFTER_SCHEMA,
+        },
+        hooks: {
+            type: CloudJobHooks,
+        },
+        disable: {
+            type: Boolean,
+            optional: true,
+            default: false,
         },
     }
 )]
-#[derive(Serialize, Deserialize, Updater, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Updater, PartialEq)]
 #[serde(rename_all = "kebab-case")]
-/// Cloud Verification Job
-pub struct CloudVerificationJobConfig {
-    /// Unique ID to address this job
+/// Cloud Replication Job
+///
+/// Copies the media sets of the source target to the target.
+pub struct CloudReplicationJobConfig {
     #[updater(skip)]
     pub id: String,
-    /// The datastore ID this verification job affects
-    pub store: String,
+    /// Target to copy media sets from
+    pub source: String,
+    /// Target to copy media sets to
+    pub target: String,
+    /// Only replicate media sets containing data of this datastore
     #[serde(skip_serializing_if = "Option::is_none")]
-    ///
+    pub store: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u64>,
+    /// Only replicate media sets containing backup groups matching these filters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_filter: Option<Vec<GroupFilter>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub splay: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_on_failure: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_delay: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_after: Option<String>,
+    #[serde(flatten)]
+    pub hooks: CloudJobHooks,
+    /// Disable this job.
+    #[serde(default, skip_serializing_if = "is_false")]
+    #[updater(serde(skip_serializing_if = "Option::is_none"))]
+    pub disable: bool,
+}
+
+#[api(
+    properties: {
+        config: {
+            type: CloudReplicationJobConfig,
+        },
+        status: {
+            type: CloudJobScheduleStatus,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Status of Cloud Replication Job
+pub struct CloudReplicationJobStatus {
+    #[serde(flatten)]
+    pub config: CloudReplicationJobConfig,
+    #[serde(flatten)]
+    pub status: CloudJobScheduleStatus,
+}
+
+pub const LDAP_GROUP_NAME_SCHEMA: Schema = StringSchema::new("Name (cn) of an LDAP group.")
+    .format(&SINGLE_LINE_COMMENT_FORMAT)
+    .min_length(1)
+    .max_length(256)
+    .schema();
+
+#[api(
+    properties: {
+        group: {
+            schema: LDAP_GROUP_NAME_SCHEMA,
+        },
+        role: {
+            type: Role,
+        },
+        path: {
+            schema: ACL_PATH_SCHEMA,
+        },
+        propagate: {
+            schema: ACL_PROPAGATE_SCHEMA,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Maps the members of an LDAP group to a role on an ACL path
+pub struct CloudRoleMapping {
+    pub group: String,
+    pub role: String,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub propagate: Option<bool>,
 }
 
+pub const CLOUD_ROLE_MAPPING_SCHEMA: Schema = StringSchema::new(
+    "Group to role mapping, e.g. 'group=backup-operators,role=CloudUser,path=/cloud/target/prod'.",
+)
+.format(&ApiStringFormat::PropertyString(
+    &CloudRoleMapping::API_SCHEMA,
+))
+.schema();
+
+pub const CLOUD_ROLE_MAPPING_LIST_SCHEMA: Schema = ArraySchema::new(
+    "List of group to role mappings.",
+    &CLOUD_ROLE_MAPPING_SCHEMA,
+)
+.min_length(1)
+.schema();
+
 #[api(
     properties: {
         id: {
             schema: JOB_ID_SCHEMA,
         },
-        setup: {
-            type: TapeBackupJobSetup,
+        realm: {
+            schema: REALM_ID_SCHEMA,
+        },
+        mapping: {
+            schema: CLOUD_ROLE_MAPPING_LIST_SCHEMA,
         },
         comment: {
             optional: true,
@@ -231,56 +1704,103 @@ pub struct CloudVerificationJobConfig {
         },
         schedule: {
             optional: true,
-            schema: SYNC_SCHEDULE_SCHEMA,
+            schema: CLOUD_SYNC_SCHEDULE_SCHEMA,
+        },
+        disable: {
+            type: Boolean,
+            optional: true,
+            default: false,
         },
     }
 )]
 #[derive(Serialize, Deserialize, Clone, Updater, PartialEq)]
 #[serde(rename_all = "kebab-case")]
-/// Tape Backup Job
-pub struct TapeBackupJobConfig {
+/// Cloud Role Sync Job
+///
+/// Grants the mapped roles to the members of LDAP groups of a realm, and
+/// removes roles it granted from users who left the group.
+pub struct CloudRoleSyncJobConfig {
     #[updater(skip)]
     pub id: String,
-    #[serde(flatten)]
-    pub setup: TapeBackupJobSetup,
+    /// LDAP realm to read the groups from
+    pub realm: String,
+    pub mapping: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub schedule: Option<String>,
+    /// Disable this job.
+    #[serde(default, skip_serializing_if = "is_false")]
+    #[updater(serde(skip_serializing_if = "Option::is_none"))]
+    pub disable: bool,
 }
 
 #[api(
     properties: {
         config: {
-            type: TapeBackupJobConfig,
+            type: CloudRoleSyncJobConfig,
         },
         status: {
-            type: JobScheduleStatus,
+            type: CloudJobScheduleStatus,
         },
     },
 )]
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "kebab-case")]
-/// Status of Tape Backup Job
-pub struct TapeBackupJobStatus {
+/// Status of Cloud Role Sync Job
+pub struct CloudRoleSyncJobStatus {
     #[serde(flatten)]
-    pub config: TapeBackupJobConfig,
+    pub config: CloudRoleSyncJobConfig,
     #[serde(flatten)]
-    pub status: JobScheduleStatus,
-    /// Next tape used (best guess)
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub next_media_label: Option<String>,
+    pub status: CloudJobScheduleStatus,
+}
+
+#[api()]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Period covered by a cloud job digest
+pub enum CloudDigestPeriod {
+    /// The last day
+    #[default]
+    Daily,
+    /// The last week
+    Weekly,
 }
 
+impl CloudDigestPeriod {
+    /// Length of the period in seconds
+    pub fn seconds(&self) -> i64 {
+        match self {
+            CloudDigestPeriod::Daily => 86400,
+            CloudDigestPeriod::Weekly => 7 * 86400,
+        }
+    }
+
+    /// Schedule of digest jobs without 'schedule'
+    pub fn default_schedule(&self) -> &'static str {
+        match self {
+            CloudDigestPeriod::Daily => "daily",
+            CloudDigestPeriod::Weekly => "weekly",
+        }
+    }
+}
 
 #[api(
     properties: {
         id: {
             schema: JOB_ID_SCHEMA,
         },
-        setup: {
-            //optional: true,
-            type: TapeBackupJobSetup,
+        period: {
+            type: CloudDigestPeriod,
+            optional: true,
+        },
+        "notify-user": {
+            optional: true,
+            type: Userid,
+        },
+        webhook: {
+            optional: true,
+            schema: HTTP_URL_SCHEMA,
         },
         comment: {
             optional: true,
@@ -288,44 +1808,61 @@ pub struct TapeBackupJobStatus {
         },
         schedule: {
             optional: true,
-            schema: SYNC_SCHEDULE_SCHEMA,
+            schema: CLOUD_SYNC_SCHEDULE_SCHEMA,
+        },
+        disable: {
+            type: Boolean,
+            optional: true,
+            default: false,
         },
     }
 )]
 #[derive(Serialize, Deserialize, Clone, Updater, PartialEq)]
 #[serde(rename_all = "kebab-case")]
-/// Cloud Backup Job; Added by SK
-pub struct CloudBackupJobConfig {
+/// Cloud Job Digest
+///
+/// Sends a single report about the runs of all cloud jobs in a period,
+/// instead of one notification per run.
+pub struct CloudDigestJobConfig {
     #[updater(skip)]
     pub id: String,
-    //#[serde(flatten)]
-    pub setup: TapeBackupJobSetup,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub period: Option<CloudDigestPeriod>,
+    /// Send the digest by mail to this user (root@pam if neither this nor 'webhook' is set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_user: Option<Userid>,
+    /// POST the digest as JSON to this URL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    /// Defaults to the period ('daily' or 'weekly')
     #[serde(skip_serializing_if = "Option::is_none")]
     pub schedule: Option<String>,
+    /// Disable this job.
+    #[serde(default, skip_serializing_if = "is_false")]
+    #[updater(serde(skip_serializing_if = "Option::is_none"))]
+    pub disable: bool,
 }
+
 #[api(
     properties: {
         config: {
-            type: CloudBackupJobConfig,
+            type: CloudDigestJobConfig,
         },
         status: {
-            type: JobScheduleStatus,
+            type: CloudJobScheduleStatus,
         },
     },
 )]
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "kebab-case")]
-/// Status of Cloud Backup Job; Added by SK
-pub struct CloudBackupJobStatus {
+/// Status of Cloud Digest Job
+pub struct CloudDigestJobStatus {
     #[serde(flatten)]
-    pub config: CloudBackupJobConfig,
+    pub config: CloudDigestJobConfig,
     #[serde(flatten)]
-    pub status: JobScheduleStatus,
-    /// Next tape used (best guess)
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub next_media_label: Option<String>,
+    pub status: CloudJobScheduleStatus,
 }
 
 #[derive(Clone, Debug)]
@@ -432,6 +1969,28 @@ pub const GROUP_FILTER_SCHEMA: Schema = StringSchema::new(
 pub const GROUP_FILTER_LIST_SCHEMA: Schema =
     ArraySchema::new("List of group filters.", &GROUP_FILTER_SCHEMA).schema();
 
+/// Check a list of group filters for rules contradicting each other
+///
+/// Exclude rules take precedence, so a rule which is both included and
+/// excluded can never match. Duplicate rules are rejected too.
+pub fn check_group_filters(filters: &[GroupFilter]) -> Result<(), anyhow::Error> {
+    for (i, filter) in filters.iter().enumerate() {
+        for other in filters[..i].iter() {
+            if filter.filter_type != other.filter_type {
+                continue;
+            }
+            if filter.is_exclude == other.is_exclude {
+                bail!("duplicate group filter '{}'", filter);
+            }
+            bail!(
+                "group filter '{}' is both included and excluded",
+                filter.filter_type
+            );
+        }
+    }
+    Ok(())
+}
+
 pub const TRANSFER_LAST_SCHEMA: Schema =
     IntegerSchema::new("Limit transfer to last N snapshots (per group), skipping others")
         .minimum(1)
@@ -735,4 +2294,319 @@ pub struct PruneJobStatus {
     pub config: PruneJobConfig,
     #[serde(flatten)]
     pub status: JobScheduleStatus,
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_group_filter_semantics() -> Result<(), anyhow::Error> {
+    use crate::BackupGroup;
+
+    let filters =
+        |list: &[&str]| -> Vec<GroupFilter> { list.iter().map(|f| f.parse().unwrap()).collect() };
+    let vm100 = BackupGroup::new(BackupType::Vm, "100");
+    let vm101 = BackupGroup::new(BackupType::Vm, "101");
+    let ct200 = BackupGroup::new(BackupType::Ct, "200");
+    let host = BackupGroup::new(BackupType::Host, "web");
+
+    // no filters, or only exclude filters, include everything else
+    assert!(vm100.apply_filters(&[]));
+    let exclude_only = filters(&["exclude:type:vm"]);
+    assert!(!vm100.apply_filters(&exclude_only));
+    assert!(ct200.apply_filters(&exclude_only));
+
+    // include filters are combined with "or"
+    let include = filters(&["group:vm/100", "type:ct"]);
+    assert!(vm100.apply_filters(&include));
+    assert!(!vm101.apply_filters(&include));
+    assert!(ct200.apply_filters(&include));
+    assert!(!host.apply_filters(&include));
+
+    // overlapping include and exclude rules - exclude wins
+    let overlap = filters(&[
+        "type:vm",
+        "exclude:regex:^vm/10[0-9]$",
+        "include:group:vm/101",
+    ]);
+    assert!(!vm100.apply_filters(&overlap));
+    assert!(!vm101.apply_filters(&overlap));
+    assert!(!ct200.apply_filters(&overlap));
+
+    let overlap = filters(&["regex:^(vm|ct)/", "exclude:group:ct/200"]);
+    assert!(vm100.apply_filters(&overlap));
+    assert!(!ct200.apply_filters(&overlap));
+    assert!(!host.apply_filters(&overlap));
+
+    // rule order does not matter
+    let reversed = filters(&["exclude:group:ct/200", "regex:^(vm|ct)/"]);
+    for group in [&vm100, &vm101, &ct200, &host] {
+        assert_eq!(
+            group.apply_filters(&overlap),
+            group.apply_filters(&reversed)
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_group_filter_parsing() -> Result<(), anyhow::Error> {
+    for input in ["group:vm/100", "exclude:type:ct", "regex:^host/.*$"] {
+        assert_eq!(input.parse::<GroupFilter>()?.to_string(), input);
+    }
+    // the default "include:" prefix is not serialized
+    assert_eq!(
+        "include:type:vm".parse::<GroupFilter>()?.to_string(),
+        "type:vm"
+    );
+
+    assert!("type:foo".parse::<GroupFilter>().is_err());
+    assert!("group:vm".parse::<GroupFilter>().is_err());
+    assert!("regex:(".parse::<GroupFilter>().is_err());
+    assert!("exclude:vm/100".parse::<GroupFilter>().is_err());
+    assert!("other:type:vm".parse::<GroupFilter>().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_check_group_filters() -> Result<(), anyhow::Error> {
+    let check = |list: &[&str]| {
+        let list: Vec<GroupFilter> = list.iter().map(|f| f.parse().unwrap()).collect();
+        check_group_filters(&list)
+    };
+
+    assert!(check(&[]).is_ok());
+    assert!(check(&["type:vm", "exclude:group:vm/100", "exclude:regex:^vm/1"]).is_ok());
+    // contradicting and duplicate rules
+    assert!(check(&["type:vm", "exclude:type:vm"]).is_err());
+    assert!(check(&["include:group:vm/100", "group:vm/100"]).is_err());
+    assert!(check(&["exclude:regex:^ct/", "exclude:regex:^ct/"]).is_err());
+    // same regex source only - overlapping regexes are allowed
+    assert!(check(&["regex:^ct/", "exclude:regex:^ct/2"]).is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_job_template_instantiate() -> Result<(), anyhow::Error> {
+    let mut template = CloudBackupJobTemplate {
+        id: "fleet".to_string(),
+        job_id: None,
+        target: None,
+        ns: Some("{store}/daily".to_string()),
+        latest_only: Some(true),
+        notify_user: None,
+        group_filter: None,
+        max_depth: None,
+        max_chain_length: None,
+        storage_class: None,
+        retention_lock: None,
+        transfer_last: None,
+        object_tag: None,
+        allowed_window: None,
+        blackout_window: None,
+        window_action: None,
+        zfs_change_detection: None,
+        local_io_latency: None,
+        local_io_min_rate: None,
+        order: None,
+        on_error: None,
+        request_trace: None,
+        comment: None,
+        schedule: None,
+        splay: None,
+        retry_on_failure: None,
+        retry_delay: None,
+    };
+
+    let job = template.instantiate("store1", Some("s3"), None)?;
+    assert_eq!(job.id, "fleet-store1");
+    assert_eq!(job.setup.target.as_deref(), Some("s3"));
+    assert_eq!(job.setup.ns, Some(BackupNamespace::new("store1/daily")?));
+    assert_eq!(job.setup.latest_only, Some(true));
+    assert_eq!(job.template.as_deref(), Some("fleet"));
+
+    // without target, the job inherits the cloud mapping
+    let job = template.instantiate("store1", None, None)?;
+    assert_eq!(job.setup.target, None);
+
+    template.job_id = Some("{store}-{target}".to_string());
+    template.target = Some("offsite".to_string());
+    template.ns = None;
+
+    let job = template.instantiate("store1", None, None)?;
+    assert_eq!(job.id, "store1-offsite");
+    assert_eq!(job.setup.target.as_deref(), Some("offsite"));
+    assert_eq!(job.setup.ns, None);
+
+    template.job_id = Some("{store}/bad".to_string());
+    assert!(template.instantiate("store1", None, None).is_err());
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn tagged_job(tags: &[&str]) -> Result<CloudBackupJobConfig, anyhow::Error> {
+    let template = CloudBackupJobTemplate {
+        id: "fleet".to_string(),
+        job_id: None,
+        target: None,
+        ns: None,
+        latest_only: None,
+        notify_user: None,
+        group_filter: None,
+        max_depth: None,
+        max_chain_length: None,
+        storage_class: None,
+        retention_lock: None,
+        transfer_last: None,
+        object_tag: None,
+        allowed_window: None,
+        blackout_window: None,
+        window_action: None,
+        zfs_change_detection: None,
+        local_io_latency: None,
+        local_io_min_rate: None,
+        order: None,
+        on_error: None,
+        request_trace: None,
+        comment: None,
+        schedule: None,
+        splay: None,
+        retry_on_failure: None,
+        retry_delay: None,
+    };
+    let mut job = template.instantiate("store1", Some("s3"), None)?;
+    job.tags = Some(tags.iter().map(|tag| tag.to_string()).collect());
+    Ok(job)
+}
+
+#[test]
+fn test_cloud_job_selector() -> Result<(), anyhow::Error> {
+    let job = tagged_job(&["prod", "eu"])?;
+
+    let select = |target: Option<&str>, store: Option<&str>, tag: Option<&str>| {
+        CloudBackupJobSelector {
+            target: target.map(String::from),
+            store: store.map(String::from),
+            tag: tag.map(String::from),
+            target_tag: None,
+        }
+        .matches(&job, job.setup.target.as_deref(), None)
+    };
+
+    assert!(select(None, None, None));
+    assert!(select(Some("s3"), None, None));
+    assert!(select(Some("s3"), Some("store1"), Some("eu")));
+    assert!(!select(Some("gcs"), None, None));
+    assert!(!select(None, Some("store2"), None));
+    assert!(!select(None, None, Some("test")));
+
+    Ok(())
+}
+
+#[test]
+fn test_cloud_tags() -> Result<(), anyhow::Error> {
+    // targets and jobs validate their tags with the same schema
+    let target_tags = CloudTargetConfig::API_SCHEMA
+        .unwrap_object_schema()
+        .lookup("tags")
+        .unwrap()
+        .1;
+    let job_tags = CloudBackupJobConfig::API_SCHEMA
+        .unwrap_object_schema()
+        .lookup("tags")
+        .unwrap()
+        .1;
+
+    for schema in [target_tags, job_tags] {
+        let item = schema.unwrap_array_schema().items;
+        for tag in ["prod", "tier-1", "eu_west.2"] {
+            item.parse_simple_value(tag)?;
+        }
+        for tag in ["x", "has space", "a/b", "ümlaut", "t".repeat(33).as_str()] {
+            assert!(
+                item.parse_simple_value(tag).is_err(),
+                "tag '{}' accepted",
+                tag
+            );
+        }
+    }
+
+    let target = CloudTargetConfig {
+        tags: Some(vec!["prod".to_string(), "sla-gold".to_string()]),
+        ..Default::default()
+    };
+    assert!(target.has_tag("prod"));
+    assert!(!target.has_tag("pro"));
+    assert!(!CloudTargetConfig::default().has_tag("prod"));
+
+    let job = tagged_job(&["eu"])?;
+    assert!(job.has_tag("eu"));
+    assert!(!job.has_tag("prod"));
+
+    let select = |tag: Option<&str>, target_tag: Option<&str>, target| {
+        CloudBackupJobSelector {
+            target: None,
+            store: None,
+            tag: tag.map(String::from),
+            target_tag: target_tag.map(String::from),
+        }
+        .matches(&job, Some("s3"), target)
+    };
+
+    // jobs by their own tags, or by the tags of their target
+    assert!(select(Some("eu"), None, None));
+    assert!(!select(Some("prod"), None, Some(&target)));
+    assert!(select(None, Some("prod"), Some(&target)));
+    assert!(select(Some("eu"), Some("sla-gold"), Some(&target)));
+    assert!(!select(Some("eu"), Some("sla-silver"), Some(&target)));
+    // jobs whose target does not exist have no target tags
+    assert!(!select(None, Some("prod"), None));
+
+    Ok(())
+}
+
+#[test]
+fn test_bulk_job_source() -> Result<(), anyhow::Error> {
+    let (store, ns) = parse_bulk_job_source("store1")?;
+    assert_eq!(store, "store1");
+    assert_eq!(ns, None);
+
+    let (store, ns) = parse_bulk_job_source("store1:")?;
+    assert_eq!(store, "store1");
+    assert_eq!(ns, None);
+
+    let (store, ns) = parse_bulk_job_source("store1:prod/db")?;
+    assert_eq!(store, "store1");
+    assert_eq!(ns, Some(BackupNamespace::new("prod/db")?));
+
+    assert!(parse_bulk_job_source(":prod").is_err());
+    assert!(parse_bulk_job_source("store1:prod//db").is_err());
+
+    let triple = CloudJobTriple::new("fleet-store1")?;
+    assert_eq!(triple.verify, "fleet-store1-verify");
+    assert_eq!(triple.prune, "fleet-store1-prune");
+
+    // derived IDs have to be valid job IDs as well
+    assert!(CloudJobTriple::new("a-rather-long-template-store1").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_cloud_backup_since() -> Result<(), anyhow::Error> {
+    let now = 1_700_000_000;
+
+    let since: CloudBackupSince = "2023-11-14T00:00:00Z".parse()?;
+    assert_eq!(since.resolve(now), 1_699_920_000);
+
+    let since: CloudBackupSince = "7d".parse()?;
+    assert_eq!(since.resolve(now), now - 7 * 86400);
+
+    let since: CloudBackupSince = "1h 30min".parse()?;
+    assert_eq!(since.resolve(now), now - 5400);
+
+    assert!("yesterday".parse::<CloudBackupSince>().is_err());
+
+    Ok(())
+}
@@ -117,7 +117,7 @@ mod remote;
 pub use remote::*;
 
 mod cloud;
-pub use cloud::*;  // Cloud module that integrates with cloud storage APIs (e.g., S3, Google Cloud Storage).
+pub use cloud::*;
 
 mod tape;
 pub use tape::*;  // This could be replaced with cloud backup-related implementations.
@@ -189,48 +189,3 @@ const_regex! {
     pub BLOCKDEVICE_DISK_AND_PARTITION_NAME_REGEX = r"^(?:(?:h|s|x?v)d[a-z]+\d*)|(?:nvme\d+n\d+(p\d+)?)$";
     pub SUBSCRIPTION_KEY_REGEX = r"^([A-Za-z0-9]{4}-){7}[A-Za-z0-9]{4}$";
 }
-
-// Cloud Backup - Module to interact with cloud storage (AWS S3 example)
-mod cloud {
-    use rusoto_core::Region;
-    use rusoto_s3::{S3, S3Client, PutObjectRequest, GetObjectRequest};
-    
-    pub fn upload_to_cloud(bucket: &str, key: &str, data: Vec<u8>) -> Result<(), String> {
-        let s3_client = S3Client::new(Region::UsEast1);
-        let put_request = PutObjectRequest {
-            bucket: bucket.to_string(),
-            key: key.to_string(),
-            body: Some(data.into()),
-            ..Default::default()
-        };
-        s3_client.put_object(put_request).sync().map_err(|e| e.to_string())?;
-        Ok(())
-    }
-
-    pub fn download_from_cloud(bucket: &str, key: &str) -> Result<Vec<u8>, String> {
-        let s3_client = S3Client::new(Region::UsEast1);
-        let get_request = GetObjectRequest {
-            bucket: bucket.to_string(),
-            key: key.to_string(),
-            ..Default::default()
-        };
-        let result = s3_client.get_object(get_request).sync().map_err(|e| e.to_string())?;
-        result.body.map(|b| b.into_iter().collect()).map_err(|e| e.to_string())
-    }
-}
-
-// Usage example
-fn main() {
-    // Example of uploading data to cloud (AWS S3)
-    let data = b"Hello, Cloud Backup!".to_vec();
-    match cloud::upload_to_cloud("my-backup-bucket", "backup/my-backup-key", data) {
-        Ok(()) => println!("Backup uploaded successfully!"),
-        Err(e) => println!("Failed to upload backup: {}", e),
-    }
-
-    // Example of downloading data from cloud (AWS S3)
-    match cloud::download_from_cloud("my-backup-bucket", "backup/my-backup-key") {
-        Ok(data) => println!("Backup downloaded: {:?}", String::from_utf8(data)),
-        Err(e) => println!("Failed to download backup: {}", e),
-    }
-}
@@ -3,6 +3,8 @@ use std::ffi::OsStr;
 use proxmox_schema::*;
 use serde::{Deserialize, Serialize};
 
+use crate::CloudChunkCacheStatus;
+
 #[api]
 #[derive(Serialize, Deserialize, Default)]
 #[serde(rename_all = "kebab-case")]
@@ -30,7 +32,7 @@ pub struct CloudSwapCounters {
 }
 
 #[api]
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "kebab-case")]
 /// Contains general cloud node information such as instance ID
 pub struct CloudNodeInformation {
@@ -88,6 +90,21 @@ pub struct CloudCpuInformation {
     pub vcpus: usize,
 }
 
+#[api]
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+/// Filesystem usage of a datastore
+pub struct CloudDatastoreUsage {
+    /// The datastore name
+    pub store: String,
+    /// Total space (in bytes)
+    pub total: u64,
+    /// Used space (in bytes)
+    pub used: u64,
+    /// Available space (in bytes)
+    pub avail: u64,
+}
+
 #[api(
     properties: {
         memory: {
@@ -108,7 +125,17 @@ pub struct CloudCpuInformation {
         },
         info: {
             type: CloudNodeInformation,
-        }
+        },
+        datastores: {
+            type: Array,
+            items: {
+                type: CloudDatastoreUsage,
+            },
+        },
+        "chunk-cache": {
+            type: CloudChunkCacheStatus,
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize)]
@@ -129,4 +156,9 @@ pub struct CloudNodeStatus {
     pub cpuinfo: CloudCpuInformation,
     /// General instance information.
     pub info: CloudNodeInformation,
+    /// Filesystem usage of the datastores (staging area of cloud uploads).
+    pub datastores: Vec<CloudDatastoreUsage>,
+    /// Utilization of the local chunk cache (if enabled).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_cache: Option<CloudChunkCacheStatus>,
 }
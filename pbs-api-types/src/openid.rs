@@ -3,7 +3,8 @@ use serde::{Deserialize, Serialize};
 use proxmox_schema::{api, ApiStringFormat, ArraySchema, Schema, StringSchema, Updater};
 
 use super::{
-    CLOUD_SAFE_ID_FORMAT, CLOUD_SAFE_ID_REGEX, CLOUD_REALM_ID_SCHEMA, SINGLE_LINE_COMMENT_SCHEMA,
+    Role, ACL_PATH_SCHEMA, ACL_PROPAGATE_SCHEMA, CLOUD_SAFE_ID_FORMAT, CLOUD_SAFE_ID_REGEX,
+    CLOUD_REALM_ID_SCHEMA, SINGLE_LINE_COMMENT_FORMAT, SINGLE_LINE_COMMENT_SCHEMA,
 };
 
 pub const OPENID_SCOPE_FORMAT: ApiStringFormat = ApiStringFormat::Pattern(&CLOUD_SAFE_ID_REGEX);
@@ -53,6 +54,58 @@ pub const OPENID_USERNAME_CLAIM_SCHEMA: Schema = StringSchema::new(
 .format(&CLOUD_SAFE_ID_FORMAT)
 .schema();
 
+pub const OPENID_ROLES_CLAIM_SCHEMA: Schema = StringSchema::new(
+    "Map the values of this claim (e.g. 'groups' or 'roles') to ACL \
+    entries on login, see 'role-mapping'. Nested claims are addressed \
+    with dots, e.g. 'realm_access.roles'.",
+)
+.max_length(64)
+.min_length(1)
+.format(&SINGLE_LINE_COMMENT_FORMAT)
+.schema();
+
+pub const OPENID_CLAIM_VALUE_SCHEMA: Schema = StringSchema::new("Value of the roles claim.")
+    .format(&SINGLE_LINE_COMMENT_FORMAT)
+    .min_length(1)
+    .max_length(256)
+    .schema();
+
+#[api(
+    properties: {
+        value: {
+            schema: OPENID_CLAIM_VALUE_SCHEMA,
+        },
+        role: {
+            type: Role,
+        },
+        path: {
+            schema: ACL_PATH_SCHEMA,
+        },
+        propagate: {
+            schema: ACL_PROPAGATE_SCHEMA,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Maps a value of the roles claim to a role on an ACL path
+pub struct CloudOpenIdRoleMapping {
+    pub value: String,
+    pub role: String,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub propagate: Option<bool>,
+}
+
+pub const OPENID_ROLE_MAPPING_SCHEMA: Schema = StringSchema::new(
+    "Claim to role mapping, e.g. 'value=backup-operators,role=CloudUser,path=/cloud/target/prod'.")
+    .format(&ApiStringFormat::PropertyString(&CloudOpenIdRoleMapping::API_SCHEMA))
+    .schema();
+
+pub const OPENID_ROLE_MAPPING_LIST_SCHEMA: Schema =
+    ArraySchema::new("List of claim to role mappings.", &OPENID_ROLE_MAPPING_SCHEMA).schema();
+
 #[api(
     properties: {
         realm: {
@@ -87,6 +140,14 @@ pub const OPENID_USERNAME_CLAIM_SCHEMA: Schema = StringSchema::new(
             schema: OPENID_USERNAME_CLAIM_SCHEMA,
             optional: true,
         },
+        "roles-claim": {
+            schema: OPENID_ROLES_CLAIM_SCHEMA,
+            optional: true,
+        },
+        "role-mapping": {
+            schema: OPENID_ROLE_MAPPING_LIST_SCHEMA,
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Updater)]
@@ -116,4 +177,8 @@ pub struct CloudOpenIdConfig {
     #[updater(skip)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub username_claim: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roles_claim: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role_mapping: Option<Vec<String>>,
 }
@@ -1,10 +1,12 @@
+use anyhow::bail;
 use serde::{Deserialize, Serialize};
 
 use proxmox_human_byte::HumanByte;
-use proxmox_schema::{api, IntegerSchema, Schema, StringSchema, Updater};
+use proxmox_schema::{api, ApiStringFormat, IntegerSchema, Schema, StringSchema, Updater};
 
 use crate::{
-    CIDR_SCHEMA, DAILY_DURATION_FORMAT, PROXMOX_SAFE_ID_FORMAT, SINGLE_LINE_COMMENT_SCHEMA,
+    CIDR_SCHEMA, CLOUD_TARGET_NAME_SCHEMA, DAILY_DURATION_FORMAT, DNS_NAME_OR_IP_REGEX,
+    PROXMOX_SAFE_ID_FORMAT, SINGLE_LINE_COMMENT_SCHEMA,
 };
 
 pub const TRAFFIC_CONTROL_TIMEFRAME_SCHEMA: Schema =
@@ -18,6 +20,20 @@ pub const TRAFFIC_CONTROL_ID_SCHEMA: Schema = StringSchema::new("Rule ID.")
     .max_length(32)
     .schema();
 
+fn verify_endpoint_pattern(input: &str) -> Result<(), anyhow::Error> {
+    let host = input.strip_prefix("*.").unwrap_or(input);
+    if DNS_NAME_OR_IP_REGEX.is_match(host) {
+        return Ok(());
+    }
+    bail!("expected '<host>' or '*.<domain>'");
+}
+
+pub const TRAFFIC_CONTROL_ENDPOINT_SCHEMA: Schema =
+    StringSchema::new("Host name of a remote endpoint ('*.' prefix matches all sub domains).")
+        .format(&ApiStringFormat::VerifyFn(verify_endpoint_pattern))
+        .type_text("<host>|*.<domain>")
+        .schema();
+
 pub const TRAFFIC_CONTROL_RATE_SCHEMA: Schema =
     IntegerSchema::new("Rate limit (for Token bucket filter) in bytes/second.")
         .minimum(100_000)
@@ -85,11 +101,26 @@ impl RateLimitConfig {
         limit: {
             type: RateLimitConfig,
         },
-        backup_targets: {
+        network: {
             type: Array,
             items: {
                 schema: CIDR_SCHEMA,
             },
+            optional: true,
+        },
+        "cloud-target": {
+            type: Array,
+            items: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            optional: true,
+        },
+        endpoint: {
+            type: Array,
+            items: {
+                schema: TRAFFIC_CONTROL_ENDPOINT_SCHEMA,
+            },
+            optional: true,
         },
         timeframe: {
             type: Array,
@@ -108,8 +139,15 @@ pub struct TrafficControlRule {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
-    /// Rule applies to backup targets within these networks
-    pub backup_targets: Vec<String>,
+    /// Rule applies to peers within these networks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub network: Vec<String>,
+    /// Rule only applies to connections of these cloud targets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_target: Option<Vec<String>>,
+    /// Rule only applies to connections to these remote endpoints
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<Vec<String>>,
     #[serde(flatten)]
     pub limit: RateLimitConfig,
     /// Enable the rule at specific times
@@ -117,6 +155,21 @@ pub struct TrafficControlRule {
     pub timeframe: Option<Vec<String>>,
 }
 
+impl TrafficControlRule {
+    /// Whether the rule only applies to cloud connections
+    pub fn is_cloud_rule(&self) -> bool {
+        self.cloud_target.is_some() || self.endpoint.is_some()
+    }
+
+    /// Check that the rule classifies some traffic
+    pub fn check_classification(&self) -> Result<(), anyhow::Error> {
+        if self.network.is_empty() && !self.is_cloud_rule() {
+            bail!("rule needs at least one of 'network', 'cloud-target' or 'endpoint'");
+        }
+        Ok(())
+    }
+}
+
 #[api(
     properties: {
         config: {
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use anyhow::Error;
+use lazy_static::lazy_static;
+
+use proxmox_schema::*;
+use proxmox_section_config::{SectionConfig, SectionConfigData, SectionConfigPlugin};
+
+use pbs_api_types::{CloudTarget, CLOUD_TARGET_NAME_SCHEMA};
+
+use crate::{open_backup_lockfile, BackupLockGuard};
+
+lazy_static! {
+    pub static ref CONFIG: SectionConfig = init();
+}
+
+fn init() -> SectionConfig {
+    let obj_schema = match CloudTarget::API_SCHEMA {
+        Schema::AllOf(ref allof_schema) => allof_schema,
+        _ => unreachable!(),
+    };
+
+    let plugin =
+        SectionConfigPlugin::new("target".to_string(), Some("name".to_string()), obj_schema);
+    let mut config = SectionConfig::new(&CLOUD_TARGET_NAME_SCHEMA);
+    config.register_plugin(plugin);
+
+    config
+}
+
+pub const CLOUD_CFG_FILENAME: &str = "/etc/proxmox-backup/cloud.cfg";
+pub const CLOUD_CFG_LOCKFILE: &str = "/etc/proxmox-backup/.cloud.lck";
+
+/// Get exclusive lock
+pub fn lock_config() -> Result<BackupLockGuard, Error> {
+    open_backup_lockfile(CLOUD_CFG_LOCKFILE, None, true)
+}
+
+pub fn config() -> Result<(SectionConfigData, [u8; 32]), Error> {
+    let content =
+        proxmox_sys::fs::file_read_optional_string(CLOUD_CFG_FILENAME)?.unwrap_or_default();
+
+    let digest = openssl::sha::sha256(content.as_bytes());
+    let data = CONFIG.parse(CLOUD_CFG_FILENAME, &content)?;
+    Ok((data, digest))
+}
+
+pub fn save_config(config: &SectionConfigData) -> Result<(), Error> {
+    let raw = CONFIG.write(CLOUD_CFG_FILENAME, config)?;
+    crate::replace_backup_config(CLOUD_CFG_FILENAME, raw.as_bytes())
+}
+
+/// Lookup a cloud target by name
+pub fn lookup_target(name: &str) -> Result<CloudTarget, Error> {
+    let (config, _digest) = config()?;
+    config.lookup("target", name)
+}
+
+// shell completion helper
+pub fn complete_cloud_target_name(_arg: &str, _param: &HashMap<String, String>) -> Vec<String> {
+    match config() {
+        Ok((data, _digest)) => data.sections.keys().map(|id| id.to_string()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
@@ -5,7 +5,10 @@ use std::collections::HashMap;
 use proxmox_schema::{ApiType, Schema};
 use proxmox_section_config::{SectionConfig, SectionConfigData, SectionConfigPlugin};
 
-use pbs_api_types::{CloudBackupJobConfig, JOB_ID_SCHEMA};
+use pbs_api_types::{
+    CloudBackupJobConfig, CloudBackupJobTemplate, CloudDigestJobConfig, CloudPruneJobConfig,
+    CloudReplicationJobConfig, CloudRoleSyncJobConfig, CloudVerificationJobConfig, JOB_ID_SCHEMA,
+};
 
 use crate::{open_backup_lockfile, replace_backup_config, BackupLockGuard};
 
@@ -24,10 +27,63 @@ fn init() -> SectionConfig {
     let mut config = SectionConfig::new(&JOB_ID_SCHEMA);
     config.register_plugin(plugin);
 
+    let obj_schema = match CloudBackupJobTemplate::API_SCHEMA {
+        Schema::Object(ref obj_schema) => obj_schema,
+        _ => unreachable!(),
+    };
+    let plugin =
+        SectionConfigPlugin::new("template".to_string(), Some(String::from("id")), obj_schema);
+    config.register_plugin(plugin);
+
+    let obj_schema = match CloudReplicationJobConfig::API_SCHEMA {
+        Schema::Object(ref obj_schema) => obj_schema,
+        _ => unreachable!(),
+    };
+    let plugin = SectionConfigPlugin::new(
+        "replication".to_string(),
+        Some(String::from("id")),
+        obj_schema,
+    );
+    config.register_plugin(plugin);
+
+    let obj_schema = match CloudRoleSyncJobConfig::API_SCHEMA {
+        Schema::Object(ref obj_schema) => obj_schema,
+        _ => unreachable!(),
+    };
+    let plugin = SectionConfigPlugin::new(
+        "role-sync".to_string(),
+        Some(String::from("id")),
+        obj_schema,
+    );
+    config.register_plugin(plugin);
+
+    let obj_schema = match CloudDigestJobConfig::API_SCHEMA {
+        Schema::Object(ref obj_schema) => obj_schema,
+        _ => unreachable!(),
+    };
+    let plugin =
+        SectionConfigPlugin::new("digest".to_string(), Some(String::from("id")), obj_schema);
+    config.register_plugin(plugin);
+
+    let obj_schema = match CloudVerificationJobConfig::API_SCHEMA {
+        Schema::Object(ref obj_schema) => obj_schema,
+        _ => unreachable!(),
+    };
+    let plugin =
+        SectionConfigPlugin::new("verify".to_string(), Some(String::from("id")), obj_schema);
+    config.register_plugin(plugin);
+
+    let obj_schema = match CloudPruneJobConfig::API_SCHEMA {
+        Schema::AllOf(ref allof_schema) => allof_schema,
+        _ => unreachable!(),
+    };
+    let plugin =
+        SectionConfigPlugin::new("prune".to_string(), Some(String::from("id")), obj_schema);
+    config.register_plugin(plugin);
+
     config
 }
 
-
 pub const CLOUD_JOB_CFG_FILENAME: &str = "/etc/proxmox-backup/cloud-job.cfg";
 pub const CLOUD_JOB_CFG_LOCKFILE: &str = "/etc/proxmox-backup/.cloud-job.lck";
 
@@ -52,16 +108,45 @@ pub fn save_config(config: &SectionConfigData) -> Result<(), Error> {
 
 // shell completion helper
 
-/// List all tape job IDs
-pub fn complete_cloud_job_id(_arg: &str, _param: &HashMap<String, String>) -> Vec<String> {
+fn complete_section_id(section_type: &str) -> Vec<String> {
     match config() {
-        Ok((data, _digest)) => data.sections.keys().map(|id| id.to_string()).collect(),
+        Ok((data, _digest)) => data
+            .sections
+            .iter()
+            .filter(|(_, (ty, _))| ty == section_type)
+            .map(|(id, _)| id.to_string())
+            .collect(),
         Err(_) => Vec::new(),
     }
 }
 
-pub fn continue_cloud_job_id (_arg: &str, _param : &HashMap<String , String>) -> Vec<String> {
-    match config() {
+/// List all cloud job IDs
+pub fn complete_cloud_job_id(_arg: &str, _param: &HashMap<String, String>) -> Vec<String> {
+    complete_section_id("backup")
+}
 
-    }
-}
\ No newline at end of file
+/// List all cloud replication job IDs
+pub fn complete_cloud_replication_job_id(
+    _arg: &str,
+    _param: &HashMap<String, String>,
+) -> Vec<String> {
+    complete_section_id("replication")
+}
+
+/// List all cloud role sync job IDs
+pub fn complete_cloud_role_sync_job_id(
+    _arg: &str,
+    _param: &HashMap<String, String>,
+) -> Vec<String> {
+    complete_section_id("role-sync")
+}
+
+/// List all cloud digest job IDs
+pub fn complete_cloud_digest_job_id(_arg: &str, _param: &HashMap<String, String>) -> Vec<String> {
+    complete_section_id("digest")
+}
+
+/// List all cloud job template IDs
+pub fn complete_cloud_job_template_id(_arg: &str, _param: &HashMap<String, String>) -> Vec<String> {
+    complete_section_id("template")
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use anyhow::Error;
+use lazy_static::lazy_static;
+
+use proxmox_schema::{ApiType, Schema};
+use proxmox_section_config::{SectionConfig, SectionConfigData, SectionConfigPlugin};
+
+use pbs_api_types::{CloudMapping, CLOUD_MAPPING_ID_SCHEMA};
+
+use crate::{open_backup_lockfile, replace_backup_config, BackupLockGuard};
+
+lazy_static! {
+    pub static ref CONFIG: SectionConfig = init();
+}
+
+fn init() -> SectionConfig {
+    let obj_schema = match CloudMapping::API_SCHEMA {
+        Schema::Object(ref obj_schema) => obj_schema,
+        _ => unreachable!(),
+    };
+
+    let plugin =
+        SectionConfigPlugin::new("mapping".to_string(), Some(String::from("id")), obj_schema);
+    let mut config = SectionConfig::new(&CLOUD_MAPPING_ID_SCHEMA);
+    config.register_plugin(plugin);
+
+    config
+}
+
+pub const CLOUD_MAPPING_CFG_FILENAME: &str = "/etc/proxmox-backup/cloud-mapping.cfg";
+pub const CLOUD_MAPPING_CFG_LOCKFILE: &str = "/etc/proxmox-backup/.cloud-mapping.lck";
+
+/// Get exclusive lock
+pub fn lock() -> Result<BackupLockGuard, Error> {
+    open_backup_lockfile(CLOUD_MAPPING_CFG_LOCKFILE, None, true)
+}
+
+pub fn config() -> Result<(SectionConfigData, [u8; 32]), Error> {
+    let content =
+        proxmox_sys::fs::file_read_optional_string(CLOUD_MAPPING_CFG_FILENAME)?.unwrap_or_default();
+
+    let digest = openssl::sha::sha256(content.as_bytes());
+    let data = CONFIG.parse(CLOUD_MAPPING_CFG_FILENAME, &content)?;
+    Ok((data, digest))
+}
+
+pub fn save_config(config: &SectionConfigData) -> Result<(), Error> {
+    let raw = CONFIG.write(CLOUD_MAPPING_CFG_FILENAME, config)?;
+    replace_backup_config(CLOUD_MAPPING_CFG_FILENAME, raw.as_bytes())
+}
+
+/// All configured mappings
+pub fn mapping_list() -> Result<Vec<CloudMapping>, Error> {
+    let (config, _digest) = config()?;
+    config.convert_to_typed_array("mapping")
+}
+
+// shell completion helper
+pub fn complete_cloud_mapping_id(_arg: &str, _param: &HashMap<String, String>) -> Vec<String> {
+    match config() {
+        Ok((data, _digest)) => data.sections.keys().map(|id| id.to_string()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
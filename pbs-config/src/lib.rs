@@ -1,6 +1,9 @@
 pub mod acl;
 mod cached_user_info;
 pub use cached_user_info::CachedUserInfo;
+pub mod cloud;
+pub mod cloud_job;
+pub mod cloud_mapping;
 pub mod datastore;
 pub mod domains;
 pub mod drive;
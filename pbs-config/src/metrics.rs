@@ -6,7 +6,10 @@ use lazy_static::lazy_static;
 use proxmox_schema::*;
 use proxmox_section_config::{SectionConfig, SectionConfigData, SectionConfigPlugin};
 
-use pbs_api_types::{InfluxDbHttp, InfluxDbUdp, METRIC_SERVER_ID_SCHEMA};
+use pbs_api_types::{
+    CloudMetricsHttp, CloudMetricsUdp, CLOUD_BUCKET_SCHEMA, CLOUD_ORGANIZATION_SCHEMA,
+    HOST_PORT_SCHEMA, HTTP_URL_SCHEMA, METRIC_SERVER_ID_SCHEMA, SINGLE_LINE_COMMENT_SCHEMA,
+};
 
 use crate::{open_backup_lockfile, BackupLockGuard};
 
@@ -14,26 +17,92 @@ lazy_static! {
     pub static ref CONFIG: SectionConfig = init();
 }
 
+/// Section types of the InfluxDB servers of earlier versions
+///
+/// They are still parsed, so existing configs keep loading and can be
+/// saved, but no metrics are sent to them.
+pub const LEGACY_SECTION_TYPES: &[&str] = &["influxdb-http", "influxdb-udp"];
+
+const LEGACY_ENABLE_SCHEMA: Schema = BooleanSchema::new("Enables or disables the metrics server")
+    .default(true)
+    .schema();
+
+const LEGACY_MTU_SCHEMA: Schema = IntegerSchema::new("The MTU")
+    .minimum(1)
+    .maximum(65535)
+    .default(1500)
+    .schema();
+
+const LEGACY_TOKEN_SCHEMA: Schema = StringSchema::new("The (optional) API token").schema();
+
+const LEGACY_MAX_BODY_SIZE_SCHEMA: Schema = IntegerSchema::new("The (optional) maximum body size")
+    .minimum(1)
+    .default(25_000_000)
+    .schema();
+
+const LEGACY_VERIFY_TLS_SCHEMA: Schema =
+    BooleanSchema::new("If true, the certificate will be validated.")
+        .default(true)
+        .schema();
+
+const LEGACY_UDP_SCHEMA: ObjectSchema = ObjectSchema::new(
+    "InfluxDB Server (UDP), no longer supported",
+    &[
+        ("comment", true, &SINGLE_LINE_COMMENT_SCHEMA),
+        ("enable", true, &LEGACY_ENABLE_SCHEMA),
+        ("host", false, &HOST_PORT_SCHEMA),
+        ("mtu", true, &LEGACY_MTU_SCHEMA),
+        ("name", false, &METRIC_SERVER_ID_SCHEMA),
+    ],
+);
+
+const LEGACY_HTTP_SCHEMA: ObjectSchema = ObjectSchema::new(
+    "InfluxDB Server (HTTP(s)), no longer supported",
+    &[
+        ("bucket", true, &CLOUD_BUCKET_SCHEMA),
+        ("comment", true, &SINGLE_LINE_COMMENT_SCHEMA),
+        ("enable", true, &LEGACY_ENABLE_SCHEMA),
+        ("max-body-size", true, &LEGACY_MAX_BODY_SIZE_SCHEMA),
+        ("name", false, &METRIC_SERVER_ID_SCHEMA),
+        ("organization", true, &CLOUD_ORGANIZATION_SCHEMA),
+        ("token", true, &LEGACY_TOKEN_SCHEMA),
+        ("url", false, &HTTP_URL_SCHEMA),
+        ("verify-tls", true, &LEGACY_VERIFY_TLS_SCHEMA),
+    ],
+);
+
 fn init() -> SectionConfig {
     let mut config = SectionConfig::new(&METRIC_SERVER_ID_SCHEMA);
 
-    const UDP_SCHEMA: &ObjectSchema = InfluxDbUdp::API_SCHEMA.unwrap_object_schema();
-    let udp_plugin = SectionConfigPlugin::new(
-        "influxdb-udp".to_string(),
+    const CLOUD_UDP_SCHEMA: &ObjectSchema = CloudMetricsUdp::API_SCHEMA.unwrap_object_schema();
+    let cloud_udp_plugin = SectionConfigPlugin::new(
+        "cloud-udp".to_string(),
         Some("name".to_string()),
-        UDP_SCHEMA,
+        CLOUD_UDP_SCHEMA,
     );
-    config.register_plugin(udp_plugin);
+    config.register_plugin(cloud_udp_plugin);
 
-    const HTTP_SCHEMA: &ObjectSchema = InfluxDbHttp::API_SCHEMA.unwrap_object_schema();
+    const CLOUD_HTTP_SCHEMA: &ObjectSchema = CloudMetricsHttp::API_SCHEMA.unwrap_object_schema();
+    let cloud_http_plugin = SectionConfigPlugin::new(
+        "cloud-http".to_string(),
+        Some("name".to_string()),
+        CLOUD_HTTP_SCHEMA,
+    );
+    config.register_plugin(cloud_http_plugin);
 
-    let http_plugin = SectionConfigPlugin::new(
-        "influxdb-http".to_string(),
+    let legacy_udp_plugin = SectionConfigPlugin::new(
+        "influxdb-udp".to_string(),
         Some("name".to_string()),
-        HTTP_SCHEMA,
+        &LEGACY_UDP_SCHEMA,
     );
+    config.register_plugin(legacy_udp_plugin);
 
-    config.register_plugin(http_plugin);
+    let legacy_http_plugin = SectionConfigPlugin::new(
+        "influxdb-http".to_string(),
+        Some("name".to_string()),
+        &LEGACY_HTTP_SCHEMA,
+    );
+    config.register_plugin(legacy_http_plugin);
 
     config
 }
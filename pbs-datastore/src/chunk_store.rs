@@ -25,6 +25,8 @@ pub struct ChunkStore {
     chunk_dir: PathBuf,
     mutex: Mutex<()>,
     locker: Option<Arc<Mutex<ProcessLocker>>>,
+    // opened without locker, see `open_read_only`
+    read_only: bool,
     sync_level: DatastoreFSyncLevel,
 }
 
@@ -72,6 +74,7 @@ impl ChunkStore {
             chunk_dir: PathBuf::new(),
             mutex: Mutex::new(()),
             locker: None,
+            read_only: false,
             sync_level: Default::default(),
         }
     }
@@ -190,11 +193,40 @@ impl ChunkStore {
             base,
             chunk_dir,
             locker: Some(locker),
+            read_only: false,
             mutex: Mutex::new(()),
             sync_level,
         })
     }
 
+    /// Opens the chunk store without a process locker.
+    ///
+    /// Meant for read-only views of a chunk store, like file system snapshots. Chunks can only be
+    /// loaded and stat'ed, everything requiring the process locker panics.
+    pub(crate) fn open_read_only<P: Into<PathBuf>>(name: &str, base: P) -> Result<Self, Error> {
+        let base: PathBuf = base.into();
+
+        if !base.is_absolute() {
+            bail!("expected absolute path - got {:?}", base);
+        }
+
+        let chunk_dir = Self::chunk_dir(&base);
+
+        if let Err(err) = std::fs::metadata(&chunk_dir) {
+            bail!("unable to open chunk store '{name}' at {chunk_dir:?} - {err}");
+        }
+
+        Ok(ChunkStore {
+            name: name.to_owned(),
+            base,
+            chunk_dir,
+            locker: None,
+            read_only: true,
+            mutex: Mutex::new(()),
+            sync_level: Default::default(),
+        })
+    }
+
     pub fn touch_chunk(&self, digest: &[u8; 32]) -> Result<(), Error> {
         // unwrap: only `None` in unit tests
         assert!(self.locker.is_some());
@@ -523,8 +555,8 @@ impl ChunkStore {
     }
 
     pub fn chunk_path(&self, digest: &[u8; 32]) -> (PathBuf, String) {
-        // unwrap: only `None` in unit tests
-        assert!(self.locker.is_some());
+        // unwrap: only `None` in unit tests and read-only views
+        assert!(self.locker.is_some() || self.read_only);
 
         let mut chunk_path = self.chunk_dir.clone();
         let prefix = digest_to_prefix(digest);
@@ -535,8 +567,8 @@ impl ChunkStore {
     }
 
     pub fn relative_path(&self, path: &Path) -> PathBuf {
-        // unwrap: only `None` in unit tests
-        assert!(self.locker.is_some());
+        // unwrap: only `None` in unit tests and read-only views
+        assert!(self.locker.is_some() || self.read_only);
 
         let mut full_path = self.base.clone();
         full_path.push(path);
@@ -548,8 +580,8 @@ impl ChunkStore {
     }
 
     pub fn base_path(&self) -> PathBuf {
-        // unwrap: only `None` in unit tests
-        assert!(self.locker.is_some());
+        // unwrap: only `None` in unit tests and read-only views
+        assert!(self.locker.is_some() || self.read_only);
 
         self.base.clone()
     }
@@ -244,6 +244,35 @@ impl DataStore {
         Ok(Arc::new(Self { inner, operation }))
     }
 
+    /// Open a read-only view of the datastore `name` at `path`.
+    ///
+    /// For reading from a copy of the datastore, like a file system snapshot, while the datastore
+    /// itself stays in use. The view uses the configuration of the datastore, but has no process
+    /// locker and is not cached, so it must not be used to write or touch chunks.
+    pub fn open_read_only_path(name: &str, path: impl AsRef<Path>) -> Result<Arc<Self>, Error> {
+        let path = path
+            .as_ref()
+            .to_str()
+            .ok_or_else(|| format_err!("non-utf8 paths not supported"))?
+            .to_owned();
+
+        let (config, _digest) = pbs_config::datastore::config()?;
+        let config: DataStoreConfig = config.lookup("datastore", name)?;
+        let config = DataStoreConfig { path, ..config };
+
+        let chunk_store = ChunkStore::open_read_only(name, &config.path)?;
+        let inner = Arc::new(Self::with_store_and_config(
+            Arc::new(chunk_store),
+            config,
+            None,
+        )?);
+
+        Ok(Arc::new(Self {
+            inner,
+            operation: None,
+        }))
+    }
+
     fn with_store_and_config(
         chunk_store: Arc<ChunkStore>,
         config: DataStoreConfig,
@@ -605,9 +634,7 @@ impl DataStore {
         owner
             .trim_end() // remove trailing newline
             .parse()
-            .map_err(|err| {
-                format_err!("parsing owner for {backup_group} failed: {err}")
-            })
+            .map_err(|err| format_err!("parsing owner for {backup_group} failed: {err}"))
     }
 
     pub fn owns_backup(
@@ -174,6 +174,13 @@ impl<'a, F: Fn(&[u8; 32]) -> bool> Iterator for SnapshotChunkIterator<'a, F> {
 }
 
 impl<'a, F: Fn(&[u8; 32]) -> bool> SnapshotChunkIterator<'a, F> {
+    /// Do not iterate over the chunks of index file `filename`
+    ///
+    /// Call this before the first call to `next()`.
+    pub fn skip_file(&mut self, filename: &str) {
+        self.todo_list.retain(|name| name != filename);
+    }
+
     pub fn new(snapshot_reader: &'a SnapshotReader, skip_fn: F) -> Result<Self, Error> {
         let mut todo_list = Vec::new();
 
@@ -23,6 +23,7 @@ use pbs_config::CachedUserInfo;
 
 use crate::auth::private_auth_keyring;
 use crate::auth_helpers::*;
+use crate::cloud::{openid_roles::sync_login_roles, CLOUD_STATUS_DIR};
 
 fn openid_authenticator(
     realm_config: &OpenIdRealmConfig,
@@ -199,6 +200,19 @@ pub fn openid_login(
             }
         }
 
+        if let Some(ref roles_claim) = config.roles_claim {
+            let role_mapping = config.role_mapping.as_deref().unwrap_or_default();
+            sync_login_roles(
+                CLOUD_STATUS_DIR,
+                &realm,
+                roles_claim,
+                role_mapping,
+                &user_id,
+                &info,
+            )
+            .map_err(|err| format_err!("role mapping failed - {}", err))?;
+        }
+
         let api_ticket = ApiTicket::Full(user_id.clone());
         let ticket = Ticket::new("PBS", &api_ticket)?.sign(private_auth_keyring(), None)?;
         let token = assemble_csrf_prevention_token(csrf_secret(), &user_id);
@@ -33,13 +33,13 @@ use pxar::EntryKind;
 
 use pbs_api_types::{
     print_ns_and_snapshot, print_store_and_ns, Authid, BackupContent, BackupNamespace, BackupType,
-    Counts, CryptMode, DataStoreListItem, DataStoreStatus, GarbageCollectionStatus, GroupListItem,
-    KeepOptions, Operation, PruneJobOptions, RRDMode, RRDTimeFrame, SnapshotListItem,
-    SnapshotVerifyState, BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA,
-    BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA, DATASTORE_SCHEMA, IGNORE_VERIFIED_BACKUPS_SCHEMA,
-    MAX_NAMESPACE_DEPTH, NS_MAX_DEPTH_SCHEMA, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP,
-    PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE, PRIV_DATASTORE_READ, PRIV_DATASTORE_VERIFY,
-    UPID_SCHEMA, VERIFICATION_OUTDATED_AFTER_SCHEMA,
+    CloudTarget, Counts, CryptMode, DataStoreListItem, DataStoreStatus, GarbageCollectionStatus,
+    GroupListItem, KeepOptions, Operation, PruneJobOptions, RRDMode, RRDTimeFrame,
+    SnapshotListItem, SnapshotVerifyState, BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA,
+    BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA, DATASTORE_SCHEMA,
+    IGNORE_VERIFIED_BACKUPS_SCHEMA, MAX_NAMESPACE_DEPTH, NS_MAX_DEPTH_SCHEMA, PRIV_CLOUD_AUDIT,
+    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE,
+    PRIV_DATASTORE_READ, PRIV_DATASTORE_VERIFY, UPID_SCHEMA, VERIFICATION_OUTDATED_AFTER_SCHEMA,
 };
 use pbs_client::pxar::{create_tar, create_zip};
 use pbs_config::CachedUserInfo;
@@ -66,6 +66,7 @@ use crate::backup::{
     check_ns_privs_full, verify_all_backups, verify_backup_dir, verify_backup_group, verify_filter,
     ListAccessibleBackupGroups, NS_PRIVS_OK,
 };
+use crate::cloud::{catalog::CloudCatalog, content::snapshot_cloud_copies, CLOUD_STATUS_DIR};
 
 use crate::server::jobstate::Job;
 
@@ -455,6 +456,44 @@ pub async fn list_snapshots(
     .map_err(|err| format_err!("failed to await blocking task: {err}"))?
 }
 
+// catalogs of all cloud targets the user may audit
+fn load_cloud_catalogs(auth_id: &Authid) -> Vec<(String, CloudCatalog)> {
+    let targets: Vec<CloudTarget> = match pbs_config::cloud::config()
+        .and_then(|(config, _digest)| config.convert_to_typed_array("target"))
+    {
+        Ok(targets) => targets,
+        Err(err) => {
+            eprintln!("unable to read cloud target config - {}", err);
+            return Vec::new();
+        }
+    };
+
+    let user_info = match CachedUserInfo::new() {
+        Ok(user_info) => user_info,
+        Err(_) => return Vec::new(),
+    };
+
+    targets
+        .into_iter()
+        .filter(|target| {
+            let privs = user_info.lookup_privs(auth_id, &["cloud", "target", &target.name]);
+            privs & PRIV_CLOUD_AUDIT != 0
+        })
+        .filter_map(
+            |target| match CloudCatalog::load(CLOUD_STATUS_DIR, &target.name) {
+                Ok(catalog) => Some((target.name, catalog)),
+                Err(err) => {
+                    eprintln!(
+                        "unable to load catalog of cloud target '{}' - {}",
+                        target.name, err
+                    );
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
 /// This must not run in a main worker thread as it potentially does tons of I/O.
 unsafe fn list_snapshots_blocking(
     store: String,
@@ -497,12 +536,16 @@ unsafe fn list_snapshots_blocking(
         (None, None) => datastore.list_backup_groups(ns.clone())?,
     };
 
+    // offsite status, without a request per snapshot
+    let cloud_copies = snapshot_cloud_copies(&load_cloud_catalogs(&auth_id), &store, &ns);
+
     let info_to_snapshot_list_item = |group: &BackupGroup, owner, info: BackupInfo| {
         let backup = pbs_api_types::BackupDir {
             group: group.into(),
             time: info.backup_dir.backup_time(),
         };
         let protected = info.backup_dir.is_protected();
+        let cloud_copies = cloud_copies.get(&backup).cloned().unwrap_or_default();
 
         match get_all_snapshot_files(&info) {
             Ok((manifest, files)) => {
@@ -541,6 +584,7 @@ unsafe fn list_snapshots_blocking(
                     size,
                     owner,
                     protected,
+                    cloud_copies,
                 }
             }
             Err(err) => {
@@ -564,6 +608,7 @@ unsafe fn list_snapshots_blocking(
                     size: None,
                     owner,
                     protected,
+                    cloud_copies,
                 }
             }
         }
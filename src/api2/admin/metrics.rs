@@ -29,6 +29,9 @@ pub fn list_metric_servers(
     let mut list = Vec::new();
 
     for (_, (section_type, v)) in config.sections.iter() {
+        if metrics::LEGACY_SECTION_TYPES.contains(&section_type.as_str()) {
+            continue;
+        }
         let mut entry = v.clone();
         entry["type"] = Value::from(section_type.clone());
         if entry.get("url").is_some() {
@@ -37,6 +40,9 @@ pub fn list_metric_servers(
         if entry.get("host").is_some() {
             entry["server"] = entry["host"].clone();
         }
+        if entry.get("endpoint").is_some() {
+            entry["server"] = entry["endpoint"].clone();
+        }
         list.push(serde_json::from_value(entry)?);
     }
 
@@ -1,22 +1,20 @@
-//use anyhow::Error;
-//use serde_json::Value;
-//use proxmox_schema::api;
-//use proxmox_router::{list_subdirs_api_method, Router, SubdirMap};
-
-
-
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, format_err, Error};
-use serde_json::Value;
+use serde_json::{json, Value};
 
-use proxmox_lang::try_block;
-use proxmox_router::{list_subdirs_api_method, SubdirMap, Permission, Router, RpcEnvironment, RpcEnvironmentType};
-use proxmox_schema::api;
+use proxmox_human_byte::HumanByte;
+use proxmox_router::{Permission, Router, RpcEnvironment, RpcEnvironmentType};
+use proxmox_schema::{api, param_bail};
 use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
 
 use pbs_api_types::{
-    print_ns_and_snapshot, print_store_and_ns, Authid, CloudBackupJobSetup, MediaPoolConfig, Operation, TapeBackupJobConfig, TapeBackupJobSetup, TapeBackupJobStatus, Userid, JOB_ID_SCHEMA, PRIV_DATASTORE_READ, PRIV_TAPE_AUDIT, PRIV_TAPE_WRITE, UPID_SCHEMA
+    check_group_filters, print_ns_and_snapshot, print_store_and_ns, Authid, CloudBackupJobConfig,
+    CloudBackupJobSetup, CloudBackupJobStatus, CloudBackupSince, CloudErrorPolicy, CloudJobHooks,
+    CloudJobScheduleStatus, CloudSnapshotOrder, CloudTarget, Operation, Userid,
+    CLOUD_BACKUP_SINCE_SCHEMA, CLOUD_EXPORT_PATH_SCHEMA, CLOUD_TAG_SCHEMA, JOB_ID_SCHEMA,
+    PRIV_CLOUD_AUDIT, PRIV_CLOUD_BACKUP, PRIV_DATASTORE_READ, UPID_SCHEMA,
 };
 
 use pbs_config::CachedUserInfo;
@@ -25,130 +23,149 @@ use pbs_datastore::{DataStore, StoreProgress};
 use proxmox_rest_server::WorkerTask;
 
 use crate::{
+    api2::cloud::storage::start_staging_upload,
+    cloud::{
+        backend::{
+            error_message, open_fastest_backend, CloudBackend, LocalBackend, PutOptions,
+            StagingBackend,
+        },
+        catalog::CloudCatalog,
+        dedup_stats::{
+            add_group_stats, group_stats_name, log_group_stats, update_dedup_stats, DedupStats,
+        },
+        events::{publish_target_event, CloudEvent},
+        io_pacing::IoPacer,
+        job_chain::run_triggered_by,
+        job_errors::{ErrorAction, JobErrors, JobOutcome},
+        job_hooks::{run_post_hook, run_pre_hook},
+        job_pause::job_paused,
+        job_retry::{retry_schedule_status, RetryOptions},
+        job_splay::splay_schedule_status,
+        job_window::{wait_for_window, JobWindow},
+        mapping::{apply_cloud_mapping, job_mapping, job_target},
+        quota::{estimate_snapshot_usage, QuotaTracker},
+        request_trace::TaskRequestTrace,
+        staging::StagingSpool,
+        synthetic::create_synthetic_full,
+        task_checkpoint::run_with_checkpoint,
+        zfs_changes::ZfsChangeTracker,
+        zfs_snapshot::{self, ZfsSnapshotView},
+        CloudWriter, CLOUD_STATUS_DIR,
+    },
     server::{
         jobstate::{compute_schedule_status, Job, JobState},
-        lookup_user_email, TapeBackupJobSummary, CloudBackupJobSummary,
-    },
-    tape::{
-        changer::update_changer_online_status,
-        drive::{lock_tape_device, media_changer, set_tape_device_state, TapeLockError},
-        Inventory, MediaPool, PoolWriter, TAPE_STATUS_DIR,
+        lookup_user_email, CloudBackupJobSummary,
     },
-    cloud::CloudWriter,
 };
 
+const CLOUD_BACKUP_JOB_ROUTER: Router = Router::new().post(&API_METHOD_RUN_CLOUD_BACKUP_JOB);
 
-enum SnapshotBackupResult {
-    Success,
-    Error,
-    Ignored,
-}
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_CLOUD_BACKUP_JOBS)
+    .post(&API_METHOD_BACKUP)
+    .match_all("id", &CLOUD_BACKUP_JOB_ROUTER);
 
-// pub const ROUTER: Router = Router::new()
-//     .get(&API_METHOD_CLOUD_HELLO_BACKUP);
+pub(crate) fn check_backup_permission(
+    auth_id: &Authid,
+    store: &str,
+    target: &str,
+) -> Result<(), Error> {
+    let user_info = CachedUserInfo::new()?;
 
-const SUBDIRS: SubdirMap = &[
-    ("status", &Router::new().get(&API_METHOD_CLOUD_HELLO_BACKUP)),
-];
+    user_info.check_privs(auth_id, &["datastore", store], PRIV_DATASTORE_READ, false)?;
 
-const ITEM_ROUTER: Router = Router::new()
-    .get(&list_subdirs_api_method!(SUBDIRS))
-    .subdirs(SUBDIRS);
+    user_info.check_privs(
+        auth_id,
+        &["cloud", "target", target],
+        PRIV_CLOUD_BACKUP,
+        false,
+    )?;
 
-pub const ROUTER: Router = Router::new()
-    .get(&API_METHOD_CLOUD_HELLO_BACKUP)
-    .match_all("name", &ITEM_ROUTER);
-
-
-    fn check_backup_permission(
-        auth_id: &Authid,
-        store: &str,
-        pool: &str,
-        drive: &str,
-    ) -> Result<(), Error> {
-        let user_info = CachedUserInfo::new()?;
-    
-        user_info.check_privs(auth_id, &["datastore", store], PRIV_DATASTORE_READ, false)?;
-    
-        user_info.check_privs(auth_id, &["tape", "drive", drive], PRIV_TAPE_WRITE, false)?;
-    
-        user_info.check_privs(auth_id, &["tape", "pool", pool], PRIV_TAPE_WRITE, false)?;
-    
-        Ok(())
-    }
+    Ok(())
+}
 
 #[api(
     input: {
-        properties: {},
+        properties: {
+            tag: {
+                schema: CLOUD_TAG_SCHEMA,
+                optional: true,
+            },
+        },
     },
     returns: {
-        description: "Cloud hello backup.",
-        type: String,
+        description: "List configured cloud backup jobs and their status",
+        type: Array,
+        items: { type: CloudBackupJobStatus },
+    },
+    access: {
+        description: "List configured cloud jobs filtered by Cloud.Audit privileges",
+        permission: &Permission::Anybody,
     },
 )]
-/// Cloud Hello
-pub fn cloud_hello_backup(_param: Value) -> Result<String, Error> {
-    let prm = _param.to_string();
-    Ok(format!("api2/json/cloud/backup cloud-hello-world and value is: {}", prm))
-}
-
-/// List all tape backup jobs
-pub fn list_tape_backup_jobs(
-    _param: Value,
+/// List all cloud backup jobs
+pub fn list_cloud_backup_jobs(
+    tag: Option<String>,
     rpcenv: &mut dyn RpcEnvironment,
-) -> Result<Vec<TapeBackupJobStatus>, Error> {
+) -> Result<Vec<CloudBackupJobStatus>, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let user_info = CachedUserInfo::new()?;
 
-    let (job_config, digest) = pbs_config::tape_job::config()?;
-    let (pool_config, _pool_digest) = pbs_config::media_pool::config()?;
-    let (drive_config, _digest) = pbs_config::drive::config()?;
+    let (job_config, digest) = pbs_config::cloud_job::config()?;
 
-    let job_list_iter = job_config
-        .convert_to_typed_array("backup")?
-        .into_iter()
-        .filter(|_job: &TapeBackupJobConfig| {
-            // fixme: check access permission
-            true
-        });
+    let job_list: Vec<CloudBackupJobConfig> = job_config.convert_to_typed_array("backup")?;
 
     let mut list = Vec::new();
-    let current_time = proxmox_time::epoch_i64();
 
-    for job in job_list_iter {
-        let privs = user_info.lookup_privs(&auth_id, &["tape", "job", &job.id]);
-        if (privs & PRIV_TAPE_AUDIT) == 0 {
+    for job in job_list {
+        let privs = user_info.lookup_privs(&auth_id, &["cloud", "job", &job.id]);
+        if (privs & PRIV_CLOUD_AUDIT) == 0 {
             continue;
         }
+        if let Some(ref tag) = tag {
+            if !job.has_tag(tag) {
+                continue;
+            }
+        }
 
-        let last_state = JobState::load("tape-backup-job", &job.id)
+        let last_state = JobState::load("cloud-backup-job", &job.id)
             .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
 
         let status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
 
-        let next_run = status.next_run.unwrap_or(current_time);
-
-        let mut next_media_label = None;
-
-        if let Ok(pool) = pool_config.lookup::<MediaPoolConfig>("pool", &job.setup.pool) {
-            let mut changer_name = None;
-            if let Ok(Some((_, name))) = media_changer(&drive_config, &job.setup.drive) {
-                changer_name = Some(name);
-            }
-            if let Ok(mut pool) = MediaPool::with_config(TAPE_STATUS_DIR, &pool, changer_name, true)
-            {
-                if pool.start_write_session(next_run, false).is_ok() {
-                    if let Ok(media_id) = pool.guess_next_writable_media(next_run) {
-                        next_media_label = Some(media_id.label.label_text);
-                    }
-                }
-            }
+        let mut status: CloudJobScheduleStatus = status.into();
+        splay_schedule_status(
+            &mut status,
+            "cloud-backup-job",
+            &job.id,
+            job.schedule.as_deref(),
+            job.splay.as_deref(),
+        );
+        let retry =
+            RetryOptions::new(job.retry_on_failure, job.retry_delay.as_deref()).unwrap_or(None);
+        retry_schedule_status(
+            &mut status,
+            CLOUD_STATUS_DIR,
+            "cloud-backup-job",
+            &job.id,
+            &last_state,
+            retry.as_ref(),
+        );
+        status.paused = job_paused(CLOUD_STATUS_DIR, &job.id);
+        if job.disable || status.paused {
+            status.next_run = None;
+            status.next_retry = None;
         }
+        status.triggered_by = run_triggered_by(
+            CLOUD_STATUS_DIR,
+            "cloud-backup-job",
+            &job.id,
+            status.last_run_upid.as_deref(),
+        );
 
-        list.push(TapeBackupJobStatus {
+        list.push(CloudBackupJobStatus {
             config: job,
             status,
-            next_media_label,
         });
     }
 
@@ -157,44 +174,42 @@ pub fn list_tape_backup_jobs(
     Ok(list)
 }
 
+/// Job summary passed to the post-hook
+fn summary_hook_value(summary: &CloudBackupJobSummary) -> Value {
+    json!({
+        "snapshot-list": summary.snapshot_list,
+        "media-set": summary.media_set,
+        "duration": summary.duration.as_secs(),
+        "dedup-stats": summary.dedup_stats,
+        "group-errors": summary.group_errors,
+    })
+}
 
 pub fn do_cloud_backup_job(
     mut job: Job,
-    setup: CloudBackupJobSetup,
+    mut setup: CloudBackupJobSetup,
+    hooks: CloudJobHooks,
     auth_id: &Authid,
     schedule: Option<String>,
     to_stdout: bool,
 ) -> Result<String, Error> {
-    let job_id = format!(
-        "{}:{}:{}:{}",
-        setup.store,
-        setup.pool,
-        setup.drive,
-        job.jobname()
-    );
+    // keep the mapped target, even if the mapping changes while the job runs
+    let target = job_target(&setup)?;
+    setup.target = Some(target.clone());
+    let job_id = format!("{}:{}:{}", setup.store, target, job.jobname());
 
     let worker_type = job.jobtype().to_string();
 
     let datastore = DataStore::lookup_datastore(&setup.store, Some(Operation::Read))?;
 
-    // let (config, _digest) = pbs_config::media_pool::config()?;
-    // let pool_config: MediaPoolConfig = config.lookup("pool", &setup.pool)?;
-
-    let (drive_config, _digest) = pbs_config::drive::config()?;
-
-    // for scheduled jobs we acquire the lock later in the worker
-    // let drive_lock = if schedule.is_some() {
-    //     None
-    // } else {
-    //     Some(lock_tape_device(&drive_config, &setup.drive)?)
-    // };
-
     let notify_user = setup
         .notify_user
         .as_ref()
         .unwrap_or_else(|| Userid::root_userid());
     let email = lookup_user_email(notify_user);
 
+    let owner = auth_id.clone();
+
     let upid_str = WorkerTask::new_thread(
         &worker_type,
         Some(job_id.clone()),
@@ -202,42 +217,66 @@ pub fn do_cloud_backup_job(
         to_stdout,
         move |worker| {
             job.start(&worker.upid().to_string())?;
-            // let mut drive_lock = drive_lock;
+
+            let (job_type, job_name) = (job.jobtype().to_string(), job.jobname().to_string());
+            let upid = worker.upid().to_string();
 
             let mut summary = Default::default();
-            let job_result = try_block!({
-                if schedule.is_some() {
-                    // for scheduled tape backup jobs, we wait indefinitely for the lock
-                    task_log!(worker, "scheduling a cloud backup...");
-                    loop {
-                        worker.check_abort()?;
-                        // match lock_tape_device(&drive_config, &setup.drive) {
-                        //     Ok(lock) => {
-                        //         drive_lock = Some(lock);
-                        //         break;
-                        //     }
-                        //     Err(TapeLockError::TimeOut) => continue,
-                        //     Err(TapeLockError::Other(err)) => return Err(err),
-                        // }
+            let job_result = run_with_checkpoint(
+                &worker,
+                CLOUD_STATUS_DIR,
+                &job_type,
+                &job_name,
+                &owner,
+                || {
+                    run_pre_hook(&*worker, &hooks, &job_type, &job_name, &upid)?;
+                    publish_target_event(
+                        &*worker,
+                        &target,
+                        &CloudEvent::job_started(&target, &job_type, &job_name, &upid),
+                    );
+                    task_log!(worker, "Starting cloud backup job '{}'", job_id);
+                    if let Some(event_str) = schedule {
+                        task_log!(
+                            worker,
+                            "cloud backup task triggered by schedule '{}'",
+                            event_str
+                        );
                     }
-                }
-                //set_tape_device_state(&setup.drive, &worker.upid().to_string())?;
-
-                task_log!(worker, "Starting cloud backup job '{}'", job_id);
-                if let Some(event_str) = schedule {
-                    task_log!(worker, "cloud backup task triggered by schedule '{}'", event_str);
-                }
 
-                backup_worker(
-                    &worker,
-                    datastore,
-                    //&pool_config,
-                    &setup,
-                    email.clone(),
-                    &mut summary,
-                    //false,
-                )
-            });
+                    backup_worker(
+                        &worker,
+                        datastore,
+                        &setup,
+                        email.clone(),
+                        &mut summary,
+                        false,
+                        None,
+                        None,
+                    )
+                },
+            );
+            let job_result = run_post_hook(
+                &*worker,
+                &hooks,
+                &job_type,
+                &job_name,
+                &upid,
+                job_result,
+                summary_hook_value(&summary),
+            );
+            publish_target_event(
+                &*worker,
+                &target,
+                &CloudEvent::job_finished(
+                    &target,
+                    &job_type,
+                    &job_name,
+                    &upid,
+                    &job_result,
+                    summary_hook_value(&summary),
+                ),
+            );
 
             let status = worker.create_state(&job_result);
 
@@ -257,10 +296,6 @@ pub fn do_cloud_backup_job(
                 eprintln!("could not finish job state for {}: {}", job.jobtype(), err);
             }
 
-            if let Err(err) = set_tape_device_state(&setup.drive, "") {
-                eprintln!("could not unset drive state for {}: {}", setup.drive, err);
-            }
-
             job_result
         },
     )?;
@@ -268,20 +303,68 @@ pub fn do_cloud_backup_job(
     Ok(upid_str)
 }
 
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+        },
+    },
+    access: {
+        // Note: parameters are from job config, so we need to test inside function body
+        description: "The user needs Cloud.Backup privilege on /cloud/target/{target} \
+                      and Datastore.Read privilege on /datastore/{store}.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Runs a cloud backup job manually.
+pub fn run_cloud_backup_job(id: String, rpcenv: &mut dyn RpcEnvironment) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let (config, _digest) = pbs_config::cloud_job::config()?;
+    let backup_job: CloudBackupJobConfig = config.lookup("backup", &id)?;
+
+    let target = job_target(&backup_job.setup)?;
+    check_backup_permission(&auth_id, &backup_job.setup.store, &target)?;
+
+    let job = Job::new("cloud-backup-job", &id)?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = do_cloud_backup_job(
+        job,
+        backup_job.setup,
+        backup_job.hooks,
+        &auth_id,
+        None,
+        to_stdout,
+    )?;
+
+    Ok(upid_str)
+}
 
 #[api(
     input: {
         properties: {
             setup: {
-                type: TapeBackupJobSetup,
+                type: CloudBackupJobSetup,
                 flatten: true,
             },
-            // "force-media-set": {
-            //     description: "Ignore the allocation policy and start a new media-set.",
-            //     optional: true,
-            //     type: bool,
-            //     default: false,
-            // },
+            "force-full": {
+                description: "Start a new full media set instead of an incremental one.",
+                optional: true,
+                type: bool,
+                default: false,
+            },
+            since: {
+                schema: CLOUD_BACKUP_SINCE_SCHEMA,
+                optional: true,
+            },
+            "export-path": {
+                schema: CLOUD_EXPORT_PATH_SCHEMA,
+                optional: true,
+            },
         },
     },
     returns: {
@@ -289,36 +372,54 @@ pub fn do_cloud_backup_job(
     },
     access: {
         // Note: parameters are no uri parameter, so we need to test inside function body
-        description: "The user needs Tape.Write privilege on /tape/pool/{pool} \
-                      and /tape/drive/{drive}, Datastore.Read privilege on /datastore/{store}.",
+        description: "The user needs Cloud.Backup privilege on /cloud/target/{target} \
+                      and Datastore.Read privilege on /datastore/{store}.",
         permission: &Permission::Anybody,
     },
 )]
-/// Backup datastore to cloud
+/// Backup datastore to cloud target
 pub fn backup(
-    setup: CloudBackupJobSetup,
-    //force_media_set: bool,
+    mut setup: CloudBackupJobSetup,
+    force_full: bool,
+    since: Option<String>,
+    export_path: Option<String>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
-    print!("cloud/backup starting to progress");
-    log::info!("cloud/backup starting to progress.../s");
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
 
-    //check_backup_permission(&auth_id, &setup.store, &setup.pool, &setup.drive)?;
+    let target = job_target(&setup)?;
+    check_backup_permission(&auth_id, &setup.store, &target)?;
+    setup.target = Some(target.clone());
 
-    let datastore = DataStore::lookup_datastore(&setup.store, Some(Operation::Read))?;
+    if let Some(ref filters) = setup.group_filter {
+        if let Err(err) = check_group_filters(filters) {
+            param_bail!("group-filter", err);
+        }
+    }
+
+    // resolve relative values now, not when the worker gets to run
+    let since = match since {
+        Some(since) => match since.parse::<CloudBackupSince>() {
+            Ok(since) => Some(since.resolve(proxmox_time::epoch_i64())),
+            Err(err) => param_bail!("since", err),
+        },
+        None => None,
+    };
 
-    let (config, _digest) = pbs_config::media_pool::config()?;
-    let pool_config: MediaPoolConfig = config.lookup("pool", &setup.pool)?;
+    if let Some(ref path) = export_path {
+        if !Path::new(path).is_dir() {
+            param_bail!("export-path", "'{}' is not a directory", path);
+        }
+    }
 
-    let (drive_config, _digest) = pbs_config::drive::config()?;
+    let datastore = DataStore::lookup_datastore(&setup.store, Some(Operation::Read))?;
 
-    // early check/lock before starting worker
-    //let drive_lock = lock_tape_device(&drive_config, &setup.drive)?;
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&target)?;
 
     let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
 
-    let job_id = format!("{}:{}:{}", setup.store, setup.pool, setup.drive);
+    let job_id = format!("{}:{}", setup.store, target);
 
     let notify_user = setup
         .notify_user
@@ -332,18 +433,16 @@ pub fn backup(
         auth_id.to_string(),
         to_stdout,
         move |worker| {
-            //let _drive_lock = drive_lock; // keep lock guard
-            //set_tape_device_state(&setup.drive, &worker.upid().to_string())?; // commenting out tape device state check
-
             let mut summary = Default::default();
             let job_result = backup_worker(
                 &worker,
                 datastore,
-                //&pool_config,
                 &setup,
                 email.clone(),
                 &mut summary,
-                //force_media_set,
+                force_full,
+                since,
+                export_path.as_deref(),
             );
 
             if let Some(email) = email {
@@ -358,8 +457,6 @@ pub fn backup(
                 }
             }
 
-            // ignore errors
-            //let _ = set_tape_device_state(&setup.drive, "");
             job_result
         },
     )?;
@@ -367,28 +464,241 @@ pub fn backup(
     Ok(upid_str.into())
 }
 
+enum SnapshotBackupResult {
+    Success,
+    Error(Error),
+    Ignored,
+}
+
+// a snapshot selected for backup, with its position in its group
+struct PlannedSnapshot {
+    group_number: usize,
+    group_snapshots: usize,
+    snapshot_number: usize,
+    group_name: String,
+    info: BackupInfo,
+}
+
+// logical size of a snapshot, as recorded in its manifest
+fn snapshot_size(info: &BackupInfo) -> Option<u64> {
+    let (manifest, _) = info.backup_dir.load_manifest().ok()?;
+    Some(manifest.files().iter().map(|file| file.size).sum())
+}
+
+/// Split off the snapshots of a group (sorted oldest first) which are not
+/// among the newest `transfer_last` ones
+///
+/// Nothing is skipped without `transfer_last`, or if the group has fewer
+/// snapshots.
+pub fn skip_transfer_last<T>(list: &mut Vec<T>, transfer_last: Option<usize>) -> Vec<T> {
+    let cutoff = transfer_last
+        .map(|count| list.len().saturating_sub(count))
+        .unwrap_or_default();
+    list.drain(..cutoff).collect()
+}
+
+/// Order a backup plan, given in group order with the oldest snapshot first
+///
+/// The sort is stable, so snapshots with equal keys keep the group order.
+/// With `smallest-first`, snapshots of unknown size go last.
+pub fn order_snapshots<T>(
+    plan: &mut [T],
+    order: CloudSnapshotOrder,
+    backup_time: impl Fn(&T) -> i64,
+    size: impl Fn(&T) -> Option<u64>,
+) {
+    match order {
+        CloudSnapshotOrder::OldestFirst => {}
+        CloudSnapshotOrder::NewestFirst => {
+            plan.sort_by_key(|planned| std::cmp::Reverse(backup_time(planned)));
+        }
+        CloudSnapshotOrder::SmallestFirst => {
+            plan.sort_by_cached_key(|planned| size(planned).unwrap_or(u64::MAX));
+        }
+    }
+}
 
 fn backup_worker(
     worker: &WorkerTask,
     datastore: Arc<DataStore>,
-    //pool_config: &MediaPoolConfig,
     setup: &CloudBackupJobSetup,
     email: Option<String>,
     summary: &mut CloudBackupJobSummary,
-    //force_media_set: bool,
+    force_full: bool,
+    since: Option<i64>,
+    export_path: Option<&str>,
 ) -> Result<(), Error> {
     let start = std::time::Instant::now();
+    let _request_trace = TaskRequestTrace::start(worker, setup.request_trace.unwrap_or(false));
+
+    let mut target = pbs_config::cloud::lookup_target(&job_target(setup)?)?;
+
+    task_log!(
+        worker,
+        "cloud target: {} ({})",
+        target.name,
+        target.config.provider
+    );
+
+    if let Some(mapping) = job_mapping(setup)? {
+        task_log!(worker, "cloud mapping: {}", mapping.id);
+        apply_cloud_mapping(&mapping, &mut target)?;
+    }
+
+    let backend: Arc<dyn CloudBackend> = match export_path {
+        Some(path) => {
+            // keep the object keys of the target, so the export can be imported as is
+            let mut base = PathBuf::from(path);
+            if let Some(ref prefix) = target.config.prefix {
+                base.push(prefix);
+            }
+            task_log!(
+                worker,
+                "exporting to {:?} - reconcile the target after importing the data",
+                base
+            );
+            Arc::new(LocalBackend::with_base(base))
+        }
+        None => open_fastest_backend(worker, CLOUD_STATUS_DIR, &target)?,
+    };
+
+    let staging = match export_path {
+        Some(_) => None,
+        None => StagingSpool::for_target(CLOUD_STATUS_DIR, &target)?,
+    };
+    let backend: Arc<dyn CloudBackend> = match staging {
+        Some(ref spool) => {
+            task_log!(
+                worker,
+                "write-back: objects are uploaded asynchronously by 'cloud-staging-upload'"
+            );
+            Arc::new(StagingBackend::new(backend, Arc::clone(spool)))
+        }
+        None => backend,
+    };
 
-    task_log!(worker, "update media online status");
-    let changer_name = update_media_online_status(&setup.drive)?;
+    if let (Some(max_chain_length), false, None) = (setup.max_chain_length, force_full, export_path)
+    {
+        let chain_length = CloudCatalog::load(CLOUD_STATUS_DIR, &target.name)?
+            .current_chain()
+            .len() as u64;
+        if chain_length >= max_chain_length {
+            task_log!(
+                worker,
+                "chain has {} media sets (max-chain-length {}), creating synthetic full",
+                chain_length,
+                max_chain_length
+            );
+            create_synthetic_full(worker, CLOUD_STATUS_DIR, &target, &backend)?;
+        }
+    }
 
     let root_namespace = setup.ns.clone().unwrap_or_default();
-    let ns_magic = !root_namespace.is_root() || setup.max_depth != Some(0);
 
-    //let pool = MediaPool::with_config(TAPE_STATUS_DIR, pool_config, changer_name, false)?;
+    let put_options = match export_path {
+        Some(_) => {
+            if setup.storage_class.is_some() || setup.retention_lock.is_some() {
+                task_warn!(
+                    worker,
+                    "storage class and retention lock are not applied to exported objects"
+                );
+            }
+            PutOptions::default()
+        }
+        None => PutOptions::from_job_setup(setup)
+            .with_object_tags(&target, Some(setup))?
+            .with_request_tags(&target, &setup.store, worker.upid().worker_id.as_deref()),
+    };
+    put_options.check_capabilities(&backend.capabilities()?)?;
+    if let Some(ref storage_class) = put_options.storage_class {
+        task_log!(worker, "storage class: {}", storage_class);
+    }
+    if let (Some(days), Some(_)) = (setup.retention_lock, put_options.retain_until) {
+        task_log!(worker, "retention lock: {} days", days);
+    }
 
-    //let mut pool_writer = PoolWriter::new(pool, &setup.drive, worker, email, force_media_set, ns_magic)?;
-    let mut cloud_writer = CloudWriter::new(worker, email)?;
+    let window = JobWindow::from_job_setup(setup)?;
+    if let Some(ref window) = window {
+        // nothing written yet, so there is no lease to keep
+        if !wait_for_window(worker, window, || Ok(()))? {
+            if worker.shutdown_requested() {
+                bail!("server shutdown requested - job did not start");
+            }
+            task_warn!(
+                worker,
+                "job did not start, outside of the allowed time window"
+            );
+            return Ok(());
+        }
+    }
+
+    let zfs_tracker = open_zfs_tracker(worker, &datastore, &target, setup, since, export_path);
+    let zfs_changes = match zfs_tracker {
+        Some(ref tracker) => {
+            let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &target.name)?;
+            match tracker.changes(&catalog) {
+                Ok(Some(changes)) => {
+                    task_log!(
+                        worker,
+                        "zfs-change-detection: {} path(s) changed since {}",
+                        changes.len(),
+                        tracker.snapshot_name()
+                    );
+                    Some(changes)
+                }
+                Ok(None) => {
+                    task_log!(
+                        worker,
+                        "zfs-change-detection: no snapshot of the last run, scanning all groups"
+                    );
+                    None
+                }
+                Err(err) => {
+                    task_warn!(worker, "zfs-change-detection: {}", err);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    // keep the live datastore (and its operation count), read from the snapshot
+    let zfs_snapshot = create_zfs_snapshot(worker, &datastore, &target.name)?;
+    let datastore = match zfs_snapshot {
+        Some(ref snapshot) => snapshot.open_datastore(datastore.name())?,
+        None => Arc::clone(&datastore),
+    };
+
+    let quotas = target.config.quotas()?;
+    let mut quota_tracker = if quotas.is_empty() {
+        None
+    } else {
+        let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &target.name)?;
+        Some(QuotaTracker::new(quotas, &catalog))
+    };
+
+    let target_name = target.name.clone();
+    let mut cloud_writer =
+        CloudWriter::new(target, backend, worker, email, force_full, put_options)?;
+    if let Some(spool) = staging {
+        cloud_writer = cloud_writer.with_staging(spool);
+        // drain the spool while the job writes to it
+        if let Some(upid) = start_staging_upload(&target_name, Authid::root_auth_id(), false)? {
+            task_log!(worker, "started uploader {}", upid);
+        }
+    }
+
+    if let Some(pacer) = IoPacer::from_job_setup(setup) {
+        task_log!(
+            worker,
+            "local-io-latency: {} ms (minimum rate {}/s)",
+            pacer.threshold().as_millis(),
+            HumanByte::from(pacer.min_rate()),
+        );
+        cloud_writer = cloud_writer.with_io_pacer(pacer);
+    }
+
+    summary.media_set = Some(cloud_writer.media_set_uuid().to_string());
 
     let mut group_list = Vec::new();
     let namespaces = datastore.recursive_iter_backup_ns_ok(root_namespace, setup.max_depth)?;
@@ -401,10 +711,31 @@ fn backup_worker(
     let group_count_full = group_list.len();
 
     let group_list = match &setup.group_filter {
-        Some(f) => group_list
-            .into_iter()
-            .filter(|group| group.group().apply_filters(f))
-            .collect(),
+        Some(f) => {
+            let filters: Vec<String> = f.iter().map(|filter| filter.to_string()).collect();
+            task_log!(worker, "group filter: {}", filters.join(", "));
+            group_list
+                .into_iter()
+                .filter(|group| group.group().apply_filters(f))
+                .collect()
+        }
+        None => group_list,
+    };
+
+    let group_list = match zfs_changes {
+        Some(ref changes) => {
+            let count = group_list.len();
+            let group_list: Vec<_> = group_list
+                .into_iter()
+                .filter(|group| changes.touches(&group.full_group_path()))
+                .collect();
+            task_log!(
+                worker,
+                "zfs-change-detection: skipping {} unchanged groups",
+                count - group_list.len()
+            );
+            group_list
+        }
         None => group_list,
     };
 
@@ -415,7 +746,7 @@ fn backup_worker(
         group_count_full
     );
 
-    let mut progress = StoreProgress::new(group_list.len() as u64);
+    let group_count = group_list.len();
 
     let latest_only = setup.latest_only.unwrap_or(false);
 
@@ -426,18 +757,37 @@ fn backup_worker(
         );
     }
 
-    let datastore_name = datastore.name();
+    if let Some(since) = since {
+        task_log!(
+            worker,
+            "since: {} (only considering newer snapshots)",
+            proxmox_time::epoch_to_rfc3339_utc(since)?
+        );
+    }
 
-    let mut errors = false;
+    let transfer_last = if latest_only {
+        None
+    } else {
+        setup.transfer_last
+    };
 
-    let mut need_catalog = false; // avoid writing catalog for empty jobs
+    if let Some(count) = transfer_last {
+        task_log!(
+            worker,
+            "transfer-last: {} (only considering the newest snapshots of each group)",
+            count
+        );
+    } else if latest_only && setup.transfer_last.is_some() {
+        task_warn!(worker, "ignoring transfer-last, latest-only is set");
+    }
 
-    for (group_number, group) in group_list.into_iter().enumerate() {
-        progress.done_groups = group_number as u64;
-        progress.done_snapshots = 0;
-        progress.group_snapshots = 0;
+    let datastore_name = datastore.name();
 
+    // select the snapshots of each group, then order them
+    let mut plan = Vec::new();
+    for (group_number, group) in group_list.into_iter().enumerate() {
         let snapshot_list = group.list_backups()?;
+        let group_name = group_stats_name(datastore_name, group.backup_ns(), group.group());
 
         // filter out unfinished backups
         let mut snapshot_list: Vec<_> = snapshot_list
@@ -455,128 +805,355 @@ fn backup_worker(
             continue;
         }
 
+        if let Some(since) = since {
+            snapshot_list.retain(|item| item.backup_dir.backup_time() >= since);
+            if snapshot_list.is_empty() {
+                task_log!(
+                    worker,
+                    "{}, group {} has no new snapshots",
+                    print_store_and_ns(datastore_name, group.backup_ns()),
+                    group.group()
+                );
+                continue;
+            }
+        }
+
         BackupInfo::sort_list(&mut snapshot_list, true); // oldest first
 
         if latest_only {
-            progress.group_snapshots = 1;
-            if let Some(info) = snapshot_list.pop() {
-                let rel_path =
-                    print_ns_and_snapshot(info.backup_dir.backup_ns(), info.backup_dir.as_ref());
-                // if pool_writer.contains_snapshot(
-                //     datastore_name,
-                //     info.backup_dir.backup_ns(),
-                //     info.backup_dir.as_ref(),
-                // ) {
-                //     task_log!(worker, "skip snapshot {}", rel_path);
-                //     continue;
-                // }
-
-                need_catalog = true;
-
-                // match backup_snapshot(worker, &mut pool_writer, datastore.clone(), info.backup_dir)?
-                // {
-                //     SnapshotBackupResult::Success => summary.snapshot_list.push(rel_path),
-                //     SnapshotBackupResult::Error => errors = true,
-                //     SnapshotBackupResult::Ignored => {}
-                // }
-                progress.done_snapshots = 1;
-                task_log!(worker, "percentage done: {}", progress);
-            }
+            snapshot_list.drain(..snapshot_list.len() - 1);
         } else {
-            progress.group_snapshots = snapshot_list.len() as u64;
-            for (snapshot_number, info) in snapshot_list.into_iter().enumerate() {
-                let rel_path =
-                    print_ns_and_snapshot(info.backup_dir.backup_ns(), info.backup_dir.as_ref());
-
-                // if pool_writer.contains_snapshot(
-                //     datastore_name,
-                //     info.backup_dir.backup_ns(),
-                //     info.backup_dir.as_ref(),
-                // ) {
-                //     task_log!(worker, "skip snapshot {}", rel_path);
-                //     continue;
-                // }
-
-                need_catalog = true;
-
-                // match backup_snapshot(worker, &mut pool_writer, datastore.clone(), info.backup_dir)?
-                // {
-                //     SnapshotBackupResult::Success => summary.snapshot_list.push(rel_path),
-                //     SnapshotBackupResult::Error => errors = true,
-                //     SnapshotBackupResult::Ignored => {}
-                // }
-                progress.done_snapshots = snapshot_number as u64 + 1;
-                task_log!(worker, "percentage done: {}", progress);
+            let skipped = skip_transfer_last(&mut snapshot_list, transfer_last);
+            if let (Some(first), Some(last)) = (skipped.first(), skipped.last()) {
+                task_log!(
+                    worker,
+                    "{}, group {}: skipped {} snapshot(s) ({} .. {}) due to transfer-last",
+                    print_store_and_ns(datastore_name, group.backup_ns()),
+                    group.group(),
+                    skipped.len(),
+                    first.backup_dir.backup_time_string(),
+                    last.backup_dir.backup_time_string(),
+                );
             }
         }
+
+        let group_snapshots = snapshot_list.len();
+        for (snapshot_number, info) in snapshot_list.into_iter().enumerate() {
+            plan.push(PlannedSnapshot {
+                group_number,
+                group_snapshots,
+                snapshot_number,
+                group_name: group_name.clone(),
+                info,
+            });
+        }
+    }
+
+    let order = setup.order.unwrap_or_default();
+    if order != CloudSnapshotOrder::OldestFirst {
+        task_log!(worker, "order: {}", order);
+    }
+    order_snapshots(
+        &mut plan,
+        order,
+        |planned| planned.info.backup_dir.backup_time(),
+        |planned| snapshot_size(&planned.info),
+    );
+
+    let planned = plan.len();
+    // without group order, progress is counted in snapshots
+    let mut progress = match order {
+        CloudSnapshotOrder::OldestFirst => StoreProgress::new(group_count as u64),
+        _ => {
+            let mut progress = StoreProgress::new(1);
+            progress.group_snapshots = planned as u64;
+            progress
+        }
+    };
+
+    let mut job_errors = JobErrors::new(setup.on_error.unwrap_or_default());
+    if job_errors.policy() != CloudErrorPolicy::Continue {
+        task_log!(worker, "on-error: {}", job_errors.policy());
     }
 
-    // pool_writer.commit()?;
+    let mut interrupted = false;
+    let mut window_closed = false;
+
+    let mut plan = plan.into_iter().enumerate();
+    for (number, planned) in plan.by_ref() {
+        if worker.shutdown_requested() {
+            interrupted = true;
+            break;
+        }
 
-    // if need_catalog {
-    //     task_log!(worker, "append media catalog");
+        let info = planned.info;
+        let rel_path = print_ns_and_snapshot(info.backup_dir.backup_ns(), info.backup_dir.as_ref());
 
-    //     let uuid = pool_writer.load_writable_media(worker)?;
-    //     let done = pool_writer.append_catalog_archive(worker)?;
-    //     if !done {
-    //         task_log!(
-    //             worker,
-    //             "catalog does not fit on tape, writing to next volume"
-    //         );
-    //         pool_writer.set_media_status_full(&uuid)?;
-    //         pool_writer.load_writable_media(worker)?;
-    //         let done = pool_writer.append_catalog_archive(worker)?;
-    //         if !done {
-    //             bail!("write_catalog_archive failed on second media");
-    //         }
-    //     }
-    // }
+        if cloud_writer.contains_snapshot(
+            datastore_name,
+            info.backup_dir.backup_ns(),
+            info.backup_dir.as_ref(),
+        ) {
+            task_log!(worker, "skip snapshot {}", rel_path);
+            continue;
+        }
+
+        if job_errors.skip_snapshot(planned.group_number, &planned.group_name) {
+            task_log!(worker, "skip snapshot {} (group failed)", rel_path);
+            continue;
+        }
 
-    // if setup.export_media_set.unwrap_or(false) {
-    //     pool_writer.export_media_set(worker)?;
-    // } else if setup.eject_media.unwrap_or(false) {
-    //     pool_writer.eject_media(worker)?;
-    // }
+        if let Some(ref window) = window {
+            if !wait_for_window(worker, window, || cloud_writer.keep_alive())? {
+                interrupted = worker.shutdown_requested();
+                window_closed = !interrupted;
+                break;
+            }
+        }
 
-    if errors {
-        bail!("Tape backup finished with some errors. Please check the task log.");
+        let mut stats = DedupStats::default();
+        let error = match backup_snapshot(
+            worker,
+            &mut cloud_writer,
+            datastore.clone(),
+            info.backup_dir,
+            &mut stats,
+            quota_tracker.as_mut(),
+        ) {
+            Ok(SnapshotBackupResult::Success) => {
+                add_group_stats(&mut summary.dedup_stats, &planned.group_name, &stats);
+                summary.snapshot_list.push(rel_path.clone());
+                job_errors.record_success();
+                None
+            }
+            Ok(SnapshotBackupResult::Error(err)) => Some(err),
+            Ok(SnapshotBackupResult::Ignored) => None,
+            // aborted tasks stop regardless of the error policy
+            Err(err) if worker.abort_requested() => return Err(err),
+            Err(err) => {
+                task_warn!(
+                    worker,
+                    "backup of snapshot {} failed - {}",
+                    rel_path,
+                    error_message(&err)
+                );
+                Some(err)
+            }
+        };
+
+        if let Some(err) = error {
+            let action =
+                job_errors.record_error(planned.group_number, &planned.group_name, &rel_path, &err);
+            match action {
+                ErrorAction::Continue => {}
+                ErrorAction::Abort => {
+                    task_warn!(
+                        worker,
+                        "on-error: abort-job - stopping after failed snapshot"
+                    );
+                    break;
+                }
+            }
+            if job_errors.policy() == CloudErrorPolicy::AbortGroup {
+                task_log!(
+                    worker,
+                    "on-error: abort-group - skipping remaining snapshots of {}",
+                    planned.group_name
+                );
+            }
+        }
+
+        match order {
+            CloudSnapshotOrder::OldestFirst => {
+                progress.done_groups = planned.group_number as u64;
+                progress.group_snapshots = planned.group_snapshots as u64;
+                progress.done_snapshots = planned.snapshot_number as u64 + 1;
+            }
+            _ => progress.done_snapshots = number as u64 + 1,
+        }
+        task_log!(worker, "percentage done: {}", progress);
     }
 
-    // summary.used_tapes = match pool_writer.get_used_media_labels() {
-    //     Ok(tapes) => Some(tapes),
-    //     Err(err) => {
-    //         task_warn!(worker, "could not collect list of used tapes: {err}");
-    //         None
-    //     }
-    // };
+    if job_errors.aborted() {
+        for (_, planned) in plan {
+            let backup_dir = &planned.info.backup_dir;
+            if !cloud_writer.contains_snapshot(
+                datastore_name,
+                backup_dir.backup_ns(),
+                backup_dir.as_ref(),
+            ) {
+                job_errors.record_not_attempted(&planned.group_name);
+            }
+        }
+    }
+    summary.group_errors = job_errors.groups().to_vec();
 
-    summary.duration = start.elapsed();
+    // keep what was written so far, a resumed job skips those snapshots
+    task_log!(worker, "write media set catalog");
+    cloud_writer.commit()?;
 
-    Ok(())
-}
+    if let Some(throttled) = cloud_writer.io_throttled() {
+        if !throttled.is_zero() {
+            task_log!(
+                worker,
+                "throttled for {:.1}s to protect local I/O",
+                throttled.as_secs_f64()
+            );
+        }
+    }
 
-// Try to update the the media online status
-fn update_media_online_status(drive: &str) -> Result<Option<String>, Error> {
-    let (config, _digest) = pbs_config::drive::config()?;
+    log_group_stats(worker, &summary.dedup_stats);
+    if export_path.is_none() {
+        let target_name = &cloud_writer.target().name;
+        if let Err(err) = update_dedup_stats(CLOUD_STATUS_DIR, target_name, &summary.dedup_stats) {
+            task_warn!(worker, "unable to store deduplication statistics - {}", err);
+        }
+    }
 
-    if let Ok(Some((mut changer, changer_name))) = media_changer(&config, drive) {
-        let label_text_list = changer.online_media_label_texts()?;
+    if interrupted {
+        bail!("server shutdown requested - stopped after {}", progress);
+    }
 
-        let mut inventory = Inventory::load(TAPE_STATUS_DIR)?;
+    if window_closed {
+        // the next run continues with the remaining snapshots
+        task_warn!(
+            worker,
+            "stopped at the end of the allowed time window after {}",
+            progress
+        );
+    }
 
-        update_changer_online_status(&config, &mut inventory, &changer_name, &label_text_list)?;
+    if let Some(ref tracker) = zfs_tracker {
+        // groups of an incomplete run must be scanned again
+        let result = if window_closed || job_errors.has_errors() {
+            tracker.abort()
+        } else {
+            tracker.commit(cloud_writer.media_set_uuid())
+        };
+        if let Err(err) = result {
+            task_warn!(worker, "zfs-change-detection: {}", err);
+        }
+    }
 
-        Ok(Some(changer_name))
-    } else {
-        Ok(None)
+    if let Some(snapshot) = zfs_snapshot {
+        drop(datastore);
+        task_log!(worker, "destroy ZFS snapshot {}", snapshot.snapshot_name());
+        if let Err(err) = snapshot.destroy() {
+            task_warn!(worker, "unable to destroy ZFS snapshot - {}", err);
+        }
+    }
+
+    let failed_groups = summary
+        .group_errors
+        .iter()
+        .filter(|group| !group.errors.is_empty())
+        .count();
+
+    if job_errors.aborted() {
+        bail!(
+            "Cloud backup stopped after a failed snapshot (on-error: abort-job), \
+             {} snapshot(s) backed up. Please check the task log.",
+            summary.snapshot_list.len()
+        );
+    }
+
+    match job_errors.outcome() {
+        JobOutcome::Success => {}
+        JobOutcome::PartialSuccess => {
+            // a warning, so the task state tells partial success from failure
+            task_warn!(
+                worker,
+                "Cloud backup finished with errors: {} snapshot(s) in {} group(s) failed, \
+                 {} snapshot(s) backed up. Please check the task log.",
+                job_errors.error_count(),
+                failed_groups,
+                summary.snapshot_list.len()
+            );
+        }
+        JobOutcome::Failed => bail!(
+            "Cloud backup failed: none of the {} attempted snapshot(s) could be backed up. \
+             Please check the task log.",
+            job_errors.error_count()
+        ),
+    }
+
+    summary.duration = start.elapsed();
+
+    Ok(())
+}
+
+// snapshot the datastore dataset for 'zfs-change-detection'
+fn open_zfs_tracker(
+    worker: &WorkerTask,
+    datastore: &Arc<DataStore>,
+    target: &CloudTarget,
+    setup: &CloudBackupJobSetup,
+    since: Option<i64>,
+    export_path: Option<&str>,
+) -> Option<ZfsChangeTracker> {
+    if !setup.zfs_change_detection.unwrap_or(false) {
+        return None;
+    }
+    if since.is_some() || export_path.is_some() {
+        task_log!(
+            worker,
+            "zfs-change-detection: not used for runs with 'since' or 'export-path'"
+        );
+        return None;
+    }
+    let tracker = match ZfsChangeTracker::open(&datastore.base_path(), &target.name, setup) {
+        Ok(Some(tracker)) => tracker,
+        Ok(None) => {
+            task_warn!(
+                worker,
+                "zfs-change-detection: datastore '{}' is not on ZFS",
+                datastore.name()
+            );
+            return None;
+        }
+        Err(err) => {
+            task_warn!(worker, "zfs-change-detection: {}", err);
+            return None;
+        }
+    };
+    match tracker.begin() {
+        Ok(()) => Some(tracker),
+        Err(err) => {
+            task_warn!(worker, "zfs-change-detection: {}", err);
+            None
+        }
+    }
+}
+
+// snapshot the datastore dataset for 'cloud-zfs-snapshot'
+fn create_zfs_snapshot(
+    worker: &WorkerTask,
+    datastore: &Arc<DataStore>,
+    target: &str,
+) -> Result<Option<ZfsSnapshotView>, Error> {
+    let options = zfs_snapshot::datastore_options(datastore.name())?;
+    if !options.enable {
+        return Ok(None);
     }
+    let snapshot =
+        ZfsSnapshotView::create(&datastore.base_path(), datastore.name(), target, &options)
+            .map_err(|err| format_err!("cloud-zfs-snapshot: {}", err))?;
+    task_log!(
+        worker,
+        "reading from ZFS snapshot {} ({:?})",
+        snapshot.snapshot_name(),
+        snapshot.path()
+    );
+    Ok(Some(snapshot))
 }
 
 fn backup_snapshot(
     worker: &WorkerTask,
-    pool_writer: &mut PoolWriter,
+    cloud_writer: &mut CloudWriter,
     datastore: Arc<DataStore>,
     snapshot: BackupDir,
+    stats: &mut DedupStats,
+    quota_tracker: Option<&mut QuotaTracker>,
 ) -> Result<SnapshotBackupResult, Error> {
     let snapshot_path = snapshot.relative_path();
     task_log!(worker, "backup snapshot {:?}", snapshot_path);
@@ -596,14 +1173,53 @@ fn backup_snapshot(
                 snapshot_path,
                 err
             );
-            return Ok(SnapshotBackupResult::Error);
+            return Ok(SnapshotBackupResult::Error(format_err!(
+                "failed opening snapshot - {}",
+                err
+            )));
         }
     };
 
+    if let Some(fingerprint) = cloud_writer.select_namespace_key(snapshot.backup_ns())? {
+        task_log!(worker, "encrypt with key {}", fingerprint.signature());
+    }
+
+    // unchanged index files need no chunk lookups
+    let delta = match cloud_writer.delta_base(&snapshot_reader) {
+        Ok(Some(delta)) => {
+            task_log!(
+                worker,
+                "{} index file(s) unchanged since {}",
+                delta.unchanged.len(),
+                delta.snapshot
+            );
+            Some(delta)
+        }
+        Ok(None) => None,
+        Err(err) => {
+            task_warn!(worker, "unable to compare with previous snapshot - {}", err);
+            None
+        }
+    };
+
+    *stats = cloud_writer.snapshot_dedup_stats(&snapshot_reader, delta.as_ref())?;
+
+    let owner = snapshot.get_owner().ok();
+    if let Some(ref tracker) = quota_tracker {
+        let estimate = estimate_snapshot_usage(stats, snapshot_reader.file_list().len());
+        if let Err(err) = tracker.check(owner.as_ref(), snapshot.backup_ns(), &estimate) {
+            task_warn!(worker, "skip snapshot {:?}: {}", snapshot_path, err);
+            return Ok(SnapshotBackupResult::Error(err));
+        }
+    }
+
     let snapshot_reader = Arc::new(Mutex::new(snapshot_reader));
 
-    let (reader_thread, chunk_iter) =
-        pool_writer.spawn_chunk_reader_thread(datastore.clone(), snapshot_reader.clone())?;
+    let (reader_thread, chunk_iter) = cloud_writer.spawn_chunk_reader_thread(
+        datastore.clone(),
+        snapshot_reader.clone(),
+        delta.as_ref(),
+    )?;
 
     let mut chunk_iter = chunk_iter.peekable();
 
@@ -617,15 +1233,12 @@ fn backup_snapshot(
             Some(Err(err)) => bail!("{}", err),
         }
 
-        let uuid = pool_writer.load_writable_media(worker)?;
-
-        worker.check_abort()?;
-
-        let (leom, _bytes) =
-            pool_writer.append_chunk_archive(worker, &mut chunk_iter, datastore.name())?;
+        let (done, bytes) =
+            cloud_writer.append_chunk_archive(worker, &mut chunk_iter, datastore.name())?;
+        stats.uploaded_bytes += bytes as u64;
 
-        if leom {
-            pool_writer.set_media_status_full(&uuid)?;
+        if done {
+            break;
         }
     }
 
@@ -635,26 +1248,11 @@ fn backup_snapshot(
 
     worker.check_abort()?;
 
-    let uuid = pool_writer.load_writable_media(worker)?;
-
-    worker.check_abort()?;
-
     let snapshot_reader = snapshot_reader.lock().unwrap();
 
-    let (done, _bytes) = pool_writer.append_snapshot_archive(worker, &snapshot_reader)?;
-
-    if !done {
-        // does not fit on tape, so we try on next volume
-        pool_writer.set_media_status_full(&uuid)?;
-
-        worker.check_abort()?;
-
-        pool_writer.load_writable_media(worker)?;
-        let (done, _bytes) = pool_writer.append_snapshot_archive(worker, &snapshot_reader)?;
-
-        if !done {
-            bail!("write_snapshot_archive failed on second media");
-        }
+    let attributed = cloud_writer.append_snapshot_archive(worker, &snapshot_reader)?;
+    if let Some(tracker) = quota_tracker {
+        tracker.add(owner.as_ref(), snapshot.backup_ns(), &attributed);
     }
 
     task_log!(
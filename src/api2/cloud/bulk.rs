@@ -0,0 +1,824 @@
+//! Bulk operations on cloud backup jobs
+
+use anyhow::{bail, Error};
+use hex::FromHex;
+
+use proxmox_router::{
+    list_subdirs_api_method, Permission, Router, RpcEnvironment, RpcEnvironmentType, SubdirMap,
+};
+use proxmox_schema::{api, param_bail};
+use proxmox_section_config::SectionConfigData;
+use proxmox_sortable_macro::sortable;
+
+use pbs_api_types::{
+    parse_bulk_job_source, Authid, CloudBackupJobConfig, CloudBackupJobSelector,
+    CloudBackupJobTemplate, CloudBulkJobResult, CloudJobTriple, CloudPruneJobConfig,
+    CloudTargetWithoutSecret, CloudVerificationJobConfig, KeepOptions, PruneJobConfig,
+    PruneJobOptions, CLOUD_BULK_JOB_SOURCE_SCHEMA, CLOUD_CONFIG_VALIDATE_SCHEMA,
+    CLOUD_JOB_TEMPLATE_ID_SCHEMA, CLOUD_PRUNE_SCHEDULE_SCHEMA, CLOUD_TARGET_NAME_SCHEMA,
+    CLOUD_VERIFICATION_OUTDATED_AFTER_SCHEMA, CLOUD_VERIFICATION_SCHEDULE_SCHEMA, PRIV_CLOUD_AUDIT,
+    PRIV_CLOUD_DELETE, PRIV_CLOUD_MODIFY, PRIV_DATASTORE_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+use pbs_config::CachedUserInfo;
+
+use crate::api2::config::cloud_backup_job::check_job_setup;
+use crate::cloud::config_history::{record_config_change, section_data};
+use crate::cloud::job_chain::chained_jobs;
+use crate::cloud::job_pause::resume_job;
+use crate::cloud::job_retry::remove_job_retries;
+use crate::cloud::mapping::job_target;
+use crate::cloud::CLOUD_STATUS_DIR;
+use crate::server::jobstate::Job;
+
+use super::backup::{check_backup_permission, do_cloud_backup_job};
+
+/// Returns all jobs matching the selector which the user can see.
+fn select_jobs(
+    auth_id: &Authid,
+    selector: &CloudBackupJobSelector,
+    config: &SectionConfigData,
+) -> Result<Vec<CloudBackupJobConfig>, Error> {
+    let user_info = CachedUserInfo::new()?;
+
+    let list: Vec<CloudBackupJobConfig> = config.convert_to_typed_array("backup")?;
+
+    let (target_config, _digest) = pbs_config::cloud::config()?;
+
+    Ok(list
+        .into_iter()
+        .filter(|job| {
+            let privs = user_info.lookup_privs(auth_id, &["cloud", "job", &job.id]);
+            if privs & PRIV_CLOUD_AUDIT == 0 {
+                return false;
+            }
+            let target_name = job_target(&job.setup).ok();
+            let target: Option<CloudTargetWithoutSecret> = target_name
+                .as_ref()
+                .and_then(|name| target_config.lookup("target", name).ok());
+            selector.matches(
+                job,
+                target_name.as_deref(),
+                target.as_ref().map(|target| &target.config),
+            )
+        })
+        .collect())
+}
+
+fn set_jobs_disabled(
+    auth_id: &Authid,
+    selector: &CloudBackupJobSelector,
+    digest: Option<String>,
+    disable: bool,
+) -> Result<Vec<CloudBulkJobResult>, Error> {
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let jobs = select_jobs(auth_id, selector, &config)?;
+
+    // check everything first, so that either all jobs are changed or none
+    let user_info = CachedUserInfo::new()?;
+    for job in jobs.iter() {
+        user_info.check_privs(
+            auth_id,
+            &["cloud", "job", &job.id],
+            PRIV_CLOUD_MODIFY,
+            false,
+        )?;
+    }
+
+    let mut changes = Vec::new();
+    let mut result = Vec::new();
+    for mut job in jobs {
+        let message = if job.disable == disable {
+            Some(format!(
+                "already {}",
+                if disable { "disabled" } else { "enabled" }
+            ))
+        } else {
+            let old = section_data(&config, &job.id);
+            job.disable = disable;
+            config.set_data(&job.id, "backup", &job)?;
+            changes.push((job.id.clone(), old, section_data(&config, &job.id)));
+            None
+        };
+        result.push(CloudBulkJobResult {
+            id: job.id,
+            success: true,
+            upid: None,
+            message,
+        });
+    }
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    for (id, old, new) in changes {
+        record_config_change(auth_id, "backup", &id, old.as_ref(), new.as_ref());
+    }
+
+    Ok(result)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            selector: {
+                type: CloudBackupJobSelector,
+                flatten: true,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Result for each selected job.",
+        type: Array,
+        items: { type: CloudBulkJobResult },
+    },
+    access: {
+        description: "The user needs Cloud.Modify privilege on /cloud/job/{id} for every \
+                      selected job. Jobs without Cloud.Audit privilege are not selected.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Enable all selected cloud backup jobs.
+pub fn enable_cloud_backup_jobs(
+    selector: CloudBackupJobSelector,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudBulkJobResult>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    set_jobs_disabled(&auth_id, &selector, digest, false)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            selector: {
+                type: CloudBackupJobSelector,
+                flatten: true,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Result for each selected job.",
+        type: Array,
+        items: { type: CloudBulkJobResult },
+    },
+    access: {
+        description: "The user needs Cloud.Modify privilege on /cloud/job/{id} for every \
+                      selected job. Jobs without Cloud.Audit privilege are not selected.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Disable all selected cloud backup jobs.
+pub fn disable_cloud_backup_jobs(
+    selector: CloudBackupJobSelector,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudBulkJobResult>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    set_jobs_disabled(&auth_id, &selector, digest, true)
+}
+
+#[api(
+    input: {
+        properties: {
+            selector: {
+                type: CloudBackupJobSelector,
+                flatten: true,
+            },
+        },
+    },
+    returns: {
+        description: "Result for each selected job.",
+        type: Array,
+        items: { type: CloudBulkJobResult },
+    },
+    access: {
+        description: "The user needs Cloud.Backup privilege on /cloud/target/{target} \
+                      and Datastore.Read privilege on /datastore/{store} for every \
+                      selected job. Jobs without Cloud.Audit privilege are not selected.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Run all selected cloud backup jobs. Disabled jobs are skipped.
+pub fn run_cloud_backup_jobs(
+    selector: CloudBackupJobSelector,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudBulkJobResult>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let (config, _digest) = pbs_config::cloud_job::config()?;
+
+    let jobs = select_jobs(&auth_id, &selector, &config)?;
+
+    // do not start anything if the user lacks permissions for one of the jobs
+    for job in jobs.iter().filter(|job| !job.disable) {
+        check_backup_permission(&auth_id, &job.setup.store, &job_target(&job.setup)?)?;
+    }
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let mut result = Vec::new();
+    for job in jobs {
+        if job.disable {
+            result.push(CloudBulkJobResult {
+                id: job.id,
+                success: false,
+                upid: None,
+                message: Some("job is disabled".to_string()),
+            });
+            continue;
+        }
+
+        let started = Job::new("cloud-backup-job", &job.id).and_then(|state| {
+            do_cloud_backup_job(state, job.setup, job.hooks, &auth_id, None, to_stdout)
+        });
+
+        result.push(match started {
+            Ok(upid) => CloudBulkJobResult {
+                id: job.id,
+                success: true,
+                upid: Some(upid),
+                message: None,
+            },
+            Err(err) => CloudBulkJobResult {
+                id: job.id,
+                success: false,
+                upid: None,
+                message: Some(err.to_string()),
+            },
+        });
+    }
+
+    Ok(result)
+}
+
+/// Cloud job config with the local prune job config
+///
+/// Job triples with a local prune job change both configs, which are
+/// saved together.
+#[derive(Clone)]
+pub struct JobTripleConfigs {
+    pub jobs: SectionConfigData,
+    pub local_prune: SectionConfigData,
+}
+
+impl JobTripleConfigs {
+    // the digest is the one of the cloud job config
+    fn load() -> Result<(Self, [u8; 32]), Error> {
+        let (jobs, digest) = pbs_config::cloud_job::config()?;
+        let (local_prune, _digest) = pbs_config::prune::config()?;
+        Ok((Self { jobs, local_prune }, digest))
+    }
+
+    // if saving one of the configs fails, the ones saved already are restored
+    fn save(&self, old: &Self) -> Result<(), Error> {
+        save_configs(&[
+            (&self.jobs, &old.jobs, &pbs_config::cloud_job::save_config),
+            (
+                &self.local_prune,
+                &old.local_prune,
+                &pbs_config::prune::save_config,
+            ),
+        ])
+    }
+}
+
+/// Function saving a config file
+pub type SaveConfigFn<'a> = &'a dyn Fn(&SectionConfigData) -> Result<(), Error>;
+
+/// Save configs in order, each given as new config, old config and save
+/// function
+///
+/// If saving a config fails, the configs saved before it are restored to
+/// their old state, so that either all config files change or none.
+pub fn save_configs(
+    list: &[(&SectionConfigData, &SectionConfigData, SaveConfigFn)],
+) -> Result<(), Error> {
+    for (i, (new, _old, save)) in list.iter().enumerate() {
+        if let Err(err) = save(new) {
+            for (_new, old, save) in list[..i].iter().rev() {
+                if let Err(restore_err) = save(old) {
+                    log::error!(
+                        "unable to restore config after failed update - {}",
+                        restore_err
+                    );
+                }
+            }
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Backup jobs created from `template` for `sources`, with their job
+/// triple
+///
+/// Fails if a job ID would be used twice, or exists already in one of the
+/// configs, so that either all job triples are created or none.
+pub fn new_job_triples(
+    template: &CloudBackupJobTemplate,
+    sources: &[String],
+    target: Option<&str>,
+    local_prune: bool,
+    configs: &JobTripleConfigs,
+) -> Result<Vec<(CloudJobTriple, CloudBackupJobConfig)>, Error> {
+    let mut list: Vec<(CloudJobTriple, CloudBackupJobConfig)> = Vec::new();
+    for source in sources.iter() {
+        let (store, ns) = match parse_bulk_job_source(source) {
+            Ok(source) => source,
+            Err(err) => param_bail!("source", err),
+        };
+
+        let job = match template.instantiate(&store, target, ns.as_ref()) {
+            Ok(job) => job,
+            Err(err) => param_bail!("source", "'{}': {}", source, err),
+        };
+        let triple = match CloudJobTriple::new(&job.id) {
+            Ok(triple) if local_prune => triple.with_local_prune(),
+            result => result,
+        };
+        let triple = match triple {
+            Ok(triple) => triple,
+            Err(err) => param_bail!("source", "'{}': {}", source, err),
+        };
+
+        // the cloud jobs of all triples share one config
+        for id in [&triple.backup, &triple.verify, &triple.prune] {
+            let used = list
+                .iter()
+                .any(|(other, _)| [&other.backup, &other.verify, &other.prune].contains(&id));
+            if used {
+                param_bail!("source", "job id '{}' would be used twice", id);
+            }
+            if configs.jobs.sections.contains_key(id) {
+                param_bail!("source", "job '{}' already exists.", id);
+            }
+        }
+        if let Some(ref id) = triple.local_prune {
+            if configs.local_prune.sections.contains_key(id) {
+                param_bail!("source", "prune job '{}' already exists.", id);
+            }
+        }
+
+        list.push((triple, job));
+    }
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            template: {
+                schema: CLOUD_JOB_TEMPLATE_ID_SCHEMA,
+            },
+            source: {
+                description: "Create a job triple for each of these datastores (or namespaces).",
+                type: Array,
+                items: {
+                    schema: CLOUD_BULK_JOB_SOURCE_SCHEMA,
+                },
+            },
+            target: {
+                description: "Value of the '{target}' placeholder.",
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+                optional: true,
+            },
+            keep: {
+                type: KeepOptions,
+                flatten: true,
+            },
+            "prune-schedule": {
+                schema: CLOUD_PRUNE_SCHEDULE_SCHEMA,
+            },
+            "verify-schedule": {
+                schema: CLOUD_VERIFICATION_SCHEDULE_SCHEMA,
+            },
+            "outdated-after": {
+                schema: CLOUD_VERIFICATION_OUTDATED_AFTER_SCHEMA,
+                optional: true,
+            },
+            "local-prune": {
+                description: "Also create a prune job for the local datastore (or namespace), \
+                              with the same keep options and schedule. This removes local \
+                              snapshots!",
+                type: Boolean,
+                optional: true,
+                default: false,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "The created job triples (or the triples which would be created with \
+                      'validate').",
+        type: Array,
+        items: { type: CloudJobTriple },
+    },
+    access: {
+        description: "Requires Cloud.Modify on /cloud/job and Cloud.Delete on the target of \
+                      every backup job. With 'local-prune', Datastore.Modify on every \
+                      datastore (or namespace) is required, too.",
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Create cloud backup, verification and prune jobs from a template.
+///
+/// The backup jobs are created like with the template's 'instantiate'.
+/// The verification and prune jobs are cloud jobs, which cover the copies
+/// of the same datastore namespace on the backup job's target, the local
+/// datastore is only pruned with 'local-prune'. Either all jobs are
+/// created, or none.
+#[allow(clippy::too_many_arguments)]
+pub fn create_cloud_job_triples(
+    template: String,
+    source: Vec<String>,
+    target: Option<String>,
+    keep: KeepOptions,
+    prune_schedule: String,
+    verify_schedule: String,
+    outdated_after: Option<i64>,
+    local_prune: bool,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudJobTriple>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    if !keep.keeps_something() {
+        bail!("the prune jobs need at least one keep option");
+    }
+
+    let _lock = pbs_config::cloud_job::lock()?;
+    let _prune_lock = pbs_config::prune::lock_config()?;
+
+    let (mut configs, _digest) = JobTripleConfigs::load()?;
+
+    let template: CloudBackupJobTemplate = configs.jobs.lookup("template", &template)?;
+
+    let mut jobs: Vec<(
+        CloudJobTriple,
+        CloudBackupJobConfig,
+        CloudVerificationJobConfig,
+        CloudPruneJobConfig,
+        Option<PruneJobConfig>,
+    )> = Vec::new();
+    let triples = new_job_triples(&template, &source, target.as_deref(), local_prune, &configs)?;
+    for (triple, job) in triples {
+        check_job_setup(&job.setup)?;
+
+        let target = job_target(&job.setup)?;
+        user_info.check_privs(
+            &auth_id,
+            &["cloud", "target", &target],
+            PRIV_CLOUD_DELETE,
+            false,
+        )?;
+
+        let comment = Some(format!("cloud backup job '{}'", job.id));
+
+        let verify_job = CloudVerificationJobConfig {
+            id: triple.verify.clone(),
+            target: target.clone(),
+            store: job.setup.store.clone(),
+            ignore_verified: Some(true),
+            outdated_after,
+            comment: comment.clone(),
+            schedule: Some(verify_schedule.clone()),
+            ns: job.setup.ns.clone(),
+            max_depth: job.setup.max_depth,
+            disable: false,
+        };
+
+        let prune_job = CloudPruneJobConfig {
+            id: triple.prune.clone(),
+            target,
+            store: job.setup.store.clone(),
+            ns: job.setup.ns.clone(),
+            max_depth: job.setup.max_depth,
+            keep: keep.clone(),
+            schedule: prune_schedule.clone(),
+            comment: comment.clone(),
+            disable: false,
+        };
+
+        let local_prune_job = match triple.local_prune {
+            Some(ref id) => {
+                let local_prune_job = PruneJobConfig {
+                    id: id.clone(),
+                    store: job.setup.store.clone(),
+                    disable: false,
+                    schedule: prune_schedule.clone(),
+                    comment,
+                    options: PruneJobOptions {
+                        keep: keep.clone(),
+                        max_depth: job.setup.max_depth,
+                        ns: job.setup.ns.clone(),
+                    },
+                };
+                user_info.check_privs(
+                    &auth_id,
+                    &local_prune_job.acl_path(),
+                    PRIV_DATASTORE_MODIFY,
+                    true,
+                )?;
+                Some(local_prune_job)
+            }
+            None => None,
+        };
+
+        jobs.push((triple, job, verify_job, prune_job, local_prune_job));
+    }
+
+    if validate {
+        return Ok(jobs.into_iter().map(|(triple, ..)| triple).collect());
+    }
+
+    let old_configs = configs.clone();
+    for (triple, job, verify_job, prune_job, local_prune_job) in jobs.iter() {
+        configs.jobs.set_data(&triple.backup, "backup", job)?;
+        configs
+            .jobs
+            .set_data(&triple.verify, "verify", verify_job)?;
+        configs.jobs.set_data(&triple.prune, "prune", prune_job)?;
+        if let Some(local_prune_job) = local_prune_job {
+            configs
+                .local_prune
+                .set_data(&local_prune_job.id, "prune", local_prune_job)?;
+        }
+    }
+
+    configs.save(&old_configs)?;
+
+    let mut list = Vec::new();
+    for (triple, ..) in jobs {
+        for (section_type, id) in [
+            ("backup", &triple.backup),
+            ("verify", &triple.verify),
+            ("prune", &triple.prune),
+        ] {
+            let new = section_data(&configs.jobs, id);
+            record_config_change(&auth_id, section_type, id, None, new.as_ref());
+        }
+        crate::server::jobstate::create_state_file("cloud-backup-job", &triple.backup)?;
+        crate::server::jobstate::create_state_file("cloud-verify-job", &triple.verify)?;
+        crate::server::jobstate::create_state_file("cloud-prune-job", &triple.prune)?;
+        if let Some(ref id) = triple.local_prune {
+            crate::server::jobstate::create_state_file("prunejob", id)?;
+        }
+        list.push(triple);
+    }
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            template: {
+                schema: CLOUD_JOB_TEMPLATE_ID_SCHEMA,
+            },
+            source: {
+                description: "Only delete the job triples of these datastores (or namespaces).",
+                type: Array,
+                optional: true,
+                items: {
+                    schema: CLOUD_BULK_JOB_SOURCE_SCHEMA,
+                },
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "The deleted job triples.",
+        type: Array,
+        items: { type: CloudJobTriple },
+    },
+    access: {
+        description: "Requires Cloud.Modify on /cloud/job. Datastore.Modify is required on \
+                      every datastore (or namespace) with a local prune job.",
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Delete cloud backup jobs created from a template, with their
+/// verification and prune jobs.
+///
+/// Verification and prune jobs which were removed already are skipped,
+/// local prune jobs are deleted if they exist. Either all job triples are
+/// deleted, or none.
+pub fn delete_cloud_job_triples(
+    template: String,
+    source: Option<Vec<String>>,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudJobTriple>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+    let _prune_lock = pbs_config::prune::lock_config()?;
+
+    let (mut configs, expected_digest) = JobTripleConfigs::load()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let sources = match source {
+        Some(source) => {
+            let mut list = Vec::new();
+            for source in source.iter() {
+                match parse_bulk_job_source(source) {
+                    Ok(source) => list.push(source),
+                    Err(err) => param_bail!("source", err),
+                }
+            }
+            Some(list)
+        }
+        None => None,
+    };
+
+    let job_list: Vec<CloudBackupJobConfig> = configs.jobs.convert_to_typed_array("backup")?;
+    let jobs: Vec<CloudBackupJobConfig> = job_list
+        .into_iter()
+        .filter(|job| job.template.as_deref() == Some(template.as_str()))
+        .filter(|job| {
+            sources.as_ref().map_or(true, |sources| {
+                sources
+                    .iter()
+                    .any(|(store, ns)| *store == job.setup.store && *ns == job.setup.ns)
+            })
+        })
+        .collect();
+
+    let mut triples = Vec::new();
+    for job in jobs.iter() {
+        let mut triple = CloudJobTriple::new(&job.id)?;
+
+        if let Some(other) = chained_jobs(&configs.jobs, &job.id)
+            .into_iter()
+            .find(|id| !jobs.iter().any(|job| job.id == *id))
+        {
+            bail!("job '{}' is the 'run-after' trigger of '{}'", job.id, other);
+        }
+
+        let verify_job: Option<CloudVerificationJobConfig> =
+            configs.jobs.lookup("verify", &triple.verify).ok();
+        if let Some(ref verify_job) = verify_job {
+            if verify_job.store != job.setup.store {
+                bail!(
+                    "verification job '{}' belongs to datastore '{}', not to '{}'",
+                    triple.verify,
+                    verify_job.store,
+                    job.setup.store
+                );
+            }
+        }
+
+        let prune_job: Option<CloudPruneJobConfig> =
+            configs.jobs.lookup("prune", &triple.prune).ok();
+        if let Some(ref prune_job) = prune_job {
+            if prune_job.store != job.setup.store {
+                bail!(
+                    "prune job '{}' belongs to datastore '{}', not to '{}'",
+                    triple.prune,
+                    prune_job.store,
+                    job.setup.store
+                );
+            }
+        }
+
+        // IDs too long for a local prune job cannot have one
+        let local_prune_id = triple
+            .clone()
+            .with_local_prune()
+            .ok()
+            .and_then(|triple| triple.local_prune);
+        let local_prune_job: Option<PruneJobConfig> = local_prune_id
+            .as_ref()
+            .and_then(|id| configs.local_prune.lookup("prune", id).ok());
+        if let Some(ref local_prune_job) = local_prune_job {
+            if local_prune_job.store != job.setup.store {
+                bail!(
+                    "prune job '{}' belongs to datastore '{}', not to '{}'",
+                    local_prune_job.id,
+                    local_prune_job.store,
+                    job.setup.store
+                );
+            }
+            user_info.check_privs(
+                &auth_id,
+                &local_prune_job.acl_path(),
+                PRIV_DATASTORE_MODIFY,
+                true,
+            )?;
+            triple.local_prune = local_prune_id;
+        }
+
+        triples.push((triple, verify_job.is_some(), prune_job.is_some()));
+    }
+
+    let old_configs = configs.clone();
+    let mut changes = Vec::new();
+    for (triple, has_verify, has_prune) in triples.iter() {
+        let mut removed = vec![("backup", &triple.backup)];
+        if *has_verify {
+            removed.push(("verify", &triple.verify));
+        }
+        if *has_prune {
+            removed.push(("prune", &triple.prune));
+        }
+        let removed: Vec<_> = removed
+            .into_iter()
+            .map(|(section_type, id)| {
+                let old = configs.jobs.sections.remove(id).map(|(_, data)| data);
+                (section_type, id, old)
+            })
+            .collect();
+        if let Some(ref id) = triple.local_prune {
+            configs.local_prune.sections.remove(id);
+        }
+        changes.push(removed);
+    }
+
+    configs.save(&old_configs)?;
+
+    for removed in changes {
+        for (section_type, id, old) in removed {
+            record_config_change(&auth_id, section_type, id, old.as_ref(), None);
+        }
+    }
+
+    let mut list = Vec::new();
+    for (triple, has_verify, has_prune) in triples {
+        crate::server::jobstate::remove_state_file("cloud-backup-job", &triple.backup)?;
+        resume_job(CLOUD_STATUS_DIR, &triple.backup)?;
+        remove_job_retries(CLOUD_STATUS_DIR, "cloud-backup-job", &triple.backup)?;
+        if has_verify {
+            crate::server::jobstate::remove_state_file("cloud-verify-job", &triple.verify)?;
+            resume_job(CLOUD_STATUS_DIR, &triple.verify)?;
+        }
+        if has_prune {
+            crate::server::jobstate::remove_state_file("cloud-prune-job", &triple.prune)?;
+            resume_job(CLOUD_STATUS_DIR, &triple.prune)?;
+        }
+        if let Some(ref id) = triple.local_prune {
+            crate::server::jobstate::remove_state_file("prunejob", id)?;
+        }
+        list.push(triple);
+    }
+
+    Ok(list)
+}
+
+#[sortable]
+const SUBDIRS: SubdirMap = &sorted!([
+    (
+        "create",
+        &Router::new().post(&API_METHOD_CREATE_CLOUD_JOB_TRIPLES)
+    ),
+    (
+        "delete",
+        &Router::new().post(&API_METHOD_DELETE_CLOUD_JOB_TRIPLES)
+    ),
+    (
+        "disable",
+        &Router::new().post(&API_METHOD_DISABLE_CLOUD_BACKUP_JOBS)
+    ),
+    (
+        "enable",
+        &Router::new().post(&API_METHOD_ENABLE_CLOUD_BACKUP_JOBS)
+    ),
+    (
+        "run",
+        &Router::new().post(&API_METHOD_RUN_CLOUD_BACKUP_JOBS)
+    ),
+]);
+
+pub const ROUTER: Router = Router::new()
+    .get(&list_subdirs_api_method!(SUBDIRS))
+    .subdirs(SUBDIRS);
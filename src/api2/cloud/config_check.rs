@@ -0,0 +1,53 @@
+//! Consistency check of the cloud configuration
+
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::{Authid, CloudConfigIssue, PRIV_CLOUD_AUDIT};
+use pbs_config::CachedUserInfo;
+
+use crate::cloud::config_check::check_cloud_config;
+
+#[api(
+    input: {
+        properties: {
+            probe: {
+                description: "Check the reachability of all targets with a request, \
+                              instead of using their recorded health checks.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+    returns: {
+        description: "Problems found in the cloud configuration.",
+        type: Array,
+        items: { type: CloudConfigIssue },
+    },
+    access: {
+        description: "Requires Cloud.Audit on /cloud/job and /cloud/target.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Check the whole cloud configuration.
+///
+/// Reports jobs and mappings referencing missing targets, unreachable
+/// targets, keys missing in the key store, schedules which never fire and
+/// targets with overlapping prefixes.
+pub fn check_config(
+    probe: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudConfigIssue>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    user_info.check_privs(&auth_id, &["cloud", "job"], PRIV_CLOUD_AUDIT, false)?;
+    user_info.check_privs(&auth_id, &["cloud", "target"], PRIV_CLOUD_AUDIT, false)?;
+
+    check_cloud_config(probe)
+}
+
+pub const ROUTER: Router = Router::new().get(&API_METHOD_CHECK_CONFIG);
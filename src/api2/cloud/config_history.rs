@@ -0,0 +1,72 @@
+//! Change history of the cloud configuration
+
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::{Authid, CloudConfigChange, PRIV_CLOUD_AUDIT};
+use pbs_config::CachedUserInfo;
+
+use crate::cloud::{config_history::load_history, CLOUD_STATUS_DIR};
+
+#[api(
+    input: {
+        properties: {
+            "config-type": {
+                description: "Only list changes of this section type \
+                              ('target', 'backup', 'template' or 'replication').",
+                type: String,
+                optional: true,
+            },
+            id: {
+                description: "Only list changes of the object with this ID.",
+                type: String,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "Recorded changes, newest first.",
+        type: Array,
+        items: { type: CloudConfigChange },
+    },
+    access: {
+        description: "Changes of targets are filtered by Cloud.Audit privileges on \
+                      /cloud/target/{id}, all others by Cloud.Audit privileges on /cloud/job/{id}.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// List the change history of the cloud configuration
+pub fn list_config_history(
+    config_type: Option<String>,
+    id: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudConfigChange>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let mut list = load_history(CLOUD_STATUS_DIR)?;
+    list.retain(|change| {
+        if let Some(ref config_type) = config_type {
+            if &change.config_type != config_type {
+                return false;
+            }
+        }
+        if let Some(ref id) = id {
+            if &change.id != id {
+                return false;
+            }
+        }
+        let path = match change.config_type.as_str() {
+            "target" => ["cloud", "target", &change.id],
+            _ => ["cloud", "job", &change.id],
+        };
+        user_info.lookup_privs(&auth_id, &path) & PRIV_CLOUD_AUDIT != 0
+    });
+    list.reverse();
+
+    Ok(list)
+}
+
+pub const ROUTER: Router = Router::new().get(&API_METHOD_LIST_CONFIG_HISTORY);
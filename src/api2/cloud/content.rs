@@ -0,0 +1,327 @@
+//! List the content of cloud targets
+
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::{
+    list_subdirs_api_method, Permission, Router, RpcEnvironment, RpcEnvironmentType, SubdirMap,
+};
+use proxmox_schema::api;
+use proxmox_sys::task_log;
+
+use pbs_api_types::{
+    Authid, BackupNamespace, BackupType, CloudGroupListItem, CloudNamespaceListItem,
+    CloudSnapshotListItem, BACKUP_ID_SCHEMA, CLOUD_RESTORE_SNAPSHOT_SCHEMA,
+    CLOUD_TARGET_NAME_SCHEMA, CLOUD_VERIFICATION_OUTDATED_AFTER_SCHEMA, DATASTORE_SCHEMA,
+    PRIV_CLOUD_AUDIT, PRIV_CLOUD_DELETE, UPID_SCHEMA,
+};
+use proxmox_rest_server::WorkerTask;
+
+use crate::api2::cloud::paginate;
+use crate::api2::cloud::restore::parse_restore_snapshot;
+use crate::cloud::{
+    backend::open_target_backend,
+    catalog::CloudCatalog,
+    content::{self, CloudContentFilter},
+    events::{publish_event, CloudEvent},
+    trash::move_to_trash,
+    CLOUD_STATUS_DIR,
+};
+
+#[api(
+    input: {
+        properties: {
+            target: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            start: {
+                type: u64,
+                description: "List namespaces beginning from this offset.",
+                default: 0,
+                optional: true,
+            },
+            limit: {
+                type: u64,
+                description: "Only list this amount of namespaces. (0 means no limit)",
+                default: 50,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "List of namespaces with snapshots on the target.",
+        type: Array,
+        items: {
+            type: CloudNamespaceListItem,
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{target}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List the namespaces stored on a cloud target.
+pub fn list_namespaces(
+    target: String,
+    store: Option<String>,
+    start: u64,
+    limit: u64,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudNamespaceListItem>, Error> {
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &target)?;
+    let filter = CloudContentFilter {
+        store,
+        ..Default::default()
+    };
+
+    let list = content::list_namespaces(&catalog, &filter);
+
+    Ok(paginate(list, start, limit, rpcenv))
+}
+
+#[api(
+    input: {
+        properties: {
+            target: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "backup-type": {
+                type: BackupType,
+                optional: true,
+            },
+            start: {
+                type: u64,
+                description: "List groups beginning from this offset.",
+                default: 0,
+                optional: true,
+            },
+            limit: {
+                type: u64,
+                description: "Only list this amount of groups. (0 means no limit)",
+                default: 50,
+                optional: true,
+            },
+            "outdated-after": {
+                schema: CLOUD_VERIFICATION_OUTDATED_AFTER_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "List of backup groups on the target.",
+        type: Array,
+        items: {
+            type: CloudGroupListItem,
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{target}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List the backup groups stored on a cloud target.
+///
+/// Verifications older than `outdated-after` days are reported as outdated.
+#[allow(clippy::too_many_arguments)]
+pub fn list_groups(
+    target: String,
+    store: Option<String>,
+    ns: Option<BackupNamespace>,
+    backup_type: Option<BackupType>,
+    start: u64,
+    limit: u64,
+    outdated_after: Option<i64>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudGroupListItem>, Error> {
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &target)?;
+    let filter = CloudContentFilter {
+        store,
+        ns,
+        backup_type,
+        ..Default::default()
+    };
+
+    let list = content::list_groups(&catalog, &filter, outdated_after);
+
+    Ok(paginate(list, start, limit, rpcenv))
+}
+
+#[api(
+    input: {
+        properties: {
+            target: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "backup-type": {
+                type: BackupType,
+                optional: true,
+            },
+            "backup-id": {
+                schema: BACKUP_ID_SCHEMA,
+                optional: true,
+            },
+            start: {
+                type: u64,
+                description: "List snapshots beginning from this offset.",
+                default: 0,
+                optional: true,
+            },
+            limit: {
+                type: u64,
+                description: "Only list this amount of snapshots. (0 means no limit)",
+                default: 50,
+                optional: true,
+            },
+            "outdated-after": {
+                schema: CLOUD_VERIFICATION_OUTDATED_AFTER_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "List of snapshots on the target.",
+        type: Array,
+        items: {
+            type: CloudSnapshotListItem,
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{target}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List the snapshots stored on a cloud target.
+///
+/// Verifications older than `outdated-after` days are reported as outdated.
+#[allow(clippy::too_many_arguments)]
+pub fn list_snapshots(
+    target: String,
+    store: Option<String>,
+    ns: Option<BackupNamespace>,
+    backup_type: Option<BackupType>,
+    backup_id: Option<String>,
+    start: u64,
+    limit: u64,
+    outdated_after: Option<i64>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudSnapshotListItem>, Error> {
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &target)?;
+    let filter = CloudContentFilter {
+        store,
+        ns,
+        backup_type,
+        backup_id,
+        ..Default::default()
+    };
+
+    let list = content::list_snapshots(&catalog, &filter, outdated_after);
+
+    Ok(paginate(list, start, limit, rpcenv))
+}
+
+#[api(
+    input: {
+        properties: {
+            target: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            snapshot: {
+                schema: CLOUD_RESTORE_SNAPSHOT_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{target}"], PRIV_CLOUD_DELETE, false),
+    },
+)]
+/// Delete a snapshot from a cloud target.
+///
+/// The snapshot is moved to the trash of the target, it can be restored
+/// until the trash retention expires.
+pub fn delete_snapshot(
+    target: String,
+    snapshot: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let (store, ns, dir) = parse_restore_snapshot(&snapshot)?;
+
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&target)?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-snapshot-delete",
+        Some(target.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (target, backend) = open_target_backend(&target)?;
+            move_to_trash(
+                &*worker,
+                CLOUD_STATUS_DIR,
+                &target,
+                &backend,
+                &store,
+                &ns,
+                &dir,
+            )?;
+            task_log!(
+                worker,
+                "moved snapshot {} to the trash (retention {} days)",
+                snapshot,
+                target.config.trash_retention_secs() / (24 * 3600)
+            );
+            publish_event(
+                &*worker,
+                &target,
+                &CloudEvent::snapshot_pruned(&target.name, &snapshot, &worker.upid().to_string()),
+            );
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+const CONTENT_SUBDIRS: SubdirMap = &[
+    ("groups", &Router::new().get(&API_METHOD_LIST_GROUPS)),
+    (
+        "namespaces",
+        &Router::new().get(&API_METHOD_LIST_NAMESPACES),
+    ),
+    (
+        "snapshots",
+        &Router::new()
+            .get(&API_METHOD_LIST_SNAPSHOTS)
+            .delete(&API_METHOD_DELETE_SNAPSHOT),
+    ),
+];
+
+const CONTENT_ROUTER: Router = Router::new()
+    .get(&list_subdirs_api_method!(CONTENT_SUBDIRS))
+    .subdirs(CONTENT_SUBDIRS);
+
+pub const ROUTER: Router = Router::new().match_all("target", &CONTENT_ROUTER);
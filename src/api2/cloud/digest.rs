@@ -0,0 +1,160 @@
+//! Cloud job digests
+
+use anyhow::{format_err, Error};
+
+use proxmox_router::{Permission, Router, RpcEnvironment, RpcEnvironmentType};
+use proxmox_schema::api;
+use proxmox_sys::task_log;
+
+use pbs_api_types::{
+    Authid, CloudDigestJobConfig, CloudDigestJobStatus, CloudJobScheduleStatus, CloudTarget,
+    JOB_ID_SCHEMA, PRIV_CLOUD_AUDIT, PRIV_CLOUD_MODIFY, UPID_SCHEMA,
+};
+
+use proxmox_rest_server::WorkerTask;
+
+use crate::{
+    cloud::{digest::send_digest, job_pause::job_paused, CLOUD_STATUS_DIR},
+    server::jobstate::{compute_schedule_status, Job, JobState},
+};
+
+const CLOUD_DIGEST_JOB_ROUTER: Router = Router::new().post(&API_METHOD_RUN_CLOUD_DIGEST_JOB);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_CLOUD_DIGEST_JOBS)
+    .match_all("id", &CLOUD_DIGEST_JOB_ROUTER);
+
+/// Schedule of a digest job, defaults to its period
+pub fn digest_schedule(config: &CloudDigestJobConfig) -> String {
+    match config.schedule {
+        Some(ref schedule) => schedule.clone(),
+        None => config
+            .period
+            .unwrap_or_default()
+            .default_schedule()
+            .to_string(),
+    }
+}
+
+#[api(
+    returns: {
+        description: "List configured cloud digest jobs and their status",
+        type: Array,
+        items: { type: CloudDigestJobStatus },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List all cloud digest jobs
+pub fn list_cloud_digest_jobs(
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudDigestJobStatus>, Error> {
+    let (job_config, digest) = pbs_config::cloud_job::config()?;
+
+    let job_list: Vec<CloudDigestJobConfig> = job_config.convert_to_typed_array("digest")?;
+
+    let mut list = Vec::new();
+
+    for job in job_list {
+        let last_state = JobState::load("cloud-digest-job", &job.id)
+            .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
+
+        let status = compute_schedule_status(&last_state, Some(digest_schedule(&job).as_str()))?;
+
+        let mut status: CloudJobScheduleStatus = status.into();
+        status.paused = job_paused(CLOUD_STATUS_DIR, &job.id);
+        if job.disable || status.paused {
+            status.next_run = None;
+        }
+
+        list.push(CloudDigestJobStatus {
+            config: job,
+            status,
+        });
+    }
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+pub fn do_cloud_digest_job(
+    mut job: Job,
+    config: CloudDigestJobConfig,
+    auth_id: &Authid,
+    schedule: Option<String>,
+    to_stdout: bool,
+) -> Result<String, Error> {
+    let job_id = job.jobname().to_string();
+
+    let worker_type = job.jobtype().to_string();
+
+    let upid_str = WorkerTask::spawn(
+        &worker_type,
+        Some(job_id.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| async move {
+            job.start(&worker.upid().to_string())?;
+
+            task_log!(worker, "Starting cloud digest job '{}'", job_id);
+            if let Some(event_str) = schedule {
+                task_log!(
+                    worker,
+                    "cloud digest task triggered by schedule '{}'",
+                    event_str
+                );
+            }
+
+            let job_result: Result<(), Error> = async {
+                let (target_config, _digest) = pbs_config::cloud::config()?;
+                let targets: Vec<CloudTarget> = target_config.convert_to_typed_array("target")?;
+                send_digest(&*worker, CLOUD_STATUS_DIR, &config, &targets).await?;
+                Ok(())
+            }
+            .await;
+
+            let status = worker.create_state(&job_result);
+
+            if let Err(err) = job.finish(status) {
+                eprintln!("could not finish job state for {}: {}", job.jobtype(), err);
+            }
+
+            job_result
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Compile and send a cloud job digest manually.
+pub fn run_cloud_digest_job(id: String, rpcenv: &mut dyn RpcEnvironment) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let (config, _digest) = pbs_config::cloud_job::config()?;
+    let digest_job: CloudDigestJobConfig = config.lookup("digest", &id)?;
+
+    let job = Job::new("cloud-digest-job", &id)?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = do_cloud_digest_job(job, digest_job, &auth_id, None, to_stdout)?;
+
+    Ok(upid_str)
+}
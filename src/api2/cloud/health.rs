@@ -0,0 +1,50 @@
+//! Health summary of all cloud targets
+
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::{Authid, CloudTarget, CloudTargetHealth, PRIV_CLOUD_AUDIT};
+use pbs_config::CachedUserInfo;
+
+use crate::cloud::{
+    health::{health_check_enabled, load_health_history, target_health},
+    CLOUD_STATUS_DIR,
+};
+
+#[api(
+    returns: {
+        description: "Health of all targets with health checks enabled (without history).",
+        type: Array,
+        items: { type: CloudTargetHealth },
+    },
+    access: {
+        description: "List is filtered by Cloud.Audit privileges on /cloud/target/{name}.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Show the health of all cloud targets.
+pub fn health_summary(rpcenv: &mut dyn RpcEnvironment) -> Result<Vec<CloudTargetHealth>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let (config, _digest) = pbs_config::cloud::config()?;
+    let targets: Vec<CloudTarget> = config.convert_to_typed_array("target")?;
+
+    let mut list = Vec::new();
+    for target in targets {
+        let privs = user_info.lookup_privs(&auth_id, &["cloud", "target", &target.name]);
+        if privs & PRIV_CLOUD_AUDIT == 0 || !health_check_enabled(&target) {
+            continue;
+        }
+        let history = load_health_history(CLOUD_STATUS_DIR, &target.name)?;
+        let mut health = target_health(&target.name, history);
+        health.history.clear();
+        list.push(health);
+    }
+
+    Ok(list)
+}
+
+pub const ROUTER: Router = Router::new().get(&API_METHOD_HEALTH_SUMMARY);
@@ -0,0 +1,97 @@
+//! Runtime controls of cloud jobs
+
+use anyhow::Error;
+
+use proxmox_router::{
+    http_bail, list_subdirs_api_method, Permission, Router, RpcEnvironment, SubdirMap,
+};
+use proxmox_schema::api;
+use proxmox_sortable_macro::sortable;
+
+use pbs_api_types::{Authid, JOB_ID_SCHEMA, PRIV_CLOUD_MODIFY, PRIV_PERMISSIONS_MODIFY};
+use pbs_config::CachedUserInfo;
+
+use crate::cloud::{
+    job_pause::{pause_job, resume_job},
+    CLOUD_STATUS_DIR,
+};
+
+// jobs of all types share the cloud job config, role sync jobs are
+// managed like the ACL they modify
+fn check_job_modify(auth_id: &Authid, id: &str) -> Result<(), Error> {
+    let (config, _digest) = pbs_config::cloud_job::config()?;
+    let section_type = match config.sections.get(id) {
+        Some((section_type, _)) if section_type != "template" => section_type.clone(),
+        _ => http_bail!(NOT_FOUND, "cloud job '{}' does not exist.", id),
+    };
+
+    let user_info = CachedUserInfo::new()?;
+    if section_type == "role-sync" {
+        user_info.check_privs(auth_id, &["access", "acl"], PRIV_PERMISSIONS_MODIFY, false)
+    } else {
+        user_info.check_privs(auth_id, &["cloud", "job", id], PRIV_CLOUD_MODIFY, false)
+    }
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+        },
+    },
+    access: {
+        description: "Requires Cloud.Modify on /cloud/job/{id}, or Permissions.Modify on \
+            /access/acl for role sync jobs.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Pause a cloud job.
+///
+/// Paused jobs are not started by their schedule or trigger job until they
+/// are resumed. A running job is not affected.
+pub fn pause(id: String, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    check_job_modify(&auth_id, &id)?;
+
+    pause_job(CLOUD_STATUS_DIR, &id, &auth_id)
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+        },
+    },
+    access: {
+        description: "Requires Cloud.Modify on /cloud/job/{id}, or Permissions.Modify on \
+            /access/acl for role sync jobs.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Resume a paused cloud job.
+pub fn resume(id: String, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    check_job_modify(&auth_id, &id)?;
+
+    if !resume_job(CLOUD_STATUS_DIR, &id)? {
+        http_bail!(BAD_REQUEST, "cloud job '{}' is not paused.", id);
+    }
+
+    Ok(())
+}
+
+#[sortable]
+const JOB_SUBDIRS: SubdirMap = &sorted!([
+    ("pause", &Router::new().post(&API_METHOD_PAUSE)),
+    ("resume", &Router::new().post(&API_METHOD_RESUME)),
+]);
+
+const JOB_ROUTER: Router = Router::new()
+    .get(&list_subdirs_api_method!(JOB_SUBDIRS))
+    .subdirs(JOB_SUBDIRS);
+
+pub const ROUTER: Router = Router::new().match_all("id", &JOB_ROUTER);
@@ -1,36 +1,70 @@
 //! Cloud Backup Management
 
-use anyhow::Error;
-use serde_json::Value;
-
-use proxmox_router::{list_subdirs_api_method, Router, SubdirMap};
-use proxmox_schema::api;
+use proxmox_router::{list_subdirs_api_method, Router, RpcEnvironment, SubdirMap};
 
 pub mod backup;
-
-#[api(
-    input: {
-        properties: {},
-    },
-    returns: {
-        description: "Cloud backup.",
-        type: String,
-    },
-)]
-/// Cloud Hello
-pub fn cloud_hello(_param: Value) -> Result<String, Error> {
-    Ok("api2/json/cloud/cloud-hello cloud-hello-world".to_string())
-}
+pub mod bulk;
+pub mod config_check;
+pub mod config_history;
+pub mod content;
+pub mod digest;
+pub mod health;
+pub mod jobs;
+pub mod node;
+pub mod prune;
+pub mod prune_simulate;
+pub mod quota;
+pub mod replication;
+pub mod restore;
+pub mod role_sync;
+pub mod share;
+pub mod storage;
+pub mod tasks;
+pub mod trash;
+pub mod verify;
 
 const SUBDIRS: SubdirMap = &[
-    ("backup", &backup::ROUTER),    
-    (
-        "cloud-hello",
-        &Router::new().get(&API_METHOD_CLOUD_HELLO),
-    ),
-    //("scan-drives", &Router::new().get(&API_METHOD_SCAN_DRIVES)),
+    ("backup", &backup::ROUTER),
+    ("bulk", &bulk::ROUTER),
+    ("config-check", &config_check::ROUTER),
+    ("config-history", &config_history::ROUTER),
+    ("content", &content::ROUTER),
+    ("digest", &digest::ROUTER),
+    ("health", &health::ROUTER),
+    ("jobs", &jobs::ROUTER),
+    ("node", &node::ROUTER),
+    ("prune", &prune::ROUTER),
+    ("prune-simulate", &prune_simulate::ROUTER),
+    ("quota", &quota::ROUTER),
+    ("replication", &replication::ROUTER),
+    ("restore", &restore::ROUTER),
+    ("role-sync", &role_sync::ROUTER),
+    ("share", &share::ROUTER),
+    ("storage", &storage::ROUTER),
+    ("tasks", &tasks::ROUTER),
+    ("trash", &trash::ROUTER),
+    ("verify", &verify::ROUTER),
 ];
 
+/// Apply 'start' and 'limit' (0 means no limit) to a list
+///
+/// The number of entries before pagination is reported as 'total'.
+pub(crate) fn paginate<T>(
+    list: Vec<T>,
+    start: u64,
+    limit: u64,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Vec<T> {
+    rpcenv["total"] = list.len().into();
+
+    let list = list.into_iter().skip(start as usize);
+    if limit > 0 {
+        list.take(limit as usize).collect()
+    } else {
+        list.collect()
+    }
+}
+
 pub const ROUTER: Router = Router::new()
     .get(&list_subdirs_api_method!(SUBDIRS))
     .subdirs(SUBDIRS);
@@ -0,0 +1,29 @@
+//! Status of the cloud instance running the server
+
+use anyhow::Error;
+
+use proxmox_router::{list_subdirs_api_method, Permission, Router, SubdirMap};
+use proxmox_schema::api;
+
+use pbs_api_types::{CloudNodeStatus, PRIV_SYS_AUDIT};
+
+use crate::cloud::node_status::collect_node_status;
+
+#[api(
+    returns: {
+        type: CloudNodeStatus,
+    },
+    access: {
+        permission: &Permission::Privilege(&["system", "status"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// Read memory, swap, load, CPU and datastore usage, and the cloud instance information.
+pub async fn node_status() -> Result<CloudNodeStatus, Error> {
+    collect_node_status().await
+}
+
+const SUBDIRS: SubdirMap = &[("status", &Router::new().get(&API_METHOD_NODE_STATUS))];
+
+pub const ROUTER: Router = Router::new()
+    .get(&list_subdirs_api_method!(SUBDIRS))
+    .subdirs(SUBDIRS);
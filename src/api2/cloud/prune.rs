@@ -0,0 +1,202 @@
+//! Cloud prune jobs
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_lang::try_block;
+use proxmox_router::{Permission, Router, RpcEnvironment, RpcEnvironmentType};
+use proxmox_schema::api;
+use proxmox_sys::task_log;
+
+use pbs_api_types::{
+    Authid, CloudJobScheduleStatus, CloudPruneJobConfig, CloudPruneJobStatus, JOB_ID_SCHEMA,
+    PRIV_CLOUD_AUDIT, PRIV_CLOUD_DELETE, PRIV_CLOUD_MODIFY, UPID_SCHEMA,
+};
+use pbs_config::CachedUserInfo;
+
+use proxmox_rest_server::WorkerTask;
+
+use crate::{
+    cloud::{
+        backend::open_target_backend,
+        content::CloudContentFilter,
+        events::{publish_event, CloudEvent},
+        job_pause::job_paused,
+        prune::prune_snapshots,
+        CLOUD_STATUS_DIR,
+    },
+    server::jobstate::{compute_schedule_status, Job, JobState},
+};
+
+const CLOUD_PRUNE_JOB_ROUTER: Router = Router::new().post(&API_METHOD_RUN_CLOUD_PRUNE_JOB);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_CLOUD_PRUNE_JOBS)
+    .match_all("id", &CLOUD_PRUNE_JOB_ROUTER);
+
+#[api(
+    returns: {
+        description: "List configured cloud prune jobs and their status",
+        type: Array,
+        items: { type: CloudPruneJobStatus },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List all cloud prune jobs
+pub fn list_cloud_prune_jobs(
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudPruneJobStatus>, Error> {
+    let (job_config, digest) = pbs_config::cloud_job::config()?;
+
+    let job_list: Vec<CloudPruneJobConfig> = job_config.convert_to_typed_array("prune")?;
+
+    let mut list = Vec::new();
+
+    for job in job_list {
+        let last_state = JobState::load("cloud-prune-job", &job.id)
+            .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
+
+        let status = compute_schedule_status(&last_state, Some(job.schedule.as_str()))?;
+
+        let mut status: CloudJobScheduleStatus = status.into();
+        status.paused = job_paused(CLOUD_STATUS_DIR, &job.id);
+        if job.disable || status.paused {
+            status.next_run = None;
+        }
+
+        list.push(CloudPruneJobStatus {
+            config: job,
+            status,
+        });
+    }
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+pub fn do_cloud_prune_job(
+    mut job: Job,
+    config: CloudPruneJobConfig,
+    auth_id: &Authid,
+    schedule: Option<String>,
+    to_stdout: bool,
+) -> Result<String, Error> {
+    let job_id = job.jobname().to_string();
+
+    let worker_type = job.jobtype().to_string();
+
+    let upid_str = WorkerTask::new_thread(
+        &worker_type,
+        Some(job_id.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            job.start(&worker.upid().to_string())?;
+
+            task_log!(worker, "Starting cloud prune job '{}'", job_id);
+            if let Some(event_str) = schedule {
+                task_log!(
+                    worker,
+                    "cloud prune task triggered by schedule '{}'",
+                    event_str
+                );
+            }
+
+            let job_result = try_block!({
+                if !config.keep.keeps_something() {
+                    bail!("refusing to prune without keep options");
+                }
+                let (target, backend) = open_target_backend(&config.target)?;
+                let filter = CloudContentFilter::recursive(
+                    &config.store,
+                    config.ns.as_ref(),
+                    config.max_depth,
+                );
+                let result = prune_snapshots(
+                    &*worker,
+                    CLOUD_STATUS_DIR,
+                    &target,
+                    &backend,
+                    &filter,
+                    &config.keep,
+                )?;
+                let upid = worker.upid().to_string();
+                for name in result.removed.iter() {
+                    publish_event(
+                        &*worker,
+                        &target,
+                        &CloudEvent::snapshot_pruned(&target.name, name, &upid),
+                    );
+                }
+                task_log!(
+                    worker,
+                    "moved {} snapshots to the trash (retention {} days), kept {}",
+                    result.removed.len(),
+                    target.config.trash_retention_secs() / (24 * 3600),
+                    result.kept
+                );
+                if !result.failed.is_empty() {
+                    task_log!(worker, "failed to prune the following snapshots:");
+                    for name in result.failed.iter() {
+                        task_log!(worker, "\t{}", name);
+                    }
+                    bail!("prune failed - please check the log for details");
+                }
+                Ok(())
+            });
+
+            let status = worker.create_state(&job_result);
+
+            if let Err(err) = job.finish(status) {
+                eprintln!("could not finish job state for {}: {}", job.jobtype(), err);
+            }
+
+            job_result
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        description: "Requires Cloud.Modify on /cloud/job and Cloud.Delete on \
+                      /cloud/target/{target} of the job.",
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Runs a cloud prune job manually.
+pub fn run_cloud_prune_job(id: String, rpcenv: &mut dyn RpcEnvironment) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let (config, _digest) = pbs_config::cloud_job::config()?;
+    let prune_job: CloudPruneJobConfig = config.lookup("prune", &id)?;
+
+    let user_info = CachedUserInfo::new()?;
+    user_info.check_privs(
+        &auth_id,
+        &["cloud", "target", &prune_job.target],
+        PRIV_CLOUD_DELETE,
+        false,
+    )?;
+
+    let job = Job::new("cloud-prune-job", &id)?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = do_cloud_prune_job(job, prune_job, &auth_id, None, to_stdout)?;
+
+    Ok(upid_str)
+}
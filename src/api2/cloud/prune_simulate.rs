@@ -0,0 +1,81 @@
+//! Prune simulation for cloud targets
+
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router};
+use proxmox_schema::api;
+
+use pbs_api_types::{
+    BackupNamespace, BackupType, CloudPruneSimulation, KeepOptions, BACKUP_ID_SCHEMA,
+    CLOUD_TARGET_NAME_SCHEMA, DATASTORE_SCHEMA, PRIV_CLOUD_AUDIT,
+};
+
+use crate::cloud::{
+    catalog::CloudCatalog, content::CloudContentFilter, prune::simulate_prune, CLOUD_STATUS_DIR,
+};
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "backup-type": {
+                type: BackupType,
+                optional: true,
+            },
+            "backup-id": {
+                schema: BACKUP_ID_SCHEMA,
+                optional: true,
+            },
+            "keep-options": {
+                type: KeepOptions,
+                flatten: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudPruneSimulation,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Show which snapshots on a target a prune with the given keep options
+/// would keep or remove, and the space this would reclaim.
+///
+/// Nothing is removed. Without keep options, all snapshots are kept.
+pub fn prune_simulate(
+    name: String,
+    store: Option<String>,
+    ns: Option<BackupNamespace>,
+    backup_type: Option<BackupType>,
+    backup_id: Option<String>,
+    keep_options: KeepOptions,
+) -> Result<CloudPruneSimulation, Error> {
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+    let filter = CloudContentFilter {
+        store,
+        ns,
+        backup_type,
+        backup_id,
+        ..Default::default()
+    };
+
+    simulate_prune(&catalog, &filter, &keep_options)
+}
+
+const PRUNE_SIMULATE_ROUTER: Router = Router::new().get(&API_METHOD_PRUNE_SIMULATE);
+
+pub const ROUTER: Router = Router::new().match_all("name", &PRUNE_SIMULATE_ROUTER);
@@ -0,0 +1,45 @@
+//! Cloud target quotas
+
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router};
+use proxmox_schema::api;
+
+use pbs_api_types::{CloudQuotaStatus, CLOUD_TARGET_NAME_SCHEMA, PRIV_CLOUD_AUDIT};
+
+use crate::cloud::{catalog::CloudCatalog, quota::QuotaTracker, CLOUD_STATUS_DIR};
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Usage and remaining quota of the configured quotas.",
+        type: Array,
+        items: { type: CloudQuotaStatus },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Show the usage and remaining quota of the quotas of a target.
+pub fn quota_status(name: String) -> Result<Vec<CloudQuotaStatus>, Error> {
+    let target = pbs_config::cloud::lookup_target(&name)?;
+
+    let quotas = target.config.quotas()?;
+    if quotas.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+
+    Ok(QuotaTracker::new(quotas, &catalog).status())
+}
+
+const QUOTA_ROUTER: Router = Router::new().get(&API_METHOD_QUOTA_STATUS);
+
+pub const ROUTER: Router = Router::new().match_all("name", &QUOTA_ROUTER);
@@ -0,0 +1,287 @@
+//! Cloud replication jobs
+
+use anyhow::{format_err, Error};
+use serde_json::json;
+
+use proxmox_router::{Permission, Router, RpcEnvironment, RpcEnvironmentType};
+use proxmox_schema::api;
+use proxmox_sys::task_log;
+
+use pbs_api_types::{
+    Authid, CloudJobScheduleStatus, CloudReplicationJobConfig, CloudReplicationJobStatus,
+    JOB_ID_SCHEMA, PRIV_CLOUD_AUDIT, PRIV_CLOUD_BACKUP, UPID_SCHEMA,
+};
+
+use pbs_config::CachedUserInfo;
+use proxmox_rest_server::WorkerTask;
+
+use crate::{
+    cloud::{
+        backend::open_target_backend,
+        events::{publish_target_event, CloudEvent},
+        job_chain::run_triggered_by,
+        job_hooks::{run_post_hook, run_pre_hook},
+        job_pause::job_paused,
+        job_retry::{retry_schedule_status, RetryOptions},
+        job_splay::splay_schedule_status,
+        replication::{replicate_media_sets, ReplicationFilter, ReplicationStats},
+        task_checkpoint::run_with_checkpoint,
+        CLOUD_STATUS_DIR,
+    },
+    server::jobstate::{compute_schedule_status, Job, JobState},
+};
+
+const CLOUD_REPLICATION_JOB_ROUTER: Router =
+    Router::new().post(&API_METHOD_RUN_CLOUD_REPLICATION_JOB);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_CLOUD_REPLICATION_JOBS)
+    .match_all("id", &CLOUD_REPLICATION_JOB_ROUTER);
+
+pub(crate) fn check_replication_permission(
+    auth_id: &Authid,
+    source: &str,
+    target: &str,
+) -> Result<(), Error> {
+    let user_info = CachedUserInfo::new()?;
+
+    for name in [source, target] {
+        user_info.check_privs(
+            auth_id,
+            &["cloud", "target", name],
+            PRIV_CLOUD_BACKUP,
+            false,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[api(
+    returns: {
+        description: "List configured cloud replication jobs and their status",
+        type: Array,
+        items: { type: CloudReplicationJobStatus },
+    },
+    access: {
+        description: "List configured cloud jobs filtered by Cloud.Audit privileges",
+        permission: &Permission::Anybody,
+    },
+)]
+/// List all cloud replication jobs
+pub fn list_cloud_replication_jobs(
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudReplicationJobStatus>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let (job_config, digest) = pbs_config::cloud_job::config()?;
+
+    let job_list: Vec<CloudReplicationJobConfig> =
+        job_config.convert_to_typed_array("replication")?;
+
+    let mut list = Vec::new();
+
+    for job in job_list {
+        let privs = user_info.lookup_privs(&auth_id, &["cloud", "job", &job.id]);
+        if (privs & PRIV_CLOUD_AUDIT) == 0 {
+            continue;
+        }
+
+        let last_state = JobState::load("cloud-replication-job", &job.id)
+            .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
+
+        let status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+
+        let mut status: CloudJobScheduleStatus = status.into();
+        splay_schedule_status(
+            &mut status,
+            "cloud-replication-job",
+            &job.id,
+            job.schedule.as_deref(),
+            job.splay.as_deref(),
+        );
+        let retry =
+            RetryOptions::new(job.retry_on_failure, job.retry_delay.as_deref()).unwrap_or(None);
+        retry_schedule_status(
+            &mut status,
+            CLOUD_STATUS_DIR,
+            "cloud-replication-job",
+            &job.id,
+            &last_state,
+            retry.as_ref(),
+        );
+        status.paused = job_paused(CLOUD_STATUS_DIR, &job.id);
+        if job.disable || status.paused {
+            status.next_run = None;
+            status.next_retry = None;
+        }
+        status.triggered_by = run_triggered_by(
+            CLOUD_STATUS_DIR,
+            "cloud-replication-job",
+            &job.id,
+            status.last_run_upid.as_deref(),
+        );
+
+        list.push(CloudReplicationJobStatus {
+            config: job,
+            status,
+        });
+    }
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+pub fn do_cloud_replication_job(
+    mut job: Job,
+    config: CloudReplicationJobConfig,
+    auth_id: &Authid,
+    schedule: Option<String>,
+    to_stdout: bool,
+) -> Result<String, Error> {
+    let job_id = format!("{}:{}:{}", config.source, config.target, job.jobname());
+
+    let worker_type = job.jobtype().to_string();
+
+    let owner = auth_id.clone();
+
+    let upid_str = WorkerTask::new_thread(
+        &worker_type,
+        Some(job_id.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            job.start(&worker.upid().to_string())?;
+
+            let (job_type, job_name) = (job.jobtype().to_string(), job.jobname().to_string());
+            let upid = worker.upid().to_string();
+
+            let hooks = config.hooks.clone();
+            let mut stats = ReplicationStats::default();
+            let job_result = run_with_checkpoint(
+                &worker,
+                CLOUD_STATUS_DIR,
+                &job_type,
+                &job_name,
+                &owner,
+                || {
+                    run_pre_hook(&*worker, &hooks, &job_type, &job_name, &upid)?;
+                    publish_target_event(
+                        &*worker,
+                        &config.target,
+                        &CloudEvent::job_started(&config.target, &job_type, &job_name, &upid),
+                    );
+                    task_log!(worker, "Starting cloud replication job '{}'", job_id);
+                    if let Some(event_str) = schedule {
+                        task_log!(
+                            worker,
+                            "cloud replication task triggered by schedule '{}'",
+                            event_str
+                        );
+                    }
+
+                    let filter = ReplicationFilter {
+                        store: config.store.clone(),
+                        since: config
+                            .max_age
+                            .map(|days| proxmox_time::epoch_i64() - (days as i64) * 24 * 3600),
+                        group_filter: config.group_filter.clone(),
+                    };
+
+                    let (source, source_backend) = open_target_backend(&config.source)?;
+                    let (target, target_backend) = open_target_backend(&config.target)?;
+
+                    stats = replicate_media_sets(
+                        &*worker,
+                        CLOUD_STATUS_DIR,
+                        &source,
+                        &source_backend,
+                        &target,
+                        &target_backend,
+                        &filter,
+                    )?;
+
+                    Ok(())
+                },
+            );
+            let summary = json!({
+                "source": config.source,
+                "target": config.target,
+                "media-sets": stats.media_sets,
+                "bytes": stats.bytes,
+            });
+            let job_result = run_post_hook(
+                &*worker,
+                &hooks,
+                &job_type,
+                &job_name,
+                &upid,
+                job_result,
+                summary.clone(),
+            );
+            publish_target_event(
+                &*worker,
+                &config.target,
+                &CloudEvent::job_finished(
+                    &config.target,
+                    &job_type,
+                    &job_name,
+                    &upid,
+                    &job_result,
+                    summary,
+                ),
+            );
+
+            let status = worker.create_state(&job_result);
+
+            if let Err(err) = job.finish(status) {
+                eprintln!("could not finish job state for {}: {}", job.jobtype(), err);
+            }
+
+            job_result
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        // Note: parameters are from job config, so we need to test inside function body
+        description: "The user needs Cloud.Backup privilege on /cloud/target/{source} \
+                      and /cloud/target/{target}.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Runs a cloud replication job manually.
+pub fn run_cloud_replication_job(
+    id: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let (config, _digest) = pbs_config::cloud_job::config()?;
+    let replication_job: CloudReplicationJobConfig = config.lookup("replication", &id)?;
+
+    check_replication_permission(&auth_id, &replication_job.source, &replication_job.target)?;
+
+    let job = Job::new("cloud-replication-job", &id)?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = do_cloud_replication_job(job, replication_job, &auth_id, None, to_stdout)?;
+
+    Ok(upid_str)
+}
@@ -0,0 +1,491 @@
+//! Restore snapshots from a cloud target
+//!
+//! Data encrypted with a namespace key can be restored with the key from
+//! the server key store, or with a key supplied by the tenant for this
+//! request only ("bring your own key"). Supplied keys are never stored,
+//! and the decrypted key is zeroed when the restore task ends. The crypt
+//! config derived from it keeps a copy of the key material which cannot be
+//! zeroed, so it is created for each snapshot and dropped right after
+//! restoring it.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+use serde_json::Value;
+
+use proxmox_human_byte::HumanByte;
+use proxmox_router::{Permission, Router, RpcEnvironment, RpcEnvironmentType};
+use proxmox_schema::{api, param_bail};
+use proxmox_sys::fs::{replace_file, CreateOptions};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{
+    parse_ns_and_snapshot, print_ns_and_snapshot, Authid, BackupNamespace, CryptMode, Operation,
+    CLOUD_RESTORE_DOWNLOAD_THREADS_SCHEMA, CLOUD_RESTORE_SNAPSHOT_SCHEMA,
+    CLOUD_RESTORE_STAGING_DIR_SCHEMA, CLOUD_TARGET_NAME_SCHEMA, DATASTORE_SCHEMA,
+    PRIV_CLOUD_MODIFY, PRIV_CLOUD_RESTORE, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_MODIFY,
+    UPID_SCHEMA,
+};
+use pbs_config::CachedUserInfo;
+use pbs_datastore::manifest::MANIFEST_BLOB_NAME;
+use pbs_datastore::{DataBlob, DataStore};
+use pbs_key_config::KeyConfig;
+use pbs_tools::crypt_config::CryptConfig;
+use proxmox_rest_server::WorkerTask;
+
+use crate::cloud::{
+    backend::{open_fastest_backend, CloudBackend, MeteredBackend, StagingBackend},
+    catalog::{CloudCatalog, MediaSetCatalog, SnapshotEntry},
+    chunk_download::{ChunkDownloader, DEFAULT_DOWNLOAD_THREADS},
+    chunk_reader::CloudChunkReader,
+    egress::{estimate_restore_egress, EgressMeter},
+    encryption_keys::{decrypt_object, load_crypt_config, zero_string, TenantKey},
+    staging::StagingSpool,
+    CLOUD_STATUS_DIR,
+};
+
+pub const ROUTER: Router = Router::new().post(&API_METHOD_RESTORE);
+
+fn check_restore_privs(
+    user_info: &CachedUserInfo,
+    auth_id: &Authid,
+    target: &str,
+    store: &str,
+    ns: &BackupNamespace,
+    owner: Option<&Authid>,
+) -> Result<(), Error> {
+    user_info.check_privs(
+        auth_id,
+        &["cloud", "target", target],
+        PRIV_CLOUD_RESTORE,
+        false,
+    )?;
+
+    let acl_path = ns.acl_path(store);
+    let privs = user_info.lookup_privs(auth_id, &acl_path);
+    if (privs & PRIV_DATASTORE_BACKUP) == 0 {
+        bail!("no permissions on /{}", acl_path.join("/"));
+    }
+
+    if let Some(owner) = owner {
+        let correct_owner = owner == auth_id
+            || (owner.is_token() && !auth_id.is_token() && owner.user() == auth_id.user());
+
+        // same permission as changing ownership after syncing
+        if !correct_owner && privs & PRIV_DATASTORE_MODIFY == 0 {
+            bail!("no permission to restore as '{}'", owner);
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse 'store:[ns/namespace/...]type/id/time'
+pub(crate) fn parse_restore_snapshot(
+    input: &str,
+) -> Result<(String, BackupNamespace, pbs_api_types::BackupDir), Error> {
+    let (store, snapshot) = input
+        .split_once(':')
+        .ok_or_else(|| format_err!("invalid snapshot '{}' - missing store", input))?;
+    let (ns, dir) = parse_ns_and_snapshot(snapshot)?;
+    Ok((store.to_string(), ns, dir))
+}
+
+#[api(
+    input: {
+        properties: {
+            target: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            namespace: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            snapshots: {
+                description: "List of snapshots.",
+                type: Array,
+                items: {
+                    schema: CLOUD_RESTORE_SNAPSHOT_SCHEMA,
+                },
+            },
+            owner: {
+                type: Authid,
+                optional: true,
+            },
+            "key-config": {
+                description: "Encryption key (JSON key configuration) used for this restore only. \
+                    The key is never stored on the server.",
+                type: String,
+                min_length: 300,
+                max_length: 600,
+                optional: true,
+            },
+            password: {
+                description: "Password of the supplied encryption key.",
+                optional: true,
+            },
+            "force-egress": {
+                description: "Restore even if this exceeds the monthly egress budget of the target.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "download-threads": {
+                schema: CLOUD_RESTORE_DOWNLOAD_THREADS_SCHEMA,
+                optional: true,
+            },
+            "staging-dir": {
+                schema: CLOUD_RESTORE_STAGING_DIR_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        // Note: parameters are no uri parameter, so we need to test inside function body
+        description: "The user needs Cloud.Restore privilege on /cloud/target/{target} and \
+            Datastore.Backup privilege on /datastore/{store}/[{namespace}]. Users with \
+            Cloud.Modify privilege on the target may exceed its egress budget.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Restore snapshots from a cloud target.
+///
+/// Snapshots are restored into `namespace` if given, or into their
+/// original namespace otherwise.
+#[allow(clippy::too_many_arguments)]
+pub fn restore(
+    target: String,
+    store: String,
+    namespace: Option<BackupNamespace>,
+    snapshots: Vec<String>,
+    owner: Option<Authid>,
+    key_config: Option<String>,
+    mut password: Option<String>,
+    force_egress: bool,
+    download_threads: Option<usize>,
+    staging_dir: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let target_privs = user_info.lookup_privs(&auth_id, &["cloud", "target", &target]);
+    let force_egress = force_egress || target_privs & PRIV_CLOUD_MODIFY != 0;
+
+    // decrypt the supplied key first, so that the password is zeroed early
+    let tenant_key = match key_config {
+        Some(key_config) => {
+            let key_config: KeyConfig = serde_json::from_str(&key_config)
+                .map_err(|err| format_err!("unable to parse key configuration - {}", err))?;
+            let password = password
+                .as_mut()
+                .ok_or_else(|| format_err!("missing password for the supplied key"))?;
+            Some(TenantKey::decrypt(&key_config, password)?)
+        }
+        None => None,
+    };
+    if let Some(password) = password.as_mut() {
+        zero_string(password);
+    }
+
+    if snapshots.is_empty() {
+        bail!("no snapshots given");
+    }
+
+    let mut list = Vec::new();
+    for snapshot in snapshots.iter() {
+        let (source_store, source_ns, dir) = parse_restore_snapshot(snapshot)?;
+        let target_ns = namespace.clone().unwrap_or_else(|| source_ns.clone());
+        check_restore_privs(
+            &user_info,
+            &auth_id,
+            &target,
+            &store,
+            &target_ns,
+            owner.as_ref(),
+        )?;
+        list.push((source_store, source_ns, dir, target_ns));
+    }
+
+    if let Some(ref path) = staging_dir {
+        if !Path::new(path).is_dir() {
+            param_bail!("staging-dir", "'{}' is not a directory", path);
+        }
+    }
+
+    let mut downloader = ChunkDownloader::new(download_threads.unwrap_or(DEFAULT_DOWNLOAD_THREADS));
+    if let Some(path) = staging_dir {
+        downloader = downloader.with_staging_dir(path.into());
+    }
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-restore",
+        Some(format!("{}:{}", target, store)),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let restore_owner = owner.as_ref().unwrap_or(&auth_id);
+
+            let target = pbs_config::cloud::lookup_target(&target)?;
+            let catalog = Arc::new(CloudCatalog::load(CLOUD_STATUS_DIR, &target.name)?);
+
+            task_log!(worker, "cloud target: {}", target.name);
+            let backend = open_fastest_backend(&*worker, CLOUD_STATUS_DIR, &target)?;
+            // media sets may still wait in the write-back spool
+            let backend: Arc<dyn CloudBackend> =
+                match StagingSpool::for_target(CLOUD_STATUS_DIR, &target)? {
+                    Some(spool) => Arc::new(StagingBackend::new(backend, spool)),
+                    None => backend,
+                };
+            if let Some(ref key) = tenant_key {
+                task_log!(
+                    worker,
+                    "using supplied encryption key {}",
+                    key.fingerprint().signature()
+                );
+            }
+
+            let meter = Arc::new(EgressMeter::open(CLOUD_STATUS_DIR, &target, !force_egress)?);
+            let entries: Vec<&SnapshotEntry> = list
+                .iter()
+                .filter_map(|(store, ns, dir, _)| catalog.lookup_snapshot(store, ns, dir))
+                .map(|(_, entry)| entry)
+                .collect();
+            let estimate = estimate_restore_egress(&catalog, &entries, |digest| {
+                datastore.chunk_path(digest).0.exists()
+            });
+            task_log!(worker, "data to download: {}", HumanByte::from(estimate));
+            if let Some(remaining) = meter.remaining() {
+                task_log!(
+                    worker,
+                    "egress budget left this month: {}",
+                    HumanByte::from(remaining)
+                );
+            }
+            meter.check(estimate)?;
+            let backend: Arc<dyn CloudBackend> =
+                Arc::new(MeteredBackend::new(backend, Arc::clone(&meter)));
+
+            let mut errors = 0;
+            for (source_store, source_ns, dir, target_ns) in list {
+                worker.check_abort()?;
+
+                let source = format!(
+                    "{}:{}",
+                    source_store,
+                    print_ns_and_snapshot(&source_ns, &dir)
+                );
+                let (media_set, entry) =
+                    match catalog.lookup_snapshot(&source_store, &source_ns, &dir) {
+                        Some(found) => found,
+                        None => {
+                            task_warn!(worker, "snapshot {} not found on cloud target", source);
+                            errors += 1;
+                            continue;
+                        }
+                    };
+
+                let crypt_config = match select_key(entry, tenant_key.as_ref()) {
+                    Ok(crypt_config) => crypt_config,
+                    Err(err) => {
+                        task_warn!(worker, "skip snapshot {} - {}", source, err);
+                        errors += 1;
+                        continue;
+                    }
+                };
+
+                task_log!(
+                    worker,
+                    "restore {} to {}",
+                    source,
+                    print_ns_and_snapshot(&target_ns, &dir)
+                );
+
+                if let Err(err) = restore_snapshot(
+                    &worker,
+                    &backend,
+                    &catalog,
+                    &datastore,
+                    &downloader,
+                    media_set,
+                    entry,
+                    &target_ns,
+                    restore_owner,
+                    crypt_config,
+                ) {
+                    task_warn!(worker, "restore of {} failed - {}", source, err);
+                    errors += 1;
+                }
+            }
+
+            // zero the supplied key before the task finishes
+            drop(tenant_key);
+
+            task_log!(worker, "downloaded {}", HumanByte::from(meter.recorded()));
+
+            if errors > 0 {
+                bail!("restore failed for {} snapshot(s)", errors);
+            }
+
+            task_log!(worker, "restore finished successfully");
+
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+/// Select the key needed to decrypt the data of a snapshot
+///
+/// A supplied key must match the key the snapshot was encrypted with.
+/// Without supplied key we look into the server key store.
+///
+/// The returned crypt config is not zeroed when dropped, callers must not
+/// keep it beyond the snapshot it was selected for.
+fn select_key(
+    entry: &SnapshotEntry,
+    tenant_key: Option<&TenantKey>,
+) -> Result<Option<(pbs_api_types::Fingerprint, Arc<CryptConfig>)>, Error> {
+    let fingerprint = match entry.key {
+        Some(ref fingerprint) => fingerprint,
+        None => return Ok(None),
+    };
+
+    let crypt_config = match tenant_key {
+        Some(key) => {
+            key.check_fingerprint(fingerprint)?;
+            key.crypt_config()?
+        }
+        None => load_crypt_config(fingerprint).map_err(|_| {
+            format_err!(
+                "data is encrypted with key '{}' which is not stored on this server - \
+                    please supply the key",
+                fingerprint.signature()
+            )
+        })?,
+    };
+
+    Ok(Some((fingerprint.clone(), crypt_config)))
+}
+
+/// Check the CRC of a downloaded chunk, and the digest if the chunk is not encrypted
+fn verify_chunk(digest: &[u8; 32], data: &[u8]) -> Result<(), Error> {
+    let chunk = DataBlob::load_from_reader(&mut &data[..])?;
+    chunk.verify_crc()?;
+    if chunk.crypt_mode()? == CryptMode::None {
+        chunk.decode(None, Some(digest))?;
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn restore_snapshot(
+    worker: &WorkerTask,
+    backend: &Arc<dyn CloudBackend>,
+    catalog: &Arc<CloudCatalog>,
+    datastore: &Arc<DataStore>,
+    downloader: &ChunkDownloader,
+    media_set: &MediaSetCatalog,
+    entry: &SnapshotEntry,
+    target_ns: &BackupNamespace,
+    owner: &Authid,
+    crypt_config: Option<(pbs_api_types::Fingerprint, Arc<CryptConfig>)>,
+) -> Result<(), Error> {
+    let (group_owner, _group_lock) =
+        datastore.create_locked_backup_group(target_ns, entry.snapshot.as_ref(), owner)?;
+    if group_owner != *owner {
+        bail!(
+            "cannot restore into group '{}' - owner check failed ({} != {})",
+            entry.snapshot.group,
+            owner,
+            group_owner,
+        );
+    }
+
+    let (path, is_new, _snap_lock) =
+        datastore.create_locked_backup_dir(target_ns, &entry.snapshot)?;
+    if !is_new {
+        task_log!(worker, "skip snapshot {} - already exists", entry.snapshot);
+        return Ok(());
+    }
+
+    let result = proxmox_lang::try_block!({
+        let mut reader = CloudChunkReader::new(Arc::clone(backend), Arc::clone(catalog), None)?;
+        if let Some((ref fingerprint, ref crypt_config)) = crypt_config {
+            reader = reader.with_namespace_key(fingerprint.clone(), Arc::clone(crypt_config));
+        }
+
+        let reader = Arc::new(reader);
+
+        let mut missing = Vec::new();
+        for digest in entry.chunks.iter() {
+            worker.check_abort()?;
+            if !datastore.cond_touch_chunk(digest, false)? {
+                missing.push(*digest);
+            }
+        }
+
+        let stats =
+            downloader.download(worker, &reader, &missing, verify_chunk, |digest, data| {
+                let chunk = DataBlob::load_from_reader(&mut &data[..])?;
+                datastore.insert_chunk(&chunk, digest)?;
+                Ok(())
+            })?;
+        reader.finish()?;
+        task_log!(
+            worker,
+            "restored {} of {} chunks ({} in {:.2}s)",
+            stats.chunks,
+            entry.chunks.len(),
+            HumanByte::from(stats.bytes),
+            stats.elapsed,
+        );
+
+        // write the manifest last, it marks the snapshot as finished
+        let mut files: Vec<_> = entry.files.iter().collect();
+        files.sort_by_key(|file| file.filename == MANIFEST_BLOB_NAME);
+
+        for file in files {
+            let key = media_set.snapshot_file_key(entry, &file.filename);
+            let data = backend.get_object(&key)?;
+            let data = match crypt_config {
+                Some((_, ref crypt_config)) => decrypt_object(&data, crypt_config)?,
+                None => data,
+            };
+            if openssl::sha::sha256(&data) != file.csum {
+                bail!("checksum mismatch on file '{}'", file.filename);
+            }
+
+            let mut file_path = path.clone();
+            file_path.push(&file.filename);
+            replace_file(&file_path, &data, CreateOptions::new(), false)?;
+        }
+
+        Ok(())
+    });
+
+    if result.is_err() {
+        if let Err(err) = std::fs::remove_dir_all(&path) {
+            task_warn!(
+                worker,
+                "unable to cleanup snapshot dir {:?} - {}",
+                path,
+                err
+            );
+        }
+    }
+
+    result
+}
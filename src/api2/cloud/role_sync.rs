@@ -0,0 +1,153 @@
+//! Cloud role sync jobs
+
+use anyhow::{format_err, Error};
+
+use proxmox_router::{Permission, Router, RpcEnvironment, RpcEnvironmentType};
+use proxmox_schema::api;
+use proxmox_sys::task_log;
+
+use pbs_api_types::{
+    Authid, CloudJobScheduleStatus, CloudRoleSyncJobConfig, CloudRoleSyncJobStatus, JOB_ID_SCHEMA,
+    PRIV_PERMISSIONS_MODIFY, PRIV_SYS_AUDIT, UPID_SCHEMA,
+};
+
+use proxmox_rest_server::WorkerTask;
+
+use crate::{
+    cloud::{job_pause::job_paused, role_sync::sync_roles, CLOUD_STATUS_DIR},
+    server::jobstate::{compute_schedule_status, Job, JobState},
+};
+
+const CLOUD_ROLE_SYNC_JOB_ROUTER: Router = Router::new().post(&API_METHOD_RUN_CLOUD_ROLE_SYNC_JOB);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_CLOUD_ROLE_SYNC_JOBS)
+    .match_all("id", &CLOUD_ROLE_SYNC_JOB_ROUTER);
+
+#[api(
+    returns: {
+        description: "List configured cloud role sync jobs and their status",
+        type: Array,
+        items: { type: CloudRoleSyncJobStatus },
+    },
+    access: {
+        permission: &Permission::Privilege(&["access", "acl"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// List all cloud role sync jobs
+pub fn list_cloud_role_sync_jobs(
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudRoleSyncJobStatus>, Error> {
+    let (job_config, digest) = pbs_config::cloud_job::config()?;
+
+    let job_list: Vec<CloudRoleSyncJobConfig> = job_config.convert_to_typed_array("role-sync")?;
+
+    let mut list = Vec::new();
+
+    for job in job_list {
+        let last_state = JobState::load("cloud-role-sync-job", &job.id)
+            .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
+
+        let status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+
+        let mut status: CloudJobScheduleStatus = status.into();
+        status.paused = job_paused(CLOUD_STATUS_DIR, &job.id);
+        if job.disable || status.paused {
+            status.next_run = None;
+        }
+
+        list.push(CloudRoleSyncJobStatus {
+            config: job,
+            status,
+        });
+    }
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+pub fn do_cloud_role_sync_job(
+    mut job: Job,
+    config: CloudRoleSyncJobConfig,
+    auth_id: &Authid,
+    schedule: Option<String>,
+    to_stdout: bool,
+    dry_run: bool,
+) -> Result<String, Error> {
+    let job_id = format!("{}:{}", config.realm, job.jobname());
+
+    let worker_type = job.jobtype().to_string();
+
+    let upid_str = WorkerTask::spawn(
+        &worker_type,
+        Some(job_id.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| async move {
+            job.start(&worker.upid().to_string())?;
+
+            task_log!(worker, "Starting cloud role sync job '{}'", job_id);
+            if let Some(event_str) = schedule {
+                task_log!(
+                    worker,
+                    "cloud role sync task triggered by schedule '{}'",
+                    event_str
+                );
+            }
+
+            let job_result = sync_roles(&*worker, CLOUD_STATUS_DIR, &config, dry_run).await;
+
+            let status = worker.create_state(&job_result);
+
+            if let Err(err) = job.finish(status) {
+                eprintln!("could not finish job state for {}: {}", job.jobtype(), err);
+            }
+
+            job_result
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            "dry-run": {
+                type: bool,
+                description: "Only log the roles which would be granted and revoked.",
+                default: false,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["access", "acl"], PRIV_PERMISSIONS_MODIFY, false),
+    },
+)]
+/// Runs a cloud role sync job manually.
+pub fn run_cloud_role_sync_job(
+    id: String,
+    dry_run: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let (config, _digest) = pbs_config::cloud_job::config()?;
+    let role_sync_job: CloudRoleSyncJobConfig = config.lookup("role-sync", &id)?;
+
+    let job = Job::new("cloud-role-sync-job", &id)?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = do_cloud_role_sync_job(job, role_sync_job, &auth_id, None, to_stdout, dry_run)?;
+
+    Ok(upid_str)
+}
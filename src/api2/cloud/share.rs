@@ -0,0 +1,100 @@
+//! Pre-signed download URLs of cloud snapshot exports
+
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::{
+    Authid, CloudShareRecord, CloudShareUrl, CLOUD_EXPORT_KEY_SCHEMA, CLOUD_SHARE_TTL_SCHEMA,
+    CLOUD_TARGET_NAME_SCHEMA, PRIV_CLOUD_AUDIT, PRIV_CLOUD_RESTORE, SINGLE_LINE_COMMENT_SCHEMA,
+};
+
+use crate::cloud::{
+    backend::{into_http_error, open_target_backend},
+    share::{load_share_log, share_export},
+    CLOUD_STATUS_DIR,
+};
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Shared URLs, oldest first.",
+        type: Array,
+        items: { type: CloudShareRecord },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List the download URLs shared from a target (audit log).
+pub fn list(name: String) -> Result<Vec<CloudShareRecord>, Error> {
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    load_share_log(CLOUD_STATUS_DIR, &name)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            key: {
+                schema: CLOUD_EXPORT_KEY_SCHEMA,
+            },
+            ttl: {
+                schema: CLOUD_SHARE_TTL_SCHEMA,
+                optional: true,
+            },
+            comment: {
+                schema: SINGLE_LINE_COMMENT_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudShareUrl,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_RESTORE, false),
+    },
+)]
+/// Create a time limited download URL for a snapshot export.
+///
+/// Anyone knowing the URL can download the export until it expires, and
+/// the URL cannot be revoked. Every URL is recorded in the audit log.
+pub fn create(
+    name: String,
+    key: String,
+    ttl: Option<u64>,
+    comment: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<CloudShareUrl, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let (target, backend) = open_target_backend(&name)?;
+
+    share_export(
+        CLOUD_STATUS_DIR,
+        &target,
+        &*backend,
+        &key,
+        ttl,
+        &auth_id,
+        comment,
+        proxmox_time::epoch_i64(),
+    )
+    .map_err(into_http_error)
+}
+
+const SHARE_ROUTER: Router = Router::new().get(&API_METHOD_LIST).post(&API_METHOD_CREATE);
+
+pub const ROUTER: Router = Router::new().match_all("name", &SHARE_ROUTER);
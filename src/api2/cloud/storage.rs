@@ -0,0 +1,2034 @@
+//! Runtime operations on cloud targets
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+use serde_json::Value;
+
+use proxmox_human_byte::HumanByte;
+use proxmox_router::{
+    http_bail, list_subdirs_api_method, Permission, Router, RpcEnvironment, RpcEnvironmentType,
+    SubdirMap,
+};
+use proxmox_schema::{api, param_bail};
+use proxmox_sortable_macro::sortable;
+use proxmox_sys::task_log;
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{
+    check_group_filters, Authid, BackupNamespace, BackupType, CloudAccessAnomaly,
+    CloudBackupJobConfig, CloudBackupSince, CloudCatalogDigest, CloudDeleteQueueEntry,
+    CloudEgressStatus, CloudEndpointProbe, CloudForeignLayout, CloudFsckReport, CloudObjectVersion,
+    CloudPlacementAdvice, CloudRawObject, CloudRestorePreview, CloudRetentionAttestation,
+    CloudSnapshotChecksums, CloudSnapshotSummary, CloudStagingStatus, CloudStandbyStatus,
+    CloudTarget, CloudTargetCapabilities, CloudUploadEstimate, CloudUsageReport, GroupFilter,
+    Operation, BACKUP_ID_SCHEMA, CLOUD_BACKUP_SINCE_SCHEMA, CLOUD_COMPACT_THRESHOLD_SCHEMA,
+    CLOUD_MEDIA_SET_UUID_SCHEMA, CLOUD_OBJECT_PREFIX_SCHEMA, CLOUD_RESTORE_SNAPSHOT_SCHEMA,
+    CLOUD_TARGET_NAME_SCHEMA, CLOUD_USAGE_MONTH_SCHEMA, CLOUD_VERIFICATION_OUTDATED_AFTER_SCHEMA,
+    DATASTORE_SCHEMA, GROUP_FILTER_LIST_SCHEMA, IGNORE_VERIFIED_CLOUD_BACKUPS_SCHEMA,
+    NS_MAX_DEPTH_SCHEMA, PRIV_CLOUD_AUDIT, PRIV_CLOUD_BACKUP, PRIV_CLOUD_MODIFY,
+    PRIV_CLOUD_RESTORE, PRIV_DATASTORE_READ, TRANSFER_LAST_SCHEMA, UPID_SCHEMA,
+};
+use pbs_buildcfg::configdir;
+use pbs_config::CachedUserInfo;
+use pbs_datastore::DataStore;
+use pbs_key_config::KeyConfig;
+use proxmox_rest_server::WorkerTask;
+
+use crate::api2::cloud::backup::check_backup_permission;
+use crate::api2::cloud::paginate;
+use crate::api2::cloud::restore::parse_restore_snapshot;
+use crate::cloud::{
+    access_log::{load_access_anomalies, scan_access_logs},
+    backend::{
+        into_http_error, load_endpoint_probes, open_target_backend, CloudBackend, MeteredBackend,
+    },
+    catalog::CloudCatalog,
+    catalog_export::{export_catalog, import_catalog, parse_catalog_export},
+    checksums::snapshot_checksums,
+    chunk_cache::{node_chunk_cache, prewarm_chunk_cache},
+    chunk_reader::CloudChunkReader,
+    compaction::{compact_media_sets, DEFAULT_COMPACT_THRESHOLD},
+    config_history::{record_config_change, section_data},
+    content::CloudContentFilter,
+    delete_queue::DeleteQueue,
+    egress::{egress_status, EgressMeter},
+    encryption_keys::load_crypt_config,
+    foreign_import::{detect_foreign_layouts, import_datastore},
+    fsck::{fsck_target, load_fsck_report, FsckOptions},
+    health::{load_health_history, target_health},
+    mapping::job_target,
+    migration::{delete_migrated_objects, migrate_target, switch_target_storage},
+    parity::repair_target,
+    popularity::ChunkPopularity,
+    reconcile::reconcile_target,
+    repair::{datastore_chunk, repair_snapshot},
+    restore_preview::restore_preview,
+    retag::retag_objects,
+    retention_report::{build_retention_report, sign_retention_report},
+    rollback::{list_noncurrent_versions, rollback_media_sets},
+    snapshot_export::export_snapshot,
+    snapshot_summary::load_snapshot_summary,
+    staging::{upload_staged_objects, StagingSpool},
+    standby,
+    synthetic::create_synthetic_full,
+    upload_estimate::{estimate_datastore_upload, UploadSelection},
+    usage,
+    verify::{verify_snapshots, CloudVerifyOptions},
+    CLOUD_STATUS_DIR,
+};
+
+/// Time the uploader waits for new objects before it exits
+const STAGING_UPLOAD_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Default local cache size used for placement advice (1 GiB)
+const DEFAULT_ADVISOR_CACHE_SIZE: u64 = 1024 * 1024 * 1024;
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            "compact-when-below": {
+                schema: CLOUD_COMPACT_THRESHOLD_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_BACKUP, false),
+    },
+)]
+/// Compact media sets with little live data.
+///
+/// The live chunks of such media sets are rewritten into new chunk archives,
+/// the old archives are deleted.
+pub fn compact(
+    name: String,
+    compact_when_below: Option<u64>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    let threshold = compact_when_below.unwrap_or(DEFAULT_COMPACT_THRESHOLD);
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-compact",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (target, backend) = open_target_backend(&name)?;
+            compact_media_sets(&*worker, CLOUD_STATUS_DIR, &target, &backend, threshold)?;
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_BACKUP, false),
+    },
+)]
+/// Replace the tags of all existing objects with the current object tags of the target.
+pub fn retag(name: String, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-retag",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (target, backend) = open_target_backend(&name)?;
+            let updated = retag_objects(&*worker, &target, &*backend)?;
+            task_log!(worker, "updated tags of {} objects", updated);
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_BACKUP, false),
+    },
+)]
+/// Create a synthetic full media set from the current chain of a target.
+pub fn synthetic_full(name: String, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-synthetic-full",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (target, backend) = open_target_backend(&name)?;
+            let uuid = create_synthetic_full(&*worker, CLOUD_STATUS_DIR, &target, &backend)?;
+            task_log!(worker, "created synthetic full media set {}", uuid);
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Backup data of other tools found on the target.",
+        type: Array,
+        items: { type: CloudForeignLayout },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Detect backup data written to a target by other tools.
+///
+/// Lists the whole target, which can take a while for large buckets.
+pub fn foreign_layouts(name: String) -> Result<Vec<CloudForeignLayout>, Error> {
+    let (_target, backend) = open_target_backend(&name)?;
+    detect_foreign_layouts(&*backend).map_err(into_http_error)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            prefix: {
+                schema: CLOUD_OBJECT_PREFIX_SCHEMA,
+                optional: true,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_BACKUP, false),
+    },
+)]
+/// Import the snapshots of a datastore copied to a target.
+///
+/// The snapshots are added to the catalog as a read-only media set, as if
+/// they were backed up from datastore 'store'. The copied objects stay
+/// where they are.
+pub fn foreign_import(
+    name: String,
+    prefix: Option<String>,
+    store: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-foreign-import",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (target, backend) = open_target_backend(&name)?;
+            let prefix = prefix.unwrap_or_default();
+            import_datastore(
+                &*worker,
+                CLOUD_STATUS_DIR,
+                &target,
+                &backend,
+                &prefix,
+                &store,
+            )?;
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_RESTORE, false),
+    },
+)]
+/// Fill the local chunk cache with chunks of the newest media set of a target.
+pub fn chunk_cache_prewarm(name: String, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    let cache = match node_chunk_cache() {
+        Some(cache) => cache,
+        None => bail!("chunk cache is disabled (no 'cloud-chunk-cache-size' in node config)"),
+    };
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-chunk-cache-prewarm",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (target, backend) = open_target_backend(&name)?;
+            let catalog = Arc::new(CloudCatalog::load(CLOUD_STATUS_DIR, &target.name)?);
+
+            // downloads count against the egress budget like any restore
+            let meter = Arc::new(EgressMeter::open(CLOUD_STATUS_DIR, &target, true)?);
+            let backend: Arc<dyn CloudBackend> =
+                Arc::new(MeteredBackend::new(backend, Arc::clone(&meter)));
+
+            let reader = CloudChunkReader::new(backend, catalog.clone(), None)?.with_cache(None);
+            let downloaded = prewarm_chunk_cache(&*worker, &cache, &reader, &catalog)?;
+            reader.finish()?;
+            cache.flush()?;
+
+            let status = cache.status();
+            task_log!(
+                worker,
+                "downloaded {} chunks, cache holds {} chunks ({} of {})",
+                downloaded,
+                status.entries,
+                HumanByte::from(status.size),
+                HumanByte::from(status.max_size)
+            );
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            "cache-size": {
+                description: "Size of the local chunk cache in bytes.",
+                type: u64,
+                optional: true,
+                minimum: 0,
+            },
+        },
+    },
+    returns: {
+        type: CloudPlacementAdvice,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Cache and storage tier placement advice, based on chunk download statistics.
+pub fn advisor(name: String, cache_size: Option<u64>) -> Result<CloudPlacementAdvice, Error> {
+    pbs_config::cloud::lookup_target(&name)?;
+
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+    let popularity = ChunkPopularity::load(CLOUD_STATUS_DIR, &name)?;
+
+    Ok(popularity.advise(
+        &catalog,
+        proxmox_time::epoch_i64(),
+        cache_size.unwrap_or(DEFAULT_ADVISOR_CACHE_SIZE),
+    ))
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: CloudTargetCapabilities,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Query the features supported by the object storage of a target.
+pub fn capabilities(name: String) -> Result<CloudTargetCapabilities, Error> {
+    let (_target, backend) = open_target_backend(&name)?;
+    backend.capabilities().map_err(into_http_error)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Queued object deletions.",
+        type: Array,
+        items: { type: CloudDeleteQueueEntry },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List object deletions deferred by the delete protection of a target.
+///
+/// The list can be used to delete the objects with separate credentials,
+/// or to configure lifecycle rules at the provider.
+pub fn delete_queue(name: String) -> Result<Vec<CloudDeleteQueueEntry>, Error> {
+    let queue = DeleteQueue::load(CLOUD_STATUS_DIR, &name)?;
+    Ok(queue.entries().to_vec())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: CloudEgressStatus,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Show the egress (download) volume and budget of a target in the current month.
+pub fn egress(name: String) -> Result<CloudEgressStatus, Error> {
+    let target = pbs_config::cloud::lookup_target(&name)?;
+    egress_status(CLOUD_STATUS_DIR, &target)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: CloudTargetHealth,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Show the reachability and latency history of a target.
+pub fn health(name: String) -> Result<CloudTargetHealth, Error> {
+    let _target = pbs_config::cloud::lookup_target(&name)?;
+    let history = load_health_history(CLOUD_STATUS_DIR, &name)?;
+    Ok(target_health(&name, history))
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            month: {
+                schema: CLOUD_USAGE_MONTH_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudUsageReport,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Show the transferred data and requests of a target in a month (default: current month).
+pub fn usage_report(name: String, month: Option<String>) -> Result<CloudUsageReport, Error> {
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    usage::usage_report(CLOUD_STATUS_DIR, &name, month.as_deref())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Endpoint latencies measured at the start of the last job.",
+        type: Array,
+        items: { type: CloudEndpointProbe },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Show the result of the last endpoint latency probe of a target.
+pub fn endpoints(name: String) -> Result<Vec<CloudEndpointProbe>, Error> {
+    load_endpoint_probes(CLOUD_STATUS_DIR, &name)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Noncurrent versions of catalog and index objects.",
+        type: Array,
+        items: { type: CloudObjectVersion },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List noncurrent versions of catalog and index objects (versioned targets only).
+pub fn list_versions(name: String) -> Result<Vec<CloudObjectVersion>, Error> {
+    let (_target, backend) = open_target_backend(&name)?;
+    list_noncurrent_versions(&*backend).map_err(into_http_error)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            prefix: {
+                description: "Only list objects with keys starting with this prefix.",
+                type: String,
+                optional: true,
+            },
+            start: {
+                type: u64,
+                description: "List objects beginning from this offset.",
+                default: 0,
+                optional: true,
+            },
+            limit: {
+                type: u64,
+                description: "Only list this amount of objects. (0 means no limit)",
+                default: 50,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "Objects below the prefix, sorted by key.",
+        type: Array,
+        items: { type: CloudRawObject },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List raw objects of a target, without interpreting the catalog (for debugging).
+pub fn raw_list(
+    name: String,
+    prefix: Option<String>,
+    start: u64,
+    limit: u64,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudRawObject>, Error> {
+    let (_target, backend) = open_target_backend(&name)?;
+
+    let list =
+        list_raw_objects(&*backend, prefix.as_deref().unwrap_or("")).map_err(into_http_error)?;
+
+    Ok(paginate(list, start, limit, rpcenv))
+}
+
+/// Objects with keys starting with `prefix`, sorted by key
+pub fn list_raw_objects(
+    backend: &dyn CloudBackend,
+    prefix: &str,
+) -> Result<Vec<CloudRawObject>, Error> {
+    let mut list = backend.list_objects(prefix)?;
+    list.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(list.into_iter().map(CloudRawObject::from).collect())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_BACKUP, false),
+    },
+)]
+/// Scan new provider access logs for object accesses not originating from PBS.
+///
+/// The task fails if such accesses are found.
+pub fn access_log_scan(name: String, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let target = pbs_config::cloud::lookup_target(&name)?;
+    if target.config.access_log_prefix.is_none() {
+        bail!("target '{}' has no 'access-log-prefix'", name);
+    }
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-access-log-scan",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (target, backend) = open_target_backend(&name)?;
+            let found = scan_access_logs(&*worker, CLOUD_STATUS_DIR, &target, &*backend)?;
+            if !found.is_empty() {
+                bail!(
+                    "found {} object accesses not originating from PBS",
+                    found.len()
+                );
+            }
+            task_log!(worker, "no foreign object accesses found");
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Object accesses not originating from PBS, newest first.",
+        type: Array,
+        items: { type: CloudAccessAnomaly },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List object accesses not originating from PBS found by previous access log scans.
+pub fn access_anomalies(name: String) -> Result<Vec<CloudAccessAnomaly>, Error> {
+    pbs_config::cloud::lookup_target(&name)?;
+    load_access_anomalies(CLOUD_STATUS_DIR, &name)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            timestamp: {
+                description: "Restore the state as of this time (UNIX epoch).",
+                type: i64,
+                minimum: 0,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_BACKUP, false),
+    },
+)]
+/// Restore media set catalogs and data deleted after the given time (versioned targets only).
+pub fn catalog_rollback(
+    name: String,
+    timestamp: i64,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-catalog-rollback",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (target, backend) = open_target_backend(&name)?;
+            task_log!(
+                worker,
+                "rollback to {}",
+                proxmox_time::epoch_to_rfc3339_utc(timestamp)?
+            );
+            let restored =
+                rollback_media_sets(&*worker, CLOUD_STATUS_DIR, &target, &backend, timestamp)?;
+            task_log!(worker, "restored {} objects", restored);
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            repair: {
+                description: "Rebuild lost chunk archives and parity objects from parity.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+        description: "Repairing objects additionally requires Cloud.Backup on the target.",
+    },
+)]
+/// Check that all objects referenced by the local catalog exist on the target.
+///
+/// Use this after importing an offline export into the bucket. With
+/// `repair`, lost chunk archives are rebuilt from their parity objects.
+pub fn reconcile(
+    name: String,
+    repair: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    if repair {
+        let user_info = CachedUserInfo::new()?;
+        user_info.check_privs(
+            &auth_id,
+            &["cloud", "target", &name],
+            PRIV_CLOUD_BACKUP,
+            false,
+        )?;
+    }
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-reconcile",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (target, backend) = open_target_backend(&name)?;
+            let mut result = reconcile_target(&*worker, CLOUD_STATUS_DIR, &target, &backend)?;
+            task_log!(worker, "checked {} objects", result.checked);
+            if repair && !result.is_ok() {
+                let repaired =
+                    repair_target(&*worker, CLOUD_STATUS_DIR, &target, &backend, &result)?;
+                task_log!(worker, "repaired {} objects", repaired.len());
+                result.remove_repaired(&repaired);
+            }
+            if !result.is_ok() {
+                bail!(
+                    "{} objects missing, {} objects with wrong size",
+                    result.missing.len(),
+                    result.size_mismatch.len()
+                );
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: CloudFsckReport,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Report of the last consistency check of a target.
+pub fn fsck_report(name: String) -> Result<CloudFsckReport, Error> {
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    match load_fsck_report(CLOUD_STATUS_DIR, &name)? {
+        Some(report) => Ok(report),
+        None => http_bail!(NOT_FOUND, "no consistency check of target '{}' found", name),
+    }
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            verify: {
+                description: "Verify the checksums of chunk archives and parity objects. Provider \
+                    checksums are used where available, other objects are downloaded.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            repair: {
+                description: "Rebuild damaged objects from parity or local chunks and delete orphaned objects and dangling media sets.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+        description: "Repairing objects additionally requires Cloud.Backup on the target.",
+    },
+)]
+/// Check the consistency of the objects on a target with the local catalog.
+///
+/// Besides missing and damaged objects, this reports objects no catalog
+/// references and media sets left behind by aborted backups.
+pub fn fsck(
+    name: String,
+    verify: bool,
+    repair: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    if repair {
+        let user_info = CachedUserInfo::new()?;
+        user_info.check_privs(
+            &auth_id,
+            &["cloud", "target", &name],
+            PRIV_CLOUD_BACKUP,
+            false,
+        )?;
+    }
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-fsck",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (target, backend) = open_target_backend(&name)?;
+            let options = FsckOptions { verify, repair };
+            let report = fsck_target(
+                &*worker,
+                CLOUD_STATUS_DIR,
+                &target,
+                &backend,
+                options,
+                &datastore_chunk,
+            )?;
+            let unrepaired = report.unrepaired();
+            if unrepaired > 0 {
+                bail!("found {} problems", unrepaired);
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            snapshot: {
+                schema: CLOUD_RESTORE_SNAPSHOT_SCHEMA,
+            },
+            "dry-run": {
+                description: "Only check the snapshot and report damaged objects.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_BACKUP, false),
+        description: "Also requires Datastore.Read on the datastore of the snapshot.",
+    },
+)]
+/// Repair a damaged snapshot on a target from the local datastore.
+///
+/// All objects of the snapshot are checked against the catalog, lost or
+/// modified objects are uploaded again from the local copy of the snapshot.
+pub fn repair(
+    name: String,
+    snapshot: String,
+    dry_run: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let (store, ns, dir) = parse_restore_snapshot(&snapshot)?;
+    check_backup_permission(&auth_id, &store, &name)?;
+
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+    if catalog.lookup_snapshot(&store, &ns, &dir).is_none() {
+        http_bail!(
+            NOT_FOUND,
+            "snapshot '{}' not found on target '{}'",
+            snapshot,
+            name
+        );
+    }
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-repair",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (target, backend) = open_target_backend(&name)?;
+            task_log!(worker, "checking snapshot {}", snapshot);
+            let damage = repair_snapshot(
+                &*worker,
+                CLOUD_STATUS_DIR,
+                &target,
+                &backend,
+                &store,
+                &ns,
+                &dir,
+                dry_run,
+            )?;
+            if damage.is_empty() {
+                task_log!(worker, "snapshot is intact");
+            } else if dry_run {
+                bail!(
+                    "{} files and {} chunk archives damaged",
+                    damage.files.len(),
+                    damage.archives.len()
+                );
+            } else {
+                task_log!(
+                    worker,
+                    "repaired {} files and {} chunk archives",
+                    damage.files.len(),
+                    damage.archives.len()
+                );
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            snapshot: {
+                schema: CLOUD_RESTORE_SNAPSHOT_SCHEMA,
+            },
+            "key-config": {
+                description: "Password protected client encryption key (JSON key configuration) \
+                    to include in the export.",
+                type: String,
+                min_length: 300,
+                max_length: 600,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_RESTORE, false),
+        description: "Also requires Datastore.Read on the namespace of the snapshot.",
+    },
+)]
+/// Export a snapshot into a standalone archive object on the target.
+///
+/// The archive contains the indexes, chunks and manifest of the snapshot,
+/// so it can be handed over without access to this server.
+pub fn export(
+    name: String,
+    snapshot: String,
+    key_config: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let (store, ns, dir) = parse_restore_snapshot(&snapshot)?;
+    let user_info = CachedUserInfo::new()?;
+    user_info.check_privs(&auth_id, &ns.acl_path(&store), PRIV_DATASTORE_READ, false)?;
+
+    let key_config: Option<KeyConfig> = match key_config {
+        Some(key_config) => Some(
+            serde_json::from_str(&key_config)
+                .map_err(|err| format_err!("unable to parse key configuration - {}", err))?,
+        ),
+        None => None,
+    };
+
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+    let namespace_key = match catalog.lookup_snapshot(&store, &ns, &dir) {
+        Some((_, entry)) => entry.key.clone(),
+        None => http_bail!(
+            NOT_FOUND,
+            "snapshot '{}' not found on target '{}'",
+            snapshot,
+            name
+        ),
+    };
+    let crypt_config = match namespace_key {
+        Some(fingerprint) => {
+            let crypt_config = load_crypt_config(&fingerprint).map_err(|_| {
+                format_err!(
+                    "data is encrypted with key '{}' which is not stored on this server",
+                    fingerprint.signature()
+                )
+            })?;
+            Some((fingerprint, crypt_config))
+        }
+        None => None,
+    };
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-export",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (_target, backend) = open_target_backend(&name)?;
+            let catalog = Arc::new(catalog);
+            task_log!(worker, "exporting snapshot {}", snapshot);
+            let key = export_snapshot(
+                &*worker,
+                &backend,
+                &catalog,
+                &store,
+                &ns,
+                &dir,
+                crypt_config,
+                key_config.as_ref(),
+            )?;
+            task_log!(worker, "exported snapshot to '{}'", key);
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "backup-type": {
+                type: BackupType,
+                optional: true,
+            },
+            "backup-id": {
+                schema: BACKUP_ID_SCHEMA,
+                optional: true,
+            },
+            "ignore-verified": {
+                schema: IGNORE_VERIFIED_CLOUD_BACKUPS_SCHEMA,
+                optional: true,
+            },
+            "outdated-after": {
+                schema: CLOUD_VERIFICATION_OUTDATED_AFTER_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_BACKUP, false),
+    },
+)]
+/// Verify the snapshots on a target.
+///
+/// The objects of the newest copy of each snapshot are downloaded and
+/// checked against the catalog. The result is stored in the catalog and
+/// shown in the content listing.
+#[allow(clippy::too_many_arguments)]
+pub fn verify(
+    name: String,
+    store: Option<String>,
+    ns: Option<BackupNamespace>,
+    backup_type: Option<BackupType>,
+    backup_id: Option<String>,
+    ignore_verified: Option<bool>,
+    outdated_after: Option<i64>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    let filter = CloudContentFilter {
+        store,
+        ns,
+        backup_type,
+        backup_id,
+        ..Default::default()
+    };
+    let options = CloudVerifyOptions {
+        ignore_verified: ignore_verified.unwrap_or(true),
+        outdated_after,
+    };
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-verify",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (target, backend) = open_target_backend(&name)?;
+            let result = verify_snapshots(
+                &*worker,
+                CLOUD_STATUS_DIR,
+                &target,
+                &backend,
+                &filter,
+                &options,
+                worker.upid(),
+            )?;
+            task_log!(
+                worker,
+                "verified {} snapshots, skipped {} recently verified",
+                result.verified,
+                result.skipped
+            );
+            if !result.failed.is_empty() {
+                task_log!(worker, "failed to verify the following snapshots:");
+                for name in result.failed.iter() {
+                    task_log!(worker, "\t{}", name);
+                }
+                bail!("verification failed - please check the log for details");
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+// move the storage of `destination` to `name`, unless either target
+// changed since the migration started
+fn switch_migrated_target(
+    auth_id: &Authid,
+    name: &str,
+    destination: &str,
+    expected: &(Option<Value>, Option<Value>),
+) -> Result<(), Error> {
+    let _lock = pbs_config::cloud::lock_config()?;
+    let (mut config, _digest) = pbs_config::cloud::config()?;
+
+    let old = section_data(&config, name);
+    let old_destination = section_data(&config, destination);
+    if (&old, &old_destination) != (&expected.0, &expected.1) {
+        bail!("target configuration changed during migration - not switching");
+    }
+
+    let mut target: CloudTarget = config.lookup("target", name)?;
+    let other: CloudTarget = config.lookup("target", destination)?;
+    switch_target_storage(&mut target, &other);
+
+    config.set_data(name, "target", &target)?;
+    config.sections.remove(destination);
+
+    pbs_config::cloud::save_config(&config)?;
+
+    record_config_change(
+        auth_id,
+        "target",
+        name,
+        old.as_ref(),
+        section_data(&config, name).as_ref(),
+    );
+    record_config_change(
+        auth_id,
+        "target",
+        destination,
+        old_destination.as_ref(),
+        None,
+    );
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            destination: {
+                description: "Empty target whose storage (provider, bucket, prefix and \
+                    credentials) the target is migrated to. It is removed afterwards.",
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            "delete-source": {
+                description: "Delete the objects from the old storage after the copies \
+                    were verified and the target was switched.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_MODIFY, false),
+        description: "Also requires Cloud.Modify on the destination target.",
+    },
+)]
+/// Migrate a target to the storage of another target.
+///
+/// All objects are copied (server-side if possible) and verified, then the
+/// storage properties of the destination are moved to the target. Jobs,
+/// catalog and other local state of the target are kept.
+pub fn migrate(
+    name: String,
+    destination: String,
+    delete_source: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let user_info = CachedUserInfo::new()?;
+    user_info.check_privs(
+        &auth_id,
+        &["cloud", "target", &destination],
+        PRIV_CLOUD_MODIFY,
+        false,
+    )?;
+
+    if name == destination {
+        param_bail!("destination", "cannot migrate a target to itself");
+    }
+
+    let (config, _digest) = pbs_config::cloud::config()?;
+    let expected = (
+        section_data(&config, &name),
+        section_data(&config, &destination),
+    );
+    if expected.0.is_none() {
+        http_bail!(NOT_FOUND, "cloud target '{}' does not exist.", name);
+    }
+    if expected.1.is_none() {
+        param_bail!(
+            "destination",
+            "cloud target '{}' does not exist.",
+            destination
+        );
+    }
+
+    let (job_config, _) = pbs_config::cloud_job::config()?;
+    let job_list: Vec<CloudBackupJobConfig> = job_config.convert_to_typed_array("backup")?;
+    if let Some(job) = job_list
+        .iter()
+        .find(|job| job_target(&job.setup).ok().as_deref() == Some(destination.as_str()))
+    {
+        param_bail!(
+            "destination",
+            "cloud target '{}' is used by cloud backup job '{}'",
+            destination,
+            job.id
+        );
+    }
+
+    if !CloudCatalog::load(CLOUD_STATUS_DIR, &destination)?
+        .media_sets()
+        .is_empty()
+    {
+        param_bail!(
+            "destination",
+            "cloud target '{}' already contains media sets",
+            destination
+        );
+    }
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-migrate",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+            let (target, backend) = open_target_backend(&name)?;
+            let (other, other_backend) = open_target_backend(&destination)?;
+
+            task_log!(
+                worker,
+                "migrating target '{}' to storage of '{}'",
+                name,
+                destination
+            );
+            migrate_target(
+                &*worker,
+                &catalog,
+                &target,
+                &backend,
+                &other,
+                &other_backend,
+            )?;
+
+            switch_migrated_target(&auth_id, &name, &destination, &expected)?;
+            task_log!(
+                worker,
+                "switched target '{}' to new storage, removed target '{}'",
+                name,
+                destination
+            );
+
+            if delete_source {
+                let deleted = delete_migrated_objects(&*worker, &*backend)?;
+                task_log!(worker, "deleted {} objects from old storage", deleted);
+            }
+
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: CloudRetentionAttestation,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Object lock state of all media sets and snapshots, signed with the node certificate.
+pub fn retention_report(name: String) -> Result<CloudRetentionAttestation, Error> {
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+    let (target, backend) = open_target_backend(&name)?;
+
+    let report = build_retention_report(&target, &*backend, &catalog)?;
+
+    let key_pem = proxmox_sys::fs::file_get_contents(configdir!("/proxy.key"))?;
+    let cert_pem = proxmox_sys::fs::file_get_contents(configdir!("/proxy.pem"))?;
+
+    sign_retention_report(report, &key_pem, &cert_pem)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            snapshot: {
+                schema: CLOUD_RESTORE_SNAPSHOT_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: CloudSnapshotSummary,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Sizes of a snapshot on a target, without downloading its manifest.
+pub fn snapshot_summary(name: String, snapshot: String) -> Result<CloudSnapshotSummary, Error> {
+    let (store, ns, dir) = parse_restore_snapshot(&snapshot)?;
+
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+    let (media_set, entry) = match catalog.lookup_snapshot(&store, &ns, &dir) {
+        Some(found) => found,
+        None => http_bail!(
+            NOT_FOUND,
+            "snapshot '{}' not found on target '{}'",
+            snapshot,
+            name
+        ),
+    };
+
+    let (_target, backend) = open_target_backend(&name)?;
+
+    load_snapshot_summary(&*backend, &catalog, media_set.uuid(), entry)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            snapshot: {
+                schema: CLOUD_RESTORE_SNAPSHOT_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: CloudRestorePreview,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_RESTORE, false),
+    },
+)]
+/// Preview the archives of a snapshot before restoring it.
+///
+/// Lists size, file count and largest files per archive, from metadata
+/// only (no chunk objects are downloaded).
+pub fn preview_restore(name: String, snapshot: String) -> Result<CloudRestorePreview, Error> {
+    let (store, ns, dir) = parse_restore_snapshot(&snapshot)?;
+
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+    let (media_set, entry) = match catalog.lookup_snapshot(&store, &ns, &dir) {
+        Some(found) => found,
+        None => http_bail!(
+            NOT_FOUND,
+            "snapshot '{}' not found on target '{}'",
+            snapshot,
+            name
+        ),
+    };
+
+    let (_target, backend) = open_target_backend(&name)?;
+
+    restore_preview(&*backend, &catalog, media_set.uuid(), entry)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            "group-filter": {
+                schema: GROUP_FILTER_LIST_SCHEMA,
+                optional: true,
+            },
+            "latest-only": {
+                description: "Only consider the latest snapshots.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "transfer-last": {
+                schema: TRANSFER_LAST_SCHEMA,
+                optional: true,
+            },
+            since: {
+                schema: CLOUD_BACKUP_SINCE_SCHEMA,
+                optional: true,
+            },
+            "force-full": {
+                description: "Estimate a new full media set instead of an incremental one.",
+                optional: true,
+                type: bool,
+                default: false,
+            },
+            "upload-rate": {
+                description: "Upload rate used to estimate the transfer time (per second).",
+                type: HumanByte,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudUploadEstimate,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_BACKUP, false),
+        description: "Also requires Datastore.Read on the datastore.",
+    },
+)]
+/// Estimate the upload of a backup to a target.
+///
+/// Compares the chunks of the selected snapshots against the chunks the
+/// target already holds (according to the catalog), and reports how many
+/// bytes would be uploaded and how many would be reused. Snapshots already
+/// on the target are skipped, like a backup job does.
+#[allow(clippy::too_many_arguments)]
+pub fn upload_estimate(
+    name: String,
+    store: String,
+    ns: Option<BackupNamespace>,
+    max_depth: Option<usize>,
+    group_filter: Option<Vec<GroupFilter>>,
+    latest_only: bool,
+    transfer_last: Option<usize>,
+    since: Option<String>,
+    force_full: bool,
+    upload_rate: Option<HumanByte>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<CloudUploadEstimate, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    check_backup_permission(&auth_id, &store, &name)?;
+
+    if let Some(ref filters) = group_filter {
+        if let Err(err) = check_group_filters(filters) {
+            param_bail!("group-filter", err);
+        }
+    }
+
+    let since = match since {
+        Some(since) => match since.parse::<CloudBackupSince>() {
+            Ok(since) => Some(since.resolve(proxmox_time::epoch_i64())),
+            Err(err) => param_bail!("since", err),
+        },
+        None => None,
+    };
+
+    let target = pbs_config::cloud::lookup_target(&name)?;
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+
+    let selection = UploadSelection {
+        ns: ns.unwrap_or_default(),
+        max_depth,
+        group_filter,
+        latest_only,
+        transfer_last,
+        since,
+    };
+
+    estimate_datastore_upload(
+        &datastore,
+        &target,
+        &catalog,
+        &selection,
+        force_full,
+        upload_rate.map(|rate| rate.as_u64()),
+    )
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            snapshot: {
+                schema: CLOUD_RESTORE_SNAPSHOT_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: CloudSnapshotChecksums,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List object keys and checksums of a snapshot, for verification with external tools.
+pub fn checksums(name: String, snapshot: String) -> Result<CloudSnapshotChecksums, Error> {
+    let (store, ns, dir) = parse_restore_snapshot(&snapshot)?;
+
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+    let (media_set, entry) = match catalog.lookup_snapshot(&store, &ns, &dir) {
+        Some(found) => found,
+        None => http_bail!(
+            NOT_FOUND,
+            "snapshot '{}' not found on target '{}'",
+            snapshot,
+            name
+        ),
+    };
+
+    snapshot_checksums(&catalog, media_set, entry)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Digests of the media set catalogs, oldest first.",
+        type: Array,
+        items: { type: CloudCatalogDigest },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List the digests of all local media set catalogs of a target.
+///
+/// Used by warm standby nodes to detect changed catalogs.
+pub fn catalog_digests(name: String) -> Result<Vec<CloudCatalogDigest>, Error> {
+    let _target = pbs_config::cloud::lookup_target(&name)?;
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+    standby::catalog_digests(&catalog)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Versioned export of all media set catalogs.",
+        type: Object,
+        properties: {},
+        additional_properties: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Export the local catalog of a target in a portable format.
+pub fn catalog_export(name: String) -> Result<Value, Error> {
+    let _target = pbs_config::cloud::lookup_target(&name)?;
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+    Ok(serde_json::to_value(export_catalog(&catalog))?)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            data: {
+                description: "Catalog export (JSON).",
+                type: String,
+            },
+            overwrite: {
+                description: "Replace media sets already in the local catalog.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Import a catalog export into the local catalog of a target.
+///
+/// All media sets of the export have to exist on the target.
+pub fn catalog_import(name: String, data: String, overwrite: bool) -> Result<(), Error> {
+    let export = parse_catalog_export(data.as_bytes())?;
+    let (_target, backend) = open_target_backend(&name)?;
+    let result = import_catalog(CLOUD_STATUS_DIR, &name, &*backend, &export, overwrite)?;
+    log::info!(
+        "cloud target '{}': imported {} media sets from catalog export of '{}' (skipped {})",
+        name,
+        result.imported,
+        export.target,
+        result.skipped
+    );
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            uuid: {
+                schema: CLOUD_MEDIA_SET_UUID_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Media set catalog.",
+        type: Object,
+        properties: {},
+        additional_properties: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Get the local catalog of a media set.
+pub fn media_set_catalog(name: String, uuid: String) -> Result<Value, Error> {
+    let _target = pbs_config::cloud::lookup_target(&name)?;
+    let uuid: Uuid = uuid.parse()?;
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+    match catalog.lookup_media_set(&uuid) {
+        Some(media_set) => Ok(serde_json::to_value(media_set)?),
+        None => http_bail!(
+            NOT_FOUND,
+            "media set {} not found on target '{}'",
+            uuid,
+            name
+        ),
+    }
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: CloudStandbyStatus,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Show the catalog sync state of a warm standby target.
+pub fn standby_status(name: String) -> Result<CloudStandbyStatus, CloudTargetHealth, Error> {
+    let target = pbs_config::cloud::lookup_target(&name)?;
+    let remote = match target.config.standby_remote {
+        Some(remote) => remote,
+        None => http_bail!(BAD_REQUEST, "target '{}' is not a warm standby", name),
+    };
+    let mut status =
+        standby::load_standby_status(Path::new(CLOUD_STATUS_DIR), &name)?.unwrap_or_default();
+    status.remote = remote;
+    Ok(status)
+}
+
+/// Start the uploader of the write-back spool of a target
+///
+/// Returns `None` if an uploader is already running.
+pub fn start_staging_upload(
+    name: &str,
+    auth_id: &Authid,
+    to_stdout: bool,
+) -> Result<Option<String>, Error> {
+    let target = pbs_config::cloud::lookup_target(name)?;
+    let spool = StagingSpool::open_target(CLOUD_STATUS_DIR, &target)?;
+    let lock = match spool.try_lock_uploader() {
+        Some(lock) => lock,
+        None => return Ok(None),
+    };
+
+    let name = name.to_string();
+    let upid_str = WorkerTask::new_thread(
+        "cloud-staging-upload",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let _lock = lock;
+            let (_target, backend) = open_target_backend(&name)?;
+            let stats =
+                upload_staged_objects(&*worker, &spool, &*backend, STAGING_UPLOAD_IDLE_TIMEOUT)?;
+            task_log!(
+                worker,
+                "uploaded {} objects ({})",
+                stats.objects,
+                HumanByte::from(stats.bytes)
+            );
+            Ok(())
+        },
+    )?;
+
+    Ok(Some(upid_str))
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: CloudStagingStatus,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Objects of a target waiting in the write-back spool and the health of
+/// its uploader.
+pub fn staging_status(name: String) -> Result<CloudStagingStatus, Error> {
+    let target = pbs_config::cloud::lookup_target(&name)?;
+    let spool = StagingSpool::open_target(CLOUD_STATUS_DIR, &target)?;
+    spool.status(target.config.write_back.unwrap_or(false))
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_BACKUP, false),
+    },
+)]
+/// Upload the objects of the write-back spool of a target now.
+pub fn staging_upload(name: String, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    match start_staging_upload(&name, &auth_id, to_stdout)? {
+        Some(upid_str) => Ok(upid_str.into()),
+        None => bail!("uploader of target '{}' is already running", name),
+    }
+}
+
+#[sortable]
+const STORAGE_SUBDIRS: SubdirMap = &sorted!([
+    (
+        "access-anomalies",
+        &Router::new().get(&API_METHOD_ACCESS_ANOMALIES)
+    ),
+    (
+        "access-log-scan",
+        &Router::new().post(&API_METHOD_ACCESS_LOG_SCAN)
+    ),
+    ("advisor", &Router::new().get(&API_METHOD_ADVISOR)),
+    ("capabilities", &Router::new().get(&API_METHOD_CAPABILITIES)),
+    (
+        "catalog-digests",
+        &Router::new().get(&API_METHOD_CATALOG_DIGESTS)
+    ),
+    (
+        "catalog-export",
+        &Router::new().get(&API_METHOD_CATALOG_EXPORT)
+    ),
+    (
+        "catalog-import",
+        &Router::new().post(&API_METHOD_CATALOG_IMPORT)
+    ),
+    (
+        "catalog-rollback",
+        &Router::new().post(&API_METHOD_CATALOG_ROLLBACK)
+    ),
+    ("checksums", &Router::new().get(&API_METHOD_CHECKSUMS)),
+    (
+        "chunk-cache-prewarm",
+        &Router::new().post(&API_METHOD_CHUNK_CACHE_PREWARM)
+    ),
+    ("compact", &Router::new().post(&API_METHOD_COMPACT)),
+    ("delete-queue", &Router::new().get(&API_METHOD_DELETE_QUEUE)),
+    ("egress", &Router::new().get(&API_METHOD_EGRESS)),
+    ("endpoints", &Router::new().get(&API_METHOD_ENDPOINTS)),
+    ("export", &Router::new().post(&API_METHOD_EXPORT)),
+    (
+        "foreign",
+        &Router::new()
+            .get(&API_METHOD_FOREIGN_LAYOUTS)
+            .post(&API_METHOD_FOREIGN_IMPORT)
+    ),
+    (
+        "fsck",
+        &Router::new()
+            .get(&API_METHOD_FSCK_REPORT)
+            .post(&API_METHOD_FSCK)
+    ),
+    ("health", &Router::new().get(&API_METHOD_HEALTH)),
+    (
+        "media-set-catalog",
+        &Router::new().get(&API_METHOD_MEDIA_SET_CATALOG)
+    ),
+    ("migrate", &Router::new().post(&API_METHOD_MIGRATE)),
+    ("raw-list", &Router::new().get(&API_METHOD_RAW_LIST)),
+    ("reconcile", &Router::new().post(&API_METHOD_RECONCILE)),
+    ("repair", &Router::new().post(&API_METHOD_REPAIR)),
+    (
+        "restore-preview",
+        &Router::new().get(&API_METHOD_PREVIEW_RESTORE)
+    ),
+    ("retag", &Router::new().post(&API_METHOD_RETAG)),
+    (
+        "retention-report",
+        &Router::new().get(&API_METHOD_RETENTION_REPORT)
+    ),
+    (
+        "snapshot-summary",
+        &Router::new().get(&API_METHOD_SNAPSHOT_SUMMARY)
+    ),
+    (
+        "staging",
+        &Router::new()
+            .get(&API_METHOD_STAGING_STATUS)
+            .post(&API_METHOD_STAGING_UPLOAD)
+    ),
+    ("standby", &Router::new().get(&API_METHOD_STANDBY_STATUS)),
+    (
+        "synthetic-full",
+        &Router::new().post(&API_METHOD_SYNTHETIC_FULL)
+    ),
+    (
+        "upload-estimate",
+        &Router::new().get(&API_METHOD_UPLOAD_ESTIMATE)
+    ),
+    ("usage-report", &Router::new().get(&API_METHOD_USAGE_REPORT)),
+    ("verify", &Router::new().post(&API_METHOD_VERIFY)),
+    ("versions", &Router::new().get(&API_METHOD_LIST_VERSIONS)),
+]);
+
+const STORAGE_ROUTER: Router = Router::new()
+    .get(&list_subdirs_api_method!(STORAGE_SUBDIRS))
+    .subdirs(STORAGE_SUBDIRS);
+
+pub const ROUTER: Router = Router::new().match_all("name", &STORAGE_ROUTER);
@@ -0,0 +1,134 @@
+//! Task logs of cloud workers
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use anyhow::Error;
+use serde_json::{json, Value};
+
+use proxmox_router::{list_subdirs_api_method, Permission, Router, RpcEnvironment, SubdirMap};
+use proxmox_schema::api;
+
+use pbs_api_types::{Authid, CloudRequestTrace, CloudTaskLogFormat, UPID, UPID_SCHEMA};
+use proxmox_rest_server::upid_log_path;
+
+use crate::api2::cloud::paginate;
+use crate::api2::node::tasks::check_task_access;
+use crate::cloud::{
+    request_trace::load_request_trace, task_records::read_task_records, CLOUD_STATUS_DIR,
+};
+
+#[api(
+    input: {
+        properties: {
+            upid: {
+                schema: UPID_SCHEMA,
+            },
+            format: {
+                type: CloudTaskLogFormat,
+                optional: true,
+            },
+            start: {
+                type: u64,
+                description: "Start at this line (or record).",
+                default: 0,
+                optional: true,
+            },
+            limit: {
+                type: u64,
+                description: "Only return this amount of lines (or records). (0 means no limit)",
+                default: 50,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "Task log lines ('n' and 't'), or structured records (see CloudTaskRecord).",
+        type: Array,
+        items: {
+            type: Object,
+            description: "Log line or record.",
+            properties: {},
+            additional_properties: true,
+        },
+    },
+    access: {
+        description: "Users can access their own tasks, or need Sys.Audit on /system/tasks.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Read the log of a cloud task, as text lines or structured records.
+pub fn read_task_log(
+    upid: String,
+    format: Option<CloudTaskLogFormat>,
+    start: u64,
+    limit: u64,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<Value>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let parsed: UPID = upid.parse()?;
+    check_task_access(&auth_id, &parsed)?;
+
+    let list = match format.unwrap_or_default() {
+        CloudTaskLogFormat::Text => {
+            let file = File::open(upid_log_path(&parsed)?)?;
+            let mut list = Vec::new();
+            for (n, line) in BufReader::new(file).lines().enumerate() {
+                list.push(json!({ "n": n + 1, "t": line? }));
+            }
+            list
+        }
+        CloudTaskLogFormat::Json => read_task_records(CLOUD_STATUS_DIR, &upid)?
+            .into_iter()
+            .map(serde_json::to_value)
+            .collect::<Result<_, _>>()?,
+    };
+
+    Ok(paginate(list, start, limit, rpcenv))
+}
+
+#[api(
+    input: {
+        properties: {
+            upid: {
+                schema: UPID_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Provider requests of the task, oldest first.",
+        type: Array,
+        items: { type: CloudRequestTrace },
+    },
+    access: {
+        description: "Users can access their own tasks, or need Sys.Audit on /system/tasks.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Download the request trace of a cloud task.
+///
+/// Only tasks of jobs with 'request-trace' enabled record their requests.
+pub fn read_request_trace(
+    upid: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudRequestTrace>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let parsed: UPID = upid.parse()?;
+    check_task_access(&auth_id, &parsed)?;
+
+    load_request_trace(CLOUD_STATUS_DIR, &upid)
+}
+
+const TASK_SUBDIRS: SubdirMap = &[
+    ("log", &Router::new().get(&API_METHOD_READ_TASK_LOG)),
+    (
+        "requests",
+        &Router::new().get(&API_METHOD_READ_REQUEST_TRACE),
+    ),
+];
+
+const TASK_ROUTER: Router = Router::new()
+    .get(&list_subdirs_api_method!(TASK_SUBDIRS))
+    .subdirs(TASK_SUBDIRS);
+
+pub const ROUTER: Router = Router::new().match_all("upid", &TASK_ROUTER);
@@ -0,0 +1,193 @@
+//! Trash of deleted cloud snapshots
+
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_router::{
+    list_subdirs_api_method, Permission, Router, RpcEnvironment, RpcEnvironmentType, SubdirMap,
+};
+use proxmox_schema::{api, param_bail};
+use proxmox_sys::task_log;
+
+use pbs_api_types::{
+    Authid, CloudTrashEntry, CLOUD_RESTORE_SNAPSHOT_SCHEMA, CLOUD_TARGET_NAME_SCHEMA,
+    PRIV_CLOUD_AUDIT, PRIV_CLOUD_DELETE, UPID_SCHEMA,
+};
+use proxmox_rest_server::WorkerTask;
+
+use crate::api2::cloud::restore::parse_restore_snapshot;
+use crate::cloud::{
+    backend::open_target_backend,
+    catalog::CloudCatalog,
+    trash::{list_trash, purge_expired_trash, purge_trash, restore_from_trash},
+    CLOUD_STATUS_DIR,
+};
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Snapshots in the trash, most recently deleted first.",
+        type: Array,
+        items: { type: CloudTrashEntry },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List the deleted snapshots in the trash of a target.
+pub fn list(name: String) -> Result<Vec<CloudTrashEntry>, Error> {
+    let target = pbs_config::cloud::lookup_target(&name)?;
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &name)?;
+
+    Ok(list_trash(&catalog, target.config.trash_retention_secs()))
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            snapshot: {
+                schema: CLOUD_RESTORE_SNAPSHOT_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_DELETE, false),
+    },
+)]
+/// Restore a deleted snapshot from the trash.
+pub fn restore(
+    name: String,
+    snapshot: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let (store, ns, dir) = parse_restore_snapshot(&snapshot)?;
+
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-trash-restore",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (target, backend) = open_target_backend(&name)?;
+            restore_from_trash(
+                &*worker,
+                CLOUD_STATUS_DIR,
+                &target,
+                &backend,
+                &store,
+                &ns,
+                &dir,
+            )?;
+            task_log!(worker, "restored snapshot {} from the trash", snapshot);
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            snapshot: {
+                schema: CLOUD_RESTORE_SNAPSHOT_SCHEMA,
+                optional: true,
+            },
+            all: {
+                type: bool,
+                description: "Purge all snapshots, even if their trash retention did not expire.",
+                optional: true,
+                default: false,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_DELETE, false),
+    },
+)]
+/// Purge snapshots from the trash.
+///
+/// Without parameters, only snapshots whose trash retention expired are
+/// purged. Their chunks are removed by the next compaction.
+pub fn purge(
+    name: String,
+    snapshot: Option<String>,
+    all: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    if snapshot.is_some() && all {
+        param_bail!("all", "cannot be combined with 'snapshot'");
+    }
+    let snapshot = snapshot
+        .map(|snapshot| parse_restore_snapshot(&snapshot))
+        .transpose()?;
+
+    // early check that the target exists
+    pbs_config::cloud::lookup_target(&name)?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "cloud-trash-purge",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            let (target, backend) = open_target_backend(&name)?;
+            let purged = match snapshot {
+                Some((store, ns, dir)) => {
+                    purge_trash(&*worker, CLOUD_STATUS_DIR, &target, &backend, |trashed| {
+                        trashed.entry.matches(&store, &ns, &dir)
+                    })?
+                }
+                None if all => {
+                    purge_trash(&*worker, CLOUD_STATUS_DIR, &target, &backend, |_| true)?
+                }
+                None => purge_expired_trash(&*worker, CLOUD_STATUS_DIR, &target, &backend)?,
+            };
+            task_log!(worker, "purged {} snapshots from the trash", purged);
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str.into())
+}
+
+const TRASH_SUBDIRS: SubdirMap = &[
+    ("list", &Router::new().get(&API_METHOD_LIST)),
+    ("purge", &Router::new().post(&API_METHOD_PURGE)),
+    ("restore", &Router::new().post(&API_METHOD_RESTORE)),
+];
+
+const TRASH_ROUTER: Router = Router::new()
+    .get(&list_subdirs_api_method!(TRASH_SUBDIRS))
+    .subdirs(TRASH_SUBDIRS);
+
+pub const ROUTER: Router = Router::new().match_all("name", &TRASH_ROUTER);
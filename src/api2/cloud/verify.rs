@@ -0,0 +1,183 @@
+//! Cloud verification jobs
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_lang::try_block;
+use proxmox_router::{Permission, Router, RpcEnvironment, RpcEnvironmentType};
+use proxmox_schema::api;
+use proxmox_sys::task_log;
+
+use pbs_api_types::{
+    Authid, CloudJobScheduleStatus, CloudVerificationJobConfig, CloudVerificationJobStatus,
+    JOB_ID_SCHEMA, PRIV_CLOUD_AUDIT, PRIV_CLOUD_MODIFY, UPID_SCHEMA,
+};
+
+use proxmox_rest_server::WorkerTask;
+
+use crate::{
+    cloud::{
+        backend::open_target_backend,
+        content::CloudContentFilter,
+        job_pause::job_paused,
+        verify::{verify_snapshots, CloudVerifyOptions},
+        CLOUD_STATUS_DIR,
+    },
+    server::jobstate::{compute_schedule_status, Job, JobState},
+};
+
+const CLOUD_VERIFY_JOB_ROUTER: Router = Router::new().post(&API_METHOD_RUN_CLOUD_VERIFY_JOB);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_CLOUD_VERIFY_JOBS)
+    .match_all("id", &CLOUD_VERIFY_JOB_ROUTER);
+
+#[api(
+    returns: {
+        description: "List configured cloud verification jobs and their status",
+        type: Array,
+        items: { type: CloudVerificationJobStatus },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List all cloud verification jobs
+pub fn list_cloud_verify_jobs(
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudVerificationJobStatus>, Error> {
+    let (job_config, digest) = pbs_config::cloud_job::config()?;
+
+    let job_list: Vec<CloudVerificationJobConfig> = job_config.convert_to_typed_array("verify")?;
+
+    let mut list = Vec::new();
+
+    for job in job_list {
+        let last_state = JobState::load("cloud-verify-job", &job.id)
+            .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
+
+        let status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+
+        let mut status: CloudJobScheduleStatus = status.into();
+        status.paused = job_paused(CLOUD_STATUS_DIR, &job.id);
+        if job.disable || status.paused {
+            status.next_run = None;
+        }
+
+        list.push(CloudVerificationJobStatus {
+            config: job,
+            status,
+        });
+    }
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+pub fn do_cloud_verify_job(
+    mut job: Job,
+    config: CloudVerificationJobConfig,
+    auth_id: &Authid,
+    schedule: Option<String>,
+    to_stdout: bool,
+) -> Result<String, Error> {
+    let job_id = job.jobname().to_string();
+
+    let worker_type = job.jobtype().to_string();
+
+    let upid_str = WorkerTask::new_thread(
+        &worker_type,
+        Some(job_id.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            job.start(&worker.upid().to_string())?;
+
+            task_log!(worker, "Starting cloud verification job '{}'", job_id);
+            if let Some(event_str) = schedule {
+                task_log!(
+                    worker,
+                    "cloud verification task triggered by schedule '{}'",
+                    event_str
+                );
+            }
+
+            let job_result = try_block!({
+                let (target, backend) = open_target_backend(&config.target)?;
+                let filter = CloudContentFilter::recursive(
+                    &config.store,
+                    config.ns.as_ref(),
+                    config.max_depth,
+                );
+                let options = CloudVerifyOptions {
+                    ignore_verified: config.ignore_verified.unwrap_or(true),
+                    outdated_after: config.outdated_after,
+                };
+                let result = verify_snapshots(
+                    &*worker,
+                    CLOUD_STATUS_DIR,
+                    &target,
+                    &backend,
+                    &filter,
+                    &options,
+                    worker.upid(),
+                )?;
+                task_log!(
+                    worker,
+                    "verified {} snapshots, skipped {} recently verified",
+                    result.verified,
+                    result.skipped
+                );
+                if !result.failed.is_empty() {
+                    task_log!(worker, "failed to verify the following snapshots:");
+                    for name in result.failed.iter() {
+                        task_log!(worker, "\t{}", name);
+                    }
+                    bail!("verification failed - please check the log for details");
+                }
+                Ok(())
+            });
+
+            let status = worker.create_state(&job_result);
+
+            if let Err(err) = job.finish(status) {
+                eprintln!("could not finish job state for {}: {}", job.jobtype(), err);
+            }
+
+            job_result
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Runs a cloud verification job manually.
+pub fn run_cloud_verify_job(id: String, rpcenv: &mut dyn RpcEnvironment) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let (config, _digest) = pbs_config::cloud_job::config()?;
+    let verify_job: CloudVerificationJobConfig = config.lookup("verify", &id)?;
+
+    let job = Job::new("cloud-verify-job", &id)?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = do_cloud_verify_job(job, verify_job, &auth_id, None, to_stdout)?;
+
+    Ok(upid_str)
+}
@@ -14,6 +14,26 @@ use pbs_api_types::{
 
 use pbs_config::domains;
 
+use crate::cloud::openid_roles::parse_role_mappings;
+
+/// Check the role mappings of a realm before it is stored
+fn check_role_mapping(config: &OpenIdRealmConfig) -> Result<(), Error> {
+    let list = config.role_mapping.as_deref().unwrap_or_default();
+    let mappings = match parse_role_mappings(list) {
+        Ok(mappings) => mappings,
+        Err(err) => param_bail!("role-mapping", err),
+    };
+    for mapping in mappings {
+        if !pbs_config::acl::ROLE_NAMES.contains_key(mapping.role.as_str()) {
+            param_bail!("role-mapping", "unknown role '{}'", mapping.role);
+        }
+        if let Err(err) = pbs_config::acl::check_acl_path(&mapping.path) {
+            param_bail!("role-mapping", err);
+        }
+    }
+    Ok(())
+}
+
 #[api(
     input: {
         properties: {},
@@ -65,6 +85,8 @@ pub fn create_openid_realm(config: OpenIdRealmConfig) -> Result<(), Error> {
         param_bail!("realm", "realm '{}' already exists.", config.realm);
     }
 
+    check_role_mapping(&config)?;
+
     domains.set_data(&config.realm, "openid", &config)?;
 
     domains::save_config(&domains)?;
@@ -157,6 +179,10 @@ pub enum DeletableProperty {
     Prompt,
     /// Delete the acr_values property
     AcrValues,
+    /// Delete the roles-claim property
+    RolesClaim,
+    /// Delete the role-mapping property
+    RoleMapping,
 }
 
 #[api(
@@ -229,6 +255,12 @@ pub fn update_openid_realm(
                 DeletableProperty::AcrValues => {
                     config.acr_values = None;
                 }
+                DeletableProperty::RolesClaim => {
+                    config.roles_claim = None;
+                }
+                DeletableProperty::RoleMapping => {
+                    config.role_mapping = None;
+                }
             }
         }
     }
@@ -264,6 +296,14 @@ pub fn update_openid_realm(
     if update.acr_values.is_some() {
         config.acr_values = update.acr_values;
     }
+    if update.roles_claim.is_some() {
+        config.roles_claim = update.roles_claim;
+    }
+    if update.role_mapping.is_some() {
+        config.role_mapping = update.role_mapping;
+    }
+
+    check_role_mapping(&config)?;
 
     domains.set_data(&realm, "openid", &config)?;
 
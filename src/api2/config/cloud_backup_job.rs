@@ -1,21 +1,94 @@
 use ::serde::{Deserialize, Serialize};
-use anyhow::Error;
+use anyhow::{bail, Error};
 use hex::FromHex;
-use serde_json::Value;
 
 use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
 use proxmox_schema::{api, param_bail};
 
 use pbs_api_types::{
-    Authid, CloudBackupJobConfig, CloudBackupJobConfigUpdater, JOB_ID_SCHEMA, PRIV_CLOUD_AUDIT,
-    PRIV_CLOUD_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA,
+    check_group_filters, Authid, CloudBackupJobConfig, CloudBackupJobConfigUpdater,
+    CloudBackupJobSetup, CloudJobHooks, CLOUD_CONFIG_VALIDATE_SCHEMA, CLOUD_TAG_SCHEMA,
+    JOB_ID_SCHEMA, PRIV_CLOUD_AUDIT, PRIV_CLOUD_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA,
 };
 
 use pbs_config::CachedUserInfo;
 
+use crate::cloud::backend::check_job_capabilities;
+use crate::cloud::config_history::{record_config_change, save_section, section_data};
+use crate::cloud::job_chain::{chained_jobs, check_run_after};
+use crate::cloud::job_pause::resume_job;
+use crate::cloud::job_retry::remove_job_retries;
+use crate::cloud::job_window::JobWindow;
+use crate::cloud::mapping::{check_mapping_prefix, job_mapping, job_target};
+use crate::cloud::CLOUD_STATUS_DIR;
+
+/// Checks done before a job setup is stored
+pub(crate) fn check_job_setup(setup: &CloudBackupJobSetup) -> Result<(), Error> {
+    let (datastore_config, _digest) = pbs_config::datastore::config()?;
+    if !datastore_config.sections.contains_key(&setup.store) {
+        param_bail!("store", "datastore '{}' does not exist.", setup.store);
+    }
+    let target = match job_target(setup) {
+        Ok(target) => target,
+        Err(err) => param_bail!("target", err),
+    };
+    let target = match pbs_config::cloud::lookup_target(&target) {
+        Ok(target) => target,
+        Err(err) => param_bail!("target", err),
+    };
+    if let Some(mapping) = job_mapping(setup)? {
+        if let Err(err) = check_mapping_prefix(&mapping, &target) {
+            param_bail!("target", err);
+        }
+    }
+    if let Some(ref filters) = setup.group_filter {
+        if let Err(err) = check_group_filters(filters) {
+            param_bail!("group-filter", err);
+        }
+    }
+    match JobWindow::from_job_setup(setup) {
+        Ok(Some(window)) => {
+            if let Err(err) = window.next_open(proxmox_time::epoch_i64()) {
+                param_bail!("blackout-window", err);
+            }
+        }
+        Ok(None) => {}
+        Err(err) => param_bail!("allowed-window", err),
+    }
+    check_job_capabilities(setup)
+}
+
+/// Checks done before job hooks are stored
+///
+/// Hooks run commands on the host, so only superusers may change them.
+pub(crate) fn check_job_hooks(
+    auth_id: &Authid,
+    old: Option<&CloudJobHooks>,
+    hooks: &CloudJobHooks,
+) -> Result<(), Error> {
+    let changed = match old {
+        Some(old) => old != hooks,
+        None => *hooks != CloudJobHooks::default(),
+    };
+    if changed && !CachedUserInfo::new()?.is_superuser(auth_id) {
+        http_bail!(FORBIDDEN, "job hooks can only be configured by superusers");
+    }
+    if let Some(ref user) = hooks.hook_user {
+        if nix::unistd::User::from_name(user)?.is_none() {
+            param_bail!("hook-user", "user '{}' does not exist.", user);
+        }
+    }
+    Ok(())
+}
+
 #[api(
     input: {
-        properties: {},
+        properties: {
+            tag: {
+                schema: CLOUD_TAG_SCHEMA,
+                optional: true,
+            },
+        },
     },
     returns: {
         description: "List configured jobs.",
@@ -29,7 +102,7 @@ use pbs_config::CachedUserInfo;
 )]
 /// List all cloud backup jobs
 pub fn list_cloud_backup_jobs(
-    _param: Value,
+    tag: Option<String>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Vec<CloudBackupJobConfig>, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
@@ -43,7 +116,7 @@ pub fn list_cloud_backup_jobs(
         .into_iter()
         .filter(|job| {
             let privs = user_info.lookup_privs(&auth_id, &["cloud", "job", &job.id]);
-            privs & PRIV_CLOUD_AUDIT != 0
+            privs & PRIV_CLOUD_AUDIT != 0 && tag.as_deref().map_or(true, |tag| job.has_tag(tag))
         })
         .collect();
 
@@ -60,17 +133,30 @@ pub fn list_cloud_backup_jobs(
                 type: CloudBackupJobConfig,
                 flatten: true,
             },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
         },
     },
+    returns: {
+        type: CloudBackupJobConfig,
+        optional: true,
+    },
     access: {
         permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
     },
 )]
 /// Create a new cloud backup job.
+///
+/// With 'validate' the job is only checked and returned, but not saved.
 pub fn create_cloud_backup_job(
     job: CloudBackupJobConfig,
-    _rpcenv: &mut dyn RpcEnvironment,
-) -> Result<(), Error> {
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudBackupJobConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
     let _lock = pbs_config::cloud_job::lock()?;
 
     let (mut config, _digest) = pbs_config::cloud_job::config()?;
@@ -79,13 +165,36 @@ pub fn create_cloud_backup_job(
         param_bail!("id", "job '{}' already exists.", job.id);
     }
 
-    config.set_data(&job.id, "backup", &job)?;
+    check_job_setup(&job.setup)?;
+    check_job_hooks(&auth_id, None, &job.hooks)?;
+    if let Some(ref run_after) = job.run_after {
+        if let Err(err) = check_run_after(&config, &job.id, run_after) {
+            param_bail!("run-after", err);
+        }
+    }
+
+    if !save_section(
+        &mut config,
+        "backup",
+        &job.id,
+        &job,
+        validate,
+        pbs_config::cloud_job::save_config,
+    )? {
+        return Ok(Some(job));
+    }
 
-    pbs_config::cloud_job::save_config(&config)?;
+    record_config_change(
+        &auth_id,
+        "backup",
+        &job.id,
+        None,
+        section_data(&config, &job.id).as_ref(),
+    );
 
     crate::server::jobstate::create_state_file("cloud-backup-job", &job.id)?;
 
-    Ok(())
+    Ok(None)
 }
 
 #[api(
@@ -124,10 +233,16 @@ pub enum DeletableProperty {
     Comment,
     /// Delete the job schedule.
     Schedule,
-    /// Delete the eject-media property
-    EjectMedia,
-    /// Delete the export-media-set property
-    ExportMediaSet,
+    /// Delete the 'splay' property
+    Splay,
+    /// Delete the 'retry-on-failure' property
+    RetryOnFailure,
+    /// Delete the 'retry-delay' property
+    RetryDelay,
+    /// Delete the 'run-after' property
+    RunAfter,
+    /// Delete the 'target' property (use the cloud mapping)
+    Target,
     /// Delete the 'latest-only' property
     LatestOnly,
     /// Delete the 'notify-user' property
@@ -138,6 +253,50 @@ pub enum DeletableProperty {
     MaxDepth,
     /// Delete the 'ns' property
     Ns,
+    /// Delete the 'max-chain-length' property
+    MaxChainLength,
+    /// Delete the 'storage-class' property
+    StorageClass,
+    /// Delete the 'retention-lock' property
+    RetentionLock,
+    /// Delete the 'transfer-last' property
+    TransferLast,
+    /// Delete the 'object-tag' property
+    ObjectTag,
+    /// Delete the 'allowed-window' property
+    AllowedWindow,
+    /// Delete the 'blackout-window' property
+    BlackoutWindow,
+    /// Delete the 'window-action' property
+    WindowAction,
+    /// Delete the 'zfs-change-detection' property
+    ZfsChangeDetection,
+    /// Delete the 'local-io-latency' property
+    LocalIoLatency,
+    /// Delete the 'local-io-min-rate' property
+    LocalIoMinRate,
+    /// Delete the 'order' property
+    Order,
+    /// Delete the 'on-error' property
+    OnError,
+    /// Delete the 'request-trace' property
+    RequestTrace,
+    /// Delete the 'template' property (detach the job from its template)
+    Template,
+    /// Delete the 'pre-hook' property
+    PreHook,
+    /// Delete the 'post-hook' property
+    PostHook,
+    /// Delete the 'hook-user' property
+    HookUser,
+    /// Delete the 'hook-timeout' property
+    HookTimeout,
+    /// Delete the 'hook-failure' property
+    HookFailure,
+    /// Unset the disable flag.
+    Disable,
+    /// Delete the 'tags' property
+    Tags,
 }
 
 #[api(
@@ -163,24 +322,39 @@ pub enum DeletableProperty {
                 optional: true,
                 schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
             },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
         },
     },
+    returns: {
+        type: CloudBackupJobConfig,
+        optional: true,
+    },
     access: {
         permission: &Permission::Privilege(&["cloud", "job", "{id}"], PRIV_CLOUD_MODIFY, false),
     },
 )]
 /// Update the cloud backup job
+///
+/// With 'validate' the updated job is only checked and returned, but not saved.
 pub fn update_cloud_backup_job(
     id: String,
     update: CloudBackupJobConfigUpdater,
     delete: Option<Vec<DeletableProperty>>,
     digest: Option<String>,
-) -> Result<(), Error> {
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudBackupJobConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
     let _lock = pbs_config::cloud_job::lock()?;
 
     let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
 
     let mut data: CloudBackupJobConfig = config.lookup("backup", &id)?;
+    let old_hooks = data.hooks.clone();
 
     if let Some(ref digest) = digest {
         let digest = <[u8; 32]>::from_hex(digest)?;
@@ -190,11 +364,8 @@ pub fn update_cloud_backup_job(
     if let Some(delete) = delete {
         for delete_prop in delete {
             match delete_prop {
-                DeletableProperty::EjectMedia => {
-                    data.setup.eject_media = None;
-                }
-                DeletableProperty::ExportMediaSet => {
-                    data.setup.export_media_set = None;
+                DeletableProperty::Target => {
+                    data.setup.target = None;
                 }
                 DeletableProperty::LatestOnly => {
                     data.setup.latest_only = None;
@@ -205,6 +376,18 @@ pub fn update_cloud_backup_job(
                 DeletableProperty::Schedule => {
                     data.schedule = None;
                 }
+                DeletableProperty::Splay => {
+                    data.splay = None;
+                }
+                DeletableProperty::RetryOnFailure => {
+                    data.retry_on_failure = None;
+                }
+                DeletableProperty::RetryDelay => {
+                    data.retry_delay = None;
+                }
+                DeletableProperty::RunAfter => {
+                    data.run_after = None;
+                }
                 DeletableProperty::Comment => {
                     data.comment = None;
                 }
@@ -217,6 +400,72 @@ pub fn update_cloud_backup_job(
                 DeletableProperty::Ns => {
                     data.setup.ns = None;
                 }
+                DeletableProperty::MaxChainLength => {
+                    data.setup.max_chain_length = None;
+                }
+                DeletableProperty::StorageClass => {
+                    data.setup.storage_class = None;
+                }
+                DeletableProperty::RetentionLock => {
+                    data.setup.retention_lock = None;
+                }
+                DeletableProperty::TransferLast => {
+                    data.setup.transfer_last = None;
+                }
+                DeletableProperty::ObjectTag => {
+                    data.setup.object_tag = None;
+                }
+                DeletableProperty::AllowedWindow => {
+                    data.setup.allowed_window = None;
+                }
+                DeletableProperty::BlackoutWindow => {
+                    data.setup.blackout_window = None;
+                }
+                DeletableProperty::WindowAction => {
+                    data.setup.window_action = None;
+                }
+                DeletableProperty::ZfsChangeDetection => {
+                    data.setup.zfs_change_detection = None;
+                }
+                DeletableProperty::LocalIoLatency => {
+                    data.setup.local_io_latency = None;
+                }
+                DeletableProperty::LocalIoMinRate => {
+                    data.setup.local_io_min_rate = None;
+                }
+                DeletableProperty::Order => {
+                    data.setup.order = None;
+                }
+                DeletableProperty::OnError => {
+                    data.setup.on_error = None;
+                }
+                DeletableProperty::RequestTrace => {
+                    data.setup.request_trace = None;
+                }
+                DeletableProperty::Template => {
+                    data.template = None;
+                }
+                DeletableProperty::PreHook => {
+                    data.hooks.pre_hook = None;
+                }
+                DeletableProperty::PostHook => {
+                    data.hooks.post_hook = None;
+                }
+                DeletableProperty::HookUser => {
+                    data.hooks.hook_user = None;
+                }
+                DeletableProperty::HookTimeout => {
+                    data.hooks.hook_timeout = None;
+                }
+                DeletableProperty::HookFailure => {
+                    data.hooks.hook_failure = None;
+                }
+                DeletableProperty::Disable => {
+                    data.disable = false;
+                }
+                DeletableProperty::Tags => {
+                    data.tags = None;
+                }
             }
         }
     }
@@ -224,19 +473,10 @@ pub fn update_cloud_backup_job(
     if let Some(store) = update.setup.store {
         data.setup.store = store;
     }
-    if let Some(pool) = update.setup.pool {
-        data.setup.pool = pool;
-    }
-    if let Some(drive) = update.setup.drive {
-        data.setup.drive = drive;
+    if update.setup.target.is_some() {
+        data.setup.target = update.setup.target;
     }
 
-    if update.setup.eject_media.is_some() {
-        data.setup.eject_media = update.setup.eject_media;
-    };
-    if update.setup.export_media_set.is_some() {
-        data.setup.export_media_set = update.setup.export_media_set;
-    }
     if update.setup.latest_only.is_some() {
         data.setup.latest_only = update.setup.latest_only;
     }
@@ -252,11 +492,89 @@ pub fn update_cloud_backup_job(
     if update.setup.max_depth.is_some() {
         data.setup.max_depth = update.setup.max_depth;
     }
+    if update.setup.max_chain_length.is_some() {
+        data.setup.max_chain_length = update.setup.max_chain_length;
+    }
+    if update.setup.storage_class.is_some() {
+        data.setup.storage_class = update.setup.storage_class;
+    }
+    if update.setup.retention_lock.is_some() {
+        data.setup.retention_lock = update.setup.retention_lock;
+    }
+    if update.setup.transfer_last.is_some() {
+        data.setup.transfer_last = update.setup.transfer_last;
+    }
+    if update.setup.object_tag.is_some() {
+        data.setup.object_tag = update.setup.object_tag;
+    }
+    if update.setup.allowed_window.is_some() {
+        data.setup.allowed_window = update.setup.allowed_window;
+    }
+    if update.setup.blackout_window.is_some() {
+        data.setup.blackout_window = update.setup.blackout_window;
+    }
+    if update.setup.window_action.is_some() {
+        data.setup.window_action = update.setup.window_action;
+    }
+    if update.setup.zfs_change_detection.is_some() {
+        data.setup.zfs_change_detection = update.setup.zfs_change_detection;
+    }
+    if update.setup.local_io_latency.is_some() {
+        data.setup.local_io_latency = update.setup.local_io_latency;
+    }
+    if update.setup.local_io_min_rate.is_some() {
+        data.setup.local_io_min_rate = update.setup.local_io_min_rate;
+    }
+    if update.setup.order.is_some() {
+        data.setup.order = update.setup.order;
+    }
+    if update.setup.on_error.is_some() {
+        data.setup.on_error = update.setup.on_error;
+    }
+    if update.setup.request_trace.is_some() {
+        data.setup.request_trace = update.setup.request_trace;
+    }
+
+    check_job_setup(&data.setup)?;
+
+    if update.hooks.pre_hook.is_some() {
+        data.hooks.pre_hook = update.hooks.pre_hook;
+    }
+    if update.hooks.post_hook.is_some() {
+        data.hooks.post_hook = update.hooks.post_hook;
+    }
+    if update.hooks.hook_user.is_some() {
+        data.hooks.hook_user = update.hooks.hook_user;
+    }
+    if update.hooks.hook_timeout.is_some() {
+        data.hooks.hook_timeout = update.hooks.hook_timeout;
+    }
+    if update.hooks.hook_failure.is_some() {
+        data.hooks.hook_failure = update.hooks.hook_failure;
+    }
+    check_job_hooks(&auth_id, Some(&old_hooks), &data.hooks)?;
 
     let schedule_changed = data.schedule != update.schedule;
     if update.schedule.is_some() {
         data.schedule = update.schedule;
     }
+    if update.splay.is_some() {
+        data.splay = update.splay;
+    }
+    if update.retry_on_failure.is_some() {
+        data.retry_on_failure = update.retry_on_failure;
+    }
+    if update.retry_delay.is_some() {
+        data.retry_delay = update.retry_delay;
+    }
+    if update.run_after.is_some() {
+        data.run_after = update.run_after;
+    }
+    if let Some(ref run_after) = data.run_after {
+        if let Err(err) = check_run_after(&config, &data.id, run_after) {
+            param_bail!("run-after", err);
+        }
+    }
 
     if let Some(comment) = update.comment {
         let comment = comment.trim();
@@ -267,15 +585,39 @@ pub fn update_cloud_backup_job(
         }
     }
 
-    config.set_data(&id, "backup", &data)?;
+    if let Some(value) = update.disable {
+        data.disable = value;
+    }
+    if update.tags.is_some() {
+        data.tags = update.tags;
+    }
 
-    pbs_config::cloud_job::save_config(&config)?;
+    let old = section_data(&config, &id);
+
+    if !save_section(
+        &mut config,
+        "backup",
+        &id,
+        &data,
+        validate,
+        pbs_config::cloud_job::save_config,
+    )? {
+        return Ok(Some(data));
+    }
+
+    record_config_change(
+        &auth_id,
+        "backup",
+        &id,
+        old.as_ref(),
+        section_data(&config, &id).as_ref(),
+    );
 
     if schedule_changed {
         crate::server::jobstate::update_job_last_run_time("cloud-backup-job", &id)?;
     }
 
-    Ok(())
+    Ok(None)
 }
 
 #[api(
@@ -299,8 +641,10 @@ pub fn update_cloud_backup_job(
 pub fn delete_cloud_backup_job(
     id: String,
     digest: Option<String>,
-    _rpcenv: &mut dyn RpcEnvironment,
+    rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
     let _lock = pbs_config::cloud_job::lock()?;
 
     let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
@@ -310,10 +654,17 @@ pub fn delete_cloud_backup_job(
         crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
     }
 
-    match config.lookup::<CloudBackupJobConfig>("backup", &id) {
-        Ok(_job) => {
-            config.sections.remove(&id);
-        }
+    let chained = chained_jobs(&config, &id);
+    if !chained.is_empty() {
+        bail!(
+            "job '{}' is the 'run-after' trigger of {}",
+            id,
+            chained.join(", ")
+        );
+    }
+
+    let old = match config.lookup::<CloudBackupJobConfig>("backup", &id) {
+        Ok(_job) => config.sections.remove(&id).map(|(_, data)| data),
         Err(_) => {
             http_bail!(NOT_FOUND, "job '{}' does not exist.", id)
         }
@@ -321,7 +672,11 @@ pub fn delete_cloud_backup_job(
 
     pbs_config::cloud_job::save_config(&config)?;
 
+    record_config_change(&auth_id, "backup", &id, old.as_ref(), None);
+
     crate::server::jobstate::remove_state_file("cloud-backup-job", &id)?;
+    resume_job(CLOUD_STATUS_DIR, &id)?;
+    remove_job_retries(CLOUD_STATUS_DIR, "cloud-backup-job", &id)?;
 
     Ok(())
 }
@@ -0,0 +1,700 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::Error;
+use hex::FromHex;
+use serde_json::Value;
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+use proxmox_section_config::SectionConfigData;
+
+use pbs_api_types::{
+    check_group_filters, Authid, BackupNamespace, CloudBackupJobConfig, CloudBackupJobTemplate,
+    CloudBackupJobTemplateUpdater, CLOUD_CONFIG_VALIDATE_SCHEMA, CLOUD_JOB_TEMPLATE_ID_SCHEMA,
+    CLOUD_TARGET_NAME_SCHEMA, DATASTORE_SCHEMA, JOB_ID_SCHEMA, PRIV_CLOUD_AUDIT, PRIV_CLOUD_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+
+use crate::cloud::config_history::{record_config_change, save_section, section_data};
+
+use super::cloud_backup_job::check_job_setup;
+
+fn check_template(template: &CloudBackupJobTemplate) -> Result<(), Error> {
+    if let Some(ref filters) = template.group_filter {
+        if let Err(err) = check_group_filters(filters) {
+            param_bail!("group-filter", err);
+        }
+    }
+    // targets with placeholders are checked when the template is instantiated
+    if let Some(ref target) = template.target {
+        if !target.contains('{') {
+            if let Err(err) = pbs_config::cloud::lookup_target(target) {
+                param_bail!("target", err);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {},
+    },
+    returns: {
+        description: "List configured job templates.",
+        type: Array,
+        items: { type: CloudBackupJobTemplate },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List all cloud backup job templates
+pub fn list_cloud_backup_job_templates(
+    _param: Value,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudBackupJobTemplate>, Error> {
+    let (config, digest) = pbs_config::cloud_job::config()?;
+
+    let list = config.convert_to_typed_array::<CloudBackupJobTemplate>("template")?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            template: {
+                type: CloudBackupJobTemplate,
+                flatten: true,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudBackupJobTemplate,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Create a new cloud backup job template.
+///
+/// With 'validate' the template is only checked and returned, but not saved.
+pub fn create_cloud_backup_job_template(
+    template: CloudBackupJobTemplate,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudBackupJobTemplate>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, _digest) = pbs_config::cloud_job::config()?;
+
+    if config.sections.get(&template.id).is_some() {
+        param_bail!("id", "job or template '{}' already exists.", template.id);
+    }
+
+    check_template(&template)?;
+
+    if !save_section(
+        &mut config,
+        "template",
+        &template.id,
+        &template,
+        validate,
+        pbs_config::cloud_job::save_config,
+    )? {
+        return Ok(Some(template));
+    }
+
+    record_config_change(
+        &auth_id,
+        "template",
+        &template.id,
+        None,
+        section_data(&config, &template.id).as_ref(),
+    );
+
+    Ok(None)
+}
+
+#[api(
+    input: {
+        properties: {
+            id: {
+                schema: CLOUD_JOB_TEMPLATE_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: CloudBackupJobTemplate },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Read a cloud backup job template.
+pub fn read_cloud_backup_job_template(
+    id: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<CloudBackupJobTemplate, Error> {
+    let (config, digest) = pbs_config::cloud_job::config()?;
+
+    let template = config.lookup("template", &id)?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(template)
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the comment property.
+    Comment,
+    /// Delete the job schedule.
+    Schedule,
+    /// Delete the 'splay' property
+    Splay,
+    /// Delete the 'retry-on-failure' property
+    RetryOnFailure,
+    /// Delete the 'retry-delay' property
+    RetryDelay,
+    /// Delete the 'job-id' property
+    JobId,
+    /// Delete the 'target' property
+    Target,
+    /// Delete the 'ns' property
+    Ns,
+    /// Delete the 'latest-only' property
+    LatestOnly,
+    /// Delete the 'notify-user' property
+    NotifyUser,
+    /// Delete the 'group_filter' property
+    GroupFilter,
+    /// Delete the 'max-depth' property
+    MaxDepth,
+    /// Delete the 'max-chain-length' property
+    MaxChainLength,
+    /// Delete the 'storage-class' property
+    StorageClass,
+    /// Delete the 'retention-lock' property
+    RetentionLock,
+    /// Delete the 'transfer-last' property
+    TransferLast,
+    /// Delete the 'object-tag' property
+    ObjectTag,
+    /// Delete the 'allowed-window' property
+    AllowedWindow,
+    /// Delete the 'blackout-window' property
+    BlackoutWindow,
+    /// Delete the 'window-action' property
+    WindowAction,
+    /// Delete the 'zfs-change-detection' property
+    ZfsChangeDetection,
+    /// Delete the 'local-io-latency' property
+    LocalIoLatency,
+    /// Delete the 'local-io-min-rate' property
+    LocalIoMinRate,
+    /// Delete the 'order' property
+    Order,
+    /// Delete the 'on-error' property
+    OnError,
+    /// Delete the 'request-trace' property
+    RequestTrace,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: CLOUD_JOB_TEMPLATE_ID_SCHEMA,
+            },
+            update: {
+                type: CloudBackupJobTemplateUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudBackupJobTemplate,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Update a cloud backup job template
+///
+/// Existing jobs are not changed, instantiate the template again with
+/// 'replace' to update them. With 'validate' the updated template is only
+/// checked and returned, but not saved.
+pub fn update_cloud_backup_job_template(
+    id: String,
+    update: CloudBackupJobTemplateUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudBackupJobTemplate>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
+
+    let mut data: CloudBackupJobTemplate = config.lookup("template", &id)?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Comment => {
+                    data.comment = None;
+                }
+                DeletableProperty::Schedule => {
+                    data.schedule = None;
+                }
+                DeletableProperty::Splay => {
+                    data.splay = None;
+                }
+                DeletableProperty::RetryOnFailure => {
+                    data.retry_on_failure = None;
+                }
+                DeletableProperty::RetryDelay => {
+                    data.retry_delay = None;
+                }
+                DeletableProperty::JobId => {
+                    data.job_id = None;
+                }
+                DeletableProperty::Target => {
+                    data.target = None;
+                }
+                DeletableProperty::Ns => {
+                    data.ns = None;
+                }
+                DeletableProperty::LatestOnly => {
+                    data.latest_only = None;
+                }
+                DeletableProperty::NotifyUser => {
+                    data.notify_user = None;
+                }
+                DeletableProperty::GroupFilter => {
+                    data.group_filter = None;
+                }
+                DeletableProperty::MaxDepth => {
+                    data.max_depth = None;
+                }
+                DeletableProperty::MaxChainLength => {
+                    data.max_chain_length = None;
+                }
+                DeletableProperty::StorageClass => {
+                    data.storage_class = None;
+                }
+                DeletableProperty::RetentionLock => {
+                    data.retention_lock = None;
+                }
+                DeletableProperty::TransferLast => {
+                    data.transfer_last = None;
+                }
+                DeletableProperty::ObjectTag => {
+                    data.object_tag = None;
+                }
+                DeletableProperty::AllowedWindow => {
+                    data.allowed_window = None;
+                }
+                DeletableProperty::BlackoutWindow => {
+                    data.blackout_window = None;
+                }
+                DeletableProperty::WindowAction => {
+                    data.window_action = None;
+                }
+                DeletableProperty::ZfsChangeDetection => {
+                    data.zfs_change_detection = None;
+                }
+                DeletableProperty::LocalIoLatency => {
+                    data.local_io_latency = None;
+                }
+                DeletableProperty::LocalIoMinRate => {
+                    data.local_io_min_rate = None;
+                }
+                DeletableProperty::Order => {
+                    data.order = None;
+                }
+                DeletableProperty::OnError => {
+                    data.on_error = None;
+                }
+                DeletableProperty::RequestTrace => {
+                    data.request_trace = None;
+                }
+            }
+        }
+    }
+
+    if update.job_id.is_some() {
+        data.job_id = update.job_id;
+    }
+    if update.target.is_some() {
+        data.target = update.target;
+    }
+    if update.ns.is_some() {
+        data.ns = update.ns;
+    }
+    if update.latest_only.is_some() {
+        data.latest_only = update.latest_only;
+    }
+    if update.notify_user.is_some() {
+        data.notify_user = update.notify_user;
+    }
+    if update.group_filter.is_some() {
+        data.group_filter = update.group_filter;
+    }
+    if update.max_depth.is_some() {
+        data.max_depth = update.max_depth;
+    }
+    if update.max_chain_length.is_some() {
+        data.max_chain_length = update.max_chain_length;
+    }
+    if update.storage_class.is_some() {
+        data.storage_class = update.storage_class;
+    }
+    if update.retention_lock.is_some() {
+        data.retention_lock = update.retention_lock;
+    }
+    if update.transfer_last.is_some() {
+        data.transfer_last = update.transfer_last;
+    }
+    if update.object_tag.is_some() {
+        data.object_tag = update.object_tag;
+    }
+    if update.allowed_window.is_some() {
+        data.allowed_window = update.allowed_window;
+    }
+    if update.blackout_window.is_some() {
+        data.blackout_window = update.blackout_window;
+    }
+    if update.window_action.is_some() {
+        data.window_action = update.window_action;
+    }
+    if update.zfs_change_detection.is_some() {
+        data.zfs_change_detection = update.zfs_change_detection;
+    }
+    if update.local_io_latency.is_some() {
+        data.local_io_latency = update.local_io_latency;
+    }
+    if update.local_io_min_rate.is_some() {
+        data.local_io_min_rate = update.local_io_min_rate;
+    }
+    if update.order.is_some() {
+        data.order = update.order;
+    }
+    if update.on_error.is_some() {
+        data.on_error = update.on_error;
+    }
+    if update.request_trace.is_some() {
+        data.request_trace = update.request_trace;
+    }
+    if update.schedule.is_some() {
+        data.schedule = update.schedule;
+    }
+    if update.splay.is_some() {
+        data.splay = update.splay;
+    }
+    if update.retry_on_failure.is_some() {
+        data.retry_on_failure = update.retry_on_failure;
+    }
+    if update.retry_delay.is_some() {
+        data.retry_delay = update.retry_delay;
+    }
+    if let Some(comment) = update.comment {
+        let comment = comment.trim();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment.to_string());
+        }
+    }
+
+    check_template(&data)?;
+
+    let old = section_data(&config, &id);
+
+    if !save_section(
+        &mut config,
+        "template",
+        &id,
+        &data,
+        validate,
+        pbs_config::cloud_job::save_config,
+    )? {
+        return Ok(Some(data));
+    }
+
+    record_config_change(
+        &auth_id,
+        "template",
+        &id,
+        old.as_ref(),
+        section_data(&config, &id).as_ref(),
+    );
+
+    Ok(None)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: CLOUD_JOB_TEMPLATE_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Remove a cloud backup job template
+///
+/// Jobs created from the template are kept.
+pub fn delete_cloud_backup_job_template(
+    id: String,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let old = match config.lookup::<CloudBackupJobTemplate>("template", &id) {
+        Ok(_template) => config.sections.remove(&id).map(|(_, data)| data),
+        Err(_) => {
+            http_bail!(NOT_FOUND, "template '{}' does not exist.", id)
+        }
+    };
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(&auth_id, "template", &id, old.as_ref(), None);
+
+    Ok(())
+}
+
+/// Jobs created from template `id` for each of `stores`, with whether
+/// they replace an existing job
+///
+/// `digest` is compared with `expected_digest`, the digest of `config`,
+/// so that jobs changed since they were reviewed are not replaced.
+#[allow(clippy::too_many_arguments)]
+pub fn template_jobs(
+    config: &SectionConfigData,
+    expected_digest: &[u8; 32],
+    digest: Option<&str>,
+    id: &str,
+    stores: &[String],
+    target: Option<&str>,
+    ns: Option<&BackupNamespace>,
+    replace: bool,
+) -> Result<Vec<(CloudBackupJobConfig, bool)>, Error> {
+    if let Some(digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, expected_digest)?;
+    }
+
+    let template: CloudBackupJobTemplate = config.lookup("template", id)?;
+
+    let mut jobs: Vec<(CloudBackupJobConfig, bool)> = Vec::new();
+    for store in stores.iter() {
+        let job = match template.instantiate(store, target, ns) {
+            Ok(job) => job,
+            Err(err) => param_bail!("store", "datastore '{}': {}", store, err),
+        };
+
+        if jobs.iter().any(|(other, _)| other.id == job.id) {
+            param_bail!("store", "job id '{}' would be used twice", job.id);
+        }
+
+        let exists = match config.sections.get(&job.id) {
+            Some((section_type, _)) if section_type == "backup" => {
+                let existing: CloudBackupJobConfig = config.lookup("backup", &job.id)?;
+                if !replace || existing.template.as_deref() != Some(template.id.as_str()) {
+                    param_bail!("store", "job '{}' already exists.", job.id);
+                }
+                true
+            }
+            Some(_) => param_bail!("store", "'{}' is used by a template.", job.id),
+            None => false,
+        };
+
+        jobs.push((job, exists));
+    }
+
+    Ok(jobs)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: CLOUD_JOB_TEMPLATE_ID_SCHEMA,
+            },
+            store: {
+                description: "Create a job for each of these datastores.",
+                type: Array,
+                items: {
+                    schema: DATASTORE_SCHEMA,
+                },
+            },
+            target: {
+                description: "Value of the '{target}' placeholder.",
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            replace: {
+                description: "Replace jobs previously created from this template.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "IDs of the created jobs (or the jobs which would be created with 'validate').",
+        type: Array,
+        items: { schema: JOB_ID_SCHEMA },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Create cloud backup jobs from a template.
+///
+/// Either all jobs are created, or none. With 'replace', pass the digest
+/// of the job config the jobs were reviewed with, so that concurrent
+/// changes to them are not overwritten.
+#[allow(clippy::too_many_arguments)]
+pub fn instantiate_cloud_backup_job_template(
+    id: String,
+    store: Vec<String>,
+    target: Option<String>,
+    ns: Option<BackupNamespace>,
+    replace: bool,
+    digest: Option<String>,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<String>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
+
+    let jobs = template_jobs(
+        &config,
+        &expected_digest,
+        digest.as_deref(),
+        &id,
+        &store,
+        target.as_deref(),
+        ns.as_ref(),
+        replace,
+    )?;
+
+    for (job, _exists) in jobs.iter() {
+        check_job_setup(&job.setup)?;
+    }
+
+    if validate {
+        return Ok(jobs.into_iter().map(|(job, _exists)| job.id).collect());
+    }
+
+    let mut changes = Vec::new();
+    for (job, _exists) in jobs.iter() {
+        let old = section_data(&config, &job.id);
+        config.set_data(&job.id, "backup", job)?;
+        changes.push((old, section_data(&config, &job.id)));
+    }
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    for ((job, _exists), (old, new)) in jobs.iter().zip(changes.iter()) {
+        record_config_change(&auth_id, "backup", &job.id, old.as_ref(), new.as_ref());
+    }
+
+    let mut list = Vec::new();
+    for (job, exists) in jobs {
+        if !exists {
+            crate::server::jobstate::create_state_file("cloud-backup-job", &job.id)?;
+        }
+        list.push(job.id);
+    }
+
+    Ok(list)
+}
+
+const INSTANTIATE_ROUTER: Router =
+    Router::new().post(&API_METHOD_INSTANTIATE_CLOUD_BACKUP_JOB_TEMPLATE);
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_CLOUD_BACKUP_JOB_TEMPLATE)
+    .put(&API_METHOD_UPDATE_CLOUD_BACKUP_JOB_TEMPLATE)
+    .delete(&API_METHOD_DELETE_CLOUD_BACKUP_JOB_TEMPLATE)
+    .subdirs(&[("instantiate", &INSTANTIATE_ROUTER)]);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_CLOUD_BACKUP_JOB_TEMPLATES)
+    .post(&API_METHOD_CREATE_CLOUD_BACKUP_JOB_TEMPLATE)
+    .match_all("id", &ITEM_ROUTER);
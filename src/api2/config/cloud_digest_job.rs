@@ -0,0 +1,353 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::Error;
+use hex::FromHex;
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    Authid, CloudDigestJobConfig, CloudDigestJobConfigUpdater, CLOUD_CONFIG_VALIDATE_SCHEMA,
+    JOB_ID_SCHEMA, PRIV_CLOUD_AUDIT, PRIV_CLOUD_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+
+use crate::cloud::config_history::{record_config_change, section_data};
+use crate::cloud::digest::remove_digest_state;
+use crate::cloud::job_pause::resume_job;
+use crate::cloud::CLOUD_STATUS_DIR;
+
+#[api(
+    returns: {
+        description: "List configured jobs.",
+        type: Array,
+        items: { type: CloudDigestJobConfig },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List all cloud digest jobs
+pub fn list_cloud_digest_jobs(
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudDigestJobConfig>, Error> {
+    let (config, digest) = pbs_config::cloud_job::config()?;
+
+    let list = config.convert_to_typed_array::<CloudDigestJobConfig>("digest")?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            job: {
+                type: CloudDigestJobConfig,
+                flatten: true,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudDigestJobConfig,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Create a new cloud digest job.
+///
+/// With 'validate' the job is only checked and returned, but not saved.
+pub fn create_cloud_digest_job(
+    job: CloudDigestJobConfig,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudDigestJobConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, _digest) = pbs_config::cloud_job::config()?;
+
+    if config.sections.get(&job.id).is_some() {
+        param_bail!("id", "job '{}' already exists.", job.id);
+    }
+
+    if validate {
+        return Ok(Some(job));
+    }
+
+    config.set_data(&job.id, "digest", &job)?;
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(
+        &auth_id,
+        "digest",
+        &job.id,
+        None,
+        section_data(&config, &job.id).as_ref(),
+    );
+
+    crate::server::jobstate::create_state_file("cloud-digest-job", &job.id)?;
+
+    Ok(None)
+}
+
+#[api(
+   input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: CloudDigestJobConfig },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Read a cloud digest job configuration.
+pub fn read_cloud_digest_job(
+    id: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<CloudDigestJobConfig, Error> {
+    let (config, digest) = pbs_config::cloud_job::config()?;
+
+    let job = config.lookup("digest", &id)?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(job)
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the comment property.
+    Comment,
+    /// Delete the job schedule.
+    Schedule,
+    /// Delete the period (daily).
+    Period,
+    /// Delete the 'notify-user' property.
+    NotifyUser,
+    /// Delete the webhook.
+    Webhook,
+    /// Unset the disable flag.
+    Disable,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            update: {
+                type: CloudDigestJobConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudDigestJobConfig,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Update the cloud digest job
+///
+/// With 'validate' the updated job is only checked and returned, but not saved.
+pub fn update_cloud_digest_job(
+    id: String,
+    update: CloudDigestJobConfigUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudDigestJobConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
+
+    let mut data: CloudDigestJobConfig = config.lookup("digest", &id)?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let old_schedule = data.schedule.clone();
+    let old_period = data.period;
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Comment => {
+                    data.comment = None;
+                }
+                DeletableProperty::Schedule => {
+                    data.schedule = None;
+                }
+                DeletableProperty::Period => {
+                    data.period = None;
+                }
+                DeletableProperty::NotifyUser => {
+                    data.notify_user = None;
+                }
+                DeletableProperty::Webhook => {
+                    data.webhook = None;
+                }
+                DeletableProperty::Disable => {
+                    data.disable = false;
+                }
+            }
+        }
+    }
+
+    if update.period.is_some() {
+        data.period = update.period;
+    }
+    if update.notify_user.is_some() {
+        data.notify_user = update.notify_user;
+    }
+    if update.webhook.is_some() {
+        data.webhook = update.webhook;
+    }
+    if update.schedule.is_some() {
+        data.schedule = update.schedule;
+    }
+
+    if let Some(comment) = update.comment {
+        let comment = comment.trim();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment.to_string());
+        }
+    }
+
+    if let Some(value) = update.disable {
+        data.disable = value;
+    }
+
+    if validate {
+        return Ok(Some(data));
+    }
+
+    // without a schedule, the period determines it
+    let schedule_changed = data.schedule != old_schedule
+        || (data.schedule.is_none()
+            && data.period.unwrap_or_default() != old_period.unwrap_or_default());
+
+    let old = section_data(&config, &id);
+
+    config.set_data(&id, "digest", &data)?;
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(
+        &auth_id,
+        "digest",
+        &id,
+        old.as_ref(),
+        section_data(&config, &id).as_ref(),
+    );
+
+    if schedule_changed {
+        crate::server::jobstate::update_job_last_run_time("cloud-digest-job", &id)?;
+    }
+
+    Ok(None)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Remove a cloud digest job configuration
+pub fn delete_cloud_digest_job(
+    id: String,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let old = match config.lookup::<CloudDigestJobConfig>("digest", &id) {
+        Ok(_job) => config.sections.remove(&id).map(|(_, data)| data),
+        Err(_) => {
+            http_bail!(NOT_FOUND, "job '{}' does not exist.", id)
+        }
+    };
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(&auth_id, "digest", &id, old.as_ref(), None);
+
+    crate::server::jobstate::remove_state_file("cloud-digest-job", &id)?;
+    resume_job(CLOUD_STATUS_DIR, &id)?;
+    remove_digest_state(CLOUD_STATUS_DIR, &id)?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_CLOUD_DIGEST_JOB)
+    .put(&API_METHOD_UPDATE_CLOUD_DIGEST_JOB)
+    .delete(&API_METHOD_DELETE_CLOUD_DIGEST_JOB);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_CLOUD_DIGEST_JOBS)
+    .post(&API_METHOD_CREATE_CLOUD_DIGEST_JOB)
+    .match_all("id", &ITEM_ROUTER);
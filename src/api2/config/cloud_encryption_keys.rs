@@ -0,0 +1,331 @@
+use anyhow::{bail, format_err, Error};
+use hex::FromHex;
+use serde_json::Value;
+
+use proxmox_router::{http_bail, ApiMethod, Permission, Router, RpcEnvironment, SubdirMap};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    CloudTarget, Fingerprint, Kdf, KeyInfo, CLOUD_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+    CLOUD_KEY_SHARE_SCHEMA, PASSWORD_HINT_SCHEMA, PRIV_CLOUD_AUDIT, PRIV_CLOUD_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+
+use pbs_config::open_backup_lockfile;
+use pbs_key_config::KeyConfig;
+
+use crate::cloud::encryption_keys::{
+    insert_key, load_key_configs, load_keys, save_key_configs, save_keys, zero_bytes,
+    CLOUD_KEYS_LOCKFILE,
+};
+use crate::cloud::key_escrow::{recover_key, split_key, KeyShare};
+
+#[api(
+    input: {
+        properties: {},
+    },
+    returns: {
+        description: "The list of cloud encryption keys (with config digest).",
+        type: Array,
+        items: { type: KeyInfo },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List existing keys
+pub fn list_keys(
+    _param: Value,
+    _info: &ApiMethod,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<KeyInfo>, Error> {
+    let (key_map, digest) = load_key_configs()?;
+
+    let list = key_map.values().map(|item| item.into()).collect();
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            kdf: {
+                type: Kdf,
+                optional: true,
+            },
+            password: {
+                description: "A secret password.",
+                min_length: 5,
+            },
+            hint: {
+                schema: PASSWORD_HINT_SCHEMA,
+                optional: true,
+            },
+            key: {
+                description: "Restore/Re-create a key from this JSON string.",
+                type: String,
+                min_length: 300,
+                max_length: 600,
+                optional: true,
+            },
+            shares: {
+                description: "Re-create a key from shares of a split key.",
+                type: Array,
+                optional: true,
+                items: {
+                    schema: CLOUD_KEY_SHARE_SCHEMA,
+                },
+            },
+        },
+    },
+    returns: {
+        schema: CLOUD_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Create a new encryption key
+pub fn create_key(
+    kdf: Option<Kdf>,
+    password: String,
+    hint: Option<String>,
+    key: Option<String>,
+    shares: Option<Vec<String>>,
+    _rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Fingerprint, Error> {
+    let kdf = kdf.unwrap_or_default();
+
+    if key.is_some() && shares.is_some() {
+        param_bail!(
+            "shares",
+            format_err!("Please specify either a key or key shares, not both")
+        );
+    }
+
+    if key.is_none() {
+        if let Kdf::None = kdf {
+            param_bail!(
+                "kdf",
+                format_err!("Please specify a key derivation function (none is not allowed here).")
+            );
+        }
+        if hint.is_none() && shares.is_none() {
+            param_bail!("hint", format_err!("Please specify either a hint or a key"));
+        }
+    }
+
+    let (key_decrypt, mut key_config) = match key {
+        Some(key) => {
+            let key_config: KeyConfig =
+                serde_json::from_str(&key).map_err(|err| format_err!("<errmsg>: {}", err))?;
+            let (key_decrypt, _created, _fp) =
+                key_config.decrypt(&|| Ok(password.as_bytes().to_vec()))?;
+            (key_decrypt, key_config)
+        }
+        None => match shares {
+            Some(shares) => {
+                let shares = shares
+                    .iter()
+                    .map(|share| share.parse())
+                    .collect::<Result<Vec<KeyShare>, Error>>()?;
+                let key_decrypt = recover_key(&shares)?;
+                let key_config = KeyConfig::with_key(&key_decrypt, password.as_bytes(), kdf)?;
+                (key_decrypt, key_config)
+            }
+            None => KeyConfig::new(password.as_bytes(), kdf)?,
+        },
+    };
+
+    if hint.is_some() {
+        key_config.hint = hint;
+    }
+
+    let fingerprint = key_config.fingerprint.clone().unwrap();
+    insert_key(key_decrypt, key_config, false)?;
+
+    Ok(fingerprint)
+}
+
+#[api(
+    input: {
+        properties: {
+            fingerprint: {
+                schema: CLOUD_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: KeyInfo,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Get key config (public key part)
+pub fn read_key(
+    fingerprint: Fingerprint,
+    _rpcenv: &mut dyn RpcEnvironment,
+) -> Result<KeyInfo, Error> {
+    let (config_map, _digest) = load_key_configs()?;
+
+    let key_config = match config_map.get(&fingerprint) {
+        Some(key_config) => key_config,
+        None => http_bail!(
+            NOT_FOUND,
+            "cloud encryption key '{}' does not exist.",
+            fingerprint
+        ),
+    };
+
+    if key_config.kdf.is_none() {
+        bail!("found unencrypted key - internal error");
+    }
+
+    Ok(key_config.into())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            fingerprint: {
+                schema: CLOUD_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Remove a encryption key from the database
+///
+/// Please note that you can no longer restore data encrypted with this key.
+pub fn delete_key(
+    fingerprint: Fingerprint,
+    digest: Option<String>,
+    _rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let _lock = open_backup_lockfile(CLOUD_KEYS_LOCKFILE, None, true)?;
+
+    let (mut config_map, expected_digest) = load_key_configs()?;
+    let (mut key_map, _) = load_keys()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let (target_config, _) = pbs_config::cloud::config()?;
+    let targets: Vec<CloudTarget> = target_config.convert_to_typed_array("target")?;
+    for target in targets {
+        for item in target.config.namespace_keys()? {
+            if item.key.parse::<Fingerprint>()? == fingerprint {
+                bail!(
+                    "encryption key '{}' is used by cloud target '{}'",
+                    fingerprint,
+                    target.name
+                );
+            }
+        }
+    }
+
+    match config_map.get(&fingerprint) {
+        Some(_) => {
+            config_map.remove(&fingerprint);
+        }
+        None => http_bail!(
+            NOT_FOUND,
+            "cloud encryption key '{}' does not exist.",
+            fingerprint
+        ),
+    }
+    save_key_configs(config_map)?;
+
+    key_map.remove(&fingerprint);
+    save_keys(key_map)?;
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            fingerprint: {
+                schema: CLOUD_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+            },
+            password: {
+                description: "The current password of the key.",
+                min_length: 5,
+            },
+            shares: {
+                description: "Number of shares to create.",
+                type: u8,
+                minimum: 2,
+            },
+            threshold: {
+                description: "Number of shares needed to recover the key.",
+                type: u8,
+                minimum: 2,
+            },
+        },
+    },
+    returns: {
+        description: "The key shares (keep them at separate places).",
+        type: Array,
+        items: {
+            schema: CLOUD_KEY_SHARE_SCHEMA,
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Split a key into shares for escrow (Shamir's secret sharing)
+///
+/// Any `threshold` of the shares can re-create the key (see the `shares`
+/// parameter of key creation), fewer shares reveal nothing about it.
+pub fn split(
+    fingerprint: Fingerprint,
+    password: String,
+    shares: u8,
+    threshold: u8,
+    _rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<String>, Error> {
+    let (config_map, _digest) = load_key_configs()?;
+
+    let key_config = match config_map.get(&fingerprint) {
+        Some(key_config) => key_config,
+        None => http_bail!(
+            NOT_FOUND,
+            "cloud encryption key '{}' does not exist.",
+            fingerprint
+        ),
+    };
+
+    let (mut key, _created, _fp) = key_config.decrypt(&|| Ok(password.as_bytes().to_vec()))?;
+    let result = split_key(&key, &fingerprint, shares, threshold);
+    zero_bytes(&mut key);
+
+    Ok(result?.iter().map(|share| share.to_string()).collect())
+}
+
+const ITEM_SUBDIRS: SubdirMap = &[("split", &Router::new().post(&API_METHOD_SPLIT))];
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_KEY)
+    .delete(&API_METHOD_DELETE_KEY)
+    .subdirs(ITEM_SUBDIRS);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_KEYS)
+    .post(&API_METHOD_CREATE_KEY)
+    .match_all("fingerprint", &ITEM_ROUTER);
@@ -0,0 +1,385 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::Error;
+use hex::FromHex;
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    Authid, CloudBackupJobConfig, CloudMapping, CloudMappingUpdater, Fingerprint,
+    CLOUD_CONFIG_VALIDATE_SCHEMA, CLOUD_MAPPING_ID_SCHEMA, PRIV_CLOUD_AUDIT, PRIV_CLOUD_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+
+use crate::cloud::config_history::{record_config_change, section_data};
+use crate::cloud::encryption_keys::load_key_configs;
+use crate::cloud::mapping::{check_mapping_prefix, select_job_mapping};
+
+/// Checks done before a mapping is stored
+fn check_mapping(mapping: &CloudMapping, config: &[CloudMapping]) -> Result<(), Error> {
+    let (datastore_config, _digest) = pbs_config::datastore::config()?;
+    if !datastore_config.sections.contains_key(&mapping.store) {
+        param_bail!("store", "datastore '{}' does not exist.", mapping.store);
+    }
+
+    if let Some(other) = config.iter().find(|other| {
+        other.id != mapping.id && other.store == mapping.store && other.ns == mapping.ns
+    }) {
+        param_bail!(
+            "ns",
+            "cloud mapping '{}' already maps this datastore and namespace.",
+            other.id
+        );
+    }
+
+    let target = match pbs_config::cloud::lookup_target(&mapping.target) {
+        Ok(target) => target,
+        Err(err) => param_bail!("target", err),
+    };
+    if let Err(err) = check_mapping_prefix(mapping, &target) {
+        param_bail!("prefix", err);
+    }
+
+    if let Some(ref key) = mapping.key {
+        let fingerprint: Fingerprint = key.parse()?;
+        let (key_map, _digest) = load_key_configs()?;
+        if !key_map.contains_key(&fingerprint) {
+            param_bail!(
+                "key",
+                "cloud encryption key '{}' does not exist",
+                fingerprint
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[api(
+    returns: {
+        description: "List configured cloud mappings.",
+        type: Array,
+        items: { type: CloudMapping },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List all cloud mappings
+pub fn list_cloud_mappings(rpcenv: &mut dyn RpcEnvironment) -> Result<Vec<CloudMapping>, Error> {
+    let (config, digest) = pbs_config::cloud_mapping::config()?;
+
+    let list = config.convert_to_typed_array::<CloudMapping>("mapping")?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            mapping: {
+                type: CloudMapping,
+                flatten: true,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudMapping,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Create a new cloud mapping.
+///
+/// With 'validate' the mapping is only checked and returned, but not saved.
+pub fn create_cloud_mapping(
+    mapping: CloudMapping,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudMapping>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_mapping::lock()?;
+
+    let (mut config, _digest) = pbs_config::cloud_mapping::config()?;
+
+    if config.sections.get(&mapping.id).is_some() {
+        param_bail!("id", "cloud mapping '{}' already exists.", mapping.id);
+    }
+
+    check_mapping(&mapping, &config.convert_to_typed_array("mapping")?)?;
+
+    if validate {
+        return Ok(Some(mapping));
+    }
+
+    config.set_data(&mapping.id, "mapping", &mapping)?;
+
+    pbs_config::cloud_mapping::save_config(&config)?;
+
+    record_config_change(
+        &auth_id,
+        "mapping",
+        &mapping.id,
+        None,
+        section_data(&config, &mapping.id).as_ref(),
+    );
+
+    Ok(None)
+}
+
+#[api(
+   input: {
+        properties: {
+            id: {
+                schema: CLOUD_MAPPING_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: CloudMapping },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Read a cloud mapping.
+pub fn read_cloud_mapping(
+    id: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<CloudMapping, Error> {
+    let (config, digest) = pbs_config::cloud_mapping::config()?;
+
+    let mapping = config.lookup("mapping", &id)?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(mapping)
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the 'ns' property (map the whole datastore).
+    Ns,
+    /// Delete the 'prefix' property.
+    Prefix,
+    /// Delete the 'key' property.
+    Key,
+    /// Delete the comment property.
+    Comment,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: CLOUD_MAPPING_ID_SCHEMA,
+            },
+            update: {
+                type: CloudMappingUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudMapping,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Update a cloud mapping
+///
+/// With 'validate' the updated mapping is only checked and returned, but not saved.
+pub fn update_cloud_mapping(
+    id: String,
+    update: CloudMappingUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudMapping>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_mapping::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_mapping::config()?;
+
+    let mut data: CloudMapping = config.lookup("mapping", &id)?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Ns => {
+                    data.ns = None;
+                }
+                DeletableProperty::Prefix => {
+                    data.prefix = None;
+                }
+                DeletableProperty::Key => {
+                    data.key = None;
+                }
+                DeletableProperty::Comment => {
+                    data.comment = None;
+                }
+            }
+        }
+    }
+
+    if let Some(store) = update.store {
+        data.store = store;
+    }
+    if update.ns.is_some() {
+        data.ns = update.ns;
+    }
+    if let Some(target) = update.target {
+        data.target = target;
+    }
+    if update.prefix.is_some() {
+        data.prefix = update.prefix;
+    }
+    if update.key.is_some() {
+        data.key = update.key;
+    }
+
+    if let Some(comment) = update.comment {
+        let comment = comment.trim();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment.to_string());
+        }
+    }
+
+    check_mapping(&data, &config.convert_to_typed_array("mapping")?)?;
+
+    if validate {
+        return Ok(Some(data));
+    }
+
+    let old = section_data(&config, &id);
+
+    config.set_data(&id, "mapping", &data)?;
+
+    pbs_config::cloud_mapping::save_config(&config)?;
+
+    record_config_change(
+        &auth_id,
+        "mapping",
+        &id,
+        old.as_ref(),
+        section_data(&config, &id).as_ref(),
+    );
+
+    Ok(None)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: CLOUD_MAPPING_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Remove a cloud mapping
+///
+/// Backup jobs without target of their own which use the mapping have
+/// to be changed first.
+pub fn delete_cloud_mapping(
+    id: String,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_mapping::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_mapping::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    if config.lookup::<CloudMapping>("mapping", &id).is_err() {
+        http_bail!(NOT_FOUND, "cloud mapping '{}' does not exist.", id);
+    }
+
+    let mappings: Vec<CloudMapping> = config.convert_to_typed_array("mapping")?;
+    let (job_config, _) = pbs_config::cloud_job::config()?;
+    let job_list: Vec<CloudBackupJobConfig> = job_config.convert_to_typed_array("backup")?;
+    for job in job_list {
+        if job.setup.target.is_some() {
+            continue;
+        }
+        if select_job_mapping(&mappings, &job.setup).map_or(false, |mapping| mapping.id == id) {
+            param_bail!(
+                "id",
+                "cloud mapping '{}' is used by cloud backup job '{}' (no target of its own)",
+                id,
+                job.id
+            );
+        }
+    }
+
+    let old = config.sections.remove(&id).map(|(_, data)| data);
+
+    pbs_config::cloud_mapping::save_config(&config)?;
+
+    record_config_change(&auth_id, "mapping", &id, old.as_ref(), None);
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_CLOUD_MAPPING)
+    .put(&API_METHOD_UPDATE_CLOUD_MAPPING)
+    .delete(&API_METHOD_DELETE_CLOUD_MAPPING);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_CLOUD_MAPPINGS)
+    .post(&API_METHOD_CREATE_CLOUD_MAPPING)
+    .match_all("id", &ITEM_ROUTER);
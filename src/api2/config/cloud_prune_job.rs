@@ -0,0 +1,417 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::Error;
+use hex::FromHex;
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    Authid, CloudPruneJobConfig, CloudPruneJobConfigUpdater, CLOUD_CONFIG_VALIDATE_SCHEMA,
+    JOB_ID_SCHEMA, PRIV_CLOUD_AUDIT, PRIV_CLOUD_DELETE, PRIV_CLOUD_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+use pbs_config::CachedUserInfo;
+
+use crate::cloud::config_history::{record_config_change, section_data};
+use crate::cloud::job_pause::resume_job;
+use crate::cloud::CLOUD_STATUS_DIR;
+
+// prune jobs delete snapshots on their target
+fn check_prune_target(auth_id: &Authid, job: &CloudPruneJobConfig) -> Result<(), Error> {
+    if let Err(err) = pbs_config::cloud::lookup_target(&job.target) {
+        param_bail!("target", err);
+    }
+    let user_info = CachedUserInfo::new()?;
+    user_info.check_privs(
+        auth_id,
+        &["cloud", "target", &job.target],
+        PRIV_CLOUD_DELETE,
+        false,
+    )?;
+    if !job.keep.keeps_something() {
+        param_bail!("keep-last", "the prune job needs at least one keep option");
+    }
+    Ok(())
+}
+
+#[api(
+    returns: {
+        description: "List configured jobs.",
+        type: Array,
+        items: { type: CloudPruneJobConfig },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List all cloud prune jobs
+pub fn list_cloud_prune_jobs(
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudPruneJobConfig>, Error> {
+    let (config, digest) = pbs_config::cloud_job::config()?;
+
+    let list = config.convert_to_typed_array::<CloudPruneJobConfig>("prune")?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            job: {
+                type: CloudPruneJobConfig,
+                flatten: true,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudPruneJobConfig,
+        optional: true,
+    },
+    access: {
+        description: "Requires Cloud.Modify on /cloud/job and Cloud.Delete on \
+                      /cloud/target/{target}.",
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Create a new cloud prune job.
+///
+/// With 'validate' the job is only checked and returned, but not saved.
+pub fn create_cloud_prune_job(
+    job: CloudPruneJobConfig,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudPruneJobConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, _digest) = pbs_config::cloud_job::config()?;
+
+    if config.sections.get(&job.id).is_some() {
+        param_bail!("id", "job '{}' already exists.", job.id);
+    }
+
+    check_prune_target(&auth_id, &job)?;
+
+    if validate {
+        return Ok(Some(job));
+    }
+
+    config.set_data(&job.id, "prune", &job)?;
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(
+        &auth_id,
+        "prune",
+        &job.id,
+        None,
+        section_data(&config, &job.id).as_ref(),
+    );
+
+    crate::server::jobstate::create_state_file("cloud-prune-job", &job.id)?;
+
+    Ok(None)
+}
+
+#[api(
+   input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: CloudPruneJobConfig },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Read a cloud prune job configuration.
+pub fn read_cloud_prune_job(
+    id: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<CloudPruneJobConfig, Error> {
+    let (config, digest) = pbs_config::cloud_job::config()?;
+
+    let job = config.lookup("prune", &id)?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(job)
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the comment property.
+    Comment,
+    /// Delete the namespace property, prune the whole datastore.
+    Ns,
+    /// Delete the 'max-depth' property, recurse into all namespaces.
+    MaxDepth,
+    /// Delete number of last backups to keep.
+    KeepLast,
+    /// Delete number of hourly backups to keep.
+    KeepHourly,
+    /// Delete number of daily backups to keep.
+    KeepDaily,
+    /// Delete number of weekly backups to keep.
+    KeepWeekly,
+    /// Delete number of monthly backups to keep.
+    KeepMonthly,
+    /// Delete number of yearly backups to keep.
+    KeepYearly,
+    /// Unset the disable flag.
+    Disable,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            update: {
+                type: CloudPruneJobConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudPruneJobConfig,
+        optional: true,
+    },
+    access: {
+        description: "Requires Cloud.Modify on /cloud/job and Cloud.Delete on \
+                      /cloud/target/{target}.",
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Update the cloud prune job
+///
+/// With 'validate' the updated job is only checked and returned, but not saved.
+pub fn update_cloud_prune_job(
+    id: String,
+    update: CloudPruneJobConfigUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudPruneJobConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
+
+    let mut data: CloudPruneJobConfig = config.lookup("prune", &id)?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let old_schedule = data.schedule.clone();
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Comment => {
+                    data.comment = None;
+                }
+                DeletableProperty::Ns => {
+                    data.ns = None;
+                }
+                DeletableProperty::MaxDepth => {
+                    data.max_depth = None;
+                }
+                DeletableProperty::KeepLast => {
+                    data.keep.keep_last = None;
+                }
+                DeletableProperty::KeepHourly => {
+                    data.keep.keep_hourly = None;
+                }
+                DeletableProperty::KeepDaily => {
+                    data.keep.keep_daily = None;
+                }
+                DeletableProperty::KeepWeekly => {
+                    data.keep.keep_weekly = None;
+                }
+                DeletableProperty::KeepMonthly => {
+                    data.keep.keep_monthly = None;
+                }
+                DeletableProperty::KeepYearly => {
+                    data.keep.keep_yearly = None;
+                }
+                DeletableProperty::Disable => {
+                    data.disable = false;
+                }
+            }
+        }
+    }
+
+    if let Some(target) = update.target {
+        data.target = target;
+    }
+    if let Some(store) = update.store {
+        data.store = store;
+    }
+    if update.ns.is_some() {
+        data.ns = update.ns;
+    }
+    if update.max_depth.is_some() {
+        data.max_depth = update.max_depth;
+    }
+    if let Some(schedule) = update.schedule {
+        data.schedule = schedule;
+    }
+
+    if let Some(value) = update.keep.keep_last {
+        data.keep.keep_last = Some(value);
+    }
+    if let Some(value) = update.keep.keep_hourly {
+        data.keep.keep_hourly = Some(value);
+    }
+    if let Some(value) = update.keep.keep_daily {
+        data.keep.keep_daily = Some(value);
+    }
+    if let Some(value) = update.keep.keep_weekly {
+        data.keep.keep_weekly = Some(value);
+    }
+    if let Some(value) = update.keep.keep_monthly {
+        data.keep.keep_monthly = Some(value);
+    }
+    if let Some(value) = update.keep.keep_yearly {
+        data.keep.keep_yearly = Some(value);
+    }
+
+    if let Some(comment) = update.comment {
+        let comment = comment.trim();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment.to_string());
+        }
+    }
+
+    if let Some(value) = update.disable {
+        data.disable = value;
+    }
+
+    check_prune_target(&auth_id, &data)?;
+
+    if validate {
+        return Ok(Some(data));
+    }
+
+    let schedule_changed = data.schedule != old_schedule;
+
+    let old = section_data(&config, &id);
+
+    config.set_data(&id, "prune", &data)?;
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(
+        &auth_id,
+        "prune",
+        &id,
+        old.as_ref(),
+        section_data(&config, &id).as_ref(),
+    );
+
+    if schedule_changed {
+        crate::server::jobstate::update_job_last_run_time("cloud-prune-job", &id)?;
+    }
+
+    Ok(None)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Remove a cloud prune job configuration
+pub fn delete_cloud_prune_job(
+    id: String,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let old = match config.lookup::<CloudPruneJobConfig>("prune", &id) {
+        Ok(_job) => config.sections.remove(&id).map(|(_, data)| data),
+        Err(_) => {
+            http_bail!(NOT_FOUND, "job '{}' does not exist.", id)
+        }
+    };
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(&auth_id, "prune", &id, old.as_ref(), None);
+
+    crate::server::jobstate::remove_state_file("cloud-prune-job", &id)?;
+    resume_job(CLOUD_STATUS_DIR, &id)?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_CLOUD_PRUNE_JOB)
+    .put(&API_METHOD_UPDATE_CLOUD_PRUNE_JOB)
+    .delete(&API_METHOD_DELETE_CLOUD_PRUNE_JOB);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_CLOUD_PRUNE_JOBS)
+    .post(&API_METHOD_CREATE_CLOUD_PRUNE_JOB)
+    .match_all("id", &ITEM_ROUTER);
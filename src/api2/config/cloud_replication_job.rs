@@ -0,0 +1,495 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::{bail, Error};
+use hex::FromHex;
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    check_group_filters, Authid, CloudReplicationJobConfig, CloudReplicationJobConfigUpdater,
+    CLOUD_CONFIG_VALIDATE_SCHEMA, JOB_ID_SCHEMA, PRIV_CLOUD_AUDIT, PRIV_CLOUD_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+
+use pbs_config::CachedUserInfo;
+
+use crate::cloud::config_history::{record_config_change, section_data};
+use crate::cloud::job_chain::{chained_jobs, check_run_after};
+use crate::cloud::job_pause::resume_job;
+use crate::cloud::job_retry::remove_job_retries;
+use crate::cloud::CLOUD_STATUS_DIR;
+
+use super::cloud_backup_job::check_job_hooks;
+
+/// Checks done before a replication job is stored
+fn check_replication_job(job: &CloudReplicationJobConfig) -> Result<(), Error> {
+    if let Err(err) = pbs_config::cloud::lookup_target(&job.source) {
+        param_bail!("source", err);
+    }
+    if let Err(err) = pbs_config::cloud::lookup_target(&job.target) {
+        param_bail!("target", err);
+    }
+    if job.source == job.target {
+        param_bail!("target", "source and target must differ.");
+    }
+    if let Some(ref store) = job.store {
+        let (datastore_config, _digest) = pbs_config::datastore::config()?;
+        if !datastore_config.sections.contains_key(store) {
+            param_bail!("store", "datastore '{}' does not exist.", store);
+        }
+    }
+    if let Some(ref filters) = job.group_filter {
+        if let Err(err) = check_group_filters(filters) {
+            param_bail!("group-filter", err);
+        }
+    }
+    Ok(())
+}
+
+#[api(
+    returns: {
+        description: "List configured jobs.",
+        type: Array,
+        items: { type: CloudReplicationJobConfig },
+    },
+    access: {
+        description: "List configured cloud jobs filtered by Cloud.Audit privileges",
+        permission: &Permission::Anybody,
+    },
+)]
+/// List all cloud replication jobs
+pub fn list_cloud_replication_jobs(
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudReplicationJobConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let (config, digest) = pbs_config::cloud_job::config()?;
+
+    let list = config.convert_to_typed_array::<CloudReplicationJobConfig>("replication")?;
+
+    let list = list
+        .into_iter()
+        .filter(|job| {
+            let privs = user_info.lookup_privs(&auth_id, &["cloud", "job", &job.id]);
+            privs & PRIV_CLOUD_AUDIT != 0
+        })
+        .collect();
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            job: {
+                type: CloudReplicationJobConfig,
+                flatten: true,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudReplicationJobConfig,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Create a new cloud replication job.
+///
+/// With 'validate' the job is only checked and returned, but not saved.
+pub fn create_cloud_replication_job(
+    job: CloudReplicationJobConfig,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudReplicationJobConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, _digest) = pbs_config::cloud_job::config()?;
+
+    if config.sections.get(&job.id).is_some() {
+        param_bail!("id", "job '{}' already exists.", job.id);
+    }
+
+    check_replication_job(&job)?;
+    check_job_hooks(&auth_id, None, &job.hooks)?;
+    if let Some(ref run_after) = job.run_after {
+        if let Err(err) = check_run_after(&config, &job.id, run_after) {
+            param_bail!("run-after", err);
+        }
+    }
+
+    if validate {
+        return Ok(Some(job));
+    }
+
+    config.set_data(&job.id, "replication", &job)?;
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(
+        &auth_id,
+        "replication",
+        &job.id,
+        None,
+        section_data(&config, &job.id).as_ref(),
+    );
+
+    crate::server::jobstate::create_state_file("cloud-replication-job", &job.id)?;
+
+    Ok(None)
+}
+
+#[api(
+   input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: CloudReplicationJobConfig },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job", "{id}"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Read a cloud replication job configuration.
+pub fn read_cloud_replication_job(
+    id: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<CloudReplicationJobConfig, Error> {
+    let (config, digest) = pbs_config::cloud_job::config()?;
+
+    let job = config.lookup("replication", &id)?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(job)
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the comment property.
+    Comment,
+    /// Delete the job schedule.
+    Schedule,
+    /// Delete the 'splay' property
+    Splay,
+    /// Delete the 'retry-on-failure' property
+    RetryOnFailure,
+    /// Delete the 'retry-delay' property
+    RetryDelay,
+    /// Delete the 'run-after' property
+    RunAfter,
+    /// Delete the 'pre-hook' property
+    PreHook,
+    /// Delete the 'post-hook' property
+    PostHook,
+    /// Delete the 'hook-user' property
+    HookUser,
+    /// Delete the 'hook-timeout' property
+    HookTimeout,
+    /// Delete the 'hook-failure' property
+    HookFailure,
+    /// Delete the 'store' property
+    Store,
+    /// Delete the 'max-age' property
+    MaxAge,
+    /// Delete the 'group-filter' property
+    GroupFilter,
+    /// Unset the disable flag.
+    Disable,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            update: {
+                type: CloudReplicationJobConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudReplicationJobConfig,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job", "{id}"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Update the cloud replication job
+///
+/// With 'validate' the updated job is only checked and returned, but not saved.
+pub fn update_cloud_replication_job(
+    id: String,
+    update: CloudReplicationJobConfigUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudReplicationJobConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
+
+    let mut data: CloudReplicationJobConfig = config.lookup("replication", &id)?;
+    let old_hooks = data.hooks.clone();
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Comment => {
+                    data.comment = None;
+                }
+                DeletableProperty::Schedule => {
+                    data.schedule = None;
+                }
+                DeletableProperty::Splay => {
+                    data.splay = None;
+                }
+                DeletableProperty::RetryOnFailure => {
+                    data.retry_on_failure = None;
+                }
+                DeletableProperty::RetryDelay => {
+                    data.retry_delay = None;
+                }
+                DeletableProperty::RunAfter => {
+                    data.run_after = None;
+                }
+                DeletableProperty::PreHook => {
+                    data.hooks.pre_hook = None;
+                }
+                DeletableProperty::PostHook => {
+                    data.hooks.post_hook = None;
+                }
+                DeletableProperty::HookUser => {
+                    data.hooks.hook_user = None;
+                }
+                DeletableProperty::HookTimeout => {
+                    data.hooks.hook_timeout = None;
+                }
+                DeletableProperty::HookFailure => {
+                    data.hooks.hook_failure = None;
+                }
+                DeletableProperty::Store => {
+                    data.store = None;
+                }
+                DeletableProperty::MaxAge => {
+                    data.max_age = None;
+                }
+                DeletableProperty::GroupFilter => {
+                    data.group_filter = None;
+                }
+                DeletableProperty::Disable => {
+                    data.disable = false;
+                }
+            }
+        }
+    }
+
+    if let Some(source) = update.source {
+        data.source = source;
+    }
+    if let Some(target) = update.target {
+        data.target = target;
+    }
+    if update.store.is_some() {
+        data.store = update.store;
+    }
+    if update.max_age.is_some() {
+        data.max_age = update.max_age;
+    }
+    if update.group_filter.is_some() {
+        data.group_filter = update.group_filter;
+    }
+
+    check_replication_job(&data)?;
+
+    if update.hooks.pre_hook.is_some() {
+        data.hooks.pre_hook = update.hooks.pre_hook;
+    }
+    if update.hooks.post_hook.is_some() {
+        data.hooks.post_hook = update.hooks.post_hook;
+    }
+    if update.hooks.hook_user.is_some() {
+        data.hooks.hook_user = update.hooks.hook_user;
+    }
+    if update.hooks.hook_timeout.is_some() {
+        data.hooks.hook_timeout = update.hooks.hook_timeout;
+    }
+    if update.hooks.hook_failure.is_some() {
+        data.hooks.hook_failure = update.hooks.hook_failure;
+    }
+    check_job_hooks(&auth_id, Some(&old_hooks), &data.hooks)?;
+
+    let schedule_changed = data.schedule != update.schedule;
+    if update.schedule.is_some() {
+        data.schedule = update.schedule;
+    }
+    if update.splay.is_some() {
+        data.splay = update.splay;
+    }
+    if update.retry_on_failure.is_some() {
+        data.retry_on_failure = update.retry_on_failure;
+    }
+    if update.retry_delay.is_some() {
+        data.retry_delay = update.retry_delay;
+    }
+    if update.run_after.is_some() {
+        data.run_after = update.run_after;
+    }
+    if let Some(ref run_after) = data.run_after {
+        if let Err(err) = check_run_after(&config, &data.id, run_after) {
+            param_bail!("run-after", err);
+        }
+    }
+
+    if let Some(comment) = update.comment {
+        let comment = comment.trim();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment.to_string());
+        }
+    }
+
+    if let Some(value) = update.disable {
+        data.disable = value;
+    }
+
+    if validate {
+        return Ok(Some(data));
+    }
+
+    let old = section_data(&config, &id);
+
+    config.set_data(&id, "replication", &data)?;
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(
+        &auth_id,
+        "replication",
+        &id,
+        old.as_ref(),
+        section_data(&config, &id).as_ref(),
+    );
+
+    if schedule_changed {
+        crate::server::jobstate::update_job_last_run_time("cloud-replication-job", &id)?;
+    }
+
+    Ok(None)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job", "{id}"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Remove a cloud replication job configuration
+pub fn delete_cloud_replication_job(
+    id: String,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let chained = chained_jobs(&config, &id);
+    if !chained.is_empty() {
+        bail!(
+            "job '{}' is the 'run-after' trigger of {}",
+            id,
+            chained.join(", ")
+        );
+    }
+
+    let old = match config.lookup::<CloudReplicationJobConfig>("replication", &id) {
+        Ok(_job) => config.sections.remove(&id).map(|(_, data)| data),
+        Err(_) => {
+            http_bail!(NOT_FOUND, "job '{}' does not exist.", id)
+        }
+    };
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(&auth_id, "replication", &id, old.as_ref(), None);
+
+    crate::server::jobstate::remove_state_file("cloud-replication-job", &id)?;
+    resume_job(CLOUD_STATUS_DIR, &id)?;
+    remove_job_retries(CLOUD_STATUS_DIR, "cloud-replication-job", &id)?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_CLOUD_REPLICATION_JOB)
+    .put(&API_METHOD_UPDATE_CLOUD_REPLICATION_JOB)
+    .delete(&API_METHOD_DELETE_CLOUD_REPLICATION_JOB);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_CLOUD_REPLICATION_JOBS)
+    .post(&API_METHOD_CREATE_CLOUD_REPLICATION_JOB)
+    .match_all("id", &ITEM_ROUTER);
@@ -0,0 +1,361 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::Error;
+use hex::FromHex;
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    Authid, CloudRoleSyncJobConfig, CloudRoleSyncJobConfigUpdater, LdapRealmConfig,
+    CLOUD_CONFIG_VALIDATE_SCHEMA, JOB_ID_SCHEMA, PRIV_PERMISSIONS_MODIFY, PRIV_SYS_AUDIT,
+    PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+
+use crate::cloud::config_history::{record_config_change, section_data};
+use crate::cloud::job_pause::resume_job;
+use crate::cloud::role_sync::{parse_mappings, remove_role_grants};
+use crate::cloud::CLOUD_STATUS_DIR;
+
+/// Checks done before a role sync job is stored
+fn check_role_sync_job(job: &CloudRoleSyncJobConfig) -> Result<(), Error> {
+    let (domains, _digest) = pbs_config::domains::config()?;
+    if domains
+        .lookup::<LdapRealmConfig>("ldap", &job.realm)
+        .is_err()
+    {
+        param_bail!("realm", "LDAP realm '{}' does not exist.", job.realm);
+    }
+
+    let mappings = match parse_mappings(job) {
+        Ok(mappings) => mappings,
+        Err(err) => param_bail!("mapping", err),
+    };
+    for mapping in mappings {
+        if !pbs_config::acl::ROLE_NAMES.contains_key(mapping.role.as_str()) {
+            param_bail!("mapping", "unknown role '{}'", mapping.role);
+        }
+        if let Err(err) = pbs_config::acl::check_acl_path(&mapping.path) {
+            param_bail!("mapping", err);
+        }
+    }
+    Ok(())
+}
+
+#[api(
+    returns: {
+        description: "List configured jobs.",
+        type: Array,
+        items: { type: CloudRoleSyncJobConfig },
+    },
+    access: {
+        permission: &Permission::Privilege(&["access", "acl"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// List all cloud role sync jobs
+pub fn list_cloud_role_sync_jobs(
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudRoleSyncJobConfig>, Error> {
+    let (config, digest) = pbs_config::cloud_job::config()?;
+
+    let list = config.convert_to_typed_array::<CloudRoleSyncJobConfig>("role-sync")?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            job: {
+                type: CloudRoleSyncJobConfig,
+                flatten: true,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudRoleSyncJobConfig,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["access", "acl"], PRIV_PERMISSIONS_MODIFY, false),
+    },
+)]
+/// Create a new cloud role sync job.
+///
+/// With 'validate' the job is only checked and returned, but not saved.
+pub fn create_cloud_role_sync_job(
+    job: CloudRoleSyncJobConfig,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudRoleSyncJobConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, _digest) = pbs_config::cloud_job::config()?;
+
+    if config.sections.get(&job.id).is_some() {
+        param_bail!("id", "job '{}' already exists.", job.id);
+    }
+
+    check_role_sync_job(&job)?;
+
+    if validate {
+        return Ok(Some(job));
+    }
+
+    config.set_data(&job.id, "role-sync", &job)?;
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(
+        &auth_id,
+        "role-sync",
+        &job.id,
+        None,
+        section_data(&config, &job.id).as_ref(),
+    );
+
+    crate::server::jobstate::create_state_file("cloud-role-sync-job", &job.id)?;
+
+    Ok(None)
+}
+
+#[api(
+   input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: CloudRoleSyncJobConfig },
+    access: {
+        permission: &Permission::Privilege(&["access", "acl"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// Read a cloud role sync job configuration.
+pub fn read_cloud_role_sync_job(
+    id: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<CloudRoleSyncJobConfig, Error> {
+    let (config, digest) = pbs_config::cloud_job::config()?;
+
+    let job = config.lookup("role-sync", &id)?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(job)
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the comment property.
+    Comment,
+    /// Delete the job schedule.
+    Schedule,
+    /// Unset the disable flag.
+    Disable,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            update: {
+                type: CloudRoleSyncJobConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudRoleSyncJobConfig,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["access", "acl"], PRIV_PERMISSIONS_MODIFY, false),
+    },
+)]
+/// Update the cloud role sync job
+///
+/// With 'validate' the updated job is only checked and returned, but not saved.
+pub fn update_cloud_role_sync_job(
+    id: String,
+    update: CloudRoleSyncJobConfigUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudRoleSyncJobConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
+
+    let mut data: CloudRoleSyncJobConfig = config.lookup("role-sync", &id)?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Comment => {
+                    data.comment = None;
+                }
+                DeletableProperty::Schedule => {
+                    data.schedule = None;
+                }
+                DeletableProperty::Disable => {
+                    data.disable = false;
+                }
+            }
+        }
+    }
+
+    if let Some(realm) = update.realm {
+        data.realm = realm;
+    }
+    if let Some(mapping) = update.mapping {
+        data.mapping = mapping;
+    }
+
+    check_role_sync_job(&data)?;
+
+    let schedule_changed = data.schedule != update.schedule;
+    if update.schedule.is_some() {
+        data.schedule = update.schedule;
+    }
+
+    if let Some(comment) = update.comment {
+        let comment = comment.trim();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment.to_string());
+        }
+    }
+
+    if let Some(value) = update.disable {
+        data.disable = value;
+    }
+
+    if validate {
+        return Ok(Some(data));
+    }
+
+    let old = section_data(&config, &id);
+
+    config.set_data(&id, "role-sync", &data)?;
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(
+        &auth_id,
+        "role-sync",
+        &id,
+        old.as_ref(),
+        section_data(&config, &id).as_ref(),
+    );
+
+    if schedule_changed {
+        crate::server::jobstate::update_job_last_run_time("cloud-role-sync-job", &id)?;
+    }
+
+    Ok(None)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["access", "acl"], PRIV_PERMISSIONS_MODIFY, false),
+    },
+)]
+/// Remove a cloud role sync job configuration
+///
+/// Roles granted by the job are kept in the ACL, but not managed anymore.
+pub fn delete_cloud_role_sync_job(
+    id: String,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let old = match config.lookup::<CloudRoleSyncJobConfig>("role-sync", &id) {
+        Ok(_job) => config.sections.remove(&id).map(|(_, data)| data),
+        Err(_) => {
+            http_bail!(NOT_FOUND, "job '{}' does not exist.", id)
+        }
+    };
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(&auth_id, "role-sync", &id, old.as_ref(), None);
+
+    crate::server::jobstate::remove_state_file("cloud-role-sync-job", &id)?;
+    resume_job(CLOUD_STATUS_DIR, &id)?;
+    remove_role_grants(CLOUD_STATUS_DIR, &id)?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_CLOUD_ROLE_SYNC_JOB)
+    .put(&API_METHOD_UPDATE_CLOUD_ROLE_SYNC_JOB)
+    .delete(&API_METHOD_DELETE_CLOUD_ROLE_SYNC_JOB);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_CLOUD_ROLE_SYNC_JOBS)
+    .post(&API_METHOD_CREATE_CLOUD_ROLE_SYNC_JOB)
+    .match_all("id", &ITEM_ROUTER);
@@ -0,0 +1,821 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::{bail, format_err, Error};
+use hex::FromHex;
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    Authid, CloudBackupJobConfig, CloudProvider, CloudPruneJobConfig, CloudTarget,
+    CloudTargetConfig, CloudTargetConfigUpdater, CloudTargetWithoutSecret,
+    CloudVerificationJobConfig, CLOUD_CONFIG_VALIDATE_SCHEMA, CLOUD_SECRET_KEY_SCHEMA,
+    CLOUD_TAG_SCHEMA, CLOUD_TARGET_NAME_SCHEMA, PRIV_CLOUD_AUDIT, PRIV_CLOUD_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+
+use pbs_config::CachedUserInfo;
+
+use crate::cloud::backend::{cloud_proxy_config, open_backend, S3Backend};
+use crate::cloud::config_history::{record_config_change, save_section, section_data};
+use crate::cloud::encryption_keys::load_key_configs;
+
+/// Check that all namespace encryption keys exist
+fn check_namespace_keys(config: &CloudTargetConfig) -> Result<(), Error> {
+    let items = config.namespace_keys()?;
+    if items.is_empty() {
+        return Ok(());
+    }
+    let (key_map, _digest) = load_key_configs()?;
+    for item in items {
+        let fingerprint = item.key.parse()?;
+        if !key_map.contains_key(&fingerprint) {
+            bail!("cloud encryption key '{}' does not exist", fingerprint);
+        }
+    }
+    Ok(())
+}
+
+/// Check that the quotas are valid
+fn check_quotas(config: &CloudTargetConfig) -> Result<(), Error> {
+    if let Err(err) = config.quotas() {
+        param_bail!("quota", err);
+    }
+    Ok(())
+}
+
+/// Check that the standby remote exists
+fn check_standby_remote(config: &CloudTargetConfig) -> Result<(), Error> {
+    if let Some(ref remote) = config.standby_remote {
+        let (remote_config, _digest) = pbs_config::remote::config()?;
+        if !remote_config.sections.contains_key(remote) {
+            param_bail!("standby-remote", "remote '{}' does not exist.", remote);
+        }
+    }
+    Ok(())
+}
+
+/// Connect to the target and query its capabilities (checks the credentials)
+///
+/// The proxy settings are checked as well, failures through a proxy name it.
+fn check_target_connection(target: &CloudTarget) -> Result<(), Error> {
+    let proxy = match target.config.provider {
+        CloudProvider::S3 => {
+            let endpoint = S3Backend::configured_endpoint(&target.config);
+            cloud_proxy_config(target, &endpoint)?
+        }
+        CloudProvider::Local => None,
+    };
+
+    let backend = open_backend(target)?;
+    backend.capabilities().map_err(|err| match proxy {
+        Some(proxy) => format_err!(
+            "connection check via proxy {}:{} failed - {}",
+            proxy.host,
+            proxy.port,
+            err
+        ),
+        None => format_err!("connection check failed - {}", err),
+    })?;
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            tag: {
+                schema: CLOUD_TAG_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "The list of configured cloud targets (with config digest).",
+        type: Array,
+        items: { type: CloudTargetWithoutSecret },
+    },
+    access: {
+        description: "List configured cloud targets filtered by Cloud.Audit privileges",
+        permission: &Permission::Anybody,
+    },
+)]
+/// List all cloud targets
+pub fn list_cloud_targets(
+    tag: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudTargetWithoutSecret>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let (config, digest) = pbs_config::cloud::config()?;
+
+    // Note: This removes the secret key (we do not want to return it).
+    let list: Vec<CloudTargetWithoutSecret> = config.convert_to_typed_array("target")?;
+
+    let list = list
+        .into_iter()
+        .filter(|target| {
+            let privs = user_info.lookup_privs(&auth_id, &["cloud", "target", &target.name]);
+            privs & PRIV_CLOUD_AUDIT != 0
+                && tag
+                    .as_deref()
+                    .map_or(true, |tag| target.config.has_tag(tag))
+        })
+        .collect();
+
+    rpcenv["digest"] = hex::encode(digest).into();
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            config: {
+                type: CloudTargetConfig,
+                flatten: true,
+            },
+            "secret-key": {
+                // We expect the plain secret here (not base64 encoded)
+                optional: true,
+                schema: CLOUD_SECRET_KEY_SCHEMA,
+            },
+            "check-connection": {
+                description: "Check the connection and credentials before saving.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudTargetWithoutSecret,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Create new cloud target.
+///
+/// With 'validate' the target is only checked and returned, but not saved.
+pub fn create_cloud_target(
+    name: String,
+    config: CloudTargetConfig,
+    secret_key: Option<String>,
+    check_connection: bool,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudTargetWithoutSecret>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud::lock_config()?;
+
+    let (mut section_config, _digest) = pbs_config::cloud::config()?;
+
+    if section_config.sections.get(&name).is_some() {
+        param_bail!("name", "cloud target '{}' already exists.", name);
+    }
+
+    config.check_provider_properties()?;
+    check_namespace_keys(&config)?;
+    check_quotas(&config)?;
+    check_standby_remote(&config)?;
+
+    let target = CloudTarget {
+        name: name.clone(),
+        secret_key: secret_key.unwrap_or_default(),
+        config,
+    };
+
+    if check_connection {
+        check_target_connection(&target)?;
+    }
+
+    if !save_section(
+        &mut section_config,
+        "target",
+        &name,
+        &target,
+        validate,
+        pbs_config::cloud::save_config,
+    )? {
+        return Ok(Some(CloudTargetWithoutSecret {
+            name,
+            config: target.config,
+        }));
+    }
+
+    record_config_change(
+        &auth_id,
+        "target",
+        &name,
+        None,
+        section_data(&section_config, &name).as_ref(),
+    );
+
+    Ok(None)
+}
+
+#[api(
+   input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: { type: CloudTargetWithoutSecret },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_AUDIT, false),
+    }
+)]
+/// Read cloud target configuration data.
+pub fn read_cloud_target(
+    name: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<CloudTargetWithoutSecret, Error> {
+    let (config, digest) = pbs_config::cloud::config()?;
+    let data: CloudTargetWithoutSecret = config.lookup("target", &name)?;
+    rpcenv["digest"] = hex::encode(digest).into();
+    Ok(data)
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the comment property.
+    Comment,
+    /// Delete the endpoint property.
+    Endpoint,
+    /// Delete the region property.
+    Region,
+    /// Delete all alternate endpoints.
+    AlternateEndpoint,
+    /// Delete all failover endpoints.
+    FailoverEndpoint,
+    /// Delete the bucket property.
+    Bucket,
+    /// Delete the prefix property.
+    Prefix,
+    /// Delete the path property.
+    Path,
+    /// Delete the access-key property (and the secret key).
+    AccessKey,
+    /// Delete the credential-process property.
+    CredentialProcess,
+    /// Delete the path-style property.
+    PathStyle,
+    /// Delete the transfer-acceleration property.
+    TransferAcceleration,
+    /// Delete the download-host property.
+    DownloadHost,
+    /// Delete the metadata-timeout property.
+    MetadataTimeout,
+    /// Delete the data-timeout property.
+    DataTimeout,
+    /// Delete the user-agent property.
+    UserAgent,
+    /// Delete the proxy property.
+    Proxy,
+    /// Delete the no-proxy property.
+    NoProxy,
+    /// Delete the ip-family property.
+    IpFamily,
+    /// Delete the source-address property.
+    SourceAddress,
+    /// Delete the source-address6 property.
+    SourceAddress6,
+    /// Delete the source-interface property.
+    SourceInterface,
+    /// Delete the request-tagging property.
+    RequestTagging,
+    /// Delete the egress-budget property.
+    EgressBudget,
+    /// Delete all namespace encryption keys.
+    NamespaceKey,
+    /// Delete all quotas.
+    Quota,
+    /// Delete the delete-protection property.
+    DeleteProtection,
+    /// Delete all object tags.
+    ObjectTag,
+    /// Delete the access-log-prefix property.
+    AccessLogPrefix,
+    /// Delete the standby-remote property.
+    StandbyRemote,
+    /// Delete the parity-group property.
+    ParityGroup,
+    /// Delete the health-check property.
+    HealthCheck,
+    /// Delete the write-back property.
+    WriteBack,
+    /// Delete the write-back-spool-size property.
+    WriteBackSpoolSize,
+    /// Delete the trash-retention property.
+    TrashRetention,
+    /// Delete the upload-checksums property.
+    UploadChecksums,
+    /// Delete the event-topic property.
+    EventTopic,
+    /// Delete the event-queue property.
+    EventQueue,
+    /// Delete the event-credentials property.
+    EventCredentials,
+    /// Delete the change-queue property.
+    ChangeQueue,
+    /// Delete the share-max-ttl property.
+    ShareMaxTtl,
+    /// Delete all tags.
+    Tags,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            update: {
+                type: CloudTargetConfigUpdater,
+                flatten: true,
+            },
+            "secret-key": {
+                // We expect the plain secret here (not base64 encoded)
+                optional: true,
+                schema: CLOUD_SECRET_KEY_SCHEMA,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+            "check-connection": {
+                description: "Check the connection and credentials before saving.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudTargetWithoutSecret,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Update cloud target configuration.
+///
+/// With 'validate' the updated target is only checked and returned, but not saved.
+#[allow(clippy::too_many_arguments)]
+pub fn update_cloud_target(
+    name: String,
+    update: CloudTargetConfigUpdater,
+    secret_key: Option<String>,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+    check_connection: bool,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudTargetWithoutSecret>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let mut data: CloudTarget = config.lookup("target", &name)?;
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Comment => {
+                    data.config.comment = None;
+                }
+                DeletableProperty::Endpoint => {
+                    data.config.endpoint = None;
+                }
+                DeletableProperty::Region => {
+                    data.config.region = None;
+                }
+                DeletableProperty::AlternateEndpoint => {
+                    data.config.alternate_endpoint = None;
+                }
+                DeletableProperty::FailoverEndpoint => {
+                    data.config.failover_endpoint = None;
+                }
+                DeletableProperty::Bucket => {
+                    data.config.bucket = None;
+                }
+                DeletableProperty::Prefix => {
+                    data.config.prefix = None;
+                }
+                DeletableProperty::Path => {
+                    data.config.path = None;
+                }
+                DeletableProperty::AccessKey => {
+                    data.config.access_key = None;
+                    data.secret_key = String::new();
+                }
+                DeletableProperty::CredentialProcess => {
+                    data.config.credential_process = None;
+                }
+                DeletableProperty::PathStyle => {
+                    data.config.path_style = None;
+                }
+                DeletableProperty::TransferAcceleration => {
+                    data.config.transfer_acceleration = None;
+                }
+                DeletableProperty::DownloadHost => {
+                    data.config.download_host = None;
+                }
+                DeletableProperty::MetadataTimeout => {
+                    data.config.metadata_timeout = None;
+                }
+                DeletableProperty::DataTimeout => {
+                    data.config.data_timeout = None;
+                }
+                DeletableProperty::UserAgent => {
+                    data.config.user_agent = None;
+                }
+                DeletableProperty::Proxy => {
+                    data.config.proxy = None;
+                }
+                DeletableProperty::NoProxy => {
+                    data.config.no_proxy = None;
+                }
+                DeletableProperty::IpFamily => {
+                    data.config.ip_family = None;
+                }
+                DeletableProperty::SourceAddress => {
+                    data.config.source_address = None;
+                }
+                DeletableProperty::SourceAddress6 => {
+                    data.config.source_address6 = None;
+                }
+                DeletableProperty::SourceInterface => {
+                    data.config.source_interface = None;
+                }
+                DeletableProperty::RequestTagging => {
+                    data.config.request_tagging = None;
+                }
+                DeletableProperty::EgressBudget => {
+                    data.config.egress_budget = None;
+                }
+                DeletableProperty::NamespaceKey => {
+                    data.config.namespace_key = None;
+                }
+                DeletableProperty::Quota => {
+                    data.config.quota = None;
+                }
+                DeletableProperty::DeleteProtection => {
+                    data.config.delete_protection = None;
+                }
+                DeletableProperty::ObjectTag => {
+                    data.config.object_tag = None;
+                }
+                DeletableProperty::AccessLogPrefix => {
+                    data.config.access_log_prefix = None;
+                }
+                DeletableProperty::StandbyRemote => {
+                    data.config.standby_remote = None;
+                }
+                DeletableProperty::ParityGroup => {
+                    data.config.parity_group = None;
+                }
+                DeletableProperty::HealthCheck => {
+                    data.config.health_check = None;
+                }
+                DeletableProperty::WriteBack => {
+                    data.config.write_back = None;
+                }
+                DeletableProperty::WriteBackSpoolSize => {
+                    data.config.write_back_spool_size = None;
+                }
+                DeletableProperty::TrashRetention => {
+                    data.config.trash_retention = None;
+                }
+                DeletableProperty::UploadChecksums => {
+                    data.config.upload_checksums = None;
+                }
+                DeletableProperty::EventTopic => {
+                    data.config.event_topic = None;
+                }
+                DeletableProperty::EventQueue => {
+                    data.config.event_queue = None;
+                }
+                DeletableProperty::EventCredentials => {
+                    data.config.event_credentials = None;
+                }
+                DeletableProperty::ChangeQueue => {
+                    data.config.change_queue = None;
+                }
+                DeletableProperty::ShareMaxTtl => {
+                    data.config.share_max_ttl = None;
+                }
+                DeletableProperty::Tags => {
+                    data.config.tags = None;
+                }
+            }
+        }
+    }
+
+    if let Some(comment) = update.comment {
+        let comment = comment.trim().to_string();
+        if comment.is_empty() {
+            data.config.comment = None;
+        } else {
+            data.config.comment = Some(comment);
+        }
+    }
+    if update.endpoint.is_some() {
+        data.config.endpoint = update.endpoint;
+    }
+    if update.region.is_some() {
+        data.config.region = update.region;
+    }
+    if update.alternate_endpoint.is_some() {
+        data.config.alternate_endpoint = update.alternate_endpoint;
+    }
+    if update.failover_endpoint.is_some() {
+        data.config.failover_endpoint = update.failover_endpoint;
+    }
+    if update.bucket.is_some() {
+        data.config.bucket = update.bucket;
+    }
+    if update.prefix.is_some() {
+        data.config.prefix = update.prefix;
+    }
+    if update.path.is_some() {
+        data.config.path = update.path;
+    }
+    if update.access_key.is_some() {
+        data.config.access_key = update.access_key;
+    }
+    if update.credential_process.is_some() {
+        data.config.credential_process = update.credential_process;
+    }
+    if update.path_style.is_some() {
+        data.config.path_style = update.path_style;
+    }
+    if update.transfer_acceleration.is_some() {
+        data.config.transfer_acceleration = update.transfer_acceleration;
+    }
+    if update.download_host.is_some() {
+        data.config.download_host = update.download_host;
+    }
+    if update.metadata_timeout.is_some() {
+        data.config.metadata_timeout = update.metadata_timeout;
+    }
+    if update.data_timeout.is_some() {
+        data.config.data_timeout = update.data_timeout;
+    }
+    if update.user_agent.is_some() {
+        data.config.user_agent = update.user_agent;
+    }
+    if update.proxy.is_some() {
+        data.config.proxy = update.proxy;
+    }
+    if update.no_proxy.is_some() {
+        data.config.no_proxy = update.no_proxy;
+    }
+    if update.ip_family.is_some() {
+        data.config.ip_family = update.ip_family;
+    }
+    if update.source_address.is_some() {
+        data.config.source_address = update.source_address;
+    }
+    if update.source_address6.is_some() {
+        data.config.source_address6 = update.source_address6;
+    }
+    if update.source_interface.is_some() {
+        data.config.source_interface = update.source_interface;
+    }
+    if update.request_tagging.is_some() {
+        data.config.request_tagging = update.request_tagging;
+    }
+    if update.egress_budget.is_some() {
+        data.config.egress_budget = update.egress_budget;
+    }
+    if update.namespace_key.is_some() {
+        data.config.namespace_key = update.namespace_key;
+    }
+    if update.quota.is_some() {
+        data.config.quota = update.quota;
+    }
+    if update.delete_protection.is_some() {
+        data.config.delete_protection = update.delete_protection;
+    }
+    if update.object_tag.is_some() {
+        data.config.object_tag = update.object_tag;
+    }
+    if update.access_log_prefix.is_some() {
+        data.config.access_log_prefix = update.access_log_prefix;
+    }
+    if update.standby_remote.is_some() {
+        data.config.standby_remote = update.standby_remote;
+    }
+    if update.parity_group.is_some() {
+        data.config.parity_group = update.parity_group;
+    }
+    if update.health_check.is_some() {
+        data.config.health_check = update.health_check;
+    }
+    if update.write_back.is_some() {
+        data.config.write_back = update.write_back;
+    }
+    if update.write_back_spool_size.is_some() {
+        data.config.write_back_spool_size = update.write_back_spool_size;
+    }
+    if update.trash_retention.is_some() {
+        data.config.trash_retention = update.trash_retention;
+    }
+    if update.upload_checksums.is_some() {
+        data.config.upload_checksums = update.upload_checksums;
+    }
+    if update.event_topic.is_some() {
+        data.config.event_topic = update.event_topic;
+    }
+    if update.event_queue.is_some() {
+        data.config.event_queue = update.event_queue;
+    }
+    if update.event_credentials.is_some() {
+        data.config.event_credentials = update.event_credentials;
+    }
+    if update.change_queue.is_some() {
+        data.config.change_queue = update.change_queue;
+    }
+    if update.share_max_ttl.is_some() {
+        data.config.share_max_ttl = update.share_max_ttl;
+    }
+    if update.tags.is_some() {
+        data.config.tags = update.tags;
+    }
+    if let Some(secret_key) = secret_key {
+        data.secret_key = secret_key;
+    }
+
+    data.config.check_provider_properties()?;
+    check_namespace_keys(&data.config)?;
+    check_quotas(&data.config)?;
+    check_standby_remote(&data.config)?;
+
+    if check_connection {
+        check_target_connection(&data)?;
+    }
+
+    let old = section_data(&config, &name);
+
+    if !save_section(
+        &mut config,
+        "target",
+        &name,
+        &data,
+        validate,
+        pbs_config::cloud::save_config,
+    )? {
+        return Ok(Some(CloudTargetWithoutSecret {
+            name,
+            config: data.config,
+        }));
+    }
+
+    record_config_change(
+        &auth_id,
+        "target",
+        &name,
+        old.as_ref(),
+        section_data(&config, &name).as_ref(),
+    );
+
+    Ok(None)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "target", "{name}"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Remove a cloud target from the configuration file.
+pub fn delete_cloud_target(
+    name: String,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let (job_config, _) = pbs_config::cloud_job::config()?;
+
+    let job_list: Vec<CloudBackupJobConfig> = job_config.convert_to_typed_array("backup")?;
+    for job in job_list {
+        if job.setup.target.as_deref() == Some(name.as_str()) {
+            param_bail!(
+                "name",
+                "cloud target '{}' is used by cloud backup job '{}' (datastore '{}')",
+                name,
+                job.id,
+                job.setup.store
+            );
+        }
+    }
+
+    let verify_list: Vec<CloudVerificationJobConfig> =
+        job_config.convert_to_typed_array("verify")?;
+    let prune_list: Vec<CloudPruneJobConfig> = job_config.convert_to_typed_array("prune")?;
+    let target_jobs = verify_list
+        .iter()
+        .map(|job| (&job.id, &job.target))
+        .chain(prune_list.iter().map(|job| (&job.id, &job.target)));
+    for (id, target) in target_jobs {
+        if *target == name {
+            param_bail!(
+                "name",
+                "cloud target '{}' is used by cloud job '{}'",
+                name,
+                id
+            );
+        }
+    }
+
+    // jobs without target of their own use the mapping
+    for mapping in pbs_config::cloud_mapping::mapping_list()? {
+        if mapping.target == name {
+            param_bail!(
+                "name",
+                "cloud target '{}' is used by cloud mapping '{}' (datastore '{}')",
+                name,
+                mapping.id,
+                mapping.store
+            );
+        }
+    }
+
+    let _lock = pbs_config::cloud::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let old = match config.sections.remove(&name) {
+        Some((_, data)) => data,
+        None => http_bail!(NOT_FOUND, "cloud target '{}' does not exist.", name),
+    };
+
+    pbs_config::cloud::save_config(&config)?;
+
+    record_config_change(&auth_id, "target", &name, Some(&old), None);
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_CLOUD_TARGET)
+    .put(&API_METHOD_UPDATE_CLOUD_TARGET)
+    .delete(&API_METHOD_DELETE_CLOUD_TARGET);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_CLOUD_TARGETS)
+    .post(&API_METHOD_CREATE_CLOUD_TARGET)
+    .match_all("name", &ITEM_ROUTER);
@@ -0,0 +1,369 @@
+use ::serde::{Deserialize, Serialize};
+use anyhow::Error;
+use hex::FromHex;
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
+
+use pbs_api_types::{
+    Authid, CloudVerificationJobConfig, CloudVerificationJobConfigUpdater,
+    CLOUD_CONFIG_VALIDATE_SCHEMA, JOB_ID_SCHEMA, PRIV_CLOUD_AUDIT, PRIV_CLOUD_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA,
+};
+
+use crate::cloud::config_history::{record_config_change, section_data};
+use crate::cloud::job_pause::resume_job;
+use crate::cloud::CLOUD_STATUS_DIR;
+
+#[api(
+    returns: {
+        description: "List configured jobs.",
+        type: Array,
+        items: { type: CloudVerificationJobConfig },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// List all cloud verification jobs
+pub fn list_cloud_verify_jobs(
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<CloudVerificationJobConfig>, Error> {
+    let (config, digest) = pbs_config::cloud_job::config()?;
+
+    let list = config.convert_to_typed_array::<CloudVerificationJobConfig>("verify")?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            job: {
+                type: CloudVerificationJobConfig,
+                flatten: true,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudVerificationJobConfig,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Create a new cloud verification job.
+///
+/// With 'validate' the job is only checked and returned, but not saved.
+pub fn create_cloud_verify_job(
+    job: CloudVerificationJobConfig,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudVerificationJobConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, _digest) = pbs_config::cloud_job::config()?;
+
+    if config.sections.get(&job.id).is_some() {
+        param_bail!("id", "job '{}' already exists.", job.id);
+    }
+
+    if let Err(err) = pbs_config::cloud::lookup_target(&job.target) {
+        param_bail!("target", err);
+    }
+
+    if validate {
+        return Ok(Some(job));
+    }
+
+    config.set_data(&job.id, "verify", &job)?;
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(
+        &auth_id,
+        "verify",
+        &job.id,
+        None,
+        section_data(&config, &job.id).as_ref(),
+    );
+
+    crate::server::jobstate::create_state_file("cloud-verify-job", &job.id)?;
+
+    Ok(None)
+}
+
+#[api(
+   input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: CloudVerificationJobConfig },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_AUDIT, false),
+    },
+)]
+/// Read a cloud verification job configuration.
+pub fn read_cloud_verify_job(
+    id: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<CloudVerificationJobConfig, Error> {
+    let (config, digest) = pbs_config::cloud_job::config()?;
+
+    let job = config.lookup("verify", &id)?;
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(job)
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the comment property.
+    Comment,
+    /// Delete the job schedule.
+    Schedule,
+    /// Delete the 'ignore-verified' property.
+    IgnoreVerified,
+    /// Delete the 'outdated-after' property.
+    OutdatedAfter,
+    /// Delete the namespace property, verify the whole datastore.
+    Ns,
+    /// Delete the 'max-depth' property, recurse into all namespaces.
+    MaxDepth,
+    /// Unset the disable flag.
+    Disable,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            update: {
+                type: CloudVerificationJobConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+            validate: {
+                schema: CLOUD_CONFIG_VALIDATE_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: CloudVerificationJobConfig,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Update the cloud verification job
+///
+/// With 'validate' the updated job is only checked and returned, but not saved.
+pub fn update_cloud_verify_job(
+    id: String,
+    update: CloudVerificationJobConfigUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+    validate: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<CloudVerificationJobConfig>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
+
+    let mut data: CloudVerificationJobConfig = config.lookup("verify", &id)?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let old_schedule = data.schedule.clone();
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Comment => {
+                    data.comment = None;
+                }
+                DeletableProperty::Schedule => {
+                    data.schedule = None;
+                }
+                DeletableProperty::IgnoreVerified => {
+                    data.ignore_verified = None;
+                }
+                DeletableProperty::OutdatedAfter => {
+                    data.outdated_after = None;
+                }
+                DeletableProperty::Ns => {
+                    data.ns = None;
+                }
+                DeletableProperty::MaxDepth => {
+                    data.max_depth = None;
+                }
+                DeletableProperty::Disable => {
+                    data.disable = false;
+                }
+            }
+        }
+    }
+
+    if let Some(target) = update.target {
+        if let Err(err) = pbs_config::cloud::lookup_target(&target) {
+            param_bail!("target", err);
+        }
+        data.target = target;
+    }
+    if let Some(store) = update.store {
+        data.store = store;
+    }
+    if update.ignore_verified.is_some() {
+        data.ignore_verified = update.ignore_verified;
+    }
+    if update.outdated_after.is_some() {
+        data.outdated_after = update.outdated_after;
+    }
+    if update.schedule.is_some() {
+        data.schedule = update.schedule;
+    }
+    if update.ns.is_some() {
+        data.ns = update.ns;
+    }
+    if update.max_depth.is_some() {
+        data.max_depth = update.max_depth;
+    }
+
+    if let Some(comment) = update.comment {
+        let comment = comment.trim();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment.to_string());
+        }
+    }
+
+    if let Some(value) = update.disable {
+        data.disable = value;
+    }
+
+    if validate {
+        return Ok(Some(data));
+    }
+
+    let schedule_changed = data.schedule != old_schedule;
+
+    let old = section_data(&config, &id);
+
+    config.set_data(&id, "verify", &data)?;
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(
+        &auth_id,
+        "verify",
+        &id,
+        old.as_ref(),
+        section_data(&config, &id).as_ref(),
+    );
+
+    if schedule_changed {
+        crate::server::jobstate::update_job_last_run_time("cloud-verify-job", &id)?;
+    }
+
+    Ok(None)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            id: {
+                schema: JOB_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["cloud", "job"], PRIV_CLOUD_MODIFY, false),
+    },
+)]
+/// Remove a cloud verification job configuration
+pub fn delete_cloud_verify_job(
+    id: String,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::cloud_job::lock()?;
+
+    let (mut config, expected_digest) = pbs_config::cloud_job::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let old = match config.lookup::<CloudVerificationJobConfig>("verify", &id) {
+        Ok(_job) => config.sections.remove(&id).map(|(_, data)| data),
+        Err(_) => {
+            http_bail!(NOT_FOUND, "job '{}' does not exist.", id)
+        }
+    };
+
+    pbs_config::cloud_job::save_config(&config)?;
+
+    record_config_change(&auth_id, "verify", &id, old.as_ref(), None);
+
+    crate::server::jobstate::remove_state_file("cloud-verify-job", &id)?;
+    resume_job(CLOUD_STATUS_DIR, &id)?;
+
+    Ok(())
+}
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_CLOUD_VERIFY_JOB)
+    .put(&API_METHOD_UPDATE_CLOUD_VERIFY_JOB)
+    .delete(&API_METHOD_DELETE_CLOUD_VERIFY_JOB);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_CLOUD_VERIFY_JOBS)
+    .post(&API_METHOD_CREATE_CLOUD_VERIFY_JOB)
+    .match_all("id", &ITEM_ROUTER);
@@ -226,6 +226,8 @@ pub enum DeletableProperty {
     Tuning,
     /// Delete the maintenance-mode property
     MaintenanceMode,
+    /// Delete the cloud-zfs-snapshot property
+    CloudZfsSnapshot,
 }
 
 #[api(
@@ -321,6 +323,9 @@ pub fn update_datastore(
                 DeletableProperty::MaintenanceMode => {
                     data.maintenance_mode = None;
                 }
+                DeletableProperty::CloudZfsSnapshot => {
+                    data.cloud_zfs_snapshot = None;
+                }
             }
         }
     }
@@ -393,6 +398,10 @@ pub fn update_datastore(
         data.maintenance_mode = update.maintenance_mode;
     }
 
+    if update.cloud_zfs_snapshot.is_some() {
+        data.cloud_zfs_snapshot = update.cloud_zfs_snapshot;
+    }
+
     config.set_data(&name, "datastore", &data)?;
 
     pbs_config::datastore::save_config(&config)?;
@@ -1,16 +1,565 @@
-use proxmox_router::list_subdirs_api_method;
-use proxmox_router::{Router, SubdirMap};
+use anyhow::{bail, format_err, Error};
+use hex::FromHex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment, SubdirMap};
+use proxmox_schema::{api, ObjectSchemaType};
+use proxmox_section_config::SectionConfigData;
 use proxmox_sortable_macro::sortable;
 
-pub mod influxdbhttp;
-pub mod influxdbudp;
+use pbs_api_types::{
+    CloudMetricsHttp, CloudMetricsUdp, MetricServerInfo, MetricServerType, CLOUD_BUCKET_SCHEMA,
+    CLOUD_ORGANIZATION_SCHEMA, HOST_PORT_SCHEMA, HTTP_URL_SCHEMA, METRIC_SERVER_ID_SCHEMA,
+    PRIV_SYS_AUDIT, PRIV_SYS_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA, SINGLE_LINE_COMMENT_SCHEMA,
+};
+use pbs_config::metrics;
+
+use crate::cloud::metrics::http::{spool_dir, HttpMetricSender};
+use crate::cloud::metrics::udp::UdpMetricSender;
+use crate::cloud::metrics::MetricPoint;
+use crate::cloud::CLOUD_STATUS_DIR;
+
+// parameters which are not server properties
+const NON_CONFIG_PARAMETERS: &[&str] = &["name", "type", "delete", "digest"];
+
+fn section_type(ty: MetricServerType) -> &'static str {
+    match ty {
+        MetricServerType::CloudUdp => "cloud-udp",
+        MetricServerType::CloudHttp => "cloud-http",
+    }
+}
+
+fn parse_section_type(section_type: &str) -> Result<MetricServerType, Error> {
+    match section_type {
+        "cloud-udp" => Ok(MetricServerType::CloudUdp),
+        "cloud-http" => Ok(MetricServerType::CloudHttp),
+        other if metrics::LEGACY_SECTION_TYPES.contains(&other) => {
+            bail!("metric server type '{}' is no longer supported", other)
+        }
+        other => bail!("unsupported metric server type '{}'", other),
+    }
+}
+
+/// Check that a server only has properties of its type
+pub fn check_server_properties(ty: MetricServerType, config: &Value) -> Result<(), Error> {
+    let object_schema = match ty {
+        MetricServerType::CloudUdp => CloudMetricsUdp::API_SCHEMA.unwrap_object_schema(),
+        MetricServerType::CloudHttp => CloudMetricsHttp::API_SCHEMA.unwrap_object_schema(),
+    };
+    if let Some(map) = config.as_object() {
+        for key in map.keys() {
+            if object_schema.lookup(key).is_none() {
+                bail!(
+                    "property '{}' is not supported by {} metric servers",
+                    key,
+                    section_type(ty)
+                );
+            }
+        }
+    }
+    match ty {
+        MetricServerType::CloudUdp => {
+            serde_json::from_value::<CloudMetricsUdp>(config.clone())?;
+        }
+        MetricServerType::CloudHttp => {
+            serde_json::from_value::<CloudMetricsHttp>(config.clone())?;
+        }
+    }
+    Ok(())
+}
+
+// validate the properties of a server and test the connection if enabled
+async fn check_server_config(ty: MetricServerType, config: &Value) -> Result<(), Error> {
+    check_server_properties(ty, config)?;
+
+    match ty {
+        MetricServerType::CloudUdp => {
+            let server: CloudMetricsUdp = serde_json::from_value(config.clone())?;
+            if server.enable {
+                UdpMetricSender::connect(&server).await?;
+            }
+        }
+        MetricServerType::CloudHttp => {
+            let server: CloudMetricsHttp = serde_json::from_value(config.clone())?;
+            HttpMetricSender::new(&server, CLOUD_STATUS_DIR)?;
+        }
+    }
+    Ok(())
+}
+
+/// Server configuration from the parameters of a create call
+pub fn new_server_config(name: &str, param: &Value) -> Value {
+    let mut config = Map::new();
+    for (key, value) in param.as_object().cloned().unwrap_or_default() {
+        if !NON_CONFIG_PARAMETERS.contains(&key.as_str()) {
+            config.insert(key, value);
+        }
+    }
+    config.insert("name".to_string(), name.into());
+    Value::Object(config)
+}
+
+/// Add a server to the metric server config
+pub fn add_metric_server(
+    metrics: &mut SectionConfigData,
+    name: &str,
+    ty: MetricServerType,
+    config: Value,
+) -> Result<(), Error> {
+    if metrics.sections.get(name).is_some() {
+        bail!("metric server '{}' already exists.", name);
+    }
+    metrics
+        .sections
+        .insert(name.to_string(), (section_type(ty).to_string(), config));
+    Ok(())
+}
+
+/// Apply the parameters of an update call to a server configuration
+pub fn update_server_config(
+    config: &mut Value,
+    delete: &[DeletableProperty],
+    param: &Value,
+) -> Result<(), Error> {
+    let map = config
+        .as_object_mut()
+        .ok_or_else(|| format_err!("invalid metric server configuration"))?;
+
+    for delete_prop in delete {
+        let key = match delete_prop {
+            DeletableProperty::Enable => "enable",
+            DeletableProperty::Mtu => "mtu",
+            DeletableProperty::Token => "token",
+            DeletableProperty::Bucket => "bucket",
+            DeletableProperty::Organization => "organization",
+            DeletableProperty::MaxBodySize => "max-body-size",
+            DeletableProperty::VerifyTls => "verify-tls",
+            DeletableProperty::Comment => "comment",
+        };
+        map.remove(key);
+    }
+
+    for (key, value) in param.as_object().cloned().unwrap_or_default() {
+        if NON_CONFIG_PARAMETERS.contains(&key.as_str()) {
+            continue;
+        }
+        if key == "comment" {
+            match value.as_str().map(str::trim) {
+                Some("") | None => map.remove("comment"),
+                Some(comment) => map.insert(key, comment.into()),
+            };
+            continue;
+        }
+        map.insert(key, value);
+    }
+    Ok(())
+}
+
+/// Remove a server from the metric server config
+pub fn remove_metric_server(metrics: &mut SectionConfigData, name: &str) -> Result<(), Error> {
+    if metrics.sections.remove(name).is_none() {
+        http_bail!(NOT_FOUND, "metric server '{}' does not exist.", name);
+    }
+    Ok(())
+}
+
+fn test_point() -> Result<MetricPoint, Error> {
+    Ok(MetricPoint::new(
+        "cloud_metrics_test",
+        proxmox_time::epoch_i64(),
+        json!({ "value": 1 }),
+    )?
+    .tag("object", "host")
+    .tag("host", proxmox_sys::nodename()))
+}
+
+#[api(
+    input: {
+        properties: {},
+    },
+    returns: {
+        description: "List of configured cloud metric servers.",
+        type: Array,
+        items: { type: MetricServerInfo },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// List configured cloud metric servers.
+pub fn list_metric_servers(
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<MetricServerInfo>, Error> {
+    let (config, digest) = metrics::config()?;
+
+    let mut list = Vec::new();
+    for (name, (section_type, data)) in config.sections.iter() {
+        let ty = match parse_section_type(section_type) {
+            Ok(ty) => ty,
+            Err(_) => continue,
+        };
+        let server = match ty {
+            MetricServerType::CloudUdp => &data["endpoint"],
+            MetricServerType::CloudHttp => &data["url"],
+        };
+        list.push(MetricServerInfo {
+            name: name.clone(),
+            ty,
+            enable: data["enable"].as_bool(),
+            server: server.as_str().unwrap_or_default().to_string(),
+            comment: data["comment"].as_str().map(String::from),
+        });
+    }
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: METRIC_SERVER_ID_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "The server configuration (without token), with its type.",
+        type: Object,
+        properties: {},
+        additional_properties: true,
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// Read a cloud metric server configuration.
+pub fn read_metric_server(name: String, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let (metrics, digest) = metrics::config()?;
+
+    let mut config = match metrics.sections.get(&name) {
+        Some((section_type, data)) => {
+            parse_section_type(section_type)?;
+            let mut data = data.clone();
+            data["type"] = section_type.clone().into();
+            data
+        }
+        None => http_bail!(NOT_FOUND, "metric server '{}' does not exist.", name),
+    };
+
+    if let Some(map) = config.as_object_mut() {
+        map.remove("token");
+    }
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(config)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: METRIC_SERVER_ID_SCHEMA,
+            },
+            "type": {
+                type: MetricServerType,
+            },
+            enable: {
+                type: bool,
+                optional: true,
+                default: true,
+            },
+            endpoint: {
+                schema: HOST_PORT_SCHEMA,
+                optional: true,
+            },
+            mtu: {
+                type: u16,
+                optional: true,
+                default: 1500,
+            },
+            url: {
+                schema: HTTP_URL_SCHEMA,
+                optional: true,
+            },
+            token: {
+                type: String,
+                optional: true,
+            },
+            bucket: {
+                schema: CLOUD_BUCKET_SCHEMA,
+                optional: true,
+            },
+            organization: {
+                schema: CLOUD_ORGANIZATION_SCHEMA,
+                optional: true,
+            },
+            "max-body-size": {
+                type: usize,
+                optional: true,
+                default: 50_000_000,
+            },
+            "verify-tls": {
+                type: bool,
+                optional: true,
+                default: true,
+            },
+            comment: {
+                optional: true,
+                schema: SINGLE_LINE_COMMENT_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Create a cloud metric server ('endpoint' for udp servers, 'url' for http servers).
+pub async fn create_metric_server(
+    name: String,
+    r#type: MetricServerType,
+    param: Value,
+) -> Result<(), Error> {
+    let config = new_server_config(&name, &param);
+
+    check_server_config(r#type, &config).await?;
+
+    let _lock = metrics::lock_config()?;
+
+    let (mut metrics, _digest) = metrics::config()?;
+
+    add_metric_server(&mut metrics, &name, r#type, config)?;
+
+    metrics::save_config(&metrics)?;
+
+    Ok(())
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the enable property.
+    Enable,
+    /// Delete the mtu property.
+    Mtu,
+    /// Delete the token property.
+    Token,
+    /// Delete the bucket property.
+    Bucket,
+    /// Delete the organization property.
+    Organization,
+    /// Delete the max-body-size property.
+    MaxBodySize,
+    /// Delete the verify-tls property.
+    VerifyTls,
+    /// Delete the comment property.
+    Comment,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: METRIC_SERVER_ID_SCHEMA,
+            },
+            enable: {
+                type: bool,
+                optional: true,
+            },
+            endpoint: {
+                schema: HOST_PORT_SCHEMA,
+                optional: true,
+            },
+            mtu: {
+                type: u16,
+                optional: true,
+            },
+            url: {
+                schema: HTTP_URL_SCHEMA,
+                optional: true,
+            },
+            token: {
+                type: String,
+                optional: true,
+            },
+            bucket: {
+                schema: CLOUD_BUCKET_SCHEMA,
+                optional: true,
+            },
+            organization: {
+                schema: CLOUD_ORGANIZATION_SCHEMA,
+                optional: true,
+            },
+            "max-body-size": {
+                type: usize,
+                optional: true,
+            },
+            "verify-tls": {
+                type: bool,
+                optional: true,
+            },
+            comment: {
+                optional: true,
+                schema: SINGLE_LINE_COMMENT_SCHEMA,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Update a cloud metric server configuration.
+pub async fn update_metric_server(
+    name: String,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+    param: Value,
+) -> Result<(), Error> {
+    let (metrics, expected_digest) = metrics::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let (ty, mut config) = match metrics.sections.get(&name) {
+        Some((section_type, data)) => (parse_section_type(section_type)?, data.clone()),
+        None => http_bail!(NOT_FOUND, "metric server '{}' does not exist.", name),
+    };
+    update_server_config(&mut config, &delete.unwrap_or_default(), &param)?;
+
+    check_server_config(ty, &config).await?;
+
+    let _lock = metrics::lock_config()?;
+
+    let (mut metrics, current_digest) = metrics::config()?;
+    crate::tools::detect_modified_configuration_file(&current_digest, &expected_digest)?;
+
+    metrics
+        .sections
+        .insert(name, (section_type(ty).to_string(), config));
+
+    metrics::save_config(&metrics)?;
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: METRIC_SERVER_ID_SCHEMA,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Remove a cloud metric server configuration, and its spooled data.
+pub fn delete_metric_server(name: String, digest: Option<String>) -> Result<(), Error> {
+    let _lock = metrics::lock_config()?;
+
+    let (mut metrics, expected_digest) = metrics::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    remove_metric_server(&mut metrics, &name)?;
+
+    metrics::save_config(&metrics)?;
+
+    let spool = spool_dir(CLOUD_STATUS_DIR, &name);
+    match std::fs::remove_dir_all(&spool) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            log::warn!("unable to remove metric spool {:?} - {}", spool, err);
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: METRIC_SERVER_ID_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&[], PRIV_SYS_MODIFY, false),
+    },
+)]
+/// Send a test data point to a metric server.
+pub async fn test_metric_server(name: String) -> Result<(), Error> {
+    let (config, _digest) = metrics::config()?;
+
+    let point = test_point()?;
+
+    let ty = match config.sections.get(&name) {
+        Some((section_type, _)) => parse_section_type(section_type)?,
+        None => http_bail!(NOT_FOUND, "metric server '{}' does not exist.", name),
+    };
+
+    match ty {
+        MetricServerType::CloudUdp => {
+            let server: CloudMetricsUdp = config.lookup("cloud-udp", &name)?;
+            let stats = UdpMetricSender::connect(&server).await?.send(&[point])?;
+            if stats.dropped > 0 {
+                bail!("socket to metric server '{}' is congested", name);
+            }
+        }
+        MetricServerType::CloudHttp => {
+            let server: CloudMetricsHttp = config.lookup("cloud-http", &name)?;
+            HttpMetricSender::new(&server, CLOUD_STATUS_DIR)?
+                .test(&point)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
 
 #[sortable]
-const SUBDIRS: SubdirMap = &sorted!([
-    ("influxdb-http", &influxdbhttp::ROUTER),
-    ("influxdb-udp", &influxdbudp::ROUTER),
-]);
+const ITEM_SUBDIRS: SubdirMap =
+    &sorted!([("test", &Router::new().post(&API_METHOD_TEST_METRIC_SERVER)),]);
+
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_METRIC_SERVER)
+    .post(&API_METHOD_CREATE_METRIC_SERVER)
+    .put(&API_METHOD_UPDATE_METRIC_SERVER)
+    .delete(&API_METHOD_DELETE_METRIC_SERVER)
+    .subdirs(ITEM_SUBDIRS);
 
 pub const ROUTER: Router = Router::new()
-    .get(&list_subdirs_api_method!(SUBDIRS))
-    .subdirs(SUBDIRS);
+    .get(&API_METHOD_LIST_METRIC_SERVERS)
+    .match_all("name", &ITEM_ROUTER);
@@ -7,6 +7,16 @@ use proxmox_sortable_macro::sortable;
 pub mod access;
 pub mod acme;
 pub mod changer;
+pub mod cloud_backup_job;
+pub mod cloud_backup_job_template;
+pub mod cloud_digest_job;
+pub mod cloud_encryption_keys;
+pub mod cloud_mapping;
+pub mod cloud_prune_job;
+pub mod cloud_replication_job;
+pub mod cloud_role_sync_job;
+pub mod cloud_target;
+pub mod cloud_verify_job;
 pub mod datastore;
 pub mod drive;
 pub mod media_pool;
@@ -24,6 +34,19 @@ const SUBDIRS: SubdirMap = &sorted!([
     ("access", &access::ROUTER),
     ("acme", &acme::ROUTER),
     ("changer", &changer::ROUTER),
+    ("cloud-backup-job", &cloud_backup_job::ROUTER),
+    (
+        "cloud-backup-job-template",
+        &cloud_backup_job_template::ROUTER
+    ),
+    ("cloud-digest-job", &cloud_digest_job::ROUTER),
+    ("cloud-encryption-keys", &cloud_encryption_keys::ROUTER),
+    ("cloud-mapping", &cloud_mapping::ROUTER),
+    ("cloud-prune-job", &cloud_prune_job::ROUTER),
+    ("cloud-replication-job", &cloud_replication_job::ROUTER),
+    ("cloud-role-sync-job", &cloud_role_sync_job::ROUTER),
+    ("cloud-target", &cloud_target::ROUTER),
+    ("cloud-verify-job", &cloud_verify_job::ROUTER),
     ("datastore", &datastore::ROUTER),
     ("drive", &drive::ROUTER),
     ("media-pool", &media_pool::ROUTER),
@@ -57,6 +57,8 @@ pub fn list_traffic_controls(
 pub fn create_traffic_control(config: TrafficControlRule) -> Result<(), Error> {
     let _lock = pbs_config::traffic_control::lock_config()?;
 
+    config.check_classification()?;
+
     let (mut section_config, _digest) = pbs_config::traffic_control::config()?;
 
     if section_config.sections.get(&config.name).is_some() {
@@ -116,6 +118,12 @@ pub enum DeletableProperty {
     Comment,
     /// Delete the timeframe property
     Timeframe,
+    /// Delete the network property.
+    Network,
+    /// Delete the cloud-target property.
+    CloudTarget,
+    /// Delete the endpoint property.
+    Endpoint,
 }
 
 // fixme: use  TrafficControlUpdater
@@ -187,6 +195,15 @@ pub fn update_traffic_control(
                 DeletableProperty::Timeframe => {
                     data.timeframe = None;
                 }
+                DeletableProperty::Network => {
+                    data.network = Vec::new();
+                }
+                DeletableProperty::CloudTarget => {
+                    data.cloud_target = None;
+                }
+                DeletableProperty::Endpoint => {
+                    data.endpoint = None;
+                }
             }
         }
     }
@@ -219,10 +236,18 @@ pub fn update_traffic_control(
     if let Some(network) = update.network {
         data.network = network;
     }
+    if update.cloud_target.is_some() {
+        data.cloud_target = update.cloud_target;
+    }
+    if update.endpoint.is_some() {
+        data.endpoint = update.endpoint;
+    }
     if update.timeframe.is_some() {
         data.timeframe = update.timeframe;
     }
 
+    data.check_classification()?;
+
     config.set_data(&name, "rule", &data)?;
 
     pbs_config::traffic_control::save_config(&config)?;
@@ -67,6 +67,14 @@ pub enum DeletableProperty {
     Description,
     /// Delete the task-log-max-days property
     TaskLogMaxDays,
+    /// Delete the cloud-config-history-days property
+    CloudConfigHistoryDays,
+    /// Delete the cloud-proxy property
+    CloudProxy,
+    /// Delete the cloud-no-proxy property
+    CloudNoProxy,
+    /// Delete the cloud-chunk-cache-size property
+    CloudChunkCacheSize,
 }
 
 #[api(
@@ -155,6 +163,18 @@ pub fn update_node_config(
                 DeletableProperty::TaskLogMaxDays => {
                     config.task_log_max_days = None;
                 }
+                DeletableProperty::CloudConfigHistoryDays => {
+                    config.cloud_config_history_days = None;
+                }
+                DeletableProperty::CloudProxy => {
+                    config.cloud_proxy = None;
+                }
+                DeletableProperty::CloudNoProxy => {
+                    config.cloud_no_proxy = None;
+                }
+                DeletableProperty::CloudChunkCacheSize => {
+                    config.cloud_chunk_cache_size = None;
+                }
             }
         }
     }
@@ -198,6 +218,18 @@ pub fn update_node_config(
     if update.task_log_max_days.is_some() {
         config.task_log_max_days = update.task_log_max_days;
     }
+    if update.cloud_config_history_days.is_some() {
+        config.cloud_config_history_days = update.cloud_config_history_days;
+    }
+    if update.cloud_proxy.is_some() {
+        config.cloud_proxy = update.cloud_proxy;
+    }
+    if update.cloud_no_proxy.is_some() {
+        config.cloud_no_proxy = update.cloud_no_proxy;
+    }
+    if update.cloud_chunk_cache_size.is_some() {
+        config.cloud_chunk_cache_size = update.cloud_chunk_cache_size;
+    }
 
     crate::config::node::save_config(&config)?;
 
@@ -148,7 +148,7 @@ fn check_job_store(upid: &UPID, store: &str) -> bool {
     false
 }
 
-fn check_task_access(auth_id: &Authid, upid: &UPID) -> Result<(), Error> {
+pub(crate) fn check_task_access(auth_id: &Authid, upid: &UPID) -> Result<(), Error> {
     let task_auth_id: Authid = upid.auth_id.parse()?;
     if auth_id == &task_auth_id
         || (task_auth_id.is_token() && &Authid::from(task_auth_id.user().clone()) == auth_id)
@@ -13,6 +13,7 @@ fn main() {
         .insert("inspect", inspect::inspect_commands())
         .insert("recover", recover::recover_commands())
         .insert("api", api::api_commands())
+        .insert("cloud", cloud::cloud_commands())
         .insert("diff", diff::diff_commands());
 
     let uid = nix::unistd::Uid::current();
@@ -433,6 +433,8 @@ async fn run() -> Result<(), Error> {
 
     let cmd_def = CliCommandMap::new()
         .insert("acl", acl_commands())
+        .insert("cloud-config", cloud_config_commands())
+        .insert("cloud-key", cloud_encryption_key_commands())
         .insert("datastore", datastore_commands())
         .insert("disk", disk_commands())
         .insert("dns", dns_commands())
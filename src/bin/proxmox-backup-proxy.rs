@@ -1,4 +1,5 @@
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, format_err, Context, Error};
@@ -13,7 +14,6 @@ use openssl::ssl::SslAcceptor;
 use serde_json::{json, Value};
 
 use proxmox_lang::try_block;
-use proxmox_metrics::MetricsData;
 use proxmox_router::{RpcEnvironment, RpcEnvironmentType};
 use proxmox_sys::fs::{CreateOptions, FileSystemInformation};
 use proxmox_sys::linux::procfs::{Loadavg, ProcFsMemInfo, ProcFsNetDev, ProcFsStat};
@@ -23,8 +23,8 @@ use proxmox_sys::{task_log, task_warn};
 use pbs_datastore::DataStore;
 
 use proxmox_rest_server::{
-    cleanup_old_tasks, cookie_from_header, rotate_task_log_archive, ApiConfig, Redirector,
-    RestEnvironment, RestServer, WorkerTask,
+    cleanup_old_tasks, cookie_from_header, rotate_task_log_archive, upid_log_path, ApiConfig,
+    Redirector, RestEnvironment, RestServer, WorkerTask,
 };
 
 use proxmox_backup::rrd_cache::{
@@ -43,8 +43,10 @@ use pbs_buildcfg::configdir;
 use proxmox_time::CalendarEvent;
 
 use pbs_api_types::{
-    Authid, DataStoreConfig, Operation, PruneJobConfig, SyncJobConfig, TapeBackupJobConfig,
-    VerificationJobConfig,
+    Authid, CloudBackupJobConfig, CloudDigestJobConfig, CloudHealthSample, CloudPruneJobConfig,
+    CloudReplicationJobConfig, CloudRoleSyncJobConfig, CloudTarget, CloudVerificationJobConfig,
+    DataStoreConfig, Operation, PruneJobConfig, Remote, SyncJobConfig, TapeBackupJobConfig,
+    VerificationJobConfig, UPID,
 };
 
 use proxmox_rest_server::daemon;
@@ -56,8 +58,41 @@ use proxmox_backup::tools::{
     PROXMOX_BACKUP_TCP_KEEPALIVE_TIME,
 };
 
+use proxmox_backup::api2::cloud::backup::do_cloud_backup_job;
+use proxmox_backup::api2::cloud::digest::{digest_schedule, do_cloud_digest_job};
+use proxmox_backup::api2::cloud::prune::do_cloud_prune_job;
+use proxmox_backup::api2::cloud::replication::do_cloud_replication_job;
+use proxmox_backup::api2::cloud::role_sync::do_cloud_role_sync_job;
+use proxmox_backup::api2::cloud::storage::start_staging_upload;
+use proxmox_backup::api2::cloud::verify::do_cloud_verify_job;
+use proxmox_backup::api2::config::remote::remote_client;
 use proxmox_backup::api2::pull::do_sync_job;
 use proxmox_backup::api2::tape::backup::do_tape_backup_job;
+use proxmox_backup::cloud::backend::open_backend;
+use proxmox_backup::cloud::catalog::CloudCatalog;
+use proxmox_backup::cloud::change_feed::{run_change_feed, ChangeQueue};
+use proxmox_backup::cloud::chunk_cache::node_cache_status;
+use proxmox_backup::cloud::dedup_stats::list_dedup_stats;
+use proxmox_backup::cloud::health::{
+    check_target_health, health_check_due, health_check_enabled, load_health_history,
+    record_health_sample,
+};
+use proxmox_backup::cloud::job_chain::{
+    cloud_job_worker_type, due_chained_jobs, save_chained_run, ChainedRun,
+};
+use proxmox_backup::cloud::job_pause::job_paused;
+use proxmox_backup::cloud::job_retry::{load_job_retries, next_retry, record_retry, RetryOptions};
+use proxmox_backup::cloud::job_splay::{next_splayed_run, splay_offset};
+use proxmox_backup::cloud::metrics::udp;
+use proxmox_backup::cloud::metrics::{MetricPoint, MetricSenders};
+use proxmox_backup::cloud::request_trace::prune_request_traces;
+use proxmox_backup::cloud::staging::{staging_dir, StagingSpool};
+use proxmox_backup::cloud::standby::{self, StandbyDelta};
+use proxmox_backup::cloud::task_checkpoint::{
+    load_checkpoints, CloudJobCheckpoint, MAX_RESUME_ATTEMPTS,
+};
+use proxmox_backup::cloud::task_records::prune_task_records;
+use proxmox_backup::cloud::CLOUD_STATUS_DIR;
 use proxmox_backup::server::do_prune_job;
 use proxmox_backup::server::do_verification_job;
 
@@ -453,6 +488,17 @@ async fn schedule_tasks() -> Result<(), Error> {
     schedule_datastore_sync_jobs().await;
     schedule_datastore_verify_jobs().await;
     schedule_tape_backup_jobs().await;
+    schedule_cloud_jobs().await;
+    schedule_cloud_job_resume().await;
+    schedule_cloud_chained_jobs().await;
+    schedule_cloud_standby_sync().await;
+    schedule_cloud_change_feeds().await;
+    schedule_cloud_health_checks().await;
+    schedule_cloud_staging_uploads().await;
+    schedule_cloud_role_sync_jobs().await;
+    schedule_cloud_digest_jobs().await;
+    schedule_cloud_verify_jobs().await;
+    schedule_cloud_prune_jobs().await;
     schedule_task_log_rotate().await;
 
     Ok(())
@@ -712,6 +758,794 @@ async fn schedule_tape_backup_jobs() {
     }
 }
 
+// start cloud backup and replication jobs by their schedule, delayed by
+// their splay, and retry failed runs, see proxmox_backup::cloud::job_splay
+// and proxmox_backup::cloud::job_retry
+async fn schedule_cloud_jobs() {
+    let config = match pbs_config::cloud_job::config() {
+        Err(err) => {
+            eprintln!("unable to read cloud job config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+
+    let auth_id = Authid::root_auth_id().clone();
+
+    let backup_jobs: Vec<CloudBackupJobConfig> = match config.convert_to_typed_array("backup") {
+        Err(err) => {
+            eprintln!("cloud backup job config from_value failed - {err}");
+            Vec::new()
+        }
+        Ok(list) => list,
+    };
+    for job_config in backup_jobs {
+        if job_config.disable || job_paused(CLOUD_STATUS_DIR, &job_config.id) {
+            continue;
+        }
+
+        let worker_type = "cloud-backup-job";
+        let job_id = job_config.id.clone();
+        let scheduled = job_config.schedule.clone().filter(|event_str| {
+            check_splayed_schedule(worker_type, event_str, &job_id, job_config.splay.as_deref())
+        });
+        let retry = match scheduled {
+            Some(_) => None,
+            None => match cloud_job_retry_due(
+                worker_type,
+                &job_id,
+                job_config.retry_on_failure,
+                job_config.retry_delay.as_deref(),
+            ) {
+                Some(state) => Some(state),
+                None => continue,
+            },
+        };
+
+        let job = match Job::new(worker_type, &job_id) {
+            Ok(job) => job,
+            Err(_) => continue, // could not get lock
+        };
+        let result = do_cloud_backup_job(
+            job,
+            job_config.setup,
+            job_config.hooks,
+            &auth_id,
+            scheduled,
+            false,
+        );
+        start_cloud_job_result(worker_type, &job_id, retry.as_ref(), result);
+    }
+
+    let replication_jobs: Vec<CloudReplicationJobConfig> =
+        match config.convert_to_typed_array("replication") {
+            Err(err) => {
+                eprintln!("cloud replication job config from_value failed - {err}");
+                return;
+            }
+            Ok(list) => list,
+        };
+    for job_config in replication_jobs {
+        if job_config.disable || job_paused(CLOUD_STATUS_DIR, &job_config.id) {
+            continue;
+        }
+
+        let worker_type = "cloud-replication-job";
+        let job_id = job_config.id.clone();
+        let scheduled = job_config.schedule.clone().filter(|event_str| {
+            check_splayed_schedule(worker_type, event_str, &job_id, job_config.splay.as_deref())
+        });
+        let retry = match scheduled {
+            Some(_) => None,
+            None => match cloud_job_retry_due(
+                worker_type,
+                &job_id,
+                job_config.retry_on_failure,
+                job_config.retry_delay.as_deref(),
+            ) {
+                Some(state) => Some(state),
+                None => continue,
+            },
+        };
+
+        let job = match Job::new(worker_type, &job_id) {
+            Ok(job) => job,
+            Err(_) => continue, // could not get lock
+        };
+        let result = do_cloud_replication_job(job, job_config, &auth_id, scheduled, false);
+        start_cloud_job_result(worker_type, &job_id, retry.as_ref(), result);
+    }
+}
+
+// the state of the failed last run of a cloud job, if a retry is due
+fn cloud_job_retry_due(
+    worker_type: &str,
+    job_id: &str,
+    retry_on_failure: Option<u64>,
+    retry_delay: Option<&str>,
+) -> Option<jobstate::JobState> {
+    let options = match RetryOptions::new(retry_on_failure, retry_delay) {
+        Ok(options) => options?,
+        Err(err) => {
+            eprintln!("invalid retry options of {worker_type} {job_id} - {err}");
+            return None;
+        }
+    };
+    let state = match jobstate::JobState::load(worker_type, job_id) {
+        Ok(state) => state,
+        Err(err) => {
+            eprintln!("could not load state of {worker_type} {job_id}: {err}");
+            return None;
+        }
+    };
+    let retries = match load_job_retries(CLOUD_STATUS_DIR, worker_type, job_id) {
+        Ok(retries) => retries,
+        Err(err) => {
+            eprintln!("could not load retries of {worker_type} {job_id}: {err}");
+            return None;
+        }
+    };
+
+    match next_retry(&state, retries.as_ref(), &options) {
+        Some(time) if time <= proxmox_time::epoch_i64() => Some(state),
+        _ => None,
+    }
+}
+
+fn start_cloud_job_result(
+    worker_type: &str,
+    job_id: &str,
+    retry: Option<&jobstate::JobState>,
+    result: Result<String, Error>,
+) {
+    let upid = match result {
+        Ok(upid) => upid,
+        Err(err) => {
+            eprintln!("unable to start {worker_type} {job_id} - {err}");
+            return;
+        }
+    };
+    if let Some(state) = retry {
+        match record_retry(CLOUD_STATUS_DIR, worker_type, job_id, state, &upid) {
+            Ok(attempt) => {
+                log::info!("retrying failed {worker_type} {job_id} ({attempt}) - {upid}")
+            }
+            Err(err) => eprintln!("unable to record retry of {worker_type} {job_id} - {err}"),
+        }
+    }
+}
+
+// restart cloud jobs interrupted by a shutdown (or killed), see
+// proxmox_backup::cloud::task_checkpoint
+async fn schedule_cloud_job_resume() {
+    let checkpoints = match load_checkpoints(CLOUD_STATUS_DIR) {
+        Err(err) => {
+            eprintln!("unable to read cloud job checkpoints - {err}");
+            return;
+        }
+        Ok(list) => list,
+    };
+    if checkpoints.is_empty() {
+        return;
+    }
+
+    let config = match pbs_config::cloud_job::config() {
+        Err(err) => {
+            eprintln!("unable to read cloud job config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+
+    for mut checkpoint in checkpoints {
+        let (job_type, job_id) = (checkpoint.job_type.clone(), checkpoint.job_id.clone());
+
+        // keep the checkpoint, the job resumes once it is no longer paused
+        if job_paused(CLOUD_STATUS_DIR, &job_id) {
+            continue;
+        }
+
+        // the worker may still run in the old daemon after a reload
+        if let Ok(upid) = checkpoint.upid.parse::<UPID>() {
+            match proxmox_rest_server::worker_is_active(&upid).await {
+                Ok(false) => {}
+                Ok(true) => continue,
+                Err(err) => {
+                    eprintln!("unable to check worker of cloud job {job_id} - {err}");
+                    continue;
+                }
+            }
+        }
+
+        if checkpoint.attempts >= MAX_RESUME_ATTEMPTS {
+            eprintln!("giving up resuming cloud job {job_id} after {MAX_RESUME_ATTEMPTS} attempts");
+            let _ = CloudJobCheckpoint::remove(CLOUD_STATUS_DIR, &job_type, &job_id);
+            continue;
+        }
+
+        let job = match Job::new(&job_type, &job_id) {
+            Ok(job) => job,
+            Err(_) => continue, // could not get lock, job is running
+        };
+
+        // the new worker takes over the checkpoint, including the attempts
+        checkpoint.attempts += 1;
+        if let Err(err) = checkpoint.save(CLOUD_STATUS_DIR) {
+            eprintln!("unable to update checkpoint of cloud job {job_id} - {err}");
+            continue;
+        }
+
+        let result = match job_type.as_str() {
+            "cloud-backup-job" => config
+                .lookup::<CloudBackupJobConfig>("backup", &job_id)
+                .and_then(|job_config| {
+                    do_cloud_backup_job(
+                        job,
+                        job_config.setup,
+                        job_config.hooks,
+                        &checkpoint.auth_id,
+                        None,
+                        false,
+                    )
+                }),
+            "cloud-replication-job" => config
+                .lookup::<CloudReplicationJobConfig>("replication", &job_id)
+                .and_then(|job_config| {
+                    do_cloud_replication_job(job, job_config, &checkpoint.auth_id, None, false)
+                }),
+            _ => Err(format_err!("unknown job type '{job_type}'")),
+        };
+
+        match result {
+            Ok(upid) => log::info!("resumed interrupted cloud job {job_id} - {upid}"),
+            Err(err) => {
+                eprintln!("unable to resume cloud job {job_id}, dropping it - {err}");
+                let _ = CloudJobCheckpoint::remove(CLOUD_STATUS_DIR, &job_type, &job_id);
+            }
+        }
+    }
+}
+
+// start cloud jobs with 'run-after' once their trigger job finished, see
+// proxmox_backup::cloud::job_chain
+async fn schedule_cloud_chained_jobs() {
+    let config = match pbs_config::cloud_job::config() {
+        Err(err) => {
+            eprintln!("unable to read cloud job config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+
+    let auth_id = Authid::root_auth_id().clone();
+
+    for due in due_chained_jobs(&config) {
+        let job_id = due.job_id.clone();
+        if job_paused(CLOUD_STATUS_DIR, &job_id) {
+            continue;
+        }
+        let worker_type = match cloud_job_worker_type(&due.section_type) {
+            Some(worker_type) => worker_type,
+            None => continue,
+        };
+
+        let job = match Job::new(worker_type, &job_id) {
+            Ok(job) => job,
+            Err(_) => continue, // could not get lock, job is running
+        };
+
+        let result = match due.section_type.as_str() {
+            "backup" => config
+                .lookup::<CloudBackupJobConfig>("backup", &job_id)
+                .and_then(|job_config| {
+                    do_cloud_backup_job(
+                        job,
+                        job_config.setup,
+                        job_config.hooks,
+                        &auth_id,
+                        None,
+                        false,
+                    )
+                }),
+            _ => config
+                .lookup::<CloudReplicationJobConfig>("replication", &job_id)
+                .and_then(|job_config| {
+                    do_cloud_replication_job(job, job_config, &auth_id, None, false)
+                }),
+        };
+
+        match result {
+            Ok(upid) => {
+                let run = ChainedRun {
+                    trigger: due.trigger,
+                    trigger_upid: due.trigger_upid,
+                    upid,
+                };
+                if let Err(err) = save_chained_run(CLOUD_STATUS_DIR, worker_type, &job_id, &run) {
+                    eprintln!("unable to record trigger of cloud job {job_id} - {err}");
+                }
+            }
+            Err(err) => eprintln!("unable to start chained cloud job {job_id} - {err}"),
+        }
+    }
+}
+
+// follow the catalog of the primary on warm standby targets, see
+// proxmox_backup::cloud::standby
+async fn schedule_cloud_standby_sync() {
+    static SYNC_RUNNING: AtomicBool = AtomicBool::new(false);
+
+    let config = match pbs_config::cloud::config() {
+        Err(err) => {
+            eprintln!("unable to read cloud target config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+    let targets: Vec<CloudTarget> = match config.convert_to_typed_array("target") {
+        Err(err) => {
+            eprintln!("unable to parse cloud target config - {err}");
+            return;
+        }
+        Ok(targets) => targets,
+    };
+    let targets: Vec<CloudTarget> = targets
+        .into_iter()
+        .filter(|target| target.config.standby_remote.is_some())
+        .collect();
+
+    // a slow remote must neither delay the scheduler nor start a second sync
+    // of the same catalog
+    if targets.is_empty() || SYNC_RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    tokio::spawn(async move {
+        for target in targets {
+            let remote_name = target.config.standby_remote.clone().unwrap(); // filtered above
+            let name = target.name.clone();
+            let base_path = Path::new(CLOUD_STATUS_DIR);
+
+            let result: Result<Option<StandbyDelta>, Error> = async {
+                let (remote_config, _digest) = pbs_config::remote::config()?;
+                let remote: Remote = remote_config.lookup("remote", &remote_name)?;
+                let client = remote_client(&remote, None).await?;
+
+                let local = standby::catalog_digests(&CloudCatalog::load(base_path, &name)?)?;
+                let remote_digests = standby::remote_catalog_digests(&client, &name).await?;
+                let delta = standby::standby_delta(&local, &remote_digests)?;
+                if delta.is_empty() {
+                    return Ok(None);
+                }
+                Ok(Some(delta))
+            }
+            .await;
+
+            let delta = match result {
+                Ok(Some(delta)) => delta,
+                Ok(None) => {
+                    let _ = standby::update_standby_status(
+                        base_path,
+                        &name,
+                        &remote_name,
+                        false,
+                        &Ok(()),
+                    );
+                    continue;
+                }
+                Err(err) => {
+                    eprintln!("unable to check standby catalog of cloud target {name} - {err}");
+                    let _ = standby::update_standby_status(
+                        base_path,
+                        &name,
+                        &remote_name,
+                        false,
+                        &Err(err),
+                    );
+                    continue;
+                }
+            };
+
+            // the guard is held until the worker finished
+            let (done_tx, done_rx) = tokio::sync::oneshot::channel::<()>();
+
+            if let Err(err) = WorkerTask::spawn(
+                "cloud-standby-sync",
+                Some(name.clone()),
+                Authid::root_auth_id().to_string(),
+                false,
+                move |worker| async move {
+                    task_log!(
+                        worker,
+                        "sync catalog of target '{}' from remote '{}' ({} changed, {} removed media sets)",
+                        name,
+                        remote_name,
+                        delta.fetch.len(),
+                        delta.remove.len(),
+                    );
+
+                    let base_path = Path::new(CLOUD_STATUS_DIR);
+                    let result = async {
+                        let (remote_config, _digest) = pbs_config::remote::config()?;
+                        let remote: Remote = remote_config.lookup("remote", &remote_name)?;
+                        let client = remote_client(&remote, None).await?;
+                        standby::sync_standby_catalog(&*worker, &client, base_path, &name, &delta)
+                            .await
+                    }
+                    .await;
+
+                    if let Err(err) = standby::update_standby_status(
+                        base_path,
+                        &name,
+                        &remote_name,
+                        true,
+                        &result,
+                    ) {
+                        task_warn!(worker, "unable to update standby status - {}", err);
+                    }
+                    let _ = done_tx.send(());
+                    result
+                },
+            ) {
+                eprintln!(
+                    "unable to start standby catalog sync of cloud target {} - {err}",
+                    target.name
+                );
+            }
+
+            // a dropped sender (failed spawn, aborted worker) ends the wait, too
+            let _ = done_rx.await;
+        }
+        SYNC_RUNNING.store(false, Ordering::SeqCst);
+    });
+}
+
+// apply S3 event notifications of targets with a change queue, see
+// proxmox_backup::cloud::change_feed
+async fn schedule_cloud_change_feeds() {
+    static POLL_RUNNING: AtomicBool = AtomicBool::new(false);
+
+    let config = match pbs_config::cloud::config() {
+        Err(err) => {
+            eprintln!("unable to read cloud target config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+    let targets: Vec<CloudTarget> = match config.convert_to_typed_array("target") {
+        Err(err) => {
+            eprintln!("unable to parse cloud target config - {err}");
+            return;
+        }
+        Ok(targets) => targets,
+    };
+    let targets: Vec<CloudTarget> = targets
+        .into_iter()
+        .filter(|target| target.config.change_queue.is_some())
+        .collect();
+
+    if targets.is_empty() || POLL_RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    tokio::task::spawn_blocking(move || {
+        for target in targets {
+            let name = target.name.clone();
+            let result = ChangeQueue::new(&target).and_then(|queue| {
+                let queue = queue.unwrap(); // filtered above
+                let messages = queue.receive()?;
+                Ok((queue, messages))
+            });
+            let (queue, messages) = match result {
+                Ok((_, messages)) if messages.is_empty() => continue,
+                Ok(received) => received,
+                Err(err) => {
+                    eprintln!("unable to poll change queue of cloud target {name} - {err}");
+                    continue;
+                }
+            };
+
+            let worker_id = name.clone();
+            if let Err(err) = WorkerTask::new_thread(
+                "cloud-change-feed",
+                Some(worker_id.clone()),
+                Authid::root_auth_id().to_string(),
+                false,
+                move |worker| {
+                    task_log!(
+                        worker,
+                        "apply {} change notifications of target '{}'",
+                        messages.len(),
+                        name
+                    );
+                    let backend = open_backend(&target)?;
+                    run_change_feed(
+                        &*worker,
+                        Path::new(CLOUD_STATUS_DIR),
+                        &target,
+                        &*backend,
+                        &queue,
+                        messages,
+                    )?;
+                    Ok(())
+                },
+            ) {
+                eprintln!("unable to start change feed of cloud target {worker_id} - {err}");
+            }
+        }
+        POLL_RUNNING.store(false, Ordering::SeqCst);
+    });
+}
+
+// check the reachability of cloud targets, see proxmox_backup::cloud::health
+async fn schedule_cloud_health_checks() {
+    static CHECK_RUNNING: AtomicBool = AtomicBool::new(false);
+
+    let config = match pbs_config::cloud::config() {
+        Err(err) => {
+            eprintln!("unable to read cloud target config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+    let targets: Vec<CloudTarget> = match config.convert_to_typed_array("target") {
+        Err(err) => {
+            eprintln!("unable to parse cloud target config - {err}");
+            return;
+        }
+        Ok(targets) => targets,
+    };
+
+    let now = proxmox_time::epoch_i64();
+    let due: Vec<CloudTarget> = targets
+        .into_iter()
+        .filter(health_check_enabled)
+        .filter(
+            |target| match load_health_history(CLOUD_STATUS_DIR, &target.name) {
+                Ok(history) => health_check_due(&history, now),
+                Err(_) => true,
+            },
+        )
+        .collect();
+
+    // a hanging check must neither delay the scheduler nor pile up
+    if due.is_empty() || CHECK_RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    tokio::task::spawn_blocking(move || {
+        for target in due {
+            let sample = match open_backend(&target) {
+                Ok(backend) => check_target_health(&*backend),
+                Err(err) => CloudHealthSample {
+                    time: proxmox_time::epoch_i64(),
+                    latency: None,
+                    error: Some(err.to_string()),
+                },
+            };
+            if let Some(ref error) = sample.error {
+                log::warn!("cloud target {} is not reachable - {}", target.name, error);
+            }
+            if let Err(err) = record_health_sample(CLOUD_STATUS_DIR, &target.name, sample) {
+                eprintln!(
+                    "unable to record health of cloud target {} - {err}",
+                    target.name
+                );
+            }
+        }
+        CHECK_RUNNING.store(false, Ordering::SeqCst);
+    });
+}
+
+// upload what is left in write-back spools (e.g. after a restart or failed
+// uploads), see proxmox_backup::cloud::staging
+async fn schedule_cloud_staging_uploads() {
+    let config = match pbs_config::cloud::config() {
+        Err(err) => {
+            eprintln!("unable to read cloud target config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+    let targets: Vec<CloudTarget> = match config.convert_to_typed_array("target") {
+        Err(err) => {
+            eprintln!("unable to parse cloud target config - {err}");
+            return;
+        }
+        Ok(targets) => targets,
+    };
+
+    for target in targets {
+        // spools remain after disabling write-back, they still need an upload
+        if !staging_dir(CLOUD_STATUS_DIR, &target.name).exists() {
+            continue;
+        }
+        let pending = StagingSpool::open_target(CLOUD_STATUS_DIR, &target)
+            .and_then(|spool| spool.list())
+            .map(|list| !list.is_empty());
+        match pending {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(err) => {
+                eprintln!(
+                    "unable to read write-back spool of cloud target {} - {err}",
+                    target.name
+                );
+                continue;
+            }
+        }
+        if let Err(err) = start_staging_upload(&target.name, Authid::root_auth_id(), false) {
+            eprintln!(
+                "unable to start uploader of cloud target {} - {err}",
+                target.name
+            );
+        }
+    }
+}
+
+async fn schedule_cloud_role_sync_jobs() {
+    let config = match pbs_config::cloud_job::config() {
+        Err(err) => {
+            eprintln!("unable to read cloud job config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+    let job_list: Vec<CloudRoleSyncJobConfig> = match config.convert_to_typed_array("role-sync") {
+        Err(err) => {
+            eprintln!("cloud role sync job config from_value failed - {err}");
+            return;
+        }
+        Ok(list) => list,
+    };
+
+    for job_config in job_list {
+        if job_config.disable || job_paused(CLOUD_STATUS_DIR, &job_config.id) {
+            continue;
+        }
+        let event_str = match job_config.schedule {
+            Some(ref event_str) => event_str.clone(),
+            None => continue,
+        };
+
+        let worker_type = "cloud-role-sync-job";
+        let job_id = job_config.id.clone();
+        if check_schedule(worker_type, &event_str, &job_id) {
+            let job = match Job::new(worker_type, &job_id) {
+                Ok(job) => job,
+                Err(_) => continue, // could not get lock
+            };
+
+            let auth_id = Authid::root_auth_id().clone();
+            if let Err(err) =
+                do_cloud_role_sync_job(job, job_config, &auth_id, Some(event_str), false, false)
+            {
+                eprintln!("unable to start cloud role sync job {job_id} - {err}");
+            }
+        };
+    }
+}
+
+async fn schedule_cloud_digest_jobs() {
+    let config = match pbs_config::cloud_job::config() {
+        Err(err) => {
+            eprintln!("unable to read cloud job config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+    let job_list: Vec<CloudDigestJobConfig> = match config.convert_to_typed_array("digest") {
+        Err(err) => {
+            eprintln!("cloud digest job config from_value failed - {err}");
+            return;
+        }
+        Ok(list) => list,
+    };
+
+    for job_config in job_list {
+        if job_config.disable || job_paused(CLOUD_STATUS_DIR, &job_config.id) {
+            continue;
+        }
+        let event_str = digest_schedule(&job_config);
+
+        let worker_type = "cloud-digest-job";
+        let job_id = job_config.id.clone();
+        if check_schedule(worker_type, &event_str, &job_id) {
+            let job = match Job::new(worker_type, &job_id) {
+                Ok(job) => job,
+                Err(_) => continue, // could not get lock
+            };
+
+            let auth_id = Authid::root_auth_id().clone();
+            if let Err(err) = do_cloud_digest_job(job, job_config, &auth_id, Some(event_str), false)
+            {
+                eprintln!("unable to start cloud digest job {job_id} - {err}");
+            }
+        };
+    }
+}
+
+async fn schedule_cloud_verify_jobs() {
+    let config = match pbs_config::cloud_job::config() {
+        Err(err) => {
+            eprintln!("unable to read cloud job config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+    let job_list: Vec<CloudVerificationJobConfig> = match config.convert_to_typed_array("verify") {
+        Err(err) => {
+            eprintln!("cloud verification job config from_value failed - {err}");
+            return;
+        }
+        Ok(list) => list,
+    };
+
+    for job_config in job_list {
+        if job_config.disable || job_paused(CLOUD_STATUS_DIR, &job_config.id) {
+            continue;
+        }
+        let event_str = match job_config.schedule {
+            Some(ref event_str) => event_str.clone(),
+            None => continue,
+        };
+
+        let worker_type = "cloud-verify-job";
+        let job_id = job_config.id.clone();
+        if check_schedule(worker_type, &event_str, &job_id) {
+            let job = match Job::new(worker_type, &job_id) {
+                Ok(job) => job,
+                Err(_) => continue, // could not get lock
+            };
+
+            let auth_id = Authid::root_auth_id().clone();
+            if let Err(err) = do_cloud_verify_job(job, job_config, &auth_id, Some(event_str), false)
+            {
+                eprintln!("unable to start cloud verification job {job_id} - {err}");
+            }
+        };
+    }
+}
+
+async fn schedule_cloud_prune_jobs() {
+    let config = match pbs_config::cloud_job::config() {
+        Err(err) => {
+            eprintln!("unable to read cloud job config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+    let job_list: Vec<CloudPruneJobConfig> = match config.convert_to_typed_array("prune") {
+        Err(err) => {
+            eprintln!("cloud prune job config from_value failed - {err}");
+            return;
+        }
+        Ok(list) => list,
+    };
+
+    for job_config in job_list {
+        if job_config.disable || job_paused(CLOUD_STATUS_DIR, &job_config.id) {
+            continue;
+        }
+        let event_str = job_config.schedule.clone();
+
+        let worker_type = "cloud-prune-job";
+        let job_id = job_config.id.clone();
+        if check_schedule(worker_type, &event_str, &job_id) {
+            let job = match Job::new(worker_type, &job_id) {
+                Ok(job) => job,
+                Err(_) => continue, // could not get lock
+            };
+
+            let auth_id = Authid::root_auth_id().clone();
+            if let Err(err) = do_cloud_prune_job(job, job_config, &auth_id, Some(event_str), false)
+            {
+                eprintln!("unable to start cloud prune job {job_id} - {err}");
+            }
+        };
+    }
+}
+
 async fn schedule_task_log_rotate() {
     let worker_type = "logrotate";
     let job_id = "access-log_and_task-archive";
@@ -807,6 +1641,19 @@ async fn schedule_task_log_rotate() {
                     if let Err(err) = cleanup_old_tasks(&worker, true) {
                         task_warn!(worker, "could not completely cleanup old tasks: {err}");
                     }
+                    // records of cloud tasks go away with their task log
+                    let keep = |upid: &str| match upid.parse::<UPID>() {
+                        Ok(upid) => upid_log_path(&upid)
+                            .map(|path| path.exists())
+                            .unwrap_or(false),
+                        Err(_) => false,
+                    };
+                    if let Err(err) = prune_task_records(CLOUD_STATUS_DIR, keep) {
+                        task_warn!(worker, "could not cleanup cloud task records: {err}");
+                    }
+                    if let Err(err) = prune_request_traces(CLOUD_STATUS_DIR, keep) {
+                        task_warn!(worker, "could not cleanup cloud request traces: {err}");
+                    }
                 }
 
                 Ok(())
@@ -868,6 +1715,8 @@ async fn command_reopen_auth_logfiles() -> Result<(), Error> {
 }
 
 async fn run_stat_generator() {
+    let mut cloud_senders = None;
+
     loop {
         let delay_target = Instant::now() + Duration::from_secs(10);
 
@@ -894,7 +1743,7 @@ async fn run_stat_generator() {
             }
         });
 
-        let metrics_future = send_data_to_metric_servers(stats);
+        let metrics_future = send_data_to_metric_servers(stats, &mut cloud_senders);
 
         let (rrd_res, metrics_res) = join!(rrd_future, metrics_future);
         if let Err(err) = rrd_res {
@@ -910,11 +1759,20 @@ async fn run_stat_generator() {
 
 async fn send_data_to_metric_servers(
     stats: Arc<(HostStats, DiskStat, Vec<DiskStat>)>,
+    cloud_senders: &mut Option<MetricSenders>,
 ) -> Result<(), Error> {
-    let (config, _digest) = pbs_config::metrics::config()?;
-    let channel_list = get_metric_server_connections(config)?;
+    let (config, digest) = pbs_config::metrics::config()?;
 
-    if channel_list.is_empty() {
+    let reload = match cloud_senders {
+        Some(senders) => !senders.is_current(&digest),
+        None => true,
+    };
+    if reload {
+        *cloud_senders = Some(MetricSenders::load(&config, digest, CLOUD_STATUS_DIR).await);
+    }
+    let cloud_senders = cloud_senders.as_ref().unwrap();
+
+    if cloud_senders.is_empty() {
         return Ok(());
     }
 
@@ -934,66 +1792,92 @@ async fn send_data_to_metric_servers(
         cpuvalue["avg15"] = Value::from(loadavg.2);
     }
 
-    values.push(Arc::new(
-        MetricsData::new("cpustat", ctime, cpuvalue)?
+    values.push(
+        MetricPoint::new("cpustat", ctime, cpuvalue)?
             .tag("object", "host")
             .tag("host", nodename),
-    ));
+    );
 
     if let Some(stat) = &stats.0.meminfo {
-        values.push(Arc::new(
-            MetricsData::new("memory", ctime, stat)?
+        values.push(
+            MetricPoint::new("memory", ctime, stat)?
                 .tag("object", "host")
                 .tag("host", nodename),
-        ));
+        );
     }
 
     if let Some(netdev) = &stats.0.net {
         for item in netdev {
-            values.push(Arc::new(
-                MetricsData::new("nics", ctime, item)?
+            values.push(
+                MetricPoint::new("nics", ctime, item)?
                     .tag("object", "host")
                     .tag("host", nodename)
                     .tag("instance", item.device.clone()),
-            ));
+            );
         }
     }
 
-    values.push(Arc::new(
-        MetricsData::new("blockstat", ctime, stats.1.to_value())?
+    values.push(
+        MetricPoint::new("blockstat", ctime, stats.1.to_value())?
             .tag("object", "host")
             .tag("host", nodename),
-    ));
+    );
 
     for datastore in stats.2.iter() {
-        values.push(Arc::new(
-            MetricsData::new("blockstat", ctime, datastore.to_value())?
+        values.push(
+            MetricPoint::new("blockstat", ctime, datastore.to_value())?
                 .tag("object", "host")
                 .tag("host", nodename)
                 .tag("datastore", datastore.name.clone()),
-        ));
+        );
     }
 
-    // we must have a concrete functions, because the inferred lifetime from a
-    // closure is not general enough for the tokio::spawn call we are in here...
-    fn map_fn(item: &(proxmox_metrics::Metrics, String)) -> &proxmox_metrics::Metrics {
-        &item.0
+    match list_dedup_stats(CLOUD_STATUS_DIR) {
+        Ok(list) => {
+            for (target, stats) in list {
+                for entry in stats.groups.iter() {
+                    values.push(
+                        MetricPoint::new("cloud_dedup", ctime, &entry.stats)?
+                            .tag("object", "host")
+                            .tag("host", nodename)
+                            .tag("target", target.clone())
+                            .tag("group", entry.group.clone()),
+                    );
+                }
+            }
+        }
+        Err(err) => log::error!("could not load cloud deduplication statistics: {err}"),
     }
 
-    let results =
-        proxmox_metrics::send_data_to_channels(&values, channel_list.iter().map(map_fn)).await;
-    for (res, name) in results
-        .into_iter()
-        .zip(channel_list.iter().map(|(_, name)| name))
-    {
-        if let Err(err) = res {
-            log::error!("error sending into channel of {name}: {err}");
+    match node_cache_status() {
+        Ok(Some(status)) => values.push(
+            MetricPoint::new("cloud_chunk_cache", ctime, &status)?
+                .tag("object", "host")
+                .tag("host", nodename),
+        ),
+        Ok(None) => {}
+        Err(err) => log::error!("could not load cloud chunk cache status: {err}"),
+    }
+
+    for (server, dropped) in udp::dropped_records() {
+        values.push(
+            MetricPoint::new("cloud_metrics", ctime, json!({ "dropped": dropped }))?
+                .tag("object", "host")
+                .tag("host", nodename)
+                .tag("server", server),
+        );
+    }
+
+    for sender in cloud_senders.udp.iter() {
+        if let Err(err) = sender.send(&values) {
+            log::error!("{err}");
         }
     }
 
-    futures::future::join_all(channel_list.into_iter().map(|(channel, name)| async move {
-        if let Err(err) = channel.join().await {
-            log::error!("error sending to metric server {name}: {err}");
+    let values = &values;
+    futures::future::join_all(cloud_senders.http.iter().map(|sender| async move {
+        if let Err(err) = sender.send(values).await {
+            log::error!("{err}");
         }
     }))
     .await;
@@ -1001,41 +1885,6 @@ async fn send_data_to_metric_servers(
     Ok(())
 }
 
-/// Get the metric server connections from a config
-pub fn get_metric_server_connections(
-    metric_config: proxmox_section_config::SectionConfigData,
-) -> Result<Vec<(proxmox_metrics::Metrics, String)>, Error> {
-    let mut res = Vec::new();
-
-    for config in
-        metric_config.convert_to_typed_array::<pbs_api_types::InfluxDbUdp>("influxdb-udp")?
-    {
-        if !config.enable {
-            continue;
-        }
-        let future = proxmox_metrics::influxdb_udp(&config.host, config.mtu);
-        res.push((future, config.name));
-    }
-
-    for config in
-        metric_config.convert_to_typed_array::<pbs_api_types::InfluxDbHttp>("influxdb-http")?
-    {
-        if !config.enable {
-            continue;
-        }
-        let future = proxmox_metrics::influxdb_http(
-            &config.url,
-            config.organization.as_deref().unwrap_or("proxmox"),
-            config.bucket.as_deref().unwrap_or("proxmox"),
-            config.token.as_deref(),
-            config.verify_tls.unwrap_or(true),
-            config.max_body_size.unwrap_or(25_000_000),
-        )?;
-        res.push((future, config.name));
-    }
-    Ok(res)
-}
-
 struct HostStats {
     proc: Option<ProcFsStat>,
     meminfo: Option<ProcFsMemInfo>,
@@ -1241,6 +2090,33 @@ fn check_schedule(worker_type: &str, event_str: &str, id: &str) -> bool {
     next <= now
 }
 
+fn check_splayed_schedule(
+    worker_type: &str,
+    event_str: &str,
+    id: &str,
+    splay: Option<&str>,
+) -> bool {
+    let offset = match splay_offset(id, splay) {
+        Ok(offset) => offset,
+        Err(err) => {
+            eprintln!("unable to compute splay of {worker_type} {id} - {err}");
+            return false;
+        }
+    };
+    if offset == 0 {
+        return check_schedule(worker_type, event_str, id);
+    }
+
+    match next_splayed_run(worker_type, id, event_str, offset) {
+        Ok(Some(next)) => next <= proxmox_time::epoch_i64(),
+        Ok(None) => false,
+        Err(err) => {
+            eprintln!("could not compute next run of {worker_type} {id}: {err}");
+            false
+        }
+    }
+}
+
 fn gather_disk_stats(disk_manager: Arc<DiskManage>, path: &Path, name: &str) -> DiskStat {
     let usage = match proxmox_sys::fs::fs_info(path) {
         Ok(status) => Some(status),
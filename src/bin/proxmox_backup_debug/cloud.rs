@@ -0,0 +1,281 @@
+use std::io::IsTerminal;
+use std::path::Path;
+
+use anyhow::{bail, Error};
+use serde_json::Value;
+
+use proxmox_router::cli::{
+    complete_file_name, default_table_format_options, format_and_print_result_full,
+    get_output_format, CliCommand, CliCommandMap, ColumnConfig, CommandLineInterface,
+    OUTPUT_FORMAT,
+};
+use proxmox_router::{ApiHandler, RpcEnvironment};
+use proxmox_schema::api;
+use proxmox_sys::fs::CreateOptions;
+use proxmox_sys::linux::tty;
+
+use pbs_api_types::{CLOUD_ACCESS_KEY_SCHEMA, CLOUD_TARGET_NAME_SCHEMA};
+use pbs_config::cloud::complete_cloud_target_name;
+
+use proxmox_backup::api2;
+use proxmox_backup::cloud::backend::{open_backend, open_target_backend};
+use proxmox_backup::cloud::catalog::CloudCatalog;
+use proxmox_backup::cloud::catalog_export::{export_catalog, import_catalog, parse_catalog_export};
+use proxmox_backup::cloud::delete_queue::DeleteQueue;
+use proxmox_backup::cloud::snapshot_export::extract_snapshot_export;
+use proxmox_backup::cloud::CLOUD_STATUS_DIR;
+
+#[api(
+    input: {
+        properties: {
+            target: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// List object deletions queued by the delete protection of a target.
+fn list_delete_queue(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
+
+    param["name"] = param["target"].take();
+    if let Some(map) = param.as_object_mut() {
+        map.remove("output-format");
+        map.remove("target");
+    }
+
+    let info = &api2::cloud::storage::API_METHOD_DELETE_QUEUE;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("key"))
+        .column(ColumnConfig::new("queued").renderer(pbs_tools::format::render_epoch));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            target: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            "access-key": {
+                schema: CLOUD_ACCESS_KEY_SCHEMA,
+            },
+            "dry-run": {
+                description: "Only show what would be deleted.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+)]
+/// Delete the queued objects of a delete protected target.
+///
+/// Uses the given credentials (which need delete permissions) instead of
+/// the ones configured for the target. The secret key is read from the
+/// terminal, so it is never stored on the server.
+fn process_delete_queue(target: String, access_key: String, dry_run: bool) -> Result<(), Error> {
+    let mut queue = DeleteQueue::load(CLOUD_STATUS_DIR, &target)?;
+    if queue.entries().is_empty() {
+        println!("delete queue of target '{}' is empty", target);
+        return Ok(());
+    }
+
+    if dry_run {
+        for entry in queue.entries() {
+            println!("would delete {}", entry.key);
+        }
+        return Ok(());
+    }
+
+    if !std::io::stdin().is_terminal() {
+        bail!("no password input mechanism available");
+    }
+    let secret_key = String::from_utf8(tty::read_password("Secret Key: ")?)?;
+
+    let mut config = pbs_config::cloud::lookup_target(&target)?;
+    config.config.access_key = Some(access_key);
+    config.config.credential_process = None;
+    config.config.delete_protection = None;
+    config.secret_key = secret_key;
+    let backend = open_backend(&config)?;
+
+    let count = queue.process(&backend, |entry| println!("deleted {}", entry.key))?;
+    println!("deleted {} objects", count);
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            target: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            "output-file": {
+                description: "Write the export to this file instead of standard output.",
+                type: String,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Export the local catalog of a target into a portable JSON file.
+fn export_target_catalog(target: String, output_file: Option<String>) -> Result<(), Error> {
+    let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &target)?;
+    let export = export_catalog(&catalog);
+
+    match output_file {
+        Some(path) => {
+            let data = serde_json::to_vec(&export)?;
+            proxmox_sys::fs::replace_file(&path, &data, CreateOptions::new(), true)?;
+            eprintln!(
+                "exported {} media sets of target '{}' to {}",
+                export.media_sets.len(),
+                target,
+                path
+            );
+        }
+        None => serde_json::to_writer(std::io::stdout(), &export)?,
+    }
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            target: {
+                schema: CLOUD_TARGET_NAME_SCHEMA,
+            },
+            file: {
+                description: "Catalog export to import.",
+                type: String,
+            },
+            overwrite: {
+                description: "Replace media sets already in the local catalog.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+)]
+/// Import a catalog export into the local catalog of a target.
+///
+/// All media sets of the export have to exist on the target.
+fn import_target_catalog(target: String, file: String, overwrite: bool) -> Result<(), Error> {
+    let data = proxmox_sys::fs::file_get_contents(&file)?;
+    let export = parse_catalog_export(&data)?;
+
+    if export.target != target {
+        println!(
+            "importing catalog of target '{}' (node {}) into target '{}'",
+            export.target, export.node, target
+        );
+    }
+
+    let (_config, backend) = open_target_backend(&target)?;
+    let result = import_catalog(CLOUD_STATUS_DIR, &target, &*backend, &export, overwrite)?;
+
+    println!(
+        "imported {} media sets, skipped {} existing media sets",
+        result.imported, result.skipped
+    );
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            file: {
+                description: "Snapshot export (downloaded from the target).",
+                type: String,
+            },
+            directory: {
+                description: "Datastore directory to unpack the snapshot into.",
+                type: String,
+            },
+        },
+    },
+)]
+/// Unpack a snapshot export into a datastore directory.
+///
+/// All files and chunks are checked against the description of the
+/// export. Does not need access to a backup server or cloud target.
+fn extract_snapshot_export_file(file: String, directory: String) -> Result<(), Error> {
+    let reader = std::io::BufReader::new(std::fs::File::open(&file)?);
+    let info = extract_snapshot_export(reader, Path::new(&directory))?;
+
+    println!(
+        "extracted snapshot {} of datastore '{}' ({} files, {} chunks)",
+        info.snapshot_path(),
+        info.store,
+        info.files.len(),
+        info.chunks
+    );
+    if let Some(fingerprint) = info.fingerprint {
+        println!("snapshot is encrypted with key {}", fingerprint.signature());
+    }
+
+    Ok(())
+}
+
+pub fn cloud_commands() -> CommandLineInterface {
+    let delete_queue = CliCommandMap::new()
+        .insert(
+            "list",
+            CliCommand::new(&API_METHOD_LIST_DELETE_QUEUE)
+                .arg_param(&["target"])
+                .completion_cb("target", complete_cloud_target_name),
+        )
+        .insert(
+            "process",
+            CliCommand::new(&API_METHOD_PROCESS_DELETE_QUEUE)
+                .arg_param(&["target"])
+                .completion_cb("target", complete_cloud_target_name),
+        );
+
+    let catalog = CliCommandMap::new()
+        .insert(
+            "export",
+            CliCommand::new(&API_METHOD_EXPORT_TARGET_CATALOG)
+                .arg_param(&["target"])
+                .completion_cb("target", complete_cloud_target_name),
+        )
+        .insert(
+            "import",
+            CliCommand::new(&API_METHOD_IMPORT_TARGET_CATALOG)
+                .arg_param(&["target", "file"])
+                .completion_cb("target", complete_cloud_target_name)
+                .completion_cb("file", complete_file_name),
+        );
+
+    let snapshot_export = CliCommandMap::new().insert(
+        "extract",
+        CliCommand::new(&API_METHOD_EXTRACT_SNAPSHOT_EXPORT_FILE)
+            .arg_param(&["file", "directory"])
+            .completion_cb("file", complete_file_name)
+            .completion_cb("directory", complete_file_name),
+    );
+
+    let cmd_def = CliCommandMap::new()
+        .insert("catalog", catalog)
+        .insert("delete-queue", delete_queue)
+        .insert("snapshot-export", snapshot_export);
+
+    cmd_def.into()
+}
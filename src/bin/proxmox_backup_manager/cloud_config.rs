@@ -0,0 +1,55 @@
+use anyhow::{bail, Error};
+use serde_json::Value;
+
+use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
+use proxmox_schema::api;
+
+use proxmox_backup::api2;
+
+pub fn cloud_config_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new().insert("check", CliCommand::new(&API_METHOD_CHECK_CONFIG));
+
+    cmd_def.into()
+}
+
+#[api(
+    input: {
+        properties: {
+            probe: {
+                description: "Check the reachability of all targets with a request, \
+                              instead of using their recorded health checks.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Check the whole cloud configuration (fails if problems were found)
+fn check_config(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
+    let info = &api2::cloud::config_check::API_METHOD_CHECK_CONFIG;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let problems = data.as_array().map(|list| list.len()).unwrap_or(0);
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("kind"))
+        .column(ColumnConfig::new("entity"))
+        .column(ColumnConfig::new("message"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    if problems > 0 {
+        bail!("found {} problem(s) in the cloud configuration", problems);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use std::io::IsTerminal;
+
+use anyhow::{bail, Error};
+use serde_json::Value;
+
+use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
+use proxmox_schema::api;
+use proxmox_sys::linux::tty;
+
+use pbs_api_types::{Kdf, CLOUD_ENCRYPTION_KEY_FINGERPRINT_SCHEMA, PASSWORD_HINT_SCHEMA};
+
+use proxmox_backup::api2;
+use proxmox_backup::cloud::encryption_keys::complete_key_fingerprint;
+
+pub fn cloud_encryption_key_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_KEYS))
+        .insert("create", CliCommand::new(&API_METHOD_CREATE_KEY))
+        .insert(
+            "split",
+            CliCommand::new(&API_METHOD_SPLIT_KEY)
+                .arg_param(&["fingerprint"])
+                .completion_cb("fingerprint", complete_key_fingerprint),
+        )
+        .insert("recover", CliCommand::new(&API_METHOD_RECOVER_KEY))
+        .insert(
+            "remove",
+            CliCommand::new(&api2::config::cloud_encryption_keys::API_METHOD_DELETE_KEY)
+                .arg_param(&["fingerprint"])
+                .completion_cb("fingerprint", complete_key_fingerprint),
+        );
+
+    cmd_def.into()
+}
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// List cloud encryption keys
+fn list_keys(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
+    let info = &api2::config::cloud_encryption_keys::API_METHOD_LIST_KEYS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("fingerprint"))
+        .column(ColumnConfig::new("hint"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            kdf: {
+                type: Kdf,
+                optional: true,
+            },
+            hint: {
+                schema: PASSWORD_HINT_SCHEMA,
+            },
+        },
+    },
+)]
+/// Create a cloud encryption key (read password from stdin)
+fn create_key(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    if !std::io::stdin().is_terminal() {
+        bail!("no password input mechanism available");
+    }
+
+    let password = tty::read_and_verify_password("Cloud Encryption Key Password: ")?;
+    param["password"] = String::from_utf8(password)?.into();
+
+    let info = &api2::config::cloud_encryption_keys::API_METHOD_CREATE_KEY;
+    let fingerprint = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    println!("{}", fingerprint);
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            fingerprint: {
+                schema: CLOUD_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+            },
+            shares: {
+                description: "Number of shares to create.",
+                type: u8,
+                minimum: 2,
+            },
+            threshold: {
+                description: "Number of shares needed to recover the key.",
+                type: u8,
+                minimum: 2,
+            },
+        },
+    },
+)]
+/// Split a key into shares for escrow (read password from stdin)
+///
+/// Prints one share per line. Each share should be printed (or written
+/// down) and kept by a different person or at a different place.
+fn split_key(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    if !std::io::stdin().is_terminal() {
+        bail!("no password input mechanism available");
+    }
+
+    let password = tty::read_password("Cloud Encryption Key Password: ")?;
+    param["password"] = String::from_utf8(password)?.into();
+
+    let fingerprint = param["fingerprint"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    let threshold = param["threshold"].as_u64().unwrap_or_default();
+
+    let info = &api2::config::cloud_encryption_keys::API_METHOD_SPLIT;
+    let shares = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+    let shares: Vec<String> = serde_json::from_value(shares)?;
+
+    println!("Key: {}", fingerprint);
+    println!("Any {} of the following shares recover the key.", threshold);
+    for (i, share) in shares.iter().enumerate() {
+        println!();
+        println!("Share {} of {}:", i + 1, shares.len());
+        println!("{}", share);
+    }
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            kdf: {
+                type: Kdf,
+                optional: true,
+            },
+            hint: {
+                schema: PASSWORD_HINT_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Recover a split key from its shares (read shares and password from stdin)
+fn recover_key(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    if !std::io::stdin().is_terminal() {
+        bail!("no password input mechanism available");
+    }
+
+    println!("Enter the key shares, one per line (empty line to finish):");
+    let mut shares = Vec::new();
+    loop {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        shares.push(line.to_string());
+    }
+    param["shares"] = shares.into();
+
+    let password = tty::read_and_verify_password("New Cloud Encryption Key Password: ")?;
+    param["password"] = String::from_utf8(password)?.into();
+
+    let info = &api2::config::cloud_encryption_keys::API_METHOD_CREATE_KEY;
+    let fingerprint = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    println!("recovered key {}", fingerprint);
+
+    Ok(())
+}
@@ -4,6 +4,10 @@ mod acme;
 pub use acme::*;
 mod cert;
 pub use cert::*;
+mod cloud_config;
+pub use cloud_config::*;
+mod cloud_encryption_key;
+pub use cloud_encryption_key::*;
 mod datastore;
 pub use datastore::*;
 mod dns;
@@ -37,6 +37,8 @@ fn list_traffic_controls(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Resul
         .column(ColumnConfig::new("rate-out"))
         .column(ColumnConfig::new("burst-out"))
         .column(ColumnConfig::new("network"))
+        .column(ColumnConfig::new("cloud-target"))
+        .column(ColumnConfig::new("endpoint"))
         .column(ColumnConfig::new("timeframe"))
         .column(ColumnConfig::new("comment"));
 
@@ -0,0 +1,328 @@
+//! Provider access log analysis
+//!
+//! Targets with `access-log-prefix` get the S3 server access logs of their
+//! bucket delivered below that prefix. Scanning these logs finds object
+//! accesses which did not originate from PBS, e.g. downloads with leaked
+//! credentials. Requests sent by PBS are recognized by their User-Agent
+//! (see the `user-agent` target option), so a custom user agent must not
+//! be shared with other clients of the bucket.
+//!
+//! Processed log objects are remembered locally, each log object is
+//! scanned only once. Found accesses are kept in a local list.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{create_path, replace_file};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{CloudAccessAnomaly, CloudTarget};
+
+use super::backend::CloudBackend;
+use super::create_options;
+
+/// Number of anomalies kept in the local list
+const MAX_ANOMALIES: usize = 1000;
+
+/// User-Agent prefix of requests sent with the default user agent
+const DEFAULT_USER_AGENT_PREFIX: &str = "proxmox-backup-server/";
+
+/// A parsed line of an S3 server access log
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccessLogRecord {
+    pub time: i64,
+    pub remote_ip: String,
+    pub requester: String,
+    pub operation: String,
+    /// Full object key in the bucket (`None` for bucket operations)
+    pub key: Option<String>,
+    pub status: u16,
+    pub user_agent: Option<String>,
+}
+
+// split a log line into fields, keeping '[...]' and '"..."' together
+fn split_fields(line: &str) -> Result<Vec<&str>, Error> {
+    let mut fields = Vec::new();
+    let mut rest = line.trim();
+
+    while !rest.is_empty() {
+        let (field, remaining) = match rest.as_bytes()[0] {
+            b'[' => match rest.find(']') {
+                Some(end) => (&rest[1..end], &rest[end + 1..]),
+                None => bail!("unterminated '[' in access log line"),
+            },
+            b'"' => match rest[1..].find('"') {
+                Some(end) => (&rest[1..end + 1], &rest[end + 2..]),
+                None => bail!("unterminated '\"' in access log line"),
+            },
+            _ => match rest.find(' ') {
+                Some(end) => (&rest[..end], &rest[end..]),
+                None => (rest, ""),
+            },
+        };
+        fields.push(field);
+        rest = remaining.trim_start();
+    }
+
+    Ok(fields)
+}
+
+// '-' marks empty fields
+fn optional_field(field: &str) -> Option<String> {
+    match field {
+        "-" | "" => None,
+        value => Some(value.to_string()),
+    }
+}
+
+// parse a log timestamp like '06/Feb/2019:00:00:38 +0000'
+fn parse_log_time(time: &str) -> Result<i64, Error> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let parse = || -> Option<String> {
+        let (date, offset) = time.split_once(' ')?;
+        let (day, rest) = date.split_once('/')?;
+        let (month, rest) = rest.split_once('/')?;
+        let (year, clock) = rest.split_once(':')?;
+        let month = MONTHS.iter().position(|m| *m == month)? + 1;
+        if offset.len() != 5 {
+            return None;
+        }
+        Some(format!(
+            "{}-{:02}-{}T{}{}:{}",
+            year,
+            month,
+            day,
+            clock,
+            &offset[..3],
+            &offset[3..]
+        ))
+    };
+
+    let rfc3339 = parse().ok_or_else(|| format_err!("invalid access log time '{}'", time))?;
+    proxmox_time::parse_rfc3339(&rfc3339)
+}
+
+/// Parse a line of an S3 server access log
+pub fn parse_access_log_line(line: &str) -> Result<AccessLogRecord, Error> {
+    let fields = split_fields(line)?;
+    if fields.len() < 17 {
+        bail!("access log line with {} fields", fields.len());
+    }
+
+    // bucket owner, bucket, time, remote IP, requester, request ID,
+    // operation, key (URL encoded), request URI, status, ... user agent
+    Ok(AccessLogRecord {
+        time: parse_log_time(fields[2])?,
+        remote_ip: fields[3].to_string(),
+        requester: fields[4].to_string(),
+        operation: fields[6].to_string(),
+        key: optional_field(fields[7])
+            .map(|key| percent_decode_str(&key).decode_utf8_lossy().into_owned()),
+        status: fields[9]
+            .parse()
+            .map_err(|_| format_err!("invalid access log status '{}'", fields[9]))?,
+        user_agent: optional_field(fields[16]),
+    })
+}
+
+/// Checks if a logged request was sent by PBS
+pub fn is_pbs_request(target: &CloudTarget, record: &AccessLogRecord) -> bool {
+    let user_agent = match record.user_agent {
+        Some(ref user_agent) => user_agent,
+        None => return false,
+    };
+    match target.config.user_agent {
+        Some(ref expected) => user_agent == expected,
+        None => user_agent.starts_with(DEFAULT_USER_AGENT_PREFIX),
+    }
+}
+
+// key relative to the target prefix, `None` for other objects of the bucket
+fn target_key<'a>(target: &CloudTarget, key: &'a str) -> Option<&'a str> {
+    match target.config.prefix {
+        Some(ref prefix) => key.strip_prefix(prefix.as_str())?.strip_prefix('/'),
+        None => Some(key),
+    }
+}
+
+/// Find the object accesses of a log not originating from PBS
+///
+/// Only accesses to objects of the target are reported, accesses to the
+/// access logs themselves are ignored.
+pub fn find_anomalies(
+    target: &CloudTarget,
+    log_key: &str,
+    data: &str,
+) -> Result<Vec<CloudAccessAnomaly>, Error> {
+    let log_prefix = target
+        .config
+        .access_log_prefix
+        .as_ref()
+        .map(|prefix| format!("{}/", prefix));
+    let mut list = Vec::new();
+
+    for line in data.lines().filter(|line| !line.trim().is_empty()) {
+        let record = parse_access_log_line(line)
+            .map_err(|err| format_err!("unable to parse access log '{}' - {}", log_key, err))?;
+
+        if !record.operation.starts_with("REST.") || is_pbs_request(target, &record) {
+            continue;
+        }
+        let key = match record
+            .key
+            .as_deref()
+            .and_then(|key| target_key(target, key))
+        {
+            Some(key) => key,
+            None => continue,
+        };
+        if let Some(ref log_prefix) = log_prefix {
+            if key.starts_with(log_prefix.as_str()) {
+                continue;
+            }
+        }
+
+        list.push(CloudAccessAnomaly {
+            time: record.time,
+            operation: record.operation,
+            key: key.to_string(),
+            remote_ip: record.remote_ip,
+            requester: record.requester,
+            status: record.status,
+            user_agent: record.user_agent,
+            log: log_key.to_string(),
+        });
+    }
+
+    Ok(list)
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct AccessLogState {
+    /// Last processed log object (logs are delivered in key order)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_log: Option<String>,
+    anomalies: Vec<CloudAccessAnomaly>,
+}
+
+fn state_path(base_path: &Path, target: &str) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("access-log");
+    path.push(format!("{}.json", target));
+    path
+}
+
+fn load_state(path: &Path) -> Result<AccessLogState, Error> {
+    match proxmox_sys::fs::file_get_optional_contents(path)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        None => Ok(AccessLogState::default()),
+    }
+}
+
+fn save_state(path: &Path, state: &AccessLogState) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        create_path(
+            parent,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+    }
+    let data = serde_json::to_vec(state)?;
+    replace_file(path, &data, create_options(0o0640)?, true)
+}
+
+/// Anomalies found by previous scans of a target, newest first
+pub fn load_access_anomalies<P: AsRef<Path>>(
+    base_path: P,
+    target: &str,
+) -> Result<Vec<CloudAccessAnomaly>, Error> {
+    let mut list = load_state(&state_path(base_path.as_ref(), target))?.anomalies;
+    list.reverse();
+    Ok(list)
+}
+
+/// Scan the access logs delivered since the last scan
+///
+/// Returns the newly found anomalies. Logs which cannot be parsed are
+/// skipped with a warning.
+pub fn scan_access_logs<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &dyn CloudBackend,
+) -> Result<Vec<CloudAccessAnomaly>, Error> {
+    let log_prefix = match target.config.access_log_prefix {
+        Some(ref prefix) => format!("{}/", prefix),
+        None => bail!("target '{}' has no 'access-log-prefix'", target.name),
+    };
+
+    let path = state_path(base_path.as_ref(), &target.name);
+    let mut state = load_state(&path)?;
+
+    let mut logs: Vec<String> = backend
+        .list_objects(&log_prefix)?
+        .into_iter()
+        .map(|info| info.key)
+        .filter(|key| {
+            state
+                .last_log
+                .as_ref()
+                .map(|last| key > last)
+                .unwrap_or(true)
+        })
+        .collect();
+    logs.sort();
+
+    task_log!(worker, "scanning {} new access logs", logs.len());
+
+    let mut found = Vec::new();
+    for key in logs {
+        worker.check_abort()?;
+
+        let data = backend.get_object(&key)?;
+        let data = match String::from_utf8(data) {
+            Ok(data) => data,
+            Err(_) => {
+                task_warn!(worker, "access log '{}' is not valid UTF-8, skipping", key);
+                state.last_log = Some(key);
+                continue;
+            }
+        };
+
+        match find_anomalies(target, &key, &data) {
+            Ok(list) => {
+                for anomaly in list.iter() {
+                    task_warn!(
+                        worker,
+                        "{} of '{}' from {} ({}), user agent {:?}",
+                        anomaly.operation,
+                        anomaly.key,
+                        anomaly.remote_ip,
+                        anomaly.requester,
+                        anomaly.user_agent.as_deref().unwrap_or("-"),
+                    );
+                }
+                found.extend(list);
+            }
+            Err(err) => task_warn!(worker, "{}, skipping", err),
+        }
+        state.last_log = Some(key);
+    }
+
+    state.anomalies.extend(found.iter().cloned());
+    if state.anomalies.len() > MAX_ANOMALIES {
+        let excess = state.anomalies.len() - MAX_ANOMALIES;
+        state.anomalies.drain(..excess);
+    }
+    save_state(&path, &state)?;
+
+    Ok(found)
+}
@@ -0,0 +1,164 @@
+//! Backend wrapper counting requests and transferred data
+//!
+//! See [`crate::cloud::usage`].
+
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use pbs_api_types::{
+    CloudObjectLockConfig, CloudObjectVersion, CloudTargetCapabilities, CloudTransferUsage,
+};
+
+use super::{CloudBackend, CopySource, ObjectInfo, PutOptions};
+use crate::cloud::usage::TransferRecorder;
+
+pub struct AccountedBackend {
+    inner: Arc<dyn CloudBackend>,
+    recorder: Arc<TransferRecorder>,
+}
+
+impl AccountedBackend {
+    pub fn new(inner: Arc<dyn CloudBackend>, recorder: Arc<TransferRecorder>) -> Self {
+        Self { inner, recorder }
+    }
+
+    // requests are billed even if they fail, data only if transferred
+    fn upload(&self, data: &[u8], result: Result<(), Error>) -> Result<(), Error> {
+        let bytes = if result.is_ok() { data.len() as u64 } else { 0 };
+        self.recorder.record(|usage| {
+            usage.requests.put += 1;
+            usage.uploaded += bytes;
+        });
+        result
+    }
+
+    fn download(&self, result: Result<Vec<u8>, Error>) -> Result<Vec<u8>, Error> {
+        let bytes = result.as_ref().map(|data| data.len() as u64).unwrap_or(0);
+        self.recorder.record(|usage| {
+            usage.requests.get += 1;
+            usage.downloaded += bytes;
+        });
+        result
+    }
+
+    fn request<T>(&self, result: T, update: impl FnOnce(&mut CloudTransferUsage)) -> T {
+        self.recorder.record(update);
+        result
+    }
+}
+
+impl CloudBackend for AccountedBackend {
+    fn capabilities(&self) -> Result<CloudTargetCapabilities, Error> {
+        self.inner.capabilities()
+    }
+
+    fn object_lock_configuration(&self) -> Result<CloudObjectLockConfig, Error> {
+        self.request(self.inner.object_lock_configuration(), |usage| {
+            usage.requests.get += 1
+        })
+    }
+
+    fn put_object(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.upload(data, self.inner.put_object(key, data))
+    }
+
+    fn put_object_with_options(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        self.upload(data, self.inner.put_object_with_options(key, data, options))
+    }
+
+    fn put_object_multipart(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        self.upload(data, self.inner.put_object_multipart(key, data, options))
+    }
+
+    fn put_object_if_absent(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.upload(data, self.inner.put_object_if_absent(key, data))
+    }
+
+    fn get_object(&self, key: &str) -> Result<Vec<u8>, Error> {
+        self.download(self.inner.get_object(key))
+    }
+
+    fn get_object_range(&self, key: &str, offset: u64, length: u64) -> Result<Vec<u8>, Error> {
+        self.download(self.inner.get_object_range(key, offset, length))
+    }
+
+    fn head_object(&self, key: &str) -> Result<Option<ObjectInfo>, Error> {
+        self.request(self.inner.head_object(key), |usage| {
+            usage.requests.head += 1
+        })
+    }
+
+    fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, Error> {
+        self.request(self.inner.list_objects(prefix), |usage| {
+            usage.requests.list += 1
+        })
+    }
+
+    fn object_checksum(&self, key: &str) -> Result<Option<[u8; 32]>, Error> {
+        self.request(self.inner.object_checksum(key), |usage| {
+            usage.requests.head += 1
+        })
+    }
+
+    fn put_object_tags(&self, key: &str, tags: &[(String, String)]) -> Result<(), Error> {
+        self.request(self.inner.put_object_tags(key, tags), |usage| {
+            usage.requests.put += 1
+        })
+    }
+
+    fn delete_object(&self, key: &str) -> Result<(), Error> {
+        let result = self.inner.delete_object(key);
+        let deleted = u64::from(result.is_ok());
+        self.request(result, |usage| {
+            usage.requests.delete += 1;
+            usage.deleted += deleted;
+        })
+    }
+
+    fn delete_protected(&self) -> bool {
+        self.inner.delete_protected()
+    }
+
+    fn copy_object(&self, src_key: &str, dst_key: &str) -> Result<(), Error> {
+        self.request(self.inner.copy_object(src_key, dst_key), |usage| {
+            usage.requests.copy += 1
+        })
+    }
+
+    fn copy_source(&self, key: &str) -> Option<CopySource> {
+        self.inner.copy_source(key)
+    }
+
+    fn copy_object_from(&self, source: &CopySource, dst_key: &str) -> Result<bool, Error> {
+        let result = self.inner.copy_object_from(source, dst_key);
+        if let Ok(false) = result {
+            return result; // nothing was sent
+        }
+        self.request(result, |usage| usage.requests.copy += 1)
+    }
+
+    fn list_object_versions(&self, prefix: &str) -> Result<Vec<CloudObjectVersion>, Error> {
+        self.request(self.inner.list_object_versions(prefix), |usage| {
+            usage.requests.list += 1
+        })
+    }
+
+    fn get_object_version(&self, key: &str, version_id: &str) -> Result<Vec<u8>, Error> {
+        self.download(self.inner.get_object_version(key, version_id))
+    }
+
+    fn presign_get_url(&self, key: &str, expires_in: u64, epoch: i64) -> Result<String, Error> {
+        self.inner.presign_get_url(key, expires_in, epoch)
+    }
+}
@@ -0,0 +1,214 @@
+//! HTTP connections of network backends
+//!
+//! Connections use the proxy of the target (see [`super::cloud_proxy_config`]),
+//! and can be limited to one address family or bound to source addresses,
+//! e.g. to send backup traffic out of a dedicated interface. Their rate is
+//! limited by the traffic control rule matching the target, the endpoint
+//! or the network of the peer.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+
+use anyhow::{bail, format_err, Error};
+use hyper::client::{Client, HttpConnector};
+use openssl::ssl::{SslConnector, SslMethod};
+
+use proxmox_http::client::HttpsConnector;
+use proxmox_http::ProxyConfig;
+
+use pbs_api_types::{CloudIpFamily, CloudTarget};
+
+use crate::traffic_control_cache::{SharedRateLimit, TRAFFIC_CONTROL_CACHE};
+
+use super::cloud_proxy_config;
+
+/// Local addresses of cloud connections as `(IPv4, IPv6)`
+///
+/// Destinations are only connected over families with a local address,
+/// unspecified addresses (`0.0.0.0`, `::`) restrict the family without
+/// binding to a specific address.
+pub type LocalAddresses = (Option<Ipv4Addr>, Option<Ipv6Addr>);
+
+/// Combine the address family and the source addresses of a target
+pub fn local_addresses(
+    family: CloudIpFamily,
+    source: LocalAddresses,
+) -> Result<LocalAddresses, Error> {
+    match (family, source) {
+        (CloudIpFamily::Any, source) => Ok(source),
+        (CloudIpFamily::Ipv4, (None, Some(_))) => {
+            bail!("'ip-family' is ipv4, but there is only an IPv6 source address")
+        }
+        (CloudIpFamily::Ipv4, (v4, _)) => Ok((Some(v4.unwrap_or(Ipv4Addr::UNSPECIFIED)), None)),
+        (CloudIpFamily::Ipv6, (Some(_), None)) => {
+            bail!("'ip-family' is ipv6, but there is only an IPv4 source address")
+        }
+        (CloudIpFamily::Ipv6, (_, v6)) => Ok((None, Some(v6.unwrap_or(Ipv6Addr::UNSPECIFIED)))),
+    }
+}
+
+// usable (non link-local) addresses of a network interface
+fn interface_addresses(name: &str) -> Result<LocalAddresses, Error> {
+    let mut addresses: LocalAddresses = (None, None);
+    let mut found = false;
+
+    for ifaddr in nix::ifaddrs::getifaddrs()? {
+        if ifaddr.interface_name != name {
+            continue;
+        }
+        found = true;
+        let address = match ifaddr.address {
+            Some(address) => address,
+            None => continue,
+        };
+        if let Some(v4) = address.as_sockaddr_in() {
+            addresses.0.get_or_insert(Ipv4Addr::from(v4.ip()));
+        } else if let Some(v6) = address.as_sockaddr_in6() {
+            let ip = v6.ip();
+            // link-local addresses need a scope, so they cannot reach a cloud
+            if ip.segments()[0] & 0xffc0 != 0xfe80 {
+                addresses.1.get_or_insert(ip);
+            }
+        }
+    }
+
+    if !found {
+        bail!("network interface '{}' does not exist", name);
+    }
+    if addresses == (None, None) {
+        bail!("network interface '{}' has no usable address", name);
+    }
+    Ok(addresses)
+}
+
+fn parse_address<T: std::str::FromStr>(address: &Option<String>) -> Result<Option<T>, Error> {
+    address
+        .as_deref()
+        .map(|address| {
+            address
+                .parse()
+                .map_err(|_| format_err!("invalid source address '{}'", address))
+        })
+        .transpose()
+}
+
+/// Local addresses used by the connections of a target
+pub fn target_local_addresses(target: &CloudTarget) -> Result<LocalAddresses, Error> {
+    let config = &target.config;
+
+    let source = match config.source_interface {
+        Some(ref name) => interface_addresses(name)?,
+        None => (
+            parse_address(&config.source_address)?,
+            parse_address(&config.source_address6)?,
+        ),
+    };
+
+    local_addresses(config.ip_family.unwrap_or_default(), source)
+}
+
+// split an endpoint into host name and port
+fn split_host_port(host: &str) -> (&str, u16) {
+    if let Some((name, port)) = host.rsplit_once(':') {
+        if let Ok(port) = port.parse() {
+            if !name.contains(':') || name.starts_with('[') {
+                return (name.trim_start_matches('[').trim_end_matches(']'), port);
+            }
+        }
+    }
+    (host, 443)
+}
+
+// host name of `host` and the address of the peer actually connected to,
+// which is the proxy if one is used
+fn resolve_peer<'a>(host: &'a str, proxy: Option<&ProxyConfig>) -> (&'a str, Option<IpAddr>) {
+    let (name, port) = split_host_port(host);
+    let (peer_host, peer_port) = match proxy {
+        Some(proxy) => (proxy.host.as_str(), proxy.port),
+        None => (name, port),
+    };
+    let peer = (peer_host, peer_port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addresses| addresses.next())
+        .map(|address| address.ip());
+    (name, peer)
+}
+
+/// Rate limiters `(read, write)` of the connections of a target to `host`
+///
+/// The network of traffic control rules is matched against the address
+/// actually connected to, which is the proxy if one is used.
+pub fn cloud_rate_limiters(
+    target: &CloudTarget,
+    host: &str,
+    proxy: Option<&ProxyConfig>,
+) -> (Option<SharedRateLimit>, Option<SharedRateLimit>) {
+    let (name, peer) = resolve_peer(host, proxy);
+
+    let now = proxmox_time::epoch_i64();
+    let mut cache = TRAFFIC_CONTROL_CACHE.lock().unwrap();
+    cache.reload(now);
+
+    let (rule, read_limiter, write_limiter) =
+        cache.lookup_cloud_rate_limiter(&target.name, name, peer, now);
+    if !rule.is_empty() {
+        log::debug!(
+            "cloud target '{}': traffic control rule '{}' applies to '{}'",
+            target.name,
+            rule,
+            host
+        );
+    }
+    (read_limiter, write_limiter)
+}
+
+/// Download rate limit (bytes/second) of a target reaching `host`, with
+/// the limiter counting the traffic of all connections using the rule
+pub fn cloud_download_limit(
+    target: &CloudTarget,
+    host: &str,
+) -> Result<Option<(u64, SharedRateLimit)>, Error> {
+    let proxy = cloud_proxy_config(target, host)?;
+    let (name, peer) = resolve_peer(host, proxy.as_ref());
+
+    let now = proxmox_time::epoch_i64();
+    let mut cache = TRAFFIC_CONTROL_CACHE.lock().unwrap();
+    cache.reload(now);
+
+    Ok(cache.lookup_cloud_read_limit(&target.name, name, peer, now))
+}
+
+/// HTTP client used by a target to reach `host`
+pub fn cloud_http_client(
+    target: &CloudTarget,
+    host: &str,
+) -> Result<Client<HttpsConnector>, Error> {
+    let mut connector = HttpConnector::new();
+    connector.set_nodelay(true);
+    connector.enforce_http(false); // we want https...
+
+    match target_local_addresses(target)? {
+        (Some(v4), Some(v6)) => connector.set_local_addresses(v4, v6),
+        (Some(v4), None) => connector.set_local_address(Some(IpAddr::V4(v4))),
+        (None, Some(v6)) => connector.set_local_address(Some(IpAddr::V6(v6))),
+        (None, None) => {}
+    }
+
+    let ssl_connector = SslConnector::builder(SslMethod::tls())?.build();
+    let mut https = HttpsConnector::with_connector(
+        connector,
+        ssl_connector,
+        crate::tools::PROXMOX_BACKUP_TCP_KEEPALIVE_TIME,
+    );
+    let proxy = cloud_proxy_config(target, host)?;
+
+    let (read_limiter, write_limiter) = cloud_rate_limiters(target, host, proxy.as_ref());
+    https.set_read_limiter(read_limiter);
+    https.set_write_limiter(write_limiter);
+
+    if let Some(proxy) = proxy {
+        https.set_proxy(proxy);
+    }
+
+    Ok(Client::builder().build(https))
+}
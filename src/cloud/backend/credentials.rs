@@ -0,0 +1,232 @@
+//! Access credentials of object storage backends
+//!
+//! Static credentials are configured with the target. Temporary
+//! credentials (e.g. from STS or an OIDC token exchange) are fetched from
+//! a [`CredentialSource`] and renewed shortly before they expire, so long
+//! running jobs never need to be restarted. If the provider rejects
+//! credentials anyway (clock skew, revoked session), backends call
+//! [`CredentialCache::invalidate`] and retry the request once.
+
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Error};
+use serde::Deserialize;
+
+use crate::cloud::encryption_keys::zero_string;
+
+/// Renew temporary credentials this many seconds before they expire
+pub const CREDENTIAL_REFRESH_MARGIN: i64 = 300;
+
+/// Credentials used to sign requests
+pub struct CloudCredentials {
+    pub access_key: String,
+    pub secret_key: String,
+    /// Session token of temporary credentials
+    pub session_token: Option<String>,
+    /// Expiration time of temporary credentials (UNIX epoch)
+    pub expires: Option<i64>,
+}
+
+impl CloudCredentials {
+    pub fn is_expired(&self, now: i64) -> bool {
+        matches!(self.expires, Some(expires) if expires <= now)
+    }
+
+    fn needs_refresh(&self, now: i64, margin: i64) -> bool {
+        matches!(self.expires, Some(expires) if expires - margin <= now)
+    }
+}
+
+impl Drop for CloudCredentials {
+    fn drop(&mut self) {
+        zero_string(&mut self.secret_key);
+    }
+}
+
+/// Error returned if the provider rejected the credentials
+#[derive(Debug)]
+pub struct CredentialsRejected(pub String);
+
+impl std::fmt::Display for CredentialsRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "credentials rejected - {}", self.0)
+    }
+}
+
+impl std::error::Error for CredentialsRejected {}
+
+/// Test if an error was caused by rejected (e.g. expired) credentials
+pub fn is_credentials_rejected(err: &Error) -> bool {
+    err.downcast_ref::<CredentialsRejected>().is_some()
+}
+
+/// Provides fresh credentials on request
+pub trait CredentialSource: Send + Sync {
+    fn fetch(&self) -> Result<CloudCredentials, Error>;
+}
+
+/// Get credentials by running an external command
+///
+/// The command has to print JSON in the format used by the AWS
+/// `credential_process` setting:
+///
+/// ```text
+/// {"Version": 1, "AccessKeyId": "...", "SecretAccessKey": "...",
+///  "SessionToken": "...", "Expiration": "2030-01-01T00:00:00Z"}
+/// ```
+pub struct ProcessCredentials {
+    command: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ProcessOutput {
+    version: u32,
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    expiration: Option<String>,
+}
+
+impl ProcessCredentials {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+        }
+    }
+}
+
+impl CredentialSource for ProcessCredentials {
+    fn fetch(&self) -> Result<CloudCredentials, Error> {
+        let output = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(&self.command)
+            .output()
+            .map_err(|err| format_err!("unable to run credential process - {}", err))?;
+        if !output.status.success() {
+            bail!(
+                "credential process failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim(),
+            );
+        }
+
+        let output: ProcessOutput = serde_json::from_slice(&output.stdout)
+            .map_err(|err| format_err!("unable to parse credential process output - {}", err))?;
+        if output.version != 1 {
+            bail!(
+                "unsupported credential process output version {}",
+                output.version
+            );
+        }
+        let expires = match output.expiration {
+            Some(ref expiration) => Some(proxmox_time::parse_rfc3339(expiration)?),
+            None => None,
+        };
+
+        Ok(CloudCredentials {
+            access_key: output.access_key_id,
+            secret_key: output.secret_access_key,
+            session_token: output.session_token,
+            expires,
+        })
+    }
+}
+
+/// Get the credentials of the EC2 instance profile role (IMDSv2)
+pub struct InstanceRoleCredentials;
+
+impl CredentialSource for InstanceRoleCredentials {
+    fn fetch(&self) -> Result<CloudCredentials, Error> {
+        proxmox_async::runtime::block_on(
+            crate::cloud::instance_metadata::instance_role_credentials(),
+        )
+    }
+}
+
+/// Current credentials of a backend, renewed before they expire
+pub struct CredentialCache {
+    source: Option<Box<dyn CredentialSource>>,
+    current: Mutex<Option<Arc<CloudCredentials>>>,
+    margin: i64,
+}
+
+impl CredentialCache {
+    /// Credentials which never change
+    pub fn with_static(credentials: CloudCredentials) -> Self {
+        Self {
+            source: None,
+            current: Mutex::new(Some(Arc::new(credentials))),
+            margin: CREDENTIAL_REFRESH_MARGIN,
+        }
+    }
+
+    /// Credentials fetched from `source` when needed
+    pub fn with_source(source: Box<dyn CredentialSource>) -> Self {
+        Self {
+            source: Some(source),
+            current: Mutex::new(None),
+            margin: CREDENTIAL_REFRESH_MARGIN,
+        }
+    }
+
+    /// Renew credentials `margin` seconds before they expire
+    pub fn refresh_margin(mut self, margin: i64) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    /// The credentials can be renewed
+    pub fn refreshable(&self) -> bool {
+        self.source.is_some()
+    }
+
+    pub fn get(&self) -> Result<Arc<CloudCredentials>, Error> {
+        self.get_at(proxmox_time::epoch_i64())
+    }
+
+    /// Credentials to use at time `now`
+    ///
+    /// If renewing fails, the current credentials are used as long as they
+    /// did not expire.
+    pub fn get_at(&self, now: i64) -> Result<Arc<CloudCredentials>, Error> {
+        let mut current = self.current.lock().unwrap();
+
+        let source = match self.source {
+            Some(ref source) => source,
+            None => match *current {
+                Some(ref credentials) => return Ok(Arc::clone(credentials)),
+                None => bail!("no credentials available"),
+            },
+        };
+
+        if let Some(ref credentials) = *current {
+            if !credentials.needs_refresh(now, self.margin) {
+                return Ok(Arc::clone(credentials));
+            }
+        }
+
+        match source.fetch() {
+            Ok(credentials) => {
+                let credentials = Arc::new(credentials);
+                *current = Some(Arc::clone(&credentials));
+                Ok(credentials)
+            }
+            Err(err) => match *current {
+                Some(ref credentials) if !credentials.is_expired(now) => {
+                    log::warn!("unable to renew cloud credentials - {}", err);
+                    Ok(Arc::clone(credentials))
+                }
+                _ => Err(format_err!("unable to get cloud credentials - {}", err)),
+            },
+        }
+    }
+
+    /// Forget the current credentials (after the provider rejected them)
+    pub fn invalidate(&self) {
+        if self.source.is_some() {
+            *self.current.lock().unwrap() = None;
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Error classes of cloud requests
+//!
+//! Backends report failed requests as [`CloudError`] (inside an
+//! [`anyhow::Error`]), so callers can decide by error class instead of
+//! parsing messages: uploads are only retried for transient errors, and
+//! API calls answer with a matching HTTP status.
+//!
+//! Errors reported by the provider are wrapped in a [`ProviderError`],
+//! which adds the provider's error code and request IDs. The IDs are
+//! part of the message, so they show up in task logs and can be matched
+//! with the provider's logs without enabling request tracing.
+//!
+//! [`EndpointUnreachable`], [`ObjectExists`] and [`CredentialsRejected`]
+//! are kept as separate types, as the failover and conditional write
+//! logic depends on them. They are classified like their [`CloudError`]
+//! counterparts.
+
+use anyhow::Error;
+use hyper::StatusCode;
+
+use proxmox_router::HttpError;
+
+use super::credentials::CredentialsRejected;
+use super::{EndpointUnreachable, ObjectExists};
+
+/// A failed cloud request
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CloudError {
+    /// Missing permissions or rejected credentials
+    Auth(String),
+    /// The object or bucket does not exist
+    NotFound(String),
+    /// The provider asks to reduce the request rate
+    Throttled(String),
+    /// Data was corrupted in transit (checksum mismatch, short read)
+    Integrity(String),
+    /// A write precondition failed or a concurrent operation is in progress
+    Conflict(String),
+    /// No (complete) response from the endpoint
+    Network(String),
+    /// Any other error reported by the provider
+    Provider { code: String, message: String },
+}
+
+impl std::fmt::Display for CloudError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CloudError::Auth(msg)
+            | CloudError::NotFound(msg)
+            | CloudError::Throttled(msg)
+            | CloudError::Integrity(msg)
+            | CloudError::Conflict(msg)
+            | CloudError::Network(msg)
+            | CloudError::Provider { message: msg, .. } => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for CloudError {}
+
+/// Error response of a provider
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProviderError {
+    pub error: CloudError,
+    /// Error code reported by the provider (e.g. `SlowDown`)
+    pub code: Option<String>,
+    /// ID of the failed request
+    pub request_id: Option<String>,
+    /// Extended request ID (S3 `x-amz-id-2`), also needed by AWS support
+    pub extended_request_id: Option<String>,
+}
+
+impl ProviderError {
+    /// Request IDs for messages, e.g. ` (request-id X, id-2 Y)`
+    fn request_ids(&self) -> String {
+        match (&self.request_id, &self.extended_request_id) {
+            (Some(id), Some(extended)) => format!(" (request-id {}, id-2 {})", id, extended),
+            (Some(id), None) => format!(" (request-id {})", id),
+            (None, Some(extended)) => format!(" (id-2 {})", extended),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}{}", self.error, self.request_ids())
+    }
+}
+
+impl std::error::Error for ProviderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Provider error codes reporting a throttled request
+const THROTTLE_ERROR_CODES: &[&str] = &["SlowDown", "Throttling", "RequestLimitExceeded"];
+
+/// Provider error codes reporting corrupted request data
+const INTEGRITY_ERROR_CODES: &[&str] = &[
+    "BadDigest",
+    "InvalidDigest",
+    "XAmzContentSHA256Mismatch",
+    "IncompleteBody",
+];
+
+impl CloudError {
+    /// Classify an error response by HTTP status and provider error code
+    pub fn from_response(status: StatusCode, code: Option<&str>, message: String) -> Self {
+        if let Some(code) = code {
+            if THROTTLE_ERROR_CODES.contains(&code) {
+                return CloudError::Throttled(message);
+            }
+            if INTEGRITY_ERROR_CODES.contains(&code) {
+                return CloudError::Integrity(message);
+            }
+        }
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => CloudError::Auth(message),
+            StatusCode::NOT_FOUND => CloudError::NotFound(message),
+            StatusCode::CONFLICT | StatusCode::PRECONDITION_FAILED => CloudError::Conflict(message),
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                CloudError::Throttled(message)
+            }
+            _ => CloudError::Provider {
+                code: code.unwrap_or_default().to_string(),
+                message,
+            },
+        }
+    }
+
+    /// Test if repeating the request may succeed
+    pub fn is_transient(&self) -> bool {
+        match self {
+            CloudError::Throttled(_) | CloudError::Integrity(_) | CloudError::Network(_) => true,
+            CloudError::Auth(_) | CloudError::NotFound(_) | CloudError::Conflict(_) => false,
+            // internal errors of the provider, but not invalid requests
+            CloudError::Provider { code, .. } => !code.starts_with("Invalid"),
+        }
+    }
+
+    /// HTTP status to answer API calls failing with this error
+    pub fn http_status(&self) -> StatusCode {
+        match self {
+            CloudError::Auth(_) => StatusCode::FORBIDDEN,
+            CloudError::NotFound(_) => StatusCode::NOT_FOUND,
+            CloudError::Throttled(_) => StatusCode::SERVICE_UNAVAILABLE,
+            CloudError::Conflict(_) => StatusCode::CONFLICT,
+            CloudError::Integrity(_) | CloudError::Network(_) | CloudError::Provider { .. } => {
+                StatusCode::BAD_GATEWAY
+            }
+        }
+    }
+}
+
+/// Classify an error, `None` if it did not come from a cloud request
+pub fn cloud_error(err: &Error) -> Option<CloudError> {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<CloudError>() {
+            return Some(err.clone());
+        }
+        if let Some(err) = cause.downcast_ref::<EndpointUnreachable>() {
+            return Some(CloudError::Network(err.to_string()));
+        }
+        if let Some(err) = cause.downcast_ref::<ObjectExists>() {
+            return Some(CloudError::Conflict(err.to_string()));
+        }
+        if let Some(err) = cause.downcast_ref::<CredentialsRejected>() {
+            return Some(CloudError::Auth(err.to_string()));
+        }
+    }
+    None
+}
+
+/// Error details reported by the provider, if any
+pub fn provider_error(err: &Error) -> Option<&ProviderError> {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<ProviderError>())
+}
+
+/// Error message for task logs and job summaries
+///
+/// Keeps the provider's request IDs when context was added to the error.
+pub fn error_message(err: &Error) -> String {
+    let message = err.to_string();
+    match provider_error(err) {
+        Some(provider_err) if !message.ends_with(&provider_err.request_ids()) => {
+            format!("{}{}", message, provider_err.request_ids())
+        }
+        _ => message,
+    }
+}
+
+/// Test if retrying a failed operation may help
+///
+/// Errors which are no cloud errors (e.g. local I/O) count as transient.
+pub fn is_transient_error(err: &Error) -> bool {
+    cloud_error(err)
+        .map(|err| err.is_transient())
+        .unwrap_or(true)
+}
+
+/// Map cloud errors to an [`HttpError`] with matching status
+///
+/// Used by API calls which directly forward backend errors.
+pub fn into_http_error(err: Error) -> Error {
+    match cloud_error(&err) {
+        Some(cloud_err) => HttpError::new(cloud_err.http_status(), err.to_string()).into(),
+        None => err,
+    }
+}
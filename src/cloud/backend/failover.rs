@@ -0,0 +1,185 @@
+//! Backend wrapper failing over between endpoints
+//!
+//! On-premise S3 gateways are often replicated to several sites. With
+//! `failover-endpoint` configured, requests failing because the current
+//! endpoint is unreachable are sent to the next endpoint in order of
+//! priority. The endpoint which answered is used for all further requests
+//! until it fails itself (sticky until failure).
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use pbs_api_types::{CloudObjectLockConfig, CloudObjectVersion, CloudTargetCapabilities};
+
+use super::{CloudBackend, CopySource, ObjectInfo, PutOptions};
+
+/// Error returned by backends if no connection to the endpoint was possible
+#[derive(Debug)]
+pub struct EndpointUnreachable(pub String);
+
+impl std::fmt::Display for EndpointUnreachable {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "endpoint unreachable - {}", self.0)
+    }
+}
+
+impl std::error::Error for EndpointUnreachable {}
+
+/// Test if an error was caused by an unreachable endpoint
+pub fn is_endpoint_unreachable(err: &Error) -> bool {
+    err.downcast_ref::<EndpointUnreachable>().is_some()
+}
+
+pub struct FailoverBackend {
+    // (endpoint, backend) in order of priority
+    endpoints: Vec<(String, Arc<dyn CloudBackend>)>,
+    current: AtomicUsize,
+}
+
+impl FailoverBackend {
+    pub fn new(endpoints: Vec<(String, Arc<dyn CloudBackend>)>) -> Self {
+        assert!(!endpoints.is_empty());
+        Self {
+            endpoints,
+            current: AtomicUsize::new(0),
+        }
+    }
+
+    /// The endpoint currently in use
+    pub fn current_endpoint(&self) -> &str {
+        &self.endpoints[self.current.load(Ordering::SeqCst)].0
+    }
+
+    fn current_backend(&self) -> &dyn CloudBackend {
+        &*self.endpoints[self.current.load(Ordering::SeqCst)].1
+    }
+
+    // run a request, starting with the current endpoint
+    fn run<T, F>(&self, request: F) -> Result<T, Error>
+    where
+        F: Fn(&dyn CloudBackend) -> Result<T, Error>,
+    {
+        let start = self.current.load(Ordering::SeqCst);
+        let count = self.endpoints.len();
+
+        let mut index = start;
+        loop {
+            let (ref endpoint, ref backend) = self.endpoints[index];
+            match request(&**backend) {
+                Err(err) if is_endpoint_unreachable(&err) => {
+                    let next = (index + 1) % count;
+                    if next == start {
+                        return Err(err);
+                    }
+                    log::warn!(
+                        "cloud endpoint {} failed ({}), failing over to {}",
+                        endpoint,
+                        err,
+                        self.endpoints[next].0
+                    );
+                    index = next;
+                }
+                result => {
+                    if index != start {
+                        self.current.store(index, Ordering::SeqCst);
+                    }
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+impl CloudBackend for FailoverBackend {
+    fn capabilities(&self) -> Result<CloudTargetCapabilities, Error> {
+        self.run(|backend| backend.capabilities())
+    }
+
+    fn object_lock_configuration(&self) -> Result<CloudObjectLockConfig, Error> {
+        self.run(|backend| backend.object_lock_configuration())
+    }
+
+    fn put_object(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.run(|backend| backend.put_object(key, data))
+    }
+
+    fn put_object_with_options(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        self.run(|backend| backend.put_object_with_options(key, data, options))
+    }
+
+    fn put_object_multipart(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        self.run(|backend| backend.put_object_multipart(key, data, options))
+    }
+
+    fn put_object_if_absent(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.run(|backend| backend.put_object_if_absent(key, data))
+    }
+
+    fn get_object(&self, key: &str) -> Result<Vec<u8>, Error> {
+        self.run(|backend| backend.get_object(key))
+    }
+
+    fn get_object_range(&self, key: &str, offset: u64, length: u64) -> Result<Vec<u8>, Error> {
+        self.run(|backend| backend.get_object_range(key, offset, length))
+    }
+
+    fn head_object(&self, key: &str) -> Result<Option<ObjectInfo>, Error> {
+        self.run(|backend| backend.head_object(key))
+    }
+
+    fn object_checksum(&self, key: &str) -> Result<Option<[u8; 32]>, Error> {
+        self.run(|backend| backend.object_checksum(key))
+    }
+
+    fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, Error> {
+        self.run(|backend| backend.list_objects(prefix))
+    }
+
+    fn put_object_tags(&self, key: &str, tags: &[(String, String)]) -> Result<(), Error> {
+        self.run(|backend| backend.put_object_tags(key, tags))
+    }
+
+    fn delete_object(&self, key: &str) -> Result<(), Error> {
+        self.run(|backend| backend.delete_object(key))
+    }
+
+    fn delete_protected(&self) -> bool {
+        self.current_backend().delete_protected()
+    }
+
+    fn copy_object(&self, src_key: &str, dst_key: &str) -> Result<(), Error> {
+        self.run(|backend| backend.copy_object(src_key, dst_key))
+    }
+
+    fn copy_source(&self, key: &str) -> Option<CopySource> {
+        self.current_backend().copy_source(key)
+    }
+
+    fn copy_object_from(&self, source: &CopySource, dst_key: &str) -> Result<bool, Error> {
+        self.run(|backend| backend.copy_object_from(source, dst_key))
+    }
+
+    fn list_object_versions(&self, prefix: &str) -> Result<Vec<CloudObjectVersion>, Error> {
+        self.run(|backend| backend.list_object_versions(prefix))
+    }
+
+    fn get_object_version(&self, key: &str, version_id: &str) -> Result<Vec<u8>, Error> {
+        self.run(|backend| backend.get_object_version(key, version_id))
+    }
+
+    fn presign_get_url(&self, key: &str, expires_in: u64, epoch: i64) -> Result<String, Error> {
+        self.run(|backend| backend.presign_get_url(key, expires_in, epoch))
+    }
+}
@@ -0,0 +1,269 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+
+use pbs_api_types::{CloudTarget, CloudTargetCapabilities};
+
+use super::{CloudBackend, CloudError, CopySource, ObjectExists, ObjectInfo};
+
+/// Backend storing objects as files below a local directory
+///
+/// The directory may be a mounted network share. Object keys map
+/// directly to relative file paths, so the resulting tree can be
+/// synced to a real bucket later (e.g. with `rclone copy`).
+pub struct LocalBackend {
+    base: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(target: &CloudTarget) -> Result<Self, Error> {
+        let path = match target.config.path {
+            Some(ref path) => path,
+            None => bail!("cloud target '{}' has no path configured", target.name),
+        };
+
+        let mut base = PathBuf::from(path);
+        if let Some(ref prefix) = target.config.prefix {
+            base.push(prefix);
+        }
+
+        Ok(Self::with_base(base))
+    }
+
+    /// Create a backend directly on top of `base`, without target config
+    pub fn with_base<P: Into<PathBuf>>(base: P) -> Self {
+        Self { base: base.into() }
+    }
+
+    fn object_path(&self, key: &str) -> Result<PathBuf, Error> {
+        if key.is_empty() || key.starts_with('/') || key.split('/').any(|c| c == ".." || c == ".") {
+            bail!("invalid object key '{}'", key);
+        }
+        Ok(self.base.join(key))
+    }
+
+    fn copy_file(&self, src: &Path, dst_key: &str) -> Result<(), Error> {
+        let dst = self.object_path(dst_key)?;
+        let parent = match dst.parent() {
+            Some(parent) => parent,
+            None => bail!("invalid object key '{}'", dst_key),
+        };
+        std::fs::create_dir_all(parent)?;
+
+        // copy to a hidden temporary file first, so listings never see partial objects
+        let tmp = tmp_path(&dst);
+        if let Err(err) = std::fs::copy(src, &tmp).and_then(|_| std::fs::rename(&tmp, &dst)) {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    fn object_info(&self, key: String, path: &Path) -> Result<ObjectInfo, Error> {
+        let stat = std::fs::metadata(path)?;
+        Ok(ObjectInfo {
+            key,
+            size: stat.len(),
+            mtime: stat.mtime(),
+            etag: None,
+            storage_class: None,
+            retain_until: None,
+        })
+    }
+}
+
+impl CloudBackend for LocalBackend {
+    fn capabilities(&self) -> Result<CloudTargetCapabilities, Error> {
+        Ok(CloudTargetCapabilities {
+            object_lock: false,
+            storage_classes: Vec::new(),
+            ranged_reads: true,
+            conditional_put: true,
+            server_side_copy: true,
+            versioning: false,
+        })
+    }
+
+    fn put_object(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        let path = self.object_path(key)?;
+        let parent = match path.parent() {
+            Some(parent) => parent,
+            None => bail!("invalid object key '{}'", key),
+        };
+        std::fs::create_dir_all(parent)?;
+
+        // concurrent writers of the same key each use their own temporary file
+        let tmp = tmp_path(&path);
+        let result = (|| -> Result<(), Error> {
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&tmp)?;
+            file.write_all(data)?;
+            file.sync_all()?;
+            std::fs::rename(&tmp, &path)?;
+            Ok(())
+        })();
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+
+        result.map_err(|err| format_err!("unable to write object '{}' - {}", key, err))
+    }
+
+    fn put_object_if_absent(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        let path = self.object_path(key)?;
+        let parent = match path.parent() {
+            Some(parent) => parent,
+            None => bail!("invalid object key '{}'", key),
+        };
+        std::fs::create_dir_all(parent)?;
+
+        // write a hidden temporary file, then link it into place - linking
+        // fails atomically if the object exists
+        let tmp = tmp_path(&path);
+
+        let result = (|| -> Result<(), Error> {
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&tmp)?;
+            file.write_all(data)?;
+            file.sync_all()?;
+            match std::fs::hard_link(&tmp, &path) {
+                Ok(()) => Ok(()),
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    Err(ObjectExists(key.to_string()).into())
+                }
+                Err(err) => Err(err.into()),
+            }
+        })();
+
+        let _ = std::fs::remove_file(&tmp);
+
+        result.map_err(|err| {
+            if super::is_object_exists(&err) {
+                err
+            } else {
+                format_err!("unable to write object '{}' - {}", key, err)
+            }
+        })
+    }
+
+    fn get_object(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let path = self.object_path(key)?;
+        std::fs::read(&path).map_err(|err| open_error("read", key, err))
+    }
+
+    fn get_object_range(&self, key: &str, offset: u64, length: u64) -> Result<Vec<u8>, Error> {
+        let path = self.object_path(key)?;
+        let mut file = std::fs::File::open(&path).map_err(|err| open_error("open", key, err))?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::with_capacity(length as usize);
+        file.take(length).read_to_end(&mut data)?;
+        if data.len() as u64 != length {
+            return Err(CloudError::Integrity(format!(
+                "short read on object '{}' (offset {}, length {})",
+                key, offset, length
+            ))
+            .into());
+        }
+        Ok(data)
+    }
+
+    fn head_object(&self, key: &str) -> Result<Option<ObjectInfo>, Error> {
+        let path = self.object_path(key)?;
+        match self.object_info(key.to_string(), &path) {
+            Ok(info) => Ok(Some(info)),
+            Err(err) => match err.downcast_ref::<std::io::Error>() {
+                Some(io_err) if io_err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                _ => Err(err),
+            },
+        }
+    }
+
+    fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, Error> {
+        let mut list = Vec::new();
+
+        // only walk below the directory part of the prefix
+        let start = match prefix.rfind('/') {
+            Some(pos) if pos > 0 => self.object_path(&prefix[..pos])?,
+            _ => self.base.clone(),
+        };
+
+        if !start.exists() {
+            return Ok(list);
+        }
+
+        for entry in walkdir::WalkDir::new(&start).sort_by_file_name() {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(&self.base)?;
+            let key = match relative.to_str() {
+                Some(key) => key,
+                None => continue, // not created by us
+            };
+            if key.starts_with(prefix) && !key.contains("/.") && !key.starts_with('.') {
+                list.push(self.object_info(key.to_string(), entry.path())?);
+            }
+        }
+
+        Ok(list)
+    }
+
+    fn copy_object(&self, src_key: &str, dst_key: &str) -> Result<(), Error> {
+        let src = self.object_path(src_key)?;
+        self.copy_file(&src, dst_key)
+            .map_err(|err| format_err!("unable to copy object '{}' - {}", src_key, err))
+    }
+
+    fn copy_source(&self, key: &str) -> Option<CopySource> {
+        self.object_path(key).ok().map(CopySource::Local)
+    }
+
+    fn copy_object_from(&self, source: &CopySource, dst_key: &str) -> Result<bool, Error> {
+        match source {
+            CopySource::Local(path) => {
+                self.copy_file(path, dst_key)
+                    .map_err(|err| format_err!("unable to copy {:?} - {}", path, err))?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn delete_object(&self, key: &str) -> Result<(), Error> {
+        let path = self.object_path(key)?;
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(format_err!("unable to delete object '{}' - {}", key, err)),
+        }
+    }
+}
+
+// hidden, unique temporary file next to `path`
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp = path.to_owned();
+    tmp.set_file_name(format!(
+        ".{}.{}.tmp",
+        path.file_name().unwrap().to_string_lossy(),
+        proxmox_uuid::Uuid::generate(),
+    ));
+    tmp
+}
+
+// missing files are reported as missing objects
+fn open_error(what: &str, key: &str, err: std::io::Error) -> Error {
+    let message = format!("unable to {} object '{}' - {}", what, key, err);
+    if err.kind() == std::io::ErrorKind::NotFound {
+        CloudError::NotFound(message).into()
+    } else {
+        format_err!("{}", message)
+    }
+}
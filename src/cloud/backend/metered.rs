@@ -0,0 +1,125 @@
+//! Backend wrapper enforcing the egress budget of a target
+//!
+//! See [`crate::cloud::egress`].
+
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use pbs_api_types::{CloudObjectLockConfig, CloudObjectVersion, CloudTargetCapabilities};
+
+use super::{CloudBackend, CopySource, ObjectInfo, PutOptions};
+use crate::cloud::egress::EgressMeter;
+
+pub struct MeteredBackend {
+    inner: Arc<dyn CloudBackend>,
+    meter: Arc<EgressMeter>,
+}
+
+impl MeteredBackend {
+    pub fn new(inner: Arc<dyn CloudBackend>, meter: Arc<EgressMeter>) -> Self {
+        Self { inner, meter }
+    }
+
+    fn account(&self, data: Vec<u8>) -> Vec<u8> {
+        self.meter.record(data.len() as u64);
+        data
+    }
+}
+
+impl CloudBackend for MeteredBackend {
+    fn capabilities(&self) -> Result<CloudTargetCapabilities, Error> {
+        self.inner.capabilities()
+    }
+
+    fn object_lock_configuration(&self) -> Result<CloudObjectLockConfig, Error> {
+        self.inner.object_lock_configuration()
+    }
+
+    fn put_object(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.inner.put_object(key, data)
+    }
+
+    fn put_object_with_options(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        self.inner.put_object_with_options(key, data, options)
+    }
+
+    fn put_object_multipart(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        self.inner.put_object_multipart(key, data, options)
+    }
+
+    fn put_object_if_absent(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.inner.put_object_if_absent(key, data)
+    }
+
+    // the size of whole objects is only known after the download, so we
+    // can only check that some budget is left
+    fn get_object(&self, key: &str) -> Result<Vec<u8>, Error> {
+        self.meter.check(1)?;
+        Ok(self.account(self.inner.get_object(key)?))
+    }
+
+    fn get_object_range(&self, key: &str, offset: u64, length: u64) -> Result<Vec<u8>, Error> {
+        self.meter.check(length)?;
+        Ok(self.account(self.inner.get_object_range(key, offset, length)?))
+    }
+
+    fn head_object(&self, key: &str) -> Result<Option<ObjectInfo>, Error> {
+        self.inner.head_object(key)
+    }
+
+    fn object_checksum(&self, key: &str) -> Result<Option<[u8; 32]>, Error> {
+        self.inner.object_checksum(key)
+    }
+
+    fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, Error> {
+        self.inner.list_objects(prefix)
+    }
+
+    fn put_object_tags(&self, key: &str, tags: &[(String, String)]) -> Result<(), Error> {
+        self.inner.put_object_tags(key, tags)
+    }
+
+    fn delete_object(&self, key: &str) -> Result<(), Error> {
+        self.inner.delete_object(key)
+    }
+
+    fn delete_protected(&self) -> bool {
+        self.inner.delete_protected()
+    }
+
+    fn copy_object(&self, src_key: &str, dst_key: &str) -> Result<(), Error> {
+        self.inner.copy_object(src_key, dst_key)
+    }
+
+    fn copy_source(&self, key: &str) -> Option<CopySource> {
+        self.inner.copy_source(key)
+    }
+
+    fn copy_object_from(&self, source: &CopySource, dst_key: &str) -> Result<bool, Error> {
+        self.inner.copy_object_from(source, dst_key)
+    }
+
+    fn list_object_versions(&self, prefix: &str) -> Result<Vec<CloudObjectVersion>, Error> {
+        self.inner.list_object_versions(prefix)
+    }
+
+    fn get_object_version(&self, key: &str, version_id: &str) -> Result<Vec<u8>, Error> {
+        self.meter.check(1)?;
+        Ok(self.account(self.inner.get_object_version(key, version_id)?))
+    }
+
+    fn presign_get_url(&self, key: &str, expires_in: u64, epoch: i64) -> Result<String, Error> {
+        self.inner.presign_get_url(key, expires_in, epoch)
+    }
+}
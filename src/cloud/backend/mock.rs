@@ -0,0 +1,580 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+
+use pbs_api_types::{CloudObjectVersion, CloudTargetCapabilities};
+
+use super::credentials::{CloudCredentials, CredentialCache, CredentialsRejected};
+use super::{
+    CloudBackend, CloudError, EndpointUnreachable, ObjectExists, ObjectInfo, ProviderError,
+    PutOptions,
+};
+
+/// Fault injection settings of a [`MockCloudBackend`]
+///
+/// All faults are deterministic (based on request counters), so tests
+/// behave the same on every run.
+#[derive(Clone, Debug, Default)]
+pub struct MockFaults {
+    /// Delay applied to every request
+    pub latency: Option<Duration>,
+    /// Reject every n-th request with a throttling error
+    pub throttle_every: Option<u64>,
+    /// Fail every n-th request with an internal error
+    pub fail_every: Option<u64>,
+    /// Requests touching these keys always fail
+    pub fail_keys: HashSet<String>,
+    /// New objects only show up in listings after this many further requests
+    pub list_delay: u64,
+    /// Fail all requests as if the endpoint was unreachable
+    pub unreachable: bool,
+}
+
+struct MockObject {
+    data: Vec<u8>,
+    mtime: i64,
+    storage_class: Option<String>,
+    retain_until: Option<i64>,
+    tags: Vec<(String, String)>,
+    // provider checksum recorded at upload time
+    checksum: Option<[u8; 32]>,
+    // request counter value after which the object is listed
+    listed_after: u64,
+}
+
+struct MockVersion {
+    version_id: String,
+    // None for delete markers
+    data: Option<Vec<u8>>,
+    mtime: i64,
+}
+
+struct MockState {
+    objects: BTreeMap<String, MockObject>,
+    // version history (oldest first), only recorded with versioning enabled
+    versions: BTreeMap<String, Vec<MockVersion>>,
+    faults: MockFaults,
+    capabilities: CloudTargetCapabilities,
+    requests: u64,
+    // fixed clock for tests, system time if unset
+    time: Option<i64>,
+    // seconds the fixed clock advances with each request
+    time_step: i64,
+    part_size: usize,
+    // record checksums of uploads
+    provider_checksums: bool,
+    // client side credentials, requests are unauthenticated if unset
+    credentials: Option<Arc<CredentialCache>>,
+    revoked: HashSet<String>,
+    used_access_keys: Vec<String>,
+}
+
+impl MockState {
+    fn now(&self) -> i64 {
+        self.time.unwrap_or_else(proxmox_time::epoch_i64)
+    }
+
+    fn accepts(&self, credentials: &CloudCredentials) -> bool {
+        !credentials.is_expired(self.now()) && !self.revoked.contains(&credentials.access_key)
+    }
+
+    /// Sign a request like a real client, renewing rejected credentials once
+    fn authenticate(&mut self) -> Result<(), Error> {
+        let cache = match self.credentials {
+            Some(ref cache) => Arc::clone(cache),
+            None => return Ok(()),
+        };
+        let mut credentials = cache.get_at(self.now())?;
+        if !self.accepts(&credentials) && cache.refreshable() {
+            self.requests += 1;
+            cache.invalidate();
+            credentials = cache.get_at(self.now())?;
+        }
+        self.used_access_keys.push(credentials.access_key.clone());
+        if !self.accepts(&credentials) {
+            return Err(CredentialsRejected(format!(
+                "mock: ExpiredToken - access key '{}'",
+                credentials.access_key
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    fn is_locked(&self, key: &str) -> bool {
+        let now = self.now();
+        matches!(
+            self.objects.get(key),
+            Some(MockObject { retain_until: Some(until), .. }) if *until > now
+        )
+    }
+
+    fn record_version(&mut self, key: &str, data: Option<Vec<u8>>) {
+        if !self.capabilities.versioning {
+            return;
+        }
+        let version_id = format!("v{}", self.requests);
+        let mtime = self.now();
+        self.versions
+            .entry(key.to_string())
+            .or_default()
+            .push(MockVersion {
+                version_id,
+                data,
+                mtime,
+            });
+    }
+
+    fn store_object(
+        &mut self,
+        key: &str,
+        data: Vec<u8>,
+        storage_class: Option<String>,
+        retain_until: Option<i64>,
+        tags: Vec<(String, String)>,
+    ) {
+        self.record_version(key, Some(data.clone()));
+        let listed_after = self.requests + self.faults.list_delay;
+        let mtime = self.now();
+        let checksum = self.provider_checksums.then(|| openssl::sha::sha256(&data));
+        self.objects.insert(
+            key.to_string(),
+            MockObject {
+                data,
+                mtime,
+                storage_class,
+                retain_until,
+                tags,
+                checksum,
+                listed_after,
+            },
+        );
+    }
+}
+
+impl Default for MockState {
+    fn default() -> Self {
+        Self {
+            objects: BTreeMap::new(),
+            faults: MockFaults::default(),
+            capabilities: CloudTargetCapabilities {
+                object_lock: true,
+                storage_classes: vec!["STANDARD".to_string(), "ARCHIVE".to_string()],
+                ranged_reads: true,
+                conditional_put: true,
+                server_side_copy: true,
+                versioning: true,
+            },
+            versions: BTreeMap::new(),
+            requests: 0,
+            time: None,
+            time_step: 0,
+            part_size: 1024 * 1024,
+            provider_checksums: false,
+            credentials: None,
+            revoked: HashSet::new(),
+            used_access_keys: Vec::new(),
+        }
+    }
+}
+
+/// In-memory backend for tests
+///
+/// Behaves like an object store with read-after-write consistency for
+/// single objects, and can simulate latency, throttling, request failures
+/// and eventually consistent listings.
+#[derive(Default)]
+pub struct MockCloudBackend {
+    state: Mutex<MockState>,
+}
+
+impl MockCloudBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_faults(faults: MockFaults) -> Self {
+        let backend = Self::new();
+        backend.set_faults(faults);
+        backend
+    }
+
+    /// Replace the fault injection settings
+    pub fn set_faults(&self, faults: MockFaults) {
+        self.state.lock().unwrap().faults = faults;
+    }
+
+    /// Replace the reported capabilities (all features are enabled by default)
+    pub fn set_capabilities(&self, capabilities: CloudTargetCapabilities) {
+        self.state.lock().unwrap().capabilities = capabilities;
+    }
+
+    /// Use a fixed time for object modification times and retention checks
+    pub fn set_time(&self, epoch: i64) {
+        self.state.lock().unwrap().time = Some(epoch);
+    }
+
+    /// Advance the fixed clock by `seconds` after each request
+    pub fn set_time_step(&self, seconds: i64) {
+        self.state.lock().unwrap().time_step = seconds;
+    }
+
+    /// Part size used by multipart uploads
+    pub fn set_part_size(&self, size: usize) {
+        self.state.lock().unwrap().part_size = size;
+    }
+
+    /// Record the checksum of objects uploaded in a single request, like
+    /// providers do for uploads with checksum
+    pub fn set_provider_checksums(&self, enabled: bool) {
+        self.state.lock().unwrap().provider_checksums = enabled;
+    }
+
+    /// Require requests to be signed with valid credentials from `cache`
+    pub fn set_credentials(&self, cache: Arc<CredentialCache>) {
+        self.state.lock().unwrap().credentials = Some(cache);
+    }
+
+    /// Reject all further requests signed with `access_key`
+    pub fn revoke_credentials(&self, access_key: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .revoked
+            .insert(access_key.to_string());
+    }
+
+    /// Access keys of all authenticated requests so far
+    pub fn used_access_keys(&self) -> Vec<String> {
+        self.state.lock().unwrap().used_access_keys.clone()
+    }
+
+    /// Storage class, retention time and tags an object was uploaded with
+    pub fn object_options(&self, key: &str) -> Option<PutOptions> {
+        self.state
+            .lock()
+            .unwrap()
+            .objects
+            .get(key)
+            .map(|object| PutOptions {
+                storage_class: object.storage_class.clone(),
+                retain_until: object.retain_until,
+                tags: object.tags.clone(),
+            })
+    }
+
+    /// Number of requests seen so far (including failed ones)
+    pub fn request_count(&self) -> u64 {
+        self.state.lock().unwrap().requests
+    }
+
+    /// Remove an object without counting a request (simulates data loss)
+    pub fn lose_object(&self, key: &str) -> bool {
+        self.state.lock().unwrap().objects.remove(key).is_some()
+    }
+
+    /// Overwrite object data without counting a request (simulates bit rot)
+    pub fn corrupt_object(&self, key: &str, data: &[u8]) -> bool {
+        match self.state.lock().unwrap().objects.get_mut(key) {
+            Some(object) => {
+                object.data = data.to_vec();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Count a request and apply configured faults
+    fn begin_request<'a>(
+        &'a self,
+        key: &str,
+    ) -> Result<std::sync::MutexGuard<'a, MockState>, Error> {
+        let latency = self.state.lock().unwrap().faults.latency;
+        if let Some(latency) = latency {
+            std::thread::sleep(latency);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.requests += 1;
+        let count = state.requests;
+        if let Some(time) = state.time {
+            state.time = Some(time + state.time_step);
+        }
+
+        if state.faults.unreachable {
+            return Err(EndpointUnreachable("mock: connection refused".to_string()).into());
+        }
+
+        state.authenticate()?;
+
+        if state.faults.fail_keys.contains(key) {
+            bail!("mock: injected failure for '{}'", key);
+        }
+        if let Some(n) = state.faults.throttle_every {
+            if n > 0 && count % n == 0 {
+                let error = CloudError::Throttled(
+                    "mock: SlowDown - please reduce your request rate".to_string(),
+                );
+                return Err(provider_error(error, "SlowDown", count));
+            }
+        }
+        if let Some(n) = state.faults.fail_every {
+            if n > 0 && count % n == 0 {
+                let error = CloudError::Provider {
+                    code: "InternalError".to_string(),
+                    message: "mock: InternalError - we encountered an internal error".to_string(),
+                };
+                return Err(provider_error(error, "InternalError", count));
+            }
+        }
+
+        Ok(state)
+    }
+}
+
+// error response with request IDs derived from the request counter
+fn provider_error(error: CloudError, code: &str, request: u64) -> Error {
+    ProviderError {
+        error,
+        code: Some(code.to_string()),
+        request_id: Some(format!("MOCK{:012X}", request)),
+        extended_request_id: Some(format!("mock-id-2/{}", request)),
+    }
+    .into()
+}
+
+fn not_found(key: &str) -> Error {
+    CloudError::NotFound(format!("mock: no such object '{}'", key)).into()
+}
+
+impl CloudBackend for MockCloudBackend {
+    fn capabilities(&self) -> Result<CloudTargetCapabilities, Error> {
+        Ok(self.state.lock().unwrap().capabilities.clone())
+    }
+
+    fn put_object(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.put_object_with_options(key, data, &PutOptions::default())
+    }
+
+    fn put_object_with_options(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        let mut state = self.begin_request(key)?;
+        options.check_capabilities(&state.capabilities)?;
+        if state.is_locked(key) {
+            return Err(CloudError::Auth(format!(
+                "mock: AccessDenied - object '{}' is locked",
+                key
+            ))
+            .into());
+        }
+        state.store_object(
+            key,
+            data.to_vec(),
+            options.storage_class.clone(),
+            options.retain_until,
+            options.tags.clone(),
+        );
+        Ok(())
+    }
+
+    fn put_object_multipart(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        let part_size = self.state.lock().unwrap().part_size;
+        if data.len() <= part_size {
+            return self.put_object_with_options(key, data, options);
+        }
+
+        // create the upload, send each part, then complete it
+        let state = self.begin_request(key)?;
+        options.check_capabilities(&state.capabilities)?;
+        drop(state);
+
+        for _part in data.chunks(part_size) {
+            drop(self.begin_request(key)?);
+        }
+
+        let mut state = self.begin_request(key)?;
+        if state.is_locked(key) {
+            return Err(CloudError::Auth(format!(
+                "mock: AccessDenied - object '{}' is locked",
+                key
+            ))
+            .into());
+        }
+        state.store_object(
+            key,
+            data.to_vec(),
+            options.storage_class.clone(),
+            options.retain_until,
+            options.tags.clone(),
+        );
+        // only a checksum of the part checksums is recorded
+        if let Some(object) = state.objects.get_mut(key) {
+            object.checksum = None;
+        }
+        Ok(())
+    }
+
+    fn put_object_if_absent(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        let mut state = self.begin_request(key)?;
+        if !state.capabilities.conditional_put {
+            bail!("mock: NotImplemented - conditional writes not supported");
+        }
+        if state.objects.contains_key(key) {
+            return Err(ObjectExists(key.to_string()).into());
+        }
+        state.store_object(key, data.to_vec(), None, None, Vec::new());
+        Ok(())
+    }
+
+    fn get_object(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let state = self.begin_request(key)?;
+        state
+            .objects
+            .get(key)
+            .map(|object| object.data.clone())
+            .ok_or_else(|| not_found(key))
+    }
+
+    fn get_object_range(&self, key: &str, offset: u64, length: u64) -> Result<Vec<u8>, Error> {
+        let state = self.begin_request(key)?;
+        let object = state.objects.get(key).ok_or_else(|| not_found(key))?;
+        let start = offset as usize;
+        let end = start + length as usize;
+        if end > object.data.len() {
+            return Err(CloudError::Integrity(format!(
+                "short read on object '{}' (offset {}, length {})",
+                key, offset, length
+            ))
+            .into());
+        }
+        Ok(object.data[start..end].to_vec())
+    }
+
+    fn head_object(&self, key: &str) -> Result<Option<ObjectInfo>, Error> {
+        let state = self.begin_request(key)?;
+        Ok(state.objects.get(key).map(|object| ObjectInfo {
+            key: key.to_string(),
+            size: object.data.len() as u64,
+            mtime: object.mtime,
+            etag: Some(hex::encode(openssl::sha::sha256(&object.data))),
+            storage_class: object.storage_class.clone(),
+            retain_until: object.retain_until,
+        }))
+    }
+
+    fn object_checksum(&self, key: &str) -> Result<Option<[u8; 32]>, Error> {
+        let state = self.begin_request(key)?;
+        Ok(state.objects.get(key).and_then(|object| object.checksum))
+    }
+
+    fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, Error> {
+        let state = self.begin_request(prefix)?;
+        let now = state.requests;
+        Ok(state
+            .objects
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter(|(_, object)| object.listed_after <= now)
+            .map(|(key, object)| ObjectInfo {
+                key: key.clone(),
+                size: object.data.len() as u64,
+                mtime: object.mtime,
+                etag: Some(hex::encode(openssl::sha::sha256(&object.data))),
+                storage_class: object.storage_class.clone(),
+                retain_until: object.retain_until,
+            })
+            .collect())
+    }
+
+    fn put_object_tags(&self, key: &str, tags: &[(String, String)]) -> Result<(), Error> {
+        let mut state = self.begin_request(key)?;
+        match state.objects.get_mut(key) {
+            Some(object) => object.tags = tags.to_vec(),
+            None => return Err(not_found(key)),
+        }
+        Ok(())
+    }
+
+    fn delete_object(&self, key: &str) -> Result<(), Error> {
+        let mut state = self.begin_request(key)?;
+        if state.is_locked(key) {
+            return Err(CloudError::Auth(format!(
+                "mock: AccessDenied - object '{}' is locked",
+                key
+            ))
+            .into());
+        }
+        if state.objects.remove(key).is_some() {
+            state.record_version(key, None);
+        }
+        Ok(())
+    }
+
+    fn copy_object(&self, src_key: &str, dst_key: &str) -> Result<(), Error> {
+        let mut state = self.begin_request(src_key)?;
+        if state.faults.fail_keys.contains(dst_key) {
+            bail!("mock: injected failure for '{}'", dst_key);
+        }
+        let data = state
+            .objects
+            .get(src_key)
+            .map(|object| object.data.clone())
+            .ok_or_else(|| not_found(src_key))?;
+        state.store_object(dst_key, data, None, None, Vec::new());
+        Ok(())
+    }
+
+    fn list_object_versions(&self, prefix: &str) -> Result<Vec<CloudObjectVersion>, Error> {
+        let state = self.begin_request(prefix)?;
+        if !state.capabilities.versioning {
+            bail!("mock: NotImplemented - versioning not enabled");
+        }
+        let mut list = Vec::new();
+        for (key, versions) in state
+            .versions
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+        {
+            for (i, version) in versions.iter().enumerate() {
+                list.push(CloudObjectVersion {
+                    key: key.clone(),
+                    version_id: version.version_id.clone(),
+                    mtime: version.mtime,
+                    size: version.data.as_ref().map(|d| d.len() as u64).unwrap_or(0),
+                    is_latest: i + 1 == versions.len(),
+                    delete_marker: version.data.is_none(),
+                });
+            }
+        }
+        Ok(list)
+    }
+
+    fn get_object_version(&self, key: &str, version_id: &str) -> Result<Vec<u8>, Error> {
+        let state = self.begin_request(key)?;
+        state
+            .versions
+            .get(key)
+            .and_then(|versions| versions.iter().find(|v| v.version_id == version_id))
+            .and_then(|version| version.data.clone())
+            .ok_or_else(|| format_err!("mock: no such version '{}' of '{}'", version_id, key))
+    }
+
+    fn presign_get_url(&self, key: &str, expires_in: u64, epoch: i64) -> Result<String, Error> {
+        Ok(format!(
+            "https://mock.invalid/{}?expires={}",
+            key,
+            epoch + expires_in as i64
+        ))
+    }
+}
@@ -0,0 +1,485 @@
+//! Object storage backends used by cloud targets
+//!
+//! All backends store the same object layout (see [`crate::cloud::layout`]),
+//! so data written by one provider can be copied verbatim to another one.
+//! Object keys are always relative to the configured target prefix.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+use pbs_api_types::{
+    parse_cloud_object_tag, CloudBackupJobSetup, CloudObjectLockConfig, CloudObjectVersion,
+    CloudProvider, CloudRawObject, CloudTarget, CloudTargetCapabilities,
+};
+
+use super::mapping::job_target;
+use super::usage::TransferRecorder;
+use super::CLOUD_STATUS_DIR;
+
+mod accounted;
+pub use accounted::AccountedBackend;
+
+mod connect;
+pub use connect::{
+    cloud_download_limit, cloud_http_client, local_addresses, target_local_addresses,
+    LocalAddresses,
+};
+
+pub mod credentials;
+
+mod error;
+pub use error::{
+    cloud_error, error_message, into_http_error, is_transient_error, provider_error, CloudError,
+    ProviderError,
+};
+
+mod failover;
+pub use failover::{is_endpoint_unreachable, EndpointUnreachable, FailoverBackend};
+
+mod local;
+pub use local::LocalBackend;
+
+mod metered;
+pub use metered::MeteredBackend;
+
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+pub use mock::{MockCloudBackend, MockFaults};
+
+mod probe;
+pub use probe::{load_endpoint_probes, open_fastest_backend, probe_endpoints};
+
+mod protected;
+pub use protected::DeleteProtectedBackend;
+
+mod proxy;
+pub use proxy::{cloud_proxy_config, no_proxy_match, select_proxy};
+
+mod s3;
+pub use s3::{RequestKind, S3Backend};
+
+mod staged;
+pub use staged::StagingBackend;
+
+/// Metadata of a stored object
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectInfo {
+    /// Object key, relative to the target prefix
+    pub key: String,
+    /// Object size in bytes
+    pub size: u64,
+    /// Last modification time (UNIX epoch)
+    pub mtime: i64,
+    /// Provider entity tag, if available
+    pub etag: Option<String>,
+    /// Storage class, if reported by the provider
+    pub storage_class: Option<String>,
+    /// Object lock retention time (UNIX epoch), only reported by
+    /// [`CloudBackend::head_object`]
+    pub retain_until: Option<i64>,
+}
+
+impl From<ObjectInfo> for CloudRawObject {
+    fn from(info: ObjectInfo) -> Self {
+        Self {
+            key: info.key,
+            size: info.size,
+            mtime: info.mtime,
+            etag: info.etag,
+            storage_class: info.storage_class,
+        }
+    }
+}
+
+/// Location of an object, for server-side copies between targets
+///
+/// See [`CloudBackend::copy_source`] and [`CloudBackend::copy_object_from`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum CopySource {
+    /// Object in an S3 bucket
+    S3 {
+        /// Custom endpoint of the provider, `None` for AWS
+        endpoint: Option<String>,
+        bucket: String,
+        /// Full object key (including the target prefix)
+        key: String,
+    },
+    /// Object stored as local file
+    Local(PathBuf),
+}
+
+/// Error returned by [`CloudBackend::put_object_if_absent`] if the key exists
+#[derive(Debug)]
+pub struct ObjectExists(pub String);
+
+impl std::fmt::Display for ObjectExists {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "object '{}' already exists", self.0)
+    }
+}
+
+impl std::error::Error for ObjectExists {}
+
+/// Test if an error was caused by a failed write precondition
+pub fn is_object_exists(err: &Error) -> bool {
+    err.downcast_ref::<ObjectExists>().is_some()
+}
+
+/// Maximum number of tags per object (S3 limit)
+pub const MAX_OBJECT_TAGS: usize = 10;
+
+/// Per object settings for uploads
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PutOptions {
+    /// Provider specific storage class
+    pub storage_class: Option<String>,
+    /// Lock the object against deletion until this time (UNIX epoch)
+    pub retain_until: Option<i64>,
+    /// Object tags for cost attribution, ignored by backends without tagging
+    pub tags: Vec<(String, String)>,
+}
+
+impl PutOptions {
+    /// Upload options requested by a backup job
+    pub fn from_job_setup(setup: &CloudBackupJobSetup) -> Self {
+        Self {
+            storage_class: setup.storage_class.clone(),
+            retain_until: setup
+                .retention_lock
+                .map(|days| proxmox_time::epoch_i64() + (days as i64) * 24 * 3600),
+            tags: Vec::new(),
+        }
+    }
+
+    // add a tag, replacing any tag with the same key
+    fn set_tag(&mut self, key: String, value: String) {
+        match self.tags.iter_mut().find(|(k, _)| *k == key) {
+            Some(tag) => tag.1 = value,
+            None => self.tags.push((key, value)),
+        }
+    }
+
+    /// Add the object tags of the target and job (job tags replace target
+    /// tags with the same key)
+    pub fn with_object_tags(
+        mut self,
+        target: &CloudTarget,
+        setup: Option<&CloudBackupJobSetup>,
+    ) -> Result<Self, Error> {
+        for (key, value) in target.config.object_tags()? {
+            self.set_tag(key, value);
+        }
+        for tag in setup
+            .and_then(|setup| setup.object_tag.as_ref())
+            .into_iter()
+            .flatten()
+        {
+            let (key, value) = parse_cloud_object_tag(tag)?;
+            self.set_tag(key, value);
+        }
+        Ok(self)
+    }
+
+    /// Tag uploads with node, datastore and job, if enabled on the target
+    pub fn with_request_tags(
+        mut self,
+        target: &CloudTarget,
+        store: &str,
+        job: Option<&str>,
+    ) -> Self {
+        if target.config.request_tagging.unwrap_or(false) {
+            self.set_tag("node".to_string(), proxmox_sys::nodename().to_string());
+            self.set_tag("datastore".to_string(), store.to_string());
+            if let Some(job) = job {
+                self.set_tag("job".to_string(), job.to_string());
+            }
+        }
+        self
+    }
+
+    /// Check that the options are supported by a target
+    pub fn check_capabilities(&self, capabilities: &CloudTargetCapabilities) -> Result<(), Error> {
+        if let Some(ref storage_class) = self.storage_class {
+            if !capabilities.storage_classes.contains(storage_class) {
+                bail!(
+                    "storage class '{}' not supported by target (supported: {})",
+                    storage_class,
+                    capabilities.storage_classes.join(", "),
+                );
+            }
+        }
+        if self.retain_until.is_some() && !capabilities.object_lock {
+            bail!("retention lock requires object lock support on the target");
+        }
+        if self.tags.len() > MAX_OBJECT_TAGS {
+            bail!(
+                "too many object tags ({}, at most {} per object)",
+                self.tags.len(),
+                MAX_OBJECT_TAGS
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Interface implemented by all object storage providers
+///
+/// Methods are blocking, they are expected to be called from worker threads.
+pub trait CloudBackend: Send + Sync {
+    /// Features supported by the target.
+    ///
+    /// This may query the provider (e.g. bucket settings).
+    fn capabilities(&self) -> Result<CloudTargetCapabilities, Error>;
+
+    /// Object lock configuration of the bucket.
+    ///
+    /// Backends without default retention only report whether object lock
+    /// is available.
+    fn object_lock_configuration(&self) -> Result<CloudObjectLockConfig, Error> {
+        Ok(CloudObjectLockConfig {
+            enabled: self.capabilities()?.object_lock,
+            ..Default::default()
+        })
+    }
+
+    /// Store an object, replacing any existing object with the same key.
+    fn put_object(&self, key: &str, data: &[u8]) -> Result<(), Error>;
+
+    /// Store an object using the given upload options.
+    ///
+    /// Backends supporting storage classes or object lock need to override
+    /// this, the default implementation only accepts default options.
+    fn put_object_with_options(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        if options.storage_class.is_some() || options.retain_until.is_some() {
+            bail!("upload options not supported by this backend");
+        }
+        self.put_object(key, data)
+    }
+
+    /// Store a large object in several parts.
+    ///
+    /// Each part is a separate request, so long uploads survive credential
+    /// renewals and only failed parts need to be sent again. The default
+    /// implementation uploads the object at once.
+    fn put_object_multipart(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        self.put_object_with_options(key, data, options)
+    }
+
+    /// Store an object only if no object with the same key exists.
+    ///
+    /// Fails with [`ObjectExists`] if the key is already taken. Providers
+    /// with `conditional-put` capability do this atomically, the default
+    /// implementation checks for the object first and is racy.
+    fn put_object_if_absent(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        if self.head_object(key)?.is_some() {
+            return Err(ObjectExists(key.to_string()).into());
+        }
+        self.put_object(key, data)
+    }
+
+    /// Retrieve the whole content of an object.
+    fn get_object(&self, key: &str) -> Result<Vec<u8>, Error>;
+
+    /// Retrieve `length` bytes starting at `offset`.
+    fn get_object_range(&self, key: &str, offset: u64, length: u64) -> Result<Vec<u8>, Error>;
+
+    /// Query object metadata, returns `None` if the object does not exist.
+    fn head_object(&self, key: &str) -> Result<Option<ObjectInfo>, Error>;
+
+    /// List all objects whose key starts with `prefix`.
+    fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, Error>;
+
+    /// SHA-256 of the object data, as verified and stored by the provider.
+    ///
+    /// Only available for objects uploaded with a checksum of the whole
+    /// object, `None` for other objects and backends without provider
+    /// checksums.
+    fn object_checksum(&self, _key: &str) -> Result<Option<[u8; 32]>, Error> {
+        Ok(None)
+    }
+
+    /// Replace the tags of an existing object.
+    ///
+    /// Used to update the tags of objects uploaded before the tags were
+    /// configured.
+    fn put_object_tags(&self, _key: &str, _tags: &[(String, String)]) -> Result<(), Error> {
+        bail!("object tagging not supported by this backend");
+    }
+
+    /// Remove an object. Removing a non-existent object is not an error.
+    fn delete_object(&self, key: &str) -> Result<(), Error>;
+
+    /// Deletions are only queued (see [`crate::cloud::delete_queue`]).
+    ///
+    /// Callers which need an object to disappear immediately have to
+    /// overwrite it instead.
+    fn delete_protected(&self) -> bool {
+        false
+    }
+
+    /// Copy an object inside the target, replacing `dst_key` if it exists.
+    ///
+    /// Providers should override this with a server-side copy, the default
+    /// implementation downloads and re-uploads the data.
+    fn copy_object(&self, src_key: &str, dst_key: &str) -> Result<(), Error> {
+        let data = self.get_object(src_key)?;
+        self.put_object(dst_key, &data)
+    }
+
+    /// Where another target of the same provider can copy an object from.
+    ///
+    /// `None` if the backend does not support server-side copies.
+    fn copy_source(&self, _key: &str) -> Option<CopySource> {
+        None
+    }
+
+    /// Copy an object of another target without transferring the data.
+    ///
+    /// Returns `false` if a server-side copy from `source` is not possible
+    /// (e.g. another provider), callers then have to copy the data.
+    fn copy_object_from(&self, _source: &CopySource, _dst_key: &str) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    /// List all versions (including delete markers) of objects below `prefix`.
+    ///
+    /// Only available on targets with `versioning` capability.
+    fn list_object_versions(&self, _prefix: &str) -> Result<Vec<CloudObjectVersion>, Error> {
+        bail!("object versioning not supported by this backend");
+    }
+
+    /// Retrieve the content of a specific object version.
+    fn get_object_version(&self, _key: &str, _version_id: &str) -> Result<Vec<u8>, Error> {
+        bail!("object versioning not supported by this backend");
+    }
+
+    /// URL to download an object without credentials.
+    ///
+    /// The URL is signed at `epoch` and stops working `expires_in` seconds
+    /// later. Only available for providers which accept signed URLs.
+    fn presign_get_url(&self, _key: &str, _expires_in: u64, _epoch: i64) -> Result<String, Error> {
+        bail!("pre-signed URLs not supported by this backend");
+    }
+}
+
+/// Open the backend for a cloud target configuration
+pub fn open_backend(target: &CloudTarget) -> Result<Arc<dyn CloudBackend>, Error> {
+    open_backend_with_endpoint(target, None)
+}
+
+/// Open the backend of a target using an alternate endpoint
+///
+/// `None` uses the configured endpoint. Only S3 targets have endpoints.
+pub fn open_backend_with_endpoint(
+    target: &CloudTarget,
+    endpoint: Option<&str>,
+) -> Result<Arc<dyn CloudBackend>, Error> {
+    target.config.check_provider_properties()?;
+
+    let backend: Arc<dyn CloudBackend> = match target.config.provider {
+        CloudProvider::S3 => match target.config.failover_endpoint {
+            // an explicitly selected endpoint is used as is
+            Some(ref list) if endpoint.is_none() && !list.is_empty() => {
+                let mut endpoints: Vec<(String, Arc<dyn CloudBackend>)> = vec![(
+                    S3Backend::configured_endpoint(&target.config),
+                    Arc::new(S3Backend::new(target)?),
+                )];
+                for endpoint in list {
+                    endpoints.push((
+                        endpoint.clone(),
+                        Arc::new(S3Backend::with_endpoint(target, Some(endpoint))?),
+                    ));
+                }
+                Arc::new(FailoverBackend::new(endpoints))
+            }
+            _ => Arc::new(S3Backend::with_endpoint(target, endpoint)?),
+        },
+        CloudProvider::Local => Arc::new(LocalBackend::new(target)?),
+    };
+
+    let recorder = Arc::new(TransferRecorder::new(CLOUD_STATUS_DIR, &target.name));
+    let backend: Arc<dyn CloudBackend> = Arc::new(AccountedBackend::new(backend, recorder));
+
+    if target.config.delete_protection.unwrap_or(false) {
+        return Ok(Arc::new(DeleteProtectedBackend::new(
+            backend,
+            CLOUD_STATUS_DIR,
+            &target.name,
+        )));
+    }
+
+    Ok(backend)
+}
+
+/// Lookup a cloud target by name and open its backend
+pub fn open_target_backend(name: &str) -> Result<(CloudTarget, Arc<dyn CloudBackend>), Error> {
+    let target = pbs_config::cloud::lookup_target(name)
+        .map_err(|err| format_err!("unable to lookup cloud target '{}' - {}", name, err))?;
+    let backend = open_backend(&target)?;
+    Ok((target, backend))
+}
+
+/// Copy an object from another target
+///
+/// With `server_side`, the providers are asked to copy the data
+/// themselves (S3 CopyObject, or a file copy between local targets). If
+/// that is not possible, the data is downloaded and uploaded again.
+/// Returns `true` if the object was copied server-side.
+pub fn copy_between_targets(
+    source: &dyn CloudBackend,
+    src_key: &str,
+    target: &dyn CloudBackend,
+    dst_key: &str,
+    server_side: bool,
+) -> Result<bool, Error> {
+    if server_side {
+        if let Some(copy_source) = source.copy_source(src_key) {
+            if target.copy_object_from(&copy_source, dst_key)? {
+                return Ok(true);
+            }
+        }
+    }
+
+    let data = source.get_object(src_key)?;
+    target
+        .put_object_multipart(dst_key, &data, &PutOptions::default())
+        .map_err(|err| format_err!("unable to copy '{}' - {}", src_key, err))?;
+
+    Ok(false)
+}
+
+/// Check the upload options of a backup job against the capabilities of its target
+///
+/// This is done when the job is configured, so that jobs do not fail
+/// in the middle of an upload.
+pub fn check_job_capabilities(setup: &CloudBackupJobSetup) -> Result<(), Error> {
+    let options = PutOptions::from_job_setup(setup);
+    if options == PutOptions::default() && setup.object_tag.is_none() {
+        return Ok(());
+    }
+    let (target, backend) = open_target_backend(&job_target(setup)?)?;
+    let options = options.with_object_tags(&target, Some(setup))?;
+    let capabilities = backend.capabilities().map_err(|err| {
+        format_err!(
+            "unable to query capabilities of cloud target '{}' - {}",
+            target.name,
+            err
+        )
+    })?;
+    options
+        .check_capabilities(&capabilities)
+        .map_err(|err| format_err!("cloud target '{}': {}", target.name, err))
+}
@@ -0,0 +1,176 @@
+//! Endpoint latency probing
+//!
+//! Buckets can be reachable through several endpoints (multi-region
+//! access, accelerated transfer endpoints). Jobs probe all configured
+//! endpoints when they start, and use the one with the lowest latency.
+//! The results of the last probe are stored per target.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{format_err, Error};
+
+use proxmox_sys::fs::{create_path, replace_file};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{CloudEndpointProbe, CloudProvider, CloudTarget};
+
+use super::{open_backend, open_backend_with_endpoint, CloudBackend, S3Backend};
+use crate::cloud::create_options;
+use crate::cloud::layout::LEASE_KEY;
+
+/// Number of requests per endpoint, the fastest one counts
+const PROBE_SAMPLES: usize = 3;
+
+// a small object which may or may not exist
+fn probe_latency(backend: &dyn CloudBackend) -> Result<Duration, Error> {
+    let mut best: Option<Duration> = None;
+    for _ in 0..PROBE_SAMPLES {
+        let start = Instant::now();
+        backend.head_object(LEASE_KEY)?;
+        let elapsed = start.elapsed();
+        best = Some(best.map_or(elapsed, |best| best.min(elapsed)));
+    }
+    Ok(best.unwrap_or_default())
+}
+
+/// Probe the latency of all candidates
+///
+/// Returns the probe results and the index of the fastest reachable
+/// candidate (`None` if no candidate was reachable).
+pub fn probe_endpoints(
+    candidates: &[(String, Arc<dyn CloudBackend>)],
+) -> (Vec<CloudEndpointProbe>, Option<usize>) {
+    let now = proxmox_time::epoch_i64();
+
+    let mut results: Vec<CloudEndpointProbe> = candidates
+        .iter()
+        .map(|(endpoint, backend)| {
+            let (latency, error) = match probe_latency(&**backend) {
+                Ok(latency) => (Some(latency.as_millis() as u64), None),
+                Err(err) => (None, Some(err.to_string())),
+            };
+            CloudEndpointProbe {
+                endpoint: endpoint.clone(),
+                time: now,
+                latency,
+                error,
+                selected: false,
+            }
+        })
+        .collect();
+
+    // on equal latency, prefer the configured (first) endpoint
+    let selected = results
+        .iter()
+        .enumerate()
+        .filter_map(|(i, result)| result.latency.map(|latency| (latency, i)))
+        .min()
+        .map(|(_, i)| i);
+
+    if let Some(i) = selected {
+        results[i].selected = true;
+    }
+
+    (results, selected)
+}
+
+fn probe_path(base_path: &Path, target: &str) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("endpoints");
+    path.push(format!("{}.json", target));
+    path
+}
+
+/// Results of the last endpoint probe of a target
+pub fn load_endpoint_probes<P: AsRef<Path>>(
+    base_path: P,
+    target: &str,
+) -> Result<Vec<CloudEndpointProbe>, Error> {
+    let path = probe_path(base_path.as_ref(), target);
+    match proxmox_sys::fs::file_get_optional_contents(&path)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn save_endpoint_probes(
+    base_path: &Path,
+    target: &str,
+    results: &[CloudEndpointProbe],
+) -> Result<(), Error> {
+    let path = probe_path(base_path, target);
+    if let Some(parent) = path.parent() {
+        create_path(
+            parent,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+    }
+    replace_file(
+        &path,
+        &serde_json::to_vec(results)?,
+        create_options(0o0640)?,
+        true,
+    )
+}
+
+/// Open the backend of a target using its fastest endpoint
+///
+/// Targets without alternate endpoints are opened without probing.
+pub fn open_fastest_backend<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+) -> Result<Arc<dyn CloudBackend>, Error> {
+    let alternates = match target.config.alternate_endpoint {
+        Some(ref list) if target.config.provider == CloudProvider::S3 && !list.is_empty() => list,
+        _ => return open_backend(target),
+    };
+
+    let mut candidates = vec![(
+        S3Backend::configured_endpoint(&target.config),
+        open_backend(target)?,
+    )];
+    for endpoint in alternates {
+        candidates.push((
+            endpoint.clone(),
+            open_backend_with_endpoint(target, Some(endpoint))?,
+        ));
+    }
+
+    let (results, selected) = probe_endpoints(&candidates);
+    for result in results.iter() {
+        match (result.latency, &result.error) {
+            (Some(latency), _) => {
+                task_log!(worker, "endpoint {}: {} ms", result.endpoint, latency)
+            }
+            (None, Some(error)) => {
+                task_warn!(
+                    worker,
+                    "endpoint {}: unreachable - {}",
+                    result.endpoint,
+                    error
+                )
+            }
+            (None, None) => {}
+        }
+    }
+
+    if let Err(err) = save_endpoint_probes(base_path.as_ref(), &target.name, &results) {
+        task_warn!(worker, "unable to store endpoint probe results - {}", err);
+    }
+
+    match selected {
+        Some(i) => {
+            task_log!(worker, "using endpoint {}", candidates[i].0);
+            Ok(candidates.swap_remove(i).1)
+        }
+        None => {
+            task_warn!(worker, "no endpoint reachable, using configured endpoint");
+            Ok(candidates.swap_remove(0).1)
+        }
+    }
+}
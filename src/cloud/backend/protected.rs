@@ -0,0 +1,138 @@
+//! Backend wrapper for targets with delete protection
+//!
+//! Deletions are recorded in the local [`DeleteQueue`] instead of being
+//! sent to the provider, so the target credentials do not need delete
+//! permissions.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use pbs_api_types::{CloudObjectLockConfig, CloudObjectVersion, CloudTargetCapabilities};
+
+use super::{CloudBackend, CopySource, ObjectInfo, PutOptions};
+use crate::cloud::delete_queue::DeleteQueue;
+
+pub struct DeleteProtectedBackend {
+    inner: Arc<dyn CloudBackend>,
+    base_path: PathBuf,
+    target: String,
+}
+
+impl DeleteProtectedBackend {
+    pub fn new<P: Into<PathBuf>>(inner: Arc<dyn CloudBackend>, base_path: P, target: &str) -> Self {
+        Self {
+            inner,
+            base_path: base_path.into(),
+            target: target.to_string(),
+        }
+    }
+
+    // an object written again must not be removed by a queued deletion
+    fn unqueue(&self, key: &str) -> Result<(), Error> {
+        let mut queue = DeleteQueue::load(&self.base_path, &self.target)?;
+        queue.remove(key);
+        queue.save()
+    }
+}
+
+impl CloudBackend for DeleteProtectedBackend {
+    fn capabilities(&self) -> Result<CloudTargetCapabilities, Error> {
+        self.inner.capabilities()
+    }
+
+    fn object_lock_configuration(&self) -> Result<CloudObjectLockConfig, Error> {
+        self.inner.object_lock_configuration()
+    }
+
+    fn put_object(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.unqueue(key)?;
+        self.inner.put_object(key, data)
+    }
+
+    fn put_object_with_options(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        self.unqueue(key)?;
+        self.inner.put_object_with_options(key, data, options)
+    }
+
+    fn put_object_multipart(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        self.unqueue(key)?;
+        self.inner.put_object_multipart(key, data, options)
+    }
+
+    // queued keys still exist, so this never writes a queued key
+    fn put_object_if_absent(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.inner.put_object_if_absent(key, data)
+    }
+
+    fn get_object(&self, key: &str) -> Result<Vec<u8>, Error> {
+        self.inner.get_object(key)
+    }
+
+    fn get_object_range(&self, key: &str, offset: u64, length: u64) -> Result<Vec<u8>, Error> {
+        self.inner.get_object_range(key, offset, length)
+    }
+
+    fn head_object(&self, key: &str) -> Result<Option<ObjectInfo>, Error> {
+        self.inner.head_object(key)
+    }
+
+    fn object_checksum(&self, key: &str) -> Result<Option<[u8; 32]>, Error> {
+        self.inner.object_checksum(key)
+    }
+
+    fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, Error> {
+        self.inner.list_objects(prefix)
+    }
+
+    fn put_object_tags(&self, key: &str, tags: &[(String, String)]) -> Result<(), Error> {
+        self.inner.put_object_tags(key, tags)
+    }
+
+    fn delete_object(&self, key: &str) -> Result<(), Error> {
+        let mut queue = DeleteQueue::load(&self.base_path, &self.target)?;
+        queue.push(key, proxmox_time::epoch_i64());
+        queue.save()
+    }
+
+    fn delete_protected(&self) -> bool {
+        true
+    }
+
+    fn copy_object(&self, src_key: &str, dst_key: &str) -> Result<(), Error> {
+        self.unqueue(dst_key)?;
+        self.inner.copy_object(src_key, dst_key)
+    }
+
+    fn copy_source(&self, key: &str) -> Option<CopySource> {
+        self.inner.copy_source(key)
+    }
+
+    fn copy_object_from(&self, source: &CopySource, dst_key: &str) -> Result<bool, Error> {
+        self.unqueue(dst_key)?;
+        self.inner.copy_object_from(source, dst_key)
+    }
+
+    fn list_object_versions(&self, prefix: &str) -> Result<Vec<CloudObjectVersion>, Error> {
+        self.inner.list_object_versions(prefix)
+    }
+
+    fn get_object_version(&self, key: &str, version_id: &str) -> Result<Vec<u8>, Error> {
+        self.inner.get_object_version(key, version_id)
+    }
+
+    fn presign_get_url(&self, key: &str, expires_in: u64, epoch: i64) -> Result<String, Error> {
+        self.inner.presign_get_url(key, expires_in, epoch)
+    }
+}
@@ -0,0 +1,94 @@
+//! Proxy selection for cloud traffic
+//!
+//! Targets use their own `proxy`, or the `cloud-proxy` of the node
+//! config. Hosts matching the `no-proxy` list of the target or the
+//! `cloud-no-proxy` list of the node are reached directly. TLS
+//! connections are always tunneled through the proxy with CONNECT.
+
+use anyhow::{format_err, Error};
+
+use proxmox_http::ProxyConfig;
+
+use pbs_api_types::CloudTarget;
+
+// strip the port from `host[:port]` and `[address][:port]`
+fn host_name(host: &str) -> &str {
+    if let Some(rest) = host.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    match host.rsplit_once(':') {
+        Some((name, port)) if !name.contains(':') && port.parse::<u16>().is_ok() => name,
+        _ => host,
+    }
+}
+
+/// Whether `host` matches a comma separated no-proxy list
+///
+/// Entries are `*` (all hosts), domains (`.example.com` or
+/// `*.example.com`, matching all sub-domains) or host names and
+/// addresses (matching the host and its sub-domains).
+pub fn no_proxy_match(no_proxy: &str, host: &str) -> bool {
+    let host = host_name(host).trim_end_matches('.').to_lowercase();
+
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            if entry == "*" {
+                return true;
+            }
+            match entry.strip_prefix("*.").or_else(|| entry.strip_prefix('.')) {
+                Some(domain) => host.ends_with(&format!(".{}", domain)),
+                None => host == entry || host.ends_with(&format!(".{}", entry)),
+            }
+        })
+}
+
+/// Select the proxy URL used to reach `host`
+///
+/// `default_proxy` and `default_no_proxy` are the node-wide settings.
+pub fn select_proxy<'a>(
+    target: &'a CloudTarget,
+    default_proxy: Option<&'a str>,
+    default_no_proxy: Option<&str>,
+    host: &str,
+) -> Option<&'a str> {
+    let proxy = target.config.proxy.as_deref().or(default_proxy)?;
+
+    let excluded = target
+        .config
+        .no_proxy
+        .iter()
+        .map(String::as_str)
+        .chain(default_no_proxy)
+        .any(|no_proxy| no_proxy_match(no_proxy, host));
+
+    (!excluded).then_some(proxy)
+}
+
+/// The proxy configuration used by a target to reach `host`
+pub fn cloud_proxy_config(target: &CloudTarget, host: &str) -> Result<Option<ProxyConfig>, Error> {
+    // node config is optional, e.g. in regression tests
+    let node = crate::config::node::config()
+        .ok()
+        .map(|(config, _digest)| config);
+
+    let proxy = select_proxy(
+        target,
+        node.as_ref().and_then(|node| node.cloud_proxy.as_deref()),
+        node.as_ref()
+            .and_then(|node| node.cloud_no_proxy.as_deref()),
+        host,
+    );
+
+    match proxy {
+        Some(proxy) => {
+            let mut config = ProxyConfig::parse_proxy_url(proxy)
+                .map_err(|err| format_err!("invalid cloud proxy '{}' - {}", proxy, err))?;
+            config.force_connect = true;
+            Ok(Some(config))
+        }
+        None => Ok(None),
+    }
+}
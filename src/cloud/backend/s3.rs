@@ -0,0 +1,1442 @@
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+use hyper::client::Client;
+use hyper::header::HeaderMap;
+use hyper::{Body, Method, Request, StatusCode};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use proxmox_http::client::HttpsConnector;
+
+use pbs_api_types::{
+    CloudObjectLockConfig, CloudObjectVersion, CloudRequestTrace, CloudTarget,
+    CloudTargetCapabilities, CloudTargetConfig, DEFAULT_CLOUD_DATA_TIMEOUT,
+    DEFAULT_CLOUD_METADATA_TIMEOUT,
+};
+
+use super::credentials::{CloudCredentials, CredentialCache, ProcessCredentials};
+use super::{
+    cloud_http_client, CloudBackend, CloudError, CopySource, EndpointUnreachable, ObjectExists,
+    ObjectInfo, ProviderError, PutOptions,
+};
+use crate::cloud::request_trace::record_request;
+
+/// Characters which need not be encoded according to the SigV4 rules
+const AWS_URI_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Same as above, but keeps path separators
+const AWS_PATH_ENCODE_SET: &AsciiSet = &AWS_URI_ENCODE_SET.remove(b'/');
+
+const EMPTY_PAYLOAD_SHA256: &str =
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+const DEFAULT_REGION: &str = "us-east-1";
+
+/// Global endpoint of S3 Transfer Acceleration (virtual hosted style only)
+const S3_ACCELERATE_ENDPOINT: &str = "s3-accelerate.amazonaws.com";
+
+/// Longest lifetime of pre-signed URLs accepted by S3 (seconds)
+const MAX_PRESIGN_EXPIRES: u64 = 7 * 24 * 3600;
+
+/// Part size of multipart uploads (S3 requires at least 5 MiB)
+const MULTIPART_PART_SIZE: usize = 16 * 1024 * 1024;
+
+/// Object tags are limited to 128 (key) and 256 (value) characters
+const MAX_TAG_KEY_LENGTH: usize = 128;
+const MAX_TAG_VALUE_LENGTH: usize = 256;
+
+/// Error codes returned for expired or otherwise invalid credentials
+const CREDENTIAL_ERROR_CODES: &[&str] = &[
+    "ExpiredToken",
+    "TokenRefreshRequired",
+    "InvalidToken",
+    "InvalidAccessKeyId",
+];
+
+/// Storage classes offered by AWS (other providers only guarantee STANDARD)
+const AWS_STORAGE_CLASSES: &[&str] = &[
+    "STANDARD",
+    "STANDARD_IA",
+    "ONEZONE_IA",
+    "INTELLIGENT_TIERING",
+    "GLACIER_IR",
+    "GLACIER",
+    "DEEP_ARCHIVE",
+];
+
+/// Backend for Amazon S3 and S3 compatible object storage
+pub struct S3Backend {
+    client: Client<HttpsConnector>,
+    user_agent: String,
+    host: String,
+    bucket: String,
+    region: String,
+    prefix: Option<String>,
+    path_style: bool,
+    // host for object uploads (transfer acceleration)
+    upload_host: Option<String>,
+    // host for object downloads (CDN), requests are signed for `host`
+    download_host: Option<String>,
+    // endpoint is AWS itself (not a compatible service)
+    aws: bool,
+    // configured endpoint of a compatible service, for server-side copies
+    service_endpoint: Option<String>,
+    credentials: CredentialCache,
+    metadata_timeout: Duration,
+    data_timeout: Duration,
+    // send SHA-256 checksums with all uploads
+    upload_checksums: bool,
+}
+
+/// Timeout tier of a request
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RequestKind {
+    /// Small requests like HEAD, LIST or DELETE
+    Metadata,
+    /// Object up- and downloads
+    Data,
+}
+
+/// Response of a successful S3 request
+struct S3Response {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl S3Backend {
+    pub fn new(target: &CloudTarget) -> Result<Self, Error> {
+        Self::with_endpoint(target, None)
+    }
+
+    /// Access the bucket through `endpoint` instead of the configured one
+    ///
+    /// Used for alternate endpoints (other regions serving the same bucket,
+    /// accelerated transfer endpoints).
+    pub fn with_endpoint(target: &CloudTarget, endpoint: Option<&str>) -> Result<Self, Error> {
+        let config = &target.config;
+
+        let bucket = config
+            .bucket
+            .clone()
+            .ok_or_else(|| format_err!("cloud target '{}' has no bucket", target.name))?;
+        let credentials = match config.credential_process {
+            Some(ref command) => {
+                CredentialCache::with_source(Box::new(ProcessCredentials::new(command)))
+            }
+            None => {
+                let access_key = config.access_key.clone().ok_or_else(|| {
+                    format_err!("cloud target '{}' has no access key", target.name)
+                })?;
+                CredentialCache::with_static(CloudCredentials {
+                    access_key,
+                    secret_key: target.secret_key.clone(),
+                    session_token: None,
+                    expires: None,
+                })
+            }
+        };
+
+        let region = config
+            .region
+            .clone()
+            .unwrap_or_else(|| DEFAULT_REGION.to_string());
+
+        let endpoint = match endpoint {
+            Some(endpoint) => endpoint.to_string(),
+            None => Self::configured_endpoint(config),
+        };
+
+        let path_style = config.path_style.unwrap_or(false);
+
+        let host = if path_style {
+            endpoint
+        } else {
+            format!("{}.{}", bucket, endpoint)
+        };
+
+        let upload_host = config
+            .transfer_acceleration
+            .unwrap_or(false)
+            .then(|| format!("{}.{}", bucket, S3_ACCELERATE_ENDPOINT));
+
+        let user_agent = config.user_agent.clone().unwrap_or_else(|| {
+            format!(
+                "proxmox-backup-server/{} ({})",
+                pbs_buildcfg::PROXMOX_PKG_VERSION,
+                proxmox_sys::nodename()
+            )
+        });
+
+        Ok(Self {
+            client: cloud_http_client(target, &host)?,
+            user_agent,
+            host,
+            bucket,
+            region,
+            prefix: config.prefix.clone(),
+            path_style,
+            upload_host,
+            download_host: config.download_host.clone(),
+            aws: config.endpoint.is_none(),
+            service_endpoint: config.endpoint.clone(),
+            credentials,
+            metadata_timeout: Duration::from_secs(
+                config
+                    .metadata_timeout
+                    .unwrap_or(DEFAULT_CLOUD_METADATA_TIMEOUT),
+            ),
+            data_timeout: Duration::from_secs(
+                config.data_timeout.unwrap_or(DEFAULT_CLOUD_DATA_TIMEOUT),
+            ),
+            upload_checksums: config.upload_checksums.unwrap_or(false),
+        })
+    }
+
+    /// The configured endpoint (or the AWS endpoint of the region)
+    pub fn configured_endpoint(config: &CloudTargetConfig) -> String {
+        match config.endpoint {
+            Some(ref endpoint) => endpoint.clone(),
+            None => format!(
+                "s3.{}.amazonaws.com",
+                config.region.as_deref().unwrap_or(DEFAULT_REGION)
+            ),
+        }
+    }
+
+    /// Host of the bucket at the configured endpoint, `None` without bucket
+    pub fn configured_host(config: &CloudTargetConfig) -> Option<String> {
+        let bucket = config.bucket.as_ref()?;
+        let endpoint = Self::configured_endpoint(config);
+        Some(match config.path_style.unwrap_or(false) {
+            true => endpoint,
+            false => format!("{}.{}", bucket, endpoint),
+        })
+    }
+
+    // object lock configuration of the bucket, `None` if not configured
+    fn object_lock_xml(&self) -> Result<Option<String>, Error> {
+        let response = self.request(
+            RequestKind::Metadata,
+            Method::GET,
+            None,
+            &[("object-lock", "")],
+            &[],
+            Vec::new(),
+        )?;
+        let body = String::from_utf8_lossy(&response.body).into_owned();
+        if response.status.is_success() {
+            return Ok(Some(body));
+        }
+        if xml_tag_values(&body, "Code")
+            .iter()
+            .any(|code| code == "ObjectLockConfigurationNotFoundError")
+        {
+            return Ok(None);
+        }
+        self.check_response("get object lock configuration", &self.bucket, &response)?;
+        Ok(None)
+    }
+
+    fn full_key(&self, key: &str) -> String {
+        match self.prefix {
+            Some(ref prefix) => format!("{}/{}", prefix, key),
+            None => key.to_string(),
+        }
+    }
+
+    fn strip_prefix<'a>(&self, key: &'a str) -> &'a str {
+        match self.prefix {
+            Some(ref prefix) => key
+                .strip_prefix(prefix.as_str())
+                .and_then(|key| key.strip_prefix('/'))
+                .unwrap_or(key),
+            None => key,
+        }
+    }
+
+    /// Returns the (not yet encoded) request path for an object key
+    fn object_path(&self, key: Option<&str>) -> String {
+        let mut path = String::from("/");
+        if self.path_style {
+            path.push_str(&self.bucket);
+            path.push('/');
+        }
+        if let Some(key) = key {
+            path.push_str(&self.full_key(key));
+        }
+        path
+    }
+
+    /// Returns the host to send a request to, and the host to sign it for
+    ///
+    /// Object uploads use the acceleration endpoint, downloads the
+    /// download host. Only the latter forwards requests to the bucket
+    /// endpoint, so they are signed for it.
+    pub fn request_hosts(&self, kind: RequestKind, method: &Method, body: &[u8]) -> (&str, &str) {
+        if kind == RequestKind::Data {
+            if *method == Method::PUT && !body.is_empty() {
+                if let Some(ref host) = self.upload_host {
+                    return (host, host);
+                }
+            }
+            if *method == Method::GET {
+                if let Some(ref host) = self.download_host {
+                    return (host, &self.host);
+                }
+            }
+        }
+        (&self.host, &self.host)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn sign_request(
+        &self,
+        credentials: &CloudCredentials,
+        host: &str,
+        method: &Method,
+        encoded_path: &str,
+        canonical_query: &str,
+        payload_hash: &str,
+        extra_headers: &[(&str, String)],
+        epoch: i64,
+    ) -> Result<(String, String), Error> {
+        let amz_date = proxmox_time::strftime_utc("%Y%m%dT%H%M%SZ", epoch)?;
+        let date = &amz_date[..8];
+
+        // all x-amz-* headers need to be signed
+        let mut headers: Vec<(String, String)> = vec![
+            ("host".to_string(), host.to_string()),
+            ("x-amz-content-sha256".to_string(), payload_hash.to_string()),
+            ("x-amz-date".to_string(), amz_date.clone()),
+        ];
+        for (name, value) in extra_headers {
+            let name = name.to_lowercase();
+            if name.starts_with("x-amz-") {
+                headers.push((name, value.trim().to_string()));
+            }
+        }
+        headers.sort();
+
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method.as_str(),
+            encoded_path,
+            canonical_query,
+            canonical_headers,
+            signed_headers,
+            payload_hash,
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(openssl::sha::sha256(canonical_request.as_bytes())),
+        );
+
+        let signature = self.signature(credentials, date, &string_to_sign)?;
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key, scope, signed_headers, signature,
+        );
+
+        Ok((amz_date, authorization))
+    }
+
+    // SigV4 signature, using the signing key of `date` (YYYYMMDD)
+    fn signature(
+        &self,
+        credentials: &CloudCredentials,
+        date: &str,
+        string_to_sign: &str,
+    ) -> Result<String, Error> {
+        let secret = format!("AWS4{}", credentials.secret_key);
+        let key = hmac_sha256(secret.as_bytes(), date.as_bytes())?;
+        let key = hmac_sha256(&key, self.region.as_bytes())?;
+        let key = hmac_sha256(&key, b"s3")?;
+        let key = hmac_sha256(&key, b"aws4_request")?;
+        Ok(hex::encode(hmac_sha256(&key, string_to_sign.as_bytes())?))
+    }
+
+    /// Timeout of the requests of a kind, covering the whole transfer
+    pub fn timeout(&self, kind: RequestKind) -> Duration {
+        match kind {
+            RequestKind::Metadata => self.metadata_timeout,
+            RequestKind::Data => self.data_timeout,
+        }
+    }
+
+    /// Send a request, renewing the credentials once if they were rejected
+    fn request(
+        &self,
+        kind: RequestKind,
+        method: Method,
+        key: Option<&str>,
+        query: &[(&str, &str)],
+        extra_headers: &[(&str, String)],
+        body: Vec<u8>,
+    ) -> Result<S3Response, Error> {
+        let credentials = self.credentials.get()?;
+        let response = self.send_request(
+            kind,
+            &credentials,
+            &method,
+            key,
+            query,
+            extra_headers,
+            &body,
+        )?;
+
+        if self.credentials.refreshable() && is_credential_error(&response) {
+            log::info!("cloud credentials rejected, renewing");
+            self.credentials.invalidate();
+            let credentials = self.credentials.get()?;
+            return self.send_request(
+                kind,
+                &credentials,
+                &method,
+                key,
+                query,
+                extra_headers,
+                &body,
+            );
+        }
+
+        Ok(response)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn send_request(
+        &self,
+        kind: RequestKind,
+        credentials: &CloudCredentials,
+        method: &Method,
+        key: Option<&str>,
+        query: &[(&str, &str)],
+        extra_headers: &[(&str, String)],
+        body: &[u8],
+    ) -> Result<S3Response, Error> {
+        let encoded_path =
+            utf8_percent_encode(&self.object_path(key), AWS_PATH_ENCODE_SET).to_string();
+
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(k, v)| {
+                (
+                    utf8_percent_encode(k, AWS_URI_ENCODE_SET).to_string(),
+                    utf8_percent_encode(v, AWS_URI_ENCODE_SET).to_string(),
+                )
+            })
+            .collect();
+        query.sort();
+        let canonical_query = query
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let payload_hash = if body.is_empty() {
+            EMPTY_PAYLOAD_SHA256.to_string()
+        } else {
+            hex::encode(openssl::sha::sha256(body))
+        };
+
+        let mut extra_headers = extra_headers.to_vec();
+        if let Some(ref token) = credentials.session_token {
+            extra_headers.push(("x-amz-security-token", token.clone()));
+        }
+
+        let (host, signed_host) = self.request_hosts(kind, method, body);
+
+        let (amz_date, authorization) = self.sign_request(
+            credentials,
+            signed_host,
+            method,
+            &encoded_path,
+            &canonical_query,
+            &payload_hash,
+            &extra_headers,
+            proxmox_time::epoch_i64(),
+        )?;
+
+        let mut uri = format!("https://{}{}", host, encoded_path);
+        if !canonical_query.is_empty() {
+            uri.push('?');
+            uri.push_str(&canonical_query);
+        }
+
+        let mut builder = Request::builder()
+            .method(method.clone())
+            .uri(uri)
+            .header("host", host)
+            .header("user-agent", &self.user_agent)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("authorization", authorization);
+
+        for (name, value) in extra_headers {
+            builder = builder.header(name, value);
+        }
+
+        let request = builder.body(Body::from(body.to_vec()))?;
+
+        let timeout = self.timeout(kind);
+        let what = format!("{} {}", method, self.object_path(key));
+
+        let time = proxmox_time::epoch_i64();
+        let started = std::time::Instant::now();
+
+        // the timeout covers the whole transfer, including the body
+        let result = proxmox_async::runtime::block_on(async move {
+            let response = tokio::time::timeout(timeout, async move {
+                // no response at all, another endpoint may still work
+                let response = self
+                    .client
+                    .request(request)
+                    .await
+                    .map_err(|err| EndpointUnreachable(format!("{} - {}", host, err)))?;
+                let status = response.status();
+                let headers = response.headers().clone();
+                let body = hyper::body::to_bytes(response.into_body())
+                    .await
+                    .map_err(|err| {
+                        CloudError::Network(format!("{} - reading response failed - {}", host, err))
+                    })?
+                    .to_vec();
+                Ok::<_, Error>(S3Response {
+                    status,
+                    headers,
+                    body,
+                })
+            })
+            .await
+            .map_err(|_| {
+                CloudError::Network(format!(
+                    "{} timed out after {} seconds",
+                    what,
+                    timeout.as_secs()
+                ))
+            })??;
+            Ok(response)
+        });
+
+        record_request(|| CloudRequestTrace {
+            time,
+            method: method.to_string(),
+            host: host.to_string(),
+            key: key.map(String::from),
+            status: result
+                .as_ref()
+                .ok()
+                .map(|response| response.status.as_u16()),
+            duration: started.elapsed().as_millis() as u64,
+            request_id: result
+                .as_ref()
+                .ok()
+                .and_then(|response| request_id(&response.headers)),
+        });
+
+        result
+    }
+
+    fn upload_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+        data: &[u8],
+        checksums: bool,
+    ) -> Result<(), Error> {
+        let mut complete = String::from("<CompleteMultipartUpload>");
+        for (i, part) in data.chunks(MULTIPART_PART_SIZE).enumerate() {
+            let part_number = (i + 1).to_string();
+
+            let checksum = checksums.then(|| base64::encode(openssl::sha::sha256(part)));
+            let mut headers = Vec::new();
+            if let Some(ref checksum) = checksum {
+                headers.push(("x-amz-checksum-sha256", checksum.clone()));
+            }
+
+            let response = self.request(
+                RequestKind::Data,
+                Method::PUT,
+                Some(key),
+                &[("partNumber", &part_number), ("uploadId", upload_id)],
+                &headers,
+                part.to_vec(),
+            )?;
+            self.check_response("upload part", key, &response)?;
+            let etag = response
+                .headers
+                .get("etag")
+                .and_then(|value| value.to_str().ok())
+                .ok_or_else(|| format_err!("upload part of '{}' returned no etag", key))?;
+
+            complete.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag>",
+                part_number,
+                xml_escape(etag)
+            ));
+            if let Some(checksum) = checksum {
+                complete.push_str(&format!("<ChecksumSHA256>{}</ChecksumSHA256>", checksum));
+            }
+            complete.push_str("</Part>");
+        }
+        complete.push_str("</CompleteMultipartUpload>");
+
+        let response = self.request(
+            RequestKind::Data,
+            Method::POST,
+            Some(key),
+            &[("uploadId", upload_id)],
+            &[],
+            complete.into_bytes(),
+        )?;
+        self.check_response("complete multipart upload", key, &response)?;
+        // errors can also be reported after sending a success status
+        let body = String::from_utf8_lossy(&response.body);
+        if let Some(code) = xml_tag_values(&body, "Code").into_iter().next() {
+            let message = format!("complete multipart upload '{}' failed - {}", key, code);
+            return Err(response_error(&response, Some(code), message));
+        }
+        Ok(())
+    }
+
+    /// Server-side copy of `full_src_key` in `bucket` (CopyObject)
+    fn copy_from_bucket(
+        &self,
+        bucket: &str,
+        full_src_key: &str,
+        src_key: &str,
+        dst_key: &str,
+    ) -> Result<(), Error> {
+        let source = format!("/{}/{}", bucket, full_src_key);
+        let source = utf8_percent_encode(&source, AWS_PATH_ENCODE_SET).to_string();
+        let response = self.request(
+            RequestKind::Data,
+            Method::PUT,
+            Some(dst_key),
+            &[],
+            &[("x-amz-copy-source", source)],
+            Vec::new(),
+        )?;
+        self.check_response("copy object", src_key, &response)?;
+
+        // CopyObject may fail after returning 200, the error is in the body
+        let body = String::from_utf8_lossy(&response.body);
+        if let Some(code) = xml_tag_values(&body, "Code").into_iter().next() {
+            let message = format!("copy object '{}' failed - {}", src_key, code);
+            return Err(response_error(&response, Some(code), message));
+        }
+
+        Ok(())
+    }
+
+    fn check_response(&self, what: &str, key: &str, response: &S3Response) -> Result<(), Error> {
+        if response.status.is_success() {
+            return Ok(());
+        }
+
+        let body = String::from_utf8_lossy(&response.body);
+        let code = xml_tag_values(&body, "Code").into_iter().next();
+        let message = xml_tag_values(&body, "Message").into_iter().next();
+
+        let text = format!(
+            "{} '{}' failed - {} {}: {}",
+            what,
+            key,
+            response.status,
+            code.as_deref().unwrap_or_default(),
+            message.unwrap_or_default(),
+        );
+        Err(response_error(response, code, text))
+    }
+}
+
+impl CloudBackend for S3Backend {
+    fn capabilities(&self) -> Result<CloudTargetCapabilities, Error> {
+        let object_lock = match self.object_lock_xml()? {
+            Some(body) => xml_tag_values(&body, "ObjectLockEnabled")
+                .iter()
+                .any(|value| value == "Enabled"),
+            None => false,
+        };
+
+        let response = self.request(
+            RequestKind::Metadata,
+            Method::GET,
+            None,
+            &[("versioning", "")],
+            &[],
+            Vec::new(),
+        )?;
+        self.check_response("get bucket versioning", &self.bucket, &response)?;
+        let body = String::from_utf8_lossy(&response.body);
+        let versioning = xml_tag_values(&body, "Status")
+            .iter()
+            .any(|value| value == "Enabled");
+
+        let storage_classes = if self.aws {
+            AWS_STORAGE_CLASSES
+                .iter()
+                .map(|class| class.to_string())
+                .collect()
+        } else {
+            vec!["STANDARD".to_string()]
+        };
+
+        Ok(CloudTargetCapabilities {
+            object_lock,
+            storage_classes,
+            ranged_reads: true,
+            conditional_put: self.aws,
+            server_side_copy: true,
+            versioning,
+        })
+    }
+
+    fn object_lock_configuration(&self) -> Result<CloudObjectLockConfig, Error> {
+        let body = match self.object_lock_xml()? {
+            Some(body) => body,
+            None => return Ok(CloudObjectLockConfig::default()),
+        };
+
+        let first = |tag: &str| xml_tag_values(&body, tag).into_iter().next();
+        let days = first("Days").map(|v| v.parse::<u64>()).transpose()?;
+        let years = first("Years").map(|v| v.parse::<u64>()).transpose()?;
+
+        Ok(CloudObjectLockConfig {
+            enabled: first("ObjectLockEnabled").as_deref() == Some("Enabled"),
+            default_mode: first("Mode"),
+            default_days: days.or_else(|| years.map(|years| years * 365)),
+        })
+    }
+
+    fn put_object(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.put_object_with_options(key, data, &PutOptions::default())
+    }
+
+    fn put_object_with_options(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        let mut headers = Vec::new();
+        if let Some(ref storage_class) = options.storage_class {
+            headers.push(("x-amz-storage-class", storage_class.clone()));
+        }
+        if let Some(tagging) = tagging_header(&options.tags) {
+            headers.push(("x-amz-tagging", tagging));
+        }
+        // object lock requires an integrity checksum
+        if self.upload_checksums || options.retain_until.is_some() {
+            headers.push((
+                "x-amz-checksum-sha256",
+                base64::encode(openssl::sha::sha256(data)),
+            ));
+        }
+        if let Some(retain_until) = options.retain_until {
+            headers.push(("x-amz-object-lock-mode", "COMPLIANCE".to_string()));
+            headers.push((
+                "x-amz-object-lock-retain-until-date",
+                proxmox_time::epoch_to_rfc3339_utc(retain_until)?,
+            ));
+        }
+        let response = self.request(
+            RequestKind::Data,
+            Method::PUT,
+            Some(key),
+            &[],
+            &headers,
+            data.to_vec(),
+        )?;
+        self.check_response("put object", key, &response)
+    }
+
+    fn put_object_multipart(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        if data.len() <= MULTIPART_PART_SIZE {
+            return self.put_object_with_options(key, data, options);
+        }
+
+        let mut headers = Vec::new();
+        if let Some(ref storage_class) = options.storage_class {
+            headers.push(("x-amz-storage-class", storage_class.clone()));
+        }
+        if let Some(tagging) = tagging_header(&options.tags) {
+            headers.push(("x-amz-tagging", tagging));
+        }
+        // object lock requires an integrity checksum for each part
+        let checksums = self.upload_checksums || options.retain_until.is_some();
+        if checksums {
+            headers.push(("x-amz-checksum-algorithm", "SHA256".to_string()));
+        }
+        if let Some(retain_until) = options.retain_until {
+            headers.push(("x-amz-object-lock-mode", "COMPLIANCE".to_string()));
+            headers.push((
+                "x-amz-object-lock-retain-until-date",
+                proxmox_time::epoch_to_rfc3339_utc(retain_until)?,
+            ));
+        }
+
+        let response = self.request(
+            RequestKind::Metadata,
+            Method::POST,
+            Some(key),
+            &[("uploads", "")],
+            &headers,
+            Vec::new(),
+        )?;
+        self.check_response("create multipart upload", key, &response)?;
+        let body = String::from_utf8_lossy(&response.body);
+        let upload_id = xml_tag_values(&body, "UploadId")
+            .into_iter()
+            .next()
+            .ok_or_else(|| format_err!("create multipart upload '{}' returned no id", key))?;
+
+        let result = self.upload_parts(key, &upload_id, data, checksums);
+        if result.is_err() {
+            // uploaded parts are billed until the upload is aborted
+            let aborted = self
+                .request(
+                    RequestKind::Metadata,
+                    Method::DELETE,
+                    Some(key),
+                    &[("uploadId", &upload_id)],
+                    &[],
+                    Vec::new(),
+                )
+                .and_then(|response| self.check_response("abort multipart upload", key, &response));
+            if let Err(err) = aborted {
+                log::warn!("{}", err);
+            }
+        }
+        result
+    }
+
+    fn put_object_if_absent(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        let response = self.request(
+            RequestKind::Data,
+            Method::PUT,
+            Some(key),
+            &[],
+            &[("if-none-match", "*".to_string())],
+            data.to_vec(),
+        )?;
+        // 409 is returned if a concurrent conditional write is in progress
+        if response.status == StatusCode::PRECONDITION_FAILED
+            || response.status == StatusCode::CONFLICT
+        {
+            return Err(ObjectExists(key.to_string()).into());
+        }
+        self.check_response("put object", key, &response)
+    }
+
+    fn get_object(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let response = self.request(
+            RequestKind::Data,
+            Method::GET,
+            Some(key),
+            &[],
+            &[],
+            Vec::new(),
+        )?;
+        self.check_response("get object", key, &response)?;
+        Ok(response.body)
+    }
+
+    fn get_object_range(&self, key: &str, offset: u64, length: u64) -> Result<Vec<u8>, Error> {
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+        let range = format!("bytes={}-{}", offset, offset + length - 1);
+        let response = self.request(
+            RequestKind::Data,
+            Method::GET,
+            Some(key),
+            &[],
+            &[("range", range)],
+            Vec::new(),
+        )?;
+        self.check_response("get object range", key, &response)?;
+        if response.body.len() as u64 != length {
+            return Err(CloudError::Integrity(format!(
+                "short read on object '{}' (offset {}, length {})",
+                key, offset, length
+            ))
+            .into());
+        }
+        Ok(response.body)
+    }
+
+    fn head_object(&self, key: &str) -> Result<Option<ObjectInfo>, Error> {
+        let response = self.request(
+            RequestKind::Metadata,
+            Method::HEAD,
+            Some(key),
+            &[],
+            &[],
+            Vec::new(),
+        )?;
+        if response.status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        self.check_response("head object", key, &response)?;
+
+        let header_str = |name: &str| {
+            response
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+
+        let size = header_str("content-length")
+            .map(|v| v.parse::<u64>())
+            .transpose()?
+            .unwrap_or(0);
+        let mtime = header_str("last-modified")
+            .map(parse_http_date)
+            .transpose()?
+            .unwrap_or(0);
+        let etag = header_str("etag").map(|v| v.trim_matches('"').to_string());
+        // not reported for the default class
+        let storage_class = header_str("x-amz-storage-class").map(String::from);
+        let retain_until = header_str("x-amz-object-lock-retain-until-date")
+            .map(proxmox_time::parse_rfc3339)
+            .transpose()?;
+
+        Ok(Some(ObjectInfo {
+            key: key.to_string(),
+            size,
+            mtime,
+            etag,
+            storage_class,
+            retain_until,
+        }))
+    }
+
+    fn object_checksum(&self, key: &str) -> Result<Option<[u8; 32]>, Error> {
+        let response = self.request(
+            RequestKind::Metadata,
+            Method::HEAD,
+            Some(key),
+            &[],
+            &[("x-amz-checksum-mode", "ENABLED".to_string())],
+            Vec::new(),
+        )?;
+        if response.status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        self.check_response("head object", key, &response)?;
+
+        let header_str = |name: &str| {
+            response
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+
+        // multipart uploads only have a checksum of the part checksums
+        // ("<checksum>-<parts>"), which cannot be compared with the data
+        if header_str("x-amz-checksum-type") == Some("COMPOSITE") {
+            return Ok(None);
+        }
+        let checksum = match header_str("x-amz-checksum-sha256") {
+            Some(checksum) if !checksum.contains('-') => checksum,
+            _ => return Ok(None),
+        };
+        let checksum = base64::decode(checksum)
+            .map_err(|err| format_err!("head object '{}' - invalid checksum - {}", key, err))?;
+        match <[u8; 32]>::try_from(checksum.as_slice()) {
+            Ok(checksum) => Ok(Some(checksum)),
+            Err(_) => bail!("head object '{}' - invalid checksum length", key),
+        }
+    }
+
+    fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, Error> {
+        let full_prefix = self.full_key(prefix);
+        let mut list = Vec::new();
+        let mut continuation_token: Option<String> = None;
+
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", full_prefix.as_str())];
+            if let Some(ref token) = continuation_token {
+                query.push(("continuation-token", token.as_str()));
+            }
+
+            let response = self.request(
+                RequestKind::Metadata,
+                Method::GET,
+                None,
+                &query,
+                &[],
+                Vec::new(),
+            )?;
+            self.check_response("list objects", prefix, &response)?;
+
+            let body = String::from_utf8(response.body)
+                .map_err(|err| format_err!("invalid list response - {}", err))?;
+
+            for entry in xml_tag_values(&body, "Contents") {
+                let key = xml_tag_values(&entry, "Key")
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| format_err!("list entry without key"))?;
+                let size = xml_tag_values(&entry, "Size")
+                    .into_iter()
+                    .next()
+                    .map(|v| v.parse::<u64>())
+                    .transpose()?
+                    .unwrap_or(0);
+                let mtime = xml_tag_values(&entry, "LastModified")
+                    .into_iter()
+                    .next()
+                    .map(|v| parse_iso8601(&v))
+                    .transpose()?
+                    .unwrap_or(0);
+                let etag = xml_tag_values(&entry, "ETag")
+                    .into_iter()
+                    .next()
+                    .map(|v| v.trim_matches('"').to_string());
+                let storage_class = xml_tag_values(&entry, "StorageClass").into_iter().next();
+
+                list.push(ObjectInfo {
+                    key: self.strip_prefix(&key).to_string(),
+                    size,
+                    mtime,
+                    etag,
+                    storage_class,
+                    retain_until: None,
+                });
+            }
+
+            let truncated = xml_tag_values(&body, "IsTruncated")
+                .into_iter()
+                .next()
+                .map(|v| v == "true")
+                .unwrap_or(false);
+
+            if !truncated {
+                break;
+            }
+
+            continuation_token = xml_tag_values(&body, "NextContinuationToken")
+                .into_iter()
+                .next();
+            if continuation_token.is_none() {
+                bail!("truncated list response without continuation token");
+            }
+        }
+
+        Ok(list)
+    }
+
+    fn copy_object(&self, src_key: &str, dst_key: &str) -> Result<(), Error> {
+        self.copy_from_bucket(&self.bucket, &self.full_key(src_key), src_key, dst_key)
+    }
+
+    fn copy_source(&self, key: &str) -> Option<CopySource> {
+        Some(CopySource::S3 {
+            endpoint: self.service_endpoint.clone(),
+            bucket: self.bucket.clone(),
+            key: self.full_key(key),
+        })
+    }
+
+    // AWS copies between regions, other services only inside the same endpoint
+    fn copy_object_from(&self, source: &CopySource, dst_key: &str) -> Result<bool, Error> {
+        match source {
+            CopySource::S3 {
+                endpoint,
+                bucket,
+                key,
+            } if *endpoint == self.service_endpoint => {
+                self.copy_from_bucket(bucket, key, key, dst_key)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn list_object_versions(&self, prefix: &str) -> Result<Vec<CloudObjectVersion>, Error> {
+        let full_prefix = self.full_key(prefix);
+        let mut list = Vec::new();
+        let mut key_marker: Option<String> = None;
+        let mut version_id_marker: Option<String> = None;
+
+        loop {
+            let mut query = vec![("versions", ""), ("prefix", full_prefix.as_str())];
+            if let Some(ref marker) = key_marker {
+                query.push(("key-marker", marker.as_str()));
+            }
+            if let Some(ref marker) = version_id_marker {
+                query.push(("version-id-marker", marker.as_str()));
+            }
+
+            let response = self.request(
+                RequestKind::Metadata,
+                Method::GET,
+                None,
+                &query,
+                &[],
+                Vec::new(),
+            )?;
+            self.check_response("list object versions", prefix, &response)?;
+
+            let body = String::from_utf8(response.body)
+                .map_err(|err| format_err!("invalid list response - {}", err))?;
+
+            let versions = xml_tag_values(&body, "Version")
+                .into_iter()
+                .map(|entry| (entry, false));
+            let markers = xml_tag_values(&body, "DeleteMarker")
+                .into_iter()
+                .map(|entry| (entry, true));
+
+            for (entry, delete_marker) in versions.chain(markers) {
+                let value = |tag: &str| xml_tag_values(&entry, tag).into_iter().next();
+
+                let key = value("Key").ok_or_else(|| format_err!("version entry without key"))?;
+                let version_id = value("VersionId")
+                    .ok_or_else(|| format_err!("version entry without version id"))?;
+                let size = value("Size")
+                    .map(|v| v.parse::<u64>())
+                    .transpose()?
+                    .unwrap_or(0);
+                let mtime = value("LastModified")
+                    .map(|v| parse_iso8601(&v))
+                    .transpose()?
+                    .unwrap_or(0);
+                let is_latest = value("IsLatest").map(|v| v == "true").unwrap_or(false);
+
+                list.push(CloudObjectVersion {
+                    key: self.strip_prefix(&key).to_string(),
+                    version_id,
+                    mtime,
+                    size,
+                    is_latest,
+                    delete_marker,
+                });
+            }
+
+            let truncated = xml_tag_values(&body, "IsTruncated")
+                .into_iter()
+                .next()
+                .map(|v| v == "true")
+                .unwrap_or(false);
+
+            if !truncated {
+                break;
+            }
+
+            key_marker = xml_tag_values(&body, "NextKeyMarker").into_iter().next();
+            version_id_marker = xml_tag_values(&body, "NextVersionIdMarker")
+                .into_iter()
+                .next();
+            if key_marker.is_none() {
+                bail!("truncated version list response without key marker");
+            }
+        }
+
+        Ok(list)
+    }
+
+    fn get_object_version(&self, key: &str, version_id: &str) -> Result<Vec<u8>, Error> {
+        let response = self.request(
+            RequestKind::Data,
+            Method::GET,
+            Some(key),
+            &[("versionId", version_id)],
+            &[],
+            Vec::new(),
+        )?;
+        self.check_response("get object version", key, &response)?;
+        Ok(response.body)
+    }
+
+    fn presign_get_url(&self, key: &str, expires_in: u64, epoch: i64) -> Result<String, Error> {
+        if expires_in > MAX_PRESIGN_EXPIRES {
+            bail!(
+                "pre-signed URLs expire after at most {} seconds",
+                MAX_PRESIGN_EXPIRES
+            );
+        }
+
+        let credentials = self.credentials.get()?;
+        if let Some(expires) = credentials.expires {
+            if expires < epoch + expires_in as i64 {
+                bail!("temporary credentials expire before the URL would");
+            }
+        }
+
+        let amz_date = proxmox_time::strftime_utc("%Y%m%dT%H%M%SZ", epoch)?;
+        let date = &amz_date[..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+
+        let encoded_path =
+            utf8_percent_encode(&self.object_path(Some(key)), AWS_PATH_ENCODE_SET).to_string();
+        let (host, signed_host) = self.request_hosts(RequestKind::Data, &Method::GET, &[]);
+
+        // the signature is sent in the query, only the host header is signed
+        let mut query = vec![
+            ("X-Amz-Algorithm", "AWS4-HMAC-SHA256".to_string()),
+            (
+                "X-Amz-Credential",
+                format!("{}/{}", credentials.access_key, scope),
+            ),
+            ("X-Amz-Date", amz_date.clone()),
+            ("X-Amz-Expires", expires_in.to_string()),
+            ("X-Amz-SignedHeaders", "host".to_string()),
+        ];
+        if let Some(ref token) = credentials.session_token {
+            query.push(("X-Amz-Security-Token", token.clone()));
+        }
+        query.sort();
+        let canonical_query = query
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, utf8_percent_encode(v, AWS_URI_ENCODE_SET)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let canonical_request = format!(
+            "GET\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD",
+            encoded_path, canonical_query, signed_host,
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(openssl::sha::sha256(canonical_request.as_bytes())),
+        );
+        let signature = self.signature(&credentials, date, &string_to_sign)?;
+
+        Ok(format!(
+            "https://{}{}?{}&X-Amz-Signature={}",
+            host, encoded_path, canonical_query, signature
+        ))
+    }
+
+    fn put_object_tags(&self, key: &str, tags: &[(String, String)]) -> Result<(), Error> {
+        let body = tagging_xml(tags).into_bytes();
+        let headers = [(
+            "x-amz-checksum-sha256",
+            base64::encode(openssl::sha::sha256(&body)),
+        )];
+        let response = self.request(
+            RequestKind::Metadata,
+            Method::PUT,
+            Some(key),
+            &[("tagging", "")],
+            &headers,
+            body,
+        )?;
+        self.check_response("put object tagging", key, &response)
+    }
+
+    fn delete_object(&self, key: &str) -> Result<(), Error> {
+        let response = self.request(
+            RequestKind::Metadata,
+            Method::DELETE,
+            Some(key),
+            &[],
+            &[],
+            Vec::new(),
+        )?;
+        if response.status == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        self.check_response("delete object", key, &response)
+    }
+}
+
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+// request ID assigned by the provider, needed for support requests
+fn request_id(headers: &HeaderMap) -> Option<String> {
+    header_value(headers, "x-amz-request-id")
+}
+
+// classified error of a failed request, with the IDs the provider
+// needs to look it up (also reported in the body of error responses)
+fn response_error(response: &S3Response, code: Option<String>, message: String) -> Error {
+    let body = String::from_utf8_lossy(&response.body);
+    let request_id = request_id(&response.headers)
+        .or_else(|| xml_tag_values(&body, "RequestId").into_iter().next());
+    let extended_request_id = header_value(&response.headers, "x-amz-id-2")
+        .or_else(|| xml_tag_values(&body, "HostId").into_iter().next());
+    ProviderError {
+        error: CloudError::from_response(response.status, code.as_deref(), message),
+        code,
+        request_id,
+        extended_request_id,
+    }
+    .into()
+}
+
+fn is_credential_error(response: &S3Response) -> bool {
+    if response.status != StatusCode::BAD_REQUEST && response.status != StatusCode::FORBIDDEN {
+        return false;
+    }
+    let body = String::from_utf8_lossy(&response.body);
+    xml_tag_values(&body, "Code")
+        .iter()
+        .any(|code| CREDENTIAL_ERROR_CODES.contains(&code.as_str()))
+}
+
+// encode object tags for the 'x-amz-tagging' header, replacing characters
+// S3 does not accept in tags
+// replace characters not allowed in tags, truncate to the maximum length
+fn sanitize_tag(text: &str, max_length: usize) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || " +-=._:/@".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(max_length)
+        .collect()
+}
+
+// body of a PutObjectTagging request
+fn tagging_xml(tags: &[(String, String)]) -> String {
+    let mut xml = String::from("<Tagging><TagSet>");
+    for (key, value) in tags {
+        xml.push_str(&format!(
+            "<Tag><Key>{}</Key><Value>{}</Value></Tag>",
+            xml_escape(&sanitize_tag(key, MAX_TAG_KEY_LENGTH)),
+            xml_escape(&sanitize_tag(value, MAX_TAG_VALUE_LENGTH)),
+        ));
+    }
+    xml.push_str("</TagSet></Tagging>");
+    xml
+}
+
+fn tagging_header(tags: &[(String, String)]) -> Option<String> {
+    if tags.is_empty() {
+        return None;
+    }
+
+    let encode = |text: &str, max_length: usize| {
+        utf8_percent_encode(&sanitize_tag(text, max_length), AWS_URI_ENCODE_SET).to_string()
+    };
+
+    let tagging = tags
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{}={}",
+                encode(key, MAX_TAG_KEY_LENGTH),
+                encode(value, MAX_TAG_VALUE_LENGTH)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+
+    Some(tagging)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+    let key = openssl::pkey::PKey::hmac(key)?;
+    let mut signer = openssl::sign::Signer::new(openssl::hash::MessageDigest::sha256(), &key)?;
+    signer.update(data)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+/// Extract the (unescaped) content of all `<tag>` elements
+///
+/// S3 responses are simple enough that we do not need a full XML parser.
+fn xml_tag_values(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+
+    let mut values = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let end = match rest.find(&close) {
+            Some(end) => end,
+            None => break,
+        };
+        values.push(xml_unescape(&rest[..end]));
+        rest = &rest[end + close.len()..];
+    }
+    values
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Parse ISO 8601 timestamps like `2009-10-12T17:50:30.000Z`
+fn parse_iso8601(value: &str) -> Result<i64, Error> {
+    let value = match value.find('.') {
+        Some(pos) => format!("{}Z", &value[..pos]),
+        None => value.to_string(),
+    };
+    proxmox_time::parse_rfc3339(&value)
+}
+
+/// Parse HTTP dates like `Wed, 12 Oct 2009 17:50:00 GMT`
+fn parse_http_date(value: &str) -> Result<i64, Error> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.len() != 6 || parts[5] != "GMT" {
+        bail!("unable to parse http date '{}'", value);
+    }
+
+    let month = MONTHS
+        .iter()
+        .position(|m| *m == parts[2])
+        .ok_or_else(|| format_err!("unable to parse http date '{}'", value))?;
+
+    proxmox_time::parse_rfc3339(&format!(
+        "{}-{:02}-{:0>2}T{}Z",
+        parts[3],
+        month + 1,
+        parts[1],
+        parts[4]
+    ))
+}
@@ -0,0 +1,201 @@
+//! Backend wrapper for targets with write-back uploads
+//!
+//! Objects of media sets are written to the local [`StagingSpool`] instead
+//! of the provider, see [`crate::cloud::staging`]. Other objects (e.g. the
+//! target lease) are written directly. Reads see spooled objects first.
+
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+
+use pbs_api_types::{CloudObjectLockConfig, CloudObjectVersion, CloudTargetCapabilities};
+
+use super::{CloudBackend, CopySource, ObjectExists, ObjectInfo, PutOptions};
+use crate::cloud::layout::MEDIA_SET_PREFIX;
+use crate::cloud::staging::{StagedObject, StagingSpool};
+
+pub struct StagingBackend {
+    inner: Arc<dyn CloudBackend>,
+    spool: Arc<StagingSpool>,
+}
+
+impl StagingBackend {
+    pub fn new(inner: Arc<dyn CloudBackend>, spool: Arc<StagingSpool>) -> Self {
+        Self { inner, spool }
+    }
+
+    fn staged(key: &str) -> bool {
+        key.starts_with(MEDIA_SET_PREFIX)
+    }
+
+    fn lookup(&self, key: &str) -> Result<Option<StagedObject>, Error> {
+        if !Self::staged(key) {
+            return Ok(None);
+        }
+        self.spool.lookup(key)
+    }
+}
+
+fn object_info(object: &StagedObject) -> ObjectInfo {
+    ObjectInfo {
+        key: object.key.clone(),
+        size: object.size,
+        mtime: object.ctime,
+        etag: None,
+        storage_class: None,
+        retain_until: None,
+    }
+}
+
+impl CloudBackend for StagingBackend {
+    fn capabilities(&self) -> Result<CloudTargetCapabilities, Error> {
+        self.inner.capabilities()
+    }
+
+    fn object_lock_configuration(&self) -> Result<CloudObjectLockConfig, Error> {
+        self.inner.object_lock_configuration()
+    }
+
+    fn put_object(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        self.put_object_with_options(key, data, &PutOptions::default())
+    }
+
+    fn put_object_with_options(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        if Self::staged(key) {
+            return self.spool.push(key, data, options, false);
+        }
+        self.inner.put_object_with_options(key, data, options)
+    }
+
+    // multipart is decided by the uploader
+    fn put_object_multipart(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+    ) -> Result<(), Error> {
+        if Self::staged(key) {
+            return self.spool.push(key, data, options, false);
+        }
+        self.inner.put_object_multipart(key, data, options)
+    }
+
+    // checked again by the uploader, the conditional write happens there
+    fn put_object_if_absent(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        if !Self::staged(key) {
+            return self.inner.put_object_if_absent(key, data);
+        }
+        if self.spool.lookup(key)?.is_some() || self.inner.head_object(key)?.is_some() {
+            return Err(ObjectExists(key.to_string()).into());
+        }
+        self.spool.push(key, data, &PutOptions::default(), true)
+    }
+
+    fn get_object(&self, key: &str) -> Result<Vec<u8>, Error> {
+        match self.lookup(key)? {
+            Some(object) => object.read(),
+            None => self.inner.get_object(key),
+        }
+    }
+
+    fn get_object_range(&self, key: &str, offset: u64, length: u64) -> Result<Vec<u8>, Error> {
+        match self.lookup(key)? {
+            Some(object) => {
+                let data = object.read()?;
+                let start = (offset as usize).min(data.len());
+                let end = (offset.saturating_add(length) as usize).min(data.len());
+                Ok(data[start..end].to_vec())
+            }
+            None => self.inner.get_object_range(key, offset, length),
+        }
+    }
+
+    fn head_object(&self, key: &str) -> Result<Option<ObjectInfo>, Error> {
+        match self.lookup(key)? {
+            Some(object) => Ok(Some(object_info(&object))),
+            None => self.inner.head_object(key),
+        }
+    }
+
+    fn object_checksum(&self, key: &str) -> Result<Option<[u8; 32]>, Error> {
+        // spooled objects were not uploaded yet
+        match self.lookup(key)? {
+            Some(_) => Ok(None),
+            None => self.inner.object_checksum(key),
+        }
+    }
+
+    fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, Error> {
+        let mut list = self.inner.list_objects(prefix)?;
+        for object in self.spool.list()? {
+            if !object.key.starts_with(prefix) {
+                continue;
+            }
+            let info = object_info(&object);
+            match list.iter_mut().find(|listed| listed.key == info.key) {
+                Some(listed) => *listed = info,
+                None => list.push(info),
+            }
+        }
+        Ok(list)
+    }
+
+    fn put_object_tags(&self, key: &str, tags: &[(String, String)]) -> Result<(), Error> {
+        self.inner.put_object_tags(key, tags)
+    }
+
+    fn delete_object(&self, key: &str) -> Result<(), Error> {
+        if Self::staged(key) {
+            self.spool.remove_key(key)?;
+        }
+        self.inner.delete_object(key)
+    }
+
+    fn delete_protected(&self) -> bool {
+        self.inner.delete_protected()
+    }
+
+    fn copy_object(&self, src_key: &str, dst_key: &str) -> Result<(), Error> {
+        if self.lookup(src_key)?.is_some() || Self::staged(dst_key) {
+            let data = self.get_object(src_key)?;
+            return self.put_object(dst_key, &data);
+        }
+        self.inner.copy_object(src_key, dst_key)
+    }
+
+    // spooled objects do not exist on the provider yet
+    fn copy_source(&self, key: &str) -> Option<CopySource> {
+        match self.lookup(key) {
+            Ok(None) => self.inner.copy_source(key),
+            _ => None,
+        }
+    }
+
+    fn copy_object_from(&self, source: &CopySource, dst_key: &str) -> Result<bool, Error> {
+        if Self::staged(dst_key) {
+            return Ok(false);
+        }
+        self.inner.copy_object_from(source, dst_key)
+    }
+
+    fn list_object_versions(&self, prefix: &str) -> Result<Vec<CloudObjectVersion>, Error> {
+        self.inner.list_object_versions(prefix)
+    }
+
+    fn get_object_version(&self, key: &str, version_id: &str) -> Result<Vec<u8>, Error> {
+        self.inner.get_object_version(key, version_id)
+    }
+
+    fn presign_get_url(&self, key: &str, expires_in: u64, epoch: i64) -> Result<String, Error> {
+        // spooled objects were not uploaded yet
+        if self.lookup(key)?.is_some() {
+            bail!("object '{}' is not uploaded yet", key);
+        }
+        self.inner.presign_get_url(key, expires_in, epoch)
+    }
+}
@@ -0,0 +1,687 @@
+//! Cloud catalog
+//!
+//! Every media set written to a cloud target has a catalog describing
+//! its chunk archives (with the location of each chunk) and the
+//! snapshots it contains. A copy of each catalog is stored on the
+//! target itself, and a local copy is kept below a status directory
+//! (usually [`CLOUD_STATUS_DIR`](super::CLOUD_STATUS_DIR)) for fast lookups.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{replace_file, CreateOptions};
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{
+    Authid, BackupDir, BackupNamespace, CloudForeignFormat, Fingerprint, SnapshotVerifyState,
+};
+
+use super::backend::{is_object_exists, CloudBackend};
+use super::layout;
+
+/// Media set label, stored as first object of each media set
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MediaSetLabel {
+    pub uuid: Uuid,
+    /// Creation time (UNIX epoch)
+    pub ctime: i64,
+    /// Previous media set in the chain, `None` for full media sets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base: Option<Uuid>,
+    /// Node which created the media set
+    pub node: String,
+}
+
+/// Location of a chunk inside a chunk archive
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ChunkEntry {
+    #[serde(with = "hex::serde")]
+    pub digest: [u8; 32],
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ChunkArchiveEntry {
+    pub uuid: Uuid,
+    /// Source datastore
+    pub store: String,
+    /// Key used to encrypt the chunks (`None` if stored as is)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<Fingerprint>,
+    /// Total archive size in bytes
+    pub size: u64,
+    /// SHA-256 of the archive object (not recorded by older versions)
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "optional_digest"
+    )]
+    pub csum: Option<[u8; 32]>,
+    pub chunks: Vec<ChunkEntry>,
+}
+
+/// Parity object of a group of chunk archives
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ParityEntry {
+    pub uuid: Uuid,
+    /// Chunk archives of the group, in the order they were written
+    pub archives: Vec<Uuid>,
+    /// Size of the parity object (size of the largest archive)
+    pub size: u64,
+    /// SHA-256 of the parity object
+    #[serde(with = "hex::serde")]
+    pub csum: [u8; 32],
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SnapshotFileEntry {
+    pub filename: String,
+    pub size: u64,
+    #[serde(with = "hex::serde")]
+    pub csum: [u8; 32],
+}
+
+/// Data uploaded for a snapshot, counted against quotas
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AttributedUsage {
+    /// Bytes of the chunk archives and files written for the snapshot
+    pub bytes: u64,
+    /// Number of objects written for the snapshot
+    pub objects: u64,
+}
+
+impl AttributedUsage {
+    pub fn add(&mut self, other: &Self) {
+        self.bytes += other.bytes;
+        self.objects += other.objects;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SnapshotEntry {
+    pub store: String,
+    #[serde(default, skip_serializing_if = "BackupNamespace::is_root")]
+    pub ns: BackupNamespace,
+    pub snapshot: BackupDir,
+    /// Key used to encrypt files and chunks (`None` if stored as is)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<Fingerprint>,
+    pub files: Vec<SnapshotFileEntry>,
+    /// All chunks referenced by the snapshot indexes
+    #[serde(with = "digest_list")]
+    pub chunks: Vec<[u8; 32]>,
+    /// Verification state from the manifest at backup time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification: Option<SnapshotVerifyState>,
+    /// Client side encryption key fingerprint from the manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<Fingerprint>,
+    /// Owner of the backup group at backup time (not recorded by older versions)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Authid>,
+    /// Data uploaded for the snapshot (not recorded by older versions)
+    #[serde(default, skip_serializing_if = "AttributedUsage::is_empty")]
+    pub attributed: AttributedUsage,
+    /// Last verification of the uploaded objects (see [`crate::cloud::verify`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cloud_verification: Option<SnapshotVerifyState>,
+}
+
+impl SnapshotEntry {
+    pub fn matches(&self, store: &str, ns: &BackupNamespace, snapshot: &BackupDir) -> bool {
+        self.store == store && &self.ns == ns && &self.snapshot == snapshot
+    }
+}
+
+/// A snapshot moved to the trash (see [`crate::cloud::trash`])
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TrashedSnapshot {
+    #[serde(flatten)]
+    pub entry: SnapshotEntry,
+    /// Deletion time (UNIX epoch)
+    pub deleted: i64,
+}
+
+/// Foreign data of an imported media set (see [`crate::cloud::foreign_import`])
+///
+/// Imported media sets are read-only. Their chunk archive lists single
+/// chunk objects of the foreign layout (each at offset 0), and their
+/// snapshot files are read from there as well.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ImportSource {
+    pub format: CloudForeignFormat,
+    /// Key prefix of the foreign data, relative to the target prefix
+    pub prefix: String,
+}
+
+impl ImportSource {
+    pub fn chunk_key(&self, digest: &[u8; 32]) -> String {
+        layout::datastore_chunk_key(&self.prefix, digest)
+    }
+
+    pub fn snapshot_file_key(
+        &self,
+        ns: &BackupNamespace,
+        snapshot: &BackupDir,
+        filename: &str,
+    ) -> String {
+        layout::datastore_snapshot_file_key(&self.prefix, ns, snapshot, filename)
+    }
+}
+
+/// Catalog of a single media set
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MediaSetCatalog {
+    #[serde(flatten)]
+    pub label: MediaSetLabel,
+    pub archives: Vec<ChunkArchiveEntry>,
+    pub snapshots: Vec<SnapshotEntry>,
+    /// Parity objects (only written if the target has 'parity-group' set)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parity: Vec<ParityEntry>,
+    /// Deleted snapshots, their chunks are kept until they are purged
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trash: Vec<TrashedSnapshot>,
+    /// Foreign data the media set was imported from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub import: Option<ImportSource>,
+}
+
+impl MediaSetCatalog {
+    pub fn new(label: MediaSetLabel) -> Self {
+        Self {
+            label,
+            archives: Vec::new(),
+            snapshots: Vec::new(),
+            parity: Vec::new(),
+            trash: Vec::new(),
+            import: None,
+        }
+    }
+
+    pub fn uuid(&self) -> &Uuid {
+        &self.label.uuid
+    }
+
+    /// The media set refers to foreign data and must not be modified
+    pub fn is_imported(&self) -> bool {
+        self.import.is_some()
+    }
+
+    /// Object key of a snapshot file of this media set
+    pub fn snapshot_file_key(&self, entry: &SnapshotEntry, filename: &str) -> String {
+        match self.import {
+            Some(ref import) => import.snapshot_file_key(&entry.ns, &entry.snapshot, filename),
+            None => layout::snapshot_file_key(
+                self.uuid(),
+                &entry.store,
+                &entry.ns,
+                &entry.snapshot,
+                filename,
+            ),
+        }
+    }
+
+    pub fn contains_snapshot(
+        &self,
+        store: &str,
+        ns: &BackupNamespace,
+        snapshot: &BackupDir,
+    ) -> bool {
+        self.snapshots
+            .iter()
+            .any(|entry| entry.matches(store, ns, snapshot))
+    }
+
+    fn local_path(base_path: &Path, target: &str, uuid: &Uuid) -> PathBuf {
+        let mut path = target_catalog_dir(base_path, target);
+        path.push(format!("{}.json", uuid));
+        path
+    }
+
+    /// Load the local copy of a media set catalog
+    pub fn load(base_path: &Path, target: &str, uuid: &Uuid) -> Result<Self, Error> {
+        let path = Self::local_path(base_path, target, uuid);
+        let data = proxmox_sys::fs::file_get_contents(&path)?;
+        serde_json::from_slice(&data)
+            .map_err(|err| format_err!("unable to parse catalog {:?} - {}", path, err))
+    }
+
+    /// Store the local copy of a media set catalog
+    pub fn save(&self, base_path: &Path, target: &str) -> Result<(), Error> {
+        create_target_catalog_dir(base_path, target)?;
+        let data = serde_json::to_vec(self)?;
+        replace_file(
+            Self::local_path(base_path, target, self.uuid()),
+            &data,
+            catalog_create_options()?,
+            true,
+        )
+    }
+
+    /// Remove the local copy of a media set catalog
+    pub fn remove(base_path: &Path, target: &str, uuid: &Uuid) -> Result<(), Error> {
+        let path = Self::local_path(base_path, target, uuid);
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => bail!("unable to remove catalog {:?} - {}", path, err),
+        }
+    }
+}
+
+/// Location of a chunk on the cloud target
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkLocation {
+    pub media_set: Uuid,
+    pub archive: Uuid,
+    pub key: Option<Fingerprint>,
+    pub offset: u64,
+    pub size: u64,
+    /// Key of the single chunk object of imported media sets
+    pub object: Option<String>,
+}
+
+impl ChunkLocation {
+    /// Key of the object containing the chunk
+    pub fn object_key(&self) -> String {
+        match self.object {
+            Some(ref key) => key.clone(),
+            None => layout::chunk_archive_key(&self.media_set, &self.archive),
+        }
+    }
+}
+
+// imported media sets first, so their chunks never shadow our own copies
+fn media_set_order(media_set: &MediaSetCatalog) -> (bool, i64) {
+    (!media_set.is_imported(), media_set.label.ctime)
+}
+
+/// All media set catalogs of a cloud target
+pub struct CloudCatalog {
+    base_path: PathBuf,
+    target: String,
+    media_sets: Vec<MediaSetCatalog>,
+    // the same chunk is stored once per encryption key
+    chunk_map: HashMap<(Option<Fingerprint>, [u8; 32]), ChunkLocation>,
+    keys: HashSet<Option<Fingerprint>>,
+}
+
+impl CloudCatalog {
+    /// Load all local catalogs of a target, ordered by creation time
+    pub fn load<P: AsRef<Path>>(base_path: P, target: &str) -> Result<Self, Error> {
+        let base_path = base_path.as_ref();
+        let dir = target_catalog_dir(base_path, target);
+
+        let mut media_sets = Vec::new();
+
+        if dir.exists() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = entry.file_name();
+                let name = match name.to_str() {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let uuid: Uuid = match name.strip_suffix(".json").map(str::parse) {
+                    Some(Ok(uuid)) => uuid,
+                    _ => continue, // ignore tmp files and garbage
+                };
+                media_sets.push(MediaSetCatalog::load(base_path, target, &uuid)?);
+            }
+        }
+
+        Ok(Self::from_media_sets(base_path, target, media_sets))
+    }
+
+    pub fn from_media_sets<P: AsRef<Path>>(
+        base_path: P,
+        target: &str,
+        mut media_sets: Vec<MediaSetCatalog>,
+    ) -> Self {
+        media_sets.sort_by_key(media_set_order);
+
+        let mut catalog = Self {
+            base_path: base_path.as_ref().to_owned(),
+            target: target.to_string(),
+            media_sets,
+            chunk_map: HashMap::new(),
+            keys: HashSet::new(),
+        };
+        catalog.rebuild_chunk_map();
+
+        catalog
+    }
+
+    /// Map each chunk to its most recent location
+    fn rebuild_chunk_map(&mut self) {
+        self.chunk_map.clear();
+        self.keys.clear();
+        for media_set in self.media_sets.iter() {
+            for archive in media_set.archives.iter() {
+                self.keys.insert(archive.key.clone());
+                for chunk in archive.chunks.iter() {
+                    self.chunk_map.insert(
+                        (archive.key.clone(), chunk.digest),
+                        ChunkLocation {
+                            media_set: media_set.label.uuid.clone(),
+                            archive: archive.uuid.clone(),
+                            key: archive.key.clone(),
+                            offset: chunk.offset,
+                            size: chunk.size,
+                            object: media_set
+                                .import
+                                .as_ref()
+                                .map(|import| import.chunk_key(&chunk.digest)),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Status directory containing the local catalogs
+    pub fn base_path(&self) -> &Path {
+        &self.base_path
+    }
+
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// Media set catalogs, oldest first (imported media sets before all others)
+    pub fn media_sets(&self) -> &[MediaSetCatalog] {
+        &self.media_sets
+    }
+
+    pub fn lookup_media_set(&self, uuid: &Uuid) -> Option<&MediaSetCatalog> {
+        self.media_sets.iter().find(|set| set.uuid() == uuid)
+    }
+
+    /// The most recently created media set (imported media sets excluded)
+    pub fn last_media_set(&self) -> Option<&MediaSetCatalog> {
+        self.media_sets.last().filter(|set| !set.is_imported())
+    }
+
+    /// Media sets of the current chain (last full media set and all
+    /// incremental sets based on it), oldest first
+    ///
+    /// Imported media sets are never part of a chain.
+    pub fn current_chain(&self) -> &[MediaSetCatalog] {
+        let imported = self
+            .media_sets
+            .partition_point(MediaSetCatalog::is_imported);
+        let media_sets = &self.media_sets[imported..];
+        match media_sets.iter().rposition(|set| set.label.base.is_none()) {
+            Some(start) => &media_sets[start..],
+            None => media_sets,
+        }
+    }
+
+    /// Add (or replace) a media set catalog
+    pub fn insert_media_set(&mut self, media_set: MediaSetCatalog) {
+        let uuid = media_set.uuid().clone();
+        self.media_sets.retain(|set| set.uuid() != &uuid);
+        self.media_sets.push(media_set);
+        self.media_sets.sort_by_key(media_set_order);
+        self.rebuild_chunk_map();
+    }
+
+    /// Remove a media set from the in-memory catalog
+    pub fn remove_media_set(&mut self, uuid: &Uuid) {
+        self.media_sets.retain(|set| set.uuid() != uuid);
+        self.rebuild_chunk_map();
+    }
+
+    /// Location of a chunk encrypted with `key` (or stored as is)
+    pub fn lookup_chunk(
+        &self,
+        digest: &[u8; 32],
+        key: Option<&Fingerprint>,
+    ) -> Option<&ChunkLocation> {
+        self.chunk_map.get(&(key.cloned(), *digest))
+    }
+
+    /// Location of a chunk, regardless of encryption
+    pub fn find_chunk(&self, digest: &[u8; 32]) -> Option<&ChunkLocation> {
+        self.keys
+            .iter()
+            .find_map(|key| self.chunk_map.get(&(key.clone(), *digest)))
+    }
+
+    /// Check if a chunk is stored at all (with any key)
+    pub fn contains_chunk(&self, digest: &[u8; 32]) -> bool {
+        self.find_chunk(digest).is_some()
+    }
+
+    /// Check if a chunk is stored in a media set of the current chain
+    ///
+    /// Incremental media sets may only reference chunks of their own chain,
+    /// so that older chains can be removed independently.
+    pub fn chain_contains_chunk(&self, digest: &[u8; 32], key: Option<&Fingerprint>) -> bool {
+        let location = match self.lookup_chunk(digest, key) {
+            Some(location) => location,
+            None => return false,
+        };
+        self.current_chain()
+            .iter()
+            .any(|set| set.uuid() == &location.media_set)
+    }
+
+    pub fn contains_snapshot(
+        &self,
+        store: &str,
+        ns: &BackupNamespace,
+        snapshot: &BackupDir,
+    ) -> bool {
+        self.media_sets
+            .iter()
+            .any(|set| set.contains_snapshot(store, ns, snapshot))
+    }
+
+    /// Find the media set (and entry) containing a snapshot
+    pub fn lookup_snapshot(
+        &self,
+        store: &str,
+        ns: &BackupNamespace,
+        snapshot: &BackupDir,
+    ) -> Option<(&MediaSetCatalog, &SnapshotEntry)> {
+        for media_set in self.media_sets.iter().rev() {
+            if let Some(entry) = media_set
+                .snapshots
+                .iter()
+                .find(|entry| entry.matches(store, ns, snapshot))
+            {
+                return Some((media_set, entry));
+            }
+        }
+        None
+    }
+
+    /// Iterate over all snapshots (with the media set containing them)
+    pub fn snapshots(&self) -> impl Iterator<Item = (&MediaSetCatalog, &SnapshotEntry)> {
+        self.media_sets
+            .iter()
+            .flat_map(|set| set.snapshots.iter().map(move |entry| (set, entry)))
+    }
+
+    /// Iterate over all snapshots in the trash (with their media set)
+    pub fn trashed_snapshots(&self) -> impl Iterator<Item = (&MediaSetCatalog, &TrashedSnapshot)> {
+        self.media_sets
+            .iter()
+            .flat_map(|set| set.trash.iter().map(move |trashed| (set, trashed)))
+    }
+}
+
+/// Write the label of a new media set to the target
+///
+/// The label marks the media set as owned by `label.node`. Uploading
+/// fails if a media set with the same UUID was already started.
+pub fn upload_media_set_label(
+    backend: &dyn CloudBackend,
+    label: &MediaSetLabel,
+) -> Result<(), Error> {
+    let key = layout::media_set_label_key(&label.uuid);
+    match backend.put_object_if_absent(&key, &serde_json::to_vec(label)?) {
+        Ok(()) => Ok(()),
+        Err(err) if is_object_exists(&err) => Err(media_set_conflict(backend, label)),
+        Err(err) => bail!("unable to write media set label - {}", err),
+    }
+}
+
+/// Write the catalog of a media set to the target
+///
+/// The catalog object marks the media set as complete, so it is never
+/// replaced once written. Uploading fails if another node owns the media
+/// set or the catalog already exists.
+pub fn upload_media_set_catalog(
+    backend: &dyn CloudBackend,
+    catalog: &MediaSetCatalog,
+) -> Result<(), Error> {
+    let key = layout::media_set_catalog_key(catalog.uuid());
+    match backend.put_object_if_absent(&key, &serde_json::to_vec(catalog)?) {
+        Ok(()) => Ok(()),
+        Err(err) if is_object_exists(&err) => Err(media_set_conflict(backend, &catalog.label)),
+        Err(err) => bail!("unable to upload media set catalog - {}", err),
+    }
+}
+
+/// Replace the catalog of a complete media set on the target
+///
+/// Only used when the archives of a media set are rewritten (see
+/// [`crate::cloud::compaction`]), with the writer lease held. The catalog
+/// object is overwritten with a single write, so readers see either the
+/// old or the new catalog.
+pub fn replace_media_set_catalog(
+    backend: &dyn CloudBackend,
+    catalog: &MediaSetCatalog,
+) -> Result<(), Error> {
+    let key = layout::media_set_catalog_key(catalog.uuid());
+    if backend.head_object(&key)?.is_none() {
+        bail!("media set {} is not complete", catalog.uuid());
+    }
+    backend
+        .put_object(&key, &serde_json::to_vec(catalog)?)
+        .map_err(|err| format_err!("unable to replace media set catalog - {}", err))
+}
+
+// build the error for a conflicting write, naming the owner if possible
+fn media_set_conflict(backend: &dyn CloudBackend, label: &MediaSetLabel) -> Error {
+    let key = layout::media_set_label_key(&label.uuid);
+    let owner = backend
+        .get_object(&key)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<MediaSetLabel>(&data).ok());
+
+    match owner {
+        Some(owner) if owner.node != label.node => format_err!(
+            "media set {} owned by another node ('{}')",
+            label.uuid,
+            owner.node
+        ),
+        _ => format_err!("media set {} was already written", label.uuid),
+    }
+}
+
+/// Directory containing the local catalogs of a target
+pub fn target_catalog_dir(base_path: &Path, target: &str) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("catalog");
+    path.push(target);
+    path
+}
+
+fn catalog_create_options() -> Result<CreateOptions, Error> {
+    let backup_user = pbs_config::backup_user()?;
+    let mode = nix::sys::stat::Mode::from_bits_truncate(0o0640);
+    Ok(CreateOptions::new()
+        .perm(mode)
+        .owner(backup_user.uid)
+        .group(backup_user.gid))
+}
+
+fn create_target_catalog_dir(base_path: &Path, target: &str) -> Result<(), Error> {
+    let backup_user = pbs_config::backup_user()?;
+    let mode = nix::sys::stat::Mode::from_bits_truncate(0o0750);
+    let options = CreateOptions::new()
+        .perm(mode)
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+
+    proxmox_sys::fs::create_path(
+        target_catalog_dir(base_path, target),
+        Some(options.clone()),
+        Some(options),
+    )
+    .map_err(|err: Error| format_err!("unable to create cloud catalog dir - {}", err))?;
+
+    Ok(())
+}
+
+/// (De)serialize a list of digests as hex strings
+mod digest_list {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(list: &[[u8; 32]], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(list.iter().map(hex::encode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<[u8; 32]>, D::Error> {
+        let list = Vec::<String>::deserialize(deserializer)?;
+        list.iter()
+            .map(|digest| {
+                let mut buf = [0u8; 32];
+                hex::decode_to_slice(digest, &mut buf).map_err(serde::de::Error::custom)?;
+                Ok(buf)
+            })
+            .collect()
+    }
+}
+
+/// (De)serialize an optional digest as hex string
+mod optional_digest {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        digest: &Option<[u8; 32]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match digest {
+            Some(digest) => serializer.serialize_some(&hex::encode(digest)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<[u8; 32]>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(digest) => {
+                let mut buf = [0u8; 32];
+                hex::decode_to_slice(digest, &mut buf).map_err(serde::de::Error::custom)?;
+                Ok(Some(buf))
+            }
+            None => Ok(None),
+        }
+    }
+}
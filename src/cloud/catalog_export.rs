@@ -0,0 +1,125 @@
+//! Portable catalog export
+//!
+//! The local catalog of a target (media sets with their chunk archives,
+//! snapshots, verification states and trash) can be exported into a
+//! single versioned JSON file. Importing it on another node, which uses
+//! the same bucket, avoids downloading and parsing all media set catalogs
+//! from the target, and the file serves as a backup of the catalog.
+//!
+//! Imports are checked against the target: every media set label of the
+//! export has to exist in the bucket.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::backend::CloudBackend;
+use super::catalog::{CloudCatalog, MediaSetCatalog};
+use super::layout;
+
+/// Current version of the export format
+pub const CATALOG_EXPORT_VERSION: u64 = 1;
+
+/// Exported catalog of a target
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CatalogExport {
+    /// Format version, newer versions are rejected on import
+    pub version: u64,
+    /// Name of the exported target
+    pub target: String,
+    /// Node which created the export
+    pub node: String,
+    /// Export time (UNIX epoch)
+    pub time: i64,
+    /// All media set catalogs, ordered by creation time
+    pub media_sets: Vec<MediaSetCatalog>,
+}
+
+/// Result of a catalog import
+#[derive(Debug, Default)]
+pub struct CatalogImportResult {
+    /// Number of media sets written to the local catalog
+    pub imported: usize,
+    /// Number of media sets already in the local catalog
+    pub skipped: usize,
+}
+
+/// Export the whole catalog of a target
+pub fn export_catalog(catalog: &CloudCatalog) -> CatalogExport {
+    CatalogExport {
+        version: CATALOG_EXPORT_VERSION,
+        target: catalog.target().to_string(),
+        node: proxmox_sys::nodename().to_string(),
+        time: proxmox_time::epoch_i64(),
+        media_sets: catalog.media_sets().to_vec(),
+    }
+}
+
+/// Parse an exported catalog
+///
+/// The version is checked first, so exports of newer versions fail with
+/// a useful error instead of a parse error.
+pub fn parse_catalog_export(data: &[u8]) -> Result<CatalogExport, Error> {
+    let value: Value = serde_json::from_slice(data)
+        .map_err(|err| format_err!("unable to parse catalog export - {}", err))?;
+
+    match value["version"].as_u64() {
+        Some(version) if version > CATALOG_EXPORT_VERSION => bail!(
+            "catalog export version {} is not supported (newest supported version {})",
+            version,
+            CATALOG_EXPORT_VERSION
+        ),
+        Some(_) => (),
+        None => bail!("catalog export without version"),
+    }
+
+    serde_json::from_value(value)
+        .map_err(|err| format_err!("unable to parse catalog export - {}", err))
+}
+
+/// Import an exported catalog into the local catalog of `target`
+///
+/// Media sets already in the local catalog are only replaced with
+/// `overwrite`, other local media sets are kept.
+pub fn import_catalog<P: AsRef<Path>>(
+    base_path: P,
+    target: &str,
+    backend: &dyn CloudBackend,
+    export: &CatalogExport,
+    overwrite: bool,
+) -> Result<CatalogImportResult, Error> {
+    let base_path = base_path.as_ref();
+
+    let mut uuids = HashSet::new();
+    for media_set in export.media_sets.iter() {
+        if !uuids.insert(media_set.uuid()) {
+            bail!("media set {} exported twice", media_set.uuid());
+        }
+        let key = layout::media_set_label_key(media_set.uuid());
+        if backend.head_object(&key)?.is_none() {
+            bail!(
+                "media set {} not found on target '{}'",
+                media_set.uuid(),
+                target
+            );
+        }
+    }
+
+    let local = CloudCatalog::load(base_path, target)?;
+
+    let mut result = CatalogImportResult::default();
+    for media_set in export.media_sets.iter() {
+        if !overwrite && local.lookup_media_set(media_set.uuid()).is_some() {
+            result.skipped += 1;
+            continue;
+        }
+        media_set.save(base_path, target)?;
+        result.imported += 1;
+    }
+
+    Ok(result)
+}
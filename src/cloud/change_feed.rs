@@ -0,0 +1,394 @@
+//! Ingest remote changes from S3 event notifications
+//!
+//! When other producers write into the bucket of a target (e.g. another
+//! node sharing it), the local catalog only learns about their media sets
+//! from a full listing. Targets with `change-queue` get the S3 event
+//! notifications of the bucket through an SQS queue instead. The proxy
+//! polls the queue every minute and applies the changes to the local
+//! catalog: new or replaced media set catalogs are downloaded, removed
+//! ones are dropped. All other objects are described by the catalogs, so
+//! their events are ignored.
+//!
+//! Messages are deleted from the queue once applied. Messages which could
+//! not be applied become visible again after [`VISIBILITY_TIMEOUT`] and
+//! are retried.
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+use hyper::client::Client;
+use hyper::{Body, Request};
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use proxmox_http::client::HttpsConnector;
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::CloudTarget;
+
+use super::backend::credentials::CredentialCache;
+use super::backend::{cloud_http_client, CloudBackend};
+use super::catalog::MediaSetCatalog;
+use super::events::{event_credentials, sign_post_request, EventDestination};
+use super::layout;
+use super::standby::media_set_digest;
+
+/// Messages received per request (SQS maximum)
+pub const MAX_MESSAGES: usize = 10;
+
+/// Received messages stay invisible to other consumers this long (seconds)
+pub const VISIBILITY_TIMEOUT: u64 = 300;
+
+/// Timeout of a single queue request
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const SQS_JSON_CONTENT_TYPE: &str = "application/x-amz-json-1.0";
+
+/// A change of an object, relative to the target prefix
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectChange {
+    pub key: String,
+    pub removed: bool,
+}
+
+/// A message received from the change queue
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct QueueMessage {
+    pub receipt_handle: String,
+    pub body: String,
+}
+
+// S3 escapes keys in event notifications like form values
+fn decode_event_key(key: &str) -> Result<String, Error> {
+    let key = key.replace('+', " ");
+    Ok(percent_decode_str(&key).decode_utf8()?.into_owned())
+}
+
+/// Object changes contained in an S3 event notification
+///
+/// Accepts notifications sent to the queue directly or through an SNS
+/// topic. Only objects of `bucket` below `prefix` are returned, with the
+/// prefix removed.
+pub fn parse_notification(
+    body: &str,
+    bucket: &str,
+    prefix: Option<&str>,
+) -> Result<Vec<ObjectChange>, Error> {
+    let mut value: Value = serde_json::from_str(body)
+        .map_err(|err| format_err!("unable to parse notification - {}", err))?;
+
+    // SNS envelope
+    if value["Type"] == "Notification" {
+        let message = value["Message"]
+            .as_str()
+            .ok_or_else(|| format_err!("SNS notification without message"))?;
+        value = serde_json::from_str(message)
+            .map_err(|err| format_err!("unable to parse SNS message - {}", err))?;
+    }
+
+    // sent when the notification configuration is created
+    if value["Event"] == "s3:TestEvent" {
+        return Ok(Vec::new());
+    }
+
+    let records = value["Records"]
+        .as_array()
+        .ok_or_else(|| format_err!("notification without records"))?;
+
+    let mut list = Vec::new();
+    for record in records {
+        if record["eventSource"] != "aws:s3" || record["s3"]["bucket"]["name"] != bucket {
+            continue;
+        }
+        let event_name = record["eventName"].as_str().unwrap_or_default();
+        let removed = if event_name.starts_with("ObjectCreated:") {
+            false
+        } else if event_name.starts_with("ObjectRemoved:") {
+            true
+        } else {
+            continue;
+        };
+        let key = match record["s3"]["object"]["key"].as_str() {
+            Some(key) => decode_event_key(key)?,
+            None => bail!("record without object key"),
+        };
+        let key = match prefix {
+            Some(prefix) => match key
+                .strip_prefix(prefix)
+                .and_then(|key| key.strip_prefix('/'))
+            {
+                Some(key) => key.to_string(),
+                None => continue,
+            },
+            None => key,
+        };
+        list.push(ObjectChange { key, removed });
+    }
+
+    Ok(list)
+}
+
+/// Media set whose catalog object is `key`
+pub fn catalog_media_set(key: &str) -> Option<Uuid> {
+    let uuid = layout::parse_media_set_uuid(key)?;
+    (layout::media_set_catalog_key(&uuid) == key).then_some(uuid)
+}
+
+/// Changes applied to the local catalog
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChangeFeedStats {
+    /// Media set catalogs downloaded
+    pub updated: usize,
+    /// Media set catalogs removed
+    pub removed: usize,
+    /// Changes which needed no update
+    pub unchanged: usize,
+}
+
+/// Apply object changes to the local catalog of a target
+///
+/// The current state of each media set catalog is looked up on the
+/// target, so changes arriving out of order are harmless.
+pub fn apply_changes(
+    worker: &dyn WorkerTaskContext,
+    base_path: &Path,
+    target: &str,
+    backend: &dyn CloudBackend,
+    changes: &[ObjectChange],
+    stats: &mut ChangeFeedStats,
+) -> Result<(), Error> {
+    for change in changes {
+        let uuid = match catalog_media_set(&change.key) {
+            Some(uuid) => uuid,
+            None => continue,
+        };
+        let local = MediaSetCatalog::load(base_path, target, &uuid).ok();
+
+        if backend.head_object(&change.key)?.is_none() {
+            if local.is_some() {
+                task_log!(worker, "remove catalog of media set {}", uuid);
+                MediaSetCatalog::remove(base_path, target, &uuid)?;
+                stats.removed += 1;
+            } else {
+                stats.unchanged += 1;
+            }
+            continue;
+        }
+
+        let data = backend.get_object(&change.key)?;
+        let media_set: MediaSetCatalog = serde_json::from_slice(&data).map_err(|err| {
+            format_err!("unable to parse catalog of media set {} - {}", uuid, err)
+        })?;
+        if media_set.uuid() != &uuid {
+            bail!(
+                "object '{}' contains the catalog of media set {}",
+                change.key,
+                media_set.uuid()
+            );
+        }
+
+        let changed = match local {
+            Some(ref local) => media_set_digest(local)? != media_set_digest(&media_set)?,
+            None => true,
+        };
+        if changed {
+            task_log!(worker, "update catalog of media set {}", uuid);
+            media_set.save(base_path, target)?;
+            stats.updated += 1;
+        } else {
+            stats.unchanged += 1;
+        }
+    }
+    Ok(())
+}
+
+/// SQS queue receiving the event notifications of a target bucket
+pub struct ChangeQueue {
+    url: String,
+    region: String,
+    host: String,
+    client: Client<HttpsConnector>,
+    credentials: CredentialCache,
+}
+
+impl ChangeQueue {
+    /// Queue of `target`, `None` if the target has no change queue
+    pub fn new(target: &CloudTarget) -> Result<Option<Self>, Error> {
+        let url = match target.config.change_queue {
+            Some(ref url) => url.clone(),
+            None => return Ok(None),
+        };
+        let region = match EventDestination::queue(&url)? {
+            EventDestination::Queue { region, .. } => region,
+            EventDestination::Topic { .. } => unreachable!(),
+        };
+        let host = format!("sqs.{}.amazonaws.com", region);
+
+        Ok(Some(Self {
+            client: cloud_http_client(target, &host)?,
+            credentials: event_credentials(target)?,
+            url,
+            region,
+            host,
+        }))
+    }
+
+    // send a request using the SQS JSON protocol
+    fn call(&self, action: &str, mut params: Value) -> Result<Value, Error> {
+        params["QueueUrl"] = self.url.clone().into();
+        let body = params.to_string();
+
+        let credentials = self.credentials.get()?;
+        let headers = sign_post_request(
+            &credentials,
+            "sqs",
+            &self.region,
+            &self.host,
+            "/",
+            &[
+                ("content-type", SQS_JSON_CONTENT_TYPE.to_string()),
+                ("x-amz-target", format!("AmazonSQS.{}", action)),
+            ],
+            &body,
+            proxmox_time::epoch_i64(),
+        )?;
+
+        let mut builder = Request::builder()
+            .method("POST")
+            .uri(format!("https://{}/", self.host))
+            .header("host", &self.host);
+        for (name, value) in headers {
+            builder = builder.header(name, value);
+        }
+        let request = builder.body(Body::from(body))?;
+
+        let (status, body) = proxmox_async::runtime::block_on(async {
+            tokio::time::timeout(QUEUE_TIMEOUT, async {
+                let response = self.client.request(request).await?;
+                let status = response.status();
+                let body = hyper::body::to_bytes(response.into_body()).await?;
+                Ok::<_, Error>((status, body))
+            })
+            .await
+        })
+        .map_err(|_| {
+            format_err!(
+                "{} timed out after {} seconds",
+                action,
+                QUEUE_TIMEOUT.as_secs()
+            )
+        })??;
+
+        if !status.is_success() {
+            bail!(
+                "{} failed - {} {}",
+                action,
+                status,
+                String::from_utf8_lossy(&body).trim()
+            );
+        }
+        if body.is_empty() {
+            return Ok(Value::Null);
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Receive pending messages (at most [`MAX_MESSAGES`])
+    pub fn receive(&self) -> Result<Vec<QueueMessage>, Error> {
+        let mut response = self.call(
+            "ReceiveMessage",
+            json!({
+                "MaxNumberOfMessages": MAX_MESSAGES,
+                "VisibilityTimeout": VISIBILITY_TIMEOUT,
+                "WaitTimeSeconds": 0,
+            }),
+        )?;
+        match response["Messages"].take() {
+            Value::Null => Ok(Vec::new()),
+            messages => Ok(serde_json::from_value(messages)?),
+        }
+    }
+
+    /// Delete processed messages from the queue
+    pub fn delete(&self, messages: &[QueueMessage]) -> Result<(), Error> {
+        for batch in messages.chunks(MAX_MESSAGES) {
+            let entries: Vec<Value> = batch
+                .iter()
+                .enumerate()
+                .map(|(i, message)| json!({ "Id": i.to_string(), "ReceiptHandle": message.receipt_handle }))
+                .collect();
+            let response = self.call("DeleteMessageBatch", json!({ "Entries": entries }))?;
+            if let Some(failed) = response["Failed"].as_array() {
+                if !failed.is_empty() {
+                    bail!("unable to delete {} messages", failed.len());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Apply received messages to the local catalog of a target
+///
+/// Messages which cannot be parsed are dropped with a warning, messages
+/// whose changes failed to apply are kept in the queue for a retry.
+pub fn run_change_feed(
+    worker: &dyn WorkerTaskContext,
+    base_path: &Path,
+    target: &CloudTarget,
+    backend: &dyn CloudBackend,
+    queue: &ChangeQueue,
+    messages: Vec<QueueMessage>,
+) -> Result<ChangeFeedStats, Error> {
+    let bucket = target.config.bucket.as_deref().unwrap_or_default();
+    let prefix = target.config.prefix.as_deref();
+
+    let mut stats = ChangeFeedStats::default();
+    let mut done = Vec::new();
+    let mut errors = 0;
+
+    for message in messages {
+        let changes = match parse_notification(&message.body, bucket, prefix) {
+            Ok(changes) => changes,
+            Err(err) => {
+                task_warn!(worker, "dropping message - {}", err);
+                done.push(message);
+                continue;
+            }
+        };
+        match apply_changes(
+            worker,
+            base_path,
+            &target.name,
+            backend,
+            &changes,
+            &mut stats,
+        ) {
+            Ok(()) => done.push(message),
+            Err(err) => {
+                task_warn!(worker, "unable to apply changes - {}", err);
+                errors += 1;
+            }
+        }
+    }
+
+    queue.delete(&done)?;
+
+    task_log!(
+        worker,
+        "{} media set catalogs updated, {} removed, {} unchanged",
+        stats.updated,
+        stats.removed,
+        stats.unchanged
+    );
+
+    if errors > 0 {
+        bail!("unable to apply {} messages, they will be retried", errors);
+    }
+
+    Ok(stats)
+}
@@ -0,0 +1,97 @@
+//! Checksum listings for external audits
+//!
+//! Lists all objects a snapshot consists of, with the checksums recorded
+//! in the catalog, so that auditors can verify the bucket contents with
+//! their own provider access:
+//!
+//! - snapshot files: SHA-256 of the unencrypted file
+//! - chunk archives: SHA-256 of the object (recorded since archives carry
+//!   checksums)
+//! - chunks: archive, offset and size of each chunk blob; the digest is
+//!   the SHA-256 of the decoded, unencrypted chunk
+
+use std::collections::BTreeMap;
+
+use anyhow::{format_err, Error};
+
+use pbs_api_types::{
+    print_ns_and_snapshot, CloudArchiveChecksum, CloudChunkChecksum, CloudFileChecksum,
+    CloudSnapshotChecksums,
+};
+
+use super::catalog::{CloudCatalog, MediaSetCatalog, SnapshotEntry};
+
+/// List the checksums of all objects of a snapshot
+pub fn snapshot_checksums(
+    catalog: &CloudCatalog,
+    media_set: &MediaSetCatalog,
+    entry: &SnapshotEntry,
+) -> Result<CloudSnapshotChecksums, Error> {
+    let uuid = media_set.uuid();
+
+    let files = entry
+        .files
+        .iter()
+        .map(|file| CloudFileChecksum {
+            key: media_set.snapshot_file_key(entry, &file.filename),
+            filename: file.filename.clone(),
+            size: file.size,
+            csum: hex::encode(file.csum),
+        })
+        .collect();
+
+    let mut archives = BTreeMap::new();
+    let mut chunks = Vec::with_capacity(entry.chunks.len());
+
+    for digest in entry.chunks.iter() {
+        let location = catalog
+            .lookup_chunk(digest, entry.key.as_ref())
+            .ok_or_else(|| format_err!("chunk {} not found in catalog", hex::encode(digest)))?;
+
+        let archive_key = location.object_key();
+
+        if !archives.contains_key(&archive_key) {
+            let archive = catalog
+                .lookup_media_set(&location.media_set)
+                .and_then(|set| {
+                    set.archives
+                        .iter()
+                        .find(|archive| archive.uuid == location.archive)
+                })
+                .ok_or_else(|| format_err!("chunk archive {} not found in catalog", archive_key))?;
+
+            archives.insert(
+                archive_key.clone(),
+                CloudArchiveChecksum {
+                    key: archive_key.clone(),
+                    // imported chunks are single objects
+                    size: match location.object {
+                        Some(_) => location.size,
+                        None => archive.size,
+                    },
+                    csum: archive.csum.map(hex::encode),
+                },
+            );
+        }
+
+        chunks.push(CloudChunkChecksum {
+            digest: hex::encode(digest),
+            archive: archive_key,
+            offset: location.offset,
+            size: location.size,
+        });
+    }
+
+    Ok(CloudSnapshotChecksums {
+        snapshot: format!(
+            "{}:{}",
+            entry.store,
+            print_ns_and_snapshot(&entry.ns, &entry.snapshot)
+        ),
+        media_set: uuid.to_string(),
+        encrypted: entry.key.is_some(),
+        files,
+        archives: archives.into_values().collect(),
+        chunks,
+    })
+}
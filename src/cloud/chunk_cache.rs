@@ -0,0 +1,439 @@
+//! Local cache of downloaded chunks
+//!
+//! Restores and verifications often read the same chunks again, e.g. when
+//! several snapshots of a group are restored. Chunks read from a cloud
+//! target are kept in a size-bounded directory, keyed by digest, and read
+//! from there before any download. The least recently used chunks are
+//! evicted first, the order survives restarts via the file modification
+//! time.
+//!
+//! Chunks are cached as stored in the datastore, i.e. after removing the
+//! namespace encryption of the target, so the cache is shared by all
+//! targets. The cache is disabled unless the node config has a
+//! `cloud-chunk-cache-size`.
+//!
+//! Counters are written to a status file by [`ChunkCache::flush`], which
+//! is read for the node status and metrics.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{format_err, Error};
+use hex::FromHex;
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{create_path, replace_file};
+use proxmox_sys::{task_log, WorkerTaskContext};
+
+use pbs_api_types::CloudChunkCacheStatus;
+use pbs_buildcfg::PROXMOX_BACKUP_CACHE_DIR_M;
+
+use super::catalog::CloudCatalog;
+use super::chunk_reader::CloudChunkReader;
+use super::create_options;
+
+/// Directory of the node chunk cache
+pub const CLOUD_CHUNK_CACHE_DIR: &str = concat!(PROXMOX_BACKUP_CACHE_DIR_M!(), "/cloud-chunks");
+
+const STATUS_FILE_NAME: &str = "status.json";
+
+// cache of this process, see node_chunk_cache()
+static NODE_CHUNK_CACHE: Mutex<Option<Arc<ChunkCache>>> = Mutex::new(None);
+
+// counters kept across restarts
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct CacheCounters {
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    max_size: u64,
+    size: u64,
+    // digest -> (size, access sequence)
+    entries: HashMap<[u8; 32], (u64, u64)>,
+    // access sequence -> digest, oldest first
+    order: BTreeMap<u64, [u8; 32]>,
+    clock: u64,
+    counters: CacheCounters,
+}
+
+impl CacheState {
+    fn touch(&mut self, digest: &[u8; 32]) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some((_, access)) = self.entries.get_mut(digest) {
+            self.order.remove(access);
+            *access = clock;
+            self.order.insert(clock, *digest);
+        }
+    }
+
+    fn insert(&mut self, digest: [u8; 32], size: u64) {
+        self.remove(&digest);
+        self.clock += 1;
+        self.entries.insert(digest, (size, self.clock));
+        self.order.insert(self.clock, digest);
+        self.size += size;
+    }
+
+    fn remove(&mut self, digest: &[u8; 32]) -> bool {
+        match self.entries.remove(digest) {
+            Some((size, access)) => {
+                self.order.remove(&access);
+                self.size -= size;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // remove the least recently used entry
+    fn pop_oldest(&mut self) -> Option<[u8; 32]> {
+        let (_, digest) = self.order.pop_first()?;
+        if let Some((size, _)) = self.entries.remove(&digest) {
+            self.size -= size;
+        }
+        Some(digest)
+    }
+}
+
+/// Size-bounded on-disk LRU cache of chunks
+pub struct ChunkCache {
+    base_path: PathBuf,
+    state: Mutex<CacheState>,
+}
+
+impl ChunkCache {
+    /// Open the cache in `base_path`, evicting chunks beyond `max_size`
+    pub fn open<P: AsRef<Path>>(base_path: P, max_size: u64) -> Result<Self, Error> {
+        let base_path = base_path.as_ref().to_owned();
+        create_path(
+            &base_path,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+
+        let mut found = Vec::new();
+        for dir in std::fs::read_dir(&base_path)? {
+            let dir = dir?;
+            if !dir.file_type()?.is_dir() {
+                continue;
+            }
+            for file in std::fs::read_dir(dir.path())? {
+                let file = file?;
+                let name = file.file_name();
+                let digest = match name
+                    .to_str()
+                    .and_then(|name| <[u8; 32]>::from_hex(name).ok())
+                {
+                    Some(digest) => digest,
+                    None => continue, // e.g. temporary files
+                };
+                let metadata = file.metadata()?;
+                found.push((metadata.modified()?, digest, metadata.len()));
+            }
+        }
+        found.sort();
+
+        let mut state = CacheState {
+            max_size,
+            ..Default::default()
+        };
+        for (_, digest, size) in found {
+            state.insert(digest, size);
+        }
+        if let Some(data) = proxmox_sys::fs::file_get_optional_contents(status_path(&base_path))? {
+            state.counters = serde_json::from_slice(&data).unwrap_or_default();
+        }
+
+        let cache = Self {
+            base_path,
+            state: Mutex::new(state),
+        };
+        cache.evict()?;
+        Ok(cache)
+    }
+
+    fn chunk_path(&self, digest: &[u8; 32]) -> PathBuf {
+        let digest_str = hex::encode(digest);
+        let mut path = self.base_path.clone();
+        path.push(&digest_str[0..2]);
+        path.push(digest_str);
+        path
+    }
+
+    // remove least recently used chunks until the cache fits
+    fn evict(&self) -> Result<(), Error> {
+        loop {
+            let digest = {
+                let mut state = self.state.lock().unwrap();
+                if state.size <= state.max_size {
+                    return Ok(());
+                }
+                match state.pop_oldest() {
+                    Some(digest) => {
+                        state.counters.evictions += 1;
+                        digest
+                    }
+                    None => return Ok(()),
+                }
+            };
+            match std::fs::remove_file(self.chunk_path(&digest)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(format_err!(
+                        "unable to evict cached chunk {} - {}",
+                        hex::encode(digest),
+                        err
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Change the maximum size, evicting chunks if it shrinks
+    pub fn set_max_size(&self, max_size: u64) -> Result<(), Error> {
+        self.state.lock().unwrap().max_size = max_size;
+        self.evict()
+    }
+
+    /// Read a cached chunk
+    pub fn get(&self, digest: &[u8; 32]) -> Option<Vec<u8>> {
+        let path = self.chunk_path(digest);
+
+        let cached = self.state.lock().unwrap().entries.contains_key(digest);
+        let data = if cached {
+            match std::fs::read(&path) {
+                Ok(data) => Some(data),
+                Err(err) => {
+                    log::warn!("unable to read cached chunk {:?} - {}", path, err);
+                    self.state.lock().unwrap().remove(digest);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut state = self.state.lock().unwrap();
+        match data {
+            Some(data) => {
+                state.counters.hits += 1;
+                state.touch(digest);
+                drop(state);
+                // keep the access order across restarts
+                let now = nix::sys::time::TimeVal::new(proxmox_time::epoch_i64(), 0);
+                let _ = nix::sys::stat::utimes(&path, &now, &now);
+                Some(data)
+            }
+            None => {
+                state.counters.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Check whether a chunk is cached, without counting an access
+    pub fn contains(&self, digest: &[u8; 32]) -> bool {
+        self.state.lock().unwrap().entries.contains_key(digest)
+    }
+
+    /// Add a chunk, evicting the least recently used ones if needed
+    ///
+    /// Chunks larger than the cache are not stored.
+    pub fn insert(&self, digest: &[u8; 32], data: &[u8]) -> Result<(), Error> {
+        let size = data.len() as u64;
+        if size > self.state.lock().unwrap().max_size {
+            return Ok(());
+        }
+
+        let path = self.chunk_path(digest);
+        if let Some(parent) = path.parent() {
+            create_path(parent, None, Some(create_options(0o0750)?))?;
+        }
+        replace_file(&path, data, create_options(0o0640)?, false)?;
+
+        self.state.lock().unwrap().insert(*digest, size);
+        self.evict()
+    }
+
+    /// Current utilization
+    pub fn status(&self) -> CloudChunkCacheStatus {
+        let state = self.state.lock().unwrap();
+        cache_status(
+            state.max_size,
+            state.size,
+            state.entries.len() as u64,
+            &state.counters,
+        )
+    }
+
+    /// Write the current utilization to the status file
+    pub fn flush(&self) -> Result<(), Error> {
+        let status = self.status();
+        let data = serde_json::to_vec(&status)?;
+        replace_file(
+            status_path(&self.base_path),
+            &data,
+            create_options(0o0640)?,
+            true,
+        )
+    }
+}
+
+fn status_path(base_path: &Path) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push(STATUS_FILE_NAME);
+    path
+}
+
+fn cache_status(
+    max_size: u64,
+    size: u64,
+    entries: u64,
+    counters: &CacheCounters,
+) -> CloudChunkCacheStatus {
+    let reads = counters.hits + counters.misses;
+    CloudChunkCacheStatus {
+        max_size,
+        size,
+        entries,
+        hits: counters.hits,
+        misses: counters.misses,
+        evictions: counters.evictions,
+        hit_rate: (reads > 0).then(|| counters.hits as f64 / reads as f64),
+    }
+}
+
+/// Utilization of a cache as of its last flush (`None` if never used)
+pub fn load_cache_status<P: AsRef<Path>>(
+    base_path: P,
+) -> Result<Option<CloudChunkCacheStatus>, Error> {
+    let path = status_path(base_path.as_ref());
+    match proxmox_sys::fs::file_get_optional_contents(&path)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        None => Ok(None),
+    }
+}
+
+/// Utilization of the node chunk cache (`None` if disabled)
+///
+/// Counters are those of the last flush, by any process.
+pub fn node_cache_status() -> Result<Option<CloudChunkCacheStatus>, Error> {
+    let max_size = node_chunk_cache_size();
+    if max_size == 0 {
+        return Ok(None);
+    }
+    let mut status = load_cache_status(CLOUD_CHUNK_CACHE_DIR)?.unwrap_or_default();
+    // the cache shrinks on next use
+    status.max_size = max_size;
+    Ok(Some(status))
+}
+
+/// Configured size of the node chunk cache in bytes (0 if disabled)
+pub fn node_chunk_cache_size() -> u64 {
+    // node config is optional, e.g. in regression tests
+    crate::config::node::config()
+        .ok()
+        .and_then(|(config, _digest)| config.cloud_chunk_cache_size)
+        .unwrap_or(0)
+        .saturating_mul(1024 * 1024 * 1024)
+}
+
+/// The node chunk cache, if enabled
+///
+/// Opened once per process, size changes of the node config are applied
+/// on the next call.
+pub fn node_chunk_cache() -> Option<Arc<ChunkCache>> {
+    let max_size = node_chunk_cache_size();
+
+    let mut cache = NODE_CHUNK_CACHE.lock().unwrap();
+    if max_size == 0 {
+        *cache = None;
+        return None;
+    }
+
+    if let Some(ref cache) = *cache {
+        if let Err(err) = cache.set_max_size(max_size) {
+            log::warn!("unable to resize chunk cache - {}", err);
+        }
+        return Some(Arc::clone(cache));
+    }
+
+    match ChunkCache::open(CLOUD_CHUNK_CACHE_DIR, max_size) {
+        Ok(opened) => {
+            let opened = Arc::new(opened);
+            *cache = Some(Arc::clone(&opened));
+            Some(opened)
+        }
+        Err(err) => {
+            log::warn!("unable to open chunk cache - {}", err);
+            None
+        }
+    }
+}
+
+/// Fill the cache with chunks of the newest media set
+///
+/// Chunks are downloaded until the cache is full. Cached chunks and
+/// snapshots encrypted with a namespace key are skipped. `reader` should
+/// not use a cache itself, so the cache counters only reflect actual
+/// reads. Returns the number of downloaded chunks.
+pub fn prewarm_chunk_cache(
+    worker: &dyn WorkerTaskContext,
+    cache: &ChunkCache,
+    reader: &CloudChunkReader,
+    catalog: &CloudCatalog,
+) -> Result<usize, Error> {
+    let media_set = match catalog.last_media_set() {
+        Some(media_set) => media_set,
+        None => {
+            task_log!(worker, "no media set on target, nothing to cache");
+            return Ok(0);
+        }
+    };
+    task_log!(
+        worker,
+        "pre-warm chunk cache from media set {}",
+        media_set.uuid()
+    );
+
+    let max_size = cache.status().max_size;
+    let mut added = 0;
+    let mut downloaded = 0;
+    let mut seen = std::collections::HashSet::new();
+
+    for entry in media_set.snapshots.iter() {
+        // the namespace keys are not available to a background task
+        if entry.key.is_some() {
+            continue;
+        }
+        for digest in entry.chunks.iter() {
+            worker.check_abort()?;
+            if !seen.insert(*digest) || cache.contains(digest) {
+                continue;
+            }
+            let size = match catalog.lookup_chunk(digest, None) {
+                Some(location) => location.size,
+                None => continue,
+            };
+            // do not evict what we just downloaded
+            if added + size > max_size {
+                return Ok(downloaded);
+            }
+            let data = reader.fetch_chunk(digest)?;
+            cache.insert(digest, &data)?;
+            added += size;
+            downloaded += 1;
+        }
+    }
+
+    Ok(downloaded)
+}
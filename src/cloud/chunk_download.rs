@@ -0,0 +1,183 @@
+//! Download chunks from a cloud target in parallel
+//!
+//! Chunks are downloaded and verified by a pool of threads and inserted
+//! batch by batch, so that the (single threaded) chunk store insertion
+//! does not stall the downloads. Downloaded chunks are kept in memory,
+//! or in a staging directory if one is configured.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::Error;
+
+use proxmox_human_byte::HumanByte;
+use proxmox_sys::fs::{replace_file, CreateOptions};
+use proxmox_sys::{task_log, WorkerTaskContext};
+
+use crate::tools::parallel_handler::ParallelHandler;
+
+use super::chunk_reader::CloudChunkReader;
+
+/// Default number of download threads
+pub const DEFAULT_DOWNLOAD_THREADS: usize = 4;
+
+/// Number of chunks inserted at once
+const BATCH_SIZE: usize = 256;
+
+/// Statistics of a finished download
+#[derive(Debug, Default)]
+pub struct ChunkDownloadStats {
+    /// Number of downloaded chunks
+    pub chunks: usize,
+    /// Downloaded bytes
+    pub bytes: u64,
+    /// Time in seconds
+    pub elapsed: f64,
+}
+
+enum StagedChunk {
+    Memory(Vec<u8>),
+    File(PathBuf),
+}
+
+/// Parallel chunk download with optional local staging
+pub struct ChunkDownloader {
+    threads: usize,
+    staging_dir: Option<PathBuf>,
+}
+
+impl ChunkDownloader {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            staging_dir: None,
+        }
+    }
+
+    /// Stage downloaded chunks in this directory instead of memory
+    pub fn with_staging_dir(mut self, staging_dir: PathBuf) -> Self {
+        self.staging_dir = Some(staging_dir);
+        self
+    }
+
+    /// Download all `digests`
+    ///
+    /// `verify` runs in the download threads and gets the raw chunk
+    /// data, `insert` is called for each verified chunk after its batch
+    /// is complete. Progress (throughput and ETA) is logged per batch.
+    pub fn download<V, I>(
+        &self,
+        worker: &dyn WorkerTaskContext,
+        reader: &Arc<CloudChunkReader>,
+        digests: &[[u8; 32]],
+        verify: V,
+        mut insert: I,
+    ) -> Result<ChunkDownloadStats, Error>
+    where
+        V: Fn(&[u8; 32], &[u8]) -> Result<(), Error> + Send + Clone + 'static,
+        I: FnMut(&[u8; 32], Vec<u8>) -> Result<(), Error>,
+    {
+        let start_time = Instant::now();
+        let bytes = Arc::new(AtomicU64::new(0));
+        let mut done = 0;
+
+        for batch in digests.chunks(BATCH_SIZE) {
+            let staged = Arc::new(Mutex::new(Vec::with_capacity(batch.len())));
+
+            let result = self.download_batch(worker, reader, batch, &verify, &bytes, &staged);
+
+            let mut staged = std::mem::take(&mut *staged.lock().unwrap());
+
+            let result = result.and_then(|()| {
+                for (digest, chunk) in staged.iter_mut() {
+                    let data = match chunk {
+                        StagedChunk::Memory(data) => std::mem::take(data),
+                        StagedChunk::File(path) => std::fs::read(path)?,
+                    };
+                    insert(digest, data)?;
+                }
+                Ok(())
+            });
+
+            for (_, chunk) in staged.iter() {
+                if let StagedChunk::File(path) = chunk {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+
+            result?;
+
+            done += batch.len();
+
+            let elapsed = start_time.elapsed().as_secs_f64();
+            let downloaded = bytes.load(Ordering::SeqCst);
+            let rate = (downloaded as f64 / elapsed.max(0.001)) as u64;
+            let eta = elapsed * (digests.len() - done) as f64 / done as f64;
+            task_log!(
+                worker,
+                "downloaded {} of {} chunks ({}, {}/s, ETA {:.0}s)",
+                done,
+                digests.len(),
+                HumanByte::from(downloaded),
+                HumanByte::from(rate),
+                eta,
+            );
+        }
+
+        Ok(ChunkDownloadStats {
+            chunks: done,
+            bytes: bytes.load(Ordering::SeqCst),
+            elapsed: start_time.elapsed().as_secs_f64(),
+        })
+    }
+
+    fn download_batch<V>(
+        &self,
+        worker: &dyn WorkerTaskContext,
+        reader: &Arc<CloudChunkReader>,
+        batch: &[[u8; 32]],
+        verify: &V,
+        bytes: &Arc<AtomicU64>,
+        staged: &Arc<Mutex<Vec<([u8; 32], StagedChunk)>>>,
+    ) -> Result<(), Error>
+    where
+        V: Fn(&[u8; 32], &[u8]) -> Result<(), Error> + Send + Clone + 'static,
+    {
+        let reader = Arc::clone(reader);
+        let verify = verify.clone();
+        let bytes = Arc::clone(bytes);
+        let staged = Arc::clone(staged);
+        let staging_dir = self.staging_dir.clone();
+
+        let pool = ParallelHandler::new(
+            "cloud chunk download",
+            self.threads,
+            move |digest: [u8; 32]| {
+                let data = reader.fetch_chunk(&digest)?;
+                verify(&digest, &data)?;
+                bytes.fetch_add(data.len() as u64, Ordering::SeqCst);
+
+                let chunk = match staging_dir {
+                    Some(ref dir) => {
+                        let mut path = dir.clone();
+                        path.push(hex::encode(digest));
+                        replace_file(&path, &data, CreateOptions::new(), false)?;
+                        StagedChunk::File(path)
+                    }
+                    None => StagedChunk::Memory(data),
+                };
+                staged.lock().unwrap().push((digest, chunk));
+                Ok(())
+            },
+        );
+
+        for digest in batch {
+            worker.check_abort()?;
+            pool.send(*digest)?;
+        }
+
+        pool.complete()
+    }
+}
@@ -0,0 +1,142 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{format_err, Error};
+
+use pbs_api_types::Fingerprint;
+use pbs_datastore::read_chunk::ReadChunk;
+use pbs_datastore::DataBlob;
+use pbs_tools::crypt_config::CryptConfig;
+
+use super::backend::CloudBackend;
+use super::catalog::CloudCatalog;
+use super::chunk_cache::{node_chunk_cache, ChunkCache};
+use super::encryption_keys::decrypt_object;
+use super::popularity::ChunkPopularity;
+
+/// Read chunks from the chunk archives of a cloud target
+///
+/// Every download is counted in the target's [`ChunkPopularity`]
+/// statistics, which are written back by [`Self::finish`]. Chunks in the
+/// node [`ChunkCache`] are not downloaded at all.
+///
+/// `crypt_config` is the (client side) key of the backup itself, chunks
+/// encrypted with a namespace key of the target need
+/// [`Self::with_namespace_key`].
+pub struct CloudChunkReader {
+    backend: Arc<dyn CloudBackend>,
+    catalog: Arc<CloudCatalog>,
+    crypt_config: Option<Arc<CryptConfig>>,
+    namespace_key: Option<(Fingerprint, Arc<CryptConfig>)>,
+    popularity: Mutex<ChunkPopularity>,
+    cache: Option<Arc<ChunkCache>>,
+}
+
+impl CloudChunkReader {
+    pub fn new(
+        backend: Arc<dyn CloudBackend>,
+        catalog: Arc<CloudCatalog>,
+        crypt_config: Option<Arc<CryptConfig>>,
+    ) -> Result<Self, Error> {
+        let popularity = ChunkPopularity::load(catalog.base_path(), catalog.target())?;
+        Ok(Self {
+            backend,
+            catalog,
+            crypt_config,
+            namespace_key: None,
+            popularity: Mutex::new(popularity),
+            cache: node_chunk_cache(),
+        })
+    }
+
+    /// Read chunks encrypted with a namespace key of the target
+    pub fn with_namespace_key(
+        mut self,
+        fingerprint: Fingerprint,
+        crypt_config: Arc<CryptConfig>,
+    ) -> Self {
+        self.namespace_key = Some((fingerprint, crypt_config));
+        self
+    }
+
+    /// Use another chunk cache (`None` to always download)
+    pub fn with_cache(mut self, cache: Option<Arc<ChunkCache>>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Read the raw chunk data (without decoding it)
+    pub fn fetch_chunk(&self, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        if let Some(ref cache) = self.cache {
+            if let Some(data) = cache.get(digest) {
+                return Ok(data);
+            }
+        }
+
+        let fingerprint = self.namespace_key.as_ref().map(|(fp, _)| fp);
+        let location = self
+            .catalog
+            .lookup_chunk(digest, fingerprint)
+            .ok_or_else(|| {
+                format_err!(
+                    "chunk {} not found on cloud target '{}'",
+                    hex::encode(digest),
+                    self.catalog.target()
+                )
+            })?;
+
+        let key = location.object_key();
+        let data = self
+            .backend
+            .get_object_range(&key, location.offset, location.size)
+            .map_err(|err| format_err!("unable to read chunk {} - {}", hex::encode(digest), err))?;
+
+        self.popularity
+            .lock()
+            .unwrap()
+            .record(digest, proxmox_time::epoch_i64());
+
+        let data = match self.namespace_key {
+            Some((_, ref crypt_config)) => decrypt_object(&data, crypt_config).map_err(|err| {
+                format_err!("unable to decrypt chunk {} - {}", hex::encode(digest), err)
+            })?,
+            None => data,
+        };
+
+        if let Some(ref cache) = self.cache {
+            // a full cache disk must not fail the restore
+            if let Err(err) = cache.insert(digest, &data) {
+                log::warn!("unable to cache chunk {} - {}", hex::encode(digest), err);
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Store the updated popularity statistics and cache counters
+    pub fn finish(&self) -> Result<(), Error> {
+        if let Some(ref cache) = self.cache {
+            cache.flush()?;
+        }
+        self.popularity.lock().unwrap().save()
+    }
+}
+
+impl ReadChunk for CloudChunkReader {
+    fn read_raw_chunk(&self, digest: &[u8; 32]) -> Result<DataBlob, Error> {
+        let data = self.fetch_chunk(digest)?;
+        DataBlob::load_from_reader(&mut &data[..])
+    }
+
+    fn read_chunk(&self, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        let chunk = ReadChunk::read_raw_chunk(self, digest)?;
+        chunk.decode(self.crypt_config.as_ref().map(Arc::as_ref), Some(digest))
+    }
+}
+
+impl Drop for CloudChunkReader {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            log::error!("unable to save chunk popularity - {}", err);
+        }
+    }
+}
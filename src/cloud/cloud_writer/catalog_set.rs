@@ -1,112 +1,155 @@
+use std::collections::HashMap;
+
 use anyhow::{bail, Error};
 
 use proxmox_uuid::Uuid;
 
-use crate::tape::{MediaCatalog, MediaSetCatalog};
+use pbs_api_types::{BackupDir, BackupNamespace, Fingerprint};
+
+use crate::cloud::catalog::{
+    ChunkArchiveEntry, CloudCatalog, MediaSetCatalog, ParityEntry, SnapshotEntry,
+};
 
 /// Helper to build and query sets of catalogs
 ///
-/// Similar to MediaSetCatalog, but allows to modify the last catalog.
-#[derive(Default)]
+/// Similar to CloudCatalog, but allows to modify the current media set.
 pub struct CatalogSet {
     // read only part
-    pub media_set_catalog: MediaSetCatalog,
-    // catalog to modify (latest in  set)
-    pub catalog: Option<MediaCatalog>,
+    pub cloud_catalog: CloudCatalog,
+    // catalog to modify (media set we are writing)
+    pub catalog: Option<MediaSetCatalog>,
+    // chunks written to the current media set (per encryption key), with stored size
+    current_chunks: HashMap<(Option<Fingerprint>, [u8; 32]), u64>,
 }
 
 impl CatalogSet {
-    /// Create empty instance
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    /// Add catalog to the read-only set
-    pub fn append_read_only_catalog(&mut self, catalog: MediaCatalog) -> Result<(), Error> {
-        self.media_set_catalog.append_catalog(catalog)
+    /// Create a new instance from the catalogs of a target
+    pub fn new(cloud_catalog: CloudCatalog) -> Self {
+        Self {
+            cloud_catalog,
+            catalog: None,
+            current_chunks: HashMap::new(),
+        }
     }
 
     /// Test if the catalog already contains a snapshot
     pub fn contains_snapshot(
         &self,
         store: &str,
-        ns: &pbs_api_types::BackupNamespace,
-        snapshot: &pbs_api_types::BackupDir,
+        ns: &BackupNamespace,
+        snapshot: &BackupDir,
     ) -> bool {
         if let Some(ref catalog) = self.catalog {
             if catalog.contains_snapshot(store, ns, snapshot) {
                 return true;
             }
         }
-        self.media_set_catalog
-            .contains_snapshot(store, ns, snapshot)
+        self.cloud_catalog.contains_snapshot(store, ns, snapshot)
     }
 
-    /// Test if the catalog already contains a chunk
-    pub fn contains_chunk(&self, store: &str, digest: &[u8; 32]) -> bool {
-        if let Some(ref catalog) = self.catalog {
-            if catalog.contains_chunk(store, digest) {
-                return true;
-            }
+    /// Test if the current chain already contains a chunk encrypted with `key`
+    pub fn contains_chunk(&self, digest: &[u8; 32], key: Option<&Fingerprint>) -> bool {
+        if self.current_chunks.contains_key(&(key.cloned(), *digest)) {
+            return true;
+        }
+        match self.catalog {
+            // a new full media set must not reference older chains
+            Some(ref catalog) if catalog.label.base.is_none() => false,
+            _ => self.cloud_catalog.chain_contains_chunk(digest, key),
         }
-        self.media_set_catalog.contains_chunk(store, digest)
     }
 
-    /// Add a new catalog, move the old on to the read-only set
-    pub fn append_catalog(&mut self, new_catalog: MediaCatalog) -> Result<(), Error> {
-        // append current catalog to read-only set
-        if let Some(catalog) = self.catalog.take() {
-            self.media_set_catalog.append_catalog(catalog)?;
-        }
+    /// Newest earlier snapshot of the group of `snapshot`, written with `key`
+    ///
+    /// Only snapshots whose chunks the current media set may reference are
+    /// considered. Returns the media set and catalog entry of the snapshot.
+    pub fn previous_snapshot(
+        &self,
+        store: &str,
+        ns: &BackupNamespace,
+        snapshot: &BackupDir,
+        key: Option<&Fingerprint>,
+    ) -> Option<(Uuid, &SnapshotEntry)> {
+        let chain: &[MediaSetCatalog] = match self.catalog {
+            // a new full media set must not reference older chains
+            Some(ref catalog) if catalog.label.base.is_none() => &[],
+            _ => self.cloud_catalog.current_chain(),
+        };
+        chain
+            .iter()
+            .chain(self.catalog.iter())
+            .flat_map(|set| set.snapshots.iter().map(move |entry| (set.uuid(), entry)))
+            .filter(|(_, entry)| {
+                entry.store == store
+                    && &entry.ns == ns
+                    && entry.snapshot.group == snapshot.group
+                    && entry.snapshot.time < snapshot.time
+                    && entry.key.as_ref() == key
+            })
+            .max_by_key(|(_, entry)| entry.snapshot.time)
+            .map(|(uuid, entry)| (uuid.clone(), entry))
+    }
 
-        // remove read-only version from set (in case it is there)
-        self.media_set_catalog.remove_catalog(new_catalog.uuid());
+    /// Stored size of a chunk encrypted with `key`, if it was written before
+    pub fn chunk_size(&self, digest: &[u8; 32], key: Option<&Fingerprint>) -> Option<u64> {
+        if let Some(size) = self.current_chunks.get(&(key.cloned(), *digest)) {
+            return Some(*size);
+        }
+        self.cloud_catalog
+            .lookup_chunk(digest, key)
+            .map(|location| location.size)
+    }
 
+    /// Start a new media set
+    pub fn start_media_set(&mut self, new_catalog: MediaSetCatalog) -> Result<(), Error> {
+        if self.catalog.is_some() {
+            bail!("media set already started - internal error");
+        }
+        self.current_chunks.clear();
         self.catalog = Some(new_catalog);
-
         Ok(())
     }
 
     /// Register a snapshot
-    pub fn register_snapshot(
-        &mut self,
-        uuid: Uuid, // Uuid form MediaContentHeader
-        file_number: u64,
-        store: &str,
-        ns: &pbs_api_types::BackupNamespace,
-        snapshot: &pbs_api_types::BackupDir,
-    ) -> Result<(), Error> {
+    pub fn register_snapshot(&mut self, entry: SnapshotEntry) -> Result<(), Error> {
         match self.catalog {
-            Some(ref mut catalog) => {
-                catalog.register_snapshot(uuid, file_number, store, ns, snapshot)?;
-            }
+            Some(ref mut catalog) => catalog.snapshots.push(entry),
             None => bail!("no catalog loaded - internal error"),
         }
         Ok(())
     }
 
     /// Register a chunk archive
-    pub fn register_chunk_archive(
-        &mut self,
-        uuid: Uuid, // Uuid form MediaContentHeader
-        file_number: u64,
-        store: &str,
-        chunk_list: &[[u8; 32]],
-    ) -> Result<(), Error> {
+    pub fn register_chunk_archive(&mut self, entry: ChunkArchiveEntry) -> Result<(), Error> {
         match self.catalog {
             Some(ref mut catalog) => {
-                catalog.register_chunk_archive(uuid, file_number, store, chunk_list)?;
+                for chunk in entry.chunks.iter() {
+                    self.current_chunks
+                        .insert((entry.key.clone(), chunk.digest), chunk.size);
+                }
+                catalog.archives.push(entry);
             }
             None => bail!("no catalog loaded - internal error"),
         }
         Ok(())
     }
 
-    /// Commit the catalog changes
-    pub fn commit(&mut self) -> Result<(), Error> {
-        if let Some(ref mut catalog) = self.catalog {
-            catalog.commit()?;
+    /// Register a parity object
+    pub fn register_parity(&mut self, entry: ParityEntry) -> Result<(), Error> {
+        match self.catalog {
+            Some(ref mut catalog) => catalog.parity.push(entry),
+            None => bail!("no catalog loaded - internal error"),
         }
         Ok(())
     }
+
+    /// Commit the catalog changes (write local copy)
+    ///
+    /// Returns the committed media set catalog.
+    pub fn commit(&mut self) -> Result<Option<&MediaSetCatalog>, Error> {
+        if let Some(ref catalog) = self.catalog {
+            catalog.save(self.cloud_catalog.base_path(), self.cloud_catalog.target())?;
+        }
+        Ok(self.catalog.as_ref())
+    }
 }
@@ -0,0 +1,46 @@
+use std::collections::HashSet;
+
+use pbs_datastore::manifest::{archive_type, ArchiveType, BackupManifest};
+
+/// Index files of a snapshot unchanged since the previous snapshot
+///
+/// The chunks of those files are on the target already, so they are not
+/// looked up in the catalog again.
+pub struct DeltaBase {
+    /// The previous snapshot of the group
+    pub snapshot: pbs_api_types::BackupDir,
+    /// Names of the unchanged index files
+    pub unchanged: HashSet<String>,
+}
+
+impl DeltaBase {
+    pub fn contains(&self, filename: &str) -> bool {
+        self.unchanged.contains(filename)
+    }
+}
+
+/// Index files listed with the same size and checksum in both manifests
+///
+/// The manifest checksum of an index covers its chunk digests, so equal
+/// checksums mean both files reference the same chunks.
+pub fn unchanged_index_files(
+    manifest: &BackupManifest,
+    previous: &BackupManifest,
+) -> HashSet<String> {
+    manifest
+        .files()
+        .iter()
+        .filter(|info| {
+            matches!(
+                archive_type(&info.filename),
+                Ok(ArchiveType::FixedIndex | ArchiveType::DynamicIndex)
+            )
+        })
+        .filter(|info| {
+            previous.files().iter().any(|prev| {
+                prev.filename == info.filename && prev.size == info.size && prev.csum == info.csum
+            })
+        })
+        .map(|info| info.filename.clone())
+        .collect()
+}
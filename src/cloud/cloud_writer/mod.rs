@@ -1,116 +1,243 @@
 mod catalog_set;
 pub use catalog_set::*;
 
+mod delta;
+pub use delta::*;
+
 mod new_chunks_iterator;
 pub use new_chunks_iterator::*;
 
-use std::collections::HashSet;
-use std::fs::File;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
 use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
-use anyhow::{bail, Error};
+use anyhow::{bail, format_err, Error};
 
 use proxmox_sys::{task_log, task_warn};
 use proxmox_uuid::Uuid;
 
-use pbs_datastore::{DataStore, SnapshotReader};
-use pbs_tape::{sg_tape::tape_alert_flags_critical, TapeWrite};
-use proxmox_rest_server::WorkerTask;
-
-use crate::tape::{
-    drive::{media_changer, request_and_load_media, TapeDriver},
-    encryption_keys::load_key_configs,
-    file_formats::{
-        tape_write_catalog, tape_write_snapshot_archive, ChunkArchiveWriter, MediaSetLabel,
-    },
-    MediaCatalog, MediaId, MediaPool, COMMIT_BLOCK_SIZE, MAX_CHUNK_ARCHIVE_SIZE, TAPE_STATUS_DIR,
+use pbs_api_types::{
+    BackupNamespace, CloudTarget, CloudTaskRecord, Fingerprint, SnapshotVerifyState,
 };
+use pbs_datastore::dynamic_index::DynamicIndexReader;
+use pbs_datastore::fixed_index::FixedIndexReader;
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::manifest::{archive_type, ArchiveType, BackupManifest, MANIFEST_BLOB_NAME};
+use pbs_datastore::{DataBlob, DataStore, SnapshotReader};
+use pbs_tools::crypt_config::CryptConfig;
+use proxmox_rest_server::WorkerTask;
 
-use super::file_formats::{
-    PROXMOX_BACKUP_CATALOG_ARCHIVE_MAGIC_1_0, PROXMOX_BACKUP_CATALOG_ARCHIVE_MAGIC_1_1,
+use super::backend::{CloudBackend, PutOptions};
+use super::catalog::{
+    upload_media_set_catalog, upload_media_set_label, AttributedUsage, ChunkArchiveEntry,
+    ChunkEntry, CloudCatalog, MediaSetCatalog, MediaSetLabel, SnapshotEntry, SnapshotFileEntry,
 };
-
-struct CloudWriterState {
-    drive: Box<dyn TapeDriver>,
-    // Media Uuid from loaded media
-    media_uuid: Uuid,
-    // tell if we already moved to EOM
-    at_eom: bool,
-    // bytes written after the last tape fush/sync
-    bytes_written: usize,
+use super::dedup_stats::DedupStats;
+use super::encryption_keys::{encrypt_object, load_crypt_config};
+use super::io_pacing::IoPacer;
+use super::lease::{CloudLease, LEASE_TIMEOUT};
+use super::parity::ParityBuilder;
+use super::restore_preview::{load_manifest, local_archive_previews};
+use super::snapshot_summary::{build_snapshot_summary, upload_snapshot_summary};
+use super::staging::StagingSpool;
+use super::task_records::task_record;
+use super::{layout, CLOUD_STATUS_DIR};
+
+/// Maximum size of a single chunk archive object
+///
+/// Archives are uploaded in one request, so we keep them small enough
+/// to be held in memory and retried cheaply.
+pub const MAX_CHUNK_ARCHIVE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Verification state and key fingerprint recorded in a manifest
+pub fn manifest_info(
+    manifest: &BackupManifest,
+) -> Result<(Option<SnapshotVerifyState>, Option<Fingerprint>), Error> {
+    let verification = serde_json::from_value(manifest.unprotected["verify_state"].clone())?;
+    Ok((verification, manifest.fingerprint()?))
 }
 
-/// Helper to manage a backup job, writing several tapes of a pool
+/// Helper to manage a backup job, writing a media set to a cloud target
 pub struct CloudWriter {
-    //pool: MediaPool,
-    //drive_name: String,
-    status: Option<CloudWriterState>,
+    target: CloudTarget,
+    backend: Arc<dyn CloudBackend>,
+    lease: CloudLease,
     catalog_set: Arc<Mutex<CatalogSet>>,
+    media_set_uuid: Uuid,
     notify_email: Option<String>,
-    //ns_magic: bool,
-    //used_tapes: HashSet<Uuid>,
+    put_options: PutOptions,
+    // loaded namespace keys
+    crypt_configs: HashMap<Fingerprint, Arc<CryptConfig>>,
+    // key used for the snapshot currently written
+    current_key: Option<(Fingerprint, Arc<CryptConfig>)>,
+    // parity of the archives written since the last parity object
+    parity: ParityBuilder,
+    // write-back spool the backend writes to
+    staging: Option<Arc<StagingSpool>>,
+    // chunk archives written for the current snapshot
+    pending_usage: AttributedUsage,
+    // paces chunk reads to protect local I/O
+    io_pacer: Option<Arc<Mutex<IoPacer>>>,
 }
 
 impl CloudWriter {
+    /// Start a new media set on the target
+    ///
+    /// The new media set is incremental (based on the last media set of
+    /// the target) unless `force_full` is set or no media set exists yet.
+    /// Chunk archives and snapshot files are uploaded with `put_options`.
+    /// The writer holds the target lease until it is dropped.
     pub fn new(
-        //mut pool: MediaPool,
-        //drive_name: &str,
+        target: CloudTarget,
+        backend: Arc<dyn CloudBackend>,
         worker: &WorkerTask,
         notify_email: Option<String>,
-        //force_media_set: bool,
-        //ns_magic: bool,
+        force_full: bool,
+        put_options: PutOptions,
     ) -> Result<Self, Error> {
-        let current_time = proxmox_time::epoch_i64();
+        if let Some(ref remote) = target.config.standby_remote {
+            bail!(
+                "target '{}' is a warm standby of remote '{}' - remove 'standby-remote' to take over",
+                target.name,
+                remote
+            );
+        }
+
+        let lease =
+            CloudLease::acquire(Arc::clone(&backend), proxmox_sys::nodename(), LEASE_TIMEOUT)?;
+
+        let cloud_catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &target.name)?;
+
+        let base = if force_full {
+            None
+        } else {
+            cloud_catalog.last_media_set().map(|set| set.uuid().clone())
+        };
+
+        let label = MediaSetLabel {
+            uuid: Uuid::generate(),
+            ctime: proxmox_time::epoch_i64(),
+            base,
+            node: proxmox_sys::nodename().to_string(),
+        };
 
-        //let new_media_set_reason = pool.start_write_session(current_time, force_media_set)?;
-        // if let Some(reason) = new_media_set_reason {
-        //     task_log!(worker, "starting new media set - reason: {}", reason,);
-        // }
+        match label.base {
+            Some(ref base) => task_log!(worker, "starting incremental media set (base {})", base),
+            None => task_log!(worker, "starting full media set"),
+        }
+        task_log!(worker, "media set uuid: {}", label.uuid);
 
-        //let media_set_uuid = pool.current_media_set().uuid();
-        // task_log!(worker, "media set uuid: {}", media_set_uuid);
+        upload_media_set_label(&*backend, &label)?;
 
-        let mut catalog_set = CatalogSet::new();
+        let media_set_uuid = label.uuid.clone();
 
-        // load all catalogs read-only at start
-        // for media_uuid in pool.current_media_list()? {
-        //     let media_info = pool.lookup_media(media_uuid).unwrap();
-        //     let media_catalog = MediaCatalog::open(TAPE_STATUS_DIR, media_info.id(), false, false)?;
-        //     catalog_set.append_read_only_catalog(media_catalog)?;
-        // }
+        let mut catalog_set = CatalogSet::new(cloud_catalog);
+        catalog_set.start_media_set(MediaSetCatalog::new(label))?;
 
         Ok(Self {
-            //pool,
-            //drive_name: drive_name.to_string(),
-            status: None,
+            target,
+            backend,
+            lease,
             catalog_set: Arc::new(Mutex::new(catalog_set)),
+            media_set_uuid,
             notify_email,
-            //ns_magic,
-            //used_tapes: HashSet::new(),
+            put_options,
+            crypt_configs: HashMap::new(),
+            current_key: None,
+            parity: ParityBuilder::new(),
+            staging: None,
+            pending_usage: AttributedUsage::default(),
+            io_pacer: None,
         })
     }
 
-    // pub fn pool(&mut self) -> &mut MediaPool {
-    //     &mut self.pool
-    // }
+    /// Wait for room in the write-back spool before each upload
+    ///
+    /// Use this if `backend` writes to `spool` (see
+    /// [`super::backend::StagingBackend`]).
+    pub fn with_staging(mut self, spool: Arc<StagingSpool>) -> Self {
+        self.staging = Some(spool);
+        self
+    }
+
+    /// Pace chunk reads with `pacer` (see [`super::io_pacing`])
+    pub fn with_io_pacer(mut self, pacer: IoPacer) -> Self {
+        self.io_pacer = Some(Arc::new(Mutex::new(pacer)));
+        self
+    }
 
-    /// Set media status to FULL (persistent - stores pool status)
-    // pub fn set_media_status_full(&mut self, uuid: &Uuid) -> Result<(), Error> {
-    //     self.pool.set_media_status_full(uuid)?;
-    //     Ok(())
-    // }
+    /// Time the chunk reader waited for local I/O so far
+    pub fn io_throttled(&self) -> Option<std::time::Duration> {
+        self.io_pacer
+            .as_ref()
+            .map(|pacer| pacer.lock().unwrap().throttled())
+    }
 
-    // pub fn get_used_media_labels(&self) -> Result<Vec<String>, Error> {
-    //     let mut res = Vec::with_capacity(self.used_tapes.len());
-    //     for media_uuid in &self.used_tapes {
-    //         let media_info = self.pool.lookup_media(media_uuid)?;
-    //         res.push(media_info.label_text().to_string());
-    //     }
+    pub fn target(&self) -> &CloudTarget {
+        &self.target
+    }
 
-    //     Ok(res)
-    // }
+    pub fn media_set_uuid(&self) -> &Uuid {
+        &self.media_set_uuid
+    }
+
+    pub fn notify_email(&self) -> Option<&str> {
+        self.notify_email.as_deref()
+    }
+
+    /// Select the encryption key for snapshots of namespace `ns`
+    ///
+    /// Uses the namespace keys configured on the target. Call this before
+    /// writing chunks and files of a snapshot. Returns the key fingerprint,
+    /// or `None` if the data is stored unencrypted.
+    pub fn select_namespace_key(
+        &mut self,
+        ns: &BackupNamespace,
+    ) -> Result<Option<&Fingerprint>, Error> {
+        self.current_key = match self.target.config.namespace_key_for(ns)? {
+            Some(fingerprint) => {
+                let crypt_config = match self.crypt_configs.get(&fingerprint) {
+                    Some(crypt_config) => Arc::clone(crypt_config),
+                    None => {
+                        let crypt_config = load_crypt_config(&fingerprint)?;
+                        self.crypt_configs
+                            .insert(fingerprint.clone(), Arc::clone(&crypt_config));
+                        crypt_config
+                    }
+                };
+                Some((fingerprint, crypt_config))
+            }
+            None => None,
+        };
+        Ok(self
+            .current_key
+            .as_ref()
+            .map(|(fingerprint, _)| fingerprint))
+    }
+
+    fn current_fingerprint(&self) -> Option<Fingerprint> {
+        self.current_key
+            .as_ref()
+            .map(|(fingerprint, _)| fingerprint.clone())
+    }
+
+    // back-pressure of the write-back spool
+    fn wait_for_staging(&mut self, worker: &WorkerTask, size: usize) -> Result<(), Error> {
+        if let Some(ref spool) = self.staging {
+            let lease = &mut self.lease;
+            spool.wait_for_space(worker, size as u64, || lease.heartbeat())?;
+        }
+        Ok(())
+    }
+
+    // encrypt data with the current key (if any)
+    fn encode_object(&self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        match self.current_key {
+            Some((_, ref crypt_config)) => encrypt_object(&data, crypt_config),
+            None => Ok(data),
+        }
+    }
 
     pub fn contains_snapshot(
         &self,
@@ -124,553 +251,383 @@ impl CloudWriter {
             .contains_snapshot(store, ns, snapshot)
     }
 
-    /// Eject media and drop CloudWriterState (close drive)
-    pub fn eject_media(&mut self, worker: &WorkerTask) -> Result<(), Error> {
-        let mut status = match self.status.take() {
-            Some(status) => status,
-            None => return Ok(()), // no media loaded
+    /// Upload all files of a snapshot and register it in the catalog
+    ///
+    /// Chunks are not written here, use [`Self::append_chunk_archive`]
+    /// before calling this. Returns the data attributed to the snapshot,
+    /// the files and the chunk archives written since the last snapshot.
+    pub fn append_snapshot_archive(
+        &mut self,
+        worker: &WorkerTask,
+        snapshot_reader: &SnapshotReader,
+    ) -> Result<AttributedUsage, Error> {
+        let store = snapshot_reader.datastore_name().to_string();
+        let snapshot = snapshot_reader.snapshot();
+        let ns = snapshot.backup_ns().clone();
+        let dir = snapshot.dir().clone();
+
+        self.lease.heartbeat()?;
+
+        let mut files = Vec::new();
+        let mut bytes_written = 0;
+        let mut verification = None;
+        let mut fingerprint = None;
+        let mut manifest = None;
+
+        for filename in snapshot_reader.file_list().iter() {
+            let mut file = snapshot_reader.open_file(filename)?;
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+
+            // size and checksum always refer to the plain data
+            let size = data.len() as u64;
+            let csum = openssl::sha::sha256(&data);
+
+            if filename == MANIFEST_BLOB_NAME {
+                let parsed = DataBlob::load_from_reader(&mut &data[..])
+                    .and_then(BackupManifest::try_from)
+                    .and_then(|parsed| Ok((manifest_info(&parsed)?, parsed)));
+                match parsed {
+                    Ok((info, parsed)) => {
+                        (verification, fingerprint) = info;
+                        manifest = Some(parsed);
+                    }
+                    Err(err) => task_warn!(worker, "unable to parse manifest of {} - {}", dir, err),
+                }
+            }
+
+            let data = self.encode_object(data)?;
+            self.wait_for_staging(worker, data.len())?;
+
+            let key = layout::snapshot_file_key(&self.media_set_uuid, &store, &ns, &dir, filename);
+            self.backend
+                .put_object_multipart(&key, &data, &self.put_options)
+                .map_err(|err| format_err!("unable to upload '{}' - {}", filename, err))?;
+            task_record(
+                worker,
+                CloudTaskRecord::new("snapshot-file")
+                    .key(&key)
+                    .bytes(data.len() as u64),
+            );
+
+            files.push(SnapshotFileEntry {
+                filename: filename.to_string(),
+                size,
+                csum,
+            });
+            bytes_written += data.len();
+        }
+
+        let mut chunks = Vec::new();
+        let mut chunk_index = HashSet::new();
+        for digest in snapshot_reader.chunk_iterator(|_| false)? {
+            let digest = digest?;
+            if chunk_index.insert(digest) {
+                chunks.push(digest);
+            }
+        }
+
+        task_log!(
+            worker,
+            "uploaded {} files ({} bytes) for snapshot {}",
+            files.len(),
+            bytes_written,
+            dir,
+        );
+
+        let owner = match snapshot.get_owner() {
+            Ok(owner) => Some(owner),
+            Err(err) => {
+                task_warn!(worker, "unable to read owner of {} - {}", dir, err);
+                None
+            }
+        };
+
+        // the files plus the summary object
+        let mut attributed = std::mem::take(&mut self.pending_usage);
+        attributed.add(&AttributedUsage {
+            bytes: bytes_written as u64,
+            objects: files.len() as u64 + 1,
+        });
+
+        let entry = SnapshotEntry {
+            store,
+            ns,
+            snapshot: dir,
+            key: self.current_fingerprint(),
+            files,
+            chunks,
+            verification,
+            fingerprint,
+            owner,
+            attributed,
+            cloud_verification: None,
+        };
+
+        let chunk_size = {
+            let catalog_set = self.catalog_set.lock().unwrap();
+            entry
+                .chunks
+                .iter()
+                .map(|digest| catalog_set.chunk_size(digest, entry.key.as_ref()))
+                .sum()
+        };
+        let mut summary = build_snapshot_summary(&self.media_set_uuid, &entry, chunk_size);
+        if let Some(ref manifest) = manifest {
+            match local_archive_previews(snapshot, manifest) {
+                Ok(archives) => summary.archives = archives,
+                Err(err) => task_warn!(
+                    worker,
+                    "unable to read catalog of {} - {}",
+                    entry.snapshot,
+                    err
+                ),
+            }
+        }
+        upload_snapshot_summary(
+            &*self.backend,
+            &self.media_set_uuid,
+            &entry,
+            &summary,
+            &self.put_options,
+        )?;
+
+        self.catalog_set.lock().unwrap().register_snapshot(entry)?;
+
+        Ok(attributed)
+    }
+
+    /// Write a new chunk archive using chunks from `chunk_iter`
+    ///
+    /// This stops when `chunk_iter` is exhausted or the archive reaches
+    /// [`MAX_CHUNK_ARCHIVE_SIZE`]. Returns `(done, bytes_written)`.
+    pub fn append_chunk_archive(
+        &mut self,
+        worker: &WorkerTask,
+        chunk_iter: &mut std::iter::Peekable<NewChunksIterator>,
+        store: &str,
+    ) -> Result<(bool, usize), Error> {
+        let start_time = SystemTime::now();
+
+        let mut data = Vec::new();
+        let mut chunks = Vec::new();
+
+        loop {
+            if data.len() >= MAX_CHUNK_ARCHIVE_SIZE {
+                break;
+            }
+            let (digest, blob) = match chunk_iter.next() {
+                None => break,
+                Some(Err(err)) => bail!("{}", err),
+                Some(Ok(chunk)) => chunk,
+            };
+            let raw = self.encode_object(blob.into_inner())?;
+            chunks.push(ChunkEntry {
+                digest,
+                offset: data.len() as u64,
+                size: raw.len() as u64,
+            });
+            data.extend_from_slice(&raw);
+        }
+
+        let done = chunk_iter.peek().is_none();
+
+        if chunks.is_empty() {
+            return Ok((done, 0));
+        }
+
+        self.lease.heartbeat()?;
+        self.wait_for_staging(worker, data.len())?;
+
+        let archive_uuid = Uuid::generate();
+        let key = layout::chunk_archive_key(&self.media_set_uuid, &archive_uuid);
+        self.backend
+            .put_object_multipart(&key, &data, &self.put_options)
+            .map_err(|err| format_err!("unable to upload chunk archive - {}", err))?;
+        task_record(
+            worker,
+            CloudTaskRecord::new("chunk-archive")
+                .key(&key)
+                .bytes(data.len() as u64),
+        );
+
+        if let Some(group) = self.target.config.parity_group {
+            self.parity.add(&archive_uuid, &data);
+            if self.parity.len() as u64 >= group {
+                self.finish_parity()?;
+            }
+        }
+
+        let bytes_written = data.len();
+        self.pending_usage.add(&AttributedUsage {
+            bytes: bytes_written as u64,
+            objects: 1,
+        });
+
+        let elapsed = start_time.elapsed()?.as_secs_f64();
+        task_log!(
+            worker,
+            "wrote {} chunks ({:.2} MB at {:.2} MB/s)",
+            chunks.len(),
+            bytes_written as f64 / 1_000_000.0,
+            (bytes_written as f64) / (1_000_000.0 * elapsed),
+        );
+
+        self.catalog_set
+            .lock()
+            .unwrap()
+            .register_chunk_archive(ChunkArchiveEntry {
+                uuid: archive_uuid,
+                store: store.to_string(),
+                key: self.current_fingerprint(),
+                size: bytes_written as u64,
+                csum: Some(openssl::sha::sha256(&data)),
+                chunks,
+            })?;
+
+        Ok((done, bytes_written))
+    }
+
+    /// Compare a snapshot with the previous snapshot of its group
+    ///
+    /// Looks for the newest earlier snapshot of the group the current media
+    /// set may reference (written with the current key), and returns the
+    /// index files which did not change since. Their chunks are on the
+    /// target already. The manifest of the previous snapshot is read from
+    /// the datastore, or downloaded if the snapshot was pruned locally.
+    pub fn delta_base(&self, snapshot_reader: &SnapshotReader) -> Result<Option<DeltaBase>, Error> {
+        let snapshot = snapshot_reader.snapshot();
+        let key = self.current_fingerprint();
+
+        let (media_set, entry) = {
+            let catalog_set = self.catalog_set.lock().unwrap();
+            match catalog_set.previous_snapshot(
+                snapshot_reader.datastore_name(),
+                snapshot.backup_ns(),
+                snapshot.as_ref(),
+                key.as_ref(),
+            ) {
+                Some((media_set, entry)) => (media_set, entry.clone()),
+                None => return Ok(None),
+            }
         };
 
-        // let (drive_config, _digest) = pbs_config::drive::config()?;
+        let (manifest, _) = snapshot.load_manifest()?;
 
-        // if let Some((mut changer, _)) = media_changer(&drive_config, &self.drive_name)? {
-        //     task_log!(worker, "eject media");
-        //     status.drive.eject_media()?; // rewind and eject early, so that unload_media is faster
-        //     drop(status); // close drive
-        //     task_log!(worker, "unload media");
-        //     changer.unload_media(None)?; //eject and unload
-        // } else {
-        //     task_log!(worker, "standalone drive - ejecting media");
-        //     status.drive.eject_media()?;
-        // }
+        let local = snapshot
+            .datastore()
+            .backup_dir(entry.ns.clone(), entry.snapshot.clone())?;
+        let previous = if local.full_path().exists() {
+            local.load_manifest()?.0
+        } else {
+            load_manifest(&*self.backend, &media_set, &entry)?
+        };
 
-        Ok(())
+        Ok(Some(DeltaBase {
+            snapshot: entry.snapshot,
+            unchanged: unchanged_index_files(&manifest, &previous),
+        }))
     }
 
-    /// Export current media set and drop CloudWriterState (close drive)
-    // pub fn export_media_set(&mut self, worker: &WorkerTask) -> Result<(), Error> {
-    //     let mut status = self.status.take();
-
-    //     let (drive_config, _digest) = pbs_config::drive::config()?;
-
-    //     if let Some((mut changer, _)) = media_changer(&drive_config, &self.drive_name)? {
-    //         if let Some(ref mut status) = status {
-    //             task_log!(worker, "rewind media");
-    //             // rewind first so that the unload command later does not run into a timeout
-    //             status.drive.rewind()?;
-    //         }
-    //         drop(status); // close drive
-
-    //         for media_uuid in self.pool.current_media_list()? {
-    //             let media = self.pool.lookup_media(media_uuid)?;
-    //             let label_text = media.label_text();
-    //             if let Some(slot) = changer.export_media(label_text)? {
-    //                 task_log!(
-    //                     worker,
-    //                     "exported media '{}' to import/export slot {}",
-    //                     label_text,
-    //                     slot
-    //                 );
-    //             } else {
-    //                 task_warn!(
-    //                     worker,
-    //                     "export failed - media '{}' is not online or in different drive",
-    //                     label_text
-    //                 );
-    //             }
-    //         }
-    //     } else if let Some(mut status) = status {
-    //         task_log!(
-    //             worker,
-    //             "standalone drive - ejecting media instead of export"
-    //         );
-    //         status.drive.eject_media()?;
-    //     }
-
-    //     Ok(())
-    // }
-
-    /// commit changes to tape and catalog
+    /// Deduplication statistics of the chunks of a snapshot
     ///
-    /// This is done automatically during a backupsession, but needs to
-    /// be called explicitly before dropping the CloudWriter
-    // pub fn commit(&mut self) -> Result<(), Error> {
-    //     if let Some(CloudWriterState { ref mut drive, .. }) = self.status {
-    //         drive.sync()?; // sync all data to the tape
-    //     }
-    //     self.catalog_set.lock().unwrap().commit()?; // then commit the catalog
-    //     Ok(())
-    // }
-
-    /// Load a writable media into the drive
-    // pub fn load_writable_media(&mut self, worker: &WorkerTask) -> Result<Uuid, Error> {
-    //     let last_media_uuid = match self.status {
-    //         Some(CloudWriterState { ref media_uuid, .. }) => Some(media_uuid.clone()),
-    //         None => None,
-    //     };
-
-    //     let current_time = proxmox_time::epoch_i64();
-    //     let media_uuid = self.pool.alloc_writable_media(current_time)?;
-
-    //     let media = self.pool.lookup_media(&media_uuid).unwrap();
-
-    //     let media_changed = match last_media_uuid {
-    //         Some(ref last_media_uuid) => last_media_uuid != &media_uuid,
-    //         None => true,
-    //     };
-
-    //     if !media_changed {
-    //         self.used_tapes.insert(media_uuid.clone());
-    //         return Ok(media_uuid);
-    //     }
-
-    //     task_log!(
-    //         worker,
-    //         "allocated new writable media '{}'",
-    //         media.label_text()
-    //     );
-
-    //     if let Some(CloudWriterState { mut drive, .. }) = self.status.take() {
-    //         if last_media_uuid.is_some() {
-    //             task_log!(worker, "eject current media");
-    //             drive.eject_media()?;
-    //         }
-    //     }
-
-    //     let (drive_config, _digest) = pbs_config::drive::config()?;
-
-    //     let (mut drive, old_media_id) = request_and_load_media(
-    //         worker,
-    //         &drive_config,
-    //         &self.drive_name,
-    //         media.label(),
-    //         &self.notify_email,
-    //     )?;
-
-    //     // test for critical tape alert flags
-    //     if let Ok(alert_flags) = drive.tape_alert_flags() {
-    //         if !alert_flags.is_empty() {
-    //             task_log!(worker, "TapeAlertFlags: {:?}", alert_flags);
-    //             if tape_alert_flags_critical(alert_flags) {
-    //                 self.pool.set_media_status_damaged(&media_uuid)?;
-    //                 bail!(
-    //                     "aborting due to critical tape alert flags: {:?}",
-    //                     alert_flags
-    //                 );
-    //             }
-    //         }
-    //     }
-
-    //     let (catalog, is_new_media) = update_media_set_label(
-    //         worker,
-    //         drive.as_mut(),
-    //         old_media_id.media_set_label,
-    //         media.id(),
-    //     )?;
-
-    //     self.catalog_set.lock().unwrap().append_catalog(catalog)?;
-
-    //     let media_set = media.media_set_label().unwrap();
-
-    //     let encrypt_fingerprint = media_set
-    //         .encryption_key_fingerprint
-    //         .clone()
-    //         .map(|fp| (fp, media_set.uuid.clone()));
-
-    //     drive.set_encryption(encrypt_fingerprint)?;
-
-    //     self.status = Some(CloudWriterState {
-    //         drive,
-    //         media_uuid: media_uuid.clone(),
-    //         at_eom: false,
-    //         bytes_written: 0,
-    //     });
-
-    //     if is_new_media {
-    //         // add catalogs from previous media
-    //         self.append_media_set_catalogs(worker)?;
-    //     }
-
-    //     self.used_tapes.insert(media_uuid.clone());
-    //     Ok(media_uuid)
-    // }
-
-    fn open_catalog_file(uuid: &Uuid) -> Result<File, Error> {
-        let mut path = PathBuf::from(TAPE_STATUS_DIR);
-        path.push(uuid.to_string());
-        path.set_extension("log");
-
-        let file = std::fs::OpenOptions::new().read(true).open(&path)?;
-
-        Ok(file)
+    /// Counts the logical size of all chunk references, and the part the
+    /// target already holds (encrypted with the current key). Index files
+    /// unchanged since `delta` count as present without lookups. Call this
+    /// before writing the chunks of the snapshot.
+    pub fn snapshot_dedup_stats(
+        &self,
+        snapshot_reader: &SnapshotReader,
+        delta: Option<&DeltaBase>,
+    ) -> Result<DedupStats, Error> {
+        let key = self.current_fingerprint();
+        let catalog_set = self.catalog_set.lock().unwrap();
+
+        let mut stats = DedupStats {
+            snapshots: 1,
+            ..Default::default()
+        };
+
+        for filename in snapshot_reader.file_list().iter() {
+            let index: Box<dyn IndexFile> = match archive_type(filename)? {
+                ArchiveType::FixedIndex => {
+                    Box::new(FixedIndexReader::new(snapshot_reader.open_file(filename)?)?)
+                }
+                ArchiveType::DynamicIndex => Box::new(DynamicIndexReader::new(
+                    snapshot_reader.open_file(filename)?,
+                )?),
+                ArchiveType::Blob => continue,
+            };
+            if delta.map(|delta| delta.contains(filename)).unwrap_or(false) {
+                stats.logical_bytes += index.index_bytes();
+                stats.present_bytes += index.index_bytes();
+                continue;
+            }
+            for pos in 0..index.index_count() {
+                let info = match index.chunk_info(pos) {
+                    Some(info) => info,
+                    None => bail!("missing chunk info in '{}' - internal error", filename),
+                };
+                stats.logical_bytes += info.size();
+                if catalog_set.contains_chunk(&info.digest, key.as_ref()) {
+                    stats.present_bytes += info.size();
+                }
+            }
+        }
+
+        Ok(stats)
     }
 
-    // Check it tape is loaded, then move to EOM (if not already there)
-    //
-    // Returns the tape position at EOM.
-    // fn prepare_tape_write(status: &mut CloudWriterState, worker: &WorkerTask) -> Result<u64, Error> {
-    //     if !status.at_eom {
-    //         task_log!(worker, "moving to end of media");
-    //         status.drive.move_to_eom(true)?;
-    //         status.at_eom = true;
-    //         task_log!(worker, "arrived at end of media");
-    //     }
-
-    //     let current_file_number = status.drive.current_file_number()?;
-    //     if current_file_number < 2 {
-    //         bail!(
-    //             "got strange file position number from drive ({})",
-    //             current_file_number
-    //         );
-    //     }
-
-    //     Ok(current_file_number)
-    // }
-
-    // Move to EOM (if not already there), then write the current
-    // catalog to the tape. On success, this return 'Ok(true)'.
-
-    // Please note that this may fail when there is not enough space
-    // on the media (return value 'Ok(false, _)'). In that case, the
-    // archive is marked incomplete. The caller should mark the media
-    // as full and try again using another media.
-    // pub fn append_catalog_archive(&mut self, worker: &WorkerTask) -> Result<bool, Error> {
-    //     let catalog_magic = self.catalog_version();
-
-    //     let status = match self.status {
-    //         Some(ref mut status) => status,
-    //         None => bail!("CloudWriter - no media loaded"),
-    //     };
-
-    //     Self::prepare_tape_write(status, worker)?;
-
-    //     let catalog_set = self.catalog_set.lock().unwrap();
-
-    //     let catalog = match catalog_set.catalog {
-    //         None => bail!("append_catalog_archive failed: no catalog - internal error"),
-    //         Some(ref catalog) => catalog,
-    //     };
-
-    //     let media_set = self.pool.current_media_set();
-
-    //     let media_list = media_set.media_list();
-    //     let uuid = match media_list.last() {
-    //         None => bail!("got empty media list - internal error"),
-    //         Some(None) => bail!("got incomplete media list - internal error"),
-    //         Some(Some(last_uuid)) => {
-    //             if last_uuid != catalog.uuid() {
-    //                 bail!("got wrong media - internal error");
-    //             }
-    //             last_uuid
-    //         }
-    //     };
-
-    //     let seq_nr = media_list.len() - 1;
-
-    //     let mut writer: Box<dyn TapeWrite> = status.drive.write_file()?;
-
-    //     let mut file = Self::open_catalog_file(uuid)?;
-
-    //     let done = tape_write_catalog(
-    //         writer.as_mut(),
-    //         uuid,
-    //         media_set.uuid(),
-    //         seq_nr,
-    //         &mut file,
-    //         catalog_magic,
-    //     )?
-    //     .is_some();
-
-    //     Ok(done)
-    // }
-
-    // Append catalogs for all previous media in set (without last)
-    // fn append_media_set_catalogs(&mut self, worker: &WorkerTask) -> Result<(), Error> {
-    //     let media_set = self.pool.current_media_set();
-
-    //     let mut media_list = media_set.media_list();
-    //     if media_list.len() < 2 {
-    //         return Ok(());
-    //     }
-    //     media_list = &media_list[..(media_list.len() - 1)];
-
-    //     let catalog_magic = self.catalog_version();
-
-    //     let status = match self.status {
-    //         Some(ref mut status) => status,
-    //         None => bail!("CloudWriter - no media loaded"),
-    //     };
-
-    //     Self::prepare_tape_write(status, worker)?;
-
-    //     for (seq_nr, uuid) in media_list.iter().enumerate() {
-    //         let uuid = match uuid {
-    //             None => bail!("got incomplete media list - internal error"),
-    //             Some(uuid) => uuid,
-    //         };
-
-    //         let mut writer: Box<dyn TapeWrite> = status.drive.write_file()?;
-
-    //         let mut file = Self::open_catalog_file(uuid)?;
-
-    //         task_log!(worker, "write catalog for previous media: {}", uuid);
-
-    //         if tape_write_catalog(
-    //             writer.as_mut(),
-    //             uuid,
-    //             media_set.uuid(),
-    //             seq_nr,
-    //             &mut file,
-    //             catalog_magic,
-    //         )?
-    //         .is_none()
-    //         {
-    //             bail!("got EOM while writing start catalog");
-    //         }
-    //     }
-
-    //     Ok(())
-    // }
-
-    // Move to EOM (if not already there), then creates a new snapshot
-    // archive writing specified files (as .pxar) into it. On
-    // success, this return 'Ok(true)' and the media catalog gets
-    // updated.
-
-    // Please note that this may fail when there is not enough space
-    // on the media (return value 'Ok(false, _)'). In that case, the
-    // archive is marked incomplete, and we do not use it. The caller
-    // should mark the media as full and try again using another
-    // media.
-    // pub fn append_snapshot_archive(
-    //     &mut self,
-    //     worker: &WorkerTask,
-    //     snapshot_reader: &SnapshotReader,
-    // ) -> Result<(bool, usize), Error> {
-    //     let status = match self.status {
-    //         Some(ref mut status) => status,
-    //         None => bail!("CloudWriter - no media loaded"),
-    //     };
-
-    //     let current_file_number = Self::prepare_tape_write(status, worker)?;
-
-    //     let (done, bytes_written) = {
-    //         let mut writer: Box<dyn TapeWrite> = status.drive.write_file()?;
-
-    //         match tape_write_snapshot_archive(writer.as_mut(), snapshot_reader)? {
-    //             Some(content_uuid) => {
-    //                 self.catalog_set.lock().unwrap().register_snapshot(
-    //                     content_uuid,
-    //                     current_file_number,
-    //                     snapshot_reader.datastore_name(),
-    //                     snapshot_reader.snapshot().backup_ns(),
-    //                     snapshot_reader.snapshot().as_ref(),
-    //                 )?;
-    //                 (true, writer.bytes_written())
-    //             }
-    //             None => (false, writer.bytes_written()),
-    //         }
-    //     };
-
-    //     status.bytes_written += bytes_written;
-
-    //     let request_sync = status.bytes_written >= COMMIT_BLOCK_SIZE;
-
-    //     if !done || request_sync {
-    //         self.commit()?;
-    //     }
-
-    //     Ok((done, bytes_written))
-    // }
-
-    // Move to EOM (if not already there), then creates a new chunk
-    // archive and writes chunks from 'chunk_iter'. This stops when
-    // it detect LEOM or when we reach max archive size
-    // (4GB). Written chunks are registered in the media catalog.
-    // pub fn append_chunk_archive(
-    //     &mut self,
-    //     worker: &WorkerTask,
-    //     chunk_iter: &mut std::iter::Peekable<NewChunksIterator>,
-    //     store: &str,
-    // ) -> Result<(bool, usize), Error> {
-    //     let status = match self.status {
-    //         Some(ref mut status) => status,
-    //         None => bail!("CloudWriter - no media loaded"),
-    //     };
-
-    //     let current_file_number = Self::prepare_tape_write(status, worker)?;
-
-    //     let writer = status.drive.write_file()?;
-
-    //     let start_time = SystemTime::now();
-
-    //     let (saved_chunks, content_uuid, leom, bytes_written) =
-    //         write_chunk_archive(worker, writer, chunk_iter, store, MAX_CHUNK_ARCHIVE_SIZE)?;
-
-    //     status.bytes_written += bytes_written;
-
-    //     let elapsed = start_time.elapsed()?.as_secs_f64();
-    //     task_log!(
-    //         worker,
-    //         "wrote {} chunks ({:.2} MB at {:.2} MB/s)",
-    //         saved_chunks.len(),
-    //         bytes_written as f64 / 1_000_000.0,
-    //         (bytes_written as f64) / (1_000_000.0 * elapsed),
-    //     );
-
-    //     let request_sync = status.bytes_written >= COMMIT_BLOCK_SIZE;
-
-    //     // register chunks in media_catalog
-    //     self.catalog_set.lock().unwrap().register_chunk_archive(
-    //         content_uuid,
-    //         current_file_number,
-    //         store,
-    //         &saved_chunks,
-    //     )?;
-
-    //     if leom || request_sync {
-    //         self.commit()?;
-    //     }
-
-    //     Ok((leom, bytes_written))
-    // }
-
-    // pub fn spawn_chunk_reader_thread(
-    //     &self,
-    //     datastore: Arc<DataStore>,
-    //     snapshot_reader: Arc<Mutex<SnapshotReader>>,
-    // ) -> Result<(std::thread::JoinHandle<()>, NewChunksIterator), Error> {
-    //     NewChunksIterator::spawn(datastore, snapshot_reader, Arc::clone(&self.catalog_set))
-    // }
-
-    // pub(crate) fn catalog_version(&self) -> [u8; 8] {
-    //     if self.ns_magic {
-    //         PROXMOX_BACKUP_CATALOG_ARCHIVE_MAGIC_1_1
-    //     } else {
-    //         PROXMOX_BACKUP_CATALOG_ARCHIVE_MAGIC_1_0
-    //     }
-    // }
-}
+    /// Read the new chunks of a snapshot in a separate thread
+    ///
+    /// Index files unchanged since `delta` are skipped.
+    pub fn spawn_chunk_reader_thread(
+        &self,
+        datastore: Arc<DataStore>,
+        snapshot_reader: Arc<Mutex<SnapshotReader>>,
+        delta: Option<&DeltaBase>,
+    ) -> Result<(std::thread::JoinHandle<()>, NewChunksIterator), Error> {
+        NewChunksIterator::spawn(
+            datastore,
+            snapshot_reader,
+            Arc::clone(&self.catalog_set),
+            self.current_fingerprint(),
+            delta
+                .map(|delta| delta.unchanged.clone())
+                .unwrap_or_default(),
+            self.io_pacer.clone(),
+        )
+    }
+
+    // upload the parity object of the archives written since the last one
+    fn finish_parity(&mut self) -> Result<(), Error> {
+        if self.parity.is_empty() {
+            return Ok(());
+        }
+        let parity = std::mem::take(&mut self.parity);
+        let entry = parity.finish(&*self.backend, &self.media_set_uuid, &self.put_options)?;
+        self.catalog_set.lock().unwrap().register_parity(entry)
+    }
+
+    /// Keep the target lease while the writer is idle
+    pub fn keep_alive(&mut self) -> Result<(), Error> {
+        self.lease.heartbeat()
+    }
+
+    /// Store the media set catalog (locally and on the target)
+    ///
+    /// The catalog object marks the media set as complete, so this
+    /// should be called once all archives are written.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        self.lease.renew()?;
+        self.finish_parity()?;
+
+        let mut catalog_set = self.catalog_set.lock().unwrap();
+        let catalog = match catalog_set.commit()? {
+            Some(catalog) => catalog,
+            None => bail!("no catalog loaded - internal error"),
+        };
 
-// write up to <max_size> of chunks
-// #[allow(clippy::type_complexity)]
-// fn write_chunk_archive<'a>(
-//     _worker: &WorkerTask,
-//     writer: Box<dyn 'a + TapeWrite>,
-//     chunk_iter: &mut std::iter::Peekable<NewChunksIterator>,
-//     store: &str,
-//     max_size: usize,
-// ) -> Result<(Vec<[u8; 32]>, Uuid, bool, usize), Error> {
-//     let (mut writer, content_uuid) = ChunkArchiveWriter::new(writer, store, true)?;
-
-//     // we want to get the chunk list in correct order
-//     let mut chunk_list: Vec<[u8; 32]> = Vec::new();
-
-//     let mut leom = false;
-
-//     loop {
-//         let (digest, blob) = match chunk_iter.peek() {
-//             None => break,
-//             Some(Ok((digest, blob))) => (digest, blob),
-//             Some(Err(err)) => bail!("{}", err),
-//         };
-
-//         //println!("CHUNK {} size {}", hex::encode(digest), blob.raw_size());
-
-//         match writer.try_write_chunk(digest, blob) {
-//             Ok(true) => {
-//                 chunk_list.push(*digest);
-//                 chunk_iter.next(); // consume
-//             }
-//             Ok(false) => {
-//                 // Note; we do not consume the chunk (no chunk_iter.next())
-//                 leom = true;
-//                 break;
-//             }
-//             Err(err) => bail!("write chunk failed - {}", err),
-//         }
-
-//         if writer.bytes_written() > max_size {
-//             //task_log!(worker, "Chunk Archive max size reached, closing archive");
-//             break;
-//         }
-//     }
-
-//     writer.finish()?;
-
-//     Ok((chunk_list, content_uuid, leom, writer.bytes_written()))
-// }
-
-// Compare the media set label. If the media is empty, or the existing
-// set label does not match the expected media set, overwrite the
-// media set label.
-// fn update_media_set_label(
-//     worker: &WorkerTask,
-//     drive: &mut dyn TapeDriver,
-//     old_set: Option<MediaSetLabel>,
-//     media_id: &MediaId,
-// ) -> Result<(MediaCatalog, bool), Error> {
-//     let media_catalog;
-
-//     let new_set = match media_id.media_set_label {
-//         None => bail!("got media without media set - internal error"),
-//         Some(ref set) => set,
-//     };
-
-//     let key_config = if let Some(ref fingerprint) = new_set.encryption_key_fingerprint {
-//         let (config_map, _digest) = load_key_configs()?;
-//         match config_map.get(fingerprint) {
-//             Some(key_config) => Some(key_config.clone()),
-//             None => {
-//                 bail!(
-//                     "unable to find tape encryption key config '{}'",
-//                     fingerprint
-//                 );
-//             }
-//         }
-//     } else {
-//         None
-//     };
-
-//     let new_media = match old_set {
-//         None => {
-//             task_log!(worker, "writing new media set label");
-//             drive.write_media_set_label(new_set, key_config.as_ref())?;
-//             media_catalog = MediaCatalog::overwrite(TAPE_STATUS_DIR, media_id, false)?;
-//             true
-//         }
-//         Some(media_set_label) => {
-//             if new_set.uuid == media_set_label.uuid {
-//                 if new_set.seq_nr != media_set_label.seq_nr {
-//                     bail!(
-//                         "got media with wrong media sequence number ({} != {}",
-//                         new_set.seq_nr,
-//                         media_set_label.seq_nr
-//                     );
-//                 }
-//                 if new_set.encryption_key_fingerprint != media_set_label.encryption_key_fingerprint
-//                 {
-//                     bail!("detected changed encryption fingerprint - internal error");
-//                 }
-//                 media_catalog = MediaCatalog::open(TAPE_STATUS_DIR, media_id, true, false)?;
-
-//                 // todo: verify last content/media_catalog somehow?
-
-//                 false
-//             } else {
-//                 task_log!(
-//                     worker,
-//                     "writing new media set label (overwrite '{}/{}')",
-//                     media_set_label.uuid.to_string(),
-//                     media_set_label.seq_nr,
-//                 );
-
-//                 drive.write_media_set_label(new_set, key_config.as_ref())?;
-//                 media_catalog = MediaCatalog::overwrite(TAPE_STATUS_DIR, media_id, false)?;
-//                 true
-//             }
-//         }
-//     };
-
-//     Ok((media_catalog, new_media))
-// }
+        upload_media_set_catalog(&*self.backend, catalog)
+    }
+}
@@ -1,16 +1,22 @@
 use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use anyhow::{format_err, Error};
 
+use pbs_api_types::Fingerprint;
 use pbs_datastore::{DataBlob, DataStore, SnapshotReader};
 
-use crate::tape::CatalogSet;
+use crate::cloud::io_pacing::IoPacer;
+
+use super::CatalogSet;
 
 /// Chunk iterator which use a separate thread to read chunks
 ///
-/// The iterator skips duplicate chunks and chunks already in the
-/// catalog.
+/// The iterator skips duplicate chunks, chunks already in the catalog
+/// (encrypted with the same key) and the chunks of the index files in
+/// `skip_files`. With a `pacer`, the reader waits as long as it
+/// requests after each chunk.
 pub struct NewChunksIterator {
     #[allow(clippy::type_complexity)]
     rx: std::sync::mpsc::Receiver<Result<Option<([u8; 32], DataBlob)>, Error>>,
@@ -24,6 +30,9 @@ impl NewChunksIterator {
         datastore: Arc<DataStore>,
         snapshot_reader: Arc<Mutex<SnapshotReader>>,
         catalog_set: Arc<Mutex<CatalogSet>>,
+        key: Option<Fingerprint>,
+        skip_files: HashSet<String>,
+        pacer: Option<Arc<Mutex<IoPacer>>>,
     ) -> Result<(std::thread::JoinHandle<()>, Self), Error> {
         let (tx, rx) = std::sync::mpsc::sync_channel(3);
 
@@ -32,15 +41,16 @@ impl NewChunksIterator {
 
             let mut chunk_index: HashSet<[u8; 32]> = HashSet::new();
 
-            let datastore_name = snapshot_reader.datastore_name().to_string();
-
             let result: Result<(), Error> = proxmox_lang::try_block!({
                 let mut chunk_iter = snapshot_reader.chunk_iterator(move |digest| {
                     catalog_set
                         .lock()
                         .unwrap()
-                        .contains_chunk(&datastore_name, digest)
+                        .contains_chunk(digest, key.as_ref())
                 })?;
+                for filename in skip_files.iter() {
+                    chunk_iter.skip_file(filename);
+                }
 
                 loop {
                     let digest = match chunk_iter.next() {
@@ -55,7 +65,17 @@ impl NewChunksIterator {
                         continue;
                     }
 
+                    let start = Instant::now();
                     let blob = datastore.load_chunk(&digest)?;
+                    if let Some(ref pacer) = pacer {
+                        let delay = pacer
+                            .lock()
+                            .unwrap()
+                            .record_read(blob.raw_size(), start.elapsed());
+                        if !delay.is_zero() {
+                            std::thread::sleep(delay);
+                        }
+                    }
                     //println!("LOAD CHUNK {}", hex::encode(&digest));
                     match tx.send(Ok(Some((digest, blob)))) {
                         Ok(()) => {}
@@ -0,0 +1,404 @@
+//! Compaction of sparse media sets
+//!
+//! Chunks stay in their chunk archives as long as the media set exists,
+//! even if no snapshot references them anymore. Compaction rewrites the
+//! live chunks of media sets with little live data into new archives of
+//! the same media set, replaces the media set catalog and deletes the old
+//! archives afterwards. Archives which are still mostly live are kept as
+//! they are, archives without live chunks are only deleted.
+//!
+//! Snapshots in the trash whose retention expired are purged first.
+//!
+//! Like backups, this needs the writer lease of the target.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_human_byte::HumanByte;
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{CloudTarget, Fingerprint};
+
+use super::backend::{CloudBackend, PutOptions};
+use super::catalog::{
+    replace_media_set_catalog, ChunkArchiveEntry, ChunkEntry, CloudCatalog, MediaSetCatalog,
+};
+use super::lease::{CloudLease, LEASE_TIMEOUT};
+use super::trash::purge_expired_trash;
+use super::{layout, MAX_CHUNK_ARCHIVE_SIZE};
+
+/// Default for 'compact-when-below' (percent of live data)
+pub const DEFAULT_COMPACT_THRESHOLD: u64 = 40;
+
+/// A chunk on the target (encryption key and digest)
+pub type ChunkId = (Option<Fingerprint>, [u8; 32]);
+
+/// Statistics of a finished compaction
+#[derive(Debug, Default)]
+pub struct CompactionStats {
+    /// Number of compacted media sets
+    pub media_sets: usize,
+    /// Chunk archives written
+    pub archives_written: usize,
+    /// Chunk archives removed
+    pub archives_removed: usize,
+    /// Size of the removed archives
+    pub bytes_removed: u64,
+    /// Size of the written archives
+    pub bytes_written: u64,
+}
+
+/// Chunks referenced by any snapshot of the catalog
+///
+/// Snapshots in the trash keep their chunks alive until they are purged.
+pub fn live_chunks(catalog: &CloudCatalog) -> HashSet<ChunkId> {
+    let entries = catalog.snapshots().map(|(_, entry)| entry).chain(
+        catalog
+            .trashed_snapshots()
+            .map(|(_, trashed)| &trashed.entry),
+    );
+
+    let mut live = HashSet::new();
+    for entry in entries {
+        for digest in entry.chunks.iter() {
+            live.insert((entry.key.clone(), *digest));
+        }
+    }
+    live
+}
+
+fn live_bytes(archive: &ChunkArchiveEntry, live: &HashSet<ChunkId>) -> u64 {
+    archive
+        .chunks
+        .iter()
+        .filter(|chunk| live.contains(&(archive.key.clone(), chunk.digest)))
+        .map(|chunk| chunk.size)
+        .sum()
+}
+
+fn is_below(live: u64, size: u64, threshold: u64) -> bool {
+    live * 100 < size * threshold
+}
+
+/// Percentage of live data in the archives of a media set
+///
+/// Returns `None` for media sets without chunk archives.
+pub fn live_percentage(media_set: &MediaSetCatalog, live: &HashSet<ChunkId>) -> Option<u64> {
+    let size: u64 = media_set.archives.iter().map(|archive| archive.size).sum();
+    if size == 0 {
+        return None;
+    }
+    let live: u64 = media_set
+        .archives
+        .iter()
+        .map(|archive| live_bytes(archive, live))
+        .sum();
+    Some(live * 100 / size)
+}
+
+// chunk archive under construction
+struct ArchiveBuilder {
+    store: String,
+    key: Option<Fingerprint>,
+    data: Vec<u8>,
+    chunks: Vec<ChunkEntry>,
+}
+
+impl ArchiveBuilder {
+    fn new(store: &str, key: &Option<Fingerprint>) -> Self {
+        Self {
+            store: store.to_string(),
+            key: key.clone(),
+            data: Vec::new(),
+            chunks: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, digest: [u8; 32], raw: &[u8]) {
+        self.chunks.push(ChunkEntry {
+            digest,
+            offset: self.data.len() as u64,
+            size: raw.len() as u64,
+        });
+        self.data.extend_from_slice(raw);
+    }
+
+    // upload the archive and return its catalog entry
+    fn finish(
+        self,
+        backend: &dyn CloudBackend,
+        media_set: &Uuid,
+        options: &PutOptions,
+    ) -> Result<ChunkArchiveEntry, Error> {
+        let uuid = Uuid::generate();
+        backend
+            .put_object_with_options(
+                &layout::chunk_archive_key(media_set, &uuid),
+                &self.data,
+                options,
+            )
+            .map_err(|err| format_err!("unable to upload chunk archive - {}", err))?;
+
+        Ok(ChunkArchiveEntry {
+            uuid,
+            store: self.store,
+            key: self.key,
+            size: self.data.len() as u64,
+            csum: Some(openssl::sha::sha256(&self.data)),
+            chunks: self.chunks,
+        })
+    }
+}
+
+// rewrite a single media set, returns the archives to delete
+fn compact_media_set(
+    worker: &dyn WorkerTaskContext,
+    backend: &dyn CloudBackend,
+    lease: &mut CloudLease,
+    media_set: &mut MediaSetCatalog,
+    live: &HashSet<ChunkId>,
+    threshold: u64,
+    options: &PutOptions,
+    stats: &mut CompactionStats,
+) -> Result<Vec<ChunkArchiveEntry>, Error> {
+    let uuid = media_set.uuid().clone();
+
+    let mut kept = Vec::new();
+    let mut obsolete = Vec::new();
+    let mut rewrite: HashMap<(String, Option<Fingerprint>), Vec<ChunkArchiveEntry>> =
+        HashMap::new();
+
+    for archive in media_set.archives.drain(..) {
+        let live_size = live_bytes(&archive, live);
+        if live_size == 0 {
+            obsolete.push(archive);
+        } else if is_below(live_size, archive.size, threshold) {
+            rewrite
+                .entry((archive.store.clone(), archive.key.clone()))
+                .or_default()
+                .push(archive);
+        } else {
+            kept.push(archive);
+        }
+    }
+
+    let mut written: HashSet<ChunkId> = HashSet::new();
+
+    for ((store, key), archives) in rewrite {
+        let mut builder = ArchiveBuilder::new(&store, &key);
+
+        for archive in archives {
+            worker.check_abort()?;
+            lease.heartbeat()?;
+
+            let archive_key = layout::chunk_archive_key(&uuid, &archive.uuid);
+            let data = match backend.get_object(&archive_key) {
+                Ok(data) if data.len() as u64 == archive.size => data,
+                Ok(data) => {
+                    task_warn!(
+                        worker,
+                        "chunk archive {} has wrong size ({} != {}), keeping it",
+                        archive.uuid,
+                        data.len(),
+                        archive.size
+                    );
+                    kept.push(archive);
+                    continue;
+                }
+                Err(err) => {
+                    task_warn!(
+                        worker,
+                        "unable to read chunk archive {} - {}, keeping it",
+                        archive.uuid,
+                        err
+                    );
+                    kept.push(archive);
+                    continue;
+                }
+            };
+
+            for chunk in archive.chunks.iter() {
+                let id = (key.clone(), chunk.digest);
+                if !live.contains(&id) || written.contains(&id) {
+                    continue;
+                }
+                let start = chunk.offset as usize;
+                let raw = match data.get(start..start + chunk.size as usize) {
+                    Some(raw) => raw,
+                    None => bail!(
+                        "chunk {} outside of chunk archive {}",
+                        hex::encode(chunk.digest),
+                        archive.uuid
+                    ),
+                };
+
+                if !builder.chunks.is_empty()
+                    && builder.data.len() + raw.len() > MAX_CHUNK_ARCHIVE_SIZE
+                {
+                    lease.heartbeat()?;
+                    let new_archive = builder.finish(backend, &uuid, options)?;
+                    stats.archives_written += 1;
+                    stats.bytes_written += new_archive.size;
+                    kept.push(new_archive);
+                    builder = ArchiveBuilder::new(&store, &key);
+                }
+                builder.push(chunk.digest, raw);
+                written.insert(id);
+            }
+
+            obsolete.push(archive);
+        }
+
+        if !builder.chunks.is_empty() {
+            lease.heartbeat()?;
+            let new_archive = builder.finish(backend, &uuid, options)?;
+            stats.archives_written += 1;
+            stats.bytes_written += new_archive.size;
+            kept.push(new_archive);
+        }
+    }
+
+    media_set.archives = kept;
+
+    Ok(obsolete)
+}
+
+/// Compact all media sets of a target with less than `threshold` percent
+/// live data
+///
+/// New archives are uploaded first, then the catalog is replaced, and the
+/// old archives are deleted last. An interrupted compaction leaves
+/// unreferenced archives on the target at worst, never a catalog
+/// referencing missing archives.
+pub fn compact_media_sets<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+    threshold: u64,
+) -> Result<CompactionStats, Error> {
+    let base_path = base_path.as_ref();
+
+    let purged = purge_expired_trash(worker, base_path, target, backend)?;
+    if purged > 0 {
+        task_log!(worker, "purged {} expired snapshots from the trash", purged);
+    }
+
+    let catalog = CloudCatalog::load(base_path, &target.name)?;
+    let live = live_chunks(&catalog);
+
+    let mut stats = CompactionStats::default();
+
+    let candidates: Vec<&MediaSetCatalog> = catalog
+        .media_sets()
+        .iter()
+        .filter(|media_set| !media_set.is_imported())
+        .filter(|media_set| match live_percentage(media_set, &live) {
+            Some(percentage) => percentage < threshold,
+            None => false,
+        })
+        .collect();
+
+    if candidates.is_empty() {
+        task_log!(
+            worker,
+            "no media sets with less than {}% live data",
+            threshold
+        );
+        return Ok(stats);
+    }
+
+    let put_options = PutOptions::default().with_object_tags(target, None)?;
+
+    let mut lease =
+        CloudLease::acquire(Arc::clone(backend), proxmox_sys::nodename(), LEASE_TIMEOUT)?;
+
+    for media_set in candidates {
+        worker.check_abort()?;
+        worker.fail_on_shutdown()?;
+
+        task_log!(
+            worker,
+            "compact media set {} ({}% live data)",
+            media_set.uuid(),
+            live_percentage(media_set, &live).unwrap_or(0)
+        );
+
+        let mut media_set = media_set.clone();
+        let obsolete = compact_media_set(
+            worker,
+            &**backend,
+            &mut lease,
+            &mut media_set,
+            &live,
+            threshold,
+            &put_options,
+            &mut stats,
+        )?;
+
+        if obsolete.is_empty() {
+            continue;
+        }
+
+        // parity objects only cover their original group of archives
+        let (parity, stale_parity): (Vec<_>, Vec<_>) =
+            media_set.parity.drain(..).partition(|parity| {
+                !parity
+                    .archives
+                    .iter()
+                    .any(|uuid| obsolete.iter().any(|archive| &archive.uuid == uuid))
+            });
+        media_set.parity = parity;
+
+        lease.heartbeat()?;
+        replace_media_set_catalog(&**backend, &media_set)?;
+        media_set.save(base_path, &target.name)?;
+
+        for archive in obsolete {
+            let key = layout::chunk_archive_key(media_set.uuid(), &archive.uuid);
+            if let Err(err) = backend.delete_object(&key) {
+                task_warn!(
+                    worker,
+                    "unable to delete chunk archive {} - {}",
+                    archive.uuid,
+                    err
+                );
+                continue;
+            }
+            stats.archives_removed += 1;
+            stats.bytes_removed += archive.size;
+        }
+
+        for parity in stale_parity {
+            let key = layout::parity_key(media_set.uuid(), &parity.uuid);
+            if let Err(err) = backend.delete_object(&key) {
+                task_warn!(
+                    worker,
+                    "unable to delete parity object {} - {}",
+                    parity.uuid,
+                    err
+                );
+            }
+        }
+
+        stats.media_sets += 1;
+    }
+
+    lease.release()?;
+
+    task_log!(
+        worker,
+        "compacted {} media sets ({} archives / {} removed, {} archives / {} written)",
+        stats.media_sets,
+        stats.archives_removed,
+        HumanByte::from(stats.bytes_removed),
+        stats.archives_written,
+        HumanByte::from(stats.bytes_written),
+    );
+
+    Ok(stats)
+}
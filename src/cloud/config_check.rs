@@ -0,0 +1,377 @@
+//! Consistency check of the whole cloud configuration
+//!
+//! Every config entry is checked when it is changed, but the references
+//! between them (jobs to targets, targets and mappings to keys) can break
+//! later, and some problems only show up when a job runs. The check goes
+//! over the whole configuration graph at once:
+//!
+//! - jobs and mappings referencing targets which do not exist
+//! - targets which are not reachable (by their recorded health checks)
+//! - keys referenced by targets, mappings or media sets which are not in
+//!   the key store (the data cannot be restored without them)
+//! - schedules of enabled jobs which never fire again
+//! - targets storing their objects below the same prefix, which would
+//!   mix up their media sets
+
+use std::collections::HashSet;
+
+use anyhow::Error;
+use serde_json::Value;
+
+use proxmox_section_config::SectionConfigData;
+use proxmox_time::CalendarEvent;
+
+use pbs_api_types::{
+    CloudBackupJobConfig, CloudConfigIssue, CloudConfigIssueKind, CloudHealthSample, CloudMapping,
+    CloudProvider, CloudPruneJobConfig, CloudReplicationJobConfig, CloudTarget, CloudTargetConfig,
+    CloudTargetHealth, CloudVerificationJobConfig, Fingerprint,
+};
+
+use super::backend::open_backend;
+use super::catalog::CloudCatalog;
+use super::encryption_keys::load_key_configs;
+use super::health::{
+    check_target_health, health_check_enabled, load_health_history, target_health,
+};
+use super::mapping::select_job_mapping;
+use super::CLOUD_STATUS_DIR;
+
+fn issue(kind: CloudConfigIssueKind, entity: String, message: String) -> CloudConfigIssue {
+    CloudConfigIssue {
+        kind,
+        entity,
+        message,
+    }
+}
+
+/// Jobs and mappings referencing targets which do not exist
+///
+/// Backup jobs without target of their own need a mapping.
+pub fn check_job_targets(
+    jobs: &SectionConfigData,
+    mappings: &[CloudMapping],
+    targets: &HashSet<String>,
+) -> Result<Vec<CloudConfigIssue>, Error> {
+    let mut list = Vec::new();
+
+    let missing = |entity: String, target: &str| {
+        issue(
+            CloudConfigIssueKind::MissingTarget,
+            entity,
+            format!("cloud target '{}' does not exist", target),
+        )
+    };
+
+    let backup_jobs: Vec<CloudBackupJobConfig> = jobs.convert_to_typed_array("backup")?;
+    for job in backup_jobs {
+        let entity = format!("backup/{}", job.id);
+        match job.setup.target {
+            Some(ref target) if !targets.contains(target) => list.push(missing(entity, target)),
+            Some(_) => (),
+            None => {
+                if select_job_mapping(mappings, &job.setup).is_none() {
+                    list.push(issue(
+                        CloudConfigIssueKind::MissingTarget,
+                        entity,
+                        format!(
+                            "no cloud target configured and no cloud mapping for datastore '{}'",
+                            job.setup.store
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+
+    let replication_jobs: Vec<CloudReplicationJobConfig> =
+        jobs.convert_to_typed_array("replication")?;
+    for job in replication_jobs {
+        for target in [&job.source, &job.target] {
+            if !targets.contains(target) {
+                list.push(missing(format!("replication/{}", job.id), target));
+            }
+        }
+    }
+
+    let verify_jobs: Vec<CloudVerificationJobConfig> = jobs.convert_to_typed_array("verify")?;
+    for job in verify_jobs {
+        if !targets.contains(&job.target) {
+            list.push(missing(format!("verify/{}", job.id), &job.target));
+        }
+    }
+
+    let prune_jobs: Vec<CloudPruneJobConfig> = jobs.convert_to_typed_array("prune")?;
+    for job in prune_jobs {
+        if !targets.contains(&job.target) {
+            list.push(missing(format!("prune/{}", job.id), &job.target));
+        }
+    }
+
+    for mapping in mappings {
+        if !targets.contains(&mapping.target) {
+            list.push(missing(format!("mapping/{}", mapping.id), &mapping.target));
+        }
+    }
+
+    Ok(list)
+}
+
+/// Report a target whose last health check failed
+pub fn check_target_reachable(health: &CloudTargetHealth) -> Option<CloudConfigIssue> {
+    if health.last_check.is_none() || health.reachable {
+        return None;
+    }
+    Some(issue(
+        CloudConfigIssueKind::UnreachableTarget,
+        format!("target/{}", health.target),
+        match health.last_error {
+            Some(ref err) => format!("target is not reachable - {}", err),
+            None => "target is not reachable".to_string(),
+        },
+    ))
+}
+
+/// Keys used by the media sets of a catalog, per media set UUID
+pub fn media_set_keys(catalog: &CloudCatalog) -> Vec<(String, Vec<Fingerprint>)> {
+    catalog
+        .media_sets()
+        .iter()
+        .map(|media_set| {
+            let mut keys = Vec::new();
+            for key in media_set
+                .snapshots
+                .iter()
+                .filter_map(|entry| entry.key.as_ref())
+            {
+                if !keys.contains(key) {
+                    keys.push(key.clone());
+                }
+            }
+            (media_set.uuid().to_string(), keys)
+        })
+        .collect()
+}
+
+/// Keys referenced by targets, mappings and media sets which are not in
+/// the key store
+///
+/// `media_sets` lists the keys of each media set by target name and
+/// media set UUID.
+pub fn check_keys(
+    targets: &[CloudTarget],
+    mappings: &[CloudMapping],
+    media_sets: &[(String, String, Vec<Fingerprint>)],
+    known: &HashSet<Fingerprint>,
+) -> Result<Vec<CloudConfigIssue>, Error> {
+    let mut list = Vec::new();
+
+    let missing = |entity: String, what: String, key: &Fingerprint| {
+        issue(
+            CloudConfigIssueKind::MissingKey,
+            entity,
+            format!("{} uses key {}, which is not in the key store", what, key),
+        )
+    };
+
+    for target in targets {
+        for entry in target.config.namespace_keys()? {
+            let key: Fingerprint = entry.key.parse()?;
+            if !known.contains(&key) {
+                let what = if entry.ns.is_root() {
+                    "target".to_string()
+                } else {
+                    format!("namespace '{}'", entry.ns)
+                };
+                list.push(missing(format!("target/{}", target.name), what, &key));
+            }
+        }
+    }
+
+    for mapping in mappings {
+        if let Some(ref key) = mapping.key {
+            let key: Fingerprint = key.parse()?;
+            if !known.contains(&key) {
+                list.push(missing(
+                    format!("mapping/{}", mapping.id),
+                    "mapping".to_string(),
+                    &key,
+                ));
+            }
+        }
+    }
+
+    for (target, uuid, keys) in media_sets {
+        for key in keys.iter().filter(|key| !known.contains(key)) {
+            list.push(missing(
+                format!("target/{}", target),
+                format!("media set {}", uuid),
+                key,
+            ));
+        }
+    }
+
+    Ok(list)
+}
+
+/// Schedules of enabled jobs (and templates) without events after `now`
+///
+/// Invalid schedules are reported as well, they never fire either.
+pub fn check_schedules(jobs: &SectionConfigData, now: i64) -> Vec<CloudConfigIssue> {
+    let mut list = Vec::new();
+
+    let mut ids: Vec<&String> = jobs.sections.keys().collect();
+    ids.sort();
+
+    for id in ids {
+        let (section_type, data) = &jobs.sections[id];
+        if data["disable"].as_bool().unwrap_or(false) {
+            continue;
+        }
+        let schedule = match data["schedule"] {
+            Value::String(ref schedule) => schedule,
+            _ => continue,
+        };
+        let message = match schedule.parse::<CalendarEvent>() {
+            Ok(event) => match event.compute_next_event(now) {
+                Ok(Some(_)) => continue,
+                Ok(None) => format!("schedule '{}' never fires again", schedule),
+                Err(err) => format!("schedule '{}' cannot be evaluated - {}", schedule, err),
+            },
+            Err(err) => format!("invalid schedule '{}' - {}", schedule, err),
+        };
+        list.push(issue(
+            CloudConfigIssueKind::DeadSchedule,
+            format!("{}/{}", section_type, id),
+            message,
+        ));
+    }
+
+    list
+}
+
+// where a target stores its objects (without prefix), `None` if unknown
+fn storage_location(config: &CloudTargetConfig) -> Option<String> {
+    match config.provider {
+        CloudProvider::S3 => {
+            let bucket = config.bucket.as_ref()?;
+            let endpoint = config.endpoint.as_deref().unwrap_or("s3.amazonaws.com");
+            Some(format!(
+                "s3://{}/{}",
+                endpoint.trim_end_matches('/').to_lowercase(),
+                bucket
+            ))
+        }
+        CloudProvider::Local => config
+            .path
+            .as_ref()
+            .map(|path| format!("file://{}", path.trim_end_matches('/'))),
+    }
+}
+
+// prefixes are joined with '/', so one prefix contains the objects of
+// the other if it is a parent path of it (or no prefix at all)
+fn prefixes_overlap(a: Option<&str>, b: Option<&str>) -> bool {
+    let a = a.unwrap_or_default().trim_matches('/');
+    let b = b.unwrap_or_default().trim_matches('/');
+    let contains = |parent: &str, child: &str| {
+        parent.is_empty()
+            || child == parent
+            || (child.starts_with(parent) && child[parent.len()..].starts_with('/'))
+    };
+    contains(a, b) || contains(b, a)
+}
+
+/// Targets storing their objects below the same prefix of the same bucket
+/// (or directory)
+pub fn check_prefix_overlap(targets: &[CloudTarget]) -> Vec<CloudConfigIssue> {
+    let mut list = Vec::new();
+
+    for (i, target) in targets.iter().enumerate() {
+        let location = match storage_location(&target.config) {
+            Some(location) => location,
+            None => continue,
+        };
+        for other in targets[i + 1..].iter() {
+            if storage_location(&other.config).as_ref() != Some(&location) {
+                continue;
+            }
+            if prefixes_overlap(
+                target.config.prefix.as_deref(),
+                other.config.prefix.as_deref(),
+            ) {
+                list.push(issue(
+                    CloudConfigIssueKind::OverlappingPrefix,
+                    format!("target/{}", target.name),
+                    format!(
+                        "objects overlap with target '{}' in {} (prefix {} and {})",
+                        other.name,
+                        location,
+                        prefix_text(&target.config),
+                        prefix_text(&other.config),
+                    ),
+                ));
+            }
+        }
+    }
+
+    list
+}
+
+fn prefix_text(config: &CloudTargetConfig) -> String {
+    match config.prefix {
+        Some(ref prefix) => format!("'{}'", prefix),
+        None => "none".to_string(),
+    }
+}
+
+/// Check the whole cloud configuration
+///
+/// Without `probe`, the reachability of targets is taken from their
+/// recorded health checks (targets without health checks are skipped),
+/// with `probe` every target is checked with a request.
+pub fn check_cloud_config(probe: bool) -> Result<Vec<CloudConfigIssue>, Error> {
+    let (target_config, _digest) = pbs_config::cloud::config()?;
+    let targets: Vec<CloudTarget> = target_config.convert_to_typed_array("target")?;
+    let target_names: HashSet<String> = targets.iter().map(|target| target.name.clone()).collect();
+
+    let (jobs, _digest) = pbs_config::cloud_job::config()?;
+    let mappings = pbs_config::cloud_mapping::mapping_list()?;
+
+    let (key_map, _digest) = load_key_configs()?;
+    let known: HashSet<Fingerprint> = key_map.into_keys().collect();
+
+    let mut list = check_job_targets(&jobs, &mappings, &target_names)?;
+
+    let mut media_sets = Vec::new();
+    for target in targets.iter() {
+        let history = if probe {
+            vec![match open_backend(target) {
+                Ok(backend) => check_target_health(&*backend),
+                Err(err) => CloudHealthSample {
+                    time: proxmox_time::epoch_i64(),
+                    latency: None,
+                    error: Some(err.to_string()),
+                },
+            }]
+        } else if health_check_enabled(target) {
+            load_health_history(CLOUD_STATUS_DIR, &target.name)?
+        } else {
+            Vec::new()
+        };
+        list.extend(check_target_reachable(&target_health(
+            &target.name,
+            history,
+        )));
+
+        let catalog = CloudCatalog::load(CLOUD_STATUS_DIR, &target.name)?;
+        for (uuid, keys) in media_set_keys(&catalog) {
+            media_sets.push((target.name.clone(), uuid, keys));
+        }
+    }
+
+    list.extend(check_keys(&targets, &mappings, &media_sets, &known)?);
+    list.extend(check_schedules(&jobs, proxmox_time::epoch_i64()));
+    list.extend(check_prefix_overlap(&targets));
+
+    Ok(list)
+}
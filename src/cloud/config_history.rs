@@ -0,0 +1,189 @@
+//! Change history of the cloud configuration
+//!
+//! Every change of a cloud target, job or job template done through the
+//! API is recorded with the user, the time and the changed properties.
+//! Roles granted or revoked on OpenID login (see [`super::openid_roles`])
+//! are recorded as `acl` changes of the logged in user.
+//! Entries older than the configured number of days (node option
+//! `cloud-config-history-days`) are removed when new changes are
+//! recorded.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Error};
+use serde::Serialize;
+use serde_json::Value;
+
+use proxmox_section_config::SectionConfigData;
+use proxmox_sys::fs::{create_path, open_file_locked, replace_file};
+
+use pbs_api_types::{Authid, CloudConfigAction, CloudConfigChange, CloudConfigPropertyChange};
+
+use super::create_options;
+use super::CLOUD_STATUS_DIR;
+
+/// Default number of days to keep history entries
+pub const DEFAULT_HISTORY_DAYS: usize = 365;
+
+/// Values of these properties are never recorded
+const SECRET_PROPERTIES: &[&str] = &["secret-key"];
+
+fn history_path(base_path: &Path) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("config-history.json");
+    path
+}
+
+fn read_history(path: &Path) -> Result<Vec<CloudConfigChange>, Error> {
+    match proxmox_sys::fs::file_get_optional_contents(path)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// All recorded changes, oldest first
+pub fn load_history<P: AsRef<Path>>(base_path: P) -> Result<Vec<CloudConfigChange>, Error> {
+    read_history(&history_path(base_path.as_ref()))
+}
+
+fn format_value(property: &str, value: &Value) -> String {
+    if SECRET_PROPERTIES.contains(&property) {
+        return "<redacted>".to_string();
+    }
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Compare two section config entries property by property
+///
+/// `None` stands for a non-existent object.
+pub fn property_changes(
+    old: Option<&Value>,
+    new: Option<&Value>,
+) -> Vec<CloudConfigPropertyChange> {
+    let old = old.and_then(Value::as_object);
+    let new = new.and_then(Value::as_object);
+
+    let properties: BTreeSet<&String> = old
+        .iter()
+        .chain(new.iter())
+        .flat_map(|map| map.keys())
+        .collect();
+
+    let mut list = Vec::new();
+    for property in properties {
+        let old_value = old.and_then(|map| map.get(property));
+        let new_value = new.and_then(|map| map.get(property));
+        if old_value == new_value {
+            continue;
+        }
+        list.push(CloudConfigPropertyChange {
+            property: property.clone(),
+            old: old_value.map(|value| format_value(property, value)),
+            new: new_value.map(|value| format_value(property, value)),
+        });
+    }
+    list
+}
+
+/// Add a change to the history and remove entries older than `max_days`
+pub fn record_change<P: AsRef<Path>>(
+    base_path: P,
+    change: CloudConfigChange,
+    max_days: usize,
+) -> Result<(), Error> {
+    let path = history_path(base_path.as_ref());
+
+    create_path(
+        base_path.as_ref(),
+        Some(create_options(0o0750)?),
+        Some(create_options(0o0750)?),
+    )?;
+    let mut lock_path = path.clone();
+    lock_path.set_extension("lck");
+    let timeout = std::time::Duration::new(10, 0);
+    let _lock = open_file_locked(&lock_path, timeout, true, create_options(0o0640)?)?;
+
+    let cutoff = change.time - (max_days as i64) * 24 * 3600;
+
+    let mut list = read_history(&path)?;
+    list.retain(|entry| entry.time >= cutoff);
+    list.push(change);
+
+    replace_file(
+        &path,
+        &serde_json::to_vec(&list)?,
+        create_options(0o0640)?,
+        true,
+    )
+}
+
+/// Current data of a section, for use with [`record_config_change`]
+pub fn section_data(config: &SectionConfigData, id: &str) -> Option<Value> {
+    config.sections.get(id).map(|(_, data)| data.clone())
+}
+
+/// Set section `id` of `config` to `data` and save the config with `save`
+///
+/// With `validate` neither `config` nor the config file are changed.
+/// Returns whether the config was saved.
+pub fn save_section<T: Serialize>(
+    config: &mut SectionConfigData,
+    section_type: &str,
+    id: &str,
+    data: &T,
+    validate: bool,
+    save: impl FnOnce(&SectionConfigData) -> Result<(), Error>,
+) -> Result<bool, Error> {
+    if validate {
+        return Ok(false);
+    }
+    config.set_data(id, section_type, data)?;
+    save(config)?;
+    Ok(true)
+}
+
+/// Record a change done through the API
+///
+/// `old` and `new` are the section data before and after the change.
+/// Failures are logged, the change itself was already saved.
+pub fn record_config_change(
+    auth_id: &Authid,
+    config_type: &str,
+    id: &str,
+    old: Option<&Value>,
+    new: Option<&Value>,
+) {
+    let action = match (old, new) {
+        (None, _) => CloudConfigAction::Create,
+        (Some(_), Some(_)) => CloudConfigAction::Update,
+        (Some(_), None) => CloudConfigAction::Delete,
+    };
+
+    let changes = property_changes(old, new);
+    if action == CloudConfigAction::Update && changes.is_empty() {
+        return;
+    }
+
+    let max_days = crate::config::node::config()
+        .ok()
+        .and_then(|(config, _)| config.cloud_config_history_days)
+        .unwrap_or(DEFAULT_HISTORY_DAYS);
+
+    let change = CloudConfigChange {
+        time: proxmox_time::epoch_i64(),
+        user: auth_id.clone(),
+        config_type: config_type.to_string(),
+        id: id.to_string(),
+        action,
+        changes,
+    };
+
+    if let Err(err) = record_change(CLOUD_STATUS_DIR, change, max_days) {
+        log::error!("unable to record cloud config change - {}", err);
+    }
+}
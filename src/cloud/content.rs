@@ -0,0 +1,231 @@
+//! Content of cloud targets
+//!
+//! Lists the namespaces, groups and snapshots stored on a target, based
+//! on the local copy of its catalog. A snapshot contained in several
+//! media sets is listed once, with the newest media set containing it.
+
+use std::collections::BTreeMap;
+
+use pbs_api_types::{
+    BackupDir, BackupGroup, BackupNamespace, BackupType, CloudGroupListItem,
+    CloudNamespaceListItem, CloudSnapshotCopy, CloudSnapshotListItem, CloudVerifyStatus,
+    MAX_NAMESPACE_DEPTH,
+};
+
+use super::catalog::{CloudCatalog, MediaSetCatalog, SnapshotEntry};
+use super::snapshot_summary::catalog_chunk_size;
+use super::verify::verify_status;
+
+/// Filter for content listings, unset fields match everything
+#[derive(Clone, Debug, Default)]
+pub struct CloudContentFilter {
+    pub store: Option<String>,
+    pub ns: Option<BackupNamespace>,
+    /// Also match the namespaces below `ns`, up to this depth (only `ns`
+    /// itself if unset)
+    pub max_depth: Option<usize>,
+    pub backup_type: Option<BackupType>,
+    pub backup_id: Option<String>,
+}
+
+impl CloudContentFilter {
+    /// Snapshots of `store` in `ns` (the root if unset) and the namespaces
+    /// up to `max_depth` levels below it (all if unset), like the
+    /// datastore jobs select them
+    pub fn recursive(store: &str, ns: Option<&BackupNamespace>, max_depth: Option<usize>) -> Self {
+        Self {
+            store: Some(store.to_string()),
+            ns: Some(ns.cloned().unwrap_or_default()),
+            max_depth: Some(max_depth.unwrap_or(MAX_NAMESPACE_DEPTH)),
+            ..Default::default()
+        }
+    }
+
+    pub fn matches(&self, entry: &SnapshotEntry) -> bool {
+        if let Some(ref store) = self.store {
+            if &entry.store != store {
+                return false;
+            }
+        }
+        if let Some(ref ns) = self.ns {
+            match ns.contains(&entry.ns) {
+                Some(depth) if depth <= self.max_depth.unwrap_or(0) => (),
+                _ => return false,
+            }
+        }
+        if let Some(backup_type) = self.backup_type {
+            if entry.snapshot.ty() != backup_type {
+                return false;
+            }
+        }
+        if let Some(ref backup_id) = self.backup_id {
+            if entry.snapshot.id() != backup_id {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Snapshots of the catalog matching `filter`, newest media set only
+///
+/// Sorted by store, namespace, group and backup time.
+pub fn latest_snapshots<'a>(
+    catalog: &'a CloudCatalog,
+    filter: &CloudContentFilter,
+) -> Vec<(&'a MediaSetCatalog, &'a SnapshotEntry)> {
+    // media sets are ordered by ctime, so later entries win
+    let mut latest = BTreeMap::new();
+    for (media_set, entry) in catalog.snapshots() {
+        if filter.matches(entry) {
+            latest.insert(
+                (&entry.store, &entry.ns, &entry.snapshot),
+                (media_set, entry),
+            );
+        }
+    }
+    latest.into_values().collect()
+}
+
+/// List the snapshots of a target
+///
+/// Verifications older than `outdated_after` days are reported as outdated.
+pub fn list_snapshots(
+    catalog: &CloudCatalog,
+    filter: &CloudContentFilter,
+    outdated_after: Option<i64>,
+) -> Vec<CloudSnapshotListItem> {
+    let now = proxmox_time::epoch_i64();
+    latest_snapshots(catalog, filter)
+        .into_iter()
+        .map(|(media_set, entry)| {
+            let files_size: u64 = entry.files.iter().map(|file| file.size).sum();
+            let verify_status = entry
+                .cloud_verification
+                .as_ref()
+                .map(|state| verify_status(state, outdated_after, now));
+            CloudSnapshotListItem {
+                store: entry.store.clone(),
+                ns: entry.ns.clone(),
+                backup: entry.snapshot.clone(),
+                media_set: media_set.uuid().to_string(),
+                ctime: media_set.label.ctime,
+                size: catalog_chunk_size(catalog, entry).map(|size| size + files_size),
+                verification: entry.verification.clone(),
+                cloud_verification: entry.cloud_verification.clone(),
+                verify_status,
+                fingerprint: entry.fingerprint.clone(),
+                encrypted: entry.key.is_some(),
+            }
+        })
+        .collect()
+}
+
+/// List the backup groups of a target
+///
+/// The verification status of a group is the worst status of its
+/// snapshots, see [`list_snapshots`] for `outdated_after`.
+pub fn list_groups(
+    catalog: &CloudCatalog,
+    filter: &CloudContentFilter,
+    outdated_after: Option<i64>,
+) -> Vec<CloudGroupListItem> {
+    // with a flag for unverified snapshots
+    let mut groups: BTreeMap<
+        (&String, &BackupNamespace, &BackupGroup),
+        (CloudGroupListItem, bool),
+    > = BTreeMap::new();
+
+    let now = proxmox_time::epoch_i64();
+    for (_media_set, entry) in latest_snapshots(catalog, filter) {
+        let time = entry.snapshot.time;
+        let status = entry
+            .cloud_verification
+            .as_ref()
+            .map(|state| verify_status(state, outdated_after, now));
+        let (item, unverified) = groups
+            .entry((&entry.store, &entry.ns, &entry.snapshot.group))
+            .or_insert_with(|| {
+                let item = CloudGroupListItem {
+                    store: entry.store.clone(),
+                    ns: entry.ns.clone(),
+                    backup: entry.snapshot.group.clone(),
+                    last_backup: time,
+                    backup_count: 0,
+                    verify_status: None,
+                };
+                (item, false)
+            });
+        item.last_backup = item.last_backup.max(time);
+        item.backup_count += 1;
+        item.verify_status = item.verify_status.max(status);
+        *unverified |= status.is_none();
+    }
+
+    groups
+        .into_values()
+        .map(|(mut item, unverified)| {
+            if unverified && item.verify_status == Some(CloudVerifyStatus::Ok) {
+                item.verify_status = Some(CloudVerifyStatus::Outdated);
+            }
+            item
+        })
+        .collect()
+}
+
+/// List the namespaces of a target
+///
+/// Only namespaces containing snapshots are listed.
+pub fn list_namespaces(
+    catalog: &CloudCatalog,
+    filter: &CloudContentFilter,
+) -> Vec<CloudNamespaceListItem> {
+    let mut namespaces: BTreeMap<(String, BackupNamespace), CloudNamespaceListItem> =
+        BTreeMap::new();
+
+    for group in list_groups(catalog, filter, None) {
+        let item = namespaces
+            .entry((group.store.clone(), group.ns.clone()))
+            .or_insert_with(|| CloudNamespaceListItem {
+                store: group.store,
+                ns: group.ns,
+                group_count: 0,
+                snapshot_count: 0,
+            });
+        item.group_count += 1;
+        item.snapshot_count += group.backup_count;
+    }
+
+    namespaces.into_values().collect()
+}
+
+/// Copies of the snapshots of a datastore namespace on cloud targets
+///
+/// `catalogs` are `(target name, catalog)` pairs. Lists the newest copy
+/// per target for each snapshot, in the order of `catalogs`.
+pub fn snapshot_cloud_copies(
+    catalogs: &[(String, CloudCatalog)],
+    store: &str,
+    ns: &BackupNamespace,
+) -> BTreeMap<BackupDir, Vec<CloudSnapshotCopy>> {
+    let filter = CloudContentFilter {
+        store: Some(store.to_string()),
+        ns: Some(ns.clone()),
+        ..Default::default()
+    };
+
+    let mut copies: BTreeMap<BackupDir, Vec<CloudSnapshotCopy>> = BTreeMap::new();
+    for (target, catalog) in catalogs {
+        for (media_set, entry) in latest_snapshots(catalog, &filter) {
+            copies
+                .entry(entry.snapshot.clone())
+                .or_default()
+                .push(CloudSnapshotCopy {
+                    target: target.clone(),
+                    upload_time: media_set.label.ctime,
+                    verification: entry.verification.clone(),
+                });
+        }
+    }
+    copies
+}
@@ -0,0 +1,204 @@
+//! Per-group deduplication statistics
+//!
+//! Backup jobs count, for each backup group, the logical size of all
+//! chunks referenced by the written snapshots, the part of it the target
+//! already held, and the bytes actually uploaded for new chunks. Groups
+//! which defeat deduplication (e.g. data encrypted at the source) show up
+//! with uploaded bytes close to their logical size.
+//!
+//! The statistics of the last run of each group are kept locally per
+//! target and sent to the configured metric servers.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_human_byte::HumanByte;
+use proxmox_sys::fs::{create_path, replace_file};
+use proxmox_sys::task_log;
+use proxmox_sys::WorkerTaskContext;
+
+use pbs_api_types::{BackupGroup, BackupNamespace};
+
+use super::create_options;
+
+/// Deduplication statistics of a snapshot or backup group
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DedupStats {
+    /// Number of snapshots written
+    pub snapshots: u64,
+    /// Plain size of all chunk references
+    pub logical_bytes: u64,
+    /// Plain size of the chunk references the target already held
+    pub present_bytes: u64,
+    /// Stored size of the uploaded chunks
+    pub uploaded_bytes: u64,
+}
+
+impl DedupStats {
+    pub fn add(&mut self, other: &DedupStats) {
+        self.snapshots += other.snapshots;
+        self.logical_bytes += other.logical_bytes;
+        self.present_bytes += other.present_bytes;
+        self.uploaded_bytes += other.uploaded_bytes;
+    }
+}
+
+/// Deduplication statistics of a backup group
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GroupDedupStats {
+    /// Datastore, namespace and group (`store:ns/type/id`)
+    pub group: String,
+    #[serde(flatten)]
+    pub stats: DedupStats,
+}
+
+/// Name of a group in the statistics (`store:ns/type/id`)
+pub fn group_stats_name(store: &str, ns: &BackupNamespace, group: &BackupGroup) -> String {
+    if ns.is_root() {
+        format!("{}:{}", store, group)
+    } else {
+        format!("{}:{}/{}", store, ns.display_as_path(), group)
+    }
+}
+
+/// Add the statistics of a snapshot of `group`
+///
+/// Snapshots of a group are written one after the other, so the
+/// statistics are merged into the last entry if it belongs to `group`.
+pub fn add_group_stats(list: &mut Vec<GroupDedupStats>, group: &str, stats: &DedupStats) {
+    match list.last_mut() {
+        Some(last) if last.group == group => last.stats.add(stats),
+        _ => list.push(GroupDedupStats {
+            group: group.to_string(),
+            stats: stats.clone(),
+        }),
+    }
+}
+
+/// Log the statistics of all groups
+pub fn log_group_stats(worker: &dyn WorkerTaskContext, list: &[GroupDedupStats]) {
+    if list.is_empty() {
+        return;
+    }
+
+    task_log!(
+        worker,
+        "deduplication per group (logical / already present / uploaded):"
+    );
+    for entry in list.iter() {
+        let stats = &entry.stats;
+        let present = if stats.logical_bytes > 0 {
+            format!(
+                " ({:.1}%)",
+                stats.present_bytes as f64 * 100.0 / stats.logical_bytes as f64
+            )
+        } else {
+            String::new()
+        };
+        task_log!(
+            worker,
+            "{}: {} / {}{} / {}",
+            entry.group,
+            HumanByte::from(stats.logical_bytes),
+            HumanByte::from(stats.present_bytes),
+            present,
+            HumanByte::from(stats.uploaded_bytes),
+        );
+    }
+}
+
+/// Statistics of the last run of each group written to a target
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TargetDedupStats {
+    /// Time of the last update
+    pub updated: i64,
+    pub groups: Vec<GroupDedupStats>,
+}
+
+fn dedup_stats_path(base_path: &Path, target: &str) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("dedup-stats");
+    path.push(format!("{}.json", target));
+    path
+}
+
+/// Load the statistics of a target
+pub fn load_dedup_stats<P: AsRef<Path>>(
+    base_path: P,
+    target: &str,
+) -> Result<TargetDedupStats, Error> {
+    let path = dedup_stats_path(base_path.as_ref(), target);
+    match proxmox_sys::fs::file_get_optional_contents(&path)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        None => Ok(TargetDedupStats::default()),
+    }
+}
+
+/// Store the statistics of a job run
+///
+/// Replaces the statistics of the groups written by the run, and keeps
+/// those of all other groups.
+pub fn update_dedup_stats<P: AsRef<Path>>(
+    base_path: P,
+    target: &str,
+    groups: &[GroupDedupStats],
+) -> Result<(), Error> {
+    let base_path = base_path.as_ref();
+
+    let mut stats = load_dedup_stats(base_path, target)?;
+    stats
+        .groups
+        .retain(|entry| !groups.iter().any(|group| group.group == entry.group));
+    stats.groups.extend(groups.iter().cloned());
+    stats.groups.sort_by(|a, b| a.group.cmp(&b.group));
+    stats.updated = proxmox_time::epoch_i64();
+
+    let path = dedup_stats_path(base_path, target);
+    if let Some(parent) = path.parent() {
+        create_path(
+            parent,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+    }
+    let data = serde_json::to_vec(&stats)?;
+    replace_file(path, &data, create_options(0o0640)?, true)
+}
+
+/// Statistics of all targets, as `(target, stats)`
+pub fn list_dedup_stats<P: AsRef<Path>>(
+    base_path: P,
+) -> Result<Vec<(String, TargetDedupStats)>, Error> {
+    let base_path = base_path.as_ref();
+
+    let mut path = base_path.to_owned();
+    path.push("dedup-stats");
+
+    let mut list = Vec::new();
+    let dir = match std::fs::read_dir(&path) {
+        Ok(dir) => dir,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(list),
+        Err(err) => return Err(format_err!("unable to read {:?} - {}", path, err)),
+    };
+    for entry in dir {
+        let file_name = entry?.file_name();
+        let target = match file_name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".json"))
+        {
+            Some(target) => target.to_string(),
+            None => continue,
+        };
+        let stats = load_dedup_stats(base_path, &target)?;
+        list.push((target, stats));
+    }
+    list.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(list)
+}
@@ -0,0 +1,135 @@
+//! Deferred deletions of delete protected targets
+//!
+//! Targets with `delete-protection` are used with credentials which can
+//! only upload, read and list objects. Instead of deleting objects (prune,
+//! garbage collection), the server records the keys in a local queue.
+//! The queue is processed by an administrator using separate credentials
+//! which are allowed to delete (`proxmox-backup-debug cloud delete-queue`),
+//! or exported to configure lifecycle rules at the provider.
+//!
+//! A compromised server can therefore not remove backups from the target.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_sys::fs::{create_path, open_file_locked, replace_file};
+
+use pbs_api_types::CloudDeleteQueueEntry;
+
+use super::backend::CloudBackend;
+use super::create_options;
+
+/// Local queue of object deletions of a target (locked while loaded)
+pub struct DeleteQueue {
+    path: PathBuf,
+    entries: Vec<CloudDeleteQueueEntry>,
+    dirty: bool,
+    _lock: File,
+}
+
+impl DeleteQueue {
+    fn queue_path(base_path: &Path, target: &str) -> PathBuf {
+        let mut path = base_path.to_owned();
+        path.push("delete-queue");
+        path.push(format!("{}.json", target));
+        path
+    }
+
+    /// Lock and load the queue of a target (empty if there is none yet)
+    pub fn load<P: AsRef<Path>>(base_path: P, target: &str) -> Result<Self, Error> {
+        let path = Self::queue_path(base_path.as_ref(), target);
+
+        if let Some(parent) = path.parent() {
+            create_path(
+                parent,
+                Some(create_options(0o0750)?),
+                Some(create_options(0o0750)?),
+            )?;
+        }
+
+        let mut lock_path = path.clone();
+        lock_path.set_extension("lck");
+        let timeout = std::time::Duration::new(10, 0);
+        let lock = open_file_locked(&lock_path, timeout, true, create_options(0o0640)?)
+            .map_err(|err| format_err!("unable to lock delete queue {:?} - {}", path, err))?;
+
+        let entries = match proxmox_sys::fs::file_get_optional_contents(&path)? {
+            Some(data) => serde_json::from_slice(&data)
+                .map_err(|err| format_err!("unable to parse {:?} - {}", path, err))?,
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            path,
+            entries,
+            dirty: false,
+            _lock: lock,
+        })
+    }
+
+    /// Store the queue (only if something changed)
+    pub fn save(&mut self) -> Result<(), Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let data = serde_json::to_vec(&self.entries)?;
+        replace_file(&self.path, &data, create_options(0o0640)?, true)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[CloudDeleteQueueEntry] {
+        &self.entries
+    }
+
+    /// Queue the deletion of `key` (queuing a key twice keeps the first entry)
+    pub fn push(&mut self, key: &str, now: i64) {
+        if self.entries.iter().any(|entry| entry.key == key) {
+            return;
+        }
+        self.entries.push(CloudDeleteQueueEntry {
+            key: key.to_string(),
+            queued: now,
+        });
+        self.dirty = true;
+    }
+
+    /// Remove `key` from the queue (e.g. because it was uploaded again)
+    pub fn remove(&mut self, key: &str) {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.key != key);
+        if self.entries.len() != len {
+            self.dirty = true;
+        }
+    }
+
+    /// Delete all queued objects using `backend`
+    ///
+    /// `backend` must use credentials which are allowed to delete. The queue
+    /// is saved after each deletion, so an interrupted run can be resumed.
+    /// Returns the number of deleted objects.
+    pub fn process(
+        &mut self,
+        backend: &Arc<dyn CloudBackend>,
+        mut log: impl FnMut(&CloudDeleteQueueEntry),
+    ) -> Result<usize, Error> {
+        if backend.delete_protected() {
+            bail!("cannot process delete queue with delete protected credentials");
+        }
+        let mut count = 0;
+        while let Some(entry) = self.entries.first().cloned() {
+            backend
+                .delete_object(&entry.key)
+                .map_err(|err| format_err!("unable to delete '{}' - {}", entry.key, err))?;
+            log(&entry);
+            self.entries.remove(0);
+            self.dirty = true;
+            self.save()?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
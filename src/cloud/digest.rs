@@ -0,0 +1,388 @@
+//! Digest reports of cloud jobs
+//!
+//! Instead of one mail per job run, a digest job summarizes the runs of all
+//! cloud jobs in its period (the last day or week): runs and failures per
+//! job, the bytes uploaded according to the task records, and the data
+//! stored on each target. The digest is mailed to the notify user and/or
+//! posted as JSON to a webhook.
+//!
+//! Stored data is taken from the local catalogs. The sizes reported by the
+//! last digest are kept, so the growth of a target shows from the second
+//! run on.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use hyper::client::{Client, HttpConnector};
+use hyper::{header, Body, Request};
+use openssl::ssl::{SslConnector, SslMethod};
+use serde::{Deserialize, Serialize};
+
+use proxmox_http::client::HttpsConnector;
+use proxmox_rest_server::{TaskListInfoIterator, TaskState};
+use proxmox_sys::fs::{create_path, replace_file};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{CloudDigestJobConfig, CloudDigestPeriod, CloudTarget, Userid, UPID};
+
+use super::catalog::CloudCatalog;
+use super::create_options;
+use super::task_records::read_task_records;
+
+/// Worker types of the jobs covered by a digest
+pub const DIGEST_JOB_TYPES: &[&str] = &[
+    "cloud-backup-job",
+    "cloud-replication-job",
+    "cloud-role-sync-job",
+];
+
+/// Runs of a job in the digest period
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DigestJobSummary {
+    pub job_type: String,
+    pub job_id: String,
+    pub runs: u64,
+    pub failed: u64,
+    /// Bytes uploaded by all runs
+    pub bytes: u64,
+    /// End time of the last failed run
+    pub last_failure: Option<i64>,
+    /// Error of the last failed run
+    pub last_error: Option<String>,
+}
+
+/// Data stored on a target
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DigestTargetUsage {
+    pub target: String,
+    pub stored_bytes: u64,
+    /// Stored bytes reported by the last digest
+    pub previous_bytes: Option<u64>,
+}
+
+/// Digest of the cloud job runs in a period
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CloudJobDigest {
+    pub id: String,
+    pub period: CloudDigestPeriod,
+    pub since: i64,
+    pub until: i64,
+    pub runs: u64,
+    pub failed: u64,
+    pub bytes: u64,
+    /// Jobs which ran in the period, sorted by type and ID
+    pub jobs: Vec<DigestJobSummary>,
+    pub targets: Vec<DigestTargetUsage>,
+}
+
+impl CloudJobDigest {
+    /// Empty digest for the period ending at `until`
+    pub fn new(id: &str, period: CloudDigestPeriod, until: i64) -> Self {
+        Self {
+            id: id.to_string(),
+            period,
+            since: until - period.seconds(),
+            until,
+            runs: 0,
+            failed: 0,
+            bytes: 0,
+            jobs: Vec::new(),
+            targets: Vec::new(),
+        }
+    }
+
+    /// Add a finished run, which uploaded `bytes`
+    pub fn add_run(&mut self, upid: &UPID, state: &TaskState, bytes: u64) {
+        let job_type = upid.worker_type.as_str();
+        // worker IDs of jobs end with the job ID
+        let job_id = upid
+            .worker_id
+            .as_deref()
+            .and_then(|id| id.rsplit(':').next())
+            .unwrap_or("-");
+
+        let index = match self.jobs.binary_search_by(|job| {
+            (job.job_type.as_str(), job.job_id.as_str()).cmp(&(job_type, job_id))
+        }) {
+            Ok(index) => index,
+            Err(index) => {
+                let job = DigestJobSummary {
+                    job_type: job_type.to_string(),
+                    job_id: job_id.to_string(),
+                    ..Default::default()
+                };
+                self.jobs.insert(index, job);
+                index
+            }
+        };
+        let job = &mut self.jobs[index];
+
+        job.runs += 1;
+        job.bytes += bytes;
+        self.runs += 1;
+        self.bytes += bytes;
+
+        let error = match state {
+            TaskState::Error { message, .. } => message.clone(),
+            TaskState::Unknown { .. } => "unknown task state".to_string(),
+            TaskState::OK { .. } | TaskState::Warning { .. } => return,
+        };
+        job.failed += 1;
+        self.failed += 1;
+        if job
+            .last_failure
+            .map_or(true, |last| state.endtime() >= last)
+        {
+            job.last_failure = Some(state.endtime());
+            job.last_error = Some(error);
+        }
+    }
+
+    /// Add the data stored on `target`, compared to the `previous` digest
+    pub fn add_target(&mut self, target: &str, stored_bytes: u64, previous: &DigestState) {
+        self.targets.push(DigestTargetUsage {
+            target: target.to_string(),
+            stored_bytes,
+            previous_bytes: previous.targets.get(target).copied(),
+        });
+    }
+
+    /// State to compare the next digest with
+    pub fn state(&self) -> DigestState {
+        DigestState {
+            time: self.until,
+            targets: self
+                .targets
+                .iter()
+                .map(|usage| (usage.target.clone(), usage.stored_bytes))
+                .collect(),
+        }
+    }
+}
+
+/// Bytes stored on a target according to its catalog
+///
+/// Counts chunk archives and snapshot files, but not the catalogs.
+pub fn stored_bytes(catalog: &CloudCatalog) -> u64 {
+    catalog
+        .media_sets()
+        .iter()
+        .map(|media_set| {
+            let archives: u64 = media_set.archives.iter().map(|archive| archive.size).sum();
+            let files: u64 = media_set
+                .snapshots
+                .iter()
+                .flat_map(|entry| entry.files.iter())
+                .map(|file| file.size)
+                .sum();
+            archives + files
+        })
+        .sum()
+}
+
+/// Add the runs in the digest period from the task archive
+pub fn collect_runs<P: AsRef<Path>>(
+    digest: &mut CloudJobDigest,
+    base_path: P,
+) -> Result<(), Error> {
+    let base_path = base_path.as_ref();
+
+    for info in TaskListInfoIterator::new(false)? {
+        let info = info?;
+        let state = match info.state {
+            Some(state) => state,
+            None => continue, // still running
+        };
+        if state.endtime() < digest.since {
+            // older tasks ended before the period, too
+            break;
+        }
+        if state.endtime() >= digest.until
+            || !DIGEST_JOB_TYPES.contains(&info.upid.worker_type.as_str())
+        {
+            continue;
+        }
+
+        let bytes = read_task_records(base_path, &info.upid_str)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|record| record.bytes)
+            .sum();
+        digest.add_run(&info.upid, &state, bytes);
+    }
+
+    Ok(())
+}
+
+/// What the last digest of a job reported
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DigestState {
+    /// End of the period of the last digest
+    pub time: i64,
+    /// Stored bytes per target
+    pub targets: BTreeMap<String, u64>,
+}
+
+fn digest_state_path(base_path: &Path, job_id: &str) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("digest");
+    path.push(format!("{}.json", job_id));
+    path
+}
+
+/// State of the last digest of job `job_id` (empty before the first one)
+pub fn load_digest_state<P: AsRef<Path>>(base_path: P, job_id: &str) -> Result<DigestState, Error> {
+    let path = digest_state_path(base_path.as_ref(), job_id);
+    match proxmox_sys::fs::file_get_optional_contents(&path)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        None => Ok(DigestState::default()),
+    }
+}
+
+/// Store the state of the last digest of job `job_id`
+pub fn save_digest_state<P: AsRef<Path>>(
+    base_path: P,
+    job_id: &str,
+    state: &DigestState,
+) -> Result<(), Error> {
+    let path = digest_state_path(base_path.as_ref(), job_id);
+    if let Some(parent) = path.parent() {
+        create_path(
+            parent,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+    }
+    let data = serde_json::to_vec(state)?;
+    replace_file(path, &data, create_options(0o0640)?, true)
+}
+
+/// Remove the state of the digests of job `job_id`
+pub fn remove_digest_state<P: AsRef<Path>>(base_path: P, job_id: &str) -> Result<(), Error> {
+    let path = digest_state_path(base_path.as_ref(), job_id);
+    match std::fs::remove_file(&path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(format_err!("unable to remove {:?} - {}", path, err)),
+    }
+}
+
+/// POST a digest as JSON to `url`
+pub async fn post_webhook(url: &str, digest: &CloudJobDigest) -> Result<(), Error> {
+    let mut connector = HttpConnector::new();
+    connector.enforce_http(false); // we want https...
+    let https = HttpsConnector::with_connector(
+        connector,
+        SslConnector::builder(SslMethod::tls())?.build(),
+        crate::tools::PROXMOX_BACKUP_TCP_KEEPALIVE_TIME,
+    );
+    let client: Client<HttpsConnector> = Client::builder().build(https);
+
+    let request = Request::builder()
+        .method("POST")
+        .uri(url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(digest)?))?;
+
+    let response = client
+        .request(request)
+        .await
+        .map_err(|err| format_err!("request failed - {}", err))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap_or_default();
+        bail!(
+            "server returned {} - {}",
+            status,
+            String::from_utf8_lossy(&body).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Compile the digest of a job and send it
+///
+/// The state is only stored once the digest was sent, so the next digest
+/// compares target sizes with what the recipients last saw.
+pub async fn send_digest<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    config: &CloudDigestJobConfig,
+    targets: &[CloudTarget],
+) -> Result<CloudJobDigest, Error> {
+    let base_path = base_path.as_ref();
+    let period = config.period.unwrap_or_default();
+    let mut digest = CloudJobDigest::new(&config.id, period, proxmox_time::epoch_i64());
+
+    collect_runs(&mut digest, base_path)?;
+
+    let previous = load_digest_state(base_path, &config.id)?;
+    for target in targets {
+        match CloudCatalog::load(base_path, &target.name) {
+            Ok(catalog) => digest.add_target(&target.name, stored_bytes(&catalog), &previous),
+            Err(err) => task_warn!(
+                worker,
+                "unable to load catalog of {} - {}",
+                target.name,
+                err
+            ),
+        }
+    }
+
+    task_log!(
+        worker,
+        "{} runs of {} jobs, {} failed",
+        digest.runs,
+        digest.jobs.len(),
+        digest.failed
+    );
+
+    let mut errors = 0;
+
+    // without webhook, mails go to root@pam by default
+    let notify_user = match (&config.notify_user, &config.webhook) {
+        (Some(user), _) => Some(user),
+        (None, None) => Some(Userid::root_userid()),
+        (None, Some(_)) => None,
+    };
+    if let Some(user) = notify_user {
+        match crate::server::lookup_user_email(user) {
+            Some(email) => {
+                task_log!(worker, "sending digest to {}", email);
+                if let Err(err) = crate::server::send_cloud_job_digest(&email, &digest) {
+                    task_warn!(worker, "unable to send digest mail - {}", err);
+                    errors += 1;
+                }
+            }
+            None => {
+                task_warn!(worker, "user {} has no email address", user);
+                errors += 1;
+            }
+        }
+    }
+
+    if let Some(ref url) = config.webhook {
+        task_log!(worker, "posting digest to {}", url);
+        if let Err(err) = post_webhook(url, &digest).await {
+            task_warn!(worker, "unable to post digest - {}", err);
+            errors += 1;
+        }
+    }
+
+    if errors > 0 {
+        bail!("sending digest failed");
+    }
+
+    save_digest_state(base_path, &config.id, &digest.state())?;
+
+    Ok(digest)
+}
@@ -0,0 +1,135 @@
+//! Egress budgets
+//!
+//! Downloading from object storage is usually billed per byte. Targets can
+//! have a monthly `egress-budget`, operations which would exceed it need to
+//! be forced explicitly. Past downloads are taken from the transfer
+//! accounting (see [`crate::cloud::usage`]), downloads of the running
+//! operation are counted by a [`MeteredBackend`](super::backend::MeteredBackend).
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{bail, Error};
+
+use proxmox_human_byte::HumanByte;
+
+use pbs_api_types::{CloudEgressStatus, CloudTarget};
+
+use super::catalog::{CloudCatalog, SnapshotEntry};
+use super::usage::{current_month, load_usage};
+
+/// Downloads of a target in the current month
+pub struct EgressMeter {
+    budget: Option<u64>,
+    enforce: bool,
+    // stored by the transfer accounting (at open time)
+    used_before: u64,
+    recorded: AtomicU64,
+}
+
+fn downloaded_this_month(base_path: &Path, target: &str) -> Result<(String, u64), Error> {
+    let month = current_month()?;
+    let used = load_usage(base_path, target)?
+        .get(&month)
+        .map(|usage| usage.downloaded)
+        .unwrap_or(0);
+    Ok((month, used))
+}
+
+/// Egress status of a target for the current month
+pub fn egress_status<P: AsRef<Path>>(
+    base_path: P,
+    target: &CloudTarget,
+) -> Result<CloudEgressStatus, Error> {
+    let (month, used) = downloaded_this_month(base_path.as_ref(), &target.name)?;
+    Ok(CloudEgressStatus {
+        month,
+        used,
+        budget: target.config.egress_budget_bytes(),
+    })
+}
+
+impl EgressMeter {
+    /// Start counting downloads of `target`
+    ///
+    /// With `enforce`, downloads beyond the budget of the target fail.
+    pub fn open<P: AsRef<Path>>(
+        base_path: P,
+        target: &CloudTarget,
+        enforce: bool,
+    ) -> Result<Self, Error> {
+        let (_month, used_before) = downloaded_this_month(base_path.as_ref(), &target.name)?;
+
+        Ok(Self {
+            budget: target.config.egress_budget_bytes(),
+            enforce,
+            used_before,
+            recorded: AtomicU64::new(0),
+        })
+    }
+
+    /// Bytes downloaded this month (including this meter)
+    pub fn used(&self) -> u64 {
+        self.used_before + self.recorded.load(Ordering::SeqCst)
+    }
+
+    /// Bytes recorded by this meter
+    pub fn recorded(&self) -> u64 {
+        self.recorded.load(Ordering::SeqCst)
+    }
+
+    /// Remaining budget (`None` if the target has no budget)
+    pub fn remaining(&self) -> Option<u64> {
+        self.budget.map(|budget| budget.saturating_sub(self.used()))
+    }
+
+    /// Check that `bytes` more can be downloaded
+    pub fn check(&self, bytes: u64) -> Result<(), Error> {
+        if !self.enforce {
+            return Ok(());
+        }
+        if let (Some(budget), Some(remaining)) = (self.budget, self.remaining()) {
+            if bytes > remaining {
+                bail!(
+                    "egress budget exceeded - {} needed, {} of {} left this month \
+                        (use --force-egress to continue anyway)",
+                    HumanByte::from(bytes),
+                    HumanByte::from(remaining),
+                    HumanByte::from(budget),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Count a download of `bytes`
+    pub fn record(&self, bytes: u64) {
+        self.recorded.fetch_add(bytes, Ordering::SeqCst);
+    }
+}
+
+/// Data downloaded to restore `entries`
+///
+/// Counts the files and all distinct chunks of the snapshots, except
+/// chunks for which `is_present` returns true (already in the datastore).
+pub fn estimate_restore_egress(
+    catalog: &CloudCatalog,
+    entries: &[&SnapshotEntry],
+    is_present: impl Fn(&[u8; 32]) -> bool,
+) -> u64 {
+    let mut seen = HashSet::new();
+    let mut bytes = 0;
+    for entry in entries {
+        bytes += entry.files.iter().map(|file| file.size).sum::<u64>();
+        for digest in entry.chunks.iter() {
+            if !seen.insert((entry.key.clone(), *digest)) || is_present(digest) {
+                continue;
+            }
+            if let Some(location) = catalog.lookup_chunk(digest, entry.key.as_ref()) {
+                bytes += location.size;
+            }
+        }
+    }
+    bytes
+}
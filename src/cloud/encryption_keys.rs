@@ -0,0 +1,301 @@
+//! Store cloud encryption keys
+//!
+//! Works like the tape key store (see [`crate::tape::encryption_keys`]):
+//! we store the plain key (readable by root only), as well as an
+//! encrypted version protected by password. Keys are indexed by their
+//! fingerprint and assigned to namespaces in the target configuration,
+//! so that tenants sharing a target are cryptographically isolated.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_sys::fs::file_read_optional_string;
+
+use pbs_api_types::Fingerprint;
+use pbs_config::{open_backup_lockfile, replace_backup_config, replace_secret_config};
+use pbs_datastore::DataBlob;
+use pbs_key_config::KeyConfig;
+use pbs_tools::crypt_config::CryptConfig;
+
+pub use crate::tape::encryption_keys::EncryptionKeyInfo;
+
+pub const CLOUD_KEYS_FILENAME: &str = "/etc/proxmox-backup/cloud-encryption-keys.json";
+pub const CLOUD_KEY_CONFIG_FILENAME: &str = "/etc/proxmox-backup/cloud-encryption-key-config.json";
+pub const CLOUD_KEYS_LOCKFILE: &str = "/etc/proxmox-backup/.cloud-encryption-keys.lck";
+
+/// Load cloud encryption keys (plain, unprotected keys)
+pub fn load_keys() -> Result<(HashMap<Fingerprint, EncryptionKeyInfo>, [u8; 32]), Error> {
+    let content = file_read_optional_string(CLOUD_KEYS_FILENAME)?;
+    let content = content.unwrap_or_else(|| String::from("[]"));
+
+    let digest = openssl::sha::sha256(content.as_bytes());
+
+    let key_list: Vec<EncryptionKeyInfo> = serde_json::from_str(&content)?;
+
+    let mut map = HashMap::new();
+
+    for item in key_list {
+        let key_config = KeyConfig::without_password(item.key)?; // to compute fingerprint
+        let expected_fingerprint = key_config.fingerprint.unwrap();
+        if item.fingerprint != expected_fingerprint {
+            bail!(
+                "inconsistent fingerprint ({} != {})",
+                item.fingerprint,
+                expected_fingerprint,
+            );
+        }
+
+        if map.insert(item.fingerprint.clone(), item).is_some() {
+            bail!("found duplicate fingerprint");
+        }
+    }
+
+    Ok((map, digest))
+}
+
+/// Load cloud encryption key configurations (password protected keys)
+pub fn load_key_configs() -> Result<(HashMap<Fingerprint, KeyConfig>, [u8; 32]), Error> {
+    let content = file_read_optional_string(CLOUD_KEY_CONFIG_FILENAME)?;
+    let content = content.unwrap_or_else(|| String::from("[]"));
+
+    let digest = openssl::sha::sha256(content.as_bytes());
+
+    let key_list: Vec<KeyConfig> = serde_json::from_str(&content)?;
+
+    let mut map = HashMap::new();
+
+    for key_config in key_list {
+        match key_config.fingerprint {
+            Some(ref fingerprint) => {
+                if map.insert(fingerprint.clone(), key_config).is_some() {
+                    bail!("found duplicate fingerprint");
+                }
+            }
+            None => bail!("missing fingerprint"),
+        }
+    }
+
+    Ok((map, digest))
+}
+
+/// Store cloud encryption keys (plain, unprotected keys)
+///
+/// The file is only accessible by user root (mode 0600).
+pub fn save_keys(map: HashMap<Fingerprint, EncryptionKeyInfo>) -> Result<(), Error> {
+    let list: Vec<EncryptionKeyInfo> = map.into_values().collect();
+    let raw = serde_json::to_string_pretty(&list)?;
+    replace_secret_config(CLOUD_KEYS_FILENAME, raw.as_bytes())
+}
+
+/// Store cloud encryption key configurations (password protected keys)
+pub fn save_key_configs(map: HashMap<Fingerprint, KeyConfig>) -> Result<(), Error> {
+    let list: Vec<KeyConfig> = map.into_values().collect();
+    let raw = serde_json::to_string_pretty(&list)?;
+    replace_backup_config(CLOUD_KEY_CONFIG_FILENAME, raw.as_bytes())
+}
+
+/// Insert a new key
+///
+/// Get the lock, load both files, insert the new key, store files.
+pub fn insert_key(key: [u8; 32], key_config: KeyConfig, force: bool) -> Result<(), Error> {
+    let _lock = open_backup_lockfile(CLOUD_KEYS_LOCKFILE, None, true)?;
+
+    let (mut key_map, _) = load_keys()?;
+    let (mut config_map, _) = load_key_configs()?;
+
+    let fingerprint = match key_config.fingerprint.clone() {
+        Some(fingerprint) => fingerprint,
+        None => bail!("missing encryption key fingerprint - internal error"),
+    };
+
+    if !force && config_map.get(&fingerprint).is_some() {
+        bail!("encryption key '{}' already exists.", fingerprint);
+    }
+
+    let item = EncryptionKeyInfo::new(key, fingerprint.clone());
+    key_map.insert(fingerprint.clone(), item);
+    save_keys(key_map)?;
+
+    config_map.insert(fingerprint, key_config);
+    save_key_configs(config_map)?;
+
+    Ok(())
+}
+
+/// Load the plain key with the given fingerprint
+pub fn load_crypt_config(fingerprint: &Fingerprint) -> Result<Arc<CryptConfig>, Error> {
+    let (key_map, _digest) = load_keys()?;
+    let item = key_map
+        .get(fingerprint)
+        .ok_or_else(|| format_err!("cloud encryption key '{}' not found", fingerprint))?;
+    Ok(Arc::new(CryptConfig::new(item.key)?))
+}
+
+/// Key supplied by a tenant for a single task
+///
+/// Such keys are never written to the key store. The plain key is
+/// overwritten with zeros when the value is dropped, so it does not
+/// outlive the task which needed it.
+///
+/// Note that this does not cover the [`CryptConfig`] returned by
+/// [`TenantKey::crypt_config`], which keeps its own copy of the key
+/// material and cannot be zeroed. Callers should keep it only as long as
+/// they use it.
+pub struct TenantKey {
+    key: [u8; 32],
+    fingerprint: Fingerprint,
+}
+
+impl TenantKey {
+    /// Decrypt a password protected key configuration
+    ///
+    /// Unlike [`KeyConfig::decrypt`], this does not copy the password, and
+    /// zeroes the derived and decrypted key data. The password is zeroed
+    /// as well, regardless of the result.
+    pub fn decrypt(key_config: &KeyConfig, password: &mut String) -> Result<Self, Error> {
+        let result = decrypt_key_data(key_config, password.as_bytes());
+        zero_string(password);
+        let mut key =
+            result.map_err(|err| format_err!("unable to decrypt tenant key - {}", err))?;
+
+        let fingerprint = match check_key_fingerprint(key_config, &key) {
+            Ok(fingerprint) => fingerprint,
+            Err(err) => {
+                zero_bytes(&mut key);
+                bail!("unable to decrypt tenant key - {}", err);
+            }
+        };
+
+        Ok(Self { key, fingerprint })
+    }
+
+    pub fn fingerprint(&self) -> &Fingerprint {
+        &self.fingerprint
+    }
+
+    /// Check that this is the key the data was encrypted with
+    pub fn check_fingerprint(&self, expected: &Fingerprint) -> Result<(), Error> {
+        if &self.fingerprint != expected {
+            bail!(
+                "wrong encryption key - data was encrypted with key '{}', got '{}'",
+                expected.signature(),
+                self.fingerprint.signature(),
+            );
+        }
+        Ok(())
+    }
+
+    pub fn crypt_config(&self) -> Result<Arc<CryptConfig>, Error> {
+        Ok(Arc::new(CryptConfig::new(self.key)?))
+    }
+}
+
+impl Drop for TenantKey {
+    fn drop(&mut self) {
+        zero_bytes(&mut self.key);
+    }
+}
+
+// decrypt the key data of a key configuration, without keeping copies of
+// the password or key around
+fn decrypt_key_data(key_config: &KeyConfig, password: &[u8]) -> Result<[u8; 32], Error> {
+    let mut data = match key_config.kdf {
+        Some(ref kdf) => {
+            if password.len() < 5 {
+                bail!("Passphrase is too short!");
+            }
+            let raw_data = &key_config.data;
+            if raw_data.len() < 32 {
+                bail!("Unable to decrypt key - short data");
+            }
+            let iv = &raw_data[0..16];
+            let tag = &raw_data[16..32];
+            let enc_data = &raw_data[32..];
+
+            let mut derived_key = kdf.derive_key(password)?;
+            let cipher = openssl::symm::Cipher::aes_256_gcm();
+            let result =
+                openssl::symm::decrypt_aead(cipher, &derived_key, Some(iv), b"", enc_data, tag);
+            zero_bytes(&mut derived_key);
+
+            result.map_err(|err| match key_config.hint {
+                Some(ref hint) => format_err!("Unable to decrypt key (password hint: {})", hint),
+                None => format_err!("Unable to decrypt key (wrong password?) - {}", err),
+            })?
+        }
+        None => key_config.data.clone(),
+    };
+
+    let mut key = [0u8; 32];
+    let result = if data.len() == key.len() {
+        key.copy_from_slice(&data);
+        Ok(key)
+    } else {
+        Err(format_err!("wrong key size {}", data.len()))
+    };
+    zero_bytes(&mut data);
+
+    result
+}
+
+// compute the fingerprint of a decrypted key, and check it against the one
+// stored in the key configuration
+fn check_key_fingerprint(key_config: &KeyConfig, key: &[u8; 32]) -> Result<Fingerprint, Error> {
+    let fingerprint = Fingerprint::new(CryptConfig::new(*key)?.fingerprint());
+    if let Some(ref stored_fingerprint) = key_config.fingerprint {
+        if &fingerprint != stored_fingerprint {
+            bail!(
+                "KeyConfig contains wrong fingerprint {}, contained key has fingerprint {}",
+                stored_fingerprint,
+                fingerprint
+            );
+        }
+    }
+    Ok(fingerprint)
+}
+
+/// Overwrite secret data with zeros
+pub fn zero_bytes(data: &mut [u8]) {
+    for byte in data.iter_mut() {
+        // volatile, so the compiler cannot optimize the writes away
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Overwrite a (secret) string with zeros and clear it
+pub fn zero_string(data: &mut String) {
+    // SAFETY: all zero bytes are valid UTF-8
+    zero_bytes(unsafe { data.as_bytes_mut() });
+    data.clear();
+}
+
+/// Encrypt object data before upload
+///
+/// The data is wrapped into an encrypted (uncompressed) blob, so the
+/// result is self describing and authenticated.
+pub fn encrypt_object(data: &[u8], crypt_config: &CryptConfig) -> Result<Vec<u8>, Error> {
+    Ok(DataBlob::encode(data, Some(crypt_config), false)?.into_inner())
+}
+
+/// Decrypt object data written by [`encrypt_object`]
+pub fn decrypt_object(data: &[u8], crypt_config: &CryptConfig) -> Result<Vec<u8>, Error> {
+    let blob = DataBlob::load_from_reader(&mut &data[..])?;
+    if !blob.is_encrypted() {
+        bail!("object is not encrypted");
+    }
+    blob.decode(Some(crypt_config), None)
+}
+
+// shell completion helper
+/// Complete cloud encryption key fingerprints
+pub fn complete_key_fingerprint(_arg: &str, _param: &HashMap<String, String>) -> Vec<String> {
+    let data = match load_key_configs() {
+        Ok((data, _digest)) => data,
+        Err(_) => return Vec::new(),
+    };
+
+    data.keys().map(|fp| fp.signature()).collect()
+}
@@ -0,0 +1,554 @@
+//! Job lifecycle events
+//!
+//! Targets with `event-topic` (SNS) or `event-queue` (SQS) get a JSON
+//! message when a job writing to the target starts, completes or fails,
+//! and when a snapshot is deleted from the target (`pruned`). Downstream
+//! automation can subscribe to these instead of polling the task list.
+//!
+//! Requests are signed with the credentials of the target, or with the
+//! role of the EC2 instance profile (`event-credentials`). Publishing is
+//! best effort, failures are logged as task warnings and never fail a job.
+
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+use hyper::client::Client;
+use hyper::{Body, Request};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Serialize;
+use serde_json::Value;
+
+use proxmox_http::client::HttpsConnector;
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{CloudEventCredentials, CloudTarget, CloudTargetConfig};
+
+use super::backend::cloud_http_client;
+use super::backend::credentials::{
+    CloudCredentials, CredentialCache, InstanceRoleCredentials, ProcessCredentials,
+};
+
+/// Version of the event message schema
+pub const CLOUD_EVENT_VERSION: u32 = 1;
+
+/// Timeout of a single publish request
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Characters which need not be encoded in form values (SigV4 rules)
+const FORM_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded; charset=utf-8";
+
+const SNS_API_VERSION: &str = "2010-03-31";
+const SQS_API_VERSION: &str = "2012-11-05";
+
+/// Lifecycle event types
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CloudEventKind {
+    Started,
+    Completed,
+    Failed,
+    Pruned,
+}
+
+impl CloudEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CloudEventKind::Started => "started",
+            CloudEventKind::Completed => "completed",
+            CloudEventKind::Failed => "failed",
+            CloudEventKind::Pruned => "pruned",
+        }
+    }
+}
+
+/// Event message, sent as JSON
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CloudEvent {
+    /// Message schema version ([`CLOUD_EVENT_VERSION`])
+    pub version: u32,
+    pub event: CloudEventKind,
+    /// Time of the event (UNIX epoch)
+    pub time: i64,
+    /// Node publishing the event
+    pub node: String,
+    /// Cloud target the event refers to
+    pub target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upid: Option<String>,
+    /// Deleted snapshot (`pruned` only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+    /// Job error (`failed` only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Job summary, as passed to the post-hook
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub summary: Value,
+}
+
+impl CloudEvent {
+    fn new(event: CloudEventKind, target: &str) -> Self {
+        Self {
+            version: CLOUD_EVENT_VERSION,
+            event,
+            time: proxmox_time::epoch_i64(),
+            node: proxmox_sys::nodename().to_string(),
+            target: target.to_string(),
+            job_type: None,
+            job_id: None,
+            upid: None,
+            snapshot: None,
+            error: None,
+            summary: Value::Null,
+        }
+    }
+
+    /// A job writing to `target` started
+    pub fn job_started(target: &str, job_type: &str, job_id: &str, upid: &str) -> Self {
+        Self {
+            job_type: Some(job_type.to_string()),
+            job_id: Some(job_id.to_string()),
+            upid: Some(upid.to_string()),
+            ..Self::new(CloudEventKind::Started, target)
+        }
+    }
+
+    /// A job writing to `target` finished with `result`
+    pub fn job_finished(
+        target: &str,
+        job_type: &str,
+        job_id: &str,
+        upid: &str,
+        result: &Result<(), Error>,
+        summary: Value,
+    ) -> Self {
+        let (event, error) = match result {
+            Ok(()) => (CloudEventKind::Completed, None),
+            Err(err) => (CloudEventKind::Failed, Some(err.to_string())),
+        };
+        Self {
+            job_type: Some(job_type.to_string()),
+            job_id: Some(job_id.to_string()),
+            upid: Some(upid.to_string()),
+            error,
+            summary,
+            ..Self::new(event, target)
+        }
+    }
+
+    /// A snapshot was deleted from `target`
+    pub fn snapshot_pruned(target: &str, snapshot: &str, upid: &str) -> Self {
+        Self {
+            upid: Some(upid.to_string()),
+            snapshot: Some(snapshot.to_string()),
+            ..Self::new(CloudEventKind::Pruned, target)
+        }
+    }
+}
+
+/// Where events of a target are published
+#[derive(Clone, Debug, PartialEq)]
+pub enum EventDestination {
+    /// SNS topic
+    Topic { arn: String, region: String },
+    /// SQS queue
+    Queue { url: String, region: String },
+}
+
+impl EventDestination {
+    /// SNS topic `arn:aws:sns:<region>:<account>:<topic>`
+    pub fn topic(arn: &str) -> Result<Self, Error> {
+        let region = match arn.split(':').collect::<Vec<_>>()[..] {
+            ["arn", _, "sns", region, _, _] if !region.is_empty() => region.to_string(),
+            _ => bail!("invalid SNS topic ARN '{}'", arn),
+        };
+        Ok(Self::Topic {
+            arn: arn.to_string(),
+            region,
+        })
+    }
+
+    /// SQS queue `https://sqs.<region>.amazonaws.com/<account>/<queue>`
+    pub fn queue(url: &str) -> Result<Self, Error> {
+        let region = url
+            .strip_prefix("https://sqs.")
+            .and_then(|rest| rest.split_once(".amazonaws.com"))
+            .map(|(region, _)| region)
+            .filter(|region| !region.is_empty() && !region.contains('/'))
+            .ok_or_else(|| format_err!("invalid SQS queue URL '{}'", url))?;
+        Ok(Self::Queue {
+            url: url.to_string(),
+            region: region.to_string(),
+        })
+    }
+
+    fn service(&self) -> &'static str {
+        match self {
+            Self::Topic { .. } => "sns",
+            Self::Queue { .. } => "sqs",
+        }
+    }
+
+    fn region(&self) -> &str {
+        match self {
+            Self::Topic { region, .. } | Self::Queue { region, .. } => region,
+        }
+    }
+
+    // FIFO topics and queues need a message group and deduplication ID
+    fn is_fifo(&self) -> bool {
+        match self {
+            Self::Topic { arn, .. } => arn.ends_with(".fifo"),
+            Self::Queue { url, .. } => url.ends_with(".fifo"),
+        }
+    }
+
+    /// Host and path the publish request is sent to
+    pub fn host_and_path(&self) -> (String, String) {
+        match self {
+            Self::Topic { region, .. } => {
+                let domain = if region.starts_with("cn-") {
+                    "amazonaws.com.cn"
+                } else {
+                    "amazonaws.com"
+                };
+                (format!("sns.{}.{}", region, domain), "/".to_string())
+            }
+            Self::Queue { url, .. } => {
+                let rest = url.strip_prefix("https://").unwrap_or(url);
+                match rest.split_once('/') {
+                    Some((host, path)) => (host.to_string(), format!("/{}", path)),
+                    None => (rest.to_string(), "/".to_string()),
+                }
+            }
+        }
+    }
+
+    /// Form encoded body of the publish request for `message`
+    pub fn request_body(&self, message: &str, group: &str) -> String {
+        let mut params: Vec<(&str, String)> = match self {
+            Self::Topic { arn, .. } => vec![
+                ("Action", "Publish".to_string()),
+                ("TopicArn", arn.clone()),
+                ("Message", message.to_string()),
+                ("Version", SNS_API_VERSION.to_string()),
+            ],
+            Self::Queue { .. } => vec![
+                ("Action", "SendMessage".to_string()),
+                ("MessageBody", message.to_string()),
+                ("Version", SQS_API_VERSION.to_string()),
+            ],
+        };
+        if self.is_fifo() {
+            params.push(("MessageGroupId", group.to_string()));
+            params.push((
+                "MessageDeduplicationId",
+                hex::encode(openssl::sha::sha256(message.as_bytes())),
+            ));
+        }
+        params
+            .iter()
+            .map(|(name, value)| {
+                format!("{}={}", name, utf8_percent_encode(value, FORM_ENCODE_SET))
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+impl std::fmt::Display for EventDestination {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Topic { arn, .. } => write!(f, "SNS topic '{}'", arn),
+            Self::Queue { url, .. } => write!(f, "SQS queue '{}'", url),
+        }
+    }
+}
+
+/// Configured event destinations of a target
+pub fn event_destinations(config: &CloudTargetConfig) -> Result<Vec<EventDestination>, Error> {
+    let mut list = Vec::new();
+    if let Some(ref arn) = config.event_topic {
+        list.push(EventDestination::topic(arn)?);
+    }
+    if let Some(ref url) = config.event_queue {
+        list.push(EventDestination::queue(url)?);
+    }
+    Ok(list)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+    let key = openssl::pkey::PKey::hmac(key)?;
+    let mut signer = openssl::sign::Signer::new(openssl::hash::MessageDigest::sha256(), &key)?;
+    signer.update(data)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+/// SigV4 headers of a POST request to an AWS API
+///
+/// `headers` (lower case names, e.g. `content-type`) are signed and
+/// returned together with the date, session token and `authorization`
+/// headers, which all need to be added to the request.
+#[allow(clippy::too_many_arguments)]
+pub fn sign_post_request(
+    credentials: &CloudCredentials,
+    service: &str,
+    region: &str,
+    host: &str,
+    path: &str,
+    headers: &[(&'static str, String)],
+    body: &str,
+    epoch: i64,
+) -> Result<Vec<(&'static str, String)>, Error> {
+    let amz_date = proxmox_time::strftime_utc("%Y%m%dT%H%M%SZ", epoch)?;
+    let date = &amz_date[..8];
+
+    let mut headers = headers.to_vec();
+    headers.push(("host", host.to_string()));
+    headers.push(("x-amz-date", amz_date.clone()));
+    if let Some(ref token) = credentials.session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    headers.sort();
+
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let canonical_request = format!(
+        "POST\n{}\n\n{}\n{}\n{}",
+        path,
+        canonical_headers,
+        signed_headers,
+        hex::encode(openssl::sha::sha256(body.as_bytes())),
+    );
+
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(openssl::sha::sha256(canonical_request.as_bytes())),
+    );
+
+    let secret = format!("AWS4{}", credentials.secret_key);
+    let key = hmac_sha256(secret.as_bytes(), date.as_bytes())?;
+    let key = hmac_sha256(&key, region.as_bytes())?;
+    let key = hmac_sha256(&key, service.as_bytes())?;
+    let key = hmac_sha256(&key, b"aws4_request")?;
+    let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes())?);
+
+    headers.retain(|(name, _)| *name != "host");
+    headers.push((
+        "authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key, scope, signed_headers, signature,
+        ),
+    ));
+
+    Ok(headers)
+}
+
+/// SigV4 headers of a form POST request to an AWS query API
+pub fn sign_form_request(
+    credentials: &CloudCredentials,
+    service: &str,
+    region: &str,
+    host: &str,
+    path: &str,
+    body: &str,
+    epoch: i64,
+) -> Result<Vec<(&'static str, String)>, Error> {
+    sign_post_request(
+        credentials,
+        service,
+        region,
+        host,
+        path,
+        &[("content-type", FORM_CONTENT_TYPE.to_string())],
+        body,
+        epoch,
+    )
+}
+
+/// Credentials for SNS and SQS requests of a target
+///
+/// See the `event-credentials` property of the target.
+pub fn event_credentials(target: &CloudTarget) -> Result<CredentialCache, Error> {
+    let config = &target.config;
+    let credentials = match config.event_credentials.unwrap_or_default() {
+        CloudEventCredentials::InstanceRole => {
+            CredentialCache::with_source(Box::new(InstanceRoleCredentials))
+        }
+        CloudEventCredentials::Target => match config.credential_process {
+            Some(ref command) => {
+                CredentialCache::with_source(Box::new(ProcessCredentials::new(command)))
+            }
+            None => {
+                let access_key = config.access_key.clone().ok_or_else(|| {
+                    format_err!("cloud target '{}' has no access key", target.name)
+                })?;
+                CredentialCache::with_static(CloudCredentials {
+                    access_key,
+                    secret_key: target.secret_key.clone(),
+                    session_token: None,
+                    expires: None,
+                })
+            }
+        },
+    };
+    Ok(credentials)
+}
+
+/// Publishes the events of a target
+pub struct EventPublisher {
+    target: String,
+    destinations: Vec<(EventDestination, Client<HttpsConnector>)>,
+    credentials: CredentialCache,
+}
+
+impl EventPublisher {
+    /// Publisher for `target`, `None` if the target has no event destination
+    pub fn new(target: &CloudTarget) -> Result<Option<Self>, Error> {
+        let config = &target.config;
+
+        let list = event_destinations(config)?;
+        if list.is_empty() {
+            return Ok(None);
+        }
+
+        let credentials = event_credentials(target)?;
+
+        let mut destinations = Vec::new();
+        for destination in list {
+            let (host, _) = destination.host_and_path();
+            let client = cloud_http_client(target, &host)?;
+            destinations.push((destination, client));
+        }
+
+        Ok(Some(Self {
+            target: target.name.clone(),
+            destinations,
+            credentials,
+        }))
+    }
+
+    fn send(
+        &self,
+        destination: &EventDestination,
+        client: &Client<HttpsConnector>,
+        message: &str,
+    ) -> Result<(), Error> {
+        let credentials = self.credentials.get()?;
+        let (host, path) = destination.host_and_path();
+        let body = destination.request_body(message, &self.target);
+
+        let headers = sign_form_request(
+            &credentials,
+            destination.service(),
+            destination.region(),
+            &host,
+            &path,
+            &body,
+            proxmox_time::epoch_i64(),
+        )?;
+
+        let mut builder = Request::builder()
+            .method("POST")
+            .uri(format!("https://{}{}", host, path))
+            .header("host", &host);
+        for (name, value) in headers {
+            builder = builder.header(name, value);
+        }
+        let request = builder.body(Body::from(body))?;
+
+        proxmox_async::runtime::block_on(async move {
+            tokio::time::timeout(PUBLISH_TIMEOUT, async move {
+                let response = client.request(request).await?;
+                let status = response.status();
+                if !status.is_success() {
+                    let body = hyper::body::to_bytes(response.into_body())
+                        .await
+                        .unwrap_or_default();
+                    bail!(
+                        "server returned {} - {}",
+                        status,
+                        String::from_utf8_lossy(&body).trim()
+                    );
+                }
+                Ok(())
+            })
+            .await
+            .map_err(|_| format_err!("timed out after {} seconds", PUBLISH_TIMEOUT.as_secs()))?
+        })
+    }
+
+    /// Publish `event` to all destinations
+    pub fn publish(&self, event: &CloudEvent) -> Result<(), Error> {
+        let message = serde_json::to_string(event)?;
+        let mut errors = Vec::new();
+        for (destination, client) in self.destinations.iter() {
+            if let Err(err) = self.send(destination, client, &message) {
+                errors.push(format!("{} - {}", destination, err));
+            }
+        }
+        if !errors.is_empty() {
+            bail!("{}", errors.join(", "));
+        }
+        Ok(())
+    }
+}
+
+/// Publish an event of a target, logging failures
+pub fn publish_event(worker: &dyn WorkerTaskContext, target: &CloudTarget, event: &CloudEvent) {
+    let result = EventPublisher::new(target).and_then(|publisher| match publisher {
+        Some(publisher) => publisher.publish(event).map(|_| true),
+        None => Ok(false),
+    });
+    match result {
+        Ok(true) => task_log!(
+            worker,
+            "published '{}' event of target '{}'",
+            event.event.as_str(),
+            target.name
+        ),
+        Ok(false) => {}
+        Err(err) => task_warn!(
+            worker,
+            "unable to publish event of target '{}' - {}",
+            target.name,
+            err
+        ),
+    }
+}
+
+/// Like [`publish_event`], looking up the target by name
+pub fn publish_target_event(worker: &dyn WorkerTaskContext, target: &str, event: &CloudEvent) {
+    match pbs_config::cloud::lookup_target(target) {
+        Ok(target) => publish_event(worker, &target, event),
+        Err(err) => task_warn!(
+            worker,
+            "unable to publish event - cannot lookup cloud target '{}' - {}",
+            target,
+            err
+        ),
+    }
+}
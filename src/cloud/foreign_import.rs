@@ -0,0 +1,524 @@
+//! Import of backups written by other tools
+//!
+//! Data copied into the bucket of a target by other means can be restored
+//! through the catalog, without uploading it again.
+//! [`detect_foreign_layouts`] looks for known layouts below the target
+//! prefix:
+//!
+//! * a Proxmox Backup Server datastore copied as is, e.g. with `rclone
+//!   copy` (a `.chunks/` directory next to the backup groups)
+//! * a restic repository (`config` next to `keys/`)
+//!
+//! The snapshots of a copied datastore are imported as a read-only media
+//! set whose catalog refers to the foreign objects (see
+//! [`ImportSource`]). Only the label and catalog of that media set are
+//! written, the foreign objects are never modified or deleted. restic
+//! repositories use their own chunking and encryption, so they are
+//! detected but cannot be imported.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{
+    print_ns_and_snapshot, Authid, BackupDir, BackupGroup, BackupNamespace, CloudForeignFormat,
+    CloudForeignLayout, CloudTarget,
+};
+use pbs_datastore::dynamic_index::DynamicIndexHeader;
+use pbs_datastore::file_formats::{DYNAMIC_SIZED_CHUNK_INDEX_1_0, FIXED_SIZED_CHUNK_INDEX_1_0};
+use pbs_datastore::fixed_index::FixedIndexHeader;
+use pbs_datastore::manifest::{archive_type, ArchiveType, BackupManifest, MANIFEST_BLOB_NAME};
+use pbs_datastore::DataBlob;
+
+use super::backend::{CloudBackend, ObjectInfo};
+use super::catalog::{
+    upload_media_set_catalog, upload_media_set_label, ChunkArchiveEntry, ChunkEntry, CloudCatalog,
+    ImportSource, MediaSetCatalog, MediaSetLabel, SnapshotEntry, SnapshotFileEntry,
+};
+use super::layout::{self, DATASTORE_CHUNK_DIR};
+use super::lease::{CloudLease, LEASE_TIMEOUT};
+use super::manifest_info;
+
+const RESTIC_CONFIG_NAME: &str = "config";
+const RESTIC_KEYS_DIR: &str = "keys/";
+
+/// Name of the owner file of a backup group
+const OWNER_FILE_NAME: &str = "owner";
+
+// our own objects, never part of foreign data
+fn is_own_object(key: &str) -> bool {
+    key.starts_with(layout::MEDIA_SET_PREFIX)
+        || key.starts_with(layout::TRASH_PREFIX)
+        || key.starts_with(layout::EXPORT_PREFIX)
+        || key == layout::LEASE_KEY
+}
+
+// prefix of a key whose path below the prefix starts with `name`
+//
+// `name` must be the whole first component (or components) of that path.
+fn prefix_before<'a>(key: &'a str, name: &str) -> Option<&'a str> {
+    if key.starts_with(name) {
+        return Some("");
+    }
+    let pos = key.find(&format!("/{}", name))?;
+    Some(&key[..pos])
+}
+
+fn join_prefix(prefix: &str, rest: &str) -> String {
+    if prefix.is_empty() {
+        rest.to_string()
+    } else {
+        format!("{}/{}", prefix, rest)
+    }
+}
+
+// key relative to `prefix`, `None` for keys outside of it
+fn strip_prefix<'a>(key: &'a str, prefix: &str) -> Option<&'a str> {
+    if prefix.is_empty() {
+        Some(key)
+    } else {
+        key.strip_prefix(prefix)?.strip_prefix('/')
+    }
+}
+
+fn group_path(ns: &BackupNamespace, group: &BackupGroup) -> String {
+    if ns.is_root() {
+        group.to_string()
+    } else {
+        format!("{}/{}", ns.display_as_path(), group)
+    }
+}
+
+fn parse_digest(hex_digest: &str) -> Option<[u8; 32]> {
+    let mut digest = [0u8; 32];
+    hex::decode_to_slice(hex_digest, &mut digest).ok()?;
+    Some(digest)
+}
+
+/// An object of a copied datastore
+#[derive(Clone, Debug, PartialEq)]
+pub enum DatastoreObject {
+    Chunk([u8; 32]),
+    SnapshotFile {
+        ns: BackupNamespace,
+        snapshot: BackupDir,
+        filename: String,
+    },
+    Owner {
+        ns: BackupNamespace,
+        group: BackupGroup,
+    },
+}
+
+/// Classify a key relative to the prefix of a copied datastore
+///
+/// Returns `None` for objects an import ignores (lock files, GC status,
+/// namespace directories, ...).
+pub fn parse_datastore_key(key: &str) -> Option<DatastoreObject> {
+    if let Some(rest) = key.strip_prefix(DATASTORE_CHUNK_DIR) {
+        let (dir, name) = rest.split_once('/')?;
+        let digest = parse_digest(name)?;
+        if dir.len() != 4 || !name.starts_with(dir) {
+            return None;
+        }
+        return Some(DatastoreObject::Chunk(digest));
+    }
+
+    let components: Vec<&str> = key.split('/').collect();
+    let (filename, dir) = components.split_last()?;
+    if filename.starts_with('.') {
+        return None;
+    }
+
+    if *filename == OWNER_FILE_NAME && dir.len() >= 2 {
+        let (ns, group) = dir.split_at(dir.len() - 2);
+        let group: BackupGroup = group.join("/").parse().ok()?;
+        let ns = BackupNamespace::from_path(&ns.join("/")).ok()?;
+        return Some(DatastoreObject::Owner { ns, group });
+    }
+
+    if dir.len() < 3 {
+        return None;
+    }
+    let (ns, snapshot) = dir.split_at(dir.len() - 3);
+    let snapshot: BackupDir = snapshot.join("/").parse().ok()?;
+    let ns = BackupNamespace::from_path(&ns.join("/")).ok()?;
+    Some(DatastoreObject::SnapshotFile {
+        ns,
+        snapshot,
+        filename: filename.to_string(),
+    })
+}
+
+/// Find the backup data of other tools in a listing of the target
+pub fn detect_layouts(objects: &[ObjectInfo]) -> Vec<CloudForeignLayout> {
+    let mut datastores: BTreeMap<String, u64> = BTreeMap::new();
+    let mut restic_configs = BTreeSet::new();
+    let mut restic_keys = BTreeSet::new();
+
+    for object in objects.iter().filter(|object| !is_own_object(&object.key)) {
+        let key = &object.key;
+        if let Some(prefix) = prefix_before(key, DATASTORE_CHUNK_DIR) {
+            let rest = key[prefix.len()..].trim_start_matches('/');
+            if let Some(DatastoreObject::Chunk(_)) = parse_datastore_key(rest) {
+                datastores.entry(prefix.to_string()).or_default();
+            }
+        }
+        if let Some(prefix) = prefix_before(key, RESTIC_KEYS_DIR) {
+            restic_keys.insert(prefix.to_string());
+        }
+        if key == RESTIC_CONFIG_NAME {
+            restic_configs.insert(String::new());
+        } else if let Some(prefix) = key.strip_suffix(&format!("/{}", RESTIC_CONFIG_NAME)) {
+            restic_configs.insert(prefix.to_string());
+        }
+    }
+
+    // count the snapshots (by their manifest) of each datastore
+    for object in objects.iter().filter(|object| !is_own_object(&object.key)) {
+        for (prefix, count) in datastores.iter_mut() {
+            if let Some(DatastoreObject::SnapshotFile { filename, .. }) =
+                strip_prefix(&object.key, prefix).and_then(parse_datastore_key)
+            {
+                if filename == MANIFEST_BLOB_NAME {
+                    *count += 1;
+                }
+            }
+        }
+    }
+
+    let mut list: Vec<CloudForeignLayout> = datastores
+        .into_iter()
+        .map(|(prefix, snapshots)| CloudForeignLayout {
+            prefix,
+            format: CloudForeignFormat::PbsDatastore,
+            snapshots: Some(snapshots),
+            importable: true,
+        })
+        .collect();
+
+    list.extend(
+        restic_configs
+            .intersection(&restic_keys)
+            .map(|prefix| CloudForeignLayout {
+                prefix: prefix.clone(),
+                format: CloudForeignFormat::Restic,
+                snapshots: None,
+                importable: false,
+            }),
+    );
+
+    list
+}
+
+/// Find the backup data of other tools on a target
+pub fn detect_foreign_layouts(
+    backend: &dyn CloudBackend,
+) -> Result<Vec<CloudForeignLayout>, Error> {
+    Ok(detect_layouts(&backend.list_objects("")?))
+}
+
+/// Chunk digests referenced by a fixed or dynamic index
+pub fn index_digests(data: &[u8]) -> Result<Vec<[u8; 32]>, Error> {
+    if data.len() < 8 {
+        bail!("index too small");
+    }
+    let (header_size, entry_size, digest_offset) = if data[..8] == FIXED_SIZED_CHUNK_INDEX_1_0 {
+        (std::mem::size_of::<FixedIndexHeader>(), 32, 0)
+    } else if data[..8] == DYNAMIC_SIZED_CHUNK_INDEX_1_0 {
+        // entries are (end offset, digest)
+        (std::mem::size_of::<DynamicIndexHeader>(), 40, 8)
+    } else {
+        bail!("unknown index magic");
+    };
+
+    if data.len() < header_size || (data.len() - header_size) % entry_size != 0 {
+        bail!("index has wrong size ({})", data.len());
+    }
+
+    Ok(data[header_size..]
+        .chunks_exact(entry_size)
+        .map(|entry| {
+            let mut digest = [0u8; 32];
+            digest.copy_from_slice(&entry[digest_offset..digest_offset + 32]);
+            digest
+        })
+        .collect())
+}
+
+/// Objects of a copied datastore
+#[derive(Default)]
+struct DatastoreListing {
+    chunks: HashMap<[u8; 32], u64>,
+    // snapshots by their path, with the file names
+    snapshots: BTreeMap<String, (BackupNamespace, BackupDir, Vec<String>)>,
+    owners: HashSet<(BackupNamespace, BackupGroup)>,
+}
+
+fn list_datastore(backend: &dyn CloudBackend, prefix: &str) -> Result<DatastoreListing, Error> {
+    let mut listing = DatastoreListing::default();
+
+    let objects = backend.list_objects(&join_prefix(prefix, ""))?;
+    for object in objects
+        .into_iter()
+        .filter(|object| !is_own_object(&object.key))
+    {
+        match strip_prefix(&object.key, prefix).and_then(parse_datastore_key) {
+            Some(DatastoreObject::Chunk(digest)) => {
+                listing.chunks.insert(digest, object.size);
+            }
+            Some(DatastoreObject::SnapshotFile {
+                ns,
+                snapshot,
+                filename,
+            }) => {
+                listing
+                    .snapshots
+                    .entry(print_ns_and_snapshot(&ns, &snapshot))
+                    .or_insert_with(|| (ns, snapshot, Vec::new()))
+                    .2
+                    .push(filename);
+            }
+            Some(DatastoreObject::Owner { ns, group }) => {
+                listing.owners.insert((ns, group));
+            }
+            None => {}
+        }
+    }
+
+    Ok(listing)
+}
+
+fn load_owner(
+    backend: &dyn CloudBackend,
+    source: &ImportSource,
+    ns: &BackupNamespace,
+    group: &BackupGroup,
+) -> Result<Authid, Error> {
+    let key = join_prefix(
+        &source.prefix,
+        &format!("{}/{}", group_path(ns, group), OWNER_FILE_NAME),
+    );
+    let data = backend.get_object(&key)?;
+    Ok(String::from_utf8(data)?.trim().parse()?)
+}
+
+// catalog entry of a copied snapshot, `None` if the copy is incomplete
+#[allow(clippy::too_many_arguments)]
+fn import_snapshot(
+    worker: &dyn WorkerTaskContext,
+    backend: &dyn CloudBackend,
+    source: &ImportSource,
+    store: &str,
+    ns: BackupNamespace,
+    snapshot: BackupDir,
+    filenames: &[String],
+    chunks: &HashMap<[u8; 32], u64>,
+) -> Result<Option<SnapshotEntry>, Error> {
+    let mut files = Vec::new();
+    let mut digests = Vec::new();
+    let mut seen = HashSet::new();
+    let mut verification = None;
+    let mut fingerprint = None;
+
+    for filename in filenames {
+        let key = source.snapshot_file_key(&ns, &snapshot, filename);
+        let data = backend
+            .get_object(&key)
+            .map_err(|err| format_err!("unable to read '{}' - {}", key, err))?;
+
+        if filename == MANIFEST_BLOB_NAME {
+            let manifest = DataBlob::load_from_reader(&mut &data[..])
+                .and_then(BackupManifest::try_from)
+                .map_err(|err| format_err!("unable to parse manifest - {}", err))?;
+            (verification, fingerprint) = manifest_info(&manifest)?;
+        }
+
+        match archive_type(filename) {
+            Ok(ArchiveType::FixedIndex) | Ok(ArchiveType::DynamicIndex) => {
+                let list = index_digests(&data)
+                    .map_err(|err| format_err!("unable to parse '{}' - {}", filename, err))?;
+                for digest in list {
+                    if !chunks.contains_key(&digest) {
+                        task_warn!(
+                            worker,
+                            "skip snapshot {} - chunk {} is missing",
+                            snapshot,
+                            hex::encode(digest)
+                        );
+                        return Ok(None);
+                    }
+                    if seen.insert(digest) {
+                        digests.push(digest);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        files.push(SnapshotFileEntry {
+            filename: filename.clone(),
+            size: data.len() as u64,
+            csum: openssl::sha::sha256(&data),
+        });
+    }
+
+    let owner = match load_owner(backend, source, &ns, &snapshot.group) {
+        Ok(owner) => Some(owner),
+        Err(err) => {
+            task_warn!(worker, "unable to read owner of {} - {}", snapshot, err);
+            None
+        }
+    };
+
+    Ok(Some(SnapshotEntry {
+        store: store.to_string(),
+        ns,
+        snapshot,
+        key: None,
+        files,
+        chunks: digests,
+        verification,
+        fingerprint,
+        owner,
+        attributed: Default::default(),
+        cloud_verification: None,
+    }))
+}
+
+/// Import the snapshots of a datastore copied below `prefix`
+///
+/// The snapshots are added to the catalog as if they were backed up from
+/// datastore `store`. Snapshots already in the catalog are skipped, so an
+/// import can be repeated after copying more data. Returns the UUID of
+/// the new media set, `None` if there was nothing to import.
+pub fn import_datastore<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+    prefix: &str,
+    store: &str,
+) -> Result<Option<Uuid>, Error> {
+    let base_path = base_path.as_ref();
+    let catalog = CloudCatalog::load(base_path, &target.name)?;
+
+    if is_own_object(&join_prefix(prefix, "")) {
+        bail!(
+            "prefix '{}' contains the objects of the target itself",
+            prefix
+        );
+    }
+
+    let source = ImportSource {
+        format: CloudForeignFormat::PbsDatastore,
+        prefix: prefix.to_string(),
+    };
+
+    let listing = list_datastore(&**backend, prefix)?;
+    if listing.chunks.is_empty() {
+        bail!("no datastore found below prefix '{}'", prefix);
+    }
+    task_log!(
+        worker,
+        "found {} snapshots and {} chunks below '{}'",
+        listing.snapshots.len(),
+        listing.chunks.len(),
+        prefix
+    );
+
+    let mut snapshots = Vec::new();
+    for (path, (ns, snapshot, filenames)) in listing.snapshots {
+        worker.check_abort()?;
+
+        if !filenames.iter().any(|name| name == MANIFEST_BLOB_NAME) {
+            task_log!(worker, "skip snapshot {} - no manifest", path);
+            continue;
+        }
+        if catalog.contains_snapshot(store, &ns, &snapshot) {
+            task_log!(worker, "skip snapshot {} - already in catalog", path);
+            continue;
+        }
+        if !listing
+            .owners
+            .contains(&(ns.clone(), snapshot.group.clone()))
+        {
+            task_warn!(worker, "group of snapshot {} has no owner file", path);
+        }
+
+        match import_snapshot(
+            worker,
+            &**backend,
+            &source,
+            store,
+            ns,
+            snapshot,
+            &filenames,
+            &listing.chunks,
+        ) {
+            Ok(Some(entry)) => {
+                task_log!(worker, "import snapshot {}", path);
+                snapshots.push(entry);
+            }
+            Ok(None) => {}
+            Err(err) => task_warn!(worker, "skip snapshot {} - {}", path, err),
+        }
+    }
+
+    if snapshots.is_empty() {
+        task_log!(worker, "nothing to import");
+        return Ok(None);
+    }
+
+    // a single archive listing the chunk objects (see ImportSource)
+    let mut seen = HashSet::new();
+    let mut chunks = Vec::new();
+    for digest in snapshots.iter().flat_map(|entry| entry.chunks.iter()) {
+        if seen.insert(*digest) {
+            chunks.push(ChunkEntry {
+                digest: *digest,
+                offset: 0,
+                size: listing.chunks[digest],
+            });
+        }
+    }
+
+    let label = MediaSetLabel {
+        uuid: Uuid::generate(),
+        ctime: proxmox_time::epoch_i64(),
+        base: None,
+        node: proxmox_sys::nodename().to_string(),
+    };
+    let mut media_set = MediaSetCatalog::new(label);
+    media_set.archives.push(ChunkArchiveEntry {
+        uuid: Uuid::generate(),
+        store: store.to_string(),
+        key: None,
+        size: chunks.iter().map(|chunk| chunk.size).sum(),
+        csum: None,
+        chunks,
+    });
+    media_set.snapshots = snapshots;
+    media_set.import = Some(source);
+
+    let lease = CloudLease::acquire(Arc::clone(backend), proxmox_sys::nodename(), LEASE_TIMEOUT)?;
+    upload_media_set_label(&**backend, &media_set.label)?;
+    upload_media_set_catalog(&**backend, &media_set)?;
+    media_set.save(base_path, &target.name)?;
+    lease.release()?;
+
+    task_log!(
+        worker,
+        "imported {} snapshots ({} chunks) as media set {}",
+        media_set.snapshots.len(),
+        media_set.archives[0].chunks.len(),
+        media_set.uuid()
+    );
+
+    Ok(Some(media_set.uuid().clone()))
+}
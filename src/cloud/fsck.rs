@@ -0,0 +1,636 @@
+//! Consistency check of cloud targets
+//!
+//! Cross-checks the local catalog with the objects actually stored on a
+//! target. Besides the problems found by a reconciliation (missing
+//! objects or objects with the wrong size), this finds objects no catalog
+//! references and media sets without a committed catalog. Optionally,
+//! chunk archives and parity objects are checked against the catalog
+//! checksums, in parallel. Objects with a provider checksum (uploaded with
+//! `upload-checksums`) are checked without downloading them. The number
+//! of threads adapts to throttling by the provider and to the traffic
+//! control limit of the target (see [`super::verify_tuning`]).
+//!
+//! Some problems can be repaired safely: damaged chunk archives are
+//! rebuilt from parity (see [`super::parity`]) or from the chunks still in
+//! the local datastores (see [`super::repair`]), media sets left behind by
+//! aborted backups and orphaned objects are deleted while holding the
+//! writer lease. Orphaned objects are only deleted if the catalog of
+//! their media set on the target matches the local one, so an outdated
+//! local catalog never causes data loss. Committed media sets missing in
+//! the local catalog are only reported.
+//!
+//! The report of the last check is kept in the status directory.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Error};
+use serde_json::Value;
+
+use proxmox_sys::fs::{create_path, replace_file};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{
+    CloudFsckIssue, CloudFsckIssueKind, CloudFsckReport, CloudProvider, CloudTarget,
+};
+
+use crate::tools::parallel_handler::ParallelHandler;
+
+use super::backend::{cloud_download_limit, cloud_error, CloudBackend, CloudError, S3Backend};
+use super::catalog::{CloudCatalog, MediaSetCatalog};
+use super::create_options;
+use super::layout;
+use super::lease::{CloudLease, LEASE_TIMEOUT};
+use super::parity::repair_target;
+use super::reconcile::{expected_objects, ReconcileResult};
+use super::repair::{reupload_chunk_archives, ChunkSource};
+use super::verify_tuning::{VerifyTuner, MIN_VERIFY_THREADS};
+
+/// Objects per thread in a batch of the verification
+const VERIFY_BATCH_PER_THREAD: usize = 8;
+
+/// Batches in a row the provider throttled completely before giving up
+const MAX_THROTTLED_BATCHES: usize = 3;
+
+/// Options of a consistency check
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FsckOptions {
+    /// Download chunk archives and parity objects to verify their checksums
+    pub verify: bool,
+    /// Repair the problems which can be fixed safely
+    pub repair: bool,
+}
+
+fn report_path(base_path: &Path, target: &str) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("fsck");
+    path.push(format!("{}.json", target));
+    path
+}
+
+/// Report of the last consistency check of a target
+pub fn load_fsck_report<P: AsRef<Path>>(
+    base_path: P,
+    target: &str,
+) -> Result<Option<CloudFsckReport>, Error> {
+    let path = report_path(base_path.as_ref(), target);
+    match proxmox_sys::fs::file_get_optional_contents(&path)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        None => Ok(None),
+    }
+}
+
+fn save_fsck_report(base_path: &Path, target: &str, report: &CloudFsckReport) -> Result<(), Error> {
+    let path = report_path(base_path, target);
+    if let Some(parent) = path.parent() {
+        create_path(
+            parent,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+    }
+    let data = serde_json::to_vec(report)?;
+    replace_file(path, &data, create_options(0o0640)?, true)
+}
+
+// media set of an object below 'media-set/' or 'trash/'
+fn object_media_set(key: &str) -> Option<Uuid> {
+    match key.strip_prefix(layout::TRASH_PREFIX) {
+        Some(rest) => rest.split('/').next()?.parse().ok(),
+        None => layout::parse_media_set_uuid(key),
+    }
+}
+
+// files of the trashed snapshots of a media set, with the expected size if known
+fn expected_trash_objects(media_set: &MediaSetCatalog) -> Vec<(String, Option<u64>)> {
+    let mut list = Vec::new();
+    for trashed in media_set.trash.iter() {
+        let entry = &trashed.entry;
+        for file in entry.files.iter() {
+            let size = match entry.key {
+                Some(_) => None,
+                None => Some(file.size),
+            };
+            list.push((
+                layout::trash_file_key(
+                    media_set.uuid(),
+                    &entry.store,
+                    &entry.ns,
+                    &entry.snapshot,
+                    &file.filename,
+                ),
+                size,
+            ));
+        }
+    }
+    list
+}
+
+// snapshot summaries are optional (not written by older versions)
+fn optional_objects(media_set: &MediaSetCatalog) -> Vec<String> {
+    let uuid = media_set.uuid();
+    let summaries = media_set
+        .snapshots
+        .iter()
+        .map(|entry| layout::snapshot_summary_key(uuid, &entry.store, &entry.ns, &entry.snapshot));
+    let trash_summaries = media_set.trash.iter().map(|trashed| {
+        let entry = &trashed.entry;
+        layout::trash_file_key(
+            uuid,
+            &entry.store,
+            &entry.ns,
+            &entry.snapshot,
+            layout::SNAPSHOT_SUMMARY_NAME,
+        )
+    });
+    summaries.chain(trash_summaries).collect()
+}
+
+// objects with a checksum in the catalog
+fn checksummed_objects(media_set: &MediaSetCatalog) -> Vec<(String, [u8; 32])> {
+    let uuid = media_set.uuid();
+    let archives = media_set.archives.iter().filter_map(|archive| {
+        archive
+            .csum
+            .map(|csum| (layout::chunk_archive_key(uuid, &archive.uuid), csum))
+    });
+    let parity = media_set
+        .parity
+        .iter()
+        .map(|parity| (layout::parity_key(uuid, &parity.uuid), parity.csum));
+    archives.chain(parity).collect()
+}
+
+struct VerifyResult {
+    key: String,
+    ok: bool,
+    provider_checksum: bool,
+}
+
+// object to verify, with its index in the list and the catalog checksum
+type VerifyItem = (usize, String, [u8; 32]);
+
+// checksum of an object and the number of bytes downloaded for it
+fn object_digest(backend: &dyn CloudBackend, key: &str) -> Result<([u8; 32], bool, u64), Error> {
+    if let Some(digest) = backend.object_checksum(key)? {
+        return Ok((digest, true, 0));
+    }
+    let data = backend.get_object(key)?;
+    Ok((openssl::sha::sha256(&data), false, data.len() as u64))
+}
+
+fn is_throttled(err: &Error) -> bool {
+    matches!(cloud_error(err), Some(CloudError::Throttled(_)))
+}
+
+// tuner for the download rate limit of a target
+fn verify_tuner(target: &CloudTarget) -> Result<VerifyTuner, Error> {
+    let limit = match target.config.provider {
+        CloudProvider::S3 => match S3Backend::configured_host(&target.config) {
+            Some(host) => cloud_download_limit(target, &host)?,
+            None => None,
+        },
+        CloudProvider::Local => None,
+    };
+    Ok(VerifyTuner::new(limit))
+}
+
+// compare objects with the catalog checksums, in parallel
+//
+// The provider checksum is used if available, other objects are downloaded.
+// Objects whose requests the provider throttled are verified again in a
+// later batch, with fewer threads.
+fn verify_objects(
+    worker: &dyn WorkerTaskContext,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+    objects: Vec<(String, [u8; 32])>,
+) -> Result<Vec<VerifyResult>, Error> {
+    if objects.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut tuner = verify_tuner(target)?;
+
+    let mut queue: VecDeque<VerifyItem> = objects
+        .into_iter()
+        .enumerate()
+        .map(|(index, (key, csum))| (index, key, csum))
+        .collect();
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let mut throttled_batches = 0;
+
+    while !queue.is_empty() {
+        let threads = tuner.threads();
+        let batch_size = queue.len().min(threads * VERIFY_BATCH_PER_THREAD);
+        let batch: Vec<VerifyItem> = queue.drain(..batch_size).collect();
+
+        let throttled = Arc::new(Mutex::new(Vec::new()));
+        let bytes = Arc::new(AtomicU64::new(0));
+
+        let pool = {
+            let backend = Arc::clone(backend);
+            let results = Arc::clone(&results);
+            let throttled = Arc::clone(&throttled);
+            let bytes = Arc::clone(&bytes);
+            ParallelHandler::new(
+                "cloud fsck verify",
+                threads,
+                move |(index, key, csum): VerifyItem| {
+                    let (digest, provider_checksum, size) = match object_digest(&*backend, &key) {
+                        Ok(result) => result,
+                        Err(err) if is_throttled(&err) => {
+                            throttled.lock().unwrap().push(((index, key, csum), err));
+                            return Ok(());
+                        }
+                        Err(err) => bail!("unable to read '{}' - {}", key, err),
+                    };
+                    bytes.fetch_add(size, Ordering::SeqCst);
+                    let result = VerifyResult {
+                        key,
+                        ok: digest == csum,
+                        provider_checksum,
+                    };
+                    results.lock().unwrap().push((index, result));
+                    Ok(())
+                },
+            )
+        };
+
+        tuner.start_batch();
+        for item in batch {
+            worker.check_abort()?;
+            pool.send(item)?;
+        }
+        pool.complete()?;
+
+        let throttled = std::mem::take(&mut *throttled.lock().unwrap());
+        if throttled.len() < batch_size {
+            throttled_batches = 0;
+        } else if threads == MIN_VERIFY_THREADS {
+            throttled_batches += 1;
+            if throttled_batches >= MAX_THROTTLED_BATCHES {
+                let (_, err) = throttled.into_iter().next().unwrap();
+                bail!("provider keeps throttling requests - {}", err);
+            }
+        }
+
+        let next_threads = tuner.finish_batch(bytes.load(Ordering::SeqCst), throttled.len());
+        if next_threads != threads {
+            task_log!(
+                worker,
+                "verifying with {} threads ({} requests throttled)",
+                next_threads,
+                throttled.len()
+            );
+        }
+        queue.extend(throttled.into_iter().map(|(item, _)| item));
+    }
+
+    let mut results = std::mem::take(&mut *results.lock().unwrap());
+    results.sort_by_key(|(index, _)| *index);
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+fn add_issue(
+    worker: &dyn WorkerTaskContext,
+    report: &mut CloudFsckReport,
+    kind: CloudFsckIssueKind,
+    key: String,
+) {
+    task_warn!(worker, "{}: {}", kind, key);
+    report.issues.push(CloudFsckIssue {
+        kind,
+        key,
+        repaired: false,
+    });
+}
+
+// the catalog of the media set on the target equals the local one
+fn remote_catalog_matches(
+    backend: &dyn CloudBackend,
+    media_set: &MediaSetCatalog,
+) -> Result<bool, Error> {
+    let data = backend.get_object(&layout::media_set_catalog_key(media_set.uuid()))?;
+    let remote: Value = serde_json::from_slice(&data)?;
+    Ok(remote == serde_json::to_value(media_set)?)
+}
+
+// rebuild damaged chunk archives parity could not repair from local chunks
+#[allow(clippy::too_many_arguments)]
+fn reupload_damaged(
+    worker: &dyn WorkerTaskContext,
+    base_path: &Path,
+    target: &CloudTarget,
+    catalog: &CloudCatalog,
+    backend: &Arc<dyn CloudBackend>,
+    lease: &mut CloudLease,
+    report: &mut CloudFsckReport,
+    load_chunk: ChunkSource,
+) -> Result<(), Error> {
+    let damaged: HashSet<&String> = report
+        .issues
+        .iter()
+        .filter(|issue| !issue.repaired)
+        .map(|issue| &issue.key)
+        .collect();
+
+    let mut archives = Vec::new();
+    for media_set in catalog.media_sets() {
+        for archive in media_set.archives.iter() {
+            if damaged.contains(&layout::chunk_archive_key(media_set.uuid(), &archive.uuid)) {
+                archives.push((media_set.uuid().clone(), archive.uuid.clone()));
+            }
+        }
+    }
+    if archives.is_empty() {
+        return Ok(());
+    }
+
+    let uploaded = reupload_chunk_archives(
+        worker, base_path, target, backend, lease, &archives, load_chunk,
+    )?;
+    task_log!(
+        worker,
+        "uploaded {} of {} damaged chunk archives from local chunks",
+        uploaded.len(),
+        archives.len()
+    );
+
+    let uploaded: HashSet<String> = uploaded
+        .iter()
+        .map(|(media_set, archive)| layout::chunk_archive_key(media_set, archive))
+        .collect();
+    for issue in report.issues.iter_mut() {
+        if uploaded.contains(&issue.key) {
+            issue.repaired = true;
+        }
+    }
+
+    Ok(())
+}
+
+// delete orphaned objects and dangling media sets
+fn delete_unreferenced(
+    worker: &dyn WorkerTaskContext,
+    catalog: &CloudCatalog,
+    backend: &Arc<dyn CloudBackend>,
+    stored: &BTreeMap<String, u64>,
+    lease: &mut CloudLease,
+    report: &mut CloudFsckReport,
+) -> Result<(), Error> {
+    let mut current: HashMap<Uuid, bool> = HashMap::new();
+
+    for issue in report.issues.iter_mut() {
+        worker.check_abort()?;
+
+        let keys: Vec<&String> = match issue.kind {
+            CloudFsckIssueKind::Orphaned => {
+                let media_set =
+                    object_media_set(&issue.key).and_then(|uuid| catalog.lookup_media_set(&uuid));
+                let media_set = match media_set {
+                    Some(media_set) => media_set,
+                    None => continue,
+                };
+                let matches = match current.get(media_set.uuid()) {
+                    Some(matches) => *matches,
+                    None => {
+                        let matches = remote_catalog_matches(&**backend, media_set)?;
+                        if !matches {
+                            task_warn!(
+                                worker,
+                                "local catalog of media set {} is outdated, not deleting orphaned objects",
+                                media_set.uuid()
+                            );
+                        }
+                        current.insert(media_set.uuid().clone(), matches);
+                        matches
+                    }
+                };
+                if !matches {
+                    continue;
+                }
+                stored
+                    .get_key_value(&issue.key)
+                    .map(|(key, _)| key)
+                    .into_iter()
+                    .collect()
+            }
+            CloudFsckIssueKind::DanglingMediaSet => {
+                // committed by a backup which finished after the listing
+                let uuid = object_media_set(&issue.key)
+                    .ok_or_else(|| format_err!("invalid media set prefix '{}'", issue.key))?;
+                if backend
+                    .head_object(&layout::media_set_catalog_key(&uuid))?
+                    .is_some()
+                {
+                    continue;
+                }
+                stored
+                    .range(issue.key.clone()..)
+                    .map(|(key, _)| key)
+                    .take_while(|key| key.starts_with(&issue.key))
+                    .collect()
+            }
+            _ => continue,
+        };
+
+        lease.heartbeat()?;
+
+        let mut failed = false;
+        for key in keys {
+            if let Err(err) = backend.delete_object(key) {
+                task_warn!(worker, "unable to delete '{}' - {}", key, err);
+                failed = true;
+            }
+        }
+        issue.repaired = !failed;
+    }
+
+    Ok(())
+}
+
+/// Check the objects of a target against the local catalog
+///
+/// Only objects below the media set and trash prefixes are considered.
+/// With `repair`, chunk archives which cannot be rebuilt from parity are
+/// rebuilt from the chunks returned by `load_chunk`. The report is stored
+/// as the last report of the target.
+pub fn fsck_target<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+    options: FsckOptions,
+    load_chunk: ChunkSource,
+) -> Result<CloudFsckReport, Error> {
+    let base_path = base_path.as_ref();
+    let catalog = CloudCatalog::load(base_path, &target.name)?;
+
+    let stored: BTreeMap<String, u64> = backend
+        .list_objects("")?
+        .into_iter()
+        .filter(|object| {
+            object.key.starts_with(layout::MEDIA_SET_PREFIX)
+                || object.key.starts_with(layout::TRASH_PREFIX)
+        })
+        .map(|object| (object.key, object.size))
+        .collect();
+
+    let mut report = CloudFsckReport {
+        time: proxmox_time::epoch_i64(),
+        objects: stored.len() as u64,
+        ..Default::default()
+    };
+
+    // damaged objects, in the form parity repair expects
+    let mut damaged = ReconcileResult::default();
+    let mut referenced = HashSet::new();
+    let mut to_verify = Vec::new();
+
+    for media_set in catalog.media_sets() {
+        worker.check_abort()?;
+
+        let mut expected = expected_objects(media_set);
+        expected.append(&mut expected_trash_objects(media_set));
+
+        for (key, size) in expected {
+            report.checked += 1;
+            match (stored.get(&key), size) {
+                (None, _) => {
+                    add_issue(
+                        worker,
+                        &mut report,
+                        CloudFsckIssueKind::Missing,
+                        key.clone(),
+                    );
+                    damaged.missing.push(key.clone());
+                }
+                (Some(stored_size), Some(size)) if *stored_size != size => {
+                    add_issue(
+                        worker,
+                        &mut report,
+                        CloudFsckIssueKind::SizeMismatch,
+                        key.clone(),
+                    );
+                    damaged.size_mismatch.push(key.clone());
+                }
+                _ => {}
+            }
+            referenced.insert(key);
+        }
+
+        referenced.extend(optional_objects(media_set));
+
+        if options.verify {
+            for (key, csum) in checksummed_objects(media_set) {
+                if stored.contains_key(&key) && !damaged.size_mismatch.contains(&key) {
+                    to_verify.push((key, csum));
+                }
+            }
+        }
+    }
+
+    for result in verify_objects(worker, target, backend, to_verify)? {
+        report.verified += 1;
+        if result.provider_checksum {
+            report.provider_checksums += 1;
+        }
+        if !result.ok {
+            add_issue(
+                worker,
+                &mut report,
+                CloudFsckIssueKind::DigestMismatch,
+                result.key.clone(),
+            );
+            damaged.size_mismatch.push(result.key);
+        }
+    }
+
+    // objects no catalog references
+    let mut unknown_sets = BTreeSet::new();
+    for key in stored.keys() {
+        if referenced.contains(key) {
+            continue;
+        }
+        match object_media_set(key) {
+            Some(uuid) if catalog.lookup_media_set(&uuid).is_some() => {
+                add_issue(
+                    worker,
+                    &mut report,
+                    CloudFsckIssueKind::Orphaned,
+                    key.clone(),
+                );
+            }
+            Some(uuid) if key.starts_with(layout::MEDIA_SET_PREFIX) => {
+                unknown_sets.insert(uuid.to_string());
+            }
+            _ => add_issue(
+                worker,
+                &mut report,
+                CloudFsckIssueKind::Orphaned,
+                key.clone(),
+            ),
+        }
+    }
+    for uuid in unknown_sets {
+        let uuid: Uuid = uuid.parse()?;
+        let kind = if stored.contains_key(&layout::media_set_catalog_key(&uuid)) {
+            CloudFsckIssueKind::UnknownMediaSet
+        } else {
+            CloudFsckIssueKind::DanglingMediaSet
+        };
+        add_issue(worker, &mut report, kind, layout::media_set_prefix(&uuid));
+    }
+
+    task_log!(
+        worker,
+        "checked {} of {} objects, verified {} checksums ({} by provider checksum, {} downloaded), \
+        found {} problems",
+        report.checked,
+        report.objects,
+        report.verified,
+        report.provider_checksums,
+        report.verified - report.provider_checksums,
+        report.issues.len()
+    );
+
+    if options.repair && !report.issues.is_empty() {
+        if !damaged.is_ok() {
+            let repaired = repair_target(worker, base_path, target, backend, &damaged)?;
+            for issue in report.issues.iter_mut() {
+                if repaired.contains(&issue.key) {
+                    issue.repaired = true;
+                }
+            }
+        }
+
+        let mut lease =
+            CloudLease::acquire(Arc::clone(backend), proxmox_sys::nodename(), LEASE_TIMEOUT)?;
+        reupload_damaged(
+            worker,
+            base_path,
+            target,
+            &catalog,
+            backend,
+            &mut lease,
+            &mut report,
+            load_chunk,
+        )?;
+        // checksums of encrypted archives may have changed
+        let catalog = CloudCatalog::load(base_path, &target.name)?;
+        delete_unreferenced(worker, &catalog, backend, &stored, &mut lease, &mut report)?;
+        lease.release()?;
+
+        let repaired = report.issues.len() - report.unrepaired();
+        task_log!(worker, "repaired {} problems", repaired);
+    }
+
+    save_fsck_report(base_path, &target.name, &report)?;
+
+    Ok(report)
+}
@@ -0,0 +1,138 @@
+//! Health checks of cloud targets
+//!
+//! The proxy checks all targets with `health-check` enabled every
+//! [`HEALTH_CHECK_INTERVAL`] seconds with a single metadata request, and
+//! keeps the last [`HEALTH_HISTORY_SIZE`] results per target. Unreachable
+//! targets (expired credentials, deleted buckets, network problems) thus
+//! show up before the next job fails.
+
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{format_err, Error};
+
+use proxmox_sys::fs::{create_path, replace_file};
+
+use pbs_api_types::{CloudHealthSample, CloudTarget, CloudTargetHealth};
+
+use super::backend::CloudBackend;
+use super::create_options;
+use super::layout::LEASE_KEY;
+
+/// Seconds between two health checks of a target
+pub const HEALTH_CHECK_INTERVAL: i64 = 300;
+
+/// Number of recorded health checks per target (one day)
+pub const HEALTH_HISTORY_SIZE: usize = 288;
+
+/// Whether periodic health checks are enabled for a target
+pub fn health_check_enabled(target: &CloudTarget) -> bool {
+    target.config.health_check.unwrap_or(true)
+}
+
+/// Check a target with a single metadata request
+///
+/// The lease object may or may not exist, only errors count.
+pub fn check_target_health(backend: &dyn CloudBackend) -> CloudHealthSample {
+    let time = proxmox_time::epoch_i64();
+    let start = Instant::now();
+    match backend.head_object(LEASE_KEY) {
+        Ok(_) => CloudHealthSample {
+            time,
+            latency: Some(start.elapsed().as_millis() as u64),
+            error: None,
+        },
+        Err(err) => CloudHealthSample {
+            time,
+            latency: None,
+            error: Some(err.to_string()),
+        },
+    }
+}
+
+fn health_path(base_path: &Path, target: &str) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("health");
+    path.push(format!("{}.json", target));
+    path
+}
+
+/// Recorded health checks of a target, oldest first
+pub fn load_health_history<P: AsRef<Path>>(
+    base_path: P,
+    target: &str,
+) -> Result<Vec<CloudHealthSample>, Error> {
+    let path = health_path(base_path.as_ref(), target);
+    match proxmox_sys::fs::file_get_optional_contents(&path)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Record the result of a health check, dropping the oldest results
+pub fn record_health_sample<P: AsRef<Path>>(
+    base_path: P,
+    target: &str,
+    sample: CloudHealthSample,
+) -> Result<(), Error> {
+    let base_path = base_path.as_ref();
+
+    let mut history = load_health_history(base_path, target)?;
+    history.push(sample);
+    if history.len() > HEALTH_HISTORY_SIZE {
+        history.drain(..history.len() - HEALTH_HISTORY_SIZE);
+    }
+
+    let path = health_path(base_path, target);
+    if let Some(parent) = path.parent() {
+        create_path(
+            parent,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+    }
+    replace_file(
+        &path,
+        &serde_json::to_vec(&history)?,
+        create_options(0o0640)?,
+        true,
+    )
+}
+
+/// Whether the next health check of a target is due at `now`
+pub fn health_check_due(history: &[CloudHealthSample], now: i64) -> bool {
+    match history.last() {
+        Some(last) => now - last.time >= HEALTH_CHECK_INTERVAL || last.time > now,
+        None => true,
+    }
+}
+
+/// Summarize the recorded health checks of a target
+pub fn target_health(target: &str, history: Vec<CloudHealthSample>) -> CloudTargetHealth {
+    let mut health = CloudTargetHealth {
+        target: target.to_string(),
+        ..Default::default()
+    };
+
+    if let Some(last) = history.last() {
+        health.reachable = last.error.is_none();
+        health.last_check = Some(last.time);
+    }
+    if let Some(success) = history.iter().rev().find(|sample| sample.error.is_none()) {
+        health.last_success = Some(success.time);
+        health.latency = success.latency;
+    }
+    health.last_error = history.iter().rev().find_map(|sample| sample.error.clone());
+
+    if !history.is_empty() {
+        let successful = history
+            .iter()
+            .filter(|sample| sample.error.is_none())
+            .count();
+        health.availability = Some(successful as f64 * 100.0 / history.len() as f64);
+    }
+
+    health.history = history;
+    health
+}
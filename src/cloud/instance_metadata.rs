@@ -0,0 +1,304 @@
+//! Cloud instance metadata
+//!
+//! Identifies the instance the server runs on, using the metadata service
+//! of the cloud provider (EC2 IMDSv2, GCE and Azure IMDS). The provider is
+//! guessed from the DMI system vendor, so only one service is queried on
+//! known clouds. Each request is limited to [`METADATA_TIMEOUT`], outside of
+//! a cloud the provider is reported as `none`.
+//!
+//! Instance information does not change while the system is running, so
+//! the result is cached (failed lookups on a known cloud are retried).
+//!
+//! On EC2, the temporary credentials of the instance profile role are
+//! available as well (see [`instance_role_credentials`]).
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+use hyper::client::{Client, HttpConnector};
+use hyper::{Body, Method, Request};
+use serde_json::Value;
+
+use pbs_api_types::CloudNodeInformation;
+
+use super::backend::credentials::CloudCredentials;
+
+/// Timeout of a single metadata request
+pub const METADATA_TIMEOUT: Duration = Duration::from_secs(2);
+
+const METADATA_HOST: &str = "http://169.254.169.254";
+const DMI_SYS_VENDOR: &str = "/sys/class/dmi/id/sys_vendor";
+
+// token lifetime requested for EC2 IMDSv2 (we only need it once)
+const EC2_TOKEN_TTL: &str = "60";
+
+static INSTANCE_INFO: Mutex<Option<CloudNodeInformation>> = Mutex::new(None);
+
+/// Cloud providers with a supported metadata service
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloudProvider {
+    Aws,
+    Gcp,
+    Azure,
+}
+
+impl CloudProvider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CloudProvider::Aws => "aws",
+            CloudProvider::Gcp => "gcp",
+            CloudProvider::Azure => "azure",
+        }
+    }
+}
+
+/// Provider of a DMI system vendor
+pub fn provider_from_vendor(vendor: &str) -> Option<CloudProvider> {
+    match vendor.trim() {
+        "Amazon EC2" => Some(CloudProvider::Aws),
+        "Google" => Some(CloudProvider::Gcp),
+        "Microsoft Corporation" => Some(CloudProvider::Azure),
+        _ => None,
+    }
+}
+
+/// Zone name of a GCE zone path (`projects/<project>/zones/<zone>`)
+pub fn parse_gce_zone(zone: &str) -> String {
+    zone.trim()
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Instance information of an Azure IMDS `compute` object
+///
+/// VMs without availability zone report their region instead.
+pub fn parse_azure_compute(compute: &Value) -> Result<CloudNodeInformation, Error> {
+    let instance_id = compute["vmId"]
+        .as_str()
+        .ok_or_else(|| format_err!("missing 'vmId' in azure metadata"))?;
+    let location = compute["location"].as_str().unwrap_or_default();
+    let availability_zone = match compute["zone"].as_str() {
+        Some(zone) if !zone.is_empty() => format!("{}-{}", location, zone),
+        _ => location.to_string(),
+    };
+
+    Ok(CloudNodeInformation {
+        instance_id: instance_id.to_string(),
+        availability_zone,
+        provider: CloudProvider::Azure.as_str().to_string(),
+    })
+}
+
+/// Credentials of an EC2 instance profile role
+///
+/// `data` is the JSON document returned for the role by IMDS.
+pub fn parse_role_credentials(data: &Value) -> Result<CloudCredentials, Error> {
+    if let Some(code) = data["Code"].as_str() {
+        if code != "Success" {
+            bail!("instance role credentials not available - {}", code);
+        }
+    }
+    let field = |name: &str| {
+        data[name]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| format_err!("missing '{}' in instance role credentials", name))
+    };
+    let expires = match data["Expiration"].as_str() {
+        Some(expiration) => Some(proxmox_time::parse_rfc3339(expiration)?),
+        None => None,
+    };
+    Ok(CloudCredentials {
+        access_key: field("AccessKeyId")?,
+        secret_key: field("SecretAccessKey")?,
+        session_token: Some(field("Token")?),
+        expires,
+    })
+}
+
+struct MetadataClient {
+    client: Client<HttpConnector>,
+}
+
+impl MetadataClient {
+    fn new() -> Self {
+        // link-local, so never through a proxy
+        Self {
+            client: Client::new(),
+        }
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<String, Error> {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", METADATA_HOST, path));
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let request = request.body(Body::empty())?;
+
+        let response = tokio::time::timeout(METADATA_TIMEOUT, self.client.request(request))
+            .await
+            .map_err(|_| format_err!("metadata request timed out"))??;
+        let status = response.status();
+        let body = tokio::time::timeout(METADATA_TIMEOUT, hyper::body::to_bytes(response))
+            .await
+            .map_err(|_| format_err!("metadata request timed out"))??;
+        if !status.is_success() {
+            bail!("metadata request {} failed - {}", path, status);
+        }
+        Ok(String::from_utf8(body.to_vec())?)
+    }
+
+    async fn aws_token(&self) -> Result<String, Error> {
+        let token = self
+            .request(
+                Method::PUT,
+                "/latest/api/token",
+                &[("X-aws-ec2-metadata-token-ttl-seconds", EC2_TOKEN_TTL)],
+            )
+            .await?;
+        Ok(token.trim().to_string())
+    }
+
+    async fn query_aws_role_credentials(&self) -> Result<CloudCredentials, Error> {
+        let token = self.aws_token().await?;
+        let headers = [("X-aws-ec2-metadata-token", token.as_str())];
+
+        let roles = self
+            .request(
+                Method::GET,
+                "/latest/meta-data/iam/security-credentials/",
+                &headers,
+            )
+            .await?;
+        let role = match roles.lines().map(str::trim).find(|line| !line.is_empty()) {
+            Some(role) => role.to_string(),
+            None => bail!("no instance profile role attached"),
+        };
+        let data = self
+            .request(
+                Method::GET,
+                &format!("/latest/meta-data/iam/security-credentials/{}", role),
+                &headers,
+            )
+            .await?;
+        parse_role_credentials(&serde_json::from_str(&data)?)
+    }
+
+    async fn query_aws(&self) -> Result<CloudNodeInformation, Error> {
+        let token = self.aws_token().await?;
+        let headers = [("X-aws-ec2-metadata-token", token.as_str())];
+
+        let instance_id = self
+            .request(Method::GET, "/latest/meta-data/instance-id", &headers)
+            .await?;
+        let availability_zone = self
+            .request(
+                Method::GET,
+                "/latest/meta-data/placement/availability-zone",
+                &headers,
+            )
+            .await?;
+
+        Ok(CloudNodeInformation {
+            instance_id: instance_id.trim().to_string(),
+            availability_zone: availability_zone.trim().to_string(),
+            provider: CloudProvider::Aws.as_str().to_string(),
+        })
+    }
+
+    async fn query_gcp(&self) -> Result<CloudNodeInformation, Error> {
+        let headers = [("Metadata-Flavor", "Google")];
+
+        let instance_id = self
+            .request(Method::GET, "/computeMetadata/v1/instance/id", &headers)
+            .await?;
+        let zone = self
+            .request(Method::GET, "/computeMetadata/v1/instance/zone", &headers)
+            .await?;
+
+        Ok(CloudNodeInformation {
+            instance_id: instance_id.trim().to_string(),
+            availability_zone: parse_gce_zone(&zone),
+            provider: CloudProvider::Gcp.as_str().to_string(),
+        })
+    }
+
+    async fn query_azure(&self) -> Result<CloudNodeInformation, Error> {
+        let compute = self
+            .request(
+                Method::GET,
+                "/metadata/instance/compute?api-version=2021-02-01",
+                &[("Metadata", "true")],
+            )
+            .await?;
+        parse_azure_compute(&serde_json::from_str(&compute)?)
+    }
+
+    async fn query(&self, provider: CloudProvider) -> Result<CloudNodeInformation, Error> {
+        match provider {
+            CloudProvider::Aws => self.query_aws().await,
+            CloudProvider::Gcp => self.query_gcp().await,
+            CloudProvider::Azure => self.query_azure().await,
+        }
+    }
+}
+
+fn no_instance() -> CloudNodeInformation {
+    CloudNodeInformation {
+        instance_id: String::new(),
+        availability_zone: String::new(),
+        provider: "none".to_string(),
+    }
+}
+
+/// Information about the cloud instance the server runs on
+pub async fn instance_information() -> CloudNodeInformation {
+    if let Some(info) = INSTANCE_INFO.lock().unwrap().clone() {
+        return info;
+    }
+
+    let vendor = std::fs::read_to_string(DMI_SYS_VENDOR).unwrap_or_default();
+    let (candidates, known_cloud) = match provider_from_vendor(&vendor) {
+        Some(provider) => (vec![provider], true),
+        None => (
+            vec![CloudProvider::Aws, CloudProvider::Gcp, CloudProvider::Azure],
+            false,
+        ),
+    };
+
+    let client = MetadataClient::new();
+    for provider in candidates {
+        match client.query(provider).await {
+            Ok(info) => {
+                *INSTANCE_INFO.lock().unwrap() = Some(info.clone());
+                return info;
+            }
+            Err(err) => log::debug!("no {} instance metadata - {}", provider.as_str(), err),
+        }
+    }
+
+    let info = no_instance();
+    // on a known cloud, the metadata service may just be unavailable for now
+    if !known_cloud {
+        *INSTANCE_INFO.lock().unwrap() = Some(info.clone());
+    }
+    info
+}
+
+/// Temporary credentials of the EC2 instance profile role
+pub async fn instance_role_credentials() -> Result<CloudCredentials, Error> {
+    MetadataClient::new()
+        .query_aws_role_credentials()
+        .await
+        .map_err(|err| format_err!("unable to get instance role credentials - {}", err))
+}
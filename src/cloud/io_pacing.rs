@@ -0,0 +1,159 @@
+//! Upload pacing to protect local datastore I/O
+//!
+//! With `local-io-latency`, the chunk reader of a backup job measures how
+//! long reading chunks from the datastore takes. While other operations
+//! (backups, restores, verification, ...) are active on the datastore and
+//! the read latency exceeds the threshold, the job halves its transfer
+//! rate, down to `local-io-min-rate`. Once the latency drops, the rate
+//! recovers step by step, and the limit is lifted when the datastore is
+//! not used by others anymore.
+//!
+//! Pacing the chunk reader throttles the uploads as well, since only a
+//! few chunks are buffered between reader and uploader.
+
+use std::time::{Duration, Instant};
+
+use pbs_api_types::CloudBackupJobSetup;
+
+/// Upload rate guaranteed while local I/O is protected (bytes/second)
+pub const DEFAULT_LOCAL_IO_MIN_RATE: u64 = 10_000_000;
+
+/// Interval between rate adjustments
+const ADJUST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Weight of new samples in the average read latency
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// Longest single pause, so aborts are noticed in time
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Paces the chunk reader of a backup job
+pub struct IoPacer {
+    store: String,
+    threshold: Duration,
+    min_rate: u64,
+    // average read latency (seconds)
+    latency: Option<f64>,
+    // current rate limit, `None` if unlimited
+    rate: Option<u64>,
+    window_start: Instant,
+    window_bytes: u64,
+    throttled: Duration,
+}
+
+impl IoPacer {
+    /// Pacer for a job, `None` if the job does not protect local I/O
+    pub fn from_job_setup(setup: &CloudBackupJobSetup) -> Option<Self> {
+        let threshold = Duration::from_millis(setup.local_io_latency?);
+        let min_rate = setup
+            .local_io_min_rate
+            .map(|rate| rate.as_u64())
+            .unwrap_or(DEFAULT_LOCAL_IO_MIN_RATE);
+        Some(Self::new(&setup.store, threshold, min_rate))
+    }
+
+    pub fn new(store: &str, threshold: Duration, min_rate: u64) -> Self {
+        Self {
+            store: store.to_string(),
+            threshold,
+            min_rate: min_rate.max(1),
+            latency: None,
+            rate: None,
+            window_start: Instant::now(),
+            window_bytes: 0,
+            throttled: Duration::ZERO,
+        }
+    }
+
+    pub fn threshold(&self) -> Duration {
+        self.threshold
+    }
+
+    pub fn min_rate(&self) -> u64 {
+        self.min_rate
+    }
+
+    /// Current rate limit (bytes/second), `None` if unlimited
+    pub fn rate(&self) -> Option<u64> {
+        self.rate
+    }
+
+    /// Time spent waiting so far
+    pub fn throttled(&self) -> Duration {
+        self.throttled
+    }
+
+    /// Record a chunk read, returns how long the reader has to wait
+    pub fn record_read(&mut self, bytes: u64, elapsed: Duration) -> Duration {
+        let store = self.store.clone();
+        self.record_read_at(bytes, elapsed, Instant::now(), || {
+            other_operations_active(&store)
+        })
+    }
+
+    /// Like [`Self::record_read`], with an explicit time and activity check
+    pub fn record_read_at<F: FnOnce() -> bool>(
+        &mut self,
+        bytes: u64,
+        elapsed: Duration,
+        now: Instant,
+        busy: F,
+    ) -> Duration {
+        let sample = elapsed.as_secs_f64();
+        self.latency = Some(match self.latency {
+            Some(latency) => latency + LATENCY_WEIGHT * (sample - latency),
+            None => sample,
+        });
+        self.window_bytes += bytes;
+
+        let window = now.saturating_duration_since(self.window_start);
+        if window >= ADJUST_INTERVAL {
+            self.adjust(busy(), window);
+            self.window_start = now;
+            self.window_bytes = 0;
+            return Duration::ZERO;
+        }
+
+        let rate = match self.rate {
+            Some(rate) => rate,
+            None => return Duration::ZERO,
+        };
+        let due = Duration::from_secs_f64(self.window_bytes as f64 / rate as f64);
+        let delay = due.saturating_sub(window).min(MAX_DELAY);
+        self.throttled += delay;
+        delay
+    }
+
+    fn adjust(&mut self, busy: bool, window: Duration) {
+        let congested = self
+            .latency
+            .map(|latency| latency > self.threshold.as_secs_f64())
+            .unwrap_or(false);
+
+        self.rate = match (busy, congested, self.rate) {
+            // nobody else uses the datastore
+            (false, _, _) => None,
+            (true, true, rate) => {
+                let current = rate.unwrap_or_else(|| {
+                    (self.window_bytes as f64 / window.as_secs_f64().max(0.001)) as u64
+                });
+                Some((current / 2).max(self.min_rate))
+            }
+            (true, false, Some(rate)) => Some(rate.saturating_add(rate / 4).max(self.min_rate)),
+            (true, false, None) => None,
+        };
+    }
+}
+
+/// Check for operations of other tasks on a datastore
+///
+/// The backup job itself holds one read operation.
+pub fn other_operations_active(store: &str) -> bool {
+    match pbs_datastore::task_tracking::get_active_operations(store) {
+        Ok(active) => active.read > 1 || active.write > 0,
+        Err(err) => {
+            log::warn!("unable to read active operations of '{}' - {}", store, err);
+            false
+        }
+    }
+}
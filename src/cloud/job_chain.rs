@@ -0,0 +1,230 @@
+//! Job chaining (`run-after`)
+//!
+//! Instead of a calendar schedule, a cloud backup or replication job can
+//! run after each successful run of another cloud job, e.g. replicate a
+//! target right after the backup to it finished. The scheduler starts a
+//! chained job once the last run of its trigger finished successfully
+//! after the last run of the chained job.
+//!
+//! For each chained run, the trigger is remembered locally, so the job
+//! status can tell scheduled and chained runs apart.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_rest_server::TaskState;
+use proxmox_section_config::SectionConfigData;
+use proxmox_sys::fs::{create_path, replace_file};
+
+use crate::server::jobstate::{last_run_time, JobState};
+
+use super::create_options;
+
+/// Worker type of the jobs of a cloud job config section type
+pub fn cloud_job_worker_type(section_type: &str) -> Option<&'static str> {
+    match section_type {
+        "backup" => Some("cloud-backup-job"),
+        "replication" => Some("cloud-replication-job"),
+        _ => None,
+    }
+}
+
+fn run_after_of(config: &SectionConfigData, id: &str) -> Option<String> {
+    let (_, data) = config.sections.get(id)?;
+    data["run-after"].as_str().map(String::from)
+}
+
+/// Check the `run-after` option of job `job_id`
+///
+/// The trigger must be a cloud backup or replication job, and following
+/// the `run-after` options from there must not lead back to the job.
+pub fn check_run_after(
+    config: &SectionConfigData,
+    job_id: &str,
+    run_after: &str,
+) -> Result<(), Error> {
+    match config.sections.get(run_after) {
+        Some((section_type, _)) if cloud_job_worker_type(section_type).is_some() => {}
+        _ => bail!(
+            "cloud backup or replication job '{}' does not exist",
+            run_after
+        ),
+    }
+
+    let mut chain = vec![job_id.to_string()];
+    let mut next = Some(run_after.to_string());
+
+    while let Some(id) = next {
+        chain.push(id.clone());
+        if id == job_id {
+            bail!("job chain contains a cycle ({})", chain.join(" -> "));
+        }
+        if chain[..chain.len() - 1].contains(&id) {
+            break; // an existing cycle not involving this job
+        }
+        next = run_after_of(config, &id);
+    }
+
+    Ok(())
+}
+
+/// Jobs which have `job_id` as `run-after` trigger
+pub fn chained_jobs(config: &SectionConfigData, job_id: &str) -> Vec<String> {
+    let mut list: Vec<String> = config
+        .sections
+        .keys()
+        .filter(|id| run_after_of(config, id).as_deref() == Some(job_id))
+        .cloned()
+        .collect();
+    list.sort();
+    list
+}
+
+/// Provenance of a chained job run
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ChainedRun {
+    /// The triggering job
+    pub trigger: String,
+    /// Task UPID of the triggering run
+    pub trigger_upid: String,
+    /// Task UPID of the chained run
+    pub upid: String,
+}
+
+fn chained_run_path(base_path: &Path, job_type: &str, job_id: &str) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("job-chain");
+    path.push(format!("{}-{}.json", job_type, job_id));
+    path
+}
+
+/// Remember the trigger of a chained run
+pub fn save_chained_run<P: AsRef<Path>>(
+    base_path: P,
+    job_type: &str,
+    job_id: &str,
+    run: &ChainedRun,
+) -> Result<(), Error> {
+    let path = chained_run_path(base_path.as_ref(), job_type, job_id);
+    if let Some(parent) = path.parent() {
+        create_path(
+            parent,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+    }
+    let data = serde_json::to_vec(run)?;
+    replace_file(path, &data, create_options(0o0640)?, true)
+}
+
+/// The last chained run of a job
+pub fn load_chained_run<P: AsRef<Path>>(
+    base_path: P,
+    job_type: &str,
+    job_id: &str,
+) -> Result<Option<ChainedRun>, Error> {
+    let path = chained_run_path(base_path.as_ref(), job_type, job_id);
+    match proxmox_sys::fs::file_get_optional_contents(&path)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        None => Ok(None),
+    }
+}
+
+/// The trigger of a job run, if `upid` was a chained run
+pub fn run_triggered_by<P: AsRef<Path>>(
+    base_path: P,
+    job_type: &str,
+    job_id: &str,
+    upid: Option<&str>,
+) -> Option<String> {
+    let run = load_chained_run(base_path, job_type, job_id).ok()??;
+    (Some(run.upid.as_str()) == upid).then_some(run.trigger)
+}
+
+/// A chained job which is due to run
+#[derive(Clone, Debug, PartialEq)]
+pub struct DueChainedJob {
+    /// Config section type of the job
+    pub section_type: String,
+    pub job_id: String,
+    pub trigger: String,
+    pub trigger_upid: String,
+}
+
+/// Check if a trigger run finished successfully after `last_run`
+///
+/// Returns the UPID of the trigger run.
+pub fn trigger_completed(state: &JobState, last_run: i64) -> Option<String> {
+    match state {
+        JobState::Finished {
+            upid,
+            state: state @ (TaskState::OK { .. } | TaskState::Warning { .. }),
+            ..
+        } if state.endtime() > last_run => Some(upid.clone()),
+        _ => None,
+    }
+}
+
+/// Chained jobs whose trigger completed since their last run
+pub fn due_chained_jobs(config: &SectionConfigData) -> Vec<DueChainedJob> {
+    let mut list = Vec::new();
+
+    for (job_id, (section_type, data)) in config.sections.iter() {
+        let worker_type = match cloud_job_worker_type(section_type) {
+            Some(worker_type) => worker_type,
+            None => continue,
+        };
+        let trigger = match data["run-after"].as_str() {
+            Some(trigger) => trigger,
+            None => continue,
+        };
+        if data["disable"].as_bool().unwrap_or(false) {
+            continue;
+        }
+
+        let trigger_type = match config
+            .sections
+            .get(trigger)
+            .and_then(|(section_type, _)| cloud_job_worker_type(section_type))
+        {
+            Some(trigger_type) => trigger_type,
+            None => {
+                log::warn!("trigger '{trigger}' of cloud job '{job_id}' does not exist");
+                continue;
+            }
+        };
+
+        let last_run = match last_run_time(worker_type, job_id) {
+            Ok(time) => time,
+            Err(err) => {
+                log::error!("could not get last run time of {worker_type} {job_id}: {err}");
+                continue;
+            }
+        };
+
+        let trigger_upid = match JobState::load(trigger_type, trigger) {
+            Ok(state) => match trigger_completed(&state, last_run) {
+                Some(upid) => upid,
+                None => continue,
+            },
+            Err(err) => {
+                log::error!("could not load job state of {trigger_type} {trigger}: {err}");
+                continue;
+            }
+        };
+
+        list.push(DueChainedJob {
+            section_type: section_type.clone(),
+            job_id: job_id.clone(),
+            trigger: trigger.to_string(),
+            trigger_upid,
+        });
+    }
+
+    list
+}
@@ -0,0 +1,190 @@
+//! Per-group error handling of backup jobs
+//!
+//! A snapshot which fails to back up does not stop the job by default.
+//! The job's `on-error` policy decides whether it continues with the
+//! remaining snapshots, skips the rest of the failed group, or stops.
+//! Errors are collected per group for the job summary (notification and
+//! post-hook), and the outcome tells a partially successful run from one
+//! where nothing could be backed up.
+//!
+//! Errors reported by the provider keep its error code and request ID,
+//! so failures can be looked up in the provider's logs.
+
+use std::collections::HashSet;
+
+use anyhow::Error;
+use serde::Serialize;
+
+use pbs_api_types::CloudErrorPolicy;
+
+use crate::cloud::backend::{error_message, provider_error};
+
+/// A snapshot which could not be backed up
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SnapshotError {
+    pub snapshot: String,
+    pub error: String,
+    /// Error code reported by the provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// ID of the failed provider request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl SnapshotError {
+    pub fn new(snapshot: &str, error: &Error) -> Self {
+        let provider_err = provider_error(error);
+        Self {
+            snapshot: snapshot.to_string(),
+            error: error_message(error),
+            code: provider_err.and_then(|err| err.code.clone()),
+            request_id: provider_err.and_then(|err| err.request_id.clone()),
+        }
+    }
+}
+
+/// Errors of a backup group
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GroupErrors {
+    /// Datastore, namespace and group (`store:ns/type/id`)
+    pub group: String,
+    pub errors: Vec<SnapshotError>,
+    /// Snapshots not attempted because of `abort-group` or `abort-job`
+    pub skipped: u64,
+}
+
+/// What to do after a failed snapshot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorAction {
+    /// Continue with the next snapshot
+    Continue,
+    /// Stop the job
+    Abort,
+}
+
+/// Outcome of a backup run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobOutcome {
+    /// All snapshots were backed up
+    Success,
+    /// Some snapshots failed, others were backed up
+    PartialSuccess,
+    /// Snapshots failed and none was backed up
+    Failed,
+}
+
+/// Tracks the failed snapshots of a backup run
+pub struct JobErrors {
+    policy: CloudErrorPolicy,
+    groups: Vec<GroupErrors>,
+    aborted_groups: HashSet<usize>,
+    aborted: bool,
+    succeeded: u64,
+}
+
+impl JobErrors {
+    pub fn new(policy: CloudErrorPolicy) -> Self {
+        Self {
+            policy,
+            groups: Vec::new(),
+            aborted_groups: HashSet::new(),
+            aborted: false,
+            succeeded: 0,
+        }
+    }
+
+    pub fn policy(&self) -> CloudErrorPolicy {
+        self.policy
+    }
+
+    fn group_mut(&mut self, group_name: &str) -> &mut GroupErrors {
+        match self
+            .groups
+            .iter()
+            .position(|entry| entry.group == group_name)
+        {
+            Some(pos) => &mut self.groups[pos],
+            None => {
+                self.groups.push(GroupErrors {
+                    group: group_name.to_string(),
+                    errors: Vec::new(),
+                    skipped: 0,
+                });
+                self.groups.last_mut().unwrap()
+            }
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        self.succeeded += 1;
+    }
+
+    /// Record a failed snapshot of group `group_number`
+    pub fn record_error(
+        &mut self,
+        group_number: usize,
+        group_name: &str,
+        snapshot: &str,
+        error: &Error,
+    ) -> ErrorAction {
+        self.group_mut(group_name)
+            .errors
+            .push(SnapshotError::new(snapshot, error));
+        match self.policy {
+            CloudErrorPolicy::Continue => ErrorAction::Continue,
+            CloudErrorPolicy::AbortGroup => {
+                self.aborted_groups.insert(group_number);
+                ErrorAction::Continue
+            }
+            CloudErrorPolicy::AbortJob => {
+                self.aborted = true;
+                ErrorAction::Abort
+            }
+        }
+    }
+
+    /// Check if a snapshot has to be skipped, counts it if so
+    pub fn skip_snapshot(&mut self, group_number: usize, group_name: &str) -> bool {
+        if !self.aborted_groups.contains(&group_number) {
+            return false;
+        }
+        self.group_mut(group_name).skipped += 1;
+        true
+    }
+
+    /// Count snapshots left over when the job was stopped by `abort-job`
+    pub fn record_not_attempted(&mut self, group_name: &str) {
+        self.group_mut(group_name).skipped += 1;
+    }
+
+    /// The job was stopped by `abort-job`
+    pub fn aborted(&self) -> bool {
+        self.aborted
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.groups.iter().any(|group| !group.errors.is_empty())
+    }
+
+    /// Number of failed snapshots
+    pub fn error_count(&self) -> usize {
+        self.groups.iter().map(|group| group.errors.len()).sum()
+    }
+
+    pub fn groups(&self) -> &[GroupErrors] {
+        &self.groups
+    }
+
+    pub fn outcome(&self) -> JobOutcome {
+        if !self.has_errors() {
+            JobOutcome::Success
+        } else if self.succeeded > 0 {
+            JobOutcome::PartialSuccess
+        } else {
+            JobOutcome::Failed
+        }
+    }
+}
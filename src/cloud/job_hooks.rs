@@ -0,0 +1,236 @@
+//! Hook commands around cloud jobs
+//!
+//! Cloud backup and replication jobs can run a command before (`pre-hook`)
+//! and after (`post-hook`) the job, e.g. to quiesce an application or to
+//! update an external dashboard. Hooks get the job information as JSON on
+//! stdin, their output ends up in the task log.
+//!
+//! The post-hook always runs once the pre-hook was started, so it can undo
+//! what the pre-hook did even if the job failed.
+
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Error};
+use serde_json::{json, Value};
+
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{CloudHookFailure, CloudJobHooks};
+
+/// Default timeout of hook commands (seconds)
+pub const DEFAULT_HOOK_TIMEOUT: u64 = 300;
+
+/// Interval to check if a hook command finished
+const HOOK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Which hook to run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HookPhase {
+    Pre,
+    Post,
+}
+
+impl HookPhase {
+    fn name(&self) -> &'static str {
+        match self {
+            HookPhase::Pre => "pre-hook",
+            HookPhase::Post => "post-hook",
+        }
+    }
+}
+
+/// Job information passed to a hook
+///
+/// `result` and `summary` are only set for the post-hook.
+pub fn hook_input(
+    phase: HookPhase,
+    job_type: &str,
+    job_id: &str,
+    upid: &str,
+    result: Option<&Result<(), Error>>,
+    summary: Value,
+) -> Value {
+    let mut input = json!({
+        "phase": phase.name(),
+        "job-type": job_type,
+        "job-id": job_id,
+        "upid": upid,
+    });
+    if let Some(result) = result {
+        input["result"] = match result {
+            Ok(()) => "ok".into(),
+            Err(_) => "error".into(),
+        };
+        if let Err(err) = result {
+            input["error"] = err.to_string().into();
+        }
+        input["summary"] = summary;
+    }
+    input
+}
+
+// read a pipe in a separate thread, so the command cannot block on a full pipe
+fn read_pipe<R: Read + Send + 'static>(mut pipe: R) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut data = Vec::new();
+        let _ = pipe.read_to_end(&mut data);
+        data
+    })
+}
+
+fn log_output(worker: &dyn WorkerTaskContext, name: &str, output: &[u8]) {
+    for line in String::from_utf8_lossy(output).lines() {
+        task_log!(worker, "{}: {}", name, line);
+    }
+}
+
+/// Run a hook command
+///
+/// Runs as `user` if set, which needs root privileges unless it is the
+/// user running the job. The command is killed after `timeout` seconds.
+pub fn run_hook_command(
+    worker: &dyn WorkerTaskContext,
+    name: &str,
+    command: &str,
+    user: Option<&str>,
+    timeout: u64,
+    input: &Value,
+) -> Result<(), Error> {
+    let mut cmd = Command::new("/bin/sh");
+    cmd.arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    if let Some(user) = user {
+        let user = nix::unistd::User::from_name(user)?
+            .ok_or_else(|| format_err!("user '{}' does not exist", user))?;
+        let euid = nix::unistd::geteuid();
+        if user.uid != euid {
+            if !euid.is_root() {
+                bail!(
+                    "cannot run {} as user '{}' - the job does not run as root",
+                    name,
+                    user.name
+                );
+            }
+            cmd.uid(user.uid.as_raw()).gid(user.gid.as_raw());
+        }
+    }
+
+    task_log!(worker, "run {}", name);
+
+    let mut child = cmd
+        .spawn()
+        .map_err(|err| format_err!("unable to run {} - {}", name, err))?;
+
+    let stdout = read_pipe(child.stdout.take().unwrap());
+    let stderr = read_pipe(child.stderr.take().unwrap());
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // the command does not need to read its input
+        let _ = stdin.write_all(&serde_json::to_vec(input)?);
+    }
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if start.elapsed() >= Duration::from_secs(timeout) {
+            let _ = child.kill();
+            let _ = child.wait();
+            break None;
+        }
+        std::thread::sleep(HOOK_POLL_INTERVAL);
+    };
+
+    log_output(worker, name, &stdout.join().unwrap_or_default());
+    log_output(worker, name, &stderr.join().unwrap_or_default());
+
+    match status {
+        None => bail!("{} timed out after {} seconds", name, timeout),
+        Some(status) if !status.success() => bail!("{} failed ({})", name, status),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Run the pre- or post-hook of a job, if configured
+///
+/// Failures are only logged if the job uses the 'warn' failure policy.
+pub fn run_job_hook(
+    worker: &dyn WorkerTaskContext,
+    hooks: &CloudJobHooks,
+    phase: HookPhase,
+    input: &Value,
+) -> Result<(), Error> {
+    let command = match phase {
+        HookPhase::Pre => hooks.pre_hook.as_deref(),
+        HookPhase::Post => hooks.post_hook.as_deref(),
+    };
+    let command = match command {
+        Some(command) => command,
+        None => return Ok(()),
+    };
+
+    let result = run_hook_command(
+        worker,
+        phase.name(),
+        command,
+        hooks.hook_user.as_deref(),
+        hooks.hook_timeout.unwrap_or(DEFAULT_HOOK_TIMEOUT),
+        input,
+    );
+
+    match (result, hooks.hook_failure.unwrap_or_default()) {
+        (Ok(()), _) => Ok(()),
+        (Err(err), CloudHookFailure::Warn) => {
+            task_warn!(worker, "{}", err);
+            Ok(())
+        }
+        (Err(err), CloudHookFailure::Abort) => Err(err),
+    }
+}
+
+/// Run the pre-hook of a job
+///
+/// An error means the job must not run.
+pub fn run_pre_hook(
+    worker: &dyn WorkerTaskContext,
+    hooks: &CloudJobHooks,
+    job_type: &str,
+    job_id: &str,
+    upid: &str,
+) -> Result<(), Error> {
+    let input = hook_input(HookPhase::Pre, job_type, job_id, upid, None, Value::Null);
+    run_job_hook(worker, hooks, HookPhase::Pre, &input)
+        .map_err(|err| format_err!("job skipped - {}", err))
+}
+
+/// Run the post-hook of a job with the job result and summary
+///
+/// Returns the job result, a failing post-hook fails a successful job.
+pub fn run_post_hook(
+    worker: &dyn WorkerTaskContext,
+    hooks: &CloudJobHooks,
+    job_type: &str,
+    job_id: &str,
+    upid: &str,
+    result: Result<(), Error>,
+    summary: Value,
+) -> Result<(), Error> {
+    let input = hook_input(
+        HookPhase::Post,
+        job_type,
+        job_id,
+        upid,
+        Some(&result),
+        summary,
+    );
+    let post_result = run_job_hook(worker, hooks, HookPhase::Post, &input);
+    result.and(post_result)
+}
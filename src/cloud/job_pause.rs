@@ -0,0 +1,91 @@
+//! Paused cloud jobs
+//!
+//! Unlike `disable`, pausing a job is a runtime state and does not touch
+//! the job config. The scheduler neither starts paused jobs by their
+//! schedule or `run-after` trigger, nor resumes their interrupted runs.
+//! Running jobs continue, and paused jobs can still be run manually.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{create_path, replace_file};
+
+use pbs_api_types::Authid;
+
+use super::create_options;
+
+/// A paused job
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct JobPause {
+    /// Time the job was paused
+    pub time: i64,
+    /// User who paused the job
+    pub user: Authid,
+}
+
+fn job_pause_path(base_path: &Path, job_id: &str) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("job-pause");
+    path.push(format!("{}.json", job_id));
+    path
+}
+
+/// Pause job `job_id`
+///
+/// Pausing a paused job keeps the original pause.
+pub fn pause_job<P: AsRef<Path>>(base_path: P, job_id: &str, user: &Authid) -> Result<(), Error> {
+    let base_path = base_path.as_ref();
+    if load_job_pause(base_path, job_id)?.is_some() {
+        return Ok(());
+    }
+
+    let path = job_pause_path(base_path, job_id);
+    if let Some(parent) = path.parent() {
+        create_path(
+            parent,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+    }
+    let pause = JobPause {
+        time: proxmox_time::epoch_i64(),
+        user: user.clone(),
+    };
+    let data = serde_json::to_vec(&pause)?;
+    replace_file(path, &data, create_options(0o0640)?, true)
+}
+
+/// Resume job `job_id`, returns false if the job was not paused
+pub fn resume_job<P: AsRef<Path>>(base_path: P, job_id: &str) -> Result<bool, Error> {
+    let path = job_pause_path(base_path.as_ref(), job_id);
+    match std::fs::remove_file(&path) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(format_err!("unable to remove {:?} - {}", path, err)),
+    }
+}
+
+/// The pause of job `job_id`, if it is paused
+pub fn load_job_pause<P: AsRef<Path>>(
+    base_path: P,
+    job_id: &str,
+) -> Result<Option<JobPause>, Error> {
+    let path = job_pause_path(base_path.as_ref(), job_id);
+    match proxmox_sys::fs::file_get_optional_contents(&path)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        None => Ok(None),
+    }
+}
+
+/// Check if job `job_id` is paused
+///
+/// Unreadable pause files count as paused, so the scheduler errs on the
+/// safe side.
+pub fn job_paused<P: AsRef<Path>>(base_path: P, job_id: &str) -> bool {
+    !matches!(load_job_pause(base_path, job_id), Ok(None))
+}
@@ -0,0 +1,209 @@
+//! Retries of failed cloud jobs
+//!
+//! With `retry-on-failure`, the scheduler starts a failed job again after
+//! `retry-delay`, which doubles with each retry. Once all retries failed,
+//! the job stays failed until its next scheduled run.
+//!
+//! The UPIDs of the retries of a failed run are recorded locally. A run
+//! which is not one of them (i.e. a scheduled, chained or manual run)
+//! starts a new series of retries.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_rest_server::TaskState;
+use proxmox_sys::fs::{create_path, replace_file};
+use proxmox_time::TimeSpan;
+
+use pbs_api_types::CloudJobScheduleStatus;
+
+use crate::server::jobstate::JobState;
+
+use super::create_options;
+
+/// Delay before the first retry without `retry-delay` (seconds)
+pub const DEFAULT_RETRY_DELAY: i64 = 600;
+
+/// Retries of a failed run
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct JobRetries {
+    /// Task UPID of the failed run
+    pub failed_upid: String,
+    /// Task UPIDs of the retries
+    pub attempts: Vec<String>,
+}
+
+/// Retry options of a job
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryOptions {
+    /// Number of retries
+    pub count: u64,
+    /// Delay before the first retry (seconds)
+    pub delay: i64,
+}
+
+impl RetryOptions {
+    /// Parse the retry options of a job, `None` if failed runs are not retried
+    pub fn new(count: Option<u64>, delay: Option<&str>) -> Result<Option<Self>, Error> {
+        let count = count.unwrap_or(0);
+        if count == 0 {
+            return Ok(None);
+        }
+        let delay = match delay {
+            Some(delay) => {
+                let span: TimeSpan = delay
+                    .parse()
+                    .map_err(|err| format_err!("invalid retry-delay '{}' - {}", delay, err))?;
+                f64::from(span) as i64
+            }
+            None => DEFAULT_RETRY_DELAY,
+        };
+        Ok(Some(Self { count, delay }))
+    }
+
+    /// Delay before retry number `attempt` (starting at 0)
+    pub fn backoff(&self, attempt: u64) -> i64 {
+        self.delay.saturating_mul(1 << attempt.min(16))
+    }
+}
+
+fn job_retries_path(base_path: &Path, job_type: &str, job_id: &str) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("job-retry");
+    path.push(format!("{}-{}.json", job_type, job_id));
+    path
+}
+
+/// The recorded retries of a job
+pub fn load_job_retries<P: AsRef<Path>>(
+    base_path: P,
+    job_type: &str,
+    job_id: &str,
+) -> Result<Option<JobRetries>, Error> {
+    let path = job_retries_path(base_path.as_ref(), job_type, job_id);
+    match proxmox_sys::fs::file_get_optional_contents(&path)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        None => Ok(None),
+    }
+}
+
+fn save_job_retries(
+    base_path: &Path,
+    job_type: &str,
+    job_id: &str,
+    retries: &JobRetries,
+) -> Result<(), Error> {
+    let path = job_retries_path(base_path, job_type, job_id);
+    if let Some(parent) = path.parent() {
+        create_path(
+            parent,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+    }
+    let data = serde_json::to_vec(retries)?;
+    replace_file(path, &data, create_options(0o0640)?, true)
+}
+
+/// Remove the recorded retries of a job
+pub fn remove_job_retries<P: AsRef<Path>>(
+    base_path: P,
+    job_type: &str,
+    job_id: &str,
+) -> Result<(), Error> {
+    let path = job_retries_path(base_path.as_ref(), job_type, job_id);
+    match std::fs::remove_file(&path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(format_err!("unable to remove {:?} - {}", path, err)),
+    }
+}
+
+/// The retries of the failed run `failed_upid`
+///
+/// If `failed_upid` was a retry itself, this continues its series.
+fn retries_of(retries: Option<&JobRetries>, failed_upid: &str) -> JobRetries {
+    match retries {
+        Some(retries) if retries.attempts.last().map(String::as_str) == Some(failed_upid) => {
+            retries.clone()
+        }
+        _ => JobRetries {
+            failed_upid: failed_upid.to_string(),
+            attempts: Vec::new(),
+        },
+    }
+}
+
+/// Time of the next retry, if the last run of a job failed
+///
+/// Returns `None` if the last run succeeded or all retries failed.
+pub fn next_retry(
+    state: &JobState,
+    retries: Option<&JobRetries>,
+    options: &RetryOptions,
+) -> Option<i64> {
+    let (upid, endtime) = match state {
+        JobState::Finished {
+            upid,
+            state: state @ TaskState::Error { .. },
+            ..
+        } => (upid, state.endtime()),
+        _ => return None,
+    };
+
+    let attempts = retries_of(retries, upid).attempts.len() as u64;
+    if attempts >= options.count {
+        return None;
+    }
+    Some(endtime + options.backoff(attempts))
+}
+
+/// Record that the failed last run of a job is retried by `upid`
+///
+/// Returns the number of the attempt (starting at 1).
+pub fn record_retry<P: AsRef<Path>>(
+    base_path: P,
+    job_type: &str,
+    job_id: &str,
+    state: &JobState,
+    upid: &str,
+) -> Result<usize, Error> {
+    let failed_upid = match state {
+        JobState::Finished { upid, .. } | JobState::Started { upid } => upid,
+        JobState::Created { .. } => return Err(format_err!("job {} has not run yet", job_id)),
+    };
+    let base_path = base_path.as_ref();
+    let retries = load_job_retries(base_path, job_type, job_id)?;
+
+    let mut retries = retries_of(retries.as_ref(), failed_upid);
+    retries.attempts.push(upid.to_string());
+    save_job_retries(base_path, job_type, job_id, &retries)?;
+
+    Ok(retries.attempts.len())
+}
+
+/// Include the retries of the last run in the status of a job
+pub fn retry_schedule_status<P: AsRef<Path>>(
+    status: &mut CloudJobScheduleStatus,
+    base_path: P,
+    job_type: &str,
+    job_id: &str,
+    state: &JobState,
+    options: Option<&RetryOptions>,
+) {
+    let retries = load_job_retries(base_path, job_type, job_id).ok().flatten();
+
+    if let (Some(retries), Some(upid)) = (&retries, &status.last_run_upid) {
+        if let Some(index) = retries.attempts.iter().position(|attempt| attempt == upid) {
+            status.retry_attempts = Some(index as u64 + 1);
+        }
+    }
+    if let Some(options) = options {
+        status.next_retry = next_retry(state, retries.as_ref(), options);
+    }
+}
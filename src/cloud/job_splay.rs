@@ -0,0 +1,82 @@
+//! Schedule splay of cloud jobs
+//!
+//! Jobs sharing a schedule (e.g. all jobs created from one template) would
+//! start at the same time and hit the provider simultaneously. With
+//! `splay`, scheduled runs of a job are delayed by a fixed offset below the
+//! configured time span. The offset is derived from the job ID, so it is
+//! the same on every evaluation and spreads different jobs evenly.
+//!
+//! The schedule itself is evaluated without the offset: a job with
+//! schedule `02:00` and an offset of 10 minutes runs at 02:10 every day.
+
+use anyhow::{format_err, Error};
+
+use proxmox_time::{CalendarEvent, TimeSpan};
+
+use pbs_api_types::CloudJobScheduleStatus;
+
+use crate::server::jobstate::last_run_time;
+
+/// Offset of the scheduled runs of `job_id` in seconds
+///
+/// Returns 0 without `splay`.
+pub fn splay_offset(job_id: &str, splay: Option<&str>) -> Result<i64, Error> {
+    let splay = match splay {
+        Some(splay) => splay,
+        None => return Ok(0),
+    };
+    let span: TimeSpan = splay
+        .parse()
+        .map_err(|err| format_err!("invalid splay '{}' - {}", splay, err))?;
+    let span = f64::from(span) as u64;
+    if span == 0 {
+        return Ok(0);
+    }
+
+    let digest = openssl::sha::sha256(job_id.as_bytes());
+    let value = u64::from_le_bytes(digest[..8].try_into().unwrap());
+    Ok((value % span) as i64)
+}
+
+/// Next run of a schedule delayed by `offset`, for a job last run at `last`
+pub fn next_splayed_event(
+    event: &CalendarEvent,
+    last: i64,
+    offset: i64,
+) -> Result<Option<i64>, Error> {
+    // the last run was delayed, too
+    let next = event.compute_next_event(last - offset)?;
+    Ok(next.map(|next| next + offset))
+}
+
+/// Next run of job `jobname` of type `jobtype`, see [`next_splayed_event`]
+pub fn next_splayed_run(
+    jobtype: &str,
+    jobname: &str,
+    schedule: &str,
+    offset: i64,
+) -> Result<Option<i64>, Error> {
+    let event: CalendarEvent = schedule.parse()?;
+    let last = last_run_time(jobtype, jobname)?;
+    next_splayed_event(&event, last, offset)
+}
+
+/// Include the splay of a job in its schedule status
+///
+/// Like the schedule status itself, this ignores errors.
+pub fn splay_schedule_status(
+    status: &mut CloudJobScheduleStatus,
+    jobtype: &str,
+    jobname: &str,
+    schedule: Option<&str>,
+    splay: Option<&str>,
+) {
+    let offset = splay_offset(jobname, splay).unwrap_or(0);
+    if offset == 0 {
+        return;
+    }
+    status.splay_offset = Some(offset);
+    if let Some(schedule) = schedule {
+        status.next_run = next_splayed_run(jobtype, jobname, schedule, offset).unwrap_or(None);
+    }
+}
@@ -0,0 +1,172 @@
+//! Time windows of cloud jobs
+//!
+//! Schedules only define when a job starts. With `allowed-window` and
+//! `blackout-window`, a job may only transfer data at certain times, e.g.
+//! outside office hours on a shared WAN link. Windows are checked between
+//! snapshots, so a snapshot is always transferred completely.
+
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_sys::{task_log, WorkerTaskContext};
+use proxmox_time::{parse_daily_duration, DailyDuration};
+
+use pbs_api_types::{CloudBackupJobSetup, CloudWindowAction};
+
+/// Windows are evaluated with this resolution (seconds)
+const WINDOW_STEP: i64 = 60;
+
+/// Interval to check for aborts while paused (seconds)
+const PAUSE_POLL_INTERVAL: u64 = 5;
+
+/// Windows repeat weekly, so searching one week (and a step) is enough
+const WINDOW_SEARCH_LIMIT: i64 = 7 * 24 * 3600 + WINDOW_STEP;
+
+fn parse_windows(list: &Option<Vec<String>>, name: &str) -> Result<Vec<DailyDuration>, Error> {
+    list.iter()
+        .flatten()
+        .map(|window| {
+            parse_daily_duration(window)
+                .map_err(|err| format_err!("invalid {} '{}' - {}", name, window, err))
+        })
+        .collect()
+}
+
+/// Parsed time windows of a job
+pub struct JobWindow {
+    allowed: Vec<DailyDuration>,
+    blackout: Vec<DailyDuration>,
+    action: CloudWindowAction,
+    utc: bool, // currently only used for testing
+}
+
+impl JobWindow {
+    /// Parse the windows of a job, `None` if the job has no windows
+    pub fn from_job_setup(setup: &CloudBackupJobSetup) -> Result<Option<Self>, Error> {
+        let allowed = parse_windows(&setup.allowed_window, "allowed-window")?;
+        let blackout = parse_windows(&setup.blackout_window, "blackout-window")?;
+
+        if allowed.is_empty() && blackout.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            allowed,
+            blackout,
+            action: setup.window_action.unwrap_or_default(),
+            utc: false,
+        }))
+    }
+
+    /// Evaluate the windows in UTC instead of local time
+    pub fn use_utc(mut self, utc: bool) -> Self {
+        self.utc = utc;
+        self
+    }
+
+    pub fn action(&self) -> CloudWindowAction {
+        self.action
+    }
+
+    /// Check if the job may transfer data at `epoch`
+    pub fn is_open(&self, epoch: i64) -> Result<bool, Error> {
+        for window in self.blackout.iter() {
+            if window.time_match(epoch, self.utc)? {
+                return Ok(false);
+            }
+        }
+
+        if self.allowed.is_empty() {
+            return Ok(true);
+        }
+        for window in self.allowed.iter() {
+            if window.time_match(epoch, self.utc)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Start of the next window at or after `epoch`
+    ///
+    /// Fails if the windows never open (e.g. a blackout window covering
+    /// all allowed windows).
+    pub fn next_open(&self, epoch: i64) -> Result<i64, Error> {
+        if self.is_open(epoch)? {
+            return Ok(epoch);
+        }
+
+        // continue at full minutes, windows have minute resolution
+        let start = epoch - epoch.rem_euclid(WINDOW_STEP);
+
+        let mut next = start + WINDOW_STEP;
+        while next <= start + WINDOW_SEARCH_LIMIT {
+            if self.is_open(next)? {
+                return Ok(next);
+            }
+            next += WINDOW_STEP;
+        }
+
+        bail!("the time windows of the job never open");
+    }
+}
+
+/// Wait until the job may transfer data
+///
+/// Returns `false` if the job should stop, either because the window is
+/// closed and the window action is 'stop', or because of a shutdown
+/// request while paused. `keep_alive` is called once per minute while
+/// paused (e.g. to keep the target lease).
+pub fn wait_for_window(
+    worker: &dyn WorkerTaskContext,
+    window: &JobWindow,
+    mut keep_alive: impl FnMut() -> Result<(), Error>,
+) -> Result<bool, Error> {
+    let now = proxmox_time::epoch_i64();
+    if window.is_open(now)? {
+        return Ok(true);
+    }
+
+    let next = window.next_open(now)?;
+    let next_str = proxmox_time::epoch_to_rfc3339(next)?;
+
+    if window.action() == CloudWindowAction::Stop {
+        task_log!(
+            worker,
+            "outside of the allowed time window, stopping (next window opens at {})",
+            next_str
+        );
+        return Ok(false);
+    }
+
+    task_log!(
+        worker,
+        "outside of the allowed time window, pausing until {}",
+        next_str
+    );
+
+    let mut last_keep_alive = now;
+    loop {
+        worker.check_abort()?;
+        if worker.shutdown_requested() {
+            return Ok(false);
+        }
+
+        let now = proxmox_time::epoch_i64();
+        if now >= next && window.is_open(now)? {
+            break;
+        }
+        if now - last_keep_alive >= WINDOW_STEP {
+            keep_alive()?;
+            last_keep_alive = now;
+        }
+
+        std::thread::sleep(Duration::from_secs(PAUSE_POLL_INTERVAL));
+    }
+
+    task_log!(worker, "time window opened, continuing");
+
+    Ok(true)
+}
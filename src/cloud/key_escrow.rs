@@ -0,0 +1,253 @@
+//! Key escrow using Shamir's secret sharing
+//!
+//! A cloud encryption key can be split into `n` shares, any `k` of which
+//! are enough to recover the key. Shares are printed as single lines, so
+//! they can be handed out as paper keys:
+//!
+//! ```text
+//! pbs-share-v1-<key-id>-<threshold>-<index>-<share data>-<checksum>
+//! ```
+//!
+//! The key ID is the start of the key fingerprint, and is used to detect
+//! shares of different keys. Each share is protected by a checksum against
+//! typing errors. Arithmetic is done byte-wise in GF(2^8).
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, format_err, Error};
+
+use pbs_api_types::Fingerprint;
+use pbs_tools::crypt_config::CryptConfig;
+
+use super::encryption_keys::zero_bytes;
+
+const SHARE_PREFIX: &str = "pbs-share-v1";
+
+/// A single share of a split key
+pub struct KeyShare {
+    key_id: [u8; 4],
+    threshold: u8,
+    index: u8,
+    data: [u8; 32],
+}
+
+impl KeyShare {
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    fn body(&self) -> String {
+        format!(
+            "{}-{}-{}-{}-{}",
+            SHARE_PREFIX,
+            hex::encode(self.key_id),
+            self.threshold,
+            self.index,
+            hex::encode(self.data),
+        )
+    }
+}
+
+fn share_checksum(body: &str) -> String {
+    hex::encode(&openssl::sha::sha256(body.as_bytes())[..4])
+}
+
+impl fmt::Display for KeyShare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let body = self.body();
+        write!(f, "{}-{}", body, share_checksum(&body))
+    }
+}
+
+impl FromStr for KeyShare {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Error> {
+        let input = input.trim();
+        let (body, checksum) = input
+            .rsplit_once('-')
+            .ok_or_else(|| format_err!("invalid key share"))?;
+        if share_checksum(body) != checksum {
+            bail!("invalid key share - wrong checksum (typing error?)");
+        }
+
+        let parts: Vec<&str> = body
+            .strip_prefix(SHARE_PREFIX)
+            .and_then(|rest| rest.strip_prefix('-'))
+            .ok_or_else(|| format_err!("invalid key share - unknown format"))?
+            .split('-')
+            .collect();
+        if parts.len() != 4 {
+            bail!("invalid key share - unknown format");
+        }
+
+        let mut key_id = [0u8; 4];
+        hex::decode_to_slice(parts[0], &mut key_id)?;
+        let threshold = parts[1].parse()?;
+        let index = parts[2].parse()?;
+        let mut data = [0u8; 32];
+        hex::decode_to_slice(parts[3], &mut data)?;
+
+        if index == 0 || threshold < 2 {
+            bail!("invalid key share - bad index or threshold");
+        }
+
+        Ok(Self {
+            key_id,
+            threshold,
+            index,
+            data,
+        })
+    }
+}
+
+impl Drop for KeyShare {
+    fn drop(&mut self) {
+        zero_bytes(&mut self.data);
+    }
+}
+
+fn key_id(fingerprint: &Fingerprint) -> [u8; 4] {
+    let mut id = [0u8; 4];
+    id.copy_from_slice(&fingerprint.bytes()[..4]);
+    id
+}
+
+// multiplication in GF(2^8) (AES polynomial)
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut result = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            result ^= a;
+        }
+        let carry = a & 0x80;
+        a <<= 1;
+        if carry != 0 {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    result
+}
+
+// multiplicative inverse (a^254), a must not be 0
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1;
+    for _ in 0..254 {
+        result = gf_mul(result, a);
+    }
+    result
+}
+
+/// Split a key into `shares` shares, `threshold` of which recover the key
+pub fn split_key(
+    key: &[u8; 32],
+    fingerprint: &Fingerprint,
+    shares: u8,
+    threshold: u8,
+) -> Result<Vec<KeyShare>, Error> {
+    if threshold < 2 {
+        bail!("threshold must be at least 2");
+    }
+    if shares < threshold {
+        bail!(
+            "number of shares ({}) is smaller than the threshold ({})",
+            shares,
+            threshold
+        );
+    }
+
+    // one random polynomial per key byte, the constant term is the key
+    let mut coefficients = vec![0u8; 32 * (threshold as usize - 1)];
+    openssl::rand::rand_bytes(&mut coefficients)?;
+
+    let mut list = Vec::with_capacity(shares as usize);
+    for x in 1..=shares {
+        let mut data = [0u8; 32];
+        for (i, byte) in data.iter_mut().enumerate() {
+            // horner scheme, highest coefficient first
+            let mut y = 0;
+            for coefficient in coefficients[i * (threshold as usize - 1)..]
+                .iter()
+                .take(threshold as usize - 1)
+                .rev()
+            {
+                y = gf_mul(y, x) ^ coefficient;
+            }
+            *byte = gf_mul(y, x) ^ key[i];
+        }
+        list.push(KeyShare {
+            key_id: key_id(fingerprint),
+            threshold,
+            index: x,
+            data,
+        });
+    }
+
+    zero_bytes(&mut coefficients);
+
+    Ok(list)
+}
+
+/// Recover a key from its shares
+///
+/// Needs at least `threshold` distinct shares of the same key. The result
+/// is verified against the key ID stored in the shares.
+pub fn recover_key(shares: &[KeyShare]) -> Result<[u8; 32], Error> {
+    let first = match shares.first() {
+        Some(first) => first,
+        None => bail!("no key shares given"),
+    };
+
+    for share in shares.iter() {
+        if share.key_id != first.key_id {
+            bail!("key shares belong to different keys");
+        }
+        if share.threshold != first.threshold {
+            bail!("key shares use different thresholds");
+        }
+    }
+
+    let mut used: Vec<&KeyShare> = Vec::new();
+    for share in shares.iter() {
+        if !used.iter().any(|other| other.index == share.index) {
+            used.push(share);
+        }
+    }
+    if used.len() < first.threshold as usize {
+        bail!(
+            "not enough key shares ({} of {} needed)",
+            used.len(),
+            first.threshold
+        );
+    }
+    used.truncate(first.threshold as usize);
+
+    // lagrange interpolation at x = 0
+    let mut key = [0u8; 32];
+    for share in used.iter() {
+        let mut basis = 1;
+        for other in used.iter().filter(|other| other.index != share.index) {
+            basis = gf_mul(
+                basis,
+                gf_mul(other.index, gf_inv(other.index ^ share.index)),
+            );
+        }
+        for (byte, y) in key.iter_mut().zip(share.data.iter()) {
+            *byte ^= gf_mul(basis, *y);
+        }
+    }
+
+    let fingerprint = Fingerprint::new(CryptConfig::new(key)?.fingerprint());
+    if key_id(&fingerprint) != first.key_id {
+        zero_bytes(&mut key);
+        bail!("recovered key does not match the key ID of the shares");
+    }
+
+    Ok(key)
+}
@@ -0,0 +1,193 @@
+//! Object key layout of a cloud target
+//!
+//! ```text
+//! lease.json
+//! media-set/<set-uuid>/label.json
+//! media-set/<set-uuid>/catalog.json
+//! media-set/<set-uuid>/chunk-archive/<archive-uuid>
+//! media-set/<set-uuid>/parity/<parity-uuid>
+//! media-set/<set-uuid>/snapshot/<store>/[ns/<ns>/...]<type>/<id>/<time>/<file>
+//! media-set/<set-uuid>/snapshot/<store>/[ns/<ns>/...]<type>/<id>/<time>/cloud-summary.json
+//! trash/<set-uuid>/<store>/[ns/<ns>/...]<type>/<id>/<time>/<file>
+//! export/<store>/[ns/<ns>/...]<type>/<id>/<time>.tar
+//! ```
+//!
+//! All keys are relative to the target prefix. The layout is identical
+//! for all providers.
+//!
+//! Imported media sets (see [`super::foreign_import`]) refer to a copied
+//! datastore below its own prefix instead:
+//!
+//! ```text
+//! <prefix>/.chunks/<digest prefix>/<digest>
+//! <prefix>/[ns/<ns>/...]<type>/<id>/<time>/<file>
+//! ```
+
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{print_ns_and_snapshot, BackupDir, BackupNamespace};
+
+/// Writer lease of the target (see [`super::lease`])
+pub const LEASE_KEY: &str = "lease.json";
+
+/// File name of snapshot summaries (never used for snapshot files)
+pub const SNAPSHOT_SUMMARY_NAME: &str = "cloud-summary.json";
+
+/// Prefix of all media set objects
+pub const MEDIA_SET_PREFIX: &str = "media-set/";
+
+/// Prefix of all objects belonging to a media set
+pub fn media_set_prefix(media_set: &Uuid) -> String {
+    format!("{}{}/", MEDIA_SET_PREFIX, media_set)
+}
+
+/// Media set label, written when a media set is started
+pub fn media_set_label_key(media_set: &Uuid) -> String {
+    format!("{}label.json", media_set_prefix(media_set))
+}
+
+/// Media set catalog, written when a media set is committed
+pub fn media_set_catalog_key(media_set: &Uuid) -> String {
+    format!("{}catalog.json", media_set_prefix(media_set))
+}
+
+/// Chunk archive containing concatenated chunk blobs
+pub fn chunk_archive_key(media_set: &Uuid, archive: &Uuid) -> String {
+    format!("{}chunk-archive/{}", media_set_prefix(media_set), archive)
+}
+
+/// XOR parity of a group of chunk archives (see [`super::parity`])
+pub fn parity_key(media_set: &Uuid, parity: &Uuid) -> String {
+    format!("{}parity/{}", media_set_prefix(media_set), parity)
+}
+
+/// Prefix of all files of a snapshot
+pub fn snapshot_prefix(
+    media_set: &Uuid,
+    store: &str,
+    ns: &BackupNamespace,
+    snapshot: &BackupDir,
+) -> String {
+    format!(
+        "{}snapshot/{}/{}/",
+        media_set_prefix(media_set),
+        store,
+        print_ns_and_snapshot(ns, snapshot),
+    )
+}
+
+/// A single snapshot file (index, blob or manifest)
+pub fn snapshot_file_key(
+    media_set: &Uuid,
+    store: &str,
+    ns: &BackupNamespace,
+    snapshot: &BackupDir,
+    filename: &str,
+) -> String {
+    format!(
+        "{}{}",
+        snapshot_prefix(media_set, store, ns, snapshot),
+        filename
+    )
+}
+
+/// Summary of a snapshot (sizes and chunk count)
+pub fn snapshot_summary_key(
+    media_set: &Uuid,
+    store: &str,
+    ns: &BackupNamespace,
+    snapshot: &BackupDir,
+) -> String {
+    format!(
+        "{}{}",
+        snapshot_prefix(media_set, store, ns, snapshot),
+        SNAPSHOT_SUMMARY_NAME
+    )
+}
+
+/// Prefix of deleted snapshot files (see [`super::trash`])
+pub const TRASH_PREFIX: &str = "trash/";
+
+/// Prefix of all files of a snapshot in the trash
+pub fn trash_snapshot_prefix(
+    media_set: &Uuid,
+    store: &str,
+    ns: &BackupNamespace,
+    snapshot: &BackupDir,
+) -> String {
+    format!(
+        "{}{}/{}/{}/",
+        TRASH_PREFIX,
+        media_set,
+        store,
+        print_ns_and_snapshot(ns, snapshot),
+    )
+}
+
+/// A snapshot file (or summary) in the trash
+pub fn trash_file_key(
+    media_set: &Uuid,
+    store: &str,
+    ns: &BackupNamespace,
+    snapshot: &BackupDir,
+    filename: &str,
+) -> String {
+    format!(
+        "{}{}",
+        trash_snapshot_prefix(media_set, store, ns, snapshot),
+        filename
+    )
+}
+
+/// Prefix of all snapshot exports (see [`super::snapshot_export`])
+pub const EXPORT_PREFIX: &str = "export/";
+
+/// Standalone export of a snapshot
+pub fn snapshot_export_key(store: &str, ns: &BackupNamespace, snapshot: &BackupDir) -> String {
+    format!(
+        "{}{}/{}.tar",
+        EXPORT_PREFIX,
+        store,
+        print_ns_and_snapshot(ns, snapshot)
+    )
+}
+
+/// Extract the media set UUID from an object key
+pub fn parse_media_set_uuid(key: &str) -> Option<Uuid> {
+    let rest = key.strip_prefix(MEDIA_SET_PREFIX)?;
+    let uuid = rest.split('/').next()?;
+    uuid.parse().ok()
+}
+
+/// Chunk directory of a copied datastore
+pub const DATASTORE_CHUNK_DIR: &str = ".chunks/";
+
+fn join_prefix(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}/{}", prefix, key)
+    }
+}
+
+/// A chunk of a datastore copied below `prefix`
+pub fn datastore_chunk_key(prefix: &str, digest: &[u8; 32]) -> String {
+    let digest = hex::encode(digest);
+    join_prefix(
+        prefix,
+        &format!("{}{}/{}", DATASTORE_CHUNK_DIR, &digest[..4], digest),
+    )
+}
+
+/// A snapshot file of a datastore copied below `prefix`
+pub fn datastore_snapshot_file_key(
+    prefix: &str,
+    ns: &BackupNamespace,
+    snapshot: &BackupDir,
+    filename: &str,
+) -> String {
+    join_prefix(
+        prefix,
+        &format!("{}/{}", print_ns_and_snapshot(ns, snapshot), filename),
+    )
+}
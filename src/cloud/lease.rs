@@ -0,0 +1,222 @@
+//! Writer lease of a cloud target
+//!
+//! Several nodes may share a bucket. Only the node holding the lease
+//! object ([`layout::LEASE_KEY`]) is allowed to write media sets, readers
+//! (restore, catalog sync) never need the lease.
+//!
+//! The lease is created with a conditional write, so only one node can
+//! get it. The holder refreshes the heartbeat while writing, and checks
+//! that it still owns the lease before each upload (fencing). A lease
+//! without heartbeat for [`LEASE_TIMEOUT`] seconds is considered stale and
+//! can be taken over by another node.
+//!
+//! On delete protected targets the lease object cannot be removed, so it
+//! is overwritten with an expired heartbeat on release instead.
+//!
+//! Note: renewing and taking over a lease are read-then-write sequences,
+//! so a small race window remains on providers without compare-and-swap.
+
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_uuid::Uuid;
+
+use super::backend::{is_object_exists, CloudBackend};
+use super::layout::LEASE_KEY;
+
+/// Seconds without heartbeat after which a lease can be taken over
+pub const LEASE_TIMEOUT: i64 = 300;
+
+/// Content of the lease object
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LeaseInfo {
+    /// Node holding the lease
+    pub node: String,
+    /// Unique for each acquisition, to detect takeovers
+    pub token: Uuid,
+    pub acquired: i64,
+    pub heartbeat: i64,
+}
+
+impl LeaseInfo {
+    pub fn is_stale(&self, now: i64, timeout: i64) -> bool {
+        now - self.heartbeat >= timeout
+    }
+}
+
+/// Writer lease held by this node (released on drop)
+pub struct CloudLease {
+    backend: Arc<dyn CloudBackend>,
+    info: LeaseInfo,
+    timeout: i64,
+    released: bool,
+}
+
+impl CloudLease {
+    /// Read the current lease (if any)
+    pub fn read(backend: &dyn CloudBackend) -> Result<Option<LeaseInfo>, Error> {
+        if backend.head_object(LEASE_KEY)?.is_none() {
+            return Ok(None);
+        }
+        let data = match backend.get_object(LEASE_KEY) {
+            Ok(data) => data,
+            // released in the meantime
+            Err(_) if backend.head_object(LEASE_KEY)?.is_none() => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let info = serde_json::from_slice(&data)
+            .map_err(|err| format_err!("unable to parse cloud target lease - {}", err))?;
+        Ok(Some(info))
+    }
+
+    /// Acquire the lease for `node`
+    ///
+    /// Fails if another holder sent a heartbeat within `timeout` seconds.
+    pub fn acquire(
+        backend: Arc<dyn CloudBackend>,
+        node: &str,
+        timeout: i64,
+    ) -> Result<Self, Error> {
+        let now = proxmox_time::epoch_i64();
+        let info = LeaseInfo {
+            node: node.to_string(),
+            token: Uuid::generate(),
+            acquired: now,
+            heartbeat: now,
+        };
+        let data = serde_json::to_vec(&info)?;
+
+        match backend.put_object_if_absent(LEASE_KEY, &data) {
+            Ok(()) => return Ok(Self::new(backend, info, timeout)),
+            Err(err) if is_object_exists(&err) => { /* check holder below */ }
+            Err(err) => bail!("unable to acquire cloud target lease - {}", err),
+        }
+
+        if let Some(current) = Self::read(&*backend)? {
+            if !current.is_stale(now, timeout) {
+                bail!(
+                    "cloud target is in use by node '{}' (last heartbeat {} seconds ago)",
+                    current.node,
+                    now - current.heartbeat,
+                );
+            }
+            if current.heartbeat != 0 {
+                log::warn!(
+                    "taking over stale cloud target lease of node '{}'",
+                    current.node
+                );
+            }
+            if backend.delete_protected() {
+                return Self::take_over(backend, info, timeout);
+            }
+            backend.delete_object(LEASE_KEY)?;
+        }
+
+        match backend.put_object_if_absent(LEASE_KEY, &data) {
+            Ok(()) => Ok(Self::new(backend, info, timeout)),
+            Err(err) if is_object_exists(&err) => {
+                bail!("unable to acquire cloud target lease - acquired by another node")
+            }
+            Err(err) => bail!("unable to acquire cloud target lease - {}", err),
+        }
+    }
+
+    // overwrite a stale lease, then make sure no other node did the same
+    fn take_over(
+        backend: Arc<dyn CloudBackend>,
+        info: LeaseInfo,
+        timeout: i64,
+    ) -> Result<Self, Error> {
+        backend
+            .put_object(LEASE_KEY, &serde_json::to_vec(&info)?)
+            .map_err(|err| format_err!("unable to acquire cloud target lease - {}", err))?;
+        let mut lease = Self::new(backend, info, timeout);
+        if let Err(err) = lease.check() {
+            // lost the race, the lease belongs to the other node
+            lease.released = true;
+            bail!("unable to acquire cloud target lease - {}", err);
+        }
+        Ok(lease)
+    }
+
+    fn new(backend: Arc<dyn CloudBackend>, info: LeaseInfo, timeout: i64) -> Self {
+        Self {
+            backend,
+            info,
+            timeout,
+            released: false,
+        }
+    }
+
+    pub fn info(&self) -> &LeaseInfo {
+        &self.info
+    }
+
+    /// Make sure we still hold the lease (fencing)
+    ///
+    /// Call this before writing to the target.
+    pub fn check(&self) -> Result<(), Error> {
+        match Self::read(&*self.backend)? {
+            Some(current) if current.token == self.info.token => Ok(()),
+            Some(current) => bail!(
+                "lost cloud target lease - now held by node '{}'",
+                current.node
+            ),
+            None => bail!("lost cloud target lease - lease was removed"),
+        }
+    }
+
+    /// Check the lease and update the heartbeat
+    pub fn renew(&mut self) -> Result<(), Error> {
+        self.check()?;
+        let mut info = self.info.clone();
+        info.heartbeat = proxmox_time::epoch_i64();
+        self.backend
+            .put_object(LEASE_KEY, &serde_json::to_vec(&info)?)
+            .map_err(|err| format_err!("unable to renew cloud target lease - {}", err))?;
+        self.info = info;
+        Ok(())
+    }
+
+    /// Check the lease, and renew it if a third of the timeout has passed
+    pub fn heartbeat(&mut self) -> Result<(), Error> {
+        if proxmox_time::epoch_i64() - self.info.heartbeat >= self.timeout / 3 {
+            self.renew()
+        } else {
+            self.check()
+        }
+    }
+
+    /// Give up the lease
+    pub fn release(mut self) -> Result<(), Error> {
+        self.do_release()
+    }
+
+    fn do_release(&mut self) -> Result<(), Error> {
+        if self.released {
+            return Ok(());
+        }
+        self.released = true;
+        // never remove a lease taken over by another node
+        self.check()?;
+        if self.backend.delete_protected() {
+            let mut info = self.info.clone();
+            info.heartbeat = 0;
+            return self
+                .backend
+                .put_object(LEASE_KEY, &serde_json::to_vec(&info)?);
+        }
+        self.backend.delete_object(LEASE_KEY)
+    }
+}
+
+impl Drop for CloudLease {
+    fn drop(&mut self) {
+        if let Err(err) = self.do_release() {
+            log::error!("unable to release cloud target lease - {}", err);
+        }
+    }
+}
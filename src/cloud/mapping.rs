@@ -0,0 +1,93 @@
+//! Datastore to cloud target mappings
+//!
+//! A `cloud-mapping` binds a datastore (or one of its namespaces) to a
+//! default cloud target. Backup jobs of the datastore which do not set a
+//! target of their own use the mapped target, and the mapping's
+//! encryption key and prefix apply to all jobs of the datastore writing
+//! to that target. This way a fleet of datastores can be configured
+//! once, and the jobs only name the datastore.
+//!
+//! The prefix is not applied to the target, it is the prefix the target
+//! has to use: catalogs, restores and pruning address the objects by
+//! target, so a job must not write them somewhere else.
+
+use anyhow::{bail, Error};
+
+use pbs_api_types::{find_cloud_mapping, CloudBackupJobSetup, CloudMapping, CloudTarget};
+
+/// The mapping a backup job inherits from, if any
+///
+/// Jobs with a target of their own only use a mapping of that target.
+pub fn select_job_mapping<'a>(
+    mappings: &'a [CloudMapping],
+    setup: &CloudBackupJobSetup,
+) -> Option<&'a CloudMapping> {
+    let ns = setup.ns.clone().unwrap_or_default();
+    find_cloud_mapping(mappings, &setup.store, &ns).filter(|mapping| {
+        setup
+            .target
+            .as_ref()
+            .map_or(true, |target| *target == mapping.target)
+    })
+}
+
+/// Load the mapping a backup job inherits from, if any
+pub fn job_mapping(setup: &CloudBackupJobSetup) -> Result<Option<CloudMapping>, Error> {
+    let mappings = pbs_config::cloud_mapping::mapping_list()?;
+    Ok(select_job_mapping(&mappings, setup).cloned())
+}
+
+/// The target of a backup job, from the mapping if the job has none
+pub fn job_target(setup: &CloudBackupJobSetup) -> Result<String, Error> {
+    if let Some(ref target) = setup.target {
+        return Ok(target.clone());
+    }
+    match job_mapping(setup)? {
+        Some(mapping) => Ok(mapping.target),
+        None => bail!(
+            "no cloud target configured and no cloud mapping for datastore '{}'",
+            setup.store
+        ),
+    }
+}
+
+/// Check that `target` uses the prefix of `mapping`
+pub fn check_mapping_prefix(mapping: &CloudMapping, target: &CloudTarget) -> Result<(), Error> {
+    if let Some(ref prefix) = mapping.prefix {
+        if target.config.prefix.as_ref() != Some(prefix) {
+            bail!(
+                "cloud mapping '{}' expects prefix '{}', but target '{}' uses {}",
+                mapping.id,
+                prefix,
+                target.name,
+                match target.config.prefix {
+                    Some(ref prefix) => format!("prefix '{}'", prefix),
+                    None => "no prefix".to_string(),
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Apply `mapping` to the configuration of its target for a backup
+///
+/// The key of the mapping takes precedence over the target's key for
+/// the same namespace.
+pub fn apply_cloud_mapping(mapping: &CloudMapping, target: &mut CloudTarget) -> Result<(), Error> {
+    check_mapping_prefix(mapping, target)?;
+
+    if let Some(ref key) = mapping.key {
+        let entry = match mapping.ns {
+            Some(ref ns) if !ns.is_root() => format!("ns={},key={}", ns, key),
+            _ => format!("key={}", key),
+        };
+        target
+            .config
+            .namespace_key
+            .get_or_insert_with(Vec::new)
+            .insert(0, entry);
+    }
+
+    Ok(())
+}
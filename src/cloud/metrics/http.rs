@@ -0,0 +1,312 @@
+//! HTTP(S) metric servers
+//!
+//! Records are written to the InfluxDB v2 write API of the server, as gzip
+//! compressed request bodies. A run is split into several requests if the
+//! uncompressed records exceed the `max-body-size` of the server.
+//!
+//! If the server is unreachable or temporarily fails (server errors, rate
+//! limiting), bodies are spooled to disk and sent before new data with the
+//! next run. The spool of a server is limited to [`MAX_SPOOL_SIZE`], the
+//! oldest bodies are dropped first.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{bail, format_err, Error};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hyper::client::{Client, HttpConnector};
+use hyper::{header, Body, Request, StatusCode};
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+
+use proxmox_http::client::HttpsConnector;
+use proxmox_sys::fs::{create_path, replace_file};
+
+use pbs_api_types::CloudMetricsHttp;
+
+use super::{batch_records, MetricPoint};
+use crate::cloud::create_options;
+
+/// Maximum size of the spooled (compressed) bodies per server
+pub const MAX_SPOOL_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Default for `max-body-size`
+pub const DEFAULT_MAX_BODY_SIZE: usize = 50_000_000;
+
+const DEFAULT_ORGANIZATION: &str = "cloud-backup";
+const DEFAULT_BUCKET: &str = "cloud-backup";
+
+const SPOOL_FILE_EXT: &str = "lp.gz";
+
+// distinguishes spool files written within the same second
+static SPOOL_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Spool directory of a server
+pub fn spool_dir<P: AsRef<Path>>(base_path: P, server: &str) -> PathBuf {
+    let mut path = base_path.as_ref().to_owned();
+    path.push("metrics-spool");
+    path.push(server);
+    path
+}
+
+/// Spool a compressed body
+pub fn spool_body(dir: &Path, body: &[u8]) -> Result<PathBuf, Error> {
+    create_path(
+        dir,
+        Some(create_options(0o0750)?),
+        Some(create_options(0o0750)?),
+    )?;
+
+    let sequence = SPOOL_SEQUENCE.fetch_add(1, Ordering::SeqCst);
+    let mut path = dir.to_owned();
+    path.push(format!(
+        "{:016x}-{:08x}.{}",
+        proxmox_time::epoch_i64(),
+        sequence,
+        SPOOL_FILE_EXT
+    ));
+    replace_file(&path, body, create_options(0o0640)?, false)?;
+    Ok(path)
+}
+
+/// Spooled bodies with their size, oldest first
+pub fn spooled_bodies(dir: &Path) -> Result<Vec<(PathBuf, u64)>, Error> {
+    let mut list = Vec::new();
+
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(list),
+        Err(err) => bail!("unable to read spool dir {:?} - {}", dir, err),
+    };
+    for entry in read_dir {
+        let entry = entry?;
+        let path = entry.path();
+        if path
+            .to_str()
+            .map_or(false, |name| name.ends_with(SPOOL_FILE_EXT))
+        {
+            list.push((path, entry.metadata()?.len()));
+        }
+    }
+
+    list.sort();
+    Ok(list)
+}
+
+/// Remove the oldest spooled bodies until the spool fits into `max_size`
+///
+/// Returns the number of removed bodies.
+pub fn trim_spool(dir: &Path, max_size: u64) -> Result<usize, Error> {
+    let list = spooled_bodies(dir)?;
+    let mut size: u64 = list.iter().map(|(_, size)| size).sum();
+
+    let mut removed = 0;
+    for (path, file_size) in list {
+        if size <= max_size {
+            break;
+        }
+        std::fs::remove_file(&path)?;
+        size -= file_size;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+/// Compress a request body
+pub fn gzip_body(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+// why a request failed
+enum PostError {
+    // server unreachable or temporarily failing, worth a retry
+    Outage(Error),
+    // the server rejected the data
+    Rejected(Error),
+}
+
+/// Result of a send run
+#[derive(Debug, Default, PartialEq)]
+pub struct HttpSendStats {
+    /// Bodies sent, including spooled ones
+    pub sent: u64,
+    /// Bodies spooled for the next run
+    pub spooled: u64,
+    /// Bodies rejected by the server or dropped from the spool
+    pub dropped: u64,
+}
+
+/// Sender for a `cloud-http` metric server
+pub struct HttpMetricSender {
+    name: String,
+    client: Client<HttpsConnector>,
+    write_url: String,
+    token: Option<String>,
+    max_body_size: usize,
+    spool_dir: PathBuf,
+}
+
+impl HttpMetricSender {
+    /// Create a sender, spooling below `base_path`
+    pub fn new<P: AsRef<Path>>(config: &CloudMetricsHttp, base_path: P) -> Result<Self, Error> {
+        let mut connector = HttpConnector::new();
+        connector.set_nodelay(true);
+        connector.enforce_http(false); // we want https...
+
+        let mut ssl_connector = SslConnector::builder(SslMethod::tls())?;
+        if !config.verify_tls.unwrap_or(true) {
+            ssl_connector.set_verify(SslVerifyMode::NONE);
+        }
+        let https = HttpsConnector::with_connector(
+            connector,
+            ssl_connector.build(),
+            crate::tools::PROXMOX_BACKUP_TCP_KEEPALIVE_TIME,
+        );
+
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair(
+                "org",
+                config
+                    .organization
+                    .as_deref()
+                    .unwrap_or(DEFAULT_ORGANIZATION),
+            )
+            .append_pair("bucket", config.bucket.as_deref().unwrap_or(DEFAULT_BUCKET))
+            .append_pair("precision", "ns")
+            .finish();
+
+        Ok(Self {
+            name: config.name.clone(),
+            client: Client::builder().build(https),
+            write_url: format!(
+                "{}/api/v2/write?{}",
+                config.url.trim_end_matches('/'),
+                query
+            ),
+            token: config.token.clone(),
+            max_body_size: config.max_body_size.unwrap_or(DEFAULT_MAX_BODY_SIZE),
+            spool_dir: spool_dir(base_path, &config.name),
+        })
+    }
+
+    async fn post(&self, body: Vec<u8>) -> Result<(), PostError> {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(&self.write_url)
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .header(header::CONTENT_ENCODING, "gzip");
+        if let Some(ref token) = self.token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let request = request
+            .body(Body::from(body))
+            .map_err(|err| PostError::Rejected(err.into()))?;
+
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(|err| PostError::Outage(format_err!("request failed - {}", err)))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap_or_default();
+        let err = format_err!(
+            "server returned {} - {}",
+            status,
+            String::from_utf8_lossy(&body).trim()
+        );
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            Err(PostError::Outage(err))
+        } else {
+            Err(PostError::Rejected(err))
+        }
+    }
+
+    /// Send a single data point, without spooling
+    pub async fn test(&self, point: &MetricPoint) -> Result<(), Error> {
+        let line = point
+            .to_line()
+            .ok_or_else(|| format_err!("data point has no fields"))?;
+        match self.post(gzip_body(line.as_bytes())?).await {
+            Ok(()) => Ok(()),
+            Err(PostError::Outage(err)) | Err(PostError::Rejected(err)) => {
+                bail!("sending to {} failed - {}", self.name, err)
+            }
+        }
+    }
+
+    /// Send data points, after the bodies spooled by earlier runs
+    ///
+    /// Fails if the server is unreachable (the data is spooled) or rejected
+    /// data.
+    pub async fn send(&self, points: &[MetricPoint]) -> Result<HttpSendStats, Error> {
+        let lines: Vec<String> = points.iter().filter_map(MetricPoint::to_line).collect();
+        let mut stats = HttpSendStats::default();
+        let mut last_err = None;
+        let mut outage = false;
+
+        for (path, _size) in spooled_bodies(&self.spool_dir)? {
+            let body = std::fs::read(&path)?;
+            match self.post(body).await {
+                Ok(()) => stats.sent += 1,
+                Err(PostError::Rejected(err)) => {
+                    stats.dropped += 1;
+                    last_err = Some(err);
+                }
+                Err(PostError::Outage(err)) => {
+                    last_err = Some(err);
+                    outage = true;
+                    break;
+                }
+            }
+            std::fs::remove_file(&path)?;
+        }
+
+        for batch in batch_records(lines.iter().map(String::as_str), self.max_body_size) {
+            let body = gzip_body(&batch.data)?;
+            if outage {
+                spool_body(&self.spool_dir, &body)?;
+                stats.spooled += 1;
+                continue;
+            }
+            match self.post(body.clone()).await {
+                Ok(()) => stats.sent += 1,
+                Err(PostError::Rejected(err)) => {
+                    stats.dropped += 1;
+                    last_err = Some(err);
+                }
+                Err(PostError::Outage(err)) => {
+                    spool_body(&self.spool_dir, &body)?;
+                    stats.spooled += 1;
+                    last_err = Some(err);
+                    outage = true;
+                }
+            }
+        }
+
+        if outage {
+            stats.dropped += trim_spool(&self.spool_dir, MAX_SPOOL_SIZE)? as u64;
+        }
+
+        match last_err {
+            Some(err) => bail!(
+                "sending to {} failed ({} sent, {} spooled, {} dropped) - {}",
+                self.name,
+                stats.sent,
+                stats.spooled,
+                stats.dropped,
+                err
+            ),
+            None => Ok(stats),
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Cloud metric servers
+//!
+//! Host and cloud statistics are sent to the configured metric servers in
+//! InfluxDB line protocol, see [`udp`] and [`http`] for the transports.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::Error;
+use serde::Serialize;
+use serde_json::Value;
+
+use proxmox_section_config::SectionConfigData;
+
+use pbs_api_types::{CloudMetricsHttp, CloudMetricsUdp};
+
+pub mod http;
+pub mod udp;
+
+use http::HttpMetricSender;
+use udp::UdpMetricSender;
+
+/// A single data point
+#[derive(Clone, Debug)]
+pub struct MetricPoint {
+    pub measurement: String,
+    /// Time of the data point (epoch, seconds)
+    pub ctime: i64,
+    pub tags: Vec<(String, String)>,
+    /// The fields, usually an object of numbers
+    pub values: Value,
+}
+
+/// Newline separated records, sent as one datagram or request body
+#[derive(Debug, PartialEq)]
+pub struct RecordBatch {
+    pub data: Vec<u8>,
+    pub records: u64,
+}
+
+/// Pack records into batches of at most `max_size` bytes
+///
+/// Record order is kept. A record larger than `max_size` gets a batch of
+/// its own.
+pub fn batch_records<'a, I>(records: I, max_size: usize) -> Vec<RecordBatch>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut list = Vec::new();
+    let mut current = RecordBatch {
+        data: Vec::new(),
+        records: 0,
+    };
+
+    for record in records {
+        // records are separated by a newline
+        if current.records > 0 && current.data.len() + 1 + record.len() > max_size {
+            list.push(std::mem::replace(
+                &mut current,
+                RecordBatch {
+                    data: Vec::new(),
+                    records: 0,
+                },
+            ));
+        }
+        if current.records > 0 {
+            current.data.push(b'\n');
+        }
+        current.data.extend_from_slice(record.as_bytes());
+        current.records += 1;
+    }
+
+    if current.records > 0 {
+        list.push(current);
+    }
+    list
+}
+
+fn escape_measurement(name: &str) -> String {
+    name.replace(',', "\\,").replace(' ', "\\ ")
+}
+
+fn escape_key(key: &str) -> String {
+    key.replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+fn format_field(value: &Value) -> Option<String> {
+    match value {
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(value) => Some(value.to_string()),
+        Value::String(text) => Some(format!(
+            "\"{}\"",
+            text.replace('\\', "\\\\").replace('"', "\\\"")
+        )),
+        _ => None,
+    }
+}
+
+impl MetricPoint {
+    pub fn new<V: Serialize>(measurement: &str, ctime: i64, values: V) -> Result<Self, Error> {
+        Ok(Self {
+            measurement: measurement.to_string(),
+            ctime,
+            tags: Vec::new(),
+            values: serde_json::to_value(values)?,
+        })
+    }
+
+    /// Add a tag
+    pub fn tag<S: Into<String>>(mut self, name: &str, value: S) -> Self {
+        self.tags.push((name.to_string(), value.into()));
+        self
+    }
+
+    /// Format the data point as line protocol record (without newline)
+    ///
+    /// Nested values are skipped, returns `None` if no field is left.
+    pub fn to_line(&self) -> Option<String> {
+        let fields: Vec<String> = match self.values {
+            Value::Object(ref map) => map
+                .iter()
+                .filter_map(|(key, value)| {
+                    format_field(value).map(|value| format!("{}={}", escape_key(key), value))
+                })
+                .collect(),
+            ref value => format_field(value)
+                .map(|value| format!("value={}", value))
+                .into_iter()
+                .collect(),
+        };
+        if fields.is_empty() {
+            return None;
+        }
+
+        let mut tags: Vec<&(String, String)> = self.tags.iter().collect();
+        tags.sort();
+
+        let mut line = escape_measurement(&self.measurement);
+        for (name, value) in tags {
+            let _ = write!(line, ",{}={}", escape_key(name), escape_key(value));
+        }
+        let _ = write!(line, " {} {}", fields.join(","), self.ctime * 1_000_000_000);
+        Some(line)
+    }
+}
+
+/// Senders of the enabled metric servers
+///
+/// Kept between stat runs, so HTTP connections are reused. Rebuilt when the
+/// metric server config changes, or when a server could not be set up.
+pub struct MetricSenders {
+    // config digest, None if a server failed
+    digest: Option<[u8; 32]>,
+    pub udp: Vec<UdpMetricSender>,
+    pub http: Vec<HttpMetricSender>,
+}
+
+impl MetricSenders {
+    /// Set up the senders of a metric server config, failures are logged
+    ///
+    /// HTTP servers spool their data below `base_path`.
+    pub async fn load<P: AsRef<Path>>(
+        metric_config: &SectionConfigData,
+        digest: [u8; 32],
+        base_path: P,
+    ) -> Self {
+        let mut senders = Self {
+            digest: Some(digest),
+            udp: Vec::new(),
+            http: Vec::new(),
+        };
+
+        match metric_config.convert_to_typed_array::<CloudMetricsUdp>("cloud-udp") {
+            Ok(list) => {
+                for config in list.into_iter().filter(|config| config.enable) {
+                    match UdpMetricSender::connect(&config).await {
+                        Ok(sender) => senders.udp.push(sender),
+                        Err(err) => {
+                            log::error!("error connecting to metric server {}: {err}", config.name);
+                            senders.digest = None;
+                        }
+                    }
+                }
+            }
+            Err(err) => log::error!("unable to parse cloud-udp metric servers: {err}"),
+        }
+
+        match metric_config.convert_to_typed_array::<CloudMetricsHttp>("cloud-http") {
+            Ok(list) => {
+                for config in list.into_iter().filter(|config| config.enable) {
+                    match HttpMetricSender::new(&config, base_path.as_ref()) {
+                        Ok(sender) => senders.http.push(sender),
+                        Err(err) => {
+                            log::error!("error setting up metric server {}: {err}", config.name);
+                            senders.digest = None;
+                        }
+                    }
+                }
+            }
+            Err(err) => log::error!("unable to parse cloud-http metric servers: {err}"),
+        }
+
+        senders
+    }
+
+    /// The senders are up to date with the config of `digest`
+    pub fn is_current(&self, digest: &[u8; 32]) -> bool {
+        self.digest.as_ref() == Some(digest)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.udp.is_empty() && self.http.is_empty()
+    }
+}
@@ -0,0 +1,132 @@
+//! UDP metric servers
+//!
+//! Records are batched into datagrams which fit into the configured MTU
+//! (after IP and UDP headers), so a collection run is split over as many
+//! datagrams as needed without IP fragmentation. Only a record larger than
+//! a datagram is sent on its own and left to be fragmented.
+//!
+//! The socket is never waited on: if the send buffer is full, the datagram
+//! is dropped and its records are counted per server (see
+//! [`dropped_records`]), so a slow collector cannot stall the proxy.
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Mutex;
+
+use anyhow::{format_err, Error};
+use tokio::net::UdpSocket;
+
+use pbs_api_types::CloudMetricsUdp;
+
+use super::{batch_records, MetricPoint};
+
+/// MTU used if the server has none configured
+pub const DEFAULT_MTU: u16 = 1500;
+
+const UDP_HEADER_SIZE: usize = 8;
+const IPV4_HEADER_SIZE: usize = 20;
+const IPV6_HEADER_SIZE: usize = 40;
+
+// records dropped since the start of the proxy, per server
+static DROPPED_RECORDS: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+/// Records dropped since startup, per server
+pub fn dropped_records() -> BTreeMap<String, u64> {
+    DROPPED_RECORDS.lock().unwrap().clone()
+}
+
+fn count_dropped(server: &str, records: u64) {
+    if records == 0 {
+        return;
+    }
+    *DROPPED_RECORDS
+        .lock()
+        .unwrap()
+        .entry(server.to_string())
+        .or_default() += records;
+}
+
+/// Maximum payload of a datagram to `addr` without IP fragmentation
+pub fn max_payload(mtu: u16, addr: &SocketAddr) -> usize {
+    let header_size = UDP_HEADER_SIZE
+        + match addr {
+            SocketAddr::V4(_) => IPV4_HEADER_SIZE,
+            SocketAddr::V6(_) => IPV6_HEADER_SIZE,
+        };
+    (mtu as usize).saturating_sub(header_size).max(1)
+}
+
+/// Result of a send run
+#[derive(Debug, Default, PartialEq)]
+pub struct UdpSendStats {
+    pub datagrams: u64,
+    pub records: u64,
+    pub dropped: u64,
+}
+
+/// Sender for a `cloud-udp` metric server
+pub struct UdpMetricSender {
+    name: String,
+    socket: UdpSocket,
+    max_payload: usize,
+}
+
+impl UdpMetricSender {
+    /// Resolve the endpoint of the server and connect a socket to it
+    pub async fn connect(config: &CloudMetricsUdp) -> Result<Self, Error> {
+        let addr = tokio::net::lookup_host(&config.endpoint)
+            .await?
+            .next()
+            .ok_or_else(|| format_err!("unable to resolve '{}'", config.endpoint))?;
+
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+
+        Ok(Self {
+            name: config.name.clone(),
+            socket,
+            max_payload: max_payload(config.mtu.unwrap_or(DEFAULT_MTU), &addr),
+        })
+    }
+
+    /// Send data points, without waiting for a congested socket
+    ///
+    /// Dropped records are added to the counter of the server. Fails on
+    /// other socket errors, e.g. if the server refused an earlier datagram.
+    pub fn send(&self, points: &[MetricPoint]) -> Result<UdpSendStats, Error> {
+        let lines: Vec<String> = points.iter().filter_map(MetricPoint::to_line).collect();
+
+        let mut stats = UdpSendStats::default();
+        let mut result = Ok(());
+
+        for datagram in batch_records(lines.iter().map(String::as_str), self.max_payload) {
+            stats.records += datagram.records;
+            if result.is_err() {
+                stats.dropped += datagram.records;
+                continue;
+            }
+            match self.socket.try_send(&datagram.data) {
+                Ok(_) => stats.datagrams += 1,
+                Err(err)
+                    if err.kind() == ErrorKind::WouldBlock
+                        || err.raw_os_error() == Some(libc::ENOBUFS) =>
+                {
+                    stats.dropped += datagram.records;
+                }
+                Err(err) => {
+                    stats.dropped += datagram.records;
+                    result = Err(format_err!("sending to {} failed - {}", self.name, err));
+                }
+            }
+        }
+
+        count_dropped(&self.name, stats.dropped);
+
+        result.map(|()| stats)
+    }
+}
@@ -0,0 +1,301 @@
+//! Migration of a cloud target to another bucket or provider
+//!
+//! All objects of the target are copied to the storage of a second
+//! (empty) target, server-side if possible, otherwise by download and
+//! upload. Object keys are relative to the target prefix and objects are
+//! copied unchanged, so the catalogs stay valid and nothing is encrypted
+//! again. Media set catalogs are copied last, an interrupted migration
+//! can simply be started again.
+//!
+//! After verifying the copies, the storage properties of the second
+//! target are moved to the migrated target, which keeps its name, jobs
+//! and local state.
+//! The objects on the old storage can be deleted afterwards, once the
+//! copies were verified and the target switched over.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_human_byte::HumanByte;
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::CloudTarget;
+
+use super::backend::{CloudBackend, ObjectInfo};
+use super::catalog::CloudCatalog;
+use super::layout::{self, LEASE_KEY};
+use super::lease::{CloudLease, LEASE_TIMEOUT};
+use super::replication::{copy_object, ReplicationStats};
+
+/// Move the storage properties (provider, location and credentials) of
+/// `destination` to `target`
+///
+/// All other properties of `target` are kept.
+pub fn switch_target_storage(target: &mut CloudTarget, destination: &CloudTarget) {
+    let config = &mut target.config;
+    let other = &destination.config;
+
+    config.provider = other.provider;
+    config.endpoint = other.endpoint.clone();
+    config.region = other.region.clone();
+    config.alternate_endpoint = other.alternate_endpoint.clone();
+    config.failover_endpoint = other.failover_endpoint.clone();
+    config.bucket = other.bucket.clone();
+    config.prefix = other.prefix.clone();
+    config.path = other.path.clone();
+    config.access_key = other.access_key.clone();
+    config.credential_process = other.credential_process.clone();
+    config.path_style = other.path_style;
+    config.transfer_acceleration = other.transfer_acceleration;
+    config.download_host = other.download_host.clone();
+    config.access_log_prefix = other.access_log_prefix.clone();
+
+    target.secret_key = destination.secret_key.clone();
+}
+
+// all objects of a target, except the lease
+fn list_target_objects(backend: &dyn CloudBackend) -> Result<Vec<ObjectInfo>, Error> {
+    let mut list = backend.list_objects("")?;
+    list.retain(|object| object.key != LEASE_KEY);
+    Ok(list)
+}
+
+fn heartbeat(leases: &mut [CloudLease]) -> Result<(), Error> {
+    for lease in leases.iter_mut() {
+        lease.heartbeat()?;
+    }
+    Ok(())
+}
+
+fn is_media_set_catalog(key: &str) -> bool {
+    layout::parse_media_set_uuid(key)
+        .map(|uuid| layout::media_set_catalog_key(&uuid) == key)
+        .unwrap_or(false)
+}
+
+/// Copy all objects of a target to the storage of another target
+///
+/// The destination must not contain objects missing on the source.
+/// Objects already copied (same size) are skipped.
+pub fn migrate_objects(
+    worker: &dyn WorkerTaskContext,
+    source: &Arc<dyn CloudBackend>,
+    destination: &Arc<dyn CloudBackend>,
+    leases: &mut [CloudLease],
+) -> Result<ReplicationStats, Error> {
+    let objects = list_target_objects(&**source)?;
+
+    let sizes: HashMap<&str, u64> = objects
+        .iter()
+        .map(|object| (object.key.as_str(), object.size))
+        .collect();
+
+    let mut existing = HashMap::new();
+    for object in list_target_objects(&**destination)? {
+        if !sizes.contains_key(object.key.as_str()) {
+            bail!(
+                "destination contains unrelated object '{}' - refusing to migrate",
+                object.key
+            );
+        }
+        existing.insert(object.key, object.size);
+    }
+
+    task_log!(
+        worker,
+        "migrating {} objects ({} already present)",
+        objects.len(),
+        existing.len()
+    );
+
+    // media set catalogs mark media sets as complete, copy them last
+    let (catalogs, objects): (Vec<ObjectInfo>, Vec<ObjectInfo>) = objects
+        .into_iter()
+        .partition(|object| is_media_set_catalog(&object.key));
+
+    let mut stats = ReplicationStats::default();
+    let mut server_side = true;
+
+    for object in objects.iter().chain(catalogs.iter()) {
+        worker.check_abort()?;
+        worker.fail_on_shutdown()?;
+
+        if existing.get(&object.key) == Some(&object.size) {
+            stats.skipped += 1;
+            continue;
+        }
+        heartbeat(leases)?;
+        copy_object(
+            worker,
+            source,
+            destination,
+            object,
+            &mut server_side,
+            &mut stats,
+        )?;
+    }
+    stats.media_sets = catalogs.len();
+
+    task_log!(
+        worker,
+        "copied {} objects server-side, {} objects / {} streamed, {} skipped",
+        stats.server_side,
+        stats.streamed,
+        HumanByte::from(stats.bytes),
+        stats.skipped,
+    );
+
+    Ok(stats)
+}
+
+/// Verify the copies of a migration
+///
+/// Checks that the destination holds all objects of the source with the
+/// same size, and that chunk archives and parity objects match the
+/// checksums in the catalog. Returns the number of checked objects.
+pub fn verify_migration(
+    worker: &dyn WorkerTaskContext,
+    catalog: &CloudCatalog,
+    source: &dyn CloudBackend,
+    destination: &dyn CloudBackend,
+    leases: &mut [CloudLease],
+) -> Result<usize, Error> {
+    let copies: HashMap<String, u64> = list_target_objects(destination)?
+        .into_iter()
+        .map(|object| (object.key, object.size))
+        .collect();
+
+    let objects = list_target_objects(source)?;
+    if objects.len() != copies.len() {
+        bail!(
+            "object count mismatch ({} on source, {} on destination)",
+            objects.len(),
+            copies.len()
+        );
+    }
+    for object in objects.iter() {
+        match copies.get(&object.key) {
+            Some(size) if *size == object.size => {}
+            Some(size) => bail!(
+                "object '{}' has wrong size on destination ({} != {})",
+                object.key,
+                size,
+                object.size
+            ),
+            None => bail!("object '{}' missing on destination", object.key),
+        }
+    }
+
+    let mut digests = Vec::new();
+    for media_set in catalog.media_sets() {
+        for archive in media_set.archives.iter() {
+            if let Some(csum) = archive.csum {
+                digests.push((
+                    layout::chunk_archive_key(media_set.uuid(), &archive.uuid),
+                    csum,
+                ));
+            }
+        }
+        for parity in media_set.parity.iter() {
+            digests.push((
+                layout::parity_key(media_set.uuid(), &parity.uuid),
+                parity.csum,
+            ));
+        }
+    }
+
+    task_log!(worker, "verifying {} object checksums", digests.len());
+    for (key, csum) in digests.iter() {
+        worker.check_abort()?;
+        heartbeat(leases)?;
+        let data = destination
+            .get_object(key)
+            .map_err(|err| format_err!("unable to read '{}' - {}", key, err))?;
+        if openssl::sha::sha256(&data) != *csum {
+            bail!("object '{}' has wrong checksum on destination", key);
+        }
+    }
+
+    Ok(objects.len())
+}
+
+/// Copy and verify all objects of `target` on the storage of `destination`
+///
+/// Holds the lease on both targets, so no job writes to them meanwhile.
+/// The config switch is left to the caller.
+pub fn migrate_target(
+    worker: &dyn WorkerTaskContext,
+    catalog: &CloudCatalog,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+    destination: &CloudTarget,
+    destination_backend: &Arc<dyn CloudBackend>,
+) -> Result<ReplicationStats, Error> {
+    if target.name == destination.name {
+        bail!("source and destination of a migration must differ");
+    }
+
+    let mut leases = vec![
+        CloudLease::acquire(Arc::clone(backend), proxmox_sys::nodename(), LEASE_TIMEOUT)?,
+        CloudLease::acquire(
+            Arc::clone(destination_backend),
+            proxmox_sys::nodename(),
+            LEASE_TIMEOUT,
+        )?,
+    ];
+
+    let stats = migrate_objects(worker, backend, destination_backend, &mut leases)?;
+
+    let checked = verify_migration(
+        worker,
+        catalog,
+        &**backend,
+        &**destination_backend,
+        &mut leases,
+    )?;
+    task_log!(worker, "verified {} objects", checked);
+
+    for lease in leases {
+        lease.release()?;
+    }
+
+    Ok(stats)
+}
+
+/// Delete all objects of a migrated target from its old storage
+///
+/// Only call this after [`migrate_target`] verified the copies. Returns
+/// the number of deleted objects, failed deletions are logged and
+/// counted as errors.
+pub fn delete_migrated_objects(
+    worker: &dyn WorkerTaskContext,
+    source: &dyn CloudBackend,
+) -> Result<usize, Error> {
+    let objects = list_target_objects(source)?;
+    task_log!(
+        worker,
+        "deleting {} objects from old storage",
+        objects.len()
+    );
+
+    let mut deleted = 0;
+    let mut errors = 0;
+    for object in objects {
+        worker.check_abort()?;
+        match source.delete_object(&object.key) {
+            Ok(()) => deleted += 1,
+            Err(err) => {
+                task_warn!(worker, "unable to delete '{}' - {}", object.key, err);
+                errors += 1;
+            }
+        }
+    }
+
+    if errors > 0 {
+        bail!("unable to delete {} objects from old storage", errors);
+    }
+
+    Ok(deleted)
+}
@@ -1,36 +1,118 @@
 //! Cloud Backup Management
 
+use anyhow::{format_err, Error};
+
+use proxmox_sys::fs::{create_path, CreateOptions};
+
+use pbs_buildcfg::PROXMOX_BACKUP_STATE_DIR_M;
+
+#[cfg(test)]
+mod test;
+
+pub mod access_log;
+pub mod backend;
+pub mod catalog;
+pub mod catalog_export;
+pub mod change_feed;
+pub mod checksums;
+pub mod chunk_cache;
+pub mod chunk_download;
+pub mod chunk_reader;
+pub mod compaction;
+pub mod config_check;
+pub mod config_history;
+pub mod content;
+pub mod dedup_stats;
+pub mod delete_queue;
+pub mod digest;
+pub mod egress;
+pub mod encryption_keys;
+pub mod events;
+pub mod foreign_import;
+pub mod fsck;
+pub mod health;
+pub mod instance_metadata;
+pub mod io_pacing;
+pub mod job_chain;
+pub mod job_errors;
+pub mod job_hooks;
+pub mod job_pause;
+pub mod job_retry;
+pub mod job_splay;
+pub mod job_window;
+pub mod key_escrow;
+pub mod layout;
+pub mod lease;
+pub mod mapping;
+pub mod metrics;
+pub mod migration;
+pub mod node_status;
+pub mod openid_roles;
+pub mod parity;
+pub mod popularity;
+pub mod prune;
+pub mod quota;
+pub mod reconcile;
+pub mod repair;
+pub mod replication;
+pub mod request_trace;
+pub mod restore_preview;
+pub mod retag;
+pub mod retention_report;
+pub mod role_sync;
+pub mod rollback;
+pub mod share;
+pub mod snapshot_export;
+pub mod snapshot_summary;
+pub mod staging;
+pub mod standby;
+pub mod synthetic;
+pub mod task_checkpoint;
+pub mod task_records;
+pub mod trash;
+pub mod upload_estimate;
+pub mod usage;
+pub mod verify;
+pub mod verify_tuning;
+pub mod zfs_changes;
+pub mod zfs_snapshot;
+
 mod cloud_writer;
 pub use cloud_writer::*;
 
-mod file_formats;
-
+/// Directory path where we store all cloud status information
+pub const CLOUD_STATUS_DIR: &str = concat!(PROXMOX_BACKUP_STATE_DIR_M!(), "/cloud");
 
-use anyhow::Error;
-use serde_json::Value;
+/// Create cloud status dir with correct permission
+pub fn create_cloud_status_dir() -> Result<(), Error> {
+    let backup_user = pbs_config::backup_user()?;
+    let mode = nix::sys::stat::Mode::from_bits_truncate(0o0750);
+    let options = CreateOptions::new()
+        .perm(mode)
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
 
-use proxmox_router::{list_subdirs_api_method, Router, SubdirMap};
-use proxmox_schema::api;
+    let parent_opts = CreateOptions::new()
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
 
+    create_path(CLOUD_STATUS_DIR, Some(parent_opts), Some(options))
+        .map_err(|err: Error| format_err!("unable to create cloud status dir - {}", err))?;
 
-#[api(
-    input: {
-        properties: {},
-    },
-    returns: {
-        description: "Cloud hello world.",
-        type: String,
-    },
-)]
-/// Cloud Hello
-pub fn cloud_hello(_param: Value) -> Result<String, Error> {
-    Ok("hello world".to_string())
+    Ok(())
 }
 
-const SUBDIRS: SubdirMap = &[
-    ("cloud_hello", &Router::new().get(&API_METHOD_CLOUD_HELLO)),
-];
-
-pub const ROUTER: Router = Router::new()
-    .get(&list_subdirs_api_method!(SUBDIRS))
-    .subdirs(SUBDIRS);
\ No newline at end of file
+/// Create options for files and directories below the cloud status dir,
+/// owned by the backup user
+pub(crate) fn create_options(mode: u32) -> Result<CreateOptions, Error> {
+    let mode = nix::sys::stat::Mode::from_bits_truncate(mode);
+    if cfg!(test) {
+        // We cannot use chown inside test environment (no permissions)
+        return Ok(CreateOptions::new().perm(mode));
+    }
+    let backup_user = pbs_config::backup_user()?;
+    Ok(CreateOptions::new()
+        .perm(mode)
+        .owner(backup_user.uid)
+        .group(backup_user.gid))
+}
@@ -0,0 +1,94 @@
+//! Node status of cloud instances
+//!
+//! Local memory, swap, load and CPU counters, together with the instance
+//! information from the metadata service of the cloud provider (see
+//! [`super::instance_metadata`]). The filesystem usage of the datastores
+//! is included, as they stage the data uploaded to cloud targets, as well
+//! as the utilization of the local chunk cache.
+
+use std::path::PathBuf;
+
+use anyhow::Error;
+
+use proxmox_sys::linux::procfs;
+
+use pbs_api_types::{
+    CloudCpuInformation, CloudDatastoreUsage, CloudMemoryCounters, CloudNodeStatus,
+    CloudSwapCounters, DataStoreConfig, KernelVersionInformation,
+};
+
+use super::chunk_cache::node_cache_status;
+use super::instance_metadata::instance_information;
+
+/// Filesystem usage of all datastores
+pub async fn datastore_usage() -> Result<Vec<CloudDatastoreUsage>, Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let datastores: Vec<DataStoreConfig> = config.convert_to_typed_array("datastore")?;
+    Ok(filesystem_usage(datastores).await)
+}
+
+/// Filesystem usage of `datastores`
+///
+/// Datastores which cannot be queried (e.g. removed disks) are skipped.
+pub async fn filesystem_usage(datastores: Vec<DataStoreConfig>) -> Vec<CloudDatastoreUsage> {
+    let mut list = Vec::new();
+    for datastore in datastores {
+        match crate::tools::fs::fs_info(PathBuf::from(&datastore.path)).await {
+            Ok(status) => list.push(CloudDatastoreUsage {
+                store: datastore.name,
+                total: status.total,
+                used: status.used,
+                avail: status.available,
+            }),
+            Err(err) => log::warn!("unable to query datastore '{}' - {}", datastore.name, err),
+        }
+    }
+    list
+}
+
+/// Collect the status of this node
+pub async fn collect_node_status() -> Result<CloudNodeStatus, Error> {
+    let meminfo = procfs::read_meminfo()?;
+    let memory = CloudMemoryCounters {
+        total: meminfo.memtotal,
+        used: meminfo.memused,
+        free: meminfo.memfree,
+    };
+    let swap = CloudSwapCounters {
+        total: meminfo.swaptotal,
+        used: meminfo.swapused,
+        free: meminfo.swapfree,
+    };
+
+    let cpu = procfs::read_proc_stat()?.cpu;
+
+    let loadavg = procfs::Loadavg::read()?;
+    let loadavg = [loadavg.one(), loadavg.five(), loadavg.fifteen()];
+
+    let cpuinfo = procfs::read_cpuinfo()?;
+    let cpuinfo = CloudCpuInformation {
+        model: cpuinfo.model,
+        vcpus: cpuinfo.cpus,
+    };
+
+    let uname = nix::sys::utsname::uname()?;
+    let current_kernel = KernelVersionInformation::from_uname_parts(
+        uname.sysname(),
+        uname.release(),
+        uname.version(),
+        uname.machine(),
+    );
+
+    Ok(CloudNodeStatus {
+        memory,
+        swap,
+        uptime: procfs::read_proc_uptime()?.0 as u64,
+        loadavg,
+        current_kernel,
+        cpu,
+        cpuinfo,
+        info: instance_information().await,
+        datastores: datastore_usage().await?,
+        chunk_cache: node_cache_status()?,
+    })
+}
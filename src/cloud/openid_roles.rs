@@ -0,0 +1,199 @@
+//! OpenID claim based role mapping
+//!
+//! OpenID realms with a `roles-claim` map the values of that claim (e.g.
+//! the groups of the user at the identity provider) to ACL entries on
+//! every login, using the `role-mapping` list of the realm. This also
+//! covers users created on their first login with `autocreate`.
+//!
+//! The grants made for a realm are remembered locally. Grants whose claim
+//! value disappeared are revoked on the next login of the user, while ACL
+//! entries set up by other means are never touched. Each grant and revoke
+//! is recorded in the cloud config history.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Error};
+use serde_json::{json, Value};
+
+use proxmox_sys::fs::{create_path, replace_file};
+
+use pbs_api_types::{Authid, CloudOpenIdRoleMapping, Userid};
+
+use super::config_history::record_config_change;
+use super::create_options;
+use super::role_sync::{apply_role_sync, RoleGrant, RoleSyncChanges};
+
+/// Parse the `role-mapping` list of an OpenID realm
+pub fn parse_role_mappings(list: &[String]) -> Result<Vec<CloudOpenIdRoleMapping>, Error> {
+    list.iter()
+        .map(|mapping| {
+            let value = CloudOpenIdRoleMapping::API_SCHEMA.parse_property_string(mapping)?;
+            Ok(serde_json::from_value(value)?)
+        })
+        .collect()
+}
+
+/// Values of `claim` in the user info returned by the identity provider
+///
+/// The claim can be a single string or a list of strings. If there is no
+/// top-level claim with that name, a dotted name addresses a nested claim,
+/// e.g. `realm_access.roles`.
+pub fn claim_values(info: &Value, claim: &str) -> Vec<String> {
+    let mut value = &info[claim];
+    if value.is_null() && claim.contains('.') {
+        value = claim.split('.').fold(info, |value, name| &value[name]);
+    }
+
+    let mut values: Vec<String> = match value {
+        Value::String(value) => vec![value.clone()],
+        Value::Array(list) => list
+            .iter()
+            .filter_map(Value::as_str)
+            .map(String::from)
+            .collect(),
+        _ => Vec::new(),
+    };
+    values.sort();
+    values.dedup();
+    values
+}
+
+/// The grants for `userid` with the claim `values`, with their propagate flag
+pub fn desired_grants(
+    mappings: &[CloudOpenIdRoleMapping],
+    userid: &Userid,
+    values: &[String],
+) -> BTreeMap<RoleGrant, bool> {
+    let mut grants = BTreeMap::new();
+    for mapping in mappings {
+        if !values.contains(&mapping.value) {
+            continue;
+        }
+        let grant = RoleGrant {
+            userid: userid.clone(),
+            path: mapping.path.clone(),
+            role: mapping.role.clone(),
+        };
+        // a propagating mapping wins
+        *grants.entry(grant).or_insert(false) |= mapping.propagate.unwrap_or(true);
+    }
+    grants
+}
+
+/// Apply the `desired` grants of `userid` to `tree`
+///
+/// `managed` are the grants owned by the realm, they are updated for the
+/// user. The grants of other users are left alone.
+pub fn apply_user_roles(
+    tree: &mut pbs_config::acl::AclTree,
+    userid: &Userid,
+    desired: &BTreeMap<RoleGrant, bool>,
+    managed: &mut BTreeSet<RoleGrant>,
+) -> RoleSyncChanges {
+    let previous: BTreeSet<RoleGrant> = managed
+        .iter()
+        .filter(|grant| grant.userid == *userid)
+        .cloned()
+        .collect();
+
+    let changes = apply_role_sync(tree, desired, &previous);
+
+    managed.retain(|grant| grant.userid != *userid);
+    managed.extend(changes.managed.iter().cloned());
+
+    changes
+}
+
+fn role_grants_path(base_path: &Path, realm: &str) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("openid-roles");
+    path.push(format!("{}.json", realm));
+    path
+}
+
+/// Load the grants owned by OpenID realm `realm`
+pub fn load_role_grants<P: AsRef<Path>>(
+    base_path: P,
+    realm: &str,
+) -> Result<BTreeSet<RoleGrant>, Error> {
+    let path = role_grants_path(base_path.as_ref(), realm);
+    match proxmox_sys::fs::file_get_optional_contents(&path)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        None => Ok(BTreeSet::new()),
+    }
+}
+
+/// Store the grants owned by OpenID realm `realm`
+pub fn save_role_grants<P: AsRef<Path>>(
+    base_path: P,
+    realm: &str,
+    grants: &BTreeSet<RoleGrant>,
+) -> Result<(), Error> {
+    let path = role_grants_path(base_path.as_ref(), realm);
+    if let Some(parent) = path.parent() {
+        create_path(
+            parent,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+    }
+    let data = serde_json::to_vec(grants)?;
+    replace_file(path, &data, create_options(0o0640)?, true)
+}
+
+fn grant_data(grant: &RoleGrant) -> Value {
+    json!({
+        "userid": grant.userid,
+        "role": grant.role,
+    })
+}
+
+/// Record the changes in the config history, as done by the user itself
+pub fn record_role_changes(realm: &str, userid: &Userid, changes: &RoleSyncChanges) {
+    let auth_id = Authid::from(userid.clone());
+    for grant in changes.granted.iter() {
+        log::info!("openid realm {}: grant {}", realm, grant);
+        record_config_change(&auth_id, "acl", &grant.path, None, Some(&grant_data(grant)));
+    }
+    for grant in changes.revoked.iter() {
+        log::info!("openid realm {}: revoke {}", realm, grant);
+        record_config_change(&auth_id, "acl", &grant.path, Some(&grant_data(grant)), None);
+    }
+}
+
+/// Update the roles of `userid` from the `roles_claim` in the user `info`
+///
+/// Called on login, the realm state is stored before the ACL, so that a
+/// failure never leaves grants behind which later logins would take for
+/// manually configured ones.
+pub fn sync_login_roles<P: AsRef<Path>>(
+    base_path: P,
+    realm: &str,
+    roles_claim: &str,
+    role_mapping: &[String],
+    userid: &Userid,
+    info: &Value,
+) -> Result<(), Error> {
+    let mappings = parse_role_mappings(role_mapping)?;
+    let values = claim_values(info, roles_claim);
+    let desired = desired_grants(&mappings, userid, &values);
+
+    let _acl_lock = pbs_config::acl::lock_config()?;
+    let (mut tree, _digest) = pbs_config::acl::config()?;
+
+    let mut managed = load_role_grants(&base_path, realm)?;
+    let changes = apply_user_roles(&mut tree, userid, &desired, &mut managed);
+
+    if changes.granted.is_empty() && changes.revoked.is_empty() {
+        return Ok(());
+    }
+
+    save_role_grants(&base_path, realm, &managed)?;
+    pbs_config::acl::save_config(&tree)?;
+
+    record_role_changes(realm, userid, &changes);
+
+    Ok(())
+}
@@ -0,0 +1,276 @@
+//! Parity objects for chunk archives
+//!
+//! Cheap single-region storage may lose objects. If `parity-group` is set
+//! on a target, backup jobs upload one XOR parity object per group of
+//! chunk archives of a media set. A single lost (or damaged) archive of a
+//! group can then be rebuilt from the parity object and the other archives
+//! of the group, without any local copy of the data.
+//!
+//! Archives have different sizes, shorter archives count as padded with
+//! zeros to the size of the largest one.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::CloudTarget;
+
+use super::backend::{CloudBackend, PutOptions};
+use super::catalog::{ChunkArchiveEntry, CloudCatalog, MediaSetCatalog, ParityEntry};
+use super::layout;
+use super::lease::{CloudLease, LEASE_TIMEOUT};
+use super::reconcile::ReconcileResult;
+
+/// XOR `data` into `parity`, growing it as needed
+pub fn xor_into(parity: &mut Vec<u8>, data: &[u8]) {
+    if parity.len() < data.len() {
+        parity.resize(data.len(), 0);
+    }
+    for (p, d) in parity.iter_mut().zip(data.iter()) {
+        *p ^= d;
+    }
+}
+
+/// Helper to build the parity object of a group of chunk archives
+#[derive(Default)]
+pub struct ParityBuilder {
+    archives: Vec<Uuid>,
+    data: Vec<u8>,
+}
+
+impl ParityBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the data of a chunk archive to the group
+    pub fn add(&mut self, archive: &Uuid, data: &[u8]) {
+        self.archives.push(archive.clone());
+        xor_into(&mut self.data, data);
+    }
+
+    /// Number of archives in the group
+    pub fn len(&self) -> usize {
+        self.archives.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.archives.is_empty()
+    }
+
+    /// Upload the parity object of the group
+    pub fn finish(
+        self,
+        backend: &dyn CloudBackend,
+        media_set: &Uuid,
+        options: &PutOptions,
+    ) -> Result<ParityEntry, Error> {
+        let uuid = Uuid::generate();
+        backend
+            .put_object_multipart(&layout::parity_key(media_set, &uuid), &self.data, options)
+            .map_err(|err| format_err!("unable to upload parity object - {}", err))?;
+        Ok(ParityEntry {
+            uuid,
+            archives: self.archives,
+            size: self.data.len() as u64,
+            csum: openssl::sha::sha256(&self.data),
+        })
+    }
+}
+
+fn lookup_archive<'a>(
+    media_set: &'a MediaSetCatalog,
+    uuid: &Uuid,
+) -> Result<&'a ChunkArchiveEntry, Error> {
+    media_set
+        .archives
+        .iter()
+        .find(|archive| &archive.uuid == uuid)
+        .ok_or_else(|| format_err!("chunk archive {} not in catalog", uuid))
+}
+
+// download a chunk archive and check it against the catalog
+fn load_archive(
+    backend: &dyn CloudBackend,
+    media_set: &MediaSetCatalog,
+    archive: &ChunkArchiveEntry,
+) -> Result<Vec<u8>, Error> {
+    let data = backend.get_object(&layout::chunk_archive_key(media_set.uuid(), &archive.uuid))?;
+    if data.len() as u64 != archive.size {
+        bail!(
+            "chunk archive {} has wrong size ({} != {})",
+            archive.uuid,
+            data.len(),
+            archive.size
+        );
+    }
+    if let Some(csum) = archive.csum {
+        if openssl::sha::sha256(&data) != csum {
+            bail!("chunk archive {} has wrong checksum", archive.uuid);
+        }
+    }
+    Ok(data)
+}
+
+/// Rebuild a chunk archive from the parity object of its group
+///
+/// Fails unless the parity object and all other archives of the group
+/// are intact.
+pub fn rebuild_chunk_archive(
+    backend: &dyn CloudBackend,
+    media_set: &MediaSetCatalog,
+    uuid: &Uuid,
+) -> Result<Vec<u8>, Error> {
+    let archive = lookup_archive(media_set, uuid)?;
+    let parity = match media_set
+        .parity
+        .iter()
+        .find(|parity| parity.archives.contains(uuid))
+    {
+        Some(parity) => parity,
+        None => bail!("no parity object for chunk archive {}", uuid),
+    };
+
+    let mut data = backend.get_object(&layout::parity_key(media_set.uuid(), &parity.uuid))?;
+    if data.len() as u64 != parity.size || openssl::sha::sha256(&data) != parity.csum {
+        bail!("parity object {} is damaged", parity.uuid);
+    }
+
+    for other in parity.archives.iter().filter(|other| *other != uuid) {
+        let other = lookup_archive(media_set, other)?;
+        xor_into(&mut data, &load_archive(backend, media_set, other)?);
+    }
+
+    data.truncate(archive.size as usize);
+    if let Some(csum) = archive.csum {
+        if openssl::sha::sha256(&data) != csum {
+            bail!("rebuilt chunk archive {} has wrong checksum", uuid);
+        }
+    }
+
+    Ok(data)
+}
+
+/// Rebuild a lost parity object from the archives of its group
+pub fn rebuild_parity(
+    backend: &dyn CloudBackend,
+    media_set: &MediaSetCatalog,
+    parity: &ParityEntry,
+) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    for uuid in parity.archives.iter() {
+        let archive = lookup_archive(media_set, uuid)?;
+        xor_into(&mut data, &load_archive(backend, media_set, archive)?);
+    }
+    if openssl::sha::sha256(&data) != parity.csum {
+        bail!("rebuilt parity object {} has wrong checksum", parity.uuid);
+    }
+    Ok(data)
+}
+
+/// Recompute and upload the parity object of a group whose archives were
+/// rewritten (e.g. re-encrypted), returning the updated catalog entry
+pub fn refresh_parity(
+    backend: &dyn CloudBackend,
+    media_set: &MediaSetCatalog,
+    parity: &ParityEntry,
+    options: &PutOptions,
+) -> Result<ParityEntry, Error> {
+    let mut data = Vec::new();
+    for uuid in parity.archives.iter() {
+        let archive = lookup_archive(media_set, uuid)?;
+        xor_into(&mut data, &load_archive(backend, media_set, archive)?);
+    }
+    backend
+        .put_object_multipart(
+            &layout::parity_key(media_set.uuid(), &parity.uuid),
+            &data,
+            options,
+        )
+        .map_err(|err| format_err!("unable to upload parity object - {}", err))?;
+    Ok(ParityEntry {
+        uuid: parity.uuid.clone(),
+        archives: parity.archives.clone(),
+        size: data.len() as u64,
+        csum: openssl::sha::sha256(&data),
+    })
+}
+
+/// Repair the chunk archives and parity objects reported by a
+/// reconciliation run
+///
+/// Returns the keys of the repaired objects. Objects which cannot be
+/// repaired are logged and left as they are.
+pub fn repair_target<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+    result: &ReconcileResult,
+) -> Result<Vec<String>, Error> {
+    let damaged: HashSet<&String> = result
+        .missing
+        .iter()
+        .chain(result.size_mismatch.iter())
+        .collect();
+
+    let mut repaired = Vec::new();
+    if damaged.is_empty() {
+        return Ok(repaired);
+    }
+
+    let catalog = CloudCatalog::load(base_path, &target.name)?;
+    let options = PutOptions::default().with_object_tags(target, None)?;
+
+    let mut lease =
+        CloudLease::acquire(Arc::clone(backend), proxmox_sys::nodename(), LEASE_TIMEOUT)?;
+
+    for media_set in catalog.media_sets() {
+        let uuid = media_set.uuid();
+
+        for archive in media_set.archives.iter() {
+            let key = layout::chunk_archive_key(uuid, &archive.uuid);
+            if !damaged.contains(&key) {
+                continue;
+            }
+            worker.check_abort()?;
+            lease.heartbeat()?;
+
+            match rebuild_chunk_archive(&**backend, media_set, &archive.uuid)
+                .and_then(|data| backend.put_object_multipart(&key, &data, &options))
+            {
+                Ok(()) => {
+                    task_log!(worker, "repaired chunk archive {}", key);
+                    repaired.push(key);
+                }
+                Err(err) => task_warn!(worker, "unable to repair chunk archive {} - {}", key, err),
+            }
+        }
+
+        for parity in media_set.parity.iter() {
+            let key = layout::parity_key(uuid, &parity.uuid);
+            if !damaged.contains(&key) {
+                continue;
+            }
+            worker.check_abort()?;
+            lease.heartbeat()?;
+
+            match rebuild_parity(&**backend, media_set, parity)
+                .and_then(|data| backend.put_object_multipart(&key, &data, &options))
+            {
+                Ok(()) => {
+                    task_log!(worker, "repaired parity object {}", key);
+                    repaired.push(key);
+                }
+                Err(err) => task_warn!(worker, "unable to repair parity object {} - {}", key, err),
+            }
+        }
+    }
+
+    Ok(repaired)
+}
@@ -0,0 +1,216 @@
+//! Chunk popularity tracking
+//!
+//! Chunks downloaded from a cloud target (restores, file browsing) are
+//! counted per target. The statistics decide which chunks should stay in
+//! a local cache, and which chunk archives are cold enough to be moved to
+//! a cheaper (archive) storage tier.
+//!
+//! The statistics are advisory only, so concurrent updates may lose a few
+//! hits - we never lock the file for longer than a single write.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{create_path, replace_file};
+
+use pbs_api_types::{CloudChunkPopularity, CloudPlacementAdvice, CloudTierCandidate};
+
+use super::catalog::CloudCatalog;
+use super::create_options;
+
+/// Hits lose half of their weight after this many seconds
+pub const POPULARITY_HALF_LIFE: i64 = 7 * 24 * 3600;
+
+/// Archives without downloads in this period are tier candidates
+pub const COLD_ARCHIVE_AGE: i64 = 30 * 24 * 3600;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct PopularityEntry {
+    #[serde(with = "hex::serde")]
+    digest: [u8; 32],
+    hits: u64,
+    last_access: i64,
+    /// Decayed score at `last_access`
+    score: f64,
+}
+
+impl PopularityEntry {
+    fn score_at(&self, now: i64) -> f64 {
+        let age = (now - self.last_access).max(0) as f64;
+        self.score * 0.5f64.powf(age / POPULARITY_HALF_LIFE as f64)
+    }
+}
+
+/// Download statistics of the chunks of a cloud target
+pub struct ChunkPopularity {
+    path: PathBuf,
+    map: HashMap<[u8; 32], PopularityEntry>,
+    dirty: bool,
+}
+
+impl ChunkPopularity {
+    fn stats_path(base_path: &Path, target: &str) -> PathBuf {
+        let mut path = base_path.to_owned();
+        path.push("popularity");
+        path.push(format!("{}.json", target));
+        path
+    }
+
+    /// Load the statistics of a target (empty if there are none yet)
+    pub fn load<P: AsRef<Path>>(base_path: P, target: &str) -> Result<Self, Error> {
+        let path = Self::stats_path(base_path.as_ref(), target);
+
+        let list: Vec<PopularityEntry> = match proxmox_sys::fs::file_get_optional_contents(&path)? {
+            Some(data) => serde_json::from_slice(&data)
+                .map_err(|err| format_err!("unable to parse {:?} - {}", path, err))?,
+            None => Vec::new(),
+        };
+
+        let map = list
+            .into_iter()
+            .map(|entry| (entry.digest, entry))
+            .collect();
+
+        Ok(Self {
+            path,
+            map,
+            dirty: false,
+        })
+    }
+
+    /// Store the statistics (only if something changed)
+    pub fn save(&mut self) -> Result<(), Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let options = create_options(0o0640)?;
+        if let Some(parent) = self.path.parent() {
+            create_path(
+                parent,
+                Some(create_options(0o0750)?),
+                Some(create_options(0o0750)?),
+            )?;
+        }
+
+        let list: Vec<&PopularityEntry> = self.map.values().collect();
+        let data = serde_json::to_vec(&list)?;
+        replace_file(&self.path, &data, options, true)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Count a download of `digest` at time `now`
+    pub fn record(&mut self, digest: &[u8; 32], now: i64) {
+        let entry = self.map.entry(*digest).or_insert(PopularityEntry {
+            digest: *digest,
+            hits: 0,
+            last_access: now,
+            score: 0.0,
+        });
+        entry.score = entry.score_at(now) + 1.0;
+        entry.hits += 1;
+        entry.last_access = entry.last_access.max(now);
+        self.dirty = true;
+    }
+
+    /// Number of chunks with recorded downloads
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Current score of a chunk (0 if it was never downloaded)
+    pub fn score(&self, digest: &[u8; 32], now: i64) -> f64 {
+        self.map
+            .get(digest)
+            .map(|entry| entry.score_at(now))
+            .unwrap_or(0.0)
+    }
+
+    /// Forget chunks which are no longer stored on the target
+    pub fn prune(&mut self, catalog: &CloudCatalog) {
+        let before = self.map.len();
+        self.map.retain(|digest, _| catalog.contains_chunk(digest));
+        if self.map.len() != before {
+            self.dirty = true;
+        }
+    }
+
+    /// Compute cache and tier placement advice
+    ///
+    /// The most popular chunks (up to `cache_size` bytes) should be kept in
+    /// the local cache. Chunk archives without downloads for
+    /// [`COLD_ARCHIVE_AGE`] seconds are listed as tier candidates.
+    pub fn advise(
+        &self,
+        catalog: &CloudCatalog,
+        now: i64,
+        cache_size: u64,
+    ) -> CloudPlacementAdvice {
+        let mut ranked: Vec<(&PopularityEntry, f64)> = self
+            .map
+            .values()
+            .map(|entry| (entry, entry.score_at(now)))
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let mut hot_chunks = Vec::new();
+        let mut hot_size = 0;
+        for (entry, score) in ranked {
+            let location = match catalog.find_chunk(&entry.digest) {
+                Some(location) => location,
+                None => continue, // removed from target
+            };
+            if hot_size + location.size > cache_size {
+                break;
+            }
+            hot_size += location.size;
+            hot_chunks.push(CloudChunkPopularity {
+                digest: hex::encode(entry.digest),
+                hits: entry.hits,
+                last_access: entry.last_access,
+                score,
+            });
+        }
+
+        let mut tier_candidates = Vec::new();
+        for media_set in catalog.media_sets() {
+            if now - media_set.label.ctime < COLD_ARCHIVE_AGE {
+                continue;
+            }
+            for archive in media_set.archives.iter() {
+                let last_access = archive
+                    .chunks
+                    .iter()
+                    .filter_map(|chunk| self.map.get(&chunk.digest))
+                    .map(|entry| entry.last_access)
+                    .max();
+                if matches!(last_access, Some(last) if now - last < COLD_ARCHIVE_AGE) {
+                    continue;
+                }
+                tier_candidates.push(CloudTierCandidate {
+                    media_set: media_set.uuid().clone(),
+                    archive: archive.uuid.clone(),
+                    size: archive.size,
+                    last_access,
+                });
+            }
+        }
+
+        CloudPlacementAdvice {
+            tracked_chunks: self.map.len() as u64,
+            hot_chunks,
+            hot_size,
+            tier_candidates,
+        }
+    }
+}
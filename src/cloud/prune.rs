@@ -0,0 +1,251 @@
+//! Prune simulation and cloud prune jobs
+//!
+//! Applies keep options to the snapshots on a target like a datastore
+//! prune, per backup group. The simulation does not change anything. Snapshots are not
+//! marked protected or incomplete on the target, so only the keep options
+//! decide.
+//!
+//! The reclaimed space accounts for deduplication: a chunk only counts if
+//! no remaining snapshot references it, including snapshots outside the
+//! selection and snapshots in the trash. Removed snapshots would go to the
+//! trash first (see [`super::trash`]), so the space is actually freed by
+//! the first compaction after the trash retention expired.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+use proxmox_time::strftime_local;
+
+use pbs_api_types::{
+    print_ns_and_snapshot, BackupDir, BackupGroup, BackupNamespace, CloudPruneSimulation,
+    CloudPruneSimulationItem, CloudTarget, KeepOptions,
+};
+
+use super::backend::CloudBackend;
+use super::catalog::CloudCatalog;
+use super::compaction::{live_chunks, ChunkId};
+use super::content::{latest_snapshots, CloudContentFilter};
+use super::trash::move_to_trash;
+
+// mark the newest snapshot of the first `keep` periods, like
+// pbs_datastore::prune
+fn mark_selections<F: Fn(&BackupDir) -> Result<String, Error>>(
+    marks: &mut [Option<bool>],
+    list: &[&BackupDir],
+    keep: u64,
+    select_id: F,
+) -> Result<(), Error> {
+    let mut already_included = HashSet::new();
+    for (dir, mark) in list.iter().zip(marks.iter()) {
+        if *mark == Some(true) {
+            already_included.insert(select_id(dir)?);
+        }
+    }
+
+    let mut include_hash = HashSet::new();
+    for (dir, mark) in list.iter().zip(marks.iter_mut()) {
+        if mark.is_some() {
+            continue;
+        }
+        let sel_id = select_id(dir)?;
+
+        if already_included.contains(&sel_id) {
+            continue;
+        }
+
+        if !include_hash.contains(&sel_id) {
+            if include_hash.len() as u64 >= keep {
+                break;
+            }
+            include_hash.insert(sel_id);
+            *mark = Some(true);
+        } else {
+            *mark = Some(false);
+        }
+    }
+
+    Ok(())
+}
+
+/// Which snapshots of a group to keep, `list` is sorted newest first
+pub fn compute_keep_marks(list: &[&BackupDir], options: &KeepOptions) -> Result<Vec<bool>, Error> {
+    if !options.keeps_something() {
+        return Ok(vec![true; list.len()]);
+    }
+
+    let mut marks = vec![None; list.len()];
+
+    let periods: [(Option<u64>, &str); 5] = [
+        (options.keep_hourly, "%Y/%m/%d/%H"),
+        (options.keep_daily, "%Y/%m/%d"),
+        // Note: Use iso-week year/week here. This year number
+        // might not match the calendar year number.
+        (options.keep_weekly, "%G/%V"),
+        (options.keep_monthly, "%Y/%m"),
+        (options.keep_yearly, "%Y"),
+    ];
+
+    if let Some(keep_last) = options.keep_last {
+        mark_selections(&mut marks, list, keep_last, |dir| Ok(dir.time.to_string()))?;
+    }
+
+    for (keep, format) in periods {
+        if let Some(keep) = keep {
+            mark_selections(&mut marks, list, keep, |dir| {
+                strftime_local(format, dir.time).map_err(Error::from)
+            })?;
+        }
+    }
+
+    Ok(marks.into_iter().map(|mark| mark == Some(true)).collect())
+}
+
+/// Simulate pruning the snapshots matching `filter` with `options`
+pub fn simulate_prune(
+    catalog: &CloudCatalog,
+    filter: &CloudContentFilter,
+    options: &KeepOptions,
+) -> Result<CloudPruneSimulation, Error> {
+    // sorted by store, namespace, group and time
+    let snapshots = latest_snapshots(catalog, filter);
+
+    let mut groups: BTreeMap<(&str, &BackupNamespace, &BackupGroup), Vec<&BackupDir>> =
+        BTreeMap::new();
+    for (_, entry) in snapshots.iter() {
+        groups
+            .entry((entry.store.as_str(), &entry.ns, &entry.snapshot.group))
+            .or_default()
+            .push(&entry.snapshot);
+    }
+
+    let mut removed = BTreeSet::new();
+    for ((store, ns, _group), mut list) in groups {
+        list.reverse();
+        let marks = compute_keep_marks(&list, options)?;
+        for (dir, keep) in list.into_iter().zip(marks) {
+            if !keep {
+                removed.insert((store, ns, dir));
+            }
+        }
+    }
+
+    let is_removed =
+        |store: &str, ns: &BackupNamespace, dir: &BackupDir| removed.contains(&(store, ns, dir));
+
+    // files of all copies of removed snapshots
+    let mut reclaimed_bytes = 0;
+    let mut remaining_chunks: HashSet<ChunkId> = HashSet::new();
+    for (_, entry) in catalog.snapshots() {
+        if is_removed(&entry.store, &entry.ns, &entry.snapshot) {
+            reclaimed_bytes += entry.files.iter().map(|file| file.size).sum::<u64>();
+        } else {
+            for digest in entry.chunks.iter() {
+                remaining_chunks.insert((entry.key.clone(), *digest));
+            }
+        }
+    }
+    for (_, trashed) in catalog.trashed_snapshots() {
+        let entry = &trashed.entry;
+        for digest in entry.chunks.iter() {
+            remaining_chunks.insert((entry.key.clone(), *digest));
+        }
+    }
+
+    // stored chunks which are live now, but not afterwards
+    let live = live_chunks(catalog);
+    for media_set in catalog.media_sets() {
+        for archive in media_set.archives.iter() {
+            for chunk in archive.chunks.iter() {
+                let id = (archive.key.clone(), chunk.digest);
+                if live.contains(&id) && !remaining_chunks.contains(&id) {
+                    reclaimed_bytes += chunk.size;
+                }
+            }
+        }
+    }
+
+    let snapshots: Vec<CloudPruneSimulationItem> = snapshots
+        .into_iter()
+        .map(|(_, entry)| CloudPruneSimulationItem {
+            store: entry.store.clone(),
+            ns: entry.ns.clone(),
+            backup: entry.snapshot.clone(),
+            keep: !is_removed(&entry.store, &entry.ns, &entry.snapshot),
+        })
+        .collect();
+
+    let kept = snapshots.iter().filter(|item| item.keep).count() as u64;
+
+    Ok(CloudPruneSimulation {
+        removed: snapshots.len() as u64 - kept,
+        kept,
+        snapshots,
+        reclaimed_bytes,
+    })
+}
+
+/// Result of a prune run
+#[derive(Debug, Default)]
+pub struct CloudPruneResult {
+    /// Snapshots moved to the trash
+    pub removed: Vec<String>,
+    /// Number of kept snapshots
+    pub kept: usize,
+    /// Snapshots which could not be moved to the trash
+    pub failed: Vec<String>,
+}
+
+/// Prune the snapshots of a target matching `filter` with `options`
+///
+/// Snapshots which are not kept are moved to the trash of the target. A
+/// snapshot which cannot be moved is recorded as failed, the other ones are
+/// pruned anyway.
+pub fn prune_snapshots<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+    filter: &CloudContentFilter,
+    options: &KeepOptions,
+) -> Result<CloudPruneResult, Error> {
+    let base_path = base_path.as_ref();
+    let catalog = CloudCatalog::load(base_path, &target.name)?;
+    let simulation = simulate_prune(&catalog, filter, options)?;
+
+    let mut result = CloudPruneResult::default();
+    for item in simulation.snapshots {
+        if item.keep {
+            result.kept += 1;
+            continue;
+        }
+        worker.check_abort()?;
+
+        let name = format!(
+            "{}:{}",
+            item.store,
+            print_ns_and_snapshot(&item.ns, &item.backup)
+        );
+        task_log!(worker, "prune {}", name);
+        match move_to_trash(
+            worker,
+            base_path,
+            target,
+            backend,
+            &item.store,
+            &item.ns,
+            &item.backup,
+        ) {
+            Ok(_) => result.removed.push(name),
+            Err(err) => {
+                task_warn!(worker, "prune {} failed - {}", name, err);
+                result.failed.push(name);
+            }
+        }
+    }
+
+    Ok(result)
+}
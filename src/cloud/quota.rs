@@ -0,0 +1,116 @@
+//! Storage quotas
+//!
+//! Targets can limit the data uploaded for a backup owner or a namespace
+//! (target option `quota`). The chunk archives and files written for a
+//! snapshot are attributed to it in the catalog, together with the owner
+//! of its backup group. The usage of a quota is the sum over all snapshots
+//! on the target, so chunks shared through deduplication count for the
+//! snapshot which uploaded them first.
+//!
+//! Backup jobs skip snapshots whose estimated upload would exceed a quota.
+
+use anyhow::{bail, Error};
+
+use pbs_api_types::{Authid, BackupNamespace, CloudQuota, CloudQuotaStatus};
+
+use super::catalog::{AttributedUsage, CloudCatalog};
+use super::dedup_stats::DedupStats;
+use super::MAX_CHUNK_ARCHIVE_SIZE;
+
+/// Estimated upload of a snapshot with `file_count` files
+///
+/// Uses the plain size of the chunks missing on the target (see
+/// [`DedupStats`]), the files themselves are small and not counted.
+pub fn estimate_snapshot_usage(stats: &DedupStats, file_count: usize) -> AttributedUsage {
+    let bytes = stats.logical_bytes.saturating_sub(stats.present_bytes);
+    let archives = bytes.div_ceil(MAX_CHUNK_ARCHIVE_SIZE as u64);
+    AttributedUsage {
+        bytes,
+        // the files plus the summary object
+        objects: archives + file_count as u64 + 1,
+    }
+}
+
+/// Tracks the usage of the quotas of a target during a backup job
+pub struct QuotaTracker {
+    quotas: Vec<(CloudQuota, AttributedUsage)>,
+}
+
+impl QuotaTracker {
+    /// Sum up the usage of `quotas` over the snapshots in `catalog`
+    pub fn new(quotas: Vec<CloudQuota>, catalog: &CloudCatalog) -> Self {
+        let mut tracker = Self {
+            quotas: quotas
+                .into_iter()
+                .map(|quota| (quota, AttributedUsage::default()))
+                .collect(),
+        };
+        for (_, entry) in catalog.snapshots() {
+            tracker.add(entry.owner.as_ref(), &entry.ns, &entry.attributed);
+        }
+        tracker
+    }
+
+    /// Check that uploading `usage` for a snapshot of `ns` owned by `owner`
+    /// stays within all quotas
+    pub fn check(
+        &self,
+        owner: Option<&Authid>,
+        ns: &BackupNamespace,
+        usage: &AttributedUsage,
+    ) -> Result<(), Error> {
+        for (quota, used) in self.quotas.iter() {
+            if !quota.applies_to(owner, ns) {
+                continue;
+            }
+            if let Some(max_bytes) = quota.max_bytes() {
+                if used.bytes + usage.bytes > max_bytes {
+                    bail!(
+                        "{} exceeded - {} of {} bytes used, snapshot needs about {} bytes",
+                        quota,
+                        used.bytes,
+                        max_bytes,
+                        usage.bytes
+                    );
+                }
+            }
+            if let Some(max_objects) = quota.max_objects {
+                if used.objects + usage.objects > max_objects {
+                    bail!(
+                        "{} exceeded - {} of {} objects used, snapshot needs about {} objects",
+                        quota,
+                        used.objects,
+                        max_objects,
+                        usage.objects
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Attribute `usage` of a snapshot of `ns` owned by `owner`
+    pub fn add(&mut self, owner: Option<&Authid>, ns: &BackupNamespace, usage: &AttributedUsage) {
+        for (quota, used) in self.quotas.iter_mut() {
+            if quota.applies_to(owner, ns) {
+                used.add(usage);
+            }
+        }
+    }
+
+    /// Usage and remaining quota
+    pub fn status(&self) -> Vec<CloudQuotaStatus> {
+        self.quotas
+            .iter()
+            .map(|(quota, used)| CloudQuotaStatus {
+                quota: quota.clone(),
+                used_bytes: used.bytes,
+                used_objects: used.objects,
+                remaining_bytes: quota.max_bytes().map(|max| max.saturating_sub(used.bytes)),
+                remaining_objects: quota
+                    .max_objects
+                    .map(|max| max.saturating_sub(used.objects)),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,150 @@
+//! Compare the objects stored on a target with the local catalog
+//!
+//! Initial backups of large datastores can be exported to a local disk
+//! (see the `export-path` parameter of the cloud backup API) and shipped
+//! to the provider for import. Reconciliation afterwards makes sure that
+//! every object referenced by the catalog arrived in the bucket.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::CloudTarget;
+
+use super::backend::CloudBackend;
+use super::catalog::{CloudCatalog, MediaSetCatalog};
+use super::layout;
+
+/// Result of a reconciliation run
+#[derive(Debug, Default)]
+pub struct ReconcileResult {
+    /// Number of checked objects
+    pub checked: usize,
+    /// Keys of objects not found on the target
+    pub missing: Vec<String>,
+    /// Keys of objects with unexpected size
+    pub size_mismatch: Vec<String>,
+}
+
+impl ReconcileResult {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.size_mismatch.is_empty()
+    }
+
+    /// Forget about objects which were repaired
+    pub fn remove_repaired(&mut self, repaired: &[String]) {
+        self.missing.retain(|key| !repaired.contains(key));
+        self.size_mismatch.retain(|key| !repaired.contains(key));
+    }
+}
+
+/// Objects a committed media set consists of, with the expected size if known
+pub fn expected_objects(media_set: &MediaSetCatalog) -> Vec<(String, Option<u64>)> {
+    let uuid = media_set.uuid();
+
+    let mut list = vec![
+        (layout::media_set_label_key(uuid), None),
+        (layout::media_set_catalog_key(uuid), None),
+    ];
+
+    // the foreign objects of imported media sets are not ours to check
+    if media_set.is_imported() {
+        return list;
+    }
+
+    for archive in media_set.archives.iter() {
+        list.push((
+            layout::chunk_archive_key(uuid, &archive.uuid),
+            Some(archive.size),
+        ));
+    }
+
+    for parity in media_set.parity.iter() {
+        list.push((layout::parity_key(uuid, &parity.uuid), Some(parity.size)));
+    }
+
+    for snapshot in media_set.snapshots.iter() {
+        for file in snapshot.files.iter() {
+            // the catalog stores the size of the plain data
+            let size = match snapshot.key {
+                Some(_) => None,
+                None => Some(file.size),
+            };
+            list.push((
+                layout::snapshot_file_key(
+                    uuid,
+                    &snapshot.store,
+                    &snapshot.ns,
+                    &snapshot.snapshot,
+                    &file.filename,
+                ),
+                size,
+            ));
+        }
+    }
+
+    list
+}
+
+/// Check that all objects of all media sets in the local catalog exist on the target
+pub fn reconcile_target<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+) -> Result<ReconcileResult, Error> {
+    let catalog = CloudCatalog::load(base_path, &target.name)?;
+
+    let mut result = ReconcileResult::default();
+
+    for media_set in catalog.media_sets() {
+        worker.check_abort()?;
+
+        let stored: HashMap<String, u64> = backend
+            .list_objects(&layout::media_set_prefix(media_set.uuid()))?
+            .into_iter()
+            .map(|info| (info.key, info.size))
+            .collect();
+
+        let expected = expected_objects(media_set);
+        let mut errors = 0;
+
+        for (key, size) in expected.iter() {
+            match (stored.get(key), size) {
+                (None, _) => {
+                    task_warn!(worker, "missing object {}", key);
+                    result.missing.push(key.clone());
+                    errors += 1;
+                }
+                (Some(stored_size), Some(size)) if stored_size != size => {
+                    task_warn!(
+                        worker,
+                        "object {} has size {}, expected {}",
+                        key,
+                        stored_size,
+                        size
+                    );
+                    result.size_mismatch.push(key.clone());
+                    errors += 1;
+                }
+                _ => {}
+            }
+        }
+
+        result.checked += expected.len();
+
+        task_log!(
+            worker,
+            "media set {}: checked {} objects, {} errors",
+            media_set.uuid(),
+            expected.len(),
+            errors
+        );
+    }
+
+    Ok(result)
+}
@@ -0,0 +1,439 @@
+//! Repair of damaged snapshots from local data
+//!
+//! If objects of a snapshot were lost or modified on the target, but the
+//! snapshot still exists in the local datastore, only the damaged objects
+//! are uploaded again instead of a full new backup of the snapshot:
+//!
+//! - snapshot files are uploaded if the local file still has the checksum
+//!   recorded in the catalog
+//! - chunk archives are rebuilt from the local chunks at the offsets
+//!   recorded in the catalog (also used by the consistency check, see
+//!   [`super::fsck`])
+//!
+//! Encrypted archives get a new checksum (new IV), which is updated in the
+//! media set catalog together with the parity object of the archive.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{BackupDir, BackupNamespace, CloudTarget, Operation};
+use pbs_datastore::{DataStore, SnapshotReader};
+use pbs_tools::crypt_config::CryptConfig;
+
+use super::backend::{CloudBackend, PutOptions};
+use super::catalog::{
+    replace_media_set_catalog, ChunkArchiveEntry, CloudCatalog, MediaSetCatalog, SnapshotEntry,
+};
+use super::encryption_keys::{decrypt_object, encrypt_object, load_crypt_config};
+use super::layout;
+use super::lease::{CloudLease, LEASE_TIMEOUT};
+use super::parity::refresh_parity;
+
+/// Damaged objects of a snapshot
+#[derive(Debug, Default, PartialEq)]
+pub struct SnapshotDamage {
+    /// Names of lost or modified snapshot files
+    pub files: Vec<String>,
+    /// Lost or modified chunk archives as `(media set, archive)`
+    pub archives: Vec<(Uuid, Uuid)>,
+}
+
+impl SnapshotDamage {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.archives.is_empty()
+    }
+}
+
+fn lookup_archive<'a>(
+    catalog: &'a CloudCatalog,
+    media_set: &Uuid,
+    archive: &Uuid,
+) -> Result<(&'a MediaSetCatalog, &'a ChunkArchiveEntry), Error> {
+    catalog
+        .lookup_media_set(media_set)
+        .and_then(|set| {
+            set.archives
+                .iter()
+                .find(|entry| &entry.uuid == archive)
+                .map(|entry| (set, entry))
+        })
+        .ok_or_else(|| format_err!("chunk archive {} not found in catalog", archive))
+}
+
+// whether a snapshot file on the target matches the catalog
+fn snapshot_file_intact(
+    backend: &dyn CloudBackend,
+    key: &str,
+    size: u64,
+    csum: &[u8; 32],
+    crypt_config: Option<&CryptConfig>,
+) -> Result<bool, Error> {
+    if backend.head_object(key)?.is_none() {
+        return Ok(false);
+    }
+    let data = backend.get_object(key)?;
+    let data = match crypt_config {
+        Some(crypt_config) => match decrypt_object(&data, crypt_config) {
+            Ok(data) => data,
+            Err(_) => return Ok(false),
+        },
+        None => data,
+    };
+    Ok(data.len() as u64 == size && &openssl::sha::sha256(&data) == csum)
+}
+
+// whether a chunk archive on the target matches the catalog
+fn chunk_archive_intact(
+    backend: &dyn CloudBackend,
+    key: &str,
+    archive: &ChunkArchiveEntry,
+) -> Result<bool, Error> {
+    match backend.head_object(key)? {
+        Some(info) if info.size == archive.size => {}
+        _ => return Ok(false),
+    }
+    match archive.csum {
+        Some(csum) => Ok(openssl::sha::sha256(&backend.get_object(key)?) == csum),
+        // archives without checksum can only be checked by size
+        None => Ok(true),
+    }
+}
+
+/// Download all objects of a snapshot and check them against the catalog
+///
+/// Snapshot files are compared by size and checksum of the plain data,
+/// chunk archives (of all media sets holding chunks of the snapshot) by
+/// size and checksum of the object.
+pub fn check_snapshot(
+    backend: &dyn CloudBackend,
+    catalog: &CloudCatalog,
+    media_set: &MediaSetCatalog,
+    entry: &SnapshotEntry,
+    crypt_config: Option<&CryptConfig>,
+) -> Result<SnapshotDamage, Error> {
+    if entry.key.is_some() && crypt_config.is_none() {
+        bail!("snapshot is encrypted, but no key was given");
+    }
+
+    let mut damage = SnapshotDamage::default();
+
+    for file in entry.files.iter() {
+        let key = layout::snapshot_file_key(
+            media_set.uuid(),
+            &entry.store,
+            &entry.ns,
+            &entry.snapshot,
+            &file.filename,
+        );
+        if !snapshot_file_intact(backend, &key, file.size, &file.csum, crypt_config)? {
+            damage.files.push(file.filename.clone());
+        }
+    }
+
+    let mut checked = HashSet::new();
+    for digest in entry.chunks.iter() {
+        let location = catalog
+            .lookup_chunk(digest, entry.key.as_ref())
+            .ok_or_else(|| format_err!("chunk {} not found in catalog", hex::encode(digest)))?;
+
+        let key = layout::chunk_archive_key(&location.media_set, &location.archive);
+        if !checked.insert(key.clone()) {
+            continue;
+        }
+
+        let (_, archive) = lookup_archive(catalog, &location.media_set, &location.archive)?;
+        if !chunk_archive_intact(backend, &key, archive)? {
+            damage
+                .archives
+                .push((location.media_set.clone(), location.archive.clone()));
+        }
+    }
+
+    Ok(damage)
+}
+
+/// Rebuild the data of a chunk archive
+///
+/// `load_chunk` returns the chunk blob as stored in the archive (i.e.
+/// already encrypted for encrypted archives). Unencrypted archives must
+/// match the checksum from the catalog.
+pub fn build_chunk_archive<F>(
+    archive: &ChunkArchiveEntry,
+    mut load_chunk: F,
+) -> Result<Vec<u8>, Error>
+where
+    F: FnMut(&[u8; 32]) -> Result<Vec<u8>, Error>,
+{
+    let mut data = vec![0u8; archive.size as usize];
+    let mut covered = 0;
+
+    for chunk in archive.chunks.iter() {
+        let raw = load_chunk(&chunk.digest)?;
+        if raw.len() as u64 != chunk.size {
+            bail!(
+                "chunk {} has wrong size ({} != {})",
+                hex::encode(chunk.digest),
+                raw.len(),
+                chunk.size
+            );
+        }
+        let start = chunk.offset as usize;
+        let end = start + raw.len();
+        if end > data.len() {
+            bail!(
+                "chunk {} exceeds chunk archive {}",
+                hex::encode(chunk.digest),
+                archive.uuid
+            );
+        }
+        data[start..end].copy_from_slice(&raw);
+        covered += raw.len();
+    }
+
+    if covered != data.len() {
+        bail!("chunks do not cover chunk archive {}", archive.uuid);
+    }
+
+    if archive.key.is_none() {
+        if let Some(csum) = archive.csum {
+            if openssl::sha::sha256(&data) != csum {
+                bail!("rebuilt chunk archive {} has wrong checksum", archive.uuid);
+            }
+        }
+    }
+
+    Ok(data)
+}
+
+// re-encode a local snapshot file, which must still match the catalog
+fn load_snapshot_file(
+    reader: &SnapshotReader,
+    entry: &SnapshotEntry,
+    filename: &str,
+    crypt_config: Option<&CryptConfig>,
+) -> Result<Vec<u8>, Error> {
+    let file = entry
+        .files
+        .iter()
+        .find(|file| file.filename == filename)
+        .ok_or_else(|| format_err!("file '{}' not found in catalog", filename))?;
+
+    let mut data = Vec::new();
+    reader.open_file(filename)?.read_to_end(&mut data)?;
+    if data.len() as u64 != file.size || openssl::sha::sha256(&data) != file.csum {
+        bail!("local file '{}' differs from the uploaded file", filename);
+    }
+
+    match crypt_config {
+        Some(crypt_config) => encrypt_object(&data, crypt_config),
+        None => Ok(data),
+    }
+}
+
+/// Source of the chunks chunk archives are rebuilt from
+///
+/// Gets the datastore and digest and returns the plain chunk blob.
+pub type ChunkSource<'a> = &'a dyn Fn(&str, &[u8; 32]) -> Result<Vec<u8>, Error>;
+
+/// Load a chunk from a local datastore
+pub fn datastore_chunk(store: &str, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+    let datastore = DataStore::lookup_datastore(store, Some(Operation::Read))?;
+    Ok(datastore.load_chunk(digest)?.into_inner())
+}
+
+/// Rebuild chunk archives from local chunks and upload them again
+///
+/// On versioned buckets this creates a new version of the archive.
+/// Archives which cannot be rebuilt, for example because some chunks were
+/// garbage collected locally, are logged and skipped. Encrypted archives
+/// get a new checksum, which is updated in the media set catalog together
+/// with the parity objects of the archives.
+///
+/// Returns the uploaded archives.
+pub fn reupload_chunk_archives<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+    lease: &mut CloudLease,
+    archives: &[(Uuid, Uuid)],
+    load_chunk: ChunkSource,
+) -> Result<Vec<(Uuid, Uuid)>, Error> {
+    let base_path = base_path.as_ref();
+    let catalog = CloudCatalog::load(base_path, &target.name)?;
+    let options = PutOptions::default().with_object_tags(target, None)?;
+
+    let mut uploaded = Vec::new();
+
+    // media sets with changed archive checksums
+    let mut changed: HashMap<Uuid, MediaSetCatalog> = HashMap::new();
+
+    for (set_uuid, archive_uuid) in archives.iter() {
+        worker.check_abort()?;
+        lease.heartbeat()?;
+
+        let (set, archive) = lookup_archive(&catalog, set_uuid, archive_uuid)?;
+        let key = layout::chunk_archive_key(set_uuid, archive_uuid);
+        let crypt_config = archive.key.as_ref().map(load_crypt_config).transpose()?;
+
+        let data = build_chunk_archive(archive, |digest| {
+            let raw = load_chunk(&archive.store, digest)?;
+            match crypt_config.as_deref() {
+                Some(crypt_config) => encrypt_object(&raw, crypt_config),
+                None => Ok(raw),
+            }
+        });
+        let data = match data {
+            Ok(data) => data,
+            Err(err) => {
+                task_warn!(worker, "unable to rebuild chunk archive {} - {}", key, err);
+                continue;
+            }
+        };
+
+        backend
+            .put_object_multipart(&key, &data, &options)
+            .map_err(|err| format_err!("unable to upload chunk archive - {}", err))?;
+        task_log!(worker, "uploaded chunk archive {}", key);
+
+        if archive.key.is_some() {
+            let set = changed
+                .entry(set_uuid.clone())
+                .or_insert_with(|| set.clone());
+            if let Some(archive) = set
+                .archives
+                .iter_mut()
+                .find(|entry| &entry.uuid == archive_uuid)
+            {
+                archive.csum = Some(openssl::sha::sha256(&data));
+            }
+        }
+
+        uploaded.push((set_uuid.clone(), archive_uuid.clone()));
+    }
+
+    for (_, mut set) in changed {
+        lease.heartbeat()?;
+
+        let mut parity_list = std::mem::take(&mut set.parity);
+        for parity in parity_list.iter_mut() {
+            let affected = uploaded.iter().any(|(set_uuid, archive)| {
+                set_uuid == set.uuid() && parity.archives.contains(archive)
+            });
+            if affected {
+                *parity = refresh_parity(&**backend, &set, parity, &options)?;
+                task_log!(worker, "updated parity object {}", parity.uuid);
+            }
+        }
+        set.parity = parity_list;
+
+        replace_media_set_catalog(&**backend, &set)?;
+        set.save(base_path, &target.name)?;
+    }
+
+    Ok(uploaded)
+}
+
+/// Check a snapshot on the target and upload its damaged objects again
+/// from the local datastore
+///
+/// Returns the damaged objects, which are only reported with `dry_run`.
+#[allow(clippy::too_many_arguments)]
+pub fn repair_snapshot<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+    store: &str,
+    ns: &BackupNamespace,
+    snapshot: &BackupDir,
+    dry_run: bool,
+) -> Result<SnapshotDamage, Error> {
+    let base_path = base_path.as_ref();
+    let catalog = CloudCatalog::load(base_path, &target.name)?;
+
+    let (media_set, entry) = catalog
+        .lookup_snapshot(store, ns, snapshot)
+        .ok_or_else(|| format_err!("snapshot {} not found on cloud target", snapshot))?;
+
+    if media_set.is_imported() {
+        bail!(
+            "snapshot {} is part of imported media set {}, which is read-only",
+            snapshot,
+            media_set.uuid()
+        );
+    }
+
+    let crypt_config = entry.key.as_ref().map(load_crypt_config).transpose()?;
+    let crypt_config = crypt_config.as_deref();
+
+    let damage = check_snapshot(&**backend, &catalog, media_set, entry, crypt_config)?;
+    for filename in damage.files.iter() {
+        task_log!(worker, "damaged file '{}'", filename);
+    }
+    for (set, archive) in damage.archives.iter() {
+        task_log!(
+            worker,
+            "damaged chunk archive {}",
+            layout::chunk_archive_key(set, archive)
+        );
+    }
+    if damage.is_empty() || dry_run {
+        return Ok(damage);
+    }
+
+    // keep the snapshot locked, so it cannot be pruned while we read it
+    let datastore = DataStore::lookup_datastore(store, Some(Operation::Read))?;
+    let reader = datastore
+        .backup_dir(ns.clone(), snapshot.clone())?
+        .locked_reader()
+        .map_err(|err| format_err!("unable to open local snapshot {} - {}", snapshot, err))?;
+
+    let options = PutOptions::default().with_object_tags(target, None)?;
+    let mut lease =
+        CloudLease::acquire(Arc::clone(backend), proxmox_sys::nodename(), LEASE_TIMEOUT)?;
+
+    for filename in damage.files.iter() {
+        worker.check_abort()?;
+        lease.heartbeat()?;
+
+        let data = load_snapshot_file(&reader, entry, filename, crypt_config)?;
+        let key = layout::snapshot_file_key(
+            media_set.uuid(),
+            &entry.store,
+            &entry.ns,
+            &entry.snapshot,
+            filename,
+        );
+        backend
+            .put_object_multipart(&key, &data, &options)
+            .map_err(|err| format_err!("unable to upload '{}' - {}", filename, err))?;
+        task_log!(worker, "uploaded file '{}'", filename);
+    }
+
+    let uploaded = reupload_chunk_archives(
+        worker,
+        base_path,
+        target,
+        backend,
+        &mut lease,
+        &damage.archives,
+        &datastore_chunk,
+    )?;
+    if uploaded.len() != damage.archives.len() {
+        bail!(
+            "unable to rebuild {} of {} chunk archives",
+            damage.archives.len() - uploaded.len(),
+            damage.archives.len()
+        );
+    }
+
+    Ok(damage)
+}
@@ -0,0 +1,263 @@
+//! Replication of media sets between cloud targets
+//!
+//! Media sets are copied object by object, the catalog last (it marks the
+//! media set as complete on the destination). Objects are copied
+//! server-side if the destination can read from the source directly (e.g.
+//! two buckets on the same endpoint), otherwise they are downloaded and
+//! uploaded again. Objects already present on the destination are
+//! skipped, so an interrupted replication continues where it stopped.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+
+use proxmox_human_byte::HumanByte;
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{CloudTarget, GroupFilter};
+
+use super::backend::{copy_between_targets, CloudBackend, ObjectInfo};
+use super::catalog::{CloudCatalog, MediaSetCatalog};
+use super::layout;
+use super::lease::{CloudLease, LEASE_TIMEOUT};
+
+/// Selects the media sets to replicate
+#[derive(Clone, Debug, Default)]
+pub struct ReplicationFilter {
+    /// Only media sets containing data of this datastore
+    pub store: Option<String>,
+    /// Only media sets created at or after this time (UNIX epoch)
+    pub since: Option<i64>,
+    /// Only media sets containing snapshots of backup groups matching these filters
+    pub group_filter: Option<Vec<GroupFilter>>,
+}
+
+impl ReplicationFilter {
+    fn matches(&self, media_set: &MediaSetCatalog) -> bool {
+        if let Some(since) = self.since {
+            if media_set.label.ctime < since {
+                return false;
+            }
+        }
+        if let Some(ref store) = self.store {
+            let has_store = media_set.archives.iter().any(|a| &a.store == store)
+                || media_set.snapshots.iter().any(|s| &s.store == store);
+            if !has_store {
+                return false;
+            }
+        }
+        if let Some(ref filters) = self.group_filter {
+            // chunk archives are shared by all groups, so only snapshots count
+            let has_group = media_set.snapshots.iter().any(|entry| {
+                self.store
+                    .as_ref()
+                    .map_or(true, |store| &entry.store == store)
+                    && entry.snapshot.group.apply_filters(filters)
+            });
+            if !has_group {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Statistics of a finished replication
+#[derive(Debug, Default)]
+pub struct ReplicationStats {
+    /// Number of replicated media sets
+    pub media_sets: usize,
+    /// Objects copied server-side
+    pub server_side: usize,
+    /// Objects downloaded and uploaded again
+    pub streamed: usize,
+    /// Bytes transferred by streaming copies
+    pub bytes: u64,
+    /// Objects already present on the destination
+    pub skipped: usize,
+}
+
+/// Media sets to replicate, oldest first
+///
+/// Incremental media sets reference chunks of their base media sets, so
+/// the whole base chain of each matching media set is included.
+pub fn select_media_sets<'a>(
+    catalog: &'a CloudCatalog,
+    filter: &ReplicationFilter,
+) -> Vec<&'a MediaSetCatalog> {
+    let mut wanted: HashSet<&Uuid> = HashSet::new();
+
+    // newest first, bases are always older than their incrementals
+    for media_set in catalog.media_sets().iter().rev() {
+        if wanted.contains(media_set.uuid()) || filter.matches(media_set) {
+            wanted.insert(media_set.uuid());
+            if let Some(ref base) = media_set.label.base {
+                wanted.insert(base);
+            }
+        }
+    }
+
+    catalog
+        .media_sets()
+        .iter()
+        .filter(|media_set| wanted.contains(media_set.uuid()))
+        .collect()
+}
+
+// copy a single object, falling back to a streaming copy for the rest of
+// the run if server-side copies are not possible
+pub(super) fn copy_object(
+    worker: &dyn WorkerTaskContext,
+    source: &Arc<dyn CloudBackend>,
+    target: &Arc<dyn CloudBackend>,
+    object: &ObjectInfo,
+    server_side: &mut bool,
+    stats: &mut ReplicationStats,
+) -> Result<(), Error> {
+    let key = &object.key;
+
+    let copied = match copy_between_targets(&**source, key, &**target, key, *server_side) {
+        Err(err) if *server_side => {
+            task_warn!(worker, "server-side copy of '{}' failed - {}", key, err);
+            *server_side = false;
+            copy_between_targets(&**source, key, &**target, key, false)?
+        }
+        result => result?,
+    };
+
+    if copied {
+        stats.server_side += 1;
+        return Ok(());
+    }
+
+    if *server_side {
+        task_log!(
+            worker,
+            "server-side copy not possible, using streaming copy"
+        );
+        *server_side = false;
+    }
+    stats.streamed += 1;
+    stats.bytes += object.size;
+
+    Ok(())
+}
+
+/// Copy the media sets selected by `filter` from `source` to `target`
+///
+/// Media sets already complete on the target are skipped, their local
+/// catalogs are written nonetheless.
+pub fn replicate_media_sets<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    source: &CloudTarget,
+    source_backend: &Arc<dyn CloudBackend>,
+    target: &CloudTarget,
+    target_backend: &Arc<dyn CloudBackend>,
+    filter: &ReplicationFilter,
+) -> Result<ReplicationStats, Error> {
+    let base_path = base_path.as_ref();
+
+    if source.name == target.name {
+        bail!("source and target of a replication must differ");
+    }
+
+    let catalog = CloudCatalog::load(base_path, &source.name)?;
+    let media_sets = select_media_sets(&catalog, filter);
+
+    let mut stats = ReplicationStats::default();
+    if media_sets.is_empty() {
+        task_log!(worker, "no media sets to replicate");
+        return Ok(stats);
+    }
+
+    let mut lease = CloudLease::acquire(
+        Arc::clone(target_backend),
+        proxmox_sys::nodename(),
+        LEASE_TIMEOUT,
+    )?;
+
+    let mut server_side = true;
+
+    for media_set in media_sets {
+        worker.check_abort()?;
+        worker.fail_on_shutdown()?;
+
+        let uuid = media_set.uuid();
+        let prefix = layout::media_set_prefix(uuid);
+        let catalog_key = layout::media_set_catalog_key(uuid);
+
+        if target_backend.head_object(&catalog_key)?.is_some() {
+            media_set.save(base_path, &target.name)?;
+            continue;
+        }
+
+        task_log!(worker, "replicate media set {}", uuid);
+
+        let existing: HashMap<String, u64> = target_backend
+            .list_objects(&prefix)?
+            .into_iter()
+            .map(|object| (object.key, object.size))
+            .collect();
+
+        let (catalog_object, objects): (Vec<ObjectInfo>, Vec<ObjectInfo>) = source_backend
+            .list_objects(&prefix)?
+            .into_iter()
+            .partition(|object| object.key == catalog_key);
+        let catalog_object = match catalog_object.into_iter().next() {
+            Some(object) => object,
+            None => bail!("media set {} is incomplete on source target", uuid),
+        };
+
+        for object in objects.iter() {
+            worker.check_abort()?;
+            worker.fail_on_shutdown()?;
+
+            if existing.get(&object.key) == Some(&object.size) {
+                stats.skipped += 1;
+                continue;
+            }
+
+            lease.heartbeat()?;
+            copy_object(
+                worker,
+                source_backend,
+                target_backend,
+                object,
+                &mut server_side,
+                &mut stats,
+            )?;
+        }
+
+        // the catalog marks the media set as complete
+        lease.heartbeat()?;
+        copy_object(
+            worker,
+            source_backend,
+            target_backend,
+            &catalog_object,
+            &mut server_side,
+            &mut stats,
+        )?;
+
+        media_set.save(base_path, &target.name)?;
+        stats.media_sets += 1;
+    }
+
+    lease.release()?;
+
+    task_log!(
+        worker,
+        "replicated {} media sets ({} objects server-side, {} objects / {} streamed, {} skipped)",
+        stats.media_sets,
+        stats.server_side,
+        stats.streamed,
+        HumanByte::from(stats.bytes),
+        stats.skipped,
+    );
+
+    Ok(stats)
+}
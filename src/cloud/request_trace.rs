@@ -0,0 +1,237 @@
+//! Request tracing of cloud tasks
+//!
+//! Provider support usually asks for the request IDs of failed or slow
+//! requests. With `request-trace` enabled, a backup job records every
+//! provider request (method, endpoint, object key, status, duration and
+//! request ID - no headers, query parameters or data) in a ring buffer.
+//! The buffer is saved when the task finishes and can be downloaded with
+//! `api2/cloud/tasks/{upid}/requests`.
+//!
+//! The trace belongs to the worker thread running the task, backends
+//! record requests made from that thread only.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{format_err, Error};
+
+use proxmox_sys::fs::{create_path, replace_file};
+use proxmox_sys::{task_log, task_warn};
+
+use pbs_api_types::CloudRequestTrace;
+use proxmox_rest_server::WorkerTask;
+
+use super::create_options;
+use super::CLOUD_STATUS_DIR;
+
+/// Number of requests kept per task, older requests are dropped
+pub const REQUEST_TRACE_SIZE: usize = 10_000;
+
+thread_local! {
+    static CURRENT_TRACE: RefCell<Option<Arc<RequestTrace>>> = RefCell::new(None);
+}
+
+/// Ring buffer of the requests of a task
+pub struct RequestTrace {
+    capacity: usize,
+    state: Mutex<TraceState>,
+}
+
+#[derive(Default)]
+struct TraceState {
+    entries: VecDeque<CloudRequestTrace>,
+    dropped: u64,
+}
+
+impl RequestTrace {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(TraceState::default()),
+        }
+    }
+
+    pub fn record(&self, entry: CloudRequestTrace) {
+        let mut state = self.state.lock().unwrap();
+        if state.entries.len() >= self.capacity {
+            state.entries.pop_front();
+            state.dropped += 1;
+        }
+        state.entries.push_back(entry);
+    }
+
+    /// Recorded requests, oldest first
+    pub fn entries(&self) -> Vec<CloudRequestTrace> {
+        self.state.lock().unwrap().entries.iter().cloned().collect()
+    }
+
+    /// Number of requests dropped from the buffer
+    pub fn dropped(&self) -> u64 {
+        self.state.lock().unwrap().dropped
+    }
+}
+
+/// Records the requests of the current thread while alive
+pub struct TraceGuard {
+    trace: Arc<RequestTrace>,
+    previous: Option<Arc<RequestTrace>>,
+}
+
+impl TraceGuard {
+    pub fn install(trace: Arc<RequestTrace>) -> Self {
+        let previous = CURRENT_TRACE.with(|current| current.replace(Some(Arc::clone(&trace))));
+        Self { trace, previous }
+    }
+
+    pub fn trace(&self) -> &Arc<RequestTrace> {
+        &self.trace
+    }
+}
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT_TRACE.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Record a request in the trace of the current thread, if any
+///
+/// `entry` is only called while tracing.
+pub fn record_request<F: FnOnce() -> CloudRequestTrace>(entry: F) {
+    let trace = CURRENT_TRACE.with(|current| current.borrow().clone());
+    if let Some(trace) = trace {
+        trace.record(entry());
+    }
+}
+
+fn traces_dir(base_path: &Path) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("request-traces");
+    path
+}
+
+fn trace_path(base_path: &Path, upid: &str) -> PathBuf {
+    let mut path = traces_dir(base_path);
+    path.push(format!("{}.json", upid));
+    path
+}
+
+/// Save the requests recorded for task `upid`
+pub fn save_request_trace<P: AsRef<Path>>(
+    base_path: P,
+    upid: &str,
+    entries: &[CloudRequestTrace],
+) -> Result<(), Error> {
+    let dir = traces_dir(base_path.as_ref());
+    create_path(
+        &dir,
+        Some(create_options(0o0750)?),
+        Some(create_options(0o0750)?),
+    )?;
+    let data = serde_json::to_vec(entries)?;
+    replace_file(
+        trace_path(base_path.as_ref(), upid),
+        &data,
+        create_options(0o0640)?,
+        false,
+    )
+}
+
+/// The requests recorded for task `upid`
+///
+/// Tasks without request tracing have an empty list.
+pub fn load_request_trace<P: AsRef<Path>>(
+    base_path: P,
+    upid: &str,
+) -> Result<Vec<CloudRequestTrace>, Error> {
+    let path = trace_path(base_path.as_ref(), upid);
+    match std::fs::read(&path) {
+        Ok(data) => serde_json::from_slice(&data)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(format_err!("unable to read {:?} - {}", path, err)),
+    }
+}
+
+/// Remove the traces of all tasks for which `keep` returns false
+///
+/// Returns the number of removed traces.
+pub fn prune_request_traces<P, F>(base_path: P, keep: F) -> Result<usize, Error>
+where
+    P: AsRef<Path>,
+    F: Fn(&str) -> bool,
+{
+    let dir = traces_dir(base_path.as_ref());
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(format_err!("unable to read {:?} - {}", dir, err)),
+    };
+
+    let mut removed = 0;
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let upid = match file_name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".json"))
+        {
+            Some(upid) => upid,
+            None => continue,
+        };
+        if keep(upid) {
+            continue;
+        }
+        std::fs::remove_file(entry.path())?;
+        removed += 1;
+    }
+
+    Ok(removed)
+}
+
+/// Request tracing of a worker task
+///
+/// Saves the trace when dropped, so it is available for failed tasks too.
+pub struct TaskRequestTrace<'a> {
+    worker: &'a WorkerTask,
+    guard: TraceGuard,
+}
+
+impl<'a> TaskRequestTrace<'a> {
+    /// Start tracing the requests of `worker`, if `enabled`
+    pub fn start(worker: &'a WorkerTask, enabled: bool) -> Option<Self> {
+        if !enabled {
+            return None;
+        }
+        task_log!(
+            worker,
+            "request-trace: recording the last {} provider requests",
+            REQUEST_TRACE_SIZE
+        );
+        let trace = Arc::new(RequestTrace::new(REQUEST_TRACE_SIZE));
+        Some(Self {
+            worker,
+            guard: TraceGuard::install(trace),
+        })
+    }
+}
+
+impl Drop for TaskRequestTrace<'_> {
+    fn drop(&mut self) {
+        let trace = self.guard.trace();
+        let entries = trace.entries();
+        let upid = self.worker.upid().to_string();
+        match save_request_trace(CLOUD_STATUS_DIR, &upid, &entries) {
+            Ok(()) => task_log!(
+                self.worker,
+                "request-trace: saved {} requests ({} dropped)",
+                entries.len(),
+                trace.dropped()
+            ),
+            Err(err) => task_warn!(self.worker, "unable to save request trace - {}", err),
+        }
+    }
+}
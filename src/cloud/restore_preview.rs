@@ -0,0 +1,251 @@
+//! Restore previews
+//!
+//! Shows per archive what a restore of a snapshot brings back (size,
+//! number of files and the largest files), so users can choose between a
+//! full and a file-level restore before starting one.
+//!
+//! The breakdown of file archives is taken from the local pxar catalog at
+//! backup time and stored in the snapshot summary. For snapshots without
+//! breakdown, the archive sizes are read from the uploaded manifest.
+//! Chunk objects are never downloaded.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::{Read, Seek};
+
+use anyhow::{format_err, Error};
+
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{CloudArchiveEntrySize, CloudArchivePreview, CloudRestorePreview, CryptMode};
+use pbs_datastore::catalog::{CatalogReader, DirEntry, DirEntryAttribute};
+use pbs_datastore::dynamic_index::{BufferedDynamicReader, DynamicIndexReader};
+use pbs_datastore::manifest::{BackupManifest, MANIFEST_BLOB_NAME};
+use pbs_datastore::{BackupDir, DataBlob, LocalChunkReader, CATALOG_NAME};
+
+use super::backend::CloudBackend;
+use super::catalog::{CloudCatalog, SnapshotEntry};
+use super::encryption_keys::{decrypt_object, load_crypt_config};
+use super::layout;
+use super::snapshot_summary::load_snapshot_summary;
+
+/// Number of largest files listed per archive
+pub const PREVIEW_LARGEST_FILES: usize = 10;
+
+// file count, total size and largest files of a directory tree
+#[derive(Default)]
+struct ArchiveStats {
+    file_count: u64,
+    files_size: u64,
+    // min-heap of the largest files
+    largest: BinaryHeap<Reverse<(u64, Vec<u8>)>>,
+}
+
+impl ArchiveStats {
+    fn add_file(&mut self, path: Vec<u8>, size: u64, max_largest: usize) {
+        self.file_count += 1;
+        self.files_size += size;
+        if max_largest == 0 {
+            return;
+        }
+        if self.largest.len() < max_largest {
+            self.largest.push(Reverse((size, path)));
+        } else if matches!(self.largest.peek(), Some(Reverse((min, _))) if size > *min) {
+            self.largest.pop();
+            self.largest.push(Reverse((size, path)));
+        }
+    }
+
+    fn into_preview(self, archive: String) -> CloudArchivePreview {
+        let largest = self
+            .largest
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((size, path))| CloudArchiveEntrySize {
+                path: String::from_utf8_lossy(&path).into_owned(),
+                size,
+            })
+            .collect();
+        CloudArchivePreview {
+            archive,
+            size: self.files_size,
+            file_count: Some(self.file_count),
+            files_size: Some(self.files_size),
+            largest,
+        }
+    }
+}
+
+fn walk_directory<R: Read + Seek>(
+    reader: &mut CatalogReader<R>,
+    dir: &DirEntry,
+    prefix: &[u8],
+    stats: &mut ArchiveStats,
+    max_largest: usize,
+) -> Result<(), Error> {
+    for entry in reader.read_dir(dir)? {
+        let mut path = prefix.to_vec();
+        path.push(b'/');
+        path.extend_from_slice(&entry.name);
+
+        match entry.attr {
+            DirEntryAttribute::Directory { .. } => {
+                walk_directory(reader, &entry, &path, stats, max_largest)?
+            }
+            DirEntryAttribute::File { size, .. } => stats.add_file(path, size, max_largest),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Breakdown of all archives listed in a pxar catalog
+///
+/// `size` is the total size of the regular files, the largest
+/// `max_largest` files are listed with their path inside the archive.
+pub fn catalog_breakdown<R: Read + Seek>(
+    reader: &mut CatalogReader<R>,
+    max_largest: usize,
+) -> Result<Vec<CloudArchivePreview>, Error> {
+    let root = reader.root()?;
+
+    let mut list = Vec::new();
+    for archive in reader.read_dir(&root)? {
+        if !archive.is_directory() {
+            continue;
+        }
+        let mut stats = ArchiveStats::default();
+        walk_directory(reader, &archive, b"", &mut stats, max_largest)?;
+        list.push(stats.into_preview(String::from_utf8_lossy(&archive.name).into_owned()));
+    }
+    Ok(list)
+}
+
+/// Archive list of a manifest, with the catalog breakdown where available
+///
+/// Archive sizes are taken from the manifest (the size of the archive
+/// content, e.g. the pxar stream or disk image).
+pub fn archive_previews(
+    manifest: &BackupManifest,
+    breakdown: Vec<CloudArchivePreview>,
+) -> Vec<CloudArchivePreview> {
+    let mut breakdown: HashMap<String, CloudArchivePreview> = breakdown
+        .into_iter()
+        .map(|preview| (preview.archive.clone(), preview))
+        .collect();
+
+    manifest
+        .files()
+        .iter()
+        .filter(|file| file.filename != CATALOG_NAME)
+        .map(|file| match breakdown.remove(&file.filename) {
+            Some(preview) => CloudArchivePreview {
+                size: file.size,
+                ..preview
+            },
+            None => CloudArchivePreview {
+                archive: file.filename.clone(),
+                size: file.size,
+                file_count: None,
+                files_size: None,
+                largest: Vec::new(),
+            },
+        })
+        .collect()
+}
+
+/// Breakdown of the archives of a local snapshot
+///
+/// File archives are only broken down if the snapshot has an unencrypted
+/// catalog. Called at backup time, so the data is local.
+pub fn local_archive_previews(
+    snapshot: &BackupDir,
+    manifest: &BackupManifest,
+) -> Result<Vec<CloudArchivePreview>, Error> {
+    let breakdown = match manifest.lookup_file_info(CATALOG_NAME) {
+        Ok(info) if info.crypt_mode != CryptMode::Encrypt => {
+            let mut path = snapshot.full_path();
+            path.push(CATALOG_NAME);
+
+            let index = DynamicIndexReader::open(&path)
+                .map_err(|err| format_err!("unable to read catalog index - {}", err))?;
+            let chunk_reader =
+                LocalChunkReader::new(snapshot.datastore().clone(), None, CryptMode::None);
+            let mut reader = CatalogReader::new(BufferedDynamicReader::new(index, chunk_reader));
+            catalog_breakdown(&mut reader, PREVIEW_LARGEST_FILES)?
+        }
+        _ => Vec::new(),
+    };
+
+    Ok(archive_previews(manifest, breakdown))
+}
+
+/// Download the manifest of a snapshot from the target
+pub fn load_manifest(
+    backend: &dyn CloudBackend,
+    media_set: &Uuid,
+    entry: &SnapshotEntry,
+) -> Result<BackupManifest, Error> {
+    let key = layout::snapshot_file_key(
+        media_set,
+        &entry.store,
+        &entry.ns,
+        &entry.snapshot,
+        MANIFEST_BLOB_NAME,
+    );
+    read_manifest(backend, &key, entry)
+}
+
+fn read_manifest(
+    backend: &dyn CloudBackend,
+    key: &str,
+    entry: &SnapshotEntry,
+) -> Result<BackupManifest, Error> {
+    let data = backend.get_object(key)?;
+    let data = match entry.key {
+        Some(ref fingerprint) => decrypt_object(&data, &load_crypt_config(fingerprint)?)?,
+        None => data,
+    };
+    let blob = DataBlob::load_from_reader(&mut &data[..])?;
+    BackupManifest::try_from(blob)
+}
+
+/// Restore preview of a snapshot
+///
+/// Reads the snapshot summary, and the manifest for snapshots without
+/// archive breakdown.
+pub fn restore_preview(
+    backend: &dyn CloudBackend,
+    catalog: &CloudCatalog,
+    media_set: &Uuid,
+    entry: &SnapshotEntry,
+) -> Result<CloudRestorePreview, Error> {
+    let summary = load_snapshot_summary(backend, catalog, media_set, entry)?;
+
+    let archives = if summary.archives.is_empty() {
+        let manifest = match catalog.lookup_media_set(media_set) {
+            Some(set) if set.is_imported() => read_manifest(
+                backend,
+                &set.snapshot_file_key(entry, MANIFEST_BLOB_NAME),
+                entry,
+            ),
+            _ => load_manifest(backend, media_set, entry),
+        };
+        let manifest = manifest.map_err(|err| format_err!("unable to load manifest - {}", err))?;
+        archive_previews(&manifest, Vec::new())
+    } else {
+        summary.archives
+    };
+
+    Ok(CloudRestorePreview {
+        store: summary.store,
+        ns: summary.ns,
+        snapshot: summary.snapshot,
+        media_set: summary.media_set,
+        download_size: summary
+            .chunk_size
+            .map(|chunk_size| chunk_size + summary.files_size),
+        encrypted: summary.encrypted,
+        archives,
+    })
+}
@@ -0,0 +1,69 @@
+//! Update the tags of existing objects
+//!
+//! Object tags (`object-tag` of targets and jobs) are applied on upload.
+//! After changing the tags of a target, this replaces the tags of all
+//! objects already stored on the target with the current target tags.
+//! Job tags and request tags (`request-tagging`) of existing objects are
+//! not restored, as the objects do not record which job uploaded them.
+
+use anyhow::{bail, Error};
+
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::CloudTarget;
+
+use super::backend::{CloudBackend, PutOptions, MAX_OBJECT_TAGS};
+
+/// Replace the tags of all objects of a target, returns the number of
+/// updated objects
+///
+/// Objects below the `access-log-prefix` are left alone.
+pub fn retag_objects(
+    worker: &dyn WorkerTaskContext,
+    target: &CloudTarget,
+    backend: &dyn CloudBackend,
+) -> Result<usize, Error> {
+    let tags = PutOptions::default().with_object_tags(target, None)?.tags;
+    if tags.len() > MAX_OBJECT_TAGS {
+        bail!(
+            "too many object tags ({}, at most {} per object)",
+            tags.len(),
+            MAX_OBJECT_TAGS
+        );
+    }
+
+    let log_prefix = target
+        .config
+        .access_log_prefix
+        .as_ref()
+        .map(|prefix| format!("{}/", prefix));
+
+    let objects = backend.list_objects("")?;
+    task_log!(worker, "updating tags of {} objects", objects.len());
+
+    let mut updated = 0;
+    let mut errors = 0;
+    for object in objects {
+        worker.check_abort()?;
+
+        if let Some(ref log_prefix) = log_prefix {
+            if object.key.starts_with(log_prefix.as_str()) {
+                continue;
+            }
+        }
+
+        match backend.put_object_tags(&object.key, &tags) {
+            Ok(()) => updated += 1,
+            Err(err) => {
+                task_warn!(worker, "unable to tag '{}' - {}", object.key, err);
+                errors += 1;
+            }
+        }
+    }
+
+    if errors > 0 {
+        bail!("unable to update tags of {} objects", errors);
+    }
+
+    Ok(updated)
+}
@@ -0,0 +1,228 @@
+//! Object lock retention reports
+//!
+//! For compliance audits, a report lists the object lock state of all
+//! media sets and snapshots of a target together with the bucket lock
+//! configuration. Retention times are read from the objects themselves
+//! (HEAD requests), not from the local catalog, so the report reflects
+//! what the provider actually enforces.
+//!
+//! Reports are signed with the key of the node certificate, so they can
+//! be handed to auditors and checked against the certificate later.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, format_err, Error};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::{Signer, Verifier};
+
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{
+    print_ns_and_snapshot, CloudRetentionAttestation, CloudRetentionMediaSet, CloudRetentionReport,
+    CloudRetentionSnapshot, CloudTarget,
+};
+use pbs_tools::cert::CertInfo;
+
+use super::backend::CloudBackend;
+use super::catalog::{CloudCatalog, MediaSetCatalog};
+use super::layout;
+
+const SIGNATURE_ALGORITHM: &str = "sha256";
+
+// minimum of two optional retention times, `None` if any object is unlocked
+fn min_locked(a: Option<i64>, b: Option<i64>) -> Option<i64> {
+    Some(a?.min(b?))
+}
+
+// retention time of an object, `None` for unlocked or missing objects
+fn object_retain_until(backend: &dyn CloudBackend, key: &str) -> Result<Option<i64>, Error> {
+    Ok(backend
+        .head_object(key)
+        .map_err(|err| format_err!("unable to query object '{}' - {}", key, err))?
+        .and_then(|info| info.retain_until))
+}
+
+struct MediaSetLocks {
+    report: CloudRetentionMediaSet,
+    /// Retention time of each chunk archive
+    archives: HashMap<Uuid, Option<i64>>,
+    /// Retention time of the files of each snapshot (same order as the catalog)
+    snapshots: Vec<Option<i64>>,
+}
+
+fn media_set_locks(
+    backend: &dyn CloudBackend,
+    media_set: &MediaSetCatalog,
+) -> Result<MediaSetLocks, Error> {
+    let uuid = media_set.uuid();
+
+    let mut objects = 0;
+    let mut locked_objects = 0;
+    let mut locked_until = Some(i64::MAX);
+
+    let mut query = |key: &str| -> Result<Option<i64>, Error> {
+        let retain_until = object_retain_until(backend, key)?;
+        objects += 1;
+        if retain_until.is_some() {
+            locked_objects += 1;
+        }
+        locked_until = min_locked(locked_until, retain_until);
+        Ok(retain_until)
+    };
+
+    query(&layout::media_set_label_key(uuid))?;
+    query(&layout::media_set_catalog_key(uuid))?;
+
+    let mut snapshots = Vec::new();
+    for entry in media_set.snapshots.iter() {
+        let mut snapshot_locked_until = Some(i64::MAX);
+        for file in entry.files.iter() {
+            let key = media_set.snapshot_file_key(entry, &file.filename);
+            snapshot_locked_until = min_locked(snapshot_locked_until, query(&key)?);
+        }
+        snapshots.push(snapshot_locked_until);
+    }
+
+    let mut archives = HashMap::new();
+    // imported media sets have no archive objects
+    for archive in media_set
+        .archives
+        .iter()
+        .filter(|_| !media_set.is_imported())
+    {
+        let retain_until = query(&layout::chunk_archive_key(uuid, &archive.uuid))?;
+        archives.insert(archive.uuid.clone(), retain_until);
+    }
+
+    Ok(MediaSetLocks {
+        report: CloudRetentionMediaSet {
+            uuid: uuid.to_string(),
+            ctime: media_set.label.ctime,
+            objects,
+            locked_objects,
+            locked_until,
+        },
+        archives,
+        snapshots,
+    })
+}
+
+/// Build the retention report of a target
+///
+/// A snapshot counts as locked until the earliest retention time of its
+/// files and of all chunk archives holding its chunks, which may belong
+/// to older media sets of the chain.
+pub fn build_retention_report(
+    target: &CloudTarget,
+    backend: &dyn CloudBackend,
+    catalog: &CloudCatalog,
+) -> Result<CloudRetentionReport, Error> {
+    let lock_config = backend.object_lock_configuration()?;
+
+    let mut locks = Vec::new();
+    let mut archive_locks: HashMap<(Uuid, Uuid), Option<i64>> = HashMap::new();
+    for media_set in catalog.media_sets() {
+        let media_set_locks = media_set_locks(backend, media_set)?;
+        for (archive, retain_until) in media_set_locks.archives.iter() {
+            archive_locks.insert((media_set.uuid().clone(), archive.clone()), *retain_until);
+        }
+        locks.push(media_set_locks);
+    }
+
+    let mut snapshots = Vec::new();
+    for (media_set, media_set_locks) in catalog.media_sets().iter().zip(locks.iter()) {
+        for (entry, files_locked_until) in media_set
+            .snapshots
+            .iter()
+            .zip(media_set_locks.snapshots.iter())
+        {
+            let mut locked_until = *files_locked_until;
+            for digest in entry.chunks.iter() {
+                if locked_until.is_none() {
+                    break;
+                }
+                let retain_until = catalog
+                    .lookup_chunk(digest, entry.key.as_ref())
+                    .and_then(|location| {
+                        archive_locks
+                            .get(&(location.media_set.clone(), location.archive.clone()))
+                            .copied()
+                    })
+                    .flatten();
+                locked_until = min_locked(locked_until, retain_until);
+            }
+
+            snapshots.push(CloudRetentionSnapshot {
+                snapshot: format!(
+                    "{}:{}",
+                    entry.store,
+                    print_ns_and_snapshot(&entry.ns, &entry.snapshot)
+                ),
+                media_set: media_set.uuid().to_string(),
+                locked_until,
+            });
+        }
+    }
+
+    Ok(CloudRetentionReport {
+        target: target.name.clone(),
+        bucket: target.config.bucket.clone(),
+        node: proxmox_sys::nodename().to_string(),
+        generated: proxmox_time::epoch_i64(),
+        lock_config,
+        media_sets: locks.into_iter().map(|locks| locks.report).collect(),
+        snapshots,
+    })
+}
+
+/// Sign a retention report with a private key and its certificate (PEM)
+pub fn sign_retention_report(
+    report: CloudRetentionReport,
+    key_pem: &[u8],
+    cert_pem: &[u8],
+) -> Result<CloudRetentionAttestation, Error> {
+    let key = PKey::private_key_from_pem(key_pem)
+        .map_err(|err| format_err!("unable to parse signing key - {}", err))?;
+    let cert = CertInfo::from_pem(cert_pem)?;
+
+    if !cert.public_key()?.public_eq(&key) {
+        bail!("signing key does not match the certificate");
+    }
+
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(&serde_json::to_vec(&report)?)?;
+    let signature = signer.sign_to_vec()?;
+
+    Ok(CloudRetentionAttestation {
+        report,
+        algorithm: SIGNATURE_ALGORITHM.to_string(),
+        signature: base64::encode(signature),
+        certificate: String::from_utf8(cert_pem.to_vec())?,
+        fingerprint: cert.fingerprint()?,
+    })
+}
+
+/// Check the signature of a retention report against its certificate
+pub fn verify_retention_attestation(attestation: &CloudRetentionAttestation) -> Result<(), Error> {
+    if attestation.algorithm != SIGNATURE_ALGORITHM {
+        bail!("unknown signature algorithm '{}'", attestation.algorithm);
+    }
+
+    let cert = CertInfo::from_pem(attestation.certificate.as_bytes())?;
+    if cert.fingerprint()? != attestation.fingerprint {
+        bail!("certificate fingerprint mismatch");
+    }
+
+    let signature = base64::decode(&attestation.signature)
+        .map_err(|err| format_err!("unable to decode signature - {}", err))?;
+
+    let key = cert.public_key()?;
+    let mut verifier = Verifier::new(MessageDigest::sha256(), &key)?;
+    verifier.update(&serde_json::to_vec(&attestation.report)?)?;
+    if !verifier.verify(&signature)? {
+        bail!("retention report signature is invalid");
+    }
+
+    Ok(())
+}
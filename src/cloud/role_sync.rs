@@ -0,0 +1,380 @@
+//! LDAP group to role sync
+//!
+//! A role sync job reads the members of LDAP groups of a realm and grants
+//! each member the role mapped to the group on the ACL path of the
+//! mapping, e.g. `CloudUser` on `/cloud/target/prod` for the members of
+//! `backup-operators`.
+//!
+//! The grants of a job are remembered locally. A later run revokes them
+//! from users who left the group (or when the mapping was removed), while
+//! ACL entries set up by other means are never touched.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_ldap::{Connection, SearchParameters};
+use proxmox_sys::fs::{create_path, replace_file};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{Authid, CloudRoleMapping, CloudRoleSyncJobConfig, LdapRealmConfig, Userid};
+use pbs_config::acl::AclTree;
+
+use crate::auth::LdapAuthenticator;
+use crate::server::LdapSyncSettings;
+
+use super::create_options;
+
+/// Object classes of LDAP groups
+const GROUP_CLASSES: [&str; 4] = ["groupOfNames", "groupOfUniqueNames", "posixGroup", "group"];
+
+/// Attributes listing the members of LDAP groups (DNs or user names)
+const GROUP_MEMBER_ATTRIBUTES: [&str; 3] = ["member", "uniqueMember", "memberUid"];
+
+/// A role granted to a user on an ACL path
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RoleGrant {
+    pub userid: Userid,
+    pub path: String,
+    pub role: String,
+}
+
+impl std::fmt::Display for RoleGrant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} on {} for {}", self.role, self.path, self.userid)
+    }
+}
+
+/// Parse the mappings of a role sync job
+pub fn parse_mappings(job: &CloudRoleSyncJobConfig) -> Result<Vec<CloudRoleMapping>, Error> {
+    job.mapping
+        .iter()
+        .map(|mapping| {
+            let value = CloudRoleMapping::API_SCHEMA.parse_property_string(mapping)?;
+            Ok(serde_json::from_value(value)?)
+        })
+        .collect()
+}
+
+/// Escape a value for use in an LDAP search filter (RFC 4515)
+pub fn escape_filter_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '*' => escaped.push_str("\\2a"),
+            '(' => escaped.push_str("\\28"),
+            ')' => escaped.push_str("\\29"),
+            '\\' => escaped.push_str("\\5c"),
+            '\0' => escaped.push_str("\\00"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// User names of the members of a group
+///
+/// `values` are the values of the member attributes, either DNs (looked up
+/// in `user_dns`, which maps lower case DNs to user names) or user names.
+/// Members which are no users of the realm (e.g. nested groups) are left
+/// out.
+pub fn member_usernames(values: &[String], user_dns: &HashMap<String, String>) -> Vec<String> {
+    let mut names = BTreeSet::new();
+    for value in values {
+        if value.contains('=') {
+            if let Some(name) = user_dns.get(&value.to_lowercase()) {
+                names.insert(name.clone());
+            }
+        } else {
+            names.insert(value.clone());
+        }
+    }
+    names.into_iter().collect()
+}
+
+/// Read the members of `groups` from the LDAP server of a realm
+///
+/// Returns the user names of the members of each group. Fails if one of
+/// the groups does not exist, so a typo never revokes all grants.
+pub async fn ldap_group_members(
+    config: &LdapRealmConfig,
+    groups: &BTreeSet<String>,
+) -> Result<HashMap<String, Vec<String>>, Error> {
+    let settings = LdapSyncSettings::from_config(config)?;
+    let ldap = Connection::new(LdapAuthenticator::api_type_to_config(config)?);
+
+    let users = ldap
+        .search_entities(&SearchParameters {
+            attributes: vec![settings.user_attr.clone()],
+            user_classes: settings.user_classes.clone(),
+            user_filter: settings.user_filter.clone(),
+        })
+        .await?;
+
+    let mut user_dns = HashMap::new();
+    for user in users {
+        if let Some(name) = user
+            .attributes
+            .get(&settings.user_attr)
+            .and_then(|values| values.first())
+        {
+            user_dns.insert(user.dn.to_lowercase(), name.clone());
+        }
+    }
+
+    let mut members = HashMap::new();
+    for group in groups {
+        let result = ldap
+            .search_entities(&SearchParameters {
+                attributes: GROUP_MEMBER_ATTRIBUTES.map(String::from).to_vec(),
+                user_classes: GROUP_CLASSES.map(String::from).to_vec(),
+                user_filter: Some(format!("(cn={})", escape_filter_value(group))),
+            })
+            .await?;
+
+        let entry = match result.as_slice() {
+            [entry] => entry,
+            [] => bail!("LDAP group '{}' not found", group),
+            _ => bail!("LDAP group name '{}' is ambiguous", group),
+        };
+
+        let values: Vec<String> = entry
+            .attributes
+            .iter()
+            .filter(|(name, _)| {
+                GROUP_MEMBER_ATTRIBUTES
+                    .iter()
+                    .any(|attr| attr.eq_ignore_ascii_case(name))
+            })
+            .flat_map(|(_, values)| values.iter().cloned())
+            .collect();
+
+        members.insert(group.clone(), member_usernames(&values, &user_dns));
+    }
+
+    Ok(members)
+}
+
+/// The grants of the mappings, with their propagate flag
+pub fn desired_grants(
+    mappings: &[CloudRoleMapping],
+    members: &HashMap<String, Vec<Userid>>,
+) -> BTreeMap<RoleGrant, bool> {
+    let mut grants = BTreeMap::new();
+    for mapping in mappings {
+        let propagate = mapping.propagate.unwrap_or(true);
+        for userid in members.get(&mapping.group).into_iter().flatten() {
+            let grant = RoleGrant {
+                userid: userid.clone(),
+                path: mapping.path.clone(),
+                role: mapping.role.clone(),
+            };
+            // a propagating mapping wins
+            *grants.entry(grant).or_insert(false) |= propagate;
+        }
+    }
+    grants
+}
+
+/// Changes made by a role sync
+#[derive(Debug, Default)]
+pub struct RoleSyncChanges {
+    pub granted: Vec<RoleGrant>,
+    pub revoked: Vec<RoleGrant>,
+    /// Grants owned by the job after the sync
+    pub managed: BTreeSet<RoleGrant>,
+}
+
+/// Apply the `desired` grants to `tree`
+///
+/// `previous` are the grants owned by the job so far, those no longer
+/// desired are revoked. Desired grants which already exist, but are not
+/// owned by the job, are left alone.
+pub fn apply_role_sync(
+    tree: &mut AclTree,
+    desired: &BTreeMap<RoleGrant, bool>,
+    previous: &BTreeSet<RoleGrant>,
+) -> RoleSyncChanges {
+    let mut changes = RoleSyncChanges::default();
+
+    for (grant, propagate) in desired {
+        let auth_id = Authid::from(grant.userid.clone());
+        let existing = tree
+            .find_node(&grant.path)
+            .and_then(|node| node.users.get(&auth_id))
+            .and_then(|roles| roles.get(&grant.role))
+            .copied();
+
+        match existing {
+            Some(_) if !previous.contains(grant) => continue,
+            Some(existing) if existing == *propagate => {}
+            _ => {
+                tree.insert_user_role(&grant.path, &auth_id, &grant.role, *propagate);
+                changes.granted.push(grant.clone());
+            }
+        }
+        changes.managed.insert(grant.clone());
+    }
+
+    for grant in previous {
+        if !desired.contains_key(grant) {
+            let auth_id = Authid::from(grant.userid.clone());
+            tree.delete_user_role(&grant.path, &auth_id, &grant.role);
+            changes.revoked.push(grant.clone());
+        }
+    }
+
+    changes
+}
+
+fn role_grants_path(base_path: &Path, job_id: &str) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("role-sync");
+    path.push(format!("{}.json", job_id));
+    path
+}
+
+/// Load the grants owned by role sync job `job_id`
+pub fn load_role_grants<P: AsRef<Path>>(
+    base_path: P,
+    job_id: &str,
+) -> Result<BTreeSet<RoleGrant>, Error> {
+    let path = role_grants_path(base_path.as_ref(), job_id);
+    match proxmox_sys::fs::file_get_optional_contents(&path)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        None => Ok(BTreeSet::new()),
+    }
+}
+
+/// Store the grants owned by role sync job `job_id`
+pub fn save_role_grants<P: AsRef<Path>>(
+    base_path: P,
+    job_id: &str,
+    grants: &BTreeSet<RoleGrant>,
+) -> Result<(), Error> {
+    let path = role_grants_path(base_path.as_ref(), job_id);
+    if let Some(parent) = path.parent() {
+        create_path(
+            parent,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+    }
+    let data = serde_json::to_vec(grants)?;
+    replace_file(path, &data, create_options(0o0640)?, true)
+}
+
+/// Forget the grants of a removed role sync job
+pub fn remove_role_grants<P: AsRef<Path>>(base_path: P, job_id: &str) -> Result<(), Error> {
+    let path = role_grants_path(base_path.as_ref(), job_id);
+    match std::fs::remove_file(&path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(format_err!("unable to remove {:?} - {}", path, err)),
+    }
+}
+
+// resolve the member names of each group to existing users of the realm
+fn member_userids(
+    worker: &dyn WorkerTaskContext,
+    realm: &str,
+    members: HashMap<String, Vec<String>>,
+) -> Result<HashMap<String, Vec<Userid>>, Error> {
+    let (user_config, _digest) = pbs_config::user::config()?;
+
+    let mut userids = HashMap::new();
+    for (group, names) in members {
+        let mut list = Vec::new();
+        for name in names {
+            let userid: Userid = match format!("{}@{}", name, realm).parse() {
+                Ok(userid) => userid,
+                Err(err) => {
+                    task_warn!(
+                        worker,
+                        "group {}: invalid user name '{}' - {}",
+                        group,
+                        name,
+                        err
+                    );
+                    continue;
+                }
+            };
+            if !user_config.sections.contains_key(userid.as_str()) {
+                task_log!(
+                    worker,
+                    "group {}: skipping {}, no such user (sync the realm first)",
+                    group,
+                    userid
+                );
+                continue;
+            }
+            list.push(userid);
+        }
+        task_log!(worker, "group {}: {} member(s)", group, list.len());
+        userids.insert(group, list);
+    }
+
+    Ok(userids)
+}
+
+/// Run role sync job `job`
+///
+/// With `dry_run`, the changes are only logged.
+pub async fn sync_roles<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    job: &CloudRoleSyncJobConfig,
+    dry_run: bool,
+) -> Result<(), Error> {
+    if dry_run {
+        task_log!(worker, "this is a DRY RUN - changes will not be persisted");
+    }
+
+    let mappings = parse_mappings(job)?;
+
+    let (domains, _digest) = pbs_config::domains::config()?;
+    let realm_config: LdapRealmConfig = domains
+        .lookup("ldap", &job.realm)
+        .map_err(|_| format_err!("unknown LDAP realm '{}'", job.realm))?;
+
+    let groups: BTreeSet<String> = mappings.iter().map(|m| m.group.clone()).collect();
+    let members = ldap_group_members(&realm_config, &groups).await?;
+    let members = member_userids(worker, &job.realm, members)?;
+
+    let desired = desired_grants(&mappings, &members);
+
+    let _acl_lock = pbs_config::acl::lock_config()?;
+    let (mut tree, _digest) = pbs_config::acl::config()?;
+
+    let previous = load_role_grants(&base_path, &job.id)?;
+    let changes = apply_role_sync(&mut tree, &desired, &previous);
+
+    for grant in changes.granted.iter() {
+        task_log!(worker, "grant {}", grant);
+    }
+    for grant in changes.revoked.iter() {
+        task_log!(worker, "revoke {}", grant);
+    }
+    task_log!(
+        worker,
+        "{} role(s) granted, {} revoked, {} managed by the job",
+        changes.granted.len(),
+        changes.revoked.len(),
+        changes.managed.len()
+    );
+
+    if dry_run {
+        return Ok(());
+    }
+
+    // store the grants first, a failure must not leave grants behind which
+    // later runs would take for manually configured ones
+    save_role_grants(&base_path, &job.id, &changes.managed)?;
+    pbs_config::acl::save_config(&tree)?;
+
+    Ok(())
+}
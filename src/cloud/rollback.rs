@@ -0,0 +1,143 @@
+//! Point-in-time recovery on versioned targets
+//!
+//! If versioning is enabled on the bucket, overwritten and deleted
+//! objects are kept as noncurrent versions. This allows to undo
+//! accidental deletions (e.g. a misconfigured prune or GC run) by
+//! restoring the media sets as they were at a given time.
+//!
+//! Rollback never removes anything: media sets written after the
+//! rollback time are kept.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_sys::{task_log, WorkerTaskContext};
+
+use pbs_api_types::{CloudObjectVersion, CloudTarget};
+use pbs_datastore::manifest::MANIFEST_BLOB_NAME;
+
+use super::backend::CloudBackend;
+use super::catalog::MediaSetCatalog;
+use super::layout;
+use super::lease::{CloudLease, LEASE_TIMEOUT};
+
+/// Select the version of each object which was current at `timestamp`
+///
+/// Objects which did not exist at that time (or were deleted) are not
+/// included.
+pub fn versions_at(
+    list: &[CloudObjectVersion],
+    timestamp: i64,
+) -> HashMap<&str, &CloudObjectVersion> {
+    let mut map: HashMap<&str, &CloudObjectVersion> = HashMap::new();
+    for version in list.iter().filter(|version| version.mtime <= timestamp) {
+        match map.get(version.key.as_str()) {
+            // on equal times, prefer the current version
+            Some(other) if other.mtime > version.mtime => continue,
+            Some(other) if other.mtime == version.mtime && other.is_latest => continue,
+            _ => {
+                map.insert(&version.key, version);
+            }
+        }
+    }
+    map.retain(|_, version| !version.delete_marker);
+    map
+}
+
+/// Test if an object is a media set catalog or label, or a snapshot index
+pub fn is_catalog_or_index_key(key: &str) -> bool {
+    key.ends_with("/catalog.json")
+        || key.ends_with("/label.json")
+        || key.ends_with(".fidx")
+        || key.ends_with(".didx")
+        || key.ends_with(MANIFEST_BLOB_NAME)
+}
+
+/// Noncurrent versions (and delete markers) of catalogs and indexes
+pub fn list_noncurrent_versions(
+    backend: &dyn CloudBackend,
+) -> Result<Vec<CloudObjectVersion>, Error> {
+    let mut list: Vec<CloudObjectVersion> = backend
+        .list_object_versions(layout::MEDIA_SET_PREFIX)?
+        .into_iter()
+        .filter(|version| !version.is_latest || version.delete_marker)
+        .filter(|version| is_catalog_or_index_key(&version.key))
+        .collect();
+    list.sort_by(|a, b| a.key.cmp(&b.key).then(b.mtime.cmp(&a.mtime)));
+    Ok(list)
+}
+
+// catalogs mark media sets as complete, so they are restored last
+fn restore_order(key: &str) -> u8 {
+    if key.ends_with("/catalog.json") {
+        2
+    } else if key.ends_with("/label.json") {
+        1
+    } else {
+        0
+    }
+}
+
+/// Restore all media sets of a target as they were at `timestamp`
+///
+/// Objects which were deleted or overwritten since then are restored from
+/// their noncurrent versions, and the local catalogs are rewritten.
+/// Returns the number of restored objects.
+pub fn rollback_media_sets<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+    timestamp: i64,
+) -> Result<usize, Error> {
+    let base_path = base_path.as_ref();
+
+    if !backend.capabilities()?.versioning {
+        bail!(
+            "versioning is not enabled on cloud target '{}'",
+            target.name
+        );
+    }
+
+    let mut lease =
+        CloudLease::acquire(Arc::clone(backend), proxmox_sys::nodename(), LEASE_TIMEOUT)?;
+
+    let list = backend.list_object_versions(layout::MEDIA_SET_PREFIX)?;
+    let wanted = versions_at(&list, timestamp);
+
+    let mut keys: Vec<&str> = wanted.keys().copied().collect();
+    keys.sort_by_key(|key| (restore_order(key), *key));
+
+    let mut restored = 0;
+    for key in keys.iter() {
+        worker.check_abort()?;
+
+        let version = wanted[key];
+        if version.is_latest {
+            continue;
+        }
+
+        lease.heartbeat()?;
+        let data = backend.get_object_version(key, &version.version_id)?;
+        backend
+            .put_object(key, &data)
+            .map_err(|err| format_err!("unable to restore '{}' - {}", key, err))?;
+        task_log!(worker, "restored {} (version {})", key, version.version_id);
+        restored += 1;
+    }
+
+    for key in keys.iter().filter(|key| key.ends_with("/catalog.json")) {
+        let data = backend.get_object(key)?;
+        let catalog: MediaSetCatalog = serde_json::from_slice(&data)
+            .map_err(|err| format_err!("unable to parse catalog '{}' - {}", key, err))?;
+        catalog.save(base_path, &target.name)?;
+        task_log!(worker, "media set {} is available", catalog.uuid());
+    }
+
+    lease.release()?;
+
+    Ok(restored)
+}
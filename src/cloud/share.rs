@@ -0,0 +1,137 @@
+//! Shared downloads of snapshot exports
+//!
+//! Snapshot exports (see [`super::snapshot_export`]) can be handed to an
+//! external party as a pre-signed download URL, which works without any
+//! credentials until it expires. Only export objects can be shared, and
+//! URLs never live longer than the `share-max-ttl` of the target.
+//!
+//! Pre-signed URLs cannot be revoked (short of removing the object), so
+//! every URL is recorded in an audit log of the target before it is
+//! handed out.
+
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_sys::fs::create_path;
+
+use pbs_api_types::{Authid, CloudShareRecord, CloudShareUrl, CloudTarget};
+
+use super::backend::CloudBackend;
+use super::create_options;
+use super::layout;
+
+/// Lifetime of shared URLs if none is requested (seconds)
+pub const DEFAULT_SHARE_TTL: u64 = 3600;
+
+fn share_log_dir(base_path: &Path) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("share-log");
+    path
+}
+
+fn share_log_path(base_path: &Path, target: &str) -> PathBuf {
+    let mut path = share_log_dir(base_path);
+    path.push(format!("{}.json", target));
+    path
+}
+
+/// Append a shared URL to the audit log of its target
+pub fn record_share<P: AsRef<Path>>(base_path: P, record: &CloudShareRecord) -> Result<(), Error> {
+    let dir = share_log_dir(base_path.as_ref());
+    create_path(
+        &dir,
+        Some(create_options(0o0750)?),
+        Some(create_options(0o0750)?),
+    )?;
+
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+
+    let path = share_log_path(base_path.as_ref(), &record.target);
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|err| format_err!("unable to open {:?} - {}", path, err))?;
+    // a single write, so concurrent writers never interleave lines
+    file.write_all(&line)?;
+
+    Ok(())
+}
+
+/// All URLs shared from a target, oldest first
+pub fn load_share_log<P: AsRef<Path>>(
+    base_path: P,
+    target: &str,
+) -> Result<Vec<CloudShareRecord>, Error> {
+    let path = share_log_path(base_path.as_ref(), target);
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(format_err!("unable to open {:?} - {}", path, err)),
+    };
+
+    let mut list = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if let Ok(record) = serde_json::from_str(&line) {
+            list.push(record);
+        }
+    }
+    Ok(list)
+}
+
+/// Create a pre-signed download URL for a snapshot export
+///
+/// `ttl` defaults to [`DEFAULT_SHARE_TTL`] (or the maximum of the target,
+/// if lower). The URL is recorded in the audit log before it is returned.
+#[allow(clippy::too_many_arguments)]
+pub fn share_export<P: AsRef<Path>>(
+    base_path: P,
+    target: &CloudTarget,
+    backend: &dyn CloudBackend,
+    key: &str,
+    ttl: Option<u64>,
+    auth_id: &Authid,
+    comment: Option<String>,
+    now: i64,
+) -> Result<CloudShareUrl, Error> {
+    if !key.starts_with(layout::EXPORT_PREFIX) {
+        bail!("only snapshot exports can be shared");
+    }
+
+    let max_ttl = target.config.share_max_ttl();
+    let ttl = ttl.unwrap_or_else(|| DEFAULT_SHARE_TTL.min(max_ttl));
+    if ttl > max_ttl {
+        bail!(
+            "lifetime of {} seconds exceeds the maximum of target '{}' ({} seconds)",
+            ttl,
+            target.name,
+            max_ttl
+        );
+    }
+
+    if backend.head_object(key)?.is_none() {
+        bail!("export '{}' not found on target '{}'", key, target.name);
+    }
+
+    let url = backend.presign_get_url(key, ttl, now)?;
+    let expires = now + ttl as i64;
+
+    record_share(
+        base_path,
+        &CloudShareRecord {
+            time: now,
+            target: target.name.clone(),
+            key: key.to_string(),
+            auth_id: auth_id.clone(),
+            expires,
+            comment,
+        },
+    )
+    .map_err(|err| format_err!("unable to record shared URL - {}", err))?;
+
+    Ok(CloudShareUrl { url, expires })
+}
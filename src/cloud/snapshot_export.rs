@@ -0,0 +1,395 @@
+//! Standalone snapshot exports
+//!
+//! A snapshot can be exported into a single object on its target, to hand
+//! it over to a third party without access to the backup server. The
+//! object is an uncompressed tar archive (chunks are compressed already):
+//!
+//! ```text
+//! export.json
+//! key.json                                  (optional)
+//! [ns/<ns>/...]<type>/<id>/<time>/<file>
+//! .chunks/<digest prefix>/<digest>
+//! ```
+//!
+//! `export.json` describes the content ([`SnapshotExportInfo`]) and is
+//! always the first entry. Unpacking the archive with plain `tar` yields a
+//! datastore directory containing the snapshot.
+//! `proxmox-backup-debug cloud snapshot-export extract` does the same, but
+//! checks every file and chunk while unpacking.
+//!
+//! Data encrypted with a namespace key of the target is decrypted, so the
+//! archive contains the snapshot as written by the client. Snapshots
+//! encrypted by the client stay encrypted, their key fingerprint is
+//! recorded and the (password protected) key can be included.
+
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{create_path, replace_file, CreateOptions};
+use proxmox_sys::{task_log, WorkerTaskContext};
+
+use pbs_api_types::{
+    print_ns_and_snapshot, Authid, BackupDir, BackupNamespace, CryptMode, Fingerprint,
+};
+use pbs_datastore::manifest::MANIFEST_BLOB_NAME;
+use pbs_datastore::DataBlob;
+use pbs_key_config::KeyConfig;
+use pbs_tools::crypt_config::CryptConfig;
+
+use super::backend::{CloudBackend, PutOptions};
+use super::catalog::{CloudCatalog, MediaSetCatalog, SnapshotEntry, SnapshotFileEntry};
+use super::chunk_reader::CloudChunkReader;
+use super::encryption_keys::decrypt_object;
+use super::layout;
+
+/// Current version of the export format
+pub const SNAPSHOT_EXPORT_VERSION: u64 = 1;
+
+/// Name of the description, the first entry of every export
+pub const EXPORT_INFO_NAME: &str = "export.json";
+
+/// Name of the included key configuration
+pub const EXPORT_KEY_NAME: &str = "key.json";
+
+/// Exports are assembled in memory, larger snapshots are rejected
+pub const MAX_EXPORT_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Description of an exported snapshot
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SnapshotExportInfo {
+    /// Format version, newer versions are rejected on extraction
+    pub version: u64,
+    /// Datastore the snapshot was backed up from
+    pub store: String,
+    #[serde(default, skip_serializing_if = "BackupNamespace::is_root")]
+    pub ns: BackupNamespace,
+    pub snapshot: BackupDir,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Authid>,
+    /// Node which created the export
+    pub node: String,
+    /// Export time (UNIX epoch)
+    pub time: i64,
+    /// Snapshot files, the manifest last
+    pub files: Vec<SnapshotFileEntry>,
+    /// Number of chunks
+    pub chunks: usize,
+    /// Client side encryption key fingerprint from the manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<Fingerprint>,
+}
+
+impl SnapshotExportInfo {
+    /// Directory of the snapshot inside the archive
+    pub fn snapshot_path(&self) -> String {
+        print_ns_and_snapshot(&self.ns, &self.snapshot)
+    }
+}
+
+fn append_file<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &str,
+    data: &[u8],
+    mtime: i64,
+) -> Result<(), Error> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime.max(0) as u64);
+    builder
+        .append_data(&mut header, path, data)
+        .map_err(|err| format_err!("unable to add '{}' to export - {}", path, err))
+}
+
+/// Check the CRC of a chunk, and the digest if the chunk is not encrypted
+fn verify_chunk(digest: &[u8; 32], data: &[u8]) -> Result<(), Error> {
+    let chunk = DataBlob::load_from_reader(&mut &data[..])?;
+    chunk.verify_crc()?;
+    if chunk.crypt_mode()? == CryptMode::None {
+        chunk.decode(None, Some(digest))?;
+    }
+    Ok(())
+}
+
+/// Assemble the export archive of a snapshot
+///
+/// `crypt_config` is the namespace key the snapshot is stored with on the
+/// target. `key_config` is included in the archive, it has to match the
+/// client side encryption key of the snapshot.
+#[allow(clippy::too_many_arguments)]
+pub fn build_snapshot_export(
+    worker: &dyn WorkerTaskContext,
+    backend: &Arc<dyn CloudBackend>,
+    catalog: &Arc<CloudCatalog>,
+    media_set: &MediaSetCatalog,
+    entry: &SnapshotEntry,
+    crypt_config: Option<(Fingerprint, Arc<CryptConfig>)>,
+    key_config: Option<&KeyConfig>,
+    time: i64,
+) -> Result<Vec<u8>, Error> {
+    if let Some(key_config) = key_config {
+        match (&entry.fingerprint, &key_config.fingerprint) {
+            (None, _) => bail!("snapshot is not encrypted, no key needed"),
+            (Some(expected), Some(fingerprint)) if expected != fingerprint => bail!(
+                "key {} does not match the snapshot key {}",
+                fingerprint.signature(),
+                expected.signature()
+            ),
+            _ => {}
+        }
+    }
+
+    let namespace_key = crypt_config.as_ref().map(|(fingerprint, _)| fingerprint);
+    let mut size: u64 = entry.files.iter().map(|file| file.size).sum();
+    for digest in entry.chunks.iter() {
+        let location = catalog
+            .lookup_chunk(digest, namespace_key)
+            .ok_or_else(|| format_err!("chunk {} not found in catalog", hex::encode(digest)))?;
+        size += location.size;
+    }
+    if size > MAX_EXPORT_SIZE {
+        bail!(
+            "snapshot too large for an export ({} bytes, at most {})",
+            size,
+            MAX_EXPORT_SIZE
+        );
+    }
+
+    // the manifest marks the snapshot as finished
+    let mut files = entry.files.clone();
+    files.sort_by_key(|file| file.filename == MANIFEST_BLOB_NAME);
+
+    let info = SnapshotExportInfo {
+        version: SNAPSHOT_EXPORT_VERSION,
+        store: entry.store.clone(),
+        ns: entry.ns.clone(),
+        snapshot: entry.snapshot.clone(),
+        owner: entry.owner.clone(),
+        node: proxmox_sys::nodename().to_string(),
+        time,
+        files,
+        chunks: entry.chunks.len(),
+        fingerprint: entry.fingerprint.clone(),
+    };
+
+    let mut builder = tar::Builder::new(Vec::with_capacity(size as usize));
+    append_file(
+        &mut builder,
+        EXPORT_INFO_NAME,
+        &serde_json::to_vec_pretty(&info)?,
+        time,
+    )?;
+    if let Some(key_config) = key_config {
+        append_file(
+            &mut builder,
+            EXPORT_KEY_NAME,
+            &serde_json::to_vec_pretty(key_config)?,
+            time,
+        )?;
+    }
+
+    let snapshot_path = info.snapshot_path();
+    for file in info.files.iter() {
+        worker.check_abort()?;
+        let key = media_set.snapshot_file_key(entry, &file.filename);
+        let data = backend.get_object(&key)?;
+        let data = match crypt_config {
+            Some((_, ref crypt_config)) => decrypt_object(&data, crypt_config)?,
+            None => data,
+        };
+        if openssl::sha::sha256(&data) != file.csum {
+            bail!("checksum mismatch on file '{}'", file.filename);
+        }
+        append_file(
+            &mut builder,
+            &format!("{}/{}", snapshot_path, file.filename),
+            &data,
+            entry.snapshot.time,
+        )?;
+    }
+
+    let mut reader = CloudChunkReader::new(Arc::clone(backend), Arc::clone(catalog), None)?;
+    if let Some((ref fingerprint, ref crypt_config)) = crypt_config {
+        reader = reader.with_namespace_key(fingerprint.clone(), Arc::clone(crypt_config));
+    }
+    for (i, digest) in entry.chunks.iter().enumerate() {
+        worker.check_abort()?;
+        let data = reader.fetch_chunk(digest)?;
+        verify_chunk(digest, &data)
+            .map_err(|err| format_err!("chunk {} is damaged - {}", hex::encode(digest), err))?;
+        append_file(
+            &mut builder,
+            &layout::datastore_chunk_key("", digest),
+            &data,
+            entry.snapshot.time,
+        )?;
+        if (i + 1) % 1000 == 0 {
+            task_log!(
+                worker,
+                "exported {} of {} chunks",
+                i + 1,
+                entry.chunks.len()
+            );
+        }
+    }
+    reader.finish()?;
+
+    Ok(builder.into_inner()?)
+}
+
+/// Export a snapshot into an object on its target
+///
+/// Returns the key of the export object, an existing export of the
+/// snapshot is replaced.
+#[allow(clippy::too_many_arguments)]
+pub fn export_snapshot(
+    worker: &dyn WorkerTaskContext,
+    backend: &Arc<dyn CloudBackend>,
+    catalog: &Arc<CloudCatalog>,
+    store: &str,
+    ns: &BackupNamespace,
+    snapshot: &BackupDir,
+    crypt_config: Option<(Fingerprint, Arc<CryptConfig>)>,
+    key_config: Option<&KeyConfig>,
+) -> Result<String, Error> {
+    let (media_set, entry) = catalog
+        .lookup_snapshot(store, ns, snapshot)
+        .ok_or_else(|| format_err!("snapshot {} not found on target", snapshot))?;
+
+    let data = build_snapshot_export(
+        worker,
+        backend,
+        catalog,
+        media_set,
+        entry,
+        crypt_config,
+        key_config,
+        proxmox_time::epoch_i64(),
+    )?;
+
+    let key = layout::snapshot_export_key(store, ns, snapshot);
+    task_log!(worker, "upload {} ({} bytes)", key, data.len());
+    backend.put_object_multipart(&key, &data, &PutOptions::default())?;
+
+    Ok(key)
+}
+
+// write a file below `dir`, creating the parent directories
+fn write_file(dir: &Path, path: &str, data: &[u8]) -> Result<(), Error> {
+    let path = dir.join(path);
+    if let Some(parent) = path.parent() {
+        create_path(parent, None, None)?;
+    }
+    replace_file(&path, data, CreateOptions::new(), false)
+}
+
+/// Unpack an export into the datastore directory `dir`
+///
+/// Every entry is checked against the description: snapshot files by
+/// their checksum, chunks by their CRC and, if not encrypted, by their
+/// digest. Entries not described by `export.json` are rejected.
+pub fn extract_snapshot_export<R: Read>(
+    reader: R,
+    dir: &Path,
+) -> Result<SnapshotExportInfo, Error> {
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = archive.entries()?;
+
+    let info: SnapshotExportInfo = match entries.next() {
+        Some(entry) => {
+            let mut entry = entry?;
+            if entry.path()?.to_str() != Some(EXPORT_INFO_NAME) {
+                bail!("not a snapshot export - missing {}", EXPORT_INFO_NAME);
+            }
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            let value: serde_json::Value = serde_json::from_slice(&data)
+                .map_err(|err| format_err!("unable to parse {} - {}", EXPORT_INFO_NAME, err))?;
+            match value["version"].as_u64() {
+                Some(version) if version > SNAPSHOT_EXPORT_VERSION => bail!(
+                    "export version {} is not supported (newest supported version {})",
+                    version,
+                    SNAPSHOT_EXPORT_VERSION
+                ),
+                Some(_) => (),
+                None => bail!("export without version"),
+            }
+            serde_json::from_value(value)?
+        }
+        None => bail!("empty archive"),
+    };
+
+    // file names end up in paths
+    if let Some(file) = info
+        .files
+        .iter()
+        .find(|file| file.filename.contains('/') || file.filename.starts_with('.'))
+    {
+        bail!("invalid file name '{}' in export", file.filename);
+    }
+
+    let snapshot_path = info.snapshot_path();
+    let mut missing_files: Vec<SnapshotFileEntry> = info.files.clone();
+    let mut chunks = 0;
+
+    for entry in entries {
+        let mut entry = entry?;
+        let path = entry
+            .path()?
+            .to_str()
+            .ok_or_else(|| format_err!("invalid path in export"))?
+            .to_string();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+
+        if path == EXPORT_KEY_NAME {
+            let key_config: KeyConfig = serde_json::from_slice(&data)
+                .map_err(|err| format_err!("unable to parse {} - {}", EXPORT_KEY_NAME, err))?;
+            if key_config.fingerprint.is_some() && key_config.fingerprint != info.fingerprint {
+                bail!("included key does not match the snapshot");
+            }
+        } else if let Some(filename) = path
+            .strip_prefix(&snapshot_path)
+            .and_then(|rest| rest.strip_prefix('/'))
+        {
+            let pos = missing_files
+                .iter()
+                .position(|file| file.filename == filename)
+                .ok_or_else(|| format_err!("unexpected file '{}' in export", path))?;
+            let file = missing_files.remove(pos);
+            if data.len() as u64 != file.size || openssl::sha::sha256(&data) != file.csum {
+                bail!("checksum mismatch on file '{}'", file.filename);
+            }
+        } else {
+            let digest = path
+                .rsplit_once('/')
+                .and_then(|(_, name)| <[u8; 32]>::try_from(hex::decode(name).ok()?).ok())
+                .filter(|digest| layout::datastore_chunk_key("", digest) == path)
+                .ok_or_else(|| format_err!("unexpected entry '{}' in export", path))?;
+            verify_chunk(&digest, &data)
+                .map_err(|err| format_err!("chunk {} is damaged - {}", hex::encode(digest), err))?;
+            chunks += 1;
+        }
+
+        write_file(dir, &path, &data)?;
+    }
+
+    if let Some(file) = missing_files.first() {
+        bail!("export is incomplete - missing file '{}'", file.filename);
+    }
+    if chunks < info.chunks {
+        bail!(
+            "export is incomplete - {} of {} chunks",
+            chunks,
+            info.chunks
+        );
+    }
+
+    Ok(info)
+}
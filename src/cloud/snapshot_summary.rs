@@ -0,0 +1,100 @@
+//! Snapshot summaries
+//!
+//! Each snapshot on a cloud target gets a small summary object (file
+//! sizes, chunk count and stored chunk size) next to its files. Size
+//! queries and restore previews read the summary instead of downloading
+//! the manifest. Media sets written before summaries existed have none,
+//! their summaries are built from the catalog.
+
+use anyhow::{format_err, Error};
+
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{CloudSnapshotFileSize, CloudSnapshotSummary};
+
+use super::backend::{CloudBackend, PutOptions};
+use super::catalog::{CloudCatalog, SnapshotEntry};
+use super::layout;
+
+/// Build the summary of a snapshot entry
+///
+/// `chunk_size` is the stored size of all chunks referenced by the
+/// snapshot, if known.
+pub fn build_snapshot_summary(
+    media_set: &Uuid,
+    entry: &SnapshotEntry,
+    chunk_size: Option<u64>,
+) -> CloudSnapshotSummary {
+    let files: Vec<CloudSnapshotFileSize> = entry
+        .files
+        .iter()
+        .map(|file| CloudSnapshotFileSize {
+            filename: file.filename.clone(),
+            size: file.size,
+        })
+        .collect();
+
+    CloudSnapshotSummary {
+        store: entry.store.clone(),
+        ns: entry.ns.clone(),
+        snapshot: entry.snapshot.to_string(),
+        media_set: media_set.to_string(),
+        files_size: files.iter().map(|file| file.size).sum(),
+        files,
+        chunk_count: entry.chunks.len() as u64,
+        chunk_size,
+        encrypted: entry.key.is_some(),
+        archives: Vec::new(),
+    }
+}
+
+/// Stored size of the chunks of a snapshot, `None` if a chunk is unknown
+pub fn catalog_chunk_size(catalog: &CloudCatalog, entry: &SnapshotEntry) -> Option<u64> {
+    entry
+        .chunks
+        .iter()
+        .map(|digest| {
+            catalog
+                .lookup_chunk(digest, entry.key.as_ref())
+                .map(|location| location.size)
+        })
+        .sum()
+}
+
+/// Upload the summary of a snapshot
+pub fn upload_snapshot_summary(
+    backend: &dyn CloudBackend,
+    media_set: &Uuid,
+    entry: &SnapshotEntry,
+    summary: &CloudSnapshotSummary,
+    options: &PutOptions,
+) -> Result<(), Error> {
+    let key = layout::snapshot_summary_key(media_set, &entry.store, &entry.ns, &entry.snapshot);
+    backend
+        .put_object_with_options(&key, &serde_json::to_vec(summary)?, options)
+        .map_err(|err| format_err!("unable to upload snapshot summary - {}", err))
+}
+
+/// Load the summary of a snapshot from the target
+///
+/// Falls back to the catalog if the snapshot has no summary object.
+pub fn load_snapshot_summary(
+    backend: &dyn CloudBackend,
+    catalog: &CloudCatalog,
+    media_set: &Uuid,
+    entry: &SnapshotEntry,
+) -> Result<CloudSnapshotSummary, Error> {
+    let key = layout::snapshot_summary_key(media_set, &entry.store, &entry.ns, &entry.snapshot);
+
+    if backend.head_object(&key)?.is_some() {
+        let data = backend.get_object(&key)?;
+        return serde_json::from_slice(&data)
+            .map_err(|err| format_err!("unable to parse snapshot summary '{}' - {}", key, err));
+    }
+
+    Ok(build_snapshot_summary(
+        media_set,
+        entry,
+        catalog_chunk_size(catalog, entry),
+    ))
+}
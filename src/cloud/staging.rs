@@ -0,0 +1,518 @@
+//! Write-back staging of uploads
+//!
+//! Targets with `write-back` do not receive the objects of backup jobs
+//! directly. The job writes them to a local spool (see
+//! [`super::backend::StagingBackend`]) and finishes at local disk speed,
+//! while a separate worker ("cloud-staging-upload") uploads the spooled
+//! objects with retries.
+//!
+//! Objects are uploaded oldest first, so the catalog of a media set (the
+//! last object a job writes) only shows up on the target after all its
+//! archives. Until then, the media set is incomplete for other nodes, but
+//! local reads through the staging backend see the spooled objects.
+//!
+//! The spool is limited to `write-back-spool-size`. Jobs wait while it is
+//! full ([`StagingSpool::wait_for_space`]), a single object is always
+//! accepted into an empty spool.
+//!
+//! The staging status reports the health of the uploader: queue depth, age
+//! of the oldest object and the last object the provider accepted, with
+//! alerts once [`STAGING_AGE_ALERT`] or [`STAGING_FILL_ALERT`] are exceeded.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{create_path, open_file_locked, replace_file};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{CloudStagingStatus, CloudTarget};
+
+use super::backend::{is_object_exists, is_transient_error, CloudBackend, PutOptions};
+use super::create_options;
+
+/// Default for `write-back-spool-size` (GiB)
+pub const DEFAULT_STAGING_SPOOL_SIZE: u64 = 16;
+
+/// Attempts per object before the uploader gives up (for this run)
+pub const UPLOAD_ATTEMPTS: u32 = 5;
+
+/// Seconds objects may wait for upload before the status alerts
+pub const STAGING_AGE_ALERT: i64 = 3600;
+
+/// Fill level of the spool (percent) at which the status alerts
+pub const STAGING_FILL_ALERT: u64 = 90;
+
+// interval for checking the spool (waiting writers, idle uploader)
+const STAGING_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+const DATA_FILE_EXT: &str = "obj";
+const META_FILE_EXT: &str = "json";
+
+// time the provider last accepted an object of the spool
+const LAST_UPLOAD_FILE: &str = ".last-upload";
+
+// distinguishes objects spooled within the same second
+static STAGING_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+// delay before retrying a failed upload
+fn retry_delay(attempt: u32) -> Duration {
+    if cfg!(test) {
+        return Duration::ZERO;
+    }
+    Duration::from_secs((10u64 << attempt).min(300))
+}
+
+// upload settings stored next to the data
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct StagedMeta {
+    key: String,
+    // upload with put_object_if_absent
+    #[serde(default)]
+    if_absent: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    storage_class: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retain_until: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<(String, String)>,
+    ctime: i64,
+}
+
+/// An object waiting in the spool
+#[derive(Clone, Debug)]
+pub struct StagedObject {
+    /// Object key on the target
+    pub key: String,
+    /// Size of the data (in bytes)
+    pub size: u64,
+    /// Time the object was spooled (epoch)
+    pub ctime: i64,
+    meta: StagedMeta,
+    path: PathBuf,
+}
+
+impl StagedObject {
+    fn data_path(&self) -> PathBuf {
+        self.path.with_extension(DATA_FILE_EXT)
+    }
+
+    /// Read the spooled data
+    pub fn read(&self) -> Result<Vec<u8>, Error> {
+        let path = self.data_path();
+        std::fs::read(&path).map_err(|err| format_err!("unable to read {:?} - {}", path, err))
+    }
+
+    fn put_options(&self) -> PutOptions {
+        PutOptions {
+            storage_class: self.meta.storage_class.clone(),
+            retain_until: self.meta.retain_until,
+            tags: self.meta.tags.clone(),
+        }
+    }
+}
+
+/// Local spool of the objects written to a target
+pub struct StagingSpool {
+    path: PathBuf,
+    max_size: u64,
+}
+
+/// Spool directory of a target
+pub fn staging_dir<P: AsRef<Path>>(base_path: P, target: &str) -> PathBuf {
+    let mut path = base_path.as_ref().to_owned();
+    path.push("staging");
+    path.push(target);
+    path
+}
+
+impl StagingSpool {
+    /// Open the spool of `target`, limited to `max_size` bytes
+    pub fn open<P: AsRef<Path>>(base_path: P, target: &str, max_size: u64) -> Result<Self, Error> {
+        let path = staging_dir(base_path, target);
+        create_path(
+            &path,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+        Ok(Self { path, max_size })
+    }
+
+    /// Open the spool of a target with its configured size
+    pub fn open_target<P: AsRef<Path>>(base_path: P, target: &CloudTarget) -> Result<Self, Error> {
+        let max_size = target
+            .config
+            .write_back_spool_size
+            .unwrap_or(DEFAULT_STAGING_SPOOL_SIZE)
+            * 1024
+            * 1024
+            * 1024;
+        Self::open(base_path, &target.name, max_size)
+    }
+
+    /// Open the spool of a target with `write-back` (`None` otherwise)
+    pub fn for_target<P: AsRef<Path>>(
+        base_path: P,
+        target: &CloudTarget,
+    ) -> Result<Option<Arc<Self>>, Error> {
+        if !target.config.write_back.unwrap_or(false) {
+            return Ok(None);
+        }
+        Ok(Some(Arc::new(Self::open_target(base_path, target)?)))
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Spooled objects, oldest first
+    pub fn list(&self) -> Result<Vec<StagedObject>, Error> {
+        let mut list = Vec::new();
+
+        let read_dir = match std::fs::read_dir(&self.path) {
+            Ok(read_dir) => read_dir,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(list),
+            Err(err) => bail!("unable to read spool {:?} - {}", self.path, err),
+        };
+        for entry in read_dir {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(META_FILE_EXT) {
+                continue;
+            }
+            // the data file is written first, the metadata commits the entry
+            let size = match std::fs::metadata(path.with_extension(DATA_FILE_EXT)) {
+                Ok(metadata) => metadata.len(),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            let meta: StagedMeta = match proxmox_sys::fs::file_get_optional_contents(&path)? {
+                Some(data) => serde_json::from_slice(&data)
+                    .map_err(|err| format_err!("unable to parse {:?} - {}", path, err))?,
+                None => continue, // uploaded meanwhile
+            };
+            list.push(StagedObject {
+                key: meta.key.clone(),
+                size,
+                ctime: meta.ctime,
+                meta,
+                path,
+            });
+        }
+
+        list.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(list)
+    }
+
+    /// Total size of the spooled objects
+    pub fn size(&self) -> Result<u64, Error> {
+        Ok(self.list()?.iter().map(|object| object.size).sum())
+    }
+
+    /// The newest spooled version of `key`
+    pub fn lookup(&self, key: &str) -> Result<Option<StagedObject>, Error> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .rev()
+            .find(|object| object.key == key))
+    }
+
+    /// Add an object to the spool
+    ///
+    /// With `if_absent`, the object is uploaded with a conditional write.
+    pub fn push(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &PutOptions,
+        if_absent: bool,
+    ) -> Result<(), Error> {
+        let ctime = proxmox_time::epoch_i64();
+        let sequence = STAGING_SEQUENCE.fetch_add(1, Ordering::SeqCst);
+        let mut path = self.path.clone();
+        path.push(format!(
+            "{:016x}-{:08x}-{:08x}.{}",
+            ctime,
+            std::process::id(),
+            sequence,
+            META_FILE_EXT
+        ));
+
+        let meta = StagedMeta {
+            key: key.to_string(),
+            if_absent,
+            storage_class: options.storage_class.clone(),
+            retain_until: options.retain_until,
+            tags: options.tags.clone(),
+            ctime,
+        };
+
+        replace_file(
+            path.with_extension(DATA_FILE_EXT),
+            data,
+            create_options(0o0640)?,
+            true,
+        )?;
+        replace_file(
+            &path,
+            &serde_json::to_vec(&meta)?,
+            create_options(0o0640)?,
+            true,
+        )
+    }
+
+    /// Remove an object from the spool
+    pub fn remove(&self, object: &StagedObject) -> Result<(), Error> {
+        // without metadata, the data file is ignored
+        for path in [object.path.clone(), object.data_path()] {
+            match std::fs::remove_file(&path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    bail!("unable to remove {:?} - {}", path, err);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove all spooled versions of `key`
+    pub fn remove_key(&self, key: &str) -> Result<(), Error> {
+        for object in self.list()? {
+            if object.key == key {
+                self.remove(&object)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait until an object of `size` bytes fits into the spool
+    ///
+    /// `keep_alive` is called while waiting, e.g. to renew the target lease.
+    pub fn wait_for_space(
+        &self,
+        worker: &dyn WorkerTaskContext,
+        size: u64,
+        mut keep_alive: impl FnMut() -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut logged = false;
+        loop {
+            let used = self.size()?;
+            if used == 0 || used + size <= self.max_size {
+                return Ok(());
+            }
+            if !logged {
+                task_log!(
+                    worker,
+                    "write-back spool full ({} of {} bytes), waiting for uploads",
+                    used,
+                    self.max_size
+                );
+                logged = true;
+            }
+            worker.check_abort()?;
+            keep_alive()?;
+            std::thread::sleep(STAGING_POLL_INTERVAL);
+        }
+    }
+
+    /// Lock the spool for uploading (`None` if an uploader is running)
+    pub fn try_lock_uploader(&self) -> Option<File> {
+        let mut lock_path = self.path.clone();
+        lock_path.push(".upload.lck");
+        let options = create_options(0o0640).ok()?;
+        open_file_locked(&lock_path, Duration::new(0, 0), true, options).ok()
+    }
+
+    /// Remember that the provider accepted an object at `time`
+    pub fn record_upload(&self, time: i64) -> Result<(), Error> {
+        replace_file(
+            self.path.join(LAST_UPLOAD_FILE),
+            time.to_string().as_bytes(),
+            create_options(0o0640)?,
+            false,
+        )
+    }
+
+    /// Time the provider last accepted an object of the spool
+    pub fn last_upload(&self) -> Result<Option<i64>, Error> {
+        let path = self.path.join(LAST_UPLOAD_FILE);
+        match proxmox_sys::fs::file_get_optional_contents(&path)? {
+            Some(data) => {
+                let time = String::from_utf8(data)?;
+                let time = time
+                    .trim()
+                    .parse()
+                    .map_err(|err| format_err!("unable to parse {:?} - {}", path, err))?;
+                Ok(Some(time))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Current utilization
+    pub fn status(&self, write_back: bool) -> Result<CloudStagingStatus, Error> {
+        self.status_at(write_back, proxmox_time::epoch_i64())
+    }
+
+    /// Utilization and uploader health at `now`
+    pub fn status_at(&self, write_back: bool, now: i64) -> Result<CloudStagingStatus, Error> {
+        let list = self.list()?;
+        let oldest = list.iter().map(|object| object.ctime).min();
+        let mut status = CloudStagingStatus {
+            write_back,
+            objects: list.len() as u64,
+            size: list.iter().map(|object| object.size).sum(),
+            max_size: self.max_size,
+            oldest,
+            oldest_age: oldest.map(|oldest| (now - oldest).max(0)),
+            uploading: self.try_lock_uploader().is_none(),
+            last_upload: self.last_upload()?,
+            alerts: Vec::new(),
+        };
+        status.alerts = staging_alerts(&status, now);
+        Ok(status)
+    }
+}
+
+// exceeded health thresholds of the uploader
+fn staging_alerts(status: &CloudStagingStatus, now: i64) -> Vec<String> {
+    let mut alerts = Vec::new();
+
+    if let Some(age) = status.oldest_age {
+        if age > STAGING_AGE_ALERT {
+            alerts.push(format!("oldest object is waiting for upload for {}s", age));
+        }
+    }
+
+    if status.max_size > 0 && status.size * 100 >= status.max_size * STAGING_FILL_ALERT {
+        alerts.push(format!(
+            "spool is {}% full",
+            status.size * 100 / status.max_size
+        ));
+    }
+
+    // a running uploader which does not get anything through
+    if let Some(oldest) = status.oldest.filter(|_| status.uploading) {
+        let since = status.last_upload.map_or(oldest, |last| last.max(oldest));
+        if now - since > STAGING_AGE_ALERT {
+            alerts.push(format!(
+                "provider did not accept an upload for {}s",
+                now - since
+            ));
+        }
+    }
+
+    alerts
+}
+
+/// Result of an uploader run
+#[derive(Debug, Default, PartialEq)]
+pub struct StagingUploadStats {
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+// upload a single object, retrying failed requests
+fn upload_object(
+    worker: &dyn WorkerTaskContext,
+    backend: &dyn CloudBackend,
+    object: &StagedObject,
+) -> Result<(), Error> {
+    let data = object.read()?;
+    let options = object.put_options();
+
+    let mut attempt = 0;
+    loop {
+        worker.check_abort()?;
+        let result = if object.meta.if_absent {
+            backend.put_object_if_absent(&object.key, &data)
+        } else {
+            backend.put_object_multipart(&object.key, &data, &options)
+        };
+        match result {
+            Ok(()) => return Ok(()),
+            Err(err) if object.meta.if_absent && is_object_exists(&err) => {
+                // uploaded by an earlier, interrupted run - or a conflict
+                // we cannot resolve by retrying
+                task_warn!(worker, "skip '{}' - {}", object.key, err);
+                return Ok(());
+            }
+            // e.g. missing permissions, retrying cannot help
+            Err(err) if !is_transient_error(&err) => {
+                bail!("unable to upload '{}' - {}", object.key, err);
+            }
+            Err(err) => {
+                attempt += 1;
+                if attempt >= UPLOAD_ATTEMPTS {
+                    bail!(
+                        "unable to upload '{}' after {} attempts - {}",
+                        object.key,
+                        attempt,
+                        err
+                    );
+                }
+                let delay = retry_delay(attempt);
+                task_warn!(
+                    worker,
+                    "upload of '{}' failed, retry in {}s - {}",
+                    object.key,
+                    delay.as_secs(),
+                    err
+                );
+                std::thread::sleep(delay);
+            }
+        }
+    }
+}
+
+/// Upload all spooled objects, oldest first
+///
+/// Keeps running until the spool stayed empty for `idle_timeout`, so
+/// objects of a running job are picked up. Objects are removed from the
+/// spool once uploaded. Fails if an object cannot be uploaded after
+/// [`UPLOAD_ATTEMPTS`], the remaining objects stay in the spool.
+pub fn upload_staged_objects(
+    worker: &dyn WorkerTaskContext,
+    spool: &StagingSpool,
+    backend: &dyn CloudBackend,
+    idle_timeout: Duration,
+) -> Result<StagingUploadStats, Error> {
+    let mut stats = StagingUploadStats::default();
+    let mut idle_since = Instant::now();
+
+    loop {
+        worker.check_abort()?;
+
+        let list = spool.list()?;
+        if list.is_empty() {
+            if idle_since.elapsed() >= idle_timeout {
+                return Ok(stats);
+            }
+            std::thread::sleep(STAGING_POLL_INTERVAL.min(idle_timeout));
+            continue;
+        }
+
+        for object in list {
+            upload_object(worker, backend, &object)?;
+            if let Err(err) = spool.record_upload(proxmox_time::epoch_i64()) {
+                task_warn!(worker, "unable to record upload time - {}", err);
+            }
+            spool.remove(&object)?;
+            stats.objects += 1;
+            stats.bytes += object.size;
+        }
+        task_log!(
+            worker,
+            "uploaded {} objects ({} bytes) so far",
+            stats.objects,
+            stats.bytes
+        );
+        idle_since = Instant::now();
+    }
+}
@@ -0,0 +1,234 @@
+//! Warm standby of a cloud target
+//!
+//! A target with `standby-remote` set follows the catalog of the target
+//! with the same name on the remote PBS node. The standby periodically
+//! fetches the digests of all media set catalogs from the primary and only
+//! downloads the catalogs which changed, so it can take over restores
+//! without a full catalog rebuild from the cloud.
+//!
+//! While `standby-remote` is set, no job can write to the target. Removing
+//! the property makes the standby a regular (writable) target.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_sys::fs::{create_path, open_file_locked, replace_file};
+use proxmox_sys::{task_log, WorkerTaskContext};
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{CloudCatalogDigest, CloudStandbyStatus};
+use pbs_client::HttpClient;
+
+use super::catalog::{CloudCatalog, MediaSetCatalog};
+use super::create_options;
+
+/// SHA-256 of a media set catalog (hex)
+pub fn media_set_digest(media_set: &MediaSetCatalog) -> Result<String, Error> {
+    let data = serde_json::to_vec(media_set)?;
+    Ok(hex::encode(openssl::sha::sha256(&data)))
+}
+
+/// Digests of all media set catalogs, oldest first
+pub fn catalog_digests(catalog: &CloudCatalog) -> Result<Vec<CloudCatalogDigest>, Error> {
+    catalog
+        .media_sets()
+        .iter()
+        .map(|media_set| {
+            Ok(CloudCatalogDigest {
+                uuid: media_set.uuid().to_string(),
+                ctime: media_set.label.ctime,
+                digest: media_set_digest(media_set)?,
+            })
+        })
+        .collect()
+}
+
+/// Changes needed to bring the standby catalog up to date
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StandbyDelta {
+    /// Media sets which are new or changed on the primary
+    pub fetch: Vec<String>,
+    /// Media sets which no longer exist on the primary
+    pub remove: Vec<String>,
+}
+
+impl StandbyDelta {
+    pub fn is_empty(&self) -> bool {
+        self.fetch.is_empty() && self.remove.is_empty()
+    }
+}
+
+/// Compare the local catalog digests with those of the primary
+///
+/// Refuses to empty a non-empty standby catalog, an empty catalog on the
+/// primary rather means a wrong or recreated target than an empty one.
+pub fn standby_delta(
+    local: &[CloudCatalogDigest],
+    remote: &[CloudCatalogDigest],
+) -> Result<StandbyDelta, Error> {
+    if remote.is_empty() && !local.is_empty() {
+        bail!("catalog on primary is empty - refusing to remove all local media sets");
+    }
+
+    let local_map: HashMap<&str, &str> = local
+        .iter()
+        .map(|entry| (entry.uuid.as_str(), entry.digest.as_str()))
+        .collect();
+    let remote_map: HashMap<&str, &str> = remote
+        .iter()
+        .map(|entry| (entry.uuid.as_str(), entry.digest.as_str()))
+        .collect();
+
+    let fetch = remote
+        .iter()
+        .filter(|entry| local_map.get(entry.uuid.as_str()) != Some(&entry.digest.as_str()))
+        .map(|entry| entry.uuid.clone())
+        .collect();
+    let remove = local
+        .iter()
+        .filter(|entry| !remote_map.contains_key(entry.uuid.as_str()))
+        .map(|entry| entry.uuid.clone())
+        .collect();
+
+    Ok(StandbyDelta { fetch, remove })
+}
+
+/// Fetch the catalog digests of a target from the primary
+pub async fn remote_catalog_digests(
+    client: &HttpClient,
+    target: &str,
+) -> Result<Vec<CloudCatalogDigest>, Error> {
+    let path = format!("api2/json/cloud/storage/{}/catalog-digests", target);
+    let mut result = client.get(&path, None).await?;
+    Ok(serde_json::from_value(result["data"].take())?)
+}
+
+/// Fetch a media set catalog from the primary
+pub async fn remote_media_set_catalog(
+    client: &HttpClient,
+    target: &str,
+    uuid: &str,
+) -> Result<MediaSetCatalog, Error> {
+    let path = format!("api2/json/cloud/storage/{}/media-set-catalog", target);
+    let args = serde_json::json!({ "uuid": uuid });
+    let mut result = client.get(&path, Some(args)).await?;
+    let media_set: MediaSetCatalog = serde_json::from_value(result["data"].take())
+        .map_err(|err| format_err!("unable to parse catalog of media set {} - {}", uuid, err))?;
+    if media_set.uuid().to_string() != uuid {
+        bail!(
+            "got catalog of media set {} instead of {}",
+            media_set.uuid(),
+            uuid
+        );
+    }
+    Ok(media_set)
+}
+
+/// Apply a delta to the local catalog of a target
+///
+/// Changed media sets are fetched from the primary before anything is
+/// removed, so a failed sync leaves a usable catalog behind.
+pub async fn sync_standby_catalog(
+    worker: &dyn WorkerTaskContext,
+    client: &HttpClient,
+    base_path: &Path,
+    target: &str,
+    delta: &StandbyDelta,
+) -> Result<(), Error> {
+    let mut lock_path = standby_status_path(base_path, target);
+    lock_path.set_extension("lck");
+    if let Some(parent) = lock_path.parent() {
+        create_path(
+            parent,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+    }
+    let timeout = std::time::Duration::new(10, 0);
+    let _lock = open_file_locked(&lock_path, timeout, true, create_options(0o0640)?)?;
+
+    for uuid in delta.fetch.iter() {
+        worker.check_abort()?;
+        task_log!(worker, "fetch catalog of media set {}", uuid);
+        let media_set = remote_media_set_catalog(client, target, uuid).await?;
+        media_set.save(base_path, target)?;
+    }
+
+    for uuid in delta.remove.iter() {
+        task_log!(worker, "remove catalog of media set {}", uuid);
+        let uuid: Uuid = uuid.parse()?;
+        MediaSetCatalog::remove(base_path, target, &uuid)?;
+    }
+
+    Ok(())
+}
+
+fn standby_status_path(base_path: &Path, target: &str) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("standby");
+    path.push(format!("{}.json", target));
+    path
+}
+
+/// Load the standby status of a target
+pub fn load_standby_status(
+    base_path: &Path,
+    target: &str,
+) -> Result<Option<CloudStandbyStatus>, Error> {
+    let path = standby_status_path(base_path, target);
+    match proxmox_sys::fs::file_get_optional_contents(&path)? {
+        Some(data) => {
+            Ok(Some(serde_json::from_slice(&data).map_err(|err| {
+                format_err!("unable to parse {:?} - {}", path, err)
+            })?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Store the standby status of a target
+pub fn save_standby_status(
+    base_path: &Path,
+    target: &str,
+    status: &CloudStandbyStatus,
+) -> Result<(), Error> {
+    let path = standby_status_path(base_path, target);
+    if let Some(parent) = path.parent() {
+        create_path(
+            parent,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+    }
+    let data = serde_json::to_vec(status)?;
+    replace_file(path, &data, create_options(0o0640)?, true)
+}
+
+/// Record the result of a catalog check
+pub fn update_standby_status(
+    base_path: &Path,
+    target: &str,
+    remote: &str,
+    changed: bool,
+    result: &Result<(), Error>,
+) -> Result<(), Error> {
+    let mut status = load_standby_status(base_path, target)?.unwrap_or_default();
+    let now = proxmox_time::epoch_i64();
+
+    status.remote = remote.to_string();
+    match result {
+        Ok(()) => {
+            status.last_sync = Some(now);
+            status.last_error = None;
+            if changed {
+                status.last_change = Some(now);
+            }
+        }
+        Err(err) => status.last_error = Some(err.to_string()),
+    }
+    status.media_sets = CloudCatalog::load(base_path, target)?.media_sets().len() as u64;
+
+    save_standby_status(base_path, target, &status)
+}
@@ -0,0 +1,357 @@
+//! Synthetic full media sets
+//!
+//! A synthetic full is a new full media set built from the current chain
+//! on the target. Archives which are still intact are copied server-side,
+//! chunks lost to damaged archives are re-uploaded from the local
+//! datastore. Afterwards, new incremental media sets are based on the
+//! synthetic full, and the old chain can be removed.
+//!
+//! Like backups, this needs the writer lease of the target.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{CloudTarget, Fingerprint, Operation};
+use pbs_datastore::DataStore;
+
+use super::backend::{CloudBackend, PutOptions};
+use super::catalog::{
+    upload_media_set_catalog, upload_media_set_label, ChunkArchiveEntry, ChunkEntry, CloudCatalog,
+    MediaSetCatalog, MediaSetLabel, SnapshotEntry,
+};
+use super::encryption_keys::{encrypt_object, load_crypt_config};
+use super::lease::{CloudLease, LEASE_TIMEOUT};
+use super::snapshot_summary::{build_snapshot_summary, upload_snapshot_summary};
+use super::{layout, MAX_CHUNK_ARCHIVE_SIZE};
+
+/// Create a synthetic full media set from the current chain of a target
+///
+/// Snapshots which cannot be restored completely (neither from the target
+/// nor from a local datastore) are left out. Returns the new media set UUID.
+pub fn create_synthetic_full<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+) -> Result<Uuid, Error> {
+    let base_path = base_path.as_ref();
+    let catalog = CloudCatalog::load(base_path, &target.name)?;
+    let chain = catalog.current_chain();
+
+    if chain.is_empty() {
+        bail!("cloud target '{}' has no media set", target.name);
+    }
+
+    let put_options = PutOptions::default().with_object_tags(target, None)?;
+
+    task_log!(
+        worker,
+        "creating synthetic full from chain of {} media sets",
+        chain.len()
+    );
+
+    // newest snapshot entries first, so that duplicates are skipped
+    let mut snapshots: Vec<(&MediaSetCatalog, &SnapshotEntry)> = Vec::new();
+    for media_set in chain.iter().rev() {
+        for entry in media_set.snapshots.iter() {
+            if !snapshots
+                .iter()
+                .any(|(_, other)| other.matches(&entry.store, &entry.ns, &entry.snapshot))
+            {
+                snapshots.push((media_set, entry));
+            }
+        }
+    }
+    snapshots.reverse();
+
+    // chunks are stored once per encryption key
+    let needed: HashSet<(Option<Fingerprint>, [u8; 32])> = snapshots
+        .iter()
+        .flat_map(|(_, entry)| {
+            entry
+                .chunks
+                .iter()
+                .map(move |digest| (entry.key.clone(), *digest))
+        })
+        .collect();
+
+    let mut lease =
+        CloudLease::acquire(Arc::clone(backend), proxmox_sys::nodename(), LEASE_TIMEOUT)?;
+
+    let label = MediaSetLabel {
+        uuid: Uuid::generate(),
+        ctime: proxmox_time::epoch_i64(),
+        base: None,
+        node: proxmox_sys::nodename().to_string(),
+    };
+    task_log!(worker, "synthetic full media set uuid: {}", label.uuid);
+
+    upload_media_set_label(&**backend, &label)?;
+
+    let mut new_set = MediaSetCatalog::new(label);
+    let mut present: HashSet<(Option<Fingerprint>, [u8; 32])> = HashSet::new();
+
+    // copy intact archives which still contain needed chunks
+    for media_set in chain.iter() {
+        for archive in media_set.archives.iter() {
+            worker.check_abort()?;
+            lease.heartbeat()?;
+
+            if !archive.chunks.iter().any(|chunk| {
+                let id = (archive.key.clone(), chunk.digest);
+                needed.contains(&id) && !present.contains(&id)
+            }) {
+                continue;
+            }
+
+            let key = layout::chunk_archive_key(media_set.uuid(), &archive.uuid);
+            match backend.head_object(&key) {
+                Ok(Some(info)) if info.size == archive.size => {}
+                Ok(Some(info)) => {
+                    task_warn!(
+                        worker,
+                        "chunk archive {} has wrong size ({} != {}), skipping",
+                        key,
+                        info.size,
+                        archive.size
+                    );
+                    continue;
+                }
+                Ok(None) => {
+                    task_warn!(worker, "chunk archive {} is missing, skipping", key);
+                    continue;
+                }
+                Err(err) => {
+                    task_warn!(worker, "unable to stat chunk archive {} - {}", key, err);
+                    continue;
+                }
+            }
+
+            let uuid = Uuid::generate();
+            let new_key = layout::chunk_archive_key(new_set.uuid(), &uuid);
+            if let Err(err) = backend.copy_object(&key, &new_key) {
+                task_warn!(worker, "unable to copy chunk archive {} - {}", key, err);
+                continue;
+            }
+
+            for chunk in archive.chunks.iter() {
+                present.insert((archive.key.clone(), chunk.digest));
+            }
+
+            new_set.archives.push(ChunkArchiveEntry {
+                uuid,
+                store: archive.store.clone(),
+                key: archive.key.clone(),
+                size: archive.size,
+                csum: archive.csum,
+                chunks: archive.chunks.clone(),
+            });
+        }
+    }
+
+    task_log!(
+        worker,
+        "copied {} chunk archives server-side",
+        new_set.archives.len()
+    );
+
+    // re-upload chunks lost with damaged archives from the local datastores
+    let mut unavailable = HashSet::new();
+    let mut missing_by_store: HashMap<(&str, Option<Fingerprint>), Vec<[u8; 32]>> = HashMap::new();
+    let mut queued = HashSet::new();
+    for (_, entry) in snapshots.iter() {
+        for digest in entry.chunks.iter() {
+            let id = (entry.key.clone(), *digest);
+            if !present.contains(&id) && queued.insert(id) {
+                missing_by_store
+                    .entry((entry.store.as_str(), entry.key.clone()))
+                    .or_default()
+                    .push(*digest);
+            }
+        }
+    }
+
+    for ((store, key), digests) in missing_by_store {
+        task_log!(
+            worker,
+            "re-uploading {} chunks from datastore '{}'",
+            digests.len(),
+            store
+        );
+
+        let datastore = match DataStore::lookup_datastore(store, Some(Operation::Read)) {
+            Ok(datastore) => datastore,
+            Err(err) => {
+                task_warn!(worker, "unable to open datastore '{}' - {}", store, err);
+                unavailable.extend(digests.into_iter().map(|digest| (key.clone(), digest)));
+                continue;
+            }
+        };
+
+        let crypt_config = match key {
+            Some(ref fingerprint) => match load_crypt_config(fingerprint) {
+                Ok(crypt_config) => Some(crypt_config),
+                Err(err) => {
+                    task_warn!(worker, "{}", err);
+                    unavailable.extend(digests.into_iter().map(|digest| (key.clone(), digest)));
+                    continue;
+                }
+            },
+            None => None,
+        };
+
+        let mut pending = digests.into_iter().peekable();
+        while pending.peek().is_some() {
+            worker.check_abort()?;
+            lease.heartbeat()?;
+
+            let mut data = Vec::new();
+            let mut chunks = Vec::new();
+
+            while data.len() < MAX_CHUNK_ARCHIVE_SIZE {
+                let digest = match pending.next() {
+                    Some(digest) => digest,
+                    None => break,
+                };
+                let blob = match datastore.load_chunk(&digest) {
+                    Ok(blob) => blob,
+                    Err(err) => {
+                        task_warn!(
+                            worker,
+                            "unable to load chunk {} - {}",
+                            hex::encode(digest),
+                            err
+                        );
+                        unavailable.insert((key.clone(), digest));
+                        continue;
+                    }
+                };
+                let raw = match crypt_config {
+                    Some(ref crypt_config) => encrypt_object(blob.raw_data(), crypt_config)?,
+                    None => blob.into_inner(),
+                };
+                chunks.push(ChunkEntry {
+                    digest,
+                    offset: data.len() as u64,
+                    size: raw.len() as u64,
+                });
+                data.extend_from_slice(&raw);
+            }
+
+            if chunks.is_empty() {
+                continue;
+            }
+
+            let uuid = Uuid::generate();
+            backend
+                .put_object_with_options(
+                    &layout::chunk_archive_key(new_set.uuid(), &uuid),
+                    &data,
+                    &put_options,
+                )
+                .map_err(|err| format_err!("unable to upload chunk archive - {}", err))?;
+
+            new_set.archives.push(ChunkArchiveEntry {
+                uuid,
+                store: store.to_string(),
+                key: key.clone(),
+                size: data.len() as u64,
+                csum: Some(openssl::sha::sha256(&data)),
+                chunks,
+            });
+        }
+    }
+
+    let chunk_sizes: HashMap<(Option<Fingerprint>, [u8; 32]), u64> = new_set
+        .archives
+        .iter()
+        .flat_map(|archive| {
+            archive
+                .chunks
+                .iter()
+                .map(move |chunk| ((archive.key.clone(), chunk.digest), chunk.size))
+        })
+        .collect();
+
+    // copy snapshot files of all complete snapshots
+    for (media_set, entry) in snapshots {
+        worker.check_abort()?;
+        lease.heartbeat()?;
+
+        if entry
+            .chunks
+            .iter()
+            .any(|digest| unavailable.contains(&(entry.key.clone(), *digest)))
+        {
+            task_warn!(
+                worker,
+                "snapshot {} has unavailable chunks, leaving it out",
+                entry.snapshot
+            );
+            continue;
+        }
+
+        let mut complete = true;
+        for file in entry.files.iter() {
+            let src = layout::snapshot_file_key(
+                media_set.uuid(),
+                &entry.store,
+                &entry.ns,
+                &entry.snapshot,
+                &file.filename,
+            );
+            let dst = layout::snapshot_file_key(
+                new_set.uuid(),
+                &entry.store,
+                &entry.ns,
+                &entry.snapshot,
+                &file.filename,
+            );
+            if let Err(err) = backend.copy_object(&src, &dst) {
+                task_warn!(worker, "unable to copy {} - {}", src, err);
+                complete = false;
+                break;
+            }
+        }
+
+        if !complete {
+            task_warn!(
+                worker,
+                "snapshot {} has missing files, leaving it out",
+                entry.snapshot
+            );
+            continue;
+        }
+
+        let chunk_size = entry
+            .chunks
+            .iter()
+            .map(|digest| chunk_sizes.get(&(entry.key.clone(), *digest)).copied())
+            .sum();
+        let summary = build_snapshot_summary(new_set.uuid(), entry, chunk_size);
+        upload_snapshot_summary(&**backend, new_set.uuid(), entry, &summary, &put_options)?;
+
+        new_set.snapshots.push(entry.clone());
+    }
+
+    task_log!(
+        worker,
+        "synthetic full contains {} snapshots in {} chunk archives",
+        new_set.snapshots.len(),
+        new_set.archives.len()
+    );
+
+    new_set.save(base_path, &target.name)?;
+
+    lease.renew()?;
+    upload_media_set_catalog(&**backend, &new_set)?;
+    lease.release()?;
+
+    Ok(new_set.uuid().clone())
+}
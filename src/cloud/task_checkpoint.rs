@@ -0,0 +1,196 @@
+//! Checkpoints of running cloud jobs
+//!
+//! Cloud jobs keep a checkpoint file while they run. When the proxy shuts
+//! down (stop, restart or package upgrade), workers stop at the next safe
+//! point - a backup commits the media set written so far, a replication
+//! stops between two objects - and leave the checkpoint behind. Workers
+//! killed without a chance to react leave it as well.
+//!
+//! The task scheduler picks up checkpoints whose worker is no longer
+//! running and starts the job again. Backups skip snapshots already on
+//! the target and replications skip objects already copied, so resumed
+//! jobs continue where they stopped. Jobs which cannot be resumed are
+//! dropped (see [`MAX_RESUME_ATTEMPTS`]) and run at their next scheduled
+//! time.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{create_path, replace_file};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{Authid, CloudTaskRecord};
+use proxmox_rest_server::WorkerTask;
+
+use super::create_options;
+use super::task_records::task_record;
+
+/// Give up resuming a job after this many attempts
+pub const MAX_RESUME_ATTEMPTS: u64 = 3;
+
+/// Checkpoint of a running (or interrupted) cloud job
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CloudJobCheckpoint {
+    /// Job type, e.g. 'cloud-backup-job'
+    pub job_type: String,
+    pub job_id: String,
+    /// User the job runs as
+    pub auth_id: Authid,
+    /// Worker running the job
+    pub upid: String,
+    /// Number of times the job was resumed
+    #[serde(default)]
+    pub attempts: u64,
+}
+
+fn checkpoint_dir(base_path: &Path) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("checkpoints");
+    path
+}
+
+fn checkpoint_path(base_path: &Path, job_type: &str, job_id: &str) -> PathBuf {
+    let mut path = checkpoint_dir(base_path);
+    path.push(format!("{}-{}.json", job_type, job_id));
+    path
+}
+
+impl CloudJobCheckpoint {
+    /// Record a starting job
+    ///
+    /// The resume attempts of an existing checkpoint of the same job are
+    /// kept, they are only reset when the job finishes.
+    pub fn start<P: AsRef<Path>>(
+        base_path: P,
+        job_type: &str,
+        job_id: &str,
+        auth_id: &Authid,
+        upid: &str,
+    ) -> Result<Self, Error> {
+        let base_path = base_path.as_ref();
+        let attempts = Self::load(base_path, job_type, job_id)?
+            .map(|checkpoint| checkpoint.attempts)
+            .unwrap_or(0);
+        let checkpoint = Self {
+            job_type: job_type.to_string(),
+            job_id: job_id.to_string(),
+            auth_id: auth_id.clone(),
+            upid: upid.to_string(),
+            attempts,
+        };
+        checkpoint.save(base_path)?;
+        Ok(checkpoint)
+    }
+
+    pub fn load<P: AsRef<Path>>(
+        base_path: P,
+        job_type: &str,
+        job_id: &str,
+    ) -> Result<Option<Self>, Error> {
+        let path = checkpoint_path(base_path.as_ref(), job_type, job_id);
+        match proxmox_sys::fs::file_get_optional_contents(&path)? {
+            Some(data) => serde_json::from_slice(&data)
+                .map(Some)
+                .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, base_path: P) -> Result<(), Error> {
+        let dir = checkpoint_dir(base_path.as_ref());
+        create_path(
+            &dir,
+            Some(create_options(0o0750)?),
+            Some(create_options(0o0750)?),
+        )?;
+        replace_file(
+            checkpoint_path(base_path.as_ref(), &self.job_type, &self.job_id),
+            &serde_json::to_vec(self)?,
+            create_options(0o0640)?,
+            true,
+        )
+    }
+
+    /// Remove the checkpoint of a job (finished, or not to be resumed)
+    pub fn remove<P: AsRef<Path>>(base_path: P, job_type: &str, job_id: &str) -> Result<(), Error> {
+        let path = checkpoint_path(base_path.as_ref(), job_type, job_id);
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(format_err!("unable to remove {:?} - {}", path, err)),
+        }
+    }
+}
+
+/// All checkpoints, sorted by job type and ID
+pub fn load_checkpoints<P: AsRef<Path>>(base_path: P) -> Result<Vec<CloudJobCheckpoint>, Error> {
+    let dir = checkpoint_dir(base_path.as_ref());
+    let mut list = Vec::new();
+
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(list),
+        Err(err) => return Err(format_err!("unable to read {:?} - {}", dir, err)),
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        let data = std::fs::read(&path)?;
+        match serde_json::from_slice::<CloudJobCheckpoint>(&data) {
+            Ok(checkpoint) => list.push(checkpoint),
+            Err(err) => log::error!("ignoring invalid checkpoint {:?} - {}", path, err),
+        }
+    }
+
+    list.sort_by(|a, b| (&a.job_type, &a.job_id).cmp(&(&b.job_type, &b.job_id)));
+
+    Ok(list)
+}
+
+/// Run a job with a checkpoint
+///
+/// The checkpoint is removed when the job is done, unless it failed
+/// because of a shutdown.
+pub fn run_with_checkpoint<P, F>(
+    worker: &WorkerTask,
+    base_path: P,
+    job_type: &str,
+    job_id: &str,
+    auth_id: &Authid,
+    func: F,
+) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    F: FnOnce() -> Result<(), Error>,
+{
+    let base_path = base_path.as_ref();
+
+    let upid = worker.upid().to_string();
+    let attempts = match CloudJobCheckpoint::start(base_path, job_type, job_id, auth_id, &upid) {
+        Ok(checkpoint) => checkpoint.attempts,
+        Err(err) => {
+            task_warn!(worker, "unable to write job checkpoint - {}", err);
+            0
+        }
+    };
+    task_record(worker, CloudTaskRecord::new("job-start").retries(attempts));
+
+    let result = func();
+
+    if result.is_err() && worker.shutdown_requested() {
+        task_log!(
+            worker,
+            "job interrupted by shutdown, it is resumed after restart"
+        );
+    } else if let Err(err) = CloudJobCheckpoint::remove(base_path, job_type, job_id) {
+        task_warn!(worker, "unable to remove job checkpoint - {}", err);
+    }
+
+    result
+}
@@ -0,0 +1,135 @@
+//! Structured records of cloud tasks
+//!
+//! Besides the plain text task log, cloud workers write records with fixed
+//! fields (phase, object key, bytes, retry count) for each uploaded object
+//! and job start, so long task logs can be parsed by tools (see
+//! `api2/cloud/tasks/{upid}/log?format=json`).
+//!
+//! Records are stored as JSON lines per task, and removed together with
+//! the task log on log rotation.
+
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Error};
+
+use proxmox_sys::fs::create_path;
+use proxmox_sys::task_warn;
+
+use pbs_api_types::CloudTaskRecord;
+use proxmox_rest_server::WorkerTask;
+
+use super::create_options;
+use super::CLOUD_STATUS_DIR;
+
+fn records_dir(base_path: &Path) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("task-records");
+    path
+}
+
+fn records_path(base_path: &Path, upid: &str) -> PathBuf {
+    let mut path = records_dir(base_path);
+    path.push(format!("{}.json", upid));
+    path
+}
+
+/// Append a record to the records of task `upid`
+pub fn append_task_record<P: AsRef<Path>>(
+    base_path: P,
+    upid: &str,
+    record: &CloudTaskRecord,
+) -> Result<(), Error> {
+    let dir = records_dir(base_path.as_ref());
+    create_path(
+        &dir,
+        Some(create_options(0o0750)?),
+        Some(create_options(0o0750)?),
+    )?;
+
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+
+    let path = records_path(base_path.as_ref(), upid);
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|err| format_err!("unable to open {:?} - {}", path, err))?;
+    // a single write, so concurrent writers never interleave lines
+    file.write_all(&line)?;
+
+    Ok(())
+}
+
+/// Read the records of task `upid`, oldest first
+///
+/// Tasks without records (e.g. not a cloud task) have an empty list.
+pub fn read_task_records<P: AsRef<Path>>(
+    base_path: P,
+    upid: &str,
+) -> Result<Vec<CloudTaskRecord>, Error> {
+    let path = records_path(base_path.as_ref(), upid);
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(format_err!("unable to open {:?} - {}", path, err)),
+    };
+
+    let mut list = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        // the last line may be incomplete while the task writes it
+        if let Ok(record) = serde_json::from_str(&line) {
+            list.push(record);
+        }
+    }
+    Ok(list)
+}
+
+/// Remove the records of all tasks for which `keep` returns false
+///
+/// Returns the number of removed record files.
+pub fn prune_task_records<P, F>(base_path: P, keep: F) -> Result<usize, Error>
+where
+    P: AsRef<Path>,
+    F: Fn(&str) -> bool,
+{
+    let dir = records_dir(base_path.as_ref());
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(format_err!("unable to read {:?} - {}", dir, err)),
+    };
+
+    let mut removed = 0;
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let upid = match file_name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".json"))
+        {
+            Some(upid) => upid,
+            None => continue,
+        };
+        if keep(upid) {
+            continue;
+        }
+        std::fs::remove_file(entry.path())?;
+        removed += 1;
+    }
+
+    Ok(removed)
+}
+
+/// Record a step of a worker task
+///
+/// Records are an addition to the task log, failing to write one only
+/// produces a warning.
+pub fn task_record(worker: &WorkerTask, record: CloudTaskRecord) {
+    let upid = worker.upid().to_string();
+    if let Err(err) = append_task_record(CLOUD_STATUS_DIR, &upid, &record) {
+        task_warn!(worker, "unable to write task record - {}", err);
+    }
+}
@@ -0,0 +1,98 @@
+// Access log analysis tests
+//
+// # cargo test --release cloud::test::access_log
+
+use anyhow::Error;
+
+use crate::cloud::access_log::{load_access_anomalies, parse_access_log_line, scan_access_logs};
+use crate::cloud::backend::{CloudBackend, MockCloudBackend};
+
+use super::harness::{create_testdir, test_target, TestWorker};
+
+fn log_line(operation: &str, key: &str, user_agent: &str) -> String {
+    format!(
+        "79a5 bucket1 [06/Feb/2019:00:00:38 +0000] 192.0.2.3 arn:aws:iam::1:user/x 3E57 \
+         {} {} \"GET /bucket1/{} HTTP/1.1\" 200 - 113 113 7 6 \"-\" \"{}\" - abc= SigV4 \
+         ECDHE-RSA-AES128-GCM-SHA256 AuthHeader bucket1.s3.amazonaws.com TLSv1.2 - -",
+        operation, key, key, user_agent
+    )
+}
+
+#[test]
+fn test_parse_access_log_line() -> Result<(), Error> {
+    let record = parse_access_log_line(&log_line(
+        "REST.GET.OBJECT",
+        "pbs/media-sets/a%3Ab",
+        "curl/7.88.1",
+    ))?;
+
+    assert_eq!(
+        record.time,
+        proxmox_time::parse_rfc3339("2019-02-06T00:00:38Z")?
+    );
+    assert_eq!(record.remote_ip, "192.0.2.3");
+    assert_eq!(record.operation, "REST.GET.OBJECT");
+    assert_eq!(record.key.as_deref(), Some("pbs/media-sets/a:b"));
+    assert_eq!(record.status, 200);
+    assert_eq!(record.user_agent.as_deref(), Some("curl/7.88.1"));
+
+    assert!(parse_access_log_line("79a5 bucket1 [06/Feb/2019").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_scan_access_logs() -> Result<(), Error> {
+    let testdir = create_testdir("test_scan_access_logs")?;
+    let worker = TestWorker::default();
+    let backend = MockCloudBackend::new();
+
+    let mut target = test_target("test");
+    target.config.prefix = Some("pbs".to_string());
+    target.config.access_log_prefix = Some("logs".to_string());
+
+    let log = [
+        log_line(
+            "REST.GET.OBJECT",
+            "pbs/media-sets/1/catalog.json",
+            "proxmox-backup-server/3.1.2 (node1)",
+        ),
+        log_line(
+            "REST.GET.OBJECT",
+            "pbs/media-sets/1/catalog.json",
+            "curl/7.88.1",
+        ),
+        log_line("REST.PUT.OBJECT", "pbs/logs/2019-02-06-00-00-00-1", "-"),
+        log_line("REST.GET.OBJECT", "other/data", "curl/7.88.1"),
+        log_line("REST.GET.BUCKET", "-", "curl/7.88.1"),
+    ]
+    .join("\n");
+    backend.put_object("logs/2019-02-06-00-00-00-1", log.as_bytes())?;
+
+    let found = scan_access_logs(&worker, &testdir, &target, &backend)?;
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].key, "media-sets/1/catalog.json");
+    assert_eq!(found[0].log, "logs/2019-02-06-00-00-00-1");
+
+    // logs are only scanned once
+    assert!(scan_access_logs(&worker, &testdir, &target, &backend)?.is_empty());
+
+    let log = log_line("REST.DELETE.OBJECT", "pbs/chunks/1", "aws-cli/2.0");
+    backend.put_object("logs/2019-02-06-01-00-00-2", log.as_bytes())?;
+    assert_eq!(
+        scan_access_logs(&worker, &testdir, &target, &backend)?.len(),
+        1
+    );
+
+    let list = load_access_anomalies(&testdir, "test")?;
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0].operation, "REST.DELETE.OBJECT");
+
+    // a custom user agent is matched exactly
+    target.config.user_agent = Some("pbs-node1".to_string());
+    let log = log_line("REST.GET.OBJECT", "pbs/chunks/2", "pbs-node1");
+    backend.put_object("logs/2019-02-06-02-00-00-3", log.as_bytes())?;
+    assert!(scan_access_logs(&worker, &testdir, &target, &backend)?.is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,100 @@
+// Catalog export tests
+//
+// # cargo test --release cloud::test::catalog_export
+
+use anyhow::Error;
+
+use crate::cloud::backend::CloudBackend;
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::catalog_export::{
+    export_catalog, import_catalog, parse_catalog_export, CATALOG_EXPORT_VERSION,
+};
+use crate::cloud::layout;
+
+use super::harness::{create_testdir, digest, TestTarget};
+
+#[test]
+fn test_export_import() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_catalog_export")?);
+    let import_dir = create_testdir("test_catalog_import")?;
+
+    let full = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    target.write_media_set(
+        Some(full.uuid()),
+        &[digest(3)],
+        &[("host/a/2020-01-02T00:00:00Z", vec![digest(2), digest(3)])],
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let data = serde_json::to_vec(&export_catalog(&catalog))?;
+
+    let export = parse_catalog_export(&data)?;
+    assert_eq!(export.version, CATALOG_EXPORT_VERSION);
+    assert_eq!(export.target, "test");
+    assert_eq!(export.media_sets.len(), 2);
+
+    let result = import_catalog(&import_dir, "copy", &*target.backend, &export, false)?;
+    assert_eq!(result.imported, 2);
+    assert_eq!(result.skipped, 0);
+
+    let imported = CloudCatalog::load(&import_dir, "copy")?;
+    assert_eq!(imported.media_sets().len(), 2);
+    assert_eq!(imported.current_chain().len(), 2);
+    assert_eq!(imported.snapshots().count(), 2);
+    assert_eq!(
+        imported.find_chunk(&digest(3)),
+        catalog.find_chunk(&digest(3))
+    );
+
+    // existing media sets are only replaced with 'overwrite'
+    let result = import_catalog(&import_dir, "copy", &*target.backend, &export, false)?;
+    assert_eq!(result.imported, 0);
+    assert_eq!(result.skipped, 2);
+
+    let result = import_catalog(&import_dir, "copy", &*target.backend, &export, true)?;
+    assert_eq!(result.imported, 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_import_checks() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_catalog_import_checks")?);
+    let import_dir = create_testdir("test_catalog_import_checks_dest")?;
+
+    let media_set = target.write_media_set(
+        None,
+        &[digest(1)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1)])],
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let mut export = export_catalog(&catalog);
+
+    // newer export versions are rejected
+    export.version = CATALOG_EXPORT_VERSION + 1;
+    assert!(parse_catalog_export(&serde_json::to_vec(&export)?).is_err());
+    assert!(parse_catalog_export(b"{\"media-sets\": []}").is_err());
+    export.version = CATALOG_EXPORT_VERSION;
+
+    // duplicate media sets
+    let mut duplicate = export.clone();
+    duplicate.media_sets.push(media_set.clone());
+    assert!(import_catalog(&import_dir, "copy", &*target.backend, &duplicate, false).is_err());
+
+    // media sets have to exist on the target
+    target
+        .backend
+        .delete_object(&layout::media_set_label_key(media_set.uuid()))?;
+    assert!(import_catalog(&import_dir, "copy", &*target.backend, &export, false).is_err());
+
+    assert!(CloudCatalog::load(&import_dir, "copy")?
+        .media_sets()
+        .is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,163 @@
+// S3 event notification ingestion tests
+//
+// # cargo test --release cloud::test::change_feed
+
+use anyhow::Error;
+use serde_json::json;
+
+use crate::cloud::catalog::MediaSetCatalog;
+use crate::cloud::change_feed::{
+    apply_changes, catalog_media_set, parse_notification, ChangeFeedStats, ObjectChange,
+};
+use crate::cloud::layout;
+
+use super::harness::{create_testdir, digest, TestTarget, TestWorker};
+
+fn s3_record(event_name: &str, bucket: &str, key: &str) -> serde_json::Value {
+    json!({
+        "eventVersion": "2.1",
+        "eventSource": "aws:s3",
+        "eventName": event_name,
+        "s3": {
+            "bucket": { "name": bucket },
+            "object": { "key": key },
+        },
+    })
+}
+
+#[test]
+fn test_parse_notification() -> Result<(), Error> {
+    let body = json!({
+        "Records": [
+            s3_record("ObjectCreated:Put", "bucket1", "pbs/media-set/a%2Bb/catalog+1.json"),
+            s3_record("ObjectRemoved:Delete", "bucket1", "pbs/chunks/x"),
+            s3_record("ObjectRemoved:Delete", "bucket1", "other/chunks/x"),
+            s3_record("ObjectCreated:Put", "bucket2", "pbs/chunks/y"),
+            s3_record("ObjectRestore:Completed", "bucket1", "pbs/chunks/z"),
+        ],
+    })
+    .to_string();
+
+    assert_eq!(
+        parse_notification(&body, "bucket1", Some("pbs"))?,
+        vec![
+            ObjectChange {
+                key: "media-set/a+b/catalog 1.json".to_string(),
+                removed: false,
+            },
+            ObjectChange {
+                key: "chunks/x".to_string(),
+                removed: true,
+            },
+        ]
+    );
+    assert_eq!(parse_notification(&body, "bucket1", None)?.len(), 3);
+
+    // delivered through an SNS topic
+    let envelope = json!({
+        "Type": "Notification",
+        "TopicArn": "arn:aws:sns:eu-central-1:123456789012:bucket1-events",
+        "Message": body,
+    })
+    .to_string();
+    assert_eq!(
+        parse_notification(&envelope, "bucket1", Some("pbs"))?.len(),
+        2
+    );
+
+    let test_event = json!({ "Service": "Amazon S3", "Event": "s3:TestEvent" }).to_string();
+    assert!(parse_notification(&test_event, "bucket1", None)?.is_empty());
+
+    assert!(parse_notification("not json", "bucket1", None).is_err());
+    assert!(parse_notification("{}", "bucket1", None).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_apply_changes() -> Result<(), Error> {
+    let testdir = create_testdir("test_apply_changes")?;
+    let worker = TestWorker::default();
+    let mut target = TestTarget::new(testdir);
+    let backend = target.backend();
+
+    let media_set = target.write_media_set(None, &[digest(1)], &[])?;
+    let uuid = media_set.uuid().clone();
+    let key = layout::media_set_catalog_key(&uuid);
+    assert_eq!(catalog_media_set(&key), Some(uuid.clone()));
+    assert_eq!(catalog_media_set(&layout::media_set_label_key(&uuid)), None);
+
+    // written by another node, not yet known locally
+    MediaSetCatalog::remove(&target.base_path, &target.target.name, &uuid)?;
+
+    let created = [
+        ObjectChange {
+            key: key.clone(),
+            removed: false,
+        },
+        ObjectChange {
+            key: layout::media_set_label_key(&uuid),
+            removed: false,
+        },
+    ];
+    let mut stats = ChangeFeedStats::default();
+    apply_changes(
+        &worker,
+        &target.base_path,
+        &target.target.name,
+        &*backend,
+        &created,
+        &mut stats,
+    )?;
+    assert_eq!(
+        stats,
+        ChangeFeedStats {
+            updated: 1,
+            removed: 0,
+            unchanged: 0,
+        }
+    );
+    assert_eq!(
+        MediaSetCatalog::load(&target.base_path, &target.target.name, &uuid)?.uuid(),
+        &uuid
+    );
+
+    // duplicate delivery
+    apply_changes(
+        &worker,
+        &target.base_path,
+        &target.target.name,
+        &*backend,
+        &created,
+        &mut stats,
+    )?;
+    assert_eq!(stats.updated, 1);
+    assert_eq!(stats.unchanged, 1);
+
+    // a stale "created" event after the removal removes the catalog as well
+    backend.delete_object(&key)?;
+    apply_changes(
+        &worker,
+        &target.base_path,
+        &target.target.name,
+        &*backend,
+        &created,
+        &mut stats,
+    )?;
+    assert_eq!(stats.removed, 1);
+    assert!(MediaSetCatalog::load(&target.base_path, &target.target.name, &uuid).is_err());
+
+    let removed = [ObjectChange { key, removed: true }];
+    apply_changes(
+        &worker,
+        &target.base_path,
+        &target.target.name,
+        &*backend,
+        &removed,
+        &mut stats,
+    )?;
+    assert_eq!(stats.removed, 1);
+    assert_eq!(stats.unchanged, 2);
+
+    Ok(())
+}
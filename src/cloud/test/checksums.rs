@@ -0,0 +1,64 @@
+// Checksum listing tests
+//
+// # cargo test --release cloud::test::checksums
+
+use anyhow::Error;
+
+use crate::cloud::backend::CloudBackend;
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::checksums::snapshot_checksums;
+
+use super::harness::{chunk_data, create_testdir, digest, TestTarget};
+
+#[test]
+fn test_snapshot_checksums() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_snapshot_checksums")?);
+
+    let full = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    target.write_media_set(
+        Some(full.uuid()),
+        &[digest(3)],
+        &[("host/a/2020-01-02T00:00:00Z", vec![digest(1), digest(3)])],
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let (media_set, entry) = catalog
+        .snapshots()
+        .find(|(_, entry)| entry.snapshot.to_string() == "host/a/2020-01-02T00:00:00Z")
+        .unwrap();
+
+    let list = snapshot_checksums(&catalog, media_set, entry)?;
+    assert_eq!(list.snapshot, "store1:host/a/2020-01-02T00:00:00Z");
+    assert!(!list.encrypted);
+
+    // files can be verified with the listed checksum
+    assert_eq!(list.files.len(), 1);
+    let data = target.backend.get_object(&list.files[0].key)?;
+    assert_eq!(hex::encode(openssl::sha::sha256(&data)), list.files[0].csum);
+
+    // chunks reference archives of both media sets
+    assert_eq!(list.archives.len(), 2);
+    for archive in list.archives.iter() {
+        let data = target.backend.get_object(&archive.key)?;
+        assert_eq!(data.len() as u64, archive.size);
+        assert_eq!(
+            archive.csum.as_deref(),
+            Some(hex::encode(openssl::sha::sha256(&data)).as_str())
+        );
+    }
+
+    assert_eq!(list.chunks.len(), 2);
+    for (chunk, digest) in list.chunks.iter().zip([digest(1), digest(3)]) {
+        assert_eq!(chunk.digest, hex::encode(digest));
+        let data = target
+            .backend
+            .get_object_range(&chunk.archive, chunk.offset, chunk.size)?;
+        assert_eq!(data, chunk_data(&digest));
+    }
+
+    Ok(())
+}
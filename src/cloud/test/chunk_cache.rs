@@ -0,0 +1,148 @@
+// Chunk cache tests
+//
+// # cargo test --release cloud::test::chunk_cache
+
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::chunk_cache::{load_cache_status, prewarm_chunk_cache, ChunkCache};
+use crate::cloud::chunk_reader::CloudChunkReader;
+use crate::cloud::popularity::ChunkPopularity;
+
+use super::harness::{chunk_data, create_testdir, digest, TestTarget, TestWorker};
+
+#[test]
+fn test_chunk_cache_eviction() -> Result<(), Error> {
+    let testdir = create_testdir("test_chunk_cache_eviction")?;
+
+    // room for two chunks
+    let data = vec![0u8; 100];
+    let cache = ChunkCache::open(&testdir, 200)?;
+
+    cache.insert(&digest(1), &data)?;
+    cache.insert(&digest(2), &data)?;
+    assert_eq!(cache.get(&digest(1)), Some(data.clone()));
+
+    // digest 2 is the least recently used one
+    cache.insert(&digest(3), &data)?;
+    assert!(cache.contains(&digest(1)));
+    assert!(!cache.contains(&digest(2)));
+    assert!(cache.contains(&digest(3)));
+    assert_eq!(cache.get(&digest(2)), None);
+
+    // chunks larger than the cache are not stored
+    cache.insert(&digest(4), &vec![0u8; 300])?;
+    assert!(!cache.contains(&digest(4)));
+
+    let status = cache.status();
+    assert_eq!(status.size, 200);
+    assert_eq!(status.entries, 2);
+    assert_eq!(status.hits, 1);
+    assert_eq!(status.misses, 1);
+    assert_eq!(status.evictions, 1);
+    assert_eq!(status.hit_rate, Some(0.5));
+
+    // shrinking evicts
+    cache.set_max_size(100)?;
+    assert_eq!(cache.status().entries, 1);
+    assert!(cache.contains(&digest(3)));
+
+    Ok(())
+}
+
+#[test]
+fn test_chunk_cache_reopen() -> Result<(), Error> {
+    let testdir = create_testdir("test_chunk_cache_reopen")?;
+
+    let cache = ChunkCache::open(&testdir, 1000)?;
+    cache.insert(&digest(1), b"first")?;
+    cache.insert(&digest(2), b"second")?;
+    assert!(cache.get(&digest(1)).is_some());
+    cache.flush()?;
+
+    let status = load_cache_status(&testdir)?.unwrap();
+    assert_eq!(status.entries, 2);
+    assert_eq!(status.hits, 1);
+
+    // chunks and counters survive, a smaller cache is trimmed
+    let cache = ChunkCache::open(&testdir, 6)?;
+    let status = cache.status();
+    assert_eq!(status.entries, 1);
+    assert_eq!(status.hits, 1);
+    assert_eq!(status.evictions, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_chunk_reader_uses_cache() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_chunk_reader_uses_cache")?);
+
+    target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+
+    let mut cache_dir = target.base_path.clone();
+    cache_dir.push("chunk-cache");
+    let cache = Arc::new(ChunkCache::open(&cache_dir, 1024 * 1024)?);
+
+    let catalog = Arc::new(CloudCatalog::load(&target.base_path, "test")?);
+    let reader = CloudChunkReader::new(target.backend(), catalog, None)?
+        .with_cache(Some(Arc::clone(&cache)));
+
+    assert_eq!(reader.fetch_chunk(&digest(1))?, chunk_data(&digest(1)));
+    assert_eq!(reader.fetch_chunk(&digest(1))?, chunk_data(&digest(1)));
+    reader.finish()?;
+
+    // only the first read was a download
+    let popularity = ChunkPopularity::load(&target.base_path, "test")?;
+    let score = popularity.score(&digest(1), proxmox_time::epoch_i64());
+    assert!(score > 0.5 && score <= 1.0);
+
+    let status = load_cache_status(&cache_dir)?.unwrap();
+    assert_eq!(status.entries, 1);
+    assert_eq!(status.hits, 1);
+    assert_eq!(status.misses, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_chunk_cache_prewarm() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_chunk_cache_prewarm")?);
+    let worker = TestWorker::default();
+
+    let full = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    target.write_media_set(
+        Some(full.uuid()),
+        &[digest(3)],
+        &[("host/a/2020-01-02T00:00:00Z", vec![digest(1), digest(3)])],
+    )?;
+
+    let mut cache_dir = target.base_path.clone();
+    cache_dir.push("chunk-cache");
+    let cache = ChunkCache::open(&cache_dir, 1024 * 1024)?;
+
+    let catalog = Arc::new(CloudCatalog::load(&target.base_path, "test")?);
+    let reader =
+        CloudChunkReader::new(target.backend(), Arc::clone(&catalog), None)?.with_cache(None);
+
+    // chunks of the newest snapshot only
+    assert_eq!(prewarm_chunk_cache(&worker, &cache, &reader, &catalog)?, 2);
+    assert!(cache.contains(&digest(1)));
+    assert!(!cache.contains(&digest(2)));
+    assert_eq!(cache.get(&digest(3)), Some(chunk_data(&digest(3))));
+
+    // nothing left to download
+    assert_eq!(prewarm_chunk_cache(&worker, &cache, &reader, &catalog)?, 0);
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+// Parallel chunk download tests
+//
+// # cargo test --release cloud::test::chunk_download
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::chunk_download::ChunkDownloader;
+use crate::cloud::chunk_reader::CloudChunkReader;
+
+use super::harness::{chunk_data, create_testdir, digest, TestTarget, TestWorker};
+
+fn setup_reader(name: &str) -> Result<(TestTarget, Arc<CloudChunkReader>), Error> {
+    let mut target = TestTarget::new(create_testdir(name)?);
+
+    let chunks: Vec<[u8; 32]> = (1..=20).map(digest).collect();
+    target.write_media_set(
+        None,
+        &chunks,
+        &[("host/a/2020-01-01T00:00:00Z", chunks.clone())],
+    )?;
+
+    let catalog = Arc::new(CloudCatalog::load(&target.base_path, "test")?);
+    let reader = Arc::new(CloudChunkReader::new(target.backend(), catalog, None)?);
+
+    Ok((target, reader))
+}
+
+fn check_data(digest: &[u8; 32], data: &[u8]) -> Result<(), Error> {
+    if data != chunk_data(digest) {
+        bail!("wrong data for chunk {}", hex::encode(digest));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parallel_download() -> Result<(), Error> {
+    let (_target, reader) = setup_reader("test_parallel_download")?;
+    let worker = TestWorker::default();
+
+    let digests: Vec<[u8; 32]> = (1..=20).map(digest).collect();
+
+    let mut inserted = BTreeMap::new();
+    let stats = ChunkDownloader::new(4).download(
+        &worker,
+        &reader,
+        &digests,
+        check_data,
+        |digest, data| {
+            inserted.insert(*digest, data);
+            Ok(())
+        },
+    )?;
+
+    assert_eq!(stats.chunks, 20);
+    assert_eq!(
+        stats.bytes,
+        digests
+            .iter()
+            .map(|d| chunk_data(d).len() as u64)
+            .sum::<u64>()
+    );
+    assert_eq!(inserted.len(), 20);
+    for digest in digests.iter() {
+        assert_eq!(inserted[digest], chunk_data(digest));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_staged_download() -> Result<(), Error> {
+    let (target, reader) = setup_reader("test_staged_download")?;
+    let worker = TestWorker::default();
+
+    let mut staging_dir = target.base_path.clone();
+    staging_dir.push("staging");
+    std::fs::create_dir_all(&staging_dir)?;
+
+    let digests = vec![digest(3), digest(5), digest(7)];
+
+    let mut inserted = Vec::new();
+    ChunkDownloader::new(2)
+        .with_staging_dir(staging_dir.clone())
+        .download(&worker, &reader, &digests, check_data, |digest, data| {
+            assert_eq!(data, chunk_data(digest));
+            inserted.push(*digest);
+            Ok(())
+        })?;
+
+    inserted.sort();
+    assert_eq!(inserted, digests);
+
+    // staged chunks are removed after insertion
+    assert_eq!(std::fs::read_dir(&staging_dir)?.count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_download_verify_failure() -> Result<(), Error> {
+    let (target, reader) = setup_reader("test_download_verify_failure")?;
+    let worker = TestWorker::default();
+
+    let mut staging_dir = target.base_path.clone();
+    staging_dir.push("staging");
+    std::fs::create_dir_all(&staging_dir)?;
+
+    let digests: Vec<[u8; 32]> = (1..=10).map(digest).collect();
+
+    let mut inserted = 0;
+    let result = ChunkDownloader::new(3)
+        .with_staging_dir(staging_dir.clone())
+        .download(
+            &worker,
+            &reader,
+            &digests,
+            |digest, _data| {
+                if digest[0] == 4 {
+                    bail!("verification failed");
+                }
+                Ok(())
+            },
+            |_digest, _data| {
+                inserted += 1;
+                Ok(())
+            },
+        );
+
+    assert!(result.is_err());
+    // nothing of the failed batch is inserted, and nothing is left behind
+    assert_eq!(inserted, 0);
+    assert_eq!(std::fs::read_dir(&staging_dir)?.count(), 0);
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+// Cloud error classification tests
+//
+// # cargo test --release cloud::test::cloud_error
+
+use anyhow::{format_err, Error};
+use hyper::StatusCode;
+
+use proxmox_router::HttpError;
+
+use crate::cloud::backend::{
+    cloud_error, error_message, into_http_error, is_transient_error, provider_error, CloudBackend,
+    CloudError, MockCloudBackend, MockFaults,
+};
+use crate::cloud::job_errors::SnapshotError;
+
+#[test]
+fn test_from_response() -> Result<(), Error> {
+    let classify = |status, code| CloudError::from_response(status, code, "failed".to_string());
+
+    assert_eq!(
+        classify(StatusCode::FORBIDDEN, Some("AccessDenied")),
+        CloudError::Auth("failed".to_string())
+    );
+    assert_eq!(
+        classify(StatusCode::NOT_FOUND, Some("NoSuchKey")),
+        CloudError::NotFound("failed".to_string())
+    );
+    assert_eq!(
+        classify(StatusCode::PRECONDITION_FAILED, None),
+        CloudError::Conflict("failed".to_string())
+    );
+    assert_eq!(
+        classify(StatusCode::SERVICE_UNAVAILABLE, Some("SlowDown")),
+        CloudError::Throttled("failed".to_string())
+    );
+    assert_eq!(
+        classify(StatusCode::BAD_REQUEST, Some("BadDigest")),
+        CloudError::Integrity("failed".to_string())
+    );
+    assert_eq!(
+        classify(StatusCode::INTERNAL_SERVER_ERROR, Some("InternalError")),
+        CloudError::Provider {
+            code: "InternalError".to_string(),
+            message: "failed".to_string(),
+        }
+    );
+
+    assert!(!classify(StatusCode::FORBIDDEN, None).is_transient());
+    assert!(classify(StatusCode::INTERNAL_SERVER_ERROR, Some("InternalError")).is_transient());
+    assert!(!classify(StatusCode::BAD_REQUEST, Some("InvalidArgument")).is_transient());
+
+    Ok(())
+}
+
+#[test]
+fn test_backend_errors() -> Result<(), Error> {
+    let backend = MockCloudBackend::with_faults(MockFaults {
+        throttle_every: Some(2),
+        ..Default::default()
+    });
+
+    backend.put_object("a", b"1")?;
+    let err = backend.put_object("b", b"2").unwrap_err();
+    assert!(matches!(cloud_error(&err), Some(CloudError::Throttled(_))));
+    assert!(is_transient_error(&err));
+
+    let err = backend.get_object("missing").unwrap_err();
+    assert!(matches!(cloud_error(&err), Some(CloudError::NotFound(_))));
+    assert!(!is_transient_error(&err));
+
+    // the class survives added context
+    let err = backend.get_object("missing").unwrap_err();
+    let err = err.context("unable to load catalog");
+    assert!(matches!(cloud_error(&err), Some(CloudError::NotFound(_))));
+
+    // errors of other origin
+    let err = format_err!("local I/O error");
+    assert_eq!(cloud_error(&err), None);
+    assert!(is_transient_error(&err));
+
+    Ok(())
+}
+
+#[test]
+fn test_http_status() -> Result<(), Error> {
+    let backend = MockCloudBackend::new();
+
+    let err = into_http_error(backend.get_object("missing").unwrap_err());
+    let http_err = err.downcast_ref::<HttpError>().unwrap();
+    assert_eq!(http_err.code, StatusCode::NOT_FOUND);
+    assert!(http_err.message.contains("missing"));
+
+    let err = into_http_error(format_err!("other error"));
+    assert!(err.downcast_ref::<HttpError>().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_provider_request_id() -> Result<(), Error> {
+    let backend = MockCloudBackend::with_faults(MockFaults {
+        fail_every: Some(1),
+        ..Default::default()
+    });
+
+    let err = backend.put_object("a", b"1").unwrap_err();
+    let provider_err = provider_error(&err).unwrap();
+    assert_eq!(provider_err.code.as_deref(), Some("InternalError"));
+    let request_id = provider_err.request_id.clone().unwrap();
+    assert!(err.to_string().contains(&request_id));
+
+    // still classified, and the ID survives added context
+    assert!(matches!(
+        cloud_error(&err),
+        Some(CloudError::Provider { .. })
+    ));
+    let err = err.context("upload of chunk failed");
+    assert!(!err.to_string().contains(&request_id));
+    assert!(error_message(&err).starts_with("upload of chunk failed"));
+    assert!(error_message(&err).contains(&request_id));
+
+    let snapshot_err = SnapshotError::new("vm/100/2020-01-01T00:00:00Z", &err);
+    assert_eq!(snapshot_err.code.as_deref(), Some("InternalError"));
+    assert_eq!(snapshot_err.request_id, Some(request_id));
+
+    // no request IDs for other errors
+    let err = MockCloudBackend::new().get_object("missing").unwrap_err();
+    assert!(provider_error(&err).is_none());
+    assert_eq!(error_message(&err), err.to_string());
+
+    Ok(())
+}
@@ -0,0 +1,126 @@
+// Datastore to cloud target mapping tests
+//
+// # cargo test --release cloud::test::cloud_mapping
+
+use anyhow::Error;
+
+use pbs_api_types::{
+    find_cloud_mapping, BackupNamespace, CloudBackupJobSetup, CloudMapping, Fingerprint,
+};
+
+use crate::cloud::mapping::{apply_cloud_mapping, check_mapping_prefix, select_job_mapping};
+
+use super::harness::test_target;
+
+const KEY1: &str =
+    "01:23:45:67:89:ab:cd:ef:01:23:45:67:89:ab:cd:ef:01:23:45:67:89:ab:cd:ef:01:23:45:67:89:ab:cd:ef";
+const KEY2: &str =
+    "fe:dc:ba:98:76:54:32:10:fe:dc:ba:98:76:54:32:10:fe:dc:ba:98:76:54:32:10:fe:dc:ba:98:76:54:32:10";
+
+fn mapping(id: &str, store: &str, ns: Option<&str>, target: &str) -> CloudMapping {
+    CloudMapping {
+        id: id.to_string(),
+        store: store.to_string(),
+        ns: ns.map(|ns| BackupNamespace::new(ns).unwrap()),
+        target: target.to_string(),
+        prefix: None,
+        key: None,
+        comment: None,
+    }
+}
+
+fn job_setup(
+    store: &str,
+    ns: Option<&str>,
+    target: Option<&str>,
+) -> Result<CloudBackupJobSetup, Error> {
+    let mut setup: CloudBackupJobSetup = serde_json::from_value(serde_json::json!({
+        "store": store,
+    }))?;
+    setup.ns = ns.map(BackupNamespace::new).transpose()?;
+    setup.target = target.map(String::from);
+    Ok(setup)
+}
+
+#[test]
+fn test_find_mapping() -> Result<(), Error> {
+    let mappings = vec![
+        mapping("store1", "store1", None, "s3"),
+        mapping("store1-prod", "store1", Some("prod"), "offsite"),
+        mapping("store2", "store2", None, "s3"),
+    ];
+
+    let find = |store, ns| {
+        find_cloud_mapping(&mappings, store, &BackupNamespace::new(ns).unwrap())
+            .map(|mapping| mapping.id.as_str())
+    };
+
+    assert_eq!(find("store1", ""), Some("store1"));
+    assert_eq!(find("store1", "test"), Some("store1"));
+    assert_eq!(find("store1", "prod"), Some("store1-prod"));
+    assert_eq!(find("store1", "prod/db"), Some("store1-prod"));
+    assert_eq!(find("store2", "prod"), Some("store2"));
+    assert_eq!(find("store3", ""), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_job_mapping() -> Result<(), Error> {
+    let mappings = vec![
+        mapping("store1", "store1", None, "s3"),
+        mapping("store1-prod", "store1", Some("prod"), "offsite"),
+    ];
+
+    let select = |setup: &CloudBackupJobSetup| {
+        select_job_mapping(&mappings, setup).map(|mapping| mapping.id.clone())
+    };
+
+    // jobs without target inherit the mapping
+    assert_eq!(
+        select(&job_setup("store1", None, None)?),
+        Some("store1".into())
+    );
+    assert_eq!(
+        select(&job_setup("store1", Some("prod"), None)?),
+        Some("store1-prod".into())
+    );
+
+    // jobs with a target of their own only use a mapping of that target
+    assert_eq!(
+        select(&job_setup("store1", None, Some("s3"))?),
+        Some("store1".into())
+    );
+    assert_eq!(select(&job_setup("store1", None, Some("other"))?), None);
+    assert_eq!(select(&job_setup("store2", None, None)?), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_apply_mapping() -> Result<(), Error> {
+    let mut target = test_target("s3");
+    target.config.namespace_key = Some(vec![format!("key={}", KEY2)]);
+
+    let mut store_mapping = mapping("store1", "store1", Some("prod"), "s3");
+    store_mapping.key = Some(KEY1.to_string());
+    apply_cloud_mapping(&store_mapping, &mut target)?;
+
+    // the mapped key wins for the mapped namespace only
+    let key1: Fingerprint = KEY1.parse()?;
+    let key = target
+        .config
+        .namespace_key_for(&BackupNamespace::new("prod/db")?)?;
+    assert_eq!(key, Some(key1.clone()));
+    let key = target.config.namespace_key_for(&BackupNamespace::root())?;
+    assert_ne!(key, Some(key1));
+
+    // the target has to use the prefix of the mapping
+    store_mapping.prefix = Some("store1".to_string());
+    assert!(check_mapping_prefix(&store_mapping, &target).is_err());
+    assert!(apply_cloud_mapping(&store_mapping, &mut target).is_err());
+    target.config.prefix = Some("store1".to_string());
+    check_mapping_prefix(&store_mapping, &target)?;
+
+    Ok(())
+}
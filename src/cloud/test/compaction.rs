@@ -0,0 +1,144 @@
+// Compaction tests
+//
+// # cargo test --release cloud::test::compaction
+
+use anyhow::Error;
+
+use crate::cloud::backend::CloudBackend;
+use crate::cloud::catalog::{CloudCatalog, MediaSetCatalog};
+use crate::cloud::compaction::{compact_media_sets, live_chunks, live_percentage};
+use crate::cloud::layout;
+
+use super::harness::{chunk_data, create_testdir, digest, TestTarget, TestWorker};
+
+fn remote_catalog(
+    target: &TestTarget,
+    uuid: &proxmox_uuid::Uuid,
+) -> Result<MediaSetCatalog, Error> {
+    let data = target
+        .backend
+        .get_object(&layout::media_set_catalog_key(uuid))?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+#[test]
+fn test_compact_sparse_media_set() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_compact_sparse_media_set")?);
+    let worker = TestWorker::default();
+
+    // only chunk 1 is still referenced
+    let sparse = target.write_media_set(
+        None,
+        &[digest(1), digest(2), digest(3), digest(4)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1)])],
+    )?;
+    let old_archive = sparse.archives[0].uuid.clone();
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let live = live_chunks(&catalog);
+    assert!(live_percentage(&sparse, &live).unwrap() < 40);
+
+    let stats = compact_media_sets(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        40,
+    )?;
+    assert_eq!(stats.media_sets, 1);
+    assert_eq!(stats.archives_written, 1);
+    assert_eq!(stats.archives_removed, 1);
+
+    // old archive deleted, catalog on target and local catalog updated
+    assert!(target
+        .backend
+        .head_object(&layout::chunk_archive_key(sparse.uuid(), &old_archive))?
+        .is_none());
+
+    let compacted = remote_catalog(&target, sparse.uuid())?;
+    assert_eq!(compacted.archives.len(), 1);
+    let archive = &compacted.archives[0];
+    assert_eq!(archive.chunks.len(), 1);
+    assert_eq!(archive.chunks[0].digest, digest(1));
+
+    let data = target
+        .backend
+        .get_object(&layout::chunk_archive_key(sparse.uuid(), &archive.uuid))?;
+    assert_eq!(data, chunk_data(&digest(1)));
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let location = catalog.find_chunk(&digest(1)).unwrap();
+    assert_eq!(location.archive, archive.uuid);
+    assert!(!catalog.contains_chunk(&digest(2)));
+
+    // nothing left to do
+    let stats = compact_media_sets(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        40,
+    )?;
+    assert_eq!(stats.media_sets, 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_compact_threshold() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_compact_threshold")?);
+    let worker = TestWorker::default();
+
+    // chunks 1 and 2 are live, more than half of the data
+    let media_set = target.write_media_set(
+        None,
+        &[digest(1), digest(2), digest(3)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+
+    let stats = compact_media_sets(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        40,
+    )?;
+    assert_eq!(stats.media_sets, 0);
+
+    let catalog = remote_catalog(&target, media_set.uuid())?;
+    assert_eq!(catalog.archives[0].uuid, media_set.archives[0].uuid);
+
+    Ok(())
+}
+
+#[test]
+fn test_compact_dead_archive() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_compact_dead_archive")?);
+    let worker = TestWorker::default();
+
+    // no snapshot references the chunks of the first media set anymore
+    let dead = target.write_media_set(None, &[digest(1), digest(2)], &[])?;
+    target.write_media_set(
+        None,
+        &[digest(3)],
+        &[("host/a/2020-01-02T00:00:00Z", vec![digest(3)])],
+    )?;
+
+    let stats = compact_media_sets(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        40,
+    )?;
+    assert_eq!(stats.media_sets, 1);
+    assert_eq!(stats.archives_written, 0);
+    assert_eq!(stats.archives_removed, 1);
+
+    assert!(remote_catalog(&target, dead.uuid())?.archives.is_empty());
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    assert!(!catalog.contains_chunk(&digest(1)));
+    assert!(catalog.contains_chunk(&digest(3)));
+
+    Ok(())
+}
@@ -0,0 +1,65 @@
+// Conditional write tests (media set ownership)
+//
+// # cargo test --release cloud::test::conditional_write
+
+use anyhow::Error;
+
+use proxmox_uuid::Uuid;
+
+use crate::cloud::backend::{is_object_exists, CloudBackend, LocalBackend, MockCloudBackend};
+use crate::cloud::catalog::{
+    upload_media_set_catalog, upload_media_set_label, MediaSetCatalog, MediaSetLabel,
+};
+
+use super::harness::create_testdir;
+
+fn test_label(node: &str) -> MediaSetLabel {
+    MediaSetLabel {
+        uuid: Uuid::generate(),
+        ctime: 1_600_000_000,
+        base: None,
+        node: node.to_string(),
+    }
+}
+
+#[test]
+fn test_local_put_if_absent() -> Result<(), Error> {
+    let testdir = create_testdir("test_local_put_if_absent")?;
+    let backend = LocalBackend::with_base(&testdir);
+
+    backend.put_object_if_absent("media-set/a/catalog.json", b"first")?;
+    let err = backend
+        .put_object_if_absent("media-set/a/catalog.json", b"second")
+        .unwrap_err();
+    assert!(is_object_exists(&err));
+    assert_eq!(backend.get_object("media-set/a/catalog.json")?, b"first");
+
+    // no temporary files left behind
+    assert_eq!(backend.list_objects("")?.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_media_set_owned_by_other_node() -> Result<(), Error> {
+    let backend = MockCloudBackend::new();
+
+    let label = test_label("node1");
+    upload_media_set_label(&backend, &label)?;
+
+    let mut other = label.clone();
+    other.node = "node2".to_string();
+    let err = upload_media_set_label(&backend, &other).unwrap_err();
+    assert!(err.to_string().contains("owned by another node ('node1')"));
+
+    // the catalog cannot be written by the other node either
+    upload_media_set_catalog(&backend, &MediaSetCatalog::new(label.clone()))?;
+    let err = upload_media_set_catalog(&backend, &MediaSetCatalog::new(other)).unwrap_err();
+    assert!(err.to_string().contains("owned by another node"));
+
+    // committing twice on the owning node is refused, too
+    let err = upload_media_set_catalog(&backend, &MediaSetCatalog::new(label)).unwrap_err();
+    assert!(err.to_string().contains("already written"));
+
+    Ok(())
+}
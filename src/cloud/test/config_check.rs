@@ -0,0 +1,225 @@
+// Cloud configuration check tests
+//
+// # cargo test --release cloud::test::config_check
+
+use std::collections::HashSet;
+
+use anyhow::Error;
+use serde_json::json;
+
+use proxmox_section_config::SectionConfigData;
+
+use pbs_api_types::{
+    BackupNamespace, CloudConfigIssue, CloudConfigIssueKind, CloudMapping, CloudProvider,
+    CloudTargetHealth, Fingerprint,
+};
+
+use crate::cloud::config_check::{
+    check_job_targets, check_keys, check_prefix_overlap, check_schedules, check_target_reachable,
+};
+
+use super::harness::test_target;
+
+const KEY1: &str =
+    "01:23:45:67:89:ab:cd:ef:01:23:45:67:89:ab:cd:ef:01:23:45:67:89:ab:cd:ef:01:23:45:67:89:ab:cd:ef";
+const KEY2: &str =
+    "fe:dc:ba:98:76:54:32:10:fe:dc:ba:98:76:54:32:10:fe:dc:ba:98:76:54:32:10:fe:dc:ba:98:76:54:32:10";
+
+fn entities(list: &[CloudConfigIssue], kind: CloudConfigIssueKind) -> Vec<&str> {
+    list.iter()
+        .filter(|issue| issue.kind == kind)
+        .map(|issue| issue.entity.as_str())
+        .collect()
+}
+
+fn mapping(id: &str, store: &str, target: &str) -> CloudMapping {
+    CloudMapping {
+        id: id.to_string(),
+        store: store.to_string(),
+        ns: None,
+        target: target.to_string(),
+        prefix: None,
+        key: None,
+        comment: None,
+    }
+}
+
+#[test]
+fn test_check_job_targets() -> Result<(), Error> {
+    let mut jobs = SectionConfigData::new();
+    jobs.set_data(
+        "ok",
+        "backup",
+        json!({ "id": "ok", "store": "store1", "target": "s3" }),
+    )?;
+    jobs.set_data(
+        "missing",
+        "backup",
+        json!({ "id": "missing", "store": "store1", "target": "gone" }),
+    )?;
+    jobs.set_data(
+        "mapped",
+        "backup",
+        json!({ "id": "mapped", "store": "store1" }),
+    )?;
+    jobs.set_data(
+        "unmapped",
+        "backup",
+        json!({ "id": "unmapped", "store": "store2" }),
+    )?;
+    jobs.set_data(
+        "repl",
+        "replication",
+        json!({ "id": "repl", "source": "s3", "target": "gone" }),
+    )?;
+    jobs.set_data(
+        "check",
+        "verify",
+        json!({ "id": "check", "store": "store1", "target": "gone" }),
+    )?;
+    jobs.set_data(
+        "trim",
+        "prune",
+        json!({ "id": "trim", "store": "store1", "target": "s3", "schedule": "daily" }),
+    )?;
+
+    let mappings = vec![
+        mapping("store1", "store1", "s3"),
+        mapping("old", "store3", "gone"),
+    ];
+    let targets: HashSet<String> = ["s3".to_string()].into_iter().collect();
+
+    let list = check_job_targets(&jobs, &mappings, &targets)?;
+    let mut found = entities(&list, CloudConfigIssueKind::MissingTarget);
+    found.sort();
+    assert_eq!(
+        found,
+        vec![
+            "backup/missing",
+            "backup/unmapped",
+            "mapping/old",
+            "replication/repl",
+            "verify/check"
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_check_target_reachable() {
+    let mut health = CloudTargetHealth {
+        target: "s3".to_string(),
+        ..Default::default()
+    };
+    // never checked
+    assert!(check_target_reachable(&health).is_none());
+
+    health.last_check = Some(1_700_000_000);
+    health.last_error = Some("connection refused".to_string());
+    let issue = check_target_reachable(&health).unwrap();
+    assert_eq!(issue.kind, CloudConfigIssueKind::UnreachableTarget);
+    assert_eq!(issue.entity, "target/s3");
+    assert!(issue.message.contains("connection refused"));
+
+    health.reachable = true;
+    assert!(check_target_reachable(&health).is_none());
+}
+
+#[test]
+fn test_check_keys() -> Result<(), Error> {
+    let key1: Fingerprint = KEY1.parse()?;
+    let key2: Fingerprint = KEY2.parse()?;
+    let known: HashSet<Fingerprint> = [key1.clone()].into_iter().collect();
+
+    let mut target = test_target("s3");
+    target.config.namespace_key = Some(vec![
+        format!("key={}", KEY1),
+        format!("ns=prod,key={}", KEY2),
+    ]);
+
+    let mut store_mapping = mapping("store1", "store1", "s3");
+    store_mapping.ns = Some(BackupNamespace::new("prod")?);
+    store_mapping.key = Some(KEY2.to_string());
+
+    let media_sets = vec![
+        ("s3".to_string(), "set1".to_string(), vec![key1]),
+        ("s3".to_string(), "set2".to_string(), vec![key2]),
+    ];
+
+    let list = check_keys(&[target], &[store_mapping], &media_sets, &known)?;
+    assert_eq!(
+        entities(&list, CloudConfigIssueKind::MissingKey),
+        vec!["target/s3", "mapping/store1", "target/s3"]
+    );
+    assert!(list[0].message.starts_with("namespace 'prod'"));
+    assert!(list[2].message.starts_with("media set set2"));
+
+    Ok(())
+}
+
+#[test]
+fn test_check_schedules() -> Result<(), Error> {
+    let now = 1_700_000_000; // 2023-11-14
+
+    let mut jobs = SectionConfigData::new();
+    jobs.set_data(
+        "daily",
+        "backup",
+        json!({ "id": "daily", "schedule": "daily" }),
+    )?;
+    jobs.set_data(
+        "past",
+        "backup",
+        json!({ "id": "past", "schedule": "2020-01-01" }),
+    )?;
+    jobs.set_data(
+        "disabled",
+        "backup",
+        json!({ "id": "disabled", "schedule": "2020-01-01", "disable": true }),
+    )?;
+    jobs.set_data("manual", "replication", json!({ "id": "manual" }))?;
+    jobs.set_data(
+        "digest",
+        "digest",
+        json!({ "id": "digest", "schedule": "2021-06-01 02:00" }),
+    )?;
+
+    let list = check_schedules(&jobs, now);
+    assert_eq!(
+        entities(&list, CloudConfigIssueKind::DeadSchedule),
+        vec!["digest/digest", "backup/past"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_check_prefix_overlap() {
+    let s3_target = |name: &str, bucket: &str, prefix: Option<&str>| {
+        let mut target = test_target(name);
+        target.config.provider = CloudProvider::S3;
+        target.config.path = None;
+        target.config.bucket = Some(bucket.to_string());
+        target.config.prefix = prefix.map(String::from);
+        target
+    };
+
+    let targets = vec![
+        s3_target("a", "bucket1", Some("pbs/a")),
+        s3_target("b", "bucket1", Some("pbs/ab")),
+        s3_target("c", "bucket1", Some("pbs")),
+        s3_target("d", "bucket2", None),
+        s3_target("e", "bucket2", Some("other")),
+        s3_target("f", "bucket3", Some("pbs")),
+    ];
+
+    let list = check_prefix_overlap(&targets);
+    let found: Vec<&str> = list.iter().map(|issue| issue.message.as_str()).collect();
+    assert_eq!(list.len(), 3, "{:?}", found);
+    assert!(found[0].starts_with("objects overlap with target 'c'"));
+    assert_eq!(
+        entities(&list, CloudConfigIssueKind::OverlappingPrefix),
+        vec!["target/a", "target/b", "target/d"]
+    );
+}
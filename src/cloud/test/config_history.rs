@@ -0,0 +1,159 @@
+// Config change history tests
+//
+// # cargo test --release cloud::test::config_history
+
+use std::path::PathBuf;
+
+use anyhow::Error;
+use serde_json::{json, Value};
+
+use proxmox_section_config::{SectionConfig, SectionConfigData};
+
+use pbs_api_types::{Authid, CloudConfigAction, CloudConfigChange, CloudConfigPropertyChange};
+
+use crate::cloud::config_history::{load_history, property_changes, record_change, save_section};
+
+use super::harness::create_testdir;
+
+fn change(time: i64, id: &str) -> CloudConfigChange {
+    CloudConfigChange {
+        time,
+        user: Authid::root_auth_id().clone(),
+        config_type: "backup".to_string(),
+        id: id.to_string(),
+        action: CloudConfigAction::Create,
+        changes: Vec::new(),
+    }
+}
+
+#[test]
+fn test_property_changes() {
+    let old = json!({ "endpoint": "a.example.com", "secret-key": "c2VjcmV0", "bucket": "b" });
+    let new = json!({ "endpoint": "b.example.com", "secret-key": "bmV3", "port": 8443 });
+
+    let changes = property_changes(Some(&old), Some(&new));
+    assert_eq!(
+        changes,
+        vec![
+            CloudConfigPropertyChange {
+                property: "bucket".to_string(),
+                old: Some("b".to_string()),
+                new: None,
+            },
+            CloudConfigPropertyChange {
+                property: "endpoint".to_string(),
+                old: Some("a.example.com".to_string()),
+                new: Some("b.example.com".to_string()),
+            },
+            CloudConfigPropertyChange {
+                property: "port".to_string(),
+                old: None,
+                new: Some("8443".to_string()),
+            },
+            CloudConfigPropertyChange {
+                property: "secret-key".to_string(),
+                old: Some("<redacted>".to_string()),
+                new: Some("<redacted>".to_string()),
+            },
+        ]
+    );
+
+    assert!(property_changes(Some(&old), Some(&old)).is_empty());
+    assert_eq!(property_changes(None, Some(&new)).len(), 3);
+}
+
+#[test]
+fn test_history_retention() -> Result<(), Error> {
+    let testdir = create_testdir("test_history_retention")?;
+    let day = 24 * 3600;
+
+    record_change(&testdir, change(0, "old"), 10)?;
+    record_change(&testdir, change(5 * day, "middle"), 10)?;
+    record_change(&testdir, change(12 * day, "new"), 10)?;
+
+    let ids: Vec<String> = load_history(&testdir)?
+        .into_iter()
+        .map(|change| change.id)
+        .collect();
+    assert_eq!(ids, vec!["middle", "new"]);
+
+    Ok(())
+}
+
+// config file saved the way the config API does
+struct ConfigFile {
+    config: SectionConfigData,
+    plugins: &'static SectionConfig,
+    path: PathBuf,
+}
+
+impl ConfigFile {
+    fn new(plugins: &'static SectionConfig, path: PathBuf) -> Self {
+        Self {
+            config: SectionConfigData::new(),
+            plugins,
+            path,
+        }
+    }
+
+    fn save(&mut self, section_type: &str, data: &Value, validate: bool) -> Result<bool, Error> {
+        let id = data["id"].as_str().or(data["name"].as_str()).unwrap();
+        let (plugins, path) = (self.plugins, &self.path);
+        save_section(
+            &mut self.config,
+            section_type,
+            id,
+            data,
+            validate,
+            |config| {
+                let raw = plugins.write(&path.to_string_lossy(), config)?;
+                std::fs::write(path, raw)?;
+                Ok(())
+            },
+        )
+    }
+
+    fn raw(&self) -> Result<String, Error> {
+        Ok(std::fs::read_to_string(&self.path)?)
+    }
+}
+
+#[test]
+fn test_save_section_validate() -> Result<(), Error> {
+    let testdir = create_testdir("test_save_section_validate")?;
+    let mut targets = ConfigFile::new(&pbs_config::cloud::CONFIG, testdir.join("cloud.cfg"));
+    let mut jobs = ConfigFile::new(
+        &pbs_config::cloud_job::CONFIG,
+        testdir.join("cloud-job.cfg"),
+    );
+
+    let target = json!({ "name": "t1", "provider": "local", "path": "/backup" });
+    assert!(targets.save("target", &target, false)?);
+    let job = json!({ "id": "job1", "store": "store1", "target": "t1" });
+    assert!(jobs.save("backup", &job, false)?);
+
+    let target_raw = targets.raw()?;
+    let job_raw = jobs.raw()?;
+    assert!(target_raw.contains("target: t1"));
+    assert!(job_raw.contains("backup: job1"));
+
+    // creating and updating only validates
+    let target2 = json!({ "name": "t2", "provider": "local", "path": "/other" });
+    assert!(!targets.save("target", &target2, true)?);
+    let updated = json!({ "name": "t1", "provider": "local", "path": "/moved" });
+    assert!(!targets.save("target", &updated, true)?);
+
+    let template = json!({ "id": "fleet", "target": "t1" });
+    assert!(!jobs.save("template", &template, true)?);
+    let updated = json!({ "id": "job1", "store": "store2", "target": "t1" });
+    assert!(!jobs.save("backup", &updated, true)?);
+
+    assert_eq!(targets.config.order, vec!["t1"]);
+    assert_eq!(targets.config.sections["t1"].1, target);
+    assert_eq!(jobs.config.order, vec!["job1"]);
+    assert_eq!(jobs.config.sections["job1"].1, job);
+    assert_eq!(targets.raw()?, target_raw);
+    assert_eq!(jobs.raw()?, job_raw);
+
+    Ok(())
+}
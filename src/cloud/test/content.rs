@@ -0,0 +1,119 @@
+// Content listing tests
+//
+// # cargo test --release cloud::test::content
+
+use anyhow::Error;
+
+use pbs_api_types::{BackupNamespace, BackupType};
+
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::content::{
+    list_groups, list_namespaces, list_snapshots, snapshot_cloud_copies, CloudContentFilter,
+};
+
+use super::harness::{create_testdir, digest, TestTarget, TEST_STORE};
+
+#[test]
+fn test_content_listing() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_content_listing")?);
+
+    let full = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[
+            ("host/a/2020-01-01T00:00:00Z", vec![digest(1)]),
+            ("vm/100/2020-01-01T00:00:00Z", vec![digest(2)]),
+        ],
+    )?;
+    let incremental = target.write_media_set(
+        Some(full.uuid()),
+        &[digest(3)],
+        &[
+            ("host/a/2020-01-01T00:00:00Z", vec![digest(1)]),
+            ("host/a/2020-01-02T00:00:00Z", vec![digest(1), digest(3)]),
+        ],
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+
+    // snapshots in several media sets are listed once, with the newest one
+    let snapshots = list_snapshots(&catalog, &CloudContentFilter::default(), None);
+    assert_eq!(snapshots.len(), 3);
+    let first = &snapshots[0];
+    assert_eq!(first.backup.to_string(), "host/a/2020-01-01T00:00:00Z");
+    assert_eq!(first.media_set, incremental.uuid().to_string());
+    assert_eq!(first.ctime, incremental.label.ctime);
+    assert!(first.size.is_some());
+    assert!(!first.encrypted);
+
+    let filter = CloudContentFilter {
+        backup_type: Some(BackupType::Vm),
+        ..Default::default()
+    };
+    let snapshots = list_snapshots(&catalog, &filter, None);
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0].media_set, full.uuid().to_string());
+
+    let filter = CloudContentFilter {
+        store: Some("other".to_string()),
+        ..Default::default()
+    };
+    assert!(list_snapshots(&catalog, &filter, None).is_empty());
+
+    let groups = list_groups(&catalog, &CloudContentFilter::default(), None);
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].backup.to_string(), "host/a");
+    assert_eq!(groups[0].backup_count, 2);
+    assert_eq!(
+        groups[0].last_backup,
+        proxmox_time::parse_rfc3339("2020-01-02T00:00:00Z")?
+    );
+    assert_eq!(groups[1].backup.to_string(), "vm/100");
+    assert_eq!(groups[1].backup_count, 1);
+
+    let namespaces = list_namespaces(&catalog, &CloudContentFilter::default());
+    assert_eq!(namespaces.len(), 1);
+    assert_eq!(namespaces[0].store, TEST_STORE);
+    assert!(namespaces[0].ns.is_root());
+    assert_eq!(namespaces[0].group_count, 2);
+    assert_eq!(namespaces[0].snapshot_count, 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_cloud_copies() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_snapshot_cloud_copies")?);
+
+    target.write_media_set(
+        None,
+        &[digest(1)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1)])],
+    )?;
+    let incremental = target.write_media_set(
+        None,
+        &[digest(2)],
+        &[
+            ("host/a/2020-01-01T00:00:00Z", vec![digest(1)]),
+            ("host/a/2020-01-02T00:00:00Z", vec![digest(2)]),
+        ],
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let other = CloudCatalog::load(&target.base_path, "test")?;
+    let catalogs = vec![("test".to_string(), catalog), ("other".to_string(), other)];
+
+    let copies = snapshot_cloud_copies(&catalogs, TEST_STORE, &BackupNamespace::root());
+    assert_eq!(copies.len(), 2);
+    for list in copies.values() {
+        // one copy per target, with the newest media set
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].target, "test");
+        assert_eq!(list[1].target, "other");
+        assert_eq!(list[0].upload_time, incremental.label.ctime);
+    }
+
+    assert!(snapshot_cloud_copies(&catalogs, "other", &BackupNamespace::root()).is_empty());
+
+    Ok(())
+}
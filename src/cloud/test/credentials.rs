@@ -0,0 +1,136 @@
+// Credential renewal tests
+//
+// # cargo test --release cloud::test::credentials
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+
+use crate::cloud::backend::credentials::{
+    is_credentials_rejected, CloudCredentials, CredentialCache, CredentialSource,
+};
+use crate::cloud::backend::{CloudBackend, MockCloudBackend, PutOptions};
+
+const START_TIME: i64 = 1_700_000_000;
+
+/// Issues numbered temporary credentials
+///
+/// Like a token service rotating keys in fixed windows, the n-th
+/// credentials expire `n * lifetime` seconds after the start time.
+struct TestSource {
+    lifetime: Option<i64>,
+    fetched: AtomicUsize,
+    fail_after: Option<usize>,
+}
+
+impl TestSource {
+    fn new(lifetime: Option<i64>, fail_after: Option<usize>) -> Arc<Self> {
+        Arc::new(Self {
+            lifetime,
+            fetched: AtomicUsize::new(0),
+            fail_after,
+        })
+    }
+}
+
+impl CredentialSource for Arc<TestSource> {
+    fn fetch(&self) -> Result<CloudCredentials, Error> {
+        let count = self.fetched.fetch_add(1, Ordering::SeqCst) + 1;
+        if matches!(self.fail_after, Some(n) if count > n) {
+            bail!("token service unavailable");
+        }
+        Ok(CloudCredentials {
+            access_key: format!("key-{}", count),
+            secret_key: format!("secret-{}", count),
+            session_token: Some(format!("token-{}", count)),
+            expires: self
+                .lifetime
+                .map(|lifetime| START_TIME + count as i64 * lifetime),
+        })
+    }
+}
+
+fn mock_backend() -> Arc<MockCloudBackend> {
+    let backend = Arc::new(MockCloudBackend::new());
+    backend.set_time(START_TIME);
+    backend
+}
+
+#[test]
+fn test_rollover_during_multipart_upload() -> Result<(), Error> {
+    let backend = mock_backend();
+    let source = TestSource::new(Some(600), None);
+    backend.set_credentials(Arc::new(CredentialCache::with_source(Box::new(
+        Arc::clone(&source),
+    ))));
+
+    // 20 parts, one minute per request
+    backend.set_part_size(16);
+    backend.set_time_step(60);
+    let data: Vec<u8> = (0..320).map(|i| i as u8).collect();
+
+    backend.put_object_multipart("large", &data, &PutOptions::default())?;
+    assert_eq!(backend.get_object("large")?, data);
+
+    // credentials are renewed 5 minutes before they expire (after 5 and
+    // 15 minutes), so no request of the upload was rejected
+    let used = backend.used_access_keys();
+    assert_eq!(used.len() as u64, backend.request_count());
+    assert_eq!(used.first().map(String::as_str), Some("key-1"));
+    assert_eq!(used.last().map(String::as_str), Some("key-3"));
+    assert_eq!(source.fetched.load(Ordering::SeqCst), 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_revoked_credentials() -> Result<(), Error> {
+    let backend = mock_backend();
+    let source = TestSource::new(None, None);
+    backend.set_credentials(Arc::new(CredentialCache::with_source(Box::new(
+        Arc::clone(&source),
+    ))));
+    backend.set_part_size(16);
+
+    let data = vec![1u8; 64];
+    backend.put_object("a", &data)?;
+    backend.revoke_credentials("key-1");
+
+    // rejected credentials are renewed once, without failing the request
+    backend.put_object_multipart("b", &data, &PutOptions::default())?;
+    assert_eq!(source.fetched.load(Ordering::SeqCst), 2);
+    assert_eq!(backend.get_object("b")?, data);
+
+    // static credentials cannot be renewed
+    let backend = mock_backend();
+    backend.set_credentials(Arc::new(CredentialCache::with_static(CloudCredentials {
+        access_key: "static".to_string(),
+        secret_key: "secret".to_string(),
+        session_token: None,
+        expires: None,
+    })));
+    backend.put_object("a", &data)?;
+    backend.revoke_credentials("static");
+    let err = backend.put_object("a", &data).unwrap_err();
+    assert!(is_credentials_rejected(&err));
+
+    Ok(())
+}
+
+#[test]
+fn test_renewal_failure() -> Result<(), Error> {
+    let source = TestSource::new(Some(600), Some(1));
+    let cache = CredentialCache::with_source(Box::new(Arc::clone(&source))).refresh_margin(300);
+
+    assert_eq!(cache.get_at(START_TIME)?.access_key, "key-1");
+    assert_eq!(source.fetched.load(Ordering::SeqCst), 1);
+
+    // renewal fails, but the current credentials are still valid
+    assert_eq!(cache.get_at(START_TIME + 400)?.access_key, "key-1");
+    assert_eq!(source.fetched.load(Ordering::SeqCst), 2);
+
+    assert!(cache.get_at(START_TIME + 600).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+// Deduplication statistics tests
+//
+// # cargo test --release cloud::test::dedup_stats
+
+use anyhow::Error;
+
+use pbs_api_types::{BackupGroup, BackupNamespace, BackupType};
+
+use crate::cloud::dedup_stats::{
+    add_group_stats, group_stats_name, list_dedup_stats, load_dedup_stats, update_dedup_stats,
+    DedupStats, GroupDedupStats,
+};
+
+use super::harness::create_testdir;
+
+fn snapshot_stats(logical: u64, present: u64, uploaded: u64) -> DedupStats {
+    DedupStats {
+        snapshots: 1,
+        logical_bytes: logical,
+        present_bytes: present,
+        uploaded_bytes: uploaded,
+    }
+}
+
+#[test]
+fn test_add_group_stats() {
+    let group = BackupGroup::new(BackupType::Vm, "100");
+    let ns = BackupNamespace::new("a/b").unwrap();
+    assert_eq!(
+        group_stats_name("store1", &BackupNamespace::root(), &group),
+        "store1:vm/100"
+    );
+    assert_eq!(
+        group_stats_name("store1", &ns, &group),
+        "store1:ns/a/ns/b/vm/100"
+    );
+
+    let mut list = Vec::new();
+    add_group_stats(&mut list, "store1:vm/100", &snapshot_stats(100, 0, 60));
+    add_group_stats(&mut list, "store1:vm/100", &snapshot_stats(100, 90, 5));
+    add_group_stats(&mut list, "store1:vm/101", &snapshot_stats(50, 0, 50));
+
+    assert_eq!(
+        list,
+        vec![
+            GroupDedupStats {
+                group: "store1:vm/100".to_string(),
+                stats: DedupStats {
+                    snapshots: 2,
+                    logical_bytes: 200,
+                    present_bytes: 90,
+                    uploaded_bytes: 65,
+                },
+            },
+            GroupDedupStats {
+                group: "store1:vm/101".to_string(),
+                stats: snapshot_stats(50, 0, 50),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_update_dedup_stats() -> Result<(), Error> {
+    let base = create_testdir("test_update_dedup_stats")?;
+
+    assert!(list_dedup_stats(&base)?.is_empty());
+    assert!(load_dedup_stats(&base, "target1")?.groups.is_empty());
+
+    let mut first = Vec::new();
+    add_group_stats(&mut first, "store1:vm/101", &snapshot_stats(50, 0, 50));
+    add_group_stats(&mut first, "store1:vm/100", &snapshot_stats(100, 0, 60));
+    update_dedup_stats(&base, "target1", &first)?;
+
+    // a later run only replaces the groups it wrote
+    let mut second = Vec::new();
+    add_group_stats(&mut second, "store1:vm/100", &snapshot_stats(100, 95, 3));
+    update_dedup_stats(&base, "target1", &second)?;
+    update_dedup_stats(&base, "target2", &second)?;
+
+    let stats = load_dedup_stats(&base, "target1")?;
+    let groups: Vec<_> = stats
+        .groups
+        .iter()
+        .map(|entry| entry.group.as_str())
+        .collect();
+    assert_eq!(groups, vec!["store1:vm/100", "store1:vm/101"]);
+    assert_eq!(stats.groups[0].stats, snapshot_stats(100, 95, 3));
+    assert_eq!(stats.groups[1].stats, snapshot_stats(50, 0, 50));
+
+    let list = list_dedup_stats(&base)?;
+    let targets: Vec<_> = list.iter().map(|(target, _)| target.as_str()).collect();
+    assert_eq!(targets, vec!["target1", "target2"]);
+    assert_eq!(list[1].1.groups, second);
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+// Delete protection tests
+//
+// # cargo test --release cloud::test::delete_protection
+
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use crate::cloud::backend::{CloudBackend, DeleteProtectedBackend, MockCloudBackend};
+use crate::cloud::delete_queue::DeleteQueue;
+use crate::cloud::layout::LEASE_KEY;
+use crate::cloud::lease::{CloudLease, LEASE_TIMEOUT};
+
+use super::harness::create_testdir;
+
+#[test]
+fn test_deferred_delete() -> Result<(), Error> {
+    let testdir = create_testdir("test_deferred_delete")?;
+
+    let inner: Arc<dyn CloudBackend> = Arc::new(MockCloudBackend::new());
+    let backend: Arc<dyn CloudBackend> = Arc::new(DeleteProtectedBackend::new(
+        Arc::clone(&inner),
+        &testdir,
+        "test",
+    ));
+    assert!(backend.delete_protected());
+
+    backend.put_object("a", b"a")?;
+    backend.put_object("b", b"b")?;
+
+    backend.delete_object("a")?;
+    backend.delete_object("b")?;
+    backend.delete_object("a")?;
+    assert!(inner.head_object("a")?.is_some());
+    assert!(inner.head_object("b")?.is_some());
+
+    let keys = |queue: &DeleteQueue| -> Vec<String> {
+        queue
+            .entries()
+            .iter()
+            .map(|entry| entry.key.clone())
+            .collect()
+    };
+    assert_eq!(keys(&DeleteQueue::load(&testdir, "test")?), ["a", "b"]);
+
+    // written again, so it must survive the queue processing
+    backend.put_object("b", b"new")?;
+    let mut queue = DeleteQueue::load(&testdir, "test")?;
+    assert_eq!(keys(&queue), ["a"]);
+
+    // the protected backend cannot process its own queue
+    assert!(queue.process(&backend, |_| {}).is_err());
+
+    let mut deleted = Vec::new();
+    assert_eq!(
+        queue.process(&inner, |entry| deleted.push(entry.key.clone()))?,
+        1
+    );
+    assert_eq!(deleted, ["a"]);
+    drop(queue);
+
+    assert!(inner.head_object("a")?.is_none());
+    assert_eq!(inner.get_object("b")?, b"new");
+    assert!(DeleteQueue::load(&testdir, "test")?.entries().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_lease_without_delete() -> Result<(), Error> {
+    let testdir = create_testdir("test_lease_without_delete")?;
+
+    let inner: Arc<dyn CloudBackend> = Arc::new(MockCloudBackend::new());
+    let backend: Arc<dyn CloudBackend> = Arc::new(DeleteProtectedBackend::new(
+        Arc::clone(&inner),
+        &testdir,
+        "test",
+    ));
+
+    let lease = CloudLease::acquire(Arc::clone(&backend), "node1", LEASE_TIMEOUT)?;
+    assert!(CloudLease::acquire(Arc::clone(&backend), "node2", LEASE_TIMEOUT).is_err());
+    lease.release()?;
+
+    // released by expiring the heartbeat, the object stays
+    let info = CloudLease::read(&*backend)?.unwrap();
+    assert!(info.is_stale(proxmox_time::epoch_i64(), LEASE_TIMEOUT));
+    assert!(inner.head_object(LEASE_KEY)?.is_some());
+
+    let lease = CloudLease::acquire(Arc::clone(&backend), "node2", LEASE_TIMEOUT)?;
+    lease.check()?;
+    assert_eq!(CloudLease::read(&*backend)?.unwrap().node, "node2");
+    drop(lease);
+
+    assert!(DeleteQueue::load(&testdir, "test")?.entries().is_empty());
+
+    Ok(())
+}
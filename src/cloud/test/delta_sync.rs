@@ -0,0 +1,115 @@
+// Delta sync tests
+//
+// # cargo test --release cloud::test::delta_sync
+
+use anyhow::Error;
+
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{BackupDir, CryptMode};
+use pbs_datastore::manifest::BackupManifest;
+
+use crate::cloud::catalog::{CloudCatalog, MediaSetCatalog, MediaSetLabel};
+use crate::cloud::{unchanged_index_files, CatalogSet};
+
+use super::harness::{create_testdir, digest, TestTarget, TEST_STORE};
+
+fn test_manifest(snapshot: &str, disk_csum: u8) -> Result<BackupManifest, Error> {
+    let mut manifest = BackupManifest::new(snapshot.parse()?);
+    manifest.add_file(
+        "drive-scsi0.img.fidx".to_string(),
+        4096,
+        [0; 32],
+        CryptMode::None,
+    )?;
+    manifest.add_file(
+        "drive-scsi1.img.fidx".to_string(),
+        4096,
+        [disk_csum; 32],
+        CryptMode::None,
+    )?;
+    manifest.add_file(
+        "qemu-server.conf.blob".to_string(),
+        100,
+        [0; 32],
+        CryptMode::None,
+    )?;
+    Ok(manifest)
+}
+
+#[test]
+fn test_unchanged_index_files() -> Result<(), Error> {
+    let previous = test_manifest("vm/100/2020-01-01T00:00:00Z", 1)?;
+    let manifest = test_manifest("vm/100/2020-01-02T00:00:00Z", 2)?;
+
+    // blobs are always uploaded, changed indexes are compared chunk by chunk
+    let unchanged = unchanged_index_files(&manifest, &previous);
+    assert_eq!(unchanged.len(), 1);
+    assert!(unchanged.contains("drive-scsi0.img.fidx"));
+
+    let unchanged = unchanged_index_files(&manifest, &manifest);
+    assert_eq!(unchanged.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_previous_snapshot() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_previous_snapshot")?);
+
+    let full = target.write_media_set(
+        None,
+        &[digest(1)],
+        &[
+            ("vm/100/2020-01-01T00:00:00Z", vec![digest(1)]),
+            ("vm/100/2020-01-02T00:00:00Z", vec![digest(1)]),
+            ("vm/101/2020-01-03T00:00:00Z", vec![digest(1)]),
+        ],
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let mut catalog_set = CatalogSet::new(catalog);
+    catalog_set.start_media_set(MediaSetCatalog::new(MediaSetLabel {
+        uuid: Uuid::generate(),
+        ctime: 1_700_000_000,
+        base: Some(full.uuid().clone()),
+        node: "testnode".to_string(),
+    }))?;
+
+    let snapshot: BackupDir = "vm/100/2020-01-05T00:00:00Z".parse()?;
+    let ns = Default::default();
+
+    let (media_set, entry) = catalog_set
+        .previous_snapshot(TEST_STORE, &ns, &snapshot, None)
+        .unwrap();
+    assert_eq!(&media_set, full.uuid());
+    assert_eq!(entry.snapshot.to_string(), "vm/100/2020-01-02T00:00:00Z");
+
+    // only earlier snapshots of the group, written with the same key
+    let first: BackupDir = "vm/100/2020-01-01T00:00:00Z".parse()?;
+    assert!(catalog_set
+        .previous_snapshot(TEST_STORE, &ns, &first, None)
+        .is_none());
+    let other: BackupDir = "vm/102/2020-01-05T00:00:00Z".parse()?;
+    assert!(catalog_set
+        .previous_snapshot(TEST_STORE, &ns, &other, None)
+        .is_none());
+    assert!(catalog_set
+        .previous_snapshot("store2", &ns, &snapshot, None)
+        .is_none());
+
+    // a new full media set does not reference older chains
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let mut catalog_set = CatalogSet::new(catalog);
+    catalog_set.start_media_set(MediaSetCatalog::new(MediaSetLabel {
+        uuid: Uuid::generate(),
+        ctime: 1_700_000_000,
+        base: None,
+        node: "testnode".to_string(),
+    }))?;
+    assert!(catalog_set
+        .previous_snapshot(TEST_STORE, &ns, &snapshot, None)
+        .is_none());
+
+    Ok(())
+}
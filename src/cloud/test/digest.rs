@@ -0,0 +1,122 @@
+// Cloud job digest tests
+//
+// # cargo test --release cloud::test::digest
+
+use anyhow::Error;
+
+use proxmox_rest_server::TaskState;
+
+use pbs_api_types::{CloudDigestPeriod, UPID};
+
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::digest::{
+    load_digest_state, remove_digest_state, save_digest_state, stored_bytes, CloudJobDigest,
+};
+
+use super::harness::{create_testdir, digest, TestTarget};
+
+// worker type and ID are replaced
+const BASE_UPID: &str = "UPID:node1:00000001:00000001:00000001:5F5E1000:cloud-job:job:root@pam:";
+
+fn job_upid(worker_type: &str, worker_id: &str) -> Result<UPID, Error> {
+    let mut upid: UPID = BASE_UPID.parse()?;
+    upid.worker_type = worker_type.to_string();
+    upid.worker_id = Some(worker_id.to_string());
+    Ok(upid)
+}
+
+fn failed(message: &str, endtime: i64) -> TaskState {
+    TaskState::Error {
+        message: message.to_string(),
+        endtime,
+    }
+}
+
+#[test]
+fn test_digest_runs() -> Result<(), Error> {
+    let backup = job_upid("cloud-backup-job", "store1:target1:job1")?;
+    let replication = job_upid("cloud-replication-job", "target1:target2:repl1")?;
+
+    let mut digest = CloudJobDigest::new("daily", CloudDigestPeriod::Daily, 100_000);
+    assert_eq!(digest.since, 100_000 - 86400);
+
+    digest.add_run(&replication, &TaskState::OK { endtime: 90_000 }, 0);
+    digest.add_run(&backup, &failed("second", 95_000), 100);
+    digest.add_run(&backup, &TaskState::OK { endtime: 80_000 }, 4096);
+    digest.add_run(&backup, &failed("first", 85_000), 0);
+
+    assert_eq!(digest.runs, 4);
+    assert_eq!(digest.failed, 2);
+    assert_eq!(digest.bytes, 4196);
+
+    // sorted by type and ID, independent of the order of the runs
+    let jobs: Vec<(&str, &str)> = digest
+        .jobs
+        .iter()
+        .map(|job| (job.job_type.as_str(), job.job_id.as_str()))
+        .collect();
+    assert_eq!(
+        jobs,
+        vec![
+            ("cloud-backup-job", "job1"),
+            ("cloud-replication-job", "repl1")
+        ]
+    );
+
+    let job = &digest.jobs[0];
+    assert_eq!((job.runs, job.failed, job.bytes), (3, 2, 4196));
+    assert_eq!(job.last_failure, Some(95_000));
+    assert_eq!(job.last_error.as_deref(), Some("second"));
+
+    let job = &digest.jobs[1];
+    assert_eq!((job.runs, job.failed), (1, 0));
+    assert_eq!(job.last_error, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_digest_targets() -> Result<(), Error> {
+    let testdir = create_testdir("test_digest_targets")?;
+    let mut target = TestTarget::new(testdir.clone());
+
+    let media_set = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("vm/100/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    let archives: u64 = media_set.archives.iter().map(|archive| archive.size).sum();
+    let files: u64 = media_set.snapshots[0]
+        .files
+        .iter()
+        .map(|file| file.size)
+        .sum();
+
+    let catalog = CloudCatalog::load(&testdir, &target.target.name)?;
+    let size = stored_bytes(&catalog);
+    assert_eq!(size, archives + files);
+
+    // first digest, nothing to compare with
+    let previous = load_digest_state(&testdir, "weekly")?;
+    assert!(previous.targets.is_empty());
+
+    let mut digest = CloudJobDigest::new("weekly", CloudDigestPeriod::Weekly, 1_000_000);
+    digest.add_target("test", size, &previous);
+    assert_eq!(digest.targets[0].previous_bytes, None);
+    save_digest_state(&testdir, "weekly", &digest.state())?;
+
+    let previous = load_digest_state(&testdir, "weekly")?;
+    assert_eq!(previous.time, 1_000_000);
+
+    let mut digest = CloudJobDigest::new("weekly", CloudDigestPeriod::Weekly, 2_000_000);
+    digest.add_target("test", size + 100, &previous);
+    digest.add_target("other", 0, &previous);
+    assert_eq!(digest.targets[0].previous_bytes, Some(size));
+    assert_eq!(digest.targets[1].previous_bytes, None);
+
+    remove_digest_state(&testdir, "weekly")?;
+    remove_digest_state(&testdir, "weekly")?;
+    assert!(load_digest_state(&testdir, "weekly")?.targets.is_empty());
+
+    Ok(())
+}
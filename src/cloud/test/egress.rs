@@ -0,0 +1,78 @@
+// Egress budget tests
+//
+// # cargo test --release cloud::test::egress
+
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use crate::cloud::backend::{AccountedBackend, CloudBackend, MeteredBackend, MockCloudBackend};
+use crate::cloud::egress::{egress_status, EgressMeter};
+use crate::cloud::usage::TransferRecorder;
+
+use super::harness::{create_testdir, test_target};
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+#[test]
+fn test_egress_budget() -> Result<(), Error> {
+    let testdir = create_testdir("test_egress_budget")?;
+    let mut target = test_target("test");
+    target.config.egress_budget = Some(1);
+
+    let mock: Arc<dyn CloudBackend> = Arc::new(MockCloudBackend::new());
+    mock.put_object("a", &[0u8; 100])?;
+
+    let recorder = Arc::new(TransferRecorder::new(&testdir, "test"));
+    let inner: Arc<dyn CloudBackend> = Arc::new(AccountedBackend::new(mock, recorder.clone()));
+
+    let meter = Arc::new(EgressMeter::open(&testdir, &target, true)?);
+    let backend = MeteredBackend::new(Arc::clone(&inner), Arc::clone(&meter));
+
+    assert_eq!(backend.get_object("a")?.len(), 100);
+    assert_eq!(meter.recorded(), 100);
+    assert_eq!(meter.remaining(), Some(GIB - 100));
+
+    assert!(meter.check(GIB - 100).is_ok());
+    assert!(meter.check(GIB).is_err());
+
+    // budget used up - further downloads fail
+    meter.record(GIB);
+    assert_eq!(meter.remaining(), Some(0));
+    assert!(backend.get_object("a").is_err());
+
+    // past downloads come from the transfer accounting
+    recorder.flush()?;
+    let meter = EgressMeter::open(&testdir, &target, true)?;
+    assert_eq!(meter.used(), 100);
+
+    // forced downloads ignore the budget, but are still accounted
+    let meter = Arc::new(EgressMeter::open(&testdir, &target, false)?);
+    meter.record(GIB);
+    let backend = MeteredBackend::new(Arc::clone(&inner), Arc::clone(&meter));
+    assert_eq!(backend.get_object("a")?.len(), 100);
+    recorder.flush()?;
+
+    let status = egress_status(&testdir, &target)?;
+    assert_eq!(status.used, 200);
+    assert_eq!(status.budget, Some(GIB));
+
+    Ok(())
+}
+
+#[test]
+fn test_egress_without_budget() -> Result<(), Error> {
+    let testdir = create_testdir("test_egress_without_budget")?;
+    let target = test_target("test");
+
+    let meter = EgressMeter::open(&testdir, &target, true)?;
+    meter.record(10 * GIB);
+    assert_eq!(meter.remaining(), None);
+    assert!(meter.check(10 * GIB).is_ok());
+
+    let status = egress_status(&testdir, &target)?;
+    assert_eq!(status.used, 0);
+    assert_eq!(status.budget, None);
+
+    Ok(())
+}
@@ -0,0 +1,183 @@
+// Namespace encryption tests
+//
+// # cargo test --release cloud::test::encryption
+
+use anyhow::Error;
+
+use pbs_api_types::{BackupNamespace, Fingerprint, Kdf};
+use pbs_key_config::KeyConfig;
+use pbs_tools::crypt_config::CryptConfig;
+use proxmox_uuid::Uuid;
+
+use crate::cloud::catalog::{
+    ChunkArchiveEntry, ChunkEntry, CloudCatalog, MediaSetCatalog, MediaSetLabel,
+};
+use crate::cloud::encryption_keys::{decrypt_object, encrypt_object, TenantKey};
+use crate::cloud::CatalogSet;
+
+use super::harness::{create_testdir, digest, test_target};
+
+fn fingerprint(n: u8) -> Fingerprint {
+    Fingerprint::new([n; 32])
+}
+
+#[test]
+fn test_namespace_key_selection() -> Result<(), Error> {
+    let mut target = test_target("test");
+    target.config.namespace_key = Some(vec![
+        format!("ns=tenant1,key={}", fingerprint(1)),
+        format!("ns=tenant1/sub,key={}", fingerprint(2)),
+        format!("key={}", fingerprint(3)),
+    ]);
+
+    let ns = |s: &str| -> BackupNamespace { s.parse().unwrap() };
+
+    let config = &target.config;
+    assert_eq!(
+        config.namespace_key_for(&ns("tenant1"))?,
+        Some(fingerprint(1))
+    );
+    assert_eq!(
+        config.namespace_key_for(&ns("tenant1/x"))?,
+        Some(fingerprint(1))
+    );
+    assert_eq!(
+        config.namespace_key_for(&ns("tenant1/sub/y"))?,
+        Some(fingerprint(2))
+    );
+    assert_eq!(
+        config.namespace_key_for(&ns("tenant2"))?,
+        Some(fingerprint(3))
+    );
+    assert_eq!(
+        config.namespace_key_for(&BackupNamespace::root())?,
+        Some(fingerprint(3))
+    );
+
+    target.config.namespace_key = Some(vec![format!("ns=tenant1,key={}", fingerprint(1))]);
+    assert_eq!(target.config.namespace_key_for(&ns("tenant2"))?, None);
+
+    target
+        .config
+        .namespace_key
+        .as_mut()
+        .unwrap()
+        .push(format!("ns=tenant1,key={}", fingerprint(2)));
+    assert!(target.config.namespace_keys().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_object_encryption() -> Result<(), Error> {
+    let tenant1 = CryptConfig::new([1u8; 32])?;
+    let tenant2 = CryptConfig::new([2u8; 32])?;
+
+    let data = b"snapshot index data".to_vec();
+    let encrypted = encrypt_object(&data, &tenant1)?;
+    assert!(!encrypted
+        .windows(data.len())
+        .any(|window| window == &data[..]));
+
+    assert_eq!(decrypt_object(&encrypted, &tenant1)?, data);
+    assert!(decrypt_object(&encrypted, &tenant2).is_err());
+    assert!(decrypt_object(&data, &tenant1).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_chunks_stored_per_key() -> Result<(), Error> {
+    let testdir = create_testdir("test_chunks_stored_per_key")?;
+
+    let mut media_set = MediaSetCatalog::new(MediaSetLabel {
+        uuid: Uuid::generate(),
+        ctime: 1_600_000_000,
+        base: None,
+        node: "testnode".to_string(),
+    });
+    media_set.archives.push(ChunkArchiveEntry {
+        uuid: Uuid::generate(),
+        store: "store1".to_string(),
+        key: Some(fingerprint(1)),
+        size: 100,
+        csum: None,
+        chunks: vec![ChunkEntry {
+            digest: digest(1),
+            offset: 0,
+            size: 100,
+        }],
+    });
+
+    let catalog = CloudCatalog::from_media_sets(&testdir, "test", vec![media_set.clone()]);
+    assert!(catalog.contains_chunk(&digest(1)));
+    assert!(catalog.chain_contains_chunk(&digest(1), Some(&fingerprint(1))));
+    assert!(!catalog.chain_contains_chunk(&digest(1), Some(&fingerprint(2))));
+    assert!(!catalog.chain_contains_chunk(&digest(1), None));
+    assert_eq!(
+        catalog.find_chunk(&digest(1)).unwrap().key,
+        Some(fingerprint(1))
+    );
+
+    // an incremental media set must upload the chunk again for another tenant
+    let mut catalog_set = CatalogSet::new(catalog);
+    catalog_set.start_media_set(MediaSetCatalog::new(MediaSetLabel {
+        uuid: Uuid::generate(),
+        ctime: 1_600_003_600,
+        base: Some(media_set.uuid().clone()),
+        node: "testnode".to_string(),
+    }))?;
+    assert!(catalog_set.contains_chunk(&digest(1), Some(&fingerprint(1))));
+    assert!(!catalog_set.contains_chunk(&digest(1), Some(&fingerprint(2))));
+
+    Ok(())
+}
+
+#[test]
+fn test_tenant_key() -> Result<(), Error> {
+    let (key, key_config) = KeyConfig::new(b"tenant-password", Kdf::Scrypt)?;
+    let expected = CryptConfig::new(key)?;
+    let data = encrypt_object(b"tenant data", &expected)?;
+
+    let mut password = "wrong-password".to_string();
+    assert!(TenantKey::decrypt(&key_config, &mut password).is_err());
+    assert!(password.is_empty());
+
+    let mut password = "tenant-password".to_string();
+    let tenant_key = TenantKey::decrypt(&key_config, &mut password)?;
+    assert!(password.is_empty());
+
+    let fingerprint = key_config.fingerprint.clone().unwrap();
+    assert_eq!(tenant_key.fingerprint(), &fingerprint);
+    tenant_key.check_fingerprint(&fingerprint)?;
+    assert!(tenant_key.check_fingerprint(&self::fingerprint(1)).is_err());
+
+    let crypt_config = tenant_key.crypt_config()?;
+    assert_eq!(decrypt_object(&data, &crypt_config)?, b"tenant data");
+
+    Ok(())
+}
+
+#[test]
+fn test_tenant_key_matches_key_config() -> Result<(), Error> {
+    let (key, mut key_config) = KeyConfig::new(b"tenant-password", Kdf::PBKDF2)?;
+    let (decrypted, _created, fingerprint) =
+        key_config.decrypt(&|| Ok(b"tenant-password".to_vec()))?;
+    assert_eq!(decrypted, key);
+
+    let mut password = "tenant-password".to_string();
+    let tenant_key = TenantKey::decrypt(&key_config, &mut password)?;
+    assert_eq!(tenant_key.fingerprint(), &fingerprint);
+
+    // too short passwords are rejected before deriving anything
+    let mut password = "abc".to_string();
+    assert!(TenantKey::decrypt(&key_config, &mut password).is_err());
+    assert!(password.is_empty());
+
+    // the stored fingerprint must match the contained key
+    key_config.fingerprint = Some(self::fingerprint(1));
+    let mut password = "tenant-password".to_string();
+    assert!(TenantKey::decrypt(&key_config, &mut password).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+// Endpoint failover tests (against the mock backend)
+//
+// # cargo test --release cloud::test::endpoint_failover
+
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use crate::cloud::backend::{
+    is_endpoint_unreachable, CloudBackend, FailoverBackend, MockCloudBackend, MockFaults,
+};
+
+fn unreachable() -> MockFaults {
+    MockFaults {
+        unreachable: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_endpoint_failover() -> Result<(), Error> {
+    let site1 = Arc::new(MockCloudBackend::with_faults(unreachable()));
+    let site2 = Arc::new(MockCloudBackend::new());
+    let site3 = Arc::new(MockCloudBackend::new());
+
+    let backend = FailoverBackend::new(vec![
+        ("site1".to_string(), site1.clone() as Arc<dyn CloudBackend>),
+        ("site2".to_string(), site2.clone() as Arc<dyn CloudBackend>),
+        ("site3".to_string(), site3.clone() as Arc<dyn CloudBackend>),
+    ]);
+    assert_eq!(backend.current_endpoint(), "site1");
+
+    backend.put_object("a", b"data")?;
+    assert_eq!(backend.current_endpoint(), "site2");
+    assert_eq!(site2.get_object("a")?, b"data");
+
+    // sticky until the endpoint fails, even if a preferred one is back
+    site1.set_faults(MockFaults::default());
+    backend.put_object("b", b"data")?;
+    assert_eq!(backend.current_endpoint(), "site2");
+    assert!(site1.head_object("b")?.is_none());
+
+    // wraps around to endpoints of higher priority
+    site2.set_faults(unreachable());
+    site3.set_faults(unreachable());
+    backend.put_object("c", b"data")?;
+    assert_eq!(backend.current_endpoint(), "site1");
+
+    // all endpoints unreachable
+    site1.set_faults(unreachable());
+    let err = backend.head_object("a").unwrap_err();
+    assert!(is_endpoint_unreachable(&err));
+    assert_eq!(backend.current_endpoint(), "site1");
+
+    Ok(())
+}
+
+#[test]
+fn test_no_failover_on_request_errors() -> Result<(), Error> {
+    let site1 = Arc::new(MockCloudBackend::with_faults(MockFaults {
+        fail_keys: ["a".to_string()].into_iter().collect(),
+        ..Default::default()
+    }));
+    let site2 = Arc::new(MockCloudBackend::new());
+
+    let backend = FailoverBackend::new(vec![
+        ("site1".to_string(), site1 as Arc<dyn CloudBackend>),
+        ("site2".to_string(), site2.clone() as Arc<dyn CloudBackend>),
+    ]);
+
+    // the endpoint answered, so the error is returned as is
+    let err = backend.put_object("a", b"data").unwrap_err();
+    assert!(!is_endpoint_unreachable(&err));
+    assert_eq!(backend.current_endpoint(), "site1");
+    assert!(site2.head_object("a")?.is_none());
+
+    Ok(())
+}
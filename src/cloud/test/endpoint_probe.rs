@@ -0,0 +1,77 @@
+// Endpoint latency probing tests
+//
+// # cargo test --release cloud::test::endpoint_probe
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Error;
+
+use crate::cloud::backend::{probe_endpoints, CloudBackend, MockCloudBackend, MockFaults};
+use crate::cloud::layout::LEASE_KEY;
+
+fn mock_endpoint(name: &str, faults: MockFaults) -> (String, Arc<dyn CloudBackend>) {
+    (
+        name.to_string(),
+        Arc::new(MockCloudBackend::with_faults(faults)),
+    )
+}
+
+fn with_latency(ms: u64) -> MockFaults {
+    MockFaults {
+        latency: Some(Duration::from_millis(ms)),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_select_fastest_endpoint() -> Result<(), Error> {
+    let mut unreachable = MockFaults::default();
+    unreachable.fail_keys.insert(LEASE_KEY.to_string());
+
+    let candidates = vec![
+        mock_endpoint("s3.eu-central-1.example.com", with_latency(40)),
+        mock_endpoint("s3.eu-west-1.example.com", with_latency(5)),
+        mock_endpoint("s3-accelerate.example.com", unreachable),
+    ];
+
+    let (results, selected) = probe_endpoints(&candidates);
+    assert_eq!(selected, Some(1));
+    assert_eq!(results.len(), 3);
+
+    assert!(results[0].latency.unwrap() >= 40);
+    assert!(results[1].latency.unwrap() < results[0].latency.unwrap());
+    assert!(results[1].selected);
+    assert!(!results[0].selected);
+
+    assert!(results[2].latency.is_none());
+    assert!(results[2].error.is_some());
+    assert!(!results[2].selected);
+
+    Ok(())
+}
+
+#[test]
+fn test_no_reachable_endpoint() -> Result<(), Error> {
+    let mut unreachable = MockFaults::default();
+    unreachable.fail_keys.insert(LEASE_KEY.to_string());
+
+    let candidates = vec![
+        mock_endpoint("a.example.com", unreachable.clone()),
+        mock_endpoint("b.example.com", unreachable),
+    ];
+
+    let (results, selected) = probe_endpoints(&candidates);
+    assert_eq!(selected, None);
+    assert!(results.iter().all(|result| !result.selected));
+
+    // equal latency prefers the configured (first) endpoint
+    let candidates = vec![
+        mock_endpoint("a.example.com", MockFaults::default()),
+        mock_endpoint("b.example.com", MockFaults::default()),
+    ];
+    let (_results, selected) = probe_endpoints(&candidates);
+    assert_eq!(selected, Some(0));
+
+    Ok(())
+}
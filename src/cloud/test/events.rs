@@ -0,0 +1,192 @@
+// Job lifecycle event tests
+//
+// # cargo test --release cloud::test::events
+
+use anyhow::{format_err, Error};
+use serde_json::json;
+
+use crate::cloud::backend::credentials::CloudCredentials;
+use crate::cloud::events::{
+    event_destinations, sign_form_request, CloudEvent, CloudEventKind, EventDestination,
+    CLOUD_EVENT_VERSION,
+};
+
+use super::harness::test_target;
+
+// 2015-08-30T12:36:00Z
+const SIGN_TIME: i64 = 1_440_938_160;
+
+fn test_credentials(session_token: Option<&str>) -> CloudCredentials {
+    CloudCredentials {
+        access_key: "AKIDEXAMPLE".to_string(),
+        secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+        session_token: session_token.map(str::to_string),
+        expires: None,
+    }
+}
+
+#[test]
+fn test_event_destinations() -> Result<(), Error> {
+    let topic = EventDestination::topic("arn:aws:sns:eu-central-1:123456789012:backups")?;
+    assert_eq!(
+        topic.host_and_path(),
+        (
+            "sns.eu-central-1.amazonaws.com".to_string(),
+            "/".to_string()
+        )
+    );
+    let topic = EventDestination::topic("arn:aws-cn:sns:cn-north-1:123456789012:backups")?;
+    assert_eq!(topic.host_and_path().0, "sns.cn-north-1.amazonaws.com.cn");
+    assert!(EventDestination::topic("arn:aws:sqs:eu-central-1:123456789012:backups").is_err());
+
+    let queue =
+        EventDestination::queue("https://sqs.us-west-2.amazonaws.com/123456789012/pbs-events")?;
+    assert_eq!(
+        queue,
+        EventDestination::Queue {
+            url: "https://sqs.us-west-2.amazonaws.com/123456789012/pbs-events".to_string(),
+            region: "us-west-2".to_string(),
+        }
+    );
+    assert_eq!(
+        queue.host_and_path(),
+        (
+            "sqs.us-west-2.amazonaws.com".to_string(),
+            "/123456789012/pbs-events".to_string()
+        )
+    );
+    assert!(EventDestination::queue("https://example.com/123456789012/pbs-events").is_err());
+
+    let mut target = test_target("events");
+    assert!(event_destinations(&target.config)?.is_empty());
+    target.config.event_topic = Some("arn:aws:sns:eu-central-1:123456789012:backups".into());
+    target.config.event_queue =
+        Some("https://sqs.us-west-2.amazonaws.com/123456789012/pbs-events".into());
+    assert_eq!(event_destinations(&target.config)?.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_request_body() -> Result<(), Error> {
+    let topic = EventDestination::topic("arn:aws:sns:eu-central-1:123456789012:backups")?;
+    assert_eq!(
+        topic.request_body("{\"a\":1}", "target1"),
+        "Action=Publish&TopicArn=arn%3Aaws%3Asns%3Aeu-central-1%3A123456789012%3Abackups\
+            &Message=%7B%22a%22%3A1%7D&Version=2010-03-31"
+    );
+
+    // FIFO queues need a message group and a deduplication ID
+    let queue = EventDestination::queue(
+        "https://sqs.us-west-2.amazonaws.com/123456789012/pbs-events.fifo",
+    )?;
+    let body = queue.request_body("message", "target1");
+    assert!(body.starts_with("Action=SendMessage&MessageBody=message&Version=2012-11-05"));
+    assert!(body.contains("&MessageGroupId=target1&MessageDeduplicationId="));
+    assert_eq!(body, queue.request_body("message", "target1"));
+    assert_ne!(body, queue.request_body("other message", "target1"));
+
+    Ok(())
+}
+
+#[test]
+fn test_event_message() -> Result<(), Error> {
+    let event = CloudEvent::job_started("target1", "cloud-backup-job", "job1", "UPID:1");
+    let value = serde_json::to_value(&event)?;
+    assert_eq!(value["version"], CLOUD_EVENT_VERSION);
+    assert_eq!(value["event"], "started");
+    assert_eq!(value["target"], "target1");
+    assert_eq!(value["job-type"], "cloud-backup-job");
+    assert_eq!(value["job-id"], "job1");
+    assert!(value.get("error").is_none());
+    assert!(value.get("summary").is_none());
+
+    let result = Err(format_err!("upload failed"));
+    let event = CloudEvent::job_finished(
+        "target1",
+        "cloud-backup-job",
+        "job1",
+        "UPID:1",
+        &result,
+        json!({ "duration": 10 }),
+    );
+    assert_eq!(event.event, CloudEventKind::Failed);
+    let value = serde_json::to_value(&event)?;
+    assert_eq!(value["error"], "upload failed");
+    assert_eq!(value["summary"]["duration"], 10);
+
+    let event = CloudEvent::job_finished("target1", "t", "job1", "UPID:1", &Ok(()), json!({}));
+    assert_eq!(event.event, CloudEventKind::Completed);
+    assert!(event.error.is_none());
+
+    let event = CloudEvent::snapshot_pruned("target1", "store1:vm/100/2023-01-01T00:00:00Z", "U");
+    let value = serde_json::to_value(&event)?;
+    assert_eq!(value["event"], "pruned");
+    assert_eq!(value["snapshot"], "store1:vm/100/2023-01-01T00:00:00Z");
+    assert!(value.get("job-id").is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_sign_form_request() -> Result<(), Error> {
+    let credentials = test_credentials(None);
+    let headers = sign_form_request(
+        &credentials,
+        "sns",
+        "us-east-1",
+        "sns.us-east-1.amazonaws.com",
+        "/",
+        "Action=Publish",
+        SIGN_TIME,
+    )?;
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value.clone())
+    };
+    assert_eq!(header("x-amz-date").as_deref(), Some("20150830T123600Z"));
+    assert!(header("host").is_none());
+    assert!(header("x-amz-security-token").is_none());
+
+    let authorization = header("authorization").unwrap();
+    let prefix = "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/sns/aws4_request, \
+        SignedHeaders=content-type;host;x-amz-date, Signature=";
+    assert!(authorization.starts_with(prefix));
+    assert_eq!(authorization.len(), prefix.len() + 64);
+
+    // the signature covers the body
+    let other = sign_form_request(
+        &credentials,
+        "sns",
+        "us-east-1",
+        "sns.us-east-1.amazonaws.com",
+        "/",
+        "Action=Publish&Message=x",
+        SIGN_TIME,
+    )?;
+    assert_ne!(headers, other);
+
+    // temporary credentials sign the session token
+    let credentials = test_credentials(Some("token"));
+    let headers = sign_form_request(
+        &credentials,
+        "sqs",
+        "us-west-2",
+        "sqs.us-west-2.amazonaws.com",
+        "/123456789012/pbs-events",
+        "Action=SendMessage",
+        SIGN_TIME,
+    )?;
+    assert!(headers
+        .iter()
+        .any(|(name, value)| *name == "x-amz-security-token" && value == "token"));
+    let (_, authorization) = headers.iter().find(|(n, _)| *n == "authorization").unwrap();
+    assert!(authorization.contains("/us-west-2/sqs/aws4_request"));
+    assert!(
+        authorization.contains("SignedHeaders=content-type;host;x-amz-date;x-amz-security-token")
+    );
+
+    Ok(())
+}
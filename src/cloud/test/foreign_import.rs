@@ -0,0 +1,242 @@
+// Foreign backup import tests (against the mock backend)
+//
+// # cargo test --release cloud::test::foreign_import
+
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use pbs_api_types::{BackupNamespace, CloudForeignFormat, CryptMode};
+use pbs_datastore::file_formats::FIXED_SIZED_CHUNK_INDEX_1_0;
+use pbs_datastore::manifest::{BackupManifest, MANIFEST_BLOB_NAME};
+use pbs_datastore::DataBlob;
+
+use crate::cloud::backend::{CloudBackend, ObjectInfo};
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::chunk_reader::CloudChunkReader;
+use crate::cloud::foreign_import::{
+    detect_layouts, import_datastore, index_digests, parse_datastore_key, DatastoreObject,
+};
+use crate::cloud::layout;
+use crate::cloud::trash::move_to_trash;
+
+use super::harness::{chunk_data, create_testdir, digest, TestTarget, TestWorker};
+
+fn object(key: &str) -> ObjectInfo {
+    ObjectInfo {
+        key: key.to_string(),
+        size: 1,
+        mtime: 0,
+        etag: None,
+        storage_class: None,
+        retain_until: None,
+    }
+}
+
+fn fixed_index(digests: &[[u8; 32]]) -> Vec<u8> {
+    let mut data = vec![0u8; 4096];
+    data[..8].copy_from_slice(&FIXED_SIZED_CHUNK_INDEX_1_0);
+    for digest in digests {
+        data.extend_from_slice(digest);
+    }
+    data
+}
+
+// copy a snapshot of a datastore below `prefix`, as rclone would
+fn copy_snapshot(
+    backend: &dyn CloudBackend,
+    prefix: &str,
+    snapshot: &str,
+    chunks: &[[u8; 32]],
+) -> Result<(), Error> {
+    let mut manifest = BackupManifest::new(snapshot.parse()?);
+    manifest.add_file("disk.img.fidx".to_string(), 0, [0; 32], CryptMode::None)?;
+    let blob = DataBlob::encode(manifest.to_string(None)?.as_bytes(), None, true)?;
+
+    let dir = format!("{}/{}", prefix, snapshot);
+    backend.put_object(&format!("{}/{}", dir, MANIFEST_BLOB_NAME), blob.raw_data())?;
+    backend.put_object(&format!("{}/disk.img.fidx", dir), &fixed_index(chunks))?;
+    for digest in chunks {
+        backend.put_object(
+            &layout::datastore_chunk_key(prefix, digest),
+            &chunk_data(digest),
+        )?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parse_datastore_key() -> Result<(), Error> {
+    let chunk = hex::encode(digest(1));
+    assert_eq!(
+        parse_datastore_key(&format!(".chunks/{}/{}", &chunk[..4], chunk)),
+        Some(DatastoreObject::Chunk(digest(1)))
+    );
+    assert_eq!(
+        parse_datastore_key(&format!(".chunks/0000/{}", chunk)),
+        None
+    );
+
+    assert_eq!(
+        parse_datastore_key("ns/a/ns/b/vm/100/2020-01-01T00:00:00Z/index.json.blob"),
+        Some(DatastoreObject::SnapshotFile {
+            ns: BackupNamespace::new("a/b")?,
+            snapshot: "vm/100/2020-01-01T00:00:00Z".parse()?,
+            filename: "index.json.blob".to_string(),
+        })
+    );
+    assert_eq!(
+        parse_datastore_key("host/a/owner"),
+        Some(DatastoreObject::Owner {
+            ns: BackupNamespace::root(),
+            group: "host/a".parse()?,
+        })
+    );
+    assert_eq!(parse_datastore_key(".gc-status"), None);
+    assert_eq!(
+        parse_datastore_key("host/a/2020-01-01T00:00:00Z/.lock"),
+        None
+    );
+    assert_eq!(parse_datastore_key("other/a/b/c/file"), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_detect_layouts() -> Result<(), Error> {
+    let chunk = hex::encode(digest(1));
+    let objects = vec![
+        object(&format!("old/.chunks/{}/{}", &chunk[..4], chunk)),
+        object("old/host/a/owner"),
+        object("old/host/a/2020-01-01T00:00:00Z/index.json.blob"),
+        object("old/host/a/2020-01-02T00:00:00Z/index.json.blob"),
+        object("restic/config"),
+        object("restic/keys/0123"),
+        object("restic/data/01/0123"),
+        object("not-restic/config"),
+        object("media-set/x/snapshot/store1/host/a/2020-01-01T00:00:00Z/index.json.blob"),
+    ];
+
+    let list = detect_layouts(&objects);
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0].prefix, "old");
+    assert_eq!(list[0].format, CloudForeignFormat::PbsDatastore);
+    assert_eq!(list[0].snapshots, Some(2));
+    assert!(list[0].importable);
+    assert_eq!(list[1].prefix, "restic");
+    assert_eq!(list[1].format, CloudForeignFormat::Restic);
+    assert!(!list[1].importable);
+
+    assert!(detect_layouts(&[object("media-set/x/label.json")]).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_index_digests() -> Result<(), Error> {
+    let digests = vec![digest(1), digest(2)];
+    assert_eq!(index_digests(&fixed_index(&digests))?, digests);
+    assert!(index_digests(&fixed_index(&digests)[..4100]).is_err());
+    assert!(index_digests(&[0u8; 4096]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_import_datastore() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_import_datastore")?);
+    let worker = TestWorker::default();
+    let backend = target.backend();
+
+    target.write_media_set(
+        None,
+        &[digest(9)],
+        &[("host/b/2020-01-01T00:00:00Z", vec![digest(9)])],
+    )?;
+
+    copy_snapshot(
+        &*backend,
+        "old",
+        "host/a/2020-01-01T00:00:00Z",
+        &[digest(1), digest(2)],
+    )?;
+    copy_snapshot(
+        &*backend,
+        "old",
+        "host/a/2020-01-02T00:00:00Z",
+        &[digest(2), digest(3)],
+    )?;
+    backend.put_object("old/host/a/owner", b"root@pam\n")?;
+    // incomplete copy
+    copy_snapshot(
+        &*backend,
+        "old",
+        "host/c/2020-01-01T00:00:00Z",
+        &[digest(4)],
+    )?;
+    backend.delete_object(&layout::datastore_chunk_key("old", &digest(4)))?;
+
+    let objects = backend.list_objects("")?.len();
+    let uuid = import_datastore(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &backend,
+        "old",
+        "store1",
+    )?
+    .unwrap();
+
+    // only the label and catalog were written
+    assert_eq!(backend.list_objects("")?.len(), objects + 2);
+
+    let catalog = Arc::new(CloudCatalog::load(&target.base_path, "test")?);
+    let media_set = catalog.lookup_media_set(&uuid).unwrap();
+    assert!(media_set.is_imported());
+    assert_eq!(media_set.snapshots.len(), 2);
+    assert_eq!(media_set.snapshots[0].owner, Some("root@pam".parse()?));
+    assert_eq!(media_set.archives[0].chunks.len(), 3);
+
+    // imported media sets are no part of the chain
+    assert_eq!(catalog.media_sets()[0].uuid(), &uuid);
+    assert_eq!(catalog.current_chain().len(), 1);
+    assert!(!catalog.chain_contains_chunk(&digest(1), None));
+    assert_ne!(catalog.last_media_set().unwrap().uuid(), &uuid);
+
+    // chunks and files are read from the copied datastore
+    let reader =
+        CloudChunkReader::new(backend.clone(), Arc::clone(&catalog), None)?.with_cache(None);
+    assert_eq!(reader.fetch_chunk(&digest(3))?, chunk_data(&digest(3)));
+    let (media_set, entry) = catalog
+        .snapshots()
+        .find(|(set, _)| set.is_imported())
+        .unwrap();
+    assert_eq!(
+        media_set.snapshot_file_key(entry, MANIFEST_BLOB_NAME),
+        "old/host/a/2020-01-01T00:00:00Z/index.json.blob"
+    );
+
+    // nothing new to import
+    assert!(import_datastore(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &backend,
+        "old",
+        "store1"
+    )?
+    .is_none());
+
+    // imported snapshots are read-only
+    assert!(move_to_trash(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &backend,
+        "store1",
+        &entry.ns,
+        &entry.snapshot,
+    )
+    .is_err());
+
+    Ok(())
+}
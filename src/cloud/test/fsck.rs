@@ -0,0 +1,405 @@
+// Consistency check tests
+//
+// # cargo test --release cloud::test::fsck
+
+use anyhow::{bail, Error};
+
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{BackupNamespace, CloudFsckIssueKind, CloudFsckReport};
+
+use crate::cloud::backend::{CloudBackend, MockFaults};
+use crate::cloud::fsck::{fsck_target, load_fsck_report, FsckOptions};
+use crate::cloud::layout;
+
+use super::harness::{chunk_data, create_testdir, digest, TestTarget, TestWorker, TEST_STORE};
+
+fn local_chunk(_store: &str, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+    Ok(chunk_data(digest))
+}
+
+fn issues(report: &CloudFsckReport) -> Vec<(CloudFsckIssueKind, String, bool)> {
+    report
+        .issues
+        .iter()
+        .map(|issue| (issue.kind, issue.key.clone(), issue.repaired))
+        .collect()
+}
+
+#[test]
+fn test_fsck_clean() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_fsck_clean")?);
+    let worker = TestWorker::default();
+
+    target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+
+    let options = FsckOptions {
+        verify: true,
+        repair: false,
+    };
+    let report = fsck_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        options,
+        &local_chunk,
+    )?;
+    assert!(report.issues.is_empty());
+    assert_eq!(report.objects, 4);
+    assert_eq!(report.checked, 4);
+    assert_eq!(report.verified, 1);
+
+    assert_eq!(load_fsck_report(&target.base_path, "test")?, Some(report));
+
+    Ok(())
+}
+
+#[test]
+fn test_fsck_problems() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_fsck_problems")?);
+    let worker = TestWorker::default();
+
+    let media_set = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    let uuid = media_set.uuid();
+    let archive_key = layout::chunk_archive_key(uuid, &media_set.archives[0].uuid);
+    let file_key = layout::snapshot_file_key(
+        uuid,
+        TEST_STORE,
+        &BackupNamespace::root(),
+        &media_set.snapshots[0].snapshot,
+        "index.json.blob",
+    );
+
+    // same size, different content
+    let mut data = target.backend.get_object(&archive_key)?;
+    data[0] ^= 0xff;
+    assert!(target.backend.corrupt_object(&archive_key, &data));
+    assert!(target.backend.lose_object(&file_key));
+
+    let orphan_key = layout::chunk_archive_key(uuid, &Uuid::generate());
+    target.backend.put_object(&orphan_key, b"orphan")?;
+
+    // aborted backup, only the label was written
+    let dangling = Uuid::generate();
+    target
+        .backend
+        .put_object(&layout::media_set_label_key(&dangling), b"{}")?;
+
+    // committed by another node, not in the local catalog
+    let unknown = Uuid::generate();
+    target
+        .backend
+        .put_object(&layout::media_set_catalog_key(&unknown), b"{}")?;
+
+    // without 'verify', the corrupted archive is not noticed
+    let report = fsck_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        FsckOptions::default(),
+        &local_chunk,
+    )?;
+    assert_eq!(report.verified, 0);
+    assert_eq!(report.issues.len(), 4);
+
+    let options = FsckOptions {
+        verify: true,
+        repair: false,
+    };
+    let report = fsck_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        options,
+        &local_chunk,
+    )?;
+    let mut expected = vec![
+        (CloudFsckIssueKind::Missing, file_key, false),
+        (CloudFsckIssueKind::DigestMismatch, archive_key, false),
+        (CloudFsckIssueKind::Orphaned, orphan_key.clone(), false),
+        (
+            CloudFsckIssueKind::DanglingMediaSet,
+            layout::media_set_prefix(&dangling),
+            false,
+        ),
+        (
+            CloudFsckIssueKind::UnknownMediaSet,
+            layout::media_set_prefix(&unknown),
+            false,
+        ),
+    ];
+    let mut found = issues(&report);
+    expected.sort_by(|a, b| a.1.cmp(&b.1));
+    found.sort_by(|a, b| a.1.cmp(&b.1));
+    assert_eq!(found, expected);
+    assert_eq!(report.unrepaired(), 5);
+
+    // nothing was deleted
+    assert!(target.backend.head_object(&orphan_key)?.is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_fsck_repair() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_fsck_repair")?);
+    let worker = TestWorker::default();
+
+    let media_set = target.write_media_set(
+        None,
+        &[digest(1)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1)])],
+    )?;
+    let uuid = media_set.uuid();
+
+    let orphan_key = layout::chunk_archive_key(uuid, &Uuid::generate());
+    target.backend.put_object(&orphan_key, b"orphan")?;
+
+    let dangling = Uuid::generate();
+    let dangling_keys = [
+        layout::media_set_label_key(&dangling),
+        layout::chunk_archive_key(&dangling, &Uuid::generate()),
+    ];
+    for key in dangling_keys.iter() {
+        target.backend.put_object(key, b"data")?;
+    }
+
+    let unknown_key = layout::media_set_catalog_key(&Uuid::generate());
+    target.backend.put_object(&unknown_key, b"{}")?;
+
+    let options = FsckOptions {
+        verify: false,
+        repair: true,
+    };
+    let report = fsck_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        options,
+        &local_chunk,
+    )?;
+    assert_eq!(report.issues.len(), 3);
+    assert_eq!(report.unrepaired(), 1);
+
+    assert!(target.backend.head_object(&orphan_key)?.is_none());
+    for key in dangling_keys.iter() {
+        assert!(target.backend.head_object(key)?.is_none());
+    }
+    // committed media sets of other nodes are kept
+    assert!(target.backend.head_object(&unknown_key)?.is_some());
+    // the lease was released
+    assert!(target.backend.head_object(layout::LEASE_KEY)?.is_none());
+
+    // orphaned objects are kept if the local catalog is outdated
+    target.backend.put_object(&orphan_key, b"orphan")?;
+    let mut outdated = media_set.clone();
+    outdated.snapshots.clear();
+    target.backend.put_object(
+        &layout::media_set_catalog_key(uuid),
+        &serde_json::to_vec(&outdated)?,
+    )?;
+
+    let report = fsck_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        options,
+        &local_chunk,
+    )?;
+    assert_eq!(report.unrepaired(), 2);
+    assert!(target.backend.head_object(&orphan_key)?.is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_fsck_reupload() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_fsck_reupload")?);
+    let worker = TestWorker::default();
+
+    let media_set = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    let archive_key = layout::chunk_archive_key(media_set.uuid(), &media_set.archives[0].uuid);
+    let data = target.backend.get_object(&archive_key)?;
+
+    let mut corrupted = data.clone();
+    corrupted[0] ^= 0xff;
+    assert!(target.backend.corrupt_object(&archive_key, &corrupted));
+
+    let options = FsckOptions {
+        verify: true,
+        repair: true,
+    };
+
+    // chunks no longer available locally
+    let report = fsck_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        options,
+        &|_, _| bail!("chunk missing"),
+    )?;
+    assert_eq!(report.unrepaired(), 1);
+    assert_eq!(target.backend.get_object(&archive_key)?, corrupted);
+
+    let report = fsck_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        options,
+        &local_chunk,
+    )?;
+    assert_eq!(
+        issues(&report),
+        vec![(
+            CloudFsckIssueKind::DigestMismatch,
+            archive_key.clone(),
+            true
+        )]
+    );
+    assert_eq!(target.backend.get_object(&archive_key)?, data);
+
+    let report = fsck_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        options,
+        &local_chunk,
+    )?;
+    assert!(report.issues.is_empty());
+    assert_eq!(load_fsck_report(&target.base_path, "test")?, Some(report));
+
+    Ok(())
+}
+
+#[test]
+fn test_fsck_provider_checksums() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_fsck_provider_checksums")?);
+    let worker = TestWorker::default();
+
+    target.backend.set_provider_checksums(true);
+    let first = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    let second = target.write_media_set(
+        Some(first.uuid()),
+        &[digest(3)],
+        &[("host/a/2020-01-02T00:00:00Z", vec![digest(3)])],
+    )?;
+
+    let options = FsckOptions {
+        verify: true,
+        repair: false,
+    };
+    let report = fsck_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        options,
+        &local_chunk,
+    )?;
+    assert!(report.issues.is_empty());
+    assert_eq!(report.verified, 2);
+    assert_eq!(report.provider_checksums, 2);
+
+    // replaced with other data of the same size
+    let first_key = layout::chunk_archive_key(first.uuid(), &first.archives[0].uuid);
+    let mut data = target.backend.get_object(&first_key)?;
+    data[0] ^= 0xff;
+    target.backend.put_object(&first_key, &data)?;
+
+    // multipart uploads have no checksum of the whole object
+    let second_key = layout::chunk_archive_key(second.uuid(), &second.archives[0].uuid);
+    let data = target.backend.get_object(&second_key)?;
+    target.backend.set_part_size(data.len() / 2);
+    target
+        .backend
+        .put_object_multipart(&second_key, &data, &Default::default())?;
+    assert_eq!(target.backend.object_checksum(&second_key)?, None);
+
+    let report = fsck_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        options,
+        &local_chunk,
+    )?;
+    assert_eq!(
+        issues(&report),
+        vec![(CloudFsckIssueKind::DigestMismatch, first_key, false)]
+    );
+    assert_eq!(report.verified, 2);
+    assert_eq!(report.provider_checksums, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_fsck_verify_throttled() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_fsck_verify_throttled")?);
+    let worker = TestWorker::default();
+
+    let mut base = None;
+    for n in 1..=20u8 {
+        let snapshot = format!("host/a/2020-01-{:02}T00:00:00Z", n);
+        let media_set = target.write_media_set(
+            base.as_ref(),
+            &[digest(n)],
+            &[(snapshot.as_str(), vec![digest(n)])],
+        )?;
+        base = Some(media_set.uuid().clone());
+    }
+
+    // the provider rejects every third request, but not the listing
+    while (target.backend.request_count() + 1) % 3 == 0 {
+        target.backend.head_object("unknown")?;
+    }
+    target.backend.set_faults(MockFaults {
+        throttle_every: Some(3),
+        ..Default::default()
+    });
+    let requests = target.backend.request_count();
+
+    let options = FsckOptions {
+        verify: true,
+        repair: false,
+    };
+    let report = fsck_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        options,
+        &local_chunk,
+    )?;
+    assert!(report.issues.is_empty());
+    assert_eq!(report.verified, 20);
+
+    // throttled objects were verified again
+    assert!(target.backend.request_count() - requests > 1 + 2 * 20);
+
+    Ok(())
+}
@@ -0,0 +1,194 @@
+// Helpers to run cloud logic against a MockCloudBackend
+//
+// Builds media set chains directly (catalog and objects), so tests do
+// not need a datastore or a running proxy.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use proxmox_sys::WorkerTaskContext;
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{BackupDir, CloudProvider, CloudTarget, CloudTargetConfig};
+
+use crate::cloud::backend::{CloudBackend, MockCloudBackend};
+use crate::cloud::catalog::{
+    ChunkArchiveEntry, ChunkEntry, MediaSetCatalog, MediaSetLabel, SnapshotEntry, SnapshotFileEntry,
+};
+use crate::cloud::layout;
+
+pub const TEST_STORE: &str = "store1";
+
+pub fn create_testdir(name: &str) -> Result<PathBuf, Error> {
+    let mut testdir: PathBuf = String::from("./target/testout").into();
+    testdir.push(std::module_path!());
+    testdir.push(name);
+
+    let _ = std::fs::remove_dir_all(&testdir);
+    let _ = std::fs::create_dir_all(&testdir);
+
+    Ok(testdir)
+}
+
+/// Worker context logging to stdout
+#[derive(Default)]
+pub struct TestWorker {
+    abort: AtomicBool,
+}
+
+impl TestWorker {
+    pub fn request_abort(&self) {
+        self.abort.store(true, Ordering::SeqCst);
+    }
+}
+
+impl WorkerTaskContext for TestWorker {
+    fn abort_requested(&self) -> bool {
+        self.abort.load(Ordering::SeqCst)
+    }
+
+    fn shutdown_requested(&self) -> bool {
+        false
+    }
+
+    fn log(&self, level: log::Level, message: &std::fmt::Arguments) {
+        println!("{}: {}", level, message);
+    }
+}
+
+pub fn test_target(name: &str) -> CloudTarget {
+    CloudTarget {
+        name: name.to_string(),
+        secret_key: String::new(),
+        config: CloudTargetConfig {
+            provider: CloudProvider::Local,
+            path: Some("/nonexistent".to_string()),
+            ..Default::default()
+        },
+    }
+}
+
+/// Deterministic fake chunk digest
+pub fn digest(n: u8) -> [u8; 32] {
+    [n; 32]
+}
+
+/// Fake chunk content (size depends on digest)
+pub fn chunk_data(digest: &[u8; 32]) -> Vec<u8> {
+    vec![digest[0]; 16 + digest[0] as usize]
+}
+
+/// A target with a mock backend and a local catalog directory
+pub struct TestTarget {
+    pub base_path: PathBuf,
+    pub target: CloudTarget,
+    pub backend: Arc<MockCloudBackend>,
+    ctime: i64,
+}
+
+impl TestTarget {
+    pub fn new(testdir: PathBuf) -> Self {
+        Self {
+            base_path: testdir,
+            target: test_target("test"),
+            backend: Arc::new(MockCloudBackend::new()),
+            ctime: 1_600_000_000,
+        }
+    }
+
+    pub fn backend(&self) -> Arc<dyn CloudBackend> {
+        self.backend.clone()
+    }
+
+    /// Write a committed media set containing the given snapshots
+    ///
+    /// Each snapshot is `(backup-dir, chunk digests)`. Chunks listed in
+    /// `new_chunks` are stored in a single chunk archive.
+    pub fn write_media_set(
+        &mut self,
+        base: Option<&Uuid>,
+        new_chunks: &[[u8; 32]],
+        snapshots: &[(&str, Vec<[u8; 32]>)],
+    ) -> Result<MediaSetCatalog, Error> {
+        self.ctime += 3600;
+
+        let label = MediaSetLabel {
+            uuid: Uuid::generate(),
+            ctime: self.ctime,
+            base: base.cloned(),
+            node: "testnode".to_string(),
+        };
+        self.backend.put_object(
+            &layout::media_set_label_key(&label.uuid),
+            &serde_json::to_vec(&label)?,
+        )?;
+
+        let mut media_set = MediaSetCatalog::new(label);
+
+        if !new_chunks.is_empty() {
+            let mut data = Vec::new();
+            let mut chunks = Vec::new();
+            for digest in new_chunks {
+                let raw = chunk_data(digest);
+                chunks.push(ChunkEntry {
+                    digest: *digest,
+                    offset: data.len() as u64,
+                    size: raw.len() as u64,
+                });
+                data.extend_from_slice(&raw);
+            }
+            let uuid = Uuid::generate();
+            self.backend
+                .put_object(&layout::chunk_archive_key(media_set.uuid(), &uuid), &data)?;
+            media_set.archives.push(ChunkArchiveEntry {
+                uuid,
+                store: TEST_STORE.to_string(),
+                key: None,
+                size: data.len() as u64,
+                csum: Some(openssl::sha::sha256(&data)),
+                chunks,
+            });
+        }
+
+        for (snapshot, chunks) in snapshots {
+            let snapshot: BackupDir = snapshot.parse()?;
+            let index = serde_json::to_vec(&chunks.iter().map(hex::encode).collect::<Vec<_>>())?;
+            let key = layout::snapshot_file_key(
+                media_set.uuid(),
+                TEST_STORE,
+                &Default::default(),
+                &snapshot,
+                "index.json.blob",
+            );
+            self.backend.put_object(&key, &index)?;
+            media_set.snapshots.push(SnapshotEntry {
+                store: TEST_STORE.to_string(),
+                ns: Default::default(),
+                snapshot,
+                key: None,
+                files: vec![SnapshotFileEntry {
+                    filename: "index.json.blob".to_string(),
+                    size: index.len() as u64,
+                    csum: openssl::sha::sha256(&index),
+                }],
+                chunks: chunks.clone(),
+                verification: None,
+                fingerprint: None,
+                owner: None,
+                attributed: Default::default(),
+                cloud_verification: None,
+            });
+        }
+
+        media_set.save(&self.base_path, &self.target.name)?;
+        self.backend.put_object(
+            &layout::media_set_catalog_key(media_set.uuid()),
+            &serde_json::to_vec(&media_set)?,
+        )?;
+
+        Ok(media_set)
+    }
+}
@@ -0,0 +1,79 @@
+// Target health check tests
+//
+// # cargo test --release cloud::test::health
+
+use anyhow::Error;
+
+use pbs_api_types::CloudHealthSample;
+
+use crate::cloud::backend::MockCloudBackend;
+use crate::cloud::health::{
+    check_target_health, health_check_due, load_health_history, record_health_sample,
+    target_health, HEALTH_CHECK_INTERVAL, HEALTH_HISTORY_SIZE,
+};
+
+use super::harness::create_testdir;
+
+fn sample(time: i64, error: Option<&str>) -> CloudHealthSample {
+    CloudHealthSample {
+        time,
+        latency: error.is_none().then_some(20),
+        error: error.map(str::to_string),
+    }
+}
+
+#[test]
+fn test_check_target_health() {
+    // the lease object does not exist, but the target is reachable
+    let backend = MockCloudBackend::new();
+    let result = check_target_health(&backend);
+    assert!(result.error.is_none());
+    assert!(result.latency.is_some());
+}
+
+#[test]
+fn test_health_history() -> Result<(), Error> {
+    let testdir = create_testdir("test_health_history")?;
+
+    assert!(load_health_history(&testdir, "target1")?.is_empty());
+    assert!(health_check_due(&[], 1_600_000_000));
+
+    for i in 0..(HEALTH_HISTORY_SIZE + 10) {
+        let time = 1_600_000_000 + i as i64 * HEALTH_CHECK_INTERVAL;
+        record_health_sample(&testdir, "target1", sample(time, None))?;
+    }
+    let history = load_health_history(&testdir, "target1")?;
+    assert_eq!(history.len(), HEALTH_HISTORY_SIZE);
+    assert_eq!(history[0].time, 1_600_000_000 + 10 * HEALTH_CHECK_INTERVAL);
+
+    let last = history.last().unwrap().time;
+    assert!(!health_check_due(&history, last + 60));
+    assert!(health_check_due(&history, last + HEALTH_CHECK_INTERVAL));
+    // clock jumped back
+    assert!(health_check_due(&history, last - 60));
+
+    Ok(())
+}
+
+#[test]
+fn test_target_health() {
+    let health = target_health("target1", Vec::new());
+    assert!(!health.reachable);
+    assert_eq!(health.last_check, None);
+    assert_eq!(health.availability, None);
+
+    let history = vec![
+        sample(100, None),
+        sample(400, Some("connection refused")),
+        sample(700, None),
+        sample(1000, Some("access denied")),
+    ];
+    let health = target_health("target1", history);
+    assert!(!health.reachable);
+    assert_eq!(health.last_check, Some(1000));
+    assert_eq!(health.last_success, Some(700));
+    assert_eq!(health.last_error.as_deref(), Some("access denied"));
+    assert_eq!(health.latency, Some(20));
+    assert_eq!(health.availability, Some(50.0));
+    assert_eq!(health.history.len(), 4);
+}
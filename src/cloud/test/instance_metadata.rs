@@ -0,0 +1,77 @@
+// Cloud instance metadata tests
+//
+// # cargo test --release cloud::test::instance_metadata
+
+use anyhow::Error;
+use serde_json::json;
+
+use crate::cloud::instance_metadata::{
+    parse_azure_compute, parse_gce_zone, parse_role_credentials, provider_from_vendor,
+    CloudProvider,
+};
+
+#[test]
+fn test_provider_from_vendor() {
+    assert_eq!(
+        provider_from_vendor("Amazon EC2\n"),
+        Some(CloudProvider::Aws)
+    );
+    assert_eq!(provider_from_vendor("Google\n"), Some(CloudProvider::Gcp));
+    assert_eq!(
+        provider_from_vendor("Microsoft Corporation\n"),
+        Some(CloudProvider::Azure)
+    );
+    assert_eq!(provider_from_vendor("QEMU\n"), None);
+    assert_eq!(provider_from_vendor(""), None);
+}
+
+#[test]
+fn test_parse_metadata() -> Result<(), Error> {
+    assert_eq!(
+        parse_gce_zone("projects/123456/zones/europe-west1-b\n"),
+        "europe-west1-b"
+    );
+
+    let info = parse_azure_compute(&json!({
+        "vmId": "02aab8a4-74ef-476e-8182-f6d2ba4166a6",
+        "location": "westeurope",
+        "zone": "2",
+    }))?;
+    assert_eq!(info.instance_id, "02aab8a4-74ef-476e-8182-f6d2ba4166a6");
+    assert_eq!(info.availability_zone, "westeurope-2");
+    assert_eq!(info.provider, "azure");
+
+    // without availability zone, the region is reported
+    let info = parse_azure_compute(&json!({
+        "vmId": "02aab8a4-74ef-476e-8182-f6d2ba4166a6",
+        "location": "westeurope",
+        "zone": "",
+    }))?;
+    assert_eq!(info.availability_zone, "westeurope");
+
+    assert!(parse_azure_compute(&json!({ "location": "westeurope" })).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_role_credentials() -> Result<(), Error> {
+    let credentials = parse_role_credentials(&json!({
+        "Code": "Success",
+        "LastUpdated": "2023-01-01T00:00:00Z",
+        "Type": "AWS-HMAC",
+        "AccessKeyId": "ASIAEXAMPLE",
+        "SecretAccessKey": "secret",
+        "Token": "token",
+        "Expiration": "2023-01-01T06:00:00Z",
+    }))?;
+    assert_eq!(credentials.access_key, "ASIAEXAMPLE");
+    assert_eq!(credentials.secret_key, "secret");
+    assert_eq!(credentials.session_token.as_deref(), Some("token"));
+    assert_eq!(credentials.expires, Some(1_672_552_800));
+
+    assert!(parse_role_credentials(&json!({ "Code": "AssumeRoleUnauthorizedAccess" })).is_err());
+    assert!(parse_role_credentials(&json!({ "AccessKeyId": "ASIAEXAMPLE" })).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,69 @@
+// Local I/O protection tests
+//
+// # cargo test --release cloud::test::io_pacing
+
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+
+use crate::cloud::io_pacing::IoPacer;
+
+const MB: u64 = 1_000_000;
+
+// one second of reads of `bytes` with the given latency
+fn read_second(
+    pacer: &mut IoPacer,
+    start: &mut Instant,
+    bytes: u64,
+    latency: Duration,
+    busy: bool,
+) -> Duration {
+    *start += Duration::from_secs(1);
+    pacer.record_read_at(bytes, latency, *start, || busy)
+}
+
+#[test]
+fn test_io_pacer() -> Result<(), Error> {
+    let mut pacer = IoPacer::new("store1", Duration::from_millis(20), 10 * MB);
+    let mut now = Instant::now();
+    let slow = Duration::from_millis(50);
+    let fast = Duration::from_millis(5);
+
+    // slow reads alone do not throttle
+    read_second(&mut pacer, &mut now, 100 * MB, slow, false);
+    assert_eq!(pacer.rate(), None);
+
+    // other operations and slow reads halve the measured rate
+    read_second(&mut pacer, &mut now, 100 * MB, slow, true);
+    assert_eq!(pacer.rate(), Some(50 * MB));
+    read_second(&mut pacer, &mut now, 50 * MB, slow, true);
+    assert_eq!(pacer.rate(), Some(25 * MB));
+
+    // reads within the adjustment interval are paced
+    let delay = pacer.record_read_at(5 * MB, slow, now + Duration::from_millis(100), || true);
+    assert_eq!(delay, Duration::from_millis(100));
+    assert_eq!(pacer.throttled(), delay);
+
+    // never below the minimum rate
+    for _ in 0..5 {
+        read_second(&mut pacer, &mut now, MB, slow, true);
+    }
+    assert_eq!(pacer.rate(), Some(10 * MB));
+
+    // the rate recovers once the latency drops
+    for _ in 0..20 {
+        pacer.record_read_at(MB, fast, now, || true);
+    }
+    read_second(&mut pacer, &mut now, MB, fast, true);
+    assert_eq!(pacer.rate(), Some(12_500_000));
+
+    // and is lifted when the datastore is not used by others
+    read_second(&mut pacer, &mut now, MB, slow, false);
+    assert_eq!(pacer.rate(), None);
+    assert_eq!(
+        pacer.record_read_at(100 * MB, slow, now, || true),
+        Duration::ZERO
+    );
+
+    Ok(())
+}
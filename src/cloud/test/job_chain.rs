@@ -0,0 +1,124 @@
+// Job chaining tests
+//
+// # cargo test --release cloud::test::job_chain
+
+use anyhow::Error;
+use serde_json::json;
+
+use proxmox_rest_server::TaskState;
+use proxmox_section_config::SectionConfigData;
+
+use crate::cloud::job_chain::{
+    chained_jobs, check_run_after, load_chained_run, run_triggered_by, save_chained_run,
+    trigger_completed, ChainedRun,
+};
+use crate::server::jobstate::JobState;
+
+use super::harness::create_testdir;
+
+fn job_config(jobs: &[(&str, &str, Option<&str>)]) -> Result<SectionConfigData, Error> {
+    let mut config = SectionConfigData::new();
+    for (section_type, id, run_after) in jobs {
+        let mut data = json!({ "id": id, "target": "test" });
+        if let Some(run_after) = run_after {
+            data["run-after"] = json!(run_after);
+        }
+        config.set_data(id, section_type, &data)?;
+    }
+    Ok(config)
+}
+
+#[test]
+fn test_check_run_after() -> Result<(), Error> {
+    let config = job_config(&[
+        ("backup", "backup1", None),
+        ("replication", "repl1", Some("backup1")),
+        ("replication", "repl2", Some("repl1")),
+        ("template", "template1", None),
+    ])?;
+
+    check_run_after(&config, "repl1", "backup1")?;
+    check_run_after(&config, "backup2", "repl2")?;
+
+    // the trigger must be an existing backup or replication job
+    assert!(check_run_after(&config, "repl1", "missing").is_err());
+    assert!(check_run_after(&config, "repl1", "template1").is_err());
+
+    // backup1 -> repl2 -> repl1 -> backup1
+    let err = check_run_after(&config, "backup1", "repl2").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "job chain contains a cycle (backup1 -> repl2 -> repl1 -> backup1)"
+    );
+    assert!(check_run_after(&config, "backup1", "backup1").is_err());
+
+    assert_eq!(chained_jobs(&config, "backup1"), vec!["repl1".to_string()]);
+    assert_eq!(chained_jobs(&config, "repl1"), vec!["repl2".to_string()]);
+    assert!(chained_jobs(&config, "repl2").is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_trigger_completed() {
+    let finished = |state| JobState::Finished {
+        upid: "UPID:trigger".to_string(),
+        state,
+        updated: None,
+    };
+
+    let state = finished(TaskState::OK { endtime: 1000 });
+    assert_eq!(
+        trigger_completed(&state, 999),
+        Some("UPID:trigger".to_string())
+    );
+    assert_eq!(trigger_completed(&state, 1000), None);
+
+    let state = finished(TaskState::Warning {
+        count: 2,
+        endtime: 1000,
+    });
+    assert!(trigger_completed(&state, 500).is_some());
+
+    let state = finished(TaskState::Error {
+        message: "failed".to_string(),
+        endtime: 1000,
+    });
+    assert_eq!(trigger_completed(&state, 500), None);
+
+    assert_eq!(trigger_completed(&JobState::Created { time: 0 }, 0), None);
+}
+
+#[test]
+fn test_chained_run() -> Result<(), Error> {
+    let base = create_testdir("test_chained_run")?;
+
+    assert!(load_chained_run(&base, "cloud-replication-job", "repl1")?.is_none());
+    assert_eq!(
+        run_triggered_by(&base, "cloud-replication-job", "repl1", Some("UPID:repl")),
+        None
+    );
+
+    let run = ChainedRun {
+        trigger: "backup1".to_string(),
+        trigger_upid: "UPID:backup".to_string(),
+        upid: "UPID:repl".to_string(),
+    };
+    save_chained_run(&base, "cloud-replication-job", "repl1", &run)?;
+
+    assert_eq!(
+        run_triggered_by(&base, "cloud-replication-job", "repl1", Some("UPID:repl")),
+        Some("backup1".to_string())
+    );
+    // a later scheduled run was not chained
+    assert_eq!(
+        run_triggered_by(&base, "cloud-replication-job", "repl1", Some("UPID:other")),
+        None
+    );
+    assert_eq!(
+        run_triggered_by(&base, "cloud-backup-job", "repl1", Some("UPID:repl")),
+        None
+    );
+
+    Ok(())
+}
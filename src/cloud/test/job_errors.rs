@@ -0,0 +1,98 @@
+// Backup job error policy tests
+//
+// # cargo test --release cloud::test::job_errors
+
+use anyhow::{format_err, Error};
+
+use pbs_api_types::CloudErrorPolicy;
+
+use crate::cloud::job_errors::{ErrorAction, JobErrors, JobOutcome};
+
+const GROUP_A: &str = "store1:vm/100";
+const GROUP_B: &str = "store1:vm/101";
+
+#[test]
+fn test_continue() -> Result<(), Error> {
+    let mut errors = JobErrors::new(CloudErrorPolicy::default());
+    assert_eq!(errors.outcome(), JobOutcome::Success);
+
+    assert_eq!(
+        errors.record_error(
+            0,
+            GROUP_A,
+            "vm/100/2020-01-01T00:00:00Z",
+            &format_err!("failed")
+        ),
+        ErrorAction::Continue
+    );
+    assert!(!errors.skip_snapshot(0, GROUP_A));
+    assert_eq!(errors.outcome(), JobOutcome::Failed);
+
+    errors.record_success();
+    assert_eq!(errors.outcome(), JobOutcome::PartialSuccess);
+
+    errors.record_error(
+        0,
+        GROUP_A,
+        "vm/100/2020-01-02T00:00:00Z",
+        &format_err!("failed"),
+    );
+    assert_eq!(errors.groups().len(), 1);
+    assert_eq!(errors.groups()[0].errors.len(), 2);
+    assert_eq!(errors.error_count(), 2);
+    assert!(!errors.aborted());
+
+    Ok(())
+}
+
+#[test]
+fn test_abort_group() -> Result<(), Error> {
+    let mut errors = JobErrors::new(CloudErrorPolicy::AbortGroup);
+
+    assert_eq!(
+        errors.record_error(
+            0,
+            GROUP_A,
+            "vm/100/2020-01-01T00:00:00Z",
+            &format_err!("failed")
+        ),
+        ErrorAction::Continue
+    );
+    assert!(errors.skip_snapshot(0, GROUP_A));
+    assert!(errors.skip_snapshot(0, GROUP_A));
+    assert!(!errors.skip_snapshot(1, GROUP_B));
+    errors.record_success();
+
+    assert_eq!(errors.groups().len(), 1);
+    assert_eq!(errors.groups()[0].skipped, 2);
+    assert_eq!(errors.outcome(), JobOutcome::PartialSuccess);
+    assert!(!errors.aborted());
+
+    Ok(())
+}
+
+#[test]
+fn test_abort_job() -> Result<(), Error> {
+    let mut errors = JobErrors::new(CloudErrorPolicy::AbortJob);
+
+    errors.record_success();
+    assert_eq!(
+        errors.record_error(
+            0,
+            GROUP_A,
+            "vm/100/2020-01-01T00:00:00Z",
+            &format_err!("failed")
+        ),
+        ErrorAction::Abort
+    );
+    assert!(errors.aborted());
+
+    errors.record_not_attempted(GROUP_B);
+    assert_eq!(errors.groups().len(), 2);
+    assert_eq!(errors.groups()[1].group, GROUP_B);
+    assert!(errors.groups()[1].errors.is_empty());
+    assert_eq!(errors.groups()[1].skipped, 1);
+    assert_eq!(errors.error_count(), 1);
+
+    Ok(())
+}
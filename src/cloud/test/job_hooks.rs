@@ -0,0 +1,121 @@
+// Job hook tests
+//
+// # cargo test --release cloud::test::job_hooks
+
+use anyhow::{format_err, Error};
+use serde_json::{json, Value};
+
+use pbs_api_types::{CloudHookFailure, CloudJobHooks};
+
+use crate::cloud::job_hooks::{run_hook_command, run_post_hook, run_pre_hook};
+
+use super::harness::{create_testdir, TestWorker};
+
+#[test]
+fn test_hook_command() -> Result<(), Error> {
+    let worker = TestWorker::default();
+    let base = create_testdir("test_hook_command")?;
+
+    // the hook gets its input on stdin
+    let output = base.join("input.json");
+    let command = format!("cat > {:?}", output);
+    let input = json!({ "job-id": "job1" });
+    run_hook_command(&worker, "pre-hook", &command, None, 10, &input)?;
+    let data: Value = serde_json::from_slice(&std::fs::read(&output)?)?;
+    assert_eq!(data, input);
+
+    // hooks do not need to read their input
+    run_hook_command(&worker, "pre-hook", "true", None, 10, &input)?;
+
+    let err = run_hook_command(&worker, "pre-hook", "exit 3", None, 10, &input).unwrap_err();
+    assert!(err.to_string().starts_with("pre-hook failed"));
+
+    let err = run_hook_command(&worker, "pre-hook", "sleep 10", None, 1, &input).unwrap_err();
+    assert_eq!(err.to_string(), "pre-hook timed out after 1 seconds");
+
+    assert!(run_hook_command(
+        &worker,
+        "pre-hook",
+        "true",
+        Some("nonexistent-user"),
+        10,
+        &input
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_job_hooks() -> Result<(), Error> {
+    let worker = TestWorker::default();
+    let base = create_testdir("test_job_hooks")?;
+
+    let output = base.join("post.json");
+    let mut hooks = CloudJobHooks {
+        pre_hook: Some("exit 1".to_string()),
+        post_hook: Some(format!("cat > {:?}", output)),
+        ..Default::default()
+    };
+
+    // a failing pre-hook skips the job, the post-hook still runs
+    let result = run_pre_hook(&worker, &hooks, "cloud-backup-job", "job1", "UPID:test");
+    assert!(result.is_err());
+    let result = run_post_hook(
+        &worker,
+        &hooks,
+        "cloud-backup-job",
+        "job1",
+        "UPID:test",
+        result,
+        json!({ "media-set": null }),
+    );
+    assert!(result.is_err());
+
+    let data: Value = serde_json::from_slice(&std::fs::read(&output)?)?;
+    assert_eq!(data["phase"], "post-hook");
+    assert_eq!(data["job-id"], "job1");
+    assert_eq!(data["result"], "error");
+    assert_eq!(data["summary"], json!({ "media-set": null }));
+
+    // with 'warn', failing hooks do not change the job result
+    hooks.hook_failure = Some(CloudHookFailure::Warn);
+    hooks.post_hook = Some("exit 1".to_string());
+    run_pre_hook(&worker, &hooks, "cloud-backup-job", "job1", "UPID:test")?;
+    run_post_hook(
+        &worker,
+        &hooks,
+        "cloud-backup-job",
+        "job1",
+        "UPID:test",
+        Ok(()),
+        Value::Null,
+    )?;
+
+    // the post-hook keeps job errors
+    let result = run_post_hook(
+        &worker,
+        &hooks,
+        "cloud-backup-job",
+        "job1",
+        "UPID:test",
+        Err(format_err!("job failed")),
+        Value::Null,
+    );
+    assert_eq!(result.unwrap_err().to_string(), "job failed");
+
+    // no hooks configured
+    let hooks = CloudJobHooks::default();
+    run_pre_hook(&worker, &hooks, "cloud-backup-job", "job1", "UPID:test")?;
+    run_post_hook(
+        &worker,
+        &hooks,
+        "cloud-backup-job",
+        "job1",
+        "UPID:test",
+        Ok(()),
+        Value::Null,
+    )?;
+
+    Ok(())
+}
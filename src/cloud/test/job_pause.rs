@@ -0,0 +1,37 @@
+// Job pause tests
+//
+// # cargo test --release cloud::test::job_pause
+
+use anyhow::Error;
+
+use pbs_api_types::Authid;
+
+use crate::cloud::job_pause::{job_paused, load_job_pause, pause_job, resume_job};
+
+use super::harness::create_testdir;
+
+#[test]
+fn test_job_pause() -> Result<(), Error> {
+    let base = create_testdir("test_job_pause")?;
+    let user: Authid = "admin@pbs".parse()?;
+
+    assert!(!job_paused(&base, "job1"));
+    assert_eq!(load_job_pause(&base, "job1")?, None);
+    assert!(!resume_job(&base, "job1")?);
+
+    pause_job(&base, "job1", &user)?;
+    assert!(job_paused(&base, "job1"));
+    assert!(!job_paused(&base, "job2"));
+
+    // pausing again keeps the original pause
+    let pause = load_job_pause(&base, "job1")?.unwrap();
+    assert_eq!(pause.user, user);
+    pause_job(&base, "job1", Authid::root_auth_id())?;
+    assert_eq!(load_job_pause(&base, "job1")?, Some(pause));
+
+    assert!(resume_job(&base, "job1")?);
+    assert!(!job_paused(&base, "job1"));
+    assert!(!resume_job(&base, "job1")?);
+
+    Ok(())
+}
@@ -0,0 +1,114 @@
+// Job retry tests
+//
+// # cargo test --release cloud::test::job_retry
+
+use anyhow::Error;
+
+use proxmox_rest_server::TaskState;
+
+use pbs_api_types::CloudJobScheduleStatus;
+
+use crate::cloud::job_retry::{
+    load_job_retries, next_retry, record_retry, retry_schedule_status, RetryOptions,
+    DEFAULT_RETRY_DELAY,
+};
+use crate::server::jobstate::JobState;
+
+use super::harness::create_testdir;
+
+fn failed(upid: &str, endtime: i64) -> JobState {
+    JobState::Finished {
+        upid: upid.to_string(),
+        state: TaskState::Error {
+            message: "failed".to_string(),
+            endtime,
+        },
+        updated: None,
+    }
+}
+
+#[test]
+fn test_retry_options() -> Result<(), Error> {
+    assert_eq!(RetryOptions::new(None, Some("5m"))?, None);
+    assert_eq!(RetryOptions::new(Some(0), None)?, None);
+    assert!(RetryOptions::new(Some(1), Some("later")).is_err());
+
+    let options = RetryOptions::new(Some(3), None)?.unwrap();
+    assert_eq!(options.delay, DEFAULT_RETRY_DELAY);
+
+    let options = RetryOptions::new(Some(3), Some("5m"))?.unwrap();
+    assert_eq!(
+        (0..3).map(|n| options.backoff(n)).collect::<Vec<_>>(),
+        vec![300, 600, 1200]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_job_retry() -> Result<(), Error> {
+    let base = create_testdir("test_job_retry")?;
+    let options = RetryOptions::new(Some(2), Some("5m"))?.unwrap();
+    let job_type = "cloud-backup-job";
+
+    // successful runs are not retried
+    let ok = JobState::Finished {
+        upid: "UPID:ok".to_string(),
+        state: TaskState::OK { endtime: 1000 },
+        updated: None,
+    };
+    assert_eq!(next_retry(&ok, None, &options), None);
+    assert_eq!(
+        next_retry(&JobState::Created { time: 0 }, None, &options),
+        None
+    );
+
+    let state = failed("UPID:run", 1000);
+    assert_eq!(next_retry(&state, None, &options), Some(1300));
+    assert_eq!(
+        record_retry(&base, job_type, "job1", &state, "UPID:retry1")?,
+        1
+    );
+
+    // the retry failed, too
+    let state = failed("UPID:retry1", 2000);
+    let retries = load_job_retries(&base, job_type, "job1")?;
+    assert_eq!(next_retry(&state, retries.as_ref(), &options), Some(2600));
+    assert_eq!(
+        record_retry(&base, job_type, "job1", &state, "UPID:retry2")?,
+        2
+    );
+
+    let retries = load_job_retries(&base, job_type, "job1")?.unwrap();
+    assert_eq!(retries.failed_upid, "UPID:run");
+    assert_eq!(retries.attempts, vec!["UPID:retry1", "UPID:retry2"]);
+
+    // all retries failed
+    let state = failed("UPID:retry2", 3000);
+    assert_eq!(next_retry(&state, Some(&retries), &options), None);
+
+    let mut status = CloudJobScheduleStatus {
+        last_run_upid: Some("UPID:retry2".to_string()),
+        ..Default::default()
+    };
+    retry_schedule_status(&mut status, &base, job_type, "job1", &state, Some(&options));
+    assert_eq!(status.retry_attempts, Some(2));
+    assert_eq!(status.next_retry, None);
+
+    // a failed scheduled run starts a new series
+    let state = failed("UPID:next", 4000);
+    assert_eq!(next_retry(&state, Some(&retries), &options), Some(4300));
+    assert_eq!(
+        record_retry(&base, job_type, "job1", &state, "UPID:retry3")?,
+        1
+    );
+
+    let mut status = CloudJobScheduleStatus {
+        last_run_upid: Some("UPID:next".to_string()),
+        ..Default::default()
+    };
+    retry_schedule_status(&mut status, &base, job_type, "job1", &state, Some(&options));
+    assert_eq!(status.retry_attempts, None);
+
+    Ok(())
+}
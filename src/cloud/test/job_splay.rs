@@ -0,0 +1,48 @@
+// Schedule splay tests
+//
+// # cargo test --release cloud::test::job_splay
+
+use anyhow::Error;
+
+use proxmox_time::CalendarEvent;
+
+use crate::cloud::job_splay::{next_splayed_event, splay_offset};
+
+#[test]
+fn test_splay_offset() -> Result<(), Error> {
+    assert_eq!(splay_offset("job1", None)?, 0);
+    assert_eq!(splay_offset("job1", Some("0s"))?, 0);
+    assert!(splay_offset("job1", Some("soon")).is_err());
+
+    let offsets = (0..20)
+        .map(|n| splay_offset(&format!("job{}", n), Some("30m")))
+        .collect::<Result<Vec<_>, Error>>()?;
+    assert!(offsets.iter().all(|offset| (0..1800).contains(offset)));
+
+    // the same for each evaluation, but different between jobs
+    assert_eq!(splay_offset("job0", Some("30m"))?, offsets[0]);
+    assert!(offsets.iter().any(|offset| *offset != offsets[0]));
+
+    Ok(())
+}
+
+#[test]
+fn test_next_splayed_event() -> Result<(), Error> {
+    // epoch 0 is 1970-01-01 00:00 UTC
+    let event: CalendarEvent = "02:00 UTC".parse()?;
+    let day = 86400;
+    let two = 2 * 3600;
+
+    assert_eq!(next_splayed_event(&event, 0, 0)?, Some(two));
+    assert_eq!(next_splayed_event(&event, 0, 600)?, Some(two + 600));
+
+    // after the delayed run, the next one is a day later
+    assert_eq!(
+        next_splayed_event(&event, two + 600, 600)?,
+        Some(day + two + 600)
+    );
+    // created after the schedule, but before the delayed run
+    assert_eq!(next_splayed_event(&event, two + 300, 600)?, Some(two + 600));
+
+    Ok(())
+}
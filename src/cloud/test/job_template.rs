@@ -0,0 +1,73 @@
+// Job template instantiation tests
+//
+// # cargo test --release cloud::test::job_template
+
+use anyhow::Error;
+use serde_json::json;
+
+use proxmox_section_config::SectionConfigData;
+
+use pbs_config::cloud_job::CONFIG;
+
+use crate::api2::config::cloud_backup_job_template::template_jobs;
+
+// job config with a template and a job created from it, and its digest
+fn job_config() -> Result<(SectionConfigData, [u8; 32]), Error> {
+    let mut config = SectionConfigData::new();
+    config.set_data("fleet", "template", &json!({ "id": "fleet" }))?;
+    config.set_data(
+        "fleet-store1",
+        "backup",
+        &json!({ "id": "fleet-store1", "store": "store1", "template": "fleet" }),
+    )?;
+    let raw = CONFIG.write("cloud-job.cfg", &config)?;
+    let digest = openssl::sha::sha256(raw.as_bytes());
+    Ok((CONFIG.parse("cloud-job.cfg", &raw)?, digest))
+}
+
+#[test]
+fn test_instantiate_digest() -> Result<(), Error> {
+    let (config, expected_digest) = job_config()?;
+    let stores = vec!["store1".to_string(), "store2".to_string()];
+    let instantiate = |digest: Option<&str>, replace| {
+        template_jobs(
+            &config,
+            &expected_digest,
+            digest,
+            "fleet",
+            &stores,
+            None,
+            None,
+            replace,
+        )
+    };
+
+    let current = hex::encode(expected_digest);
+    let stale = hex::encode(openssl::sha::sha256(b"reviewed before the last change"));
+
+    // replacing needs the digest of the reviewed config to match
+    let err = instantiate(Some(&stale), true).unwrap_err();
+    assert!(err.to_string().contains("detected modified configuration"));
+
+    let jobs = instantiate(Some(&current), true)?;
+    let ids: Vec<(&str, bool)> = jobs
+        .iter()
+        .map(|(job, exists)| (job.id.as_str(), *exists))
+        .collect();
+    assert_eq!(ids, vec![("fleet-store1", true), ("fleet-store2", false)]);
+
+    // existing jobs are only replaced on request
+    let err = instantiate(Some(&current), false).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("job 'fleet-store1' already exists"));
+    assert_eq!(instantiate(None, true)?.len(), 2);
+
+    // the config is left as it was
+    let (unchanged, digest) = job_config()?;
+    assert_eq!(digest, expected_digest);
+    assert_eq!(config.order, unchanged.order);
+    assert_eq!(config.sections, unchanged.sections);
+
+    Ok(())
+}
@@ -0,0 +1,176 @@
+// Bulk job triple creation tests
+//
+// # cargo test --release cloud::test::job_triples
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Error};
+use serde_json::json;
+
+use proxmox_section_config::SectionConfigData;
+
+use pbs_api_types::{BackupNamespace, CloudBackupJobTemplate};
+
+use crate::api2::cloud::bulk::{new_job_triples, save_configs, JobTripleConfigs};
+
+use super::harness::create_testdir;
+
+fn sources(list: &[&str]) -> Vec<String> {
+    list.iter().map(|source| source.to_string()).collect()
+}
+
+fn config(ids: &[(&str, &str)]) -> Result<SectionConfigData, Error> {
+    let mut config = SectionConfigData::new();
+    for (section_type, id) in ids {
+        config.set_data(id, section_type, &json!({ "id": id, "store": "store1" }))?;
+    }
+    Ok(config)
+}
+
+#[test]
+fn test_job_triple_conflicts() -> Result<(), Error> {
+    let template: CloudBackupJobTemplate = serde_json::from_value(json!({ "id": "fleet" }))?;
+    let mut configs = JobTripleConfigs {
+        jobs: config(&[("template", "fleet"), ("verify", "store2-verify")])?,
+        local_prune: config(&[("prune", "store2-local-prune")])?,
+    };
+
+    let triples = new_job_triples(
+        &template,
+        &sources(&["store1", "store2:prod"]),
+        None,
+        false,
+        &configs,
+    )?;
+    let ids: Vec<&str> = triples
+        .iter()
+        .map(|(triple, _)| triple.backup.as_str())
+        .collect();
+    assert_eq!(ids, vec!["fleet-store1", "fleet-store2"]);
+    assert_eq!(triples[1].0.verify, "fleet-store2-verify");
+    assert_eq!(triples[1].0.prune, "fleet-store2-prune");
+    assert_eq!(triples[1].0.local_prune, None);
+    assert_eq!(triples[1].1.setup.ns, Some(BackupNamespace::new("prod")?));
+
+    // a conflict of one triple aborts all of them
+    let both = sources(&["store1", "store2"]);
+    configs
+        .jobs
+        .set_data("fleet-store2-verify", "verify", &json!({ "id": "x" }))?;
+    let err = new_job_triples(&template, &both, None, false, &configs).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("job 'fleet-store2-verify' already exists"));
+
+    configs.jobs = config(&[("template", "fleet"), ("prune", "fleet-store2-prune")])?;
+    let err = new_job_triples(&template, &both, None, false, &configs).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("job 'fleet-store2-prune' already exists"));
+
+    configs.jobs = config(&[("template", "fleet"), ("backup", "fleet-store2")])?;
+    assert!(new_job_triples(&template, &both, None, false, &configs).is_err());
+
+    // local prune jobs are only created on request
+    configs.jobs = config(&[("template", "fleet")])?;
+    configs.local_prune = config(&[("prune", "fleet-store2-local-prune")])?;
+    assert!(new_job_triples(&template, &both, None, false, &configs).is_ok());
+    let err = new_job_triples(&template, &both, None, true, &configs).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("prune job 'fleet-store2-local-prune' already exists"));
+
+    configs.local_prune = SectionConfigData::new();
+    let triples = new_job_triples(&template, &both, None, true, &configs)?;
+    assert_eq!(
+        triples[0].0.local_prune.as_deref(),
+        Some("fleet-store1-local-prune")
+    );
+
+    // namespaces of the same datastore need a job ID with '{ns}'
+    let err = new_job_triples(
+        &template,
+        &sources(&["store3:a", "store3:b"]),
+        None,
+        false,
+        &configs,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("would be used twice"));
+
+    // the ID of a backup job must not be one of the derived IDs of another
+    let template: CloudBackupJobTemplate =
+        serde_json::from_value(json!({ "id": "fleet", "job-id": "{store}" }))?;
+    let err = new_job_triples(
+        &template,
+        &sources(&["store4", "store4-prune"]),
+        None,
+        false,
+        &configs,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("would be used twice"));
+
+    Ok(())
+}
+
+// save function writing the section IDs of a config
+fn save_ids(path: &Path) -> impl Fn(&SectionConfigData) -> Result<(), Error> + '_ {
+    move |config| {
+        std::fs::write(path, config.order.join(","))?;
+        Ok(())
+    }
+}
+
+fn read_ids(path: &Path) -> Result<String, Error> {
+    Ok(std::fs::read_to_string(path)?)
+}
+
+#[test]
+fn test_job_triple_rollback() -> Result<(), Error> {
+    let testdir = create_testdir("test_job_triple_rollback")?;
+    let paths: Vec<PathBuf> = ["cloud-job.cfg", "prune.cfg"]
+        .iter()
+        .map(|name| testdir.join(name))
+        .collect();
+    let (save_jobs, save_prune) = (save_ids(&paths[0]), save_ids(&paths[1]));
+    let fail_prune = |_config: &SectionConfigData| -> Result<(), Error> { bail!("disk full") };
+
+    let old = JobTripleConfigs {
+        jobs: config(&[("backup", "job1"), ("prune", "job1-prune")])?,
+        local_prune: config(&[("prune", "job1-local-prune")])?,
+    };
+    let new = JobTripleConfigs {
+        jobs: config(&[
+            ("backup", "job1"),
+            ("prune", "job1-prune"),
+            ("backup", "job2"),
+            ("prune", "job2-prune"),
+        ])?,
+        local_prune: config(&[("prune", "job1-local-prune"), ("prune", "job2-local-prune")])?,
+    };
+
+    save_configs(&[
+        (&old.jobs, &old.jobs, &save_jobs),
+        (&old.local_prune, &old.local_prune, &save_prune),
+    ])?;
+
+    // the configs saved before the failure are restored
+    let err = save_configs(&[
+        (&new.jobs, &old.jobs, &save_jobs),
+        (&new.local_prune, &old.local_prune, &fail_prune),
+    ])
+    .unwrap_err();
+    assert_eq!(err.to_string(), "disk full");
+    assert_eq!(read_ids(&paths[0])?, "job1,job1-prune");
+    assert_eq!(read_ids(&paths[1])?, "job1-local-prune");
+
+    save_configs(&[
+        (&new.jobs, &old.jobs, &save_jobs),
+        (&new.local_prune, &old.local_prune, &save_prune),
+    ])?;
+    assert_eq!(read_ids(&paths[0])?, "job1,job1-prune,job2,job2-prune");
+    assert_eq!(read_ids(&paths[1])?, "job1-local-prune,job2-local-prune");
+
+    Ok(())
+}
@@ -0,0 +1,105 @@
+// Job time window tests
+//
+// # cargo test --release cloud::test::job_window
+
+use anyhow::Error;
+
+use pbs_api_types::{CloudBackupJobSetup, CloudWindowAction};
+
+use crate::cloud::job_window::{wait_for_window, JobWindow};
+
+use super::harness::TestWorker;
+
+// Monday, 2024-01-01 00:00:00 UTC
+const MONDAY: i64 = 1_704_067_200;
+const HOUR: i64 = 3600;
+
+fn job_window(
+    allowed: &[&str],
+    blackout: &[&str],
+    action: Option<CloudWindowAction>,
+) -> Result<Option<JobWindow>, Error> {
+    let list = |windows: &[&str]| {
+        (!windows.is_empty()).then(|| windows.iter().map(|w| w.to_string()).collect())
+    };
+    let setup = CloudBackupJobSetup {
+        store: "store1".to_string(),
+        target: Some("test".to_string()),
+        latest_only: None,
+        notify_user: None,
+        group_filter: None,
+        ns: None,
+        max_depth: None,
+        max_chain_length: None,
+        storage_class: None,
+        retention_lock: None,
+        transfer_last: None,
+        object_tag: None,
+        allowed_window: list(allowed),
+        blackout_window: list(blackout),
+        window_action: action,
+        zfs_change_detection: None,
+        local_io_latency: None,
+        local_io_min_rate: None,
+        order: None,
+        on_error: None,
+        request_trace: None,
+    };
+    Ok(JobWindow::from_job_setup(&setup)?.map(|window| window.use_utc(true)))
+}
+
+#[test]
+fn test_job_window() -> Result<(), Error> {
+    assert!(job_window(&[], &[], Some(CloudWindowAction::Stop))?.is_none());
+    assert!(job_window(&["mon 25:00-26:00"], &[], None).is_err());
+
+    let window = job_window(&["mon..fri 20:00-23:59", "sat,sun 0:00-23:59"], &[], None)?.unwrap();
+    assert_eq!(window.action(), CloudWindowAction::Pause);
+    assert!(!window.is_open(MONDAY + 10 * HOUR)?);
+    assert!(window.is_open(MONDAY + 21 * HOUR)?);
+    assert!(window.is_open(MONDAY + 5 * 24 * HOUR + 10 * HOUR)?);
+    assert_eq!(
+        window.next_open(MONDAY + 10 * HOUR + 30)?,
+        MONDAY + 20 * HOUR
+    );
+    assert_eq!(window.next_open(MONDAY + 21 * HOUR)?, MONDAY + 21 * HOUR);
+
+    // blackout windows take precedence
+    let window = job_window(&[], &["mon..fri 8:00-18:00"], None)?.unwrap();
+    assert!(window.is_open(MONDAY + 7 * HOUR)?);
+    assert!(!window.is_open(MONDAY + 9 * HOUR)?);
+    let next = window.next_open(MONDAY + 9 * HOUR)?;
+    assert!(next >= MONDAY + 18 * HOUR && next <= MONDAY + 18 * HOUR + 60);
+
+    let window = job_window(&["mon 8:00-18:00"], &["mon 0:00-23:00"], None)?.unwrap();
+    assert!(window.next_open(MONDAY).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_wait_for_window() -> Result<(), Error> {
+    let worker = TestWorker::default();
+
+    const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+    // 1970-01-01 was a thursday
+    let today = ((proxmox_time::epoch_i64() / (24 * HOUR) + 3) % 7) as usize;
+
+    let other_day = format!("{} 10:00-11:00", DAYS[(today + 2) % 7]);
+
+    // only closed in two days
+    let window = job_window(&[], &[&other_day], None)?.unwrap();
+    assert!(wait_for_window(&worker, &window, || Ok(()))?);
+
+    // only open in two days, stop without waiting
+    let window = job_window(&[&other_day], &[], Some(CloudWindowAction::Stop))?.unwrap();
+    assert!(!wait_for_window(&worker, &window, || panic!(
+        "no keep alive expected"
+    ))?);
+
+    // never open
+    let window = job_window(&["mon 8:00-18:00"], &["mon 0:00-23:00"], None)?.unwrap();
+    assert!(wait_for_window(&worker, &window, || Ok(())).is_err());
+
+    Ok(())
+}
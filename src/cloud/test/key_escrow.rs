@@ -0,0 +1,73 @@
+// Key escrow (Shamir's secret sharing) tests
+//
+// # cargo test --release cloud::test::key_escrow
+
+use anyhow::Error;
+
+use pbs_api_types::{Fingerprint, CLOUD_KEY_SHARE_REGEX};
+use pbs_tools::crypt_config::CryptConfig;
+
+use crate::cloud::key_escrow::{recover_key, split_key, KeyShare};
+
+fn test_key() -> Result<([u8; 32], Fingerprint), Error> {
+    let key = [7u8; 32];
+    let fingerprint = Fingerprint::new(CryptConfig::new(key)?.fingerprint());
+    Ok((key, fingerprint))
+}
+
+fn parse(list: &[String]) -> Result<Vec<KeyShare>, Error> {
+    list.iter().map(|share| share.parse()).collect()
+}
+
+#[test]
+fn test_split_and_recover() -> Result<(), Error> {
+    let (key, fingerprint) = test_key()?;
+
+    let shares: Vec<String> = split_key(&key, &fingerprint, 5, 3)?
+        .iter()
+        .map(|share| share.to_string())
+        .collect();
+    assert_eq!(shares.len(), 5);
+    for share in shares.iter() {
+        assert!(CLOUD_KEY_SHARE_REGEX.is_match(share));
+    }
+
+    // any 3 shares work, in any order
+    assert_eq!(recover_key(&parse(&shares[0..3])?)?, key);
+    assert_eq!(recover_key(&parse(&shares[2..5])?)?, key);
+    let picked = vec![shares[4].clone(), shares[0].clone(), shares[2].clone()];
+    assert_eq!(recover_key(&parse(&picked)?)?, key);
+    assert_eq!(recover_key(&parse(&shares)?)?, key);
+
+    // not enough (distinct) shares
+    assert!(recover_key(&parse(&shares[0..2])?).is_err());
+    let duplicate = vec![shares[0].clone(), shares[0].clone(), shares[1].clone()];
+    assert!(recover_key(&parse(&duplicate)?).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_share_validation() -> Result<(), Error> {
+    let (key, fingerprint) = test_key()?;
+    let shares = split_key(&key, &fingerprint, 3, 2)?;
+
+    assert!(split_key(&key, &fingerprint, 2, 3).is_err());
+    assert!(split_key(&key, &fingerprint, 3, 1).is_err());
+
+    // typing errors are detected by the checksum
+    let mut text = shares[0].to_string();
+    let pos = text.len() - 20;
+    let replacement = if &text[pos..pos + 1] == "0" { "1" } else { "0" };
+    text.replace_range(pos..pos + 1, replacement);
+    assert!(text.parse::<KeyShare>().is_err());
+
+    // shares of different keys cannot be mixed
+    let other_key = [8u8; 32];
+    let other_fingerprint = Fingerprint::new(CryptConfig::new(other_key)?.fingerprint());
+    let other = split_key(&other_key, &other_fingerprint, 3, 2)?;
+    let mixed = vec![shares[0].to_string(), other[1].to_string()];
+    assert!(recover_key(&parse(&mixed)?).is_err());
+
+    Ok(())
+}
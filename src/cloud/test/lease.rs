@@ -0,0 +1,72 @@
+// Cloud target lease tests
+//
+// # cargo test --release cloud::test::lease
+
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use proxmox_uuid::Uuid;
+
+use crate::cloud::backend::{CloudBackend, MockCloudBackend};
+use crate::cloud::layout::LEASE_KEY;
+use crate::cloud::lease::{CloudLease, LeaseInfo, LEASE_TIMEOUT};
+
+#[test]
+fn test_lease_exclusive() -> Result<(), Error> {
+    let backend: Arc<dyn CloudBackend> = Arc::new(MockCloudBackend::new());
+
+    let lease = CloudLease::acquire(Arc::clone(&backend), "node1", LEASE_TIMEOUT)?;
+    assert_eq!(CloudLease::read(&*backend)?.as_ref(), Some(lease.info()));
+
+    // held by node1, even the same node cannot get a second lease
+    assert!(CloudLease::acquire(Arc::clone(&backend), "node2", LEASE_TIMEOUT).is_err());
+    assert!(CloudLease::acquire(Arc::clone(&backend), "node1", LEASE_TIMEOUT).is_err());
+
+    lease.release()?;
+    assert!(CloudLease::read(&*backend)?.is_none());
+
+    let lease = CloudLease::acquire(Arc::clone(&backend), "node2", LEASE_TIMEOUT)?;
+    assert_eq!(lease.info().node, "node2");
+    drop(lease);
+    assert!(CloudLease::read(&*backend)?.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_lease_stale_takeover() -> Result<(), Error> {
+    let backend: Arc<dyn CloudBackend> = Arc::new(MockCloudBackend::new());
+
+    let mut lease = CloudLease::acquire(Arc::clone(&backend), "node1", LEASE_TIMEOUT)?;
+    lease.renew()?;
+
+    // simulate a crashed holder
+    let now = proxmox_time::epoch_i64();
+    let stale = LeaseInfo {
+        heartbeat: now - LEASE_TIMEOUT - 1,
+        ..lease.info().clone()
+    };
+    backend.put_object(LEASE_KEY, &serde_json::to_vec(&stale)?)?;
+
+    let other = CloudLease::acquire(Arc::clone(&backend), "node2", LEASE_TIMEOUT)?;
+    assert_eq!(CloudLease::read(&*backend)?.unwrap().node, "node2");
+
+    // node1 is fenced and must not remove the new lease
+    assert!(lease.check().is_err());
+    assert!(lease.heartbeat().is_err());
+    assert!(lease.release().is_err());
+    other.check()?;
+
+    // leases become stale after the timeout
+    let info = LeaseInfo {
+        node: "node3".to_string(),
+        token: Uuid::generate(),
+        acquired: now,
+        heartbeat: now,
+    };
+    assert!(!info.is_stale(now, LEASE_TIMEOUT));
+    assert!(info.is_stale(now + LEASE_TIMEOUT, LEASE_TIMEOUT));
+
+    Ok(())
+}
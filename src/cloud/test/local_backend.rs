@@ -0,0 +1,197 @@
+// Local cloud backend tests
+//
+// # cargo test --release cloud::test::local_backend
+
+use anyhow::Error;
+use std::path::PathBuf;
+
+use crate::cloud::backend::{copy_between_targets, CloudBackend, LocalBackend, MockCloudBackend};
+
+fn create_testdir(name: &str) -> Result<PathBuf, Error> {
+    let mut testdir: PathBuf = String::from("./target/testout").into();
+    testdir.push(std::module_path!());
+    testdir.push(name);
+
+    let _ = std::fs::remove_dir_all(&testdir);
+    let _ = std::fs::create_dir_all(&testdir);
+
+    Ok(testdir)
+}
+
+#[test]
+fn test_put_get_delete() -> Result<(), Error> {
+    let testdir = create_testdir("test_put_get_delete")?;
+    let backend = LocalBackend::with_base(&testdir);
+
+    backend.put_object("media-set/a/label.json", b"0123456789")?;
+
+    assert_eq!(backend.get_object("media-set/a/label.json")?, b"0123456789");
+    assert_eq!(
+        backend.get_object_range("media-set/a/label.json", 2, 3)?,
+        b"234"
+    );
+    assert!(backend
+        .get_object_range("media-set/a/label.json", 8, 4)
+        .is_err());
+
+    let info = backend.head_object("media-set/a/label.json")?.unwrap();
+    assert_eq!(info.size, 10);
+    assert!(backend.head_object("media-set/a/missing")?.is_none());
+
+    backend.delete_object("media-set/a/label.json")?;
+    assert!(backend.head_object("media-set/a/label.json")?.is_none());
+
+    // deleting twice is not an error
+    backend.delete_object("media-set/a/label.json")?;
+
+    Ok(())
+}
+
+#[test]
+fn test_list_objects() -> Result<(), Error> {
+    let testdir = create_testdir("test_list_objects")?;
+    let backend = LocalBackend::with_base(&testdir);
+
+    backend.put_object("media-set/b/catalog.json", b"{}")?;
+    backend.put_object("media-set/a/label.json", b"{}")?;
+    backend.put_object("media-set/a/chunk-archive/1", b"data")?;
+    backend.put_object("other/object", b"x")?;
+
+    let keys: Vec<String> = backend
+        .list_objects("media-set/")?
+        .into_iter()
+        .map(|info| info.key)
+        .collect();
+
+    assert_eq!(
+        keys,
+        vec![
+            "media-set/a/chunk-archive/1",
+            "media-set/a/label.json",
+            "media-set/b/catalog.json",
+        ]
+    );
+
+    assert_eq!(backend.list_objects("media-set/a/")?.len(), 2);
+    assert!(backend.list_objects("none/")?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_list_objects_partial_prefix() -> Result<(), Error> {
+    let testdir = create_testdir("test_list_objects_partial_prefix")?;
+    let backend = LocalBackend::with_base(&testdir);
+
+    backend.put_object("media-set/abc/label.json", b"{}")?;
+    backend.put_object("media-set/abd/label.json", b"{}")?;
+    backend.put_object("media-set/b/label.json", b"{}")?;
+    backend.put_object("media-sets", b"x")?;
+
+    // the prefix may end in the middle of a path component
+    let keys: Vec<String> = backend
+        .list_objects("media-set/ab")?
+        .into_iter()
+        .map(|info| info.key)
+        .collect();
+    assert_eq!(
+        keys,
+        vec!["media-set/abc/label.json", "media-set/abd/label.json"]
+    );
+
+    assert_eq!(backend.list_objects("media-set")?.len(), 4);
+    assert!(backend.list_objects("media-set/none/x")?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_put_leaves_no_temporary_files() -> Result<(), Error> {
+    let testdir = create_testdir("test_put_leaves_no_temporary_files")?;
+    let backend = LocalBackend::with_base(&testdir);
+
+    backend.put_object("media-set/a/label.json", b"1")?;
+    backend.put_object("media-set/a/label.json", b"2")?;
+    backend.copy_object("media-set/a/label.json", "media-set/b/label.json")?;
+
+    let mut names: Vec<String> = std::fs::read_dir(testdir.join("media-set/a"))?
+        .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+        .collect::<Result<_, _>>()?;
+    names.sort();
+    assert_eq!(names, vec!["label.json"]);
+    assert_eq!(backend.get_object("media-set/b/label.json")?, b"2");
+
+    Ok(())
+}
+
+#[test]
+fn test_copy_object() -> Result<(), Error> {
+    let testdir = create_testdir("test_copy_object")?;
+    let backend = LocalBackend::with_base(&testdir);
+
+    backend.put_object("media-set/a/chunk-archive/1", b"data")?;
+    backend.copy_object("media-set/a/chunk-archive/1", "media-set/b/chunk-archive/2")?;
+
+    assert_eq!(backend.get_object("media-set/b/chunk-archive/2")?, b"data");
+    assert_eq!(backend.list_objects("media-set/b/")?.len(), 1);
+    assert!(backend
+        .copy_object("media-set/a/missing", "media-set/b/x")
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_keys() -> Result<(), Error> {
+    let testdir = create_testdir("test_invalid_keys")?;
+    let backend = LocalBackend::with_base(&testdir);
+
+    assert!(backend.put_object("", b"x").is_err());
+    assert!(backend.put_object("/abs", b"x").is_err());
+    assert!(backend.put_object("a/../../escape", b"x").is_err());
+    assert!(backend.get_object("./a").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_copy_between_targets() -> Result<(), Error> {
+    let testdir = create_testdir("test_copy_between_targets")?;
+    let source = LocalBackend::with_base(testdir.join("source"));
+    let target = LocalBackend::with_base(testdir.join("target"));
+
+    source.put_object("media-set/a/chunk-archive/1", b"data")?;
+
+    // both local, copied as file
+    assert!(copy_between_targets(
+        &source,
+        "media-set/a/chunk-archive/1",
+        &target,
+        "media-set/a/chunk-archive/1",
+        true
+    )?);
+    assert_eq!(target.get_object("media-set/a/chunk-archive/1")?, b"data");
+
+    // streaming copy if server-side copies are not wanted
+    assert!(!copy_between_targets(
+        &source,
+        "media-set/a/chunk-archive/1",
+        &target,
+        "media-set/b/chunk-archive/2",
+        false
+    )?);
+    assert_eq!(target.get_object("media-set/b/chunk-archive/2")?, b"data");
+
+    // a mock target cannot read local files
+    let mock = MockCloudBackend::new();
+    assert!(!copy_between_targets(
+        &source,
+        "media-set/a/chunk-archive/1",
+        &mock,
+        "media-set/a/chunk-archive/1",
+        true
+    )?);
+    assert_eq!(mock.get_object("media-set/a/chunk-archive/1")?, b"data");
+
+    Ok(())
+}
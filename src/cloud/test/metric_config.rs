@@ -0,0 +1,216 @@
+// Metric server config API and sender reload tests
+//
+// # cargo test --release cloud::test::metric_config
+
+use anyhow::Error;
+use serde_json::json;
+
+use proxmox_section_config::SectionConfigData;
+
+use pbs_api_types::{CloudMetricsHttp, MetricServerType};
+use pbs_config::metrics::CONFIG;
+
+use crate::api2::config::metrics::{
+    add_metric_server, check_server_properties, new_server_config, remove_metric_server,
+    update_server_config, DeletableProperty,
+};
+use crate::cloud::metrics::MetricSenders;
+
+use super::harness::create_testdir;
+
+// write and parse the config, like saving and loading the config file
+fn reload(metrics: &SectionConfigData) -> Result<(SectionConfigData, [u8; 32]), Error> {
+    let raw = CONFIG.write("metricserver.cfg", metrics)?;
+    let digest = openssl::sha::sha256(raw.as_bytes());
+    Ok((CONFIG.parse("metricserver.cfg", &raw)?, digest))
+}
+
+#[test]
+fn test_metric_server_config() -> Result<(), Error> {
+    let mut metrics = CONFIG.parse("metricserver.cfg", "")?;
+
+    // create
+    let param = json!({
+        "name": "influx",
+        "type": "cloud-http",
+        "url": "https://influx.example.com:8086",
+        "token": "secret",
+        "comment": "central",
+    });
+    let config = new_server_config("influx", &param);
+    check_server_properties(MetricServerType::CloudHttp, &config)?;
+    add_metric_server(
+        &mut metrics,
+        "influx",
+        MetricServerType::CloudHttp,
+        config.clone(),
+    )?;
+    assert!(add_metric_server(&mut metrics, "influx", MetricServerType::CloudUdp, config).is_err());
+
+    let param = json!({ "endpoint": "127.0.0.1:8089", "mtu": 9000 });
+    let config = new_server_config("udp", &param);
+    check_server_properties(MetricServerType::CloudUdp, &config)?;
+    add_metric_server(&mut metrics, "udp", MetricServerType::CloudUdp, config)?;
+
+    // properties of the other server type are rejected
+    let config = new_server_config(
+        "bad",
+        &json!({ "endpoint": "127.0.0.1:8089", "token": "x" }),
+    );
+    assert!(check_server_properties(MetricServerType::CloudUdp, &config).is_err());
+    let config = new_server_config("bad", &json!({ "url": "https://a.example.com", "mtu": 1 }));
+    assert!(check_server_properties(MetricServerType::CloudHttp, &config).is_err());
+
+    let (mut metrics, _digest) = reload(&metrics)?;
+    let server: CloudMetricsHttp = metrics.lookup("cloud-http", "influx")?;
+    assert!(server.enable);
+    assert_eq!(server.url, "https://influx.example.com:8086");
+    assert_eq!(server.token.as_deref(), Some("secret"));
+    assert_eq!(server.comment.as_deref(), Some("central"));
+    assert_eq!(metrics.sections["udp"].0, "cloud-udp");
+
+    // update
+    let mut config = metrics.sections["influx"].1.clone();
+    update_server_config(
+        &mut config,
+        &[DeletableProperty::Token],
+        &json!({ "name": "influx", "enable": false, "bucket": "pbs", "comment": " " }),
+    )?;
+    check_server_properties(MetricServerType::CloudHttp, &config)?;
+    metrics
+        .sections
+        .insert("influx".to_string(), ("cloud-http".to_string(), config));
+
+    let (mut metrics, _digest) = reload(&metrics)?;
+    let server: CloudMetricsHttp = metrics.lookup("cloud-http", "influx")?;
+    assert!(!server.enable);
+    assert_eq!(server.token, None);
+    assert_eq!(server.bucket.as_deref(), Some("pbs"));
+    assert_eq!(server.comment, None);
+
+    // delete
+    remove_metric_server(&mut metrics, "influx")?;
+    assert!(remove_metric_server(&mut metrics, "influx").is_err());
+
+    let (metrics, _digest) = reload(&metrics)?;
+    assert_eq!(metrics.sections.len(), 1);
+    assert!(metrics.sections.contains_key("udp"));
+
+    Ok(())
+}
+
+#[test]
+fn test_metric_sender_reload() -> Result<(), Error> {
+    let testdir = create_testdir("test_metric_sender_reload")?;
+    let mut metrics = CONFIG.parse("metricserver.cfg", "")?;
+
+    let load = |metrics: &SectionConfigData| -> Result<_, Error> {
+        let (metrics, digest) = reload(metrics)?;
+        let senders =
+            proxmox_async::runtime::block_on(MetricSenders::load(&metrics, digest, &testdir));
+        Ok((senders, digest))
+    };
+
+    let (senders, digest) = load(&metrics)?;
+    assert!(senders.is_empty());
+    assert!(senders.is_current(&digest));
+
+    // a new server changes the digest, so the senders are rebuilt
+    let config = new_server_config("udp", &json!({ "endpoint": "127.0.0.1:8089" }));
+    add_metric_server(&mut metrics, "udp", MetricServerType::CloudUdp, config)?;
+    let (_, new_digest) = reload(&metrics)?;
+    assert!(!senders.is_current(&new_digest));
+
+    let (senders, digest) = load(&metrics)?;
+    assert_eq!(senders.udp.len(), 1);
+    assert!(senders.http.is_empty());
+    assert!(senders.is_current(&digest));
+
+    // disabled servers get no sender
+    let config = new_server_config(
+        "http",
+        &json!({ "url": "https://localhost:8086", "enable": false }),
+    );
+    add_metric_server(&mut metrics, "http", MetricServerType::CloudHttp, config)?;
+    let (senders, _digest) = load(&metrics)?;
+    assert_eq!(senders.udp.len(), 1);
+    assert!(senders.http.is_empty());
+
+    let mut config = metrics.sections["http"].1.clone();
+    update_server_config(&mut config, &[DeletableProperty::Enable], &json!({}))?;
+    metrics
+        .sections
+        .insert("http".to_string(), ("cloud-http".to_string(), config));
+    let (senders, _digest) = load(&metrics)?;
+    assert_eq!(senders.http.len(), 1);
+
+    // servers which cannot be set up are retried on the next run
+    let config = new_server_config("broken", &json!({ "endpoint": "nonexistent.invalid:8089" }));
+    add_metric_server(&mut metrics, "broken", MetricServerType::CloudUdp, config)?;
+    let (senders, digest) = load(&metrics)?;
+    assert_eq!(senders.udp.len(), 1);
+    assert!(!senders.is_current(&digest));
+
+    // removed servers are gone after the reload
+    remove_metric_server(&mut metrics, "broken")?;
+    remove_metric_server(&mut metrics, "udp")?;
+    let (senders, digest) = load(&metrics)?;
+    assert!(senders.udp.is_empty());
+    assert_eq!(senders.http.len(), 1);
+    assert!(senders.is_current(&digest));
+
+    Ok(())
+}
+
+#[test]
+fn test_legacy_metric_servers() -> Result<(), Error> {
+    let testdir = create_testdir("test_legacy_metric_servers")?;
+
+    // config of an earlier version, with InfluxDB servers
+    let raw = "\
+influxdb-udp: old-udp
+\thost 127.0.0.1:8089
+\tmtu 1400
+
+influxdb-http: old-http
+\turl https://influx.example.com:8086
+\tbucket pbs
+\torganization proxmox
+\ttoken secret
+\tverify-tls false
+
+cloud-udp: udp
+\tendpoint 127.0.0.1:8090
+";
+    let mut metrics = CONFIG.parse("metricserver.cfg", raw)?;
+    assert_eq!(metrics.sections.len(), 3);
+    assert_eq!(metrics.sections["old-udp"].0, "influxdb-udp");
+    assert_eq!(metrics.sections["old-http"].0, "influxdb-http");
+
+    // no senders for legacy servers
+    let (reloaded, digest) = reload(&metrics)?;
+    assert_eq!(reloaded.sections.len(), 3);
+    let senders =
+        proxmox_async::runtime::block_on(MetricSenders::load(&reloaded, digest, &testdir));
+    assert_eq!(senders.udp.len(), 1);
+    assert!(senders.http.is_empty());
+
+    // legacy names stay taken until the server is removed
+    let config = new_server_config("old-udp", &json!({ "endpoint": "127.0.0.1:8089" }));
+    assert!(add_metric_server(
+        &mut metrics,
+        "old-udp",
+        MetricServerType::CloudUdp,
+        config.clone()
+    )
+    .is_err());
+    remove_metric_server(&mut metrics, "old-udp")?;
+    add_metric_server(&mut metrics, "old-udp", MetricServerType::CloudUdp, config)?;
+
+    let (metrics, _digest) = reload(&metrics)?;
+    assert_eq!(metrics.sections["old-udp"].0, "cloud-udp");
+    assert_eq!(metrics.sections["old-http"].0, "influxdb-http");
+    assert_eq!(metrics.sections["old-http"].1["token"], "secret");
+
+    Ok(())
+}
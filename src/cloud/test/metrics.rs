@@ -0,0 +1,103 @@
+// Cloud metric server tests
+//
+// # cargo test --release cloud::test::metrics
+
+use std::io::Read;
+use std::net::SocketAddr;
+
+use anyhow::Error;
+use serde_json::json;
+
+use crate::cloud::metrics::http::{gzip_body, spool_body, spool_dir, spooled_bodies, trim_spool};
+use crate::cloud::metrics::udp::max_payload;
+use crate::cloud::metrics::{batch_records, MetricPoint};
+
+use super::harness::create_testdir;
+
+#[test]
+fn test_line_protocol() -> Result<(), Error> {
+    let point = MetricPoint::new(
+        "blockstat",
+        10,
+        json!({ "read ios": 1, "total": 2.5, "nested": { "a": 1 }, "name": "a\"b" }),
+    )?
+    .tag("host", "node 1")
+    .tag("datastore", "a,b");
+
+    assert_eq!(
+        point.to_line().unwrap(),
+        "blockstat,datastore=a\\,b,host=node\\ 1 name=\"a\\\"b\",read\\ ios=1,total=2.5 10000000000"
+    );
+
+    let point = MetricPoint::new("empty", 10, json!({ "nested": {} }))?;
+    assert_eq!(point.to_line(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_udp_batching() -> Result<(), Error> {
+    let v4: SocketAddr = "127.0.0.1:8089".parse()?;
+    let v6: SocketAddr = "[::1]:8089".parse()?;
+    assert_eq!(max_payload(1500, &v4), 1472);
+    assert_eq!(max_payload(1500, &v6), 1452);
+    assert_eq!(max_payload(10, &v4), 1);
+
+    let records = [
+        "a".repeat(40),
+        "b".repeat(40),
+        "c".repeat(100),
+        "d".repeat(10),
+    ];
+    let list = batch_records(records.iter().map(String::as_str), 81);
+
+    // two records with separator fill a datagram exactly
+    assert_eq!(list.len(), 3);
+    assert_eq!(list[0].records, 2);
+    assert_eq!(
+        list[0].data,
+        format!("{}\n{}", records[0], records[1]).into_bytes()
+    );
+    // records larger than the payload are sent alone
+    assert_eq!(list[1].records, 1);
+    assert_eq!(list[1].data.len(), 100);
+    assert_eq!(list[2].records, 1);
+    assert_eq!(list[2].data, records[3].as_bytes());
+
+    assert!(batch_records(std::iter::empty(), 81).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_http_spool() -> Result<(), Error> {
+    let base = create_testdir("test_http_spool")?;
+    let dir = spool_dir(&base, "influx");
+
+    // a missing spool is empty
+    assert!(spooled_bodies(&dir)?.is_empty());
+
+    let first = spool_body(&dir, &gzip_body(b"first")?)?;
+    let second = spool_body(&dir, &gzip_body(b"second")?)?;
+    let third = spool_body(&dir, &gzip_body(b"third")?)?;
+
+    let list = spooled_bodies(&dir)?;
+    let paths: Vec<_> = list.iter().map(|(path, _)| path.clone()).collect();
+    assert_eq!(paths, vec![first, second.clone(), third.clone()]);
+
+    let mut data = String::new();
+    flate2::read::GzDecoder::new(&std::fs::read(&second)?[..]).read_to_string(&mut data)?;
+    assert_eq!(data, "second");
+
+    // the oldest bodies are dropped first
+    let newest: u64 = list[1..].iter().map(|(_, size)| size).sum();
+    assert_eq!(trim_spool(&dir, newest)?, 1);
+    let paths: Vec<_> = spooled_bodies(&dir)?
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    assert_eq!(paths, vec![second, third]);
+    assert_eq!(trim_spool(&dir, newest)?, 0);
+
+    Ok(())
+}
@@ -0,0 +1,161 @@
+// Target migration tests (against the mock backend)
+//
+// # cargo test --release cloud::test::migration
+
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use pbs_api_types::CloudProvider;
+
+use crate::cloud::backend::{CloudBackend, MockCloudBackend};
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::layout;
+use crate::cloud::migration::{delete_migrated_objects, migrate_target, switch_target_storage};
+
+use super::harness::{create_testdir, digest, test_target, TestTarget, TestWorker};
+
+fn object_list(backend: &dyn CloudBackend) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let mut list = Vec::new();
+    for object in backend.list_objects("")? {
+        let data = backend.get_object(&object.key)?;
+        list.push((object.key, data));
+    }
+    Ok(list)
+}
+
+#[test]
+fn test_migrate_target() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_migrate_target")?);
+    let worker = TestWorker::default();
+
+    let set1 = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("vm/100/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    target.write_media_set(
+        Some(set1.uuid()),
+        &[digest(3)],
+        &[("vm/100/2020-01-02T00:00:00Z", vec![digest(1), digest(3)])],
+    )?;
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+
+    let destination = test_target("new");
+    let destination_backend = Arc::new(MockCloudBackend::new());
+    let other: Arc<dyn CloudBackend> = destination_backend.clone();
+
+    // an interrupted migration is continued
+    let label_key = layout::media_set_label_key(set1.uuid());
+    destination_backend.put_object(&label_key, &target.backend.get_object(&label_key)?)?;
+
+    let stats = migrate_target(
+        &worker,
+        &catalog,
+        &target.target,
+        &target.backend(),
+        &destination,
+        &other,
+    )?;
+    assert_eq!(stats.media_sets, 2);
+    assert_eq!(stats.skipped, 1);
+    assert_eq!(
+        object_list(&*destination_backend)?,
+        object_list(&*target.backend)?
+    );
+
+    // copies are verified against the catalog checksums
+    let archive_key = layout::chunk_archive_key(set1.uuid(), &set1.archives[0].uuid);
+    let data = destination_backend.get_object(&archive_key)?;
+    assert!(destination_backend.corrupt_object(&archive_key, &vec![0u8; data.len()]));
+    assert!(migrate_target(
+        &worker,
+        &catalog,
+        &target.target,
+        &target.backend(),
+        &destination,
+        &other,
+    )
+    .is_err());
+
+    // never mix with data of another target
+    let unrelated = MockCloudBackend::new();
+    unrelated.put_object("media-set/unrelated/label.json", b"{}")?;
+    let unrelated: Arc<dyn CloudBackend> = Arc::new(unrelated);
+    assert!(migrate_target(
+        &worker,
+        &catalog,
+        &target.target,
+        &target.backend(),
+        &destination,
+        &unrelated,
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_delete_migrated_objects() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_delete_migrated_objects")?);
+    let worker = TestWorker::default();
+
+    target.write_media_set(
+        None,
+        &[digest(1)],
+        &[("vm/100/2020-01-01T00:00:00Z", vec![digest(1)])],
+    )?;
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+
+    let destination = test_target("new");
+    let destination_backend = Arc::new(MockCloudBackend::new());
+    let other: Arc<dyn CloudBackend> = destination_backend.clone();
+
+    migrate_target(
+        &worker,
+        &catalog,
+        &target.target,
+        &target.backend(),
+        &destination,
+        &other,
+    )?;
+    let copies = object_list(&*destination_backend)?;
+
+    let deleted = delete_migrated_objects(&worker, &*target.backend)?;
+    assert_eq!(deleted, copies.len());
+
+    assert!(target.backend.list_objects("")?.is_empty());
+    assert_eq!(object_list(&*destination_backend)?, copies);
+
+    Ok(())
+}
+
+#[test]
+fn test_switch_target_storage() {
+    let mut target = test_target("test");
+    target.config.parity_group = Some(4);
+    target.config.comment = Some("old bucket".to_string());
+
+    let mut destination = test_target("new");
+    destination.config.provider = CloudProvider::S3;
+    destination.config.path = None;
+    destination.config.bucket = Some("new-bucket".to_string());
+    destination.config.prefix = Some("pbs/".to_string());
+    destination.config.region = Some("eu-central-1".to_string());
+    destination.config.access_key = Some("AKIAEXAMPLE".to_string());
+    destination.config.comment = Some("migration".to_string());
+    destination.secret_key = "secret".to_string();
+
+    switch_target_storage(&mut target, &destination);
+
+    assert_eq!(target.name, "test");
+    assert_eq!(target.config.provider, CloudProvider::S3);
+    assert_eq!(target.config.path, None);
+    assert_eq!(target.config.bucket.as_deref(), Some("new-bucket"));
+    assert_eq!(target.config.prefix.as_deref(), Some("pbs/"));
+    assert_eq!(target.config.access_key.as_deref(), Some("AKIAEXAMPLE"));
+    assert_eq!(target.secret_key, "secret");
+    // everything else is kept
+    assert_eq!(target.config.parity_group, Some(4));
+    assert_eq!(target.config.comment.as_deref(), Some("old bucket"));
+}
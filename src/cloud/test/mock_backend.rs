@@ -0,0 +1,146 @@
+// Mock cloud backend fault injection tests
+//
+// # cargo test --release cloud::test::mock_backend
+
+use std::collections::HashSet;
+
+use anyhow::Error;
+
+use pbs_api_types::CloudTargetCapabilities;
+
+use crate::cloud::backend::{CloudBackend, MockCloudBackend, MockFaults, PutOptions};
+
+use super::harness::test_target;
+
+#[test]
+fn test_throttle_and_failures() -> Result<(), Error> {
+    let backend = MockCloudBackend::with_faults(MockFaults {
+        throttle_every: Some(3),
+        ..Default::default()
+    });
+
+    backend.put_object("a", b"1")?;
+    backend.put_object("b", b"2")?;
+    let err = backend.put_object("c", b"3").unwrap_err();
+    assert!(err.to_string().contains("SlowDown"));
+    backend.put_object("c", b"3")?;
+    assert_eq!(backend.request_count(), 4);
+
+    let mut fail_keys = HashSet::new();
+    fail_keys.insert("b".to_string());
+    backend.set_faults(MockFaults {
+        fail_keys,
+        ..Default::default()
+    });
+
+    assert!(backend.get_object("b").is_err());
+    assert!(backend.copy_object("a", "b").is_err());
+    assert_eq!(backend.get_object("a")?, b"1");
+
+    Ok(())
+}
+
+#[test]
+fn test_eventual_consistent_listing() -> Result<(), Error> {
+    let backend = MockCloudBackend::with_faults(MockFaults {
+        list_delay: 2,
+        ..Default::default()
+    });
+
+    backend.put_object("media-set/a/label.json", b"{}")?;
+
+    // read-after-write works, but listings lag behind
+    assert!(backend.head_object("media-set/a/label.json")?.is_some());
+    assert!(backend.list_objects("media-set/")?.is_empty());
+    assert_eq!(backend.list_objects("media-set/")?.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_data_loss_simulation() -> Result<(), Error> {
+    let backend = MockCloudBackend::new();
+
+    backend.put_object("x", b"data")?;
+    assert!(backend.corrupt_object("x", b"bad"));
+    assert_eq!(backend.get_object_range("x", 0, 3)?, b"bad");
+    assert!(backend.get_object_range("x", 0, 4).is_err());
+
+    assert!(backend.lose_object("x"));
+    assert!(backend.head_object("x")?.is_none());
+
+    // lose/corrupt do not count as requests
+    assert_eq!(backend.request_count(), 4);
+
+    Ok(())
+}
+
+#[test]
+fn test_put_options_capabilities() -> Result<(), Error> {
+    let backend = MockCloudBackend::new();
+
+    let options = PutOptions {
+        storage_class: Some("ARCHIVE".to_string()),
+        retain_until: Some(proxmox_time::epoch_i64() + 3600),
+        tags: vec![("node".to_string(), "testnode".to_string())],
+    };
+    options.check_capabilities(&backend.capabilities()?)?;
+
+    backend.put_object_with_options("locked", b"1", &options)?;
+    assert_eq!(backend.object_options("locked"), Some(options.clone()));
+    let listed = backend.list_objects("")?;
+    assert_eq!(listed[0].storage_class.as_deref(), Some("ARCHIVE"));
+    assert!(backend.delete_object("locked").is_err());
+    assert!(backend.put_object("locked", b"2").is_err());
+
+    let unknown_class = PutOptions {
+        storage_class: Some("COLD".to_string()),
+        retain_until: None,
+        tags: Vec::new(),
+    };
+    assert!(unknown_class
+        .check_capabilities(&backend.capabilities()?)
+        .is_err());
+
+    backend.set_capabilities(CloudTargetCapabilities {
+        ranged_reads: true,
+        ..Default::default()
+    });
+    assert!(options
+        .check_capabilities(&backend.capabilities()?)
+        .is_err());
+    assert!(backend
+        .put_object_with_options("other", b"1", &options)
+        .is_err());
+    PutOptions::default().check_capabilities(&backend.capabilities()?)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_request_tags() -> Result<(), Error> {
+    let backend = MockCloudBackend::new();
+    let mut target = test_target("test");
+
+    let options = PutOptions::default().with_request_tags(&target, "store1", Some("job1"));
+    assert!(options.tags.is_empty());
+
+    target.config.request_tagging = Some(true);
+    let options = PutOptions::default().with_request_tags(&target, "store1", Some("job1"));
+    assert_eq!(
+        options.tags,
+        vec![
+            ("node".to_string(), proxmox_sys::nodename().to_string()),
+            ("datastore".to_string(), "store1".to_string()),
+            ("job".to_string(), "job1".to_string()),
+        ]
+    );
+
+    // tags need no special capabilities
+    backend.set_capabilities(CloudTargetCapabilities::default());
+    options.check_capabilities(&backend.capabilities()?)?;
+    backend.put_object_with_options("tagged", b"1", &options)?;
+    assert_eq!(backend.object_options("tagged").unwrap().tags, options.tags);
+
+    Ok(())
+}
@@ -1,4 +1,81 @@
-mod alloc_writable_media;
-mod compute_media_state;
-mod current_set_usable;
-mod inventory;
+mod access_log;
+mod catalog_export;
+mod change_feed;
+mod checksums;
+mod chunk_cache;
+mod chunk_download;
+mod cloud_error;
+mod cloud_mapping;
+mod compaction;
+mod conditional_write;
+mod config_check;
+mod config_history;
+mod content;
+mod credentials;
+mod dedup_stats;
+mod delete_protection;
+mod delta_sync;
+mod digest;
+mod egress;
+mod encryption;
+mod endpoint_failover;
+mod endpoint_probe;
+mod events;
+mod foreign_import;
+mod fsck;
+mod harness;
+mod health;
+mod instance_metadata;
+mod io_pacing;
+mod job_chain;
+mod job_errors;
+mod job_hooks;
+mod job_pause;
+mod job_retry;
+mod job_splay;
+mod job_template;
+mod job_triples;
+mod job_window;
+mod key_escrow;
+mod lease;
+mod local_backend;
+mod metric_config;
+mod metrics;
+mod migration;
+mod mock_backend;
+mod node_status;
+mod object_tags;
+mod openid_roles;
+mod parity;
+mod popularity;
+mod proxy;
+mod prune;
+mod quota;
+mod raw_list;
+mod reconcile;
+mod repair;
+mod replication;
+mod request_trace;
+mod restore_preview;
+mod retention_report;
+mod role_sync;
+mod rollback;
+mod s3_backend;
+mod share;
+mod snapshot_export;
+mod snapshot_order;
+mod snapshot_summary;
+mod source_address;
+mod staging;
+mod standby;
+mod synthetic_full;
+mod task_checkpoint;
+mod task_records;
+mod transfer_last;
+mod trash;
+mod upload_estimate;
+mod usage;
+mod verify;
+mod verify_tuning;
+mod zfs_changes;
+mod zfs_snapshot;
@@ -0,0 +1,38 @@
+// Node status tests
+//
+// # cargo test --release cloud::test::node_status
+
+use anyhow::Error;
+
+use pbs_api_types::DataStoreConfig;
+
+use crate::cloud::node_status::filesystem_usage;
+
+use super::harness::create_testdir;
+
+#[test]
+fn test_datastore_usage() -> Result<(), Error> {
+    let testdir = create_testdir("test_datastore_usage")?;
+    let path = testdir.to_string_lossy().to_string();
+
+    let datastores = vec![
+        DataStoreConfig::new("store1".to_string(), path.clone()),
+        DataStoreConfig::new("removed".to_string(), "/nonexistent/store".to_string()),
+        DataStoreConfig::new("store2".to_string(), path),
+    ];
+    let list = proxmox_async::runtime::block_on(filesystem_usage(datastores));
+
+    // datastores which cannot be queried are skipped
+    let stores: Vec<&str> = list.iter().map(|usage| usage.store.as_str()).collect();
+    assert_eq!(stores, vec!["store1", "store2"]);
+
+    let info = proxmox_sys::fs::fs_info(&testdir)?;
+    for usage in list {
+        assert_eq!(usage.total, info.total);
+        assert!(usage.total > 0);
+        assert!(usage.used <= usage.total);
+        assert!(usage.avail <= usage.total);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,104 @@
+// Object tag tests
+//
+// # cargo test --release cloud::test::object_tags
+
+use anyhow::Error;
+
+use pbs_api_types::CloudBackupJobSetup;
+
+use crate::cloud::backend::{CloudBackend, MockCloudBackend, PutOptions};
+use crate::cloud::retag::retag_objects;
+
+use super::harness::{test_target, TestWorker};
+
+fn tags(list: &[(&str, &str)]) -> Vec<(String, String)> {
+    list.iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn test_object_tags() -> Result<(), Error> {
+    let mut target = test_target("test");
+    target.config.object_tag = Some(vec!["team=infra".to_string(), "env=prod".to_string()]);
+    target.config.request_tagging = Some(true);
+
+    let setup = CloudBackupJobSetup {
+        store: "store1".to_string(),
+        target: Some("test".to_string()),
+        latest_only: None,
+        notify_user: None,
+        group_filter: None,
+        ns: None,
+        max_depth: None,
+        max_chain_length: None,
+        storage_class: None,
+        retention_lock: None,
+        transfer_last: None,
+        object_tag: Some(vec!["env=test".to_string(), "cost-center=42".to_string()]),
+        allowed_window: None,
+        blackout_window: None,
+        window_action: None,
+        zfs_change_detection: None,
+        local_io_latency: None,
+        local_io_min_rate: None,
+        order: None,
+        on_error: None,
+        request_trace: None,
+    };
+
+    // job tags replace target tags with the same key
+    let options = PutOptions::from_job_setup(&setup)
+        .with_object_tags(&target, Some(&setup))?
+        .with_request_tags(&target, "store1", None);
+    assert_eq!(
+        options.tags,
+        tags(&[
+            ("team", "infra"),
+            ("env", "test"),
+            ("cost-center", "42"),
+            ("node", proxmox_sys::nodename()),
+            ("datastore", "store1"),
+        ])
+    );
+
+    let backend = MockCloudBackend::new();
+    options.check_capabilities(&backend.capabilities()?)?;
+
+    target.config.object_tag = Some((0..11).map(|i| format!("tag{}=x", i)).collect());
+    let options = PutOptions::default().with_object_tags(&target, None)?;
+    assert!(options
+        .check_capabilities(&backend.capabilities()?)
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_retag_objects() -> Result<(), Error> {
+    let worker = TestWorker::default();
+    let backend = MockCloudBackend::new();
+
+    let mut target = test_target("test");
+    target.config.access_log_prefix = Some("logs".to_string());
+
+    backend.put_object("media-sets/1/catalog.json", b"1")?;
+    backend.put_object("logs/2024-01-01-00-00-00-1", b"2")?;
+
+    target.config.object_tag = Some(vec!["team=infra".to_string()]);
+    assert_eq!(retag_objects(&worker, &target, &backend)?, 1);
+    assert_eq!(
+        backend
+            .object_options("media-sets/1/catalog.json")
+            .unwrap()
+            .tags,
+        tags(&[("team", "infra")])
+    );
+    assert!(backend
+        .object_options("logs/2024-01-01-00-00-00-1")
+        .unwrap()
+        .tags
+        .is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,141 @@
+// OpenID role mapping tests
+//
+// # cargo test --release cloud::test::openid_roles
+
+use std::collections::BTreeSet;
+
+use anyhow::Error;
+use serde_json::json;
+
+use pbs_api_types::{Authid, Userid};
+use pbs_config::acl::AclTree;
+
+use crate::cloud::openid_roles::{
+    apply_user_roles, claim_values, desired_grants, parse_role_mappings,
+};
+use crate::cloud::role_sync::RoleGrant;
+
+fn userid(name: &str) -> Userid {
+    format!("{}@oidc1", name).parse().unwrap()
+}
+
+fn mapping(list: &[&str]) -> Vec<String> {
+    list.iter().map(|m| m.to_string()).collect()
+}
+
+fn roles(tree: &mut AclTree, path: &str, name: &str) -> Vec<String> {
+    let auth_id = Authid::from(userid(name));
+    let mut list: Vec<_> = tree
+        .find_node(path)
+        .and_then(|node| node.users.get(&auth_id))
+        .map(|roles| roles.keys().cloned().collect())
+        .unwrap_or_default();
+    list.sort();
+    list
+}
+
+#[test]
+fn test_claim_values() {
+    let info = json!({
+        "sub": "alice",
+        "groups": ["operators", "admins", "operators", 42],
+        "role": "auditor",
+        "realm_access": { "roles": ["cloud-admin"] },
+    });
+
+    assert_eq!(claim_values(&info, "groups"), vec!["admins", "operators"]);
+    assert_eq!(claim_values(&info, "role"), vec!["auditor"]);
+    assert_eq!(
+        claim_values(&info, "realm_access.roles"),
+        vec!["cloud-admin"]
+    );
+    assert!(claim_values(&info, "roles").is_empty());
+    assert!(claim_values(&info, "realm_access.groups").is_empty());
+}
+
+#[test]
+fn test_desired_grants() -> Result<(), Error> {
+    let mappings = parse_role_mappings(&mapping(&[
+        "value=operators,role=CloudUser,path=/cloud/target/prod",
+        "value=admins,role=CloudUser,path=/cloud/target/prod,propagate=0",
+        "value=admins,role=CloudAdmin,path=/cloud",
+    ]))?;
+
+    let grants = desired_grants(&mappings, &userid("alice"), &["operators".to_string()]);
+    assert_eq!(grants.len(), 1);
+
+    let grants = desired_grants(
+        &mappings,
+        &userid("bob"),
+        &["admins".to_string(), "operators".to_string()],
+    );
+    assert_eq!(grants.len(), 2);
+    // the propagating mapping wins
+    let grant = RoleGrant {
+        userid: userid("bob"),
+        path: "/cloud/target/prod".to_string(),
+        role: "CloudUser".to_string(),
+    };
+    assert_eq!(grants.get(&grant), Some(&true));
+
+    assert!(desired_grants(&mappings, &userid("carol"), &[]).is_empty());
+
+    assert!(parse_role_mappings(&mapping(&["value=admins,role=CloudAdmin"])).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_apply_user_roles() -> Result<(), Error> {
+    let mut tree = AclTree::from_raw(
+        "\
+acl:1:/cloud/target/prod:bob@oidc1:CloudUser
+",
+    )?;
+
+    let mappings = parse_role_mappings(&mapping(&[
+        "value=operators,role=CloudUser,path=/cloud/target/prod",
+        "value=admins,role=CloudAdmin,path=/cloud",
+    ]))?;
+    let mut managed = BTreeSet::new();
+
+    // alice logs in with both claim values
+    let values = ["admins".to_string(), "operators".to_string()];
+    let desired = desired_grants(&mappings, &userid("alice"), &values);
+    let changes = apply_user_roles(&mut tree, &userid("alice"), &desired, &mut managed);
+    assert_eq!(changes.granted.len(), 2);
+    assert!(changes.revoked.is_empty());
+    assert_eq!(managed.len(), 2);
+
+    // bob got his role manually before
+    let desired = desired_grants(&mappings, &userid("bob"), &values[1..]);
+    let changes = apply_user_roles(&mut tree, &userid("bob"), &desired, &mut managed);
+    assert!(changes.granted.is_empty());
+    assert_eq!(managed.len(), 2);
+
+    // the admins claim of alice disappeared
+    let desired = desired_grants(&mappings, &userid("alice"), &values[1..]);
+    let changes = apply_user_roles(&mut tree, &userid("alice"), &desired, &mut managed);
+    assert!(changes.granted.is_empty());
+    assert_eq!(changes.revoked.len(), 1);
+    assert_eq!(changes.revoked[0].role, "CloudAdmin");
+    assert_eq!(managed.len(), 1);
+    assert!(roles(&mut tree, "/cloud", "alice").is_empty());
+    assert_eq!(
+        roles(&mut tree, "/cloud/target/prod", "alice"),
+        vec!["CloudUser"]
+    );
+
+    // bob lost the claim, but keeps the manually configured role
+    let desired = desired_grants(&mappings, &userid("bob"), &[]);
+    let changes = apply_user_roles(&mut tree, &userid("bob"), &desired, &mut managed);
+    assert!(changes.revoked.is_empty());
+    assert_eq!(
+        roles(&mut tree, "/cloud/target/prod", "bob"),
+        vec!["CloudUser"]
+    );
+    // grants of other users are kept
+    assert_eq!(managed.len(), 1);
+
+    Ok(())
+}
@@ -0,0 +1,134 @@
+// Parity object tests (against the mock backend)
+//
+// # cargo test --release cloud::test::parity
+
+use anyhow::Error;
+
+use proxmox_uuid::Uuid;
+
+use crate::cloud::backend::{CloudBackend, MockCloudBackend, PutOptions};
+use crate::cloud::catalog::{ChunkArchiveEntry, MediaSetCatalog, MediaSetLabel};
+use crate::cloud::layout;
+use crate::cloud::parity::{rebuild_chunk_archive, rebuild_parity, repair_target, ParityBuilder};
+use crate::cloud::reconcile::reconcile_target;
+
+use super::harness::{create_testdir, TestTarget, TestWorker, TEST_STORE};
+
+// media set with archives of the given sizes, protected by one parity object
+fn write_parity_media_set(
+    backend: &dyn CloudBackend,
+    sizes: &[usize],
+) -> Result<MediaSetCatalog, Error> {
+    let mut media_set = MediaSetCatalog::new(MediaSetLabel {
+        uuid: Uuid::generate(),
+        ctime: 1_600_000_000,
+        base: None,
+        node: "testnode".to_string(),
+    });
+    backend.put_object(
+        &layout::media_set_label_key(media_set.uuid()),
+        &serde_json::to_vec(&media_set.label)?,
+    )?;
+
+    let mut parity = ParityBuilder::new();
+    for (i, size) in sizes.iter().enumerate() {
+        let data: Vec<u8> = (0..*size).map(|n| (n * (i + 3)) as u8).collect();
+        let uuid = Uuid::generate();
+        backend.put_object(&layout::chunk_archive_key(media_set.uuid(), &uuid), &data)?;
+        parity.add(&uuid, &data);
+        media_set.archives.push(ChunkArchiveEntry {
+            uuid,
+            store: TEST_STORE.to_string(),
+            key: None,
+            size: data.len() as u64,
+            csum: Some(openssl::sha::sha256(&data)),
+            chunks: Vec::new(),
+        });
+    }
+    let parity = parity.finish(backend, media_set.uuid(), &PutOptions::default())?;
+    media_set.parity.push(parity);
+
+    backend.put_object(
+        &layout::media_set_catalog_key(media_set.uuid()),
+        &serde_json::to_vec(&media_set)?,
+    )?;
+
+    Ok(media_set)
+}
+
+#[test]
+fn test_rebuild_chunk_archive() -> Result<(), Error> {
+    let backend = MockCloudBackend::new();
+    let media_set = write_parity_media_set(&backend, &[1000, 4096, 10])?;
+
+    let parity = &media_set.parity[0];
+    assert_eq!(parity.archives.len(), 3);
+    assert_eq!(parity.size, 4096);
+
+    // each archive can be rebuilt from the others
+    for archive in media_set.archives.iter() {
+        let key = layout::chunk_archive_key(media_set.uuid(), &archive.uuid);
+        let data = backend.get_object(&key)?;
+        backend.delete_object(&key)?;
+        assert_eq!(
+            rebuild_chunk_archive(&backend, &media_set, &archive.uuid)?,
+            data
+        );
+        backend.put_object(&key, &data)?;
+    }
+
+    let key = layout::parity_key(media_set.uuid(), &parity.uuid);
+    let data = backend.get_object(&key)?;
+    assert_eq!(rebuild_parity(&backend, &media_set, parity)?, data);
+
+    // two lost archives of the same group cannot be repaired
+    for archive in media_set.archives.iter().take(2) {
+        backend.delete_object(&layout::chunk_archive_key(media_set.uuid(), &archive.uuid))?;
+    }
+    assert!(rebuild_chunk_archive(&backend, &media_set, &media_set.archives[0].uuid).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_repair_target() -> Result<(), Error> {
+    let target = TestTarget::new(create_testdir("test_repair_target")?);
+    let worker = TestWorker::default();
+
+    let media_set = write_parity_media_set(&*target.backend, &[300, 200, 100, 50])?;
+    media_set.save(&target.base_path, &target.target.name)?;
+
+    let lost = layout::chunk_archive_key(media_set.uuid(), &media_set.archives[1].uuid);
+    target.backend.delete_object(&lost)?;
+
+    let mut result = reconcile_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+    )?;
+    // label, catalog, four archives and the parity object
+    assert_eq!(result.checked, 7);
+    assert_eq!(result.missing, vec![lost.clone()]);
+
+    let repaired = repair_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        &result,
+    )?;
+    assert_eq!(repaired, vec![lost]);
+    result.remove_repaired(&repaired);
+    assert!(result.is_ok());
+
+    let result = reconcile_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+    )?;
+    assert!(result.is_ok());
+
+    Ok(())
+}
@@ -0,0 +1,114 @@
+// Chunk popularity tests
+//
+// # cargo test --release cloud::test::popularity
+
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::chunk_reader::CloudChunkReader;
+use crate::cloud::popularity::{ChunkPopularity, COLD_ARCHIVE_AGE, POPULARITY_HALF_LIFE};
+
+use super::harness::{chunk_data, create_testdir, digest, TestTarget};
+
+#[test]
+fn test_popularity_decay() -> Result<(), Error> {
+    let testdir = create_testdir("test_popularity_decay")?;
+    let mut popularity = ChunkPopularity::load(&testdir, "test")?;
+
+    let now = 1_600_000_000;
+    popularity.record(&digest(1), now);
+    popularity.record(&digest(1), now);
+    popularity.record(&digest(2), now);
+
+    assert_eq!(popularity.len(), 2);
+    assert_eq!(popularity.score(&digest(1), now), 2.0);
+    assert_eq!(
+        popularity.score(&digest(1), now + POPULARITY_HALF_LIFE),
+        1.0
+    );
+    assert_eq!(popularity.score(&digest(3), now), 0.0);
+
+    // a recent hit outweighs old ones
+    popularity.record(&digest(2), now + 2 * POPULARITY_HALF_LIFE);
+    let later = now + 2 * POPULARITY_HALF_LIFE;
+    assert!(popularity.score(&digest(2), later) > popularity.score(&digest(1), later));
+
+    popularity.save()?;
+    let popularity = ChunkPopularity::load(&testdir, "test")?;
+    assert_eq!(popularity.len(), 2);
+    assert_eq!(popularity.score(&digest(1), now), 2.0);
+
+    Ok(())
+}
+
+#[test]
+fn test_chunk_reader_records_hits() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_chunk_reader_records_hits")?);
+
+    target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+
+    let catalog = Arc::new(CloudCatalog::load(&target.base_path, "test")?);
+    let reader = CloudChunkReader::new(target.backend(), catalog, None)?;
+
+    assert_eq!(reader.fetch_chunk(&digest(2))?, chunk_data(&digest(2)));
+    assert_eq!(reader.fetch_chunk(&digest(2))?, chunk_data(&digest(2)));
+    assert!(reader.fetch_chunk(&digest(3)).is_err());
+    reader.finish()?;
+
+    let popularity = ChunkPopularity::load(&target.base_path, "test")?;
+    assert_eq!(popularity.len(), 1);
+    assert!(popularity.score(&digest(2), proxmox_time::epoch_i64()) > 1.5);
+
+    Ok(())
+}
+
+#[test]
+fn test_placement_advice() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_placement_advice")?);
+
+    let full = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    let incr = target.write_media_set(
+        Some(full.uuid()),
+        &[digest(3)],
+        &[("host/a/2020-01-02T00:00:00Z", vec![digest(1), digest(3)])],
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let now = incr.label.ctime + COLD_ARCHIVE_AGE;
+
+    let mut popularity = ChunkPopularity::load(&target.base_path, "test")?;
+    popularity.record(&digest(1), now - 10);
+    popularity.record(&digest(1), now - 10);
+    popularity.record(&digest(3), now - 10);
+
+    // cache only fits the most popular chunk
+    let size = chunk_data(&digest(1)).len() as u64;
+    let advice = popularity.advise(&catalog, now, size);
+    assert_eq!(advice.tracked_chunks, 2);
+    assert_eq!(advice.hot_chunks.len(), 1);
+    assert_eq!(advice.hot_chunks[0].digest, hex::encode(digest(1)));
+    assert_eq!(advice.hot_chunks[0].hits, 2);
+    assert_eq!(advice.hot_size, size);
+
+    // both archives were read recently
+    assert!(advice.tier_candidates.is_empty());
+
+    // nothing read for a long time - all archives are cold
+    let advice = popularity.advise(&catalog, now + COLD_ARCHIVE_AGE, u64::MAX);
+    assert_eq!(advice.hot_chunks.len(), 2);
+    assert_eq!(advice.tier_candidates.len(), 2);
+    assert_eq!(&advice.tier_candidates[0].media_set, full.uuid());
+    assert_eq!(&advice.tier_candidates[1].media_set, incr.uuid());
+
+    Ok(())
+}
@@ -0,0 +1,178 @@
+// Prune simulation and cloud prune job tests
+//
+// # cargo test --release cloud::test::prune
+
+use anyhow::Error;
+
+use pbs_api_types::{BackupDir, BackupNamespace, KeepOptions};
+
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::content::CloudContentFilter;
+use crate::cloud::prune::{compute_keep_marks, prune_snapshots, simulate_prune};
+use crate::cloud::trash::list_trash;
+
+use super::harness::{chunk_data, create_testdir, digest, TestTarget, TestWorker, TEST_STORE};
+
+fn dirs(list: &[&str]) -> Vec<BackupDir> {
+    list.iter().map(|dir| dir.parse().unwrap()).collect()
+}
+
+#[test]
+fn test_compute_keep_marks() -> Result<(), Error> {
+    // newest first
+    let list = dirs(&[
+        "host/a/2024-01-03T12:00:00Z",
+        "host/a/2024-01-02T13:00:00Z",
+        "host/a/2024-01-02T11:00:00Z",
+        "host/a/2024-01-01T12:00:00Z",
+    ]);
+    let list: Vec<&BackupDir> = list.iter().collect();
+
+    let options = KeepOptions {
+        keep_last: Some(1),
+        keep_daily: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(
+        compute_keep_marks(&list, &options)?,
+        vec![true, true, false, true]
+    );
+
+    // no keep options keep everything
+    assert_eq!(
+        compute_keep_marks(&list, &KeepOptions::default())?,
+        vec![true; 4]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_simulate_prune() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_simulate_prune")?);
+
+    target.write_media_set(
+        None,
+        &[digest(1), digest(2), digest(3), digest(4)],
+        &[
+            ("host/a/2024-01-01T00:00:00Z", vec![digest(1), digest(2)]),
+            ("host/a/2024-01-02T00:00:00Z", vec![digest(2)]),
+            ("host/a/2024-01-03T00:00:00Z", vec![digest(2), digest(3)]),
+            ("host/b/2024-01-01T00:00:00Z", vec![digest(4)]),
+        ],
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let options = KeepOptions {
+        keep_last: Some(1),
+        ..Default::default()
+    };
+
+    let result = simulate_prune(&catalog, &CloudContentFilter::default(), &options)?;
+    assert_eq!(result.kept, 2);
+    assert_eq!(result.removed, 2);
+    let keep: Vec<(String, bool)> = result
+        .snapshots
+        .iter()
+        .map(|item| (item.backup.to_string(), item.keep))
+        .collect();
+    assert_eq!(
+        keep,
+        vec![
+            ("host/a/2024-01-01T00:00:00Z".to_string(), false),
+            ("host/a/2024-01-02T00:00:00Z".to_string(), false),
+            ("host/a/2024-01-03T00:00:00Z".to_string(), true),
+            ("host/b/2024-01-01T00:00:00Z".to_string(), true),
+        ]
+    );
+
+    // only chunk 1 is not referenced by a kept snapshot
+    let files_size: u64 = catalog
+        .snapshots()
+        .filter(|(_, entry)| entry.snapshot.id() == "a" && entry.snapshot.time < 1704240000)
+        .flat_map(|(_, entry)| entry.files.iter().map(|file| file.size))
+        .sum();
+    assert_eq!(
+        result.reclaimed_bytes,
+        files_size + chunk_data(&digest(1)).len() as u64
+    );
+
+    let filter = CloudContentFilter {
+        backup_id: Some("a".to_string()),
+        ..Default::default()
+    };
+    let result = simulate_prune(&catalog, &filter, &options)?;
+    assert_eq!(result.snapshots.len(), 3);
+    assert_eq!(result.removed, 2);
+
+    // without keep options, nothing is removed
+    let result = simulate_prune(&catalog, &filter, &KeepOptions::default())?;
+    assert_eq!(result.removed, 0);
+    assert_eq!(result.reclaimed_bytes, 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_prune_snapshots() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_prune_snapshots")?);
+    let worker = TestWorker::default();
+
+    target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[
+            ("host/a/2024-01-01T00:00:00Z", vec![digest(1)]),
+            ("host/a/2024-01-02T00:00:00Z", vec![digest(1)]),
+            ("host/a/2024-01-03T00:00:00Z", vec![digest(2)]),
+        ],
+    )?;
+    let options = KeepOptions {
+        keep_last: Some(1),
+        ..Default::default()
+    };
+
+    // other namespaces are not touched
+    let prod = BackupNamespace::new("prod")?;
+    let filter = CloudContentFilter::recursive(TEST_STORE, Some(&prod), None);
+    let result = prune_snapshots(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        &filter,
+        &options,
+    )?;
+    assert!(result.removed.is_empty());
+    assert_eq!(result.kept, 0);
+
+    let filter = CloudContentFilter::recursive(TEST_STORE, None, None);
+    let result = prune_snapshots(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        &filter,
+        &options,
+    )?;
+    assert_eq!(
+        result.removed,
+        vec![
+            format!("{}:host/a/2024-01-01T00:00:00Z", TEST_STORE),
+            format!("{}:host/a/2024-01-02T00:00:00Z", TEST_STORE),
+        ]
+    );
+    assert_eq!(result.kept, 1);
+    assert!(result.failed.is_empty());
+
+    // the pruned snapshots are in the trash
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let remaining: Vec<String> = catalog
+        .snapshots()
+        .map(|(_, entry)| entry.snapshot.to_string())
+        .collect();
+    assert_eq!(remaining, vec!["host/a/2024-01-03T00:00:00Z"]);
+    assert_eq!(list_trash(&catalog, 3600).len(), 2);
+
+    Ok(())
+}
@@ -0,0 +1,163 @@
+// Quota tests
+//
+// # cargo test --release cloud::test::quota
+
+use anyhow::Error;
+
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{Authid, BackupNamespace};
+
+use crate::cloud::catalog::{
+    AttributedUsage, CloudCatalog, MediaSetCatalog, MediaSetLabel, SnapshotEntry,
+};
+use crate::cloud::dedup_stats::DedupStats;
+use crate::cloud::quota::{estimate_snapshot_usage, QuotaTracker};
+use crate::cloud::MAX_CHUNK_ARCHIVE_SIZE;
+
+use super::harness::{test_target, TEST_STORE};
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+fn auth_id(name: &str) -> Authid {
+    name.parse().unwrap()
+}
+
+fn ns(name: &str) -> BackupNamespace {
+    name.parse().unwrap()
+}
+
+fn snapshot_entry(ns: &str, snapshot: &str, owner: &str, bytes: u64) -> SnapshotEntry {
+    SnapshotEntry {
+        store: TEST_STORE.to_string(),
+        ns: ns.parse().unwrap(),
+        snapshot: snapshot.parse().unwrap(),
+        key: None,
+        files: Vec::new(),
+        chunks: Vec::new(),
+        verification: None,
+        fingerprint: None,
+        owner: Some(auth_id(owner)),
+        attributed: AttributedUsage { bytes, objects: 2 },
+        cloud_verification: None,
+    }
+}
+
+fn test_catalog() -> CloudCatalog {
+    let mut media_set = MediaSetCatalog::new(MediaSetLabel {
+        uuid: Uuid::generate(),
+        ctime: 0,
+        base: None,
+        node: "test".to_string(),
+    });
+    media_set.snapshots = vec![
+        snapshot_entry("", "vm/100/2024-01-01T00:00:00Z", "alice@pbs", 3 * GIB),
+        snapshot_entry("dev", "vm/101/2024-01-01T00:00:00Z", "bob@pbs", GIB),
+        snapshot_entry("dev/a", "vm/102/2024-01-01T00:00:00Z", "alice@pbs", GIB),
+    ];
+    CloudCatalog::from_media_sets("/nonexistent", "target1", vec![media_set])
+}
+
+#[test]
+fn test_parse_quotas() -> Result<(), Error> {
+    let mut target = test_target("target1");
+    target.config.quota = Some(vec![
+        "owner=alice@pbs,max-size=4".to_string(),
+        "ns=dev,max-objects=100".to_string(),
+    ]);
+    let quotas = target.config.quotas()?;
+    assert_eq!(quotas.len(), 2);
+    assert_eq!(quotas[0].max_bytes(), Some(4 * GIB));
+    assert_eq!(quotas[1].max_bytes(), None);
+
+    assert!(quotas[0].applies_to(Some(&auth_id("alice@pbs")), &ns("prod")));
+    assert!(!quotas[0].applies_to(Some(&auth_id("alice@pbs!token")), &ns("prod")));
+    assert!(!quotas[0].applies_to(None, &ns("prod")));
+    assert!(quotas[1].applies_to(None, &ns("dev/a")));
+    assert!(!quotas[1].applies_to(Some(&auth_id("bob@pbs")), &ns("prod")));
+
+    for invalid in [
+        vec!["max-size=4"],
+        vec!["owner=alice@pbs,ns=dev,max-size=4"],
+        vec!["owner=alice@pbs"],
+        vec!["ns=dev,max-size=1", "ns=dev,max-objects=1"],
+    ] {
+        target.config.quota = Some(invalid.iter().map(|q| q.to_string()).collect());
+        assert!(
+            target.config.quotas().is_err(),
+            "quota {:?} should be rejected",
+            invalid
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_quota_tracker() -> Result<(), Error> {
+    let mut target = test_target("target1");
+    target.config.quota = Some(vec![
+        "owner=alice@pbs,max-size=5".to_string(),
+        "ns=dev,max-objects=5".to_string(),
+    ]);
+    let mut tracker = QuotaTracker::new(target.config.quotas()?, &test_catalog());
+
+    let status = tracker.status();
+    assert_eq!(status[0].used_bytes, 4 * GIB);
+    assert_eq!(status[0].remaining_bytes, Some(GIB));
+    assert_eq!(status[0].remaining_objects, None);
+    assert_eq!(status[1].used_bytes, 2 * GIB);
+    assert_eq!(status[1].used_objects, 4);
+    assert_eq!(status[1].remaining_objects, Some(1));
+
+    let usage = |bytes, objects| AttributedUsage { bytes, objects };
+
+    let alice = auth_id("alice@pbs");
+    tracker.check(Some(&alice), &ns("prod"), &usage(GIB, 10))?;
+    assert!(tracker
+        .check(Some(&alice), &ns("prod"), &usage(GIB + 1, 1))
+        .is_err());
+    // the namespace quota applies as well
+    assert!(tracker
+        .check(Some(&alice), &ns("dev"), &usage(1, 2))
+        .is_err());
+    // other owners outside of 'dev' are not limited
+    tracker.check(
+        Some(&auth_id("bob@pbs")),
+        &ns("prod"),
+        &usage(100 * GIB, 100),
+    )?;
+
+    tracker.add(Some(&alice), &ns("dev/b"), &usage(GIB, 1));
+    let status = tracker.status();
+    assert_eq!(status[0].remaining_bytes, Some(0));
+    assert_eq!(status[1].remaining_objects, Some(0));
+    assert!(tracker
+        .check(Some(&alice), &ns("prod"), &usage(1, 1))
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_estimate_snapshot_usage() {
+    let stats = DedupStats {
+        snapshots: 1,
+        logical_bytes: 3 * MAX_CHUNK_ARCHIVE_SIZE as u64,
+        present_bytes: MAX_CHUNK_ARCHIVE_SIZE as u64 - 1,
+        uploaded_bytes: 0,
+    };
+    let estimate = estimate_snapshot_usage(&stats, 3);
+    assert_eq!(estimate.bytes, 2 * MAX_CHUNK_ARCHIVE_SIZE as u64 + 1);
+    // three archives, three files and the summary
+    assert_eq!(estimate.objects, 7);
+
+    let estimate = estimate_snapshot_usage(&DedupStats::default(), 2);
+    assert_eq!(
+        estimate,
+        AttributedUsage {
+            bytes: 0,
+            objects: 3
+        }
+    );
+}
@@ -0,0 +1,88 @@
+// Raw object listing tests
+//
+// # cargo test --release cloud::test::raw_list
+
+use anyhow::Error;
+
+use proxmox_router::{cli::CliEnvironment, RpcEnvironment};
+
+use pbs_api_types::CloudRawObject;
+
+use crate::api2::cloud::paginate;
+use crate::api2::cloud::storage::list_raw_objects;
+use crate::cloud::backend::{CloudBackend, MockCloudBackend, PutOptions};
+
+fn keys(list: &[CloudRawObject]) -> Vec<&str> {
+    list.iter().map(|object| object.key.as_str()).collect()
+}
+
+#[test]
+fn test_raw_list_prefix() -> Result<(), Error> {
+    let backend = MockCloudBackend::new();
+
+    for key in [
+        "media-set/b/catalog.json",
+        "chunks/bb",
+        "chunks/aa",
+        "media-set/a/label.json",
+    ] {
+        backend.put_object(key, key.as_bytes())?;
+    }
+    let options = PutOptions {
+        storage_class: Some("ARCHIVE".to_string()),
+        ..Default::default()
+    };
+    backend.put_object_with_options("chunks/cc", b"archived", &options)?;
+
+    let list = list_raw_objects(&backend, "")?;
+    assert_eq!(
+        keys(&list),
+        vec![
+            "chunks/aa",
+            "chunks/bb",
+            "chunks/cc",
+            "media-set/a/label.json",
+            "media-set/b/catalog.json",
+        ]
+    );
+
+    let list = list_raw_objects(&backend, "chunks/")?;
+    assert_eq!(keys(&list), vec!["chunks/aa", "chunks/bb", "chunks/cc"]);
+    assert_eq!(list[0].size, 9);
+    assert!(list[0].etag.is_some());
+    assert_eq!(list[2].storage_class.as_deref(), Some("ARCHIVE"));
+
+    // the prefix is not a directory, keys only have to start with it
+    let list = list_raw_objects(&backend, "media-set/a")?;
+    assert_eq!(keys(&list), vec!["media-set/a/label.json"]);
+    assert!(list_raw_objects(&backend, "chunks/x")?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_raw_list_limit() -> Result<(), Error> {
+    let backend = MockCloudBackend::new();
+    for i in 0..10 {
+        backend.put_object(&format!("chunks/{:02}", i), b"data")?;
+    }
+    let mut rpcenv = CliEnvironment::new();
+
+    let list = paginate(list_raw_objects(&backend, "")?, 0, 3, &mut rpcenv);
+    assert_eq!(keys(&list), vec!["chunks/00", "chunks/01", "chunks/02"]);
+    assert_eq!(rpcenv.result_attrib()["total"], 10);
+
+    let list = paginate(list_raw_objects(&backend, "")?, 8, 3, &mut rpcenv);
+    assert_eq!(keys(&list), vec!["chunks/08", "chunks/09"]);
+
+    // 0 means no limit
+    let list = paginate(list_raw_objects(&backend, "")?, 2, 0, &mut rpcenv);
+    assert_eq!(list.len(), 8);
+
+    let list = paginate(list_raw_objects(&backend, "chunks/0")?, 0, 50, &mut rpcenv);
+    assert_eq!(list.len(), 10);
+    assert!(paginate(list_raw_objects(&backend, "chunks/1")?, 0, 50, &mut rpcenv).is_empty());
+    assert_eq!(rpcenv.result_attrib()["total"], 0);
+
+    Ok(())
+}
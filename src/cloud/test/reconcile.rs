@@ -0,0 +1,83 @@
+// Reconciliation tests (against the mock backend)
+//
+// # cargo test --release cloud::test::reconcile
+
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use crate::cloud::backend::{CloudBackend, MockCloudBackend};
+use crate::cloud::layout;
+use crate::cloud::reconcile::{expected_objects, reconcile_target};
+
+use super::harness::{create_testdir, digest, TestTarget, TestWorker};
+
+#[test]
+fn test_reconcile_complete_target() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_reconcile_complete_target")?);
+    let worker = TestWorker::default();
+
+    let full = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    target.write_media_set(
+        Some(full.uuid()),
+        &[digest(3)],
+        &[("host/a/2020-01-02T00:00:00Z", vec![digest(1), digest(3)])],
+    )?;
+
+    let result = reconcile_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+    )?;
+    assert!(result.is_ok());
+    // label, catalog, one archive and one snapshot file per media set
+    assert_eq!(result.checked, 8);
+
+    Ok(())
+}
+
+#[test]
+fn test_reconcile_incomplete_import() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_reconcile_incomplete_import")?);
+    let worker = TestWorker::default();
+
+    let media_set = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+
+    let archive = &media_set.archives[0];
+    let archive_key = layout::chunk_archive_key(media_set.uuid(), &archive.uuid);
+    let file_key = expected_objects(&media_set)
+        .into_iter()
+        .map(|(key, _)| key)
+        .find(|key| key.ends_with("index.json.blob"))
+        .unwrap();
+
+    target.backend.delete_object(&archive_key)?;
+    target.backend.put_object(&file_key, b"truncated")?;
+
+    let result = reconcile_target(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+    )?;
+    assert!(!result.is_ok());
+    assert_eq!(result.missing, vec![archive_key]);
+    assert_eq!(result.size_mismatch, vec![file_key]);
+
+    // nothing imported at all
+    let empty: Arc<dyn CloudBackend> = Arc::new(MockCloudBackend::new());
+    let result = reconcile_target(&worker, &target.base_path, &target.target, &empty)?;
+    assert_eq!(result.missing.len(), 4);
+    assert!(result.size_mismatch.is_empty());
+
+    Ok(())
+}
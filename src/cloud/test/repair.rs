@@ -0,0 +1,99 @@
+// Snapshot repair tests (against the mock backend)
+//
+// # cargo test --release cloud::test::repair
+
+use anyhow::{bail, Error};
+
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::layout;
+use crate::cloud::repair::{build_chunk_archive, check_snapshot, SnapshotDamage};
+
+use super::harness::{chunk_data, create_testdir, digest, TestTarget, TEST_STORE};
+
+#[test]
+fn test_check_snapshot() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_check_snapshot")?);
+
+    let chunks = vec![digest(1), digest(2), digest(3)];
+    let set1 = target.write_media_set(
+        None,
+        &chunks,
+        &[("vm/100/2020-01-01T00:00:00Z", chunks.clone())],
+    )?;
+    let set2 = target.write_media_set(
+        Some(set1.uuid()),
+        &[digest(4)],
+        &[("vm/100/2020-01-02T00:00:00Z", vec![digest(1), digest(4)])],
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, &target.target.name)?;
+    let (media_set, entry) = catalog
+        .lookup_snapshot(
+            TEST_STORE,
+            &Default::default(),
+            &"vm/100/2020-01-02T00:00:00Z".parse()?,
+        )
+        .unwrap();
+    assert_eq!(media_set.uuid(), set2.uuid());
+
+    let damage = check_snapshot(&*target.backend, &catalog, media_set, entry, None)?;
+    assert!(damage.is_empty());
+
+    // chunk archive of the base media set, and a modified index
+    let archive = &set1.archives[0];
+    assert!(target
+        .backend
+        .lose_object(&layout::chunk_archive_key(set1.uuid(), &archive.uuid)));
+    let index_key = layout::snapshot_file_key(
+        set2.uuid(),
+        TEST_STORE,
+        &entry.ns,
+        &entry.snapshot,
+        "index.json.blob",
+    );
+    assert!(target.backend.corrupt_object(&index_key, b"[]"));
+
+    let damage = check_snapshot(&*target.backend, &catalog, media_set, entry, None)?;
+    assert_eq!(
+        damage,
+        SnapshotDamage {
+            files: vec!["index.json.blob".to_string()],
+            archives: vec![(set1.uuid().clone(), archive.uuid.clone())],
+        }
+    );
+
+    // bit rot with the same size is detected by the checksum
+    let archive = &set2.archives[0];
+    let key = layout::chunk_archive_key(set2.uuid(), &archive.uuid);
+    assert!(target
+        .backend
+        .corrupt_object(&key, &vec![0u8; archive.size as usize]));
+    let damage = check_snapshot(&*target.backend, &catalog, media_set, entry, None)?;
+    assert_eq!(damage.archives.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_build_chunk_archive() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_build_chunk_archive")?);
+
+    let chunks = vec![digest(1), digest(2), digest(3)];
+    let media_set = target.write_media_set(None, &chunks, &[])?;
+    let archive = &media_set.archives[0];
+
+    let data = build_chunk_archive(archive, |digest| Ok(chunk_data(digest)))?;
+    assert_eq!(
+        data,
+        target
+            .backend
+            .get_object(&layout::chunk_archive_key(media_set.uuid(), &archive.uuid))?
+    );
+
+    // local chunks must match the recorded sizes and checksum
+    assert!(build_chunk_archive(archive, |digest| Ok(vec![0u8; digest[0] as usize])).is_err());
+    assert!(build_chunk_archive(archive, |digest| Ok(vec![0u8; 16 + digest[0] as usize])).is_err());
+    assert!(build_chunk_archive(archive, |_| bail!("chunk missing")).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,202 @@
+// Replication tests (mock and local backends)
+//
+// # cargo test --release cloud::test::replication
+
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use crate::cloud::backend::{CloudBackend, LocalBackend, MockCloudBackend};
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::layout;
+use crate::cloud::replication::{replicate_media_sets, select_media_sets, ReplicationFilter};
+
+use super::harness::{create_testdir, digest, test_target, TestTarget, TestWorker, TEST_STORE};
+
+fn check_objects_equal(a: &dyn CloudBackend, b: &dyn CloudBackend) -> Result<usize, Error> {
+    let objects = a.list_objects(layout::MEDIA_SET_PREFIX)?;
+    assert_eq!(
+        objects.len(),
+        b.list_objects(layout::MEDIA_SET_PREFIX)?.len()
+    );
+    for object in objects.iter() {
+        assert_eq!(a.get_object(&object.key)?, b.get_object(&object.key)?);
+    }
+    Ok(objects.len())
+}
+
+#[test]
+fn test_streaming_replication() -> Result<(), Error> {
+    let mut source = TestTarget::new(create_testdir("test_streaming_replication")?);
+    let worker = TestWorker::default();
+
+    let full = source.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    source.write_media_set(
+        Some(full.uuid()),
+        &[digest(3)],
+        &[("host/a/2020-01-02T00:00:00Z", vec![digest(1), digest(3)])],
+    )?;
+
+    let replica = test_target("replica");
+    let replica_backend: Arc<dyn CloudBackend> = Arc::new(MockCloudBackend::new());
+
+    let stats = replicate_media_sets(
+        &worker,
+        &source.base_path,
+        &source.target,
+        &source.backend(),
+        &replica,
+        &replica_backend,
+        &ReplicationFilter::default(),
+    )?;
+
+    let objects = check_objects_equal(&*source.backend(), &*replica_backend)?;
+    assert_eq!(stats.media_sets, 2);
+    assert_eq!(stats.server_side, 0);
+    assert_eq!(stats.streamed, objects);
+
+    let catalog = CloudCatalog::load(&source.base_path, "replica")?;
+    assert_eq!(catalog.media_sets().len(), 2);
+    assert!(catalog.contains_chunk(&digest(3)));
+
+    // nothing left to do
+    let stats = replicate_media_sets(
+        &worker,
+        &source.base_path,
+        &source.target,
+        &source.backend(),
+        &replica,
+        &replica_backend,
+        &ReplicationFilter::default(),
+    )?;
+    assert_eq!(stats.media_sets, 0);
+    assert_eq!(stats.streamed, 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_select_media_sets() -> Result<(), Error> {
+    let mut source = TestTarget::new(create_testdir("test_select_media_sets")?);
+
+    let full = source.write_media_set(
+        None,
+        &[digest(1)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1)])],
+    )?;
+    let incremental = source.write_media_set(
+        Some(full.uuid()),
+        &[digest(2)],
+        &[("host/a/2020-01-02T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    let other = source.write_media_set(
+        None,
+        &[digest(3)],
+        &[("host/b/2020-01-03T00:00:00Z", vec![digest(3)])],
+    )?;
+
+    let catalog = CloudCatalog::load(&source.base_path, "test")?;
+
+    let select = |filter: ReplicationFilter| {
+        select_media_sets(&catalog, &filter)
+            .into_iter()
+            .map(|media_set| media_set.uuid().clone())
+            .collect::<Vec<_>>()
+    };
+
+    let since = |ctime| ReplicationFilter {
+        since: Some(ctime),
+        ..Default::default()
+    };
+
+    assert_eq!(select(since(other.label.ctime)), vec![other.uuid().clone()]);
+    // the base of a selected incremental media set is always included
+    assert_eq!(
+        select(since(incremental.label.ctime)),
+        vec![
+            full.uuid().clone(),
+            incremental.uuid().clone(),
+            other.uuid().clone()
+        ]
+    );
+
+    let store = |store: &str| ReplicationFilter {
+        store: Some(store.to_string()),
+        ..Default::default()
+    };
+    assert_eq!(select(store(TEST_STORE)).len(), 3);
+    assert!(select(store("other")).is_empty());
+
+    let groups = |store: Option<&str>, filters: &[&str]| ReplicationFilter {
+        store: store.map(String::from),
+        group_filter: Some(filters.iter().map(|f| f.parse().unwrap()).collect()),
+        ..Default::default()
+    };
+    assert_eq!(
+        select(groups(None, &["group:host/b"])),
+        vec![other.uuid().clone()]
+    );
+    // the base chain of a selected media set is included
+    assert_eq!(
+        select(groups(None, &["exclude:group:host/b"])),
+        vec![full.uuid().clone(), incremental.uuid().clone()]
+    );
+    assert_eq!(select(groups(Some(TEST_STORE), &["type:host"])).len(), 3);
+    assert!(select(groups(Some("other"), &["type:host"])).is_empty());
+    assert!(select(groups(None, &["type:vm"])).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_server_side_replication() -> Result<(), Error> {
+    let testdir = create_testdir("test_server_side_replication")?;
+    let mut source = TestTarget::new(testdir.clone());
+    let worker = TestWorker::default();
+
+    source.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+
+    // move the media set to a local directory, which supports server-side copies
+    let source_backend: Arc<dyn CloudBackend> =
+        Arc::new(LocalBackend::with_base(testdir.join("source")));
+    let objects = source.backend.list_objects(layout::MEDIA_SET_PREFIX)?;
+    for object in objects.iter() {
+        source_backend.put_object(&object.key, &source.backend.get_object(&object.key)?)?;
+    }
+
+    // an earlier, interrupted run copied one object already
+    let replica = test_target("replica");
+    let replica_backend: Arc<dyn CloudBackend> =
+        Arc::new(LocalBackend::with_base(testdir.join("replica")));
+    let copied = objects
+        .iter()
+        .find(|object| object.key.contains("/chunk-archive/"))
+        .unwrap();
+    replica_backend.put_object(&copied.key, &source_backend.get_object(&copied.key)?)?;
+
+    let stats = replicate_media_sets(
+        &worker,
+        &testdir,
+        &source.target,
+        &source_backend,
+        &replica,
+        &replica_backend,
+        &ReplicationFilter::default(),
+    )?;
+
+    check_objects_equal(&*source_backend, &*replica_backend)?;
+    assert_eq!(stats.media_sets, 1);
+    assert_eq!(stats.skipped, 1);
+    assert_eq!(stats.server_side, objects.len() - 1);
+    assert_eq!(stats.streamed, 0);
+
+    Ok(())
+}
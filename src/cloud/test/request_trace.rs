@@ -0,0 +1,89 @@
+// Request tracing tests
+//
+// # cargo test --release cloud::test::request_trace
+
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use pbs_api_types::CloudRequestTrace;
+
+use crate::cloud::request_trace::{
+    load_request_trace, prune_request_traces, record_request, save_request_trace, RequestTrace,
+    TraceGuard,
+};
+
+use super::harness::create_testdir;
+
+const UPID1: &str =
+    "UPID:node1:00000001:00000001:00000001:5F5E1000:cloud-backup-job:job1:root@pam:";
+const UPID2: &str =
+    "UPID:node1:00000002:00000002:00000002:5F5E1001:cloud-backup-job:job2:root@pam:";
+
+fn request(key: &str) -> CloudRequestTrace {
+    CloudRequestTrace {
+        time: 1_600_000_000,
+        method: "PUT".to_string(),
+        host: "bucket.s3.example.com".to_string(),
+        key: Some(key.to_string()),
+        status: Some(200),
+        duration: 12,
+        request_id: Some("0123456789ABCDEF".to_string()),
+    }
+}
+
+#[test]
+fn test_ring_buffer() -> Result<(), Error> {
+    let trace = RequestTrace::new(2);
+    trace.record(request("a"));
+    trace.record(request("b"));
+    trace.record(request("c"));
+
+    assert_eq!(trace.entries(), vec![request("b"), request("c")]);
+    assert_eq!(trace.dropped(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_thread_trace() -> Result<(), Error> {
+    // not tracing, the entry is not even built
+    record_request(|| unreachable!());
+
+    let trace = Arc::new(RequestTrace::new(10));
+    {
+        let _guard = TraceGuard::install(Arc::clone(&trace));
+        record_request(|| request("a"));
+
+        // other threads are not traced
+        std::thread::spawn(|| record_request(|| request("b")))
+            .join()
+            .unwrap();
+    }
+    record_request(|| unreachable!());
+
+    assert_eq!(trace.entries(), vec![request("a")]);
+
+    Ok(())
+}
+
+#[test]
+fn test_saved_traces() -> Result<(), Error> {
+    let testdir = create_testdir("test_saved_traces")?;
+
+    assert!(load_request_trace(&testdir, UPID1)?.is_empty());
+
+    save_request_trace(&testdir, UPID1, &[request("a"), request("b")])?;
+    save_request_trace(&testdir, UPID2, &[request("c")])?;
+    assert_eq!(
+        load_request_trace(&testdir, UPID1)?,
+        vec![request("a"), request("b")]
+    );
+
+    // traces go away with their task
+    assert_eq!(prune_request_traces(&testdir, |upid| upid == UPID2)?, 1);
+    assert!(load_request_trace(&testdir, UPID1)?.is_empty());
+    assert_eq!(load_request_trace(&testdir, UPID2)?.len(), 1);
+
+    Ok(())
+}
@@ -0,0 +1,162 @@
+// Restore preview tests (against the mock backend)
+//
+// # cargo test --release cloud::test::restore_preview
+
+use std::ffi::CString;
+use std::io::Cursor;
+
+use anyhow::Error;
+
+use pbs_api_types::{CloudArchiveEntrySize, CloudArchivePreview, CryptMode};
+use pbs_datastore::catalog::{BackupCatalogWriter, CatalogReader, CatalogWriter};
+use pbs_datastore::manifest::{BackupManifest, MANIFEST_BLOB_NAME};
+use pbs_datastore::{DataBlob, CATALOG_NAME};
+
+use crate::cloud::backend::{CloudBackend, PutOptions};
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::layout;
+use crate::cloud::restore_preview::{archive_previews, catalog_breakdown, restore_preview};
+use crate::cloud::snapshot_summary::{build_snapshot_summary, upload_snapshot_summary};
+
+use super::harness::{create_testdir, digest, TestTarget};
+
+fn name(name: &str) -> CString {
+    CString::new(name).unwrap()
+}
+
+fn test_catalog() -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    let mut writer = CatalogWriter::new(&mut data)?;
+
+    writer.start_directory(&name("root.pxar.didx"))?;
+    writer.add_file(&name("small"), 10, 0)?;
+    writer.start_directory(&name("etc"))?;
+    writer.add_file(&name("big"), 1000, 0)?;
+    writer.add_file(&name("medium"), 100, 0)?;
+    writer.add_symlink(&name("link"))?;
+    writer.end_directory()?;
+    writer.end_directory()?;
+
+    writer.start_directory(&name("data.pxar.didx"))?;
+    writer.end_directory()?;
+
+    writer.finish()?;
+    drop(writer);
+
+    Ok(data)
+}
+
+#[test]
+fn test_catalog_breakdown() -> Result<(), Error> {
+    let mut reader = CatalogReader::new(Cursor::new(test_catalog()?));
+    let list = catalog_breakdown(&mut reader, 2)?;
+
+    assert_eq!(list.len(), 2);
+    let root = list.iter().find(|a| a.archive == "root.pxar.didx").unwrap();
+    assert_eq!(root.file_count, Some(3));
+    assert_eq!(root.files_size, Some(1110));
+    assert_eq!(
+        root.largest,
+        vec![
+            CloudArchiveEntrySize {
+                path: "/etc/big".to_string(),
+                size: 1000,
+            },
+            CloudArchiveEntrySize {
+                path: "/etc/medium".to_string(),
+                size: 100,
+            },
+        ]
+    );
+
+    let data = list.iter().find(|a| a.archive == "data.pxar.didx").unwrap();
+    assert_eq!(data.file_count, Some(0));
+    assert!(data.largest.is_empty());
+
+    Ok(())
+}
+
+fn test_manifest(snapshot: &str) -> Result<BackupManifest, Error> {
+    let mut manifest = BackupManifest::new(snapshot.parse()?);
+    manifest.add_file("root.pxar.didx".to_string(), 2000, [0; 32], CryptMode::None)?;
+    manifest.add_file(
+        "drive-scsi0.img.fidx".to_string(),
+        4096,
+        [0; 32],
+        CryptMode::None,
+    )?;
+    manifest.add_file(CATALOG_NAME.to_string(), 300, [0; 32], CryptMode::None)?;
+    Ok(manifest)
+}
+
+#[test]
+fn test_archive_previews() -> Result<(), Error> {
+    let manifest = test_manifest("host/a/2020-01-01T00:00:00Z")?;
+
+    let mut reader = CatalogReader::new(Cursor::new(test_catalog()?));
+    let list = archive_previews(&manifest, catalog_breakdown(&mut reader, 10)?);
+
+    // archives of the manifest only, sizes from the manifest
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0].archive, "root.pxar.didx");
+    assert_eq!(list[0].size, 2000);
+    assert_eq!(list[0].file_count, Some(3));
+    assert_eq!(list[0].largest.len(), 3);
+    assert_eq!(list[1].archive, "drive-scsi0.img.fidx");
+    assert_eq!(list[1].size, 4096);
+    assert_eq!(list[1].file_count, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_restore_preview() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_restore_preview")?);
+    let snapshot = "host/a/2020-01-01T00:00:00Z";
+
+    target.write_media_set(None, &[digest(1)], &[(snapshot, vec![digest(1)])])?;
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let (media_set, entry) = catalog.snapshots().next().unwrap();
+
+    // no breakdown recorded, archive sizes from the manifest
+    let manifest = test_manifest(snapshot)?;
+    let blob = DataBlob::encode(manifest.to_string(None)?.as_bytes(), None, true)?;
+    let key = layout::snapshot_file_key(
+        media_set.uuid(),
+        &entry.store,
+        &entry.ns,
+        &entry.snapshot,
+        MANIFEST_BLOB_NAME,
+    );
+    target.backend.put_object(&key, blob.raw_data())?;
+
+    let preview = restore_preview(&*target.backend, &catalog, media_set.uuid(), entry)?;
+    assert_eq!(preview.snapshot, snapshot);
+    assert!(preview.download_size.is_some());
+    assert_eq!(preview.archives.len(), 2);
+    assert!(preview.archives.iter().all(|a| a.file_count.is_none()));
+
+    // the recorded breakdown is used as is, without reading the manifest
+    target.backend.delete_object(&key)?;
+    let mut summary = build_snapshot_summary(media_set.uuid(), entry, Some(1));
+    summary.archives = vec![CloudArchivePreview {
+        archive: "root.pxar.didx".to_string(),
+        size: 2000,
+        file_count: Some(3),
+        files_size: Some(1110),
+        largest: Vec::new(),
+    }];
+    upload_snapshot_summary(
+        &*target.backend,
+        media_set.uuid(),
+        entry,
+        &summary,
+        &PutOptions::default(),
+    )?;
+
+    let preview = restore_preview(&*target.backend, &catalog, media_set.uuid(), entry)?;
+    assert_eq!(preview.archives, summary.archives);
+    assert_eq!(preview.download_size, Some(1 + summary.files_size));
+
+    Ok(())
+}
@@ -0,0 +1,129 @@
+// Retention report tests
+//
+// # cargo test --release cloud::test::retention_report
+
+use anyhow::Error;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::x509::{X509Builder, X509NameBuilder};
+
+use crate::cloud::backend::{CloudBackend, PutOptions};
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::layout;
+use crate::cloud::retention_report::{
+    build_retention_report, sign_retention_report, verify_retention_attestation,
+};
+
+use super::harness::{create_testdir, digest, TestTarget};
+
+const RETAIN_UNTIL: i64 = 2_000_000_000;
+
+// re-upload all objects below `prefix` (except below `skip`) with a retention time
+fn lock_objects(target: &TestTarget, prefix: &str, skip: Option<&str>) -> Result<(), Error> {
+    let options = PutOptions {
+        retain_until: Some(RETAIN_UNTIL),
+        ..Default::default()
+    };
+    for info in target.backend.list_objects(prefix)? {
+        if skip.map(|skip| info.key.starts_with(skip)).unwrap_or(false) {
+            continue;
+        }
+        let data = target.backend.get_object(&info.key)?;
+        target
+            .backend
+            .put_object_with_options(&info.key, &data, &options)?;
+    }
+    Ok(())
+}
+
+// self signed certificate and key (PEM)
+fn test_certificate() -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let key = PKey::from_rsa(Rsa::generate(2048)?)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", "testnode")?;
+    let name = name.build();
+
+    let mut x509 = X509Builder::new()?;
+    x509.set_version(2)?;
+    x509.set_not_before(&*openssl::asn1::Asn1Time::days_from_now(0)?)?;
+    x509.set_not_after(&*openssl::asn1::Asn1Time::days_from_now(1)?)?;
+    x509.set_subject_name(&name)?;
+    x509.set_issuer_name(&name)?;
+    x509.set_pubkey(&key)?;
+    x509.sign(&key, MessageDigest::sha256())?;
+
+    Ok((key.private_key_to_pem_pkcs8()?, x509.build().to_pem()?))
+}
+
+#[test]
+fn test_retention_report() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_retention_report")?);
+    target.backend.set_time(1_700_000_000);
+
+    let full = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    let incremental = target.write_media_set(
+        Some(full.uuid()),
+        &[digest(3)],
+        &[("host/a/2020-01-02T00:00:00Z", vec![digest(1), digest(3)])],
+    )?;
+
+    // the full media set is locked completely, the incremental one except
+    // for its chunk archive
+    lock_objects(&target, &layout::media_set_prefix(full.uuid()), None)?;
+    lock_objects(
+        &target,
+        &layout::media_set_prefix(incremental.uuid()),
+        Some(&format!(
+            "{}chunk-archive/",
+            layout::media_set_prefix(incremental.uuid())
+        )),
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let report = build_retention_report(&target.target, &*target.backend, &catalog)?;
+
+    assert!(report.lock_config.enabled);
+    assert_eq!(report.media_sets.len(), 2);
+
+    // label, catalog, index and chunk archive
+    assert_eq!(report.media_sets[0].uuid, full.uuid().to_string());
+    assert_eq!(report.media_sets[0].objects, 4);
+    assert_eq!(report.media_sets[0].locked_objects, 4);
+    assert_eq!(report.media_sets[0].locked_until, Some(RETAIN_UNTIL));
+    assert_eq!(report.media_sets[1].objects, 4);
+    assert_eq!(report.media_sets[1].locked_objects, 3);
+    assert_eq!(report.media_sets[1].locked_until, None);
+
+    // the second snapshot references the unlocked chunk archive
+    assert_eq!(report.snapshots.len(), 2);
+    assert_eq!(
+        report.snapshots[0].snapshot,
+        "store1:host/a/2020-01-01T00:00:00Z"
+    );
+    assert_eq!(report.snapshots[0].locked_until, Some(RETAIN_UNTIL));
+    assert_eq!(
+        report.snapshots[1].media_set,
+        incremental.uuid().to_string()
+    );
+    assert_eq!(report.snapshots[1].locked_until, None);
+
+    let (key_pem, cert_pem) = test_certificate()?;
+    let mut attestation = sign_retention_report(report, &key_pem, &cert_pem)?;
+    verify_retention_attestation(&attestation)?;
+
+    // any modification of the report invalidates the signature
+    attestation.report.snapshots[1].locked_until = Some(RETAIN_UNTIL);
+    assert!(verify_retention_attestation(&attestation).is_err());
+
+    // signing needs the key of the certificate
+    let (other_key, _) = test_certificate()?;
+    assert!(sign_retention_report(attestation.report, &other_key, &cert_pem).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,166 @@
+// Role sync tests
+//
+// # cargo test --release cloud::test::role_sync
+
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::Error;
+
+use pbs_api_types::{Authid, CloudRoleSyncJobConfig, Userid};
+use pbs_config::acl::AclTree;
+
+use crate::cloud::role_sync::{
+    apply_role_sync, desired_grants, escape_filter_value, member_usernames, parse_mappings,
+    RoleGrant,
+};
+
+fn test_job(mapping: &[&str]) -> CloudRoleSyncJobConfig {
+    CloudRoleSyncJobConfig {
+        id: "sync1".to_string(),
+        realm: "ldap1".to_string(),
+        mapping: mapping.iter().map(|m| m.to_string()).collect(),
+        comment: None,
+        schedule: None,
+        disable: false,
+    }
+}
+
+fn userid(name: &str) -> Userid {
+    format!("{}@ldap1", name).parse().unwrap()
+}
+
+fn roles(tree: &mut AclTree, path: &str, name: &str) -> Vec<(String, bool)> {
+    let auth_id = Authid::from(userid(name));
+    let mut list: Vec<_> = tree
+        .find_node(path)
+        .and_then(|node| node.users.get(&auth_id))
+        .map(|roles| roles.clone().into_iter().collect())
+        .unwrap_or_default();
+    list.sort();
+    list
+}
+
+#[test]
+fn test_escape_filter_value() {
+    assert_eq!(escape_filter_value("backup-operators"), "backup-operators");
+    assert_eq!(escape_filter_value("a*(b)\\c"), "a\\2a\\28b\\29\\5cc");
+}
+
+#[test]
+fn test_member_usernames() {
+    let user_dns: HashMap<String, String> = [
+        ("uid=alice,ou=people,dc=example,dc=com", "alice"),
+        ("uid=bob,ou=people,dc=example,dc=com", "bob"),
+    ]
+    .into_iter()
+    .map(|(dn, name)| (dn.to_string(), name.to_string()))
+    .collect();
+
+    let values = vec![
+        "uid=Alice,ou=People,dc=example,dc=com".to_string(),
+        "cn=nested,ou=groups,dc=example,dc=com".to_string(),
+        "carol".to_string(),
+        "bob".to_string(),
+        "uid=bob,ou=people,dc=example,dc=com".to_string(),
+    ];
+    assert_eq!(
+        member_usernames(&values, &user_dns),
+        vec!["alice", "bob", "carol"]
+    );
+}
+
+#[test]
+fn test_desired_grants() -> Result<(), Error> {
+    let job = test_job(&[
+        "group=operators,role=CloudUser,path=/cloud/target/prod",
+        "group=admins,role=CloudUser,path=/cloud/target/prod,propagate=0",
+        "group=admins,role=CloudAdmin,path=/cloud",
+    ]);
+    let mappings = parse_mappings(&job)?;
+    assert_eq!(mappings.len(), 3);
+
+    let members = HashMap::from([
+        (
+            "operators".to_string(),
+            vec![userid("alice"), userid("bob")],
+        ),
+        ("admins".to_string(), vec![userid("bob")]),
+    ]);
+    let grants = desired_grants(&mappings, &members);
+
+    let grant = |name: &str, path: &str, role: &str| RoleGrant {
+        userid: userid(name),
+        path: path.to_string(),
+        role: role.to_string(),
+    };
+    assert_eq!(grants.len(), 3);
+    assert_eq!(
+        grants.get(&grant("alice", "/cloud/target/prod", "CloudUser")),
+        Some(&true)
+    );
+    // the propagating mapping wins
+    assert_eq!(
+        grants.get(&grant("bob", "/cloud/target/prod", "CloudUser")),
+        Some(&true)
+    );
+    assert_eq!(
+        grants.get(&grant("bob", "/cloud", "CloudAdmin")),
+        Some(&true)
+    );
+
+    assert!(parse_mappings(&test_job(&["group=admins,role=CloudAdmin"])).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_apply_role_sync() -> Result<(), Error> {
+    let mut tree = AclTree::from_raw(
+        "\
+acl:1:/cloud/target/prod:carol@ldap1:CloudUser
+acl:1:/cloud/target/prod:dave@ldap1:CloudUser
+",
+    )?;
+
+    let mappings = parse_mappings(&test_job(&[
+        "group=operators,role=CloudUser,path=/cloud/target/prod",
+    ]))?;
+
+    // alice and carol are members, carol got her role manually before
+    let members = HashMap::from([(
+        "operators".to_string(),
+        vec![userid("alice"), userid("carol")],
+    )]);
+    let changes = apply_role_sync(
+        &mut tree,
+        &desired_grants(&mappings, &members),
+        &BTreeSet::new(),
+    );
+    assert_eq!(changes.granted.len(), 1);
+    assert_eq!(changes.granted[0].userid, userid("alice"));
+    assert!(changes.revoked.is_empty());
+    assert_eq!(changes.managed.len(), 1);
+    assert_eq!(
+        roles(&mut tree, "/cloud/target/prod", "alice"),
+        vec![("CloudUser".to_string(), true)]
+    );
+
+    // alice and carol left the group, dave joined
+    let members = HashMap::from([("operators".to_string(), vec![userid("dave")])]);
+    let changes = apply_role_sync(
+        &mut tree,
+        &desired_grants(&mappings, &members),
+        &changes.managed,
+    );
+    assert!(changes.granted.is_empty());
+    assert_eq!(changes.revoked.len(), 1);
+    assert_eq!(changes.revoked[0].userid, userid("alice"));
+    assert!(changes.managed.is_empty());
+
+    assert!(roles(&mut tree, "/cloud/target/prod", "alice").is_empty());
+    // roles not granted by the job are kept
+    assert_eq!(roles(&mut tree, "/cloud/target/prod", "carol").len(), 1);
+    assert_eq!(roles(&mut tree, "/cloud/target/prod", "dave").len(), 1);
+
+    Ok(())
+}
@@ -0,0 +1,91 @@
+// Point-in-time recovery tests (versioned mock backend)
+//
+// # cargo test --release cloud::test::rollback
+
+use anyhow::Error;
+
+use crate::cloud::backend::CloudBackend;
+use crate::cloud::catalog::{CloudCatalog, MediaSetCatalog};
+use crate::cloud::layout;
+use crate::cloud::rollback::{list_noncurrent_versions, rollback_media_sets, versions_at};
+
+use super::harness::{create_testdir, digest, TestTarget, TestWorker};
+
+#[test]
+fn test_rollback_after_mass_deletion() -> Result<(), Error> {
+    let testdir = create_testdir("test_rollback_after_mass_deletion")?;
+    let mut target = TestTarget::new(testdir.clone());
+    let worker = TestWorker::default();
+
+    target.backend.set_time(1000);
+    let media_set = target.write_media_set(
+        None,
+        &[digest(1)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1)])],
+    )?;
+
+    // accidental removal of everything
+    target.backend.set_time(2000);
+    let objects = target.backend.list_objects(layout::MEDIA_SET_PREFIX)?;
+    assert_eq!(objects.len(), 4);
+    for object in objects.iter() {
+        target.backend.delete_object(&object.key)?;
+    }
+    MediaSetCatalog::remove(&testdir, "test", media_set.uuid())?;
+
+    let noncurrent = list_noncurrent_versions(&*target.backend)?;
+    // label, catalog and index - each with version and delete marker
+    assert_eq!(noncurrent.len(), 6);
+
+    // nothing existed before the backup
+    target.backend.set_time(3000);
+    assert_eq!(
+        rollback_media_sets(&worker, &testdir, &target.target, &target.backend(), 999)?,
+        0
+    );
+
+    assert_eq!(
+        rollback_media_sets(&worker, &testdir, &target.target, &target.backend(), 1500)?,
+        4
+    );
+    assert_eq!(
+        target
+            .backend
+            .list_objects(&layout::media_set_prefix(media_set.uuid()))?
+            .len(),
+        4
+    );
+
+    let catalog = CloudCatalog::load(&testdir, "test")?;
+    assert!(catalog.lookup_media_set(media_set.uuid()).is_some());
+    assert!(catalog.chain_contains_chunk(&digest(1), None));
+
+    // the current state is kept as is
+    let list = target.backend.list_object_versions("")?;
+    assert!(versions_at(&list, 2500).is_empty());
+    assert_eq!(versions_at(&list, 3000).len(), 4);
+
+    Ok(())
+}
+
+#[test]
+fn test_rollback_requires_versioning() -> Result<(), Error> {
+    let target = TestTarget::new(create_testdir("test_rollback_requires_versioning")?);
+    let worker = TestWorker::default();
+
+    let mut capabilities = target.backend.capabilities()?;
+    capabilities.versioning = false;
+    target.backend.set_capabilities(capabilities);
+
+    assert!(rollback_media_sets(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        0
+    )
+    .is_err());
+    assert!(target.backend.list_object_versions("").is_err());
+
+    Ok(())
+}
@@ -0,0 +1,194 @@
+// S3 backend request tests
+//
+// # cargo test --release cloud::test::s3_backend
+
+use std::net::TcpListener;
+use std::time::Duration;
+
+use anyhow::Error;
+use hyper::Method;
+
+use pbs_api_types::{
+    CloudProvider, CloudTarget, CloudTargetConfig, DEFAULT_CLOUD_DATA_TIMEOUT,
+    DEFAULT_CLOUD_METADATA_TIMEOUT,
+};
+
+use crate::cloud::backend::{CloudBackend, RequestKind, S3Backend};
+
+fn s3_target(config: CloudTargetConfig) -> CloudTarget {
+    CloudTarget {
+        name: "s3".to_string(),
+        secret_key: "secret".to_string(),
+        config: CloudTargetConfig {
+            provider: CloudProvider::S3,
+            bucket: Some("bucket".to_string()),
+            region: Some("eu-central-1".to_string()),
+            access_key: Some("access".to_string()),
+            ..config
+        },
+    }
+}
+
+#[test]
+fn test_request_hosts() -> Result<(), Error> {
+    let host = "bucket.s3.eu-central-1.amazonaws.com";
+    let accelerated = "bucket.s3-accelerate.amazonaws.com";
+    let cdn = "cdn.example.com";
+
+    // plain
+    let backend = S3Backend::new(&s3_target(Default::default()))?;
+    for (kind, method, body) in [
+        (RequestKind::Data, Method::PUT, &b"data"[..]),
+        (RequestKind::Data, Method::GET, &[][..]),
+        (RequestKind::Metadata, Method::HEAD, &[][..]),
+    ] {
+        assert_eq!(backend.request_hosts(kind, &method, body), (host, host));
+    }
+
+    // path style addressing
+    let backend = S3Backend::new(&s3_target(CloudTargetConfig {
+        endpoint: Some("minio.local:9000".to_string()),
+        path_style: Some(true),
+        ..Default::default()
+    }))?;
+    assert_eq!(
+        backend.request_hosts(RequestKind::Data, &Method::GET, &[]),
+        ("minio.local:9000", "minio.local:9000")
+    );
+
+    let backend = S3Backend::new(&s3_target(CloudTargetConfig {
+        transfer_acceleration: Some(true),
+        download_host: Some(cdn.to_string()),
+        ..Default::default()
+    }))?;
+
+    // uploads use the acceleration endpoint, and are signed for it
+    assert_eq!(
+        backend.request_hosts(RequestKind::Data, &Method::PUT, b"data"),
+        (accelerated, accelerated)
+    );
+    // empty PUTs (e.g. copies) and other data requests do not
+    assert_eq!(
+        backend.request_hosts(RequestKind::Data, &Method::PUT, &[]),
+        (host, host)
+    );
+    assert_eq!(
+        backend.request_hosts(RequestKind::Data, &Method::POST, b"data"),
+        (host, host)
+    );
+
+    // downloads go through the CDN, signed for the bucket endpoint
+    assert_eq!(
+        backend.request_hosts(RequestKind::Data, &Method::GET, &[]),
+        (cdn, host)
+    );
+
+    // metadata requests always use the bucket endpoint
+    for (method, body) in [
+        (Method::GET, &[][..]),
+        (Method::PUT, &b"<Tagging/>"[..]),
+        (Method::HEAD, &[][..]),
+        (Method::DELETE, &[][..]),
+    ] {
+        assert_eq!(
+            backend.request_hosts(RequestKind::Metadata, &method, body),
+            (host, host)
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_transfer_acceleration_validation() -> Result<(), Error> {
+    let check = |config: CloudTargetConfig| {
+        s3_target(CloudTargetConfig {
+            transfer_acceleration: Some(true),
+            ..config
+        })
+        .config
+        .check_provider_properties()
+    };
+
+    check(Default::default())?;
+
+    let err = check(CloudTargetConfig {
+        endpoint: Some("s3.example.com".to_string()),
+        ..Default::default()
+    })
+    .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("only available with AWS endpoints"));
+
+    let err = check(CloudTargetConfig {
+        path_style: Some(true),
+        ..Default::default()
+    })
+    .unwrap_err();
+    assert!(err.to_string().contains("virtual hosted style"));
+
+    let mut target = s3_target(CloudTargetConfig {
+        transfer_acceleration: Some(true),
+        ..Default::default()
+    });
+    target.config.bucket = Some("my.bucket".to_string());
+    let err = target.config.check_provider_properties().unwrap_err();
+    assert!(err.to_string().contains("bucket names with dots"));
+
+    // dots are fine without acceleration
+    target.config.transfer_acceleration = Some(false);
+    target.config.check_provider_properties()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_request_timeouts() -> Result<(), Error> {
+    let backend = S3Backend::new(&s3_target(Default::default()))?;
+    assert_eq!(
+        backend.timeout(RequestKind::Metadata),
+        Duration::from_secs(DEFAULT_CLOUD_METADATA_TIMEOUT)
+    );
+    assert_eq!(
+        backend.timeout(RequestKind::Data),
+        Duration::from_secs(DEFAULT_CLOUD_DATA_TIMEOUT)
+    );
+
+    // an endpoint which accepts connections, but never answers
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let endpoint = listener.local_addr()?.to_string();
+
+    let backend = S3Backend::new(&s3_target(CloudTargetConfig {
+        endpoint: Some(endpoint),
+        path_style: Some(true),
+        metadata_timeout: Some(1),
+        data_timeout: Some(2),
+        ..Default::default()
+    }))?;
+    assert_eq!(
+        backend.timeout(RequestKind::Metadata),
+        Duration::from_secs(1)
+    );
+    assert_eq!(backend.timeout(RequestKind::Data), Duration::from_secs(2));
+
+    let timed_out = |result: Result<(), Error>, seconds: u64| {
+        let err = format!("{:#}", result.unwrap_err());
+        assert!(
+            err.contains(&format!("timed out after {} seconds", seconds)),
+            "unexpected error: {}",
+            err
+        );
+    };
+
+    // metadata requests
+    timed_out(backend.head_object("key").map(|_| ()), 1);
+    timed_out(backend.list_objects("").map(|_| ()), 1);
+    timed_out(backend.delete_object("key"), 1);
+
+    // data transfers
+    timed_out(backend.get_object("key").map(|_| ()), 2);
+    timed_out(backend.put_object("key", b"data"), 2);
+
+    Ok(())
+}
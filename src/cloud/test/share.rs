@@ -0,0 +1,88 @@
+// Shared export URL tests (against the mock backend)
+//
+// # cargo test --release cloud::test::share
+
+use anyhow::Error;
+
+use pbs_api_types::Authid;
+
+use crate::cloud::backend::CloudBackend;
+use crate::cloud::share::{load_share_log, share_export, DEFAULT_SHARE_TTL};
+
+use super::harness::{create_testdir, TestTarget};
+
+const EXPORT_KEY: &str = "export/store1/vm/100/2020-01-01T00:00:00Z.tar";
+
+#[test]
+fn test_share_export() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_share_export")?);
+    let backend = target.backend();
+    let auth_id: Authid = "backup@pbs".parse()?;
+    let now = 1_600_000_000;
+
+    backend.put_object(EXPORT_KEY, b"data")?;
+    backend.put_object("media-set/x/label.json", b"{}")?;
+
+    let share = share_export(
+        &target.base_path,
+        &target.target,
+        &*backend,
+        EXPORT_KEY,
+        None,
+        &auth_id,
+        Some("for the auditor".to_string()),
+        now,
+    )?;
+    assert_eq!(share.expires, now + DEFAULT_SHARE_TTL as i64);
+    assert!(share.url.contains(EXPORT_KEY));
+
+    // only existing exports can be shared
+    for key in ["media-set/x/label.json", "export/store1/missing.tar"] {
+        assert!(share_export(
+            &target.base_path,
+            &target.target,
+            &*backend,
+            key,
+            None,
+            &auth_id,
+            None,
+            now
+        )
+        .is_err());
+    }
+
+    // the lifetime is limited by the target
+    target.target.config.share_max_ttl = Some(600);
+    assert!(share_export(
+        &target.base_path,
+        &target.target,
+        &*backend,
+        EXPORT_KEY,
+        Some(601),
+        &auth_id,
+        None,
+        now
+    )
+    .is_err());
+    let share = share_export(
+        &target.base_path,
+        &target.target,
+        &*backend,
+        EXPORT_KEY,
+        None,
+        &auth_id,
+        None,
+        now,
+    )?;
+    assert_eq!(share.expires, now + 600);
+
+    // every created URL is recorded
+    let log = load_share_log(&target.base_path, "test")?;
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[0].key, EXPORT_KEY);
+    assert_eq!(log[0].auth_id, auth_id);
+    assert_eq!(log[0].comment.as_deref(), Some("for the auditor"));
+    assert_eq!(log[1].expires, now + 600);
+
+    Ok(())
+}
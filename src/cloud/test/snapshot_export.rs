@@ -0,0 +1,171 @@
+// Snapshot export tests (against the mock backend)
+//
+// # cargo test --release cloud::test::snapshot_export
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::BackupDir;
+use pbs_datastore::data_blob::DataChunkBuilder;
+use pbs_datastore::manifest::MANIFEST_BLOB_NAME;
+use pbs_datastore::DataBlob;
+
+use crate::cloud::backend::CloudBackend;
+use crate::cloud::catalog::{
+    ChunkArchiveEntry, ChunkEntry, CloudCatalog, MediaSetCatalog, MediaSetLabel, SnapshotEntry,
+    SnapshotFileEntry,
+};
+use crate::cloud::layout;
+use crate::cloud::snapshot_export::{
+    build_snapshot_export, export_snapshot, extract_snapshot_export, EXPORT_INFO_NAME,
+};
+
+use super::harness::{create_testdir, TestTarget, TestWorker, TEST_STORE};
+
+const SNAPSHOT: &str = "vm/100/2020-01-01T00:00:00Z";
+
+// a media set with a snapshot made of real chunks, so they can be verified
+fn write_snapshot(target: &TestTarget) -> Result<MediaSetCatalog, Error> {
+    let label = MediaSetLabel {
+        uuid: Uuid::generate(),
+        ctime: 1_600_000_000,
+        base: None,
+        node: "testnode".to_string(),
+    };
+    let mut media_set = MediaSetCatalog::new(label);
+
+    let mut data = Vec::new();
+    let mut chunks = Vec::new();
+    for content in [&b"first chunk"[..], &b"second chunk"[..]] {
+        let (chunk, digest) = DataChunkBuilder::new(content).compress(true).build()?;
+        chunks.push(ChunkEntry {
+            digest,
+            offset: data.len() as u64,
+            size: chunk.raw_size(),
+        });
+        data.extend_from_slice(chunk.raw_data());
+    }
+    let archive = Uuid::generate();
+    target.backend.put_object(
+        &layout::chunk_archive_key(media_set.uuid(), &archive),
+        &data,
+    )?;
+
+    let snapshot: BackupDir = SNAPSHOT.parse()?;
+    let manifest = DataBlob::encode(b"{}", None, true)?;
+    let mut files = Vec::new();
+    for (filename, data) in [
+        (MANIFEST_BLOB_NAME, manifest.raw_data()),
+        ("drive-scsi0.img.fidx", &b"index"[..]),
+    ] {
+        let key = layout::snapshot_file_key(
+            media_set.uuid(),
+            TEST_STORE,
+            &Default::default(),
+            &snapshot,
+            filename,
+        );
+        target.backend.put_object(&key, data)?;
+        files.push(SnapshotFileEntry {
+            filename: filename.to_string(),
+            size: data.len() as u64,
+            csum: openssl::sha::sha256(data),
+        });
+    }
+
+    media_set.snapshots.push(SnapshotEntry {
+        store: TEST_STORE.to_string(),
+        ns: Default::default(),
+        snapshot,
+        key: None,
+        files,
+        chunks: chunks.iter().map(|chunk| chunk.digest).collect(),
+        verification: None,
+        fingerprint: None,
+        owner: Some("backup@pbs".parse()?),
+        attributed: Default::default(),
+        cloud_verification: None,
+    });
+    media_set.archives.push(ChunkArchiveEntry {
+        uuid: archive,
+        store: TEST_STORE.to_string(),
+        key: None,
+        size: data.len() as u64,
+        csum: Some(openssl::sha::sha256(&data)),
+        chunks,
+    });
+
+    media_set.save(&target.base_path, &target.target.name)?;
+    Ok(media_set)
+}
+
+#[test]
+fn test_snapshot_export() -> Result<(), Error> {
+    let testdir = create_testdir("test_snapshot_export")?;
+    let target = TestTarget::new(testdir.clone());
+    let worker = TestWorker::default();
+    let backend = target.backend();
+
+    let media_set = write_snapshot(&target)?;
+    let entry = &media_set.snapshots[0];
+    let catalog = Arc::new(CloudCatalog::load(&target.base_path, "test")?);
+
+    let key = export_snapshot(
+        &worker,
+        &backend,
+        &catalog,
+        TEST_STORE,
+        &entry.ns,
+        &entry.snapshot,
+        None,
+        None,
+    )?;
+    assert_eq!(key, format!("export/{}/{}.tar", TEST_STORE, SNAPSHOT));
+
+    // the description comes first, the manifest after the other files
+    let data = backend.get_object(&key)?;
+    let mut archive = tar::Archive::new(Cursor::new(&data));
+    let paths: Vec<String> = archive
+        .entries()?
+        .map(|entry| Ok(entry?.path()?.to_string_lossy().into_owned()))
+        .collect::<Result<_, Error>>()?;
+    assert_eq!(paths.len(), 5);
+    assert_eq!(paths[0], EXPORT_INFO_NAME);
+    assert_eq!(paths[1], format!("{}/drive-scsi0.img.fidx", SNAPSHOT));
+    assert_eq!(paths[2], format!("{}/{}", SNAPSHOT, MANIFEST_BLOB_NAME));
+    assert_eq!(paths[3], layout::datastore_chunk_key("", &entry.chunks[0]));
+
+    // unpacks into a datastore directory
+    let dir = testdir.join("datastore");
+    let info = extract_snapshot_export(Cursor::new(&data), &dir)?;
+    assert_eq!(info.snapshot, entry.snapshot);
+    assert_eq!(info.owner, entry.owner);
+    assert_eq!(info.chunks, 2);
+    assert!(dir.join(SNAPSHOT).join(MANIFEST_BLOB_NAME).exists());
+    assert!(dir
+        .join(layout::datastore_chunk_key("", &entry.chunks[1]))
+        .exists());
+
+    // damaged data is detected
+    let mut damaged = data.clone();
+    // payload of the last chunk (followed by padding and two end blocks)
+    let pos = damaged.len() - 1024 - 512 + 16;
+    damaged[pos] ^= 1;
+    assert!(extract_snapshot_export(Cursor::new(&damaged), &testdir.join("damaged")).is_err());
+
+    // a missing chunk makes the snapshot unusable
+    backend.delete_object(&layout::chunk_archive_key(
+        media_set.uuid(),
+        &media_set.archives[0].uuid,
+    ))?;
+    assert!(
+        build_snapshot_export(&worker, &backend, &catalog, &media_set, entry, None, None, 0,)
+            .is_err()
+    );
+
+    Ok(())
+}
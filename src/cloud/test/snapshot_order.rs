@@ -0,0 +1,93 @@
+// Cloud backup snapshot order tests
+//
+// # cargo test --release cloud::test::snapshot_order
+
+use anyhow::Error;
+
+use pbs_api_types::CloudSnapshotOrder;
+
+use crate::api2::cloud::backup::order_snapshots;
+
+// (group, backup time, size), in the order the plan is built: group by
+// group, oldest snapshot first
+fn plan() -> Vec<(&'static str, i64, Option<u64>)> {
+    vec![
+        ("vm/100", 10, Some(300)),
+        ("vm/100", 20, Some(100)),
+        ("vm/100", 40, Some(200)),
+        ("vm/101", 20, Some(100)),
+        ("vm/101", 30, None),
+        ("ct/200", 5, Some(50)),
+        ("ct/200", 40, Some(200)),
+    ]
+}
+
+fn ordered(order: CloudSnapshotOrder) -> Vec<(&'static str, i64)> {
+    let mut plan = plan();
+    order_snapshots(&mut plan, order, |item| item.1, |item| item.2);
+    plan.into_iter()
+        .map(|(group, time, _)| (group, time))
+        .collect()
+}
+
+#[test]
+fn test_oldest_first() -> Result<(), Error> {
+    // group by group, oldest first within each group
+    assert_eq!(
+        ordered(CloudSnapshotOrder::OldestFirst),
+        vec![
+            ("vm/100", 10),
+            ("vm/100", 20),
+            ("vm/100", 40),
+            ("vm/101", 20),
+            ("vm/101", 30),
+            ("ct/200", 5),
+            ("ct/200", 40),
+        ]
+    );
+    assert_eq!(
+        CloudSnapshotOrder::default(),
+        CloudSnapshotOrder::OldestFirst
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_newest_first() -> Result<(), Error> {
+    // newest across all groups, equal times keep the group order
+    assert_eq!(
+        ordered(CloudSnapshotOrder::NewestFirst),
+        vec![
+            ("vm/100", 40),
+            ("ct/200", 40),
+            ("vm/101", 30),
+            ("vm/100", 20),
+            ("vm/101", 20),
+            ("vm/100", 10),
+            ("ct/200", 5),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_smallest_first() -> Result<(), Error> {
+    // smallest across all groups, equal sizes keep the group order, and
+    // snapshots without manifest go last
+    assert_eq!(
+        ordered(CloudSnapshotOrder::SmallestFirst),
+        vec![
+            ("ct/200", 5),
+            ("vm/100", 20),
+            ("vm/101", 20),
+            ("vm/100", 40),
+            ("ct/200", 40),
+            ("vm/100", 10),
+            ("vm/101", 30),
+        ]
+    );
+
+    Ok(())
+}
@@ -0,0 +1,62 @@
+// Snapshot summary tests
+//
+// # cargo test --release cloud::test::snapshot_summary
+
+use anyhow::Error;
+
+use crate::cloud::backend::PutOptions;
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::snapshot_summary::{
+    build_snapshot_summary, load_snapshot_summary, upload_snapshot_summary,
+};
+
+use super::harness::{chunk_data, create_testdir, digest, TestTarget};
+
+#[test]
+fn test_snapshot_summary() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_snapshot_summary")?);
+
+    let full = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    target.write_media_set(
+        Some(full.uuid()),
+        &[digest(3)],
+        &[("host/a/2020-01-02T00:00:00Z", vec![digest(1), digest(3)])],
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let (media_set, entry) = catalog
+        .snapshots()
+        .find(|(_, entry)| entry.snapshot.to_string() == "host/a/2020-01-02T00:00:00Z")
+        .unwrap();
+
+    // no summary object, built from the catalog
+    let summary = load_snapshot_summary(&*target.backend, &catalog, media_set.uuid(), entry)?;
+    assert_eq!(summary.media_set, media_set.uuid().to_string());
+    assert_eq!(summary.chunk_count, 2);
+    assert_eq!(
+        summary.chunk_size,
+        Some((chunk_data(&digest(1)).len() + chunk_data(&digest(3)).len()) as u64)
+    );
+    assert_eq!(summary.files.len(), 1);
+    assert_eq!(summary.files_size, entry.files[0].size);
+    assert!(!summary.encrypted);
+
+    // a stored summary is used as is
+    let stored = build_snapshot_summary(media_set.uuid(), entry, None);
+    upload_snapshot_summary(
+        &*target.backend,
+        media_set.uuid(),
+        entry,
+        &stored,
+        &PutOptions::default(),
+    )?;
+    let summary = load_snapshot_summary(&*target.backend, &catalog, media_set.uuid(), entry)?;
+    assert_eq!(summary, stored);
+    assert_eq!(summary.chunk_size, None);
+
+    Ok(())
+}
@@ -0,0 +1,74 @@
+// Source address selection tests
+//
+// # cargo test --release cloud::test::source_address
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use anyhow::Error;
+
+use pbs_api_types::CloudIpFamily;
+
+use super::harness::test_target;
+use crate::cloud::backend::{local_addresses, target_local_addresses};
+
+#[test]
+fn test_local_addresses() -> Result<(), Error> {
+    let v4: Ipv4Addr = "192.0.2.10".parse()?;
+    let v6: Ipv6Addr = "2001:db8::10".parse()?;
+
+    // no restriction
+    assert_eq!(
+        local_addresses(CloudIpFamily::Any, (None, None))?,
+        (None, None)
+    );
+    assert_eq!(
+        local_addresses(CloudIpFamily::Any, (Some(v4), Some(v6)))?,
+        (Some(v4), Some(v6))
+    );
+
+    // families are restricted with unspecified addresses
+    assert_eq!(
+        local_addresses(CloudIpFamily::Ipv4, (None, None))?,
+        (Some(Ipv4Addr::UNSPECIFIED), None)
+    );
+    assert_eq!(
+        local_addresses(CloudIpFamily::Ipv6, (None, None))?,
+        (None, Some(Ipv6Addr::UNSPECIFIED))
+    );
+
+    // source addresses of the other family are dropped, if there is one
+    assert_eq!(
+        local_addresses(CloudIpFamily::Ipv4, (Some(v4), Some(v6)))?,
+        (Some(v4), None)
+    );
+    assert!(local_addresses(CloudIpFamily::Ipv4, (None, Some(v6))).is_err());
+    assert!(local_addresses(CloudIpFamily::Ipv6, (Some(v4), None)).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_target_local_addresses() -> Result<(), Error> {
+    let mut target = test_target("source");
+    assert_eq!(target_local_addresses(&target)?, (None, None));
+
+    target.config.source_address = Some("192.0.2.10".to_string());
+    target.config.ip_family = Some(CloudIpFamily::Ipv4);
+    assert_eq!(
+        target_local_addresses(&target)?,
+        (Some("192.0.2.10".parse()?), None)
+    );
+
+    // the loopback interface always has 127.0.0.1
+    target.config.source_address = None;
+    target.config.source_interface = Some("lo".to_string());
+    assert_eq!(
+        target_local_addresses(&target)?,
+        (Some(Ipv4Addr::LOCALHOST), None)
+    );
+
+    target.config.source_interface = Some("nonexistent0".to_string());
+    assert!(target_local_addresses(&target).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,238 @@
+// Write-back staging tests
+//
+// # cargo test --release cloud::test::staging
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Error;
+
+use crate::cloud::backend::{
+    is_object_exists, CloudBackend, MockCloudBackend, MockFaults, PutOptions, StagingBackend,
+};
+use crate::cloud::layout::LEASE_KEY;
+use crate::cloud::staging::{upload_staged_objects, StagingSpool, STAGING_AGE_ALERT};
+
+use super::harness::{create_testdir, TestWorker};
+
+#[test]
+fn test_staging_backend_spools_media_sets() -> Result<(), Error> {
+    let testdir = create_testdir("test_staging_backend_spools_media_sets")?;
+    let inner = Arc::new(MockCloudBackend::new());
+    let spool = Arc::new(StagingSpool::open(&testdir, "test", 1024 * 1024)?);
+    let backend = StagingBackend::new(inner.clone(), Arc::clone(&spool));
+
+    let options = PutOptions {
+        storage_class: Some("STANDARD_IA".to_string()),
+        ..Default::default()
+    };
+    backend.put_object_multipart("media-set/a/archive", b"archive data", &options)?;
+    backend.put_object(LEASE_KEY, b"lease")?;
+
+    // only media set objects are spooled
+    assert!(inner.head_object("media-set/a/archive")?.is_none());
+    assert!(inner.head_object(LEASE_KEY)?.is_some());
+    assert_eq!(spool.list()?.len(), 1);
+
+    // reads see the spooled data
+    assert_eq!(backend.get_object("media-set/a/archive")?, b"archive data");
+    assert_eq!(
+        backend.get_object_range("media-set/a/archive", 8, 4)?,
+        b"data"
+    );
+    assert_eq!(
+        backend
+            .head_object("media-set/a/archive")?
+            .map(|info| info.size),
+        Some(12)
+    );
+    let listed: Vec<String> = backend
+        .list_objects("media-set/")?
+        .into_iter()
+        .map(|info| info.key)
+        .collect();
+    assert_eq!(listed, vec!["media-set/a/archive".to_string()]);
+
+    // conditional writes fail on spooled objects
+    let err = backend
+        .put_object_if_absent("media-set/a/archive", b"other")
+        .unwrap_err();
+    assert!(is_object_exists(&err));
+
+    backend.delete_object("media-set/a/archive")?;
+    assert!(spool.list()?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_staging_upload_order() -> Result<(), Error> {
+    let testdir = create_testdir("test_staging_upload_order")?;
+    let worker = TestWorker::default();
+    let inner = Arc::new(MockCloudBackend::new());
+    let spool = Arc::new(StagingSpool::open(&testdir, "test", 1024 * 1024)?);
+    let backend = StagingBackend::new(inner.clone(), Arc::clone(&spool));
+
+    let options = PutOptions {
+        storage_class: Some("STANDARD_IA".to_string()),
+        ..Default::default()
+    };
+    backend.put_object_multipart("media-set/a/archive1", b"first", &options)?;
+    backend.put_object_multipart("media-set/a/archive2", b"second", &options)?;
+    backend.put_object_if_absent("media-set/a/catalog.json", b"catalog")?;
+
+    let keys: Vec<String> = spool.list()?.into_iter().map(|object| object.key).collect();
+    assert_eq!(
+        keys,
+        vec![
+            "media-set/a/archive1".to_string(),
+            "media-set/a/archive2".to_string(),
+            "media-set/a/catalog.json".to_string(),
+        ]
+    );
+
+    let stats = upload_staged_objects(&worker, &spool, &*inner, Duration::ZERO)?;
+    assert_eq!(stats.objects, 3);
+    assert_eq!(stats.bytes, 18);
+    assert!(spool.list()?.is_empty());
+
+    assert_eq!(inner.get_object("media-set/a/archive2")?, b"second");
+    assert_eq!(inner.get_object("media-set/a/catalog.json")?, b"catalog");
+    // upload options are kept
+    assert_eq!(
+        inner
+            .object_options("media-set/a/archive1")
+            .and_then(|options| options.storage_class),
+        Some("STANDARD_IA".to_string())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_staging_upload_failure() -> Result<(), Error> {
+    let testdir = create_testdir("test_staging_upload_failure")?;
+    let worker = TestWorker::default();
+    let inner = Arc::new(MockCloudBackend::new());
+    let spool = StagingSpool::open(&testdir, "test", 1024 * 1024)?;
+
+    spool.push(
+        "media-set/a/archive1",
+        b"first",
+        &PutOptions::default(),
+        false,
+    )?;
+    spool.push(
+        "media-set/a/archive2",
+        b"second",
+        &PutOptions::default(),
+        false,
+    )?;
+
+    // a failing object stops the upload, later objects stay spooled
+    let mut fail_keys = HashSet::new();
+    fail_keys.insert("media-set/a/archive1".to_string());
+    inner.set_faults(MockFaults {
+        fail_keys,
+        ..Default::default()
+    });
+    assert!(upload_staged_objects(&worker, &spool, &*inner, Duration::ZERO).is_err());
+    assert_eq!(spool.list()?.len(), 2);
+    assert!(inner.head_object("media-set/a/archive2")?.is_none());
+
+    // transient failures are retried
+    inner.set_faults(MockFaults {
+        fail_every: Some(2),
+        ..Default::default()
+    });
+    let stats = upload_staged_objects(&worker, &spool, &*inner, Duration::ZERO)?;
+    assert_eq!(stats.objects, 2);
+    assert!(spool.list()?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_staging_back_pressure() -> Result<(), Error> {
+    let testdir = create_testdir("test_staging_back_pressure")?;
+    let worker = TestWorker::default();
+    let spool = StagingSpool::open(&testdir, "test", 10)?;
+
+    // an empty spool always takes the next object
+    spool.wait_for_space(&worker, 100, || Ok(()))?;
+    spool.push(
+        "media-set/a/archive1",
+        &[0u8; 8],
+        &PutOptions::default(),
+        false,
+    )?;
+    spool.wait_for_space(&worker, 2, || Ok(()))?;
+
+    // a full spool blocks until the uploader made room (or the job aborts)
+    worker.request_abort();
+    assert!(spool.wait_for_space(&worker, 3, || Ok(())).is_err());
+
+    let status = spool.status(true)?;
+    assert_eq!(status.objects, 1);
+    assert_eq!(status.size, 8);
+    assert!(status.oldest.is_some());
+    assert!(!status.uploading);
+
+    Ok(())
+}
+
+#[test]
+fn test_staging_uploader_health() -> Result<(), Error> {
+    let testdir = create_testdir("test_staging_uploader_health")?;
+    let worker = TestWorker::default();
+    let inner = Arc::new(MockCloudBackend::new());
+    let spool = StagingSpool::open(&testdir, "test", 10)?;
+
+    spool.push(
+        "media-set/a/archive1",
+        &[0u8; 4],
+        &PutOptions::default(),
+        false,
+    )?;
+    let oldest = spool.list()?[0].ctime;
+
+    let status = spool.status_at(true, oldest + 10)?;
+    assert_eq!(status.objects, 1);
+    assert_eq!(status.oldest_age, Some(10));
+    assert_eq!(status.last_upload, None);
+    assert!(status.alerts.is_empty());
+
+    // waiting too long and a full spool alert
+    spool.push(
+        "media-set/a/archive2",
+        &[0u8; 5],
+        &PutOptions::default(),
+        false,
+    )?;
+    let now = oldest + STAGING_AGE_ALERT + 1;
+    let status = spool.status_at(true, now)?;
+    assert_eq!(status.objects, 2);
+    assert_eq!(status.alerts.len(), 2);
+    assert!(status.alerts[0].starts_with("oldest object"));
+    assert_eq!(status.alerts[1], "spool is 90% full");
+
+    // so does an uploader which does not get anything through
+    let lock = spool.try_lock_uploader().unwrap();
+    let status = spool.status_at(true, now)?;
+    assert!(status.uploading);
+    assert_eq!(status.alerts.len(), 3);
+    assert!(status.alerts[2].starts_with("provider did not accept"));
+    drop(lock);
+
+    let stats = upload_staged_objects(&worker, &spool, &*inner, Duration::ZERO)?;
+    assert_eq!(stats.objects, 2);
+
+    let status = spool.status(true)?;
+    assert_eq!(status.objects, 0);
+    assert_eq!(status.oldest_age, None);
+    assert!(status.last_upload.is_some());
+    assert!(status.alerts.is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,109 @@
+// Warm standby tests
+//
+// # cargo test --release cloud::test::standby
+
+use anyhow::Error;
+
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::CloudStandbyStatus;
+
+use crate::cloud::catalog::{CloudCatalog, MediaSetCatalog, MediaSetLabel};
+use crate::cloud::standby::{
+    catalog_digests, load_standby_status, media_set_digest, standby_delta, update_standby_status,
+    StandbyDelta,
+};
+
+use super::harness::create_testdir;
+
+fn test_media_set(ctime: i64) -> MediaSetCatalog {
+    MediaSetCatalog::new(MediaSetLabel {
+        uuid: Uuid::generate(),
+        ctime,
+        base: None,
+        node: "primary".to_string(),
+    })
+}
+
+#[test]
+fn test_standby_delta() -> Result<(), Error> {
+    let testdir = create_testdir("test_standby_delta")?;
+
+    let set1 = test_media_set(1_600_000_000);
+    let set2 = test_media_set(1_600_001_000);
+    let mut set3 = test_media_set(1_600_002_000);
+
+    let primary =
+        CloudCatalog::from_media_sets(&testdir, "target1", vec![set1.clone(), set2.clone()]);
+    let standby = CloudCatalog::from_media_sets(&testdir, "target1", vec![set1.clone()]);
+
+    let primary_digests = catalog_digests(&primary)?;
+    assert_eq!(primary_digests.len(), 2);
+    assert_eq!(primary_digests[0].digest, media_set_digest(&set1)?);
+
+    // new media set on the primary
+    let delta = standby_delta(&catalog_digests(&standby)?, &primary_digests)?;
+    assert_eq!(
+        delta,
+        StandbyDelta {
+            fetch: vec![set2.uuid().to_string()],
+            remove: Vec::new(),
+        }
+    );
+
+    // nothing to do once in sync
+    let delta = standby_delta(&primary_digests, &primary_digests)?;
+    assert!(delta.is_empty());
+
+    // changed and removed media sets
+    let standby =
+        CloudCatalog::from_media_sets(&testdir, "target1", vec![set2.clone(), set3.clone()]);
+    set3.label.node = "other".to_string();
+    let primary =
+        CloudCatalog::from_media_sets(&testdir, "target1", vec![set1.clone(), set3.clone()]);
+    let delta = standby_delta(&catalog_digests(&standby)?, &catalog_digests(&primary)?)?;
+    assert_eq!(
+        delta,
+        StandbyDelta {
+            fetch: vec![set1.uuid().to_string(), set3.uuid().to_string()],
+            remove: vec![set2.uuid().to_string()],
+        }
+    );
+
+    // an empty primary catalog never empties the standby
+    assert!(standby_delta(&catalog_digests(&standby)?, &[]).is_err());
+    assert!(standby_delta(&[], &[])?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_standby_status() -> Result<(), Error> {
+    let testdir = create_testdir("test_standby_status")?;
+
+    assert_eq!(load_standby_status(&testdir, "target1")?, None);
+
+    test_media_set(1_600_000_000).save(&testdir, "target1")?;
+
+    update_standby_status(&testdir, "target1", "primary", true, &Ok(()))?;
+    let status = load_standby_status(&testdir, "target1")?.unwrap();
+    assert_eq!(status.remote, "primary");
+    assert_eq!(status.media_sets, 1);
+    assert!(status.last_sync.is_some());
+    assert_eq!(status.last_change, status.last_sync);
+    assert_eq!(status.last_error, None);
+
+    // errors keep the time of the last successful sync
+    let result = Err(anyhow::format_err!("connection refused"));
+    update_standby_status(&testdir, "target1", "primary", false, &result)?;
+    let failed = load_standby_status(&testdir, "target1")?.unwrap();
+    assert_eq!(
+        failed,
+        CloudStandbyStatus {
+            last_error: Some("connection refused".to_string()),
+            ..status
+        }
+    );
+
+    Ok(())
+}
@@ -0,0 +1,133 @@
+// Synthetic full tests (against the mock backend)
+//
+// # cargo test --release cloud::test::synthetic_full
+
+use anyhow::Error;
+
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::layout;
+use crate::cloud::synthetic::create_synthetic_full;
+
+use super::harness::{create_testdir, digest, TestTarget, TestWorker};
+
+#[test]
+fn test_synthetic_full_intact_chain() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_synthetic_full_intact_chain")?);
+    let worker = TestWorker::default();
+
+    let full = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    target.write_media_set(
+        Some(full.uuid()),
+        &[digest(3)],
+        &[("host/a/2020-01-02T00:00:00Z", vec![digest(1), digest(3)])],
+    )?;
+
+    let uuid = create_synthetic_full(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let chain = catalog.current_chain();
+    assert_eq!(chain.len(), 1);
+    assert_eq!(chain[0].uuid(), &uuid);
+    assert!(chain[0].label.base.is_none());
+    assert_eq!(chain[0].snapshots.len(), 2);
+    assert_eq!(chain[0].archives.len(), 2);
+
+    for n in 1..=3 {
+        assert!(catalog.chain_contains_chunk(&digest(n), None));
+    }
+
+    // everything was copied on the target
+    assert_eq!(
+        target
+            .backend
+            .list_objects(&layout::media_set_prefix(&uuid))?
+            .len(),
+        2 + 2 + 2 // label/catalog, archives, snapshot files
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_synthetic_full_damaged_archive() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_synthetic_full_damaged_archive")?);
+    let worker = TestWorker::default();
+
+    let full = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+    let incremental = target.write_media_set(
+        Some(full.uuid()),
+        &[digest(3)],
+        &[("host/a/2020-01-02T00:00:00Z", vec![digest(1), digest(3)])],
+    )?;
+
+    // lose the only copy of chunk 3, the datastore does not exist either
+    let archive = &incremental.archives[0];
+    assert!(target.backend.lose_object(&layout::chunk_archive_key(
+        incremental.uuid(),
+        &archive.uuid
+    )));
+
+    let uuid = create_synthetic_full(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let new_set = catalog.lookup_media_set(&uuid).unwrap();
+
+    assert_eq!(new_set.snapshots.len(), 1);
+    assert_eq!(
+        new_set.snapshots[0].snapshot.to_string(),
+        "host/a/2020-01-01T00:00:00Z"
+    );
+    assert!(!catalog.chain_contains_chunk(&digest(3), None));
+
+    Ok(())
+}
+
+#[test]
+fn test_synthetic_full_abort() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_synthetic_full_abort")?);
+    let worker = TestWorker::default();
+
+    target.write_media_set(
+        None,
+        &[digest(1)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1)])],
+    )?;
+
+    worker.request_abort();
+
+    assert!(create_synthetic_full(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+    )
+    .is_err());
+
+    // no catalog was committed
+    assert_eq!(
+        CloudCatalog::load(&target.base_path, "test")?
+            .media_sets()
+            .len(),
+        1
+    );
+
+    Ok(())
+}
@@ -0,0 +1,54 @@
+// Job checkpoint tests
+//
+// # cargo test --release cloud::test::task_checkpoint
+
+use anyhow::Error;
+
+use pbs_api_types::Authid;
+
+use crate::cloud::task_checkpoint::{load_checkpoints, CloudJobCheckpoint};
+
+use super::harness::create_testdir;
+
+#[test]
+fn test_checkpoint_lifecycle() -> Result<(), Error> {
+    let testdir = create_testdir("test_checkpoint_lifecycle")?;
+    let auth_id = Authid::root_auth_id();
+
+    assert!(load_checkpoints(&testdir)?.is_empty());
+
+    let mut checkpoint =
+        CloudJobCheckpoint::start(&testdir, "cloud-backup-job", "daily", auth_id, "upid-1")?;
+    CloudJobCheckpoint::start(
+        &testdir,
+        "cloud-replication-job",
+        "offsite",
+        auth_id,
+        "upid-2",
+    )?;
+
+    let list = load_checkpoints(&testdir)?;
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0], checkpoint);
+    assert_eq!(list[1].job_id, "offsite");
+
+    // a resumed job keeps the number of attempts
+    checkpoint.attempts += 1;
+    checkpoint.save(&testdir)?;
+    let resumed =
+        CloudJobCheckpoint::start(&testdir, "cloud-backup-job", "daily", auth_id, "upid-3")?;
+    assert_eq!(resumed.attempts, 1);
+    assert_eq!(resumed.upid, "upid-3");
+    assert_eq!(
+        CloudJobCheckpoint::load(&testdir, "cloud-backup-job", "daily")?,
+        Some(resumed)
+    );
+
+    CloudJobCheckpoint::remove(&testdir, "cloud-backup-job", "daily")?;
+    // removing twice is fine
+    CloudJobCheckpoint::remove(&testdir, "cloud-backup-job", "daily")?;
+    assert!(CloudJobCheckpoint::load(&testdir, "cloud-backup-job", "daily")?.is_none());
+    assert_eq!(load_checkpoints(&testdir)?.len(), 1);
+
+    Ok(())
+}
@@ -0,0 +1,53 @@
+// Structured task record tests
+//
+// # cargo test --release cloud::test::task_records
+
+use std::io::Write;
+
+use anyhow::Error;
+
+use pbs_api_types::CloudTaskRecord;
+
+use crate::cloud::task_records::{append_task_record, prune_task_records, read_task_records};
+
+use super::harness::create_testdir;
+
+const UPID1: &str =
+    "UPID:node1:00000001:00000001:00000001:5F5E1000:cloud-backup-job:job1:root@pam:";
+const UPID2: &str =
+    "UPID:node1:00000002:00000002:00000002:5F5E1001:cloud-backup-job:job2:root@pam:";
+
+#[test]
+fn test_task_records() -> Result<(), Error> {
+    let testdir = create_testdir("test_task_records")?;
+
+    assert!(read_task_records(&testdir, UPID1)?.is_empty());
+
+    let start = CloudTaskRecord::new("job-start").retries(1);
+    let archive = CloudTaskRecord::new("chunk-archive")
+        .key("media-set/a/chunk-archive/b")
+        .bytes(4096);
+    append_task_record(&testdir, UPID1, &start)?;
+    append_task_record(&testdir, UPID1, &archive)?;
+    append_task_record(&testdir, UPID2, &start)?;
+
+    assert_eq!(
+        read_task_records(&testdir, UPID1)?,
+        vec![start.clone(), archive]
+    );
+
+    // a line still being written is skipped
+    let mut path = testdir.clone();
+    path.push("task-records");
+    path.push(format!("{}.json", UPID2));
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path)?;
+    file.write_all(b"{\"time\":1,\"pha")?;
+    assert_eq!(read_task_records(&testdir, UPID2)?, vec![start]);
+
+    // records go away with their task
+    assert_eq!(prune_task_records(&testdir, |upid| upid == UPID2)?, 1);
+    assert!(read_task_records(&testdir, UPID1)?.is_empty());
+    assert_eq!(read_task_records(&testdir, UPID2)?.len(), 1);
+
+    Ok(())
+}
@@ -0,0 +1,35 @@
+// Cloud backup transfer-last tests
+//
+// # cargo test --release cloud::test::transfer_last
+
+use anyhow::Error;
+
+use crate::api2::cloud::backup::skip_transfer_last;
+
+#[test]
+fn test_transfer_last() -> Result<(), Error> {
+    // backup times of a group, oldest first
+    let group = vec![10, 20, 30, 40, 50];
+
+    let select = |transfer_last| {
+        let mut list = group.clone();
+        let skipped = skip_transfer_last(&mut list, transfer_last);
+        (list, skipped)
+    };
+
+    // the newest snapshots are kept, the skipped ones stay in order
+    assert_eq!(select(Some(2)), (vec![40, 50], vec![10, 20, 30]));
+    assert_eq!(select(Some(1)), (vec![50], vec![10, 20, 30, 40]));
+
+    // all snapshots without (or with a large enough) transfer-last
+    assert_eq!(select(None), (group.clone(), vec![]));
+    assert_eq!(select(Some(5)), (group.clone(), vec![]));
+    assert_eq!(select(Some(100)), (group.clone(), vec![]));
+
+    // nothing to select from
+    let mut empty: Vec<i64> = Vec::new();
+    assert!(skip_transfer_last(&mut empty, Some(3)).is_empty());
+    assert!(empty.is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,176 @@
+// Trash tests
+//
+// # cargo test --release cloud::test::trash
+
+use anyhow::Error;
+
+use pbs_api_types::{BackupDir, BackupNamespace};
+
+use crate::cloud::backend::CloudBackend;
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::compaction::{compact_media_sets, live_chunks};
+use crate::cloud::layout;
+use crate::cloud::trash::{
+    list_trash, move_to_trash, purge_expired_trash, purge_trash, restore_from_trash,
+};
+
+use super::harness::{create_testdir, digest, TestTarget, TestWorker, TEST_STORE};
+
+const SNAPSHOT: &str = "host/a/2020-01-01T00:00:00Z";
+
+fn snapshot() -> BackupDir {
+    SNAPSHOT.parse().unwrap()
+}
+
+#[test]
+fn test_move_and_restore() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_trash_move_and_restore")?);
+    let worker = TestWorker::default();
+    let ns = BackupNamespace::root();
+
+    let media_set = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[
+            (SNAPSHOT, vec![digest(1)]),
+            ("host/b/2020-01-01T00:00:00Z", vec![digest(2)]),
+        ],
+    )?;
+    let file_key = layout::snapshot_file_key(
+        media_set.uuid(),
+        TEST_STORE,
+        &ns,
+        &snapshot(),
+        "index.json.blob",
+    );
+    let trash_key = layout::trash_file_key(
+        media_set.uuid(),
+        TEST_STORE,
+        &ns,
+        &snapshot(),
+        "index.json.blob",
+    );
+
+    let moved = move_to_trash(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        TEST_STORE,
+        &ns,
+        &snapshot(),
+    )?;
+    assert_eq!(moved, 1);
+
+    assert!(target.backend.head_object(&file_key)?.is_none());
+    assert!(target.backend.head_object(&trash_key)?.is_some());
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    assert!(!catalog.contains_snapshot(TEST_STORE, &ns, &snapshot()));
+    // the chunks stay alive while the snapshot is in the trash
+    assert!(live_chunks(&catalog).contains(&(None, digest(1))));
+
+    let list = list_trash(&catalog, 3600);
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].snapshot, SNAPSHOT);
+    assert_eq!(list[0].media_set, media_set.uuid().to_string());
+    assert_eq!(list[0].expires, list[0].deleted + 3600);
+
+    // deleting it again fails
+    assert!(move_to_trash(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        TEST_STORE,
+        &ns,
+        &snapshot(),
+    )
+    .is_err());
+
+    let restored = restore_from_trash(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        TEST_STORE,
+        &ns,
+        &snapshot(),
+    )?;
+    assert_eq!(restored, 1);
+
+    assert!(target.backend.head_object(&file_key)?.is_some());
+    assert!(target.backend.head_object(&trash_key)?.is_none());
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    assert!(catalog.contains_snapshot(TEST_STORE, &ns, &snapshot()));
+    assert!(list_trash(&catalog, 3600).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_purge() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_trash_purge")?);
+    let worker = TestWorker::default();
+    let ns = BackupNamespace::root();
+
+    let media_set = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[
+            (SNAPSHOT, vec![digest(1)]),
+            ("host/b/2020-01-01T00:00:00Z", vec![digest(2)]),
+        ],
+    )?;
+    let trash_prefix =
+        layout::trash_snapshot_prefix(media_set.uuid(), TEST_STORE, &ns, &snapshot());
+
+    move_to_trash(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        TEST_STORE,
+        &ns,
+        &snapshot(),
+    )?;
+
+    // retention did not expire yet
+    let purged = purge_expired_trash(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+    )?;
+    assert_eq!(purged, 0);
+
+    // nothing selected
+    let purged = purge_trash(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        |_| false,
+    )?;
+    assert_eq!(purged, 0);
+
+    // compaction purges expired snapshots first
+    target.target.config.trash_retention = Some(0);
+    compact_media_sets(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        100,
+    )?;
+
+    assert!(target.backend.list_objects(&trash_prefix)?.is_empty());
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    assert!(list_trash(&catalog, 0).is_empty());
+    assert!(!catalog.contains_chunk(&digest(1)));
+    assert!(catalog.contains_chunk(&digest(2)));
+
+    Ok(())
+}
@@ -0,0 +1,95 @@
+// Upload estimate tests
+//
+// # cargo test --release cloud::test::upload_estimate
+
+use anyhow::Error;
+
+use crate::cloud::catalog::CloudCatalog;
+use crate::cloud::upload_estimate::UploadEstimator;
+
+use super::harness::{create_testdir, digest, TestTarget};
+
+#[test]
+fn test_upload_estimate() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_upload_estimate")?);
+
+    target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1), digest(2)])],
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let mut estimator = UploadEstimator::new(&catalog, false);
+
+    // stored size is known for digest 3 only
+    let stored_size = |digest: &[u8; 32]| (digest[0] == 3).then_some(40);
+
+    estimator.add_present_snapshot();
+    estimator.add_snapshot(
+        "store1:host/a",
+        None,
+        &[(digest(1), 100), (digest(3), 100)],
+        stored_size,
+    );
+    // chunks of earlier snapshots are uploaded once
+    estimator.add_snapshot(
+        "store1:host/a",
+        None,
+        &[(digest(2), 100), (digest(3), 100), (digest(4), 50)],
+        stored_size,
+    );
+    estimator.add_snapshot("store1:host/b", None, &[(digest(4), 50)], stored_size);
+
+    let estimate = estimator.finish(Some(30));
+    assert_eq!(estimate.snapshots, 3);
+    assert_eq!(estimate.present_snapshots, 1);
+    assert_eq!(estimate.chunks, 6);
+    assert_eq!(estimate.logical_bytes, 500);
+    assert_eq!(estimate.new_chunks, 2);
+    assert_eq!(estimate.new_bytes, 150);
+    assert_eq!(estimate.reused_bytes, 350);
+    assert_eq!(estimate.upload_bytes, 90);
+    assert_eq!(estimate.transfer_time, Some(3));
+
+    assert_eq!(estimate.groups.len(), 2);
+    assert_eq!(estimate.groups[0].group, "store1:host/a");
+    assert_eq!(estimate.groups[0].snapshots, 2);
+    assert_eq!(estimate.groups[0].logical_bytes, 450);
+    assert_eq!(estimate.groups[0].upload_bytes, 90);
+    assert_eq!(estimate.groups[1].new_bytes, 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_upload_estimate_force_full() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_upload_estimate_force_full")?);
+
+    target.write_media_set(
+        None,
+        &[digest(1)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1)])],
+    )?;
+
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+
+    let chunks = [(digest(1), 100), (digest(1), 100)];
+
+    let mut estimator = UploadEstimator::new(&catalog, false);
+    estimator.add_snapshot("store1:host/a", None, &chunks, |_| None);
+    let estimate = estimator.finish(None);
+    assert_eq!(estimate.new_bytes, 0);
+    assert_eq!(estimate.transfer_time, None);
+
+    // a new full media set uploads every chunk once
+    let mut estimator = UploadEstimator::new(&catalog, true);
+    estimator.add_snapshot("store1:host/a", None, &chunks, |_| None);
+    let estimate = estimator.finish(None);
+    assert_eq!(estimate.new_chunks, 1);
+    assert_eq!(estimate.new_bytes, 100);
+    assert_eq!(estimate.upload_bytes, 100);
+    assert_eq!(estimate.reused_bytes, 100);
+
+    Ok(())
+}
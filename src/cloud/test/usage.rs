@@ -0,0 +1,69 @@
+// Transfer accounting tests
+//
+// # cargo test --release cloud::test::usage
+
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use crate::cloud::backend::{AccountedBackend, CloudBackend, MockCloudBackend};
+use crate::cloud::usage::{current_month, load_usage, usage_report, TransferRecorder};
+
+use super::harness::create_testdir;
+
+#[test]
+fn test_transfer_accounting() -> Result<(), Error> {
+    let testdir = create_testdir("test_transfer_accounting")?;
+
+    let inner: Arc<dyn CloudBackend> = Arc::new(MockCloudBackend::new());
+    let recorder = Arc::new(TransferRecorder::new(&testdir, "test"));
+    let backend = AccountedBackend::new(Arc::clone(&inner), Arc::clone(&recorder));
+
+    backend.put_object("a", &[0u8; 100])?;
+    backend.put_object("b", &[0u8; 50])?;
+    assert_eq!(backend.get_object("a")?.len(), 100);
+    assert_eq!(backend.get_object_range("b", 10, 20)?.len(), 20);
+    assert!(backend.head_object("a")?.is_some());
+    assert_eq!(backend.list_objects("")?.len(), 2);
+    backend.copy_object("a", "c")?;
+    backend.delete_object("b")?;
+
+    // failed requests are billed, but transfer nothing
+    assert!(backend.get_object("b").is_err());
+
+    // nothing stored before flushing
+    assert!(load_usage(&testdir, "test")?.is_empty());
+    recorder.flush()?;
+
+    let report = usage_report(&testdir, "test", None)?;
+    assert_eq!(report.month, current_month()?);
+    let usage = report.usage;
+    assert_eq!(usage.uploaded, 150);
+    assert_eq!(usage.downloaded, 120);
+    assert_eq!(usage.deleted, 1);
+    assert_eq!(usage.requests.put, 2);
+    assert_eq!(usage.requests.get, 3);
+    assert_eq!(usage.requests.head, 1);
+    assert_eq!(usage.requests.list, 1);
+    assert_eq!(usage.requests.copy, 1);
+    assert_eq!(usage.requests.delete, 1);
+
+    // other backend instances add to the stored totals
+    drop(backend);
+    drop(recorder);
+    let recorder = Arc::new(TransferRecorder::new(&testdir, "test"));
+    let backend = AccountedBackend::new(inner, Arc::clone(&recorder));
+    backend.put_object("d", &[0u8; 10])?;
+    drop(backend);
+    drop(recorder);
+
+    let usage = usage_report(&testdir, "test", None)?.usage;
+    assert_eq!(usage.uploaded, 160);
+    assert_eq!(usage.requests.put, 3);
+
+    let report = usage_report(&testdir, "test", Some("2000-01"))?;
+    assert_eq!(report.month, "2000-01");
+    assert!(report.usage.is_empty());
+
+    Ok(())
+}
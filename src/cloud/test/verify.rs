@@ -0,0 +1,213 @@
+// Cloud verification tests
+//
+// # cargo test --release cloud::test::verify
+
+use anyhow::Error;
+
+use pbs_api_types::{CloudVerifyStatus, SnapshotVerifyState, VerifyState, UPID};
+
+use crate::cloud::backend::CloudBackend;
+use crate::cloud::catalog::{CloudCatalog, MediaSetCatalog};
+use crate::cloud::content::{list_groups, list_snapshots, CloudContentFilter};
+use crate::cloud::layout;
+use crate::cloud::verify::{verify_snapshots, verify_status, CloudVerifyOptions};
+
+use super::harness::{create_testdir, digest, TestTarget, TestWorker};
+
+fn test_upid(starttime: i64) -> Result<UPID, Error> {
+    format!(
+        "UPID:testnode:00000001:00000002:00000003:{:08X}:cloud-verify:test:root@pam:",
+        starttime
+    )
+    .parse()
+}
+
+fn snapshot_states(target: &TestTarget) -> Result<Vec<Option<CloudVerifyStatus>>, Error> {
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    Ok(
+        list_snapshots(&catalog, &CloudContentFilter::default(), Some(30))
+            .into_iter()
+            .map(|item| item.verify_status)
+            .collect(),
+    )
+}
+
+#[test]
+fn test_verify_snapshots() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_verify_snapshots")?);
+    let worker = TestWorker::default();
+
+    let media_set = target.write_media_set(
+        None,
+        &[digest(1), digest(2)],
+        &[
+            ("host/a/2020-01-01T00:00:00Z", vec![digest(1)]),
+            ("host/b/2020-01-01T00:00:00Z", vec![digest(2)]),
+        ],
+    )?;
+    assert_eq!(snapshot_states(&target)?, vec![None, None]);
+
+    let upid = test_upid(proxmox_time::epoch_i64())?;
+    let result = verify_snapshots(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        &CloudContentFilter::default(),
+        &CloudVerifyOptions::default(),
+        &upid,
+    )?;
+    assert_eq!(result.verified, 2);
+    assert!(result.failed.is_empty());
+    assert_eq!(
+        snapshot_states(&target)?,
+        vec![Some(CloudVerifyStatus::Ok), Some(CloudVerifyStatus::Ok)]
+    );
+
+    // the state is stored in the catalog on the target, too
+    let data = target
+        .backend
+        .get_object(&layout::media_set_catalog_key(media_set.uuid()))?;
+    let remote: MediaSetCatalog = serde_json::from_slice(&data)?;
+    for entry in remote.snapshots.iter() {
+        let state = entry.cloud_verification.as_ref().unwrap();
+        assert_eq!(state.state, VerifyState::Ok);
+        assert_eq!(state.upid.to_string(), upid.to_string());
+    }
+    // the lease was released
+    assert!(target.backend.head_object(layout::LEASE_KEY)?.is_none());
+
+    // recently verified snapshots are skipped
+    let result = verify_snapshots(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        &CloudContentFilter::default(),
+        &CloudVerifyOptions::default(),
+        &upid,
+    )?;
+    assert_eq!((result.verified, result.skipped), (0, 2));
+
+    let key = layout::chunk_archive_key(media_set.uuid(), &media_set.archives[0].uuid);
+    let mut data = target.backend.get_object(&key)?;
+    data[0] ^= 0xff;
+    assert!(target.backend.corrupt_object(&key, &data));
+
+    let options = CloudVerifyOptions {
+        ignore_verified: false,
+        outdated_after: None,
+    };
+    let result = verify_snapshots(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        &CloudContentFilter::default(),
+        &options,
+        &upid,
+    )?;
+    assert_eq!(result.verified, 2);
+    assert_eq!(result.failed.len(), 2);
+    assert_eq!(
+        snapshot_states(&target)?,
+        vec![
+            Some(CloudVerifyStatus::Failed),
+            Some(CloudVerifyStatus::Failed)
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_verify_outdated() -> Result<(), Error> {
+    let mut target = TestTarget::new(create_testdir("test_verify_outdated")?);
+    let worker = TestWorker::default();
+
+    let full = target.write_media_set(
+        None,
+        &[digest(1)],
+        &[("host/a/2020-01-01T00:00:00Z", vec![digest(1)])],
+    )?;
+
+    let now = proxmox_time::epoch_i64();
+    let old_upid = test_upid(now - 40 * 86400)?;
+    let filter = CloudContentFilter {
+        backup_id: Some("a".to_string()),
+        ..Default::default()
+    };
+    verify_snapshots(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        &filter,
+        &CloudVerifyOptions::default(),
+        &old_upid,
+    )?;
+    assert_eq!(
+        snapshot_states(&target)?,
+        vec![Some(CloudVerifyStatus::Outdated)]
+    );
+
+    // only outdated verifications are repeated
+    let options = CloudVerifyOptions {
+        ignore_verified: true,
+        outdated_after: Some(30),
+    };
+    let result = verify_snapshots(
+        &worker,
+        &target.base_path,
+        &target.target,
+        &target.backend(),
+        &filter,
+        &options,
+        &test_upid(now)?,
+    )?;
+    assert_eq!(result.verified, 1);
+    assert_eq!(snapshot_states(&target)?, vec![Some(CloudVerifyStatus::Ok)]);
+
+    // a new, unverified snapshot makes the group outdated
+    target.write_media_set(
+        Some(full.uuid()),
+        &[digest(2)],
+        &[("host/a/2020-01-02T00:00:00Z", vec![digest(2)])],
+    )?;
+    let catalog = CloudCatalog::load(&target.base_path, "test")?;
+    let groups = list_groups(&catalog, &CloudContentFilter::default(), Some(30));
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].verify_status, Some(CloudVerifyStatus::Outdated));
+
+    Ok(())
+}
+
+#[test]
+fn test_verify_status() -> Result<(), Error> {
+    let now = proxmox_time::epoch_i64();
+    let state = |days: i64, state: VerifyState| -> Result<SnapshotVerifyState, Error> {
+        Ok(SnapshotVerifyState {
+            upid: test_upid(now - days * 86400)?,
+            state,
+        })
+    };
+
+    let recent = state(1, VerifyState::Ok)?;
+    let old = state(10, VerifyState::Ok)?;
+    let failed = state(1, VerifyState::Failed)?;
+
+    assert_eq!(verify_status(&recent, Some(7), now), CloudVerifyStatus::Ok);
+    assert_eq!(
+        verify_status(&old, Some(7), now),
+        CloudVerifyStatus::Outdated
+    );
+    // never outdated without limit
+    assert_eq!(verify_status(&old, None, now), CloudVerifyStatus::Ok);
+    assert_eq!(verify_status(&old, Some(0), now), CloudVerifyStatus::Ok);
+    assert_eq!(
+        verify_status(&failed, Some(7), now),
+        CloudVerifyStatus::Failed
+    );
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+// Verification concurrency tests
+//
+// # cargo test --release cloud::test::verify_tuning
+
+use anyhow::Error;
+
+use crate::cloud::verify_tuning::{
+    VerifyTuner, INITIAL_VERIFY_THREADS, MAX_VERIFY_THREADS, MIN_VERIFY_THREADS,
+};
+
+const MB: u64 = 1_000_000;
+
+#[test]
+fn test_verify_tuner_throttled() -> Result<(), Error> {
+    let mut tuner = VerifyTuner::with_limit_rate(None);
+    assert_eq!(tuner.threads(), INITIAL_VERIFY_THREADS);
+
+    // throttled requests halve the threads, down to the minimum
+    assert_eq!(tuner.adjust(1, 10 * MB), INITIAL_VERIFY_THREADS / 2);
+    for _ in 0..10 {
+        tuner.adjust(5, 10 * MB);
+    }
+    assert_eq!(tuner.threads(), MIN_VERIFY_THREADS);
+
+    // and recover one by one
+    assert_eq!(tuner.adjust(0, 10 * MB), MIN_VERIFY_THREADS + 1);
+    assert_eq!(tuner.adjust(0, 10 * MB), MIN_VERIFY_THREADS + 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_verify_tuner_growth() -> Result<(), Error> {
+    let mut tuner = VerifyTuner::with_limit_rate(None);
+
+    // without throttling and limit, the threads grow up to the maximum
+    for _ in 0..(2 * MAX_VERIFY_THREADS) {
+        tuner.adjust(0, 1000 * MB);
+    }
+    assert_eq!(tuner.threads(), MAX_VERIFY_THREADS);
+
+    Ok(())
+}
+
+#[test]
+fn test_verify_tuner_headroom() -> Result<(), Error> {
+    let mut tuner = VerifyTuner::with_limit_rate(Some(100 * MB));
+
+    // below the limit, the threads grow
+    assert_eq!(tuner.adjust(0, 50 * MB), INITIAL_VERIFY_THREADS + 1);
+
+    // close to the limit, they are kept
+    assert_eq!(tuner.adjust(0, 95 * MB), INITIAL_VERIFY_THREADS + 1);
+    assert_eq!(tuner.adjust(0, 120 * MB), INITIAL_VERIFY_THREADS + 1);
+
+    // throttling still halves them
+    assert_eq!(tuner.adjust(2, 95 * MB), (INITIAL_VERIFY_THREADS + 1) / 2);
+
+    // a batch measures the traffic of the verification itself
+    tuner.start_batch();
+    assert_eq!(
+        tuner.finish_batch(0, 0),
+        (INITIAL_VERIFY_THREADS + 1) / 2 + 1
+    );
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+// ZFS change detection tests
+//
+// # cargo test --release cloud::test::zfs_changes
+
+use std::path::Path;
+
+use anyhow::Error;
+
+use crate::cloud::zfs_changes::{parse_zfs_diff, selection_digest};
+
+use super::harness::TEST_STORE;
+
+#[test]
+fn test_parse_zfs_diff() -> Result<(), Error> {
+    let output = "\
+M\t/mnt/datastore/store1/vm/100
++\t/mnt/datastore/store1/vm/100/2020-01-02T00:00:00Z
++\t/mnt/datastore/store1/.chunks/0000/0000aaaa
+R\t/mnt/datastore/store1/ns/a/ct/200/owner.tmp\t/mnt/datastore/store1/ns/a/ct/200/owner
+-\t/mnt/datastore/store1/host/my\\0040host
+";
+    let changes = parse_zfs_diff(output)?;
+    assert_eq!(changes.len(), 6);
+
+    assert!(changes.touches(Path::new("/mnt/datastore/store1/vm/100")));
+    assert!(changes.touches(Path::new("/mnt/datastore/store1/ns/a/ct/200")));
+    assert!(changes.touches(Path::new("/mnt/datastore/store1/host/my host")));
+    // path components are compared, not prefixes
+    assert!(!changes.touches(Path::new("/mnt/datastore/store1/vm/10")));
+    assert!(!changes.touches(Path::new("/mnt/datastore/store1/vm/101")));
+
+    assert!(parse_zfs_diff("")?.is_empty());
+    assert!(parse_zfs_diff("X\t/some/path").is_err());
+    assert!(parse_zfs_diff("M\t/bad\\09").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_selection_digest() -> Result<(), Error> {
+    let mut setup: pbs_api_types::CloudBackupJobSetup =
+        serde_json::from_value(serde_json::json!({
+            "store": TEST_STORE,
+            "target": "test",
+        }))?;
+    let digest = selection_digest(&setup);
+
+    // options which do not change the snapshot selection
+    setup.storage_class = Some("GLACIER".to_string());
+    setup.zfs_change_detection = Some(true);
+    assert_eq!(selection_digest(&setup), digest);
+
+    setup.latest_only = Some(true);
+    assert_ne!(selection_digest(&setup), digest);
+
+    Ok(())
+}
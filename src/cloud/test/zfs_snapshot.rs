@@ -0,0 +1,57 @@
+// ZFS snapshot name template tests
+//
+// # cargo test --release cloud::test::zfs_snapshot
+
+use anyhow::Error;
+
+use pbs_api_types::{CloudZfsSnapshotOptions, CLOUD_ZFS_SNAPSHOT_DEFAULT_NAME_TEMPLATE};
+
+use crate::cloud::zfs_snapshot::expand_name_template;
+
+use super::harness::TEST_STORE;
+
+const TIME: i64 = 1706702400; // 2024-01-31T12:00:00Z
+
+#[test]
+fn test_expand_name_template() -> Result<(), Error> {
+    assert_eq!(
+        expand_name_template(
+            CLOUD_ZFS_SNAPSHOT_DEFAULT_NAME_TEMPLATE,
+            TEST_STORE,
+            "s3-1",
+            TIME
+        )?,
+        "pbs-cloud-s3-1-20240131T120000Z"
+    );
+    assert_eq!(
+        expand_name_template("{store}_{target}.{time}", TEST_STORE, "s3-1", TIME)?,
+        "store1_s3-1.20240131T120000Z"
+    );
+    assert_eq!(
+        expand_name_template("cloud-backup", TEST_STORE, "s3-1", TIME)?,
+        "cloud-backup"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_name_template() {
+    for template in ["{job}", "pbs-{target", "pbs-target}", "{}", ""] {
+        assert!(
+            expand_name_template(template, TEST_STORE, "s3-1", TIME).is_err(),
+            "template '{}' should be rejected",
+            template
+        );
+    }
+}
+
+#[test]
+fn test_default_name_template() {
+    let options = CloudZfsSnapshotOptions::default();
+    assert!(!options.enable);
+    assert_eq!(
+        options.name_template(),
+        CLOUD_ZFS_SNAPSHOT_DEFAULT_NAME_TEMPLATE
+    );
+}
@@ -0,0 +1,353 @@
+//! Trash of deleted snapshots
+//!
+//! Deleting a snapshot from a target does not remove it right away. Its
+//! files and summary are moved below `trash/` and the catalog entry is
+//! moved to the trash list of its media set. Snapshots in the trash keep
+//! their chunks alive, so they can be restored until the trash retention
+//! of the target (`trash-retention`, in days) expires.
+//!
+//! Expired snapshots are purged before each compaction, their chunks are
+//! then collected like any other unreferenced chunk.
+//!
+//! Like backups, this needs the writer lease of the target.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+
+use pbs_api_types::{BackupDir, BackupNamespace, CloudTarget, CloudTrashEntry};
+
+use super::backend::CloudBackend;
+use super::catalog::{
+    replace_media_set_catalog, CloudCatalog, MediaSetCatalog, SnapshotEntry, TrashedSnapshot,
+};
+use super::layout;
+use super::lease::{CloudLease, LEASE_TIMEOUT};
+
+/// Object names of a snapshot below its prefix (files and summary)
+fn object_names(entry: &SnapshotEntry) -> Vec<&str> {
+    entry
+        .files
+        .iter()
+        .map(|file| file.filename.as_str())
+        .chain(std::iter::once(layout::SNAPSHOT_SUMMARY_NAME))
+        .collect()
+}
+
+fn snapshot_key(media_set: &MediaSetCatalog, entry: &SnapshotEntry, name: &str) -> String {
+    layout::snapshot_file_key(
+        media_set.uuid(),
+        &entry.store,
+        &entry.ns,
+        &entry.snapshot,
+        name,
+    )
+}
+
+fn trash_key(media_set: &MediaSetCatalog, entry: &SnapshotEntry, name: &str) -> String {
+    layout::trash_file_key(
+        media_set.uuid(),
+        &entry.store,
+        &entry.ns,
+        &entry.snapshot,
+        name,
+    )
+}
+
+/// Copy the objects of a snapshot, returns the copied source keys
+///
+/// Snapshots uploaded by older versions have no summary, a missing
+/// summary is skipped.
+fn copy_objects(
+    backend: &dyn CloudBackend,
+    entry: &SnapshotEntry,
+    source: impl Fn(&str) -> String,
+    destination: impl Fn(&str) -> String,
+) -> Result<Vec<String>, Error> {
+    let mut copied = Vec::new();
+    for name in object_names(entry) {
+        let src_key = source(name);
+        if name == layout::SNAPSHOT_SUMMARY_NAME && backend.head_object(&src_key)?.is_none() {
+            continue;
+        }
+        backend.copy_object(&src_key, &destination(name))?;
+        copied.push(src_key);
+    }
+    Ok(copied)
+}
+
+fn delete_objects(worker: &dyn WorkerTaskContext, backend: &dyn CloudBackend, keys: &[String]) {
+    for key in keys {
+        if let Err(err) = backend.delete_object(key) {
+            task_warn!(worker, "unable to delete '{}' - {}", key, err);
+        }
+    }
+}
+
+/// Write the catalog of a changed media set to the target and locally
+fn update_media_set(
+    base_path: &Path,
+    target: &CloudTarget,
+    backend: &dyn CloudBackend,
+    lease: &mut CloudLease,
+    media_set: &MediaSetCatalog,
+) -> Result<(), Error> {
+    lease.heartbeat()?;
+    replace_media_set_catalog(backend, media_set)?;
+    media_set.save(base_path, &target.name)
+}
+
+/// Move a snapshot to the trash, returns the number of media sets which
+/// contained it
+///
+/// The objects are copied first, then the catalog is replaced, and the
+/// original objects are deleted last. An interrupted deletion leaves
+/// additional objects in the trash at worst.
+pub fn move_to_trash<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+    store: &str,
+    ns: &BackupNamespace,
+    snapshot: &BackupDir,
+) -> Result<usize, Error> {
+    let base_path = base_path.as_ref();
+    let catalog = CloudCatalog::load(base_path, &target.name)?;
+
+    let media_sets: Vec<&MediaSetCatalog> = catalog
+        .media_sets()
+        .iter()
+        .filter(|media_set| media_set.contains_snapshot(store, ns, snapshot))
+        .collect();
+
+    if media_sets.is_empty() {
+        bail!("snapshot '{}' not found on target", snapshot);
+    }
+    if let Some(media_set) = media_sets.iter().find(|set| set.is_imported()) {
+        bail!(
+            "snapshot '{}' is part of imported media set {}, which is read-only",
+            snapshot,
+            media_set.uuid()
+        );
+    }
+
+    let mut lease =
+        CloudLease::acquire(Arc::clone(backend), proxmox_sys::nodename(), LEASE_TIMEOUT)?;
+
+    let deleted = proxmox_time::epoch_i64();
+
+    for media_set in media_sets.iter() {
+        worker.check_abort()?;
+
+        let mut media_set = (*media_set).clone();
+        let index = media_set
+            .snapshots
+            .iter()
+            .position(|entry| entry.matches(store, ns, snapshot))
+            .unwrap();
+        let entry = media_set.snapshots.remove(index);
+
+        task_log!(
+            worker,
+            "move snapshot {} of media set {} to the trash",
+            snapshot,
+            media_set.uuid()
+        );
+
+        let copied = copy_objects(
+            &**backend,
+            &entry,
+            |name| snapshot_key(&media_set, &entry, name),
+            |name| trash_key(&media_set, &entry, name),
+        )?;
+
+        media_set.trash.push(TrashedSnapshot { entry, deleted });
+        update_media_set(base_path, target, &**backend, &mut lease, &media_set)?;
+
+        delete_objects(worker, &**backend, &copied);
+    }
+
+    lease.release()?;
+
+    Ok(media_sets.len())
+}
+
+/// Restore a snapshot from the trash, returns the number of media sets
+/// which contained it
+///
+/// Fails if the snapshot was backed up to the target again in the meantime.
+pub fn restore_from_trash<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+    store: &str,
+    ns: &BackupNamespace,
+    snapshot: &BackupDir,
+) -> Result<usize, Error> {
+    let base_path = base_path.as_ref();
+    let catalog = CloudCatalog::load(base_path, &target.name)?;
+
+    if catalog.contains_snapshot(store, ns, snapshot) {
+        bail!(
+            "snapshot '{}' exists on target, not restoring from trash",
+            snapshot
+        );
+    }
+
+    let media_sets: Vec<&MediaSetCatalog> = catalog
+        .media_sets()
+        .iter()
+        .filter(|media_set| {
+            media_set
+                .trash
+                .iter()
+                .any(|trashed| trashed.entry.matches(store, ns, snapshot))
+        })
+        .collect();
+
+    if media_sets.is_empty() {
+        bail!("snapshot '{}' not found in trash", snapshot);
+    }
+
+    let mut lease =
+        CloudLease::acquire(Arc::clone(backend), proxmox_sys::nodename(), LEASE_TIMEOUT)?;
+
+    for media_set in media_sets.iter() {
+        worker.check_abort()?;
+
+        let mut media_set = (*media_set).clone();
+        let index = media_set
+            .trash
+            .iter()
+            .position(|trashed| trashed.entry.matches(store, ns, snapshot))
+            .unwrap();
+        let entry = media_set.trash.remove(index).entry;
+
+        task_log!(
+            worker,
+            "restore snapshot {} of media set {} from the trash",
+            snapshot,
+            media_set.uuid()
+        );
+
+        let copied = copy_objects(
+            &**backend,
+            &entry,
+            |name| trash_key(&media_set, &entry, name),
+            |name| snapshot_key(&media_set, &entry, name),
+        )?;
+
+        media_set.snapshots.push(entry);
+        update_media_set(base_path, target, &**backend, &mut lease, &media_set)?;
+
+        delete_objects(worker, &**backend, &copied);
+    }
+
+    lease.release()?;
+
+    Ok(media_sets.len())
+}
+
+/// Purge the snapshots in the trash selected by `select`, returns the
+/// number of purged snapshots
+///
+/// The catalog is replaced before the objects in the trash are deleted.
+/// The chunks of purged snapshots are removed by the next compaction.
+pub fn purge_trash<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+    select: impl Fn(&TrashedSnapshot) -> bool,
+) -> Result<usize, Error> {
+    let base_path = base_path.as_ref();
+    let catalog = CloudCatalog::load(base_path, &target.name)?;
+
+    let media_sets: Vec<&MediaSetCatalog> = catalog
+        .media_sets()
+        .iter()
+        .filter(|media_set| media_set.trash.iter().any(&select))
+        .collect();
+
+    if media_sets.is_empty() {
+        return Ok(0);
+    }
+
+    let mut lease =
+        CloudLease::acquire(Arc::clone(backend), proxmox_sys::nodename(), LEASE_TIMEOUT)?;
+
+    let mut purged = 0;
+    for media_set in media_sets {
+        worker.check_abort()?;
+
+        let mut media_set = media_set.clone();
+        let (selected, kept): (Vec<_>, Vec<_>) = media_set.trash.drain(..).partition(&select);
+        media_set.trash = kept;
+
+        update_media_set(base_path, target, &**backend, &mut lease, &media_set)?;
+
+        for trashed in selected {
+            let entry = &trashed.entry;
+            task_log!(
+                worker,
+                "purge snapshot {} of media set {} from the trash",
+                entry.snapshot,
+                media_set.uuid()
+            );
+            let prefix = layout::trash_snapshot_prefix(
+                media_set.uuid(),
+                &entry.store,
+                &entry.ns,
+                &entry.snapshot,
+            );
+            let keys: Vec<String> = match backend.list_objects(&prefix) {
+                Ok(list) => list.into_iter().map(|object| object.key).collect(),
+                Err(err) => {
+                    task_warn!(worker, "unable to list '{}' - {}", prefix, err);
+                    continue;
+                }
+            };
+            delete_objects(worker, &**backend, &keys);
+            purged += 1;
+        }
+    }
+
+    lease.release()?;
+
+    Ok(purged)
+}
+
+/// Purge all snapshots whose trash retention expired
+pub fn purge_expired_trash<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+) -> Result<usize, Error> {
+    let cutoff = proxmox_time::epoch_i64() - target.config.trash_retention_secs();
+    purge_trash(worker, base_path, target, backend, |trashed| {
+        trashed.deleted <= cutoff
+    })
+}
+
+/// The snapshots in the trash, most recently deleted first
+pub fn list_trash(catalog: &CloudCatalog, retention_secs: i64) -> Vec<CloudTrashEntry> {
+    let mut list: Vec<CloudTrashEntry> = catalog
+        .trashed_snapshots()
+        .map(|(media_set, trashed)| CloudTrashEntry {
+            store: trashed.entry.store.clone(),
+            ns: trashed.entry.ns.clone(),
+            snapshot: trashed.entry.snapshot.to_string(),
+            media_set: media_set.uuid().to_string(),
+            deleted: trashed.deleted,
+            expires: trashed.deleted.saturating_add(retention_secs),
+        })
+        .collect();
+    list.sort_by(|a, b| b.deleted.cmp(&a.deleted));
+    list
+}
@@ -0,0 +1,232 @@
+//! Upload estimates
+//!
+//! Compares the chunks referenced by the snapshots a backup job would
+//! write against the chunks the current chain of the target already holds
+//! (according to the local catalog), and reports how many bytes would be
+//! uploaded and how many would be reused. Chunks referenced more than once
+//! by the selected snapshots are counted as uploaded only once.
+//!
+//! Only index files are read. The upload size of new chunks is the size
+//! of their (compressed) chunk files in the datastore, chunks encrypted
+//! with a namespace key grow by the few bytes of the encryption header.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+
+use pbs_api_types::{
+    BackupNamespace, CloudGroupUploadEstimate, CloudTarget, CloudUploadEstimate, Fingerprint,
+    GroupFilter,
+};
+use pbs_datastore::backup_info::{BackupDir, BackupInfo};
+use pbs_datastore::dynamic_index::DynamicIndexReader;
+use pbs_datastore::fixed_index::FixedIndexReader;
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::manifest::{archive_type, ArchiveType};
+use pbs_datastore::DataStore;
+
+use super::catalog::CloudCatalog;
+use super::dedup_stats::group_stats_name;
+
+/// Snapshot selection of an estimate, same semantics as for backup jobs
+#[derive(Clone, Debug, Default)]
+pub struct UploadSelection {
+    pub ns: BackupNamespace,
+    pub max_depth: Option<usize>,
+    pub group_filter: Option<Vec<GroupFilter>>,
+    pub latest_only: bool,
+    pub transfer_last: Option<usize>,
+    pub since: Option<i64>,
+}
+
+/// Accumulates the estimate snapshot by snapshot
+pub struct UploadEstimator<'a> {
+    catalog: &'a CloudCatalog,
+    // a new full media set does not reference chunks of older chains
+    force_full: bool,
+    // chunks counted as uploaded by earlier snapshots
+    new_chunks: HashSet<(Option<Fingerprint>, [u8; 32])>,
+    estimate: CloudUploadEstimate,
+}
+
+impl<'a> UploadEstimator<'a> {
+    pub fn new(catalog: &'a CloudCatalog, force_full: bool) -> Self {
+        Self {
+            catalog,
+            force_full,
+            new_chunks: HashSet::new(),
+            estimate: CloudUploadEstimate::default(),
+        }
+    }
+
+    /// Count a snapshot already on the target (it would be skipped)
+    pub fn add_present_snapshot(&mut self) {
+        self.estimate.present_snapshots += 1;
+    }
+
+    /// Add a snapshot of `group`, given its chunk references
+    ///
+    /// `chunks` are digest and plain size of each reference, `stored_size`
+    /// returns the size of a chunk as written to the target, if known.
+    pub fn add_snapshot<F>(
+        &mut self,
+        group: &str,
+        key: Option<&Fingerprint>,
+        chunks: &[([u8; 32], u64)],
+        stored_size: F,
+    ) where
+        F: Fn(&[u8; 32]) -> Option<u64>,
+    {
+        let mut stats = CloudGroupUploadEstimate {
+            group: group.to_string(),
+            snapshots: 1,
+            ..Default::default()
+        };
+
+        for (digest, size) in chunks {
+            stats.logical_bytes += size;
+            self.estimate.chunks += 1;
+
+            if !self.force_full && self.catalog.chain_contains_chunk(digest, key) {
+                continue;
+            }
+            if !self.new_chunks.insert((key.cloned(), *digest)) {
+                continue;
+            }
+            stats.new_bytes += size;
+            stats.upload_bytes += stored_size(digest).unwrap_or(*size);
+            self.estimate.new_chunks += 1;
+        }
+
+        self.estimate.snapshots += 1;
+        self.estimate.logical_bytes += stats.logical_bytes;
+        self.estimate.new_bytes += stats.new_bytes;
+        self.estimate.reused_bytes += stats.logical_bytes - stats.new_bytes;
+        self.estimate.upload_bytes += stats.upload_bytes;
+
+        // snapshots of a group are added one after the other
+        match self.estimate.groups.last_mut() {
+            Some(last) if last.group == stats.group => {
+                last.snapshots += stats.snapshots;
+                last.logical_bytes += stats.logical_bytes;
+                last.new_bytes += stats.new_bytes;
+                last.upload_bytes += stats.upload_bytes;
+            }
+            _ => self.estimate.groups.push(stats),
+        }
+    }
+
+    /// Finish the estimate, computing the transfer time at `upload_rate`
+    /// (bytes per second) if given
+    pub fn finish(mut self, upload_rate: Option<u64>) -> CloudUploadEstimate {
+        self.estimate.transfer_time = match upload_rate {
+            Some(rate) if rate > 0 => Some(self.estimate.upload_bytes.div_ceil(rate)),
+            _ => None,
+        };
+        self.estimate
+    }
+}
+
+// digest and plain size of all chunk references of a snapshot
+fn snapshot_chunks(snapshot: &BackupDir) -> Result<Vec<([u8; 32], u64)>, Error> {
+    let snapshot_reader = snapshot.locked_reader()?;
+
+    let mut chunks = Vec::new();
+    for filename in snapshot_reader.file_list().iter() {
+        let index: Box<dyn IndexFile> = match archive_type(filename)? {
+            ArchiveType::FixedIndex => {
+                Box::new(FixedIndexReader::new(snapshot_reader.open_file(filename)?)?)
+            }
+            ArchiveType::DynamicIndex => Box::new(DynamicIndexReader::new(
+                snapshot_reader.open_file(filename)?,
+            )?),
+            ArchiveType::Blob => continue,
+        };
+        for pos in 0..index.index_count() {
+            match index.chunk_info(pos) {
+                Some(info) => chunks.push((info.digest, info.size())),
+                None => bail!("missing chunk info in '{}' - internal error", filename),
+            }
+        }
+    }
+
+    Ok(chunks)
+}
+
+/// Estimate the upload of a backup job with `selection` to `target`
+pub fn estimate_datastore_upload(
+    datastore: &Arc<DataStore>,
+    target: &CloudTarget,
+    catalog: &CloudCatalog,
+    selection: &UploadSelection,
+    force_full: bool,
+    upload_rate: Option<u64>,
+) -> Result<CloudUploadEstimate, Error> {
+    let store = datastore.name();
+
+    let mut group_list = Vec::new();
+    for ns in datastore.recursive_iter_backup_ns_ok(selection.ns.clone(), selection.max_depth)? {
+        group_list.extend(datastore.list_backup_groups(ns)?);
+    }
+    group_list.sort_unstable_by(|a, b| a.group().cmp(b.group()));
+
+    if let Some(ref filters) = selection.group_filter {
+        group_list.retain(|group| group.group().apply_filters(filters));
+    }
+
+    let stored_size = |digest: &[u8; 32]| {
+        let (path, _) = datastore.chunk_path(digest);
+        std::fs::metadata(path).ok().map(|metadata| metadata.len())
+    };
+
+    let mut estimator = UploadEstimator::new(catalog, force_full);
+
+    for group in group_list {
+        let mut snapshot_list: Vec<_> = group
+            .list_backups()?
+            .into_iter()
+            .filter(|item| item.is_finished())
+            .collect();
+
+        if let Some(since) = selection.since {
+            snapshot_list.retain(|item| item.backup_dir.backup_time() >= since);
+        }
+
+        BackupInfo::sort_list(&mut snapshot_list, true); // oldest first
+
+        let keep = if selection.latest_only {
+            Some(1)
+        } else {
+            selection.transfer_last
+        };
+        if let Some(count) = keep {
+            let cutoff = snapshot_list.len().saturating_sub(count);
+            snapshot_list.drain(..cutoff);
+        }
+
+        let group_name = group_stats_name(store, group.backup_ns(), group.group());
+        let key = target.config.namespace_key_for(group.backup_ns())?;
+
+        for info in snapshot_list {
+            let ns = info.backup_dir.backup_ns();
+            if catalog.contains_snapshot(store, ns, info.backup_dir.as_ref()) {
+                estimator.add_present_snapshot();
+                continue;
+            }
+            let chunks = match snapshot_chunks(&info.backup_dir) {
+                Ok(chunks) => chunks,
+                // vanished in the meantime, a backup job skips it as well
+                Err(_) if !info.backup_dir.full_path().exists() => continue,
+                Err(err) => bail!(
+                    "unable to read snapshot {:?} - {}",
+                    info.backup_dir.relative_path(),
+                    err
+                ),
+            };
+            estimator.add_snapshot(&group_name, key.as_ref(), &chunks, &stored_size);
+        }
+    }
+
+    Ok(estimator.finish(upload_rate))
+}
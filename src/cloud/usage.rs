@@ -0,0 +1,154 @@
+//! Transfer accounting
+//!
+//! Providers bill storage requests and transferred data. All requests
+//! sent through an [`AccountedBackend`](super::backend::AccountedBackend)
+//! are counted per target and calendar month (UTC): bytes up- and
+//! downloaded, requests by type and deleted objects. Counters are kept in
+//! memory and added to the stored totals periodically, and when the
+//! backend is dropped.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{format_err, Error};
+
+use proxmox_sys::fs::{create_path, open_file_locked, replace_file};
+
+use pbs_api_types::{CloudTransferUsage, CloudUsageReport};
+
+use super::create_options;
+
+/// Store pending counters at least this often (seconds)
+const FLUSH_INTERVAL: i64 = 60;
+
+fn usage_path(base_path: &Path, target: &str) -> PathBuf {
+    let mut path = base_path.to_owned();
+    path.push("usage");
+    path.push(format!("{}.json", target));
+    path
+}
+
+/// The current accounting period (`YYYY-MM`)
+pub fn current_month() -> Result<String, Error> {
+    proxmox_time::strftime_utc("%Y-%m", proxmox_time::epoch_i64())
+}
+
+fn read_usage(path: &Path) -> Result<BTreeMap<String, CloudTransferUsage>, Error> {
+    match proxmox_sys::fs::file_get_optional_contents(path)? {
+        Some(data) => serde_json::from_slice(&data)
+            .map_err(|err| format_err!("unable to parse {:?} - {}", path, err)),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Stored totals of a target, by month
+pub fn load_usage<P: AsRef<Path>>(
+    base_path: P,
+    target: &str,
+) -> Result<BTreeMap<String, CloudTransferUsage>, Error> {
+    read_usage(&usage_path(base_path.as_ref(), target))
+}
+
+/// Transfer report of a target (`None` reports the current month)
+pub fn usage_report<P: AsRef<Path>>(
+    base_path: P,
+    target: &str,
+    month: Option<&str>,
+) -> Result<CloudUsageReport, Error> {
+    let month = match month {
+        Some(month) => month.to_string(),
+        None => current_month()?,
+    };
+    let usage = load_usage(base_path, target)?
+        .remove(&month)
+        .unwrap_or_default();
+    Ok(CloudUsageReport { month, usage })
+}
+
+struct PendingUsage {
+    usage: CloudTransferUsage,
+    last_flush: i64,
+}
+
+/// Counts the requests of one backend instance
+pub struct TransferRecorder {
+    path: PathBuf,
+    pending: Mutex<PendingUsage>,
+}
+
+impl TransferRecorder {
+    pub fn new<P: AsRef<Path>>(base_path: P, target: &str) -> Self {
+        Self {
+            path: usage_path(base_path.as_ref(), target),
+            pending: Mutex::new(PendingUsage {
+                usage: CloudTransferUsage::default(),
+                last_flush: proxmox_time::epoch_i64(),
+            }),
+        }
+    }
+
+    /// Update the pending counters
+    pub fn record(&self, update: impl FnOnce(&mut CloudTransferUsage)) {
+        let now = proxmox_time::epoch_i64();
+        let flush = {
+            let mut pending = self.pending.lock().unwrap();
+            update(&mut pending.usage);
+            now - pending.last_flush >= FLUSH_INTERVAL
+        };
+        if flush {
+            if let Err(err) = self.flush() {
+                log::error!("unable to store transfer accounting - {}", err);
+            }
+        }
+    }
+
+    /// Add the pending counters to the stored totals of the current month
+    pub fn flush(&self) -> Result<(), Error> {
+        let usage = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.last_flush = proxmox_time::epoch_i64();
+            std::mem::take(&mut pending.usage)
+        };
+        if usage.is_empty() {
+            return Ok(());
+        }
+
+        let result = proxmox_lang::try_block!({
+            if let Some(parent) = self.path.parent() {
+                create_path(
+                    parent,
+                    Some(create_options(0o0750)?),
+                    Some(create_options(0o0750)?),
+                )?;
+            }
+            let mut lock_path = self.path.clone();
+            lock_path.set_extension("lck");
+            let timeout = std::time::Duration::new(10, 0);
+            let _lock = open_file_locked(&lock_path, timeout, true, create_options(0o0640)?)?;
+
+            let mut totals = read_usage(&self.path)?;
+            totals.entry(current_month()?).or_default().add(&usage);
+            replace_file(
+                &self.path,
+                &serde_json::to_vec(&totals)?,
+                create_options(0o0640)?,
+                true,
+            )
+        });
+
+        if result.is_err() {
+            // keep them for the next try
+            self.pending.lock().unwrap().usage.add(&usage);
+        }
+        result
+    }
+}
+
+impl Drop for TransferRecorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            log::error!("unable to store transfer accounting - {}", err);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Verification of snapshots on cloud targets
+//!
+//! Downloads the objects of snapshots and checks them against the catalog
+//! (see [`super::repair::check_snapshot`]). The outcome is recorded in the
+//! catalog entry of the snapshot in its newest media set, locally and on
+//! the target, so the content listing can show which snapshots are
+//! verified, outdated or failed.
+//!
+//! Snapshots are selected like in datastore verification: with
+//! `ignore_verified`, snapshots are only verified again once their last
+//! verification is older than `outdated_after` days.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{
+    print_ns_and_snapshot, CloudTarget, CloudVerifyStatus, SnapshotVerifyState, VerifyState, UPID,
+};
+
+use super::backend::CloudBackend;
+use super::catalog::{replace_media_set_catalog, CloudCatalog, MediaSetCatalog, SnapshotEntry};
+use super::content::{latest_snapshots, CloudContentFilter};
+use super::encryption_keys::load_crypt_config;
+use super::lease::{CloudLease, LEASE_TIMEOUT};
+use super::repair::check_snapshot;
+
+/// Selection of the snapshots to verify
+#[derive(Clone, Debug)]
+pub struct CloudVerifyOptions {
+    /// Skip snapshots with a verification which is not outdated
+    pub ignore_verified: bool,
+    /// Days after which a verification is outdated (never if unset or 0)
+    pub outdated_after: Option<i64>,
+}
+
+impl Default for CloudVerifyOptions {
+    fn default() -> Self {
+        Self {
+            ignore_verified: true,
+            outdated_after: None,
+        }
+    }
+}
+
+/// Result of a verification run
+#[derive(Debug, Default)]
+pub struct CloudVerifyResult {
+    /// Number of verified snapshots
+    pub verified: usize,
+    /// Number of snapshots skipped because of a recent verification
+    pub skipped: usize,
+    /// Snapshots which failed verification
+    pub failed: Vec<String>,
+}
+
+fn verification_outdated(
+    state: &SnapshotVerifyState,
+    outdated_after: Option<i64>,
+    now: i64,
+) -> bool {
+    match outdated_after {
+        Some(max_age) if max_age > 0 => (now - state.upid.starttime) / 86400 >= max_age,
+        _ => false,
+    }
+}
+
+/// Status of a verification shown in the content listing
+pub fn verify_status(
+    state: &SnapshotVerifyState,
+    outdated_after: Option<i64>,
+    now: i64,
+) -> CloudVerifyStatus {
+    match state.state {
+        VerifyState::Failed => CloudVerifyStatus::Failed,
+        VerifyState::Ok if verification_outdated(state, outdated_after, now) => {
+            CloudVerifyStatus::Outdated
+        }
+        VerifyState::Ok => CloudVerifyStatus::Ok,
+    }
+}
+
+/// Whether a snapshot has to be verified (again)
+pub fn needs_verification(entry: &SnapshotEntry, options: &CloudVerifyOptions, now: i64) -> bool {
+    if !options.ignore_verified {
+        return true;
+    }
+    match entry.cloud_verification {
+        Some(ref state) => verification_outdated(state, options.outdated_after, now),
+        None => true,
+    }
+}
+
+fn verify_entry(
+    backend: &dyn CloudBackend,
+    catalog: &CloudCatalog,
+    media_set: &MediaSetCatalog,
+    entry: &SnapshotEntry,
+) -> Result<VerifyState, Error> {
+    let crypt_config = entry.key.as_ref().map(load_crypt_config).transpose()?;
+    let damage = check_snapshot(backend, catalog, media_set, entry, crypt_config.as_deref())?;
+    if damage.is_empty() {
+        Ok(VerifyState::Ok)
+    } else {
+        Ok(VerifyState::Failed)
+    }
+}
+
+/// Verify the snapshots of a target matching `filter`
+///
+/// The verification states are stored with the UPID of the running task.
+/// Snapshots whose objects cannot be read are recorded as failed.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_snapshots<P: AsRef<Path>>(
+    worker: &dyn WorkerTaskContext,
+    base_path: P,
+    target: &CloudTarget,
+    backend: &Arc<dyn CloudBackend>,
+    filter: &CloudContentFilter,
+    options: &CloudVerifyOptions,
+    upid: &UPID,
+) -> Result<CloudVerifyResult, Error> {
+    let base_path = base_path.as_ref();
+    let catalog = CloudCatalog::load(base_path, &target.name)?;
+    let now = proxmox_time::epoch_i64();
+
+    let mut result = CloudVerifyResult::default();
+    let mut changed: HashMap<Uuid, MediaSetCatalog> = HashMap::new();
+
+    for (media_set, entry) in latest_snapshots(&catalog, filter) {
+        worker.check_abort()?;
+
+        let name = format!(
+            "{}:{}",
+            entry.store,
+            print_ns_and_snapshot(&entry.ns, &entry.snapshot)
+        );
+        if !needs_verification(entry, options, now) {
+            result.skipped += 1;
+            continue;
+        }
+
+        task_log!(worker, "verify {}", name);
+        let state = match verify_entry(&**backend, &catalog, media_set, entry) {
+            Ok(VerifyState::Ok) => VerifyState::Ok,
+            Ok(VerifyState::Failed) => {
+                task_warn!(worker, "snapshot {} is damaged", name);
+                VerifyState::Failed
+            }
+            Err(err) => {
+                task_warn!(worker, "verify {} failed - {}", name, err);
+                VerifyState::Failed
+            }
+        };
+
+        result.verified += 1;
+        if state == VerifyState::Failed {
+            result.failed.push(name);
+        }
+
+        let set = changed
+            .entry(media_set.uuid().clone())
+            .or_insert_with(|| media_set.clone());
+        if let Some(entry) = set
+            .snapshots
+            .iter_mut()
+            .find(|other| other.matches(&entry.store, &entry.ns, &entry.snapshot))
+        {
+            entry.cloud_verification = Some(SnapshotVerifyState {
+                upid: upid.clone(),
+                state,
+            });
+        }
+    }
+
+    if !changed.is_empty() {
+        let mut lease =
+            CloudLease::acquire(Arc::clone(backend), proxmox_sys::nodename(), LEASE_TIMEOUT)?;
+        for set in changed.values() {
+            lease.heartbeat()?;
+            replace_media_set_catalog(&**backend, set)?;
+            set.save(base_path, &target.name)?;
+        }
+        lease.release()?;
+    }
+
+    Ok(result)
+}
@@ -0,0 +1,110 @@
+//! Concurrency of checksum verification
+//!
+//! Verifying objects downloads them from the target in parallel. Too many
+//! parallel downloads make the provider reject requests (`SlowDown`), or
+//! use up the bandwidth a traffic control rule allows for the target, which
+//! slows down the backups and restores running on the node at the same
+//! time. Objects are verified in batches, and the number of threads is
+//! adjusted after each batch: it is halved if the provider throttled
+//! requests, kept while the download rate is close to the limit of the
+//! traffic control rule, and increased by one otherwise.
+
+use std::time::Instant;
+
+use crate::traffic_control_cache::SharedRateLimit;
+
+/// Fewest threads verifying objects
+pub const MIN_VERIFY_THREADS: usize = 1;
+
+/// Threads verifying objects at the start of a check
+pub const INITIAL_VERIFY_THREADS: usize = 4;
+
+/// Most threads verifying objects
+pub const MAX_VERIFY_THREADS: usize = 16;
+
+/// Share of the rate limit at which the number of threads stops growing
+const RATE_HEADROOM: f64 = 0.9;
+
+/// Adjusts the number of threads verifying objects
+pub struct VerifyTuner {
+    threads: usize,
+    // download rate limit (bytes/second)
+    limit_rate: Option<u64>,
+    // limiter counting the traffic of all connections using the limit
+    limiter: Option<SharedRateLimit>,
+    batch_start: Instant,
+    batch_traffic: u64,
+}
+
+impl VerifyTuner {
+    /// Tuner for downloads limited by a traffic control rule
+    ///
+    /// `limit` is the download rate of the rule and its shared limiter (see
+    /// [`super::backend::cloud_download_limit`]).
+    pub fn new(limit: Option<(u64, SharedRateLimit)>) -> Self {
+        let (limit_rate, limiter) = match limit {
+            Some((rate, limiter)) => (Some(rate), Some(limiter)),
+            None => (None, None),
+        };
+        Self {
+            threads: INITIAL_VERIFY_THREADS,
+            limit_rate,
+            limiter,
+            batch_start: Instant::now(),
+            batch_traffic: 0,
+        }
+    }
+
+    /// Tuner for a rate limit without shared limiter
+    ///
+    /// Only the traffic of the verification itself is measured.
+    pub fn with_limit_rate(limit_rate: Option<u64>) -> Self {
+        Self {
+            limit_rate,
+            ..Self::new(None)
+        }
+    }
+
+    /// Number of threads to use for the next batch
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Start measuring the traffic of a batch
+    pub fn start_batch(&mut self) {
+        self.batch_start = Instant::now();
+        self.batch_traffic = self
+            .limiter
+            .as_ref()
+            .map(|limiter| limiter.traffic())
+            .unwrap_or(0);
+    }
+
+    /// Finish a batch which downloaded `bytes` and had `throttled` requests
+    /// rejected by the provider, returns the number of threads for the
+    /// next batch
+    pub fn finish_batch(&mut self, bytes: u64, throttled: usize) -> usize {
+        let traffic = match self.limiter {
+            Some(ref limiter) => limiter.traffic().saturating_sub(self.batch_traffic),
+            None => bytes,
+        };
+        let elapsed = self.batch_start.elapsed().as_secs_f64().max(0.001);
+        self.adjust(throttled, (traffic as f64 / elapsed) as u64)
+    }
+
+    /// Adjust the number of threads to the outcome of a batch
+    ///
+    /// `rate` is the download rate (bytes/second) measured during the batch.
+    pub fn adjust(&mut self, throttled: usize, rate: u64) -> usize {
+        let saturated = match self.limit_rate {
+            Some(limit) => rate as f64 >= limit as f64 * RATE_HEADROOM,
+            None => false,
+        };
+        if throttled > 0 {
+            self.threads = (self.threads / 2).max(MIN_VERIFY_THREADS);
+        } else if !saturated {
+            self.threads = (self.threads + 1).min(MAX_VERIFY_THREADS);
+        }
+        self.threads
+    }
+}
@@ -0,0 +1,221 @@
+//! ZFS change detection for backup jobs
+//!
+//! For datastores on a ZFS dataset, a backup job with
+//! `zfs-change-detection` keeps a ZFS snapshot of the dataset, taken when
+//! its last successful run started. `zfs diff` against that snapshot lists
+//! the paths changed since, so backup groups without changes need not be
+//! listed and compared with the catalog again. For large, mostly static
+//! datastores this avoids reading the directories and manifests of all
+//! groups on every run.
+//!
+//! Chunks are not affected: chunk files are immutable, and the catalog
+//! already decides which of them are uploaded.
+//!
+//! The snapshot name contains a digest of the snapshot selection of the
+//! job, and the snapshot records the media set the run wrote. If the
+//! selection changed, or the target got another media set since (written
+//! by another job, or rolled back), all groups are scanned as usual.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use serde_json::json;
+
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::CloudBackupJobSetup;
+
+use super::catalog::CloudCatalog;
+use crate::tools::disks::DiskManage;
+
+/// ZFS user property recording the media set of a run
+const MEDIA_SET_PROPERTY: &str = "pbs:cloud-media-set";
+
+/// Paths changed between two states of a dataset
+#[derive(Debug, Default)]
+pub struct ZfsChanges {
+    paths: HashSet<PathBuf>,
+}
+
+impl ZfsChanges {
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Check if anything below `dir` changed (including `dir` itself)
+    pub fn touches(&self, dir: &Path) -> bool {
+        self.paths.iter().any(|path| path.starts_with(dir))
+    }
+}
+
+// decode the '\NNNN' (octal) escapes of 'zfs diff'
+fn unescape_zfs_path(value: &str) -> Result<PathBuf, Error> {
+    use std::os::unix::ffi::OsStringExt;
+
+    let bytes = value.as_bytes();
+    let mut path = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+    while pos < bytes.len() {
+        if bytes[pos] == b'\\' {
+            let code = bytes
+                .get(pos + 1..pos + 5)
+                .and_then(|code| std::str::from_utf8(code).ok())
+                .and_then(|code| u8::from_str_radix(code, 8).ok())
+                .ok_or_else(|| format_err!("invalid escape sequence in '{}'", value))?;
+            path.push(code);
+            pos += 5;
+        } else {
+            path.push(bytes[pos]);
+            pos += 1;
+        }
+    }
+    Ok(PathBuf::from(std::ffi::OsString::from_vec(path)))
+}
+
+/// Parse the output of `zfs diff -H`
+///
+/// Renamed files count as changed with their old and new path.
+pub fn parse_zfs_diff(output: &str) -> Result<ZfsChanges, Error> {
+    let mut changes = ZfsChanges::default();
+    for line in output.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some("+" | "-" | "M"), Some(path), None) => {
+                changes.paths.insert(unescape_zfs_path(path)?);
+            }
+            (Some("R"), Some(from), Some(to)) => {
+                changes.paths.insert(unescape_zfs_path(from)?);
+                changes.paths.insert(unescape_zfs_path(to)?);
+            }
+            _ => bail!("unable to parse 'zfs diff' output line '{}'", line),
+        }
+    }
+    Ok(changes)
+}
+
+/// Digest of the snapshot selection of a backup job
+pub fn selection_digest(setup: &CloudBackupJobSetup) -> String {
+    let selection = json!({
+        "store": setup.store,
+        "ns": setup.ns,
+        "max-depth": setup.max_depth,
+        "group-filter": setup.group_filter,
+        "latest-only": setup.latest_only,
+        "transfer-last": setup.transfer_last,
+    });
+    hex::encode(openssl::sha::sha256(selection.to_string().as_bytes()))
+}
+
+pub(super) fn run_zfs(args: &[&str]) -> Result<String, Error> {
+    let mut command = std::process::Command::new("zfs");
+    command.args(args);
+    proxmox_sys::command::run_command(command, None)
+}
+
+// 'None' if the snapshot does not exist or has no media set recorded
+fn recorded_media_set(snapshot: &str) -> Option<Uuid> {
+    let output = run_zfs(&[
+        "get",
+        "-H",
+        "-p",
+        "-o",
+        "value",
+        MEDIA_SET_PROPERTY,
+        snapshot,
+    ])
+    .ok()?;
+    output.trim().parse().ok()
+}
+
+pub(super) fn snapshot_exists(snapshot: &str) -> bool {
+    run_zfs(&["list", "-H", "-o", "name", "-t", "snapshot", snapshot]).is_ok()
+}
+
+/// ZFS snapshots of a backup job
+pub struct ZfsChangeTracker {
+    // snapshot of the last successful run
+    snapshot: String,
+    // snapshot of the current run
+    pending: String,
+}
+
+impl ZfsChangeTracker {
+    /// Open the tracker for the datastore at `path`
+    ///
+    /// Returns `None` if the datastore is not on a ZFS dataset.
+    pub fn open(
+        path: &Path,
+        target: &str,
+        setup: &CloudBackupJobSetup,
+    ) -> Result<Option<Self>, Error> {
+        let dataset = match DiskManage::new().find_mounted_device(path)? {
+            Some((fs_type, _, Some(source))) if fs_type == "zfs" => source
+                .into_string()
+                .map_err(|_| format_err!("invalid dataset name"))?,
+            _ => return Ok(None),
+        };
+        let digest = selection_digest(setup);
+        let snapshot = format!("{}@pbs-cloud-{}-{}", dataset, target, &digest[..16]);
+        Ok(Some(Self {
+            pending: format!("{}-new", snapshot),
+            snapshot,
+        }))
+    }
+
+    pub fn snapshot_name(&self) -> &str {
+        &self.snapshot
+    }
+
+    /// Take the snapshot of the current run
+    pub fn begin(&self) -> Result<(), Error> {
+        if snapshot_exists(&self.pending) {
+            run_zfs(&["destroy", &self.pending])?;
+        }
+        run_zfs(&["snapshot", &self.pending])?;
+        Ok(())
+    }
+
+    /// Paths changed since the last successful run
+    ///
+    /// Returns `None` if there is no usable snapshot of the last run (all
+    /// groups need to be scanned). Call this after [`Self::begin`].
+    pub fn changes(&self, catalog: &CloudCatalog) -> Result<Option<ZfsChanges>, Error> {
+        let media_set = match recorded_media_set(&self.snapshot) {
+            Some(media_set) => media_set,
+            None => return Ok(None),
+        };
+        match catalog.last_media_set() {
+            Some(last) if last.uuid() == &media_set => {}
+            _ => return Ok(None),
+        }
+        let output = run_zfs(&["diff", "-H", &self.snapshot, &self.pending])?;
+        parse_zfs_diff(&output).map(Some)
+    }
+
+    /// Keep the snapshot of the current run for the next one
+    pub fn commit(&self, media_set: &Uuid) -> Result<(), Error> {
+        let property = format!("{}={}", MEDIA_SET_PROPERTY, media_set);
+        run_zfs(&["set", &property, &self.pending])?;
+        if snapshot_exists(&self.snapshot) {
+            run_zfs(&["destroy", &self.snapshot])?;
+        }
+        run_zfs(&["rename", &self.pending, &self.snapshot])?;
+        Ok(())
+    }
+
+    /// Remove the snapshot of the current run
+    pub fn abort(&self) -> Result<(), Error> {
+        if snapshot_exists(&self.pending) {
+            run_zfs(&["destroy", &self.pending])?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+//! Direct-from-ZFS-snapshot cloud backups
+//!
+//! With `cloud-zfs-snapshot` enabled on a datastore on a ZFS dataset, a
+//! cloud backup snapshots the dataset when it starts and reads backup
+//! snapshots and chunks from the ZFS snapshot. The upload then is a
+//! consistent view of the datastore, while backups, prune and garbage
+//! collection keep running on the live datastore. The ZFS snapshot is
+//! destroyed when the cloud backup finishes.
+//!
+//! Snapshot names are built from a template, see
+//! [`expand_name_template`].
+
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+use pbs_api_types::{CloudZfsSnapshotOptions, DataStoreConfig};
+use pbs_datastore::DataStore;
+
+use super::zfs_changes::{run_zfs, snapshot_exists};
+use crate::tools::disks::DiskManage;
+
+/// Load the `cloud-zfs-snapshot` options of datastore `store`
+pub fn datastore_options(store: &str) -> Result<CloudZfsSnapshotOptions, Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let config: DataStoreConfig = config.lookup("datastore", store)?;
+    let options = CloudZfsSnapshotOptions::API_SCHEMA
+        .parse_property_string(config.cloud_zfs_snapshot.as_deref().unwrap_or(""))?;
+    Ok(serde_json::from_value(options)?)
+}
+
+/// Expand a snapshot name template
+///
+/// Replaces `{store}`, `{target}` and `{time}` (UTC, `%Y%m%dT%H%M%SZ`).
+pub fn expand_name_template(
+    template: &str,
+    store: &str,
+    target: &str,
+    time: i64,
+) -> Result<String, Error> {
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        if rest[start..].starts_with('}') {
+            bail!("unmatched '}}' in snapshot name template '{}'", template);
+        }
+        name.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| {
+            format_err!("unmatched '{{' in snapshot name template '{}'", template)
+        })?;
+        match &rest[start + 1..start + end] {
+            "store" => name.push_str(store),
+            "target" => name.push_str(target),
+            "time" => name.push_str(&proxmox_time::strftime_utc("%Y%m%dT%H%M%SZ", time)?),
+            other => bail!(
+                "unknown placeholder '{{{}}}' in snapshot name template '{}'",
+                other,
+                template
+            ),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    name.push_str(rest);
+
+    if name.is_empty() {
+        bail!("snapshot name template '{}' expands to nothing", template);
+    }
+    Ok(name)
+}
+
+// root of the file system containing 'path'
+fn mount_root(path: &Path) -> Result<PathBuf, Error> {
+    let path = path.canonicalize()?;
+    let device = std::fs::metadata(&path)?.dev();
+    let mut root = path.as_path();
+    while let Some(parent) = root.parent() {
+        if std::fs::metadata(parent)?.dev() != device {
+            break;
+        }
+        root = parent;
+    }
+    Ok(root.to_path_buf())
+}
+
+/// Temporary ZFS snapshot of the dataset of a datastore
+///
+/// The snapshot is destroyed by [`Self::destroy`], or when dropped.
+pub struct ZfsSnapshotView {
+    // full snapshot name, 'dataset@name'
+    snapshot: String,
+    // datastore directory inside the snapshot
+    path: PathBuf,
+    destroyed: bool,
+}
+
+impl ZfsSnapshotView {
+    /// Snapshot the dataset of the datastore at `path`
+    pub fn create(
+        path: &Path,
+        store: &str,
+        target: &str,
+        options: &CloudZfsSnapshotOptions,
+    ) -> Result<Self, Error> {
+        let dataset = match DiskManage::new().find_mounted_device(path)? {
+            Some((fs_type, _, Some(source))) if fs_type == "zfs" => source
+                .into_string()
+                .map_err(|_| format_err!("invalid dataset name"))?,
+            _ => bail!("datastore '{}' is not on a ZFS dataset", store),
+        };
+
+        let root = mount_root(path)?;
+        let relative = path.canonicalize()?;
+        let relative = relative.strip_prefix(&root)?;
+
+        let name = expand_name_template(
+            options.name_template(),
+            store,
+            target,
+            proxmox_time::epoch_i64(),
+        )?;
+        let snapshot = format!("{}@{}", dataset, name);
+
+        // never take over (and later destroy) a snapshot not created here
+        if snapshot_exists(&snapshot) {
+            bail!("ZFS snapshot '{}' already exists", snapshot);
+        }
+        run_zfs(&["snapshot", &snapshot])?;
+
+        let mut snapshot_path = root;
+        snapshot_path.push(".zfs/snapshot");
+        snapshot_path.push(&name);
+        snapshot_path.push(relative);
+
+        Ok(Self {
+            snapshot,
+            path: snapshot_path,
+            destroyed: false,
+        })
+    }
+
+    pub fn snapshot_name(&self) -> &str {
+        &self.snapshot
+    }
+
+    /// Datastore directory inside the snapshot
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Open a read-only view of the datastore `store` on the snapshot
+    pub fn open_datastore(&self, store: &str) -> Result<Arc<DataStore>, Error> {
+        DataStore::open_read_only_path(store, &self.path)
+    }
+
+    /// Destroy the snapshot
+    ///
+    /// Drop all datastore views opened on it first.
+    pub fn destroy(mut self) -> Result<(), Error> {
+        self.destroyed = true;
+        run_zfs(&["destroy", &self.snapshot])?;
+        Ok(())
+    }
+}
+
+impl Drop for ZfsSnapshotView {
+    fn drop(&mut self) {
+        if !self.destroyed {
+            if let Err(err) = run_zfs(&["destroy", &self.snapshot]) {
+                log::error!(
+                    "unable to destroy ZFS snapshot '{}' - {}",
+                    self.snapshot,
+                    err
+                );
+            }
+        }
+    }
+}
@@ -9,7 +9,8 @@ use proxmox_schema::{api, ApiStringFormat, ApiType, Updater};
 use proxmox_http::ProxyConfig;
 
 use pbs_api_types::{
-    EMAIL_SCHEMA, MULTI_LINE_COMMENT_SCHEMA, OPENSSL_CIPHERS_TLS_1_2_SCHEMA,
+    CLOUD_CHUNK_CACHE_SIZE_SCHEMA, CLOUD_CONFIG_HISTORY_DAYS_SCHEMA, CLOUD_NO_PROXY_SCHEMA,
+    CLOUD_PROXY_SCHEMA, EMAIL_SCHEMA, MULTI_LINE_COMMENT_SCHEMA, OPENSSL_CIPHERS_TLS_1_2_SCHEMA,
     OPENSSL_CIPHERS_TLS_1_3_SCHEMA,
 };
 
@@ -174,7 +175,23 @@ pub enum Translation {
         "description" : {
             optional: true,
             schema: MULTI_LINE_COMMENT_SCHEMA,
-        }
+        },
+        "cloud-config-history-days": {
+            optional: true,
+            schema: CLOUD_CONFIG_HISTORY_DAYS_SCHEMA,
+        },
+        "cloud-proxy": {
+            optional: true,
+            schema: CLOUD_PROXY_SCHEMA,
+        },
+        "cloud-no-proxy": {
+            optional: true,
+            schema: CLOUD_NO_PROXY_SCHEMA,
+        },
+        "cloud-chunk-cache-size": {
+            optional: true,
+            schema: CLOUD_CHUNK_CACHE_SIZE_SCHEMA,
+        },
     },
 )]
 #[derive(Deserialize, Serialize, Updater)]
@@ -225,6 +242,20 @@ pub struct NodeConfig {
     /// Maximum days to keep Task logs
     #[serde(skip_serializing_if = "Option::is_none")]
     pub task_log_max_days: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_config_history_days: Option<usize>,
+
+    /// Default proxy of cloud targets without their own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_proxy: Option<String>,
+
+    /// Hosts reached without cloud proxy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_no_proxy: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_chunk_cache_size: Option<u64>,
 }
 
 impl NodeConfig {
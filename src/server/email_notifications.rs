@@ -15,6 +15,8 @@ use pbs_api_types::{
     SyncJobConfig, TapeBackupJobSetup, User, Userid, VerificationJobConfig, CloudBackupJobSetup,
 };
 
+use crate::cloud::digest::CloudJobDigest;
+
 const GC_OK_TEMPLATE: &str = r###"
 
 Datastore:            {{datastore}}
@@ -223,6 +225,123 @@ Used Tapes:
 Tape Backup failed: {{error}}
 
 
+Please visit the web interface for further details:
+
+<https://{{fqdn}}:{{port}}/#pbsServerAdministration:tasks>
+
+"###;
+
+const CLOUD_BACKUP_OK_TEMPLATE: &str = r###"
+
+{{#if id ~}}
+Job ID:       {{id}}
+{{/if~}}
+Datastore:    {{job.store}}
+Cloud Target: {{job.target}}
+
+{{#if snapshot-list ~}}
+Snapshots included:
+
+{{#each snapshot-list~}}
+{{this}}
+{{/each~}}
+{{/if}}
+Duration: {{duration}}
+{{#if media-set }}
+Media Set: {{media-set}}
+{{/if}}
+{{#if dedup-stats ~}}
+Deduplication per group (logical / already present / uploaded):
+
+{{#each dedup-stats~}}
+{{group}}: {{human-bytes logical-bytes}} / {{human-bytes present-bytes}} ({{relative-percentage present-bytes logical-bytes}}) / {{human-bytes uploaded-bytes}}
+{{/each~}}
+{{/if}}
+{{#if group-errors ~}}
+Failed groups:
+
+{{#each group-errors~}}
+{{group}}{{#if skipped}} ({{skipped}} snapshot(s) skipped){{/if}}
+{{#each errors~}}
+    {{snapshot}}: {{error}}
+{{/each~}}
+{{/each~}}
+{{/if}}
+{{#if group-errors ~}}
+Cloud Backup partially successful.
+{{else ~}}
+Cloud Backup successful.
+{{/if}}
+
+
+Please visit the web interface for further details:
+
+<https://{{fqdn}}:{{port}}/#DataStore-{{job.store}}>
+
+"###;
+
+const CLOUD_BACKUP_ERR_TEMPLATE: &str = r###"
+
+{{#if id ~}}
+Job ID:       {{id}}
+{{/if~}}
+Datastore:    {{job.store}}
+Cloud Target: {{job.target}}
+
+{{#if snapshot-list ~}}
+Snapshots included:
+
+{{#each snapshot-list~}}
+{{this}}
+{{/each~}}
+{{/if}}
+{{#if group-errors ~}}
+Failed groups:
+
+{{#each group-errors~}}
+{{group}}{{#if skipped}} ({{skipped}} snapshot(s) skipped){{/if}}
+{{#each errors~}}
+    {{snapshot}}: {{error}}
+{{/each~}}
+{{/each~}}
+{{/if}}
+Cloud Backup failed: {{error}}
+
+
+Please visit the web interface for further details:
+
+<https://{{fqdn}}:{{port}}/#pbsServerAdministration:tasks>
+
+"###;
+
+const CLOUD_JOB_DIGEST_TEMPLATE: &str = r###"
+
+Digest:       {{digest.id}}
+Period:       {{since}} - {{until}}
+
+Runs:         {{digest.runs}} ({{digest.failed}} failed)
+Uploaded:     {{human-bytes digest.bytes}}
+
+{{#if digest.jobs ~}}
+Jobs (runs / failed / uploaded):
+
+{{#each digest.jobs~}}
+{{job-type}} {{job-id}}: {{runs}} / {{failed}} / {{human-bytes bytes}}
+{{#if last-error ~}}
+    last error: {{last-error}}
+{{/if~}}
+{{/each~}}
+{{else~}}
+No cloud jobs ran in this period.
+{{/if}}
+{{#if digest.targets ~}}
+Data stored per cloud target:
+
+{{#each digest.targets~}}
+{{target}}: {{human-bytes stored-bytes}}{{#if previous-bytes}} (last digest: {{human-bytes previous-bytes}}){{/if}}
+{{/each~}}
+{{/if}}
+
 Please visit the web interface for further details:
 
 <https://{{fqdn}}:{{port}}/#pbsServerAdministration:tasks>
@@ -268,6 +387,10 @@ lazy_static::lazy_static! {
             hb.register_template_string("tape_backup_ok_template", TAPE_BACKUP_OK_TEMPLATE)?;
             hb.register_template_string("tape_backup_err_template", TAPE_BACKUP_ERR_TEMPLATE)?;
 
+            hb.register_template_string("cloud_backup_ok_template", CLOUD_BACKUP_OK_TEMPLATE)?;
+            hb.register_template_string("cloud_backup_err_template", CLOUD_BACKUP_ERR_TEMPLATE)?;
+            hb.register_template_string("cloud_job_digest_template", CLOUD_JOB_DIGEST_TEMPLATE)?;
+
             hb.register_template_string("package_update_template", PACKAGE_UPDATES_TEMPLATE)?;
 
             hb.register_template_string("certificate_renewal_err_template", ACME_CERTIFICATE_ERR_RENEWAL)?;
@@ -301,10 +424,14 @@ pub struct CloudBackupJobSummary {
     pub snapshot_list: Vec<String>,
     /// The total time of the backup job
     pub duration: std::time::Duration,
+    /// The media set written by the backup job
+    pub media_set: Option<String>,
+    /// Deduplication statistics of the written groups
+    pub dedup_stats: Vec<crate::cloud::dedup_stats::GroupDedupStats>,
+    /// Groups with snapshots which could not be backed up
+    pub group_errors: Vec<crate::cloud::job_errors::GroupErrors>,
 }
 
-
-
 fn send_job_status_mail(email: &str, subject: &str, text: &str) -> Result<(), Error> {
     let (config, _) = crate::config::node::config()?;
     let from = config.email_from;
@@ -528,29 +655,74 @@ pub fn send_cloud_backup_status(
 ) -> Result<(), Error> {
     let (fqdn, port) = get_server_url();
     let duration: proxmox_time::TimeSpan = summary.duration.into();
+    let status = if summary.group_errors.is_empty() {
+        "successful"
+    } else {
+        "partially successful"
+    };
     let mut data = json!({
         "job": job,
         "fqdn": fqdn,
         "port": port,
         "id": id,
         "snapshot-list": summary.snapshot_list,
-        //"used-tapes": summary.used_tapes,
+        "media-set": summary.media_set,
+        "dedup-stats": summary.dedup_stats,
+        "group-errors": summary.group_errors,
         "duration": duration.to_string(),
     });
 
     let text = match result {
-        Ok(()) => HANDLEBARS.render("tape_backup_ok_template", &data)?,
+        Ok(()) => HANDLEBARS.render("cloud_backup_ok_template", &data)?,
         Err(err) => {
             data["error"] = err.to_string().into();
-            HANDLEBARS.render("tape_backup_err_template", &data)?
+            HANDLEBARS.render("cloud_backup_err_template", &data)?
         }
     };
 
+    let target = job.target.as_deref().unwrap_or_default();
     let subject = match (result, id) {
-        (Ok(()), Some(id)) => format!("Tape Backup '{id}' datastore '{}' successful", job.store,),
-        (Ok(()), None) => format!("Tape Backup datastore '{}' successful", job.store,),
-        (Err(_), Some(id)) => format!("Tape Backup '{id}' datastore '{}' failed", job.store,),
-        (Err(_), None) => format!("Tape Backup datastore '{}' failed", job.store,),
+        (Ok(()), Some(id)) => format!(
+            "Cloud Backup '{id}' datastore '{}' to '{}' {status}",
+            job.store, target,
+        ),
+        (Ok(()), None) => format!(
+            "Cloud Backup datastore '{}' to '{}' {status}",
+            job.store, target,
+        ),
+        (Err(_), Some(id)) => format!(
+            "Cloud Backup '{id}' datastore '{}' to '{}' failed",
+            job.store, target,
+        ),
+        (Err(_), None) => format!(
+            "Cloud Backup datastore '{}' to '{}' failed",
+            job.store, target,
+        ),
+    };
+
+    send_job_status_mail(email, &subject, &text)?;
+
+    Ok(())
+}
+
+pub fn send_cloud_job_digest(email: &str, digest: &CloudJobDigest) -> Result<(), Error> {
+    let (fqdn, port) = get_server_url();
+    let data = json!({
+        "digest": digest,
+        "fqdn": fqdn,
+        "port": port,
+        "since": proxmox_time::strftime_local("%F %T", digest.since)?,
+        "until": proxmox_time::strftime_local("%F %T", digest.until)?,
+    });
+
+    let text = HANDLEBARS.render("cloud_job_digest_template", &data)?;
+
+    let subject = match digest.failed {
+        0 => format!("Cloud Job Digest '{}': {} runs successful", digest.id, digest.runs),
+        failed => format!(
+            "Cloud Job Digest '{}': {} of {} runs failed",
+            digest.id, failed, digest.runs
+        ),
     };
 
     send_job_status_mail(email, &subject, &text)?;
@@ -807,6 +979,10 @@ fn test_template_register() {
     assert!(HANDLEBARS.has_template("tape_backup_ok_template"));
     assert!(HANDLEBARS.has_template("tape_backup_err_template"));
 
+    assert!(HANDLEBARS.has_template("cloud_backup_ok_template"));
+    assert!(HANDLEBARS.has_template("cloud_backup_err_template"));
+    assert!(HANDLEBARS.has_template("cloud_job_digest_template"));
+
     assert!(HANDLEBARS.has_template("package_update_template"));
 
     assert!(HANDLEBARS.has_template("certificate_renewal_err_template"));
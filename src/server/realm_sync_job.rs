@@ -374,18 +374,18 @@ struct GeneralSyncSettings {
 }
 
 /// LDAP-specific realm sync settings from the realm configuration
-struct LdapSyncSettings {
-    user_attr: String,
+pub(crate) struct LdapSyncSettings {
+    pub(crate) user_attr: String,
     firstname_attr: Option<String>,
     lastname_attr: Option<String>,
     email_attr: Option<String>,
-    attributes: Vec<String>,
-    user_classes: Vec<String>,
-    user_filter: Option<String>,
+    pub(crate) attributes: Vec<String>,
+    pub(crate) user_classes: Vec<String>,
+    pub(crate) user_filter: Option<String>,
 }
 
 impl LdapSyncSettings {
-    fn from_config(config: &LdapRealmConfig) -> Result<Self, Error> {
+    pub(crate) fn from_config(config: &LdapRealmConfig) -> Result<Self, Error> {
         let mut attributes = vec![config.user_attr.clone()];
 
         let mut email = None;
@@ -92,6 +92,32 @@ fn network_match_len(networks: &[IpInet], ip: &IpAddr) -> Option<u8> {
     match_len
 }
 
+// exact host names rank above sub domain patterns, longer domains above shorter ones
+fn endpoint_match_len(patterns: &[String], host: &str) -> Option<usize> {
+    let host = host.to_ascii_lowercase();
+    let mut match_len = None;
+
+    for pattern in patterns.iter() {
+        let pattern = pattern.to_ascii_lowercase();
+        let len = match pattern.strip_prefix("*.") {
+            Some(domain) => {
+                let matched = host
+                    .strip_suffix(domain)
+                    .map(|name| name.len() > 1 && name.ends_with('.'))
+                    .unwrap_or(false);
+                if !matched {
+                    continue;
+                }
+                domain.len()
+            }
+            None if pattern == host => usize::MAX,
+            None => continue,
+        };
+        match_len = match_len.max(Some(len));
+    }
+    match_len
+}
+
 fn cannonical_ip(ip: IpAddr) -> IpAddr {
     // TODO: use std::net::IpAddr::to_cananical once stable
     match ip {
@@ -323,9 +349,29 @@ impl TrafficControlCache {
         Ok(())
     }
 
+    fn rule_limiters(
+        &self,
+        rule: Option<&ParsedTcRule>,
+    ) -> (&str, Option<SharedRateLimit>, Option<SharedRateLimit>) {
+        match rule {
+            Some(rule) => {
+                match self.limiter_map.get(&rule.config.name) {
+                    Some((read_limiter, write_limiter)) => (
+                        &rule.config.name,
+                        read_limiter.clone(),
+                        write_limiter.clone(),
+                    ),
+                    None => ("", None, None), // should never happen
+                }
+            }
+            None => ("", None, None),
+        }
+    }
+
     /// Returns the rate limiter (if any) for the specified peer address.
     ///
     /// - Rules where timeframe does not match are skipped.
+    /// - Rules for cloud targets or endpoints are skipped.
     /// - Rules with smaller network size have higher priority.
     ///
     /// Behavior is undefined if more than one rule matches after
@@ -350,7 +396,7 @@ impl TrafficControlCache {
         let mut last_rule_match = None;
 
         for rule in self.rules.iter() {
-            if !timeframe_match(&rule.timeframe, &now) {
+            if !timeframe_match(&rule.timeframe, &now) || rule.config.is_cloud_rule() {
                 continue;
             }
 
@@ -366,19 +412,115 @@ impl TrafficControlCache {
             }
         }
 
-        match last_rule_match {
-            Some((rule, _)) => {
-                match self.limiter_map.get(&rule.config.name) {
-                    Some((read_limiter, write_limiter)) => (
-                        &rule.config.name,
-                        read_limiter.clone(),
-                        write_limiter.clone(),
-                    ),
-                    None => ("", None, None), // should never happen
+        self.rule_limiters(last_rule_match.map(|(rule, _)| rule))
+    }
+
+    /// Returns the rate limiter (if any) for connections of a cloud target.
+    ///
+    /// `endpoint` is the host name connected to, `peer` its address (or
+    /// the address of the proxy). All criteria a rule sets must match.
+    ///
+    /// - Rules where timeframe does not match are skipped.
+    /// - Rules naming the cloud target have highest priority, followed by
+    ///   rules naming the endpoint (exact names before sub domains).
+    /// - Rules with smaller network size have higher priority.
+    ///
+    /// Behavior is undefined if more than one rule matches after
+    /// above selection.
+    pub fn lookup_cloud_rate_limiter(
+        &self,
+        target: &str,
+        endpoint: &str,
+        peer: Option<IpAddr>,
+        now: i64,
+    ) -> (&str, Option<SharedRateLimit>, Option<SharedRateLimit>) {
+        log::debug!(
+            "lookup_cloud_rate_limiter: {} {} {:?}",
+            target,
+            endpoint,
+            peer
+        );
+
+        self.rule_limiters(self.match_cloud_rule(target, endpoint, peer, now))
+    }
+
+    /// Returns the incoming rate and the read limiter of the rule for
+    /// connections of a cloud target (see [`Self::lookup_cloud_rate_limiter`]).
+    ///
+    /// The limiter counts the traffic of all connections using the rule,
+    /// so it can be used to measure the bandwidth left on the node.
+    pub fn lookup_cloud_read_limit(
+        &self,
+        target: &str,
+        endpoint: &str,
+        peer: Option<IpAddr>,
+        now: i64,
+    ) -> Option<(u64, SharedRateLimit)> {
+        let rule = self.match_cloud_rule(target, endpoint, peer, now)?;
+        let rate = rule.config.limit.rate_in?.as_u64();
+        let (_, read_limiter, _) = self.rule_limiters(Some(rule));
+        Some((rate, read_limiter?))
+    }
+
+    fn match_cloud_rule(
+        &self,
+        target: &str,
+        endpoint: &str,
+        peer: Option<IpAddr>,
+        now: i64,
+    ) -> Option<&ParsedTcRule> {
+        let peer_ip = peer.map(cannonical_ip);
+
+        let now = match TmEditor::with_epoch(now, self.use_utc) {
+            Ok(now) => now,
+            Err(err) => {
+                log::error!("match_cloud_rule: TmEditor::with_epoch failed - {}", err);
+                return None;
+            }
+        };
+
+        let mut last_rule_match = None;
+
+        for rule in self.rules.iter() {
+            if !timeframe_match(&rule.timeframe, &now) {
+                continue;
+            }
+
+            let target_match = match rule.config.cloud_target {
+                Some(ref targets) if targets.iter().any(|name| name == target) => true,
+                Some(_) => continue,
+                None => false,
+            };
+
+            let endpoint_len = match rule.config.endpoint {
+                Some(ref patterns) => match endpoint_match_len(patterns, endpoint) {
+                    Some(len) => Some(len),
+                    None => continue,
+                },
+                None => None,
+            };
+
+            let network_len = if rule.networks.is_empty() {
+                None
+            } else {
+                match peer_ip.and_then(|ip| network_match_len(&rule.networks, &ip)) {
+                    Some(len) => Some(len),
+                    None => continue,
                 }
+            };
+
+            if !target_match && endpoint_len.is_none() && network_len.is_none() {
+                continue;
+            }
+
+            let rank = (target_match, endpoint_len, network_len);
+            match last_rule_match {
+                Some((_, last_rank)) if rank <= last_rank => {}
+                _ => last_rule_match = Some((rule, rank)),
             }
-            None => ("", None, None),
         }
+
+        last_rule_match.map(|(rule, _)| rule)
     }
 }
 
@@ -489,4 +631,135 @@ rule: somewhere
 
         Ok(())
     }
+
+    #[test]
+    fn test_endpoint_match() {
+        let patterns = ["s3.example.com".to_string(), "*.amazonaws.com".to_string()];
+
+        assert_eq!(
+            endpoint_match_len(&patterns, "S3.Example.com"),
+            Some(usize::MAX)
+        );
+        assert_eq!(
+            endpoint_match_len(&patterns, "bucket.s3.amazonaws.com"),
+            Some(13)
+        );
+        assert_eq!(endpoint_match_len(&patterns, "amazonaws.com"), None);
+        assert_eq!(endpoint_match_len(&patterns, "notamazonaws.com"), None);
+        assert_eq!(endpoint_match_len(&patterns, "example.com"), None);
+    }
+
+    #[test]
+    fn test_cloud_rule_match() -> Result<(), Error> {
+        let config_data = "
+rule: everything
+	network 0.0.0.0/0
+	rate-out 100000000
+
+rule: aws
+	endpoint *.amazonaws.com
+	rate-out 50000000
+
+rule: offsite
+	cloud-target offsite
+	rate-out 20000000
+	timeframe 8-12
+
+rule: offsite-local
+	cloud-target offsite
+	network 192.168.2.0/24
+	rate-out 10000000
+";
+        let config = pbs_config::traffic_control::CONFIG.parse("testconfig", config_data)?;
+
+        let mut cache = TrafficControlCache::new();
+        cache.use_utc = true;
+        cache.use_shared_memory = false; // avoid permission problems in test environment
+
+        cache.update_config(&config)?;
+
+        const THURSDAY_80_00: i64 = make_test_time(0, 8, 0);
+        const THURSDAY_19_00: i64 = make_test_time(0, 19, 0);
+
+        let aws: IpAddr = "52.1.2.3".parse()?;
+        let local: IpAddr = "192.168.2.10".parse()?;
+
+        let lookup = |target, endpoint, peer, now| {
+            let (rule, _, write_limiter) =
+                cache.lookup_cloud_rate_limiter(target, endpoint, peer, now);
+            assert!(write_limiter.is_some());
+            rule.to_string()
+        };
+
+        assert_eq!(
+            lookup("other", "minio.local", Some(local), THURSDAY_80_00),
+            "everything"
+        );
+        assert_eq!(
+            lookup("other", "b.s3.amazonaws.com", Some(aws), THURSDAY_80_00),
+            "aws"
+        );
+        assert_eq!(
+            lookup("offsite", "b.s3.amazonaws.com", Some(aws), THURSDAY_80_00),
+            "offsite"
+        );
+        assert_eq!(
+            lookup("offsite", "b.s3.amazonaws.com", Some(aws), THURSDAY_19_00),
+            "aws"
+        );
+        // target and network both match
+        assert_eq!(
+            lookup("offsite", "minio.local", Some(local), THURSDAY_80_00),
+            "offsite-local"
+        );
+        // unresolved peers only match rules without network
+        assert_eq!(
+            lookup("offsite", "minio.local", None, THURSDAY_80_00),
+            "offsite"
+        );
+
+        // cloud rules do not apply to clients
+        let (rule, _, _) = cache.lookup_rate_limiter(SocketAddr::new(aws, 1234), THURSDAY_80_00);
+        assert_eq!(rule, "everything");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cloud_read_limit() -> Result<(), Error> {
+        let config_data = "
+rule: aws
+	endpoint *.amazonaws.com
+	rate-in 50000000
+
+rule: offsite
+	cloud-target offsite
+	rate-out 20000000
+";
+        let config = pbs_config::traffic_control::CONFIG.parse("testconfig", config_data)?;
+
+        let mut cache = TrafficControlCache::new();
+        cache.use_utc = true;
+        cache.use_shared_memory = false; // avoid permission problems in test environment
+
+        cache.update_config(&config)?;
+
+        const THURSDAY_80_00: i64 = make_test_time(0, 8, 0);
+
+        let (rate, _) = cache
+            .lookup_cloud_read_limit("other", "b.s3.amazonaws.com", None, THURSDAY_80_00)
+            .unwrap();
+        assert_eq!(rate, 50_000_000);
+
+        // the matching rule does not limit downloads
+        assert!(cache
+            .lookup_cloud_read_limit("offsite", "b.s3.amazonaws.com", None, THURSDAY_80_00)
+            .is_none());
+        // no rule matches
+        assert!(cache
+            .lookup_cloud_read_limit("other", "minio.local", None, THURSDAY_80_00)
+            .is_none());
+
+        Ok(())
+    }
 }